@@ -212,6 +212,12 @@ pub enum QueueCommands {
         // Cancel one or multiple message ids
         ids: Vec<String>,
     },
+
+    /// Release quarantined messages for delivery
+    Release {
+        #[clap(required = true)]
+        ids: Vec<String>,
+    },
 }
 
 #[derive(Subcommand)]
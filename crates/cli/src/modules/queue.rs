@@ -40,6 +40,8 @@ pub struct Message {
     #[serde(default)]
     pub priority: i16,
     pub env_id: Option<String>,
+    #[serde(default)]
+    pub ret: Option<String>,
 }
 
 #[derive(Debug, Deserialize, PartialEq, Eq)]
@@ -49,6 +51,8 @@ pub struct Domain {
     pub recipients: Vec<Recipient>,
 
     pub retry_num: u32,
+    #[serde(default)]
+    pub retry_class: Option<String>,
     #[serde(deserialize_with = "deserialize_maybe_datetime")]
     pub next_retry: Option<DateTime>,
     #[serde(deserialize_with = "deserialize_maybe_datetime")]
@@ -62,6 +66,8 @@ pub struct Recipient {
     pub address: String,
     pub status: Status,
     pub orcpt: Option<String>,
+    #[serde(default)]
+    pub notify: Vec<String>,
 }
 
 #[derive(Debug, PartialEq, Eq, Deserialize)]
@@ -211,6 +217,12 @@ pub async fn cmd_queue(url: &str, credentials: Credentials, command: QueueComman
                             Cell::new(env_id),
                         ]));
                     }
+                    if let Some(ret) = &message.ret {
+                        table.add_row(Row::new(vec![
+                            Cell::new("Return").with_style(Attr::Bold),
+                            Cell::new(ret),
+                        ]));
+                    }
                     if message.priority != 0 {
                         table.add_row(Row::new(vec![
                             Cell::new("Priority").with_style(Attr::Bold),
@@ -237,6 +249,12 @@ pub async fn cmd_queue(url: &str, credentials: Credentials, command: QueueComman
                             Cell::new("Retry #").with_style(Attr::Bold),
                             Cell::new(&domain.retry_num.to_string()),
                         ]));
+                        if let Some(retry_class) = &domain.retry_class {
+                            table.add_row(Row::new(vec![
+                                Cell::new("Retry Class").with_style(Attr::Bold),
+                                Cell::new(retry_class),
+                            ]));
+                        }
                         if let Some(dt) = &domain.next_retry {
                             table.add_row(Row::new(vec![
                                 Cell::new("Delivery Due").with_style(Attr::Bold),
@@ -259,12 +277,16 @@ pub async fn cmd_queue(url: &str, credentials: Credentials, command: QueueComman
                             Cell::new("Address").with_style(Attr::Bold),
                             Cell::new("Status").with_style(Attr::Bold),
                             Cell::new("Details").with_style(Attr::Bold),
+                            Cell::new("Notify").with_style(Attr::Bold),
+                            Cell::new("Original Recipient").with_style(Attr::Bold),
                         ]));
                         for rcpt in &domain.recipients {
                             rcpts.add_row(Row::new(vec![
                                 Cell::new(&rcpt.address),
                                 Cell::new(rcpt.status.status()),
                                 Cell::new(rcpt.status.details()),
+                                Cell::new(&rcpt.notify.join(",")),
+                                Cell::new(rcpt.orcpt.as_deref().unwrap_or_default()),
                             ]));
                         }
                         table.add_row(Row::new(vec![
@@ -394,6 +416,34 @@ pub async fn cmd_queue(url: &str, credentials: Credentials, command: QueueComman
             }
             eprintln!();
         }
+        QueueCommands::Release { ids } => {
+            let query = format!(
+                "{url}/admin/queue/release?ids={}",
+                append_ids(String::new(), &parse_ids(&ids))
+            );
+
+            let mut success_count = 0;
+            let mut failed_list = vec![];
+            for (success, id) in smtp_manage_request::<Vec<bool>>(&query, &credentials)
+                .await
+                .into_iter()
+                .zip(ids)
+            {
+                if success {
+                    success_count += 1;
+                } else {
+                    failed_list.push(id);
+                }
+            }
+            eprint!("\nReleased {success_count} message(s).");
+            if !failed_list.is_empty() {
+                eprint!(
+                    " Unable to release id(s): {}, they are either not queued or not quarantined.",
+                    failed_list.join(", ")
+                );
+            }
+            eprintln!();
+        }
     }
 }
 
@@ -284,6 +284,10 @@ impl Directory for LdapDirectory {
             .map(|entry| entry.is_some())
             .map_err(|e| e.into())
     }
+
+    fn subaddress<'x>(&self, address: &'x str) -> Option<&'x str> {
+        self.opt.subaddressing.to_subaddress_detail(address)
+    }
 }
 
 impl LdapDirectory {
@@ -42,9 +42,12 @@ pub mod imap;
 pub mod ldap;
 pub mod memory;
 pub mod scheduled;
+pub mod scram;
 pub mod secret;
 pub mod smtp;
 pub mod sql;
+pub mod tenant;
+pub mod totp;
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub struct Principal {
@@ -94,6 +97,11 @@ pub trait Directory: Sync + Send {
         params: &[DatabaseColumn<'_>],
     ) -> Result<Vec<DatabaseColumn<'static>>>;
 
+    /// Returns the detail part of a subaddressed recipient, if any.
+    fn subaddress<'x>(&self, _address: &'x str) -> Option<&'x str> {
+        None
+    }
+
     fn type_name(&self) -> &'static str {
         std::any::type_name::<Self>()
     }
@@ -472,6 +480,23 @@ impl AddressMapping {
         address.into()
     }
 
+    // The detail is whatever the mapping strips from the local part,
+    // minus the separator that follows the mapped local part.
+    pub fn to_subaddress_detail<'x>(&self, address: &'x str) -> Option<&'x str> {
+        let mapped = self.to_subaddress(address);
+        if mapped == address {
+            return None;
+        }
+        let (local_part, domain_part) = address.rsplit_once('@')?;
+        let (mapped_local_part, mapped_domain_part) = mapped.rsplit_once('@')?;
+        if !mapped_domain_part.eq_ignore_ascii_case(domain_part) {
+            return None;
+        }
+        let mut detail = local_part.strip_prefix(mapped_local_part)?.chars();
+        detail.next()?;
+        Some(detail.as_str()).filter(|detail| !detail.is_empty())
+    }
+
     pub fn to_catch_all<'x, 'y: 'x>(&'x self, address: &'y str) -> Option<Cow<'x, str>> {
         match self {
             AddressMapping::Enable => address
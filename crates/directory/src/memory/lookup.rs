@@ -147,4 +147,8 @@ impl Directory for MemoryDirectory {
     async fn is_local_domain(&self, domain: &str) -> crate::Result<bool> {
         Ok(self.domains.contains(domain))
     }
+
+    fn subaddress<'x>(&self, address: &'x str) -> Option<&'x str> {
+        self.opt.subaddressing.to_subaddress_detail(address)
+    }
 }
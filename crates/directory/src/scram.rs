@@ -0,0 +1,564 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use hmac::{Hmac, Mac};
+use mail_builder::encoders::base64::base64_encode;
+use mail_parser::decoders::base64::base64_decode;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::Principal;
+
+const DEFAULT_ITERATIONS: u32 = 4096;
+const NONCE_LEN: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScramAlgorithm {
+    Sha1,
+    Sha256,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScramVerifier {
+    pub algorithm: ScramAlgorithm,
+    pub iterations: u32,
+    pub salt: Vec<u8>,
+    pub stored_key: Vec<u8>,
+    pub server_key: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct ScramServer {
+    algorithm: ScramAlgorithm,
+    state: ScramState,
+}
+
+#[derive(Debug)]
+enum ScramState {
+    ClientFirst,
+    ServerFirst {
+        username: String,
+        gs2_header: String,
+        client_first_bare: String,
+        client_nonce: String,
+    },
+    ClientFinal {
+        username: String,
+        gs2_header: String,
+        auth_message: String,
+        nonce: String,
+        verifier: ScramVerifier,
+    },
+    Authenticated {
+        username: String,
+    },
+    Failed,
+}
+
+impl ScramAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScramAlgorithm::Sha1 => "SCRAM-SHA-1",
+            ScramAlgorithm::Sha256 => "SCRAM-SHA-256",
+        }
+    }
+
+    pub fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            ScramAlgorithm::Sha1 => Sha1::digest(data).to_vec(),
+            ScramAlgorithm::Sha256 => Sha256::digest(data).to_vec(),
+        }
+    }
+
+    pub fn hmac(&self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            ScramAlgorithm::Sha1 => Hmac::<Sha1>::new_from_slice(key)
+                .map(|mut mac| {
+                    mac.update(data);
+                    mac.finalize().into_bytes().to_vec()
+                })
+                .unwrap_or_default(),
+            ScramAlgorithm::Sha256 => Hmac::<Sha256>::new_from_slice(key)
+                .map(|mut mac| {
+                    mac.update(data);
+                    mac.finalize().into_bytes().to_vec()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn salted_password(&self, password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+        match self {
+            ScramAlgorithm::Sha1 => {
+                let mut result = vec![0u8; 20];
+                pbkdf2::pbkdf2_hmac::<Sha1>(password, salt, iterations, &mut result);
+                result
+            }
+            ScramAlgorithm::Sha256 => {
+                let mut result = vec![0u8; 32];
+                pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, iterations, &mut result);
+                result
+            }
+        }
+    }
+}
+
+impl ScramVerifier {
+    pub fn from_password(
+        algorithm: ScramAlgorithm,
+        password: &str,
+        salt: Vec<u8>,
+        iterations: u32,
+    ) -> Self {
+        let salted_password = algorithm.salted_password(password.as_bytes(), &salt, iterations);
+        let client_key = algorithm.hmac(&salted_password, b"Client Key");
+        ScramVerifier {
+            algorithm,
+            iterations,
+            salt,
+            stored_key: algorithm.hash(&client_key),
+            server_key: algorithm.hmac(&salted_password, b"Server Key"),
+        }
+    }
+
+    // Parses verifiers using the PostgreSQL format:
+    // SCRAM-SHA-256$<iterations>:<salt>$<StoredKey>:<ServerKey>
+    pub fn parse(value: &str) -> Option<Self> {
+        let (algorithm, value) = if let Some(value) = value.strip_prefix("SCRAM-SHA-256$") {
+            (ScramAlgorithm::Sha256, value)
+        } else if let Some(value) = value.strip_prefix("SCRAM-SHA-1$") {
+            (ScramAlgorithm::Sha1, value)
+        } else {
+            return None;
+        };
+        let (params, keys) = value.split_once('$')?;
+        let (iterations, salt) = params.split_once(':')?;
+        let (stored_key, server_key) = keys.split_once(':')?;
+
+        Some(ScramVerifier {
+            algorithm,
+            iterations: iterations.parse().ok().filter(|&i| i > 0)?,
+            salt: base64_decode(salt.as_bytes())?,
+            stored_key: base64_decode(stored_key.as_bytes())?,
+            server_key: base64_decode(server_key.as_bytes())?,
+        })
+    }
+
+    pub fn verify_password(&self, password: &str) -> bool {
+        let salted_password =
+            self.algorithm
+                .salted_password(password.as_bytes(), &self.salt, self.iterations);
+        self.algorithm
+            .hash(&self.algorithm.hmac(&salted_password, b"Client Key"))
+            == self.stored_key
+    }
+
+    fn unknown_user(algorithm: ScramAlgorithm, username: &str) -> Self {
+        // Return a verifier that can never succeed, with a salt that is stable
+        // for the username so that probing does not reveal which accounts exist.
+        let key = thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(32)
+            .collect::<Vec<_>>();
+        ScramVerifier {
+            algorithm,
+            iterations: DEFAULT_ITERATIONS,
+            salt: algorithm.hash(username.as_bytes())[..16].to_vec(),
+            stored_key: algorithm.hash(&key),
+            server_key: key,
+        }
+    }
+}
+
+impl Principal {
+    pub fn scram_verifier(&self, algorithm: ScramAlgorithm) -> Option<ScramVerifier> {
+        // Prefer stored verifiers
+        for secret in &self.secrets {
+            if let Some(verifier) = ScramVerifier::parse(secret) {
+                if verifier.algorithm == algorithm {
+                    return Some(verifier);
+                }
+            }
+        }
+
+        // Derive a verifier from clear text secrets
+        for secret in &self.secrets {
+            let password = if let Some((algo, password)) = secret
+                .strip_prefix('{')
+                .and_then(|secret| secret.split_once('}'))
+            {
+                if matches!(algo, "PLAIN" | "plain" | "CLEAR" | "clear") {
+                    password
+                } else {
+                    continue;
+                }
+            } else if !secret.starts_with(['$', '_']) && !secret.starts_with("SCRAM-SHA-") {
+                secret.as_str()
+            } else {
+                continue;
+            };
+
+            return Some(ScramVerifier::from_password(
+                algorithm,
+                password,
+                algorithm.hash(self.name.as_bytes())[..16].to_vec(),
+                DEFAULT_ITERATIONS,
+            ));
+        }
+
+        None
+    }
+}
+
+impl ScramServer {
+    pub fn new(algorithm: ScramAlgorithm) -> Self {
+        ScramServer {
+            algorithm,
+            state: ScramState::ClientFirst,
+        }
+    }
+
+    pub fn algorithm(&self) -> ScramAlgorithm {
+        self.algorithm
+    }
+
+    // Parses the client-first message and returns the authentication identity.
+    pub fn client_first(&mut self, message: &[u8]) -> Result<&str, &'static str> {
+        if !matches!(self.state, ScramState::ClientFirst) {
+            self.state = ScramState::Failed;
+            return Err("Unexpected SCRAM client-first message.");
+        }
+        let message = std::str::from_utf8(message).map_err(|_| "Invalid SCRAM message.")?;
+        let mut parts = message.splitn(3, ',');
+        match parts.next() {
+            Some("n" | "y") => (),
+            Some(cbind) if cbind.starts_with("p=") => {
+                return Err("SCRAM channel binding is not supported.");
+            }
+            _ => return Err("Invalid SCRAM GS2 header."),
+        }
+        let authzid = parts.next().ok_or("Invalid SCRAM GS2 header.")?;
+        let client_first_bare = parts.next().ok_or("Invalid SCRAM GS2 header.")?;
+
+        let mut username = None;
+        let mut client_nonce = None;
+        for (pos, attribute) in client_first_bare.split(',').enumerate() {
+            match (pos, attribute.split_once('=')) {
+                (0, Some(("n", value))) => {
+                    username = decode_saslname(value).filter(|u| !u.is_empty());
+                }
+                (1, Some(("r", value))) if !value.is_empty() => {
+                    client_nonce = Some(value);
+                }
+                (0, Some(("m", _))) => return Err("Unsupported SCRAM mandatory extension."),
+                (2.., Some(_)) => (),
+                _ => return Err("Invalid SCRAM client-first message."),
+            }
+        }
+        let (username, client_nonce) = username
+            .zip(client_nonce)
+            .ok_or("Invalid SCRAM client-first message.")?;
+
+        if !authzid.is_empty()
+            && authzid
+                .strip_prefix("a=")
+                .and_then(decode_saslname)
+                .map_or(true, |authzid| authzid != username)
+        {
+            return Err("SCRAM authorization identity must match the authentication identity.");
+        }
+
+        self.state = ScramState::ServerFirst {
+            gs2_header: message[..message.len() - client_first_bare.len()].to_string(),
+            client_first_bare: client_first_bare.to_string(),
+            client_nonce: client_nonce.to_string(),
+            username,
+        };
+
+        match &self.state {
+            ScramState::ServerFirst { username, .. } => Ok(username),
+            _ => unreachable!(),
+        }
+    }
+
+    // Builds the server-first message using the verifier of the authentication
+    // identity, or a fake one if the account does not exist.
+    pub fn server_first(
+        &mut self,
+        verifier: Option<ScramVerifier>,
+    ) -> Result<Vec<u8>, &'static str> {
+        let server_nonce = thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(NONCE_LEN)
+            .map(char::from)
+            .collect::<String>();
+        self.server_first_with_nonce(verifier, &server_nonce)
+    }
+
+    fn server_first_with_nonce(
+        &mut self,
+        verifier: Option<ScramVerifier>,
+        server_nonce: &str,
+    ) -> Result<Vec<u8>, &'static str> {
+        match std::mem::replace(&mut self.state, ScramState::Failed) {
+            ScramState::ServerFirst {
+                username,
+                gs2_header,
+                client_first_bare,
+                client_nonce,
+            } => {
+                let verifier = verifier
+                    .filter(|v| v.algorithm == self.algorithm)
+                    .unwrap_or_else(|| ScramVerifier::unknown_user(self.algorithm, &username));
+                let nonce = format!("{client_nonce}{server_nonce}");
+                let server_first = format!(
+                    "r={},s={},i={}",
+                    nonce,
+                    encode(&verifier.salt),
+                    verifier.iterations
+                );
+                self.state = ScramState::ClientFinal {
+                    username,
+                    gs2_header,
+                    auth_message: format!("{client_first_bare},{server_first}"),
+                    nonce,
+                    verifier,
+                };
+                Ok(server_first.into_bytes())
+            }
+            _ => Err("Unexpected SCRAM state."),
+        }
+    }
+
+    // Verifies the client proof and returns the server-final message.
+    pub fn client_final(&mut self, message: &[u8]) -> Result<Vec<u8>, &'static str> {
+        match std::mem::replace(&mut self.state, ScramState::Failed) {
+            ScramState::ClientFinal {
+                username,
+                gs2_header,
+                auth_message,
+                nonce,
+                verifier,
+            } => {
+                let message = std::str::from_utf8(message).map_err(|_| "Invalid SCRAM message.")?;
+                let (without_proof, proof) = message
+                    .rsplit_once(",p=")
+                    .ok_or("Invalid SCRAM client-final message.")?;
+                let mut attributes = without_proof.split(',');
+                if attributes
+                    .next()
+                    .and_then(|value| value.strip_prefix("c="))
+                    .and_then(|value| base64_decode(value.as_bytes()))
+                    .map_or(true, |value| value != gs2_header.as_bytes())
+                {
+                    return Err("Invalid SCRAM channel binding.");
+                }
+                if attributes
+                    .next()
+                    .and_then(|value| value.strip_prefix("r="))
+                    .map_or(true, |value| value != nonce)
+                {
+                    return Err("Invalid SCRAM nonce.");
+                }
+                let proof = base64_decode(proof.as_bytes()).ok_or("Invalid SCRAM client proof.")?;
+
+                let auth_message = format!("{auth_message},{without_proof}");
+                let client_signature = self
+                    .algorithm
+                    .hmac(&verifier.stored_key, auth_message.as_bytes());
+                if proof.len() != client_signature.len() {
+                    return Err("Invalid SCRAM client proof.");
+                }
+                let client_key = proof
+                    .iter()
+                    .zip(client_signature.iter())
+                    .map(|(a, b)| a ^ b)
+                    .collect::<Vec<_>>();
+                if self.algorithm.hash(&client_key) != verifier.stored_key {
+                    return Err("Invalid SCRAM client proof.");
+                }
+
+                let server_signature = self
+                    .algorithm
+                    .hmac(&verifier.server_key, auth_message.as_bytes());
+                self.state = ScramState::Authenticated { username };
+                Ok(format!("v={}", encode(&server_signature)).into_bytes())
+            }
+            _ => Err("Unexpected SCRAM client-final message."),
+        }
+    }
+
+    pub fn is_initial(&self) -> bool {
+        matches!(self.state, ScramState::ClientFirst)
+    }
+
+    pub fn is_client_final(&self) -> bool {
+        matches!(self.state, ScramState::ClientFinal { .. })
+    }
+
+    pub fn authenticated_as(&self) -> Option<&str> {
+        match &self.state {
+            ScramState::Authenticated { username } => Some(username),
+            _ => None,
+        }
+    }
+}
+
+fn decode_saslname(value: &str) -> Option<String> {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '=' => match (chars.next(), chars.next()) {
+                (Some('2'), Some('C')) => result.push(','),
+                (Some('3'), Some('D')) => result.push('='),
+                _ => return None,
+            },
+            ',' => return None,
+            _ => result.push(ch),
+        }
+    }
+    Some(result)
+}
+
+fn encode(bytes: &[u8]) -> String {
+    String::from_utf8(base64_encode(bytes).unwrap_or_default()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use mail_parser::decoders::base64::base64_decode;
+
+    use crate::Principal;
+
+    use super::{ScramAlgorithm, ScramServer, ScramVerifier};
+
+    #[test]
+    fn scram_exchange() {
+        // Test vectors from RFC 5802 and RFC 7677
+        for (algorithm, client_first, server_nonce, salt, client_final, server_final) in [
+            (
+                ScramAlgorithm::Sha1,
+                "n,,n=user,r=fyko+d2lbbFgONRv9qkxdawL",
+                "3rfcNHYJY1ZVvWVs7j",
+                "QSXCR+Q6sek8bf92",
+                "c=biws,r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,p=v0X8v3Bz2T0CJGbJQyF0X+HI4Ts=",
+                "v=rmF9pqV8S7suAoZWja4dJRkFsKQ=",
+            ),
+            (
+                ScramAlgorithm::Sha256,
+                "n,,n=user,r=rOprNGfwEbeRWgbNEkqO",
+                "%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0",
+                "W22ZaJ0SNY7soEsUEjb6gQ==",
+                concat!(
+                    "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,",
+                    "p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+                ),
+                "v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4=",
+            ),
+        ] {
+            let verifier = ScramVerifier::from_password(
+                algorithm,
+                "pencil",
+                base64_decode(salt.as_bytes()).unwrap(),
+                4096,
+            );
+            assert!(verifier.verify_password("pencil"));
+            assert!(!verifier.verify_password("pencils"));
+
+            // Verifiers should survive a round trip through the stored format
+            let stored = format!(
+                "{}${}:{}${}:{}",
+                algorithm.name(),
+                verifier.iterations,
+                super::encode(&verifier.salt),
+                super::encode(&verifier.stored_key),
+                super::encode(&verifier.server_key)
+            );
+            assert_eq!(ScramVerifier::parse(&stored).as_ref(), Some(&verifier));
+            let principal = Principal {
+                name: "user".to_string(),
+                secrets: vec![stored],
+                ..Default::default()
+            };
+            assert_eq!(principal.scram_verifier(algorithm).as_ref(), Some(&verifier));
+
+            // Successful exchange
+            let mut server = ScramServer::new(algorithm);
+            assert_eq!(server.client_first(client_first.as_bytes()), Ok("user"));
+            let server_first = server
+                .server_first_with_nonce(Some(verifier.clone()), server_nonce)
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(server_first).unwrap(),
+                format!(
+                    "r={}{},s={},i=4096",
+                    client_first.rsplit_once("r=").unwrap().1,
+                    server_nonce,
+                    salt
+                )
+            );
+            assert_eq!(
+                server.client_final(client_final.as_bytes()),
+                Ok(server_final.as_bytes().to_vec())
+            );
+            assert_eq!(server.authenticated_as(), Some("user"));
+
+            // Invalid proofs and unknown users should fail
+            for verifier in [
+                Some(ScramVerifier::from_password(
+                    algorithm,
+                    "wrong",
+                    base64_decode(salt.as_bytes()).unwrap(),
+                    4096,
+                )),
+                None,
+            ] {
+                let mut server = ScramServer::new(algorithm);
+                server.client_first(client_first.as_bytes()).unwrap();
+                server
+                    .server_first_with_nonce(verifier, server_nonce)
+                    .unwrap();
+                assert!(server.client_final(client_final.as_bytes()).is_err());
+                assert_eq!(server.authenticated_as(), None);
+            }
+        }
+
+        // Channel binding and mismatched authorization identities are rejected
+        for message in [
+            "p=tls-unique,,n=user,r=abc",
+            "n,a=admin,n=user,r=abc",
+            "n,,m=ext,n=user,r=abc",
+            "n,,n=user",
+        ] {
+            assert!(ScramServer::new(ScramAlgorithm::Sha256)
+                .client_first(message.as_bytes())
+                .is_err());
+        }
+        assert_eq!(
+            ScramServer::new(ScramAlgorithm::Sha256).client_first(b"y,a=j=2Cdoe,n=j=2Cdoe,r=abc"),
+            Ok("j,doe")
+        );
+    }
+}
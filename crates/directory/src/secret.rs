@@ -24,9 +24,10 @@
 use argon2::Argon2;
 use mail_builder::encoders::base64::base64_encode;
 use mail_parser::decoders::base64::base64_decode;
-use password_hash::PasswordHash;
+use password_hash::{PasswordHash, PasswordHasher, SaltString};
 use pbkdf2::Pbkdf2;
 use pwhash::{bcrypt, bsdi_crypt, md5_crypt, sha1_crypt, sha256_crypt, sha512_crypt, unix_crypt};
+use rand::{thread_rng, RngCore};
 use scrypt::Scrypt;
 use sha1::Digest;
 use sha1::Sha1;
@@ -34,7 +35,7 @@ use sha2::Sha256;
 use sha2::Sha512;
 use tokio::sync::oneshot;
 
-use crate::Principal;
+use crate::{scram::ScramVerifier, Principal};
 
 impl Principal {
     pub async fn verify_secret(&self, secret: &str) -> bool {
@@ -47,6 +48,29 @@ impl Principal {
     }
 }
 
+pub async fn hash_secret(secret: &str) -> Option<String> {
+    let (tx, rx) = oneshot::channel();
+    let secret = secret.to_string();
+
+    tokio::task::spawn_blocking(move || {
+        let mut salt = [0u8; 16];
+        thread_rng().fill_bytes(&mut salt);
+        tx.send(
+            SaltString::encode_b64(&salt)
+                .and_then(|salt| Argon2::default().hash_password(secret.as_bytes(), &salt))
+                .map(|hash| hash.to_string())
+                .ok(),
+        )
+        .ok();
+    });
+
+    rx.await.ok().flatten()
+}
+
+pub async fn verify_secret(hashed_secret: &str, secret: &str) -> bool {
+    verify_secret_hash(hashed_secret, secret).await
+}
+
 async fn verify_hash_prefix(hashed_secret: &str, secret: &str) -> bool {
     if hashed_secret.starts_with("$argon2")
         || hashed_secret.starts_with("$pbkdf2")
@@ -112,6 +136,10 @@ async fn verify_hash_prefix(hashed_secret: &str, secret: &str) -> bool {
 async fn verify_secret_hash(hashed_secret: &str, secret: &str) -> bool {
     if hashed_secret.starts_with('$') {
         verify_hash_prefix(hashed_secret, secret).await
+    } else if hashed_secret.starts_with("SCRAM-SHA-") {
+        // SCRAM verifier
+        ScramVerifier::parse(hashed_secret)
+            .map_or(false, |verifier| verifier.verify_password(secret))
     } else if hashed_secret.starts_with('_') {
         // Enhanced DES-based hash
         bsdi_crypt::verify(secret, hashed_secret)
@@ -207,6 +207,10 @@ impl Directory for SqlDirectory {
             .map(|id| id.is_some())
             .map_err(Into::into)
     }
+
+    fn subaddress<'x>(&self, address: &'x str) -> Option<&'x str> {
+        self.opt.subaddressing.to_subaddress_detail(address)
+    }
 }
 
 impl SqlDirectory {
@@ -0,0 +1,132 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use ahash::AHashMap;
+use mail_send::Credentials;
+
+use crate::{DatabaseColumn, Directory, Principal};
+
+/// Routes principal and address lookups to the directory of the tenant
+/// owning the domain, falling back to the default directory otherwise.
+pub struct TenantDirectory {
+    default: Arc<dyn Directory>,
+    domains: AHashMap<String, Arc<dyn Directory>>,
+}
+
+impl TenantDirectory {
+    pub fn new(default: Arc<dyn Directory>) -> Self {
+        TenantDirectory {
+            default,
+            domains: AHashMap::new(),
+        }
+    }
+
+    pub fn with_domain(mut self, domain: impl Into<String>, directory: Arc<dyn Directory>) -> Self {
+        self.domains.insert(domain.into(), directory);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    fn by_domain(&self, domain: &str) -> &Arc<dyn Directory> {
+        if !self.domains.is_empty() {
+            self.domains
+                .get(domain.to_lowercase().as_str())
+                .unwrap_or(&self.default)
+        } else {
+            &self.default
+        }
+    }
+
+    fn by_address(&self, address: &str) -> &Arc<dyn Directory> {
+        if let Some((_, domain)) = address.rsplit_once('@') {
+            self.by_domain(domain)
+        } else {
+            &self.default
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Directory for TenantDirectory {
+    async fn authenticate(
+        &self,
+        credentials: &Credentials<String>,
+    ) -> crate::Result<Option<Principal>> {
+        match credentials {
+            Credentials::Plain { username, .. }
+            | Credentials::XOauth2 { username, .. }
+            | Credentials::OAuthBearer { token: username } => {
+                self.by_address(username).authenticate(credentials).await
+            }
+        }
+    }
+
+    async fn principal(&self, name: &str) -> crate::Result<Option<Principal>> {
+        self.by_address(name).principal(name).await
+    }
+
+    async fn emails_by_name(&self, name: &str) -> crate::Result<Vec<String>> {
+        self.by_address(name).emails_by_name(name).await
+    }
+
+    async fn names_by_email(&self, address: &str) -> crate::Result<Vec<String>> {
+        self.by_address(address).names_by_email(address).await
+    }
+
+    async fn rcpt(&self, address: &str) -> crate::Result<bool> {
+        self.by_address(address).rcpt(address).await
+    }
+
+    async fn vrfy(&self, address: &str) -> crate::Result<Vec<String>> {
+        self.by_address(address).vrfy(address).await
+    }
+
+    async fn expn(&self, address: &str) -> crate::Result<Vec<String>> {
+        self.by_address(address).expn(address).await
+    }
+
+    async fn lookup(&self, query: &str, params: &[DatabaseColumn<'_>]) -> crate::Result<bool> {
+        self.default.lookup(query, params).await
+    }
+
+    async fn query(
+        &self,
+        query: &str,
+        params: &[DatabaseColumn<'_>],
+    ) -> crate::Result<Vec<DatabaseColumn<'static>>> {
+        self.default.query(query, params).await
+    }
+
+    async fn is_local_domain(&self, domain: &str) -> crate::Result<bool> {
+        self.by_domain(domain).is_local_domain(domain).await
+    }
+
+    fn subaddress<'x>(&self, address: &'x str) -> Option<&'x str> {
+        self.by_address(address).subaddress(address)
+    }
+}
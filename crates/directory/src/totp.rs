@@ -0,0 +1,151 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use hmac::{Hmac, Mac};
+use rand::{thread_rng, RngCore};
+use sha1::Sha1;
+
+const TOTP_STEP: u64 = 30;
+const TOTP_DIGITS: u32 = 6;
+const TOTP_SKEW: u64 = 1;
+const SECRET_LEN: usize = 20;
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn generate_totp_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SECRET_LEN];
+    thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts any key length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // Dynamic truncation (RFC 4226, section 5.3)
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let code = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    code % 10u32.pow(digits)
+}
+
+pub fn verify_totp(secret: &[u8], code: &str, timestamp: u64) -> bool {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize {
+        return false;
+    }
+    let code = match code.parse::<u32>() {
+        Ok(code) => code,
+        Err(_) => return false,
+    };
+
+    let counter = timestamp / TOTP_STEP;
+    (counter.saturating_sub(TOTP_SKEW)..=counter + TOTP_SKEW)
+        .any(|counter| hotp(secret, counter, TOTP_DIGITS) == code)
+}
+
+pub fn totp_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    let issuer = form_urlencode(issuer);
+    format!(
+        "otpauth://totp/{issuer}:{}?secret={}&issuer={issuer}&algorithm=SHA1&digits={TOTP_DIGITS}&period={TOTP_STEP}",
+        form_urlencode(account),
+        base32_encode(secret)
+    )
+}
+
+pub fn base32_encode(bytes: &[u8]) -> String {
+    let mut result = String::with_capacity((bytes.len() * 8 + 4) / 5);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+
+    for &byte in bytes {
+        buffer = (buffer << 8) | byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            result.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        result.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    result
+}
+
+fn form_urlencode(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for ch in value.bytes() {
+        if ch.is_ascii_alphanumeric() || matches!(ch, b'-' | b'.' | b'_' | b'~' | b'@') {
+            result.push(ch as char);
+        } else {
+            result.push_str(&format!("%{ch:02X}"));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base32_encode, hotp, totp_uri, verify_totp};
+
+    #[test]
+    fn totp_codes() {
+        // Test vectors from RFC 6238
+        let secret = b"12345678901234567890";
+        for (timestamp, code) in [
+            (59, 94287082),
+            (1111111109, 7081804),
+            (1111111111, 14050471),
+            (1234567890, 89005924),
+            (2000000000, 69279037),
+            (20000000000, 65353130),
+        ] {
+            assert_eq!(hotp(secret, timestamp / 30, 8), code, "{timestamp}");
+        }
+
+        // Codes from adjacent time steps are accepted
+        assert!(verify_totp(secret, "287082", 59));
+        assert!(verify_totp(secret, "287082", 89));
+        assert!(!verify_totp(secret, "287082", 150));
+        assert!(!verify_totp(secret, "28708", 59));
+        assert!(!verify_totp(secret, "abcdef", 59));
+
+        assert_eq!(base32_encode(secret), "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(base32_encode(b"f"), "MY");
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(
+            totp_uri("Example Mail", "jdoe@example.com", secret),
+            concat!(
+                "otpauth://totp/Example%20Mail:jdoe@example.com?",
+                "secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Example%20Mail",
+                "&algorithm=SHA1&digits=6&period=30"
+            )
+        );
+    }
+}
@@ -154,6 +154,9 @@ impl Capability {
         } else {
             capabilties.extend([
                 Capability::Auth(Mechanism::OAuthBearer),
+                Capability::Auth(Mechanism::XOauth2),
+                Capability::Auth(Mechanism::ScramSha256),
+                Capability::Auth(Mechanism::ScramSha1),
                 Capability::Auth(Mechanism::Plain),
             ]);
         }
@@ -27,7 +27,7 @@ use imap_proto::{
     receiver::{self, Request},
     Command, ResponseCode, StatusResponse,
 };
-use jmap::auth::rate_limit::AuthenticatedLimiter;
+use jmap::{auth::rate_limit::AuthenticatedLimiter, JMAP};
 use parking_lot::Mutex;
 use tokio::io::AsyncRead;
 use utils::listener::limiter::{ConcurrencyLimiter, RateLimiter};
@@ -229,7 +229,7 @@ impl<T: AsyncRead> Session<T> {
         if let State::Authenticated { data } | State::Selected { data, .. } = state {
             if !data
                 .imap
-                .get_authenticated_limiter(data.account_id)
+                .get_authenticated_limiter(&data.jmap, data.account_id)
                 .lock()
                 .request_limiter
                 .is_allowed()
@@ -379,7 +379,12 @@ impl State {
 }
 
 impl IMAP {
-    pub fn get_authenticated_limiter(&self, account_id: u32) -> Arc<Mutex<AuthenticatedLimiter>> {
+    // Method call, upload and query limits are shared with JMAP and use its settings
+    pub fn get_authenticated_limiter(
+        &self,
+        jmap: &JMAP,
+        account_id: u32,
+    ) -> Arc<Mutex<AuthenticatedLimiter>> {
         self.rate_limiter
             .get(&account_id)
             .map(|limiter| limiter.clone())
@@ -389,8 +394,17 @@ impl IMAP {
                         self.rate_requests.requests,
                         self.rate_requests.period,
                     ),
+                    method_limiter: RateLimiter::new(
+                        jmap.config.rate_method_calls.requests,
+                        jmap.config.rate_method_calls.period,
+                    ),
+                    upload_limiter: RateLimiter::new(
+                        jmap.config.rate_upload.requests,
+                        jmap.config.rate_upload.period,
+                    ),
                     concurrent_requests: ConcurrencyLimiter::new(self.rate_concurrent),
                     concurrent_uploads: ConcurrencyLimiter::new(self.rate_concurrent),
+                    concurrent_queries: ConcurrencyLimiter::new(jmap.config.query_max_concurrent),
                 }));
                 self.rate_limiter.insert(account_id, limiter.clone());
                 limiter
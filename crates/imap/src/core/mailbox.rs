@@ -3,7 +3,7 @@ use std::{collections::BTreeMap, sync::atomic::Ordering};
 use ahash::AHashMap;
 use imap_proto::{protocol::list::Attribute, StatusResponse};
 use jmap::{
-    auth::{acl::EffectiveAcl, AccessToken},
+    auth::{acl::EffectiveAcl, rate_limit::RemoteAddress, sessions::SessionProtocol, AccessToken},
     mailbox::INBOX_ID,
 };
 use jmap_proto::{
@@ -33,6 +33,13 @@ impl SessionData {
             state: access_token.state().into(),
             mailbox_locks: MutexMap::with_capacity(5),
             in_flight,
+            live_session: session.jmap.register_live_session(
+                access_token.primary_id(),
+                SessionProtocol::Imap,
+                match &session.remote_addr {
+                    RemoteAddress::IpAddress(ip) => Some(*ip),
+                },
+            ),
         };
 
         // Fetch mailboxes for the main account
@@ -545,7 +552,11 @@ impl SessionData {
                     Property::Value,
                 )
                 .await?
-                .map(|mailbox| mailbox.effective_acl(&access_token).contains(item))
+                .map(|mailbox| {
+                    mailbox
+                        .effective_acl(&access_token, account_id)
+                        .contains(item)
+                })
                 .ok_or_else(|| StatusResponse::no("Mailbox no longer exists."))?)
     }
 }
@@ -29,6 +29,7 @@ use std::{
 
 use ahash::AHashMap;
 use dashmap::DashMap;
+use directory::scram::ScramServer;
 use imap_proto::{
     protocol::{list::Attribute, ProtocolVersion},
     receiver::Receiver,
@@ -37,6 +38,7 @@ use imap_proto::{
 use jmap::{
     auth::{
         rate_limit::{AuthenticatedLimiter, RemoteAddress},
+        sessions::SessionGuard,
         AccessToken,
     },
     JMAP,
@@ -82,6 +84,7 @@ pub struct IMAP {
     pub timeout_auth: Duration,
     pub timeout_unauth: Duration,
     pub timeout_idle: Duration,
+    pub max_session_duration: Option<Duration>,
 
     pub greeting_plain: Vec<u8>,
     pub greeting_tls: Vec<u8>,
@@ -105,6 +108,8 @@ pub struct Session<T: AsyncRead> {
     pub stream_rx: ReadHalf<T>,
     pub in_flight: InFlight,
     pub remote_addr: RemoteAddress,
+    pub scram: Option<ScramServer>,
+    pub client_cert_identities: Vec<String>,
     pub span: tracing::Span,
 }
 
@@ -118,6 +123,7 @@ pub struct SessionData {
     pub writer: mpsc::Sender<writer::Event>,
     pub state: AtomicU32,
     pub in_flight: InFlight,
+    pub live_session: SessionGuard,
 }
 
 #[derive(Debug, Default)]
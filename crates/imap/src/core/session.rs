@@ -21,15 +21,23 @@
  * for more details.
 */
 
+use std::time::Instant;
+
 use imap_proto::{protocol::ProtocolVersion, receiver::Receiver};
-use jmap::auth::rate_limit::RemoteAddress;
+use jmap::auth::{
+    rate_limit::RemoteAddress,
+    sessions::{session_expired, session_revoked},
+};
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWriteExt},
     net::TcpStream,
     sync::oneshot,
 };
 use tokio_rustls::server::TlsStream;
-use utils::listener::{SessionData, SessionManager};
+use utils::{
+    config::certificate::client_certificate_identities,
+    listener::{SessionData, SessionManager},
+};
 
 use super::{writer, ImapSessionManager, Session, State};
 
@@ -57,8 +65,18 @@ impl<T: AsyncRead> Session<T> {
     pub async fn handle_conn_(&mut self) -> bool {
         let mut buf = vec![0; 8192];
         let mut shutdown_rx = self.instance.shutdown_rx.clone();
+        let session_expires = self
+            .imap
+            .max_session_duration
+            .map(|duration| Instant::now() + duration);
 
         loop {
+            let mut revoke_rx = if self.state.is_authenticated() {
+                Some(self.state.session_data().live_session.revoke_rx())
+            } else {
+                None
+            };
+
             tokio::select! {
                 result = tokio::time::timeout(
                     if !matches!(self.state, State::NotAuthenticated {..}) {
@@ -70,8 +88,19 @@ impl<T: AsyncRead> Session<T> {
                     match result {
                         Ok(Ok(bytes_read)) => {
                             if bytes_read > 0 {
+                                if self.state.is_authenticated() {
+                                    self.state.session_data().live_session.record_read(bytes_read);
+                                }
                                 match self.ingest(&buf[..bytes_read]).await {
-                                    Ok(false) => (),
+                                    Ok(false) => {
+                                        // Account for any partial request held until it is complete
+                                        if self.state.is_authenticated() {
+                                            self.state
+                                                .session_data()
+                                                .live_session
+                                                .set_memory(self.receiver.current_request_size);
+                                        }
+                                    }
                                     Ok(true) => {
                                         return true;
                                     }
@@ -101,6 +130,16 @@ impl<T: AsyncRead> Session<T> {
                     tracing::debug!(parent: &self.span, event = "shutdown", "IMAP server shutting down.");
                     break;
                 }
+                _ = session_revoked(&mut revoke_rx) => {
+                    self.write_bytes(&b"* BYE Session revoked.\r\n"[..]).await.ok();
+                    tracing::debug!(parent: &self.span, event = "disconnect", "IMAP session revoked.");
+                    break;
+                }
+                _ = session_expired(session_expires) => {
+                    self.write_bytes(&b"* BYE Maximum session duration exceeded.\r\n"[..]).await.ok();
+                    tracing::debug!(parent: &self.span, event = "disconnect", "IMAP session duration exceeded.");
+                    break;
+                }
             };
         }
 
@@ -137,6 +176,8 @@ impl Session<TcpStream> {
             span: session.span,
             in_flight: session.in_flight,
             remote_addr: RemoteAddress::IpAddress(session.remote_ip),
+            scram: None,
+            client_cert_identities: Vec::new(),
             stream_rx,
         })
     }
@@ -164,8 +205,9 @@ impl Session<TcpStream> {
         };
 
         // Upgrade to TLS
-        let (stream_rx, stream_tx) =
-            tokio::io::split(self.instance.tls_accept(stream, &self.span).await?);
+        let stream = self.instance.tls_accept(stream, &self.span).await?;
+        let client_cert_identities = client_cert_identities(&stream);
+        let (stream_rx, stream_tx) = tokio::io::split(stream);
         if let Err(err) = self.writer.send(writer::Event::StreamTls(stream_tx)).await {
             tracing::debug!("Failed to send stream: {}", err);
             return Err(());
@@ -185,6 +227,8 @@ impl Session<TcpStream> {
             span: self.span,
             in_flight: self.in_flight,
             remote_addr: self.remote_addr,
+            scram: self.scram,
+            client_cert_identities,
             stream_rx,
         })
     }
@@ -210,6 +254,7 @@ impl Session<TlsStream<TcpStream>> {
         let _ = stream.flush().await;
 
         // Spit stream into read and write halves
+        let client_cert_identities = client_cert_identities(&stream);
         let (stream_rx, stream_tx) = tokio::io::split(stream);
 
         Ok(Session {
@@ -226,6 +271,8 @@ impl Session<TlsStream<TcpStream>> {
             span,
             in_flight: session.in_flight,
             remote_addr: RemoteAddress::IpAddress(session.remote_ip),
+            scram: None,
+            client_cert_identities,
             stream_rx,
         })
     }
@@ -234,3 +281,13 @@ impl Session<TlsStream<TcpStream>> {
         self.handle_conn_().await;
     }
 }
+
+fn client_cert_identities(stream: &TlsStream<TcpStream>) -> Vec<String> {
+    stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|certs| certs.first())
+        .map(client_certificate_identities)
+        .unwrap_or_default()
+}
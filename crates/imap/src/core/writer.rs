@@ -31,7 +31,7 @@ use tokio::{
 use tokio_rustls::server::TlsStream;
 use tracing::debug;
 
-use super::{Session, SessionData};
+use super::{Session, SessionData, State};
 
 const IPC_CHANNEL_BUFFER: usize = 128;
 
@@ -137,6 +137,10 @@ impl<T: AsyncRead> Session<T> {
             String::from_utf8_lossy(&bytes[..std::cmp::min(bytes.len(), 100)])
         );*/
 
+        if let State::Authenticated { data } | State::Selected { data, .. } = &self.state {
+            data.live_session.record_write(bytes.len());
+        }
+
         if let Err(err) = self.writer.send(Event::Bytes(bytes)).await {
             debug!("Failed to send bytes: {}", err);
             Err(())
@@ -154,6 +158,8 @@ impl SessionData {
             String::from_utf8_lossy(&bytes[..std::cmp::min(bytes.len(), 100)])
         );*/
 
+        self.live_session.record_write(bytes.len());
+
         if let Err(err) = self.writer.send(Event::Bytes(bytes)).await {
             debug!("Failed to send bytes: {}", err);
             false
@@ -39,8 +39,13 @@ use jmap_proto::{
     error::method::MethodError,
     object::{index::ObjectIndexBuilder, Object},
     types::{
-        acl::Acl, collection::Collection, id::Id, property::Property, state::StateChange,
-        type_state::DataType, value::Value,
+        acl::{is_public_grantee, public_grantee_id, public_grantee_name, Acl, AclGrant},
+        collection::Collection,
+        id::Id,
+        property::Property,
+        state::StateChange,
+        type_state::DataType,
+        value::Value,
     },
 };
 use store::write::{assert::HashedValue, log::ChangeLogBuilder, BatchBuilder};
@@ -72,53 +77,33 @@ impl<T: AsyncRead> Session<T> {
                                         Some(Value::UnsignedInt(acl_bits)),
                                     ) = (item.first(), item.last())
                                     {
-                                        if let Some(account_name) = data
+                                        let account_name = if let Some(name) =
+                                            public_grantee_name(id.document_id())
+                                        {
+                                            name.to_string()
+                                        } else if let Some(account_name) = data
                                             .jmap
                                             .get_account_name(id.document_id())
                                             .await
                                             .unwrap_or_default()
                                         {
-                                            let mut rights = Vec::new();
-
-                                            for acl in Bitmap::<Acl>::from(*acl_bits) {
-                                                match acl {
-                                                    Acl::Read => {
-                                                        rights.push(Rights::Lookup);
-                                                    }
-                                                    Acl::Modify => {
-                                                        rights.push(Rights::CreateMailbox);
-                                                    }
-                                                    Acl::Delete => {
-                                                        rights.push(Rights::DeleteMailbox);
-                                                    }
-                                                    Acl::ReadItems => {
-                                                        rights.push(Rights::Read);
-                                                    }
-                                                    Acl::AddItems => {
-                                                        rights.push(Rights::Insert);
-                                                    }
-                                                    Acl::ModifyItems => {
-                                                        rights.push(Rights::Write);
-                                                        rights.push(Rights::Seen);
-                                                    }
-                                                    Acl::RemoveItems => {
-                                                        rights.push(Rights::DeleteMessages);
-                                                        rights.push(Rights::Expunge);
-                                                    }
-                                                    Acl::CreateChild => {
-                                                        rights.push(Rights::CreateMailbox);
-                                                    }
-                                                    Acl::Administer => {
-                                                        rights.push(Rights::Administer);
-                                                    }
-                                                    Acl::Submit => {
-                                                        rights.push(Rights::Post);
-                                                    }
-                                                    Acl::None => (),
-                                                }
-                                            }
+                                            account_name
+                                        } else {
+                                            continue;
+                                        };
 
-                                            permissions.push((account_name, rights));
+                                        let grant = AclGrant::from(*acl_bits);
+                                        if !grant.grant.is_empty() {
+                                            permissions.push((
+                                                account_name.clone(),
+                                                acl_rights(grant.grant),
+                                            ));
+                                        }
+                                        if !grant.deny.is_empty() {
+                                            permissions.push((
+                                                format!("-{account_name}"),
+                                                acl_rights(grant.deny),
+                                            ));
                                         }
                                     }
                                 }
@@ -180,7 +165,10 @@ impl<T: AsyncRead> Session<T> {
                                         MyRightsResponse {
                                             mailbox_name: arguments.mailbox_name,
                                             rights: if access_token.is_shared(mailbox.account_id) {
-                                                let acl = values.inner.effective_acl(&access_token);
+                                                let acl = values.inner.effective_acl(
+                                                    &access_token,
+                                                    mailbox.account_id,
+                                                );
                                                 let mut rights = Vec::with_capacity(5);
                                                 if acl.contains(Acl::ReadItems) {
                                                     rights.push(Rights::Read);
@@ -271,68 +259,75 @@ impl<T: AsyncRead> Session<T> {
 
                 tokio::spawn(async move {
                     // Validate mailbox
-                    let (mailbox, values, _) = match data.get_acl_mailbox(&arguments, true).await {
-                        Ok(Some(result)) => result,
-                        Ok(None) => {
-                            data.write_bytes(
-                                StatusResponse::no(
-                                    "ACL operations are not permitted on this mailbox.",
+                    let (mailbox, values, access_token) =
+                        match data.get_acl_mailbox(&arguments, true).await {
+                            Ok(Some(result)) => result,
+                            Ok(None) => {
+                                data.write_bytes(
+                                    StatusResponse::no(
+                                        "ACL operations are not permitted on this mailbox.",
+                                    )
+                                    .with_tag(arguments.tag)
+                                    .into_bytes(),
                                 )
-                                .with_tag(arguments.tag)
-                                .into_bytes(),
-                            )
-                            .await;
-                            return;
-                        }
-                        Err(response) => {
-                            data.write_bytes(response.with_tag(arguments.tag).into_bytes())
                                 .await;
-                            return;
-                        }
-                    };
+                                return;
+                            }
+                            Err(response) => {
+                                data.write_bytes(response.with_tag(arguments.tag).into_bytes())
+                                    .await;
+                                return;
+                            }
+                        };
 
-                    // Obtain principal id
-                    let (acl_account_id, id) = match data
-                        .jmap
-                        .directory
-                        .principal(arguments.identifier.as_ref().unwrap())
-                        .await
+                    // Obtain principal id, a leading "-" denotes negative rights
+                    let identifier = arguments.identifier.as_deref().unwrap_or_default();
+                    let (is_negative, identifier) = identifier
+                        .strip_prefix('-')
+                        .map_or((false, identifier), |identifier| (true, identifier));
+                    let (acl_account_id, id) = if let Some(account_id) =
+                        public_grantee_id(identifier)
                     {
-                        Ok(Some(principal)) => {
-                            match data.jmap.get_account_id(principal.name()).await {
-                                Ok(account_id) => (account_id, Value::Id(Id::from(account_id))),
-                                Err(_) => {
-                                    data.write_bytes(
-                                        StatusResponse::database_failure()
-                                            .with_tag(arguments.tag)
-                                            .into_bytes(),
-                                    )
-                                    .await;
-                                    return;
+                        (account_id, Value::Id(Id::from(account_id)))
+                    } else {
+                        match data.jmap.directory.principal(identifier).await {
+                            Ok(Some(principal)) => {
+                                match data.jmap.get_account_id(principal.name()).await {
+                                    Ok(account_id) => (account_id, Value::Id(Id::from(account_id))),
+                                    Err(_) => {
+                                        data.write_bytes(
+                                            StatusResponse::database_failure()
+                                                .with_tag(arguments.tag)
+                                                .into_bytes(),
+                                        )
+                                        .await;
+                                        return;
+                                    }
                                 }
                             }
-                        }
-                        Ok(None) => {
-                            data.write_bytes(
-                                StatusResponse::no("Account does not exist")
-                                    .with_tag(arguments.tag)
-                                    .into_bytes(),
-                            )
-                            .await;
-                            return;
-                        }
-                        _ => {
-                            data.write_bytes(
-                                StatusResponse::database_failure()
-                                    .with_tag(arguments.tag)
-                                    .into_bytes(),
-                            )
-                            .await;
-                            return;
+                            Ok(None) => {
+                                data.write_bytes(
+                                    StatusResponse::no("Account does not exist")
+                                        .with_tag(arguments.tag)
+                                        .into_bytes(),
+                                )
+                                .await;
+                                return;
+                            }
+                            _ => {
+                                data.write_bytes(
+                                    StatusResponse::database_failure()
+                                        .with_tag(arguments.tag)
+                                        .into_bytes(),
+                                )
+                                .await;
+                                return;
+                            }
                         }
                     };
 
                     // Prepare changes
+                    let current_acl = values.inner.get(&Property::Acl).clone();
                     let mut changes = Object::with_capacity(1);
                     let (op, rights) = arguments
                         .mod_rights
@@ -365,55 +360,85 @@ impl<T: AsyncRead> Session<T> {
                         return;
                     };
 
-                    if let Some(idx) = acl.iter().position(|item| item == &id) {
-                        match op {
-                            ModRightsOp::Replace => {
-                                if !rights.is_empty() {
-                                    acl[idx + 1] = Value::UnsignedInt(rights.into());
-                                } else {
-                                    acl.remove(idx);
-                                    acl.remove(idx);
-                                }
-                            }
-                            ModRightsOp::Add | ModRightsOp::Remove => {
-                                if let Some(Value::UnsignedInt(current)) = acl.get_mut(idx + 1) {
-                                    let mut bitmap = Bitmap::from(*current);
-                                    if op == ModRightsOp::Add {
-                                        bitmap.union(&rights);
-                                    } else {
-                                        for right in rights {
-                                            bitmap.remove(right);
-                                        }
-                                    }
-                                    if !bitmap.is_empty() {
-                                        *current = bitmap.into();
-                                    } else {
-                                        acl.remove(idx);
-                                        acl.remove(idx);
-                                    }
-                                } else {
-                                    data.write_bytes(
-                                        StatusResponse::database_failure()
-                                            .with_tag(arguments.tag)
-                                            .into_bytes(),
-                                    )
-                                    .await;
-                                    return;
+                    let idx = acl.iter().position(|item| item == &id);
+                    let mut grant = match idx.map(|idx| acl.get(idx + 1)) {
+                        Some(Some(Value::UnsignedInt(current))) => AclGrant::from(*current),
+                        Some(_) => {
+                            data.write_bytes(
+                                StatusResponse::database_failure()
+                                    .with_tag(arguments.tag)
+                                    .into_bytes(),
+                            )
+                            .await;
+                            return;
+                        }
+                        None => AclGrant::default(),
+                    };
+                    let bitmap = if is_negative {
+                        &mut grant.deny
+                    } else {
+                        &mut grant.grant
+                    };
+                    match op {
+                        ModRightsOp::Replace => {
+                            *bitmap = rights;
+                        }
+                        ModRightsOp::Add => {
+                            bitmap.union(&rights);
+                        }
+                        ModRightsOp::Remove => {
+                            for right in rights {
+                                if bitmap.contains(right) {
+                                    bitmap.remove(right);
                                 }
                             }
                         }
-                    } else if !rights.is_empty() {
-                        match op {
-                            ModRightsOp::Add | ModRightsOp::Replace => {
-                                acl.push(id);
-                                acl.push(Value::UnsignedInt(rights.into()));
+                    }
+
+                    // New shares with accounts that require acceptance are held back
+                    if !is_negative
+                        && !grant.pending
+                        && !grant.grant.is_empty()
+                        && !is_public_grantee(acl_account_id)
+                        && idx.map_or(true, |idx| {
+                            !matches!(acl.get(idx + 1), Some(Value::UnsignedInt(current))
+                                if !AclGrant::from(*current).grant.is_empty())
+                        })
+                    {
+                        match data.jmap.share_requires_acceptance(acl_account_id).await {
+                            Ok(require_acceptance) => {
+                                grant.pending = require_acceptance;
+                            }
+                            Err(_) => {
+                                data.write_bytes(
+                                    StatusResponse::database_failure()
+                                        .with_tag(arguments.tag)
+                                        .into_bytes(),
+                                )
+                                .await;
+                                return;
                             }
-                            ModRightsOp::Remove => (),
                         }
                     }
+                    match idx {
+                        Some(idx) if !grant.is_empty() => {
+                            acl[idx + 1] = Value::UnsignedInt(grant.into());
+                        }
+                        Some(idx) => {
+                            acl.remove(idx);
+                            acl.remove(idx);
+                        }
+                        None if !grant.is_empty() => {
+                            acl.push(id);
+                            acl.push(Value::UnsignedInt(grant.into()));
+                        }
+                        None => (),
+                    }
 
                     // Write changes
                     let mailbox_id = mailbox.mailbox_id.unwrap();
+                    let acl = changes.get(&Property::Acl).clone();
+                    let name = values.inner.get(&Property::Name).clone();
                     let mut batch = BatchBuilder::new();
                     batch
                         .with_account_id(mailbox.account_id)
@@ -424,6 +449,30 @@ impl<T: AsyncRead> Session<T> {
                                 .with_changes(changes)
                                 .with_current(values),
                         );
+                    let invitations = match data
+                        .jmap
+                        .share_invitations_prepare(
+                            &mut batch,
+                            mailbox.account_id,
+                            mailbox_id,
+                            &name,
+                            Some(&acl),
+                            Some(&current_acl),
+                            &access_token,
+                        )
+                        .await
+                    {
+                        Ok(invitations) => invitations,
+                        Err(_) => {
+                            data.write_bytes(
+                                StatusResponse::database_failure()
+                                    .with_tag(arguments.tag)
+                                    .into_bytes(),
+                            )
+                            .await;
+                            return;
+                        }
+                    };
                     if !batch.is_empty() {
                         match data.jmap.write_batch(batch).await {
                             Ok(_) => {
@@ -437,6 +486,9 @@ impl<T: AsyncRead> Session<T> {
                                                     .with_change(DataType::Mailbox, change_id),
                                             )
                                             .await;
+                                        data.jmap
+                                            .share_invitations_notify(invitations, &access_token)
+                                            .await;
                                     }
                                     Err(_) => {
                                         data.write_bytes(
@@ -473,7 +525,11 @@ impl<T: AsyncRead> Session<T> {
                     }
 
                     // Invalidate ACLs
-                    data.jmap.access_tokens.remove(&acl_account_id);
+                    if is_public_grantee(acl_account_id) {
+                        data.jmap.access_tokens.clear();
+                    } else {
+                        data.jmap.access_tokens.remove(&acl_account_id);
+                    }
 
                     data.write_bytes(
                         StatusResponse::completed(command)
@@ -544,7 +600,7 @@ impl SessionData {
                             || access_token.is_member(mailbox.account_id)
                             || values
                                 .inner
-                                .effective_acl(&access_token)
+                                .effective_acl(&access_token, mailbox.account_id)
                                 .contains(Acl::Administer)
                         {
                             Ok(Some((mailbox, values, access_token)))
@@ -566,3 +622,45 @@ impl SessionData {
         }
     }
 }
+
+fn acl_rights(acl: Bitmap<Acl>) -> Vec<Rights> {
+    let mut rights = Vec::new();
+    for acl in acl {
+        match acl {
+            Acl::Read => {
+                rights.push(Rights::Lookup);
+            }
+            Acl::Modify => {
+                rights.push(Rights::CreateMailbox);
+            }
+            Acl::Delete => {
+                rights.push(Rights::DeleteMailbox);
+            }
+            Acl::ReadItems => {
+                rights.push(Rights::Read);
+            }
+            Acl::AddItems => {
+                rights.push(Rights::Insert);
+            }
+            Acl::ModifyItems => {
+                rights.push(Rights::Write);
+                rights.push(Rights::Seen);
+            }
+            Acl::RemoveItems => {
+                rights.push(Rights::DeleteMessages);
+                rights.push(Rights::Expunge);
+            }
+            Acl::CreateChild => {
+                rights.push(Rights::CreateMailbox);
+            }
+            Acl::Administer => {
+                rights.push(Rights::Administer);
+            }
+            Acl::Submit => {
+                rights.push(Rights::Post);
+            }
+            Acl::None => (),
+        }
+    }
+    rights
+}
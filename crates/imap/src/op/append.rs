@@ -131,6 +131,16 @@ impl SessionData {
         let mut created_ids = Vec::with_capacity(arguments.messages.len());
         let mut last_change_id = None;
         for message in arguments.messages {
+            // Skip messages already filed when they were submitted over SMTP
+            if let Some(document_id) = self
+                .jmap
+                .claim_sent_copy(account_id, mailbox_id, &message.message)
+                .await
+            {
+                created_ids.push(document_id);
+                continue;
+            }
+
             match self
                 .jmap
                 .email_ingest(IngestEmail {
@@ -141,6 +151,9 @@ impl SessionData {
                     mailbox_ids: vec![mailbox_id],
                     keywords: message.flags.into_iter().map(Keyword::from).collect(),
                     received_at: message.received_at.map(|d| d as u64),
+                    subaddress: None,
+                    bimi_indicator: None,
+                    is_delivered: false,
                     skip_duplicates: false,
                     encrypt: self.jmap.config.encrypt && self.jmap.config.encrypt_append,
                 })
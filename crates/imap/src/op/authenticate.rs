@@ -23,11 +23,17 @@
 
 use std::sync::Arc;
 
+use directory::scram::{ScramAlgorithm, ScramServer};
 use imap_proto::{
-    protocol::{authenticate::Mechanism, capability::Capability},
+    protocol::{
+        authenticate::{self, Mechanism},
+        capability::Capability,
+    },
     receiver::{self, Request},
     Command, ResponseCode, StatusResponse,
 };
+use jmap::auth::{oauth::jwt::TokenProtocol, AccessToken};
+use mail_builder::encoders::base64::base64_encode;
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
 use tokio::io::AsyncRead;
@@ -38,14 +44,14 @@ impl<T: AsyncRead> Session<T> {
     pub async fn handle_authenticate(&mut self, request: Request<Command>) -> crate::OpResult {
         match request.parse_authenticate() {
             Ok(mut args) => match args.mechanism {
-                Mechanism::Plain | Mechanism::OAuthBearer => {
+                Mechanism::Plain | Mechanism::OAuthBearer | Mechanism::XOauth2 => {
                     if !args.params.is_empty() {
                         match base64_decode(args.params.pop().unwrap().as_bytes()) {
                             Some(challenge) => {
-                                let result = if args.mechanism == Mechanism::Plain {
-                                    decode_challenge_plain(&challenge)
-                                } else {
-                                    decode_challenge_oauth(&challenge)
+                                let result = match args.mechanism {
+                                    Mechanism::Plain => decode_challenge_plain(&challenge),
+                                    Mechanism::OAuthBearer => decode_challenge_oauth(&challenge),
+                                    _ => decode_challenge_xoauth2(&challenge),
                                 };
 
                                 match result {
@@ -80,6 +86,38 @@ impl<T: AsyncRead> Session<T> {
                         self.write_bytes(b"+ \"\"\r\n".to_vec()).await
                     }
                 }
+                Mechanism::ScramSha1 | Mechanism::ScramSha256 => self.handle_scram(args).await,
+                Mechanism::External if !self.client_cert_identities.is_empty() => {
+                    if let Some(authzid) = args.params.pop() {
+                        // An empty authorization identity is sent as "="
+                        let authzid = if authzid.is_empty() || authzid == "=" {
+                            Some(String::new())
+                        } else {
+                            base64_decode(authzid.as_bytes())
+                                .and_then(|authzid| String::from_utf8(authzid).ok())
+                        };
+
+                        if let Some(authzid) = authzid {
+                            self.authenticate_external(authzid, args.tag).await
+                        } else {
+                            self.write_bytes(
+                                StatusResponse::no("Failed to decode challenge.")
+                                    .with_tag(args.tag)
+                                    .with_code(ResponseCode::Parse)
+                                    .into_bytes(),
+                            )
+                            .await
+                        }
+                    } else {
+                        self.receiver.request = receiver::Request {
+                            tag: args.tag,
+                            command: Command::Authenticate,
+                            tokens: vec![receiver::Token::Argument(args.mechanism.into_bytes())],
+                        };
+                        self.receiver.state = receiver::State::Argument { last_ch: b' ' };
+                        self.write_bytes(b"+ \"\"\r\n".to_vec()).await
+                    }
+                }
                 _ => {
                     self.write_bytes(
                         StatusResponse::no("Authentication mechanism not supported.")
@@ -115,36 +153,206 @@ impl<T: AsyncRead> Session<T> {
 
         // Authenticate
         let access_token = match credentials {
-            Credentials::Plain { username, secret } | Credentials::XOauth2 { username, secret } => {
+            Credentials::Plain { username, secret } => {
                 self.jmap
                     .authenticate_plain(&username, &secret, &self.remote_addr)
                     .await
             }
-            Credentials::OAuthBearer { token } => {
-                match self
-                    .jmap
-                    .validate_access_token("access_token", &token)
-                    .await
-                {
-                    Ok((account_id, _, _)) => self.jmap.get_access_token(account_id).await,
-                    Err(err) => {
-                        tracing::debug!(
-                            parent: &self.span,
-                            context = "authenticate",
-                            err = err,
-                            "Failed to validate access token."
+            Credentials::OAuthBearer { token } => self.validate_token(&token, None).await,
+            Credentials::XOauth2 { username, secret } => {
+                self.validate_token(&secret, Some(&username)).await
+            }
+        };
+
+        self.complete_authentication(access_token, tag).await
+    }
+
+    async fn authenticate_external(&mut self, authzid: String, tag: String) -> crate::Result<()> {
+        let access_token = self
+            .jmap
+            .authenticate_client_cert(&self.client_cert_identities)
+            .await
+            .filter(|access_token| authzid.is_empty() || authzid == access_token.name);
+
+        self.complete_authentication(access_token, tag).await
+    }
+
+    async fn validate_token(&self, token: &str, username: Option<&str>) -> Option<AccessToken> {
+        match self
+            .jmap
+            .validate_access_token("access_token", token, TokenProtocol::Imap.into())
+            .await
+        {
+            Ok((account_id, _, _)) => {
+                let access_token = self.jmap.get_access_token(account_id).await?;
+                if username.map_or(true, |username| username == access_token.name) {
+                    Some(access_token)
+                } else {
+                    tracing::debug!(
+                        parent: &self.span,
+                        context = "authenticate",
+                        "Access token does not belong to the requested account."
+                    );
+                    None
+                }
+            }
+            Err(err) => {
+                tracing::debug!(
+                    parent: &self.span,
+                    context = "authenticate",
+                    err = err,
+                    "Failed to validate access token."
+                );
+                None
+            }
+        }
+    }
+
+    async fn handle_scram(&mut self, mut args: authenticate::Arguments) -> crate::OpResult {
+        let algorithm = if args.mechanism == Mechanism::ScramSha256 {
+            ScramAlgorithm::Sha256
+        } else {
+            ScramAlgorithm::Sha1
+        };
+        let mut scram = self
+            .scram
+            .take()
+            .filter(|scram| scram.algorithm() == algorithm)
+            .unwrap_or_else(|| ScramServer::new(algorithm));
+
+        let challenge = match args.params.pop().filter(|param| !param.is_empty()) {
+            Some(response) => {
+                let response = match base64_decode(response.as_bytes()) {
+                    Some(response) => response,
+                    None => {
+                        return self
+                            .write_bytes(
+                                StatusResponse::no("Failed to decode challenge.")
+                                    .with_tag(args.tag)
+                                    .with_code(ResponseCode::Parse)
+                                    .into_bytes(),
+                            )
+                            .await;
+                    }
+                };
+
+                if scram.is_initial() {
+                    // Throttle authentication requests
+                    if self.jmap.is_auth_allowed_soft(&self.remote_addr).is_err() {
+                        self.write_bytes(
+                            StatusResponse::bye(
+                                "Too many authentication requests from this IP address.",
+                            )
+                            .into_bytes(),
+                        )
+                        .await?;
+                        tracing::debug!(parent: &self.span,
+                            event = "disconnect",
+                            "Too many authentication attempts, disconnecting.",
                         );
-                        None
+                        return Err(());
                     }
+
+                    let username = match scram.client_first(&response) {
+                        Ok(username) => username.to_string(),
+                        Err(err) => {
+                            return self
+                                .write_bytes(
+                                    StatusResponse::no(err)
+                                        .with_tag(args.tag)
+                                        .with_code(ResponseCode::Parse)
+                                        .into_bytes(),
+                                )
+                                .await;
+                        }
+                    };
+                    let verifier = match self.jmap.directory.principal(&username).await {
+                        Ok(principal) => {
+                            principal.and_then(|principal| principal.scram_verifier(algorithm))
+                        }
+                        Err(_) => {
+                            return self
+                                .write_bytes(
+                                    StatusResponse::no("Temporary authentication failure.")
+                                        .with_tag(args.tag)
+                                        .with_code(ResponseCode::Unavailable)
+                                        .into_bytes(),
+                                )
+                                .await;
+                        }
+                    };
+                    scram.server_first(verifier)
+                } else if scram.is_client_final() {
+                    match scram.client_final(&response) {
+                        Ok(challenge) => Ok(challenge),
+                        Err(err) => {
+                            tracing::debug!(
+                                parent: &self.span,
+                                context = "authenticate",
+                                mechanism = algorithm.name(),
+                                err = err,
+                                "SCRAM authentication failed."
+                            );
+                            let _ = self.jmap.is_auth_allowed_hard(&self.remote_addr);
+                            return self.complete_authentication(None, args.tag).await;
+                        }
+                    }
+                } else {
+                    Err("Unexpected SCRAM message.")
+                }
+            }
+            None if scram.is_initial() => Ok(Vec::new()),
+            None => {
+                if let Some(username) = scram.authenticated_as() {
+                    // Client acknowledged the server signature
+                    let access_token = match self.jmap.get_account_id(username).await {
+                        Ok(account_id) => self.jmap.get_access_token(account_id).await,
+                        Err(_) => None,
+                    };
+                    return self.complete_authentication(access_token, args.tag).await;
                 }
+                Err("Unexpected SCRAM message.")
             }
         };
 
+        match challenge {
+            Ok(challenge) => {
+                self.scram = Some(scram);
+                self.receiver.request = receiver::Request {
+                    tag: args.tag,
+                    command: Command::Authenticate,
+                    tokens: vec![receiver::Token::Argument(args.mechanism.into_bytes())],
+                };
+                self.receiver.state = receiver::State::Argument { last_ch: b' ' };
+
+                let mut buf = Vec::with_capacity(challenge.len() * 2);
+                buf.extend_from_slice(b"+ ");
+                buf.extend_from_slice(&base64_encode(&challenge).unwrap_or_default());
+                buf.extend_from_slice(b"\r\n");
+                self.write_bytes(buf).await
+            }
+            Err(err) => {
+                self.write_bytes(
+                    StatusResponse::no(err)
+                        .with_tag(args.tag)
+                        .with_code(ResponseCode::Parse)
+                        .into_bytes(),
+                )
+                .await
+            }
+        }
+    }
+
+    pub async fn complete_authentication(
+        &mut self,
+        access_token: Option<AccessToken>,
+        tag: String,
+    ) -> crate::Result<()> {
         if let Some(access_token) = access_token {
             // Enforce concurrency limits
             let in_flight = self
                 .imap
-                .get_authenticated_limiter(access_token.primary_id())
+                .get_authenticated_limiter(&self.jmap, access_token.primary_id())
                 .lock()
                 .concurrent_requests
                 .is_allowed();
@@ -278,3 +486,25 @@ pub fn decode_challenge_oauth(challenge: &[u8]) -> Result<Credentials<String>, &
 
     Err("Failed to find 'auth=Bearer' in challenge.")
 }
+
+pub fn decode_challenge_xoauth2(challenge: &[u8]) -> Result<Credentials<String>, &'static str> {
+    let mut username = None;
+    let mut token = None;
+    for part in challenge.split(|&ch| ch == 0x01) {
+        if let Some(value) = part.strip_prefix(b"user=") {
+            username = std::str::from_utf8(value).ok();
+        } else if let Some(value) = part.strip_prefix(b"auth=Bearer ") {
+            token = std::str::from_utf8(value).ok();
+        }
+    }
+
+    match (username, token) {
+        (Some(username), Some(token)) if !username.is_empty() && !token.is_empty() => {
+            Ok(Credentials::XOauth2 {
+                username: username.to_string(),
+                secret: token.to_string(),
+            })
+        }
+        _ => Err("Invalid AUTH=XOAUTH2 challenge."),
+    }
+}
@@ -23,6 +23,7 @@
 
 use imap_proto::{
     protocol::{
+        authenticate::Mechanism,
         capability::{Capability, Response},
         ImapResponse,
     },
@@ -36,18 +37,16 @@ use crate::core::Session;
 
 impl<T: AsyncRead> Session<T> {
     pub async fn handle_capability(&mut self, request: Request<Command>) -> crate::OpResult {
+        let mut capabilities =
+            Capability::all_capabilities(self.state.is_authenticated(), self.is_tls);
+        if !self.state.is_authenticated() && !self.client_cert_identities.is_empty() {
+            capabilities.push(Capability::Auth(Mechanism::External));
+        }
+
         self.write_bytes(
             StatusResponse::completed(Command::Capability)
                 .with_tag(request.tag)
-                .serialize(
-                    Response {
-                        capabilities: Capability::all_capabilities(
-                            self.state.is_authenticated(),
-                            self.is_tls,
-                        ),
-                    }
-                    .serialize(),
-                ),
+                .serialize(Response { capabilities }.serialize()),
         )
         .await
     }
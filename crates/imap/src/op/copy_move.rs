@@ -28,7 +28,7 @@ use imap_proto::{
     StatusResponse,
 };
 
-use jmap::email::set::TagManager;
+use jmap::email::{set::TagManager, spam_train::spam_train_direction};
 use jmap_proto::{
     error::{method::MethodError, set::SetErrorType},
     types::{
@@ -279,6 +279,29 @@ impl SessionData {
                     }
                 }
             }
+
+            // Train the spam filter on moves between the Inbox and Junk folders
+            if let (Some(src_mailbox_id), false) =
+                (src_mailbox.id.mailbox_id, copied_ids.is_empty())
+            {
+                if let Some(is_spam) = self
+                    .jmap
+                    .spam_train_junk_id(account_id)
+                    .await
+                    .map_err(|_| StatusResponse::database_failure().with_tag(&arguments.tag))?
+                    .and_then(|junk_id| {
+                        spam_train_direction(junk_id, &[src_mailbox_id], &[dest_mailbox_id])
+                    })
+                {
+                    self.jmap
+                        .spam_train_enqueue(
+                            account_id,
+                            copied_ids.iter().map(|(_, id)| *id).collect(),
+                            is_spam,
+                        )
+                        .await;
+                }
+            }
         } else {
             // Obtain quota for target account
             let src_account_id = src_mailbox.id.account_id;
@@ -302,7 +325,7 @@ impl SessionData {
                         dest_account_id,
                         dest_quota,
                         vec![dest_mailbox_id],
-                        Vec::new(),
+                        None,
                         None,
                     )
                     .await
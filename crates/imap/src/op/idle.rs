@@ -35,6 +35,7 @@ use imap_proto::{
     Command, ResponseCode, StatusResponse,
 };
 
+use jmap::auth::sessions::session_revoked;
 use jmap_proto::types::{collection::Collection, type_state::DataType};
 use store::query::log::Query;
 use tokio::io::{AsyncRead, AsyncReadExt};
@@ -81,6 +82,7 @@ impl<T: AsyncRead> Session<T> {
             .await?;
         tracing::debug!(parent: &self.span, event = "stat", context = "idle", "Starting IDLE.");
         let mut buf = vec![0; 1024];
+        let mut revoke_rx = Some(data.live_session.revoke_rx());
         loop {
             tokio::select! {
                 result = tokio::time::timeout(self.imap.timeout_idle, self.stream_rx.read(&mut buf)) => {
@@ -135,6 +137,11 @@ impl<T: AsyncRead> Session<T> {
                         return Err(());
                     }
                 }
+                _ = session_revoked(&mut revoke_rx) => {
+                    self.write_bytes(&b"* BYE Session revoked.\r\n"[..]).await.ok();
+                    tracing::debug!(parent: &self.span, event = "disconnect", "IMAP session revoked.");
+                    return Err(());
+                }
             }
         }
     }
@@ -128,7 +128,7 @@ impl SessionData {
         if access_token.is_shared(params.account_id)
             && !mailbox
                 .inner
-                .effective_acl(&access_token)
+                .effective_acl(&access_token, params.account_id)
                 .contains(Acl::Modify)
         {
             return StatusResponse::no("You are not allowed to rename this mailbox.")
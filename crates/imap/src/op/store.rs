@@ -33,7 +33,7 @@ use imap_proto::{
     receiver::Request,
     Command, ResponseCode, ResponseType, StatusResponse,
 };
-use jmap::email::set::TagManager;
+use jmap::email::{keyword::RegisterKeywords, set::TagManager};
 use jmap_proto::{
     error::method::MethodError,
     types::{
@@ -261,7 +261,18 @@ impl SessionData {
                 batch
                     .with_account_id(account_id)
                     .with_collection(Collection::Email)
-                    .update_document(id);
+                    .update_document(id)
+                    .register_keywords(account_id, keywords.added())
+                    .unregister_keywords(
+                        account_id,
+                        self.jmap
+                            .unused_keywords(account_id, id, keywords.removed())
+                            .await
+                            .map_err(|_| {
+                                StatusResponse::database_failure()
+                                    .with_tag(response.tag.as_ref().unwrap())
+                            })?,
+                    );
                 keywords.update_batch(&mut batch, Property::Keywords);
                 if changelog.change_id == u64::MAX {
                     changelog.change_id =
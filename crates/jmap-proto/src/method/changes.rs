@@ -69,6 +69,7 @@ pub enum RequestArguments {
     Identity,
     EmailSubmission,
     Quota,
+    Principal,
 }
 
 impl JsonObjectParser for ChangesRequest {
@@ -84,6 +85,7 @@ impl JsonObjectParser for ChangesRequest {
                 MethodObject::Identity => RequestArguments::Identity,
                 MethodObject::EmailSubmission => RequestArguments::EmailSubmission,
                 MethodObject::Quota => RequestArguments::Quota,
+                MethodObject::Principal => RequestArguments::Principal,
                 _ => {
                     return Err(Error::Method(MethodError::UnknownMethod(format!(
                         "{}/changes",
@@ -54,6 +54,8 @@ pub enum RequestArguments {
     Principal,
     Quota,
     Blob(blob::GetArguments),
+    MaskedEmail,
+    ShareInvitation,
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -89,6 +91,8 @@ impl JsonObjectParser for GetRequest<RequestArguments> {
                 MethodObject::Principal => RequestArguments::Principal,
                 MethodObject::Blob => RequestArguments::Blob(Default::default()),
                 MethodObject::Quota => RequestArguments::Quota,
+                MethodObject::MaskedEmail => RequestArguments::MaskedEmail,
+                MethodObject::ShareInvitation => RequestArguments::ShareInvitation,
                 _ => {
                     return Err(Error::Method(MethodError::UnknownMethod(format!(
                         "{}/get",
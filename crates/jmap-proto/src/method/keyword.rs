@@ -0,0 +1,160 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use utils::map::vec_map::VecMap;
+
+use crate::{
+    error::set::SetError,
+    parser::{json::Parser, Ignore, JsonObjectParser, Token},
+    request::RequestProperty,
+    types::{id::Id, keyword::Keyword, state::State},
+};
+
+#[derive(Debug, Clone)]
+pub struct KeywordGetRequest {
+    pub account_id: Id,
+    pub ids: Option<Vec<Keyword>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct KeywordGetResponse {
+    #[serde(rename = "accountId")]
+    pub account_id: Id,
+
+    #[serde(rename = "state")]
+    pub state: State,
+
+    #[serde(rename = "list")]
+    pub list: Vec<KeywordCount>,
+
+    #[serde(rename = "notFound")]
+    pub not_found: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct KeywordCount {
+    #[serde(rename = "id")]
+    pub id: String,
+
+    #[serde(rename = "totalEmails")]
+    pub total_emails: u64,
+
+    #[serde(rename = "unreadEmails")]
+    pub unread_emails: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct KeywordRenameRequest {
+    pub account_id: Id,
+    pub rename: VecMap<Keyword, Keyword>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct KeywordRenameResponse {
+    #[serde(rename = "accountId")]
+    pub account_id: Id,
+
+    #[serde(rename = "oldState")]
+    pub old_state: State,
+
+    #[serde(rename = "newState")]
+    pub new_state: State,
+
+    #[serde(rename = "renamed")]
+    #[serde(skip_serializing_if = "VecMap::is_empty")]
+    pub renamed: VecMap<String, u64>,
+
+    #[serde(rename = "notRenamed")]
+    #[serde(skip_serializing_if = "VecMap::is_empty")]
+    pub not_renamed: VecMap<String, SetError>,
+}
+
+impl JsonObjectParser for KeywordGetRequest {
+    fn parse(parser: &mut Parser<'_>) -> crate::parser::Result<Self>
+    where
+        Self: Sized,
+    {
+        let mut request = KeywordGetRequest {
+            account_id: Id::default(),
+            ids: None,
+        };
+
+        parser
+            .next_token::<String>()?
+            .assert_jmap(Token::DictStart)?;
+
+        while let Some(key) = parser.next_dict_key::<RequestProperty>()? {
+            match &key.hash[0] {
+                0x0064_4974_6e75_6f63_6361 if !key.is_ref => {
+                    request.account_id = parser.next_token::<Id>()?.unwrap_string("accountId")?;
+                }
+                0x0073_6469 if !key.is_ref => {
+                    request.ids = <Option<Vec<Keyword>>>::parse(parser)?;
+                }
+                _ => {
+                    parser.skip_token(parser.depth_array, parser.depth_dict)?;
+                }
+            }
+        }
+
+        Ok(request)
+    }
+}
+
+impl JsonObjectParser for KeywordRenameRequest {
+    fn parse(parser: &mut Parser<'_>) -> crate::parser::Result<Self>
+    where
+        Self: Sized,
+    {
+        let mut request = KeywordRenameRequest {
+            account_id: Id::default(),
+            rename: VecMap::new(),
+        };
+
+        parser
+            .next_token::<String>()?
+            .assert_jmap(Token::DictStart)?;
+
+        while let Some(key) = parser.next_dict_key::<RequestProperty>()? {
+            match &key.hash[0] {
+                0x0064_4974_6e75_6f63_6361 if !key.is_ref => {
+                    request.account_id = parser.next_token::<Id>()?.unwrap_string("accountId")?;
+                }
+                0x656d_616e_6572 => {
+                    parser
+                        .next_token::<Ignore>()?
+                        .assert_jmap(Token::DictStart)?;
+                    while let Some(from) = parser.next_dict_key::<Keyword>()? {
+                        let to = parser.next_token::<Keyword>()?.unwrap_string("rename")?;
+                        request.rename.append(from, to);
+                    }
+                }
+                _ => {
+                    parser.skip_token(parser.depth_array, parser.depth_dict)?;
+                }
+            }
+        }
+
+        Ok(request)
+    }
+}
@@ -0,0 +1,257 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use utils::map::vec_map::VecMap;
+
+use crate::{
+    error::set::SetError,
+    parser::{json::Parser, Ignore, JsonObjectParser, Token},
+    request::RequestProperty,
+    types::{date::UTCDate, id::Id},
+};
+
+#[derive(Debug, Clone)]
+pub struct MailingListGetRequest {
+    pub account_id: Id,
+    pub ids: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MailingListGetResponse {
+    #[serde(rename = "accountId")]
+    pub account_id: Id,
+
+    #[serde(rename = "list")]
+    pub list: Vec<MailingList>,
+
+    #[serde(rename = "notFound")]
+    pub not_found: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MailingList {
+    #[serde(rename = "id")]
+    pub id: String,
+
+    #[serde(rename = "name")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+
+    #[serde(rename = "sender")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+
+    #[serde(rename = "mailboxId")]
+    pub mailbox_id: Option<Id>,
+
+    #[serde(rename = "unsubscribe")]
+    pub unsubscribe: Vec<String>,
+
+    #[serde(rename = "oneClick")]
+    pub one_click: bool,
+
+    #[serde(rename = "digest")]
+    pub digest: bool,
+
+    #[serde(rename = "totalEmails")]
+    pub total_emails: u64,
+
+    #[serde(rename = "firstSeenAt")]
+    pub first_seen_at: UTCDate,
+
+    #[serde(rename = "lastSeenAt")]
+    pub last_seen_at: UTCDate,
+
+    #[serde(rename = "unsubscribedAt")]
+    pub unsubscribed_at: Option<UTCDate>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MailingListSetRequest {
+    pub account_id: Id,
+    pub update: VecMap<String, MailingListUpdate>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MailingListUpdate {
+    pub mailbox_id: Option<Option<Id>>,
+    pub digest: Option<bool>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MailingListSetResponse {
+    #[serde(rename = "accountId")]
+    pub account_id: Id,
+
+    #[serde(rename = "updated")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub updated: Vec<String>,
+
+    #[serde(rename = "notUpdated")]
+    #[serde(skip_serializing_if = "VecMap::is_empty")]
+    pub not_updated: VecMap<String, SetError>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MailingListUnsubscribeRequest {
+    pub account_id: Id,
+    pub ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MailingListUnsubscribeResponse {
+    #[serde(rename = "accountId")]
+    pub account_id: Id,
+
+    #[serde(rename = "unsubscribed")]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unsubscribed: Vec<String>,
+
+    #[serde(rename = "notUnsubscribed")]
+    #[serde(skip_serializing_if = "VecMap::is_empty")]
+    pub not_unsubscribed: VecMap<String, SetError>,
+}
+
+impl JsonObjectParser for MailingListGetRequest {
+    fn parse(parser: &mut Parser<'_>) -> crate::parser::Result<Self>
+    where
+        Self: Sized,
+    {
+        let mut request = MailingListGetRequest {
+            account_id: Id::default(),
+            ids: None,
+        };
+
+        parser
+            .next_token::<String>()?
+            .assert_jmap(Token::DictStart)?;
+
+        while let Some(key) = parser.next_dict_key::<RequestProperty>()? {
+            match &key.hash[0] {
+                0x0064_4974_6e75_6f63_6361 if !key.is_ref => {
+                    request.account_id = parser.next_token::<Id>()?.unwrap_string("accountId")?;
+                }
+                0x0073_6469 if !key.is_ref => {
+                    request.ids = <Option<Vec<String>>>::parse(parser)?;
+                }
+                _ => {
+                    parser.skip_token(parser.depth_array, parser.depth_dict)?;
+                }
+            }
+        }
+
+        Ok(request)
+    }
+}
+
+impl JsonObjectParser for MailingListSetRequest {
+    fn parse(parser: &mut Parser<'_>) -> crate::parser::Result<Self>
+    where
+        Self: Sized,
+    {
+        let mut request = MailingListSetRequest {
+            account_id: Id::default(),
+            update: VecMap::new(),
+        };
+
+        parser
+            .next_token::<String>()?
+            .assert_jmap(Token::DictStart)?;
+
+        while let Some(key) = parser.next_dict_key::<RequestProperty>()? {
+            match &key.hash[0] {
+                0x0064_4974_6e75_6f63_6361 if !key.is_ref => {
+                    request.account_id = parser.next_token::<Id>()?.unwrap_string("accountId")?;
+                }
+                0x6574_6164_7075 if !key.is_ref => {
+                    parser
+                        .next_token::<Ignore>()?
+                        .assert_jmap(Token::DictStart)?;
+                    while let Some(id) = parser.next_dict_key::<String>()? {
+                        let mut update = MailingListUpdate::default();
+                        parser
+                            .next_token::<Ignore>()?
+                            .assert_jmap(Token::DictStart)?;
+                        while let Some(property) = parser.next_dict_key::<RequestProperty>()? {
+                            match &property.hash[0] {
+                                0x0064_4978_6f62_6c69_616d if !property.is_ref => {
+                                    update.mailbox_id = parser
+                                        .next_token::<Id>()?
+                                        .unwrap_string_or_null("mailboxId")?
+                                        .into();
+                                }
+                                0x7473_6567_6964 if !property.is_ref => {
+                                    update.digest = parser
+                                        .next_token::<Ignore>()?
+                                        .unwrap_bool("digest")?
+                                        .into();
+                                }
+                                _ => {
+                                    parser.skip_token(parser.depth_array, parser.depth_dict)?;
+                                }
+                            }
+                        }
+                        request.update.append(id, update);
+                    }
+                }
+                _ => {
+                    parser.skip_token(parser.depth_array, parser.depth_dict)?;
+                }
+            }
+        }
+
+        Ok(request)
+    }
+}
+
+impl JsonObjectParser for MailingListUnsubscribeRequest {
+    fn parse(parser: &mut Parser<'_>) -> crate::parser::Result<Self>
+    where
+        Self: Sized,
+    {
+        let mut request = MailingListUnsubscribeRequest {
+            account_id: Id::default(),
+            ids: Vec::new(),
+        };
+
+        parser
+            .next_token::<String>()?
+            .assert_jmap(Token::DictStart)?;
+
+        while let Some(key) = parser.next_dict_key::<RequestProperty>()? {
+            match &key.hash[0] {
+                0x0064_4974_6e75_6f63_6361 if !key.is_ref => {
+                    request.account_id = parser.next_token::<Id>()?.unwrap_string("accountId")?;
+                }
+                0x0073_6469 if !key.is_ref => {
+                    request.ids = <Vec<String>>::parse(parser)?;
+                }
+                _ => {
+                    parser.skip_token(parser.depth_array, parser.depth_dict)?;
+                }
+            }
+        }
+
+        Ok(request)
+    }
+}
@@ -24,10 +24,14 @@
 use ahash::AHashMap;
 
 pub mod changes;
+pub mod collected_address;
 pub mod copy;
 pub mod get;
 pub mod import;
+pub mod keyword;
 pub mod lookup;
+pub mod mailing_list;
+pub mod mdn;
 pub mod parse;
 pub mod query;
 pub mod query_changes;
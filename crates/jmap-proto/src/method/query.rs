@@ -115,6 +115,7 @@ pub enum Filter {
     IsActive(bool),
     Scope(String),
     ResourceType(String),
+    Subaddress(String),
     _T(String),
 
     And,
@@ -441,6 +442,9 @@ pub fn parse_filter(parser: &mut Parser) -> crate::parser::Result<Vec<Filter>> {
                                 .next_token::<String>()?
                                 .unwrap_string("resourceType")?,
                         ),
+                        (0x7373_6572_6464_6162_7573, _) => Filter::Subaddress(
+                            parser.next_token::<String>()?.unwrap_string("subaddress")?,
+                        ),
                         _ => {
                             if parser.is_eof || parser.skip_string() {
                                 let filter = Filter::_T(
@@ -645,6 +649,7 @@ impl Display for Filter {
             Filter::IsActive(_) => "isActive",
             Filter::ResourceType(_) => "resourceType",
             Filter::Scope(_) => "scope",
+            Filter::Subaddress(_) => "subaddress",
             Filter::_T(v) => v.as_str(),
             Filter::And => "and",
             Filter::Or => "or",
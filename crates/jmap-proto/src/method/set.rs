@@ -71,6 +71,9 @@ pub enum RequestArguments {
     PushSubscription,
     SieveScript(sieve::SetArguments),
     VacationResponse,
+    MaskedEmail,
+    ShareInvitation,
+    Principal,
 }
 
 #[derive(Debug, Clone, Default, serde::Serialize)]
@@ -131,6 +134,9 @@ impl JsonObjectParser for SetRequest<RequestArguments> {
                 MethodObject::PushSubscription => RequestArguments::PushSubscription,
                 MethodObject::VacationResponse => RequestArguments::VacationResponse,
                 MethodObject::SieveScript => RequestArguments::SieveScript(Default::default()),
+                MethodObject::MaskedEmail => RequestArguments::MaskedEmail,
+                MethodObject::ShareInvitation => RequestArguments::ShareInvitation,
+                MethodObject::Principal => RequestArguments::Principal,
                 _ => {
                     return Err(Error::Method(MethodError::UnknownMethod(format!(
                         "{}/set",
@@ -214,7 +220,10 @@ impl JsonObjectParser for Object<SetValue> {
                     | Property::ReceivedAt
                     | Property::Expires
                     | Property::FromDate
-                    | Property::ToDate => parser
+                    | Property::ToDate
+                    | Property::SnoozedUntil
+                    | Property::CreatedAt
+                    | Property::LastMessageAt => parser
                         .next_token::<UTCDate>()?
                         .unwrap_string_or_null("")?
                         .map(|date| SetValue::Value(Value::Date(date)))
@@ -238,7 +247,11 @@ impl JsonObjectParser for Object<SetValue> {
                     | Property::Location
                     | Property::Cid
                     | Property::Role
-                    | Property::PartId => parser
+                    | Property::PartId
+                    | Property::State
+                    | Property::ForDomain
+                    | Property::CreatedBy
+                    | Property::EmailPrefix => parser
                         .next_token::<String>()?
                         .unwrap_string_or_null("")?
                         .map(|text| SetValue::Value(Value::Text(text)))
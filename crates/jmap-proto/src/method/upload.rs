@@ -22,15 +22,14 @@
 */
 
 use ahash::AHashMap;
-use mail_parser::decoders::base64::base64_decode;
 use utils::map::vec_map::VecMap;
 
 use crate::{
     error::set::SetError,
-    parser::{json::Parser, Ignore, JsonObjectParser, Token},
+    parser::{base64::Base64Data, json::Parser, Ignore, JsonObjectParser, Token},
     request::{reference::MaybeReference, RequestProperty},
     response::Response,
-    types::{blob::BlobId, id::Id},
+    types::{blob::BlobId, id::Id, property::DigestProperty},
 };
 
 use super::ahash_is_empty;
@@ -45,6 +44,7 @@ pub struct BlobUploadRequest {
 pub struct UploadObject {
     pub type_: Option<String>,
     pub data: Vec<DataSourceObject>,
+    pub digest: Vec<(DigestProperty, String)>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -120,6 +120,7 @@ impl JsonObjectParser for UploadObject {
         let mut request = UploadObject {
             type_: None,
             data: Vec::new(),
+            digest: Vec::new(),
         };
 
         parser
@@ -133,6 +134,28 @@ impl JsonObjectParser for UploadObject {
                         .next_token::<String>()?
                         .unwrap_string_or_null("type")?;
                 }
+                0x6168_733a_7473_6567_6964 if !key.is_ref => {
+                    request.digest.push((
+                        DigestProperty::Sha,
+                        parser.next_token::<String>()?.unwrap_string("digest:sha")?,
+                    ));
+                }
+                0x3635_322d_6168_733a_7473_6567_6964 if !key.is_ref => {
+                    request.digest.push((
+                        DigestProperty::Sha256,
+                        parser
+                            .next_token::<String>()?
+                            .unwrap_string("digest:sha-256")?,
+                    ));
+                }
+                0x3231_352d_6168_733a_7473_6567_6964 if !key.is_ref => {
+                    request.digest.push((
+                        DigestProperty::Sha512,
+                        parser
+                            .next_token::<String>()?
+                            .unwrap_string("digest:sha-512")?,
+                    ));
+                }
                 0x6174_6164 if !key.is_ref => {
                     parser.next_token::<Ignore>()?.assert(Token::ArrayStart)?;
                     loop {
@@ -176,14 +199,11 @@ impl JsonObjectParser for DataSourceObject {
                         .into();
                 }
                 0x0034_3665_7361_4273_613a_6174_6164 if !key.is_ref => {
-                    data = base64_decode(
-                        parser
-                            .next_token::<String>()?
-                            .unwrap_string("data:asBase64")?
-                            .as_bytes(),
-                    )
-                    .ok_or_else(|| parser.error("Failed to decode data:asBase64"))?
-                    .into();
+                    data = parser
+                        .next_token::<Base64Data>()?
+                        .unwrap_string("data:asBase64")?
+                        .0
+                        .into();
                 }
                 0x6449_626f_6c62 if !key.is_ref => {
                     blob_id = parser
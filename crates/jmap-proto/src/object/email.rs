@@ -34,6 +34,7 @@ pub struct GetArguments {
     pub fetch_html_body_values: Option<bool>,
     pub fetch_all_body_values: Option<bool>,
     pub max_body_value_bytes: Option<usize>,
+    pub convert_body_parts: Option<bool>,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -71,6 +72,11 @@ impl RequestPropertyParser for GetArguments {
                     .next_token::<Ignore>()?
                     .unwrap_usize_or_null("maxBodyValueBytes")?;
             }
+            (0x7374_7261_5079_646f_4274_7265_766e_6f63, 0) => {
+                self.convert_body_parts = parser
+                    .next_token::<Ignore>()?
+                    .unwrap_bool_or_null("convertBodyParts")?;
+            }
             _ => return Ok(false),
         }
 
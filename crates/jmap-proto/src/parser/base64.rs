@@ -0,0 +1,171 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use super::{json::Parser, JsonObjectParser};
+
+/// Base64 encoded JSON string, decoded while it is being read
+/// from the request in order to avoid buffering the encoded text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Base64Data(pub Vec<u8>);
+
+#[derive(Debug, Default)]
+struct Base64Decoder {
+    bytes: Vec<u8>,
+    chunk: u32,
+    chunk_len: u8,
+    padding: u8,
+}
+
+impl Base64Decoder {
+    fn with_capacity(capacity: usize) -> Self {
+        Base64Decoder {
+            bytes: Vec::with_capacity(capacity),
+            ..Default::default()
+        }
+    }
+
+    #[inline(always)]
+    fn feed(&mut self, ch: u8) -> bool {
+        let value = match ch {
+            b'A'..=b'Z' => ch - b'A',
+            b'a'..=b'z' => ch - b'a' + 26,
+            b'0'..=b'9' => ch - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => {
+                self.padding += 1;
+                return self.padding <= 2 && self.chunk_len >= 2;
+            }
+            b' ' | b'\t' | b'\r' | b'\n' => return true,
+            _ => return false,
+        };
+        if self.padding > 0 {
+            return false;
+        }
+
+        self.chunk = (self.chunk << 6) | value as u32;
+        self.chunk_len += 1;
+        if self.chunk_len == 4 {
+            self.bytes.extend_from_slice(&[
+                (self.chunk >> 16) as u8,
+                (self.chunk >> 8) as u8,
+                self.chunk as u8,
+            ]);
+            self.chunk = 0;
+            self.chunk_len = 0;
+        }
+        true
+    }
+
+    fn finish(mut self) -> Option<Vec<u8>> {
+        match (self.chunk_len, self.padding) {
+            (0, 0) => (),
+            (2, 0 | 2) => {
+                self.bytes.push((self.chunk >> 4) as u8);
+            }
+            (3, 0 | 1) => {
+                self.bytes
+                    .extend_from_slice(&[(self.chunk >> 10) as u8, (self.chunk >> 2) as u8]);
+            }
+            _ => return None,
+        }
+        Some(self.bytes)
+    }
+}
+
+impl JsonObjectParser for Base64Data {
+    fn parse(parser: &mut Parser<'_>) -> super::Result<Self>
+    where
+        Self: Sized,
+    {
+        // Size the output buffer using the length of the encoded string
+        let encoded_len = parser
+            .bytes
+            .get(parser.pos..)
+            .and_then(|bytes| bytes.iter().position(|&ch| ch == b'"'))
+            .unwrap_or(0);
+        let mut decoder = Base64Decoder::with_capacity(encoded_len / 4 * 3 + 3);
+
+        loop {
+            let ch = match parser.next_char() {
+                Some(b'"') => {
+                    parser.is_eof = true;
+                    break;
+                }
+                Some(b'\\') => match parser.next_char() {
+                    Some(b'/') => b'/',
+                    Some(b'n' | b'r' | b't') => continue,
+                    Some(_) => return Err(parser.error("Invalid escape sequence in base64 data")),
+                    None => return Err(parser.error_unterminated()),
+                },
+                Some(ch) => ch,
+                None => return Err(parser.error_unterminated()),
+            };
+            if !decoder.feed(ch) {
+                return Err(parser.error("Failed to decode base64 data"));
+            }
+        }
+
+        decoder
+            .finish()
+            .map(Base64Data)
+            .ok_or_else(|| parser.error("Failed to decode base64 data"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::json::Parser;
+
+    use super::Base64Data;
+
+    #[test]
+    fn parse_base64() {
+        for (input, expected) in [
+            ("", Some(&b""[..])),
+            ("aGVsbG8gd29ybGQ=", Some(&b"hello world"[..])),
+            ("aGVsbG8gd29ybGQ", Some(&b"hello world"[..])),
+            ("aGVs\\nbG8g\\r\\nd29y bGQh", Some(&b"hello world!"[..])),
+            (
+                "aGVsbG8\\/",
+                Some(&[0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x3f][..]),
+            ),
+            ("aGVsbG8_", Some(&[0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x3f][..])),
+            ("aA==", Some(&b"h"[..])),
+            ("a===", None),
+            ("aA==aA==", None),
+            ("a", None),
+            ("aGVs*G8=", None),
+        ] {
+            let result = Parser::new(format!("\"{input}\"").as_bytes())
+                .next_token::<Base64Data>()
+                .ok()
+                .and_then(|token| token.unwrap_string("").ok());
+            assert_eq!(
+                result.as_ref().map(|data| data.0.as_slice()),
+                expected,
+                "input: {input:?}"
+            );
+        }
+    }
+}
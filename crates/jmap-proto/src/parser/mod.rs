@@ -28,6 +28,7 @@ use crate::error::{method::MethodError, request::RequestError};
 use self::json::Parser;
 
 pub mod base32;
+pub mod base64;
 pub mod impls;
 pub mod json;
 
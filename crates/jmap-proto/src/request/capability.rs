@@ -48,6 +48,50 @@ pub enum Capability {
     Blob = 1 << 8,
     #[serde(rename(serialize = "urn:ietf:params:jmap:quota"))]
     Quota = 1 << 9,
+    #[serde(rename(serialize = "urn:stalwart:jmap:snooze"))]
+    Snooze = 1 << 10,
+    #[serde(rename(serialize = "urn:ietf:params:jmap:mdn"))]
+    Mdn = 1 << 11,
+    #[serde(rename(serialize = "urn:stalwart:jmap:maskedemail"))]
+    MaskedEmail = 1 << 12,
+    #[serde(rename(serialize = "urn:stalwart:jmap:keywords"))]
+    Keywords = 1 << 13,
+    #[serde(rename(serialize = "urn:stalwart:jmap:autocomplete"))]
+    Autocomplete = 1 << 14,
+    #[serde(rename(serialize = "urn:stalwart:jmap:sharing"))]
+    Sharing = 1 << 15,
+    #[serde(rename(serialize = "urn:ietf:params:jmap:principals"))]
+    Principals = 1 << 16,
+    #[serde(rename(serialize = "urn:stalwart:jmap:upload"))]
+    Upload = 1 << 17,
+    #[serde(rename(serialize = "urn:stalwart:jmap:mailinglists"))]
+    MailingLists = 1 << 18,
+}
+
+impl Capability {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Capability::Core => "urn:ietf:params:jmap:core",
+            Capability::Mail => "urn:ietf:params:jmap:mail",
+            Capability::Submission => "urn:ietf:params:jmap:submission",
+            Capability::VacationResponse => "urn:ietf:params:jmap:vacationresponse",
+            Capability::Contacts => "urn:ietf:params:jmap:contacts",
+            Capability::Calendars => "urn:ietf:params:jmap:calendars",
+            Capability::WebSocket => "urn:ietf:params:jmap:websocket",
+            Capability::Sieve => "urn:ietf:params:jmap:sieve",
+            Capability::Blob => "urn:ietf:params:jmap:blob",
+            Capability::Quota => "urn:ietf:params:jmap:quota",
+            Capability::Snooze => "urn:stalwart:jmap:snooze",
+            Capability::Mdn => "urn:ietf:params:jmap:mdn",
+            Capability::MaskedEmail => "urn:stalwart:jmap:maskedemail",
+            Capability::Keywords => "urn:stalwart:jmap:keywords",
+            Capability::Autocomplete => "urn:stalwart:jmap:autocomplete",
+            Capability::Sharing => "urn:stalwart:jmap:sharing",
+            Capability::Principals => "urn:ietf:params:jmap:principals",
+            Capability::Upload => "urn:stalwart:jmap:upload",
+            Capability::MailingLists => "urn:stalwart:jmap:mailinglists",
+        }
+    }
 }
 
 impl JsonObjectParser for Capability {
@@ -55,7 +99,31 @@ impl JsonObjectParser for Capability {
     where
         Self: Sized,
     {
-        for ch in b"urn:ietf:params:jmap:" {
+        for ch in b"urn:" {
+            if parser
+                .next_unescaped()?
+                .ok_or_else(|| parser.error_capability())?
+                != *ch
+            {
+                return Err(parser.error_capability());
+            }
+        }
+
+        // Vendor capabilities are prefixed with "urn:stalwart:jmap:"
+        let is_vendor = match parser
+            .next_unescaped()?
+            .ok_or_else(|| parser.error_capability())?
+        {
+            b'i' => false,
+            b's' => true,
+            _ => return Err(parser.error_capability()),
+        };
+        let prefix: &[u8] = if is_vendor {
+            b"talwart:jmap:"
+        } else {
+            b"etf:params:jmap:"
+        };
+        for ch in prefix {
             if parser
                 .next_unescaped()?
                 .ok_or_else(|| parser.error_capability())?
@@ -66,6 +134,16 @@ impl JsonObjectParser for Capability {
         }
 
         match u128::parse(parser) {
+            Ok(key) if is_vendor => match key {
+                0x657a_6f6f_6e73 => Ok(Capability::Snooze),
+                0x006c_6961_6d65_6465_6b73_616d => Ok(Capability::MaskedEmail),
+                0x7364_726f_7779_656b => Ok(Capability::Keywords),
+                0x6574_656c_706d_6f63_6f74_7561 => Ok(Capability::Autocomplete),
+                0x0067_6e69_7261_6873 => Ok(Capability::Sharing),
+                0x6461_6f6c_7075 => Ok(Capability::Upload),
+                0x7374_7369_6c67_6e69_6c69_616d => Ok(Capability::MailingLists),
+                _ => Err(parser.error_capability()),
+            },
             Ok(key) => match key {
                 0x6572_6f63 => Ok(Capability::Core),
                 0x6c69_616d => Ok(Capability::Mail),
@@ -77,6 +155,8 @@ impl JsonObjectParser for Capability {
                 0x0065_7665_6973 => Ok(Capability::Sieve),
                 0x626f_6c62 => Ok(Capability::Blob),
                 0x0061_746f_7571 => Ok(Capability::Quota),
+                0x006e_646d => Ok(Capability::Mdn),
+                0x736c_6170_6963_6e69_7270 => Ok(Capability::Principals),
                 _ => Err(parser.error_capability()),
             },
             Err(Error::Method(_)) => Err(parser.error_capability()),
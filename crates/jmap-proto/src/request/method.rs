@@ -46,6 +46,12 @@ pub enum MethodObject {
     SieveScript,
     Principal,
     Quota,
+    Mdn,
+    MaskedEmail,
+    ShareInvitation,
+    Keyword,
+    CollectedAddress,
+    MailingList,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -61,6 +67,9 @@ pub enum MethodFunction {
     Validate,
     Lookup,
     Upload,
+    Send,
+    Rename,
+    Unsubscribe,
     Echo,
 }
 
@@ -113,6 +122,12 @@ impl JsonObjectParser for MethodName {
                 0x0074_7069_7263_5365_7665_6953 => MethodObject::SieveScript,
                 0x006c_6170_6963_6e69_7250 => MethodObject::Principal,
                 0x0061_746f_7551 => MethodObject::Quota,
+                0x004e_444d => MethodObject::Mdn,
+                0x006c_6961_6d45_6465_6b73_614d => MethodObject::MaskedEmail,
+                0x006e_6f69_7461_7469_766e_4965_7261_6853 => MethodObject::ShareInvitation,
+                0x0064_726f_7779_654b => MethodObject::Keyword,
+                0x7373_6572_6464_4164_6574_6365_6c6c_6f43 => MethodObject::CollectedAddress,
+                0x0074_7369_4c67_6e69_6c69_614d => MethodObject::MailingList,
                 0x6572_6f43 => MethodObject::Core,
                 _ => return Err(parser.error_value()),
             },
@@ -128,6 +143,9 @@ impl JsonObjectParser for MethodName {
                 0x6574_6164_696c_6176 => MethodFunction::Validate,
                 0x7075_6b6f_6f6c => MethodFunction::Lookup,
                 0x6461_6f6c_7075 => MethodFunction::Upload,
+                0x646e_6573 => MethodFunction::Send,
+                0x656d_616e_6572 => MethodFunction::Rename,
+                0x0065_6269_7263_7362_7573_6e75 => MethodFunction::Unsubscribe,
                 0x6f68_6365 => MethodFunction::Echo,
                 _ => return Err(parser.error_value()),
             },
@@ -199,8 +217,10 @@ impl MethodName {
             (MethodFunction::Validate, MethodObject::SieveScript) => "SieveScript/validate",
 
             (MethodFunction::Get, MethodObject::Principal) => "Principal/get",
+            (MethodFunction::Changes, MethodObject::Principal) => "Principal/changes",
             (MethodFunction::Set, MethodObject::Principal) => "Principal/set",
             (MethodFunction::Query, MethodObject::Principal) => "Principal/query",
+            (MethodFunction::QueryChanges, MethodObject::Principal) => "Principal/queryChanges",
 
             (MethodFunction::Get, MethodObject::Quota) => "Quota/get",
             (MethodFunction::Changes, MethodObject::Quota) => "Quota/changes",
@@ -212,6 +232,24 @@ impl MethodName {
             (MethodFunction::Lookup, MethodObject::Blob) => "Blob/lookup",
             (MethodFunction::Upload, MethodObject::Blob) => "Blob/upload",
 
+            (MethodFunction::Send, MethodObject::Mdn) => "MDN/send",
+            (MethodFunction::Parse, MethodObject::Mdn) => "MDN/parse",
+
+            (MethodFunction::Get, MethodObject::MaskedEmail) => "MaskedEmail/get",
+            (MethodFunction::Set, MethodObject::MaskedEmail) => "MaskedEmail/set",
+
+            (MethodFunction::Get, MethodObject::ShareInvitation) => "ShareInvitation/get",
+            (MethodFunction::Set, MethodObject::ShareInvitation) => "ShareInvitation/set",
+
+            (MethodFunction::Get, MethodObject::Keyword) => "Keyword/get",
+            (MethodFunction::Rename, MethodObject::Keyword) => "Keyword/rename",
+
+            (MethodFunction::Get, MethodObject::CollectedAddress) => "CollectedAddress/get",
+
+            (MethodFunction::Get, MethodObject::MailingList) => "MailingList/get",
+            (MethodFunction::Set, MethodObject::MailingList) => "MailingList/set",
+            (MethodFunction::Unsubscribe, MethodObject::MailingList) => "MailingList/unsubscribe",
+
             (MethodFunction::Echo, MethodObject::Core) => "Core/echo",
             _ => "error",
         }
@@ -234,6 +272,12 @@ impl Display for MethodObject {
             MethodObject::Thread => "Thread",
             MethodObject::Email => "Email",
             MethodObject::Quota => "Quota",
+            MethodObject::Mdn => "MDN",
+            MethodObject::MaskedEmail => "MaskedEmail",
+            MethodObject::ShareInvitation => "ShareInvitation",
+            MethodObject::Keyword => "Keyword",
+            MethodObject::CollectedAddress => "CollectedAddress",
+            MethodObject::MailingList => "MailingList",
         })
     }
 }
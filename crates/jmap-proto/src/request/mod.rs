@@ -37,10 +37,16 @@ use crate::{
     error::method::MethodError,
     method::{
         changes::ChangesRequest,
+        collected_address::CollectedAddressGetRequest,
         copy::{self, CopyBlobRequest, CopyRequest},
         get::{self, GetRequest},
         import::ImportEmailRequest,
+        keyword::{KeywordGetRequest, KeywordRenameRequest},
         lookup::BlobLookupRequest,
+        mailing_list::{
+            MailingListGetRequest, MailingListSetRequest, MailingListUnsubscribeRequest,
+        },
+        mdn::{MdnParseRequest, MdnSendRequest},
         parse::ParseEmailRequest,
         query::{self, QueryRequest},
         query_changes::QueryChangesRequest,
@@ -90,6 +96,14 @@ pub enum RequestMethod {
     ValidateScript(ValidateSieveScriptRequest),
     LookupBlob(BlobLookupRequest),
     UploadBlob(BlobUploadRequest),
+    SendMdn(MdnSendRequest),
+    ParseMdn(MdnParseRequest),
+    GetKeyword(KeywordGetRequest),
+    RenameKeyword(KeywordRenameRequest),
+    GetCollectedAddress(CollectedAddressGetRequest),
+    GetMailingList(MailingListGetRequest),
+    SetMailingList(MailingListSetRequest),
+    UnsubscribeMailingList(MailingListUnsubscribeRequest),
     Echo(Echo),
     Error(MethodError),
 }
@@ -30,10 +30,16 @@ use crate::{
     },
     method::{
         changes::ChangesRequest,
+        collected_address::CollectedAddressGetRequest,
         copy::{CopyBlobRequest, CopyRequest},
         get::GetRequest,
         import::ImportEmailRequest,
+        keyword::{KeywordGetRequest, KeywordRenameRequest},
         lookup::BlobLookupRequest,
+        mailing_list::{
+            MailingListGetRequest, MailingListSetRequest, MailingListUnsubscribeRequest,
+        },
+        mdn::{MdnParseRequest, MdnSendRequest},
         parse::ParseEmailRequest,
         query::QueryRequest,
         query_changes::QueryChangesRequest,
@@ -143,12 +149,37 @@ impl Request {
                                 | MethodObject::SieveScript
                                 | MethodObject::Principal
                                 | MethodObject::Quota
+                                | MethodObject::MaskedEmail
+                                | MethodObject::ShareInvitation
                                 | MethodObject::Blob,
                             ) => GetRequest::parse(parser).map(RequestMethod::Get),
                             (MethodFunction::Get, MethodObject::SearchSnippet) => {
                                 GetSearchSnippetRequest::parse(parser)
                                     .map(RequestMethod::SearchSnippet)
                             }
+                            (MethodFunction::Get, MethodObject::Keyword) => {
+                                KeywordGetRequest::parse(parser).map(RequestMethod::GetKeyword)
+                            }
+                            (MethodFunction::Get, MethodObject::CollectedAddress) => {
+                                CollectedAddressGetRequest::parse(parser)
+                                    .map(RequestMethod::GetCollectedAddress)
+                            }
+                            (MethodFunction::Rename, MethodObject::Keyword) => {
+                                KeywordRenameRequest::parse(parser)
+                                    .map(RequestMethod::RenameKeyword)
+                            }
+                            (MethodFunction::Get, MethodObject::MailingList) => {
+                                MailingListGetRequest::parse(parser)
+                                    .map(RequestMethod::GetMailingList)
+                            }
+                            (MethodFunction::Set, MethodObject::MailingList) => {
+                                MailingListSetRequest::parse(parser)
+                                    .map(RequestMethod::SetMailingList)
+                            }
+                            (MethodFunction::Unsubscribe, MethodObject::MailingList) => {
+                                MailingListUnsubscribeRequest::parse(parser)
+                                    .map(RequestMethod::UnsubscribeMailingList)
+                            }
                             (MethodFunction::Query, _) => {
                                 QueryRequest::parse(parser).map(RequestMethod::Query)
                             }
@@ -179,6 +210,12 @@ impl Request {
                             (MethodFunction::Parse, MethodObject::Email) => {
                                 ParseEmailRequest::parse(parser).map(RequestMethod::ParseEmail)
                             }
+                            (MethodFunction::Send, MethodObject::Mdn) => {
+                                MdnSendRequest::parse(parser).map(RequestMethod::SendMdn)
+                            }
+                            (MethodFunction::Parse, MethodObject::Mdn) => {
+                                MdnParseRequest::parse(parser).map(RequestMethod::ParseMdn)
+                            }
                             (MethodFunction::Validate, MethodObject::SieveScript) => {
                                 ValidateSieveScriptRequest::parse(parser)
                                     .map(RequestMethod::ValidateScript)
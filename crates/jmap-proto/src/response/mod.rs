@@ -30,10 +30,16 @@ use crate::{
     error::method::MethodError,
     method::{
         changes::ChangesResponse,
+        collected_address::CollectedAddressGetResponse,
         copy::{CopyBlobResponse, CopyResponse},
         get::GetResponse,
         import::ImportEmailResponse,
+        keyword::{KeywordGetResponse, KeywordRenameResponse},
         lookup::BlobLookupResponse,
+        mailing_list::{
+            MailingListGetResponse, MailingListSetResponse, MailingListUnsubscribeResponse,
+        },
+        mdn::{MdnParseResponse, MdnSendResponse},
         parse::ParseEmailResponse,
         query::QueryResponse,
         query_changes::QueryChangesResponse,
@@ -64,6 +70,14 @@ pub enum ResponseMethod {
     ValidateScript(ValidateSieveScriptResponse),
     LookupBlob(BlobLookupResponse),
     UploadBlob(BlobUploadResponse),
+    SendMdn(MdnSendResponse),
+    ParseMdn(MdnParseResponse),
+    GetKeyword(KeywordGetResponse),
+    RenameKeyword(KeywordRenameResponse),
+    GetCollectedAddress(CollectedAddressGetResponse),
+    GetMailingList(MailingListGetResponse),
+    SetMailingList(MailingListSetResponse),
+    UnsubscribeMailingList(MailingListUnsubscribeResponse),
     Echo(Echo),
     Error(MethodError),
 }
@@ -207,6 +221,54 @@ impl From<BlobLookupResponse> for ResponseMethod {
     }
 }
 
+impl From<MdnSendResponse> for ResponseMethod {
+    fn from(send_mdn: MdnSendResponse) -> Self {
+        ResponseMethod::SendMdn(send_mdn)
+    }
+}
+
+impl From<MdnParseResponse> for ResponseMethod {
+    fn from(parse_mdn: MdnParseResponse) -> Self {
+        ResponseMethod::ParseMdn(parse_mdn)
+    }
+}
+
+impl From<KeywordGetResponse> for ResponseMethod {
+    fn from(get_keyword: KeywordGetResponse) -> Self {
+        ResponseMethod::GetKeyword(get_keyword)
+    }
+}
+
+impl From<KeywordRenameResponse> for ResponseMethod {
+    fn from(rename_keyword: KeywordRenameResponse) -> Self {
+        ResponseMethod::RenameKeyword(rename_keyword)
+    }
+}
+
+impl From<CollectedAddressGetResponse> for ResponseMethod {
+    fn from(get_collected_address: CollectedAddressGetResponse) -> Self {
+        ResponseMethod::GetCollectedAddress(get_collected_address)
+    }
+}
+
+impl From<MailingListGetResponse> for ResponseMethod {
+    fn from(get_mailing_list: MailingListGetResponse) -> Self {
+        ResponseMethod::GetMailingList(get_mailing_list)
+    }
+}
+
+impl From<MailingListSetResponse> for ResponseMethod {
+    fn from(set_mailing_list: MailingListSetResponse) -> Self {
+        ResponseMethod::SetMailingList(set_mailing_list)
+    }
+}
+
+impl From<MailingListUnsubscribeResponse> for ResponseMethod {
+    fn from(unsubscribe_mailing_list: MailingListUnsubscribeResponse) -> Self {
+        ResponseMethod::UnsubscribeMailingList(unsubscribe_mailing_list)
+    }
+}
+
 impl<T: Into<ResponseMethod>> From<Result<T, MethodError>> for ResponseMethod {
     fn from(result: Result<T, MethodError>) -> Self {
         match result {
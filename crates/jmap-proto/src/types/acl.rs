@@ -23,7 +23,7 @@
 
 use std::fmt::{self, Display};
 
-use utils::map::bitmap::BitmapItem;
+use utils::map::bitmap::{Bitmap, BitmapItem};
 
 use crate::parser::{json::Parser, JsonObjectParser};
 
@@ -43,6 +43,23 @@ pub enum Acl {
     None = 10,
 }
 
+// Reserved grantee ids for the IMAP "anyone" and "authenticated" identifiers
+pub const ACL_ANYONE: u32 = u32::MAX - 1;
+pub const ACL_AUTHENTICATED: u32 = u32::MAX - 2;
+
+// Rights denied to a grantee are stored in the upper half of its ACL value
+const ACL_DENY_SHIFT: u64 = 32;
+
+// Shares awaiting the grantee's acceptance are flagged with the last bit of the lower half
+const ACL_PENDING: u64 = 1 << 31;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AclGrant {
+    pub grant: Bitmap<Acl>,
+    pub deny: Bitmap<Acl>,
+    pub pending: bool,
+}
+
 impl JsonObjectParser for Acl {
     fn parse(parser: &mut Parser<'_>) -> crate::parser::Result<Self>
     where
@@ -143,6 +160,67 @@ impl From<u64> for Acl {
     }
 }
 
+impl AclGrant {
+    pub fn union(&mut self, other: &AclGrant) {
+        if !other.pending {
+            self.grant.union(&other.grant);
+        }
+        self.deny.union(&other.deny);
+    }
+
+    pub fn effective(&self) -> Bitmap<Acl> {
+        if !self.pending {
+            Bitmap::from(self.grant.bitmap & !self.deny.bitmap)
+        } else {
+            Bitmap::new()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.grant.is_empty() && self.deny.is_empty()
+    }
+}
+
+impl From<u64> for AclGrant {
+    fn from(value: u64) -> Self {
+        AclGrant {
+            grant: Bitmap::from(value & (ACL_PENDING - 1)),
+            deny: Bitmap::from(value >> ACL_DENY_SHIFT),
+            pending: value & ACL_PENDING != 0,
+        }
+    }
+}
+
+impl From<AclGrant> for u64 {
+    fn from(value: AclGrant) -> Self {
+        value.grant.bitmap
+            | (value.deny.bitmap << ACL_DENY_SHIFT)
+            | if value.pending { ACL_PENDING } else { 0 }
+    }
+}
+
+pub fn is_public_grantee(account_id: u32) -> bool {
+    matches!(account_id, ACL_ANYONE | ACL_AUTHENTICATED)
+}
+
+pub fn public_grantee_name(account_id: u32) -> Option<&'static str> {
+    match account_id {
+        ACL_ANYONE => Some("anyone"),
+        ACL_AUTHENTICATED => Some("authenticated"),
+        _ => None,
+    }
+}
+
+pub fn public_grantee_id(name: &str) -> Option<u32> {
+    if name.eq_ignore_ascii_case("anyone") {
+        Some(ACL_ANYONE)
+    } else if name.eq_ignore_ascii_case("authenticated") {
+        Some(ACL_AUTHENTICATED)
+    } else {
+        None
+    }
+}
+
 /*impl SerializeInto for Acl {
     fn serialize_into(&self, buf: &mut Vec<u8>) {
         buf.push(*self as u8);
@@ -38,7 +38,10 @@ pub enum Collection {
     SieveScript = 5,
     PushSubscription = 6,
     Principal = 7,
-    None = 8,
+    MaskedEmail = 8,
+    ShareInvitation = 9,
+    AttachmentLink = 10,
+    None = 11,
 }
 
 impl From<u8> for Collection {
@@ -52,6 +55,9 @@ impl From<u8> for Collection {
             5 => Collection::SieveScript,
             6 => Collection::PushSubscription,
             7 => Collection::Principal,
+            8 => Collection::MaskedEmail,
+            9 => Collection::ShareInvitation,
+            10 => Collection::AttachmentLink,
             _ => Collection::None,
         }
     }
@@ -68,6 +74,9 @@ impl From<u64> for Collection {
             5 => Collection::SieveScript,
             6 => Collection::PushSubscription,
             7 => Collection::Principal,
+            8 => Collection::MaskedEmail,
+            9 => Collection::ShareInvitation,
+            10 => Collection::AttachmentLink,
             _ => Collection::None,
         }
     }
@@ -97,6 +106,8 @@ impl TryFrom<Collection> for DataType {
             Collection::EmailSubmission => Ok(DataType::EmailSubmission),
             Collection::SieveScript => Ok(DataType::SieveScript),
             Collection::PushSubscription => Ok(DataType::PushSubscription),
+            Collection::MaskedEmail => Ok(DataType::MaskedEmail),
+            Collection::ShareInvitation => Ok(DataType::ShareInvitation),
             _ => Err(()),
         }
     }
@@ -113,6 +124,9 @@ impl Display for Collection {
             Collection::EmailSubmission => write!(f, "emailSubmission"),
             Collection::SieveScript => write!(f, "sieveScript"),
             Collection::Principal => write!(f, "principal"),
+            Collection::MaskedEmail => write!(f, "maskedEmail"),
+            Collection::ShareInvitation => write!(f, "shareInvitation"),
+            Collection::AttachmentLink => write!(f, "attachmentLink"),
             Collection::None => write!(f, ""),
         }
     }
@@ -136,6 +136,18 @@ pub enum Property {
     WarnLimit,
     SoftLimit,
     Scope,
+    SnoozedUntil,
+    State,
+    ForDomain,
+    LastMessageAt,
+    CreatedAt,
+    CreatedBy,
+    EmailPrefix,
+    ObjectAccountId,
+    ObjectId,
+    BimiIndicator,
+    Subaddress,
+    Delivered,
     Digest(DigestProperty),
     Data(DataProperty),
     _T(String),
@@ -359,6 +371,7 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
             0x0064_4962_6f6c => Property::BlobId,
             0x6572_7574_6375_7274_5379_646f => Property::BodyStructure,
             0x0073_6575_6c61_5679_646f => Property::BodyValues,
+            0x726f_7461_6369_646e_4969_6d69 => Property::BimiIndicator,
             _ => return None,
         },
         b'c' => match hash {
@@ -366,6 +379,8 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
             0x63 => Property::Cc,
             0x7465_7372_6168 => Property::Charset,
             0x6469 => Property::Cid,
+            0x7441_6465_7461_6572 => Property::CreatedAt,
+            0x7942_6465_7461_6572 => Property::CreatedBy,
             _ => return None,
         },
         b'd' => match hash {
@@ -383,11 +398,13 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
             0x0073_6449_6c69_616d => Property::EmailIds,
             0x0065_706f_6c65_766e => Property::Envelope,
             0x7365_7269_7078 => Property::Expires,
+            0x7869_6665_7250_6c69_616d => Property::EmailPrefix,
             _ => return None,
         },
         b'f' => match hash {
             0x006d_6f72 => Property::From,
             0x0065_7461_446d_6f72 => Property::FromDate,
+            0x6e69_616d_6f44_726f => Property::ForDomain,
             _ => return None,
         },
         b'h' => match hash {
@@ -414,6 +431,7 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
         b'l' => match hash {
             0x0065_6761_7567_6e61 => Property::Language,
             0x006e_6f69_7461_636f => Property::Location,
+            0x7441_6567_6173_7365_4d74_7361 => Property::LastMessageAt,
             _ => return None,
         },
         b'm' => match hash {
@@ -429,6 +447,11 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
             0x0065_6d61 => Property::Name,
             _ => return None,
         },
+        b'o' => match hash {
+            0x6449_746e_756f_6363_4174_6365_6a62 => Property::ObjectAccountId,
+            0x0064_4974_6365_6a62 => Property::ObjectId,
+            _ => return None,
+        },
         b'p' => match hash {
             0x0064_4974_6e65_7261 => Property::ParentId,
             0x0064_4974_7261 => Property::PartId,
@@ -453,8 +476,11 @@ fn parse_property(first_char: u8, hash: u128) -> Option<Property> {
             0x0072_6564_6e65 => Property::Sender,
             0x0074_4174_6e65 => Property::SentAt,
             0x0065_7a69 => Property::Size,
+            0x006c_6974_6e55_6465_7a6f_6f6e => Property::SnoozedUntil,
             0x7265_6472_4f74_726f => Property::SortOrder,
+            0x6574_6174 => Property::State,
             0x7463_656a_6275 => Property::Subject,
+            0x0073_7365_7264_6461_6275 => Property::Subaddress,
             0x7374_7261_5062_7573 => Property::SubParts,
             _ => return None,
         },
@@ -898,6 +924,18 @@ impl Display for Property {
             Property::Used => write!(f, "used"),
             Property::HardLimit => write!(f, "hardLimit"),
             Property::Scope => write!(f, "scope"),
+            Property::SnoozedUntil => write!(f, "snoozedUntil"),
+            Property::State => write!(f, "state"),
+            Property::ForDomain => write!(f, "forDomain"),
+            Property::LastMessageAt => write!(f, "lastMessageAt"),
+            Property::CreatedAt => write!(f, "createdAt"),
+            Property::CreatedBy => write!(f, "createdBy"),
+            Property::EmailPrefix => write!(f, "emailPrefix"),
+            Property::ObjectAccountId => write!(f, "objectAccountId"),
+            Property::ObjectId => write!(f, "objectId"),
+            Property::BimiIndicator => write!(f, "bimiIndicator"),
+            Property::Subaddress => write!(f, "subaddress"),
+            Property::Delivered => write!(f, "delivered"),
             Property::WarnLimit => write!(f, "warnLimit"),
             Property::SoftLimit => write!(f, "softLimit"),
             Property::_T(s) => write!(f, "{s}"),
@@ -1080,6 +1118,18 @@ impl From<&Property> for u8 {
             Property::WarnLimit => 101,
             Property::SoftLimit => 102,
             Property::Scope => 103,
+            Property::SnoozedUntil => 104,
+            Property::State => 105,
+            Property::ForDomain => 106,
+            Property::LastMessageAt => 107,
+            Property::CreatedAt => 108,
+            Property::CreatedBy => 109,
+            Property::EmailPrefix => 110,
+            Property::ObjectAccountId => 111,
+            Property::ObjectId => 112,
+            Property::BimiIndicator => 113,
+            Property::Subaddress => 114,
+            Property::Delivered => 115,
             Property::Digest(_) | Property::Data(_) => unreachable!("invalid property"),
         }
     }
@@ -1222,6 +1272,18 @@ impl SerializeInto for Property {
             Property::WarnLimit => 101,
             Property::SoftLimit => 102,
             Property::Scope => 103,
+            Property::SnoozedUntil => 104,
+            Property::State => 105,
+            Property::ForDomain => 106,
+            Property::LastMessageAt => 107,
+            Property::CreatedAt => 108,
+            Property::CreatedBy => 109,
+            Property::EmailPrefix => 110,
+            Property::ObjectAccountId => 111,
+            Property::ObjectId => 112,
+            Property::BimiIndicator => 113,
+            Property::Subaddress => 114,
+            Property::Delivered => 115,
             Property::Digest(_) | Property::Data(_) => {
                 unreachable!("Property::Digest and Property::Data are not serializable")
             }
@@ -1340,6 +1402,18 @@ impl DeserializeFrom for Property {
             101 => Some(Property::WarnLimit),
             102 => Some(Property::SoftLimit),
             103 => Some(Property::Scope),
+            104 => Some(Property::SnoozedUntil),
+            105 => Some(Property::State),
+            106 => Some(Property::ForDomain),
+            107 => Some(Property::LastMessageAt),
+            108 => Some(Property::CreatedAt),
+            109 => Some(Property::CreatedBy),
+            110 => Some(Property::EmailPrefix),
+            111 => Some(Property::ObjectAccountId),
+            112 => Some(Property::ObjectId),
+            113 => Some(Property::BimiIndicator),
+            114 => Some(Property::Subaddress),
+            115 => Some(Property::Delivered),
             _ => None,
         }
     }
@@ -58,7 +58,11 @@ pub enum DataType {
     Quota = 11,
     #[serde(rename = "SieveScript")]
     SieveScript = 12,
-    None = 13,
+    #[serde(rename = "MaskedEmail")]
+    MaskedEmail = 13,
+    #[serde(rename = "ShareInvitation")]
+    ShareInvitation = 14,
+    None = 15,
 }
 
 impl BitmapItem for DataType {
@@ -87,6 +91,8 @@ impl From<u64> for DataType {
             10 => DataType::Mdn,
             11 => DataType::Quota,
             12 => DataType::SieveScript,
+            13 => DataType::MaskedEmail,
+            14 => DataType::ShareInvitation,
             _ => {
                 debug_assert!(false, "Invalid type_state value: {}", value);
                 DataType::None
@@ -132,6 +138,8 @@ impl JsonObjectParser for DataType {
             0x004e_444d => Ok(DataType::Mdn),
             0x0061_746f_7551 => Ok(DataType::Quota),
             0x0074_7069_7263_5365_7665_6953 => Ok(DataType::SieveScript),
+            0x006c_6961_6d45_6465_6b73_614d => Ok(DataType::MaskedEmail),
+            0x006e_6f69_7461_7469_766e_4965_7261_6853 => Ok(DataType::ShareInvitation),
             _ => Err(parser.error_value()),
         }
     }
@@ -167,6 +175,8 @@ impl TryFrom<&str> for DataType {
             0x004e_444d => Ok(DataType::Mdn),
             0x0061_746f_7551 => Ok(DataType::Quota),
             0x0074_7069_7263_5365_7665_6953 => Ok(DataType::SieveScript),
+            0x006c_6961_6d45_6465_6b73_614d => Ok(DataType::MaskedEmail),
+            0x006e_6f69_7461_7469_766e_4965_7261_6853 => Ok(DataType::ShareInvitation),
             _ => Err(()),
         }
     }
@@ -188,6 +198,8 @@ impl DataType {
             DataType::Mdn => "MDN",
             DataType::Quota => "Quota",
             DataType::SieveScript => "SieveScript",
+            DataType::MaskedEmail => "MaskedEmail",
+            DataType::ShareInvitation => "ShareInvitation",
             DataType::None => "",
         }
     }
@@ -21,39 +21,224 @@
  * for more details.
 */
 
+use hyper::StatusCode;
 use jmap_proto::{
+    error::request::RequestError,
     object::{index::ObjectIndexBuilder, Object},
-    types::{collection::Collection, property::Property, value::Value},
+    types::{
+        collection::Collection, property::Property, state::StateChange, type_state::DataType,
+        value::Value,
+    },
 };
+use smtp::{core::management::QueueRequest, queue};
 use store::{
-    write::{assert::HashedValue, BatchBuilder, Operation, ValueClass},
-    BitmapKey, Serialize, ValueKey,
+    write::{
+        assert::HashedValue, key::DeserializeBigEndian, log::ChangeLogBuilder, now, BatchBuilder,
+        Operation, ValueClass, F_VALUE,
+    },
+    AclKey, BitmapKey, BlobKind, CustomValueKey, Deserialize, Serialize, ValueKey,
 };
+use tokio::sync::oneshot;
+use utils::ipc::DeliveryResult;
 
 use crate::{auth::authenticate::AccountKey, mailbox::set::SCHEMA, JMAP};
 
 impl JMAP {
     pub async fn delete_account(&self, account_name: &str, account_id: u32) -> store::Result<()> {
+        // The directory entry and the account name mapping are removed last, so
+        // a deletion that fails halfway leaves the account in place and can be retried.
+
+        // Cancel any messages queued by the account
+        let addresses = self
+            .directory
+            .emails_by_name(account_name)
+            .await
+            .map_err(|err| {
+                store::Error::InternalError(format!("Failed to obtain account addresses: {err:?}"))
+            })?;
+        self.cancel_queued_messages(&addresses).await;
+
+        // Revoke any access granted to the account on shared mailboxes
+        self.revoke_account_acls(account_id).await?;
+
         // Delete blobs
         self.store.delete_account_blobs(account_id).await?;
 
         // Delete mailboxes
+        let mut changes =
+            ChangeLogBuilder::with_change_id(self.store.assign_change_id(u32::MAX).await?);
+        changes.log_delete(Collection::Principal, account_id);
         let mut batch = BatchBuilder::new();
         batch
             .with_account_id(u32::MAX)
             .with_collection(Collection::Principal)
+            .delete_document(account_id)
             .op(Operation::Value {
                 class: ValueClass::Custom {
-                    bytes: AccountKey::name_to_id(account_name),
+                    bytes: AccountKey::id_to_name(account_id),
                 },
                 set: None,
             })
             .op(Operation::Value {
                 class: ValueClass::Custom {
-                    bytes: AccountKey::id_to_name(account_id),
+                    bytes: AccountKey::id_to_settings(account_id),
+                },
+                set: None,
+            })
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::id_to_tokens(account_id),
                 },
                 set: None,
             })
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::id_to_consents(account_id),
+                },
+                set: None,
+            })
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::share_acceptance(account_id),
+                },
+                set: None,
+            })
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::principal(account_id),
+                },
+                set: None,
+            })
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::spam_train_opt_out(account_id),
+                },
+                set: None,
+            })
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::sender_lists(account_id),
+                },
+                set: None,
+            })
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::digest_enabled(account_id),
+                },
+                set: None,
+            })
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::attachment_link_settings(account_id),
+                },
+                set: None,
+            })
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::dedup_window(account_id),
+                },
+                set: None,
+            })
+            .custom(changes);
+        for masked_email_id in self
+            .store
+            .get_bitmap(BitmapKey::document_ids(account_id, Collection::MaskedEmail))
+            .await?
+            .unwrap_or_default()
+        {
+            if let Some(Value::Text(email)) = self
+                .store
+                .get_value::<Object<Value>>(ValueKey::new(
+                    account_id,
+                    Collection::MaskedEmail,
+                    masked_email_id,
+                    Property::Value,
+                ))
+                .await?
+                .map(|mut masked_email| masked_email.remove(&Property::Email))
+            {
+                batch.op(Operation::Value {
+                    class: ValueClass::Custom {
+                        bytes: AccountKey::masked_email(&email),
+                    },
+                    set: None,
+                });
+            }
+        }
+        for keyword in self.keyword_registry(account_id).await? {
+            batch.op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::keyword(account_id, &keyword),
+                },
+                set: None,
+            });
+        }
+        for address in self.known_senders(account_id).await? {
+            batch.op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::known_sender(account_id, &address),
+                },
+                set: None,
+            });
+        }
+        for (address, _) in self.collected_addresses(account_id).await? {
+            batch.op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::collected_address(account_id, &address),
+                },
+                set: None,
+            });
+        }
+        for (document_id, _) in self.digest_items(account_id).await? {
+            batch.op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::digest_item(account_id, document_id),
+                },
+                set: None,
+            });
+        }
+        for (_, link_id, link) in self.attachment_links(account_id.into()).await? {
+            batch
+                .op(Operation::Value {
+                    class: ValueClass::Custom {
+                        bytes: AccountKey::attachment_link(account_id, link_id),
+                    },
+                    set: None,
+                })
+                .op(Operation::Value {
+                    class: ValueClass::Custom {
+                        bytes: AccountKey::attachment_link_expiry(
+                            link.expires,
+                            account_id,
+                            link_id,
+                        ),
+                    },
+                    set: None,
+                });
+        }
+        for (_, fingerprint, _) in self.delivered_messages(account_id.into()).await? {
+            batch.op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::delivered_message(account_id, &fingerprint),
+                },
+                set: None,
+            });
+        }
+        for key in self.spam_train_keys(account_id).await? {
+            batch.op(Operation::Value {
+                class: ValueClass::Custom { bytes: key },
+                set: None,
+            });
+        }
+        for (list_id, _) in self.mailing_lists(account_id).await? {
+            batch.op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::mailing_list(account_id, &list_id),
+                },
+                set: None,
+            });
+        }
+        batch
             .with_account_id(account_id)
             .with_collection(Collection::Mailbox);
         for mailbox_id in self
@@ -82,22 +267,254 @@ impl JMAP {
             self.store.write(batch.build()).await?;
         }
 
-        // Delete account
+        // Delete account, including its indexes and change log
         self.store.purge_account(account_id).await?;
 
+        // Remove the directory entry so the account can no longer log in or receive mail
+        for query in &self.config.account_delete_queries {
+            self.directory
+                .lookup(query, &[account_name.into()])
+                .await
+                .map_err(|err| {
+                    store::Error::InternalError(format!(
+                        "Failed to delete directory entry: {err:?}"
+                    ))
+                })?;
+        }
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::name_to_id(account_name),
+                },
+                set: None,
+            });
+        self.store.write(batch.build()).await?;
+
+        // Invalidate any cached sessions
+        self.access_tokens.remove(&account_id);
+        self.sessions.retain(|_, entry| *entry.item() != account_id);
+
+        Ok(())
+    }
+
+    async fn revoke_account_acls(&self, account_id: u32) -> store::Result<()> {
+        let from_key = AclKey {
+            grant_account_id: account_id,
+            to_account_id: 0,
+            to_collection: 0,
+            to_document_id: 0,
+        };
+        let to_key = AclKey {
+            grant_account_id: account_id,
+            to_account_id: u32::MAX,
+            to_collection: u8::MAX,
+            to_document_id: u32::MAX,
+        };
+        let grants = self
+            .store
+            .iterate(
+                Vec::new(),
+                from_key,
+                to_key,
+                false,
+                true,
+                move |grants, key, _| {
+                    let acl_key = AclKey::deserialize(key)?;
+                    if acl_key.to_account_id != account_id
+                        && acl_key.to_collection == u8::from(Collection::Mailbox)
+                    {
+                        grants.push(acl_key);
+                    }
+                    Ok(true)
+                },
+            )
+            .await?;
+
+        for grant in grants {
+            let mailbox = if let Some(mailbox) = self
+                .store
+                .get_value::<HashedValue<Object<Value>>>(ValueKey::new(
+                    grant.to_account_id,
+                    Collection::Mailbox,
+                    grant.to_document_id,
+                    Property::Value,
+                ))
+                .await?
+            {
+                mailbox
+            } else {
+                continue;
+            };
+            let acl = if let Some(Value::List(acl)) = mailbox.inner.properties.get(&Property::Acl) {
+                acl.chunks_exact(2)
+                    .filter(|item| {
+                        item.first()
+                            .and_then(|id| id.as_id())
+                            .map_or(true, |id| id.document_id() != account_id)
+                    })
+                    .flatten()
+                    .cloned()
+                    .collect::<Vec<_>>()
+            } else {
+                continue;
+            };
+
+            let change_id = self.store.assign_change_id(grant.to_account_id).await?;
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(grant.to_account_id)
+                .with_collection(Collection::Mailbox)
+                .update_document(grant.to_document_id)
+                .custom(
+                    ObjectIndexBuilder::new(SCHEMA)
+                        .with_current(mailbox)
+                        .with_changes(
+                            Object::with_capacity(1).with_property(Property::Acl, Value::List(acl)),
+                        ),
+                )
+                .custom(
+                    ChangeLogBuilder::with_change_id(change_id)
+                        .with_log_update(Collection::Mailbox, grant.to_document_id),
+                );
+            self.store.write(batch.build()).await?;
+            self.broadcast_state_change(
+                StateChange::new(grant.to_account_id).with_change(DataType::Mailbox, change_id),
+            )
+            .await;
+        }
+
         Ok(())
     }
 
+    async fn cancel_queued_messages(&self, addresses: &[String]) {
+        let queue_tx = self.smtp.core().queue.tx.clone();
+        for address in addresses {
+            let (result_tx, result_rx) = oneshot::channel();
+            if queue_tx
+                .send(queue::Event::Manage(QueueRequest::List {
+                    from: address.to_lowercase().into(),
+                    to: None,
+                    before: None,
+                    after: None,
+                    result_tx,
+                }))
+                .await
+                .is_err()
+            {
+                return;
+            }
+            let queue_ids = match result_rx.await {
+                Ok(queue_ids) if !queue_ids.is_empty() => queue_ids,
+                _ => continue,
+            };
+
+            // Only cancel messages sent from this exact address
+            let (result_tx, result_rx) = oneshot::channel();
+            if queue_tx
+                .send(queue::Event::Manage(QueueRequest::Status {
+                    queue_ids: queue_ids.clone(),
+                    result_tx,
+                }))
+                .await
+                .is_err()
+            {
+                return;
+            }
+            let queue_ids = queue_ids
+                .into_iter()
+                .zip(result_rx.await.unwrap_or_default())
+                .filter_map(|(queue_id, message)| {
+                    message
+                        .filter(|message| message.return_path.eq_ignore_ascii_case(address))
+                        .map(|_| queue_id)
+                })
+                .collect::<Vec<_>>();
+            if queue_ids.is_empty() {
+                continue;
+            }
+
+            let (result_tx, result_rx) = oneshot::channel();
+            if queue_tx
+                .send(queue::Event::Manage(QueueRequest::Cancel {
+                    queue_ids,
+                    item: None,
+                    result_tx,
+                }))
+                .await
+                .is_ok()
+            {
+                let canceled = result_rx.await.unwrap_or_default();
+                tracing::debug!(
+                    context = "account",
+                    event = "cancel-queued",
+                    address = address,
+                    count = canceled.iter().filter(|c| **c).count(),
+                    "Canceled queued messages of deleted account."
+                );
+            }
+        }
+    }
+
     pub async fn rename_account(
         &self,
         new_account_name: &str,
         account_name: &str,
         account_id: u32,
     ) -> store::Result<()> {
-        // Delete blobs
-        self.store.delete_account_blobs(account_id).await?;
+        // Update the directory mappings
+        let old_addresses = self
+            .directory
+            .emails_by_name(account_name)
+            .await
+            .unwrap_or_default();
+        for query in &self.config.account_rename_queries {
+            self.directory
+                .lookup(query, &[new_account_name.into(), account_name.into()])
+                .await
+                .map_err(|err| {
+                    store::Error::InternalError(format!(
+                        "Failed to rename directory entry: {err:?}"
+                    ))
+                })?;
+        }
 
-        // Delete mailboxes
+        // Account names that are addresses also become the new primary address
+        let old_primary = old_addresses.first();
+        if let (Some(old_primary), Some(query)) = (old_primary, &self.config.account_address_query)
+        {
+            if new_account_name.contains('@') && !old_primary.eq_ignore_ascii_case(new_account_name)
+            {
+                self.directory
+                    .lookup(
+                        query,
+                        &[
+                            new_account_name.into(),
+                            new_account_name.into(),
+                            old_primary.as_str().into(),
+                        ],
+                    )
+                    .await
+                    .map_err(|err| {
+                        store::Error::InternalError(format!(
+                            "Failed to update primary address: {err:?}"
+                        ))
+                    })?;
+            }
+        }
+        let new_addresses = self
+            .directory
+            .emails_by_name(new_account_name)
+            .await
+            .unwrap_or_default();
+        let new_primary = new_addresses.first();
+
+        // Update account name mappings
+        let mut changes =
+            ChangeLogBuilder::with_change_id(self.store.assign_change_id(u32::MAX).await?);
+        changes.log_update(Collection::Principal, account_id);
         let mut batch = BatchBuilder::new();
         batch
             .with_account_id(u32::MAX)
@@ -119,8 +536,281 @@ impl JMAP {
                     bytes: AccountKey::id_to_name(account_id),
                 },
                 set: new_account_name.serialize().into(),
+            })
+            .custom(changes);
+
+        // Keep the old primary address as an alias during the grace period
+        let old_address = old_primary.filter(|address| {
+            !new_addresses
+                .iter()
+                .any(|new_address| new_address.eq_ignore_ascii_case(address))
+        });
+        if let (Some(old_address), Some(query)) = (old_address, &self.config.account_alias_query) {
+            self.directory
+                .lookup(
+                    query,
+                    &[new_account_name.into(), old_address.as_str().into()],
+                )
+                .await
+                .map_err(|err| {
+                    store::Error::InternalError(format!("Failed to create alias: {err:?}"))
+                })?;
+            batch.op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::renamed_alias(
+                        now() + self.config.account_alias_grace_period.as_secs(),
+                        old_address,
+                    ),
+                },
+                set: new_account_name.serialize().into(),
             });
+        }
         self.store.write(batch.build()).await?;
+
+        // Rewrite identities using the old primary address
+        if let (Some(old_primary), Some(new_primary)) = (old_primary, new_primary) {
+            if !old_primary.eq_ignore_ascii_case(new_primary) {
+                self.rewrite_identities(account_id, old_primary, new_primary)
+                    .await?;
+            }
+        }
+
+        // ACLs reference accounts by id, only the cached names need to be refreshed
+        self.access_tokens.remove(&account_id);
+        self.sessions.retain(|_, entry| *entry.item() != account_id);
+
+        Ok(())
+    }
+
+    async fn rewrite_identities(
+        &self,
+        account_id: u32,
+        old_address: &str,
+        new_address: &str,
+    ) -> store::Result<()> {
+        let mut changes = ChangeLogBuilder::new();
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::Identity);
+        for identity_id in self
+            .store
+            .get_bitmap(BitmapKey::document_ids(account_id, Collection::Identity))
+            .await?
+            .unwrap_or_default()
+        {
+            let mut identity = if let Some(identity) = self
+                .store
+                .get_value::<Object<Value>>(ValueKey::new(
+                    account_id,
+                    Collection::Identity,
+                    identity_id,
+                    Property::Value,
+                ))
+                .await?
+            {
+                identity
+            } else {
+                continue;
+            };
+            if matches!(identity.get(&Property::Email), Value::Text(email) if email.eq_ignore_ascii_case(old_address))
+            {
+                identity.set(Property::Email, Value::Text(new_address.to_string()));
+                batch
+                    .update_document(identity_id)
+                    .value(Property::Value, identity, F_VALUE);
+                changes.log_update(Collection::Identity, identity_id);
+            }
+        }
+
+        if !changes.is_empty() {
+            let change_id = self.store.assign_change_id(account_id).await?;
+            changes.change_id = change_id;
+            batch.custom(changes);
+            self.store.write(batch.build()).await?;
+            self.broadcast_state_change(
+                StateChange::new(account_id).with_change(DataType::Identity, change_id),
+            )
+            .await;
+        }
+
+        Ok(())
+    }
+
+    pub async fn expire_renamed_aliases(&self) -> store::Result<()> {
+        let query = if let Some(query) = &self.config.account_alias_expire_query {
+            query
+        } else {
+            return Ok(());
+        };
+        let from_key = CustomValueKey {
+            value: AccountKey::renamed_alias(0, ""),
+        };
+        let to_key = CustomValueKey {
+            value: AccountKey::renamed_alias(now() + 1, ""),
+        };
+        let expired = self
+            .store
+            .iterate(
+                Vec::new(),
+                from_key,
+                to_key,
+                false,
+                true,
+                move |expired, key, value| {
+                    // Skip the u32::MAX account prefix and the key type
+                    let offset = std::mem::size_of::<u32>() + 1;
+                    let until = key.deserialize_be_u64(offset)?;
+                    let address = key
+                        .get(offset + std::mem::size_of::<u64>()..)
+                        .and_then(|address| std::str::from_utf8(address).ok())
+                        .ok_or_else(|| {
+                            store::Error::InternalError(format!("Invalid alias key {key:?}"))
+                        })?
+                        .to_string();
+                    expired.push((until, address, String::deserialize(value)?));
+                    Ok(true)
+                },
+            )
+            .await?;
+
+        for (until, address, account_name) in expired {
+            self.directory
+                .lookup(
+                    query,
+                    &[account_name.as_str().into(), address.as_str().into()],
+                )
+                .await
+                .map_err(|err| {
+                    store::Error::InternalError(format!("Failed to remove alias: {err:?}"))
+                })?;
+
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal)
+                .op(Operation::Value {
+                    class: ValueClass::Custom {
+                        bytes: AccountKey::renamed_alias(until, &address),
+                    },
+                    set: None,
+                });
+            self.store.write(batch.build()).await?;
+        }
+
         Ok(())
     }
+
+    pub async fn catch_all_claim(
+        &self,
+        account_id: u32,
+        document_id: u32,
+        new_account_name: &str,
+    ) -> Result<(), RequestError> {
+        // Obtain message and its original recipient
+        let raw_message = self
+            .get_blob(
+                &BlobKind::LinkedMaildir {
+                    account_id,
+                    document_id,
+                },
+                0..u32::MAX,
+            )
+            .await
+            .map_err(|_| RequestError::internal_server_error())?
+            .ok_or_else(|| {
+                RequestError::blank(
+                    StatusCode::NOT_FOUND.as_u16(),
+                    "Not found",
+                    "Message not found.",
+                )
+            })?;
+        let rcpt = original_recipient(&raw_message).ok_or_else(|| {
+            RequestError::blank(
+                StatusCode::BAD_REQUEST.as_u16(),
+                "Invalid parameters",
+                "Message was not delivered through a catch-all address.",
+            )
+        })?;
+
+        // Make sure the new owner exists
+        match self.directory.principal(new_account_name).await {
+            Ok(Some(_)) => (),
+            Ok(None) => {
+                return Err(RequestError::blank(
+                    StatusCode::NOT_FOUND.as_u16(),
+                    "Not found",
+                    "Account not found.",
+                ))
+            }
+            Err(_) => return Err(RequestError::internal_server_error()),
+        }
+
+        // Create the missing alias
+        if let Some(query) = &self.config.catch_all_claim_query {
+            if let Err(err) = self
+                .directory
+                .lookup(query, &[new_account_name.into(), rcpt.as_str().into()])
+                .await
+            {
+                return Err(RequestError::blank(
+                    StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                    "Alias creation failed",
+                    format!("{err:?}"),
+                ));
+            }
+        }
+
+        // Redeliver the message to the new owner
+        match self
+            .deliver_to_account(&raw_message, "", &rcpt, new_account_name)
+            .await
+        {
+            DeliveryResult::Success | DeliveryResult::Suppressed { .. } => (),
+            DeliveryResult::TemporaryFailure { reason }
+            | DeliveryResult::PermanentFailure { reason, .. } => {
+                return Err(RequestError::blank(
+                    StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                    "Delivery failed",
+                    reason,
+                ));
+            }
+        }
+
+        // Remove the message from the review mailbox
+        match self.email_delete(account_id, document_id).await {
+            Ok(Ok(change)) => {
+                let mut changes = ChangeLogBuilder::new();
+                changes.merge(change);
+                let change_id = self
+                    .commit_changes(account_id, changes)
+                    .await
+                    .map_err(|_| RequestError::internal_server_error())?;
+                self.broadcast_state_change(
+                    StateChange::new(account_id)
+                        .with_change(DataType::Email, change_id)
+                        .with_change(DataType::Mailbox, change_id)
+                        .with_change(DataType::Thread, change_id),
+                )
+                .await;
+                Ok(())
+            }
+            Ok(Err(_)) | Err(_) => Err(RequestError::internal_server_error()),
+        }
+    }
+}
+
+fn original_recipient(raw_message: &[u8]) -> Option<String> {
+    for line in raw_message.split(|&ch| ch == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        } else if line.len() > 14 && line[..14].eq_ignore_ascii_case(b"X-Original-To:") {
+            return std::str::from_utf8(&line[14..])
+                .ok()
+                .map(|rcpt| rcpt.trim().to_lowercase())
+                .filter(|rcpt| !rcpt.is_empty());
+        }
+    }
+    None
 }
@@ -0,0 +1,135 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use hyper::{Method, StatusCode};
+use store::write::now;
+use utils::codec::html::html_escape;
+
+use crate::{
+    auth::{oauth::FormData, rate_limit::RemoteAddress},
+    blob::{BlobDownload, DownloadBody, DownloadResponse},
+    submission::attachment_link::{parse_attachment_link_token, AttachmentLink},
+    JMAP,
+};
+
+use super::{http::ToHttpResponse, HtmlResponse, HttpRequest, HttpResponse};
+
+const MAX_POST_LEN: usize = 2048;
+
+impl JMAP {
+    pub async fn handle_attachment_link_request(
+        &self,
+        req: &mut HttpRequest,
+        token: &str,
+        remote_addr: &RemoteAddress,
+    ) -> HttpResponse {
+        let (account_id, link_id, secret) = if let Some(token) = parse_attachment_link_token(token)
+        {
+            token
+        } else {
+            return attachment_page(StatusCode::NOT_FOUND, "Attachment not found.");
+        };
+        let link = match self.get_attachment_link(account_id, link_id).await {
+            Ok(Some(link)) if link.secret == secret => link,
+            Ok(_) => return attachment_page(StatusCode::NOT_FOUND, "Attachment not found."),
+            Err(_) => return attachment_page(StatusCode::INTERNAL_SERVER_ERROR, "Internal error."),
+        };
+        if link.expires <= now() {
+            return attachment_page(StatusCode::GONE, "This download link has expired.");
+        }
+
+        match (req.method(), &link.password) {
+            (&Method::GET, None) => {}
+            (&Method::GET, Some(_)) => return password_form(StatusCode::OK, &link, None),
+            (&Method::POST, Some(hashed_password)) => {
+                if let Err(err) = self.is_auth_allowed_soft(remote_addr) {
+                    return err.into_http_response();
+                }
+                let password = match FormData::from_request(req, MAX_POST_LEN).await {
+                    Ok(mut form) => form.remove("password").unwrap_or_default(),
+                    Err(err) => return err,
+                };
+                if !directory::secret::verify_secret(hashed_password, &password).await {
+                    return match self.is_auth_allowed_hard(remote_addr) {
+                        Ok(_) => password_form(
+                            StatusCode::UNAUTHORIZED,
+                            &link,
+                            "Incorrect password.".into(),
+                        ),
+                        Err(err) => err.into_http_response(),
+                    };
+                }
+            }
+            _ => return attachment_page(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed."),
+        }
+
+        match self
+            .get_blob(&AttachmentLink::blob_kind(account_id, link_id), 0..u32::MAX)
+            .await
+        {
+            Ok(Some(bytes)) => DownloadResponse {
+                filename: link.name,
+                content_type: link.content_type,
+                blob: BlobDownload {
+                    etag: format!("\"{link_id:x}\""),
+                    last_modified: None,
+                    body: DownloadBody::Full(bytes),
+                },
+            }
+            .into_http_response(),
+            Ok(None) => attachment_page(StatusCode::NOT_FOUND, "Attachment not found."),
+            Err(_) => attachment_page(StatusCode::INTERNAL_SERVER_ERROR, "Internal error."),
+        }
+    }
+}
+
+fn password_form(status: StatusCode, link: &AttachmentLink, error: Option<&str>) -> HttpResponse {
+    HtmlResponse::with_status(
+        status,
+        format!(
+            concat!(
+                "<!DOCTYPE html><html><head><title>Download attachment</title></head><body>",
+                "<p>{}Enter the password to download {}.</p>",
+                "<form method=\"post\"><input type=\"password\" name=\"password\">",
+                "<button type=\"submit\">Download</button></form></body></html>"
+            ),
+            error.map(|error| format!("{error} ")).unwrap_or_default(),
+            html_escape(&link.name)
+        ),
+    )
+    .into_http_response()
+}
+
+fn attachment_page(status: StatusCode, message: &str) -> HttpResponse {
+    HtmlResponse::with_status(
+        status,
+        format!(
+            concat!(
+                "<!DOCTYPE html><html><head><title>Download attachment</title></head>",
+                "<body><p>{}</p></body></html>"
+            ),
+            message
+        ),
+    )
+    .into_http_response()
+}
@@ -0,0 +1,269 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::io::{self, Read, Write};
+
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::{Body, Bytes},
+    header::{self, HeaderValue},
+    StatusCode,
+};
+
+use super::HttpResponse;
+
+/// Maximum ratio between decompressed and compressed request sizes
+/// accepted when no size limit is configured.
+pub const MAX_COMPRESSION_RATIO: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Zstd,
+    Brotli,
+    Gzip,
+}
+
+pub struct HttpCompression {
+    pub algorithms: Vec<ContentEncoding>,
+    pub min_size: usize,
+}
+
+impl HttpCompression {
+    pub fn parse(settings: &utils::config::Config) -> Result<Option<Self>, String> {
+        if !settings.property_or_static("jmap.http.compression.enable", "true")? {
+            return Ok(None);
+        }
+
+        let mut algorithms = Vec::new();
+        for (key, value) in settings.values("jmap.http.compression.algorithms") {
+            algorithms.push(ContentEncoding::parse(value).ok_or_else(|| {
+                format!("Invalid compression algorithm {value:?} for property {key:?}.")
+            })?);
+        }
+        if algorithms.is_empty() {
+            algorithms = vec![
+                ContentEncoding::Zstd,
+                ContentEncoding::Brotli,
+                ContentEncoding::Gzip,
+            ];
+        }
+
+        Ok(Some(HttpCompression {
+            algorithms,
+            min_size: settings.property_or_static("jmap.http.compression.min-size", "1024")?,
+        }))
+    }
+
+    /// Picks the encoding with the highest quality value in an
+    /// `Accept-Encoding` header, using the configured order to break ties.
+    pub fn negotiate(&self, accept_encoding: &str) -> Option<ContentEncoding> {
+        let mut wildcard = None;
+        let mut accepted = Vec::new();
+        for item in accept_encoding.split(',') {
+            let mut params = item.split(';');
+            let name = params.next().unwrap_or_default().trim();
+            let quality = params
+                .find_map(|param| {
+                    param
+                        .trim()
+                        .strip_prefix("q=")
+                        .and_then(|q| q.trim().parse::<f32>().ok())
+                })
+                .unwrap_or(1.0);
+            if name == "*" {
+                wildcard = Some(quality);
+            } else if let Some(encoding) = ContentEncoding::parse(name) {
+                accepted.push((encoding, quality));
+            }
+        }
+
+        let mut result: Option<(ContentEncoding, f32)> = None;
+        for &encoding in &self.algorithms {
+            let quality = accepted
+                .iter()
+                .find(|(e, _)| *e == encoding)
+                .map(|(_, q)| *q)
+                .or(wildcard)
+                .unwrap_or(0.0);
+            if quality > 0.0 && result.map_or(true, |(_, q)| quality > q) {
+                result = Some((encoding, quality));
+            }
+        }
+
+        result.map(|(encoding, _)| encoding)
+    }
+
+    /// Compresses a buffered response body if the client accepts any of the
+    /// configured encodings. Streaming responses are left untouched.
+    pub async fn compress_response(
+        &self,
+        accept_encoding: Option<&str>,
+        response: HttpResponse,
+    ) -> HttpResponse {
+        let encoding = match accept_encoding.and_then(|value| self.negotiate(value)) {
+            Some(encoding) => encoding,
+            None => return response,
+        };
+        let is_compressible = !matches!(
+            response.status(),
+            StatusCode::SWITCHING_PROTOCOLS | StatusCode::PARTIAL_CONTENT
+        ) && !response.headers().contains_key(header::CONTENT_ENCODING)
+            && response
+                .body()
+                .size_hint()
+                .exact()
+                .map_or(false, |size| size as usize >= self.min_size)
+            && response
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|ct| ct.to_str().ok())
+                .map_or(true, is_compressible_type);
+        if !is_compressible {
+            return response;
+        }
+
+        let (mut parts, body) = response.into_parts();
+        let bytes = match body.collect().await {
+            Ok(collected) => collected.to_bytes(),
+            Err(_) => Bytes::new(),
+        };
+        let (bytes, compressed) = tokio::task::spawn_blocking(move || {
+            let compressed = encoding.compress(&bytes);
+            (bytes, compressed)
+        })
+        .await
+        .unwrap_or_else(|_| (Bytes::new(), Err(io::ErrorKind::Other.into())));
+        let body = match compressed {
+            Ok(compressed) if compressed.len() < bytes.len() => {
+                parts.headers.insert(
+                    header::CONTENT_ENCODING,
+                    HeaderValue::from_static(encoding.as_str()),
+                );
+                parts.headers.remove(header::CONTENT_LENGTH);
+
+                // The encoded representation is not byte-identical to the
+                // original, so a strong entity tag can no longer be used
+                if let Some(etag) = parts.headers.get(header::ETAG).and_then(|etag| {
+                    let etag = etag.to_str().ok()?;
+                    (!etag.starts_with("W/"))
+                        .then(|| HeaderValue::from_str(&format!("W/{etag}")).ok())
+                        .flatten()
+                }) {
+                    parts.headers.insert(header::ETAG, etag);
+                }
+                Bytes::from(compressed)
+            }
+            _ => bytes,
+        };
+        parts
+            .headers
+            .append(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+
+        hyper::Response::from_parts(
+            parts,
+            Full::new(body).map_err(|never| match never {}).boxed(),
+        )
+    }
+}
+
+impl ContentEncoding {
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("zstd") {
+            Some(ContentEncoding::Zstd)
+        } else if value.eq_ignore_ascii_case("br") {
+            Some(ContentEncoding::Brotli)
+        } else if value.eq_ignore_ascii_case("gzip") || value.eq_ignore_ascii_case("x-gzip") {
+            Some(ContentEncoding::Gzip)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Zstd => "zstd",
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Gzip => "gzip",
+        }
+    }
+
+    pub fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            ContentEncoding::Zstd => zstd::stream::encode_all(bytes, 3),
+            ContentEncoding::Brotli => {
+                let mut output = Vec::with_capacity(bytes.len() / 2);
+                {
+                    let mut writer = brotli::CompressorWriter::new(&mut output, 4096, 5, 22);
+                    writer.write_all(bytes)?;
+                }
+                Ok(output)
+            }
+            ContentEncoding::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    /// Decompresses a request body, failing if the output would exceed
+    /// `max_size` bytes.
+    pub fn decompress(&self, bytes: &[u8], max_size: usize) -> io::Result<Vec<u8>> {
+        let reader: Box<dyn Read + '_> = match self {
+            ContentEncoding::Zstd => Box::new(zstd::stream::Decoder::new(bytes)?),
+            ContentEncoding::Brotli => Box::new(brotli::Decompressor::new(bytes, 4096)),
+            ContentEncoding::Gzip => Box::new(flate2::read::GzDecoder::new(bytes)),
+        };
+        let mut output = Vec::with_capacity(std::cmp::min(bytes.len() * 4, max_size));
+        reader.take(max_size as u64 + 1).read_to_end(&mut output)?;
+        if output.len() <= max_size {
+            Ok(output)
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Decompressed size exceeds limit",
+            ))
+        }
+    }
+}
+
+fn is_compressible_type(content_type: &str) -> bool {
+    let content_type = content_type.trim().to_ascii_lowercase();
+    !(content_type.starts_with("image/")
+        || content_type.starts_with("video/")
+        || content_type.starts_with("audio/")
+        || content_type.starts_with("font/woff")
+        || [
+            "application/zip",
+            "application/gzip",
+            "application/zstd",
+            "application/x-7z-compressed",
+            "application/x-bzip2",
+            "application/x-xz",
+            "application/x-rar-compressed",
+        ]
+        .iter()
+        .any(|ct| content_type.starts_with(ct)))
+}
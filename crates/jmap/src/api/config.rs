@@ -21,15 +21,39 @@
  * for more details.
 */
 
-use std::{str::FromStr, time::Duration};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+    time::Duration,
+};
 
 use nlp::language::Language;
-use store::rand::{distributions::Alphanumeric, thread_rng, Rng};
+use smtp::config::IpAddrMask;
+use store::{
+    ahash::AHashMap,
+    rand::{distributions::Alphanumeric, thread_rng, Rng},
+};
+use utils::config::utils::ParseValue;
 
-use super::session::BaseCapabilities;
+use crate::{auth::tenant::Tenants, email::html::TRACKING_PARAMETERS, sieve::limits::SieveLimits};
+
+use super::{compression::HttpCompression, cors::HttpCors, session::BaseCapabilities};
 
 impl crate::Config {
     pub fn new(settings: &utils::config::Config) -> Result<Self, String> {
+        let sieve_limits = SieveLimits::parse(settings, "sieve.untrusted.limits", None)?;
+        let mut sieve_limits_override = AHashMap::new();
+        for (key, principal) in settings.values("sieve.untrusted.limits.override") {
+            if let Some(prefix) = key.strip_suffix(".principal") {
+                sieve_limits_override.insert(
+                    principal.to_string(),
+                    SieveLimits::parse(settings, prefix, sieve_limits.into())?,
+                );
+            }
+        }
+        let html_image_proxy =
+            settings.property_or_static("jmap.html.image-proxy.enable", "false")?;
+
         let mut config = Self {
             default_language: Language::from_iso_639(
                 settings.value("jmap.fts.default-language").unwrap_or("en"),
@@ -47,6 +71,9 @@ impl crate::Config {
             request_max_calls: settings
                 .property("jmap.protocol.request.max-calls")?
                 .unwrap_or(16),
+            request_max_cost: settings
+                .property("jmap.protocol.request.max-cost")?
+                .unwrap_or(20000),
             request_max_concurrent: settings
                 .property("jmap.protocol.request.max-concurrent")?
                 .unwrap_or(4),
@@ -84,12 +111,16 @@ impl crate::Config {
             mail_parse_max_items: settings
                 .property("jmap.email.parse.max-items")?
                 .unwrap_or(10),
+            mail_bimi_authserv_id: settings
+                .value("jmap.email.bimi.authserv-id")
+                .or_else(|| settings.value("server.hostname"))
+                .unwrap_or("localhost")
+                .to_lowercase(),
             sieve_max_script_name: settings
                 .property("sieve.untrusted.limits.name-length")?
                 .unwrap_or(512),
-            sieve_max_scripts: settings
-                .property("sieve.untrusted.limits.max-scripts")?
-                .unwrap_or(256),
+            sieve_limits,
+            sieve_limits_override,
             capabilities: BaseCapabilities::default(),
             session_cache_ttl: settings
                 .property("jmap.session.cache.ttl")?
@@ -99,9 +130,16 @@ impl crate::Config {
             rate_authenticate_req: settings
                 .property_or_static("jmap.rate-limit.authentication", "10/1m")?,
             rate_anonymous: settings.property_or_static("jmap.rate-limit.anonymous", "100/1m")?,
-            rate_use_forwarded: settings
-                .property("jmap.rate-limit.use-forwarded")?
-                .unwrap_or(false),
+            rate_method_calls: settings
+                .property_or_static("jmap.rate-limit.method-calls", "5000/1m")?,
+            rate_upload: settings.property_or_static("jmap.rate-limit.upload", "100/1m")?,
+            rate_oauth: settings.property_or_static("jmap.rate-limit.oauth", "30/1m")?,
+            query_max_concurrent: settings
+                .property_or_static("jmap.rate-limit.query.max-concurrent", "4")?,
+            query_large_results: settings
+                .property_or_static("jmap.rate-limit.query.large-results", "500")?,
+            tenants: Tenants::parse(settings)?,
+            client_cert_map: settings.parse_client_certificate_map()?,
             oauth_key: settings
                 .text_file_contents("oauth.key")?
                 .unwrap_or_else(|| {
@@ -126,18 +164,171 @@ impl crate::Config {
             oauth_expiry_refresh_token_renew: settings
                 .property_or_static::<Duration>("oauth.expiry.refresh-token-renew", "4d")?
                 .as_secs(),
+            oauth_device_poll_interval: settings
+                .property_or_static::<Duration>("oauth.device.poll-interval", "5s")?
+                .as_secs(),
             oauth_max_auth_attempts: settings.property_or_static("oauth.auth.max-attempts", "3")?,
+            oauth_require_consent: settings
+                .property_or_static("oauth.auth.require-consent", "true")?,
+            oauth_jwt_algorithm: settings.property_or_static("oauth.jwt.algorithm", "ES256")?,
+            oauth_jwt_issuer: settings
+                .value("oauth.jwt.issuer")
+                .unwrap_or("stalwart")
+                .to_string(),
+            oauth_jwt_rotate: settings
+                .property_or_static::<Duration>("oauth.jwt.rotate", "30d")?
+                .as_secs(),
             event_source_throttle: settings
                 .property_or_static("jmap.event-source.throttle", "1s")?,
             web_socket_throttle: settings.property_or_static("jmap.web-socket.throttle", "1s")?,
             web_socket_timeout: settings.property_or_static("jmap.web-socket.timeout", "10m")?,
             web_socket_heartbeat: settings.property_or_static("jmap.web-socket.heartbeat", "1m")?,
+            web_socket_max_duration: settings.property("jmap.web-socket.max-duration")?,
+            session_max_memory: settings.property("server.session.max-memory")?,
             push_max_total: settings.property_or_static("jmap.push.max-total", "100")?,
             principal_allow_lookups: settings
                 .property("jmap.principal.allow-lookups")?
                 .unwrap_or(true),
+            mdn_reporting_ua: settings
+                .value("jmap.mdn.reporting-ua")
+                .map(|v| v.to_string())
+                .unwrap_or_else(|| {
+                    format!(
+                        "{}; Stalwart JMAP v{}",
+                        settings.value("server.hostname").unwrap_or("localhost"),
+                        env!("CARGO_PKG_VERSION")
+                    )
+                }),
+            mdn_auto_processed: settings
+                .property_or_static("jmap.mdn.auto-send.processed", "false")?,
+            catch_all_review_mailbox: settings
+                .value("jmap.catch-all.review.mailbox")
+                .map(|v| v.to_string()),
+            catch_all_claim_query: settings
+                .value("jmap.catch-all.claim.query")
+                .map(|v| v.to_string()),
+            account_delete_queries: settings
+                .values("jmap.account.delete.query")
+                .map(|(_, v)| v.to_string())
+                .collect(),
+            account_rename_queries: settings
+                .values("jmap.account.rename.query")
+                .map(|(_, v)| v.to_string())
+                .collect(),
+            account_address_query: settings
+                .value("jmap.account.rename.address.query")
+                .map(|v| v.to_string()),
+            account_alias_query: settings
+                .value("jmap.account.rename.alias.query")
+                .map(|v| v.to_string()),
+            account_alias_expire_query: settings
+                .value("jmap.account.rename.alias.expire-query")
+                .map(|v| v.to_string()),
+            account_alias_grace_period: settings
+                .property_or_static("jmap.account.rename.alias.grace-period", "30d")?,
+            masked_email_domain: settings
+                .value("jmap.masked-email.domain")
+                .map(|v| v.to_lowercase()),
+            masked_email_max: settings
+                .property_or_static("jmap.masked-email.max-per-account", "100")?,
+            share_require_acceptance: settings
+                .property_or_static("jmap.sharing.require-acceptance", "true")?,
+            share_invitation_email: settings
+                .property_or_static("jmap.sharing.invitation.email", "false")?,
+            spam_junk_folder: settings.property_or_static("jmap.spam.junk-folder", "false")?,
+            spam_train_enable: settings.property_or_static("jmap.spam.training.enable", "true")?,
+            spam_train_lookup: settings
+                .value("jmap.spam.training.lookup")
+                .unwrap_or("spamdb/token-insert")
+                .to_string(),
+            auto_collect: settings.property_or_static("jmap.auto-collect.enable", "true")?,
+            first_contact: settings.property_or_static("jmap.first-contact.enable", "false")?,
+            mailing_lists: settings.property_or_static("jmap.mailing-list.enable", "true")?,
+            mailing_list_unsubscribe_timeout: settings
+                .property_or_static("jmap.mailing-list.unsubscribe.timeout", "10s")?,
+            mailing_list_max_entries: settings
+                .property("jmap.mailing-list.max-lists")?
+                .unwrap_or(1000),
+            digest_from: settings
+                .value("jmap.digest.from")
+                .map(|from| from.to_string())
+                .unwrap_or_else(|| {
+                    format!(
+                        "postmaster@{}",
+                        settings.value("server.hostname").unwrap_or("localhost")
+                    )
+                }),
+            digest_url: settings.value("jmap.digest.url").map(|url| url.to_string()),
+            attachment_link_threshold: settings
+                .property("jmap.submission.attachment-links.threshold")?
+                .unwrap_or(0),
+            attachment_link_expiry: settings
+                .property_or_static::<Duration>("jmap.submission.attachment-links.expiry", "30d")?
+                .as_secs(),
+            attachment_link_url: settings
+                .value("jmap.submission.attachment-links.url")
+                .map(|url| url.to_string()),
+            html_image_proxy,
+            html_image_proxy_key: match settings.text_file_contents("jmap.html.image-proxy.key")? {
+                Some(key) => key,
+                // Signed URLs have to remain valid across restarts and cluster nodes
+                None if html_image_proxy => {
+                    return Err(
+                        "Property \"jmap.html.image-proxy.key\" is required when the image proxy is enabled."
+                            .to_string(),
+                    );
+                }
+                None => String::new(),
+            },
+            html_image_proxy_max_size: settings
+                .property("jmap.html.image-proxy.max-size")?
+                .unwrap_or(5000000),
+            html_image_proxy_timeout: settings
+                .property_or_static("jmap.html.image-proxy.timeout", "10s")?,
+            html_tracking_parameters: if settings
+                .values("jmap.html.image-proxy.strip-parameters")
+                .next()
+                .is_some()
+            {
+                settings
+                    .values("jmap.html.image-proxy.strip-parameters")
+                    .map(|(_, v)| v.to_ascii_lowercase())
+                    .collect()
+            } else {
+                TRACKING_PARAMETERS.iter().map(|v| v.to_string()).collect()
+            },
+            email_conversion_cache_size: settings
+                .property("jmap.email.conversion.cache.size")?
+                .unwrap_or(1024),
+            email_conversion_cache_ttl: settings
+                .property_or_static("jmap.email.conversion.cache.ttl", "1h")?,
+            dedup_window: settings
+                .property_or_static::<Duration>("jmap.delivery.dedup.window", "1h")?
+                .as_secs(),
+            dedup_max_window: settings
+                .property_or_static::<Duration>("jmap.delivery.dedup.max-window", "7d")?
+                .as_secs(),
+            sent_copy: settings.property_or_static("jmap.submission.sent-copy.enable", "false")?,
+            sent_copy_window: settings
+                .property_or_static::<Duration>("jmap.submission.sent-copy.window", "5m")?
+                .as_secs(),
+            admin_ui: settings.property_or_static("jmap.admin.ui.enable", "true")?,
+            settings_password_query: settings
+                .value("jmap.settings.password.query")
+                .map(|v| v.to_string()),
+            settings_password_min_length: settings
+                .property_or_static("jmap.settings.password.min-length", "8")?,
+            settings_totp_issuer: settings
+                .value("jmap.settings.totp.issuer")
+                .unwrap_or("Stalwart Mail Server")
+                .to_string(),
+            settings_forward_max: settings
+                .property_or_static("jmap.settings.forwarding.max-recipients", "5")?,
             encrypt: settings.property_or_static("jmap.encryption.enable", "true")?,
             encrypt_append: settings.property_or_static("jmap.encryption.append", "false")?,
+            http_trusted_proxies: parse_trusted_proxies(settings)?,
+            http_cors: HttpCors::parse(settings)?,
+            http_compression: HttpCompression::parse(settings)?,
             http_headers: settings
                 .values("jmap.http.headers")
                 .map(|(_, v)| {
@@ -169,3 +360,37 @@ impl crate::Config {
         Ok(config)
     }
 }
+
+pub fn parse_trusted_proxies(settings: &utils::config::Config) -> Result<Vec<IpAddrMask>, String> {
+    let trusted_proxies = settings
+        .values("jmap.http.trusted-proxies")
+        .map(|(k, v)| IpAddrMask::parse_value(k, v))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Deployments that enabled the deprecated "jmap.rate-limit.use-forwarded" setting
+    // keep trusting forwarded headers from any peer until they list their proxies
+    if trusted_proxies.is_empty()
+        && settings.property_or_static::<bool>("jmap.rate-limit.use-forwarded", "false")?
+    {
+        tracing::warn!(
+            context = "config",
+            event = "deprecated",
+            concat!(
+                "Property \"jmap.rate-limit.use-forwarded\" is deprecated, ",
+                "list the addresses of your proxies in \"jmap.http.trusted-proxies\" instead."
+            )
+        );
+        Ok(vec![
+            IpAddrMask::V4 {
+                addr: Ipv4Addr::UNSPECIFIED,
+                mask: 0,
+            },
+            IpAddrMask::V6 {
+                addr: Ipv6Addr::UNSPECIFIED,
+                mask: 0,
+            },
+        ])
+    } else {
+        Ok(trusted_proxies)
+    }
+}
@@ -0,0 +1,61 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Bytes, header, StatusCode};
+
+use super::HttpResponse;
+
+const CONSOLE_HTML: &str = include_str!("../../../../resources/htx/admin/index.html");
+const CONSOLE_JS: &str = include_str!("../../../../resources/htx/admin/admin.js");
+const CONSOLE_CSS: &str = include_str!("../../../../resources/htx/admin/admin.css");
+
+// Returns the embedded admin console asset for the given request path, if any.
+// The assets are served without authentication, the console itself
+// authenticates every call it makes to the admin API.
+pub fn console_asset(path: &str) -> Option<HttpResponse> {
+    let (content_type, body) = match path.trim_end_matches('/') {
+        "/admin" => ("text/html; charset=utf-8", CONSOLE_HTML),
+        "/admin/ui/admin.js" => ("application/javascript; charset=utf-8", CONSOLE_JS),
+        "/admin/ui/admin.css" => ("text/css; charset=utf-8", CONSOLE_CSS),
+        _ => return None,
+    };
+
+    Some(
+        hyper::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::CACHE_CONTROL, "no-cache")
+            .header(
+                header::CONTENT_SECURITY_POLICY,
+                "default-src 'self'; frame-ancestors 'none'",
+            )
+            .header(header::X_FRAME_OPTIONS, "DENY")
+            .body(
+                Full::new(Bytes::from_static(body.as_bytes()))
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap(),
+    )
+}
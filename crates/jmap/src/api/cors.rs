@@ -0,0 +1,157 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use hyper::{
+    header::{self, HeaderMap, HeaderValue},
+    Method,
+};
+
+use super::HttpResponse;
+
+pub struct HttpCors {
+    pub allowed_origins: Vec<String>,
+    pub allowed_headers: HeaderValue,
+    pub allowed_methods: HeaderValue,
+    pub allow_credentials: bool,
+    pub max_age: Duration,
+}
+
+impl HttpCors {
+    pub fn parse(settings: &utils::config::Config) -> Result<Option<Self>, String> {
+        let allowed_origins = settings
+            .values("jmap.http.cors.allowed-origins")
+            .map(|(_, origin)| origin.trim().trim_end_matches('/').to_lowercase())
+            .collect::<Vec<_>>();
+        if allowed_origins.is_empty() {
+            return Ok(None);
+        }
+
+        let header_list = |key: &str, default: &[&str]| {
+            let values = settings
+                .values(key)
+                .map(|(_, v)| v.trim().to_string())
+                .collect::<Vec<_>>();
+            let value = if !values.is_empty() {
+                values.join(", ")
+            } else {
+                default.join(", ")
+            };
+            HeaderValue::from_str(&value)
+                .map_err(|err| format!("Invalid value found in property {key:?}: {err}"))
+        };
+
+        // Browsers refuse "*" on credentialed requests, reflecting the origin instead
+        // would allow any site to make authenticated requests
+        let allow_credentials =
+            settings.property_or_static("jmap.http.cors.allow-credentials", "false")?;
+        if allow_credentials && allowed_origins.iter().any(|origin| origin == "*") {
+            return Err(concat!(
+                "Property \"jmap.http.cors.allowed-origins\" cannot contain \"*\" ",
+                "when \"jmap.http.cors.allow-credentials\" is enabled."
+            )
+            .to_string());
+        }
+
+        Ok(Some(HttpCors {
+            allowed_origins,
+            allowed_headers: header_list(
+                "jmap.http.cors.allowed-headers",
+                &["Authorization", "Content-Type", "Accept"],
+            )?,
+            allowed_methods: header_list(
+                "jmap.http.cors.allowed-methods",
+                &["GET", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"],
+            )?,
+            allow_credentials,
+            max_age: settings.property_or_static("jmap.http.cors.max-age", "1d")?,
+        }))
+    }
+
+    /// Returns the value of the `Access-Control-Allow-Origin` header for the
+    /// given request origin, or `None` if the origin is not allowed.
+    pub fn allow_origin(&self, origin: &str) -> Option<HeaderValue> {
+        let origin_lc = origin.trim_end_matches('/').to_lowercase();
+        if self.allowed_origins.iter().any(|o| o == &origin_lc) {
+            HeaderValue::from_str(origin).ok()
+        } else if self.allowed_origins.iter().any(|o| o == "*") {
+            HeaderValue::from_static("*").into()
+        } else {
+            None
+        }
+    }
+
+    pub fn is_preflight(&self, method: &Method, headers: &HeaderMap) -> bool {
+        method == Method::OPTIONS
+            && headers.contains_key(header::ORIGIN)
+            && headers.contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    }
+
+    /// Adds the CORS headers to a response, including the preflight headers
+    /// when requested.
+    pub fn apply(&self, origin: &str, is_preflight: bool, response: &mut HttpResponse) {
+        let allow_origin = if let Some(allow_origin) = self.allow_origin(origin) {
+            allow_origin
+        } else {
+            return;
+        };
+
+        let headers = response.headers_mut();
+        if allow_origin != "*" {
+            headers.append(header::VARY, HeaderValue::from_static("Origin"));
+        }
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.allow_credentials {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        if is_preflight {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_METHODS,
+                self.allowed_methods.clone(),
+            );
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                self.allowed_headers.clone(),
+            );
+            headers.insert(
+                header::ACCESS_CONTROL_MAX_AGE,
+                HeaderValue::from(self.max_age.as_secs()),
+            );
+        }
+    }
+}
+
+/// CORS only applies to the JMAP and OAuth endpoints.
+pub fn is_cors_path(path: &str) -> bool {
+    matches!(
+        path.trim_start_matches('/')
+            .split('/')
+            .next()
+            .unwrap_or_default(),
+        "jmap" | ".well-known" | "auth"
+    )
+}
@@ -0,0 +1,177 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use hyper::StatusCode;
+use jmap_proto::types::collection::Collection;
+use serde::Serialize;
+use smtp::{core::management::QueueRequest, queue};
+use tokio::sync::oneshot;
+use utils::map::vec_map::VecMap;
+
+use crate::JMAP;
+
+use super::{http::ToHttpResponse, HttpResponse, JsonResponse};
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    Fail,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentHealth {
+    pub status: HealthStatus,
+    #[serde(rename = "latencyMs")]
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "VecMap::is_empty")]
+    pub components: VecMap<&'static str, ComponentHealth>,
+}
+
+impl JMAP {
+    pub fn handle_liveness_request(&self) -> HttpResponse {
+        JsonResponse::new(HealthReport {
+            status: HealthStatus::Ok,
+            components: VecMap::new(),
+        })
+        .into_http_response()
+    }
+
+    pub async fn handle_readiness_request(&self) -> HttpResponse {
+        let report = self.readiness().await;
+        let status = if report.status == HealthStatus::Ok {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+
+        JsonResponse::with_status(status, report).into_http_response()
+    }
+
+    pub async fn readiness(&self) -> HealthReport {
+        let (store, blob, directory, queue) = tokio::join!(
+            check_component(async {
+                self.store
+                    .get_last_change_id(u32::MAX, Collection::Principal)
+                    .await
+                    .map(|_| ())
+                    .map_err(|err| err.to_string())
+            }),
+            check_component(async {
+                self.store
+                    .check_blob_store()
+                    .await
+                    .map_err(|err| err.to_string())
+            }),
+            check_component(async {
+                self.directory
+                    .is_local_domain("localhost")
+                    .await
+                    .map(|_| ())
+                    .map_err(|err| format!("{err:?}"))
+            }),
+            check_component(async {
+                let (result_tx, result_rx) = oneshot::channel();
+                self.smtp
+                    .core()
+                    .queue
+                    .tx
+                    .send(queue::Event::Manage(QueueRequest::Status {
+                        queue_ids: vec![],
+                        result_tx,
+                    }))
+                    .await
+                    .map_err(|_| "Queue manager is not running".to_string())?;
+                result_rx
+                    .await
+                    .map(|_| ())
+                    .map_err(|_| "Queue manager did not respond".to_string())
+            }),
+        );
+
+        let mut components = VecMap::with_capacity(4);
+        components.append("store", store);
+        components.append("blob", blob);
+        components.append("directory", directory);
+        components.append("queue", queue);
+
+        HealthReport {
+            status: if components
+                .values()
+                .all(|component| component.status == HealthStatus::Ok)
+            {
+                HealthStatus::Ok
+            } else {
+                HealthStatus::Fail
+            },
+            components,
+        }
+    }
+}
+
+async fn check_component(check: impl Future<Output = Result<(), String>>) -> ComponentHealth {
+    let start = Instant::now();
+    let result = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+        Ok(result) => result,
+        Err(_) => Err(format!(
+            "Check timed out after {} seconds",
+            CHECK_TIMEOUT.as_secs()
+        )),
+    };
+    let latency_ms = start.elapsed().as_millis() as u64;
+
+    match result {
+        Ok(_) => ComponentHealth {
+            status: HealthStatus::Ok,
+            latency_ms,
+            error: None,
+        },
+        Err(err) => {
+            tracing::warn!(
+                context = "health",
+                event = "error",
+                reason = %err,
+                "Readiness check failed."
+            );
+            ComponentHealth {
+                status: HealthStatus::Fail,
+                latency_ms,
+                error: Some(err),
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Bytes, header, StatusCode};
+use jmap_proto::error::request::RequestError;
+use utils::ssrf::{is_public_url, public_client, read_body};
+
+use crate::{
+    auth::AccessToken,
+    email::html::{verify_image_url, DEFAULT_SCOPE},
+    JMAP,
+};
+
+use super::{http::ToHttpResponse, HttpResponse};
+
+const MAX_REDIRECTS: usize = 5;
+
+impl JMAP {
+    pub async fn handle_html_part_request(
+        &self,
+        access_token: &AccessToken,
+        account_id: u32,
+        document_id: u32,
+        part_id: usize,
+        base_url: &str,
+        query: Option<&str>,
+    ) -> HttpResponse {
+        // Clients can pick the class name used to scope the message styles
+        let scope = query
+            .and_then(|query| {
+                form_urlencoded::parse(query.as_bytes())
+                    .find(|(k, _)| k == "scope")
+                    .map(|(_, v)| v.into_owned())
+            })
+            .filter(|scope| {
+                !scope.is_empty()
+                    && scope.len() <= 64
+                    && scope
+                        .bytes()
+                        .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, b'-' | b'_'))
+            })
+            .unwrap_or_else(|| DEFAULT_SCOPE.to_string());
+
+        match self
+            .email_html_part(
+                access_token,
+                account_id,
+                document_id,
+                part_id,
+                base_url,
+                &scope,
+            )
+            .await
+        {
+            Ok(Some(html)) => hyper::Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+                .header(header::CACHE_CONTROL, "private, no-cache")
+                .header(
+                    header::CONTENT_SECURITY_POLICY,
+                    "default-src 'none'; img-src https: http:; style-src 'unsafe-inline'",
+                )
+                .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+                .body(
+                    Full::new(Bytes::from(html))
+                        .map_err(|never| match never {})
+                        .boxed(),
+                )
+                .unwrap(),
+            Ok(None) => RequestError::not_found().into_http_response(),
+            Err(_) => RequestError::internal_server_error().into_http_response(),
+        }
+    }
+
+    // Images are fetched on behalf of the client so that senders cannot
+    // track when, where or whether a message was opened.
+    pub async fn handle_image_proxy_request(
+        &self,
+        signature: &str,
+        query: Option<&str>,
+    ) -> HttpResponse {
+        if !self.config.html_image_proxy {
+            return RequestError::not_found().into_http_response();
+        }
+        let url = query.and_then(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .find(|(k, _)| k == "url")
+                .map(|(_, v)| v.into_owned())
+        });
+        let url = match url {
+            Some(url)
+                if verify_image_url(
+                    self.config.html_image_proxy_key.as_bytes(),
+                    &url,
+                    signature,
+                ) =>
+            {
+                url
+            }
+            _ => return RequestError::forbidden().into_http_response(),
+        };
+        match reqwest::Url::parse(&url) {
+            Ok(url) if is_public_url(&url) => (),
+            _ => return RequestError::forbidden().into_http_response(),
+        }
+
+        // Resolved addresses and redirects are checked on each connection
+        let client = match public_client(
+            reqwest::Client::builder().timeout(self.config.html_image_proxy_timeout),
+            MAX_REDIRECTS,
+        )
+        .build()
+        {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::error!(
+                    context = "image_proxy",
+                    event = "error",
+                    reason = %err,
+                    "Failed to build HTTP client");
+                return bad_gateway();
+            }
+        };
+        let response = match client.get(&url).send().await {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                tracing::debug!(
+                    context = "image_proxy",
+                    event = "error",
+                    url = %url,
+                    status = %response.status(),
+                    "Remote server returned an error");
+                return bad_gateway();
+            }
+            Err(err) => {
+                tracing::debug!(
+                    context = "image_proxy",
+                    event = "error",
+                    url = %url,
+                    reason = %err,
+                    "Failed to fetch image");
+                return bad_gateway();
+            }
+        };
+
+        // SVG images can contain scripts, so only raster images are served
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_ascii_lowercase())
+            .filter(|value| value.starts_with("image/") && !value.starts_with("image/svg"));
+        let content_type = if let Some(content_type) = content_type {
+            content_type
+        } else {
+            return RequestError::forbidden().into_http_response();
+        };
+        let bytes = match read_body(response, self.config.html_image_proxy_max_size).await {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                tracing::debug!(
+                    context = "image_proxy",
+                    event = "error",
+                    url = %url,
+                    reason = %err,
+                    "Failed to read image");
+                return bad_gateway();
+            }
+        };
+
+        hyper::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::CACHE_CONTROL, "private, max-age=86400")
+            .header(header::CONTENT_SECURITY_POLICY, "default-src 'none'")
+            .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+            .body(
+                Full::new(Bytes::from(bytes))
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap()
+    }
+}
+
+fn bad_gateway() -> HttpResponse {
+    hyper::Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .body(
+            Full::new(Bytes::new())
+                .map_err(|never| match never {})
+                .boxed(),
+        )
+        .unwrap()
+}
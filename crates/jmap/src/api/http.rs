@@ -43,18 +43,30 @@ use tokio::{
     io::{AsyncRead, AsyncWrite},
     net::TcpStream,
 };
-use utils::listener::{ServerInstance, SessionData, SessionManager};
+use utils::{
+    config::certificate::client_certificate_identities,
+    listener::{ServerInstance, SessionData, SessionManager},
+    set_tracing_level, tracing_levels,
+};
 
 use crate::{
-    auth::{oauth::OAuthMetadata, AccessToken},
-    blob::{DownloadResponse, UploadResponse},
+    auth::{
+        forwarded::forwarded_client_ip, oauth::OAuthMetadata, tenant::AdminPermission, AccessToken,
+        ClientCertificate, TlsServerName,
+    },
+    blob::{download::http_date, DownloadBody, DownloadResponse, UploadResponse},
     services::state,
+    settings::policy::DomainPolicy,
     websocket::upgrade::upgrade_websocket_connection,
     JMAP,
 };
 
 use super::{
-    session::Session, HtmlResponse, HttpRequest, HttpResponse, JmapSessionManager, JsonResponse,
+    compression::{ContentEncoding, MAX_COMPRESSION_RATIO},
+    console::console_asset,
+    cors::is_cors_path,
+    session::Session,
+    HtmlResponse, HttpRequest, HttpResponse, JmapSessionManager, JsonResponse,
 };
 
 pub async fn parse_jmap_request(
@@ -111,7 +123,10 @@ pub async fn parse_jmap_request(
                         path.next().and_then(BlobId::from_base32),
                         path.next(),
                     ) {
-                        return match jmap.blob_download(&blob_id, &access_token).await {
+                        return match jmap
+                            .blob_download_conditional(&blob_id, &access_token, req.headers())
+                            .await
+                        {
                             Ok(Some(blob)) => DownloadResponse {
                                 filename: name.to_string(),
                                 content_type: req
@@ -131,6 +146,24 @@ pub async fn parse_jmap_request(
                         };
                     }
                 }
+                ("html", &Method::GET) => {
+                    if let (Some(account_id), Some(email_id), Some(part_id)) = (
+                        path.next().and_then(|p| Id::from_bytes(p.as_bytes())),
+                        path.next().and_then(|p| Id::from_bytes(p.as_bytes())),
+                        path.next().and_then(|p| p.parse::<usize>().ok()),
+                    ) {
+                        return jmap
+                            .handle_html_part_request(
+                                &access_token,
+                                account_id.document_id(),
+                                email_id.document_id(),
+                                part_id,
+                                &instance.data,
+                                req.uri().query(),
+                            )
+                            .await;
+                    }
+                }
                 ("upload", &Method::POST) => {
                     if let Some(account_id) = path.next().and_then(|p| Id::from_bytes(p.as_bytes()))
                     {
@@ -201,6 +234,25 @@ pub async fn parse_jmap_request(
                     Err(err) => err.into_http_response(),
                 };
             }
+            ("acme-challenge", &Method::GET) if !instance.acme.is_empty() => {
+                let token = path.next().unwrap_or_default();
+                return match instance
+                    .acme
+                    .iter()
+                    .find_map(|provider| provider.http_challenge(token))
+                {
+                    Some(key_authorization) => hyper::Response::builder()
+                        .status(StatusCode::OK)
+                        .header(header::CONTENT_TYPE, "text/plain")
+                        .body(
+                            Full::new(Bytes::from(key_authorization))
+                                .map_err(|never| match never {})
+                                .boxed(),
+                        )
+                        .unwrap(),
+                    None => RequestError::not_found().into_http_response(),
+                };
+            }
             (_, &Method::OPTIONS) => {
                 return ().into_http_response();
             }
@@ -211,7 +263,7 @@ pub async fn parse_jmap_request(
 
             match (path.next().unwrap_or(""), req.method()) {
                 ("", &Method::GET) => {
-                    return match jmap.is_anonymous_allowed(&remote_addr) {
+                    return match jmap.is_oauth_allowed(&remote_addr) {
                         Ok(_) => jmap.handle_user_device_auth(&mut req).await,
                         Err(err) => err.into_http_response(),
                     }
@@ -226,7 +278,7 @@ pub async fn parse_jmap_request(
                     }
                 }
                 ("code", &Method::GET) => {
-                    return match jmap.is_anonymous_allowed(&remote_addr) {
+                    return match jmap.is_oauth_allowed(&remote_addr) {
                         Ok(_) => jmap.handle_user_code_auth(&mut req).await,
                         Err(err) => err.into_http_response(),
                     }
@@ -240,18 +292,30 @@ pub async fn parse_jmap_request(
                         Err(err) => err.into_http_response(),
                     }
                 }
+                ("qr", &Method::GET) => {
+                    return match jmap.is_oauth_allowed(&remote_addr) {
+                        Ok(_) => jmap.handle_device_auth_qr(&req, instance),
+                        Err(err) => err.into_http_response(),
+                    }
+                }
                 ("device", &Method::POST) => {
-                    return match jmap.is_anonymous_allowed(&remote_addr) {
+                    return match jmap.is_oauth_allowed(&remote_addr) {
                         Ok(_) => jmap.handle_device_auth(&mut req, instance).await,
                         Err(err) => err.into_http_response(),
                     }
                 }
                 ("token", &Method::POST) => {
-                    return match jmap.is_anonymous_allowed(&remote_addr) {
+                    return match jmap.is_oauth_allowed(&remote_addr) {
                         Ok(_) => jmap.handle_token_request(&mut req).await,
                         Err(err) => err.into_http_response(),
                     }
                 }
+                ("jwks.json", &Method::GET) => {
+                    return match jmap.is_anonymous_allowed(&remote_addr) {
+                        Ok(_) => jmap.handle_jwks_request().await,
+                        Err(err) => err.into_http_response(),
+                    }
+                }
                 (_, &Method::OPTIONS) => {
                     return ().into_http_response();
                 }
@@ -274,15 +338,73 @@ pub async fn parse_jmap_request(
                 _ => (),
             }
         }
+        "settings" => {
+            // Authenticate request
+            let (_in_flight, access_token) = match jmap.authenticate_headers(&req, remote_ip).await
+            {
+                Ok(Some(session)) => session,
+                Ok(None) => return RequestError::unauthorized().into_http_response(),
+                Err(err) => return err.into_http_response(),
+            };
+            let remote_addr = jmap.build_remote_addr(&req, remote_ip);
+            let path = path
+                .filter(|p| !p.is_empty())
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>();
+
+            return jmap
+                .handle_settings_request(
+                    &mut req,
+                    &path.iter().map(|p| p.as_str()).collect::<Vec<_>>(),
+                    access_token,
+                    &remote_addr,
+                    &instance,
+                )
+                .await;
+        }
+        "image-proxy" if req.method() == Method::GET => {
+            return jmap
+                .handle_image_proxy_request(path.next().unwrap_or_default(), req.uri().query())
+                .await;
+        }
+        "attachment" => {
+            let token = path.next().unwrap_or_default().to_string();
+            let remote_addr = jmap.build_remote_addr(&req, remote_ip);
+            return jmap
+                .handle_attachment_link_request(&mut req, &token, &remote_addr)
+                .await;
+        }
+        "unsubscribe" => {
+            return jmap
+                .handle_unsubscribe_request(req.method(), path.next().unwrap_or_default())
+                .await;
+        }
+        "healthz" if req.method() == Method::GET => {
+            return jmap.handle_liveness_request();
+        }
+        "readyz" if req.method() == Method::GET => {
+            return jmap.handle_readiness_request().await;
+        }
 
         "admin" => {
-            // Make sure the user is a superuser
-            match jmap.authenticate_headers(&req, remote_ip).await {
-                Ok(Some((_, access_token))) if access_token.is_super_user() => (),
-                Ok(_) => return RequestError::unauthorized().into_http_response(),
-                Err(err) => return err.into_http_response(),
+            // Serve the admin console
+            if jmap.config.admin_ui && req.method() == Method::GET {
+                if let Some(response) = console_asset(req.uri().path()) {
+                    return response;
+                }
             }
 
+            // Make sure the user is a superuser or holds an administrative role in a tenant
+            let (access_token, role, tenant) =
+                match jmap.authenticate_headers(&req, remote_ip).await {
+                    Ok(Some((_, access_token))) => match jmap.admin_role(&access_token) {
+                        Some((role, tenant)) => (access_token, role, tenant.cloned()),
+                        None => return RequestError::unauthorized().into_http_response(),
+                    },
+                    Ok(None) => return RequestError::unauthorized().into_http_response(),
+                    Err(err) => return err.into_http_response(),
+                };
+
             match (
                 path.next().unwrap_or(""),
                 path.next().unwrap_or(""),
@@ -290,7 +412,15 @@ pub async fn parse_jmap_request(
             ) {
                 ("account", "delete", &Method::GET) => {
                     return if let Some(account_name) = path.next() {
-                        if let Ok(Some(account_id)) = jmap.try_get_account_id(account_name).await {
+                        if !jmap.has_admin_permission(
+                            &access_token,
+                            AdminPermission::AccountManage,
+                            account_name.into(),
+                        ) {
+                            RequestError::forbidden().into_http_response()
+                        } else if let Ok(Some(account_id)) =
+                            jmap.try_get_account_id(account_name).await
+                        {
                             match jmap.delete_account(account_name, account_id).await {
                                 Ok(_) => JsonResponse::new(Value::String("success".into()))
                                     .into_http_response(),
@@ -322,6 +452,18 @@ pub async fn parse_jmap_request(
                     return if let (Some(account_name), Some(new_account_name)) =
                         (path.next(), path.next())
                     {
+                        if !jmap.has_admin_permission(
+                            &access_token,
+                            AdminPermission::AccountManage,
+                            account_name.into(),
+                        ) || !jmap.has_admin_permission(
+                            &access_token,
+                            AdminPermission::AccountManage,
+                            new_account_name.into(),
+                        ) {
+                            return RequestError::forbidden().into_http_response();
+                        }
+
                         match (
                             jmap.try_get_account_id(account_name).await,
                             jmap.try_get_account_id(new_account_name).await,
@@ -364,7 +506,123 @@ pub async fn parse_jmap_request(
                         .into_http_response()
                     };
                 }
-                ("blob", "purge", &Method::GET) => {
+                ("catch-all", "claim", &Method::GET) => {
+                    return if let (Some(account_name), Some(email_id), Some(new_account_name)) = (
+                        path.next(),
+                        path.next().and_then(|p| Id::from_bytes(p.as_bytes())),
+                        path.next(),
+                    ) {
+                        if !jmap.has_admin_permission(
+                            &access_token,
+                            AdminPermission::CatchAllClaim,
+                            account_name.into(),
+                        ) || !jmap.has_admin_permission(
+                            &access_token,
+                            AdminPermission::CatchAllClaim,
+                            new_account_name.into(),
+                        ) {
+                            return RequestError::forbidden().into_http_response();
+                        }
+
+                        match jmap.try_get_account_id(account_name).await {
+                            Ok(Some(account_id)) => {
+                                match jmap
+                                    .catch_all_claim(
+                                        account_id,
+                                        email_id.document_id(),
+                                        new_account_name,
+                                    )
+                                    .await
+                                {
+                                    Ok(_) => JsonResponse::new(Value::String("success".into()))
+                                        .into_http_response(),
+                                    Err(err) => err.into_http_response(),
+                                }
+                            }
+                            Ok(None) => RequestError::blank(
+                                StatusCode::NOT_FOUND.as_u16(),
+                                "Not found",
+                                "Account not found.",
+                            )
+                            .into_http_response(),
+                            Err(_) => RequestError::internal_server_error().into_http_response(),
+                        }
+                    } else {
+                        RequestError::blank(
+                            StatusCode::BAD_REQUEST.as_u16(),
+                            "Invalid parameters",
+                            "Expected account name, email id and new account name",
+                        )
+                        .into_http_response()
+                    };
+                }
+                ("policy", action @ ("get" | "set" | "delete"), method) => {
+                    let is_post = *method == Method::POST;
+                    let domain = if let Some(domain) = path
+                        .next()
+                        .map(|domain| domain.trim_end_matches('.').to_lowercase())
+                        .filter(|domain| !domain.is_empty())
+                    {
+                        domain
+                    } else {
+                        return RequestError::blank(
+                            StatusCode::BAD_REQUEST.as_u16(),
+                            "Invalid parameters",
+                            "Expected domain name",
+                        )
+                        .into_http_response();
+                    };
+
+                    // Domain administrators can only manage the policies of their own domains
+                    if !role.has_permission(AdminPermission::PolicyManage)
+                        || tenant
+                            .as_ref()
+                            .map_or(false, |tenant| !tenant.owns_domain(&domain))
+                    {
+                        return RequestError::forbidden().into_http_response();
+                    }
+
+                    let policy = match (action, is_post) {
+                        ("get", false) => {
+                            return match jmap.get_domain_policy(&domain).await {
+                                Ok(policy) => JsonResponse::new(policy).into_http_response(),
+                                Err(_) => {
+                                    RequestError::internal_server_error().into_http_response()
+                                }
+                            };
+                        }
+                        ("set", true) => {
+                            match fetch_body(&mut req, jmap.config.request_max_size, &access_token)
+                                .await
+                                .and_then(|bytes| {
+                                    serde_json::from_slice::<DomainPolicy>(&bytes).ok()
+                                })
+                                .ok_or_else(|| "Invalid domain policy.".to_string())
+                                .and_then(|policy| policy.normalize())
+                            {
+                                Ok(policy) => policy,
+                                Err(err) => {
+                                    return RequestError::blank(
+                                        StatusCode::BAD_REQUEST.as_u16(),
+                                        "Invalid parameters",
+                                        err,
+                                    )
+                                    .into_http_response();
+                                }
+                            }
+                        }
+                        ("delete", false) => DomainPolicy::default(),
+                        _ => return RequestError::not_found().into_http_response(),
+                    };
+
+                    return match jmap.set_domain_policy(&domain, policy).await {
+                        Ok(_) => {
+                            JsonResponse::new(Value::String("success".into())).into_http_response()
+                        }
+                        Err(_) => RequestError::internal_server_error().into_http_response(),
+                    };
+                }
+                ("blob", "purge", &Method::GET) if access_token.is_super_user() => {
                     return match jmap.store.purge_tmp_blobs(jmap.config.upload_tmp_ttl).await {
                         Ok(_) => {
                             JsonResponse::new(Value::String("success".into())).into_http_response()
@@ -377,12 +635,106 @@ pub async fn parse_jmap_request(
                         .into_http_response(),
                     };
                 }
-                (path_1 @ ("queue" | "report"), path_2, &Method::GET) => {
+                ("spam-training", "report", &Method::GET) if access_token.is_super_user() => {
+                    return JsonResponse::new(jmap.spam_train_report.summary())
+                        .into_http_response();
+                }
+                ("session", "report", &Method::GET) if access_token.is_super_user() => {
+                    return JsonResponse::new(jmap.session_report()).into_http_response();
+                }
+                ("whoami", "", &Method::GET) => {
+                    return JsonResponse::new(serde_json::json!({
+                        "name": access_token.name,
+                        "role": role,
+                        "permissions": role.permissions(),
+                        "domains": tenant.as_ref().map(|tenant| tenant.domains.clone()),
+                    }))
+                    .into_http_response();
+                }
+                (path_1 @ ("queue" | "report" | "usage" | "anomaly"), path_2, &Method::GET)
+                    if role.has_permission(match (path_1, path_2) {
+                        ("queue", "list" | "status") => AdminPermission::QueueView,
+                        ("queue", "retry") => AdminPermission::QueueRetry,
+                        ("queue", "cancel") => AdminPermission::QueueCancel,
+                        ("usage", "list" | "get") => AdminPermission::UsageView,
+                        _ => AdminPermission::ServerManage,
+                    }) =>
+                {
+                    // Tenant roles only see messages and usage belonging to their own domains
                     return jmap
                         .smtp
-                        .handle_manage_request(req.uri(), req.method(), path_1, path_2)
+                        .core()
+                        .handle_manage_request(
+                            req.uri(),
+                            req.method(),
+                            path_1,
+                            path_2,
+                            tenant.as_ref().map(|tenant| tenant.domains.as_slice()),
+                        )
                         .await;
                 }
+                ("config", "reload", &Method::GET) if access_token.is_super_user() => {
+                    return match jmap.smtp.reload().await {
+                        Ok(result) => JsonResponse::new(result).into_http_response(),
+                        Err(err) => RequestError::blank(
+                            StatusCode::BAD_REQUEST.as_u16(),
+                            "Invalid configuration",
+                            err,
+                        )
+                        .into_http_response(),
+                    };
+                }
+                ("log", "level", &Method::GET) if access_token.is_super_user() => {
+                    return match tracing_levels() {
+                        Some(levels) => JsonResponse::new(levels).into_http_response(),
+                        None => RequestError::not_found().into_http_response(),
+                    };
+                }
+                ("log", "level", &Method::POST) if access_token.is_super_user() => {
+                    return if let (Some(context), Some(level)) = (path.next(), path.next()) {
+                        match set_tracing_level(context, level) {
+                            Ok(levels) => JsonResponse::new(levels).into_http_response(),
+                            Err(err) => RequestError::blank(
+                                StatusCode::BAD_REQUEST.as_u16(),
+                                "Invalid parameters",
+                                err,
+                            )
+                            .into_http_response(),
+                        }
+                    } else {
+                        RequestError::blank(
+                            StatusCode::BAD_REQUEST.as_u16(),
+                            "Invalid parameters",
+                            "Expected context and log level",
+                        )
+                        .into_http_response()
+                    };
+                }
+                ("oauth", "rotate-keys", &Method::POST) if access_token.is_super_user() => {
+                    return match jmap.rotate_jwt_keys().await {
+                        Ok(keys) => JsonResponse::new(Value::String(
+                            keys.current()
+                                .map(|key| key.kid.clone())
+                                .unwrap_or_default(),
+                        ))
+                        .into_http_response(),
+                        Err(err) => RequestError::blank(
+                            StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
+                            "Key rotation failed",
+                            err,
+                        )
+                        .into_http_response(),
+                    };
+                }
+                ("blob", "purge", _)
+                | ("config", "reload", _)
+                | ("oauth", "rotate-keys", _)
+                | ("log", "level", _)
+                | ("spam-training", "report", _)
+                | ("session", "report", _)
+                | ("queue" | "report" | "usage" | "anomaly", _, _) => {
+                    return RequestError::forbidden().into_http_response();
+                }
                 _ => (),
             }
         }
@@ -400,6 +752,18 @@ impl SessionManager for JmapSessionManager {
                 let span = session.span;
                 match tls_acceptor.accept(session.stream).await {
                     Ok(stream) => {
+                        let client_cert = stream
+                            .get_ref()
+                            .1
+                            .peer_certificates()
+                            .and_then(|certs| certs.first())
+                            .map(|cert| {
+                                ClientCertificate(Arc::new(client_certificate_identities(cert)))
+                            });
+                        let server_name = TlsServerName {
+                            name: stream.get_ref().1.server_name().map(Arc::from),
+                            tenant: session.instance.tenant(&stream).map(Arc::from),
+                        };
                         handle_request(
                             jmap,
                             SessionData {
@@ -411,6 +775,8 @@ impl SessionManager for JmapSessionManager {
                                 in_flight: session.in_flight,
                                 instance: session.instance,
                             },
+                            client_cert,
+                            server_name.into(),
                         )
                         .await;
                     }
@@ -425,7 +791,7 @@ impl SessionManager for JmapSessionManager {
                     }
                 }
             } else {
-                handle_request(jmap, session).await;
+                handle_request(jmap, session, None, None).await;
             }
         });
     }
@@ -441,29 +807,77 @@ impl SessionManager for JmapSessionManager {
 async fn handle_request<T: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
     jmap: Arc<JMAP>,
     session: SessionData<T>,
+    client_cert: Option<ClientCertificate>,
+    server_name: Option<TlsServerName>,
 ) {
     let span = session.span;
     let _in_flight = session.in_flight;
+    let mut shutdown_rx = session.instance.shutdown_rx.clone();
 
-    if let Err(http_err) = http1::Builder::new()
+    let connection = http1::Builder::new()
         .keep_alive(true)
         .serve_connection(
             TokioIo::new(session.stream),
-            service_fn(|req: hyper::Request<body::Incoming>| {
+            service_fn(|mut req: hyper::Request<body::Incoming>| {
                 let jmap = jmap.clone();
                 let span = span.clone();
                 let instance = session.instance.clone();
+                let client_cert = client_cert.clone();
+                let server_name = server_name.clone();
 
                 async move {
                     tracing::debug!(
                         parent: &span,
                         event = "request",
                         uri = req.uri().to_string(),
+                        remote_ip = forwarded_client_ip(
+                            req.headers(),
+                            session.remote_ip,
+                            &jmap.config.http_trusted_proxies
+                        )
+                        .to_string(),
                     );
 
+                    let accept_encoding = req
+                        .headers()
+                        .get(header::ACCEPT_ENCODING)
+                        .and_then(|h| h.to_str().ok())
+                        .map(|h| h.to_string());
+
+                    // Attach client certificate and SNI server name
+                    if let Some(client_cert) = client_cert {
+                        req.extensions_mut().insert(client_cert);
+                    }
+                    if let Some(server_name) = server_name {
+                        req.extensions_mut().insert(server_name);
+                    }
+
+                    // Answer CORS preflight requests without authentication
+                    let cors = jmap.config.http_cors.as_ref().and_then(|cors| {
+                        req.headers()
+                            .get(header::ORIGIN)
+                            .and_then(|origin| origin.to_str().ok())
+                            .filter(|_| is_cors_path(req.uri().path()))
+                            .map(|origin| {
+                                (
+                                    cors,
+                                    origin.to_string(),
+                                    cors.is_preflight(req.method(), req.headers()),
+                                )
+                            })
+                    });
+                    if let Some((cors, origin, true)) = &cors {
+                        let mut response = ().into_http_response();
+                        cors.apply(origin, true, &mut response);
+                        return Ok::<_, hyper::Error>(response);
+                    }
+
                     // Parse JMAP request
                     let mut response =
                         parse_jmap_request(jmap.clone(), req, session.remote_ip, instance).await;
+                    if let Some((cors, origin, _)) = &cors {
+                        cors.apply(origin, false, &mut response);
+                    }
 
                     // Add custom headers
                     if !jmap.config.http_headers.is_empty() {
@@ -474,13 +888,30 @@ async fn handle_request<T: AsyncRead + AsyncWrite + Unpin + Send + 'static>(
                         }
                     }
 
+                    // Compress response
+                    if let Some(compression) = &jmap.config.http_compression {
+                        response = compression
+                            .compress_response(accept_encoding.as_deref(), response)
+                            .await;
+                    }
+
                     Ok::<_, hyper::Error>(response)
                 }
             }),
         )
-        .with_upgrades()
-        .await
-    {
+        .with_upgrades();
+    tokio::pin!(connection);
+
+    // On shutdown, finish the request in progress and close the connection
+    let result = tokio::select! {
+        result = connection.as_mut() => result,
+        _ = shutdown_rx.changed() => {
+            connection.as_mut().graceful_shutdown();
+            connection.await
+        }
+    };
+
+    if let Err(http_err) = result {
         tracing::debug!(
             parent: &span,
             event = "error",
@@ -506,7 +937,30 @@ pub async fn fetch_body(
             }
         }
     }
-    bytes.into()
+
+    // Decompress request body, limiting the decompressed size
+    match req
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.trim())
+    {
+        None | Some("" | "identity") => bytes.into(),
+        Some(encoding) => {
+            let encoding = ContentEncoding::parse(encoding)?;
+            let max_size = if max_size == 0 {
+                bytes.len().saturating_mul(MAX_COMPRESSION_RATIO)
+            } else if access_token.is_super_user() {
+                std::cmp::max(max_size, bytes.len())
+            } else {
+                max_size
+            };
+            tokio::task::spawn_blocking(move || encoding.decompress(&bytes, max_size).ok())
+                .await
+                .ok()
+                .flatten()
+        }
+    }
 }
 
 pub trait ToHttpResponse {
@@ -569,22 +1023,53 @@ impl ToHttpResponse for Session {
 
 impl ToHttpResponse for DownloadResponse {
     fn into_http_response(self) -> HttpResponse {
-        hyper::Response::builder()
-            .status(StatusCode::OK)
-            .header(header::CONTENT_TYPE, self.content_type)
-            .header(
-                header::CONTENT_DISPOSITION,
-                format!(
-                    "attachment; filename=\"{}\"",
-                    self.filename.replace('\"', "\\\"")
-                ),
-            )
+        let mut builder = hyper::Response::builder()
+            .header(header::ETAG, &self.blob.etag)
             .header(
                 header::CACHE_CONTROL,
                 "private, immutable, max-age=31536000",
             )
+            .header(header::ACCEPT_RANGES, "bytes");
+        if let Some(last_modified) = self.blob.last_modified.and_then(http_date) {
+            builder = builder.header(header::LAST_MODIFIED, last_modified);
+        }
+
+        let (status, body) = match self.blob.body {
+            DownloadBody::Full(bytes) => (StatusCode::OK, bytes),
+            DownloadBody::Partial {
+                bytes,
+                start,
+                end,
+                total,
+            } => {
+                builder = builder.header(
+                    header::CONTENT_RANGE,
+                    format!("bytes {start}-{end}/{total}"),
+                );
+                (StatusCode::PARTIAL_CONTENT, bytes)
+            }
+            DownloadBody::NotModified => (StatusCode::NOT_MODIFIED, vec![]),
+            DownloadBody::RangeNotSatisfiable { total } => {
+                builder = builder.header(header::CONTENT_RANGE, format!("bytes */{total}"));
+                (StatusCode::RANGE_NOT_SATISFIABLE, vec![])
+            }
+        };
+        if matches!(status, StatusCode::OK | StatusCode::PARTIAL_CONTENT) {
+            builder = builder
+                .header(header::CONTENT_TYPE, self.content_type)
+                .header(
+                    header::CONTENT_DISPOSITION,
+                    format!(
+                        "attachment; filename=\"{}\"",
+                        self.filename.replace('\"', "\\\"")
+                    ),
+                );
+        }
+
+        builder
+            .status(status)
             .body(
-                Full::new(Bytes::from(self.blob))
+                Full::new(Bytes::from(body))
                     .map_err(|never| match never {})
                     .boxed(),
             )
@@ -600,9 +1085,13 @@ impl ToHttpResponse for UploadResponse {
 
 impl ToHttpResponse for RequestError {
     fn into_http_response(self) -> HttpResponse {
-        hyper::Response::builder()
+        let mut response = hyper::Response::builder()
             .status(StatusCode::from_u16(self.status).unwrap())
-            .header(header::CONTENT_TYPE, "application/problem+json")
+            .header(header::CONTENT_TYPE, "application/problem+json");
+        if let Some(retry_after) = self.retry_after {
+            response = response.header(header::RETRY_AFTER, retry_after);
+        }
+        response
             .body(
                 Full::new(Bytes::from(serde_json::to_string(&self).unwrap()))
                     .map_err(|never| match never {})
@@ -31,11 +31,18 @@ use utils::map::vec_map::VecMap;
 use crate::JMAP;
 
 pub mod admin;
+pub mod attachment_link;
+pub mod compression;
 pub mod config;
+pub mod console;
+pub mod cors;
 pub mod event_source;
+pub mod health;
+pub mod html;
 pub mod http;
 pub mod request;
 pub mod session;
+pub mod unsubscribe;
 
 #[derive(Clone)]
 pub struct JmapSessionManager {
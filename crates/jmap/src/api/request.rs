@@ -29,7 +29,7 @@ use jmap_proto::{
         get, query,
         set::{self},
     },
-    request::{method::MethodName, Call, Request, RequestMethod},
+    request::{method::MethodName, reference::MaybeReference, Call, Request, RequestMethod},
     response::{Response, ResponseMethod},
     types::collection::Collection,
 };
@@ -44,12 +44,15 @@ impl JMAP {
         access_token: Arc<AccessToken>,
         instance: &Arc<ServerInstance>,
     ) -> Result<Response, RequestError> {
+        self.is_method_calls_allowed(&access_token, request.method_calls.len())?;
+
         let mut response = Response::new(
             access_token.state(),
             request.created_ids.unwrap_or_default(),
             request.method_calls.len(),
         );
         let add_created_ids = !response.created_ids.is_empty();
+        let mut total_cost = 0;
 
         for mut call in request.method_calls {
             // Resolve result and id references
@@ -61,6 +64,13 @@ impl JMAP {
             loop {
                 let mut next_call = None;
 
+                // Refuse to process calls once the request has exceeded its cost budget
+                total_cost += self.method_cost(&call.method);
+                if total_cost > self.config.request_max_cost {
+                    response.push_error(call.id, MethodError::RequestTooLarge);
+                    break;
+                }
+
                 // Add response
                 match self
                     .handle_method_call(call.method, &access_token, &mut next_call, instance)
@@ -123,6 +133,69 @@ impl JMAP {
         Ok(response)
     }
 
+    // Estimates the number of objects a method call may process
+    fn method_cost(&self, method: &RequestMethod) -> usize {
+        let cost = match method {
+            RequestMethod::Get(req) => match &req.ids {
+                Some(MaybeReference::Value(ids)) => ids.len(),
+                _ => self.config.get_max_objects,
+            },
+            RequestMethod::Set(req) => {
+                req.create.as_ref().map_or(0, |objs| objs.len())
+                    + req.update.as_ref().map_or(0, |objs| objs.len())
+                    + match &req.destroy {
+                        Some(MaybeReference::Value(ids)) => ids.len(),
+                        Some(_) => self.config.set_max_objects,
+                        None => 0,
+                    }
+            }
+            RequestMethod::Changes(req) => req
+                .max_changes
+                .unwrap_or(self.config.changes_max_results)
+                .min(self.config.changes_max_results),
+            RequestMethod::QueryChanges(req) => req
+                .max_changes
+                .unwrap_or(self.config.changes_max_results)
+                .min(self.config.changes_max_results),
+            RequestMethod::Query(req) => req
+                .limit
+                .unwrap_or(self.config.query_max_results)
+                .min(self.config.query_max_results),
+            RequestMethod::Copy(req) => req.create.len(),
+            RequestMethod::CopyBlob(req) => req.blob_ids.len(),
+            RequestMethod::ImportEmail(req) => req.emails.len(),
+            RequestMethod::ParseEmail(req) => req.blob_ids.len(),
+            RequestMethod::SearchSnippet(req) => match &req.email_ids {
+                MaybeReference::Value(ids) => ids.len(),
+                MaybeReference::Reference(_) => self.config.get_max_objects,
+            },
+            RequestMethod::LookupBlob(req) => req.ids.len(),
+            RequestMethod::UploadBlob(req) => req.create.len(),
+            RequestMethod::SendMdn(req) => req.send.len(),
+            RequestMethod::ParseMdn(req) => req.blob_ids.len(),
+            RequestMethod::GetKeyword(req) => req
+                .ids
+                .as_ref()
+                .map_or(self.config.get_max_objects, |ids| ids.len()),
+            RequestMethod::RenameKeyword(req) => req.rename.len(),
+            RequestMethod::GetCollectedAddress(req) => req
+                .limit
+                .unwrap_or(self.config.get_max_objects)
+                .min(self.config.get_max_objects),
+            RequestMethod::GetMailingList(req) => req
+                .ids
+                .as_ref()
+                .map_or(self.config.get_max_objects, |ids| ids.len()),
+            RequestMethod::SetMailingList(req) => req.update.len(),
+            RequestMethod::UnsubscribeMailingList(req) => req.ids.len(),
+            RequestMethod::ValidateScript(_) | RequestMethod::Echo(_) | RequestMethod::Error(_) => {
+                0
+            }
+        };
+
+        cost.max(1)
+    }
+
     async fn handle_method_call(
         &self,
         method: RequestMethod,
@@ -193,10 +266,25 @@ impl JMAP {
                         .await?
                         .into()
                 }
+                get::RequestArguments::MaskedEmail => {
+                    access_token.assert_is_member(req.account_id)?;
+
+                    self.masked_email_get(req).await?.into()
+                }
+                get::RequestArguments::ShareInvitation => {
+                    access_token.assert_is_member(req.account_id)?;
+
+                    self.share_invitation_get(req).await?.into()
+                }
             },
             RequestMethod::Query(mut req) => match req.take_arguments() {
                 query::RequestArguments::Email(arguments) => {
                     access_token.assert_has_access(req.account_id, Collection::Email)?;
+                    let _in_flight = self.is_query_allowed(
+                        access_token,
+                        req.limit,
+                        req.calculate_total.unwrap_or(false),
+                    )?;
 
                     self.email_query(req.with_arguments(arguments), access_token)
                         .await?
@@ -274,6 +362,21 @@ impl JMAP {
 
                     self.vacation_response_set(req).await?.into()
                 }
+                set::RequestArguments::MaskedEmail => {
+                    access_token.assert_is_member(req.account_id)?;
+
+                    self.masked_email_set(req, access_token).await?.into()
+                }
+                set::RequestArguments::ShareInvitation => {
+                    access_token.assert_is_member(req.account_id)?;
+
+                    self.share_invitation_set(req).await?.into()
+                }
+                set::RequestArguments::Principal => {
+                    access_token.assert_is_member(req.account_id)?;
+
+                    self.principal_set(req, access_token).await?.into()
+                }
             },
             RequestMethod::Changes(req) => self.changes(req, access_token).await?.into(),
             RequestMethod::Copy(req) => {
@@ -319,6 +422,46 @@ impl JMAP {
 
                 self.blob_upload_many(req, access_token).await?.into()
             }
+            RequestMethod::SendMdn(req) => {
+                access_token.assert_is_member(req.account_id)?;
+
+                self.mdn_send(req, next_call).await?.into()
+            }
+            RequestMethod::ParseMdn(req) => {
+                access_token.assert_has_access(req.account_id, Collection::Email)?;
+
+                self.mdn_parse(req, access_token).await?.into()
+            }
+            RequestMethod::GetKeyword(req) => {
+                access_token.assert_is_member(req.account_id)?;
+
+                self.keyword_get(req).await?.into()
+            }
+            RequestMethod::RenameKeyword(req) => {
+                access_token.assert_is_member(req.account_id)?;
+
+                self.keyword_rename(req).await?.into()
+            }
+            RequestMethod::GetCollectedAddress(req) => {
+                access_token.assert_is_member(req.account_id)?;
+
+                self.collected_address_get(req).await?.into()
+            }
+            RequestMethod::GetMailingList(req) => {
+                access_token.assert_is_member(req.account_id)?;
+
+                self.mailing_list_get(req).await?.into()
+            }
+            RequestMethod::SetMailingList(req) => {
+                access_token.assert_is_member(req.account_id)?;
+
+                self.mailing_list_set(req).await?.into()
+            }
+            RequestMethod::UnsubscribeMailingList(req) => {
+                access_token.assert_is_member(req.account_id)?;
+
+                self.mailing_list_unsubscribe(req).await?.into()
+            }
             RequestMethod::Echo(req) => req.into(),
             RequestMethod::Error(error) => return Err(error),
         })
@@ -32,7 +32,7 @@ use jmap_proto::{
 use store::ahash::AHashSet;
 use utils::{listener::ServerInstance, map::vec_map::VecMap, UnwrapFailure};
 
-use crate::{auth::AccessToken, JMAP};
+use crate::{auth::AccessToken, sieve::limits::SieveLimits, JMAP};
 
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct Session {
@@ -82,6 +82,8 @@ pub enum Capabilities {
     SieveAccount(SieveAccountCapabilities),
     SieveSession(SieveSessionCapabilities),
     Blob(BlobCapabilities),
+    Principals(PrincipalCapabilities),
+    Upload(UploadCapabilities),
     Empty(EmptyCapabilities),
 }
 
@@ -127,6 +129,8 @@ pub struct SieveAccountCapabilities {
     pub max_script_size: usize,
     #[serde(rename(serialize = "maxNumberScripts"))]
     pub max_scripts: usize,
+    #[serde(rename(serialize = "maxSizeScripts"))]
+    pub max_total_size: usize,
     #[serde(rename(serialize = "maxNumberRedirects"))]
     pub max_redirects: usize,
     #[serde(rename(serialize = "sieveExtensions"))]
@@ -173,9 +177,33 @@ pub struct BlobCapabilities {
     supported_digest_algorithms: Vec<&'static str>,
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct UploadCapabilities {
+    #[serde(rename(serialize = "maxTemporaryBlobs"))]
+    max_tmp_blobs: Option<usize>,
+    #[serde(rename(serialize = "maxTemporaryBytes"))]
+    max_tmp_bytes: Option<usize>,
+    #[serde(rename(serialize = "remainingTemporaryBlobs"))]
+    remaining_tmp_blobs: Option<usize>,
+    #[serde(rename(serialize = "remainingTemporaryBytes"))]
+    remaining_tmp_bytes: Option<usize>,
+    #[serde(rename(serialize = "temporaryBlobTtl"))]
+    tmp_blob_ttl: u64,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct PrincipalCapabilities {
+    #[serde(rename(serialize = "currentUserPrincipalId"))]
+    current_user_principal_id: Option<Id>,
+}
+
 #[derive(Debug, Clone, Default, serde::Serialize)]
 pub struct EmptyCapabilities {}
 
+// Capabilities of accounts shared with the user, such as groups
+pub const SHARED_ACCOUNT_CAPABILITIES: &[Capability] =
+    &[Capability::Mail, Capability::Quota, Capability::Blob];
+
 #[derive(Default)]
 pub struct BaseCapabilities {
     pub session: VecMap<Capability, Capabilities>,
@@ -201,6 +229,38 @@ impl JMAP {
             &self.config.capabilities.account,
         );
 
+        // Apply per-account Sieve limits
+        let sieve_limits = self
+            .sieve_limits(&access_token, access_token.primary_id())
+            .await
+            .map_err(|_| RequestError::internal_server_error())?;
+        if sieve_limits != self.config.sieve_limits {
+            session.set_sieve_limits(access_token.primary_id().into(), sieve_limits);
+        }
+
+        // Reflect the SMTP message size limit in the attachment size limit,
+        // allowing for the base64 encoding overhead
+        let max_size_attachments = self
+            .smtp_max_message_size(&instance, &access_token.name)
+            .await
+            / 4
+            * 3;
+        if max_size_attachments < self.config.mail_attachments_max_size {
+            session
+                .set_max_size_attachments(access_token.primary_id().into(), max_size_attachments);
+        }
+
+        // Point the account to the principal of the authenticated user
+        session.set_current_user_principal(access_token.primary_id().into());
+
+        // Report the remaining temporary upload quota
+        if self.config.upload_tmp_quota_amount > 0 || self.config.upload_tmp_quota_size > 0 {
+            let (used_blobs, used_bytes) = self
+                .get_cached_tmp_blob_usage(access_token.primary_id())
+                .await?;
+            session.set_upload_usage(access_token.primary_id().into(), used_blobs, used_bytes);
+        }
+
         // Add secondary accounts
         for id in access_token.secondary_ids() {
             let is_personal = !access_token.is_member(*id);
@@ -218,7 +278,7 @@ impl JMAP {
                     .unwrap_or_else(|| Id::from(*id).to_string()),
                 is_personal,
                 is_readonly,
-                Some(&[Capability::Mail, Capability::Quota, Capability::Blob]),
+                Some(SHARED_ACCOUNT_CAPABILITIES),
                 &self.config.capabilities.account,
             );
         }
@@ -295,6 +355,16 @@ impl crate::Config {
             Capabilities::Blob(BlobCapabilities::new(self)),
         );
 
+        // Add upload capabilities
+        self.capabilities.session.append(
+            Capability::Upload,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::Upload,
+            Capabilities::Upload(UploadCapabilities::new(self)),
+        );
+
         // Add Quota capabilities
         self.capabilities.session.append(
             Capability::Quota,
@@ -304,6 +374,86 @@ impl crate::Config {
             Capability::Quota,
             Capabilities::Empty(EmptyCapabilities::default()),
         );
+
+        // Add Snooze capabilities
+        self.capabilities.session.append(
+            Capability::Snooze,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::Snooze,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+
+        // Add MDN capabilities
+        self.capabilities.session.append(
+            Capability::Mdn,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::Mdn,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+
+        // Add masked email capabilities
+        self.capabilities.session.append(
+            Capability::MaskedEmail,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::MaskedEmail,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+
+        // Add keyword registry capabilities
+        self.capabilities.session.append(
+            Capability::Keywords,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::Keywords,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+
+        // Add mailing list capabilities
+        self.capabilities.session.append(
+            Capability::MailingLists,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::MailingLists,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+
+        // Add autocomplete capabilities
+        self.capabilities.session.append(
+            Capability::Autocomplete,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::Autocomplete,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+
+        // Add sharing capabilities
+        self.capabilities.session.append(
+            Capability::Sharing,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::Sharing,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+
+        // Add principal capabilities
+        self.capabilities.session.append(
+            Capability::Principals,
+            Capabilities::Empty(EmptyCapabilities::default()),
+        );
+        self.capabilities.account.append(
+            Capability::Principals,
+            Capabilities::Principals(PrincipalCapabilities::default()),
+        );
     }
 }
 
@@ -361,6 +511,54 @@ impl Session {
         );
     }
 
+    pub fn set_sieve_limits(&mut self, account_id: Id, limits: SieveLimits) {
+        if let Some(Capabilities::SieveAccount(sieve)) = self
+            .accounts
+            .get_mut(&account_id)
+            .and_then(|account| account.account_capabilities.get_mut(&Capability::Sieve))
+        {
+            sieve.max_scripts = limits.max_scripts;
+            sieve.max_total_size = limits.max_total_size;
+        }
+    }
+
+    pub fn set_max_size_attachments(&mut self, account_id: Id, max_size: usize) {
+        if let Some(Capabilities::Mail(mail)) = self
+            .accounts
+            .get_mut(&account_id)
+            .and_then(|account| account.account_capabilities.get_mut(&Capability::Mail))
+        {
+            mail.max_size_attachments_per_email = max_size;
+        }
+    }
+
+    pub fn set_upload_usage(&mut self, account_id: Id, used_blobs: usize, used_bytes: usize) {
+        if let Some(Capabilities::Upload(upload)) = self
+            .accounts
+            .get_mut(&account_id)
+            .and_then(|account| account.account_capabilities.get_mut(&Capability::Upload))
+        {
+            upload.remaining_tmp_blobs = upload
+                .max_tmp_blobs
+                .map(|max| max.saturating_sub(used_blobs));
+            upload.remaining_tmp_bytes = upload
+                .max_tmp_bytes
+                .map(|max| max.saturating_sub(used_bytes));
+        }
+    }
+
+    pub fn set_current_user_principal(&mut self, account_id: Id) {
+        if let Some(Capabilities::Principals(principals)) =
+            self.accounts.get_mut(&account_id).and_then(|account| {
+                account
+                    .account_capabilities
+                    .get_mut(&Capability::Principals)
+            })
+        {
+            principals.current_user_principal_id = account_id.into();
+        }
+    }
+
     pub fn add_account(
         &mut self,
         account_id: Id,
@@ -476,7 +674,8 @@ impl SieveAccountCapabilities {
                 .property("sieve.untrusted.max-script-size")
                 .failed("Invalid configuration file")
                 .unwrap_or(1024 * 1024),
-            max_scripts: config.sieve_max_scripts,
+            max_scripts: config.sieve_limits.max_scripts,
+            max_total_size: config.sieve_limits.max_total_size,
             max_redirects: settings
                 .property("sieve.untrusted.max-redirects")
                 .failed("Invalid configuration file")
@@ -526,6 +725,20 @@ impl MailCapabilities {
     }
 }
 
+impl UploadCapabilities {
+    pub fn new(config: &crate::Config) -> Self {
+        let max_tmp_blobs = Some(config.upload_tmp_quota_amount).filter(|max| *max > 0);
+        let max_tmp_bytes = Some(config.upload_tmp_quota_size).filter(|max| *max > 0);
+        UploadCapabilities {
+            max_tmp_blobs,
+            max_tmp_bytes,
+            remaining_tmp_blobs: max_tmp_blobs,
+            remaining_tmp_bytes: max_tmp_bytes,
+            tmp_blob_ttl: config.upload_tmp_ttl,
+        }
+    }
+}
+
 impl BlobCapabilities {
     pub fn new(config: &crate::Config) -> Self {
         BlobCapabilities {
@@ -0,0 +1,93 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use hyper::{Method, StatusCode};
+use smtp::unsubscribe::UnsubscribeError;
+use utils::codec::html::html_escape;
+
+use crate::JMAP;
+
+use super::{http::ToHttpResponse, HtmlResponse, HttpResponse};
+
+impl JMAP {
+    // Unsubscribe links are opened by link scanners and prefetchers, so GET requests
+    // only display a confirmation form and recipients are unsubscribed on POST,
+    // which is also what RFC 8058 one-click clients send.
+    pub async fn handle_unsubscribe_request(&self, method: &Method, token: &str) -> HttpResponse {
+        if token.is_empty()
+            || !token
+                .bytes()
+                .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, b'-' | b'_' | b'.'))
+        {
+            return unsubscribe_page(StatusCode::BAD_REQUEST, "Invalid unsubscribe link.");
+        }
+
+        let smtp = self.smtp.core();
+        match *method {
+            Method::GET => match smtp.unsubscribe.verify(token) {
+                Ok(token) => HtmlResponse::new(format!(
+                    concat!(
+                        "<!DOCTYPE html><html><head><title>Unsubscribe</title></head><body>",
+                        "<p>Unsubscribe {} from these messages?</p>",
+                        "<form method=\"post\"><button type=\"submit\">Unsubscribe</button>",
+                        "</form></body></html>"
+                    ),
+                    html_escape(&token.to)
+                ))
+                .into_http_response(),
+                Err(err) => unsubscribe_error(err),
+            },
+            Method::POST => match smtp.process_unsubscribe(token).await {
+                Ok(_) => unsubscribe_page(StatusCode::OK, "You have been unsubscribed."),
+                Err(err) => unsubscribe_error(err),
+            },
+            _ => unsubscribe_page(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed."),
+        }
+    }
+}
+
+fn unsubscribe_error(err: UnsubscribeError) -> HttpResponse {
+    match err {
+        UnsubscribeError::Disabled => unsubscribe_page(StatusCode::NOT_FOUND, "Not found."),
+        UnsubscribeError::Invalid => {
+            unsubscribe_page(StatusCode::BAD_REQUEST, "Invalid unsubscribe link.")
+        }
+        UnsubscribeError::Expired => {
+            unsubscribe_page(StatusCode::GONE, "This unsubscribe link has expired.")
+        }
+    }
+}
+
+fn unsubscribe_page(status: StatusCode, message: &str) -> HttpResponse {
+    HtmlResponse::with_status(
+        status,
+        format!(
+            concat!(
+                "<!DOCTYPE html><html><head><title>Unsubscribe</title></head>",
+                "<body><p>{}</p></body></html>"
+            ),
+            message
+        ),
+    )
+    .into_http_response()
+}
@@ -25,13 +25,18 @@ use jmap_proto::{
     error::{method::MethodError, set::SetError},
     object::Object,
     types::{
-        acl::Acl,
+        acl::{
+            is_public_grantee, public_grantee_id, public_grantee_name, Acl, AclGrant, ACL_ANYONE,
+            ACL_AUTHENTICATED,
+        },
         collection::Collection,
+        id::Id,
         property::Property,
         value::{MaybePatchValue, Value},
     },
 };
 use store::{
+    ahash::AHashMap,
     roaring::RoaringBitmap,
     write::{assert::HashedValue, key::DeserializeBigEndian},
     AclKey, Deserialize, Error,
@@ -44,6 +49,7 @@ use super::AccessToken;
 
 impl JMAP {
     pub async fn update_access_token(&self, mut access_token: AccessToken) -> Option<AccessToken> {
+        let mut denied: AHashMap<(u32, u8, u32), Bitmap<Acl>> = AHashMap::new();
         for &grant_account_id in [access_token.primary_id]
             .iter()
             .chain(access_token.member_of.clone().iter())
@@ -63,47 +69,39 @@ impl JMAP {
             match self
                 .store
                 .iterate(
-                    access_token,
+                    (access_token, denied),
                     from_key,
                     to_key,
                     false,
                     true,
-                    |access_token, key, value| {
+                    |(access_token, denied), key, value| {
                         let acl_key = AclKey::deserialize(key)?;
                         if access_token.is_member(acl_key.to_account_id) {
                             return Ok(true);
                         }
 
-                        let acl = Bitmap::<Acl>::from(u64::deserialize(value)?);
-                        let collection = Collection::from(acl_key.to_collection);
-                        if !collection.is_valid() {
-                            return Err(Error::InternalError(format!(
-                                "Found corrupted collection in key {key:?}"
-                            )));
-                        }
-
-                        let mut collections: Bitmap<Collection> = Bitmap::new();
-                        if acl.contains(Acl::Read) || acl.contains(Acl::Administer) {
-                            collections.insert(collection);
-                        }
-                        if collection == Collection::Mailbox
-                            && (acl.contains(Acl::ReadItems) || acl.contains(Acl::Administer))
-                        {
-                            collections.insert(Collection::Email);
+                        let grant = AclGrant::from(u64::deserialize(value)?);
+                        if !grant.deny.is_empty() {
+                            denied
+                                .entry((
+                                    acl_key.to_account_id,
+                                    acl_key.to_collection,
+                                    acl_key.to_document_id,
+                                ))
+                                .or_default()
+                                .union(&grant.deny);
                         }
 
+                        let collections = granted_collections(
+                            acl_key_collection(&acl_key, key)?,
+                            grant.effective(),
+                        );
                         if !collections.is_empty() {
-                            if let Some((_, sharing)) = access_token
-                                .access_to
-                                .iter_mut()
-                                .find(|(account_id, _)| *account_id == acl_key.to_account_id)
-                            {
-                                sharing.union(&collections);
-                            } else {
-                                access_token
-                                    .access_to
-                                    .push((acl_key.to_account_id, collections));
-                            }
+                            add_access_to(
+                                &mut access_token.access_to,
+                                acl_key.to_account_id,
+                                collections,
+                            );
                         }
 
                         Ok(true)
@@ -111,8 +109,9 @@ impl JMAP {
                 )
                 .await
             {
-                Ok(access_token_) => {
+                Ok((access_token_, denied_)) => {
                     access_token = access_token_;
+                    denied = denied_;
                 }
                 Err(err) => {
                     tracing::error!(
@@ -124,6 +123,92 @@ impl JMAP {
                 }
             }
         }
+
+        // Mailboxes shared with "anyone" or "authenticated" are visible to the owner's domain
+        let mut public_grants: Vec<(AclKey, Collection, AclGrant)> = Vec::new();
+        for grant_account_id in [ACL_ANYONE, ACL_AUTHENTICATED] {
+            let from_key = AclKey {
+                grant_account_id,
+                to_account_id: 0,
+                to_collection: 0,
+                to_document_id: 0,
+            };
+            let to_key = AclKey {
+                grant_account_id,
+                to_account_id: u32::MAX,
+                to_collection: u8::MAX,
+                to_document_id: u32::MAX,
+            };
+            match self
+                .store
+                .iterate(
+                    public_grants,
+                    from_key,
+                    to_key,
+                    false,
+                    true,
+                    |public_grants, key, value| {
+                        let acl_key = AclKey::deserialize(key)?;
+                        public_grants.push((
+                            acl_key,
+                            acl_key_collection(&acl_key, key)?,
+                            AclGrant::from(u64::deserialize(value)?),
+                        ));
+
+                        Ok(true)
+                    },
+                )
+                .await
+            {
+                Ok(public_grants_) => {
+                    public_grants = public_grants_;
+                }
+                Err(err) => {
+                    tracing::error!(
+                        event = "error",
+                        context = "shared_accounts",
+                        error = ?err,
+                        "Failed to iterate ACLs.");
+                    return None;
+                }
+            }
+        }
+
+        let mut other_domains = Vec::new();
+        for (acl_key, collection, mut grant) in public_grants {
+            let to_account_id = acl_key.to_account_id;
+            if access_token.is_member(to_account_id) || other_domains.contains(&to_account_id) {
+                continue;
+            }
+
+            // Rights denied to the user or any of its groups take precedence
+            if let Some(deny) =
+                denied.get(&(to_account_id, acl_key.to_collection, acl_key.to_document_id))
+            {
+                grant.deny.union(deny);
+            }
+            let collections = granted_collections(collection, grant.effective());
+            if collections.is_empty() {
+                continue;
+            }
+
+            if !access_token.public_access.contains(&to_account_id) {
+                match self.get_account_name(to_account_id).await {
+                    Ok(Some(owner)) if same_domain(&owner, &access_token.name) => {
+                        access_token.public_access.push(to_account_id);
+                    }
+                    Ok(_) => {
+                        other_domains.push(to_account_id);
+                        continue;
+                    }
+                    Err(_) => {
+                        return None;
+                    }
+                }
+            }
+            add_access_to(&mut access_token.access_to, to_account_id, collections);
+        }
+
         access_token.into()
     }
 
@@ -135,12 +220,9 @@ impl JMAP {
         check_acls: impl Into<Bitmap<Acl>>,
     ) -> Result<RoaringBitmap, MethodError> {
         let check_acls = check_acls.into();
-        let mut document_ids = RoaringBitmap::new();
+        let mut grants: AHashMap<u32, AclGrant> = AHashMap::new();
         let to_collection = u8::from(to_collection);
-        for &grant_account_id in [access_token.primary_id]
-            .iter()
-            .chain(access_token.member_of.clone().iter())
-        {
+        for grant_account_id in access_token.grantee_ids(to_account_id) {
             let from_key = AclKey {
                 grant_account_id,
                 to_account_id,
@@ -153,28 +235,24 @@ impl JMAP {
             match self
                 .store
                 .iterate(
-                    document_ids,
+                    grants,
                     from_key,
                     to_key,
                     false,
                     true,
-                    move |document_ids, key, value| {
-                        let mut acls = Bitmap::<Acl>::from(u64::deserialize(value)?);
-
-                        acls.intersection(&check_acls);
-                        if !acls.is_empty() {
-                            document_ids.insert(
-                                key.deserialize_be_u32(key.len() - std::mem::size_of::<u32>())?,
-                            );
-                        }
+                    move |grants, key, value| {
+                        grants
+                            .entry(key.deserialize_be_u32(key.len() - std::mem::size_of::<u32>())?)
+                            .or_default()
+                            .union(&AclGrant::from(u64::deserialize(value)?));
 
                         Ok(true)
                     },
                 )
                 .await
             {
-                Ok(document_ids_) => {
-                    document_ids = document_ids_;
+                Ok(grants_) => {
+                    grants = grants_;
                 }
                 Err(err) => {
                     tracing::error!(
@@ -187,7 +265,15 @@ impl JMAP {
             }
         }
 
-        Ok(document_ids)
+        // Denied rights take precedence over any granted ones
+        Ok(grants
+            .into_iter()
+            .filter_map(|(document_id, grant)| {
+                let mut acls = grant.effective();
+                acls.intersection(&check_acls);
+                (!acls.is_empty()).then_some(document_id)
+            })
+            .collect())
     }
 
     pub async fn shared_messages(
@@ -270,10 +356,8 @@ impl JMAP {
     ) -> Result<bool, MethodError> {
         let to_collection = to_collection.into();
         let check_acls = check_acls.into();
-        for &grant_account_id in [access_token.primary_id]
-            .iter()
-            .chain(access_token.member_of.clone().iter())
-        {
+        let mut grant = AclGrant::default();
+        for grant_account_id in access_token.grantee_ids(to_account_id) {
             match self
                 .store
                 .get_value::<u64>(AclKey {
@@ -285,12 +369,7 @@ impl JMAP {
                 .await
             {
                 Ok(Some(acls)) => {
-                    let mut acls = Bitmap::<Acl>::from(acls);
-
-                    acls.intersection(&check_acls);
-                    if !acls.is_empty() {
-                        return Ok(true);
-                    }
+                    grant.union(&AclGrant::from(acls));
                 }
                 Ok(None) => (),
                 Err(err) => {
@@ -303,7 +382,10 @@ impl JMAP {
                 }
             }
         }
-        Ok(false)
+
+        let mut acls = grant.effective();
+        acls.intersection(&check_acls);
+        Ok(!acls.is_empty())
     }
 
     pub async fn acl_set(
@@ -314,10 +396,48 @@ impl JMAP {
     ) -> Result<(), SetError> {
         match acl_changes {
             MaybePatchValue::Value(Value::List(values)) => {
-                changes.properties.set(
-                    Property::Acl,
-                    Value::List(self.map_acl_accounts(values).await?),
-                );
+                let mut acl = self.map_acl_accounts(values).await?;
+
+                // Denied rights can only be managed over IMAP and pending shares
+                // can only be answered by their grantee, keep them
+                if let Some(Value::List(current)) =
+                    current.and_then(|current| current.inner.properties.get(&Property::Acl))
+                {
+                    for item in current.chunks_exact(2) {
+                        if let (Some(Value::Id(id)), Some(Value::UnsignedInt(bits))) =
+                            (item.first(), item.last())
+                        {
+                            let current_grant = AclGrant::from(*bits);
+                            if current_grant.deny.is_empty() && !current_grant.pending {
+                                continue;
+                            }
+                            if let Some(idx) = acl.iter().position(|item| item.as_id() == Some(id))
+                            {
+                                if let Some(Value::UnsignedInt(bits)) = acl.get_mut(idx + 1) {
+                                    *bits = AclGrant {
+                                        grant: AclGrant::from(*bits).grant,
+                                        deny: current_grant.deny,
+                                        pending: current_grant.pending,
+                                    }
+                                    .into();
+                                }
+                            } else if !current_grant.deny.is_empty() {
+                                acl.push(Value::Id(*id));
+                                acl.push(Value::UnsignedInt(
+                                    AclGrant {
+                                        grant: Bitmap::new(),
+                                        deny: current_grant.deny,
+                                        pending: false,
+                                    }
+                                    .into(),
+                                ));
+                            }
+                        }
+                    }
+                }
+
+                self.acl_hold_pending(&mut acl, current).await?;
+                changes.properties.set(Property::Acl, Value::List(acl));
             }
             MaybePatchValue::Patch(patch) => {
                 let patch = self.map_acl_accounts(patch).await?;
@@ -338,56 +458,46 @@ impl JMAP {
                         .with_description("Invalid ACL value found."));
                 };
                 let account_id = patch.first().unwrap().as_id().unwrap();
+                let idx = acl.iter().position(|item| item.as_id() == Some(account_id));
+                let mut grant = match idx.map(|idx| acl.get(idx + 1)) {
+                    Some(Some(Value::UnsignedInt(current))) => AclGrant::from(*current),
+                    Some(_) => {
+                        return Err(SetError::invalid_properties()
+                            .with_property(Property::Acl)
+                            .with_description("Invalid ACL value found."));
+                    }
+                    None => AclGrant::default(),
+                };
                 match patch.len() {
                     2 => {
-                        let acl_update = patch.last().unwrap().as_uint().unwrap();
-                        if let Some(idx) =
-                            acl.iter().position(|item| item.as_id() == Some(account_id))
-                        {
-                            if acl_update != 0 {
-                                acl[idx + 1] = Value::UnsignedInt(acl_update);
-                            } else if acl.len() > 2 {
-                                acl.remove(idx);
-                                acl.remove(idx);
-                            } else {
-                                acl.clear();
-                            }
-                        } else if acl_update != 0 {
-                            acl.push(Value::Id(*account_id));
-                            acl.push(Value::UnsignedInt(acl_update));
-                        }
+                        grant.grant = Bitmap::from(patch.last().unwrap().as_uint().unwrap());
                     }
                     3 => {
                         let acl_item = Acl::from(patch[1].as_uint().unwrap());
-                        let set = patch[2].as_bool().unwrap_or(false);
-                        if let Some(idx) =
-                            acl.iter().position(|item| item.as_id() == Some(account_id))
-                        {
-                            if let Some(Value::UnsignedInt(current)) = acl.get_mut(idx + 1) {
-                                let mut bitmap = Bitmap::from(*current);
-                                if set {
-                                    bitmap.insert(acl_item);
-                                } else {
-                                    bitmap.remove(acl_item);
-                                }
-                                if !bitmap.is_empty() {
-                                    *current = bitmap.into();
-                                } else {
-                                    acl.remove(idx);
-                                    acl.remove(idx);
-                                }
-                            } else {
-                                return Err(SetError::invalid_properties()
-                                    .with_property(Property::Acl)
-                                    .with_description("Invalid ACL value found."));
-                            }
-                        } else if set {
-                            acl.push(Value::Id(*account_id));
-                            acl.push(Value::UnsignedInt(Bitmap::new().with_item(acl_item).into()));
+                        if patch[2].as_bool().unwrap_or(false) {
+                            grant.grant.insert(acl_item);
+                        } else if grant.grant.contains(acl_item) {
+                            grant.grant.remove(acl_item);
                         }
                     }
                     _ => unreachable!(),
                 }
+
+                match idx {
+                    Some(idx) if !grant.is_empty() => {
+                        acl[idx + 1] = Value::UnsignedInt(grant.into());
+                    }
+                    Some(idx) => {
+                        acl.remove(idx);
+                        acl.remove(idx);
+                    }
+                    None if !grant.is_empty() => {
+                        acl.push(Value::Id(*account_id));
+                        acl.push(Value::UnsignedInt(grant.into()));
+                    }
+                    None => (),
+                }
+                self.acl_hold_pending(acl, current).await?;
             }
             _ => {
                 return Err(SetError::invalid_properties()
@@ -398,6 +508,52 @@ impl JMAP {
         Ok(())
     }
 
+    // Shares with accounts that require acceptance are held back until the
+    // grantee accepts the invitation
+    async fn acl_hold_pending(
+        &self,
+        acl: &mut [Value],
+        current: Option<&HashedValue<Object<Value>>>,
+    ) -> Result<(), SetError> {
+        let current = if let Some(Value::List(current)) =
+            current.and_then(|current| current.inner.properties.get(&Property::Acl))
+        {
+            current.as_slice()
+        } else {
+            &[]
+        };
+        for item in acl.chunks_exact_mut(2) {
+            if let [Value::Id(id), Value::UnsignedInt(bits)] = item {
+                let mut grant = AclGrant::from(*bits);
+                if grant.pending
+                    || grant.grant.is_empty()
+                    || is_public_grantee(id.document_id())
+                    || current.chunks_exact(2).any(|item| {
+                        matches!((item.first(), item.last()),
+                            (Some(Value::Id(current_id)), Some(Value::UnsignedInt(bits)))
+                                if *current_id == *id && !AclGrant::from(*bits).grant.is_empty())
+                    })
+                {
+                    continue;
+                }
+                if self
+                    .share_requires_acceptance(id.document_id())
+                    .await
+                    .map_err(|_| {
+                        SetError::forbidden()
+                            .with_property(Property::Acl)
+                            .with_description("Temporary server failure during lookup")
+                    })?
+                {
+                    grant.pending = true;
+                    *bits = grant.into();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub async fn acl_get(
         &self,
         value: &[Value],
@@ -405,32 +561,35 @@ impl JMAP {
         account_id: u32,
     ) -> Value {
         if access_token.is_member(account_id)
-            || value.chunks_exact(2).any(|item| {
-                access_token.is_member(
-                    item.first()
-                        .and_then(|v| v.as_id().map(|id| id.document_id()))
-                        .unwrap_or(u32::MAX),
-                ) && Bitmap::from(item.last().and_then(|a| a.as_uint()).unwrap_or_default())
-                    .contains(Acl::Administer)
-            })
+            || effective_acl(value, access_token, account_id).contains(Acl::Administer)
         {
             let mut acl_obj = Object::with_capacity(value.len() / 2);
             for item in value.chunks_exact(2) {
                 if let (Some(Value::Id(id)), Some(Value::UnsignedInt(acl_bits))) =
                     (item.first(), item.last())
                 {
-                    if let Some(account_name) = self
+                    // Denied rights have no JMAP representation
+                    let grant = AclGrant::from(*acl_bits).grant;
+                    if grant.is_empty() {
+                        continue;
+                    }
+                    let account_name = if let Some(name) = public_grantee_name(id.document_id()) {
+                        name.to_string()
+                    } else if let Some(account_name) = self
                         .get_account_name(id.document_id())
                         .await
                         .unwrap_or_default()
                     {
-                        acl_obj.append(
-                            Property::_T(account_name),
-                            Bitmap::<Acl>::from(*acl_bits)
-                                .map(|acl_item| Value::Text(acl_item.to_string()))
-                                .collect::<Vec<_>>(),
-                        );
-                    }
+                        account_name
+                    } else {
+                        continue;
+                    };
+                    acl_obj.append(
+                        Property::_T(account_name),
+                        grant
+                            .map(|acl_item| Value::Text(acl_item.to_string()))
+                            .collect::<Vec<_>>(),
+                    );
                 }
             }
 
@@ -447,43 +606,51 @@ impl JMAP {
     ) {
         if let Value::List(acl_changes) = changes.get(&Property::Acl) {
             let access_tokens = &self.access_tokens;
+            let invalidate = |id: &Id| {
+                if is_public_grantee(id.document_id()) {
+                    // Grants to "anyone" or "authenticated" may affect any account
+                    access_tokens.clear();
+                } else {
+                    access_tokens.remove(&id.document_id());
+                }
+            };
             if let Some(Value::List(acl_current)) = current
                 .as_ref()
                 .and_then(|current| current.inner.properties.get(&Property::Acl))
             {
                 for current_item in acl_current.chunks_exact(2) {
-                    let mut invalidate = true;
+                    let mut invalidate_item = true;
                     for change_item in acl_changes.chunks_exact(2) {
                         if change_item.first() == current_item.first() {
-                            invalidate = change_item.last() != current_item.last();
+                            invalidate_item = change_item.last() != current_item.last();
                             break;
                         }
                     }
-                    if invalidate {
+                    if invalidate_item {
                         if let Some(Value::Id(id)) = current_item.first() {
-                            access_tokens.remove(&id.document_id());
+                            invalidate(id);
                         }
                     }
                 }
 
                 for change_item in acl_changes.chunks_exact(2) {
-                    let mut invalidate = true;
+                    let mut invalidate_item = true;
                     for current_item in acl_current.chunks_exact(2) {
                         if change_item.first() == current_item.first() {
-                            invalidate = change_item.last() != current_item.last();
+                            invalidate_item = change_item.last() != current_item.last();
                             break;
                         }
                     }
-                    if invalidate {
+                    if invalidate_item {
                         if let Some(Value::Id(id)) = change_item.first() {
-                            access_tokens.remove(&id.document_id());
+                            invalidate(id);
                         }
                     }
                 }
             } else {
                 for value in acl_changes {
                     if let Value::Id(id) = value {
-                        access_tokens.remove(&id.document_id());
+                        invalidate(id);
                     }
                 }
             }
@@ -493,6 +660,10 @@ impl JMAP {
     async fn map_acl_accounts(&self, mut acl_set: Vec<Value>) -> Result<Vec<Value>, SetError> {
         for item in &mut acl_set {
             if let Value::Text(account_name) = item {
+                if let Some(account_id) = public_grantee_id(account_name) {
+                    *item = Value::Id(account_id.into());
+                    continue;
+                }
                 match self.directory.principal(account_name).await {
                     Ok(Some(_)) => {
                         *item = Value::Id(
@@ -525,24 +696,82 @@ impl JMAP {
 }
 
 pub trait EffectiveAcl {
-    fn effective_acl(&self, access_token: &AccessToken) -> Bitmap<Acl>;
+    fn effective_acl(&self, access_token: &AccessToken, account_id: u32) -> Bitmap<Acl>;
 }
 
 impl EffectiveAcl for Object<Value> {
-    fn effective_acl(&self, access_token: &AccessToken) -> Bitmap<Acl> {
-        let mut acl = Bitmap::<Acl>::new();
+    fn effective_acl(&self, access_token: &AccessToken, account_id: u32) -> Bitmap<Acl> {
         if let Some(Value::List(permissions)) = self.properties.get(&Property::Acl) {
-            for item in permissions.chunks(2) {
-                if let (Some(Value::Id(account_id)), Some(Value::UnsignedInt(acl_bits))) =
-                    (item.first(), item.last())
-                {
-                    if access_token.is_member(account_id.document_id()) {
-                        acl.union(&Bitmap::from(*acl_bits));
-                    }
-                }
+            effective_acl(permissions, access_token, account_id)
+        } else {
+            Bitmap::new()
+        }
+    }
+}
+
+fn effective_acl(
+    permissions: &[Value],
+    access_token: &AccessToken,
+    account_id: u32,
+) -> Bitmap<Acl> {
+    let mut grant = AclGrant::default();
+    for item in permissions.chunks_exact(2) {
+        if let (Some(Value::Id(grantee_id)), Some(Value::UnsignedInt(acl_bits))) =
+            (item.first(), item.last())
+        {
+            if access_token.is_grantee(grantee_id.document_id(), account_id) {
+                grant.union(&AclGrant::from(*acl_bits));
             }
         }
+    }
+
+    grant.effective()
+}
 
-        acl
+fn acl_key_collection(acl_key: &AclKey, key: &[u8]) -> store::Result<Collection> {
+    let collection = Collection::from(acl_key.to_collection);
+    if collection.is_valid() {
+        Ok(collection)
+    } else {
+        Err(Error::InternalError(format!(
+            "Found corrupted collection in key {key:?}"
+        )))
     }
 }
+
+fn granted_collections(collection: Collection, acl: Bitmap<Acl>) -> Bitmap<Collection> {
+    let mut collections: Bitmap<Collection> = Bitmap::new();
+    if acl.contains(Acl::Read) || acl.contains(Acl::Administer) {
+        collections.insert(collection);
+    }
+    if collection == Collection::Mailbox
+        && (acl.contains(Acl::ReadItems) || acl.contains(Acl::Administer))
+    {
+        collections.insert(Collection::Email);
+    }
+    collections
+}
+
+fn add_access_to(
+    access_to: &mut Vec<(u32, Bitmap<Collection>)>,
+    to_account_id: u32,
+    collections: Bitmap<Collection>,
+) {
+    if let Some((_, sharing)) = access_to
+        .iter_mut()
+        .find(|(account_id, _)| *account_id == to_account_id)
+    {
+        sharing.union(&collections);
+    } else {
+        access_to.push((to_account_id, collections));
+    }
+}
+
+fn same_domain(owner: &str, name: &str) -> bool {
+    let domain = |name: &str| {
+        name.rsplit_once('@')
+            .map_or("", |(_, domain)| domain)
+            .to_lowercase()
+    };
+    domain(owner) == domain(name)
+}
@@ -21,11 +21,7 @@
  * for more details.
 */
 
-use std::{
-    net::{IpAddr, Ipv4Addr},
-    sync::Arc,
-    time::Instant,
-};
+use std::{net::IpAddr, sync::Arc, time::Instant};
 
 use hyper::header;
 use jmap_proto::{
@@ -33,16 +29,19 @@ use jmap_proto::{
     types::collection::Collection,
 };
 use mail_parser::decoders::base64::base64_decode;
-use mail_send::Credentials;
+use smtp::anomaly::secret_fingerprint;
 use store::{
     write::{key::KeySerializer, BatchBuilder, Operation, ValueClass},
     CustomValueKey, Serialize,
 };
-use utils::{listener::limiter::InFlight, map::ttl_dashmap::TtlMap};
+use utils::{auth_log::log_auth_failure, listener::limiter::InFlight, map::ttl_dashmap::TtlMap};
 
 use crate::JMAP;
 
-use super::{rate_limit::RemoteAddress, AccessToken};
+use super::{
+    forwarded::forwarded_client_ip, oauth::jwt::TokenProtocol, rate_limit::RemoteAddress,
+    AccessToken, ClientCertificate,
+};
 
 impl JMAP {
     pub async fn authenticate_headers(
@@ -86,7 +85,10 @@ impl JMAP {
                     // Enforce anonymous rate limit for bearer auth requests
                     self.is_anonymous_allowed(&addr)?;
 
-                    match self.validate_access_token("access_token", &token).await {
+                    match self
+                        .validate_access_token("access_token", &token, TokenProtocol::Jmap.into())
+                        .await
+                    {
                         Ok((account_id, _, _)) => self.get_access_token(account_id).await,
                         Err(err) => {
                             tracing::debug!(
@@ -110,6 +112,30 @@ impl JMAP {
                 })
             };
 
+            if let Some(session) = session {
+                // Enforce authenticated rate limit
+                Ok(Some((self.is_account_allowed(&session)?, session)))
+            } else {
+                Ok(None)
+            }
+        } else if let Some(ClientCertificate(identities)) = req.extensions().get() {
+            let session_id = format!("x509:{}", identities.first().map_or("", |i| i.as_str()));
+            let session = if let Some(account_id) = self.sessions.get_with_ttl(&session_id) {
+                self.get_cached_access_token(account_id).await
+            } else {
+                // Enforce anonymous rate limit
+                self.is_anonymous_allowed(&self.build_remote_addr(req, remote_ip))?;
+
+                self.authenticate_client_cert(identities)
+                    .await
+                    .map(|access_token| {
+                        let access_token = Arc::new(access_token);
+                        self.cache_session(session_id, &access_token);
+                        self.cache_access_token(access_token.clone());
+                        access_token
+                    })
+            };
+
             if let Some(session) = session {
                 // Enforce authenticated rate limit
                 Ok(Some((self.is_account_allowed(&session)?, session)))
@@ -124,6 +150,19 @@ impl JMAP {
         }
     }
 
+    pub async fn authenticate_client_cert(&self, identities: &[String]) -> Option<AccessToken> {
+        // Only certificates with an explicit identity or fingerprint mapping are accepted
+        let (identity, name) = self.config.client_cert_map.account(identities)?;
+        tracing::debug!(
+            context = "authenticate_client_cert",
+            identity = identity,
+            account = name,
+            "Client certificate mapped to account."
+        );
+        let account_id = self.get_account_id(name).await.ok()?;
+        self.get_access_token(account_id).await
+    }
+
     pub fn cache_session(&self, session_id: String, access_token: &AccessToken) {
         self.sessions.insert_with_ttl(
             session_id,
@@ -187,6 +226,8 @@ impl JMAP {
             let key = AccountKey::name_to_id(name);
 
             // Write account ID
+            let mut changes = self.begin_changes(u32::MAX).await?;
+            changes.log_insert(Collection::Principal, account_id);
             let mut batch = BatchBuilder::new();
             batch
                 .with_account_id(u32::MAX)
@@ -202,7 +243,8 @@ impl JMAP {
                         bytes: AccountKey::id_to_name(account_id),
                     },
                     set: name.serialize().into(),
-                });
+                })
+                .custom(changes);
 
             match self.store.write(batch.build()).await {
                 Ok(_) => {
@@ -252,18 +294,11 @@ impl JMAP {
         req: &hyper::Request<hyper::body::Incoming>,
         remote_ip: IpAddr,
     ) -> RemoteAddress {
-        if !self.config.rate_use_forwarded {
-            RemoteAddress::IpAddress(remote_ip)
-        } else if let Some(forwarded_for) = req
-            .headers()
-            .get(header::FORWARDED)
-            .and_then(|h| h.to_str().ok())
-        {
-            RemoteAddress::IpAddressFwd(forwarded_for.trim().to_string())
-        } else {
-            tracing::debug!("Warning: No remote address found in request, using loopback.");
-            RemoteAddress::IpAddress(Ipv4Addr::new(127, 0, 0, 1).into())
-        }
+        RemoteAddress::IpAddress(forwarded_client_ip(
+            req.headers(),
+            remote_ip,
+            &self.config.http_trusted_proxies,
+        ))
     }
 
     pub async fn authenticate_plain(
@@ -272,17 +307,21 @@ impl JMAP {
         secret: &str,
         remote_addr: &RemoteAddress,
     ) -> Option<AccessToken> {
-        let mut principal = match self
-            .directory
-            .authenticate(&Credentials::Plain {
-                username: username.to_string(),
-                secret: secret.to_string(),
-            })
-            .await
+        // Enforce the tenant's rate limit for authentication requests
+        if self
+            .is_tenant_auth_allowed_soft(username, remote_addr)
+            .is_err()
         {
+            return None;
+        }
+
+        let RemoteAddress::IpAddress(remote_ip) = *remote_addr;
+        let mut principal = match self.authenticate_secret(username, secret).await {
             Ok(Some(principal)) => principal,
             Ok(None) => {
+                log_auth_failure(remote_ip, username);
                 let _ = self.is_auth_allowed_hard(remote_addr);
+                let _ = self.is_tenant_auth_allowed_hard(username, remote_addr);
                 return None;
             }
             Err(_) => {
@@ -293,6 +332,27 @@ impl JMAP {
         if !principal.has_name() {
             principal.name = username.to_string();
         }
+
+        // Accounts flagged as compromised must have their password reset first
+        let smtp = self.smtp.core();
+        let secret = secret_fingerprint(&principal.secrets);
+        if !smtp
+            .anomaly
+            .is_login_allowed(&principal.name, Some(secret.as_str()))
+        {
+            tracing::info!(
+                context = "auth",
+                event = "locked",
+                account = principal.name,
+                "Login refused for compromised account."
+            );
+            return None;
+        }
+        smtp.anomaly
+            .record_login(&principal.name, remote_ip, Some(secret))
+            .await;
+        self.apply_tenant_quota(&mut principal);
+
         // Obtain groups
         if let (Ok(account_id), Ok(member_of)) = (
             self.get_account_id(&principal.name).await,
@@ -312,6 +372,7 @@ impl JMAP {
     pub async fn get_access_token(&self, account_id: u32) -> Option<AccessToken> {
         let name = self.get_account_name(account_id).await.ok()??;
         let mut principal = self.directory.principal(&name).await.ok()??;
+        self.apply_tenant_quota(&mut principal);
 
         // Obtain groups
         if let (Ok(account_id), Ok(member_of)) = (
@@ -347,4 +408,209 @@ impl AccountKey {
             .write(id)
             .finalize()
     }
+    pub fn id_to_settings(id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(2u8)
+            .write(id)
+            .finalize()
+    }
+    pub fn id_to_tokens(id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(3u8)
+            .write(id)
+            .finalize()
+    }
+    pub fn oauth_code(code: &str) -> Vec<u8> {
+        KeySerializer::new(code.len() + std::mem::size_of::<u32>() + 1)
+            .write(u32::MAX)
+            .write(4u8)
+            .write(code)
+            .finalize()
+    }
+    pub fn jwt_keys() -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() + 1)
+            .write(u32::MAX)
+            .write(5u8)
+            .finalize()
+    }
+    pub fn id_to_consents(id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(6u8)
+            .write(id)
+            .finalize()
+    }
+    pub fn snooze(until: u64, account_id: u32, document_id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 3 + std::mem::size_of::<u64>() + 1)
+            .write(u32::MAX)
+            .write(7u8)
+            .write(until)
+            .write(account_id)
+            .write(document_id)
+            .finalize()
+    }
+    pub fn masked_email(address: &str) -> Vec<u8> {
+        KeySerializer::new(address.len() + std::mem::size_of::<u32>() + 1)
+            .write(u32::MAX)
+            .write(8u8)
+            .write(address)
+            .finalize()
+    }
+    pub fn keyword(account_id: u32, keyword: &str) -> Vec<u8> {
+        KeySerializer::new(keyword.len() + std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(9u8)
+            .write(account_id)
+            .write(keyword)
+            .finalize()
+    }
+    pub fn collected_address(account_id: u32, address: &str) -> Vec<u8> {
+        KeySerializer::new(address.len() + std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(10u8)
+            .write(account_id)
+            .write(address)
+            .finalize()
+    }
+    pub fn known_sender(account_id: u32, address: &str) -> Vec<u8> {
+        KeySerializer::new(address.len() + std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(11u8)
+            .write(account_id)
+            .write(address)
+            .finalize()
+    }
+    pub fn renamed_alias(until: u64, address: &str) -> Vec<u8> {
+        KeySerializer::new(
+            address.len() + std::mem::size_of::<u32>() + std::mem::size_of::<u64>() + 1,
+        )
+        .write(u32::MAX)
+        .write(12u8)
+        .write(until)
+        .write(address)
+        .finalize()
+    }
+    pub fn share_acceptance(id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(13u8)
+            .write(id)
+            .finalize()
+    }
+    pub fn principal(id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(14u8)
+            .write(id)
+            .finalize()
+    }
+    pub fn spam_train_opt_out(id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(15u8)
+            .write(id)
+            .finalize()
+    }
+    pub fn sender_lists(id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(16u8)
+            .write(id)
+            .finalize()
+    }
+    pub fn mailing_list(account_id: u32, list_id: &str) -> Vec<u8> {
+        KeySerializer::new(list_id.len() + std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(17u8)
+            .write(account_id)
+            .write(list_id)
+            .finalize()
+    }
+    pub fn digest_enabled(id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(18u8)
+            .write(id)
+            .finalize()
+    }
+    pub fn digest_item(account_id: u32, document_id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 3 + 1)
+            .write(u32::MAX)
+            .write(19u8)
+            .write(account_id)
+            .write(document_id)
+            .finalize()
+    }
+    pub fn attachment_link_settings(id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(20u8)
+            .write(id)
+            .finalize()
+    }
+    pub fn attachment_link(account_id: u32, link_id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 3 + 1)
+            .write(u32::MAX)
+            .write(21u8)
+            .write(account_id)
+            .write(link_id)
+            .finalize()
+    }
+    pub fn dedup_window(id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(22u8)
+            .write(id)
+            .finalize()
+    }
+    pub fn delivered_message(account_id: u32, message_id: &str) -> Vec<u8> {
+        KeySerializer::new(message_id.len() + std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(23u8)
+            .write(account_id)
+            .write(message_id)
+            .finalize()
+    }
+    pub fn domain_policy(domain: &str) -> Vec<u8> {
+        KeySerializer::new(domain.len() + std::mem::size_of::<u32>() + 1)
+            .write(u32::MAX)
+            .write(24u8)
+            .write(domain)
+            .finalize()
+    }
+    pub fn policy_vacation_reply(account_id: u32, address: &str) -> Vec<u8> {
+        KeySerializer::new(address.len() + std::mem::size_of::<u32>() * 2 + 1)
+            .write(u32::MAX)
+            .write(25u8)
+            .write(account_id)
+            .write(address)
+            .finalize()
+    }
+    pub fn spam_train_pending(account_id: u32, document_id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 3 + 1)
+            .write(u32::MAX)
+            .write(26u8)
+            .write(account_id)
+            .write(document_id)
+            .finalize()
+    }
+    pub fn spam_train_verdict(account_id: u32, document_id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 3 + 1)
+            .write(u32::MAX)
+            .write(27u8)
+            .write(account_id)
+            .write(document_id)
+            .finalize()
+    }
+    pub fn attachment_link_expiry(expires: u64, account_id: u32, link_id: u32) -> Vec<u8> {
+        KeySerializer::new(std::mem::size_of::<u32>() * 3 + std::mem::size_of::<u64>() + 1)
+            .write(u32::MAX)
+            .write(28u8)
+            .write(expires)
+            .write(account_id)
+            .write(link_id)
+            .finalize()
+    }
 }
@@ -0,0 +1,92 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::net::IpAddr;
+
+use hyper::header::{self, HeaderMap};
+use smtp::config::IpAddrMask;
+
+/// Obtains the client address from the `Forwarded` or `X-Forwarded-For`
+/// headers. Headers are only honored when the peer is a trusted proxy, in
+/// which case the chain is walked from right to left and the first address
+/// that does not belong to a trusted proxy is returned.
+pub fn forwarded_client_ip(headers: &HeaderMap, peer_ip: IpAddr, trusted: &[IpAddrMask]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted.iter().any(|mask| mask.matches(ip));
+    if !is_trusted(&peer_ip) {
+        return peer_ip;
+    }
+
+    let mut chain = Vec::new();
+    if let Some(forwarded) = headers
+        .get_all(header::FORWARDED)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .map(|h| h.to_string())
+        .reduce(|a, b| format!("{a},{b}"))
+    {
+        for element in forwarded.split(',') {
+            for pair in element.split(';') {
+                if let Some((name, value)) = pair.split_once('=') {
+                    if name.trim().eq_ignore_ascii_case("for") {
+                        chain.push(parse_node(value));
+                    }
+                }
+            }
+        }
+    } else {
+        for value in headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+        {
+            chain.extend(value.split(',').map(parse_node));
+        }
+    }
+
+    let mut client_ip = peer_ip;
+    for ip in chain.into_iter().rev() {
+        match ip {
+            Some(ip) => {
+                client_ip = ip;
+                if !is_trusted(&ip) {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+
+    client_ip
+}
+
+// Parses a node such as `192.0.2.1`, `"192.0.2.1:8080"` or `"[2001:db8::1]:4711"`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        Some(ip)
+    } else if let Some(node) = node.strip_prefix('[') {
+        node.split_once(']')?.0.parse().ok()
+    } else {
+        node.rsplit_once(':')?.0.parse().ok()
+    }
+}
@@ -24,6 +24,7 @@
 use std::{
     collections::hash_map::DefaultHasher,
     hash::{Hash, Hasher},
+    sync::Arc,
 };
 
 use aes_gcm_siv::{
@@ -34,21 +35,41 @@ use aes_gcm_siv::{
 use directory::{Principal, Type};
 use jmap_proto::{
     error::method::MethodError,
-    types::{collection::Collection, id::Id},
+    types::{
+        acl::{is_public_grantee, ACL_ANYONE, ACL_AUTHENTICATED},
+        collection::Collection,
+        id::Id,
+    },
 };
 use store::blake3;
 use utils::map::bitmap::Bitmap;
 
 pub mod acl;
 pub mod authenticate;
+pub mod forwarded;
 pub mod oauth;
 pub mod rate_limit;
+pub mod sessions;
+pub mod tenant;
+
+/// Identities asserted by the TLS client certificate of an HTTP connection.
+#[derive(Debug, Clone)]
+pub struct ClientCertificate(pub Arc<Vec<String>>);
+
+/// Server name requested via SNI on a TLS connection and the tenant the
+/// listener maps it to, if any.
+#[derive(Debug, Clone, Default)]
+pub struct TlsServerName {
+    pub name: Option<Arc<str>>,
+    pub tenant: Option<Arc<str>>,
+}
 
 #[derive(Debug, Clone, Default)]
 pub struct AccessToken {
     pub primary_id: u32,
     pub member_of: Vec<u32>,
     pub access_to: Vec<(u32, Bitmap<Collection>)>,
+    pub public_access: Vec<u32>,
     pub name: String,
     pub description: Option<String>,
     pub quota: u32,
@@ -61,6 +82,7 @@ impl AccessToken {
             primary_id,
             member_of: Vec::new(),
             access_to: Vec::new(),
+            public_access: Vec::new(),
             name: principal.name,
             description: principal.description,
             quota: principal.quota,
@@ -81,6 +103,7 @@ impl AccessToken {
         let mut s = DefaultHasher::new();
         self.member_of.hash(&mut s);
         self.access_to.hash(&mut s);
+        self.public_access.hash(&mut s);
         s.finish() as u32
     }
 
@@ -98,6 +121,22 @@ impl AccessToken {
         self.primary_id == account_id || self.member_of.contains(&account_id) || self.is_superuser
     }
 
+    pub fn is_grantee(&self, grant_account_id: u32, to_account_id: u32) -> bool {
+        self.is_member(grant_account_id)
+            || (is_public_grantee(grant_account_id) && self.public_access.contains(&to_account_id))
+    }
+
+    pub fn grantee_ids(&self, to_account_id: u32) -> Vec<u32> {
+        let mut ids = Vec::with_capacity(self.member_of.len() + 3);
+        ids.push(self.primary_id);
+        ids.extend_from_slice(&self.member_of);
+        if self.public_access.contains(&to_account_id) {
+            ids.push(ACL_ANYONE);
+            ids.push(ACL_AUTHENTICATED);
+        }
+        ids
+    }
+
     pub fn is_primary_id(&self, account_id: u32) -> bool {
         self.primary_id == account_id
     }
@@ -0,0 +1,217 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::types::collection::Collection;
+use store::{
+    write::{assert::HashedValue, key::KeySerializer, now, BatchBuilder, Operation, ValueClass},
+    CustomValueKey, Deserialize, Serialize,
+};
+
+use crate::{auth::authenticate::AccountKey, Bincode, JMAP};
+
+use super::OAuthCode;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+enum StoredCode {
+    Code(OAuthCode),
+    Alias { code: String, expires: u64 },
+}
+
+impl StoredCode {
+    fn expires(&self) -> u64 {
+        match self {
+            StoredCode::Code(oauth) => oauth.expires,
+            StoredCode::Alias { expires, .. } => *expires,
+        }
+    }
+}
+
+impl JMAP {
+    pub async fn insert_oauth_code(
+        &self,
+        code: &str,
+        alias: Option<&str>,
+        oauth: OAuthCode,
+    ) -> store::Result<()> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal);
+        if let Some(alias) = alias {
+            batch.op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::oauth_code(alias),
+                },
+                set: Bincode::new(StoredCode::Alias {
+                    code: code.to_string(),
+                    expires: oauth.expires,
+                })
+                .serialize()
+                .into(),
+            });
+        }
+        batch.op(Operation::Value {
+            class: ValueClass::Custom {
+                bytes: AccountKey::oauth_code(code),
+            },
+            set: Bincode::new(StoredCode::Code(oauth)).serialize().into(),
+        });
+
+        self.store.write(batch.build()).await
+    }
+
+    pub async fn get_oauth_code(&self, code: &str) -> store::Result<Option<OAuthCode>> {
+        Ok(self
+            .fetch_oauth_code(code)
+            .await?
+            .map(|(_, _, oauth)| oauth))
+    }
+
+    /// Atomically updates an OAuth code. The closure returns the new state or `None`
+    /// to leave the code unchanged. Returns the code as it was read along with
+    /// whether it was updated, or `None` if the code does not exist or has expired.
+    pub async fn update_oauth_code(
+        &self,
+        code: &str,
+        f: impl Fn(&OAuthCode) -> Option<OAuthCode>,
+    ) -> store::Result<Option<(OAuthCode, bool)>> {
+        let mut try_count = 0;
+
+        loop {
+            let (key, current, oauth) = if let Some(result) = self.fetch_oauth_code(code).await? {
+                result
+            } else {
+                return Ok(None);
+            };
+            let updated = if let Some(updated) = f(&oauth) {
+                updated
+            } else {
+                return Ok(Some((oauth, false)));
+            };
+
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal)
+                .assert_value(ValueClass::Custom { bytes: key.clone() }, &current)
+                .op(Operation::Value {
+                    class: ValueClass::Custom { bytes: key },
+                    set: Bincode::new(StoredCode::Code(updated)).serialize().into(),
+                });
+
+            match self.store.write(batch.build()).await {
+                Ok(_) => return Ok(Some((oauth, true))),
+                Err(store::Error::AssertValueFailed) if try_count < 3 => {
+                    try_count += 1;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    async fn fetch_oauth_code(
+        &self,
+        code: &str,
+    ) -> store::Result<Option<(Vec<u8>, HashedValue<Bincode<StoredCode>>, OAuthCode)>> {
+        let mut key = AccountKey::oauth_code(code);
+
+        // Resolve user code aliases
+        for _ in 0..2 {
+            let value = if let Some(value) = self
+                .store
+                .get_value::<HashedValue<Bincode<StoredCode>>>(CustomValueKey {
+                    value: key.clone(),
+                })
+                .await?
+            {
+                value
+            } else {
+                return Ok(None);
+            };
+            if value.inner.inner.expires() <= now() {
+                return Ok(None);
+            }
+
+            match &value.inner.inner {
+                StoredCode::Code(oauth) => {
+                    let oauth = oauth.clone();
+                    return Ok(Some((key, value, oauth)));
+                }
+                StoredCode::Alias { code, .. } => {
+                    key = AccountKey::oauth_code(code);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub async fn purge_oauth_codes(&self) -> store::Result<()> {
+        let from_key = CustomValueKey {
+            value: AccountKey::oauth_code(""),
+        };
+        // Codes are alphanumeric, so no key in the namespace sorts after 0xFF
+        let to_key = CustomValueKey {
+            value: KeySerializer::new(std::mem::size_of::<u32>() + 2)
+                .write(u32::MAX)
+                .write(4u8)
+                .write(&[u8::MAX][..])
+                .finalize(),
+        };
+        let now = now();
+        let expired = self
+            .store
+            .iterate(
+                Vec::new(),
+                from_key,
+                to_key,
+                false,
+                true,
+                move |expired, key, value| {
+                    if Bincode::<StoredCode>::deserialize(value)
+                        .map_or(false, |value| value.inner.expires() <= now)
+                    {
+                        expired.push(key.to_vec());
+                    }
+                    Ok(true)
+                },
+            )
+            .await?;
+
+        for keys in expired.chunks(100) {
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal);
+            for key in keys {
+                batch.op(Operation::Value {
+                    class: ValueClass::Custom { bytes: key.clone() },
+                    set: None,
+                });
+            }
+            self.store.write(batch.build()).await?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{error::method::MethodError, types::collection::Collection};
+use store::{
+    write::{assert::HashedValue, now, BatchBuilder, Operation, ValueClass},
+    CustomValueKey, Serialize,
+};
+
+use crate::{auth::authenticate::AccountKey, Bincode, JMAP};
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AccountConsents {
+    pub clients: Vec<ClientConsent>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ClientConsent {
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    pub granted: u64,
+}
+
+impl JMAP {
+    pub async fn get_oauth_consents(
+        &self,
+        account_id: u32,
+    ) -> Result<Option<HashedValue<Bincode<AccountConsents>>>, MethodError> {
+        self.store
+            .get_value::<HashedValue<Bincode<AccountConsents>>>(CustomValueKey {
+                value: AccountKey::id_to_consents(account_id),
+            })
+            .await
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve OAuth consents");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    // Returns true if the account previously allowed the client all the requested scopes
+    pub async fn has_oauth_consent(
+        &self,
+        account_id: u32,
+        client_id: &str,
+        scope: Option<&str>,
+    ) -> Result<bool, MethodError> {
+        let scopes = parse_scopes(scope);
+        Ok(self
+            .get_oauth_consents(account_id)
+            .await?
+            .map_or(false, |consents| {
+                consents.inner.inner.clients.iter().any(|consent| {
+                    consent.client_id == client_id
+                        && scopes.iter().all(|scope| consent.scopes.contains(scope))
+                })
+            }))
+    }
+
+    pub async fn grant_oauth_consent(
+        &self,
+        account_id: u32,
+        client_id: &str,
+        scope: Option<&str>,
+    ) -> Result<(), MethodError> {
+        let scopes = parse_scopes(scope);
+        self.update_oauth_consents(account_id, |consents| {
+            if let Some(consent) = consents
+                .clients
+                .iter_mut()
+                .find(|consent| consent.client_id == client_id)
+            {
+                for scope in &scopes {
+                    if !consent.scopes.contains(scope) {
+                        consent.scopes.push(scope.clone());
+                    }
+                }
+                consent.scopes.sort_unstable();
+                consent.granted = now();
+            } else {
+                consents.clients.push(ClientConsent {
+                    client_id: client_id.to_string(),
+                    scopes: scopes.clone(),
+                    granted: now(),
+                });
+            }
+            true
+        })
+        .await
+        .map(|_| ())
+    }
+
+    // Removes the consent and revokes all tokens issued to the client
+    pub async fn revoke_oauth_consent(
+        &self,
+        account_id: u32,
+        client_id: &str,
+    ) -> Result<bool, MethodError> {
+        let found = self
+            .update_oauth_consents(account_id, |consents| {
+                let num_clients = consents.clients.len();
+                consents
+                    .clients
+                    .retain(|consent| consent.client_id != client_id);
+                consents.clients.len() != num_clients
+            })
+            .await?;
+        let revoked = self.revoke_client_tokens(account_id, client_id).await?;
+
+        Ok(found || revoked)
+    }
+
+    async fn update_oauth_consents(
+        &self,
+        account_id: u32,
+        mut f: impl FnMut(&mut AccountConsents) -> bool,
+    ) -> Result<bool, MethodError> {
+        let mut try_count = 0;
+
+        loop {
+            let current = self.get_oauth_consents(account_id).await?;
+            let mut consents = current
+                .as_ref()
+                .map(|consents| consents.inner.inner.clone())
+                .unwrap_or_default();
+            if !f(&mut consents) {
+                return Ok(false);
+            }
+
+            let key = AccountKey::id_to_consents(account_id);
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal);
+            if let Some(current) = &current {
+                batch.assert_value(ValueClass::Custom { bytes: key.clone() }, current);
+            } else {
+                batch.assert_value(ValueClass::Custom { bytes: key.clone() }, ());
+            }
+            batch.op(Operation::Value {
+                class: ValueClass::Custom { bytes: key },
+                set: if !consents.clients.is_empty() {
+                    Bincode::new(consents).serialize().into()
+                } else {
+                    None
+                },
+            });
+
+            match self.store.write(batch.build()).await {
+                Ok(_) => {
+                    return Ok(true);
+                }
+                Err(store::Error::AssertValueFailed) if try_count < 3 => {
+                    try_count += 1;
+                    continue;
+                }
+                Err(err) => {
+                    tracing::error!(event = "error",
+                        context = "store",
+                        account_id = account_id,
+                        error = ?err,
+                        "Failed to write OAuth consents");
+                    return Err(MethodError::ServerPartialFail);
+                }
+            }
+        }
+    }
+}
+
+pub fn parse_scopes(scope: Option<&str>) -> Vec<String> {
+    let mut scopes = scope
+        .unwrap_or_default()
+        .split_ascii_whitespace()
+        .map(|scope| scope.to_string())
+        .collect::<Vec<_>>();
+    scopes.sort_unstable();
+    scopes.dedup();
+    scopes
+}
@@ -22,16 +22,21 @@
 */
 
 use std::{
-    sync::{atomic, Arc},
+    sync::Arc,
     time::{Duration, Instant},
 };
 
-use hyper::StatusCode;
-use store::rand::{
-    distributions::{Alphanumeric, Standard},
-    thread_rng, Rng,
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Bytes, header, StatusCode};
+use qrcode::{render::svg, QrCode};
+use store::{
+    rand::{
+        distributions::{Alphanumeric, Standard},
+        thread_rng, Rng,
+    },
+    write::now,
 };
-use utils::{listener::ServerInstance, map::ttl_dashmap::TtlMap};
+use utils::{codec::html::html_escape, listener::ServerInstance, map::ttl_dashmap::TtlMap};
 
 use crate::{
     api::{http::ToHttpResponse, HtmlResponse, HttpRequest, HttpResponse, JsonResponse},
@@ -48,7 +53,8 @@ use crate::{
 use super::{
     DeviceAuthResponse, FormData, OAuthCode, CLIENT_ID_MAX_LEN, DEVICE_CODE_LEN, OAUTH_HTML_FOOTER,
     OAUTH_HTML_HEADER, OAUTH_HTML_LOGIN_CODE, OAUTH_HTML_LOGIN_FORM,
-    OAUTH_HTML_LOGIN_HEADER_DEVICE, STATUS_PENDING, USER_CODE_ALPHABET, USER_CODE_LEN,
+    OAUTH_HTML_LOGIN_HEADER_DEVICE, SLOW_DOWN_INTERVAL, STATUS_PENDING, USER_CODE_ALPHABET,
+    USER_CODE_LEN,
 };
 
 // Device authorization endpoint
@@ -96,41 +102,51 @@ impl JMAP {
         }
 
         // Add OAuth status
-        let oauth_code = Arc::new(OAuthCode {
-            status: STATUS_PENDING.into(),
-            account_id: u32::MAX.into(),
-            client_id,
-            redirect_uri: None,
-        });
-        let expiry = Instant::now() + Duration::from_secs(self.config.oauth_expiry_user_code);
-        self.oauth_codes
-            .insert_with_ttl(device_code.clone(), oauth_code.clone(), expiry);
-        self.oauth_codes
-            .insert_with_ttl(user_code.clone(), oauth_code, expiry);
+        if let Err(err) = self
+            .insert_oauth_code(
+                &device_code,
+                Some(&user_code),
+                OAuthCode {
+                    status: STATUS_PENDING,
+                    account_id: u32::MAX,
+                    client_id,
+                    redirect_uri: None,
+                    scope: None,
+                    expires: now() + self.config.oauth_expiry_user_code,
+                },
+            )
+            .await
+        {
+            tracing::error!(event = "error",
+                context = "oauth",
+                error = ?err,
+                "Failed to store device code");
+            return HtmlResponse::with_status(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to store device code.".to_string(),
+            )
+            .into_http_response();
+        }
 
         // Build response
         JsonResponse::new(DeviceAuthResponse {
             verification_uri: format!("{}/auth", instance.data),
-            verification_uri_complete: format!("{}/auth/code?={}", instance.data, user_code),
+            verification_uri_complete: format!("{}/auth?user_code={}", instance.data, user_code),
+            verification_uri_complete_qr: format!(
+                "{}/auth/qr?user_code={}",
+                instance.data, user_code
+            ),
             device_code,
             user_code,
             expires_in: self.config.oauth_expiry_user_code,
-            interval: 5,
+            interval: self.config.oauth_device_poll_interval,
         })
         .into_http_response()
     }
 
     // Device authorization flow, renders the authorization page
     pub async fn handle_user_device_auth(&self, req: &mut HttpRequest) -> HttpResponse {
-        let code = req
-            .uri()
-            .query()
-            .and_then(|q| {
-                form_urlencoded::parse(q.as_bytes())
-                    .find(|(k, _)| k == "code")
-                    .map(|(_, v)| v.into_owned())
-            })
-            .unwrap_or_default();
+        let code = html_escape(&user_code_param(req).unwrap_or_default());
         let mut response = String::with_capacity(
             OAUTH_HTML_HEADER.len()
                 + OAUTH_HTML_LOGIN_HEADER_DEVICE.len()
@@ -147,7 +163,77 @@ impl JMAP {
         response.push_str(&OAUTH_HTML_LOGIN_FORM.replace("@@@", "about:blank"));
         response.push_str(OAUTH_HTML_FOOTER);
 
-        HtmlResponse::new(response).into_http_response()
+        HtmlResponse::new(self.brand_html(req, response)).into_http_response()
+    }
+
+    // Renders verification_uri_complete as a QR code for devices to display
+    pub fn handle_device_auth_qr(
+        &self,
+        req: &HttpRequest,
+        instance: Arc<ServerInstance>,
+    ) -> HttpResponse {
+        let user_code = match user_code_param(req) {
+            Some(user_code)
+                if user_code.len() == USER_CODE_LEN + 1
+                    && user_code
+                        .bytes()
+                        .all(|ch| ch == b'-' || USER_CODE_ALPHABET.contains(&ch)) =>
+            {
+                user_code
+            }
+            _ => {
+                return HtmlResponse::with_status(
+                    StatusCode::BAD_REQUEST,
+                    "User code is invalid.".to_string(),
+                )
+                .into_http_response();
+            }
+        };
+
+        match QrCode::new(format!("{}/auth?user_code={}", instance.data, user_code)) {
+            Ok(code) => hyper::Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "image/svg+xml")
+                .header(header::CACHE_CONTROL, "no-store")
+                .body(
+                    Full::new(Bytes::from(
+                        code.render::<svg::Color>().min_dimensions(200, 200).build(),
+                    ))
+                    .map_err(|never| match never {})
+                    .boxed(),
+                )
+                .unwrap(),
+            Err(err) => {
+                tracing::error!(event = "error",
+                    context = "oauth",
+                    error = ?err,
+                    "Failed to generate QR code");
+                HtmlResponse::with_status(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to generate QR code.".to_string(),
+                )
+                .into_http_response()
+            }
+        }
+    }
+
+    // Enforces the polling interval of a device code, which is increased
+    // every time the client polls too early
+    pub fn is_device_poll_allowed(&self, device_code: &str) -> bool {
+        let now = Instant::now();
+        let (interval, is_allowed) = match self.device_polls.get_with_ttl(device_code) {
+            Some((interval, next_poll)) if now < next_poll => {
+                (interval + SLOW_DOWN_INTERVAL, false)
+            }
+            Some((interval, _)) => (interval, true),
+            None => (self.config.oauth_device_poll_interval, true),
+        };
+        self.device_polls.insert_with_ttl(
+            device_code.to_string(),
+            (interval, now + Duration::from_secs(interval)),
+            now + Duration::from_secs(self.config.oauth_expiry_user_code),
+        );
+        is_allowed
     }
 
     // Handles POST request from the device authorization form
@@ -166,37 +252,64 @@ impl JMAP {
             Success,
             Failed,
             InvalidCode,
+            Error,
         }
 
-        let code = if let Some(oauth) = fields
-            .get("code")
-            .and_then(|code| self.oauth_codes.get_with_ttl(code))
-        {
-            if (STATUS_PENDING..STATUS_PENDING + self.config.oauth_max_auth_attempts)
-                .contains(&oauth.status.load(atomic::Ordering::Relaxed))
-            {
-                if let (Some(email), Some(password)) = (fields.get("email"), fields.get("password"))
-                {
-                    if let Some(id) = self.authenticate_plain(email, password, remote_addr).await {
-                        oauth
-                            .account_id
-                            .store(id.primary_id(), atomic::Ordering::Relaxed);
-                        oauth
-                            .status
-                            .store(STATUS_AUTHORIZED, atomic::Ordering::Relaxed);
-                        Response::Success
+        let max_status = STATUS_PENDING + self.config.oauth_max_auth_attempts;
+        let code = match fields.get("code") {
+            Some(code) => match self.get_oauth_code(code).await {
+                Ok(Some(oauth)) if (STATUS_PENDING..max_status).contains(&oauth.status) => {
+                    if let (Some(email), Some(password)) =
+                        (fields.get("email"), fields.get("password"))
+                    {
+                        let account_id = self
+                            .authenticate_plain(email, password, remote_addr)
+                            .await
+                            .map(|access_token| access_token.primary_id());
+
+                        // Authorize the code, or count the failed attempt
+                        match self
+                            .update_oauth_code(code, |oauth| {
+                                if (STATUS_PENDING..max_status).contains(&oauth.status) {
+                                    let mut oauth = oauth.clone();
+                                    if let Some(account_id) = account_id {
+                                        oauth.account_id = account_id;
+                                        oauth.status = STATUS_AUTHORIZED;
+                                    } else {
+                                        oauth.status += 1;
+                                    }
+                                    Some(oauth)
+                                } else {
+                                    None
+                                }
+                            })
+                            .await
+                        {
+                            Ok(Some((_, true))) if account_id.is_some() => Response::Success,
+                            Ok(Some((_, true))) => Response::Failed,
+                            Ok(_) => Response::InvalidCode,
+                            Err(err) => {
+                                tracing::error!(event = "error",
+                                    context = "oauth",
+                                    error = ?err,
+                                    "Failed to update device code");
+                                Response::Error
+                            }
+                        }
                     } else {
-                        oauth.status.fetch_add(1, atomic::Ordering::Relaxed);
                         Response::Failed
                     }
-                } else {
-                    Response::Failed
                 }
-            } else {
-                Response::InvalidCode
-            }
-        } else {
-            Response::InvalidCode
+                Ok(_) => Response::InvalidCode,
+                Err(err) => {
+                    tracing::error!(event = "error",
+                        context = "oauth",
+                        error = ?err,
+                        "Failed to retrieve device code");
+                    Response::Error
+                }
+            },
+            None => Response::InvalidCode,
         };
 
         let mut response = String::with_capacity(
@@ -225,16 +338,25 @@ impl JMAP {
                 response.push_str(
                     &OAUTH_HTML_ERROR.replace("@@@", "Invalid or expired authentication code."),
                 );
-            } /*Response::Error => {
-                  response.push_str(&OAUTH_HTML_ERROR.replace(
-                      "@@@",
-                      "There was a problem processing your request, please try again later.",
-                  ));
-              }*/
+            }
+            Response::Error => {
+                response.push_str(&OAUTH_HTML_ERROR.replace(
+                    "@@@",
+                    "There was a problem processing your request, please try again later.",
+                ));
+            }
         }
 
         response.push_str(OAUTH_HTML_FOOTER);
 
-        HtmlResponse::new(response).into_http_response()
+        HtmlResponse::new(self.brand_html(req, response)).into_http_response()
     }
 }
+
+fn user_code_param(req: &HttpRequest) -> Option<String> {
+    req.uri().query().and_then(|q| {
+        form_urlencoded::parse(q.as_bytes())
+            .find(|(k, _)| k == "user_code" || k == "code")
+            .map(|(_, v)| v.into_owned())
+    })
+}
@@ -0,0 +1,563 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer, Verifier};
+use jmap_proto::{error::request::RequestError, types::collection::Collection};
+use p256::{
+    ecdsa::{self, signature::Signer as _, signature::Verifier as _},
+    elliptic_curve::{rand_core::OsRng, sec1::ToEncodedPoint},
+};
+use store::{
+    rand::{thread_rng, Rng},
+    write::{assert::HashedValue, now, BatchBuilder, Operation, ValueClass},
+    CustomValueKey, Serialize,
+};
+
+use utils::config::utils::{AsKey, ParseValue};
+
+use crate::{
+    api::{http::ToHttpResponse, HttpResponse, JsonResponse},
+    auth::{authenticate::AccountKey, SymmetricEncrypt},
+    Bincode, JMAP,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum JwtAlgorithm {
+    #[serde(rename = "ES256")]
+    Es256,
+    #[serde(rename = "EdDSA")]
+    EdDsa,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct JwtClaims {
+    pub iss: String,
+    pub sub: String,
+    pub client_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    pub iat: u64,
+    pub exp: u64,
+    pub jti: String,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct JwtHeader {
+    alg: JwtAlgorithm,
+    typ: String,
+    kid: String,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+struct StoredKeySet {
+    keys: Vec<StoredKey>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct StoredKey {
+    kid: String,
+    alg: JwtAlgorithm,
+    secret: Vec<u8>,
+    nonce: Vec<u8>,
+    created: u64,
+    retired: Option<u64>,
+}
+
+#[derive(Default)]
+pub struct JwtKeys {
+    keys: Vec<JwtKey>,
+    loaded: u64,
+    refreshing: AtomicBool,
+}
+
+/// Protocols an access token can be restricted to through its scope, tokens
+/// whose scope names none of them are valid for every protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenProtocol {
+    Jmap,
+    Imap,
+    Smtp,
+    Sieve,
+}
+
+pub struct JwtKey {
+    pub kid: String,
+    pub created: u64,
+    pub retired: Option<u64>,
+    key: KeyPair,
+}
+
+enum KeyPair {
+    Es256(ecdsa::SigningKey),
+    EdDsa(Keypair),
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct JwkSet {
+    pub keys: Vec<Jwk>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct Jwk {
+    pub kty: &'static str,
+    pub crv: &'static str,
+    pub alg: JwtAlgorithm,
+    #[serde(rename = "use")]
+    pub use_: &'static str,
+    pub kid: String,
+    pub x: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+}
+
+const JWT_KEY_CONTEXT: &str = "jwt signing key";
+const JWT_KEYS_MIN_REFRESH: u64 = 30;
+
+impl JMAP {
+    pub async fn encode_jwt(
+        &self,
+        account_id: u32,
+        client_id: &str,
+        scope: Option<&str>,
+        expiry_in: u64,
+    ) -> Result<String, &'static str> {
+        let keys = self.jwt_signing_keys().await?;
+        let key = keys.current().ok_or("No signing key available")?;
+        let now = now();
+        let header = JwtHeader {
+            alg: key.algorithm(),
+            typ: "JWT".to_string(),
+            kid: key.kid.clone(),
+        };
+        let claims = JwtClaims {
+            iss: self.config.oauth_jwt_issuer.clone(),
+            sub: account_id.to_string(),
+            client_id: client_id.to_string(),
+            scope: scope.map(|s| s.to_string()),
+            iat: now,
+            exp: now + expiry_in,
+            jti: format!("{:016x}", thread_rng().gen::<u64>()),
+        };
+
+        let mut token = String::with_capacity(256);
+        token.push_str(
+            &URL_SAFE_NO_PAD
+                .encode(serde_json::to_vec(&header).map_err(|_| "Failed to serialize header")?),
+        );
+        token.push('.');
+        token.push_str(
+            &URL_SAFE_NO_PAD
+                .encode(serde_json::to_vec(&claims).map_err(|_| "Failed to serialize claims")?),
+        );
+        let signature = key.sign(token.as_bytes());
+        token.push('.');
+        token.push_str(&URL_SAFE_NO_PAD.encode(signature));
+
+        Ok(token)
+    }
+
+    pub async fn validate_jwt(&self, token: &str) -> Result<JwtClaims, &'static str> {
+        let (message, signature) = token.rsplit_once('.').ok_or("Invalid token format.")?;
+        let (header, claims) = message.split_once('.').ok_or("Invalid token format.")?;
+        let header = URL_SAFE_NO_PAD
+            .decode(header)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<JwtHeader>(&bytes).ok())
+            .ok_or("Failed to decode token header.")?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| "Failed to decode token signature.")?;
+
+        // Validate claims before looking up the signing key, they are only trusted
+        // once the signature is verified.
+        let claims = URL_SAFE_NO_PAD
+            .decode(claims)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<JwtClaims>(&bytes).ok())
+            .ok_or("Failed to decode token claims.")?;
+        if claims.exp <= now() {
+            return Err("Token expired.");
+        } else if claims.iss != self.config.oauth_jwt_issuer {
+            return Err("Invalid token issuer.");
+        }
+
+        // Obtain the signing key, reloading the key set if it was rotated by another
+        // node. Reloads are throttled so that forged key ids can't be used to flood
+        // the store with requests.
+        let mut keys = self.jwt_keys.read().clone();
+        if keys.get(&header.kid).is_none() && keys.try_refresh() {
+            keys = match self.refresh_jwt_keys().await {
+                Ok(keys) => keys,
+                Err(err) => {
+                    keys.refreshing.store(false, Ordering::Relaxed);
+                    return Err(err);
+                }
+            };
+        }
+        let key = keys.get(&header.kid).ok_or("Unknown signing key.")?;
+        if key.algorithm() != header.alg {
+            return Err("Algorithm mismatch.");
+        }
+        if !key.verify(message.as_bytes(), &signature) {
+            return Err("Invalid token signature.");
+        }
+
+        Ok(claims)
+    }
+
+    pub async fn handle_jwks_request(&self) -> HttpResponse {
+        match self.jwt_signing_keys().await {
+            Ok(keys) => JsonResponse::new(JwkSet {
+                keys: keys.keys.iter().map(|key| key.to_jwk()).collect(),
+            })
+            .into_http_response(),
+            Err(err) => {
+                tracing::error!(context = "oauth", error = err, "Failed to load JWT keys.");
+                RequestError::internal_server_error().into_http_response()
+            }
+        }
+    }
+
+    /// Returns the cached key set, rotating keys when the current one is due.
+    pub async fn jwt_signing_keys(&self) -> Result<Arc<JwtKeys>, &'static str> {
+        let keys = self.jwt_keys.read().clone();
+        let is_current = keys.current().map_or(false, |key| {
+            key.created + self.config.oauth_jwt_rotate > now()
+        });
+        if is_current {
+            Ok(keys)
+        } else {
+            self.refresh_jwt_keys().await
+        }
+    }
+
+    pub async fn refresh_jwt_keys(&self) -> Result<Arc<JwtKeys>, &'static str> {
+        self.load_jwt_keys(false).await
+    }
+
+    pub async fn rotate_jwt_keys(&self) -> Result<Arc<JwtKeys>, &'static str> {
+        self.load_jwt_keys(true).await
+    }
+
+    async fn load_jwt_keys(&self, force_rotate: bool) -> Result<Arc<JwtKeys>, &'static str> {
+        let mut try_count = 0;
+        let key = AccountKey::jwt_keys();
+
+        loop {
+            let current = self
+                .store
+                .get_value::<HashedValue<Bincode<StoredKeySet>>>(CustomValueKey {
+                    value: key.clone(),
+                })
+                .await
+                .map_err(|_| "Failed to retrieve JWT keys")?;
+            let mut key_set = current
+                .as_ref()
+                .map(|keys| keys.inner.inner.clone())
+                .unwrap_or_default();
+
+            // Drop retired keys once no token signed with them can be valid. Other nodes
+            // may keep signing with a retired key until they reload the key set, which
+            // happens at most one rotation period after the key was retired.
+            let now = now();
+            let retention = self.config.oauth_jwt_rotate + self.config.oauth_expiry_token;
+            let mut has_changes = false;
+            key_set.keys.retain(|key| {
+                let keep = key
+                    .retired
+                    .map_or(true, |retired| retired + retention > now);
+                has_changes |= !keep;
+                keep
+            });
+
+            // Decrypt keys
+            let mut keys = self.decrypt_jwt_keys(&key_set);
+
+            // Rotate the current key if it is due, unusable or rotation was requested
+            if force_rotate
+                || keys.current().map_or(true, |key| {
+                    key.created + self.config.oauth_jwt_rotate <= now
+                })
+            {
+                for key in key_set.keys.iter_mut() {
+                    if key.retired.is_none() {
+                        key.retired = now.into();
+                    }
+                }
+                key_set.keys.push(self.generate_jwt_key(now)?);
+                keys = self.decrypt_jwt_keys(&key_set);
+                has_changes = true;
+            }
+
+            if has_changes {
+                let mut batch = BatchBuilder::new();
+                batch
+                    .with_account_id(u32::MAX)
+                    .with_collection(Collection::Principal);
+                if let Some(current) = &current {
+                    batch.assert_value(ValueClass::Custom { bytes: key.clone() }, current);
+                } else {
+                    batch.assert_value(ValueClass::Custom { bytes: key.clone() }, ());
+                }
+                batch.op(Operation::Value {
+                    class: ValueClass::Custom { bytes: key.clone() },
+                    set: Bincode::new(key_set).serialize().into(),
+                });
+                match self.store.write(batch.build()).await {
+                    Ok(_) => (),
+                    Err(store::Error::AssertValueFailed) if try_count < 3 => {
+                        // Another node updated the key set first
+                        try_count += 1;
+                        continue;
+                    }
+                    Err(err) => {
+                        tracing::error!(event = "error",
+                            context = "store",
+                            error = ?err,
+                            "Failed to write JWT keys");
+                        return Err("Failed to write JWT keys");
+                    }
+                }
+            }
+
+            let keys = Arc::new(keys);
+            *self.jwt_keys.write() = keys.clone();
+            return Ok(keys);
+        }
+    }
+
+    fn generate_jwt_key(&self, now: u64) -> Result<StoredKey, &'static str> {
+        let alg = self.config.oauth_jwt_algorithm;
+        let secret = match alg {
+            JwtAlgorithm::Es256 => ecdsa::SigningKey::random(&mut OsRng).to_bytes().to_vec(),
+            JwtAlgorithm::EdDsa => thread_rng().gen::<[u8; 32]>().to_vec(),
+        };
+        let nonce = thread_rng()
+            .gen::<[u8; SymmetricEncrypt::NONCE_LEN]>()
+            .to_vec();
+        let secret = SymmetricEncrypt::new(self.config.oauth_key.as_bytes(), JWT_KEY_CONTEXT)
+            .encrypt(&secret, &nonce)
+            .map_err(|_| "Failed to encrypt JWT key")?;
+
+        Ok(StoredKey {
+            kid: format!("{:016x}", thread_rng().gen::<u64>()),
+            alg,
+            secret,
+            nonce,
+            created: now,
+            retired: None,
+        })
+    }
+
+    fn decrypt_jwt_keys(&self, key_set: &StoredKeySet) -> JwtKeys {
+        let encrypt = SymmetricEncrypt::new(self.config.oauth_key.as_bytes(), JWT_KEY_CONTEXT);
+        let mut keys = Vec::with_capacity(key_set.keys.len());
+
+        for stored in &key_set.keys {
+            let key = encrypt
+                .decrypt(&stored.secret, &stored.nonce)
+                .ok()
+                .and_then(|secret| match stored.alg {
+                    JwtAlgorithm::Es256 => ecdsa::SigningKey::from_slice(&secret)
+                        .ok()
+                        .map(KeyPair::Es256),
+                    JwtAlgorithm::EdDsa => SecretKey::from_bytes(&secret).ok().map(|secret| {
+                        KeyPair::EdDsa(Keypair {
+                            public: PublicKey::from(&secret),
+                            secret,
+                        })
+                    }),
+                });
+
+            if let Some(key) = key {
+                keys.push(JwtKey {
+                    kid: stored.kid.clone(),
+                    created: stored.created,
+                    retired: stored.retired,
+                    key,
+                });
+            } else {
+                // Most likely the OAuth key changed
+                tracing::warn!(
+                    context = "oauth",
+                    kid = %stored.kid,
+                    "Failed to decrypt JWT signing key."
+                );
+            }
+        }
+
+        // Newest keys first
+        keys.sort_unstable_by(|a, b| b.created.cmp(&a.created));
+
+        JwtKeys {
+            keys,
+            loaded: now(),
+            refreshing: AtomicBool::new(false),
+        }
+    }
+}
+
+impl JwtKeys {
+    pub fn current(&self) -> Option<&JwtKey> {
+        self.keys.iter().find(|key| key.retired.is_none())
+    }
+
+    pub fn get(&self, kid: &str) -> Option<&JwtKey> {
+        self.keys.iter().find(|key| key.kid == kid)
+    }
+
+    /// Returns true if the caller may reload this key set, at most once per
+    /// refresh interval.
+    fn try_refresh(&self) -> bool {
+        self.loaded + JWT_KEYS_MIN_REFRESH <= now()
+            && !self.refreshing.swap(true, Ordering::Relaxed)
+    }
+}
+
+impl JwtClaims {
+    pub fn allows(&self, protocol: TokenProtocol) -> bool {
+        let mut has_protocols = false;
+        for scope in self
+            .scope
+            .as_deref()
+            .unwrap_or_default()
+            .split_ascii_whitespace()
+        {
+            if let Some(scope) = TokenProtocol::parse(scope) {
+                if scope == protocol {
+                    return true;
+                }
+                has_protocols = true;
+            }
+        }
+        !has_protocols
+    }
+}
+
+impl TokenProtocol {
+    pub const ALL: [TokenProtocol; 4] = [
+        TokenProtocol::Jmap,
+        TokenProtocol::Imap,
+        TokenProtocol::Smtp,
+        TokenProtocol::Sieve,
+    ];
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "jmap" => Some(TokenProtocol::Jmap),
+            "imap" => Some(TokenProtocol::Imap),
+            "smtp" => Some(TokenProtocol::Smtp),
+            "sieve" => Some(TokenProtocol::Sieve),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TokenProtocol::Jmap => "jmap",
+            TokenProtocol::Imap => "imap",
+            TokenProtocol::Smtp => "smtp",
+            TokenProtocol::Sieve => "sieve",
+        }
+    }
+}
+
+impl JwtKey {
+    pub fn algorithm(&self) -> JwtAlgorithm {
+        match &self.key {
+            KeyPair::Es256(_) => JwtAlgorithm::Es256,
+            KeyPair::EdDsa(_) => JwtAlgorithm::EdDsa,
+        }
+    }
+
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        match &self.key {
+            KeyPair::Es256(key) => {
+                let signature: ecdsa::Signature = key.sign(message);
+                signature.to_bytes().to_vec()
+            }
+            KeyPair::EdDsa(key) => key.sign(message).to_bytes().to_vec(),
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        match &self.key {
+            KeyPair::Es256(key) => ecdsa::Signature::from_slice(signature)
+                .map_or(false, |signature| {
+                    key.verifying_key().verify(message, &signature).is_ok()
+                }),
+            KeyPair::EdDsa(key) => ed25519_dalek::Signature::try_from(signature)
+                .map_or(false, |signature| {
+                    key.public.verify(message, &signature).is_ok()
+                }),
+        }
+    }
+
+    pub fn to_jwk(&self) -> Jwk {
+        match &self.key {
+            KeyPair::Es256(key) => {
+                let point = key.verifying_key().to_encoded_point(false);
+                Jwk {
+                    kty: "EC",
+                    crv: "P-256",
+                    alg: JwtAlgorithm::Es256,
+                    use_: "sig",
+                    kid: self.kid.clone(),
+                    x: URL_SAFE_NO_PAD.encode(point.x().map(|x| &x[..]).unwrap_or_default()),
+                    y: URL_SAFE_NO_PAD
+                        .encode(point.y().map(|y| &y[..]).unwrap_or_default())
+                        .into(),
+                }
+            }
+            KeyPair::EdDsa(key) => Jwk {
+                kty: "OKP",
+                crv: "Ed25519",
+                alg: JwtAlgorithm::EdDsa,
+                use_: "sig",
+                kid: self.kid.clone(),
+                x: URL_SAFE_NO_PAD.encode(key.public.as_bytes()),
+                y: None,
+            },
+        }
+    }
+}
+
+impl ParseValue for JwtAlgorithm {
+    fn parse_value(key: impl AsKey, value: &str) -> utils::config::Result<Self> {
+        match value {
+            "ES256" | "es256" => Ok(JwtAlgorithm::Es256),
+            "EdDSA" | "eddsa" | "ed25519" => Ok(JwtAlgorithm::EdDsa),
+            _ => Err(format!(
+                "Invalid value {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
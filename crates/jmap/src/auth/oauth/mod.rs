@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::{collections::HashMap, sync::atomic::AtomicU32};
+use std::collections::HashMap;
 
 use http_body_util::BodyExt;
 use hyper::{header::CONTENT_TYPE, StatusCode};
@@ -29,7 +29,10 @@ use serde::{Deserialize, Serialize};
 
 use crate::api::{http::ToHttpResponse, HtmlResponse, HttpRequest, HttpResponse};
 
+pub mod codes;
+pub mod consent;
 pub mod device_auth;
+pub mod jwt;
 pub mod token;
 pub mod user_code;
 
@@ -48,10 +51,16 @@ const OAUTH_HTML_LOGIN_CODE_HIDDEN: &str =
 const OAUTH_HTML_LOGIN_SUCCESS: &str =
     include_str!("../../../../../resources/htx/login_success.htx");
 const OAUTH_HTML_ERROR: &str = include_str!("../../../../../resources/htx/error.htx");
+const OAUTH_HTML_CONSENT_HEADER: &str =
+    include_str!("../../../../../resources/htx/consent_hdr.htx");
+const OAUTH_HTML_CONSENT_SCOPES: &str =
+    include_str!("../../../../../resources/htx/consent_scopes.htx");
+const OAUTH_HTML_CONSENT_FORM: &str = include_str!("../../../../../resources/htx/consent.htx");
 
 const STATUS_AUTHORIZED: u32 = 0;
 const STATUS_TOKEN_ISSUED: u32 = 1;
 const STATUS_PENDING: u32 = 2;
+const STATUS_CONSENT_PENDING: u32 = u32::MAX;
 
 const DEVICE_CODE_LEN: usize = 40;
 const USER_CODE_LEN: usize = 8;
@@ -60,6 +69,8 @@ const CLIENT_ID_MAX_LEN: usize = 20;
 
 const MAX_POST_LEN: usize = 2048;
 
+const SLOW_DOWN_INTERVAL: u64 = 5;
+
 const USER_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789"; // No 0, O, I, 1
 
 pub struct OAuth {
@@ -73,11 +84,14 @@ pub struct OAuth {
     pub metadata: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct OAuthCode {
-    pub status: AtomicU32,
-    pub account_id: AtomicU32,
+    pub status: u32,
+    pub account_id: u32,
     pub client_id: String,
     pub redirect_uri: Option<String>,
+    pub scope: Option<String>,
+    pub expires: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -103,6 +117,7 @@ pub struct DeviceAuthResponse {
     pub user_code: String,
     pub verification_uri: String,
     pub verification_uri_complete: String,
+    pub verification_uri_complete_qr: String,
     pub expires_in: u64,
     pub interval: u64,
 }
@@ -183,6 +198,7 @@ pub struct OAuthMetadata {
     pub response_types_supported: Vec<String>,
     pub scopes_supported: Vec<String>,
     pub authorization_endpoint: String,
+    pub jwks_uri: String,
 }
 
 impl OAuthMetadata {
@@ -191,6 +207,7 @@ impl OAuthMetadata {
             issuer: base_url.to_string(),
             authorization_endpoint: format!("{}/auth/code", base_url),
             token_endpoint: format!("{}/auth/token", base_url),
+            jwks_uri: format!("{}/auth/jwks.json", base_url),
             grant_types_supported: vec![
                 "authorization_code".to_string(),
                 "implicit".to_string(),
@@ -198,7 +215,15 @@ impl OAuthMetadata {
             ],
             device_authorization_endpoint: format!("{}/auth/device", base_url),
             response_types_supported: vec!["code".to_string(), "code token".to_string()],
-            scopes_supported: vec!["offline_access".to_string()],
+            scopes_supported: ["offline_access"]
+                .into_iter()
+                .chain(
+                    jwt::TokenProtocol::ALL
+                        .iter()
+                        .map(|protocol| protocol.as_str()),
+                )
+                .map(|scope| scope.to_string())
+                .collect(),
         }
     }
 }
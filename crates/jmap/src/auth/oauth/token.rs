@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::{sync::atomic, time::SystemTime};
+use std::time::SystemTime;
 
 use hyper::StatusCode;
 use mail_builder::encoders::base64::base64_encode;
@@ -29,11 +29,9 @@ use mail_parser::decoders::base64::base64_decode;
 use store::{
     blake3,
     rand::{thread_rng, Rng},
+    write::now,
 };
-use utils::{
-    codec::leb128::{Leb128Iterator, Leb128Vec},
-    map::ttl_dashmap::TtlMap,
-};
+use utils::codec::leb128::{Leb128Iterator, Leb128Vec};
 
 use crate::{
     api::{http::ToHttpResponse, HttpRequest, HttpResponse, JsonResponse},
@@ -42,8 +40,8 @@ use crate::{
 };
 
 use super::{
-    ErrorType, FormData, TokenResponse, CLIENT_ID_MAX_LEN, MAX_POST_LEN, RANDOM_CODE_LEN,
-    STATUS_AUTHORIZED, STATUS_PENDING, STATUS_TOKEN_ISSUED,
+    jwt::TokenProtocol, ErrorType, FormData, OAuthCode, TokenResponse, CLIENT_ID_MAX_LEN,
+    MAX_POST_LEN, RANDOM_CODE_LEN, STATUS_AUTHORIZED, STATUS_PENDING, STATUS_TOKEN_ISSUED,
 };
 
 impl JMAP {
@@ -64,21 +62,25 @@ impl JMAP {
                 params.get("client_id"),
                 params.get("redirect_uri"),
             ) {
-                if let Some(oauth) = self.oauth_codes.get_with_ttl(code) {
-                    if client_id != oauth.client_id
-                        || redirect_uri != oauth.redirect_uri.as_deref().unwrap_or("")
-                    {
-                        TokenResponse::error(ErrorType::InvalidClient)
-                    } else if oauth.status.load(atomic::Ordering::Relaxed) == STATUS_AUTHORIZED {
-                        // Mark this token as issued
-                        oauth
-                            .status
-                            .store(STATUS_TOKEN_ISSUED, atomic::Ordering::Relaxed);
-
+                // Mark this code as used
+                match self
+                    .update_oauth_code(code, |oauth| {
+                        (client_id == oauth.client_id
+                            && redirect_uri == oauth.redirect_uri.as_deref().unwrap_or("")
+                            && oauth.status == STATUS_AUTHORIZED)
+                            .then(|| OAuthCode {
+                                status: STATUS_TOKEN_ISSUED,
+                                ..oauth.clone()
+                            })
+                    })
+                    .await
+                {
+                    Ok(Some((oauth, true))) => {
                         // Issue token
                         self.issue_token(
-                            oauth.account_id.load(atomic::Ordering::Relaxed),
+                            oauth.account_id,
                             &oauth.client_id,
+                            oauth.scope.as_deref(),
                             true,
                         )
                         .await
@@ -86,11 +88,21 @@ impl JMAP {
                             tracing::error!("Failed to generate OAuth token: {}", err);
                             TokenResponse::error(ErrorType::InvalidRequest)
                         })
-                    } else {
-                        TokenResponse::error(ErrorType::InvalidGrant)
                     }
-                } else {
-                    TokenResponse::error(ErrorType::AccessDenied)
+                    Ok(Some((oauth, false))) => {
+                        if client_id != oauth.client_id
+                            || redirect_uri != oauth.redirect_uri.as_deref().unwrap_or("")
+                        {
+                            TokenResponse::error(ErrorType::InvalidClient)
+                        } else {
+                            TokenResponse::error(ErrorType::InvalidGrant)
+                        }
+                    }
+                    Ok(None) => TokenResponse::error(ErrorType::AccessDenied),
+                    Err(err) => {
+                        tracing::error!("Failed to retrieve OAuth code: {}", err);
+                        TokenResponse::error(ErrorType::InvalidRequest)
+                    }
                 }
             } else {
                 TokenResponse::error(ErrorType::InvalidClient)
@@ -98,26 +110,30 @@ impl JMAP {
         } else if grant_type.eq_ignore_ascii_case("urn:ietf:params:oauth:grant-type:device_code") {
             response = TokenResponse::error(ErrorType::ExpiredToken);
 
-            if let (Some(oauth), Some(client_id)) = (
-                params
-                    .get("device_code")
-                    .and_then(|dc| self.oauth_codes.get_with_ttl(dc)),
-                params.get("client_id"),
-            ) {
-                response = if oauth.client_id != client_id {
-                    TokenResponse::error(ErrorType::InvalidClient)
-                } else {
-                    match oauth.status.load(atomic::Ordering::Relaxed) {
-                        STATUS_AUTHORIZED => {
-                            // Mark this token as issued
-                            oauth
-                                .status
-                                .store(STATUS_TOKEN_ISSUED, atomic::Ordering::Relaxed);
-
+            if let (Some(device_code), Some(client_id)) =
+                (params.get("device_code"), params.get("client_id"))
+            {
+                // Mark this code as used once authorized
+                match self
+                    .update_oauth_code(device_code, |oauth| {
+                        (oauth.client_id == client_id && oauth.status == STATUS_AUTHORIZED).then(
+                            || OAuthCode {
+                                status: STATUS_TOKEN_ISSUED,
+                                ..oauth.clone()
+                            },
+                        )
+                    })
+                    .await
+                {
+                    Ok(Some((oauth, is_issued))) => {
+                        response = if oauth.client_id != client_id {
+                            TokenResponse::error(ErrorType::InvalidClient)
+                        } else if is_issued {
                             // Issue token
                             self.issue_token(
-                                oauth.account_id.load(atomic::Ordering::Relaxed),
+                                oauth.account_id,
                                 &oauth.client_id,
+                                oauth.scope.as_deref(),
                                 true,
                             )
                             .await
@@ -125,30 +141,51 @@ impl JMAP {
                                 tracing::error!("Failed to generate OAuth token: {}", err);
                                 TokenResponse::error(ErrorType::InvalidRequest)
                             })
-                        }
-                        status
-                            if (STATUS_PENDING
-                                ..STATUS_PENDING + self.config.oauth_max_auth_attempts)
-                                .contains(&status) =>
-                        {
-                            TokenResponse::error(ErrorType::AuthorizationPending)
-                        }
-                        STATUS_TOKEN_ISSUED => TokenResponse::error(ErrorType::ExpiredToken),
-                        _ => TokenResponse::error(ErrorType::AccessDenied),
+                        } else {
+                            match oauth.status {
+                                status
+                                    if (STATUS_PENDING
+                                        ..STATUS_PENDING + self.config.oauth_max_auth_attempts)
+                                        .contains(&status) =>
+                                {
+                                    if self.is_device_poll_allowed(device_code) {
+                                        TokenResponse::error(ErrorType::AuthorizationPending)
+                                    } else {
+                                        TokenResponse::error(ErrorType::SlowDown)
+                                    }
+                                }
+                                STATUS_TOKEN_ISSUED => {
+                                    TokenResponse::error(ErrorType::ExpiredToken)
+                                }
+                                _ => TokenResponse::error(ErrorType::AccessDenied),
+                            }
+                        };
                     }
-                };
+                    Ok(None) => (),
+                    Err(err) => {
+                        tracing::error!("Failed to retrieve OAuth code: {}", err);
+                        response = TokenResponse::error(ErrorType::InvalidRequest);
+                    }
+                }
             }
         } else if grant_type.eq_ignore_ascii_case("refresh_token") {
             if let Some(refresh_token) = params.get("refresh_token") {
                 if let Ok((account_id, client_id, time_left)) = self
-                    .validate_access_token("refresh_token", refresh_token)
+                    .validate_access_token("refresh_token", refresh_token, None)
                     .await
                 {
                     // TODO: implement revoking client ids
+                    let scope = self
+                        .get_issued_token(account_id, refresh_token)
+                        .await
+                        .ok()
+                        .flatten()
+                        .and_then(|token| token.scope);
                     response = self
                         .issue_token(
                             account_id,
                             &client_id,
+                            scope.as_deref(),
                             time_left <= self.config.oauth_expiry_refresh_token_renew,
                         )
                         .await
@@ -177,47 +214,71 @@ impl JMAP {
         &self,
         account_id: u32,
         client_id: &str,
+        scope: Option<&str>,
         with_refresh_token: bool,
     ) -> Result<TokenResponse, &'static str> {
-        let account_name = self
-            .get_account_name(account_id)
-            .await
-            .map_err(|_| "Temporary lookup error")?
-            .ok_or("Account no longer exists")?;
-        let password_hash = self
-            .directory
-            .principal(&account_name)
-            .await
-            .map_err(|_| "Temporary lookup error")?
-            .ok_or("Account no longer exists")?
-            .secrets
-            .into_iter()
-            .next()
-            .ok_or("Failed to obtain password hash")?;
+        let access_token = self
+            .encode_jwt(account_id, client_id, scope, self.config.oauth_expiry_token)
+            .await?;
+        let refresh_token = if with_refresh_token {
+            let account_name = self
+                .get_account_name(account_id)
+                .await
+                .map_err(|_| "Temporary lookup error")?
+                .ok_or("Account no longer exists")?;
+            let password_hash = self
+                .directory
+                .principal(&account_name)
+                .await
+                .map_err(|_| "Temporary lookup error")?
+                .ok_or("Account no longer exists")?
+                .secrets
+                .into_iter()
+                .next()
+                .ok_or("Failed to obtain password hash")?;
 
-        Ok(TokenResponse::Granted {
-            access_token: self.encode_access_token(
-                "access_token",
+            self.encode_access_token(
+                "refresh_token",
                 account_id,
                 &password_hash,
                 client_id,
-                self.config.oauth_expiry_token,
-            )?,
+                self.config.oauth_expiry_refresh_token,
+            )?
+            .into()
+        } else {
+            None
+        };
+
+        // Register issued tokens so they can be listed and revoked
+        self.register_token(
+            account_id,
+            &access_token,
+            client_id,
+            scope,
+            false,
+            self.config.oauth_expiry_token,
+        )
+        .await
+        .map_err(|_| "Failed to register token")?;
+        if let Some(refresh_token) = &refresh_token {
+            self.register_token(
+                account_id,
+                refresh_token,
+                client_id,
+                scope,
+                true,
+                self.config.oauth_expiry_refresh_token,
+            )
+            .await
+            .map_err(|_| "Failed to register token")?;
+        }
+
+        Ok(TokenResponse::Granted {
+            access_token,
             token_type: "bearer".to_string(),
             expires_in: self.config.oauth_expiry_token,
-            refresh_token: if with_refresh_token {
-                self.encode_access_token(
-                    "refresh_token",
-                    account_id,
-                    &password_hash,
-                    client_id,
-                    self.config.oauth_expiry_refresh_token,
-                )?
-                .into()
-            } else {
-                None
-            },
-            scope: None,
+            refresh_token,
+            scope: scope.map(|scope| scope.to_string()),
         })
     }
 
@@ -275,8 +336,34 @@ impl JMAP {
         &self,
         grant_type: &str,
         token: &str,
+        protocol: Option<TokenProtocol>,
     ) -> Result<(u32, String, u64), &'static str> {
+        // Signed access tokens are validated locally
+        if grant_type == "access_token" && token.contains('.') {
+            let claims = self.validate_jwt(token).await?;
+            if protocol.map_or(false, |protocol| !claims.allows(protocol)) {
+                return Err("Token scope does not include this protocol.");
+            }
+            let account_id = claims
+                .sub
+                .parse::<u32>()
+                .map_err(|_| "Invalid token subject.")?;
+            if self
+                .is_token_revoked(account_id, token)
+                .await
+                .map_err(|_| "Temporary lookup error")?
+            {
+                return Err("Token revoked.");
+            }
+            return Ok((
+                account_id,
+                claims.client_id,
+                claims.exp.saturating_sub(now()),
+            ));
+        }
+
         // Base64 decode token
+        let encoded_token = token;
         let token = base64_decode(token.as_bytes()).ok_or("Failed to decode.")?;
         let (account_id, expiry, client_id) = token
             .get((RANDOM_CODE_LEN + SymmetricEncrypt::ENCRYPT_TAG_LEN)..)
@@ -346,6 +433,15 @@ impl JMAP {
             )
             .map_err(|_| "Failed to decrypt token.")?;
 
+        // Make sure the token has not been revoked
+        if self
+            .is_token_revoked(account_id, encoded_token)
+            .await
+            .map_err(|_| "Temporary lookup error")?
+        {
+            return Err("Token revoked.");
+        }
+
         // Success
         Ok((account_id, client_id, expiry - now))
     }
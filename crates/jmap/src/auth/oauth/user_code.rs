@@ -21,19 +21,18 @@
  * for more details.
 */
 
-use std::{
-    collections::HashMap,
-    sync::Arc,
-    time::{Duration, Instant},
-};
+use std::collections::HashMap;
 
 use http_body_util::{BodyExt, Full};
 use hyper::{body::Bytes, header, StatusCode};
 use mail_builder::encoders::base64::base64_encode;
 use mail_parser::decoders::base64::base64_decode;
 use std::fmt::Write;
-use store::rand::{distributions::Alphanumeric, thread_rng, Rng};
-use utils::map::ttl_dashmap::TtlMap;
+use store::{
+    rand::{distributions::Alphanumeric, thread_rng, Rng},
+    write::now,
+};
+use utils::codec::html::html_escape;
 
 use crate::{
     api::{http::ToHttpResponse, HtmlResponse, HttpRequest, HttpResponse},
@@ -42,9 +41,11 @@ use crate::{
 };
 
 use super::{
-    FormData, OAuthCode, CLIENT_ID_MAX_LEN, DEVICE_CODE_LEN, MAX_POST_LEN, OAUTH_HTML_FOOTER,
-    OAUTH_HTML_HEADER, OAUTH_HTML_LOGIN_CODE_HIDDEN, OAUTH_HTML_LOGIN_FORM,
+    consent::parse_scopes, FormData, OAuthCode, CLIENT_ID_MAX_LEN, DEVICE_CODE_LEN, MAX_POST_LEN,
+    OAUTH_HTML_CONSENT_FORM, OAUTH_HTML_CONSENT_HEADER, OAUTH_HTML_CONSENT_SCOPES,
+    OAUTH_HTML_FOOTER, OAUTH_HTML_HEADER, OAUTH_HTML_LOGIN_CODE_HIDDEN, OAUTH_HTML_LOGIN_FORM,
     OAUTH_HTML_LOGIN_HEADER_CLIENT, OAUTH_HTML_LOGIN_HEADER_FAILED, STATUS_AUTHORIZED,
+    STATUS_CONSENT_PENDING,
 };
 
 impl JMAP {
@@ -104,7 +105,7 @@ impl JMAP {
         response.push_str(&OAUTH_HTML_LOGIN_FORM.replace("@@@", &cancel_link));
         response.push_str(OAUTH_HTML_FOOTER);
 
-        HtmlResponse::new(response).into_http_response()
+        HtmlResponse::new(self.brand_html(req, response)).into_http_response()
     }
 
     // Handles POST request from the code authorization form
@@ -135,36 +136,117 @@ impl JMAP {
             }
         };
 
-        // Authenticate user
-        if let (Some(email), Some(password)) = (params.get("email"), params.get("password")) {
-            if let Some(access_token) = self.authenticate_plain(email, password, remote_addr).await
-            {
-                // Generate client code
-                let client_code = thread_rng()
-                    .sample_iter(Alphanumeric)
-                    .take(DEVICE_CODE_LEN)
-                    .map(char::from)
-                    .collect::<String>();
-
-                // Add client code
-                self.oauth_codes.insert_with_ttl(
-                    client_code.clone(),
-                    Arc::new(OAuthCode {
-                        status: STATUS_AUTHORIZED.into(),
-                        account_id: access_token.primary_id().into(),
-                        client_id: code_req
-                            .get("client_id")
-                            .map(|s| s.as_str())
-                            .unwrap_or_default()
-                            .to_string(),
-                        redirect_uri: code_req.get("redirect_uri").cloned(),
-                    }),
-                    Instant::now() + Duration::from_secs(self.config.oauth_expiry_auth_code),
-                );
-
-                auth_code = client_code.into();
+        let client_id = code_req
+            .get("client_id")
+            .map(|s| s.as_str())
+            .unwrap_or_default();
+        let redirect_uri = code_req.get("redirect_uri").map(|s| s.as_str());
+        let is_consent = if let Some(consent_code) = params.get("consent") {
+            // Consent screen submission
+            if params.get("action") == Some("allow") {
+                match self
+                    .update_oauth_code(consent_code, |oauth| {
+                        (oauth.status == STATUS_CONSENT_PENDING
+                            && oauth.client_id == client_id
+                            && oauth.redirect_uri.as_deref() == redirect_uri)
+                            .then(|| OAuthCode {
+                                status: STATUS_AUTHORIZED,
+                                ..oauth.clone()
+                            })
+                    })
+                    .await
+                {
+                    Ok(Some((oauth, true))) => {
+                        if let Err(err) = self
+                            .grant_oauth_consent(
+                                oauth.account_id,
+                                &oauth.client_id,
+                                oauth.scope.as_deref(),
+                            )
+                            .await
+                        {
+                            tracing::error!(event = "error",
+                                context = "oauth",
+                                error = ?err,
+                                "Failed to store OAuth consent");
+                        }
+                        auth_code = consent_code.to_string().into();
+                    }
+                    Ok(_) => (),
+                    Err(err) => {
+                        tracing::error!(event = "error",
+                            context = "oauth",
+                            error = ?err,
+                            "Failed to update authorization code");
+                    }
+                }
             }
-        }
+            true
+        } else {
+            // Authenticate user
+            if let (Some(email), Some(password)) = (params.get("email"), params.get("password")) {
+                if let Some(access_token) =
+                    self.authenticate_plain(email, password, remote_addr).await
+                {
+                    // Ask for consent unless the client was previously authorized
+                    let account_id = access_token.primary_id();
+                    let scope = code_req.get("scope").map(|s| s.as_str());
+                    let needs_consent = self.config.oauth_require_consent
+                        && !self
+                            .has_oauth_consent(account_id, client_id, scope)
+                            .await
+                            .unwrap_or(false);
+
+                    // Generate client code
+                    let client_code = thread_rng()
+                        .sample_iter(Alphanumeric)
+                        .take(DEVICE_CODE_LEN)
+                        .map(char::from)
+                        .collect::<String>();
+
+                    // Add client code
+                    match self
+                        .insert_oauth_code(
+                            &client_code,
+                            None,
+                            OAuthCode {
+                                status: if needs_consent {
+                                    STATUS_CONSENT_PENDING
+                                } else {
+                                    STATUS_AUTHORIZED
+                                },
+                                account_id,
+                                client_id: client_id.to_string(),
+                                redirect_uri: redirect_uri.map(|s| s.to_string()),
+                                scope: scope.map(|s| s.to_string()),
+                                expires: now() + self.config.oauth_expiry_auth_code,
+                            },
+                        )
+                        .await
+                    {
+                        Ok(_) if needs_consent => {
+                            return self.consent_page(
+                                req,
+                                client_id,
+                                scope,
+                                &client_code,
+                                params.get("code").unwrap_or_default(),
+                            );
+                        }
+                        Ok(_) => {
+                            auth_code = client_code.into();
+                        }
+                        Err(err) => {
+                            tracing::error!(event = "error",
+                                context = "oauth",
+                                error = ?err,
+                                "Failed to store authorization code");
+                        }
+                    }
+                }
+            }
+            false
+        };
 
         // Build redirect link
         let mut redirect_link = if let Some(auth_code) = &auth_code {
@@ -189,7 +271,10 @@ impl JMAP {
             let _ = write!(redirect_link, "&state={}", state);
         }
 
-        if auth_code.is_none() && (auth_attempts < self.config.oauth_max_auth_attempts) {
+        if auth_code.is_none()
+            && !is_consent
+            && (auth_attempts < self.config.oauth_max_auth_attempts)
+        {
             let code = String::from_utf8(
                 base64_encode(
                     &bincode::serialize(&(auth_attempts + 1, code_req)).unwrap_or_default(),
@@ -214,7 +299,7 @@ impl JMAP {
             response.push_str(&OAUTH_HTML_LOGIN_FORM.replace("@@@", &redirect_link));
             response.push_str(OAUTH_HTML_FOOTER);
 
-            HtmlResponse::new(response).into_http_response()
+            HtmlResponse::new(self.brand_html(req, response)).into_http_response()
         } else {
             hyper::Response::builder()
                 .status(StatusCode::TEMPORARY_REDIRECT)
@@ -227,4 +312,45 @@ impl JMAP {
                 .unwrap()
         }
     }
+
+    fn consent_page(
+        &self,
+        req: &HttpRequest,
+        client_id: &str,
+        scope: Option<&str>,
+        consent_code: &str,
+        code: &str,
+    ) -> HttpResponse {
+        let scopes = parse_scopes(scope);
+        let mut scope_list = String::new();
+        if scopes.is_empty() {
+            scope_list.push_str("<li>Full access to your account</li>");
+        } else {
+            for scope in &scopes {
+                let _ = write!(scope_list, "<li>{}</li>", html_escape(scope));
+            }
+        }
+
+        let mut response = String::with_capacity(
+            OAUTH_HTML_HEADER.len()
+                + OAUTH_HTML_CONSENT_HEADER.len()
+                + OAUTH_HTML_CONSENT_SCOPES.len()
+                + OAUTH_HTML_LOGIN_CODE_HIDDEN.len()
+                + OAUTH_HTML_CONSENT_FORM.len()
+                + OAUTH_HTML_FOOTER.len()
+                + client_id.len()
+                + scope_list.len()
+                + code.len()
+                + consent_code.len()
+                + 10,
+        );
+        response.push_str(&OAUTH_HTML_HEADER.replace("@@@", "/auth/code"));
+        response.push_str(&OAUTH_HTML_CONSENT_HEADER.replace("@@@", &html_escape(client_id)));
+        response.push_str(&OAUTH_HTML_CONSENT_SCOPES.replace("@@@", &scope_list));
+        response.push_str(&OAUTH_HTML_LOGIN_CODE_HIDDEN.replace("@@@", code));
+        response.push_str(&OAUTH_HTML_CONSENT_FORM.replace("@@@", consent_code));
+        response.push_str(OAUTH_HTML_FOOTER);
+
+        HtmlResponse::new(self.brand_html(req, response)).into_http_response()
+    }
 }
@@ -23,7 +23,10 @@
 
 use std::{net::IpAddr, sync::Arc};
 
-use jmap_proto::error::request::{RequestError, RequestLimitError};
+use jmap_proto::error::{
+    method::MethodError,
+    request::{RequestError, RequestLimitError},
+};
 use store::parking_lot::Mutex;
 use utils::listener::limiter::{ConcurrencyLimiter, InFlight, RateLimiter};
 
@@ -34,31 +37,49 @@ use super::AccessToken;
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub enum RemoteAddress {
     IpAddress(IpAddr),
-    IpAddressFwd(String),
 }
 
 pub struct AuthenticatedLimiter {
     pub request_limiter: RateLimiter,
+    pub method_limiter: RateLimiter,
+    pub upload_limiter: RateLimiter,
     pub concurrent_requests: ConcurrencyLimiter,
     pub concurrent_uploads: ConcurrencyLimiter,
+    pub concurrent_queries: ConcurrencyLimiter,
 }
 
 #[derive(Debug)]
 pub struct AnonymousLimiter {
     request_limiter: RateLimiter,
     auth_limiter: RateLimiter,
+    oauth_limiter: RateLimiter,
 }
 
 impl JMAP {
-    pub fn get_authenticated_limiter(&self, account_id: u32) -> Arc<Mutex<AuthenticatedLimiter>> {
+    pub fn get_authenticated_limiter(
+        &self,
+        access_token: &AccessToken,
+    ) -> Arc<Mutex<AuthenticatedLimiter>> {
+        let account_id = access_token.primary_id();
         self.rate_limit_auth
             .get(&account_id)
             .map(|limiter| limiter.clone())
             .unwrap_or_else(|| {
+                let rate = self
+                    .config
+                    .tenants
+                    .by_account(&access_token.name)
+                    .and_then(|tenant| tenant.rate_authenticated.as_ref())
+                    .unwrap_or(&self.config.rate_authenticated);
                 let limiter = Arc::new(Mutex::new(AuthenticatedLimiter {
-                    request_limiter: RateLimiter::new(
-                        self.config.rate_authenticated.requests,
-                        self.config.rate_authenticated.period,
+                    request_limiter: RateLimiter::new(rate.requests, rate.period),
+                    method_limiter: RateLimiter::new(
+                        self.config.rate_method_calls.requests,
+                        self.config.rate_method_calls.period,
+                    ),
+                    upload_limiter: RateLimiter::new(
+                        self.config.rate_upload.requests,
+                        self.config.rate_upload.period,
                     ),
                     concurrent_requests: ConcurrencyLimiter::new(
                         self.config.request_max_concurrent,
@@ -66,6 +87,7 @@ impl JMAP {
                     concurrent_uploads: ConcurrencyLimiter::new(
                         self.config.upload_max_concurrent as u64,
                     ),
+                    concurrent_queries: ConcurrencyLimiter::new(self.config.query_max_concurrent),
                 }));
                 self.rate_limit_auth.insert(account_id, limiter.clone());
                 limiter
@@ -86,6 +108,10 @@ impl JMAP {
                         self.config.rate_authenticate_req.requests,
                         self.config.rate_authenticate_req.period,
                     ),
+                    oauth_limiter: RateLimiter::new(
+                        self.config.rate_oauth.requests,
+                        self.config.rate_oauth.period,
+                    ),
                 }));
                 self.rate_limit_unauth.insert(addr.clone(), limiter.clone());
                 limiter
@@ -93,7 +119,7 @@ impl JMAP {
     }
 
     pub fn is_account_allowed(&self, access_token: &AccessToken) -> Result<InFlight, RequestError> {
-        let limiter_ = self.get_authenticated_limiter(access_token.primary_id());
+        let limiter_ = self.get_authenticated_limiter(access_token);
         let mut limiter = limiter_.lock();
 
         if limiter.request_limiter.is_allowed() {
@@ -107,57 +133,122 @@ impl JMAP {
         } else if access_token.is_super_user() {
             Ok(InFlight::default())
         } else {
-            Err(RequestError::too_many_requests())
+            Err(RequestError::too_many_requests()
+                .with_retry_after(retry_after(&limiter.request_limiter)))
+        }
+    }
+
+    pub fn is_method_calls_allowed(
+        &self,
+        access_token: &AccessToken,
+        num_calls: usize,
+    ) -> Result<(), RequestError> {
+        if access_token.is_super_user() {
+            return Ok(());
+        }
+
+        let limiter_ = self.get_authenticated_limiter(access_token);
+        let mut limiter = limiter_.lock();
+
+        if limiter.method_limiter.is_allowed_many(num_calls as u64) {
+            Ok(())
+        } else {
+            Err(RequestError::too_many_requests()
+                .with_retry_after(retry_after(&limiter.method_limiter)))
         }
     }
 
     pub fn is_anonymous_allowed(&self, addr: &RemoteAddress) -> Result<(), RequestError> {
-        if self
-            .get_anonymous_limiter(addr)
-            .lock()
-            .request_limiter
-            .is_allowed()
-        {
+        let limiter_ = self.get_anonymous_limiter(addr);
+        let mut limiter = limiter_.lock();
+
+        if limiter.request_limiter.is_allowed() {
             Ok(())
         } else {
-            Err(RequestError::too_many_requests())
+            Err(RequestError::too_many_requests()
+                .with_retry_after(retry_after(&limiter.request_limiter)))
+        }
+    }
+
+    pub fn is_oauth_allowed(&self, addr: &RemoteAddress) -> Result<(), RequestError> {
+        let limiter_ = self.get_anonymous_limiter(addr);
+        let mut limiter = limiter_.lock();
+
+        if !limiter.request_limiter.is_allowed() {
+            Err(RequestError::too_many_requests()
+                .with_retry_after(retry_after(&limiter.request_limiter)))
+        } else if !limiter.oauth_limiter.is_allowed() {
+            Err(RequestError::too_many_requests()
+                .with_retry_after(retry_after(&limiter.oauth_limiter)))
+        } else {
+            Ok(())
         }
     }
 
     pub fn is_upload_allowed(&self, access_token: &AccessToken) -> Result<InFlight, RequestError> {
-        if let Some(in_flight_request) = self
-            .get_authenticated_limiter(access_token.primary_id())
+        let limiter_ = self.get_authenticated_limiter(access_token);
+        let mut limiter = limiter_.lock();
+
+        if access_token.is_super_user() {
+            Ok(limiter.concurrent_uploads.is_allowed().unwrap_or_default())
+        } else if !limiter.upload_limiter.is_allowed() {
+            Err(RequestError::too_many_requests()
+                .with_retry_after(retry_after(&limiter.upload_limiter)))
+        } else if let Some(in_flight_request) = limiter.concurrent_uploads.is_allowed() {
+            Ok(in_flight_request)
+        } else {
+            Err(RequestError::limit(RequestLimitError::ConcurrentUpload))
+        }
+    }
+
+    // Limits the number of concurrent queries that could return large result sets
+    pub fn is_query_allowed(
+        &self,
+        access_token: &AccessToken,
+        limit: Option<usize>,
+        calculate_total: bool,
+    ) -> Result<Option<InFlight>, MethodError> {
+        if !calculate_total && limit.map_or(false, |limit| limit <= self.config.query_large_results)
+        {
+            Ok(None)
+        } else if let Some(in_flight) = self
+            .get_authenticated_limiter(access_token)
             .lock()
-            .concurrent_uploads
+            .concurrent_queries
             .is_allowed()
         {
-            Ok(in_flight_request)
+            Ok(Some(in_flight))
         } else if access_token.is_super_user() {
-            Ok(InFlight::default())
+            Ok(None)
         } else {
-            Err(RequestError::limit(RequestLimitError::ConcurrentUpload))
+            Err(MethodError::ServerUnavailable)
         }
     }
 
     pub fn is_auth_allowed_soft(&self, addr: &RemoteAddress) -> Result<(), RequestError> {
         match self.rate_limit_unauth.get(addr) {
-            Some(limiter) if !limiter.lock().auth_limiter.is_allowed_soft() => {
-                Err(RequestError::too_many_auth_attempts())
+            Some(limiter) => {
+                let limiter = limiter.lock();
+                if !limiter.auth_limiter.is_allowed_soft() {
+                    Err(RequestError::too_many_auth_attempts()
+                        .with_retry_after(retry_after(&limiter.auth_limiter)))
+                } else {
+                    Ok(())
+                }
             }
             _ => Ok(()),
         }
     }
 
     pub fn is_auth_allowed_hard(&self, addr: &RemoteAddress) -> Result<(), RequestError> {
-        if self
-            .get_anonymous_limiter(addr)
-            .lock()
-            .auth_limiter
-            .is_allowed()
-        {
+        let limiter_ = self.get_anonymous_limiter(addr);
+        let mut limiter = limiter_.lock();
+
+        if limiter.auth_limiter.is_allowed() {
             Ok(())
         } else {
-            Err(RequestError::too_many_auth_attempts())
+            Err(RequestError::too_many_auth_attempts()
+                .with_retry_after(retry_after(&limiter.auth_limiter)))
         }
     }
 }
@@ -165,13 +256,22 @@ impl JMAP {
 impl AuthenticatedLimiter {
     pub fn is_active(&self) -> bool {
         self.request_limiter.is_active()
+            || self.method_limiter.is_active()
+            || self.upload_limiter.is_active()
             || self.concurrent_requests.is_active()
             || self.concurrent_uploads.is_active()
+            || self.concurrent_queries.is_active()
     }
 }
 
 impl AnonymousLimiter {
     pub fn is_active(&self) -> bool {
-        self.request_limiter.is_active() || self.auth_limiter.is_active()
+        self.request_limiter.is_active()
+            || self.auth_limiter.is_active()
+            || self.oauth_limiter.is_active()
     }
 }
+
+fn retry_after(limiter: &RateLimiter) -> u64 {
+    std::cmp::max(limiter.retry_in().as_secs(), 1)
+}
@@ -0,0 +1,711 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use jmap_proto::{error::method::MethodError, types::collection::Collection};
+use store::{
+    blake3,
+    rand::{thread_rng, Rng},
+    write::{assert::HashedValue, now, BatchBuilder, Operation, ValueClass},
+    CustomValueKey, Serialize,
+};
+use tokio::sync::watch;
+use utils::{map::ttl_dashmap::TtlMap, metrics};
+
+use crate::{Bincode, JMAP};
+
+use super::authenticate::AccountKey;
+
+pub type LiveSessions = Arc<DashMap<u64, LiveSession>>;
+
+pub const METRIC_SESSION_MEMORY: &str = "session.memory";
+pub const METRIC_SESSION_EVICTED: &str = "session.evicted";
+pub const METRIC_IMAP_BYTES_IN: &str = "session.imap.bytes-in";
+pub const METRIC_IMAP_BYTES_OUT: &str = "session.imap.bytes-out";
+pub const METRIC_WEBSOCKET_BYTES_IN: &str = "session.websocket.bytes-in";
+pub const METRIC_WEBSOCKET_BYTES_OUT: &str = "session.websocket.bytes-out";
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AccountTokens {
+    pub issued: Vec<IssuedToken>,
+    pub revoked: Vec<RevokedToken>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct IssuedToken {
+    pub id: u64,
+    pub client_id: String,
+    pub scope: Option<String>,
+    pub is_refresh: bool,
+    pub issued: u64,
+    pub expires: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct RevokedToken {
+    pub id: u64,
+    pub expires: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionProtocol {
+    Imap,
+    WebSocket,
+}
+
+pub struct LiveSession {
+    pub account_id: u32,
+    pub protocol: SessionProtocol,
+    pub remote_ip: Option<IpAddr>,
+    pub created: u64,
+    pub stats: Arc<SessionStats>,
+    started: Instant,
+    revoke_tx: watch::Sender<bool>,
+}
+
+/// Resource usage of a live session, updated by the connection handler.
+#[derive(Debug, Default)]
+pub struct SessionStats {
+    pub bytes_in: AtomicU64,
+    pub bytes_out: AtomicU64,
+    pub memory: AtomicU64,
+    pub last_activity: AtomicU64,
+    evicted: AtomicBool,
+}
+
+/// Keeps a connection registered as a live session until dropped.
+pub struct SessionGuard {
+    pub id: u64,
+    protocol: SessionProtocol,
+    sessions: LiveSessions,
+    stats: Arc<SessionStats>,
+    memory_used: Arc<AtomicU64>,
+    memory_max: Option<u64>,
+    revoke_rx: watch::Receiver<bool>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+pub struct SessionReport {
+    pub memory_used: u64,
+    pub memory_max: Option<u64>,
+    pub protocols: Vec<SessionProtocolReport>,
+    pub sessions: Vec<SessionUsage>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SessionProtocolReport {
+    pub protocol: &'static str,
+    pub sessions: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub memory: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SessionUsage {
+    pub id: String,
+    pub account_id: u32,
+    pub protocol: &'static str,
+    pub remote_ip: Option<String>,
+    pub created: u64,
+    pub last_activity: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub memory: u64,
+}
+
+#[derive(Debug, Clone)]
+pub enum SessionEntry {
+    OAuth {
+        id: u64,
+        client_id: String,
+        is_refresh: bool,
+        issued: u64,
+        expires: u64,
+    },
+    Live {
+        id: u64,
+        protocol: SessionProtocol,
+        remote_ip: Option<IpAddr>,
+        created: u64,
+    },
+    Cached {
+        id: u64,
+        expires_in: Duration,
+    },
+}
+
+impl JMAP {
+    pub async fn get_account_tokens(
+        &self,
+        account_id: u32,
+    ) -> Result<Option<HashedValue<Bincode<AccountTokens>>>, MethodError> {
+        self.store
+            .get_value::<HashedValue<Bincode<AccountTokens>>>(CustomValueKey {
+                value: AccountKey::id_to_tokens(account_id),
+            })
+            .await
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve account tokens");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    async fn update_account_tokens(
+        &self,
+        account_id: u32,
+        mut f: impl FnMut(&mut AccountTokens) -> bool,
+    ) -> Result<bool, MethodError> {
+        let mut try_count = 0;
+
+        loop {
+            let current = self.get_account_tokens(account_id).await?;
+            let mut tokens = current
+                .as_ref()
+                .map(|tokens| tokens.inner.inner.clone())
+                .unwrap_or_default();
+
+            // Apply changes and purge expired entries
+            let now = now();
+            if !f(&mut tokens) {
+                return Ok(false);
+            }
+            tokens.issued.retain(|token| token.expires > now);
+            tokens.revoked.retain(|token| token.expires > now);
+
+            let key = AccountKey::id_to_tokens(account_id);
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal);
+            if let Some(current) = &current {
+                batch.assert_value(ValueClass::Custom { bytes: key.clone() }, current);
+            } else {
+                batch.assert_value(ValueClass::Custom { bytes: key.clone() }, ());
+            }
+            batch.op(Operation::Value {
+                class: ValueClass::Custom { bytes: key },
+                set: if !tokens.issued.is_empty() || !tokens.revoked.is_empty() {
+                    Bincode::new(tokens).serialize().into()
+                } else {
+                    None
+                },
+            });
+
+            match self.store.write(batch.build()).await {
+                Ok(_) => {
+                    self.revoked_tokens.remove(&account_id);
+                    return Ok(true);
+                }
+                Err(store::Error::AssertValueFailed) if try_count < 3 => {
+                    try_count += 1;
+                    continue;
+                }
+                Err(err) => {
+                    tracing::error!(event = "error",
+                        context = "store",
+                        account_id = account_id,
+                        error = ?err,
+                        "Failed to write account tokens");
+                    return Err(MethodError::ServerPartialFail);
+                }
+            }
+        }
+    }
+
+    pub async fn register_token(
+        &self,
+        account_id: u32,
+        token: &str,
+        client_id: &str,
+        scope: Option<&str>,
+        is_refresh: bool,
+        expires_in: u64,
+    ) -> Result<(), MethodError> {
+        let issued = IssuedToken {
+            id: token_id(token),
+            client_id: client_id.to_string(),
+            scope: scope.map(|scope| scope.to_string()),
+            is_refresh,
+            issued: now(),
+            expires: now() + expires_in,
+        };
+        self.update_account_tokens(account_id, |tokens| {
+            tokens.issued.push(issued.clone());
+            true
+        })
+        .await
+        .map(|_| ())
+    }
+
+    pub async fn get_issued_token(
+        &self,
+        account_id: u32,
+        token: &str,
+    ) -> Result<Option<IssuedToken>, MethodError> {
+        let id = token_id(token);
+        Ok(self
+            .get_account_tokens(account_id)
+            .await?
+            .and_then(|tokens| {
+                tokens
+                    .inner
+                    .inner
+                    .issued
+                    .into_iter()
+                    .find(|token| token.id == id)
+            }))
+    }
+
+    pub async fn is_token_revoked(
+        &self,
+        account_id: u32,
+        token: &str,
+    ) -> Result<bool, MethodError> {
+        // Revocations are cached for as long as sessions are, so validating a
+        // signed token does not require a store lookup each time.
+        let revoked = if let Some(revoked) = self.revoked_tokens.get_with_ttl(&account_id) {
+            revoked
+        } else {
+            let revoked = Arc::new(
+                self.get_account_tokens(account_id)
+                    .await?
+                    .map(|tokens| {
+                        tokens
+                            .inner
+                            .inner
+                            .revoked
+                            .into_iter()
+                            .map(|token| token.id)
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default(),
+            );
+            self.revoked_tokens.insert_with_ttl(
+                account_id,
+                revoked,
+                Instant::now() + self.config.session_cache_ttl,
+            )
+        };
+
+        Ok(revoked.contains(&token_id(token)))
+    }
+
+    pub async fn revoke_client_tokens(
+        &self,
+        account_id: u32,
+        client_id: &str,
+    ) -> Result<bool, MethodError> {
+        let mut revoked_ids = Vec::new();
+        self.update_account_tokens(account_id, |tokens| {
+            revoked_ids.clear();
+            tokens.issued.retain(|token| {
+                if token.client_id == client_id {
+                    revoked_ids.push(RevokedToken {
+                        id: token.id,
+                        expires: token.expires,
+                    });
+                    false
+                } else {
+                    true
+                }
+            });
+            tokens.revoked.extend(revoked_ids.iter().cloned());
+            !revoked_ids.is_empty()
+        })
+        .await?;
+
+        // Remove cached credentials
+        if !revoked_ids.is_empty() {
+            self.sessions.retain(|key, session| {
+                *session.item() != account_id
+                    || !revoked_ids.iter().any(|token| token.id == token_id(key))
+            });
+        }
+
+        Ok(!revoked_ids.is_empty())
+    }
+
+    pub fn register_live_session(
+        &self,
+        account_id: u32,
+        protocol: SessionProtocol,
+        remote_ip: Option<IpAddr>,
+    ) -> SessionGuard {
+        let (revoke_tx, revoke_rx) = watch::channel(false);
+        let mut id = thread_rng().gen::<u64>();
+        while self.live_sessions.contains_key(&id) {
+            id = thread_rng().gen::<u64>();
+        }
+        let stats = Arc::new(SessionStats {
+            last_activity: now().into(),
+            ..Default::default()
+        });
+        self.live_sessions.insert(
+            id,
+            LiveSession {
+                account_id,
+                protocol,
+                remote_ip,
+                created: now(),
+                stats: stats.clone(),
+                started: Instant::now(),
+                revoke_tx,
+            },
+        );
+
+        SessionGuard {
+            id,
+            protocol,
+            sessions: self.live_sessions.clone(),
+            stats,
+            memory_used: self.live_sessions_memory.clone(),
+            memory_max: self.config.session_max_memory,
+            revoke_rx,
+        }
+    }
+
+    pub fn session_report(&self) -> SessionReport {
+        let mut report = SessionReport {
+            memory_used: self.live_sessions_memory.load(Ordering::Relaxed),
+            memory_max: self.config.session_max_memory,
+            ..Default::default()
+        };
+
+        for entry in self.live_sessions.iter() {
+            let session = entry.value();
+            let usage = SessionUsage {
+                id: format!("{:016x}", entry.key()),
+                account_id: session.account_id,
+                protocol: session.protocol.as_str(),
+                remote_ip: session.remote_ip.map(|ip| ip.to_string()),
+                created: session.created,
+                last_activity: session.stats.last_activity.load(Ordering::Relaxed),
+                bytes_in: session.stats.bytes_in.load(Ordering::Relaxed),
+                bytes_out: session.stats.bytes_out.load(Ordering::Relaxed),
+                memory: session.stats.memory.load(Ordering::Relaxed),
+            };
+
+            let protocol = if let Some(protocol) = report
+                .protocols
+                .iter_mut()
+                .find(|p| p.protocol == usage.protocol)
+            {
+                protocol
+            } else {
+                report.protocols.push(SessionProtocolReport {
+                    protocol: usage.protocol,
+                    sessions: 0,
+                    bytes_in: 0,
+                    bytes_out: 0,
+                    memory: 0,
+                });
+                report.protocols.last_mut().unwrap()
+            };
+            protocol.sessions += 1;
+            protocol.bytes_in += usage.bytes_in;
+            protocol.bytes_out += usage.bytes_out;
+            protocol.memory += usage.memory;
+
+            report.sessions.push(usage);
+        }
+
+        report.sessions.sort_unstable_by_key(|s| s.created);
+        report
+    }
+
+    pub async fn list_sessions(&self, account_id: u32) -> Result<Vec<SessionEntry>, MethodError> {
+        let mut sessions = Vec::new();
+
+        // Issued OAuth tokens
+        let now = now();
+        if let Some(tokens) = self.get_account_tokens(account_id).await? {
+            for token in tokens.inner.inner.issued {
+                if token.expires > now {
+                    sessions.push(SessionEntry::OAuth {
+                        id: token.id,
+                        client_id: token.client_id,
+                        is_refresh: token.is_refresh,
+                        issued: token.issued,
+                        expires: token.expires,
+                    });
+                }
+            }
+        }
+
+        // Connections currently open on this node
+        for entry in self.live_sessions.iter() {
+            if entry.value().account_id == account_id {
+                sessions.push(SessionEntry::Live {
+                    id: *entry.key(),
+                    protocol: entry.value().protocol,
+                    remote_ip: entry.value().remote_ip,
+                    created: entry.value().created,
+                });
+            }
+        }
+
+        // Cached HTTP credentials not backed by an OAuth token
+        let instant = Instant::now();
+        for entry in self.sessions.iter() {
+            if *entry.value().item() == account_id && entry.value().valid_until() > instant {
+                let id = token_id(entry.key());
+                if !sessions
+                    .iter()
+                    .any(|session| matches!(session, SessionEntry::OAuth { id: token_id, .. } if *token_id == id))
+                {
+                    sessions.push(SessionEntry::Cached {
+                        id,
+                        expires_in: entry.value().valid_until() - instant,
+                    });
+                }
+            }
+        }
+
+        Ok(sessions)
+    }
+
+    pub async fn revoke_session(&self, account_id: u32, id: u64) -> Result<bool, MethodError> {
+        // Revoke OAuth token
+        let mut found = self
+            .update_account_tokens(account_id, |tokens| {
+                if let Some(pos) = tokens.issued.iter().position(|token| token.id == id) {
+                    let token = tokens.issued.swap_remove(pos);
+                    tokens.revoked.push(RevokedToken {
+                        id,
+                        expires: token.expires,
+                    });
+                    true
+                } else {
+                    false
+                }
+            })
+            .await?;
+
+        // Terminate live connection
+        if let Some((_, session)) = self
+            .live_sessions
+            .remove_if(&id, |_, session| session.account_id == account_id)
+        {
+            let _ = session.revoke_tx.send(true);
+            found = true;
+        }
+
+        // Remove cached credentials
+        self.sessions.retain(|key, session| {
+            if *session.item() == account_id && token_id(key) == id {
+                found = true;
+                false
+            } else {
+                true
+            }
+        });
+
+        Ok(found)
+    }
+
+    pub async fn revoke_all_sessions(&self, account_id: u32) -> Result<(), MethodError> {
+        self.update_account_tokens(account_id, |tokens| {
+            for token in tokens.issued.drain(..) {
+                tokens.revoked.push(RevokedToken {
+                    id: token.id,
+                    expires: token.expires,
+                });
+            }
+            true
+        })
+        .await?;
+
+        self.live_sessions.retain(|_, session| {
+            if session.account_id == account_id {
+                let _ = session.revoke_tx.send(true);
+                false
+            } else {
+                true
+            }
+        });
+        self.sessions
+            .retain(|_, session| *session.item() != account_id);
+
+        Ok(())
+    }
+}
+
+impl SessionGuard {
+    pub fn revoke_rx(&self) -> watch::Receiver<bool> {
+        self.revoke_rx.clone()
+    }
+
+    pub fn record_read(&self, bytes: usize) {
+        self.stats
+            .bytes_in
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        self.stats.last_activity.store(now(), Ordering::Relaxed);
+        metrics::increment(self.protocol.metric_bytes_in(), bytes as u64);
+    }
+
+    pub fn record_write(&self, bytes: usize) {
+        self.stats
+            .bytes_out
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        metrics::increment(self.protocol.metric_bytes_out(), bytes as u64);
+    }
+
+    /// Updates the memory held by this session and, when the configured
+    /// limit is exceeded, disconnects the oldest sessions until usage drops
+    /// back below it.
+    pub fn set_memory(&self, bytes: usize) {
+        if self.stats.evicted.load(Ordering::Relaxed) {
+            return;
+        }
+        let bytes = bytes as u64;
+        let prev = self.stats.memory.swap(bytes, Ordering::Relaxed);
+        if bytes <= prev {
+            self.memory_used.fetch_sub(prev - bytes, Ordering::Relaxed);
+            metrics::decrement(METRIC_SESSION_MEMORY, prev - bytes);
+            return;
+        }
+        let used = self.memory_used.fetch_add(bytes - prev, Ordering::Relaxed) + bytes - prev;
+        metrics::increment(METRIC_SESSION_MEMORY, bytes - prev);
+
+        if let Some(max) = self.memory_max.filter(|max| used > *max) {
+            let mut sessions = self
+                .sessions
+                .iter()
+                .filter(|entry| entry.value().stats.memory.load(Ordering::Relaxed) > 0)
+                .map(|entry| (entry.value().started, *entry.key()))
+                .collect::<Vec<_>>();
+            sessions.sort_unstable();
+
+            let mut excess = used - max;
+            for (_, id) in sessions {
+                if let Some((_, session)) = self.sessions.remove(&id) {
+                    session.stats.evicted.store(true, Ordering::Relaxed);
+                    let freed = session.stats.memory.swap(0, Ordering::Relaxed);
+                    self.memory_used.fetch_sub(freed, Ordering::Relaxed);
+                    metrics::decrement(METRIC_SESSION_MEMORY, freed);
+                    metrics::increment(METRIC_SESSION_EVICTED, 1);
+                    let _ = session.revoke_tx.send(true);
+
+                    tracing::info!(
+                        context = "session",
+                        event = "evict",
+                        account_id = session.account_id,
+                        protocol = session.protocol.as_str(),
+                        memory = freed,
+                        "Disconnecting session due to memory pressure"
+                    );
+
+                    if freed >= excess {
+                        break;
+                    }
+                    excess -= freed;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.sessions.remove(&self.id);
+        let freed = self.stats.memory.swap(0, Ordering::Relaxed);
+        self.memory_used.fetch_sub(freed, Ordering::Relaxed);
+        metrics::decrement(METRIC_SESSION_MEMORY, freed);
+    }
+}
+
+impl SessionProtocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionProtocol::Imap => "imap",
+            SessionProtocol::WebSocket => "websocket",
+        }
+    }
+
+    fn metric_bytes_in(&self) -> &'static str {
+        match self {
+            SessionProtocol::Imap => METRIC_IMAP_BYTES_IN,
+            SessionProtocol::WebSocket => METRIC_WEBSOCKET_BYTES_IN,
+        }
+    }
+
+    fn metric_bytes_out(&self) -> &'static str {
+        match self {
+            SessionProtocol::Imap => METRIC_IMAP_BYTES_OUT,
+            SessionProtocol::WebSocket => METRIC_WEBSOCKET_BYTES_OUT,
+        }
+    }
+}
+
+/// Resolves once the session has been revoked, never resolves otherwise.
+pub async fn session_revoked(revoke_rx: &mut Option<watch::Receiver<bool>>) {
+    if let Some(revoke_rx) = revoke_rx {
+        while !*revoke_rx.borrow() {
+            if revoke_rx.changed().await.is_err() {
+                break;
+            }
+        }
+        if *revoke_rx.borrow() {
+            return;
+        }
+    }
+    std::future::pending::<()>().await
+}
+
+/// Resolves once the session deadline has passed, never resolves if there is none.
+pub async fn session_expired(expires: Option<Instant>) {
+    if let Some(expires) = expires {
+        tokio::time::sleep_until(expires.into()).await;
+    } else {
+        std::future::pending::<()>().await
+    }
+}
+
+pub fn token_id(token: &str) -> u64 {
+    let hash = blake3::hash(token.as_bytes());
+    u64::from_be_bytes(hash.as_bytes()[..8].try_into().unwrap())
+}
+
+impl SessionEntry {
+    pub fn id(&self) -> u64 {
+        match self {
+            SessionEntry::OAuth { id, .. }
+            | SessionEntry::Live { id, .. }
+            | SessionEntry::Cached { id, .. } => *id,
+        }
+    }
+}
@@ -0,0 +1,440 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use directory::{tenant::TenantDirectory, Directory, DirectoryConfig, Principal};
+use hyper::header;
+use jmap_proto::error::request::RequestError;
+use store::{
+    ahash::{AHashMap, AHashSet},
+    parking_lot::Mutex,
+};
+use utils::{codec::html::html_escape, config::Rate, listener::limiter::RateLimiter};
+
+use crate::{api::HttpRequest, JMAP};
+
+use super::{rate_limit::RemoteAddress, AccessToken, TlsServerName};
+
+#[derive(Debug, Default)]
+pub struct Tenant {
+    pub id: String,
+    pub domains: Vec<String>,
+    pub directory: Option<String>,
+    pub quota: Option<u32>,
+    pub rate_authenticated: Option<Rate>,
+    pub rate_authenticate_req: Option<Rate>,
+    pub branding: Option<Branding>,
+    pub admins: AHashSet<String>,
+    pub helpdesk: AHashSet<String>,
+    pub auditors: AHashSet<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AdminRole {
+    SuperAdmin,
+    DomainAdmin,
+    Helpdesk,
+    Auditor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AdminPermission {
+    AccountManage,
+    CatchAllClaim,
+    QueueView,
+    QueueRetry,
+    QueueCancel,
+    UsageView,
+    PolicyManage,
+    ServerManage,
+}
+
+#[derive(Debug, Default)]
+pub struct Branding {
+    pub name: String,
+    pub logo: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Tenants {
+    domains: AHashMap<String, Arc<Tenant>>,
+}
+
+impl Tenants {
+    pub fn parse(settings: &utils::config::Config) -> Result<Self, String> {
+        let mut tenants = Tenants::default();
+
+        for id in settings.sub_keys("tenant") {
+            let domains = settings
+                .values(("tenant", id, "domains"))
+                .map(|(_, domain)| domain.trim().trim_end_matches('.').to_lowercase())
+                .collect::<Vec<_>>();
+            if domains.is_empty() {
+                return Err(format!("No domains configured for tenant {id:?}."));
+            }
+
+            let tenant = Arc::new(Tenant {
+                id: id.to_string(),
+                directory: settings
+                    .value(("tenant", id, "directory"))
+                    .map(|v| v.to_string()),
+                quota: settings.property(("tenant", id, "quota"))?,
+                rate_authenticated: settings.property(("tenant", id, "rate-limit.account"))?,
+                rate_authenticate_req: settings.property((
+                    "tenant",
+                    id,
+                    "rate-limit.authentication",
+                ))?,
+                branding: settings
+                    .value(("tenant", id, "branding.name"))
+                    .map(|name| Branding {
+                        name: name.to_string(),
+                        logo: settings
+                            .value(("tenant", id, "branding.logo"))
+                            .map(|v| v.to_string()),
+                    }),
+                admins: settings
+                    .values(("tenant", id, "admins"))
+                    .map(|(_, admin)| admin.trim().to_lowercase())
+                    .collect(),
+                helpdesk: settings
+                    .values(("tenant", id, "helpdesk"))
+                    .map(|(_, name)| name.trim().to_lowercase())
+                    .collect(),
+                auditors: settings
+                    .values(("tenant", id, "auditors"))
+                    .map(|(_, name)| name.trim().to_lowercase())
+                    .collect(),
+                domains,
+            });
+
+            for domain in &tenant.domains {
+                if tenants
+                    .domains
+                    .insert(domain.clone(), tenant.clone())
+                    .is_some()
+                {
+                    return Err(format!(
+                        "Domain {domain:?} is assigned to more than one tenant."
+                    ));
+                }
+            }
+        }
+
+        Ok(tenants)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    pub fn by_domain(&self, domain: &str) -> Option<&Arc<Tenant>> {
+        if self.domains.is_empty() {
+            return None;
+        }
+
+        // Subdomains (such as the hostname serving a tenant) belong to the parent's tenant
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        let mut domain = domain.as_str();
+        loop {
+            if let Some(tenant) = self.domains.get(domain) {
+                return Some(tenant);
+            }
+            domain = domain.split_once('.')?.1;
+        }
+    }
+
+    pub fn by_id(&self, id: &str) -> Option<&Arc<Tenant>> {
+        self.domains.values().find(|tenant| tenant.id == id)
+    }
+
+    pub fn by_account(&self, name: &str) -> Option<&Arc<Tenant>> {
+        name.rsplit_once('@')
+            .and_then(|(_, domain)| self.domains.get(domain.to_lowercase().as_str()))
+    }
+
+    pub fn by_role_member(&self, name: &str) -> Option<(&Arc<Tenant>, AdminRole)> {
+        if !self.domains.is_empty() {
+            let name = name.to_lowercase();
+            self.domains
+                .values()
+                .find_map(|tenant| tenant.role_of(&name).map(|role| (tenant, role)))
+        } else {
+            None
+        }
+    }
+
+    pub fn build_directory(
+        &self,
+        default: Arc<dyn Directory>,
+        directory_config: &DirectoryConfig,
+    ) -> Result<Arc<dyn Directory>, String> {
+        let mut directory = TenantDirectory::new(default.clone());
+        for (domain, tenant) in &self.domains {
+            if let Some(id) = &tenant.directory {
+                directory = directory.with_domain(
+                    domain.clone(),
+                    directory_config
+                        .directories
+                        .get(id)
+                        .ok_or_else(|| {
+                            format!(
+                                "Unable to find directory {id:?} for tenant {:?}.",
+                                tenant.id
+                            )
+                        })?
+                        .clone(),
+                );
+            }
+        }
+
+        Ok(if !directory.is_empty() {
+            Arc::new(directory)
+        } else {
+            default
+        })
+    }
+}
+
+impl Tenant {
+    pub fn role_of(&self, name: &str) -> Option<AdminRole> {
+        if self.admins.contains(name) {
+            Some(AdminRole::DomainAdmin)
+        } else if self.helpdesk.contains(name) {
+            Some(AdminRole::Helpdesk)
+        } else if self.auditors.contains(name) {
+            Some(AdminRole::Auditor)
+        } else {
+            None
+        }
+    }
+
+    pub fn owns_account(&self, name: &str) -> bool {
+        name.rsplit_once('@')
+            .map_or(false, |(_, domain)| self.owns_domain(domain))
+    }
+
+    pub fn owns_domain(&self, domain: &str) -> bool {
+        self.domains.iter().any(|d| d.eq_ignore_ascii_case(domain))
+    }
+
+    pub fn brand_html(&self, html: String) -> String {
+        let branding = if let Some(branding) = &self.branding {
+            branding
+        } else {
+            return html;
+        };
+        let mut html = html.replace("Stalwart Mail Server", &html_escape(&branding.name));
+
+        if let Some(logo) = &branding.logo {
+            const ILLUSTRATION: &str = "<div class=\"illustration\">";
+
+            if let Some(start) = html.find(ILLUSTRATION).map(|pos| pos + ILLUSTRATION.len()) {
+                if let Some(end) = html[start..].find("</div>").map(|pos| pos + start) {
+                    html.replace_range(
+                        start..end,
+                        &format!(
+                            "<img src=\"{}\" alt=\"{}\" style=\"max-height: 96px;\">",
+                            html_escape(logo),
+                            html_escape(&branding.name)
+                        ),
+                    );
+                }
+            }
+        }
+
+        html
+    }
+}
+
+impl AdminRole {
+    pub fn has_permission(&self, permission: AdminPermission) -> bool {
+        match self {
+            AdminRole::SuperAdmin => true,
+            AdminRole::DomainAdmin => !matches!(permission, AdminPermission::ServerManage),
+            AdminRole::Helpdesk => matches!(
+                permission,
+                AdminPermission::CatchAllClaim
+                    | AdminPermission::QueueView
+                    | AdminPermission::QueueRetry
+                    | AdminPermission::UsageView
+            ),
+            AdminRole::Auditor => matches!(
+                permission,
+                AdminPermission::QueueView | AdminPermission::UsageView
+            ),
+        }
+    }
+
+    // Tenant scoped roles can only act on accounts of their own domains,
+    // operations that do not target an account are reserved to unscoped roles.
+    pub fn is_allowed(
+        &self,
+        permission: AdminPermission,
+        tenant: Option<&Arc<Tenant>>,
+        account_name: Option<&str>,
+    ) -> bool {
+        self.has_permission(permission)
+            && match (tenant, account_name) {
+                (Some(tenant), Some(account_name)) => tenant.owns_account(account_name),
+                (Some(_), None) => false,
+                (None, _) => true,
+            }
+    }
+
+    pub fn permissions(&self) -> Vec<&'static str> {
+        [
+            (AdminPermission::AccountManage, "account-manage"),
+            (AdminPermission::CatchAllClaim, "catch-all-claim"),
+            (AdminPermission::QueueView, "queue-view"),
+            (AdminPermission::QueueRetry, "queue-retry"),
+            (AdminPermission::QueueCancel, "queue-cancel"),
+            (AdminPermission::UsageView, "usage-view"),
+            (AdminPermission::PolicyManage, "policy-manage"),
+            (AdminPermission::ServerManage, "server-manage"),
+        ]
+        .into_iter()
+        .filter_map(|(permission, name)| self.has_permission(permission).then_some(name))
+        .collect()
+    }
+}
+
+impl JMAP {
+    pub fn tenant_by_request(&self, req: &HttpRequest) -> Option<&Arc<Tenant>> {
+        // On TLS connections the tenant is selected by the SNI name the certificate
+        // was issued for, the Host header is only used on plaintext listeners.
+        if let Some(server_name) = req.extensions().get::<TlsServerName>() {
+            server_name
+                .tenant
+                .as_ref()
+                .and_then(|tenant| self.config.tenants.by_id(tenant))
+                .or_else(|| {
+                    server_name
+                        .name
+                        .as_ref()
+                        .and_then(|name| self.config.tenants.by_domain(name))
+                })
+        } else {
+            req.headers()
+                .get(header::HOST)
+                .and_then(|h| h.to_str().ok())
+                .and_then(|host| {
+                    self.config
+                        .tenants
+                        .by_domain(host.rsplit_once(':').map_or(host, |(host, _)| host))
+                })
+        }
+    }
+
+    pub fn brand_html(&self, req: &HttpRequest, html: String) -> String {
+        if let Some(tenant) = self.tenant_by_request(req) {
+            tenant.brand_html(html)
+        } else {
+            html
+        }
+    }
+
+    pub fn apply_tenant_quota(&self, principal: &mut Principal) {
+        if principal.quota == 0 {
+            if let Some(quota) = self
+                .config
+                .tenants
+                .by_account(&principal.name)
+                .and_then(|tenant| tenant.quota)
+            {
+                principal.quota = quota;
+            }
+        }
+    }
+
+    pub fn admin_role(
+        &self,
+        access_token: &AccessToken,
+    ) -> Option<(AdminRole, Option<&Arc<Tenant>>)> {
+        if access_token.is_super_user() {
+            Some((AdminRole::SuperAdmin, None))
+        } else {
+            self.config
+                .tenants
+                .by_role_member(&access_token.name)
+                .map(|(tenant, role)| (role, Some(tenant)))
+        }
+    }
+
+    pub fn has_admin_permission(
+        &self,
+        access_token: &AccessToken,
+        permission: AdminPermission,
+        account_name: Option<&str>,
+    ) -> bool {
+        match self.admin_role(access_token) {
+            Some((role, tenant)) => role.is_allowed(permission, tenant, account_name),
+            None => false,
+        }
+    }
+
+    pub fn is_tenant_auth_allowed_soft(
+        &self,
+        username: &str,
+        addr: &RemoteAddress,
+    ) -> Result<(), RequestError> {
+        match self.config.tenants.by_account(username).and_then(|tenant| {
+            self.rate_limit_tenant
+                .get(&(tenant.id.clone(), addr.clone()))
+        }) {
+            Some(limiter) if !limiter.lock().is_allowed_soft() => {
+                Err(RequestError::too_many_auth_attempts())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn is_tenant_auth_allowed_hard(
+        &self,
+        username: &str,
+        addr: &RemoteAddress,
+    ) -> Result<(), RequestError> {
+        if let Some((tenant, rate)) = self
+            .config
+            .tenants
+            .by_account(username)
+            .and_then(|tenant| Some((tenant, tenant.rate_authenticate_req.as_ref()?)))
+        {
+            if !self
+                .rate_limit_tenant
+                .entry((tenant.id.clone(), addr.clone()))
+                .or_insert_with(|| {
+                    Arc::new(Mutex::new(RateLimiter::new(rate.requests, rate.period)))
+                })
+                .lock()
+                .is_allowed()
+            {
+                return Err(RequestError::too_many_auth_attempts());
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -40,6 +40,10 @@ impl JMAP {
         request: CopyBlobRequest,
         access_token: &AccessToken,
     ) -> Result<CopyBlobResponse, MethodError> {
+        if request.blob_ids.len() > self.config.set_max_objects {
+            return Err(MethodError::RequestTooLarge);
+        }
+
         let mut response = CopyBlobResponse {
             from_account_id: request.from_account_id,
             account_id: request.account_id,
@@ -23,6 +23,7 @@
 
 use std::ops::Range;
 
+use hyper::header::{self, HeaderMap};
 use jmap_proto::{
     error::method::MethodError,
     types::{
@@ -34,16 +35,140 @@ use mail_parser::{
     decoders::{base64::base64_decode, quoted_printable::quoted_printable_decode},
     Encoding,
 };
-use store::BlobKind;
+use store::{write::now, BlobKind};
 
 use crate::{auth::AccessToken, JMAP};
 
+use super::{BlobDownload, DownloadBody};
+
 impl JMAP {
     pub async fn blob_download(
         &self,
         blob_id: &BlobId,
         access_token: &AccessToken,
     ) -> Result<Option<Vec<u8>>, MethodError> {
+        if !self.can_download_blob(blob_id, access_token).await {
+            return Ok(None);
+        }
+
+        if let Some(section) = &blob_id.section {
+            self.get_blob_section(&blob_id.kind, section).await
+        } else {
+            self.get_blob(&blob_id.kind, 0..u32::MAX).await
+        }
+    }
+
+    /// Downloads a blob honoring the `If-None-Match`, `If-Modified-Since`,
+    /// `Range` and `If-Range` request headers. Blob ids are reused once a
+    /// document id is freed, so the entity tag also includes the version and
+    /// size reported by the blob store.
+    pub async fn blob_download_conditional(
+        &self,
+        blob_id: &BlobId,
+        access_token: &AccessToken,
+        headers: &HeaderMap,
+    ) -> Result<Option<BlobDownload>, MethodError> {
+        if !self.can_download_blob(blob_id, access_token).await {
+            return Ok(None);
+        }
+        let metadata = match self.store.get_blob_metadata(&blob_id.kind).await {
+            Ok(Some(metadata)) => metadata,
+            Ok(None) => return Ok(None),
+            Err(err) => {
+                tracing::error!(event = "error",
+                                context = "blob_store",
+                                blob_id = ?blob_id.kind,
+                                error = ?err,
+                                "Failed to retrieve blob metadata");
+                return Err(MethodError::ServerPartialFail);
+            }
+        };
+        let etag = match &metadata.version {
+            Some(version) => format!("\"{blob_id}-{version}-{:x}\"", metadata.size),
+            None => format!("\"{blob_id}-{:x}\"", metadata.size),
+        };
+        let mut download = BlobDownload {
+            etag,
+            last_modified: metadata.modified,
+            body: DownloadBody::NotModified,
+        };
+
+        // Conditional requests
+        let get_header = |name: header::HeaderName| headers.get(name).and_then(|h| h.to_str().ok());
+        if let Some(if_none_match) = get_header(header::IF_NONE_MATCH) {
+            if etag_matches(if_none_match, &download.etag) {
+                return Ok(Some(download));
+            }
+        } else if let (Some(since), Some(modified)) = (
+            get_header(header::IF_MODIFIED_SINCE).and_then(parse_http_date),
+            metadata.modified,
+        ) {
+            if modified <= since {
+                return Ok(Some(download));
+            }
+        }
+
+        // Range requests, ignored if the representation changed
+        let range = get_header(header::RANGE)
+            .filter(|_| {
+                get_header(header::IF_RANGE).map_or(true, |if_range| if_range == download.etag)
+            })
+            .and_then(ByteRange::parse);
+
+        download.body = match (range, &blob_id.section) {
+            (Some(range), None) => {
+                // Read only the requested bytes from the store
+                match range.resolve(metadata.size) {
+                    Some((start, end)) => {
+                        match self
+                            .get_blob(&blob_id.kind, start as u32..(end + 1) as u32)
+                            .await?
+                        {
+                            Some(bytes) => DownloadBody::Partial {
+                                bytes,
+                                start,
+                                end,
+                                total: metadata.size,
+                            },
+                            None => return Ok(None),
+                        }
+                    }
+                    None => DownloadBody::RangeNotSatisfiable {
+                        total: metadata.size,
+                    },
+                }
+            }
+            (Some(range), Some(section)) => {
+                // Sections are decoded, slice the decoded contents
+                let bytes = match self.get_blob_section(&blob_id.kind, section).await? {
+                    Some(bytes) => bytes,
+                    None => return Ok(None),
+                };
+                let total = bytes.len() as u64;
+                match range.resolve(total) {
+                    Some((start, end)) => DownloadBody::Partial {
+                        bytes: bytes[start as usize..=end as usize].to_vec(),
+                        start,
+                        end,
+                        total,
+                    },
+                    None => DownloadBody::RangeNotSatisfiable { total },
+                }
+            }
+            (None, Some(section)) => match self.get_blob_section(&blob_id.kind, section).await? {
+                Some(bytes) => DownloadBody::Full(bytes),
+                None => return Ok(None),
+            },
+            (None, None) => match self.get_blob(&blob_id.kind, 0..u32::MAX).await? {
+                Some(bytes) => DownloadBody::Full(bytes),
+                None => return Ok(None),
+            },
+        };
+
+        Ok(Some(download))
+    }
+
+    async fn can_download_blob(&self, blob_id: &BlobId, access_token: &AccessToken) -> bool {
         if !access_token.is_member(blob_id.account_id()) {
             match &blob_id.kind {
                 BlobKind::Linked {
@@ -62,7 +187,7 @@ impl JMAP {
                         .await
                     {
                         Ok(has_access) if has_access => (),
-                        _ => return Ok(None),
+                        _ => return false,
                     }
                 }
                 BlobKind::LinkedMaildir {
@@ -74,18 +199,14 @@ impl JMAP {
                         .await
                     {
                         Ok(shared_messages) if shared_messages.contains(*document_id) => (),
-                        _ => return Ok(None),
+                        _ => return false,
                     }
                 }
-                BlobKind::Temporary { .. } => return Ok(None),
+                BlobKind::Temporary { .. } => return false,
             }
         }
 
-        if let Some(section) = &blob_id.section {
-            self.get_blob_section(&blob_id.kind, section).await
-        } else {
-            self.get_blob(&blob_id.kind, 0..u32::MAX).await
-        }
+        true
     }
 
     pub async fn get_blob_section(
@@ -112,6 +233,10 @@ impl JMAP {
         kind: &BlobKind,
         range: Range<u32>,
     ) -> Result<Option<Vec<u8>>, MethodError> {
+        if self.is_expired_blob(kind) {
+            return Ok(None);
+        }
+
         match self.store.get_blob(kind, range).await {
             Ok(blob) => Ok(blob),
             Err(err) => {
@@ -125,6 +250,11 @@ impl JMAP {
         }
     }
 
+    pub fn is_expired_blob(&self, kind: &BlobKind) -> bool {
+        matches!(kind, BlobKind::Temporary { timestamp, .. }
+            if now().saturating_sub(*timestamp) > self.config.upload_tmp_ttl)
+    }
+
     pub async fn has_access_blob(
         &self,
         blob_id: &BlobId,
@@ -158,7 +288,72 @@ impl JMAP {
                         .await?
                         .contains(*document_id)
             }
-            BlobKind::Temporary { account_id, .. } => access_token.is_member(*account_id),
+            BlobKind::Temporary { account_id, .. } => {
+                access_token.is_member(*account_id) && !self.is_expired_blob(&blob_id.kind)
+            }
         })
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ByteRange {
+    From { start: u64, end: Option<u64> },
+    Suffix(u64),
+}
+
+impl ByteRange {
+    // Only single ranges are supported, multipart ranges are served in full
+    fn parse(value: &str) -> Option<Self> {
+        let (start, end) = value.trim().strip_prefix("bytes=")?.split_once('-')?;
+        let (start, end) = (start.trim(), end.trim());
+        if end.contains(',') {
+            None
+        } else if start.is_empty() {
+            end.parse().ok().map(ByteRange::Suffix)
+        } else {
+            let start = start.parse().ok()?;
+            let end = if !end.is_empty() {
+                Some(end.parse().ok()?)
+            } else {
+                None
+            };
+            if end.map_or(true, |end| end >= start) {
+                Some(ByteRange::From { start, end })
+            } else {
+                None
+            }
+        }
+    }
+
+    // Returns the inclusive byte range, or None if it cannot be satisfied
+    fn resolve(&self, total: u64) -> Option<(u64, u64)> {
+        match *self {
+            ByteRange::From { start, end } if start < total => Some((
+                start,
+                end.map_or(total - 1, |end| std::cmp::min(end, total - 1)),
+            )),
+            ByteRange::Suffix(len) if len > 0 && total > 0 => {
+                Some((total.saturating_sub(len), total - 1))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn etag_matches(header: &str, etag: &str) -> bool {
+    header.split(',').any(|tag| {
+        let tag = tag.trim();
+        tag == "*" || tag.trim_start_matches("W/") == etag
+    })
+}
+
+pub(crate) fn http_date(timestamp: u64) -> Option<String> {
+    chrono::NaiveDateTime::from_timestamp_opt(timestamp as i64, 0)
+        .map(|date| date.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+fn parse_http_date(value: &str) -> Option<u64> {
+    chrono::DateTime::parse_from_rfc2822(value.trim())
+        .ok()
+        .and_then(|date| u64::try_from(date.timestamp()).ok())
+}
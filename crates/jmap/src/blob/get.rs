@@ -94,33 +94,7 @@ impl JMAP {
                     let value: Value = match &property {
                         Property::Id => Value::BlobId(blob_id.clone()),
                         Property::Size => bytes.len().into(),
-                        Property::Digest(digest) => match digest {
-                            DigestProperty::Sha => {
-                                let mut hasher = Sha1::new();
-                                hasher.update(bytes_range);
-                                String::from_utf8(
-                                    base64_encode(&hasher.finalize()[..]).unwrap_or_default(),
-                                )
-                                .unwrap()
-                            }
-                            DigestProperty::Sha256 => {
-                                let mut hasher = Sha256::new();
-                                hasher.update(bytes_range);
-                                String::from_utf8(
-                                    base64_encode(&hasher.finalize()[..]).unwrap_or_default(),
-                                )
-                                .unwrap()
-                            }
-                            DigestProperty::Sha512 => {
-                                let mut hasher = Sha512::new();
-                                hasher.update(bytes_range);
-                                String::from_utf8(
-                                    base64_encode(&hasher.finalize()[..]).unwrap_or_default(),
-                                )
-                                .unwrap()
-                            }
-                        }
-                        .into(),
+                        Property::Digest(digest) => blob_digest(digest, bytes_range).into(),
                         Property::Data(data) => match data {
                             DataProperty::AsText => match std::str::from_utf8(bytes_range) {
                                 Ok(text) => text.to_string().into(),
@@ -169,6 +143,10 @@ impl JMAP {
         &self,
         request: BlobLookupRequest,
     ) -> Result<BlobLookupResponse, MethodError> {
+        if request.ids.len() > self.config.get_max_objects {
+            return Err(MethodError::RequestTooLarge);
+        }
+
         let mut include_email = false;
         let mut include_mailbox = false;
         let mut include_thread = false;
@@ -286,3 +264,24 @@ impl JMAP {
         Ok(response)
     }
 }
+
+pub(crate) fn blob_digest(digest: &DigestProperty, bytes: &[u8]) -> String {
+    let hash = match digest {
+        DigestProperty::Sha => {
+            let mut hasher = Sha1::new();
+            hasher.update(bytes);
+            hasher.finalize().to_vec()
+        }
+        DigestProperty::Sha256 => {
+            let mut hasher = Sha256::new();
+            hasher.update(bytes);
+            hasher.finalize().to_vec()
+        }
+        DigestProperty::Sha512 => {
+            let mut hasher = Sha512::new();
+            hasher.update(bytes);
+            hasher.finalize().to_vec()
+        }
+    };
+    String::from_utf8(base64_encode(&hash).unwrap_or_default()).unwrap()
+}
@@ -42,5 +42,25 @@ pub struct UploadResponse {
 pub struct DownloadResponse {
     pub filename: String,
     pub content_type: String,
-    pub blob: Vec<u8>,
+    pub blob: BlobDownload,
+}
+
+pub struct BlobDownload {
+    pub etag: String,
+    pub last_modified: Option<u64>,
+    pub body: DownloadBody,
+}
+
+pub enum DownloadBody {
+    Full(Vec<u8>),
+    Partial {
+        bytes: Vec<u8>,
+        start: u64,
+        end: u64,
+        total: u64,
+    },
+    NotModified,
+    RangeNotSatisfiable {
+        total: u64,
+    },
 }
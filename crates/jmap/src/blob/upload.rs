@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use jmap_proto::{
     error::{method::MethodError, request::RequestError, set::SetError},
@@ -29,13 +29,14 @@ use jmap_proto::{
         BlobUploadRequest, BlobUploadResponse, BlobUploadResponseObject, DataSourceObject,
     },
     request::reference::MaybeReference,
-    types::{blob::BlobId, id::Id},
+    types::{blob::BlobId, id::Id, property::Property},
 };
 use store::BlobKind;
+use utils::map::ttl_dashmap::TtlMap;
 
 use crate::{auth::AccessToken, JMAP};
 
-use super::UploadResponse;
+use super::{get::blob_digest, UploadResponse};
 
 #[cfg(feature = "test_mode")]
 pub static DISABLE_UPLOAD_QUOTA: std::sync::atomic::AtomicBool =
@@ -129,7 +130,11 @@ impl JMAP {
                 };
 
                 if bytes.len() + data.len() < self.config.upload_max_size {
-                    data.extend(bytes);
+                    if data.is_empty() {
+                        data = bytes;
+                    } else {
+                        data.extend(bytes);
+                    }
                 } else {
                     response.not_created.append(
                         create_id,
@@ -151,6 +156,19 @@ impl JMAP {
                 continue 'outer;
             }
 
+            // Validate the expected digests
+            for (digest, expected) in &upload_object.digest {
+                if blob_digest(digest, &data) != *expected {
+                    response.not_created.append(
+                        create_id,
+                        SetError::invalid_properties()
+                            .with_property(Property::Digest(digest.clone()))
+                            .with_description("Digest does not match the uploaded data."),
+                    );
+                    continue 'outer;
+                }
+            }
+
             // Enforce quota
             let (total_files, total_bytes) = self
                 .store
@@ -185,6 +203,7 @@ impl JMAP {
             let blob_id = BlobId::temporary(account_id);
             match self.store.put_blob(&blob_id.kind, &data).await {
                 Ok(_) => {
+                    self.tmp_blob_usage.remove(&account_id);
                     response.created.insert(
                         create_id,
                         BlobUploadResponseObject {
@@ -265,12 +284,15 @@ impl JMAP {
         let blob_id = BlobId::temporary(account_id.document_id());
 
         match self.store.put_blob(&blob_id.kind, data).await {
-            Ok(_) => Ok(UploadResponse {
-                account_id,
-                blob_id,
-                c_type: content_type.to_string(),
-                size: data.len(),
-            }),
+            Ok(_) => {
+                self.tmp_blob_usage.remove(&account_id.document_id());
+                Ok(UploadResponse {
+                    account_id,
+                    blob_id,
+                    c_type: content_type.to_string(),
+                    size: data.len(),
+                })
+            }
             Err(err) => {
                 tracing::error!(event = "error",
                     context = "blob_store",
@@ -284,6 +306,36 @@ impl JMAP {
         }
     }
 
+    // Usage is only cached for reporting it in the session object, quotas
+    // are always enforced against the blob store.
+    pub async fn get_cached_tmp_blob_usage(
+        &self,
+        account_id: u32,
+    ) -> Result<(usize, usize), RequestError> {
+        if let Some(usage) = self.tmp_blob_usage.get_with_ttl(&account_id) {
+            return Ok(usage);
+        }
+
+        let usage = self
+            .store
+            .get_tmp_blob_usage(account_id, self.config.upload_tmp_ttl)
+            .await
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "blob_store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to obtain blob quota");
+                RequestError::internal_server_error()
+            })?;
+
+        Ok(self.tmp_blob_usage.insert_with_ttl(
+            account_id,
+            usage,
+            Instant::now() + self.config.session_cache_ttl,
+        ))
+    }
+
     pub async fn put_blob(&self, kind: &BlobKind, data: &[u8]) -> Result<(), MethodError> {
         self.store.put_blob(kind, data).await.map_err(|err| {
             tracing::error!(
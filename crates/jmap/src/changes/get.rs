@@ -67,6 +67,15 @@ impl JMAP {
 
                 return Err(MethodError::CannotCalculateChanges);
             }
+            RequestArguments::Principal => {
+                if !self.config.principal_allow_lookups && !access_token.is_super_user() {
+                    return Err(MethodError::Forbidden(
+                        "Principal lookups are disabled".to_string(),
+                    ));
+                }
+
+                Collection::Principal
+            }
         };
 
         let max_changes = if self.config.changes_max_results > 0
@@ -86,7 +95,12 @@ impl JMAP {
             destroyed: vec![],
             updated_properties: None,
         };
-        let account_id = request.account_id.document_id();
+        // Principals are shared by all accounts
+        let account_id = if collection == Collection::Principal {
+            u32::MAX
+        } else {
+            request.account_id.document_id()
+        };
 
         let (items_sent, mut changelog) = match &request.since_state {
             State::Initial => {
@@ -52,6 +52,7 @@ impl JMAP {
                             changes::RequestArguments::EmailSubmission
                         }
                         query::RequestArguments::Quota => changes::RequestArguments::Quota,
+                        query::RequestArguments::Principal => changes::RequestArguments::Principal,
                         _ => return Err(MethodError::UnknownMethod("Unknown method".to_string())),
                     },
                 },
@@ -99,6 +100,7 @@ impl JMAP {
                     self.email_submission_query(query).await?
                 }
                 query::RequestArguments::Quota => self.quota_query(query, access_token).await?,
+                query::RequestArguments::Principal => self.principal_query(query).await?,
                 _ => unreachable!(),
             };
 
@@ -0,0 +1,172 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{error::method::MethodError, types::collection::Collection};
+use mail_parser::{Address, HeaderName, HeaderValue, MessageParser};
+use store::{
+    write::{now, BatchBuilder, Operation, ValueClass},
+    CustomValueKey, Serialize,
+};
+
+use crate::{auth::authenticate::AccountKey, JMAP};
+
+pub const FIRST_CONTACT_HEADER: &str = "X-First-Contact";
+
+impl JMAP {
+    // Returns the sender address when it has never written to the account before
+    pub async fn first_contact_sender(
+        &self,
+        account_id: u32,
+        raw_message: &[u8],
+        envelope_from: &str,
+    ) -> Result<Option<String>, MethodError> {
+        let sender = if let Some(sender) = message_sender(raw_message) {
+            sender
+        } else if !envelope_from.is_empty() {
+            envelope_from.to_lowercase()
+        } else {
+            // Null reverse-path
+            return Ok(None);
+        };
+
+        if self
+            .store
+            .get_value::<u64>(CustomValueKey {
+                value: AccountKey::known_sender(account_id, &sender),
+            })
+            .await
+            .map_err(|err| {
+                tracing::error!(
+                    event = "error",
+                    context = "first_contact",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve sender history.");
+                MethodError::ServerPartialFail
+            })?
+            .is_some()
+            || self.is_collected_address(account_id, &sender).await?
+        {
+            Ok(None)
+        } else {
+            Ok(Some(sender))
+        }
+    }
+
+    pub async fn add_known_sender(&self, account_id: u32, sender: &str) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::known_sender(account_id, sender),
+                },
+                set: now().serialize().into(),
+            });
+        self.write_batch(batch).await
+    }
+
+    pub async fn known_senders(&self, account_id: u32) -> store::Result<Vec<String>> {
+        self.store
+            .iterate(
+                Vec::new(),
+                CustomValueKey {
+                    value: AccountKey::known_sender(account_id, ""),
+                },
+                CustomValueKey {
+                    value: AccountKey::known_sender(account_id + 1, ""),
+                },
+                false,
+                true,
+                move |senders, key, _| {
+                    // Skip the u32::MAX account prefix, the key type and the account id
+                    let offset = std::mem::size_of::<u32>() * 2 + 1;
+                    if let Some(sender) = key
+                        .get(offset..)
+                        .and_then(|sender| std::str::from_utf8(sender).ok())
+                    {
+                        senders.push(sender.to_string());
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+    }
+}
+
+pub fn with_first_contact_header(raw_message: &[u8], is_first_contact: bool) -> Vec<u8> {
+    let mut message = Vec::with_capacity(raw_message.len() + FIRST_CONTACT_HEADER.len() + 7);
+    message.extend_from_slice(FIRST_CONTACT_HEADER.as_bytes());
+    message.extend_from_slice(if is_first_contact {
+        b": yes\r\n"
+    } else {
+        b": no\r\n"
+    });
+
+    // Remove any existing instances to prevent spoofing
+    let mut pos = 0;
+    let mut is_skipping = false;
+    while pos < raw_message.len() {
+        let line_end = raw_message[pos..]
+            .iter()
+            .position(|&ch| ch == b'\n')
+            .map_or(raw_message.len(), |end| pos + end + 1);
+        let line = &raw_message[pos..line_end];
+        if line == b"\r\n" || line == b"\n" {
+            break;
+        } else if !matches!(line.first(), Some(b' ' | b'\t')) {
+            is_skipping = line
+                .iter()
+                .position(|&ch| ch == b':')
+                .and_then(|colon| std::str::from_utf8(&line[..colon]).ok())
+                .map_or(false, |name| {
+                    name.trim_end().eq_ignore_ascii_case(FIRST_CONTACT_HEADER)
+                });
+        }
+        if !is_skipping {
+            message.extend_from_slice(line);
+        }
+        pos = line_end;
+    }
+    message.extend_from_slice(&raw_message[pos..]);
+
+    message
+}
+
+pub(crate) fn message_sender(raw_message: &[u8]) -> Option<String> {
+    MessageParser::new()
+        .parse(raw_message)?
+        .parts
+        .into_iter()
+        .next()?
+        .headers
+        .into_iter()
+        .find_map(|header| match (header.name, header.value) {
+            (HeaderName::From, HeaderValue::Address(Address::List(addrs))) => addrs
+                .into_iter()
+                .find_map(|addr| addr.address)
+                .map(|address| address.to_lowercase()),
+            _ => None,
+        })
+}
@@ -0,0 +1,89 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    method::collected_address::{
+        CollectedAddress, CollectedAddressGetRequest, CollectedAddressGetResponse,
+    },
+    types::date::UTCDate,
+};
+
+use crate::JMAP;
+
+impl JMAP {
+    pub async fn collected_address_get(
+        &self,
+        request: CollectedAddressGetRequest,
+    ) -> Result<CollectedAddressGetResponse, MethodError> {
+        let account_id = request.account_id.document_id();
+        let limit = request
+            .limit
+            .unwrap_or(self.config.get_max_objects)
+            .min(self.config.get_max_objects);
+        let text = request
+            .text
+            .map(|text| text.trim().to_lowercase())
+            .filter(|text| !text.is_empty());
+
+        let mut addresses = self.collected_addresses(account_id).await.map_err(|err| {
+            tracing::error!(
+                    event = "error",
+                    context = "collected_address",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to obtain collected addresses.");
+            MethodError::ServerPartialFail
+        })?;
+        if let Some(text) = &text {
+            addresses.retain(|(address, entry)| {
+                address.contains(text.as_str())
+                    || entry
+                        .name
+                        .as_ref()
+                        .map_or(false, |name| name.to_lowercase().contains(text.as_str()))
+            });
+        }
+
+        // Most frequently used addresses first
+        addresses.sort_unstable_by(|(_, a), (_, b)| {
+            b.times_used
+                .cmp(&a.times_used)
+                .then_with(|| b.last_used.cmp(&a.last_used))
+        });
+
+        Ok(CollectedAddressGetResponse {
+            account_id: request.account_id,
+            list: addresses
+                .into_iter()
+                .take(limit)
+                .map(|(email, entry)| CollectedAddress {
+                    email,
+                    name: entry.name,
+                    times_used: entry.times_used,
+                    last_used_at: UTCDate::from_timestamp(entry.last_used as i64),
+                })
+                .collect(),
+        })
+    }
+}
@@ -0,0 +1,225 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{error::method::MethodError, types::collection::Collection};
+use mail_parser::{Address, HeaderName, HeaderValue, MessageParser};
+use store::{
+    ahash::{AHashMap, AHashSet},
+    write::{assert::HashedValue, now, BatchBuilder, Operation, ValueClass},
+    CustomValueKey, Deserialize, Serialize,
+};
+
+use crate::{auth::authenticate::AccountKey, Bincode, JMAP};
+
+pub mod first_contact;
+pub mod get;
+
+// Name of the Sieve "extlists" list that matches auto-collected addresses
+pub const KNOWN_SENDERS_LIST: &str = "known-senders";
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AddressEntry {
+    pub name: Option<String>,
+    pub times_used: u64,
+    pub last_used: u64,
+}
+
+impl JMAP {
+    pub async fn collect_addresses(
+        &self,
+        account_id: u32,
+        addresses: impl IntoIterator<Item = (String, Option<String>)>,
+    ) -> Result<(), MethodError> {
+        if !self.config.auto_collect {
+            return Ok(());
+        }
+
+        let mut seen = AHashSet::new();
+        for (address, name) in addresses {
+            let address = address.trim().to_lowercase();
+            if !address.is_empty() && address.contains('@') && seen.insert(address.clone()) {
+                self.collect_address(
+                    account_id,
+                    &address,
+                    name.as_deref()
+                        .map(|name| name.trim())
+                        .filter(|name| !name.is_empty()),
+                )
+                .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn collect_address(
+        &self,
+        account_id: u32,
+        address: &str,
+        name: Option<&str>,
+    ) -> Result<(), MethodError> {
+        let key = AccountKey::collected_address(account_id, address);
+        let mut try_count = 0;
+
+        loop {
+            let current = self
+                .store
+                .get_value::<HashedValue<Bincode<AddressEntry>>>(CustomValueKey {
+                    value: key.clone(),
+                })
+                .await
+                .map_err(|err| {
+                    tracing::error!(
+                        event = "error",
+                        context = "collected_address",
+                        account_id = account_id,
+                        error = ?err,
+                        "Failed to retrieve collected address.");
+                    MethodError::ServerPartialFail
+                })?;
+            let mut entry = current
+                .as_ref()
+                .map(|entry| entry.inner.inner.clone())
+                .unwrap_or_default();
+            entry.times_used += 1;
+            entry.last_used = now();
+            if let Some(name) = name {
+                entry.name = name.to_string().into();
+            }
+
+            // Increment the counter only if no other session updated it in the meantime
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal);
+            if let Some(current) = &current {
+                batch.assert_value(ValueClass::Custom { bytes: key.clone() }, current);
+            } else {
+                batch.assert_value(ValueClass::Custom { bytes: key.clone() }, ());
+            }
+            batch.op(Operation::Value {
+                class: ValueClass::Custom { bytes: key.clone() },
+                set: Bincode::new(entry).serialize().into(),
+            });
+
+            match self.store.write(batch.build()).await {
+                Ok(_) => return Ok(()),
+                Err(store::Error::AssertValueFailed) if try_count < 3 => {
+                    try_count += 1;
+                }
+                Err(err) => {
+                    tracing::error!(
+                        event = "error",
+                        context = "collected_address",
+                        account_id = account_id,
+                        error = ?err,
+                        "Failed to write collected address.");
+                    return Err(MethodError::ServerPartialFail);
+                }
+            }
+        }
+    }
+
+    pub async fn is_collected_address(
+        &self,
+        account_id: u32,
+        address: &str,
+    ) -> Result<bool, MethodError> {
+        self.store
+            .get_value::<Bincode<AddressEntry>>(CustomValueKey {
+                value: AccountKey::collected_address(account_id, &address.to_lowercase()),
+            })
+            .await
+            .map(|entry| entry.is_some())
+            .map_err(|err| {
+                tracing::error!(
+                    event = "error",
+                    context = "collected_address",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve collected address.");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn collected_addresses(
+        &self,
+        account_id: u32,
+    ) -> store::Result<Vec<(String, AddressEntry)>> {
+        self.store
+            .iterate(
+                Vec::new(),
+                CustomValueKey {
+                    value: AccountKey::collected_address(account_id, ""),
+                },
+                CustomValueKey {
+                    value: AccountKey::collected_address(account_id + 1, ""),
+                },
+                false,
+                true,
+                move |addresses, key, value| {
+                    // Skip the u32::MAX account prefix, the key type and the account id
+                    let offset = std::mem::size_of::<u32>() * 2 + 1;
+                    if let Some(address) = key
+                        .get(offset..)
+                        .and_then(|address| std::str::from_utf8(address).ok())
+                    {
+                        addresses.push((
+                            address.to_string(),
+                            Bincode::<AddressEntry>::deserialize(value)?.inner,
+                        ));
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+    }
+}
+
+pub fn recipient_names(raw_message: &[u8]) -> AHashMap<String, String> {
+    let mut names = AHashMap::new();
+    if let Some(part) = MessageParser::new()
+        .parse(raw_message)
+        .and_then(|message| message.parts.into_iter().next())
+    {
+        for header in part.headers {
+            if let (HeaderName::To | HeaderName::Cc | HeaderName::Bcc, HeaderValue::Address(addr)) =
+                (header.name, header.value)
+            {
+                let addrs = match addr {
+                    Address::List(addrs) => addrs,
+                    Address::Group(groups) => groups
+                        .into_iter()
+                        .flat_map(|group| group.addresses)
+                        .collect(),
+                };
+                for addr in addrs {
+                    if let (Some(name), Some(address)) = (addr.name, addr.address) {
+                        names.insert(address.to_lowercase(), name.into_owned());
+                    }
+                }
+            }
+        }
+    }
+    names
+}
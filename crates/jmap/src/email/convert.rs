@@ -0,0 +1,106 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Instant};
+
+use jmap_proto::types::property::Property;
+use mail_parser::{
+    decoders::html::{html_to_text, text_to_html},
+    PartType,
+};
+use sha2::{Digest, Sha256};
+use utils::map::ttl_dashmap::TtlMap;
+
+use crate::JMAP;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Conversion {
+    HtmlToText = 0,
+    TextToHtml = 1,
+}
+
+impl Conversion {
+    // Returns the conversion needed to list a part under the requested body property,
+    // if the part is not already of the expected type.
+    pub fn for_part(property: &Property, body: &PartType<'_>) -> Option<Self> {
+        match (property, body) {
+            (Property::TextBody, PartType::Html(_)) => Some(Conversion::HtmlToText),
+            (Property::HtmlBody, PartType::Text(_)) => Some(Conversion::TextToHtml),
+            _ => None,
+        }
+    }
+
+    pub fn part_id(&self, part_id: usize) -> String {
+        match self {
+            Conversion::HtmlToText => format!("{part_id}.text"),
+            Conversion::TextToHtml => format!("{part_id}.html"),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Conversion::HtmlToText => "text/plain",
+            Conversion::TextToHtml => "text/html",
+        }
+    }
+}
+
+impl JMAP {
+    pub fn convert_body_part(
+        &self,
+        body: &PartType<'_>,
+        conversion: Conversion,
+    ) -> Option<Arc<String>> {
+        let source = match (body, conversion) {
+            (PartType::Html(html), Conversion::HtmlToText) => html.as_ref(),
+            (PartType::Text(text), Conversion::TextToHtml) => text.as_ref(),
+            _ => return None,
+        };
+
+        // Converted parts are cached by content, so the same newsletter delivered
+        // to many accounts is only converted once.
+        let mut hasher = Sha256::new();
+        hasher.update([conversion as u8]);
+        hasher.update(source.as_bytes());
+        let key: [u8; 32] = hasher.finalize().into();
+        if let Some(converted) = self.converted_parts.get_with_ttl(&key) {
+            return Some(converted);
+        }
+
+        let converted = Arc::new(match conversion {
+            Conversion::HtmlToText => html_to_text(source),
+            Conversion::TextToHtml => text_to_html(source),
+        });
+
+        // Expired entries are purged by the housekeeper, stop caching once full
+        if self.converted_parts.len() >= self.config.email_conversion_cache_size {
+            return Some(converted);
+        }
+
+        Some(self.converted_parts.insert_with_ttl(
+            key,
+            converted,
+            Instant::now() + self.config.email_conversion_cache_ttl,
+        ))
+    }
+}
@@ -61,6 +61,7 @@ use crate::{auth::AccessToken, JMAP};
 use super::{
     index::{EmailIndexBuilder, TrimTextValue, MAX_SORT_FIELD_LENGTH},
     ingest::IngestedEmail,
+    keyword::RegisterKeywords,
 };
 
 impl JMAP {
@@ -77,6 +78,8 @@ impl JMAP {
             return Err(MethodError::InvalidArguments(
                 "From accountId is equal to fromAccountId".to_string(),
             ));
+        } else if request.create.len() > self.config.set_max_objects {
+            return Err(MethodError::RequestTooLarge);
         }
         let old_state = self
             .assert_state(account_id, Collection::Email, &request.if_in_state)
@@ -123,7 +126,7 @@ impl JMAP {
             }
 
             let mut mailboxes = Vec::new();
-            let mut keywords = Vec::new();
+            let mut keywords: Option<Vec<Keyword>> = None;
             let mut received_at = None;
 
             for (property, value) in create.properties {
@@ -161,12 +164,27 @@ impl JMAP {
                         keywords = keywords_
                             .into_iter()
                             .filter_map(|keyword| keyword.try_unwrap_keyword())
-                            .collect();
+                            .collect::<Vec<_>>()
+                            .into();
                     }
 
                     (Property::Keywords, MaybePatchValue::Patch(patch)) => {
                         let mut patch = patch.into_iter();
                         if let Some(keyword) = patch.next().unwrap().try_unwrap_keyword() {
+                            // Patches are applied on top of the original keywords
+                            if keywords.is_none() {
+                                keywords = self
+                                    .get_property::<Vec<Keyword>>(
+                                        from_account_id,
+                                        Collection::Email,
+                                        from_message_id,
+                                        &Property::Keywords,
+                                    )
+                                    .await?
+                                    .unwrap_or_default()
+                                    .into();
+                            }
+                            let keywords = keywords.as_mut().unwrap();
                             if patch.next().unwrap().try_unwrap_bool().unwrap_or_default() {
                                 if !keywords.contains(&keyword) {
                                     keywords.push(keyword);
@@ -238,16 +256,16 @@ impl JMAP {
             {
                 Ok(email) => {
                     response.created.append(id, email.into());
+
+                    // Add to destroy list
+                    if on_success_delete {
+                        destroy_ids.push(id);
+                    }
                 }
                 Err(err) => {
                     response.not_created.append(id, err);
                 }
             }
-
-            // Add to destroy list
-            if on_success_delete {
-                destroy_ids.push(id);
-            }
         }
 
         // Update state
@@ -290,7 +308,7 @@ impl JMAP {
         account_id: u32,
         account_quota: i64,
         mailboxes: Vec<u32>,
-        keywords: Vec<Keyword>,
+        keywords: Option<Vec<Keyword>>,
         received_at: Option<UTCDate>,
     ) -> Result<Result<IngestedEmail, SetError>, MethodError> {
         // Obtain term index and metadata
@@ -326,6 +344,20 @@ impl JMAP {
             return Ok(Err(SetError::over_quota()));
         }
 
+        // Keep the original keywords unless new ones were provided
+        let keywords = if let Some(keywords) = keywords {
+            keywords
+        } else {
+            self.get_property::<Vec<Keyword>>(
+                from_account_id,
+                Collection::Email,
+                from_message_id,
+                &Property::Keywords,
+            )
+            .await?
+            .unwrap_or_default()
+        };
+
         // Set receivedAt
         if let Some(received_at) = received_at {
             metadata.set(Property::ReceivedAt, Value::Date(received_at));
@@ -427,6 +459,7 @@ impl JMAP {
 
         // Build batch
         batch
+            .register_keywords(account_id, &keywords)
             .with_collection(Collection::Email)
             .create_document(message_id)
             .value(Property::ThreadId, thread_id, F_VALUE | F_BITMAP)
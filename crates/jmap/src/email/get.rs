@@ -26,15 +26,20 @@ use jmap_proto::{
     method::get::{GetRequest, GetResponse},
     object::{email::GetArguments, Object},
     types::{
-        acl::Acl, blob::BlobId, collection::Collection, id::Id, keyword::Keyword,
+        acl::Acl, blob::BlobId, collection::Collection, date::UTCDate, id::Id, keyword::Keyword,
         property::Property, value::Value,
     },
 };
-use mail_parser::MessageParser;
+use std::borrow::Cow;
+
+use mail_parser::{MessageParser, PartType};
 
 use crate::{auth::AccessToken, email::headers::HeaderToValue, JMAP};
 
-use super::body::{ToBodyPart, TruncateBody};
+use super::{
+    body::{ToBodyPart, TruncateBody},
+    convert::Conversion,
+};
 
 impl JMAP {
     pub async fn email_get(
@@ -87,6 +92,7 @@ impl JMAP {
         let fetch_html_body_values = request.arguments.fetch_html_body_values.unwrap_or(false);
         let fetch_all_body_values = request.arguments.fetch_all_body_values.unwrap_or(false);
         let max_body_value_bytes = request.arguments.max_body_value_bytes.unwrap_or(0);
+        let convert_body_parts = request.arguments.convert_body_parts.unwrap_or(false);
 
         let account_id = request.account_id.document_id();
         let message_ids = self
@@ -276,6 +282,20 @@ impl JMAP {
                             continue 'outer;
                         }
                     }
+                    Property::SnoozedUntil => {
+                        email.append(
+                            Property::SnoozedUntil,
+                            self.get_property::<u64>(
+                                account_id,
+                                Collection::Email,
+                                id.document_id(),
+                                &Property::SnoozedUntil,
+                            )
+                            .await?
+                            .map(|until| Value::Date(UTCDate::from_timestamp(until as i64)))
+                            .unwrap_or(Value::Null),
+                        );
+                    }
                     Property::Size
                     | Property::ReceivedAt
                     | Property::MessageId
@@ -290,7 +310,8 @@ impl JMAP {
                     | Property::Subject
                     | Property::SentAt
                     | Property::HasAttachment
-                    | Property::Preview => {
+                    | Property::Preview
+                    | Property::Subaddress => {
                         email.append(property.clone(), values.remove(property));
                     }
                     Property::Header(_) => {
@@ -309,6 +330,20 @@ impl JMAP {
                             );
                         }
                     }
+                    Property::BimiIndicator => {
+                        email.append(
+                            Property::BimiIndicator,
+                            self.get_property::<String>(
+                                account_id,
+                                Collection::Email,
+                                id.document_id(),
+                                &Property::BimiIndicator,
+                            )
+                            .await?
+                            .map(Value::Text)
+                            .unwrap_or(Value::Null),
+                        );
+                    }
                     Property::TextBody | Property::HtmlBody | Property::Attachments => {
                         if let Some(message) = &message {
                             let list = match property {
@@ -321,12 +356,49 @@ impl JMAP {
                             email.append(
                                 property.clone(),
                                 list.map(|part_id| {
-                                    message.parts.to_body_part(
+                                    let mut body_part = message.parts.to_body_part(
                                         *part_id,
                                         &body_properties,
                                         &raw_message,
                                         &blob_id,
-                                    )
+                                    );
+
+                                    // Replace HTML parts in textBody with their plain text
+                                    // conversion and vice versa, keeping the source blobId
+                                    if let (true, Some(conversion), Value::Object(body_part)) = (
+                                        convert_body_parts,
+                                        Conversion::for_part(
+                                            property,
+                                            &message.parts[*part_id].body,
+                                        ),
+                                        &mut body_part,
+                                    ) {
+                                        if let Some(converted) = self.convert_body_part(
+                                            &message.parts[*part_id].body,
+                                            conversion,
+                                        ) {
+                                            for (property, value) in [
+                                                (
+                                                    Property::PartId,
+                                                    Value::from(conversion.part_id(*part_id)),
+                                                ),
+                                                (
+                                                    Property::Type,
+                                                    Value::from(conversion.content_type()),
+                                                ),
+                                                (Property::Charset, Value::from("utf-8")),
+                                                (Property::Size, Value::from(converted.len())),
+                                            ] {
+                                                if let Some(current) =
+                                                    body_part.properties.get_mut(&property)
+                                                {
+                                                    *current = value;
+                                                }
+                                            }
+                                        }
+                                    }
+
+                                    body_part
                                 })
                                 .collect::<Vec<_>>(),
                             );
@@ -369,6 +441,60 @@ impl JMAP {
                                     );
                                 }
                             }
+
+                            // Add the values of converted parts
+                            if convert_body_parts {
+                                for (property, part_ids, fetch) in [
+                                    (
+                                        Property::TextBody,
+                                        &message.text_body,
+                                        fetch_all_body_values || fetch_text_body_values,
+                                    ),
+                                    (
+                                        Property::HtmlBody,
+                                        &message.html_body,
+                                        fetch_all_body_values || fetch_html_body_values,
+                                    ),
+                                ] {
+                                    if !fetch {
+                                        continue;
+                                    }
+                                    for &part_id in part_ids {
+                                        let part = &message.parts[part_id];
+                                        if let Some((conversion, converted)) = Conversion::for_part(
+                                            &property, &part.body,
+                                        )
+                                        .and_then(|conversion| {
+                                            self.convert_body_part(&part.body, conversion)
+                                                .map(|converted| (conversion, converted))
+                                        }) {
+                                            let converted = match conversion {
+                                                Conversion::HtmlToText => PartType::Text(
+                                                    Cow::Borrowed(converted.as_str()),
+                                                ),
+                                                Conversion::TextToHtml => PartType::Html(
+                                                    Cow::Borrowed(converted.as_str()),
+                                                ),
+                                            };
+                                            let (is_truncated, value) =
+                                                converted.truncate(max_body_value_bytes);
+                                            body_values.append(
+                                                Property::_T(conversion.part_id(part_id)),
+                                                Object::with_capacity(3)
+                                                    .with_property(
+                                                        Property::IsEncodingProblem,
+                                                        part.is_encoding_problem,
+                                                    )
+                                                    .with_property(
+                                                        Property::IsTruncated,
+                                                        is_truncated,
+                                                    )
+                                                    .with_property(Property::Value, value),
+                                            );
+                                        }
+                                    }
+                                }
+                            }
                             email.append(Property::BodyValues, body_values);
                         }
                     }
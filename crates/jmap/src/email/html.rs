@@ -0,0 +1,486 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::borrow::Cow;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use jmap_proto::{
+    error::method::MethodError,
+    types::{blob::BlobId, collection::Collection, id::Id, property::Property},
+};
+use mail_parser::{MessageParser, PartType};
+use sha2::Sha256;
+use store::ahash::AHashMap;
+
+use crate::{auth::AccessToken, JMAP};
+
+pub const DEFAULT_SCOPE: &str = "message-body";
+
+pub const TRACKING_PARAMETERS: &[&str] = &[
+    "utm_*",
+    "fbclid",
+    "gclid",
+    "dclid",
+    "msclkid",
+    "yclid",
+    "mc_cid",
+    "mc_eid",
+    "_hsenc",
+    "_hsmi",
+    "mkt_tok",
+    "vero_id",
+    "oly_anon_id",
+    "oly_enc_id",
+];
+
+#[derive(Debug, Clone)]
+pub struct ImageProxy {
+    pub base_url: String,
+    pub key: Vec<u8>,
+    pub strip_parameters: Vec<String>,
+}
+
+impl JMAP {
+    // Returns the sanitized contents of an HTML body part, with inline images
+    // referenced by Content-ID pointing to their download URLs.
+    pub async fn email_html_part(
+        &self,
+        access_token: &AccessToken,
+        account_id: u32,
+        document_id: u32,
+        part_id: usize,
+        base_url: &str,
+        scope: &str,
+    ) -> Result<Option<String>, MethodError> {
+        let blob_id = BlobId::maildir(account_id, document_id);
+        if !self.has_access_blob(&blob_id, access_token).await? {
+            return Ok(None);
+        }
+        let raw_message =
+            if let Some(raw_message) = self.get_blob(&blob_id.kind, 0..u32::MAX).await? {
+                raw_message
+            } else {
+                return Ok(None);
+            };
+        let message = if let Some(message) = MessageParser::new().parse(&raw_message) {
+            message
+        } else {
+            return Ok(None);
+        };
+        let html = if let Some(PartType::Html(html)) = message.parts.get(part_id).map(|p| &p.body) {
+            html
+        } else {
+            return Ok(None);
+        };
+
+        let mut cids = AHashMap::new();
+        for part in &message.parts {
+            if let (Some(cid), false) = (
+                part.content_id(),
+                matches!(part.body, PartType::Multipart(_) | PartType::Message(_)),
+            ) {
+                let blob_id = BlobId::new_section(
+                    blob_id.kind,
+                    part.offset_body,
+                    part.offset_end,
+                    part.encoding as u8,
+                );
+                cids.insert(
+                    cid.trim_start_matches('<')
+                        .trim_end_matches('>')
+                        .to_string(),
+                    format!(
+                        "{}/jmap/download/{}/{}/{}",
+                        base_url,
+                        Id::from(account_id),
+                        blob_id,
+                        form_urlencoded::byte_serialize(
+                            part.attachment_name().unwrap_or("inline").as_bytes()
+                        )
+                        .collect::<String>()
+                    ),
+                );
+            }
+        }
+
+        // Only remote images of delivered messages are proxied, otherwise the
+        // proxy would sign any URL found in messages imported by the user.
+        let image_proxy = if self.config.html_image_proxy
+            && self
+                .get_tag(account_id, Collection::Email, Property::Delivered, ())
+                .await?
+                .map_or(false, |delivered| delivered.contains(document_id))
+        {
+            ImageProxy {
+                base_url: base_url.to_string(),
+                key: self.config.html_image_proxy_key.as_bytes().to_vec(),
+                strip_parameters: self.config.html_tracking_parameters.clone(),
+            }
+            .into()
+        } else {
+            None
+        };
+
+        Ok(Some(sanitize_html(html, scope, cids, image_proxy)))
+    }
+}
+
+pub fn sanitize_html(
+    html: &str,
+    scope: &str,
+    cids: AHashMap<String, String>,
+    image_proxy: Option<ImageProxy>,
+) -> String {
+    let mut builder = ammonia::Builder::default();
+    builder
+        .add_tags(&["font", "center", "big", "small", "tt"])
+        .add_generic_attributes(&[
+            "style", "class", "align", "valign", "bgcolor", "color", "width", "height", "dir",
+        ])
+        .add_tag_attributes("font", &["face", "size"])
+        .add_tag_attributes("table", &["border", "cellpadding", "cellspacing"])
+        .add_url_schemes(&["cid"])
+        .set_tag_attribute_value("a", "target", "_blank")
+        .attribute_filter(
+            move |element, attribute, value| match (element, attribute) {
+                (_, "style") => Some(sanitize_declarations(value).into()),
+                ("img", "src") => {
+                    if let Some(cid) = value.strip_prefix("cid:") {
+                        cids.get(cid).map(|url| Cow::Owned(url.clone()))
+                    } else if let Some(image_proxy) = &image_proxy {
+                        image_proxy.rewrite(value).map(Cow::Owned)
+                    } else {
+                        Some(value.into())
+                    }
+                }
+                _ => Some(value.into()),
+            },
+        );
+    let body = builder.clean(html).to_string();
+
+    // Style sheets are scoped to the message container
+    let mut styles = String::new();
+    let html_lower = html.to_ascii_lowercase();
+    let mut offset = 0;
+    while let Some(start) = html_lower[offset..].find("<style") {
+        let start = offset + start;
+        let (content_start, content_end) = match (
+            html_lower[start..].find('>'),
+            html_lower[start..].find("</style"),
+        ) {
+            (Some(tag_end), Some(end)) if tag_end < end => (start + tag_end + 1, start + end),
+            _ => break,
+        };
+        scope_css(&html[content_start..content_end], scope, &mut styles);
+        offset = content_end;
+    }
+
+    if styles.is_empty() {
+        format!("<div class=\"{scope}\">{body}</div>")
+    } else {
+        format!("<div class=\"{scope}\"><style>{styles}</style>{body}</div>")
+    }
+}
+
+impl ImageProxy {
+    pub fn rewrite(&self, url: &str) -> Option<String> {
+        let url = strip_tracking_parameters(url, &self.strip_parameters)?;
+        Some(format!(
+            "{}/image-proxy/{}?url={}",
+            self.base_url,
+            sign_image_url(&self.key, &url),
+            form_urlencoded::byte_serialize(url.as_bytes()).collect::<String>()
+        ))
+    }
+}
+
+pub fn sign_image_url(key: &[u8], url: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(url.as_bytes());
+    URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+}
+
+pub fn verify_image_url(key: &[u8], url: &str, signature: &str) -> bool {
+    URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_or(false, |signature| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+            mac.update(url.as_bytes());
+            mac.verify_slice(&signature).is_ok()
+        })
+}
+
+// Removes query parameters commonly used to track message opens,
+// only HTTP(S) URLs are accepted.
+pub fn strip_tracking_parameters(url: &str, parameters: &[String]) -> Option<String> {
+    let mut url = reqwest::Url::parse(url).ok()?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return None;
+    }
+    if url.query().is_some() {
+        let query = url
+            .query_pairs()
+            .filter(|(name, _)| {
+                let name = name.to_ascii_lowercase();
+                !parameters.iter().any(|parameter| {
+                    if let Some(prefix) = parameter.strip_suffix('*') {
+                        name.starts_with(prefix)
+                    } else {
+                        &name == parameter
+                    }
+                })
+            })
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect::<Vec<_>>();
+        if query.is_empty() {
+            url.set_query(None);
+        } else {
+            url.query_pairs_mut().clear().extend_pairs(query);
+        }
+    }
+    Some(url.to_string())
+}
+
+fn scope_css(css: &str, scope: &str, result: &mut String) {
+    // Remove comments and anything that could close the style element,
+    // comment delimiters inside quoted strings are left untouched.
+    let mut clean_css = String::with_capacity(css.len());
+    let mut quote = None;
+    let mut pos = 0;
+    while let Some(ch) = css[pos..].chars().next() {
+        if quote.is_none() && css[pos..].starts_with("/*") {
+            pos = css[pos + 2..]
+                .find("*/")
+                .map_or(css.len(), |end| pos + end + 4);
+            clean_css.push(' ');
+            continue;
+        }
+        pos += ch.len_utf8();
+        match (quote, ch) {
+            (_, '<') => continue,
+            (_, '\\') => {
+                clean_css.push(ch);
+                if let Some(ch) = css[pos..].chars().next().filter(|ch| *ch != '<') {
+                    clean_css.push(ch);
+                    pos += ch.len_utf8();
+                }
+                continue;
+            }
+            (None, '"' | '\'') => quote = Some(ch),
+            (Some(end), _) if ch == end || ch == '\n' => quote = None,
+            _ => (),
+        }
+        clean_css.push(ch);
+    }
+    scope_rules(&clean_css, scope, result);
+}
+
+fn scope_rules(css: &str, scope: &str, result: &mut String) {
+    let mut rest = css;
+    while let Some((start, _)) = unquoted_chars(rest).find(|(_, ch)| *ch == '{') {
+        // Statements such as @import or @charset end with a semicolon
+        let prelude = split_unquoted(&rest[..start], ';')
+            .pop()
+            .unwrap_or_default()
+            .trim();
+        let block = &rest[start + 1..];
+        let end = if let Some(end) = block_end(block) {
+            end
+        } else {
+            break;
+        };
+        rest = &block[end + 1..];
+        let block = &block[..end];
+
+        if let Some(at_rule) = prelude.strip_prefix('@') {
+            // Other at-rules such as @font-face or @keyframes are dropped
+            let name = at_rule
+                .split(|ch: char| ch.is_whitespace() || ch == '(')
+                .next()
+                .unwrap_or_default();
+            if (name.eq_ignore_ascii_case("media") || name.eq_ignore_ascii_case("supports"))
+                && has_balanced_quotes(prelude)
+            {
+                result.push_str(prelude);
+                result.push('{');
+                scope_rules(block, scope, result);
+                result.push('}');
+            }
+        } else if !prelude.is_empty() {
+            let selectors = split_unquoted(prelude, ',')
+                .into_iter()
+                .filter(|selector| !selector.trim().is_empty() && has_balanced_quotes(selector))
+                .map(|selector| scope_selector(selector, scope))
+                .collect::<Vec<_>>();
+            if !selectors.is_empty() {
+                result.push_str(&selectors.join(", "));
+                result.push('{');
+                result.push_str(&sanitize_declarations(block));
+                result.push('}');
+            }
+        }
+    }
+}
+
+fn block_end(block: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (pos, ch) in unquoted_chars(block) {
+        match ch {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(pos),
+            '}' => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+// Selectors targeting the document root are rewritten to target the
+// message container, all other selectors are nested inside it.
+fn scope_selector(selector: &str, scope: &str) -> String {
+    let mut rest = selector.trim();
+    let mut is_root = false;
+    let mut has_descendant = false;
+    'outer: loop {
+        for name in [":root", "html", "body"] {
+            if rest.len() >= name.len()
+                && rest.is_char_boundary(name.len())
+                && rest[..name.len()].eq_ignore_ascii_case(name)
+                && !rest[name.len()..]
+                    .starts_with(|ch: char| ch.is_alphanumeric() || ch == '-' || ch == '_')
+            {
+                is_root = true;
+                rest = &rest[name.len()..];
+                if rest.starts_with(char::is_whitespace) {
+                    rest = rest.trim_start();
+                    has_descendant = !rest.is_empty();
+                    continue 'outer;
+                } else {
+                    has_descendant = false;
+                    break 'outer;
+                }
+            }
+        }
+        break;
+    }
+
+    if !is_root || has_descendant {
+        format!(".{scope} {rest}")
+    } else {
+        format!(".{scope}{rest}")
+    }
+}
+
+// Drops declarations that load remote resources, execute code
+// or could be used to overlay content outside the message.
+fn sanitize_declarations(declarations: &str) -> String {
+    let mut result = String::with_capacity(declarations.len());
+    for declaration in split_unquoted(declarations, ';') {
+        let declaration = declaration.trim();
+        if declaration.is_empty() || declaration.contains('\\') || !has_balanced_quotes(declaration)
+        {
+            continue;
+        }
+        let normalized = declaration
+            .chars()
+            .filter(|ch| !ch.is_whitespace())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if [
+            "url(",
+            "image(",
+            "image-set(",
+            "expression(",
+            "javascript:",
+            "behavior:",
+            "-moz-binding",
+            "position:fixed",
+            "position:sticky",
+        ]
+        .iter()
+        .any(|token| normalized.contains(token))
+        {
+            continue;
+        }
+        if !result.is_empty() {
+            result.push_str("; ");
+        }
+        result.push_str(declaration);
+    }
+    result
+}
+
+// Iterates over the characters that are not part of a quoted string
+// or escaped, so that delimiters inside strings are ignored.
+fn unquoted_chars(text: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let mut quote = None;
+    let mut is_escaped = false;
+    text.char_indices().filter(move |(_, ch)| {
+        if is_escaped {
+            is_escaped = false;
+            return false;
+        }
+        match (quote, *ch) {
+            (_, '\\') => {
+                is_escaped = true;
+                false
+            }
+            (None, '"' | '\'') => {
+                quote = Some(*ch);
+                false
+            }
+            (Some(end), ch) if ch == end || ch == '\n' => {
+                quote = None;
+                false
+            }
+            (Some(_), _) => false,
+            (None, _) => true,
+        }
+    })
+}
+
+fn split_unquoted(text: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut last = 0;
+    for (pos, _) in unquoted_chars(text).filter(|(_, ch)| *ch == separator) {
+        parts.push(&text[last..pos]);
+        last = pos + separator.len_utf8();
+    }
+    parts.push(&text[last..]);
+    parts
+}
+
+// Unterminated strings would swallow the rules that follow them.
+fn has_balanced_quotes(text: &str) -> bool {
+    let mut quote = None;
+    for ch in text.chars() {
+        match (quote, ch) {
+            (None, '"' | '\'') => quote = Some(ch),
+            (Some(_), '\n') => return false,
+            (Some(end), ch) if ch == end => quote = None,
+            _ => (),
+        }
+    }
+    quote.is_none()
+}
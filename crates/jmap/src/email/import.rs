@@ -49,6 +49,10 @@ impl JMAP {
         request: ImportEmailRequest,
         access_token: &AccessToken,
     ) -> Result<ImportEmailResponse, MethodError> {
+        if request.emails.len() > self.config.set_max_objects {
+            return Err(MethodError::RequestTooLarge);
+        }
+
         // Validate state
         let account_id = request.account_id.document_id();
         let old_state: State = self
@@ -140,6 +144,9 @@ impl JMAP {
                     mailbox_ids,
                     keywords: email.keywords,
                     received_at: email.received_at.map(|r| r.into()),
+                    subaddress: None,
+                    bimi_indicator: None,
+                    is_delivered: false,
                     skip_duplicates: false,
                     encrypt: self.config.encrypt && self.config.encrypt_append,
                 })
@@ -64,6 +64,7 @@ pub(super) trait IndexMessage {
         keywords: Vec<Keyword>,
         mailbox_ids: Vec<u32>,
         received_at: u64,
+        subaddress: Option<&str>,
         default_language: Language,
     ) -> store::Result<&mut Self>;
 }
@@ -75,6 +76,7 @@ impl IndexMessage for BatchBuilder {
         keywords: Vec<Keyword>,
         mailbox_ids: Vec<u32>,
         received_at: u64,
+        subaddress: Option<&str>,
         default_language: Language,
     ) -> store::Result<&mut Self> {
         let mut metadata = Object::with_capacity(15);
@@ -97,6 +99,12 @@ impl IndexMessage for BatchBuilder {
         );
         self.value(Property::ReceivedAt, received_at, F_INDEX);
 
+        // Index the subaddress the message was delivered to
+        if let Some(subaddress) = subaddress.filter(|s| !s.is_empty() && s.len() < MAX_ID_LENGTH) {
+            metadata.append(Property::Subaddress, subaddress.to_string());
+            self.value(Property::Subaddress, subaddress.to_lowercase(), F_INDEX);
+        }
+
         let mut fts = FtsIndexBuilder::with_default_language(default_language);
         let mut seen_headers = [false; 40];
         let mut language = Language::Unknown;
@@ -487,6 +495,13 @@ impl IntoOperations for EmailIndexBuilder {
                 (Property::HasAttachment, Value::Bool(true)) => {
                     batch.bitmap(Property::HasAttachment, (), options);
                 }
+                (Property::Subaddress, Value::Text(value)) => {
+                    batch.value(
+                        Property::Subaddress,
+                        value.to_lowercase(),
+                        F_INDEX | options,
+                    );
+                }
                 _ => {}
             }
         }
@@ -31,7 +31,7 @@ use jmap_proto::{
     },
 };
 use mail_parser::{
-    parsers::fields::thread::thread_name, HeaderName, HeaderValue, Message, PartType,
+    parsers::fields::thread::thread_name, HeaderName, HeaderValue, Message, MessageParser, PartType,
 };
 use store::{
     ahash::AHashSet,
@@ -49,6 +49,7 @@ use crate::{
 use super::{
     crypto::{EncryptMessage, EncryptMessageError, EncryptionParams},
     index::{TrimTextValue, MAX_SORT_FIELD_LENGTH},
+    keyword::RegisterKeywords,
 };
 
 #[derive(Default)]
@@ -67,6 +68,9 @@ pub struct IngestEmail<'x> {
     pub mailbox_ids: Vec<u32>,
     pub keywords: Vec<Keyword>,
     pub received_at: Option<u64>,
+    pub subaddress: Option<&'x str>,
+    pub bimi_indicator: Option<String>,
+    pub is_delivered: bool,
     pub skip_duplicates: bool,
     pub encrypt: bool,
 }
@@ -296,6 +300,7 @@ impl JMAP {
 
         // Build write batch
         batch
+            .register_keywords(params.account_id, &params.keywords)
             .with_collection(Collection::Email)
             .create_document(document_id)
             .index_message(
@@ -303,6 +308,7 @@ impl JMAP {
                 params.keywords,
                 params.mailbox_ids,
                 params.received_at.unwrap_or_else(now),
+                params.subaddress,
                 self.config.default_language,
             )
             .map_err(|err| {
@@ -316,6 +322,12 @@ impl JMAP {
             .value(Property::Cid, change_id, F_VALUE)
             .value(Property::ThreadId, thread_id, F_VALUE | F_BITMAP)
             .custom(changes);
+        if let Some(bimi_indicator) = params.bimi_indicator {
+            batch.value(Property::BimiIndicator, bimi_indicator, F_VALUE);
+        }
+        if params.is_delivered {
+            batch.bitmap(Property::Delivered, (), 0);
+        }
         self.store.write(batch.build()).await.map_err(|err| {
             tracing::error!(
                 event = "error",
@@ -505,6 +517,127 @@ impl JMAP {
     }
 }
 
+// Obtains the validated BIMI indicator from the Authentication-Results header
+// added by this server.
+pub fn bimi_indicator(raw_message: &[u8], authserv_id: &str) -> Option<String> {
+    let message = MessageParser::new().parse(raw_message)?;
+    let (_, results) = auth_results(&message, raw_message, authserv_id)?;
+
+    let mut is_pass = false;
+    let mut indicator = None;
+    for result in results.split(';') {
+        for token in result.split_ascii_whitespace() {
+            if let Some((name, value)) = token.split_once('=') {
+                if name.eq_ignore_ascii_case("bimi") {
+                    is_pass = value.eq_ignore_ascii_case("pass");
+                } else if name.eq_ignore_ascii_case("policy.indicator-uri") {
+                    indicator = Some(value);
+                }
+            }
+        }
+    }
+
+    indicator
+        .filter(|indicator| is_pass && indicator.starts_with("https://"))
+        .map(|indicator| indicator.to_string())
+}
+
+// Returns the domains that passed any of the authentication methods (dmarc, dkim
+// or spf) according to the Authentication-Results header added by this server.
+pub fn authenticated_domains(
+    raw_message: &[u8],
+    authserv_id: &str,
+    methods: &[&str],
+) -> Vec<String> {
+    let mut domains = Vec::new();
+    let results = MessageParser::new().parse(raw_message).and_then(|message| {
+        auth_results(&message, raw_message, authserv_id).map(|(_, results)| results)
+    });
+    for result in results.into_iter().flat_map(|results| results.split(';')) {
+        let mut tokens = result.split_ascii_whitespace();
+        let property = match tokens.next().and_then(|token| token.split_once('=')) {
+            Some((method, result))
+                if result.eq_ignore_ascii_case("pass")
+                    && methods.iter().any(|m| m.eq_ignore_ascii_case(method)) =>
+            {
+                if method.eq_ignore_ascii_case("dmarc") {
+                    "header.from"
+                } else if method.eq_ignore_ascii_case("dkim") {
+                    "header.d"
+                } else if method.eq_ignore_ascii_case("spf") {
+                    "smtp.mailfrom"
+                } else {
+                    continue;
+                }
+            }
+            _ => continue,
+        };
+        if let Some(domain) = tokens
+            .filter_map(|token| token.split_once('='))
+            .find(|(name, _)| name.eq_ignore_ascii_case(property))
+            .map(|(_, value)| {
+                value
+                    .rsplit_once('@')
+                    .map_or(value, |(_, domain)| domain)
+                    .to_lowercase()
+            })
+            .filter(|domain| !domain.is_empty() && !domains.contains(domain))
+        {
+            domains.push(domain);
+        }
+    }
+    domains
+}
+
+// Returns true if the spam filter flagged the message as spam. Only the
+// X-Spam-Status header added by this server above its own
+// Authentication-Results header is trusted.
+pub fn is_spam_verdict(raw_message: &[u8], authserv_id: &str) -> bool {
+    MessageParser::new()
+        .parse(raw_message)
+        .map_or(false, |message| {
+            auth_results(&message, raw_message, authserv_id).map_or(false, |(pos, _)| {
+                message.parts[0].headers[..pos]
+                    .iter()
+                    .find(|header| header.name.as_str().eq_ignore_ascii_case("X-Spam-Status"))
+                    .and_then(|header| header.value.as_text())
+                    .map_or(false, |value| value.trim_start().starts_with("Yes"))
+            })
+        })
+}
+
+// Obtains the position and results of the topmost Authentication-Results
+// header, provided it was added by this server. Forged headers carrying
+// the same authserv-id are removed by the SMTP server on arrival.
+fn auth_results<'x>(
+    message: &Message<'_>,
+    raw_message: &'x [u8],
+    authserv_id: &str,
+) -> Option<(usize, &'x str)> {
+    let (pos, header) = message.parts[0]
+        .headers
+        .iter()
+        .enumerate()
+        .find(|(_, header)| {
+            header
+                .name
+                .as_str()
+                .eq_ignore_ascii_case("Authentication-Results")
+        })?;
+    let header =
+        std::str::from_utf8(raw_message.get(header.offset_start..header.offset_end)?).ok()?;
+    let (host, results) = header.split_once(';')?;
+    if host
+        .split_ascii_whitespace()
+        .next()
+        .map_or(false, |host| host.eq_ignore_ascii_case(authserv_id))
+    {
+        Some((pos, results))
+    } else {
+        None
+    }
+}
+
 impl From<IngestedEmail> for Object<Value> {
     fn from(email: IngestedEmail) -> Self {
         Object::with_capacity(3)
@@ -0,0 +1,393 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::{method::MethodError, set::SetError},
+    method::keyword::{
+        KeywordCount, KeywordGetRequest, KeywordGetResponse, KeywordRenameRequest,
+        KeywordRenameResponse,
+    },
+    types::{
+        collection::Collection, id::Id, keyword::Keyword, property::Property, state::StateChange,
+        type_state::DataType,
+    },
+};
+use store::{
+    write::{
+        assert::HashedValue, key::KeySerializer, log::ChangeLogBuilder, BatchBuilder, Operation,
+        ValueClass, F_VALUE,
+    },
+    CustomValueKey,
+};
+use utils::map::vec_map::VecMap;
+
+use crate::{auth::authenticate::AccountKey, JMAP};
+
+use super::set::TagManager;
+
+pub trait RegisterKeywords {
+    fn register_keywords<'x>(
+        &mut self,
+        account_id: u32,
+        keywords: impl IntoIterator<Item = &'x Keyword>,
+    ) -> &mut Self;
+
+    fn unregister_keywords<'x>(
+        &mut self,
+        account_id: u32,
+        keywords: impl IntoIterator<Item = &'x Keyword>,
+    ) -> &mut Self;
+}
+
+impl RegisterKeywords for BatchBuilder {
+    fn register_keywords<'x>(
+        &mut self,
+        account_id: u32,
+        keywords: impl IntoIterator<Item = &'x Keyword>,
+    ) -> &mut Self {
+        // Registry keys are absolute, they do not depend on the current account or collection
+        for keyword in keywords {
+            if let Keyword::Other(name) = keyword {
+                self.op(Operation::Value {
+                    class: ValueClass::Custom {
+                        bytes: AccountKey::keyword(account_id, name),
+                    },
+                    set: Some(vec![]),
+                });
+            }
+        }
+        self
+    }
+
+    fn unregister_keywords<'x>(
+        &mut self,
+        account_id: u32,
+        keywords: impl IntoIterator<Item = &'x Keyword>,
+    ) -> &mut Self {
+        for keyword in keywords {
+            if let Keyword::Other(name) = keyword {
+                self.op(Operation::Value {
+                    class: ValueClass::Custom {
+                        bytes: AccountKey::keyword(account_id, name),
+                    },
+                    set: None,
+                });
+            }
+        }
+        self
+    }
+}
+
+impl JMAP {
+    pub async fn keyword_get(
+        &self,
+        request: KeywordGetRequest,
+    ) -> Result<KeywordGetResponse, MethodError> {
+        let account_id = request.account_id.document_id();
+        let mut response = KeywordGetResponse {
+            account_id: request.account_id,
+            state: self.get_state(account_id, Collection::Email).await?,
+            list: vec![],
+            not_found: vec![],
+        };
+
+        let (keywords, is_explicit) = if let Some(ids) = request.ids {
+            if ids.len() > self.config.get_max_objects {
+                return Err(MethodError::RequestTooLarge);
+            }
+            (ids, true)
+        } else {
+            (
+                self.keyword_registry(account_id)
+                    .await
+                    .map_err(|err| {
+                        tracing::error!(
+                            event = "error",
+                            context = "keyword_get",
+                            account_id = account_id,
+                            error = ?err,
+                            "Failed to obtain keyword registry.");
+                        MethodError::ServerPartialFail
+                    })?
+                    .into_iter()
+                    .map(Keyword::Other)
+                    .collect(),
+                false,
+            )
+        };
+
+        let seen = self
+            .get_tag(
+                account_id,
+                Collection::Email,
+                Property::Keywords,
+                Keyword::Seen,
+            )
+            .await?
+            .unwrap_or_default();
+        for keyword in keywords {
+            let document_ids = self
+                .get_tag(
+                    account_id,
+                    Collection::Email,
+                    Property::Keywords,
+                    keyword.clone(),
+                )
+                .await?
+                .unwrap_or_default();
+            let total_emails = document_ids.len();
+
+            // Registry entries are not removed when the last message is untagged
+            if total_emails > 0 {
+                response.list.push(KeywordCount {
+                    id: keyword.to_string(),
+                    total_emails,
+                    unread_emails: total_emails - document_ids.intersection_len(&seen),
+                });
+            } else if is_explicit {
+                response.not_found.push(keyword.to_string());
+            }
+        }
+
+        Ok(response)
+    }
+
+    pub async fn keyword_rename(
+        &self,
+        request: KeywordRenameRequest,
+    ) -> Result<KeywordRenameResponse, MethodError> {
+        let account_id = request.account_id.document_id();
+        let old_state = self.get_state(account_id, Collection::Email).await?;
+        let mut response = KeywordRenameResponse {
+            account_id: request.account_id,
+            new_state: old_state.clone(),
+            old_state,
+            renamed: VecMap::new(),
+            not_renamed: VecMap::new(),
+        };
+        let mut last_change_id = None;
+
+        'rename: for (from, to) in request.rename {
+            match (&from, &to) {
+                (Keyword::Other(_), Keyword::Other(name)) if !name.is_empty() && from != to => {}
+                (Keyword::Other(_), Keyword::Other(_)) => {
+                    response.not_renamed.append(
+                        from.to_string(),
+                        SetError::invalid_properties()
+                            .with_description("Invalid destination keyword."),
+                    );
+                    continue;
+                }
+                _ => {
+                    response.not_renamed.append(
+                        from.to_string(),
+                        SetError::invalid_properties()
+                            .with_description("System keywords cannot be renamed."),
+                    );
+                    continue;
+                }
+            }
+
+            // All messages are retagged in a single batch, so either every message
+            // is renamed or none is.
+            let mut try_count = 0;
+            loop {
+                let document_ids = match self
+                    .get_tag(
+                        account_id,
+                        Collection::Email,
+                        Property::Keywords,
+                        from.clone(),
+                    )
+                    .await?
+                {
+                    Some(document_ids) if !document_ids.is_empty() => document_ids,
+                    _ => {
+                        response
+                            .not_renamed
+                            .append(from.to_string(), SetError::not_found());
+                        continue 'rename;
+                    }
+                };
+                if document_ids.len() > self.config.set_max_objects as u64 {
+                    response.not_renamed.append(
+                        from.to_string(),
+                        SetError::forbidden().with_description(format!(
+                            "Keyword is assigned to more than {} messages.",
+                            self.config.set_max_objects
+                        )),
+                    );
+                    continue 'rename;
+                }
+
+                let change_id = self.assign_change_id(account_id).await?;
+                let mut changes = ChangeLogBuilder::with_change_id(change_id);
+                let mut renamed = 0;
+                let mut batch = BatchBuilder::new();
+                batch
+                    .with_account_id(account_id)
+                    .register_keywords(account_id, [&to])
+                    .unregister_keywords(account_id, [&from])
+                    .with_collection(Collection::Email);
+
+                for document_id in document_ids {
+                    let (thread_id, mut keywords) = if let (Some(thread_id), Some(keywords)) = (
+                        self.get_property::<u32>(
+                            account_id,
+                            Collection::Email,
+                            document_id,
+                            Property::ThreadId,
+                        )
+                        .await?,
+                        self.get_property::<HashedValue<Vec<Keyword>>>(
+                            account_id,
+                            Collection::Email,
+                            document_id,
+                            Property::Keywords,
+                        )
+                        .await?,
+                    ) {
+                        (thread_id, TagManager::new(keywords))
+                    } else {
+                        // Message was deleted
+                        continue;
+                    };
+
+                    keywords.update(from.clone(), false);
+                    if !keywords.has_changes() {
+                        continue;
+                    }
+                    keywords.update(to.clone(), true);
+
+                    batch.update_document(document_id);
+                    keywords.update_batch(&mut batch, Property::Keywords);
+                    batch.value(Property::Cid, change_id, F_VALUE);
+                    changes.log_update(Collection::Email, Id::from_parts(thread_id, document_id));
+                    renamed += 1;
+                }
+                batch.custom(changes);
+
+                match self.store.write(batch.build()).await {
+                    Ok(_) => {
+                        last_change_id = change_id.into();
+                        response.renamed.append(from.to_string(), renamed);
+                        break;
+                    }
+                    Err(store::Error::AssertValueFailed) if try_count < 3 => {
+                        try_count += 1;
+                    }
+                    Err(store::Error::AssertValueFailed) => {
+                        response.not_renamed.append(
+                            from.to_string(),
+                            SetError::forbidden().with_description(
+                                "Some messages were modified concurrently, try again.",
+                            ),
+                        );
+                        break;
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            event = "error",
+                            context = "keyword_rename",
+                            account_id = account_id,
+                            error = ?err,
+                            "Failed to rename keyword.");
+                        return Err(MethodError::ServerPartialFail);
+                    }
+                }
+            }
+        }
+
+        if let Some(change_id) = last_change_id {
+            response.new_state = change_id.into();
+            self.broadcast_state_change(
+                StateChange::new(account_id).with_change(DataType::Email, change_id),
+            )
+            .await;
+        }
+
+        Ok(response)
+    }
+
+    /// Returns the custom keywords that no other message is tagged with, their
+    /// registry entries are removed along with the keywords of this message.
+    pub async fn unused_keywords<'x>(
+        &self,
+        account_id: u32,
+        document_id: u32,
+        keywords: impl IntoIterator<Item = &'x Keyword>,
+    ) -> Result<Vec<&'x Keyword>, MethodError> {
+        let mut unused = Vec::new();
+        for keyword in keywords {
+            if matches!(keyword, Keyword::Other(_))
+                && self
+                    .get_tag(
+                        account_id,
+                        Collection::Email,
+                        Property::Keywords,
+                        keyword.clone(),
+                    )
+                    .await?
+                    .map_or(true, |document_ids| {
+                        document_ids.iter().all(|id| id == document_id)
+                    })
+            {
+                unused.push(keyword);
+            }
+        }
+        Ok(unused)
+    }
+
+    pub async fn keyword_registry(&self, account_id: u32) -> store::Result<Vec<String>> {
+        self.store
+            .iterate(
+                Vec::new(),
+                CustomValueKey {
+                    value: AccountKey::keyword(account_id, ""),
+                },
+                CustomValueKey {
+                    // Keywords are valid UTF-8 and never contain a 0xFF byte
+                    value: KeySerializer::new(std::mem::size_of::<u32>() * 2 + 2)
+                        .write(u32::MAX)
+                        .write(9u8)
+                        .write(account_id)
+                        .write(u8::MAX)
+                        .finalize(),
+                },
+                false,
+                true,
+                move |keywords, key, _| {
+                    // Skip the u32::MAX account prefix, the key type and the account id
+                    let offset = std::mem::size_of::<u32>() * 2 + 1;
+                    if let Some(name) = key
+                        .get(offset..)
+                        .and_then(|name| std::str::from_utf8(name).ok())
+                    {
+                        keywords.push(name.to_string());
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+    }
+}
@@ -22,14 +22,19 @@
 */
 
 pub mod body;
+pub mod convert;
 pub mod copy;
 pub mod crypto;
 pub mod get;
 pub mod headers;
+pub mod html;
 pub mod import;
 pub mod index;
 pub mod ingest;
+pub mod keyword;
 pub mod parse;
 pub mod query;
 pub mod set;
 pub mod snippet;
+pub mod snooze;
+pub mod spam_train;
@@ -236,6 +236,10 @@ impl JMAP {
                     Property::ThreadId,
                     id.document_id(),
                 )),
+                Filter::Subaddress(subaddress) => filters.push(query::Filter::eq(
+                    Property::Subaddress,
+                    subaddress.to_lowercase(),
+                )),
                 Filter::And | Filter::Or | Filter::Not | Filter::Close => {
                     filters.push(cond.into());
                 }
@@ -55,18 +55,25 @@ use store::{
     ahash::AHashSet,
     fts::term_index::TokenIndex,
     write::{
-        assert::HashedValue, log::ChangeLogBuilder, BatchBuilder, DeserializeFrom, SerializeInto,
-        ToBitmaps, ValueClass, F_BITMAP, F_CLEAR, F_VALUE,
+        assert::HashedValue, log::ChangeLogBuilder, now, BatchBuilder, DeserializeFrom, Operation,
+        SerializeInto, ToBitmaps, ValueClass, F_BITMAP, F_CLEAR, F_VALUE,
     },
     BlobKind, Serialize, ValueKey,
 };
 
-use crate::{auth::AccessToken, IngestError, JMAP};
+use crate::{
+    auth::{authenticate::AccountKey, AccessToken},
+    mailbox::INBOX_ID,
+    IngestError, JMAP,
+};
 
 use super::{
     headers::{BuildHeader, ValueToHeader},
     index::EmailIndexBuilder,
     ingest::IngestEmail,
+    keyword::RegisterKeywords,
+    snooze::SNOOZED_ROLE,
+    spam_train::spam_train_direction,
 };
 
 impl JMAP {
@@ -82,7 +89,7 @@ impl JMAP {
             .await?;
 
         // Obtain mailboxIds
-        let mailbox_ids = self.mailbox_get_or_create(account_id).await?;
+        let mut mailbox_ids = self.mailbox_get_or_create(account_id).await?;
         let (can_add_mailbox_ids, can_delete_mailbox_ids, can_modify_message_ids) = if access_token
             .is_shared(account_id)
         {
@@ -739,6 +746,9 @@ impl JMAP {
                     mailbox_ids: mailboxes,
                     keywords,
                     received_at,
+                    subaddress: None,
+                    bimi_indicator: None,
+                    is_delivered: false,
                     skip_duplicates: false,
                     encrypt: self.config.encrypt && self.config.encrypt_append,
                 })
@@ -760,6 +770,9 @@ impl JMAP {
 
         // Process updates
         let mut changes = ChangeLogBuilder::new();
+        let spam_train_junk_id = self.spam_train_junk_id(account_id).await?;
+        let mut spam_train_ids = Vec::new();
+        let mut ham_train_ids = Vec::new();
         'update: for (id, object) in request.unwrap_update() {
             // Make sure id won't be destroyed
             if will_destroy.contains(&id) {
@@ -797,6 +810,7 @@ impl JMAP {
                 .with_account_id(account_id)
                 .with_collection(Collection::Email);
 
+            let mut snooze = None;
             for (property, value) in object.properties {
                 let value = match response.eval_object_references(value) {
                     Ok(value) => value,
@@ -839,6 +853,12 @@ impl JMAP {
                             );
                         }
                     }
+                    (Property::SnoozedUntil, MaybePatchValue::Value(Value::Date(until))) => {
+                        snooze = Some(Some(until));
+                    }
+                    (Property::SnoozedUntil, MaybePatchValue::Value(Value::Null)) => {
+                        snooze = Some(None);
+                    }
                     (property, _) => {
                         response.invalid_property_update(id, property);
                         continue 'update;
@@ -846,7 +866,70 @@ impl JMAP {
                 }
             }
 
-            if !mailboxes.has_changes() && !keywords.has_changes() {
+            // Process snooze
+            let mut snooze_change = None;
+            if let Some(snooze) = snooze {
+                if access_token.is_shared(account_id) {
+                    response.not_updated.append(
+                        id,
+                        SetError::forbidden()
+                            .with_description("Messages in shared accounts cannot be snoozed."),
+                    );
+                    continue 'update;
+                } else if mailboxes.has_changes() {
+                    response.not_updated.append(
+                        id,
+                        SetError::invalid_properties()
+                            .with_property(Property::SnoozedUntil)
+                            .with_description(
+                                "snoozedUntil cannot be combined with mailboxIds changes.",
+                            ),
+                    );
+                    continue 'update;
+                }
+
+                let current = self
+                    .get_property::<u64>(
+                        account_id,
+                        Collection::Email,
+                        document_id,
+                        Property::SnoozedUntil,
+                    )
+                    .await?;
+                match snooze {
+                    Some(until) => {
+                        let until = until.timestamp();
+                        if until <= now() as i64 {
+                            response.not_updated.append(
+                                id,
+                                SetError::invalid_properties()
+                                    .with_property(Property::SnoozedUntil)
+                                    .with_description("snoozedUntil must be in the future."),
+                            );
+                            continue 'update;
+                        }
+
+                        // Hide the message from the Inbox until it is due
+                        let snoozed_id = self.mailbox_get_or_create_snoozed(account_id).await?;
+                        mailbox_ids.insert(snoozed_id);
+                        mailboxes.update(INBOX_ID, false);
+                        mailboxes.update(snoozed_id, true);
+                        snooze_change = Some((current, Some(until as u64)));
+                    }
+                    None if current.is_some() => {
+                        mailboxes.update(INBOX_ID, true);
+                        if let Some(snoozed_id) =
+                            self.mailbox_get_by_role(account_id, SNOOZED_ROLE).await?
+                        {
+                            mailboxes.update(snoozed_id, false);
+                        }
+                        snooze_change = Some((current, None));
+                    }
+                    None => (),
+                }
+            }
+
+            if !mailboxes.has_changes() && !keywords.has_changes() && snooze_change.is_none() {
                 response.not_updated.append(
                     id,
                     SetError::invalid_properties()
@@ -883,6 +966,13 @@ impl JMAP {
                 }
 
                 // Update keywords property
+                batch
+                    .register_keywords(account_id, keywords.added())
+                    .unregister_keywords(
+                        account_id,
+                        self.unused_keywords(account_id, document_id, keywords.removed())
+                            .await?,
+                    );
                 keywords.update_batch(&mut batch, Property::Keywords);
 
                 // Update last change id
@@ -893,6 +983,7 @@ impl JMAP {
             }
 
             // Process mailboxes
+            let mut spam_train = None;
             if mailboxes.has_changes() {
                 // Make sure the message is at least in one mailbox
                 if !mailboxes.has_tags() {
@@ -950,10 +1041,44 @@ impl JMAP {
                     }
                 }
 
+                // Detect moves between the Inbox and Junk folders
+                if let Some(junk_id) = spam_train_junk_id {
+                    spam_train =
+                        spam_train_direction(junk_id, mailboxes.removed(), mailboxes.added());
+                }
+
                 // Update mailboxIds property
                 mailboxes.update_batch(&mut batch, Property::MailboxIds);
             }
 
+            // Update snooze schedule
+            if let Some((current, until)) = snooze_change {
+                if let Some(until) = until {
+                    batch.value(Property::SnoozedUntil, until, F_VALUE);
+                } else {
+                    batch.value(Property::SnoozedUntil, (), F_VALUE | F_CLEAR);
+                }
+                batch
+                    .with_account_id(u32::MAX)
+                    .with_collection(Collection::Principal);
+                if let Some(current) = current {
+                    batch.op(Operation::Value {
+                        class: ValueClass::Custom {
+                            bytes: AccountKey::snooze(current, account_id, document_id),
+                        },
+                        set: None,
+                    });
+                }
+                if let Some(until) = until {
+                    batch.op(Operation::Value {
+                        class: ValueClass::Custom {
+                            bytes: AccountKey::snooze(until, account_id, document_id),
+                        },
+                        set: Some(vec![]),
+                    });
+                }
+            }
+
             // Log mailbox changes
             for mailbox_id in changed_mailboxes {
                 changes.log_child_update(Collection::Mailbox, mailbox_id);
@@ -965,6 +1090,13 @@ impl JMAP {
                     Ok(_) => {
                         // Add to updated list
                         response.updated.append(id, None);
+
+                        // Queue for spam training
+                        match spam_train {
+                            Some(true) => spam_train_ids.push(document_id),
+                            Some(false) => ham_train_ids.push(document_id),
+                            None => (),
+                        }
                     }
                     Err(store::Error::AssertValueFailed) => {
                         response.not_updated.append(
@@ -985,6 +1117,14 @@ impl JMAP {
                 }
             }
         }
+        if !spam_train_ids.is_empty() {
+            self.spam_train_enqueue(account_id, spam_train_ids, true)
+                .await;
+        }
+        if !ham_train_ids.is_empty() {
+            self.spam_train_enqueue(account_id, ham_train_ids, false)
+                .await;
+        }
 
         // Process deletions
         if !will_destroy.is_empty() {
@@ -1065,8 +1205,11 @@ impl JMAP {
             .with_collection(Collection::Email)
             .delete_document(document_id);
 
-        // Remove last changeId
-        batch.value(Property::Cid, (), F_VALUE | F_CLEAR);
+        // Remove last changeId, BIMI indicator and delivery marker
+        batch
+            .value(Property::Cid, (), F_VALUE | F_CLEAR)
+            .value(Property::BimiIndicator, (), F_VALUE | F_CLEAR)
+            .bitmap(Property::Delivered, (), F_CLEAR);
 
         // Remove mailboxes
         let mailboxes = if let Some(mailboxes) = self
@@ -1108,6 +1251,12 @@ impl JMAP {
             )
             .await?
         {
+            // Drop registry entries of keywords no other message is tagged with
+            batch.unregister_keywords(
+                account_id,
+                self.unused_keywords(account_id, document_id, &keywords.inner)
+                    .await?,
+            );
             batch.assert_value(Property::Keywords, &keywords).value(
                 Property::Keywords,
                 keywords.inner,
@@ -1124,6 +1273,20 @@ impl JMAP {
             return Ok(Err(SetError::not_found()));
         };
 
+        // Remove snooze, any pending schedule entry is discarded when due
+        if self
+            .get_property::<u64>(
+                account_id,
+                Collection::Email,
+                document_id,
+                Property::SnoozedUntil,
+            )
+            .await?
+            .is_some()
+        {
+            batch.value(Property::SnoozedUntil, (), F_VALUE | F_CLEAR);
+        }
+
         // Remove threadIds
         let mut delete_thread_id = None;
         if let Some(thread_id) = self
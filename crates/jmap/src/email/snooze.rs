@@ -0,0 +1,257 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    object::{index::ObjectIndexBuilder, Object},
+    types::{
+        collection::Collection, id::Id, keyword::Keyword, property::Property, state::StateChange,
+        type_state::DataType, value::Value,
+    },
+};
+use store::{
+    ahash::AHashSet,
+    write::{
+        assert::HashedValue, key::DeserializeBigEndian, now, BatchBuilder, Operation, ValueClass,
+        F_CLEAR, F_VALUE,
+    },
+    CustomValueKey,
+};
+
+use crate::{
+    auth::authenticate::AccountKey,
+    mailbox::{set::SCHEMA, INBOX_ID},
+    JMAP,
+};
+
+use super::set::TagManager;
+
+pub const SNOOZED_ROLE: &str = "snoozed";
+
+impl JMAP {
+    pub async fn mailbox_get_or_create_snoozed(&self, account_id: u32) -> Result<u32, MethodError> {
+        if let Some(mailbox_id) = self.mailbox_get_by_role(account_id, SNOOZED_ROLE).await? {
+            return Ok(mailbox_id);
+        }
+
+        let mailbox_id = self
+            .assign_document_id(account_id, Collection::Mailbox)
+            .await?;
+        let mut changes = self.begin_changes(account_id).await?;
+        changes.log_insert(Collection::Mailbox, mailbox_id);
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::Mailbox)
+            .create_document(mailbox_id)
+            .custom(
+                ObjectIndexBuilder::new(SCHEMA).with_changes(
+                    Object::with_capacity(3)
+                        .with_property(Property::Name, "Snoozed")
+                        .with_property(Property::Role, SNOOZED_ROLE)
+                        .with_property(Property::ParentId, Value::Id(0u64.into())),
+                ),
+            )
+            .custom(changes);
+        self.write_batch(batch).await?;
+
+        Ok(mailbox_id)
+    }
+
+    pub async fn wake_snoozed_emails(&self) -> Result<(), MethodError> {
+        let from_key = CustomValueKey {
+            value: AccountKey::snooze(0, 0, 0),
+        };
+        let to_key = CustomValueKey {
+            value: AccountKey::snooze(now(), u32::MAX, u32::MAX),
+        };
+        let due = self
+            .store
+            .iterate(
+                Vec::new(),
+                from_key,
+                to_key,
+                false,
+                true,
+                move |due, key, _| {
+                    // Skip the u32::MAX account prefix and the key type
+                    let offset = std::mem::size_of::<u32>() + 1;
+                    due.push((
+                        key.deserialize_be_u64(offset)?,
+                        key.deserialize_be_u32(offset + std::mem::size_of::<u64>())?,
+                        key.deserialize_be_u32(
+                            offset + std::mem::size_of::<u64>() + std::mem::size_of::<u32>(),
+                        )?,
+                    ));
+                    Ok(true)
+                },
+            )
+            .await
+            .map_err(|err| {
+                tracing::error!(
+                    event = "error",
+                    context = "snooze",
+                    error = ?err,
+                    "Failed to obtain snoozed emails.");
+                MethodError::ServerPartialFail
+            })?;
+
+        for (until, account_id, document_id) in due {
+            if let Some(state_change) = self
+                .wake_snoozed_email(until, account_id, document_id)
+                .await?
+            {
+                self.broadcast_state_change(state_change).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn wake_snoozed_email(
+        &self,
+        until: u64,
+        account_id: u32,
+        document_id: u32,
+    ) -> Result<Option<StateChange>, MethodError> {
+        // Remove the schedule entry
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::snooze(until, account_id, document_id),
+                },
+                set: None,
+            });
+
+        // Entries are left behind when a message is deleted or snoozed again
+        let (thread_id, mut mailboxes, mut keywords) = match (
+            self.get_property::<u64>(
+                account_id,
+                Collection::Email,
+                document_id,
+                Property::SnoozedUntil,
+            )
+            .await?,
+            self.get_property::<u32>(
+                account_id,
+                Collection::Email,
+                document_id,
+                Property::ThreadId,
+            )
+            .await?,
+            self.get_property::<HashedValue<Vec<u32>>>(
+                account_id,
+                Collection::Email,
+                document_id,
+                Property::MailboxIds,
+            )
+            .await?,
+            self.get_property::<HashedValue<Vec<Keyword>>>(
+                account_id,
+                Collection::Email,
+                document_id,
+                Property::Keywords,
+            )
+            .await?,
+        ) {
+            (Some(snoozed_until), Some(thread_id), Some(mailboxes), Some(keywords))
+                if snoozed_until == until =>
+            {
+                (
+                    thread_id,
+                    TagManager::new(mailboxes),
+                    TagManager::new(keywords),
+                )
+            }
+            _ => {
+                self.write_batch(batch).await?;
+                return Ok(None);
+            }
+        };
+
+        // Move the message back to the Inbox and mark it as unread
+        if self
+            .get_document_ids(account_id, Collection::Mailbox)
+            .await?
+            .map_or(false, |ids| ids.contains(INBOX_ID))
+        {
+            mailboxes.update(INBOX_ID, true);
+            if let Some(snoozed_id) = self.mailbox_get_by_role(account_id, SNOOZED_ROLE).await? {
+                mailboxes.update(snoozed_id, false);
+            }
+        }
+        keywords.update(Keyword::Seen, false);
+
+        // Log changes
+        let mut changes = self.begin_changes(account_id).await?;
+        changes.log_update(Collection::Email, Id::from_parts(thread_id, document_id));
+        let mut changed_mailboxes = mailboxes.changed_tags().copied().collect::<AHashSet<_>>();
+        if keywords.has_changes() {
+            changed_mailboxes.extend(mailboxes.current().iter().copied());
+        }
+        for mailbox_id in changed_mailboxes {
+            changes.log_child_update(Collection::Mailbox, mailbox_id);
+        }
+
+        // Build batch
+        let change_id = changes.change_id;
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::Email)
+            .update_document(document_id)
+            .value(Property::SnoozedUntil, (), F_VALUE | F_CLEAR);
+        if keywords.has_changes() {
+            keywords.update_batch(&mut batch, Property::Keywords);
+            batch.value(Property::Cid, change_id, F_VALUE);
+        }
+        if mailboxes.has_changes() {
+            mailboxes.update_batch(&mut batch, Property::MailboxIds);
+        }
+        batch.custom(changes);
+
+        match self.store.write(batch.build()).await {
+            Ok(_) => Ok(Some(
+                StateChange::new(account_id)
+                    .with_change(DataType::Email, change_id)
+                    .with_change(DataType::Mailbox, change_id),
+            )),
+            Err(store::Error::AssertValueFailed) => {
+                // The message was modified concurrently, retry on the next run
+                Ok(None)
+            }
+            Err(err) => {
+                tracing::error!(
+                    event = "error",
+                    context = "snooze",
+                    account_id = account_id,
+                    document_id = document_id,
+                    error = ?err,
+                    "Failed to wake snoozed email.");
+                Err(MethodError::ServerPartialFail)
+            }
+        }
+    }
+}
@@ -0,0 +1,413 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use jmap_proto::{error::method::MethodError, types::collection::Collection};
+use mail_parser::MessageParser;
+use store::{
+    write::{key::DeserializeBigEndian, BatchBuilder, Operation, ValueClass},
+    BlobKind, CustomValueKey, Deserialize, Serialize,
+};
+
+use crate::{auth::authenticate::AccountKey, mailbox::INBOX_ID, services::housekeeper, JMAP};
+
+pub const JUNK_ROLE: &str = "junk";
+
+#[derive(Debug, Default)]
+pub struct SpamTrainReport {
+    queued: AtomicU64,
+    spam: AtomicU64,
+    ham: AtomicU64,
+    skipped: AtomicU64,
+    failed: AtomicU64,
+    running: AtomicBool,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct SpamTrainSummary {
+    pub queued: u64,
+    pub spam: u64,
+    pub ham: u64,
+    pub skipped: u64,
+    pub failed: u64,
+}
+
+impl JMAP {
+    // Returns the id of the Junk mailbox when moves to and from it should
+    // be used to train the spam filter.
+    pub async fn spam_train_junk_id(&self, account_id: u32) -> Result<Option<u32>, MethodError> {
+        if self.config.spam_train_enable && !self.spam_train_opted_out(account_id).await? {
+            self.mailbox_get_by_role(account_id, JUNK_ROLE).await
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub async fn spam_train_opted_out(&self, account_id: u32) -> Result<bool, MethodError> {
+        self.store
+            .get_value::<u64>(CustomValueKey {
+                value: AccountKey::spam_train_opt_out(account_id),
+            })
+            .await
+            .map(|value| value.is_some())
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve spam training preferences");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn set_spam_train_opt_out(
+        &self,
+        account_id: u32,
+        opt_out: bool,
+    ) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::spam_train_opt_out(account_id),
+                },
+                set: if opt_out {
+                    1u64.serialize().into()
+                } else {
+                    None
+                },
+            });
+        self.write_batch(batch).await
+    }
+
+    // Queues messages for training, the housekeeper trains them in batches.
+    // Pending requests are kept in the store so they survive restarts, a
+    // message moved again before it was trained is queued with its latest verdict.
+    pub async fn spam_train_enqueue(&self, account_id: u32, document_ids: Vec<u32>, is_spam: bool) {
+        if document_ids.is_empty() {
+            return;
+        }
+        let num_messages = document_ids.len();
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal);
+        for document_id in document_ids {
+            batch.op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::spam_train_pending(account_id, document_id),
+                },
+                set: u64::from(is_spam).serialize().into(),
+            });
+        }
+        if let Err(err) = self.store.write(batch.build()).await {
+            tracing::warn!(
+                context = "spam_train",
+                event = "error",
+                account_id = account_id,
+                error = ?err,
+                "Failed to queue messages for spam training."
+            );
+            return;
+        }
+        self.spam_train_report
+            .queued
+            .fetch_add(num_messages as u64, Ordering::Relaxed);
+
+        // Wake up the housekeeper when enough messages are pending
+        let _ = self
+            .housekeeper_tx
+            .send(housekeeper::Event::SpamTrain(num_messages))
+            .await;
+    }
+
+    pub async fn spam_train_pending(
+        &self,
+        max_results: usize,
+    ) -> store::Result<Vec<(u32, u32, bool)>> {
+        self.store
+            .iterate(
+                Vec::new(),
+                CustomValueKey {
+                    value: AccountKey::spam_train_pending(0, 0),
+                },
+                CustomValueKey {
+                    value: AccountKey::spam_train_pending(u32::MAX, u32::MAX),
+                },
+                false,
+                true,
+                move |pending, key, value| {
+                    // Skip the u32::MAX account prefix and the key type
+                    let offset = std::mem::size_of::<u32>() + 1;
+                    pending.push((
+                        key.deserialize_be_u32(offset)?,
+                        key.deserialize_be_u32(offset + std::mem::size_of::<u32>())?,
+                        u64::deserialize(value)? != 0,
+                    ));
+                    Ok(pending.len() < max_results)
+                },
+            )
+            .await
+    }
+
+    pub async fn spam_train_verdict(
+        &self,
+        account_id: u32,
+        document_id: u32,
+    ) -> store::Result<Option<bool>> {
+        self.store
+            .get_value::<u64>(CustomValueKey {
+                value: AccountKey::spam_train_verdict(account_id, document_id),
+            })
+            .await
+            .map(|verdict| verdict.map(|verdict| verdict != 0))
+    }
+
+    // Returns the pending requests and verdicts stored for an account
+    pub async fn spam_train_keys(&self, account_id: u32) -> store::Result<Vec<Vec<u8>>> {
+        let mut keys = Vec::new();
+        for (from_key, to_key) in [
+            (
+                AccountKey::spam_train_pending(account_id, 0),
+                AccountKey::spam_train_pending(account_id, u32::MAX),
+            ),
+            (
+                AccountKey::spam_train_verdict(account_id, 0),
+                AccountKey::spam_train_verdict(account_id, u32::MAX),
+            ),
+        ] {
+            keys = self
+                .store
+                .iterate(
+                    keys,
+                    CustomValueKey { value: from_key },
+                    CustomValueKey { value: to_key },
+                    false,
+                    true,
+                    move |keys, key, _| {
+                        keys.push(key.to_vec());
+                        Ok(true)
+                    },
+                )
+                .await?;
+        }
+        Ok(keys)
+    }
+
+    // Trains up to batch_size pending messages, returns true if more are pending.
+    pub async fn spam_train_batch(&self, batch_size: usize) -> store::Result<bool> {
+        if self.spam_train_report.running.swap(true, Ordering::Relaxed) {
+            return Ok(false);
+        }
+        let result = self.spam_train_pending_batch(batch_size).await;
+        self.spam_train_report
+            .running
+            .store(false, Ordering::Relaxed);
+        result
+    }
+
+    async fn spam_train_pending_batch(&self, batch_size: usize) -> store::Result<bool> {
+        let pending = self.spam_train_pending(batch_size).await?;
+        if pending.is_empty() {
+            return Ok(false);
+        }
+        let has_more = pending.len() >= batch_size;
+        let smtp = self.smtp.core();
+        let lookup = smtp.sieve.lookup.get(&self.config.spam_train_lookup);
+        if lookup.is_none() {
+            tracing::warn!(
+                context = "spam_train",
+                event = "error",
+                lookup_id = %self.config.spam_train_lookup,
+                "Unknown lookup id, spam training is not possible."
+            );
+        }
+        let span = tracing::debug_span!("spam_train");
+
+        for (account_id, document_id, is_spam) in pending {
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal)
+                .op(Operation::Value {
+                    class: ValueClass::Custom {
+                        bytes: AccountKey::spam_train_pending(account_id, document_id),
+                    },
+                    set: None,
+                });
+
+            let lookup = if let Some(lookup) = lookup {
+                lookup
+            } else {
+                self.spam_train_report
+                    .failed
+                    .fetch_add(1, Ordering::Relaxed);
+                self.store.write(batch.build()).await?;
+                continue;
+            };
+            let raw_message = match self
+                .get_blob(
+                    &BlobKind::LinkedMaildir {
+                        account_id,
+                        document_id,
+                    },
+                    0..u32::MAX,
+                )
+                .await
+            {
+                Ok(Some(raw_message)) => raw_message,
+                Ok(None) => {
+                    // The message was deleted before it could be trained
+                    self.spam_train_report
+                        .skipped
+                        .fetch_add(1, Ordering::Relaxed);
+                    batch.op(Operation::Value {
+                        class: ValueClass::Custom {
+                            bytes: AccountKey::spam_train_verdict(account_id, document_id),
+                        },
+                        set: None,
+                    });
+                    self.store.write(batch.build()).await?;
+                    continue;
+                }
+                Err(_) => {
+                    // Leave the request pending and retry on the next run
+                    self.spam_train_report
+                        .failed
+                        .fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+            };
+            let text = if let Some(message) = MessageParser::new().parse(&raw_message) {
+                format!(
+                    "{} {}",
+                    message.subject().unwrap_or_default(),
+                    message.body_text(0).unwrap_or_default()
+                )
+            } else {
+                self.spam_train_report
+                    .skipped
+                    .fetch_add(1, Ordering::Relaxed);
+                self.store.write(batch.build()).await?;
+                continue;
+            };
+
+            // Undo the previous verdict before training the new one
+            match self.spam_train_verdict(account_id, document_id).await? {
+                Some(previous) if previous == is_spam => {
+                    self.spam_train_report
+                        .skipped
+                        .fetch_add(1, Ordering::Relaxed);
+                    self.store.write(batch.build()).await?;
+                    continue;
+                }
+                Some(previous) => {
+                    if !smtp
+                        .bayes_train(lookup, &text, previous, false, &span)
+                        .await
+                    {
+                        tracing::debug!(
+                            context = "spam_train",
+                            event = "failed",
+                            account_id = account_id,
+                            document_id = document_id,
+                            is_spam = previous,
+                            "Failed to untrain previous spam filter verdict."
+                        );
+                    }
+                }
+                None => (),
+            }
+
+            if smtp.bayes_train(lookup, &text, is_spam, true, &span).await {
+                if is_spam {
+                    self.spam_train_report.spam.fetch_add(1, Ordering::Relaxed);
+                } else {
+                    self.spam_train_report.ham.fetch_add(1, Ordering::Relaxed);
+                }
+                batch.op(Operation::Value {
+                    class: ValueClass::Custom {
+                        bytes: AccountKey::spam_train_verdict(account_id, document_id),
+                    },
+                    set: u64::from(is_spam).serialize().into(),
+                });
+                tracing::info!(
+                    context = "spam_train",
+                    event = "learn",
+                    account_id = account_id,
+                    document_id = document_id,
+                    is_spam = is_spam,
+                    "Trained spam filter from mailbox move."
+                );
+            } else {
+                self.spam_train_report
+                    .failed
+                    .fetch_add(1, Ordering::Relaxed);
+                batch.op(Operation::Value {
+                    class: ValueClass::Custom {
+                        bytes: AccountKey::spam_train_verdict(account_id, document_id),
+                    },
+                    set: None,
+                });
+                tracing::debug!(
+                    context = "spam_train",
+                    event = "failed",
+                    account_id = account_id,
+                    document_id = document_id,
+                    is_spam = is_spam,
+                    "Failed to train spam filter."
+                );
+            }
+            self.store.write(batch.build()).await?;
+        }
+
+        Ok(has_more)
+    }
+}
+
+// Moving a message from the Inbox to Junk marks it as spam,
+// moving it back from Junk to the Inbox marks it as ham.
+pub fn spam_train_direction(junk_id: u32, removed: &[u32], added: &[u32]) -> Option<bool> {
+    if removed.contains(&INBOX_ID) && added.contains(&junk_id) {
+        Some(true)
+    } else if removed.contains(&junk_id) && added.contains(&INBOX_ID) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+impl SpamTrainReport {
+    pub fn summary(&self) -> SpamTrainSummary {
+        SpamTrainSummary {
+            queued: self.queued.load(Ordering::Relaxed),
+            spam: self.spam.load(Ordering::Relaxed),
+            ham: self.ham.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -21,17 +21,25 @@
  * for more details.
 */
 
-use std::{collections::hash_map::RandomState, sync::Arc, time::Duration};
+use std::{
+    collections::hash_map::RandomState,
+    sync::{atomic::AtomicU64, Arc},
+    time::{Duration, Instant},
+};
 
+use crate::sieve::limits::SieveLimits;
 use ::sieve::{Compiler, Runtime};
 use api::session::BaseCapabilities;
 use auth::{
-    oauth::OAuthCode,
+    oauth::jwt::{JwtAlgorithm, JwtKeys},
     rate_limit::{AnonymousLimiter, AuthenticatedLimiter, RemoteAddress},
+    sessions::LiveSessions,
+    tenant::Tenants,
     AccessToken,
 };
 use dashmap::DashMap;
 use directory::{Directory, DirectoryConfig};
+use email::spam_train::SpamTrainReport;
 use jmap_proto::{
     error::method::MethodError,
     method::{
@@ -41,23 +49,27 @@ use jmap_proto::{
     types::{collection::Collection, property::Property},
 };
 use nlp::language::Language;
+use principal::CachedPrincipal;
 use services::{
     delivery::spawn_delivery_manager,
     housekeeper::{self, init_housekeeper, spawn_housekeeper},
     state::{self, init_state_manager, spawn_state_manager},
 };
-use smtp::core::SMTP;
+use smtp::core::reload::SmtpHandle;
 use store::{
-    parking_lot::Mutex,
+    ahash::AHashMap,
+    parking_lot::{Mutex, RwLock},
     query::{sort::Pagination, Comparator, Filter, ResultSet, SortedResultSet},
     roaring::RoaringBitmap,
     write::{BatchBuilder, BitmapFamily, ToBitmaps},
     BitmapKey, Deserialize, Serialize, Store, ValueKey,
 };
+use submission::sent_copy::SentCopy;
 use tokio::sync::mpsc;
 use utils::{
-    config::Rate,
+    config::{certificate::ClientCertificateMap, Rate},
     ipc::DeliveryEvent,
+    listener::limiter::RateLimiter,
     map::ttl_dashmap::{TtlDashMap, TtlMap},
     UnwrapFailure,
 };
@@ -66,13 +78,19 @@ pub mod api;
 pub mod auth;
 pub mod blob;
 pub mod changes;
+pub mod collected_address;
 pub mod email;
 pub mod identity;
 pub mod mailbox;
+pub mod mailing_list;
+pub mod masked_email;
+pub mod mdn;
 pub mod principal;
 pub mod push;
 pub mod quota;
 pub mod services;
+pub mod settings;
+pub mod share_invitation;
 pub mod sieve;
 pub mod submission;
 pub mod thread;
@@ -88,18 +106,29 @@ pub struct JMAP {
 
     pub sessions: TtlDashMap<String, u32>,
     pub access_tokens: TtlDashMap<u32, Arc<AccessToken>>,
+    pub revoked_tokens: TtlDashMap<u32, Arc<Vec<u64>>>,
+    pub device_polls: TtlDashMap<String, (u64, Instant)>,
+    pub converted_parts: TtlDashMap<[u8; 32], Arc<String>>,
+    pub principals: TtlDashMap<u32, Arc<CachedPrincipal>>,
+    pub tmp_blob_usage: TtlDashMap<u32, (usize, usize)>,
+    pub sent_copies: TtlDashMap<(u32, String), SentCopy>,
 
     pub rate_limit_auth: DashMap<u32, Arc<Mutex<AuthenticatedLimiter>>>,
     pub rate_limit_unauth: DashMap<RemoteAddress, Arc<Mutex<AnonymousLimiter>>>,
+    pub rate_limit_tenant: DashMap<(String, RemoteAddress), Arc<Mutex<RateLimiter>>>,
 
-    pub oauth_codes: TtlDashMap<String, Arc<OAuthCode>>,
+    pub live_sessions: LiveSessions,
+    pub live_sessions_memory: Arc<AtomicU64>,
+    pub jwt_keys: RwLock<Arc<JwtKeys>>,
 
     pub state_tx: mpsc::Sender<state::Event>,
     pub housekeeper_tx: mpsc::Sender<housekeeper::Event>,
-    pub smtp: Arc<SMTP>,
+    pub smtp: SmtpHandle,
 
     pub sieve_compiler: Compiler,
     pub sieve_runtime: Runtime<()>,
+
+    pub spam_train_report: SpamTrainReport,
 }
 
 pub struct Config {
@@ -109,6 +138,7 @@ pub struct Config {
 
     pub request_max_size: usize,
     pub request_max_calls: usize,
+    pub request_max_cost: usize,
     pub request_max_concurrent: u64,
 
     pub get_max_objects: usize,
@@ -126,15 +156,24 @@ pub struct Config {
     pub mail_attachments_max_size: usize,
     pub mail_parse_max_items: usize,
     pub mail_max_size: usize,
+    pub mail_bimi_authserv_id: String,
 
     pub sieve_max_script_name: usize,
-    pub sieve_max_scripts: usize,
+    pub sieve_limits: SieveLimits,
+    pub sieve_limits_override: AHashMap<String, SieveLimits>,
 
     pub session_cache_ttl: Duration,
     pub rate_authenticated: Rate,
     pub rate_authenticate_req: Rate,
     pub rate_anonymous: Rate,
-    pub rate_use_forwarded: bool,
+    pub rate_method_calls: Rate,
+    pub rate_upload: Rate,
+    pub rate_oauth: Rate,
+    pub query_max_concurrent: u64,
+    pub query_large_results: usize,
+
+    pub tenants: Tenants,
+    pub client_cert_map: ClientCertificateMap,
 
     pub event_source_throttle: Duration,
     pub push_max_total: usize,
@@ -142,6 +181,9 @@ pub struct Config {
     pub web_socket_throttle: Duration,
     pub web_socket_timeout: Duration,
     pub web_socket_heartbeat: Duration,
+    pub web_socket_max_duration: Option<Duration>,
+
+    pub session_max_memory: Option<u64>,
 
     pub oauth_key: String,
     pub oauth_expiry_user_code: u64,
@@ -149,14 +191,78 @@ pub struct Config {
     pub oauth_expiry_token: u64,
     pub oauth_expiry_refresh_token: u64,
     pub oauth_expiry_refresh_token_renew: u64,
+    pub oauth_device_poll_interval: u64,
     pub oauth_max_auth_attempts: u32,
+    pub oauth_require_consent: bool,
+    pub oauth_jwt_algorithm: JwtAlgorithm,
+    pub oauth_jwt_issuer: String,
+    pub oauth_jwt_rotate: u64,
 
     pub http_headers: Vec<(hyper::header::HeaderName, hyper::header::HeaderValue)>,
+    pub http_trusted_proxies: Vec<smtp::config::IpAddrMask>,
+    pub http_cors: Option<api::cors::HttpCors>,
+    pub http_compression: Option<api::compression::HttpCompression>,
 
     pub encrypt: bool,
     pub encrypt_append: bool,
 
     pub principal_allow_lookups: bool,
+    pub admin_ui: bool,
+
+    pub settings_password_query: Option<String>,
+    pub settings_password_min_length: usize,
+    pub settings_totp_issuer: String,
+    pub settings_forward_max: usize,
+
+    pub mdn_reporting_ua: String,
+    pub mdn_auto_processed: bool,
+
+    pub catch_all_review_mailbox: Option<String>,
+    pub catch_all_claim_query: Option<String>,
+    pub account_delete_queries: Vec<String>,
+    pub account_rename_queries: Vec<String>,
+    pub account_address_query: Option<String>,
+    pub account_alias_query: Option<String>,
+    pub account_alias_expire_query: Option<String>,
+    pub account_alias_grace_period: Duration,
+
+    pub masked_email_domain: Option<String>,
+    pub masked_email_max: usize,
+
+    pub share_require_acceptance: bool,
+    pub share_invitation_email: bool,
+
+    pub spam_junk_folder: bool,
+    pub spam_train_enable: bool,
+    pub spam_train_lookup: String,
+
+    pub auto_collect: bool,
+    pub first_contact: bool,
+
+    pub mailing_lists: bool,
+    pub mailing_list_unsubscribe_timeout: Duration,
+    pub mailing_list_max_entries: usize,
+    pub digest_from: String,
+    pub digest_url: Option<String>,
+
+    pub attachment_link_threshold: usize,
+    pub attachment_link_expiry: u64,
+    pub attachment_link_url: Option<String>,
+
+    pub html_image_proxy: bool,
+    pub html_image_proxy_key: String,
+    pub html_image_proxy_max_size: usize,
+    pub html_image_proxy_timeout: Duration,
+    pub html_tracking_parameters: Vec<String>,
+
+    pub email_conversion_cache_size: usize,
+    pub email_conversion_cache_ttl: Duration,
+
+    pub dedup_window: u64,
+    pub dedup_max_window: u64,
+
+    pub sent_copy: bool,
+    pub sent_copy_window: u64,
 
     pub capabilities: BaseCapabilities,
 }
@@ -176,7 +282,7 @@ impl JMAP {
         config: &utils::config::Config,
         directory_config: &DirectoryConfig,
         delivery_rx: mpsc::Receiver<DeliveryEvent>,
-        smtp: Arc<SMTP>,
+        smtp: impl Into<SmtpHandle>,
     ) -> Result<Arc<Self>, String> {
         // Init state manager and housekeeper
         let (state_tx, state_rx) = init_state_manager();
@@ -186,17 +292,26 @@ impl JMAP {
             .unwrap_or(32)
             .next_power_of_two() as usize;
 
+        let jmap_config = Config::new(config).failed("Invalid configuration file");
+        let directory = jmap_config
+            .tenants
+            .build_directory(
+                directory_config
+                    .directories
+                    .get(config.value_require("jmap.directory")?)
+                    .failed(&format!(
+                        "Unable to find directory '{}'",
+                        config.value_require("jmap.directory")?
+                    ))
+                    .clone(),
+                directory_config,
+            )
+            .failed("Invalid tenant configuration");
+
         let jmap_server = Arc::new(JMAP {
-            directory: directory_config
-                .directories
-                .get(config.value_require("jmap.directory")?)
-                .failed(&format!(
-                    "Unable to find directory '{}'",
-                    config.value_require("jmap.directory")?
-                ))
-                .clone(),
+            directory,
             store: Store::open(config).await.failed("Unable to open database"),
-            config: Config::new(config).failed("Invalid configuration file"),
+            config: jmap_config,
             sessions: TtlDashMap::with_capacity(
                 config.property("jmap.session.cache.size")?.unwrap_or(100),
                 shard_amount,
@@ -205,6 +320,10 @@ impl JMAP {
                 config.property("jmap.session.cache.size")?.unwrap_or(100),
                 shard_amount,
             ),
+            revoked_tokens: TtlDashMap::with_capacity(
+                config.property("jmap.session.cache.size")?.unwrap_or(100),
+                shard_amount,
+            ),
             rate_limit_auth: DashMap::with_capacity_and_hasher_and_shard_amount(
                 config
                     .property("jmap.rate-limit.cache.size")?
@@ -219,13 +338,44 @@ impl JMAP {
                 RandomState::default(),
                 shard_amount,
             ),
-            oauth_codes: TtlDashMap::with_capacity(
-                config.property("oauth.cache.size")?.unwrap_or(128),
+            rate_limit_tenant: DashMap::with_capacity_and_hasher_and_shard_amount(
+                config
+                    .property("jmap.rate-limit.cache.size")?
+                    .unwrap_or(1024),
+                RandomState::default(),
+                shard_amount,
+            ),
+            device_polls: TtlDashMap::with_capacity(
+                config.property("jmap.session.cache.size")?.unwrap_or(100),
+                shard_amount,
+            ),
+            converted_parts: TtlDashMap::with_capacity(
+                config
+                    .property("jmap.email.conversion.cache.size")?
+                    .unwrap_or(1024),
+                shard_amount,
+            ),
+            principals: TtlDashMap::with_capacity(
+                config.property("jmap.session.cache.size")?.unwrap_or(100),
+                shard_amount,
+            ),
+            tmp_blob_usage: TtlDashMap::with_capacity(
+                config.property("jmap.session.cache.size")?.unwrap_or(100),
+                shard_amount,
+            ),
+            sent_copies: TtlDashMap::with_capacity(
+                config
+                    .property("jmap.submission.sent-copy.cache.size")?
+                    .unwrap_or(1024),
                 shard_amount,
             ),
+            live_sessions: Default::default(),
+            live_sessions_memory: Default::default(),
+            jwt_keys: Default::default(),
             state_tx,
             housekeeper_tx,
-            smtp,
+            smtp: smtp.into(),
+            spam_train_report: Default::default(),
             sieve_compiler: Compiler::new()
                 .with_max_script_size(
                     config
@@ -387,6 +537,24 @@ impl JMAP {
         Ok(jmap_server)
     }
 
+    // Parses and validates the configuration without opening the store.
+    pub fn check_config(
+        config: &utils::config::Config,
+        directory_config: &DirectoryConfig,
+    ) -> Result<(), String> {
+        let jmap_config = Config::new(config)?;
+        let directory_id = config.value_require("jmap.directory")?;
+        let directory = directory_config
+            .directories
+            .get(directory_id)
+            .ok_or_else(|| format!("Unable to find directory '{directory_id}'"))?
+            .clone();
+        jmap_config
+            .tenants
+            .build_directory(directory, directory_config)?;
+        Ok(())
+    }
+
     pub async fn assign_document_id(
         &self,
         account_id: u32,
@@ -575,9 +743,9 @@ impl JMAP {
     ) -> Result<i64, MethodError> {
         Ok(if access_token.primary_id == account_id {
             access_token.quota as i64
-        } else {
+        } else if let Some(name) = self.get_account_name(account_id).await? {
             self.directory
-                .principal(&access_token.name)
+                .principal(&name)
                 .await
                 .map_err(|err| {
                     tracing::error!(
@@ -590,6 +758,8 @@ impl JMAP {
                 })?
                 .map(|p| p.quota as i64)
                 .unwrap_or_default()
+        } else {
+            0
         })
     }
 
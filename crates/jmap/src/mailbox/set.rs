@@ -113,14 +113,29 @@ impl JMAP {
                     let document_id = self
                         .assign_document_id(account_id, Collection::Mailbox)
                         .await?;
+                    let acl = builder.get(&Property::Acl).clone();
+                    let name = builder.get(&Property::Name).clone();
                     batch
                         .with_account_id(account_id)
                         .with_collection(Collection::Mailbox)
                         .create_document(document_id)
                         .custom(builder);
+                    let invitations = self
+                        .share_invitations_prepare(
+                            &mut batch,
+                            account_id,
+                            document_id,
+                            &name,
+                            Some(&acl),
+                            None,
+                            access_token,
+                        )
+                        .await?;
                     changes.log_insert(Collection::Mailbox, document_id);
                     ctx.mailbox_ids.insert(document_id);
                     self.write_batch(batch).await?;
+                    self.share_invitations_notify(invitations, access_token)
+                        .await;
                     ctx.response.created(id, document_id);
                 }
                 Err(err) => {
@@ -153,7 +168,7 @@ impl JMAP {
             {
                 // Validate ACL
                 if ctx.is_shared {
-                    let acl = mailbox.inner.effective_acl(access_token);
+                    let acl = mailbox.inner.effective_acl(access_token, account_id);
                     if !acl.contains(Acl::Modify) {
                         ctx.response.not_updated.append(
                             id,
@@ -174,11 +189,22 @@ impl JMAP {
                     }
                 }
 
+                let current_acl = object
+                    .properties
+                    .contains_key(&Property::Acl)
+                    .then(|| mailbox.inner.get(&Property::Acl).clone());
                 match self
                     .mailbox_set_item(object, (document_id, mailbox).into(), &ctx)
                     .await?
                 {
                     Ok(builder) => {
+                        let acl_changes = current_acl.map(|current_acl| {
+                            (
+                                current_acl,
+                                builder.get(&Property::Acl).clone(),
+                                builder.get(&Property::Name).clone(),
+                            )
+                        });
                         let mut batch = BatchBuilder::new();
                         batch
                             .with_account_id(account_id)
@@ -186,9 +212,28 @@ impl JMAP {
                             .update_document(document_id)
                             .custom(builder);
                         if !batch.is_empty() {
+                            let invitations = if let Some((current_acl, acl, name)) = &acl_changes {
+                                self.share_invitations_prepare(
+                                    &mut batch,
+                                    account_id,
+                                    document_id,
+                                    name,
+                                    Some(acl),
+                                    Some(current_acl),
+                                    access_token,
+                                )
+                                .await?
+                                .into()
+                            } else {
+                                None
+                            };
                             match self.store.write(batch.build()).await {
                                 Ok(_) => {
                                     changes.log_update(Collection::Mailbox, document_id);
+                                    if let Some(invitations) = invitations {
+                                        self.share_invitations_notify(invitations, access_token)
+                                            .await;
+                                    }
                                 }
                                 Err(store::Error::AssertValueFailed) => {
                                     ctx.response.not_updated.append(id, SetError::forbidden().with_description(
@@ -416,7 +461,7 @@ impl JMAP {
         {
             // Validate ACLs
             if access_token.is_shared(account_id) {
-                let acl = mailbox.inner.effective_acl(access_token);
+                let acl = mailbox.inner.effective_acl(access_token, account_id);
                 if !acl.contains(Acl::Administer) {
                     if !acl.contains(Acl::Delete) {
                         return Ok(Err(SetError::forbidden()
@@ -429,6 +474,9 @@ impl JMAP {
                 }
             }
 
+            // Pending invitations to this mailbox are withdrawn
+            let acl = mailbox.inner.get(&Property::Acl).clone();
+            let name = mailbox.inner.get(&Property::Name).clone();
             let mut batch = BatchBuilder::new();
             batch
                 .with_account_id(account_id)
@@ -436,10 +484,23 @@ impl JMAP {
                 .delete_document(document_id)
                 .value(Property::EmailIds, (), F_VALUE | F_CLEAR)
                 .custom(ObjectIndexBuilder::new(SCHEMA).with_current(mailbox));
+            let invitations = self
+                .share_invitations_prepare(
+                    &mut batch,
+                    account_id,
+                    document_id,
+                    &name,
+                    None,
+                    Some(&acl),
+                    access_token,
+                )
+                .await?;
 
             match self.store.write(batch.build()).await {
                 Ok(_) => {
                     changes.log_delete(Collection::Mailbox, document_id);
+                    self.share_invitations_notify(invitations, access_token)
+                        .await;
                     Ok(Ok(did_remove_emails))
                 }
                 Err(store::Error::AssertValueFailed) => Ok(Err(SetError::forbidden()
@@ -529,7 +590,7 @@ impl JMAP {
                 (Property::Role, MaybePatchValue::Value(Value::Text(value))) => {
                     let role = value.trim().to_lowercase();
                     if [
-                        "inbox", "trash", "spam", "junk", "drafts", "archive", "sent",
+                        "inbox", "trash", "spam", "junk", "drafts", "archive", "sent", "snoozed",
                     ]
                     .contains(&role.as_str())
                     {
@@ -600,7 +661,7 @@ impl JMAP {
                     if depth == 0
                         && ctx.is_shared
                         && !fields
-                            .effective_acl(ctx.access_token)
+                            .effective_acl(ctx.access_token, ctx.account_id)
                             .contains_any([Acl::CreateChild, Acl::Administer].into_iter())
                     {
                         return Ok(Err(SetError::forbidden().with_description(
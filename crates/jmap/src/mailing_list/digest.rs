@@ -0,0 +1,436 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Write;
+
+use jmap_proto::{
+    error::method::MethodError,
+    object::{index::ObjectIndexBuilder, Object},
+    types::{collection::Collection, property::Property, value::Value},
+};
+use mail_builder::{headers::HeaderType, MessageBuilder};
+use mail_parser::{
+    decoders::html::html_to_text, parsers::preview::preview_text, MessageParser, PartType,
+};
+use store::{
+    write::{key::DeserializeBigEndian, now, BatchBuilder, Operation, ValueClass},
+    CustomValueKey, Deserialize, Serialize,
+};
+use utils::ipc::DeliveryResult;
+
+use crate::{
+    auth::authenticate::AccountKey, collected_address::first_contact::message_sender,
+    email::index::PREVIEW_LENGTH, mailbox::set::SCHEMA, Bincode, JMAP,
+};
+
+use super::ListHeaders;
+
+pub const DIGEST_ROLE: &str = "digest";
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct DigestItem {
+    pub email_id: String,
+    pub list_id: Option<String>,
+    pub list_name: Option<String>,
+    pub from: Option<String>,
+    pub subject: Option<String>,
+    pub preview: String,
+    pub received: u64,
+}
+
+impl JMAP {
+    pub async fn digest_enabled(&self, account_id: u32) -> Result<bool, MethodError> {
+        self.store
+            .get_value::<u64>(CustomValueKey {
+                value: AccountKey::digest_enabled(account_id),
+            })
+            .await
+            .map(|value| value.is_some())
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve digest preferences");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn set_digest_enabled(
+        &self,
+        account_id: u32,
+        enabled: bool,
+    ) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::digest_enabled(account_id),
+                },
+                set: if enabled {
+                    now().serialize().into()
+                } else {
+                    None
+                },
+            });
+        self.write_batch(batch).await
+    }
+
+    // Held newsletters are kept in their own mailbox until the digest is sent
+    pub async fn mailbox_get_or_create_digest(&self, account_id: u32) -> Result<u32, MethodError> {
+        self.mailbox_get_or_create(account_id).await?;
+        if let Some(mailbox_id) = self.mailbox_get_by_role(account_id, DIGEST_ROLE).await? {
+            return Ok(mailbox_id);
+        }
+
+        let mailbox_id = self
+            .assign_document_id(account_id, Collection::Mailbox)
+            .await?;
+        let mut changes = self.begin_changes(account_id).await?;
+        changes.log_insert(Collection::Mailbox, mailbox_id);
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(account_id)
+            .with_collection(Collection::Mailbox)
+            .create_document(mailbox_id)
+            .custom(
+                ObjectIndexBuilder::new(SCHEMA).with_changes(
+                    Object::with_capacity(3)
+                        .with_property(Property::Name, "Digest")
+                        .with_property(Property::Role, DIGEST_ROLE)
+                        .with_property(Property::ParentId, Value::Id(0u64.into())),
+                ),
+            )
+            .custom(changes);
+        self.write_batch(batch).await?;
+
+        Ok(mailbox_id)
+    }
+
+    pub async fn hold_for_digest(
+        &self,
+        account_id: u32,
+        document_id: u32,
+        item: DigestItem,
+    ) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::digest_item(account_id, document_id),
+                },
+                set: Bincode::new(item).serialize().into(),
+            });
+        self.write_batch(batch).await
+    }
+
+    pub async fn digest_items(&self, account_id: u32) -> store::Result<Vec<(u32, DigestItem)>> {
+        self.store
+            .iterate(
+                Vec::new(),
+                CustomValueKey {
+                    value: AccountKey::digest_item(account_id, 0),
+                },
+                CustomValueKey {
+                    value: AccountKey::digest_item(account_id, u32::MAX),
+                },
+                false,
+                true,
+                move |items, key, value| {
+                    // Skip the u32::MAX account prefix, the key type and the account id
+                    items.push((
+                        key.deserialize_be_u32(2 * std::mem::size_of::<u32>() + 1)?,
+                        Bincode::<DigestItem>::deserialize(value)?.inner,
+                    ));
+                    Ok(true)
+                },
+            )
+            .await
+    }
+
+    // Returns the next account with held items, starting at the given account id
+    async fn next_digest_account(&self, from_account_id: u32) -> store::Result<Option<u32>> {
+        self.store
+            .iterate(
+                None,
+                CustomValueKey {
+                    value: AccountKey::digest_item(from_account_id, 0),
+                },
+                CustomValueKey {
+                    value: AccountKey::digest_item(u32::MAX, u32::MAX),
+                },
+                true,
+                true,
+                move |account_id, key, _| {
+                    // Skip the u32::MAX account prefix and the key type
+                    *account_id = key
+                        .deserialize_be_u32(std::mem::size_of::<u32>() + 1)?
+                        .into();
+                    Ok(false)
+                },
+            )
+            .await
+    }
+
+    // Digests are sent one account at a time, failures are retried on the next run
+    pub async fn send_digests(&self) -> Result<(), MethodError> {
+        let mut from_account_id = 0;
+        while let Some(account_id) =
+            self.next_digest_account(from_account_id)
+                .await
+                .map_err(|err| {
+                    tracing::error!(
+                    event = "error",
+                    context = "digest",
+                    error = ?err,
+                    "Failed to obtain digest accounts.");
+                    MethodError::ServerPartialFail
+                })?
+        {
+            match self.digest_items(account_id).await {
+                Ok(items) => {
+                    if let Err(err) = self.send_digest(account_id, items).await {
+                        tracing::error!(
+                            event = "error",
+                            context = "digest",
+                            account_id = account_id,
+                            error = ?err,
+                            "Failed to send digest.");
+                    }
+                }
+                Err(err) => {
+                    tracing::error!(
+                        event = "error",
+                        context = "digest",
+                        account_id = account_id,
+                        error = ?err,
+                        "Failed to obtain digest items.");
+                }
+            }
+
+            if account_id == u32::MAX {
+                break;
+            }
+            from_account_id = account_id + 1;
+        }
+
+        Ok(())
+    }
+
+    async fn send_digest(
+        &self,
+        account_id: u32,
+        items: Vec<(u32, DigestItem)>,
+    ) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal);
+        for (document_id, _) in &items {
+            batch.op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::digest_item(account_id, *document_id),
+                },
+                set: None,
+            });
+        }
+
+        // Items of deleted accounts are discarded
+        let name = if let Some(name) = self.get_account_name(account_id).await? {
+            name
+        } else {
+            return self.write_batch(batch).await;
+        };
+        let rcpt = self
+            .directory
+            .emails_by_name(&name)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| name.clone());
+
+        // Skip messages deleted since they were held
+        let document_ids = self
+            .get_document_ids(account_id, Collection::Email)
+            .await?
+            .unwrap_or_default();
+        let items = items
+            .into_iter()
+            .filter(|(document_id, _)| document_ids.contains(*document_id))
+            .collect::<Vec<_>>();
+        if items.is_empty() {
+            return self.write_batch(batch).await;
+        }
+
+        // Group items by list
+        let mut lists: Vec<(Option<String>, Option<String>, Vec<DigestItem>)> = Vec::new();
+        let num_items = items.len();
+        for (_, item) in items {
+            if let Some(list) = lists
+                .iter_mut()
+                .find(|(list_id, _, _)| list_id == &item.list_id)
+            {
+                list.2.push(item);
+            } else {
+                lists.push((item.list_id.clone(), item.list_name.clone(), vec![item]));
+            }
+        }
+        let mut body = format!(
+            "You received {num_items} newsletter message{} since your last digest.\r\n",
+            if num_items == 1 { "" } else { "s" }
+        );
+        for (list_id, list_name, mut items) in lists {
+            items.sort_unstable_by(|a, b| b.received.cmp(&a.received));
+            let title = match (&list_name, &list_id) {
+                (Some(name), Some(id)) => format!("{name} <{id}>"),
+                (None, Some(id)) => id.to_string(),
+                _ => "Other newsletters".to_string(),
+            };
+            let _ = write!(body, "\r\n{title}\r\n{}\r\n", "-".repeat(title.len()));
+            for item in items {
+                let _ = write!(
+                    body,
+                    "\r\n* {}\r\n",
+                    item.subject.as_deref().unwrap_or("(no subject)")
+                );
+                if let Some(from) = &item.from {
+                    let _ = write!(body, "  From: {from}\r\n");
+                }
+                if !item.preview.is_empty() {
+                    let _ = write!(body, "  {}\r\n", item.preview.replace('\n', " "));
+                }
+                if let Some(url) = &self.config.digest_url {
+                    let _ = write!(body, "  {}\r\n", url.replace("{id}", &item.email_id));
+                }
+            }
+
+            // Offer a way out of the list
+            if let Some(url) = match &list_id {
+                Some(list_id) => {
+                    self.get_mailing_list(account_id, list_id)
+                        .await?
+                        .and_then(|entry| {
+                            entry
+                                .unsubscribe
+                                .into_iter()
+                                .find(|uri| uri.starts_with("https://"))
+                        })
+                }
+                None => None,
+            } {
+                let _ = write!(body, "\r\n  Unsubscribe: {url}\r\n");
+            }
+        }
+
+        let message = MessageBuilder::new()
+            .from(("Daily digest", self.config.digest_from.as_str()))
+            .to(rcpt.as_str())
+            .subject(format!(
+                "Your daily digest: {num_items} new message{}",
+                if num_items == 1 { "" } else { "s" }
+            ))
+            .header("Auto-Submitted", HeaderType::Text("auto-generated".into()))
+            .text_body(body)
+            .write_to_vec()
+            .unwrap_or_default();
+
+        match self.deliver_to_account(&message, "", &rcpt, &name).await {
+            DeliveryResult::Success => self.write_batch(batch).await,
+            _ => {
+                // Try again on the next run
+                tracing::debug!(
+                    context = "digest",
+                    event = "error",
+                    account_id = account_id,
+                    "Failed to deliver digest."
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+// Returns true for messages sent in bulk by mailing lists and newsletters
+pub fn is_newsletter(raw_message: &[u8]) -> bool {
+    MessageParser::new()
+        .parse(raw_message)
+        .and_then(|message| message.parts.into_iter().next())
+        .map_or(false, |part| {
+            part.headers.iter().any(|header| {
+                let name = header.name.as_str();
+                name.eq_ignore_ascii_case("List-Id")
+                    || name.eq_ignore_ascii_case("List-Unsubscribe")
+                    || (name.eq_ignore_ascii_case("Precedence")
+                        && header.value.as_text().map_or(false, |value| {
+                            ["bulk", "list", "junk"]
+                                .iter()
+                                .any(|p| value.trim().eq_ignore_ascii_case(p))
+                        }))
+            })
+        })
+}
+
+pub fn digest_item(
+    raw_message: &[u8],
+    email_id: String,
+    mailing_list: Option<&ListHeaders>,
+) -> DigestItem {
+    let message = MessageParser::new().parse(raw_message);
+    DigestItem {
+        email_id,
+        list_id: mailing_list.map(|list| list.id.clone()),
+        list_name: mailing_list.and_then(|list| list.name.clone()),
+        from: message_sender(raw_message),
+        subject: message
+            .as_ref()
+            .and_then(|message| message.subject())
+            .map(|subject| subject.to_string()),
+        preview: message
+            .as_ref()
+            .and_then(|message| {
+                message
+                    .text_body
+                    .first()
+                    .or_else(|| message.html_body.first())
+                    .and_then(|idx| message.parts.get(*idx))
+                    .map(|part| match &part.body {
+                        PartType::Text(text) => {
+                            preview_text(text.replace('\r', "").into(), PREVIEW_LENGTH).into_owned()
+                        }
+                        PartType::Html(html) => preview_text(
+                            html_to_text(html).replace('\r', "").into(),
+                            PREVIEW_LENGTH,
+                        )
+                        .into_owned(),
+                        _ => String::new(),
+                    })
+            })
+            .unwrap_or_default(),
+        received: now(),
+    }
+}
@@ -0,0 +1,112 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    method::mailing_list::{MailingList, MailingListGetRequest, MailingListGetResponse},
+    types::{collection::Collection, date::UTCDate, id::Id},
+};
+
+use crate::JMAP;
+
+use super::MailingListEntry;
+
+impl JMAP {
+    pub async fn mailing_list_get(
+        &self,
+        request: MailingListGetRequest,
+    ) -> Result<MailingListGetResponse, MethodError> {
+        let account_id = request.account_id.document_id();
+        let mut lists = self.mailing_lists(account_id).await.map_err(|err| {
+            tracing::error!(
+                    event = "error",
+                    context = "mailing_list",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to obtain mailing lists.");
+            MethodError::ServerPartialFail
+        })?;
+
+        // Most recently active lists first
+        lists.sort_unstable_by(|(_, a), (_, b)| b.last_seen.cmp(&a.last_seen));
+
+        let mut not_found = Vec::new();
+        if let Some(ids) = request.ids {
+            if ids.len() > self.config.get_max_objects {
+                return Err(MethodError::RequestTooLarge);
+            }
+            let mut list_ids = Vec::with_capacity(ids.len());
+            for id in ids {
+                let list_id = id.trim().to_lowercase();
+                if lists.iter().any(|(existing_id, _)| existing_id == &list_id) {
+                    list_ids.push(list_id);
+                } else {
+                    not_found.push(id);
+                }
+            }
+            lists.retain(|(list_id, _)| list_ids.contains(list_id));
+        } else {
+            lists.truncate(self.config.get_max_objects);
+        }
+
+        // Filing into deleted mailboxes is not reported
+        let mailbox_ids = self
+            .get_document_ids(account_id, Collection::Mailbox)
+            .await?
+            .unwrap_or_default();
+
+        Ok(MailingListGetResponse {
+            account_id: request.account_id,
+            list: lists
+                .into_iter()
+                .map(|(id, entry)| mailing_list(id, entry, |id| mailbox_ids.contains(id)))
+                .collect(),
+            not_found,
+        })
+    }
+}
+
+fn mailing_list(
+    id: String,
+    entry: MailingListEntry,
+    mailbox_exists: impl Fn(u32) -> bool,
+) -> MailingList {
+    MailingList {
+        id,
+        name: entry.name,
+        sender: entry.sender,
+        mailbox_id: entry
+            .mailbox_id
+            .filter(|id| mailbox_exists(*id))
+            .map(Id::from),
+        unsubscribe: entry.unsubscribe,
+        one_click: entry.one_click,
+        digest: !entry.skip_digest,
+        total_emails: entry.count,
+        first_seen_at: UTCDate::from_timestamp(entry.first_seen as i64),
+        last_seen_at: UTCDate::from_timestamp(entry.last_seen as i64),
+        unsubscribed_at: entry
+            .unsubscribed_at
+            .map(|at| UTCDate::from_timestamp(at as i64)),
+    }
+}
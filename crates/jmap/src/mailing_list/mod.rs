@@ -0,0 +1,302 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{error::method::MethodError, types::collection::Collection};
+use mail_parser::MessageParser;
+use store::{
+    write::{now, BatchBuilder, Operation, ValueClass},
+    CustomValueKey, Deserialize, Serialize,
+};
+
+use crate::{
+    auth::authenticate::AccountKey, collected_address::first_contact::message_sender,
+    email::ingest::authenticated_domains, settings::sender_list::sender_matches, Bincode, JMAP,
+};
+
+pub mod digest;
+pub mod get;
+pub mod set;
+pub mod unsubscribe;
+
+// Longest List-Id accepted, RFC 2919 limits the list-id to 255 octets
+const MAX_LIST_ID_LEN: usize = 255;
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct MailingListEntry {
+    pub name: Option<String>,
+    pub sender: Option<String>,
+    pub unsubscribe: Vec<String>,
+    pub one_click: bool,
+    pub mailbox_id: Option<u32>,
+    pub count: u64,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub unsubscribed_at: Option<u64>,
+    pub skip_digest: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ListHeaders {
+    pub id: String,
+    pub name: Option<String>,
+    pub sender: Option<String>,
+    pub unsubscribe: Vec<String>,
+    pub one_click: bool,
+}
+
+impl JMAP {
+    pub async fn record_mailing_list(
+        &self,
+        account_id: u32,
+        headers: &ListHeaders,
+    ) -> Result<(), MethodError> {
+        let now = now();
+        let mut entry = if let Some(entry) = self.get_mailing_list(account_id, &headers.id).await? {
+            entry
+        } else if self
+            .mailing_lists(account_id)
+            .await
+            .map_err(|err| {
+                tracing::error!(
+                    event = "error",
+                    context = "mailing_list",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to list mailing lists.");
+                MethodError::ServerPartialFail
+            })?
+            .len()
+            < self.config.mailing_list_max_entries
+        {
+            MailingListEntry {
+                first_seen: now,
+                ..Default::default()
+            }
+        } else {
+            tracing::debug!(
+                context = "mailing_list",
+                event = "skip",
+                account_id = account_id,
+                list_id = headers.id,
+                "Mailing list limit reached, list not recorded."
+            );
+            return Ok(());
+        };
+        entry.count += 1;
+        entry.last_seen = now;
+        if headers.name.is_some() {
+            entry.name = headers.name.clone();
+        }
+        if headers.sender.is_some() {
+            entry.sender = headers.sender.clone();
+        }
+        if !headers.unsubscribe.is_empty() {
+            entry.unsubscribe = headers.unsubscribe.clone();
+            entry.one_click = headers.one_click;
+        }
+
+        self.set_mailing_list(account_id, &headers.id, Some(entry))
+            .await
+    }
+
+    pub async fn get_mailing_list(
+        &self,
+        account_id: u32,
+        list_id: &str,
+    ) -> Result<Option<MailingListEntry>, MethodError> {
+        self.store
+            .get_value::<Bincode<MailingListEntry>>(CustomValueKey {
+                value: AccountKey::mailing_list(account_id, list_id),
+            })
+            .await
+            .map(|entry| entry.map(|entry| entry.inner))
+            .map_err(|err| {
+                tracing::error!(
+                    event = "error",
+                    context = "mailing_list",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve mailing list.");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn set_mailing_list(
+        &self,
+        account_id: u32,
+        list_id: &str,
+        entry: Option<MailingListEntry>,
+    ) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::mailing_list(account_id, list_id),
+                },
+                set: entry.map(|entry| Bincode::new(entry).serialize()),
+            });
+        self.write_batch(batch).await
+    }
+
+    pub async fn mailing_lists(
+        &self,
+        account_id: u32,
+    ) -> store::Result<Vec<(String, MailingListEntry)>> {
+        self.store
+            .iterate(
+                Vec::new(),
+                CustomValueKey {
+                    value: AccountKey::mailing_list(account_id, ""),
+                },
+                CustomValueKey {
+                    value: AccountKey::mailing_list(account_id + 1, ""),
+                },
+                false,
+                true,
+                move |lists, key, value| {
+                    // Skip the u32::MAX account prefix, the key type and the account id
+                    let offset = std::mem::size_of::<u32>() * 2 + 1;
+                    if let Some(list_id) = key
+                        .get(offset..)
+                        .and_then(|list_id| std::str::from_utf8(list_id).ok())
+                    {
+                        lists.push((
+                            list_id.to_string(),
+                            Bincode::<MailingListEntry>::deserialize(value)?.inner,
+                        ));
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+    }
+}
+
+impl ListHeaders {
+    // List headers are easily forged, so lists are only recorded from messages
+    // with a DKIM signature or DMARC pass aligned with the list sender.
+    pub fn is_authenticated(&self, raw_message: &[u8], authserv_id: &str) -> bool {
+        self.sender.as_deref().map_or(false, |sender| {
+            authenticated_domains(raw_message, authserv_id, &["dmarc", "dkim"])
+                .iter()
+                .any(|domain| sender_matches(domain, sender))
+        })
+    }
+}
+
+// Obtains the RFC 2919 and RFC 2369 list headers of a message
+pub fn list_headers(raw_message: &[u8]) -> Option<ListHeaders> {
+    let message = MessageParser::new().parse(raw_message)?;
+    let mut headers = ListHeaders::default();
+
+    for header in &message.parts.first()?.headers {
+        let value = raw_message
+            .get(header.offset_start..header.offset_end)
+            .and_then(|value| std::str::from_utf8(value).ok())
+            .unwrap_or_default();
+        let name = header.name.as_str();
+        if name.eq_ignore_ascii_case("List-Id") && headers.id.is_empty() {
+            let (name, id) = parse_list_id(value)?;
+            headers.id = id;
+            headers.name = name;
+        } else if name.eq_ignore_ascii_case("List-Unsubscribe") {
+            headers.unsubscribe.extend(
+                parse_angle_uris(value)
+                    .into_iter()
+                    .filter(|uri| uri.starts_with("mailto:") || uri.starts_with("https://")),
+            );
+        } else if name.eq_ignore_ascii_case("List-Unsubscribe-Post") {
+            headers.one_click = unfold(value).eq_ignore_ascii_case("List-Unsubscribe=One-Click");
+        }
+    }
+
+    if !headers.id.is_empty() {
+        // RFC 8058 one-click requires an HTTPS URI
+        headers.one_click &= headers
+            .unsubscribe
+            .iter()
+            .any(|uri| uri.starts_with("https://"));
+        headers.sender = message_sender(raw_message);
+        Some(headers)
+    } else {
+        None
+    }
+}
+
+fn parse_list_id(value: &str) -> Option<(Option<String>, String)> {
+    let value = unfold(value);
+    let (name, id) = if let Some((name, rest)) = value.rsplit_once('<') {
+        (name, rest.split_once('>')?.0)
+    } else {
+        ("", value.as_str())
+    };
+    let id = id.trim().to_lowercase();
+    if !id.is_empty()
+        && id.len() <= MAX_LIST_ID_LEN
+        && !id.contains(|ch: char| ch.is_whitespace() || ch == '<' || ch == '>')
+    {
+        let name = name.trim().trim_matches('"').trim();
+        Some((
+            if !name.is_empty() {
+                Some(name.to_string())
+            } else {
+                None
+            },
+            id,
+        ))
+    } else {
+        None
+    }
+}
+
+fn parse_angle_uris(value: &str) -> Vec<String> {
+    let value = unfold(value);
+    let mut uris = Vec::new();
+    let mut rest = value.as_str();
+    while let Some((_, uri)) = rest.split_once('<') {
+        if let Some((uri, next)) = uri.split_once('>') {
+            let uri = uri
+                .chars()
+                .filter(|ch| !ch.is_whitespace())
+                .collect::<String>();
+            if !uri.is_empty() {
+                uris.push(uri);
+            }
+            rest = next;
+        } else {
+            break;
+        }
+    }
+    uris
+}
+
+fn unfold(value: &str) -> String {
+    value
+        .split(['\r', '\n'])
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
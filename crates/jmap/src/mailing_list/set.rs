@@ -0,0 +1,89 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::{
+        method::MethodError,
+        set::{SetError, SetErrorType},
+    },
+    method::mailing_list::{MailingListSetRequest, MailingListSetResponse},
+    types::collection::Collection,
+};
+use utils::map::vec_map::VecMap;
+
+use crate::JMAP;
+
+impl JMAP {
+    pub async fn mailing_list_set(
+        &self,
+        request: MailingListSetRequest,
+    ) -> Result<MailingListSetResponse, MethodError> {
+        if request.update.len() > self.config.set_max_objects {
+            return Err(MethodError::RequestTooLarge);
+        }
+
+        let account_id = request.account_id.document_id();
+        let mailbox_ids = self
+            .get_document_ids(account_id, Collection::Mailbox)
+            .await?
+            .unwrap_or_default();
+        let mut response = MailingListSetResponse {
+            account_id: request.account_id,
+            updated: Vec::new(),
+            not_updated: VecMap::new(),
+        };
+
+        for (id, update) in request.update {
+            let list_id = id.trim().to_lowercase();
+            let mut entry = if let Some(entry) = self.get_mailing_list(account_id, &list_id).await?
+            {
+                entry
+            } else {
+                response.not_updated.append(id, SetError::not_found());
+                continue;
+            };
+
+            // Messages from the list are filed into the mailbox on delivery
+            if let Some(mailbox_id) = update.mailbox_id {
+                let mailbox_id = mailbox_id.map(|id| id.document_id());
+                if mailbox_id.map_or(false, |id| !mailbox_ids.contains(id)) {
+                    response.not_updated.append(
+                        id,
+                        SetError::new(SetErrorType::InvalidProperties)
+                            .with_description("Mailbox does not exist."),
+                    );
+                    continue;
+                }
+                entry.mailbox_id = mailbox_id;
+            }
+            if let Some(digest) = update.digest {
+                entry.skip_digest = !digest;
+            }
+            self.set_mailing_list(account_id, &list_id, Some(entry))
+                .await?;
+            response.updated.push(id);
+        }
+
+        Ok(response)
+    }
+}
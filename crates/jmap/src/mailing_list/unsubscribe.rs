@@ -0,0 +1,245 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::{
+        method::MethodError,
+        set::{SetError, SetErrorType},
+    },
+    method::mailing_list::{MailingListUnsubscribeRequest, MailingListUnsubscribeResponse},
+};
+use mail_builder::{headers::HeaderType, MessageBuilder};
+use reqwest::header::CONTENT_TYPE;
+use smtp::core::{NullIo, Session, SessionAddress};
+use store::write::now;
+use utils::{
+    map::vec_map::VecMap,
+    ssrf::{is_public_url, public_client},
+};
+
+use crate::JMAP;
+
+use super::MailingListEntry;
+
+const MAX_REDIRECTS: usize = 5;
+
+impl JMAP {
+    pub async fn mailing_list_unsubscribe(
+        &self,
+        request: MailingListUnsubscribeRequest,
+    ) -> Result<MailingListUnsubscribeResponse, MethodError> {
+        if request.ids.len() > self.config.set_max_objects {
+            return Err(MethodError::RequestTooLarge);
+        }
+
+        // Unsubscribe requests sent by email come from the account's primary address
+        let account_id = request.account_id.document_id();
+        let from = if let Some(name) = self.get_account_name(account_id).await? {
+            self.directory
+                .emails_by_name(&name)
+                .await
+                .unwrap_or_default()
+                .into_iter()
+                .next()
+        } else {
+            None
+        };
+        let mut response = MailingListUnsubscribeResponse {
+            account_id: request.account_id,
+            unsubscribed: Vec::new(),
+            not_unsubscribed: VecMap::new(),
+        };
+
+        for id in request.ids {
+            let list_id = id.trim().to_lowercase();
+            let mut entry = if let Some(entry) = self.get_mailing_list(account_id, &list_id).await?
+            {
+                entry
+            } else {
+                response.not_unsubscribed.append(id, SetError::not_found());
+                continue;
+            };
+
+            match self
+                .unsubscribe_from_list(&list_id, &entry, from.as_deref())
+                .await
+            {
+                Ok(_) => {
+                    entry.unsubscribed_at = now().into();
+                    self.set_mailing_list(account_id, &list_id, Some(entry))
+                        .await?;
+                    response.unsubscribed.push(id);
+                }
+                Err(err) => {
+                    response.not_unsubscribed.append(id, err);
+                }
+            }
+        }
+
+        Ok(response)
+    }
+
+    async fn unsubscribe_from_list(
+        &self,
+        list_id: &str,
+        entry: &MailingListEntry,
+        from: Option<&str>,
+    ) -> Result<(), SetError> {
+        // Prefer RFC 8058 one-click unsubscribe, plain HTTPS links require user
+        // interaction and are left to the client
+        if entry.one_click {
+            for url in entry
+                .unsubscribe
+                .iter()
+                .filter(|uri| uri.starts_with("https://"))
+            {
+                if self.one_click_unsubscribe(list_id, url).await {
+                    return Ok(());
+                }
+            }
+        }
+
+        let mut has_mailto = false;
+        for uri in entry
+            .unsubscribe
+            .iter()
+            .filter_map(|uri| uri.strip_prefix("mailto:"))
+        {
+            has_mailto = true;
+            if let Some(from) = from {
+                if self.mailto_unsubscribe(list_id, uri, from).await {
+                    return Ok(());
+                }
+            }
+        }
+
+        Err(if entry.one_click || has_mailto {
+            SetError::new(SetErrorType::Forbidden)
+                .with_description("Failed to deliver the unsubscribe request.")
+        } else {
+            SetError::new(SetErrorType::Forbidden)
+                .with_description("The list does not support automatic unsubscribe requests.")
+        })
+    }
+
+    async fn one_click_unsubscribe(&self, list_id: &str, url: &str) -> bool {
+        // Unsubscribe URLs are set by the sender, only public addresses are contacted
+        if !reqwest::Url::parse(url).map_or(false, |url| is_public_url(&url)) {
+            return false;
+        }
+        let client_builder = public_client(
+            reqwest::Client::builder().timeout(self.config.mailing_list_unsubscribe_timeout),
+            MAX_REDIRECTS,
+        );
+
+        #[cfg(feature = "test_mode")]
+        let client_builder = client_builder.danger_accept_invalid_certs(true);
+
+        let client = match client_builder.build() {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::error!(
+                    context = "mailing_list",
+                    event = "error",
+                    reason = %err,
+                    "Failed to build HTTP client");
+                return false;
+            }
+        };
+        match client
+            .post(url)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body("List-Unsubscribe=One-Click")
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_success() => true,
+            Ok(response) => {
+                tracing::debug!(
+                    context = "mailing_list",
+                    event = "unsubscribe",
+                    list_id = list_id,
+                    url = url,
+                    status = response.status().as_u16(),
+                    "One-click unsubscribe request was rejected."
+                );
+                false
+            }
+            Err(err) => {
+                tracing::debug!(
+                    context = "mailing_list",
+                    event = "unsubscribe",
+                    list_id = list_id,
+                    url = url,
+                    reason = %err,
+                    "One-click unsubscribe request failed."
+                );
+                false
+            }
+        }
+    }
+
+    async fn mailto_unsubscribe(&self, list_id: &str, uri: &str, from: &str) -> bool {
+        // RFC 6068 mailto URIs may carry the subject and body to use
+        let (address, query) = uri.split_once('?').unwrap_or((uri, ""));
+        let mut subject = None;
+        let mut body = None;
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            if name.eq_ignore_ascii_case("subject") {
+                subject = value.into_owned().into();
+            } else if name.eq_ignore_ascii_case("body") {
+                body = value.into_owned().into();
+            }
+        }
+        let address = address.trim();
+        if address.is_empty() || !address.contains('@') {
+            return false;
+        }
+
+        let message = MessageBuilder::new()
+            .from(from)
+            .to(address)
+            .subject(subject.unwrap_or_else(|| "unsubscribe".to_string()))
+            .header("Auto-Submitted", HeaderType::Text("auto-generated".into()))
+            .text_body(body.unwrap_or_else(|| "unsubscribe".to_string()))
+            .write_to_vec()
+            .unwrap_or_default();
+        let mut session = Session::<NullIo>::sieve(
+            self.smtp.core(),
+            SessionAddress::new(from.to_string()),
+            vec![SessionAddress::new(address.to_string())],
+            message,
+        );
+        let result = session.queue_message().await;
+
+        tracing::debug!(
+            context = "mailing_list",
+            event = "unsubscribe",
+            list_id = list_id,
+            rcpt = address,
+            smtp_response = std::str::from_utf8(&result).unwrap_or_default()
+        );
+
+        result.first() == Some(&b'2')
+    }
+}
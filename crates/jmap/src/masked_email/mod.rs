@@ -0,0 +1,184 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    object::Object,
+    types::{
+        collection::Collection, date::UTCDate, property::Property, state::StateChange,
+        type_state::DataType, value::Value,
+    },
+};
+use store::{
+    write::{assert::HashedValue, log::ChangeLogBuilder, now, BatchBuilder, F_VALUE},
+    CustomValueKey,
+};
+use utils::ipc::MaskedEmailStatus;
+
+use crate::{auth::authenticate::AccountKey, JMAP};
+
+pub mod get;
+pub mod set;
+
+pub const STATE_PENDING: &str = "pending";
+pub const STATE_ENABLED: &str = "enabled";
+pub const STATE_DISABLED: &str = "disabled";
+pub const STATE_DELETED: &str = "deleted";
+
+pub struct MaskedEmailRoute {
+    pub account_id: u32,
+    pub document_id: u32,
+    pub is_enabled: bool,
+}
+
+impl JMAP {
+    pub async fn masked_email_route(
+        &self,
+        address: &str,
+    ) -> Result<Option<MaskedEmailRoute>, MethodError> {
+        let id = if let Some(id) = self
+            .store
+            .get_value::<u64>(CustomValueKey {
+                value: AccountKey::masked_email(&address.to_lowercase()),
+            })
+            .await
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                context = "store",
+                address = address,
+                error = ?err,
+                "Failed to retrieve masked email address");
+                MethodError::ServerPartialFail
+            })? {
+            id
+        } else {
+            return Ok(None);
+        };
+        let account_id = (id >> 32) as u32;
+        let document_id = id as u32;
+
+        Ok(self
+            .get_property::<Object<Value>>(
+                account_id,
+                Collection::MaskedEmail,
+                document_id,
+                Property::Value,
+            )
+            .await?
+            .map(|masked_email| MaskedEmailRoute {
+                account_id,
+                document_id,
+                is_enabled: matches!(
+                    masked_email.get(&Property::State),
+                    Value::Text(state) if state == STATE_PENDING || state == STATE_ENABLED
+                ),
+            }))
+    }
+
+    pub async fn masked_email_status(
+        &self,
+        address: &str,
+    ) -> Result<MaskedEmailStatus, MethodError> {
+        Ok(match self.masked_email_route(address).await? {
+            Some(route) if route.is_enabled => MaskedEmailStatus::Enabled,
+            Some(_) => MaskedEmailStatus::Disabled,
+            None => MaskedEmailStatus::NotFound,
+        })
+    }
+
+    pub async fn masked_email_delivered(
+        &self,
+        account_id: u32,
+        document_id: u32,
+    ) -> Result<(), MethodError> {
+        let mut try_count = 0;
+
+        loop {
+            let current = if let Some(current) = self
+                .get_property::<HashedValue<Object<Value>>>(
+                    account_id,
+                    Collection::MaskedEmail,
+                    document_id,
+                    Property::Value,
+                )
+                .await?
+            {
+                current
+            } else {
+                return Ok(());
+            };
+
+            // Update counters and enable pending addresses on their first message
+            let mut masked_email = current.inner.clone();
+            let total_emails = match masked_email.get(&Property::TotalEmails) {
+                Value::UnsignedInt(total_emails) => *total_emails,
+                _ => 0,
+            };
+            masked_email.set(Property::TotalEmails, Value::UnsignedInt(total_emails + 1));
+            masked_email.set(
+                Property::LastMessageAt,
+                Value::Date(UTCDate::from_timestamp(now() as i64)),
+            );
+            if matches!(masked_email.get(&Property::State), Value::Text(state) if state == STATE_PENDING)
+            {
+                masked_email.set(Property::State, Value::Text(STATE_ENABLED.to_string()));
+            }
+
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(Collection::MaskedEmail)
+                .update_document(document_id)
+                .assert_value(Property::Value, &current)
+                .value(Property::Value, masked_email, F_VALUE);
+
+            match self.store.write(batch.build()).await {
+                Ok(_) => break,
+                Err(store::Error::AssertValueFailed) if try_count < 3 => {
+                    try_count += 1;
+                }
+                Err(err) => {
+                    tracing::error!(
+                        event = "error",
+                        context = "masked_email",
+                        account_id = account_id,
+                        document_id = document_id,
+                        error = ?err,
+                        "Failed to update masked email counters.");
+                    return Err(MethodError::ServerPartialFail);
+                }
+            }
+        }
+
+        // Notify clients
+        let mut changes = ChangeLogBuilder::new();
+        changes.log_update(Collection::MaskedEmail, document_id);
+        let change_id = self.commit_changes(account_id, changes).await?;
+        self.broadcast_state_change(
+            StateChange::new(account_id).with_change(DataType::MaskedEmail, change_id),
+        )
+        .await;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,425 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::{method::MethodError, set::SetError},
+    method::set::{RequestArguments, SetRequest, SetResponse},
+    object::Object,
+    response::references::EvalObjectReferences,
+    types::{
+        collection::Collection,
+        date::UTCDate,
+        property::Property,
+        state::StateChange,
+        type_state::DataType,
+        value::{MaybePatchValue, Value},
+    },
+};
+use rand::{thread_rng, Rng};
+use store::{
+    write::{
+        assert::HashedValue, log::ChangeLogBuilder, now, BatchBuilder, Operation, ValueClass,
+        F_CLEAR, F_VALUE,
+    },
+    Serialize,
+};
+
+use crate::{
+    auth::{authenticate::AccountKey, AccessToken},
+    JMAP,
+};
+
+use super::{STATE_DELETED, STATE_DISABLED, STATE_ENABLED, STATE_PENDING};
+
+impl JMAP {
+    pub async fn masked_email_set(
+        &self,
+        mut request: SetRequest<RequestArguments>,
+        access_token: &AccessToken,
+    ) -> Result<SetResponse, MethodError> {
+        let account_id = request.account_id.document_id();
+        let mut masked_email_ids = self
+            .get_document_ids(account_id, Collection::MaskedEmail)
+            .await?
+            .unwrap_or_default();
+        let mut response = self
+            .prepare_set_response(&request, Collection::MaskedEmail)
+            .await?;
+        let will_destroy = request.unwrap_destroy();
+
+        // Process creates
+        let mut changes = ChangeLogBuilder::new();
+        let create = request.unwrap_create();
+        let domain = if !create.is_empty() {
+            self.masked_email_domain(account_id, access_token).await?
+        } else {
+            None
+        };
+        'create: for (id, object) in create {
+            if masked_email_ids.len() as usize >= self.config.masked_email_max {
+                response.not_created.append(
+                    id,
+                    SetError::over_quota().with_description(
+                        "There are too many masked email addresses, please delete some before creating a new one.",
+                    ),
+                );
+                continue 'create;
+            }
+
+            let mut masked_email = Object::with_capacity(object.properties.len() + 4);
+            let mut email_prefix = None;
+            for (property, value) in object.properties {
+                match response
+                    .eval_object_references(value)
+                    .and_then(|value| validate_masked_email_value(&property, value, true))
+                {
+                    Ok(Value::Null) => (),
+                    Ok(Value::Text(prefix)) if property == Property::EmailPrefix => {
+                        email_prefix = prefix.into();
+                    }
+                    Ok(value) => {
+                        masked_email.set(property, value);
+                    }
+                    Err(err) => {
+                        response.not_created.append(id, err);
+                        continue 'create;
+                    }
+                }
+            }
+
+            let domain = if let Some(domain) = &domain {
+                domain
+            } else {
+                response.not_created.append(
+                    id,
+                    SetError::forbidden()
+                        .with_description("No domain is available for masked email addresses."),
+                );
+                continue 'create;
+            };
+
+            // Add server-set properties
+            if let Value::Null = masked_email.get(&Property::State) {
+                masked_email.set(Property::State, Value::Text(STATE_PENDING.to_string()));
+            }
+            masked_email.set(
+                Property::CreatedAt,
+                Value::Date(UTCDate::from_timestamp(now() as i64)),
+            );
+            masked_email.set(Property::CreatedBy, Value::Text(access_token.name.clone()));
+            masked_email.set(Property::TotalEmails, Value::UnsignedInt(0));
+
+            // Assign a unique random address
+            let document_id = self
+                .assign_document_id(account_id, Collection::MaskedEmail)
+                .await?;
+            let mut try_count = 0;
+            let email = loop {
+                let email = generate_masked_email(email_prefix.as_deref(), domain);
+                let key = AccountKey::masked_email(&email);
+                masked_email.set(Property::Email, Value::Text(email.clone()));
+
+                let mut batch = BatchBuilder::new();
+                batch
+                    .with_account_id(u32::MAX)
+                    .with_collection(Collection::Principal)
+                    .assert_value(ValueClass::Custom { bytes: key.clone() }, ())
+                    .op(Operation::Value {
+                        class: ValueClass::Custom { bytes: key },
+                        set: (((account_id as u64) << 32) | document_id as u64)
+                            .serialize()
+                            .into(),
+                    })
+                    .with_account_id(account_id)
+                    .with_collection(Collection::MaskedEmail)
+                    .create_document(document_id)
+                    .value(Property::Value, &masked_email, F_VALUE);
+
+                match self.store.write(batch.build()).await {
+                    Ok(_) => break email,
+                    Err(store::Error::AssertValueFailed) if try_count < 3 => {
+                        try_count += 1;
+                    }
+                    Err(store::Error::AssertValueFailed) => {
+                        return Err(MethodError::ServerUnavailable);
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            event = "error",
+                            context = "masked_email",
+                            account_id = account_id,
+                            error = ?err,
+                            "Failed to create masked email address.");
+                        return Err(MethodError::ServerPartialFail);
+                    }
+                }
+            };
+            masked_email_ids.insert(document_id);
+            changes.log_insert(Collection::MaskedEmail, document_id);
+            response.created.insert(
+                id,
+                Object::with_capacity(5)
+                    .with_property(Property::Id, Value::Id(document_id.into()))
+                    .with_property(Property::Email, Value::Text(email))
+                    .with_property(Property::State, masked_email.remove(&Property::State))
+                    .with_property(
+                        Property::CreatedAt,
+                        masked_email.remove(&Property::CreatedAt),
+                    )
+                    .with_property(
+                        Property::CreatedBy,
+                        masked_email.remove(&Property::CreatedBy),
+                    ),
+            );
+        }
+
+        // Process updates
+        'update: for (id, object) in request.unwrap_update() {
+            // Make sure id won't be destroyed
+            if will_destroy.contains(&id) {
+                response.not_updated.append(id, SetError::will_destroy());
+                continue 'update;
+            }
+
+            let mut updates = Vec::with_capacity(object.properties.len());
+            for (property, value) in object.properties {
+                match response
+                    .eval_object_references(value)
+                    .and_then(|value| validate_masked_email_value(&property, value, false))
+                {
+                    Ok(value) => {
+                        updates.push((property, value));
+                    }
+                    Err(err) => {
+                        response.not_updated.append(id, err);
+                        continue 'update;
+                    }
+                }
+            }
+
+            // Counters might be updated concurrently by incoming messages
+            let document_id = id.document_id();
+            let mut try_count = 0;
+            loop {
+                let current = if let Some(current) = self
+                    .get_property::<HashedValue<Object<Value>>>(
+                        account_id,
+                        Collection::MaskedEmail,
+                        document_id,
+                        Property::Value,
+                    )
+                    .await?
+                {
+                    current
+                } else {
+                    response.not_updated.append(id, SetError::not_found());
+                    continue 'update;
+                };
+
+                let mut masked_email = current.inner.clone();
+                for (property, value) in &updates {
+                    if let Value::Null = value {
+                        masked_email.remove(property);
+                    } else {
+                        masked_email.set(property.clone(), value.clone());
+                    }
+                }
+
+                let mut batch = BatchBuilder::new();
+                batch
+                    .with_account_id(account_id)
+                    .with_collection(Collection::MaskedEmail)
+                    .update_document(document_id)
+                    .assert_value(Property::Value, &current)
+                    .value(Property::Value, masked_email, F_VALUE);
+
+                match self.store.write(batch.build()).await {
+                    Ok(_) => break,
+                    Err(store::Error::AssertValueFailed) if try_count < 3 => {
+                        try_count += 1;
+                    }
+                    Err(store::Error::AssertValueFailed) => {
+                        return Err(MethodError::ServerUnavailable);
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            event = "error",
+                            context = "masked_email",
+                            account_id = account_id,
+                            error = ?err,
+                            "Failed to update masked email address.");
+                        return Err(MethodError::ServerPartialFail);
+                    }
+                }
+            }
+            changes.log_update(Collection::MaskedEmail, document_id);
+            response.updated.append(id, None);
+        }
+
+        // Process deletions
+        for id in will_destroy {
+            let document_id = id.document_id();
+            if masked_email_ids.contains(document_id) {
+                let mut batch = BatchBuilder::new();
+
+                // Release the address
+                if let Some(Value::Text(email)) = self
+                    .get_property::<Object<Value>>(
+                        account_id,
+                        Collection::MaskedEmail,
+                        document_id,
+                        Property::Value,
+                    )
+                    .await?
+                    .map(|mut masked_email| masked_email.remove(&Property::Email))
+                {
+                    batch
+                        .with_account_id(u32::MAX)
+                        .with_collection(Collection::Principal)
+                        .op(Operation::Value {
+                            class: ValueClass::Custom {
+                                bytes: AccountKey::masked_email(&email),
+                            },
+                            set: None,
+                        });
+                }
+
+                batch
+                    .with_account_id(account_id)
+                    .with_collection(Collection::MaskedEmail)
+                    .delete_document(document_id)
+                    .value(Property::Value, (), F_VALUE | F_CLEAR);
+                self.write_batch(batch).await?;
+                masked_email_ids.remove(document_id);
+                changes.log_delete(Collection::MaskedEmail, document_id);
+                response.destroyed.push(id);
+            } else {
+                response.not_destroyed.append(id, SetError::not_found());
+            }
+        }
+
+        // Write changes
+        if !changes.is_empty() {
+            let change_id = self.commit_changes(account_id, changes).await?;
+            response.new_state = Some(change_id.into());
+            response.state_change = StateChange::new(account_id)
+                .with_change(DataType::MaskedEmail, change_id)
+                .into();
+        }
+
+        Ok(response)
+    }
+
+    async fn masked_email_domain(
+        &self,
+        account_id: u32,
+        access_token: &AccessToken,
+    ) -> Result<Option<String>, MethodError> {
+        if let Some(domain) = &self.config.masked_email_domain {
+            return Ok(Some(domain.clone()));
+        }
+
+        // Default to the domain of the account's primary address
+        let account_name = if access_token.primary_id == account_id {
+            access_token.name.clone()
+        } else {
+            self.get_account_name(account_id).await?.unwrap_or_default()
+        };
+        Ok(self
+            .directory
+            .emails_by_name(&account_name)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .next()
+            .and_then(|email| {
+                email
+                    .rsplit_once('@')
+                    .map(|(_, domain)| domain.to_lowercase())
+            }))
+    }
+}
+
+fn validate_masked_email_value(
+    property: &Property,
+    value: MaybePatchValue,
+    is_create: bool,
+) -> Result<Value, SetError> {
+    Ok(match (property, value) {
+        (Property::State, MaybePatchValue::Value(Value::Text(value)))
+            if [STATE_ENABLED, STATE_DISABLED, STATE_DELETED].contains(&value.as_str())
+                || (is_create && value == STATE_PENDING) =>
+        {
+            Value::Text(value)
+        }
+        (Property::ForDomain | Property::Url, MaybePatchValue::Value(Value::Text(value)))
+            if value.len() < 255 =>
+        {
+            Value::Text(value)
+        }
+        (Property::Description, MaybePatchValue::Value(Value::Text(value)))
+            if value.len() < 1024 =>
+        {
+            Value::Text(value)
+        }
+        (Property::EmailPrefix, MaybePatchValue::Value(Value::Text(value))) if is_create => {
+            if !value.is_empty()
+                && value.len() <= 64
+                && value
+                    .bytes()
+                    .all(|ch| ch.is_ascii_lowercase() || ch.is_ascii_digit() || ch == b'_')
+            {
+                Value::Text(value)
+            } else {
+                return Err(SetError::invalid_properties()
+                    .with_property(Property::EmailPrefix)
+                    .with_description(
+                        "Prefix must be up to 64 lowercase letters, digits or underscores.",
+                    ));
+            }
+        }
+        (
+            Property::ForDomain | Property::Url | Property::Description | Property::EmailPrefix,
+            MaybePatchValue::Value(Value::Null),
+        ) => Value::Null,
+
+        (property, _) => {
+            return Err(SetError::invalid_properties()
+                .with_property(property.clone())
+                .with_description("Field could not be set."));
+        }
+    })
+}
+
+fn generate_masked_email(prefix: Option<&str>, domain: &str) -> String {
+    let mut rng = thread_rng();
+    let suffix = (0..8)
+        .map(|_| char::from_digit(rng.gen_range(0..36), 36).unwrap())
+        .collect::<String>();
+
+    if let Some(prefix) = prefix {
+        format!("{prefix}.{suffix}@{domain}")
+    } else {
+        format!("{suffix}@{domain}")
+    }
+}
@@ -0,0 +1,337 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Write;
+
+use jmap_proto::{
+    error::set::{SetError, SetErrorType},
+    method::mdn::{ActionMode, Disposition, DispositionType, Mdn, SendingMode},
+    types::property::{HeaderForm, HeaderProperty, Property},
+};
+use mail_builder::{
+    headers::{
+        address::{Address, EmailAddress},
+        content_type::ContentType,
+        HeaderType,
+    },
+    mime::{BodyPart, MimePart},
+    MessageBuilder,
+};
+use mail_parser::{Message, MessageParser, MimeHeaders};
+use smtp::core::{NullIo, Session, SessionAddress};
+
+use crate::{email::headers::HeaderToValue, JMAP};
+
+pub mod parse;
+pub mod send;
+
+impl JMAP {
+    // Builds a disposition notification for a message and queues it for delivery
+    // to the addresses listed in the Disposition-Notification-To header. Any MDN
+    // properties not provided by the caller are filled in with defaults.
+    pub(crate) async fn queue_mdn(
+        &self,
+        from_name: Option<&str>,
+        from_email: &str,
+        message: &Message<'_>,
+        raw_message: &[u8],
+        disposition: Disposition,
+        mdn: &mut Mdn,
+    ) -> Result<(), SetError> {
+        // Obtain recipients
+        let rcpts = mdn_requested_by(message, raw_message);
+        if rcpts.is_empty() {
+            return Err(SetError::new(SetErrorType::NoRecipients)
+                .with_description("Email does not request a disposition notification."));
+        }
+
+        // Fill in defaults and server-set properties
+        let subject = message.subject().unwrap_or_default();
+        if mdn.subject.is_none() {
+            mdn.subject = format!(
+                "Return Receipt ({}) - {}",
+                disposition.type_.as_str(),
+                subject
+            )
+            .into();
+        }
+        if mdn.reporting_ua.is_none() {
+            mdn.reporting_ua = self.config.mdn_reporting_ua.clone().into();
+        }
+        let final_recipient = mdn
+            .final_recipient
+            .get_or_insert_with(|| format!("rfc822; {from_email}"))
+            .clone();
+        if mdn.text_body.is_none() {
+            mdn.text_body = format!(
+                concat!(
+                    "This is a disposition notification for the message sent to {} ",
+                    "with subject \"{}\".\r\n\r\nThe message has been {}. This is no ",
+                    "guarantee that the message has been read or understood.\r\n"
+                ),
+                final_recipient
+                    .split_once(';')
+                    .map_or(final_recipient.as_str(), |(_, addr)| addr.trim()),
+                subject,
+                disposition.type_.as_str()
+            )
+            .into();
+        }
+        mdn.include_original_message = mdn.include_original_message.unwrap_or(false).into();
+        mdn.original_recipient = header_text(message, raw_message, "Original-Recipient");
+        mdn.original_message_id = message.message_id().map(|id| format!("<{id}>"));
+        mdn.mdn_gateway = None;
+        mdn.disposition = disposition.into();
+
+        // Build disposition notification fields
+        let mut report = String::with_capacity(256);
+        let _ = write!(
+            report,
+            "Reporting-UA: {}\r\n",
+            field_value(mdn.reporting_ua.as_deref().unwrap_or_default())
+        );
+        if let Some(original_recipient) = &mdn.original_recipient {
+            let _ = write!(
+                report,
+                "Original-Recipient: {}\r\n",
+                field_value(original_recipient)
+            );
+        }
+        let _ = write!(
+            report,
+            "Final-Recipient: {}\r\n",
+            field_value(&final_recipient)
+        );
+        if let Some(original_message_id) = &mdn.original_message_id {
+            let _ = write!(
+                report,
+                "Original-Message-ID: {}\r\n",
+                field_value(original_message_id)
+            );
+        }
+        let _ = write!(report, "Disposition: {}\r\n", disposition.to_header());
+        for error in mdn.error.iter().flatten() {
+            let _ = write!(report, "Error: {}\r\n", field_value(error));
+        }
+        for (name, value) in mdn.extension_fields.iter().flat_map(|fields| fields.iter()) {
+            if !name.is_empty()
+                && name
+                    .bytes()
+                    .all(|ch| ch.is_ascii_alphanumeric() || ch == b'-')
+            {
+                let _ = write!(report, "{name}: {}\r\n", field_value(value));
+            }
+        }
+
+        // Include either the full original message or just its headers
+        let original = if mdn.include_original_message == Some(true) {
+            MimePart::new(
+                ContentType::new("message/rfc822"),
+                BodyPart::Binary(raw_message.into()),
+            )
+        } else {
+            MimePart::new(
+                ContentType::new("text/rfc822-headers"),
+                BodyPart::Text(String::from_utf8_lossy(
+                    raw_message
+                        .get(message.parts[0].offset_header..message.parts[0].offset_body)
+                        .unwrap_or_default(),
+                )),
+            )
+        };
+
+        let mut builder = MessageBuilder::new()
+            .from(Address::Address(EmailAddress {
+                name: from_name.map(|name| name.into()),
+                email: from_email.into(),
+            }))
+            .to(Address::new_list(
+                rcpts
+                    .iter()
+                    .map(|rcpt| {
+                        Address::Address(EmailAddress {
+                            name: None,
+                            email: rcpt.as_str().into(),
+                        })
+                    })
+                    .collect(),
+            ))
+            .subject(mdn.subject.as_deref().unwrap_or_default());
+        if let Some(message_id) = message.message_id() {
+            builder = builder.in_reply_to(message_id).references(message_id);
+        }
+        if disposition.sending_mode == SendingMode::Automatic {
+            builder = builder.header("Auto-Submitted", HeaderType::Text("auto-replied".into()));
+        }
+        let report = builder
+            .body(MimePart::new(
+                ContentType::new("multipart/report")
+                    .attribute("report-type", "disposition-notification"),
+                BodyPart::Multipart(vec![
+                    MimePart::new(
+                        ContentType::new("text/plain"),
+                        BodyPart::Text(mdn.text_body.as_deref().unwrap_or_default().into()),
+                    ),
+                    MimePart::new(
+                        ContentType::new("message/disposition-notification"),
+                        BodyPart::Text(report.into()),
+                    ),
+                    original,
+                ]),
+            ))
+            .write_to_vec()
+            .unwrap_or_default();
+        if report.len() > self.config.mail_max_size {
+            return Err(SetError::too_large().with_description(format!(
+                "Disposition notification exceeds maximum size of {} bytes.",
+                self.config.mail_max_size
+            )));
+        }
+
+        // MDNs are sent with a null return path to avoid notification loops
+        let result = Session::<NullIo>::sieve(
+            self.smtp.core(),
+            SessionAddress::new(String::new()),
+            rcpts.into_iter().map(SessionAddress::new).collect(),
+            report,
+        )
+        .queue_message()
+        .await;
+
+        if result.first() == Some(&b'2') {
+            Ok(())
+        } else {
+            Err(
+                SetError::new(SetErrorType::ForbiddenToSend).with_description(format!(
+                    "Server rejected disposition notification: {}",
+                    std::str::from_utf8(&result).unwrap_or_default().trim()
+                )),
+            )
+        }
+    }
+
+    // Sends an automatic "processed" disposition notification for a delivered message
+    // that requests one. As per RFC 8098, section 2.1, notifications are only sent
+    // automatically when requested by the envelope sender.
+    pub async fn mdn_auto_send(&self, raw_message: &[u8], sender_address: &str, rcpt: &str) {
+        if sender_address.is_empty() {
+            return;
+        }
+        let message = if let Some(message) = MessageParser::new().parse(raw_message) {
+            message
+        } else {
+            return;
+        };
+        let rcpts = mdn_requested_by(&message, raw_message);
+        if rcpts.is_empty()
+            || rcpts
+                .iter()
+                .any(|addr| !addr.eq_ignore_ascii_case(sender_address))
+            || message.parts[0].is_content_type("multipart", "report")
+            || header_text(&message, raw_message, "Auto-Submitted")
+                .map_or(false, |value| !value.eq_ignore_ascii_case("no"))
+        {
+            return;
+        }
+
+        if let Err(err) = self
+            .queue_mdn(
+                None,
+                rcpt,
+                &message,
+                raw_message,
+                Disposition {
+                    action_mode: ActionMode::Automatic,
+                    sending_mode: SendingMode::Automatic,
+                    type_: DispositionType::Processed,
+                },
+                &mut Mdn::default(),
+            )
+            .await
+        {
+            tracing::debug!(
+                context = "mdn",
+                event = "error",
+                rcpt = rcpt,
+                reason = err.description.as_deref().unwrap_or_default(),
+                "Failed to send automatic disposition notification."
+            );
+        }
+    }
+}
+
+// Returns the addresses a disposition notification was requested for
+pub fn mdn_requested_by(message: &Message<'_>, raw_message: &[u8]) -> Vec<String> {
+    message
+        .parts
+        .first()
+        .map(|part| {
+            part.header_to_value(
+                &Property::Header(HeaderProperty {
+                    form: HeaderForm::Addresses,
+                    header: "Disposition-Notification-To".to_string(),
+                    all: false,
+                }),
+                raw_message,
+            )
+        })
+        .and_then(|value| value.try_unwrap_list())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|addr| {
+            addr.as_obj()?
+                .properties
+                .get(&Property::Email)?
+                .as_string()
+                .map(|addr| addr.to_string())
+        })
+        .collect()
+}
+
+// Collapses line breaks and other control characters so that caller-provided
+// values cannot inject additional fields into the disposition notification
+pub fn field_value(value: &str) -> String {
+    value
+        .split(|ch: char| ch.is_control())
+        .filter(|part| !part.trim().is_empty())
+        .map(|part| part.trim())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn header_text(message: &Message<'_>, raw_message: &[u8], header: &str) -> Option<String> {
+    message
+        .parts
+        .first()?
+        .header_to_value(
+            &Property::Header(HeaderProperty {
+                form: HeaderForm::Text,
+                header: header.to_string(),
+                all: false,
+            }),
+            raw_message,
+        )
+        .try_unwrap_string()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+}
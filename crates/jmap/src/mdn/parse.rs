@@ -0,0 +1,146 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    method::mdn::{Disposition, Mdn, MdnParseRequest, MdnParseResponse},
+};
+use mail_parser::{MessageParser, MimeHeaders, PartType};
+use utils::map::vec_map::VecMap;
+
+use crate::{auth::AccessToken, JMAP};
+
+impl JMAP {
+    pub async fn mdn_parse(
+        &self,
+        request: MdnParseRequest,
+        access_token: &AccessToken,
+    ) -> Result<MdnParseResponse, MethodError> {
+        if request.blob_ids.len() > self.config.mail_parse_max_items {
+            return Err(MethodError::RequestTooLarge);
+        }
+        let mut response = MdnParseResponse {
+            account_id: request.account_id,
+            parsed: VecMap::with_capacity(request.blob_ids.len()),
+            not_parsable: vec![],
+            not_found: vec![],
+        };
+
+        for blob_id in request.blob_ids {
+            // Fetch raw message to parse
+            let raw_message = match self.blob_download(&blob_id, access_token).await? {
+                Some(raw_message) => raw_message,
+                None => {
+                    response.not_found.push(blob_id);
+                    continue;
+                }
+            };
+            let message = if let Some(message) = MessageParser::new().parse(&raw_message) {
+                message
+            } else {
+                response.not_parsable.push(blob_id);
+                continue;
+            };
+
+            // Locate the disposition notification part
+            let mut mdn = None;
+            let mut include_original_message = false;
+            for part in &message.parts {
+                if part.is_content_type("message", "disposition-notification") {
+                    mdn = match &part.body {
+                        PartType::Text(text) => parse_mdn_fields(text.as_ref()),
+                        PartType::Binary(bytes) | PartType::InlineBinary(bytes) => {
+                            parse_mdn_fields(&String::from_utf8_lossy(bytes.as_ref()))
+                        }
+                        _ => None,
+                    };
+                } else if part.is_content_type("message", "rfc822")
+                    || part.is_content_type("message", "global")
+                {
+                    include_original_message = true;
+                }
+            }
+
+            if let Some(mut mdn) = mdn {
+                mdn.subject = message.subject().map(|subject| subject.to_string());
+                mdn.text_body = message
+                    .text_body
+                    .first()
+                    .and_then(|part_id| message.parts.get(*part_id))
+                    .and_then(|part| match &part.body {
+                        PartType::Text(text) => Some(text.to_string()),
+                        _ => None,
+                    });
+                mdn.include_original_message = include_original_message.into();
+                response.parsed.append(blob_id, mdn);
+            } else {
+                response.not_parsable.push(blob_id);
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+// Parses the fields of a message/disposition-notification body part (RFC 8098)
+fn parse_mdn_fields(text: &str) -> Option<Mdn> {
+    let mut mdn = Mdn::default();
+    let mut fields: Vec<(String, String)> = Vec::new();
+
+    // Unfold header fields
+    for line in text.split('\n') {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            fields.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    for (name, value) in fields {
+        match name.to_ascii_lowercase().as_str() {
+            "reporting-ua" => mdn.reporting_ua = value.into(),
+            "mdn-gateway" => mdn.mdn_gateway = value.into(),
+            "original-recipient" => mdn.original_recipient = value.into(),
+            "final-recipient" => mdn.final_recipient = value.into(),
+            "original-message-id" => mdn.original_message_id = value.into(),
+            "disposition" => mdn.disposition = Disposition::from_header(&value),
+            "error" => mdn.error.get_or_insert_with(Vec::new).push(value),
+            _ => {
+                mdn.extension_fields
+                    .get_or_insert_with(VecMap::new)
+                    .append(name, value);
+            }
+        }
+    }
+
+    // Final-Recipient and Disposition are required fields
+    if mdn.final_recipient.is_some() && mdn.disposition.is_some() {
+        Some(mdn)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,236 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::{
+        method::MethodError,
+        set::{SetError, SetErrorType},
+    },
+    method::{
+        mdn::{Mdn, MdnSendRequest, MdnSendResponse},
+        set::{self, SetRequest},
+    },
+    object::Object,
+    request::{
+        method::{MethodFunction, MethodName, MethodObject},
+        reference::MaybeReference,
+        Call, RequestMethod,
+    },
+    types::{collection::Collection, id::Id, keyword::Keyword, property::Property, value::Value},
+};
+use mail_parser::MessageParser;
+use store::{ahash::AHashMap, BlobKind};
+use utils::map::vec_map::VecMap;
+
+use crate::JMAP;
+
+impl JMAP {
+    pub async fn mdn_send(
+        &self,
+        request: MdnSendRequest,
+        next_call: &mut Option<Call<RequestMethod>>,
+    ) -> Result<MdnSendResponse, MethodError> {
+        let account_id = request.account_id.document_id();
+        if request.send.len() > self.config.set_max_objects {
+            return Err(MethodError::RequestTooLarge);
+        }
+
+        // Obtain identity
+        let mut identity = self
+            .get_property::<Object<Value>>(
+                account_id,
+                Collection::Identity,
+                request.identity_id.document_id(),
+                Property::Value,
+            )
+            .await?
+            .ok_or_else(|| MethodError::InvalidArguments("Identity not found.".to_string()))?;
+        let from_email = identity
+            .properties
+            .remove(&Property::Email)
+            .and_then(|value| value.try_unwrap_string())
+            .ok_or_else(|| MethodError::InvalidArguments("Identity not found.".to_string()))?;
+        let from_name = identity
+            .properties
+            .remove(&Property::Name)
+            .and_then(|value| value.try_unwrap_string())
+            .filter(|name| !name.is_empty());
+
+        let mut response = MdnSendResponse {
+            account_id: request.account_id,
+            sent: VecMap::with_capacity(request.send.len()),
+            not_sent: VecMap::new(),
+        };
+        let mut sent_email_ids = AHashMap::with_capacity(request.send.len());
+
+        for (id, mdn) in request.send {
+            match self
+                .send_mdn(account_id, from_name.as_deref(), &from_email, mdn)
+                .await?
+            {
+                Ok((email_id, mdn)) => {
+                    sent_email_ids.insert(id.clone(), email_id);
+                    response.sent.append(id, mdn);
+                }
+                Err(err) => {
+                    response.not_sent.append(id, err);
+                }
+            }
+        }
+
+        // Update the emails an MDN was sent for, usually to set the $mdnsent keyword
+        if let Some(on_success_update_email) = request.on_success_update_email {
+            let mut update = VecMap::with_capacity(on_success_update_email.len());
+            for (id, patch) in on_success_update_email {
+                let email_id = match id {
+                    MaybeReference::Reference(id) => sent_email_ids.get(&id).copied(),
+                    MaybeReference::Value(id) => {
+                        sent_email_ids.values().find(|v| **v == id).copied()
+                    }
+                };
+                if let Some(email_id) = email_id {
+                    update.append(email_id, patch);
+                }
+            }
+
+            if !update.is_empty() {
+                *next_call = Call {
+                    id: String::new(),
+                    name: MethodName::new(MethodObject::Email, MethodFunction::Set),
+                    method: RequestMethod::Set(SetRequest {
+                        account_id: request.account_id,
+                        if_in_state: None,
+                        create: None,
+                        update: update.into(),
+                        destroy: None,
+                        arguments: set::RequestArguments::Email,
+                    }),
+                }
+                .into();
+            }
+        }
+
+        Ok(response)
+    }
+
+    async fn send_mdn(
+        &self,
+        account_id: u32,
+        from_name: Option<&str>,
+        from_email: &str,
+        mut mdn: Mdn,
+    ) -> Result<Result<(Id, Mdn), SetError>, MethodError> {
+        // Validate request
+        let (email_id, disposition) = match (mdn.for_email_id, mdn.disposition) {
+            (Some(email_id), Some(disposition)) => (email_id, disposition),
+            _ => {
+                return Ok(Err(SetError::invalid_properties().with_description(
+                    "forEmailId and disposition properties are required.",
+                )));
+            }
+        };
+
+        // Make sure an MDN was not already sent for this email
+        let document_id = email_id.document_id();
+        match self
+            .get_property::<Vec<Keyword>>(
+                account_id,
+                Collection::Email,
+                document_id,
+                Property::Keywords,
+            )
+            .await?
+        {
+            Some(keywords) if keywords.contains(&Keyword::MdnSent) => {
+                return Ok(Err(SetError::new(SetErrorType::MdnAlreadySent)
+                    .with_description("A disposition notification was already sent.")));
+            }
+            Some(_) => (),
+            None => {
+                return Ok(Err(
+                    SetError::not_found().with_description("Email not found.")
+                ));
+            }
+        }
+
+        // Obtain raw message
+        let raw_message = if let Some(raw_message) = self
+            .get_blob(
+                &BlobKind::LinkedMaildir {
+                    account_id,
+                    document_id,
+                },
+                0..u32::MAX,
+            )
+            .await?
+        {
+            raw_message
+        } else {
+            return Ok(Err(
+                SetError::not_found().with_description("Blob for email not found.")
+            ));
+        };
+        let message = if let Some(message) = MessageParser::new().parse(&raw_message) {
+            message
+        } else {
+            return Ok(Err(SetError::new(SetErrorType::InvalidEmail)
+                .with_description("Failed to parse email.")));
+        };
+
+        // Build and queue the MDN
+        let has_subject = mdn.subject.is_some();
+        let has_text_body = mdn.text_body.is_some();
+        let has_reporting_ua = mdn.reporting_ua.is_some();
+        let has_final_recipient = mdn.final_recipient.is_some();
+        let has_include_original_message = mdn.include_original_message.is_some();
+        if let Err(err) = self
+            .queue_mdn(
+                from_name,
+                from_email,
+                &message,
+                &raw_message,
+                disposition,
+                &mut mdn,
+            )
+            .await
+        {
+            return Ok(Err(err));
+        }
+
+        // Return the properties that were not set by the client
+        let sent = Mdn {
+            subject: mdn.subject.filter(|_| !has_subject),
+            text_body: mdn.text_body.filter(|_| !has_text_body),
+            reporting_ua: mdn.reporting_ua.filter(|_| !has_reporting_ua),
+            final_recipient: mdn.final_recipient.filter(|_| !has_final_recipient),
+            include_original_message: mdn
+                .include_original_message
+                .filter(|_| !has_include_original_message),
+            original_recipient: mdn.original_recipient,
+            original_message_id: mdn.original_message_id,
+            ..Default::default()
+        };
+
+        Ok(Ok((email_id, sent)))
+    }
+}
@@ -25,7 +25,7 @@ use jmap_proto::{
     error::method::MethodError,
     method::get::{GetRequest, GetResponse, RequestArguments},
     object::Object,
-    types::{collection::Collection, property::Property, state::State, value::Value},
+    types::{collection::Collection, property::Property, value::Value},
 };
 
 use crate::JMAP;
@@ -42,17 +42,17 @@ impl JMAP {
             Property::Name,
             Property::Description,
             Property::Email,
-            //Property::Timezone,
-            //Property::Capabilities,
+            Property::Timezone,
+            Property::Capabilities,
         ]);
-        let email_submission_ids = self
-            .get_document_ids(u32::MAX, Collection::EmailSubmission)
+        let principal_ids = self
+            .get_document_ids(u32::MAX, Collection::Principal)
             .await?
             .unwrap_or_default();
         let ids = if let Some(ids) = ids {
             ids
         } else {
-            email_submission_ids
+            principal_ids
                 .iter()
                 .take(self.config.get_max_objects)
                 .map(Into::into)
@@ -60,32 +60,23 @@ impl JMAP {
         };
         let mut response = GetResponse {
             account_id: request.account_id.into(),
-            state: State::Initial.into(),
+            state: self
+                .get_state(u32::MAX, Collection::Principal)
+                .await?
+                .into(),
             list: Vec::with_capacity(ids.len()),
             not_found: vec![],
         };
 
         for id in ids {
-            // Obtain the principal name
-            let name = if let Some(name) = self.get_account_name(id.document_id()).await? {
-                name
-            } else {
-                response.not_found.push(id.into());
-                continue;
-            };
-
             // Obtain the principal
-            let principal = if let Some(principal) = self
-                .directory
-                .principal(&name)
-                .await
-                .map_err(|_| MethodError::ServerPartialFail)?
-            {
+            let principal = if let Some(principal) = self.get_principal(id.document_id()).await? {
                 principal
             } else {
                 response.not_found.push(id.into());
                 continue;
             };
+            let details = self.get_principal_details(id.document_id()).await?;
 
             let mut result = Object::with_capacity(properties.len());
             for property in &properties {
@@ -93,20 +84,27 @@ impl JMAP {
                     Property::Id => Value::Id(id),
                     Property::Type => Value::Text(principal.typ.to_jmap().to_string()),
                     Property::Name => Value::Text(principal.name.clone()),
-                    Property::Description => principal
+                    Property::Description => details
                         .description
                         .clone()
+                        .or_else(|| principal.description.clone())
                         .map(Value::Text)
                         .unwrap_or(Value::Null),
                     Property::Email => self
                         .directory
-                        .emails_by_name(&name)
+                        .emails_by_name(&principal.name)
                         .await
                         .map_err(|_| MethodError::ServerPartialFail)?
                         .into_iter()
                         .next()
                         .map(Value::Text)
                         .unwrap_or(Value::Null),
+                    Property::Timezone => details
+                        .timezone
+                        .clone()
+                        .map(Value::Text)
+                        .unwrap_or(Value::Null),
+                    Property::Capabilities => self.principal_capabilities(principal.typ),
                     _ => Value::Null,
                 };
 
@@ -21,5 +21,152 @@
  * for more details.
 */
 
+use std::{sync::Arc, time::Instant};
+
+use directory::{Principal, Type};
+use jmap_proto::{
+    error::method::MethodError,
+    method::query::Filter,
+    object::Object,
+    types::{property::Property, value::Value},
+};
+use store::CustomValueKey;
+use utils::map::ttl_dashmap::TtlMap;
+
+use crate::{
+    api::session::SHARED_ACCOUNT_CAPABILITIES, auth::authenticate::AccountKey, Bincode, JMAP,
+};
+
 pub mod get;
 pub mod query;
+pub mod set;
+
+// Principal properties that can be changed over JMAP, everything
+// else is managed by the directory.
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PrincipalDetails {
+    pub description: Option<String>,
+    pub timezone: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct CachedPrincipal {
+    pub principal: Principal,
+    pub emails: Vec<String>,
+    pub details: PrincipalDetails,
+}
+
+impl JMAP {
+    pub async fn get_principal_details(
+        &self,
+        account_id: u32,
+    ) -> Result<PrincipalDetails, MethodError> {
+        self.store
+            .get_value::<Bincode<PrincipalDetails>>(CustomValueKey {
+                value: AccountKey::principal(account_id),
+            })
+            .await
+            .map(|details| details.map(|details| details.inner).unwrap_or_default())
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve principal details");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn get_principal(&self, account_id: u32) -> Result<Option<Principal>, MethodError> {
+        if let Some(name) = self.get_account_name(account_id).await? {
+            self.directory
+                .principal(&name)
+                .await
+                .map_err(|_| MethodError::ServerPartialFail)
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Principals are cached for as long as sessions are, so that filtering
+    // them does not require directory and store lookups each time.
+    pub async fn get_cached_principal(
+        &self,
+        account_id: u32,
+    ) -> Result<Option<Arc<CachedPrincipal>>, MethodError> {
+        if let Some(principal) = self.principals.get_with_ttl(&account_id) {
+            return Ok(Some(principal));
+        }
+
+        let principal = if let Some(principal) = self.get_principal(account_id).await? {
+            principal
+        } else {
+            return Ok(None);
+        };
+        let emails = self
+            .directory
+            .emails_by_name(&principal.name)
+            .await
+            .map_err(|_| MethodError::ServerPartialFail)?;
+        let details = self.get_principal_details(account_id).await?;
+
+        Ok(Some(self.principals.insert_with_ttl(
+            account_id,
+            Arc::new(CachedPrincipal {
+                principal,
+                emails,
+                details,
+            }),
+            Instant::now() + self.config.session_cache_ttl,
+        )))
+    }
+
+    pub fn principal_capabilities(&self, typ: Type) -> Value {
+        // Groups and resources are shared accounts, individuals have
+        // access to every capability enabled on the server.
+        let mut result = Object::with_capacity(self.config.capabilities.account.len());
+        for capability in self.config.capabilities.account.keys() {
+            if matches!(typ, Type::Individual | Type::Superuser)
+                || SHARED_ACCOUNT_CAPABILITIES.contains(capability)
+            {
+                result.append(
+                    Property::_T(capability.as_str().to_string()),
+                    Value::Object(Object::with_capacity(0)),
+                );
+            }
+        }
+        Value::Object(result)
+    }
+}
+
+impl CachedPrincipal {
+    pub fn matches(&self, filter: &Filter) -> bool {
+        match filter {
+            Filter::Type(typ) => typ == self.principal.typ.to_jmap(),
+            Filter::Timezone(timezone) => self
+                .details
+                .timezone
+                .as_ref()
+                .map_or(false, |tz| tz.eq_ignore_ascii_case(timezone)),
+            Filter::Text(text) => {
+                let text = text.to_lowercase();
+                self.principal.name.to_lowercase().contains(&text)
+                    || self
+                        .description()
+                        .map_or(false, |d| d.to_lowercase().contains(&text))
+                    || self
+                        .emails
+                        .iter()
+                        .any(|email| email.to_lowercase().contains(&text))
+            }
+            _ => false,
+        }
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.details
+            .description
+            .as_deref()
+            .or(self.principal.description.as_deref())
+    }
+}
@@ -35,13 +35,13 @@ impl JMAP {
         &self,
         mut request: QueryRequest<RequestArguments>,
     ) -> Result<QueryResponse, MethodError> {
-        let account_id = request.account_id.document_id();
         let mut result_set = ResultSet {
-            account_id,
+            account_id: u32::MAX,
             collection: Collection::Principal.into(),
             results: RoaringBitmap::new(),
         };
         let mut is_set = true;
+        let mut principal_filters = Vec::new();
 
         for cond in std::mem::take(&mut request.filter) {
             match cond {
@@ -81,7 +81,9 @@ impl JMAP {
                         result_set.results &= ids;
                     }
                 }
-                Filter::Type(_) => {}
+                cond @ (Filter::Type(_) | Filter::Text(_) | Filter::Timezone(_)) => {
+                    principal_filters.push(cond);
+                }
                 other => return Err(MethodError::UnsupportedFilter(other.to_string())),
             }
         }
@@ -93,6 +95,23 @@ impl JMAP {
                 .unwrap_or_default();
         }
 
+        // Evaluate the remaining filters in a single pass over the candidates
+        if !principal_filters.is_empty() {
+            for account_id in std::mem::take(&mut result_set.results) {
+                if self
+                    .get_cached_principal(account_id)
+                    .await?
+                    .map_or(false, |principal| {
+                        principal_filters
+                            .iter()
+                            .all(|filter| principal.matches(filter))
+                    })
+                {
+                    result_set.results.insert(account_id);
+                }
+            }
+        }
+
         let (response, paginate) = self.build_query_response(&result_set, &request).await?;
 
         if let Some(paginate) = paginate {
@@ -0,0 +1,169 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::{method::MethodError, set::SetError},
+    method::set::{RequestArguments, SetRequest, SetResponse},
+    response::references::EvalObjectReferences,
+    types::{
+        collection::Collection,
+        property::Property,
+        value::{MaybePatchValue, Value},
+    },
+};
+use store::{
+    write::{log::ChangeLogBuilder, BatchBuilder, Operation, ValueClass},
+    Serialize,
+};
+
+use crate::{
+    auth::{authenticate::AccountKey, AccessToken},
+    Bincode, JMAP,
+};
+
+impl JMAP {
+    pub async fn principal_set(
+        &self,
+        mut request: SetRequest<RequestArguments>,
+        access_token: &AccessToken,
+    ) -> Result<SetResponse, MethodError> {
+        let principal_ids = self
+            .get_document_ids(u32::MAX, Collection::Principal)
+            .await?
+            .unwrap_or_default();
+        let mut response = SetResponse::from_request(&request, self.config.set_max_objects)?
+            .with_state(
+                self.assert_state(u32::MAX, Collection::Principal, &request.if_in_state)
+                    .await?,
+            );
+
+        // Principals are provisioned by the directory
+        for (id, _) in request.unwrap_create() {
+            response.not_created.append(
+                id,
+                SetError::forbidden().with_description("Principals are managed by the directory."),
+            );
+        }
+        for id in request.unwrap_destroy() {
+            response.not_destroyed.append(
+                id,
+                SetError::forbidden().with_description("Principals are managed by the directory."),
+            );
+        }
+
+        // Process updates
+        let mut changes = ChangeLogBuilder::new();
+        'update: for (id, object) in request.unwrap_update() {
+            let account_id = id.document_id();
+            if !principal_ids.contains(account_id) {
+                response.not_updated.append(id, SetError::not_found());
+                continue 'update;
+            } else if !access_token.is_member(account_id) {
+                response.not_updated.append(
+                    id,
+                    SetError::forbidden()
+                        .with_description("You are not allowed to modify this principal."),
+                );
+                continue 'update;
+            }
+
+            let mut details = self.get_principal_details(account_id).await?;
+            for (property, value) in object.properties {
+                let value = match response.eval_object_references(value) {
+                    Ok(MaybePatchValue::Value(value)) => value,
+                    Ok(_) => {
+                        response.not_updated.append(
+                            id,
+                            SetError::invalid_properties()
+                                .with_property(property)
+                                .with_description("Invalid value."),
+                        );
+                        continue 'update;
+                    }
+                    Err(err) => {
+                        response.not_updated.append(id, err);
+                        continue 'update;
+                    }
+                };
+
+                match (&property, value) {
+                    (Property::Description, Value::Text(value)) if value.len() < 255 => {
+                        details.description = value.into();
+                    }
+                    (Property::Timezone, Value::Text(value)) if value.len() < 255 => {
+                        details.timezone = value.into();
+                    }
+                    (Property::Description, Value::Null) => {
+                        details.description = None;
+                    }
+                    (Property::Timezone, Value::Null) => {
+                        details.timezone = None;
+                    }
+                    (
+                        Property::Name | Property::Email | Property::Type | Property::Capabilities,
+                        _,
+                    ) => {
+                        response.not_updated.append(
+                            id,
+                            SetError::forbidden()
+                                .with_property(property.clone())
+                                .with_description("Property is managed by the directory."),
+                        );
+                        continue 'update;
+                    }
+                    _ => {
+                        response.not_updated.append(
+                            id,
+                            SetError::invalid_properties()
+                                .with_property(property.clone())
+                                .with_description("Field could not be set."),
+                        );
+                        continue 'update;
+                    }
+                }
+            }
+
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal)
+                .op(Operation::Value {
+                    class: ValueClass::Custom {
+                        bytes: AccountKey::principal(account_id),
+                    },
+                    set: Bincode::new(details).serialize().into(),
+                });
+            self.write_batch(batch).await?;
+            self.principals.remove(&account_id);
+            changes.log_update(Collection::Principal, account_id);
+            response.updated.append(id, None);
+        }
+
+        // Write changes
+        if !changes.is_empty() {
+            response.new_state = Some(self.commit_changes(u32::MAX, changes).await?.into());
+        }
+
+        Ok(response)
+    }
+}
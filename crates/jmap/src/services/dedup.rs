@@ -0,0 +1,366 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{error::method::MethodError, types::collection::Collection};
+use sha2::{Digest, Sha256};
+use smtp::tracking::find_message_id;
+use store::{
+    write::{key::DeserializeBigEndian, now, BatchBuilder, Operation, ValueClass},
+    CustomValueKey, Deserialize, Serialize,
+};
+use utils::ipc::DeliveryResult;
+
+use crate::{auth::authenticate::AccountKey, email::index::MAX_ID_LENGTH, JMAP};
+
+impl JMAP {
+    // Delivers a message unless the same message was already delivered to the account
+    // within its deduplication window, which happens when a message reaches the same
+    // person through several aliases or lists. Messages are identified by their
+    // Message-ID, envelope sender and body, so a reused Message-ID cannot be used
+    // to suppress unrelated messages.
+    pub async fn deliver_once(
+        &self,
+        raw_message: &[u8],
+        sender_address: &str,
+        rcpt: &str,
+        name: &str,
+    ) -> DeliveryResult {
+        let fingerprint = delivery_fingerprint(raw_message, sender_address);
+        let dedup = match (fingerprint, self.get_account_id(name).await) {
+            (Some(fingerprint), Ok(account_id)) => match self.dedup_window(account_id).await {
+                Ok(Some(window)) => Some((account_id, fingerprint, window)),
+                Ok(None) => None,
+                Err(_) => {
+                    return DeliveryResult::TemporaryFailure {
+                        reason: "Transient server failure.".into(),
+                    };
+                }
+            },
+            _ => None,
+        };
+
+        // Reserve the delivery before ingesting the message, so concurrent copies
+        // arriving through different aliases cannot both be delivered
+        let mut reservation = None;
+        if let Some((account_id, fingerprint, window)) = &dedup {
+            match self
+                .reserve_delivery(*account_id, fingerprint, now() + window)
+                .await
+            {
+                Ok(Some(expires)) => {
+                    reservation = Some(expires);
+                }
+                Ok(None) => {
+                    tracing::info!(
+                        context = "dedup",
+                        event = "suppressed",
+                        account_id = account_id,
+                        message_id = find_message_id(raw_message).unwrap_or_default(),
+                        rcpt = rcpt,
+                        "Suppressed duplicate delivery."
+                    );
+                    return DeliveryResult::Suppressed {
+                        reason: "Duplicate message suppressed.".into(),
+                    };
+                }
+                Err(_) => {
+                    return DeliveryResult::TemporaryFailure {
+                        reason: "Transient server failure.".into(),
+                    };
+                }
+            }
+        }
+
+        let result = self
+            .deliver_to_account(raw_message, sender_address, rcpt, name)
+            .await;
+
+        // Only successful deliveries are remembered, so retries are not suppressed
+        if let (Some((account_id, fingerprint, _)), Some(expires)) = (dedup, reservation) {
+            if !matches!(result, DeliveryResult::Success) {
+                self.release_delivery(account_id, &fingerprint, expires)
+                    .await
+                    .ok();
+            }
+        }
+
+        result
+    }
+
+    pub async fn dedup_window(&self, account_id: u32) -> Result<Option<u64>, MethodError> {
+        self.store
+            .get_value::<u64>(CustomValueKey {
+                value: AccountKey::dedup_window(account_id),
+            })
+            .await
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve deduplication preferences");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn set_dedup_window(
+        &self,
+        account_id: u32,
+        window: Option<u64>,
+    ) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::dedup_window(account_id),
+                },
+                set: window.map(|window| window.serialize()),
+            });
+        self.write_batch(batch).await
+    }
+
+    pub async fn is_duplicate_delivery(
+        &self,
+        account_id: u32,
+        fingerprint: &str,
+    ) -> Result<bool, MethodError> {
+        self.delivery_expiry(account_id, fingerprint)
+            .await
+            .map(|expires| expires.map_or(false, |expires| expires > now()))
+    }
+
+    async fn delivery_expiry(
+        &self,
+        account_id: u32,
+        fingerprint: &str,
+    ) -> Result<Option<u64>, MethodError> {
+        self.store
+            .get_value::<u64>(CustomValueKey {
+                value: AccountKey::delivered_message(account_id, fingerprint),
+            })
+            .await
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve delivered message");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    // Atomically records a delivery unless an unexpired entry already exists.
+    // Returns the stored expiry when the reservation was made, or None for duplicates.
+    pub async fn reserve_delivery(
+        &self,
+        account_id: u32,
+        fingerprint: &str,
+        expires: u64,
+    ) -> Result<Option<u64>, MethodError> {
+        let key = AccountKey::delivered_message(account_id, fingerprint);
+        let mut try_count = 0;
+
+        loop {
+            let current = self.delivery_expiry(account_id, fingerprint).await?;
+            if current.map_or(false, |current| current > now()) {
+                return Ok(None);
+            }
+
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal);
+            if let Some(current) = current {
+                batch.assert_value(ValueClass::Custom { bytes: key.clone() }, current);
+            } else {
+                batch.assert_value(ValueClass::Custom { bytes: key.clone() }, ());
+            }
+            batch.op(Operation::Value {
+                class: ValueClass::Custom { bytes: key.clone() },
+                set: expires.serialize().into(),
+            });
+
+            match self.store.write(batch.build()).await {
+                Ok(_) => return Ok(Some(expires)),
+                Err(store::Error::AssertValueFailed) if try_count < 3 => {
+                    try_count += 1;
+                }
+                Err(err) => {
+                    tracing::error!(event = "error",
+                        context = "store",
+                        account_id = account_id,
+                        error = ?err,
+                        "Failed to reserve delivery");
+                    return Err(MethodError::ServerPartialFail);
+                }
+            }
+        }
+    }
+
+    // Removes a reservation made by this delivery attempt, leaving newer ones intact.
+    async fn release_delivery(
+        &self,
+        account_id: u32,
+        fingerprint: &str,
+        expires: u64,
+    ) -> Result<(), MethodError> {
+        let key = AccountKey::delivered_message(account_id, fingerprint);
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .assert_value(ValueClass::Custom { bytes: key.clone() }, expires)
+            .op(Operation::Value {
+                class: ValueClass::Custom { bytes: key },
+                set: None,
+            });
+        match self.store.write(batch.build()).await {
+            Ok(_) | Err(store::Error::AssertValueFailed) => Ok(()),
+            Err(err) => {
+                tracing::error!(event = "error",
+                    context = "store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to release delivery");
+                Err(MethodError::ServerPartialFail)
+            }
+        }
+    }
+
+    pub async fn record_delivery(
+        &self,
+        account_id: u32,
+        fingerprint: &str,
+        expires: u64,
+    ) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::delivered_message(account_id, fingerprint),
+                },
+                set: expires.serialize().into(),
+            });
+        self.write_batch(batch).await
+    }
+
+    pub async fn delivered_messages(
+        &self,
+        account_id: Option<u32>,
+    ) -> store::Result<Vec<(u32, String, u64)>> {
+        let (from_account_id, to_account_id) =
+            account_id.map_or((0, u32::MAX), |account_id| (account_id, account_id + 1));
+        self.store
+            .iterate(
+                Vec::new(),
+                CustomValueKey {
+                    value: AccountKey::delivered_message(from_account_id, ""),
+                },
+                CustomValueKey {
+                    value: AccountKey::delivered_message(to_account_id, ""),
+                },
+                false,
+                true,
+                move |messages, key, value| {
+                    // Skip the u32::MAX account prefix and the key type
+                    let offset = std::mem::size_of::<u32>() + 1;
+                    if let Some(fingerprint) = key
+                        .get(offset + std::mem::size_of::<u32>()..)
+                        .and_then(|fingerprint| std::str::from_utf8(fingerprint).ok())
+                    {
+                        messages.push((
+                            key.deserialize_be_u32(offset)?,
+                            fingerprint.to_string(),
+                            u64::deserialize(value)?,
+                        ));
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+    }
+
+    pub async fn purge_delivered_messages(&self) -> store::Result<()> {
+        let now = now();
+        let expired = self
+            .delivered_messages(None)
+            .await?
+            .into_iter()
+            .filter(|(_, _, expires)| *expires <= now)
+            .collect::<Vec<_>>();
+
+        for messages in expired.chunks(100) {
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal);
+            for (account_id, fingerprint, _) in messages {
+                batch.op(Operation::Value {
+                    class: ValueClass::Custom {
+                        bytes: AccountKey::delivered_message(*account_id, fingerprint),
+                    },
+                    set: None,
+                });
+            }
+            self.store.write(batch.build()).await?;
+        }
+
+        Ok(())
+    }
+}
+
+// Identifies a message by its Message-ID, envelope sender and body. Headers are
+// excluded since each copy carries its own trace fields.
+pub fn delivery_fingerprint(raw_message: &[u8], sender_address: &str) -> Option<String> {
+    let message_id =
+        find_message_id(raw_message).filter(|message_id| message_id.len() < MAX_ID_LENGTH)?;
+    let body = raw_message
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| &raw_message[pos + 4..])
+        .or_else(|| {
+            raw_message
+                .windows(2)
+                .position(|window| window == b"\n\n")
+                .map(|pos| &raw_message[pos + 2..])
+        })
+        .unwrap_or_default();
+
+    let mut hasher = Sha256::new();
+    hasher.update(message_id.as_bytes());
+    hasher.update([0]);
+    hasher.update(sender_address.to_lowercase().as_bytes());
+    hasher.update([0]);
+    hasher.update(body);
+    Some(
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect(),
+    )
+}
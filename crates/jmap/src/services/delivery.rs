@@ -26,7 +26,7 @@ use std::sync::Arc;
 use tokio::sync::mpsc;
 use utils::ipc::DeliveryEvent;
 
-use crate::JMAP;
+use crate::{auth::oauth::jwt::TokenProtocol, JMAP};
 
 pub fn spawn_delivery_manager(core: Arc<JMAP>, mut delivery_rx: mpsc::Receiver<DeliveryEvent>) {
     tokio::spawn(async move {
@@ -35,6 +35,119 @@ pub fn spawn_delivery_manager(core: Arc<JMAP>, mut delivery_rx: mpsc::Receiver<D
                 DeliveryEvent::Ingest { message, result_tx } => {
                     result_tx.send(core.deliver_message(message).await).ok();
                 }
+                DeliveryEvent::ValidateToken { token, result_tx } => {
+                    // Resolve OAuth bearer tokens presented over SMTP AUTH
+                    let account_name = match core
+                        .validate_access_token("access_token", &token, TokenProtocol::Smtp.into())
+                        .await
+                    {
+                        Ok((account_id, _, _)) => {
+                            core.get_account_name(account_id).await.ok().flatten()
+                        }
+                        Err(err) => {
+                            tracing::debug!(
+                                context = "validate_token",
+                                event = "error",
+                                reason = err,
+                                "Failed to validate access token."
+                            );
+                            None
+                        }
+                    };
+                    result_tx.send(account_name).ok();
+                }
+                DeliveryEvent::Authenticate {
+                    username,
+                    secret,
+                    result_tx,
+                } => {
+                    // Password hashing is slow, do not block other deliveries
+                    let core = core.clone();
+                    tokio::spawn(async move {
+                        let account_name = match core.authenticate_secret(&username, &secret).await
+                        {
+                            Ok(principal) => principal.map(|_| username),
+                            Err(err) => {
+                                tracing::debug!(
+                                    context = "authenticate",
+                                    event = "error",
+                                    reason = ?err,
+                                    "Failed to authenticate account."
+                                );
+                                None
+                            }
+                        };
+                        result_tx.send(account_name).ok();
+                    });
+                }
+                DeliveryEvent::RequiresSecondFactor { account, result_tx } => {
+                    result_tx
+                        .send(core.requires_second_factor(&account).await)
+                        .ok();
+                }
+                DeliveryEvent::LookupMaskedEmail { address, result_tx } => {
+                    result_tx
+                        .send(core.masked_email_status(&address).await.ok())
+                        .ok();
+                }
+                DeliveryEvent::CollectAddresses { account, addresses } => {
+                    let core = core.clone();
+                    tokio::spawn(async move {
+                        if let Ok(account_id) = core.get_account_id(&account).await {
+                            core.collect_addresses(
+                                account_id,
+                                addresses.into_iter().map(|address| (address, None)),
+                            )
+                            .await
+                            .ok();
+                        }
+                    });
+                }
+                DeliveryEvent::FileSentCopy { account, message } => {
+                    let core = core.clone();
+                    tokio::spawn(async move {
+                        core.file_sent_copy(&account, &message).await;
+                    });
+                }
+                DeliveryEvent::ApplyPolicyFooter {
+                    sender,
+                    message,
+                    result_tx,
+                } => {
+                    let core = core.clone();
+                    tokio::spawn(async move {
+                        let result = match core.policy_footer(&sender, &message).await {
+                            Ok(result) => result,
+                            Err(err) => {
+                                tracing::error!(
+                                    context = "domain_policy",
+                                    event = "error",
+                                    sender = sender,
+                                    error = ?err,
+                                    "Failed to apply policy footer."
+                                );
+                                None
+                            }
+                        };
+                        result_tx.send(result).ok();
+                    });
+                }
+                DeliveryEvent::RevokeSessions { account } => {
+                    let core = core.clone();
+                    tokio::spawn(async move {
+                        if let Ok(account_id) = core.get_account_id(&account).await {
+                            if let Err(err) = core.revoke_all_sessions(account_id).await {
+                                tracing::error!(
+                                    context = "anomaly",
+                                    event = "error",
+                                    account = account,
+                                    error = ?err,
+                                    "Failed to revoke sessions."
+                                );
+                            }
+                        }
+                    });
+                }
                 DeliveryEvent::Stop => break,
             }
         }
@@ -21,7 +21,10 @@
  * for more details.
 */
 
-use std::{sync::Arc, time::Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use tokio::sync::mpsc;
 use utils::{
@@ -38,12 +41,19 @@ pub enum Event {
     PurgeDb,
     PurgeBlobs,
     PurgeSessions,
+    WakeSnoozed,
+    SendDigests,
+    SpamTrain(usize),
     Exit,
 }
 
 const TASK_PURGE_DB: usize = 0;
 const TASK_PURGE_BLOBS: usize = 1;
 const TASK_PURGE_SESSIONS: usize = 2;
+const TASK_WAKE_SNOOZED: usize = 3;
+const TASK_SPAM_TRAIN: usize = 4;
+const TASK_SWEEP_TMP_BLOBS: usize = 5;
+const TASK_SEND_DIGESTS: usize = 6;
 
 pub fn spawn_housekeeper(core: Arc<JMAP>, settings: &Config, mut rx: mpsc::Receiver<Event>) {
     let purge_db_at = settings
@@ -55,16 +65,39 @@ pub fn spawn_housekeeper(core: Arc<JMAP>, settings: &Config, mut rx: mpsc::Recei
     let purge_cache = settings
         .property_or_static::<SimpleCron>("jmap.purge.schedule.sessions", "15 * *")
         .failed("Initialize housekeeper");
+    let wake_snoozed_every = settings
+        .property_or_static::<Duration>("jmap.snooze.poll-interval", "1m")
+        .failed("Initialize housekeeper");
+    let spam_train_every = settings
+        .property_or_static::<Duration>("jmap.spam.training.interval", "1m")
+        .failed("Initialize housekeeper");
+    let spam_train_batch_size = settings
+        .property_or_static::<usize>("jmap.spam.training.batch-size", "50")
+        .failed("Initialize housekeeper");
+    let sweep_tmp_blobs_every = settings
+        .property_or_static::<Duration>("jmap.protocol.upload.purge-interval", "5m")
+        .failed("Initialize housekeeper");
+    let send_digests_at = settings
+        .property_or_static::<SimpleCron>("jmap.digest.schedule", "0 7 *")
+        .failed("Initialize housekeeper");
 
     tokio::spawn(async move {
         tracing::debug!("Housekeeper task started.");
+        let mut wake_snoozed_at = Instant::now() + wake_snoozed_every;
+        let mut spam_train_at = Instant::now() + spam_train_every;
+        let mut spam_train_queued = 0;
+        let mut sweep_tmp_blobs_at = Instant::now() + sweep_tmp_blobs_every;
         loop {
             let time_to_next = [
                 purge_db_at.time_to_next(),
                 purge_blobs_at.time_to_next(),
                 purge_cache.time_to_next(),
+                wake_snoozed_at.saturating_duration_since(Instant::now()),
+                spam_train_at.saturating_duration_since(Instant::now()),
+                sweep_tmp_blobs_at.saturating_duration_since(Instant::now()),
+                send_digests_at.time_to_next(),
             ];
-            let mut tasks_to_run = [false, false, false];
+            let mut tasks_to_run = [false, false, false, false, false, false, false];
             let start_time = Instant::now();
 
             match tokio::time::timeout(time_to_next.iter().min().copied().unwrap(), rx.recv()).await
@@ -73,6 +106,14 @@ pub fn spawn_housekeeper(core: Arc<JMAP>, settings: &Config, mut rx: mpsc::Recei
                     Event::PurgeDb => tasks_to_run[TASK_PURGE_DB] = true,
                     Event::PurgeBlobs => tasks_to_run[TASK_PURGE_BLOBS] = true,
                     Event::PurgeSessions => tasks_to_run[TASK_PURGE_SESSIONS] = true,
+                    Event::WakeSnoozed => tasks_to_run[TASK_WAKE_SNOOZED] = true,
+                    Event::SendDigests => tasks_to_run[TASK_SEND_DIGESTS] = true,
+                    Event::SpamTrain(num_messages) => {
+                        spam_train_queued += num_messages;
+                        if spam_train_queued >= spam_train_batch_size {
+                            tasks_to_run[TASK_SPAM_TRAIN] = true;
+                        }
+                    }
                     Event::Exit => {
                         tracing::debug!("Housekeeper task exiting.");
                         return;
@@ -92,6 +133,16 @@ pub fn spawn_housekeeper(core: Arc<JMAP>, settings: &Config, mut rx: mpsc::Recei
                     tasks_to_run[pos] = true;
                 }
             }
+            if tasks_to_run[TASK_WAKE_SNOOZED] {
+                wake_snoozed_at = now + wake_snoozed_every;
+            }
+            if tasks_to_run[TASK_SWEEP_TMP_BLOBS] {
+                sweep_tmp_blobs_at = now + sweep_tmp_blobs_every;
+            }
+            if tasks_to_run[TASK_SPAM_TRAIN] {
+                spam_train_at = now + spam_train_every;
+                spam_train_queued = 0;
+            }
 
             // Spawn tasks
             for (task_id, do_run) in tasks_to_run.into_iter().enumerate() {
@@ -108,24 +159,83 @@ pub fn spawn_housekeeper(core: Arc<JMAP>, settings: &Config, mut rx: mpsc::Recei
                             if let Err(err) = core.store.purge_bitmaps().await {
                                 tracing::error!("Error while purging bitmaps: {}", err);
                             }
+                            if let Err(err) = core.purge_delivered_messages().await {
+                                tracing::error!(
+                                    "Error while purging delivered message ids: {}",
+                                    err
+                                );
+                            }
+                            if let Err(err) = core.purge_policy_vacation_replies().await {
+                                tracing::error!(
+                                    "Error while purging vacation reply history: {}",
+                                    err
+                                );
+                            }
                         }
                         TASK_PURGE_BLOBS => {
-                            tracing::info!("Purging temporary blobs.",);
+                            tracing::info!("Purging attachment links.");
+                            if let Err(err) = core.purge_attachment_links().await {
+                                tracing::error!("Error while purging attachment links: {}", err);
+                            }
+                        }
+                        TASK_SWEEP_TMP_BLOBS => {
+                            tracing::debug!("Purging expired temporary blobs.");
                             if let Err(err) =
                                 core.store.purge_tmp_blobs(core.config.upload_tmp_ttl).await
                             {
-                                tracing::error!("Error while purging bitmaps: {}", err);
+                                tracing::error!("Error while purging temporary blobs: {}", err);
                             }
                         }
                         TASK_PURGE_SESSIONS => {
                             tracing::info!("Purging session cache.");
                             core.sessions.cleanup();
                             core.access_tokens.cleanup();
-                            core.oauth_codes.cleanup();
+                            core.revoked_tokens.cleanup();
+                            core.device_polls.cleanup();
+                            core.converted_parts.cleanup();
+                            core.principals.cleanup();
+                            core.tmp_blob_usage.cleanup();
+                            core.sent_copies.cleanup();
                             core.rate_limit_auth
                                 .retain(|_, limiter| limiter.lock().is_active());
                             core.rate_limit_unauth
                                 .retain(|_, limiter| limiter.lock().is_active());
+                            core.rate_limit_tenant
+                                .retain(|_, limiter| limiter.lock().is_active());
+                            if let Err(err) = core.purge_oauth_codes().await {
+                                tracing::error!("Error while purging OAuth codes: {}", err);
+                            }
+                            if let Err(err) = core.refresh_jwt_keys().await {
+                                tracing::error!("Error while refreshing JWT signing keys: {}", err);
+                            }
+                            if let Err(err) = core.expire_renamed_aliases().await {
+                                tracing::error!("Error while expiring renamed aliases: {}", err);
+                            }
+                        }
+                        TASK_WAKE_SNOOZED => {
+                            if let Err(err) = core.wake_snoozed_emails().await {
+                                tracing::error!("Error while waking snoozed emails: {}", err);
+                            }
+                        }
+                        TASK_SPAM_TRAIN => match core.spam_train_batch(spam_train_batch_size).await
+                        {
+                            Ok(true) => {
+                                // Continue with the next batch right away
+                                let _ = core
+                                    .housekeeper_tx
+                                    .send(Event::SpamTrain(spam_train_batch_size))
+                                    .await;
+                            }
+                            Ok(false) => (),
+                            Err(err) => {
+                                tracing::error!("Error while training spam filter: {}", err);
+                            }
+                        },
+                        TASK_SEND_DIGESTS => {
+                            tracing::info!("Sending newsletter digests.");
+                            if let Err(err) = core.send_digests().await {
+                                tracing::error!("Error while sending digests: {}", err);
+                            }
                         }
                         _ => unreachable!(),
                     }
@@ -21,12 +21,31 @@
  * for more details.
 */
 
-use jmap_proto::types::{state::StateChange, type_state::DataType};
+use jmap_proto::{
+    error::method::MethodError,
+    types::{collection::Collection, state::StateChange, type_state::DataType},
+};
 use mail_parser::MessageParser;
 use store::ahash::AHashMap;
 use utils::ipc::{DeliveryResult, IngestMessage};
 
-use crate::{email::ingest::IngestEmail, mailbox::INBOX_ID, IngestError, JMAP};
+use crate::{
+    collected_address::first_contact::{message_sender, with_first_contact_header},
+    email::{
+        ingest::{authenticated_domains, bimi_indicator, is_spam_verdict, IngestEmail},
+        spam_train::JUNK_ROLE,
+    },
+    mailbox::INBOX_ID,
+    mailing_list::{
+        digest::{digest_item, is_newsletter},
+        list_headers,
+    },
+    settings::{
+        policy::address_domain,
+        sender_list::{BlockAction, SenderVerdict},
+    },
+    IngestError, JMAP,
+};
 
 impl JMAP {
     pub async fn deliver_message(&self, message: IngestMessage) -> Vec<DeliveryResult> {
@@ -45,12 +64,30 @@ impl JMAP {
         // Obtain the UIDs for each recipient
         let mut recipients = Vec::with_capacity(message.recipients.len());
         let mut deliver_names = AHashMap::with_capacity(message.recipients.len());
+        let mut masked_routes = Vec::new();
         for rcpt in &message.recipients {
-            let names = self
+            let mut names = self
                 .directory
                 .names_by_email(rcpt)
                 .await
                 .unwrap_or_default();
+
+            // Route masked email addresses to their owner
+            if names.is_empty() {
+                match self.masked_email_route(rcpt).await {
+                    Ok(Some(route)) if route.is_enabled => {
+                        if let Ok(Some(name)) = self.get_account_name(route.account_id).await {
+                            masked_routes.push((recipients.len(), route));
+                            names.push(name);
+                        }
+                    }
+                    Ok(Some(route)) => {
+                        masked_routes.push((recipients.len(), route));
+                    }
+                    _ => (),
+                }
+            }
+
             for name in &names {
                 deliver_names.insert(name.clone(), (DeliveryResult::Success, rcpt));
             }
@@ -59,107 +96,31 @@ impl JMAP {
 
         // Deliver to each recipient
         for (name, (status, rcpt)) in &mut deliver_names {
-            // Obtain account id
-            let uid = match self.get_account_id(name).await {
-                Ok(uid) => uid,
-                Err(_) => {
-                    *status = DeliveryResult::TemporaryFailure {
-                        reason: "Transient server failure.".into(),
-                    };
-                    continue;
-                }
-            };
-
-            // Check if there is an active sieve script
-            let result = match self.sieve_script_get_active(uid).await {
-                Ok(Some(active_script)) => {
-                    self.sieve_script_ingest(
-                        &raw_message,
-                        &message.sender_address,
-                        rcpt,
-                        uid,
-                        name,
-                        active_script,
-                    )
-                    .await
-                }
-                Ok(None) => {
-                    let account_quota = match self.directory.principal(name).await {
-                        Ok(Some(p)) => p.quota as i64,
-                        Ok(None) => 0,
-                        Err(_) => {
-                            *status = DeliveryResult::TemporaryFailure {
-                                reason: "Transient server failure.".into(),
-                            };
-                            continue;
-                        }
-                    };
-
-                    self.email_ingest(IngestEmail {
-                        raw_message: &raw_message,
-                        message: MessageParser::new().parse(&raw_message),
-                        account_id: uid,
-                        account_quota,
-                        mailbox_ids: vec![INBOX_ID],
-                        keywords: vec![],
-                        received_at: None,
-                        skip_duplicates: true,
-                        encrypt: self.config.encrypt,
-                    })
-                    .await
-                }
-                Err(_) => {
-                    *status = DeliveryResult::TemporaryFailure {
-                        reason: "Transient server failure.".into(),
-                    };
-                    continue;
-                }
-            };
-
-            match result {
-                Ok(ingested_message) => {
-                    // Notify state change
-                    if ingested_message.change_id != u64::MAX {
-                        self.broadcast_state_change(
-                            StateChange::new(uid)
-                                .with_change(DataType::EmailDelivery, ingested_message.change_id)
-                                .with_change(DataType::Email, ingested_message.change_id)
-                                .with_change(DataType::Mailbox, ingested_message.change_id)
-                                .with_change(DataType::Thread, ingested_message.change_id),
-                        )
-                        .await;
-                    }
-                }
-                Err(err) => match err {
-                    IngestError::OverQuota => {
-                        *status = DeliveryResult::TemporaryFailure {
-                            reason: "Mailbox over quota.".into(),
-                        }
-                    }
-                    IngestError::Temporary => {
-                        *status = DeliveryResult::TemporaryFailure {
-                            reason: "Transient server failure.".into(),
-                        }
-                    }
-                    IngestError::Permanent { code, reason } => {
-                        *status = DeliveryResult::PermanentFailure {
-                            code,
-                            reason: reason.into(),
-                        }
-                    }
-                },
-            }
+            *status = self
+                .deliver_once(&raw_message, &message.sender_address, rcpt, name)
+                .await;
         }
 
         // Build result
-        recipients
+        let results = recipients
             .into_iter()
-            .map(|names| {
+            .enumerate()
+            .map(|(rcpt_idx, names)| {
                 match names.len() {
                     1 => {
                         // Delivery to single recipient
                         deliver_names.get(&names[0]).unwrap().0.clone()
                     }
+                    0 if masked_routes
+                        .iter()
+                        .any(|(idx, route)| *idx == rcpt_idx && !route.is_enabled) =>
+                    {
+                        // Masked email address has been disabled
+                        DeliveryResult::PermanentFailure {
+                            code: [5, 2, 1],
+                            reason: "Mailbox disabled.".into(),
+                        }
+                    }
                     0 => {
                         // Something went wrong
                         DeliveryResult::TemporaryFailure {
@@ -172,7 +133,9 @@ impl JMAP {
                         let mut temp_failures = 0;
                         for uid in names {
                             match deliver_names.get(&uid).unwrap().0 {
-                                DeliveryResult::Success => success += 1,
+                                DeliveryResult::Success | DeliveryResult::Suppressed { .. } => {
+                                    success += 1
+                                }
                                 DeliveryResult::TemporaryFailure { .. } => temp_failures += 1,
                                 DeliveryResult::PermanentFailure { .. } => {}
                             }
@@ -193,6 +156,463 @@ impl JMAP {
                     }
                 }
             })
-            .collect()
+            .collect::<Vec<_>>();
+
+        // Update masked email counters
+        for (rcpt_idx, route) in masked_routes {
+            if route.is_enabled && matches!(results[rcpt_idx], DeliveryResult::Success) {
+                self.masked_email_delivered(route.account_id, route.document_id)
+                    .await
+                    .ok();
+            }
+        }
+
+        results
+    }
+
+    pub async fn deliver_to_account(
+        &self,
+        raw_message: &[u8],
+        sender_address: &str,
+        rcpt: &str,
+        name: &str,
+    ) -> DeliveryResult {
+        // Obtain account id
+        let uid = match self.get_account_id(name).await {
+            Ok(uid) => uid,
+            Err(_) => {
+                return DeliveryResult::TemporaryFailure {
+                    reason: "Transient server failure.".into(),
+                };
+            }
+        };
+
+        // Apply the sender allow and block lists of the account
+        let sender_verdict = match self.get_sender_lists(uid).await {
+            Ok(lists) if !lists.is_empty() => {
+                let header_sender = message_sender(raw_message);
+                lists.verdict(
+                    [Some(sender_address), header_sender.as_deref()]
+                        .into_iter()
+                        .flatten()
+                        .filter(|sender| !sender.is_empty()),
+                    &authenticated_domains(
+                        raw_message,
+                        &self.config.mail_bimi_authserv_id,
+                        &["dmarc", "dkim", "spf"],
+                    ),
+                )
+            }
+            Ok(_) => SenderVerdict::None,
+            Err(_) => {
+                return DeliveryResult::TemporaryFailure {
+                    reason: "Transient server failure.".into(),
+                };
+            }
+        };
+        match sender_verdict {
+            SenderVerdict::Block(BlockAction::Reject) => {
+                tracing::info!(
+                    context = "sender_list",
+                    event = "reject",
+                    account_id = uid,
+                    sender = sender_address,
+                    "Rejected message from blocked sender."
+                );
+                return DeliveryResult::PermanentFailure {
+                    code: [5, 7, 1],
+                    reason: "Sender is blocked by the recipient.".into(),
+                };
+            }
+            SenderVerdict::Block(BlockAction::Trash) => {
+                tracing::info!(
+                    context = "sender_list",
+                    event = "trash",
+                    account_id = uid,
+                    sender = sender_address,
+                    "Filing message from blocked sender into Trash."
+                );
+            }
+            SenderVerdict::Allow | SenderVerdict::None => (),
+        }
+        let is_blocked = matches!(sender_verdict, SenderVerdict::Block(_));
+
+        // Obtain the policies set by the administrators of the recipient's domain
+        let policy_domain = address_domain(rcpt).unwrap_or_default();
+        let policy = match self.get_domain_policy(&policy_domain).await {
+            Ok(policy) => policy,
+            Err(_) => {
+                return DeliveryResult::TemporaryFailure {
+                    reason: "Transient server failure.".into(),
+                };
+            }
+        };
+
+        // Obtain the forwarding addresses of the account owner allowed by the domain policy,
+        // messages are only forwarded once the account's filters have accepted them
+        let (forward_to, forward_keep_copy) = match self.get_account_settings(uid).await {
+            Ok(settings) if !is_blocked => (
+                settings
+                    .forward_to
+                    .into_iter()
+                    .filter(|address| self.is_forward_allowed(&policy_domain, &policy, address))
+                    .collect::<Vec<_>>(),
+                settings.forward_keep_copy,
+            ),
+            Ok(_) => (Vec::new(), true),
+            Err(_) => {
+                return DeliveryResult::TemporaryFailure {
+                    reason: "Transient server failure.".into(),
+                };
+            }
+        };
+
+        // Flag messages from senders that have never written to this account
+        let mut first_contact = None;
+        let first_contact_message = if self.config.first_contact {
+            match self
+                .first_contact_sender(uid, raw_message, sender_address)
+                .await
+            {
+                Ok(sender) => {
+                    let message = with_first_contact_header(raw_message, sender.is_some());
+                    first_contact = sender;
+                    Some(message)
+                }
+                Err(_) => {
+                    return DeliveryResult::TemporaryFailure {
+                        reason: "Transient server failure.".into(),
+                    };
+                }
+            }
+        } else {
+            None
+        };
+        let raw_message = first_contact_message.as_deref().unwrap_or(raw_message);
+
+        // Look up the filing and digest preferences of the account owner for this list
+        let mut list_mailbox_id = None;
+        let mut skip_digest = false;
+        let mailing_list = if self.config.mailing_lists && !is_blocked {
+            list_headers(raw_message)
+        } else {
+            None
+        };
+        if let Some(mailing_list) = &mailing_list {
+            match self.get_mailing_list(uid, &mailing_list.id).await {
+                Ok(Some(entry)) => {
+                    list_mailbox_id = entry.mailbox_id;
+                    skip_digest = entry.skip_digest;
+                }
+                Ok(None) => (),
+                Err(_) => {
+                    return DeliveryResult::TemporaryFailure {
+                        reason: "Transient server failure.".into(),
+                    };
+                }
+            }
+        }
+        let mut is_digest = false;
+
+        // Check if there is an active sieve script, blocked messages are filed
+        // into Trash without running it
+        let active_script = if !is_blocked {
+            self.sieve_script_get_active(uid).await
+        } else {
+            Ok(None)
+        };
+        let result = match active_script {
+            Ok(Some(active_script)) => {
+                self.sieve_script_ingest(
+                    raw_message,
+                    sender_address,
+                    rcpt,
+                    uid,
+                    name,
+                    active_script,
+                    &policy_domain,
+                    &policy,
+                    &forward_to,
+                    forward_keep_copy,
+                )
+                .await
+            }
+            Ok(None) => {
+                let account_quota = match self.directory.principal(name).await {
+                    Ok(Some(p)) => p.quota as i64,
+                    Ok(None) => 0,
+                    Err(_) => {
+                        return DeliveryResult::TemporaryFailure {
+                            reason: "Transient server failure.".into(),
+                        };
+                    }
+                };
+
+                // File messages addressed to unknown recipients into the review mailbox
+                let accepted_message = raw_message;
+                let mut review_message = None;
+                let mut mailbox_id = INBOX_ID;
+                if let Some(review_mailbox) = &self.config.catch_all_review_mailbox {
+                    if self.is_catch_all_rcpt(rcpt, name).await {
+                        match self.catch_all_review_mailbox(uid, review_mailbox).await {
+                            Ok(Some(document_id)) => {
+                                let mut raw_review_message =
+                                    Vec::with_capacity(raw_message.len() + rcpt.len() + 17);
+                                raw_review_message.extend_from_slice(b"X-Original-To: ");
+                                raw_review_message.extend_from_slice(rcpt.as_bytes());
+                                raw_review_message.extend_from_slice(b"\r\n");
+                                raw_review_message.extend_from_slice(raw_message);
+                                review_message = raw_review_message.into();
+                                mailbox_id = document_id;
+                            }
+                            Ok(None) => {
+                                tracing::warn!(
+                                    context = "catch_all",
+                                    event = "error",
+                                    account_id = uid,
+                                    mailbox = review_mailbox,
+                                    "Invalid catch-all review mailbox name."
+                                );
+                            }
+                            Err(_) => {
+                                return DeliveryResult::TemporaryFailure {
+                                    reason: "Transient server failure.".into(),
+                                };
+                            }
+                        }
+                    }
+                }
+                let raw_message = review_message.as_deref().unwrap_or(raw_message);
+
+                // File list messages into their folder, unless the mailbox was deleted
+                if let Some(list_mailbox_id) = list_mailbox_id.filter(|_| mailbox_id == INBOX_ID) {
+                    match self.get_document_ids(uid, Collection::Mailbox).await {
+                        Ok(mailbox_ids) => {
+                            if mailbox_ids.map_or(false, |ids| ids.contains(list_mailbox_id)) {
+                                mailbox_id = list_mailbox_id;
+                            }
+                        }
+                        Err(_) => {
+                            return DeliveryResult::TemporaryFailure {
+                                reason: "Transient server failure.".into(),
+                            };
+                        }
+                    }
+                }
+
+                // Hold newsletters for the daily digest
+                if mailbox_id == INBOX_ID
+                    && !is_blocked
+                    && !skip_digest
+                    && is_newsletter(raw_message)
+                {
+                    match self.digest_enabled(uid).await {
+                        Ok(true) => match self.mailbox_get_or_create_digest(uid).await {
+                            Ok(document_id) => {
+                                mailbox_id = document_id;
+                                is_digest = true;
+                            }
+                            Err(_) => {
+                                return DeliveryResult::TemporaryFailure {
+                                    reason: "Transient server failure.".into(),
+                                };
+                            }
+                        },
+                        Ok(false) => (),
+                        Err(_) => {
+                            return DeliveryResult::TemporaryFailure {
+                                reason: "Transient server failure.".into(),
+                            };
+                        }
+                    }
+                }
+
+                // File blocked messages into Trash and spam into Junk, unless
+                // the sender is allowlisted
+                let special_role = if is_blocked {
+                    Some("trash")
+                } else if self.config.spam_junk_folder
+                    && sender_verdict != SenderVerdict::Allow
+                    && is_spam_verdict(raw_message, &self.config.mail_bimi_authserv_id)
+                {
+                    Some(JUNK_ROLE)
+                } else {
+                    None
+                };
+                if let Some(role) = special_role {
+                    match self.special_use_mailbox(uid, role).await {
+                        Ok(Some(document_id)) => {
+                            mailbox_id = document_id;
+                            is_digest = false;
+                        }
+                        Ok(None) => (),
+                        Err(_) => {
+                            return DeliveryResult::TemporaryFailure {
+                                reason: "Transient server failure.".into(),
+                            };
+                        }
+                    }
+                } else if !forward_to.is_empty()
+                    && self
+                        .forward_message(accepted_message, sender_address, name, rcpt, &forward_to)
+                        .await
+                    && !forward_keep_copy
+                {
+                    // Spam is kept in the account rather than forwarded
+                    return DeliveryResult::Success;
+                }
+
+                self.email_ingest(IngestEmail {
+                    raw_message,
+                    message: MessageParser::new().parse(raw_message),
+                    account_id: uid,
+                    account_quota,
+                    mailbox_ids: vec![mailbox_id],
+                    keywords: vec![],
+                    received_at: None,
+                    subaddress: self.directory.subaddress(rcpt),
+                    bimi_indicator: bimi_indicator(raw_message, &self.config.mail_bimi_authserv_id),
+                    is_delivered: true,
+                    skip_duplicates: true,
+                    encrypt: self.config.encrypt,
+                })
+                .await
+            }
+            Err(_) => {
+                return DeliveryResult::TemporaryFailure {
+                    reason: "Transient server failure.".into(),
+                };
+            }
+        };
+
+        match result {
+            Ok(ingested_message) => {
+                // Remember the sender once the message has been delivered
+                if let Some(sender) = first_contact {
+                    self.add_known_sender(uid, &sender).await.ok();
+                }
+
+                if ingested_message.change_id != u64::MAX {
+                    // Schedule held newsletters for the next digest
+                    if is_digest {
+                        self.hold_for_digest(
+                            uid,
+                            ingested_message.id.document_id(),
+                            digest_item(
+                                raw_message,
+                                ingested_message.id.to_string(),
+                                mailing_list.as_ref(),
+                            ),
+                        )
+                        .await
+                        .ok();
+                    }
+
+                    // Register the list in the account's subscription overview, duplicates
+                    // are not counted
+                    if let Some(mailing_list) = mailing_list.as_ref().filter(|mailing_list| {
+                        mailing_list
+                            .is_authenticated(raw_message, &self.config.mail_bimi_authserv_id)
+                    }) {
+                        self.record_mailing_list(uid, mailing_list).await.ok();
+                    }
+                }
+
+                // Notify state change
+                if ingested_message.change_id != u64::MAX {
+                    self.broadcast_state_change(
+                        StateChange::new(uid)
+                            .with_change(DataType::EmailDelivery, ingested_message.change_id)
+                            .with_change(DataType::Email, ingested_message.change_id)
+                            .with_change(DataType::Mailbox, ingested_message.change_id)
+                            .with_change(DataType::Thread, ingested_message.change_id),
+                    )
+                    .await;
+
+                    // Acknowledge disposition notification requests
+                    if self.config.mdn_auto_processed {
+                        self.mdn_auto_send(raw_message, sender_address, rcpt).await;
+                    }
+
+                    // Send the out of office response enforced by the domain policy
+                    if let Some(vacation) = policy.vacation.as_ref().filter(|_| !is_blocked) {
+                        self.policy_vacation_reply(
+                            vacation,
+                            uid,
+                            raw_message,
+                            sender_address,
+                            rcpt,
+                        )
+                        .await;
+                    }
+                }
+
+                DeliveryResult::Success
+            }
+            Err(err) => match err {
+                IngestError::OverQuota => DeliveryResult::TemporaryFailure {
+                    reason: "Mailbox over quota.".into(),
+                },
+                IngestError::Temporary => DeliveryResult::TemporaryFailure {
+                    reason: "Transient server failure.".into(),
+                },
+                IngestError::Permanent { code, reason } => DeliveryResult::PermanentFailure {
+                    code,
+                    reason: reason.into(),
+                },
+            },
+        }
+    }
+
+    async fn special_use_mailbox(
+        &self,
+        account_id: u32,
+        role: &str,
+    ) -> Result<Option<u32>, MethodError> {
+        self.mailbox_get_or_create(account_id).await?;
+        self.mailbox_get_by_role(account_id, role).await
+    }
+
+    async fn catch_all_review_mailbox(
+        &self,
+        account_id: u32,
+        path: &str,
+    ) -> Result<Option<u32>, MethodError> {
+        self.mailbox_get_or_create(account_id).await?;
+        self.mailbox_create_path(account_id, path)
+            .await
+            .map(|result| result.map(|(document_id, _)| document_id))
+    }
+
+    async fn is_catch_all_rcpt(&self, rcpt: &str, account_name: &str) -> bool {
+        // A recipient is considered to be delivered through the catch-all address
+        // when it does not belong to the account and the account owns the domain's
+        // catch-all address.
+        let rcpt = rcpt.to_lowercase();
+        let (local_part, domain_part) = if let Some(parts) = rcpt.rsplit_once('@') {
+            parts
+        } else {
+            return false;
+        };
+        let address = local_part
+            .split_once('+')
+            .map(|(local_part, _)| format!("{local_part}@{domain_part}"));
+
+        match self.directory.emails_by_name(account_name).await {
+            Ok(emails)
+                if !emails.iter().any(|email| {
+                    email.eq_ignore_ascii_case(&rcpt)
+                        || address
+                            .as_ref()
+                            .map_or(false, |address| email.eq_ignore_ascii_case(address))
+                }) =>
+            {
+                self.directory
+                    .names_by_email(&format!("@{domain_part}"))
+                    .await
+                    .map_or(false, |names| names.iter().any(|name| name == account_name))
+            }
+            _ => false,
+        }
     }
 }
@@ -21,6 +21,7 @@
  * for more details.
 */
 
+pub mod dedup;
 pub mod delivery;
 pub mod housekeeper;
 pub mod ingest;
@@ -0,0 +1,124 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::{totp::verify_totp, Principal};
+use mail_send::Credentials;
+use store::write::now;
+
+use crate::JMAP;
+
+use super::AccountSettings;
+
+impl JMAP {
+    // Authenticates a plain secret against the directory, enforcing the
+    // account's second factor and accepting its app passwords.
+    pub async fn authenticate_secret(
+        &self,
+        username: &str,
+        secret: &str,
+    ) -> directory::Result<Option<Principal>> {
+        if let Some(principal) = self.directory_authenticate(username, secret).await? {
+            return Ok(
+                match self
+                    .settings_by_name(principal_name(&principal, username))
+                    .await
+                {
+                    Some(settings) if !settings.has_totp() => Some(principal),
+                    Some(_) => {
+                        tracing::debug!(
+                            context = "authenticate",
+                            event = "failed",
+                            account = username,
+                            "Missing one-time code for account with two-factor authentication."
+                        );
+                        None
+                    }
+                    None => None,
+                },
+            );
+        }
+
+        // Password followed by a one-time code
+        if let Some((password, code)) = secret.rsplit_once('$') {
+            if let Some(principal) = self.directory_authenticate(username, password).await? {
+                return Ok(
+                    match self
+                        .settings_by_name(principal_name(&principal, username))
+                        .await
+                        .and_then(|settings| settings.totp_secret)
+                    {
+                        Some(totp_secret) if verify_totp(&totp_secret, code, now()) => {
+                            Some(principal)
+                        }
+                        _ => None,
+                    },
+                );
+            }
+        }
+
+        // App passwords
+        if let Some(settings) = self.settings_by_name(username).await {
+            if settings.verify_app_password(secret).await {
+                return self.directory.principal(username).await;
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub async fn directory_authenticate(
+        &self,
+        username: &str,
+        secret: &str,
+    ) -> directory::Result<Option<Principal>> {
+        self.directory
+            .authenticate(&Credentials::Plain {
+                username: username.to_string(),
+                secret: secret.to_string(),
+            })
+            .await
+    }
+
+    // Accounts with a second factor cannot log in with mechanisms that only
+    // prove knowledge of the password, such as SCRAM.
+    pub async fn requires_second_factor(&self, username: &str) -> bool {
+        self.settings_by_name(username)
+            .await
+            .map_or(true, |settings| settings.has_totp())
+    }
+
+    async fn settings_by_name(&self, name: &str) -> Option<AccountSettings> {
+        match self.try_get_account_id(name).await.ok()? {
+            Some(account_id) => self.get_account_settings(account_id).await.ok(),
+            None => Some(Default::default()),
+        }
+    }
+}
+
+fn principal_name<'x>(principal: &'x Principal, username: &'x str) -> &'x str {
+    if principal.has_name() {
+        principal.name()
+    } else {
+        username
+    }
+}
@@ -0,0 +1,112 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use mail_parser::MessageParser;
+use smtp::core::{NullIo, Session, SessionAddress};
+use store::write::now;
+
+use crate::JMAP;
+
+impl JMAP {
+    // Queues a copy of a delivered message for each forwarding address,
+    // returns false if the message could not be forwarded.
+    pub async fn forward_message(
+        &self,
+        raw_message: &[u8],
+        sender_address: &str,
+        account_name: &str,
+        rcpt: &str,
+        forward_to: &[String],
+    ) -> bool {
+        // Avoid forwarding loops
+        if MessageParser::new()
+            .parse(raw_message)
+            .and_then(|message| message.parts.into_iter().next())
+            .map_or(false, |part| {
+                part.headers.iter().any(|header| {
+                    header.name.as_str().eq_ignore_ascii_case("Delivered-To")
+                        && header
+                            .value
+                            .as_text()
+                            .map_or(false, |value| value.trim().eq_ignore_ascii_case(rcpt))
+                })
+            })
+        {
+            tracing::debug!(
+                context = "forward",
+                event = "loop",
+                account = account_name,
+                rcpt = rcpt,
+                "Message was already delivered to this address, not forwarding."
+            );
+            return false;
+        }
+
+        // Rewrite the original sender with SRS so the forwarded message passes SPF
+        // and bounces reach the sender, otherwise send it from the account address
+        let core = self.smtp.core();
+        let srs = &core.session.config.data.srs;
+        let mail_from = if let Some(mail_from) = srs
+            .is_enabled()
+            .then(|| srs.forward(sender_address, now()))
+            .flatten()
+        {
+            mail_from
+        } else {
+            self.directory
+                .emails_by_name(account_name)
+                .await
+                .unwrap_or_default()
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| rcpt.to_string())
+        };
+
+        let mut forwarded_message = Vec::with_capacity(raw_message.len() + rcpt.len() + 16);
+        forwarded_message.extend_from_slice(b"Delivered-To: ");
+        forwarded_message.extend_from_slice(rcpt.as_bytes());
+        forwarded_message.extend_from_slice(b"\r\n");
+        forwarded_message.extend_from_slice(raw_message);
+
+        let mut session = Session::<NullIo>::sieve(
+            core.clone(),
+            SessionAddress::new(mail_from),
+            forward_to
+                .iter()
+                .map(|address| SessionAddress::new(address.to_string()))
+                .collect(),
+            forwarded_message,
+        );
+        let result = session.queue_message().await;
+
+        tracing::debug!(
+            context = "forward",
+            event = "queue",
+            account = account_name,
+            forward_to = ?forward_to,
+            smtp_response = std::str::from_utf8(&result).unwrap_or_default()
+        );
+
+        result.first() == Some(&b'2')
+    }
+}
@@ -0,0 +1,929 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use directory::{
+    secret::hash_secret,
+    totp::{base32_encode, generate_totp_secret, totp_uri, verify_totp},
+};
+use hyper::{Method, StatusCode};
+use jmap_proto::error::request::RequestError;
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::de::DeserializeOwned;
+use store::write::now;
+use utils::listener::ServerInstance;
+
+use crate::{
+    api::{
+        http::{fetch_body, ToHttpResponse},
+        HttpRequest, HttpResponse, JsonResponse,
+    },
+    auth::{rate_limit::RemoteAddress, sessions::SessionEntry, AccessToken},
+    submission::attachment_link::AttachmentLinkSettings,
+    JMAP,
+};
+
+use super::{app_password_hint, sender_list::SenderLists, AccountSettings, AppPassword};
+
+const APP_PASSWORD_LEN: usize = 24;
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsResponse {
+    pub name: String,
+    pub description: Option<String>,
+    pub emails: Vec<String>,
+    pub quota: u32,
+    pub two_factor: bool,
+    pub app_passwords: Vec<AppPasswordResponse>,
+    pub forwarding: Forwarding,
+    pub sharing: Sharing,
+    pub spam_training: SpamTraining,
+    pub digest: Digest,
+    pub attachment_links: AttachmentLinks,
+    pub deduplication: Deduplication,
+    pub can_change_password: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppPasswordResponse {
+    pub name: String,
+    pub created: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Forwarding {
+    pub addresses: Vec<String>,
+    #[serde(default)]
+    pub keep_copy: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Sharing {
+    pub require_acceptance: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SpamTraining {
+    pub enabled: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Digest {
+    pub enabled: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AttachmentLinks {
+    pub enabled: bool,
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    #[serde(default, skip_deserializing)]
+    pub has_password: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Deduplication {
+    pub enabled: bool,
+    #[serde(default)]
+    pub window: Option<u64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpResponse {
+    pub secret: String,
+    pub uri: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionResponse {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub typ: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsentResponse {
+    pub client_id: String,
+    pub scopes: Vec<String>,
+    pub granted: u64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PasswordChange {
+    current_password: String,
+    new_password: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CurrentPassword {
+    current_password: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct AppPasswordCreate {
+    name: String,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct TotpConfirm {
+    code: String,
+}
+
+impl JMAP {
+    pub async fn handle_settings_request(
+        &self,
+        req: &mut HttpRequest,
+        path: &[&str],
+        access_token: Arc<AccessToken>,
+        remote_addr: &RemoteAddress,
+        instance: &Arc<ServerInstance>,
+    ) -> HttpResponse {
+        let result = match (path, req.method().clone()) {
+            ([], Method::GET) => self.settings_get(&access_token).await,
+            (["password"], Method::POST) => {
+                match parse_body::<PasswordChange>(req, &access_token).await {
+                    Ok(request) => {
+                        self.settings_change_password(&access_token, request, remote_addr)
+                            .await
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            (["app-passwords"], Method::POST) => {
+                match parse_body::<AppPasswordCreate>(req, &access_token).await {
+                    Ok(request) => {
+                        self.settings_create_app_password(&access_token, request.name)
+                            .await
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            (["app-passwords", name], Method::DELETE) => {
+                self.settings_delete_app_password(&access_token, name).await
+            }
+            (["totp"], Method::POST) => {
+                match parse_body::<CurrentPassword>(req, &access_token).await {
+                    Ok(request) => {
+                        self.settings_totp_enroll(&access_token, request, remote_addr)
+                            .await
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            (["totp", "confirm"], Method::POST) => {
+                match parse_body::<TotpConfirm>(req, &access_token).await {
+                    Ok(request) => {
+                        self.settings_totp_confirm(&access_token, request.code)
+                            .await
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            (["totp", "disable"], Method::POST) => {
+                match parse_body::<CurrentPassword>(req, &access_token).await {
+                    Ok(request) => {
+                        self.settings_totp_disable(&access_token, request, remote_addr)
+                            .await
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            (["forwarding"], Method::PUT) => {
+                match parse_body::<Forwarding>(req, &access_token).await {
+                    Ok(request) => self.settings_set_forwarding(&access_token, request).await,
+                    Err(err) => Err(err),
+                }
+            }
+            (["sharing"], Method::PUT) => match parse_body::<Sharing>(req, &access_token).await {
+                Ok(request) => self.settings_set_sharing(&access_token, request).await,
+                Err(err) => Err(err),
+            },
+            (["spam-training"], Method::PUT) => {
+                match parse_body::<SpamTraining>(req, &access_token).await {
+                    Ok(request) => {
+                        self.settings_set_spam_training(&access_token, request)
+                            .await
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            (["digest"], Method::PUT) => match parse_body::<Digest>(req, &access_token).await {
+                Ok(request) => self.settings_set_digest(&access_token, request).await,
+                Err(err) => Err(err),
+            },
+            (["attachment-links"], Method::PUT) => {
+                match parse_body::<AttachmentLinks>(req, &access_token).await {
+                    Ok(request) => {
+                        self.settings_set_attachment_links(&access_token, request)
+                            .await
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            (["deduplication"], Method::PUT) => {
+                match parse_body::<Deduplication>(req, &access_token).await {
+                    Ok(request) => {
+                        self.settings_set_deduplication(&access_token, request)
+                            .await
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            (["sender-lists"], Method::GET) => self.settings_sender_lists(&access_token).await,
+            (["sender-lists"], Method::PUT) => {
+                match parse_body::<SenderLists>(req, &access_token).await {
+                    Ok(request) => self.settings_set_sender_lists(&access_token, request).await,
+                    Err(err) => Err(err),
+                }
+            }
+            (["sessions"], Method::GET) => self.settings_sessions(&access_token).await,
+            (["sessions"], Method::DELETE) => {
+                self.settings_revoke_all_sessions(&access_token).await
+            }
+            (["sessions", id], Method::DELETE) => {
+                self.settings_revoke_session(&access_token, id).await
+            }
+            (["preview"], Method::POST) => {
+                match fetch_body(req, self.config.mail_max_size, &access_token).await {
+                    Some(raw_message) => {
+                        self.submission_preview(&access_token, instance, raw_message)
+                            .await
+                    }
+                    None => Err(RequestError::blank(
+                        StatusCode::PAYLOAD_TOO_LARGE.as_u16(),
+                        "Request too large",
+                        "The message is too large.",
+                    )),
+                }
+            }
+            (["consents"], Method::GET) => self.settings_consents(&access_token).await,
+            (["consents", client_id], Method::DELETE) => {
+                self.settings_revoke_consent(&access_token, client_id).await
+            }
+            _ => Err(RequestError::not_found()),
+        };
+
+        match result {
+            Ok(response) => response,
+            Err(err) => err.into_http_response(),
+        }
+    }
+
+    async fn settings_get(&self, access_token: &AccessToken) -> Result<HttpResponse, RequestError> {
+        let settings = self
+            .get_account_settings(access_token.primary_id())
+            .await
+            .map_err(|_| RequestError::internal_server_error())?;
+
+        Ok(JsonResponse::new(SettingsResponse {
+            name: access_token.name.clone(),
+            description: access_token.description.clone(),
+            emails: self
+                .directory
+                .emails_by_name(&access_token.name)
+                .await
+                .unwrap_or_default(),
+            quota: access_token.quota,
+            two_factor: settings.has_totp(),
+            app_passwords: settings
+                .app_passwords
+                .into_iter()
+                .map(|app_password| AppPasswordResponse {
+                    name: app_password.name,
+                    created: app_password.created,
+                    password: None,
+                })
+                .collect(),
+            forwarding: Forwarding {
+                addresses: settings.forward_to,
+                keep_copy: settings.forward_keep_copy,
+            },
+            sharing: Sharing {
+                require_acceptance: self
+                    .share_requires_acceptance(access_token.primary_id())
+                    .await
+                    .map_err(|_| RequestError::internal_server_error())?,
+            },
+            spam_training: SpamTraining {
+                enabled: !self
+                    .spam_train_opted_out(access_token.primary_id())
+                    .await
+                    .map_err(|_| RequestError::internal_server_error())?,
+            },
+            digest: Digest {
+                enabled: self
+                    .digest_enabled(access_token.primary_id())
+                    .await
+                    .map_err(|_| RequestError::internal_server_error())?,
+            },
+            attachment_links: self
+                .attachment_link_settings(access_token.primary_id())
+                .await
+                .map_err(|_| RequestError::internal_server_error())?
+                .map_or(
+                    AttachmentLinks {
+                        enabled: false,
+                        password: None,
+                        has_password: false,
+                    },
+                    |settings| AttachmentLinks {
+                        enabled: true,
+                        password: None,
+                        has_password: settings.password.is_some(),
+                    },
+                ),
+            deduplication: self
+                .dedup_window(access_token.primary_id())
+                .await
+                .map_err(|_| RequestError::internal_server_error())?
+                .map_or(
+                    Deduplication {
+                        enabled: false,
+                        window: None,
+                    },
+                    |window| Deduplication {
+                        enabled: true,
+                        window: window.into(),
+                    },
+                ),
+            can_change_password: self.config.settings_password_query.is_some(),
+        })
+        .into_http_response())
+    }
+
+    async fn settings_change_password(
+        &self,
+        access_token: &AccessToken,
+        request: PasswordChange,
+        remote_addr: &RemoteAddress,
+    ) -> Result<HttpResponse, RequestError> {
+        let query = self
+            .config
+            .settings_password_query
+            .as_ref()
+            .ok_or_else(|| {
+                RequestError::blank(
+                    StatusCode::FORBIDDEN.as_u16(),
+                    "Password change not supported",
+                    "Passwords cannot be changed for this directory.",
+                )
+            })?;
+        self.verify_current_password(access_token, &request.current_password, remote_addr)
+            .await?;
+        if request.new_password.chars().count() < self.config.settings_password_min_length {
+            return Err(invalid_parameter(format!(
+                "Password must be at least {} characters long.",
+                self.config.settings_password_min_length
+            )));
+        } else if request.new_password == request.current_password {
+            return Err(invalid_parameter(
+                "New password must be different from the current one.",
+            ));
+        }
+
+        let secret = hash_secret(&request.new_password)
+            .await
+            .ok_or_else(RequestError::internal_server_error)?;
+        self.directory
+            .lookup(query, &[secret.into(), access_token.name.as_str().into()])
+            .await
+            .map_err(|err| {
+                tracing::warn!(
+                    context = "settings",
+                    event = "error",
+                    account = access_token.name,
+                    reason = ?err,
+                    "Failed to update password."
+                );
+                RequestError::internal_server_error()
+            })?;
+
+        // Invalidate sessions authenticated with the old password
+        self.sessions
+            .retain(|_, session| *session.item() != access_token.primary_id());
+
+        Ok(success())
+    }
+
+    async fn settings_create_app_password(
+        &self,
+        access_token: &AccessToken,
+        name: String,
+    ) -> Result<HttpResponse, RequestError> {
+        let name = name.trim().to_string();
+        if name.is_empty() || name.len() > 255 {
+            return Err(invalid_parameter("Invalid app password name."));
+        }
+
+        let mut settings = self.account_settings(access_token).await?;
+        if settings
+            .app_passwords
+            .iter()
+            .any(|app_password| app_password.name == name)
+        {
+            return Err(invalid_parameter(format!(
+                "An app password named {name:?} already exists."
+            )));
+        }
+
+        let password = thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(APP_PASSWORD_LEN)
+            .map(char::from)
+            .collect::<String>();
+        let created = now();
+        settings.app_passwords.push(AppPassword {
+            name: name.clone(),
+            secret: hash_secret(&password)
+                .await
+                .ok_or_else(RequestError::internal_server_error)?,
+            hint: app_password_hint(&password),
+            created,
+        });
+        self.update_settings(access_token, settings).await?;
+
+        Ok(JsonResponse::new(AppPasswordResponse {
+            name,
+            created,
+            password: password.into(),
+        })
+        .into_http_response())
+    }
+
+    async fn settings_delete_app_password(
+        &self,
+        access_token: &AccessToken,
+        name: &str,
+    ) -> Result<HttpResponse, RequestError> {
+        let name = form_urlencoded::parse(format!("n={name}").as_bytes())
+            .next()
+            .map(|(_, name)| name.into_owned())
+            .unwrap_or_default();
+        let mut settings = self.account_settings(access_token).await?;
+        let num_passwords = settings.app_passwords.len();
+        settings
+            .app_passwords
+            .retain(|app_password| app_password.name != name);
+        if settings.app_passwords.len() == num_passwords {
+            return Err(RequestError::not_found());
+        }
+        self.update_settings(access_token, settings).await?;
+
+        Ok(success())
+    }
+
+    async fn settings_totp_enroll(
+        &self,
+        access_token: &AccessToken,
+        request: CurrentPassword,
+        remote_addr: &RemoteAddress,
+    ) -> Result<HttpResponse, RequestError> {
+        self.verify_current_password(access_token, &request.current_password, remote_addr)
+            .await?;
+        let mut settings = self.account_settings(access_token).await?;
+        if settings.has_totp() {
+            return Err(invalid_parameter(
+                "Two-factor authentication is already enabled.",
+            ));
+        }
+
+        let secret = generate_totp_secret();
+        let response = TotpResponse {
+            secret: base32_encode(&secret),
+            uri: totp_uri(
+                &self.config.settings_totp_issuer,
+                &access_token.name,
+                &secret,
+            ),
+        };
+        settings.totp_pending = secret.into();
+        self.update_settings(access_token, settings).await?;
+
+        Ok(JsonResponse::new(response).into_http_response())
+    }
+
+    async fn settings_totp_confirm(
+        &self,
+        access_token: &AccessToken,
+        code: String,
+    ) -> Result<HttpResponse, RequestError> {
+        let mut settings = self.account_settings(access_token).await?;
+        match settings.totp_pending.take() {
+            Some(secret) if verify_totp(&secret, &code, now()) => {
+                settings.totp_secret = secret.into();
+                self.update_settings(access_token, settings).await?;
+                Ok(success())
+            }
+            Some(_) => Err(invalid_parameter("Invalid one-time code.")),
+            None => Err(invalid_parameter(
+                "Two-factor authentication enrollment was not started.",
+            )),
+        }
+    }
+
+    async fn settings_totp_disable(
+        &self,
+        access_token: &AccessToken,
+        request: CurrentPassword,
+        remote_addr: &RemoteAddress,
+    ) -> Result<HttpResponse, RequestError> {
+        self.verify_current_password(access_token, &request.current_password, remote_addr)
+            .await?;
+        let mut settings = self.account_settings(access_token).await?;
+        settings.totp_secret = None;
+        settings.totp_pending = None;
+        self.update_settings(access_token, settings).await?;
+
+        Ok(success())
+    }
+
+    async fn settings_set_forwarding(
+        &self,
+        access_token: &AccessToken,
+        request: Forwarding,
+    ) -> Result<HttpResponse, RequestError> {
+        if request.addresses.len() > self.config.settings_forward_max {
+            return Err(invalid_parameter(format!(
+                "Messages can be forwarded to at most {} addresses.",
+                self.config.settings_forward_max
+            )));
+        }
+        let own_addresses = self
+            .directory
+            .emails_by_name(&access_token.name)
+            .await
+            .unwrap_or_default();
+        let mut addresses = Vec::with_capacity(request.addresses.len());
+        for address in request.addresses {
+            let address = address.trim().to_lowercase();
+            if !address.split_once('@').map_or(false, |(local, domain)| {
+                !local.is_empty() && domain.contains('.')
+            }) {
+                return Err(invalid_parameter(format!(
+                    "Invalid e-mail address {address:?}."
+                )));
+            } else if own_addresses
+                .iter()
+                .any(|own| own.eq_ignore_ascii_case(&address))
+            {
+                return Err(invalid_parameter(format!(
+                    "Messages cannot be forwarded to {address:?} as it belongs to this account."
+                )));
+            } else if !addresses.contains(&address) {
+                addresses.push(address);
+            }
+        }
+
+        let mut settings = self.account_settings(access_token).await?;
+        settings.forward_to = addresses;
+        settings.forward_keep_copy = request.keep_copy;
+        self.update_settings(access_token, settings).await?;
+
+        Ok(success())
+    }
+
+    async fn settings_set_sharing(
+        &self,
+        access_token: &AccessToken,
+        request: Sharing,
+    ) -> Result<HttpResponse, RequestError> {
+        self.set_share_requires_acceptance(access_token.primary_id(), request.require_acceptance)
+            .await
+            .map_err(|_| RequestError::internal_server_error())?;
+
+        Ok(success())
+    }
+
+    async fn settings_set_spam_training(
+        &self,
+        access_token: &AccessToken,
+        request: SpamTraining,
+    ) -> Result<HttpResponse, RequestError> {
+        self.set_spam_train_opt_out(access_token.primary_id(), !request.enabled)
+            .await
+            .map_err(|_| RequestError::internal_server_error())?;
+
+        Ok(success())
+    }
+
+    async fn settings_set_digest(
+        &self,
+        access_token: &AccessToken,
+        request: Digest,
+    ) -> Result<HttpResponse, RequestError> {
+        self.set_digest_enabled(access_token.primary_id(), request.enabled)
+            .await
+            .map_err(|_| RequestError::internal_server_error())?;
+
+        Ok(success())
+    }
+
+    async fn settings_set_deduplication(
+        &self,
+        access_token: &AccessToken,
+        request: Deduplication,
+    ) -> Result<HttpResponse, RequestError> {
+        let window = match (request.enabled, request.window) {
+            (true, Some(window)) if window == 0 || window > self.config.dedup_max_window => {
+                return Err(invalid_parameter(format!(
+                    "The deduplication window must be between 1 and {} seconds.",
+                    self.config.dedup_max_window
+                )));
+            }
+            (true, window) => window.unwrap_or(self.config.dedup_window).into(),
+            (false, _) => None,
+        };
+        self.set_dedup_window(access_token.primary_id(), window)
+            .await
+            .map_err(|_| RequestError::internal_server_error())?;
+
+        Ok(success())
+    }
+
+    async fn settings_set_attachment_links(
+        &self,
+        access_token: &AccessToken,
+        request: AttachmentLinks,
+    ) -> Result<HttpResponse, RequestError> {
+        let settings = if request.enabled {
+            AttachmentLinkSettings {
+                password: match request.password.filter(|password| !password.is_empty()) {
+                    Some(password) => hash_secret(&password)
+                        .await
+                        .ok_or_else(RequestError::internal_server_error)?
+                        .into(),
+                    None => None,
+                },
+            }
+            .into()
+        } else {
+            None
+        };
+        self.set_attachment_link_settings(access_token.primary_id(), settings)
+            .await
+            .map_err(|_| RequestError::internal_server_error())?;
+
+        Ok(success())
+    }
+
+    async fn settings_sender_lists(
+        &self,
+        access_token: &AccessToken,
+    ) -> Result<HttpResponse, RequestError> {
+        self.get_sender_lists(access_token.primary_id())
+            .await
+            .map(|lists| JsonResponse::new(lists).into_http_response())
+            .map_err(|_| RequestError::internal_server_error())
+    }
+
+    async fn settings_set_sender_lists(
+        &self,
+        access_token: &AccessToken,
+        request: SenderLists,
+    ) -> Result<HttpResponse, RequestError> {
+        let lists = request.normalize().map_err(invalid_parameter)?;
+        self.set_sender_lists(access_token.primary_id(), lists)
+            .await
+            .map_err(|_| RequestError::internal_server_error())?;
+
+        Ok(success())
+    }
+
+    async fn settings_sessions(
+        &self,
+        access_token: &AccessToken,
+    ) -> Result<HttpResponse, RequestError> {
+        let now = now();
+        let sessions = self
+            .list_sessions(access_token.primary_id())
+            .await
+            .map_err(|_| RequestError::internal_server_error())?;
+
+        Ok(JsonResponse::new(
+            sessions
+                .into_iter()
+                .map(|session| {
+                    let id = format!("{:016x}", session.id());
+                    match session {
+                        SessionEntry::OAuth {
+                            client_id,
+                            is_refresh,
+                            issued,
+                            expires,
+                            ..
+                        } => SessionResponse {
+                            id,
+                            typ: "oauth",
+                            client_id: client_id.into(),
+                            token_type: Some(if is_refresh { "refresh" } else { "access" }),
+                            remote_ip: None,
+                            created: issued.into(),
+                            expires_in: expires.saturating_sub(now).into(),
+                        },
+                        SessionEntry::Live {
+                            protocol,
+                            remote_ip,
+                            created,
+                            ..
+                        } => SessionResponse {
+                            id,
+                            typ: protocol.as_str(),
+                            client_id: None,
+                            token_type: None,
+                            remote_ip: remote_ip.map(|ip| ip.to_string()),
+                            created: created.into(),
+                            expires_in: None,
+                        },
+                        SessionEntry::Cached { expires_in, .. } => SessionResponse {
+                            id,
+                            typ: "http",
+                            client_id: None,
+                            token_type: None,
+                            remote_ip: None,
+                            created: None,
+                            expires_in: expires_in.as_secs().into(),
+                        },
+                    }
+                })
+                .collect::<Vec<_>>(),
+        )
+        .into_http_response())
+    }
+
+    async fn settings_revoke_session(
+        &self,
+        access_token: &AccessToken,
+        id: &str,
+    ) -> Result<HttpResponse, RequestError> {
+        let id = u64::from_str_radix(id, 16).map_err(|_| RequestError::not_found())?;
+        match self.revoke_session(access_token.primary_id(), id).await {
+            Ok(true) => Ok(success()),
+            Ok(false) => Err(RequestError::not_found()),
+            Err(_) => Err(RequestError::internal_server_error()),
+        }
+    }
+
+    async fn settings_revoke_all_sessions(
+        &self,
+        access_token: &AccessToken,
+    ) -> Result<HttpResponse, RequestError> {
+        self.revoke_all_sessions(access_token.primary_id())
+            .await
+            .map_err(|_| RequestError::internal_server_error())?;
+        Ok(success())
+    }
+
+    async fn settings_consents(
+        &self,
+        access_token: &AccessToken,
+    ) -> Result<HttpResponse, RequestError> {
+        let consents = self
+            .get_oauth_consents(access_token.primary_id())
+            .await
+            .map_err(|_| RequestError::internal_server_error())?;
+
+        Ok(JsonResponse::new(
+            consents
+                .map(|consents| consents.inner.inner.clients)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|consent| ConsentResponse {
+                    client_id: consent.client_id,
+                    scopes: consent.scopes,
+                    granted: consent.granted,
+                })
+                .collect::<Vec<_>>(),
+        )
+        .into_http_response())
+    }
+
+    async fn settings_revoke_consent(
+        &self,
+        access_token: &AccessToken,
+        client_id: &str,
+    ) -> Result<HttpResponse, RequestError> {
+        let client_id = form_urlencoded::parse(format!("n={client_id}").as_bytes())
+            .next()
+            .map(|(_, client_id)| client_id.into_owned())
+            .unwrap_or_default();
+        match self
+            .revoke_oauth_consent(access_token.primary_id(), &client_id)
+            .await
+        {
+            Ok(true) => Ok(success()),
+            Ok(false) => Err(RequestError::not_found()),
+            Err(_) => Err(RequestError::internal_server_error()),
+        }
+    }
+
+    async fn verify_current_password(
+        &self,
+        access_token: &AccessToken,
+        secret: &str,
+        remote_addr: &RemoteAddress,
+    ) -> Result<(), RequestError> {
+        self.is_auth_allowed_soft(remote_addr)?;
+        match self
+            .directory_authenticate(&access_token.name, secret)
+            .await
+        {
+            Ok(Some(_)) => Ok(()),
+            Ok(None) => {
+                self.is_auth_allowed_hard(remote_addr)?;
+                Err(invalid_parameter("The current password is incorrect."))
+            }
+            Err(_) => Err(RequestError::internal_server_error()),
+        }
+    }
+
+    async fn account_settings(
+        &self,
+        access_token: &AccessToken,
+    ) -> Result<AccountSettings, RequestError> {
+        self.get_account_settings(access_token.primary_id())
+            .await
+            .map_err(|_| RequestError::internal_server_error())
+    }
+
+    async fn update_settings(
+        &self,
+        access_token: &AccessToken,
+        settings: AccountSettings,
+    ) -> Result<(), RequestError> {
+        self.set_account_settings(access_token.primary_id(), settings)
+            .await
+            .map_err(|_| RequestError::internal_server_error())
+    }
+}
+
+async fn parse_body<T: DeserializeOwned>(
+    req: &mut HttpRequest,
+    access_token: &AccessToken,
+) -> Result<T, RequestError> {
+    let bytes = fetch_body(req, 8192, access_token).await.ok_or_else(|| {
+        RequestError::blank(
+            StatusCode::PAYLOAD_TOO_LARGE.as_u16(),
+            "Request too large",
+            "The request body is too large.",
+        )
+    })?;
+    serde_json::from_slice(&bytes).map_err(|err| invalid_parameter(err.to_string()))
+}
+
+fn invalid_parameter(detail: impl Into<String>) -> RequestError {
+    RequestError::blank(
+        StatusCode::BAD_REQUEST.as_u16(),
+        "Invalid parameter",
+        detail.into(),
+    )
+}
+
+fn success() -> HttpResponse {
+    JsonResponse::new(serde_json::Value::String("success".into())).into_http_response()
+}
@@ -0,0 +1,136 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{error::method::MethodError, types::collection::Collection};
+use sha2::{Digest, Sha256};
+use store::{
+    write::{BatchBuilder, Operation, ValueClass},
+    CustomValueKey, Serialize,
+};
+
+use crate::{auth::authenticate::AccountKey, Bincode, JMAP};
+
+pub mod authenticate;
+pub mod forward;
+pub mod manage;
+pub mod policy;
+pub mod sender_list;
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AccountSettings {
+    pub app_passwords: Vec<AppPassword>,
+    pub totp_secret: Option<Vec<u8>>,
+    pub totp_pending: Option<Vec<u8>>,
+    pub forward_to: Vec<String>,
+    pub forward_keep_copy: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AppPassword {
+    pub name: String,
+    pub secret: String,
+    pub hint: u64,
+    pub created: u64,
+}
+
+impl AccountSettings {
+    pub fn has_totp(&self) -> bool {
+        self.totp_secret.is_some()
+    }
+
+    // Only the app password with a matching hint is checked, so a login
+    // runs the password hash at most once.
+    pub async fn verify_app_password(&self, secret: &str) -> bool {
+        let hint = app_password_hint(secret);
+        for app_password in &self.app_passwords {
+            if app_password.hint == hint
+                && directory::secret::verify_secret(&app_password.secret, secret).await
+            {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+// App passwords are random, so a truncated digest identifies the candidate
+// without revealing anything useful about the password.
+pub fn app_password_hint(secret: &str) -> u64 {
+    let digest = Sha256::digest(secret.as_bytes());
+    u64::from_be_bytes(digest[..8].try_into().unwrap())
+}
+
+impl JMAP {
+    pub async fn get_account_settings(
+        &self,
+        account_id: u32,
+    ) -> Result<AccountSettings, MethodError> {
+        self.store
+            .get_value::<Bincode<AccountSettings>>(CustomValueKey {
+                value: AccountKey::id_to_settings(account_id),
+            })
+            .await
+            .map(|settings| settings.map(|settings| settings.inner).unwrap_or_default())
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve account settings");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn set_account_settings(
+        &self,
+        account_id: u32,
+        settings: AccountSettings,
+    ) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::id_to_settings(account_id),
+                },
+                set: Bincode::new(settings).serialize().into(),
+            });
+
+        self.store.write(batch.build()).await.map_err(|err| {
+            tracing::error!(event = "error",
+                context = "store",
+                account_id = account_id,
+                error = ?err,
+                "Failed to write account settings");
+            MethodError::ServerPartialFail
+        })?;
+
+        // Cached sessions may have been authenticated with credentials
+        // that are no longer valid
+        self.sessions
+            .retain(|_, session| *session.item() != account_id);
+
+        Ok(())
+    }
+}
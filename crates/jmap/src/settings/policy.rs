@@ -0,0 +1,391 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{error::method::MethodError, types::collection::Collection};
+use mail_builder::{
+    headers::{
+        address::{Address, EmailAddress},
+        HeaderType,
+    },
+    MessageBuilder,
+};
+use mail_parser::MessageParser;
+use smtp::{
+    core::{NullIo, Session, SessionAddress},
+    inbound::footer::append_footer,
+};
+use store::{
+    write::{key::DeserializeBigEndian, now, BatchBuilder, Operation, ValueClass},
+    CustomValueKey, Deserialize, Serialize,
+};
+
+use crate::{auth::authenticate::AccountKey, mailing_list::list_headers, Bincode, JMAP};
+
+pub const MAX_FOOTER_LEN: usize = 4096;
+pub const VACATION_REPLY_INTERVAL: u64 = 7 * 86400;
+
+// Organization-wide policies set by the administrators of a domain, these are
+// enforced on delivery and submission regardless of the users' own settings.
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DomainPolicy {
+    #[serde(default)]
+    pub block_external_forwarding: bool,
+    #[serde(default)]
+    pub footer: Option<String>,
+    #[serde(default)]
+    pub vacation: Option<PolicyVacation>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PolicyVacation {
+    #[serde(default)]
+    pub subject: Option<String>,
+    pub text_body: String,
+    #[serde(default)]
+    pub from_date: Option<u64>,
+    #[serde(default)]
+    pub to_date: Option<u64>,
+}
+
+impl DomainPolicy {
+    pub fn normalize(self) -> Result<Self, String> {
+        let footer = self
+            .footer
+            .map(|footer| footer.trim().to_string())
+            .filter(|footer| !footer.is_empty());
+        if footer
+            .as_ref()
+            .map_or(false, |footer| footer.len() > MAX_FOOTER_LEN)
+        {
+            return Err(format!(
+                "Footer cannot be longer than {MAX_FOOTER_LEN} bytes."
+            ));
+        }
+
+        let vacation = if let Some(vacation) = self.vacation {
+            if vacation.text_body.trim().is_empty() {
+                return Err("Vacation response text cannot be empty.".to_string());
+            } else if matches!((vacation.from_date, vacation.to_date), (Some(from), Some(to)) if from >= to)
+            {
+                return Err("Vacation response must end after it starts.".to_string());
+            }
+            Some(PolicyVacation {
+                subject: vacation
+                    .subject
+                    .map(|subject| subject.replace(['\r', '\n'], " ").trim().to_string())
+                    .filter(|subject| !subject.is_empty()),
+                ..vacation
+            })
+        } else {
+            None
+        };
+
+        Ok(DomainPolicy {
+            block_external_forwarding: self.block_external_forwarding,
+            footer,
+            vacation,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.block_external_forwarding && self.footer.is_none() && self.vacation.is_none()
+    }
+}
+
+impl PolicyVacation {
+    pub fn is_active(&self, now: u64) -> bool {
+        self.from_date.map_or(true, |from| now >= from) && self.to_date.map_or(true, |to| now < to)
+    }
+}
+
+impl JMAP {
+    pub async fn get_domain_policy(&self, domain: &str) -> Result<DomainPolicy, MethodError> {
+        self.store
+            .get_value::<Bincode<DomainPolicy>>(CustomValueKey {
+                value: AccountKey::domain_policy(domain),
+            })
+            .await
+            .map(|policy| policy.map(|policy| policy.inner).unwrap_or_default())
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "store",
+                    domain = domain,
+                    error = ?err,
+                    "Failed to retrieve domain policy");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn set_domain_policy(
+        &self,
+        domain: &str,
+        policy: DomainPolicy,
+    ) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::domain_policy(domain),
+                },
+                set: if !policy.is_empty() {
+                    Bincode::new(policy).serialize().into()
+                } else {
+                    None
+                },
+            });
+        self.write_batch(batch).await
+    }
+
+    // Addresses belong to the organization when they are either in the same domain
+    // or in a domain of the same tenant.
+    pub fn is_same_organization(&self, domain: &str, address: &str) -> bool {
+        match address_domain(address) {
+            Some(address_domain) if address_domain == domain => true,
+            Some(address_domain) => {
+                match (
+                    self.config.tenants.by_domain(domain),
+                    self.config.tenants.by_domain(&address_domain),
+                ) {
+                    (Some(tenant), Some(address_tenant)) => tenant.id == address_tenant.id,
+                    _ => false,
+                }
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_forward_allowed(&self, domain: &str, policy: &DomainPolicy, address: &str) -> bool {
+        if !policy.block_external_forwarding || self.is_same_organization(domain, address) {
+            true
+        } else {
+            tracing::info!(
+                context = "domain_policy",
+                event = "forward_blocked",
+                domain = domain,
+                rcpt = address,
+                "Domain policy does not allow forwarding outside the organization."
+            );
+            false
+        }
+    }
+
+    // Returns the message with the compliance footer required by the sender's
+    // domain appended, or None when the domain does not require one. Footers are
+    // appended by the SMTP server to all submissions, including JMAP ones.
+    pub async fn policy_footer(
+        &self,
+        sender: &str,
+        raw_message: &[u8],
+    ) -> Result<Option<Vec<u8>>, MethodError> {
+        let policy = if let Some(domain) = address_domain(sender) {
+            self.get_domain_policy(&domain).await?
+        } else {
+            return Ok(None);
+        };
+
+        Ok(policy
+            .footer
+            .as_deref()
+            .and_then(|footer| append_footer(raw_message, footer, None)))
+    }
+
+    // Sends the organization-wide out of office response, at most once per sender
+    // every seven days. Automatic messages and mailing lists are never replied to.
+    pub async fn policy_vacation_reply(
+        &self,
+        vacation: &PolicyVacation,
+        account_id: u32,
+        raw_message: &[u8],
+        sender_address: &str,
+        rcpt: &str,
+    ) {
+        let now = now();
+        let sender = sender_address.to_lowercase();
+        let local_part = sender.split_once('@').map_or("", |(local, _)| local);
+        if !vacation.is_active(now)
+            || sender.is_empty()
+            || sender.eq_ignore_ascii_case(rcpt)
+            || local_part == "mailer-daemon"
+            || local_part.starts_with("owner-")
+            || local_part.ends_with("-request")
+            || list_headers(raw_message).is_some()
+        {
+            return;
+        }
+        let message = if let Some(message) = MessageParser::new().parse(raw_message) {
+            message
+        } else {
+            return;
+        };
+        for header in message.parts.first().map_or(&[][..], |part| &part.headers) {
+            let name = header.name.as_str();
+            let value = header.value.as_text().unwrap_or_default().trim();
+            if (name.eq_ignore_ascii_case("Auto-Submitted") && !value.eq_ignore_ascii_case("no"))
+                || (name.eq_ignore_ascii_case("Precedence")
+                    && ["bulk", "list", "junk"]
+                        .iter()
+                        .any(|p| value.eq_ignore_ascii_case(p)))
+                || name.eq_ignore_ascii_case("List-Id")
+            {
+                return;
+            }
+        }
+
+        // Reply to each sender only once per interval
+        let key = AccountKey::policy_vacation_reply(account_id, &sender);
+        match self
+            .store
+            .get_value::<u64>(CustomValueKey { value: key.clone() })
+            .await
+        {
+            Ok(Some(last_reply)) if last_reply + VACATION_REPLY_INTERVAL > now => return,
+            Ok(_) => (),
+            Err(err) => {
+                tracing::error!(
+                    event = "error",
+                    context = "domain_policy",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve vacation response history.");
+                return;
+            }
+        }
+
+        let subject = vacation.subject.clone().unwrap_or_else(|| {
+            format!("Auto: {}", message.subject().unwrap_or_default())
+                .trim()
+                .to_string()
+        });
+        let mut builder = MessageBuilder::new()
+            .from(Address::Address(EmailAddress {
+                name: None,
+                email: rcpt.into(),
+            }))
+            .to(Address::Address(EmailAddress {
+                name: None,
+                email: sender_address.into(),
+            }))
+            .subject(subject)
+            .header("Auto-Submitted", HeaderType::Text("auto-replied".into()));
+        if let Some(message_id) = message.message_id() {
+            builder = builder.in_reply_to(message_id).references(message_id);
+        }
+        let reply = builder
+            .text_body(vacation.text_body.as_str())
+            .write_to_vec()
+            .unwrap_or_default();
+
+        // Auto-replies are sent with a null return path to avoid loops
+        let result = Session::<NullIo>::sieve(
+            self.smtp.core(),
+            SessionAddress::new(String::new()),
+            vec![SessionAddress::new(sender_address.to_string())],
+            reply,
+        )
+        .queue_message()
+        .await;
+
+        tracing::debug!(
+            context = "domain_policy",
+            event = "vacation_reply",
+            account_id = account_id,
+            rcpt = sender_address,
+            smtp_response = std::str::from_utf8(&result).unwrap_or_default()
+        );
+
+        if result.first() == Some(&b'2') {
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal)
+                .op(Operation::Value {
+                    class: ValueClass::Custom { bytes: key },
+                    set: now.serialize().into(),
+                });
+            self.write_batch(batch).await.ok();
+        }
+    }
+
+    // Removes vacation reply records older than the reply interval, after which
+    // the sender would be replied to again anyway
+    pub async fn purge_policy_vacation_replies(&self) -> store::Result<()> {
+        let expires = now().saturating_sub(VACATION_REPLY_INTERVAL);
+        let expired = self
+            .store
+            .iterate(
+                Vec::new(),
+                CustomValueKey {
+                    value: AccountKey::policy_vacation_reply(0, ""),
+                },
+                CustomValueKey {
+                    value: AccountKey::policy_vacation_reply(u32::MAX, ""),
+                },
+                false,
+                true,
+                move |expired, key, value| {
+                    // Skip the u32::MAX account prefix and the key type
+                    let offset = std::mem::size_of::<u32>() + 1;
+                    if let Some(address) = key
+                        .get(offset + std::mem::size_of::<u32>()..)
+                        .and_then(|address| std::str::from_utf8(address).ok())
+                    {
+                        if u64::deserialize(value)? <= expires {
+                            expired.push(AccountKey::policy_vacation_reply(
+                                key.deserialize_be_u32(offset)?,
+                                address,
+                            ));
+                        }
+                    }
+                    Ok(true)
+                },
+            )
+            .await?;
+
+        for keys in expired.chunks(100) {
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal);
+            for key in keys {
+                batch.op(Operation::Value {
+                    class: ValueClass::Custom { bytes: key.clone() },
+                    set: None,
+                });
+            }
+            self.store.write(batch.build()).await?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn address_domain(address: &str) -> Option<String> {
+    address
+        .rsplit_once('@')
+        .map(|(_, domain)| domain.trim_end_matches('.').to_lowercase())
+        .filter(|domain| !domain.is_empty())
+}
@@ -0,0 +1,177 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{error::method::MethodError, types::collection::Collection};
+use store::{
+    write::{BatchBuilder, Operation, ValueClass},
+    CustomValueKey, Serialize,
+};
+
+use crate::{auth::authenticate::AccountKey, Bincode, JMAP};
+
+pub const MAX_SENDER_LIST_ENTRIES: usize = 1000;
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SenderLists {
+    #[serde(default)]
+    pub allow: Vec<String>,
+    #[serde(default)]
+    pub block: Vec<String>,
+    #[serde(default)]
+    pub block_action: BlockAction,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BlockAction {
+    #[default]
+    Trash,
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SenderVerdict {
+    Allow,
+    Block(BlockAction),
+    None,
+}
+
+impl SenderLists {
+    // Allowlisted senders take precedence, which makes it possible to block
+    // a whole domain while still accepting messages from some of its addresses.
+    // Sender addresses are easily forged, so allow entries only apply to senders
+    // whose domain is aligned with a domain that passed DMARC, DKIM or SPF.
+    pub fn verdict<'x>(
+        &self,
+        senders: impl IntoIterator<Item = &'x str>,
+        authenticated_domains: &[String],
+    ) -> SenderVerdict {
+        let mut verdict = SenderVerdict::None;
+        for sender in senders {
+            if self.allow.iter().any(|entry| sender_matches(entry, sender))
+                && authenticated_domains
+                    .iter()
+                    .any(|domain| sender_matches(domain, sender))
+            {
+                return SenderVerdict::Allow;
+            } else if self.block.iter().any(|entry| sender_matches(entry, sender)) {
+                verdict = SenderVerdict::Block(self.block_action);
+            }
+        }
+        verdict
+    }
+
+    pub fn normalize(self) -> Result<Self, String> {
+        if self.allow.len() + self.block.len() > MAX_SENDER_LIST_ENTRIES {
+            return Err(format!(
+                "Sender lists cannot have more than {MAX_SENDER_LIST_ENTRIES} entries."
+            ));
+        }
+
+        Ok(SenderLists {
+            allow: normalize_entries(self.allow)?,
+            block: normalize_entries(self.block)?,
+            block_action: self.block_action,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.block.is_empty()
+    }
+}
+
+fn normalize_entries(entries: Vec<String>) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(entries.len());
+    for entry in entries {
+        let entry = entry.trim().trim_start_matches('@').to_lowercase();
+        let domain = entry.rsplit_once('@').map_or(entry.as_str(), |(_, d)| d);
+        if domain.is_empty()
+            || !domain.contains('.')
+            || entry.starts_with('@')
+            || entry.contains(char::is_whitespace)
+        {
+            return Err(format!("Invalid address or domain {entry:?}."));
+        }
+        if !normalized.contains(&entry) {
+            normalized.push(entry);
+        }
+    }
+    Ok(normalized)
+}
+
+// Entries are either full addresses or domains, a domain also
+// matches all of its subdomains.
+pub(crate) fn sender_matches(entry: &str, sender: &str) -> bool {
+    if entry.contains('@') {
+        entry.eq_ignore_ascii_case(sender)
+    } else if let Some((_, domain)) = sender.rsplit_once('@') {
+        domain.eq_ignore_ascii_case(entry)
+            || (domain.len() > entry.len()
+                && domain.as_bytes()[domain.len() - entry.len() - 1] == b'.'
+                && domain[domain.len() - entry.len()..].eq_ignore_ascii_case(entry))
+    } else {
+        false
+    }
+}
+
+impl JMAP {
+    pub async fn get_sender_lists(&self, account_id: u32) -> Result<SenderLists, MethodError> {
+        self.store
+            .get_value::<Bincode<SenderLists>>(CustomValueKey {
+                value: AccountKey::sender_lists(account_id),
+            })
+            .await
+            .map(|lists| lists.map(|lists| lists.inner).unwrap_or_default())
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve sender lists");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn set_sender_lists(
+        &self,
+        account_id: u32,
+        lists: SenderLists,
+    ) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::sender_lists(account_id),
+                },
+                set: if !lists.is_empty() || lists.block_action != BlockAction::default() {
+                    Bincode::new(lists).serialize().into()
+                } else {
+                    None
+                },
+            });
+        self.write_batch(batch).await
+    }
+}
@@ -0,0 +1,465 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::{method::MethodError, set::SetError},
+    object::{index::ObjectIndexBuilder, Object},
+    types::{
+        acl::{Acl, AclGrant},
+        collection::Collection,
+        date::UTCDate,
+        property::Property,
+        state::StateChange,
+        type_state::DataType,
+        value::Value,
+    },
+};
+use mail_builder::{headers::HeaderType, MessageBuilder};
+use store::{
+    write::{
+        assert::HashedValue, log::ChangeLogBuilder, now, BatchBuilder, Operation, ValueClass,
+        F_CLEAR, F_VALUE,
+    },
+    CustomValueKey, Serialize,
+};
+use utils::{ipc::DeliveryResult, map::bitmap::Bitmap};
+
+use crate::{
+    auth::{authenticate::AccountKey, AccessToken},
+    mailbox::set::SCHEMA,
+    JMAP,
+};
+
+pub mod get;
+pub mod set;
+
+pub const STATE_PENDING: &str = "pending";
+pub const STATE_ACCEPTED: &str = "accepted";
+pub const STATE_DECLINED: &str = "declined";
+
+#[derive(Default)]
+pub struct ShareInvitationChanges {
+    mailbox_name: String,
+    created: Vec<(u32, Bitmap<Acl>)>,
+    change_ids: Vec<(u32, u64)>,
+}
+
+impl JMAP {
+    pub async fn share_requires_acceptance(&self, account_id: u32) -> Result<bool, MethodError> {
+        self.store
+            .get_value::<u64>(CustomValueKey {
+                value: AccountKey::share_acceptance(account_id),
+            })
+            .await
+            .map(|value| value.map_or(self.config.share_require_acceptance, |value| value != 0))
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve sharing preferences");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn set_share_requires_acceptance(
+        &self,
+        account_id: u32,
+        require_acceptance: bool,
+    ) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::share_acceptance(account_id),
+                },
+                set: (require_acceptance as u64).serialize().into(),
+            });
+        self.write_batch(batch).await
+    }
+
+    // Adds to the batch an invitation for each grantee whose access to the mailbox
+    // became pending acceptance with this ACL change, and removes the pending
+    // invitations of grantees whose access was revoked before they answered.
+    // The invitations are written together with the mailbox.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn share_invitations_prepare(
+        &self,
+        batch: &mut BatchBuilder,
+        account_id: u32,
+        mailbox_id: u32,
+        mailbox_name: &Value,
+        acl: Option<&Value>,
+        previous_acl: Option<&Value>,
+        access_token: &AccessToken,
+    ) -> Result<ShareInvitationChanges, MethodError> {
+        let pending = pending_grants(acl);
+        let previous = pending_grants(previous_acl);
+        let mut changes = ShareInvitationChanges {
+            mailbox_name: mailbox_name.as_string().unwrap_or_default().to_string(),
+            ..Default::default()
+        };
+
+        for (grantee_id, grant) in &pending {
+            if previous.iter().any(|(id, _)| id == grantee_id) {
+                continue;
+            }
+
+            let invitation = Object::with_capacity(7)
+                .with_property(Property::State, Value::Text(STATE_PENDING.to_string()))
+                .with_property(
+                    Property::CreatedAt,
+                    Value::Date(UTCDate::from_timestamp(now() as i64)),
+                )
+                .with_property(Property::CreatedBy, Value::Text(access_token.name.clone()))
+                .with_property(Property::ObjectAccountId, Value::Id(account_id.into()))
+                .with_property(Property::ObjectId, Value::Id(mailbox_id.into()))
+                .with_property(Property::Name, mailbox_name.clone())
+                .with_property(
+                    Property::MyRights,
+                    Value::List(
+                        grant
+                            .grant
+                            .map(|acl_item| Value::Text(acl_item.to_string()))
+                            .collect(),
+                    ),
+                );
+            let document_id = self
+                .assign_document_id(*grantee_id, Collection::ShareInvitation)
+                .await?;
+            let change_id = self.assign_change_id(*grantee_id).await?;
+            batch
+                .with_account_id(*grantee_id)
+                .with_collection(Collection::ShareInvitation)
+                .create_document(document_id)
+                .value(Property::Value, &invitation, F_VALUE)
+                .custom(
+                    ChangeLogBuilder::with_change_id(change_id)
+                        .with_log_insert(Collection::ShareInvitation, document_id),
+                );
+            changes.created.push((*grantee_id, grant.grant));
+            changes.change_ids.push((*grantee_id, change_id));
+        }
+
+        for (grantee_id, _) in &previous {
+            if pending.iter().any(|(id, _)| id == grantee_id) {
+                continue;
+            }
+
+            let document_ids = self
+                .share_invitations_pending(*grantee_id, account_id, mailbox_id)
+                .await?;
+            if document_ids.is_empty() {
+                continue;
+            }
+            let change_id = self.assign_change_id(*grantee_id).await?;
+            let mut log = ChangeLogBuilder::with_change_id(change_id);
+            batch
+                .with_account_id(*grantee_id)
+                .with_collection(Collection::ShareInvitation);
+            for document_id in document_ids {
+                batch
+                    .delete_document(document_id)
+                    .value(Property::Value, (), F_VALUE | F_CLEAR);
+                log.log_delete(Collection::ShareInvitation, document_id);
+            }
+            batch.custom(log);
+            changes.change_ids.push((*grantee_id, change_id));
+        }
+
+        Ok(changes)
+    }
+
+    // Notifies the grantees once the invitations have been written.
+    pub async fn share_invitations_notify(
+        &self,
+        changes: ShareInvitationChanges,
+        access_token: &AccessToken,
+    ) {
+        for (grantee_id, change_id) in changes.change_ids {
+            self.broadcast_state_change(
+                StateChange::new(grantee_id).with_change(DataType::ShareInvitation, change_id),
+            )
+            .await;
+        }
+
+        if self.config.share_invitation_email {
+            for (grantee_id, rights) in changes.created {
+                self.share_invitation_email(
+                    grantee_id,
+                    access_token,
+                    &changes.mailbox_name,
+                    rights,
+                )
+                .await;
+            }
+        }
+    }
+
+    async fn share_invitations_pending(
+        &self,
+        grantee_id: u32,
+        account_id: u32,
+        mailbox_id: u32,
+    ) -> Result<Vec<u32>, MethodError> {
+        let mut document_ids = Vec::new();
+        for document_id in self
+            .get_document_ids(grantee_id, Collection::ShareInvitation)
+            .await?
+            .unwrap_or_default()
+        {
+            if let Some(invitation) = self
+                .get_property::<Object<Value>>(
+                    grantee_id,
+                    Collection::ShareInvitation,
+                    document_id,
+                    Property::Value,
+                )
+                .await?
+            {
+                if matches!(invitation.get(&Property::State), Value::Text(state) if state == STATE_PENDING)
+                    && matches!(invitation.get(&Property::ObjectAccountId), Value::Id(id) if id.document_id() == account_id)
+                    && matches!(invitation.get(&Property::ObjectId), Value::Id(id) if id.document_id() == mailbox_id)
+                {
+                    document_ids.push(document_id);
+                }
+            }
+        }
+
+        Ok(document_ids)
+    }
+
+    // Activates the pending grant on the owner's mailbox when accepted, or
+    // removes it when declined.
+    pub(crate) async fn share_invitation_respond(
+        &self,
+        grantee_id: u32,
+        invitation: &Object<Value>,
+        accept: bool,
+    ) -> Result<Result<(), SetError>, MethodError> {
+        let (account_id, mailbox_id) = match (
+            invitation.get(&Property::ObjectAccountId),
+            invitation.get(&Property::ObjectId),
+        ) {
+            (Value::Id(account_id), Value::Id(mailbox_id)) => {
+                (account_id.document_id(), mailbox_id.document_id())
+            }
+            _ => {
+                return Ok(Err(
+                    SetError::not_found().with_description("Shared mailbox not found.")
+                ));
+            }
+        };
+        let grantee = Value::Id(grantee_id.into());
+
+        let mut try_count = 0;
+        loop {
+            let mailbox = if let Some(mailbox) = self
+                .get_property::<HashedValue<Object<Value>>>(
+                    account_id,
+                    Collection::Mailbox,
+                    mailbox_id,
+                    Property::Value,
+                )
+                .await?
+            {
+                mailbox
+            } else if accept {
+                return Ok(Err(
+                    SetError::not_found().with_description("The shared mailbox no longer exists.")
+                ));
+            } else {
+                return Ok(Ok(()));
+            };
+
+            let mut acl =
+                if let Some(Value::List(acl)) = mailbox.inner.properties.get(&Property::Acl) {
+                    acl.clone()
+                } else {
+                    Vec::new()
+                };
+            let idx = acl.iter().position(|item| item == &grantee);
+            let mut grant = match idx.and_then(|idx| acl.get(idx + 1)) {
+                Some(Value::UnsignedInt(bits)) => AclGrant::from(*bits),
+                _ => AclGrant::default(),
+            };
+            let idx = match idx {
+                Some(idx) if grant.pending => idx,
+                _ if accept && grant.grant.is_empty() => {
+                    return Ok(Err(SetError::forbidden()
+                        .with_description("This mailbox is no longer shared with you.")));
+                }
+                _ => {
+                    return Ok(Ok(()));
+                }
+            };
+            grant.pending = false;
+            if !accept {
+                grant.grant = Bitmap::new();
+            }
+            if !grant.is_empty() {
+                acl[idx + 1] = Value::UnsignedInt(grant.into());
+            } else {
+                acl.remove(idx);
+                acl.remove(idx);
+            }
+
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(Collection::Mailbox)
+                .update_document(mailbox_id)
+                .custom(
+                    ObjectIndexBuilder::new(SCHEMA)
+                        .with_current(mailbox)
+                        .with_changes(
+                            Object::with_capacity(1).with_property(Property::Acl, Value::List(acl)),
+                        ),
+                );
+            match self.store.write(batch.build()).await {
+                Ok(_) => break,
+                Err(store::Error::AssertValueFailed) if try_count < 3 => {
+                    try_count += 1;
+                }
+                Err(store::Error::AssertValueFailed) => {
+                    return Err(MethodError::ServerUnavailable);
+                }
+                Err(err) => {
+                    tracing::error!(
+                        event = "error",
+                        context = "share_invitation",
+                        account_id = account_id,
+                        mailbox_id = mailbox_id,
+                        error = ?err,
+                        "Failed to update mailbox ACL.");
+                    return Err(MethodError::ServerPartialFail);
+                }
+            }
+        }
+
+        // Notify the owner and refresh the accounts shared with the grantee
+        let mut changes = ChangeLogBuilder::new();
+        changes.log_update(Collection::Mailbox, mailbox_id);
+        let change_id = self.commit_changes(account_id, changes).await?;
+        self.broadcast_state_change(
+            StateChange::new(account_id).with_change(DataType::Mailbox, change_id),
+        )
+        .await;
+        self.access_tokens.remove(&grantee_id);
+
+        Ok(Ok(()))
+    }
+
+    async fn share_invitation_email(
+        &self,
+        grantee_id: u32,
+        access_token: &AccessToken,
+        mailbox_name: &str,
+        rights: Bitmap<Acl>,
+    ) {
+        let grantee_name = if let Ok(Some(grantee_name)) = self.get_account_name(grantee_id).await {
+            grantee_name
+        } else {
+            return;
+        };
+        let rcpt = if let Some(rcpt) = self
+            .directory
+            .emails_by_name(&grantee_name)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .next()
+        {
+            rcpt
+        } else {
+            return;
+        };
+        let from = self
+            .directory
+            .emails_by_name(&access_token.name)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| access_token.name.clone());
+        let from_name = access_token
+            .description
+            .as_deref()
+            .unwrap_or(access_token.name.as_str());
+
+        let message = MessageBuilder::new()
+            .from((from_name, from.as_str()))
+            .to(rcpt.as_str())
+            .subject(format!(
+                "{from_name} shared the folder \"{mailbox_name}\" with you"
+            ))
+            .header("Auto-Submitted", HeaderType::Text("auto-generated".into()))
+            .text_body(format!(
+                concat!(
+                    "{} <{}> has shared the folder \"{}\" with you ({}).\r\n\r\n",
+                    "The folder will not be visible in your account until you accept ",
+                    "the invitation from your mail client. You may also decline it.\r\n"
+                ),
+                from_name,
+                from,
+                mailbox_name,
+                rights
+                    .map(|acl_item| acl_item.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+            .write_to_vec()
+            .unwrap_or_default();
+
+        if !matches!(
+            self.deliver_to_account(&message, "", &rcpt, &grantee_name)
+                .await,
+            DeliveryResult::Success
+        ) {
+            tracing::debug!(
+                context = "share_invitation",
+                event = "error",
+                rcpt = rcpt,
+                "Failed to deliver share invitation."
+            );
+        }
+    }
+}
+
+fn pending_grants(acl: Option<&Value>) -> Vec<(u32, AclGrant)> {
+    if let Some(Value::List(acl)) = acl {
+        acl.chunks_exact(2)
+            .filter_map(|item| match (item.first(), item.last()) {
+                (Some(Value::Id(id)), Some(Value::UnsignedInt(bits))) => {
+                    Some((id.document_id(), AclGrant::from(*bits)))
+                        .filter(|(_, grant)| grant.pending && !grant.grant.is_empty())
+                }
+                _ => None,
+            })
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
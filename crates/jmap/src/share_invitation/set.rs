@@ -0,0 +1,215 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::{method::MethodError, set::SetError},
+    method::set::{RequestArguments, SetRequest, SetResponse},
+    object::Object,
+    response::references::EvalObjectReferences,
+    types::{
+        collection::Collection,
+        property::Property,
+        state::StateChange,
+        type_state::DataType,
+        value::{MaybePatchValue, Value},
+    },
+};
+use store::write::{assert::HashedValue, log::ChangeLogBuilder, BatchBuilder, F_CLEAR, F_VALUE};
+
+use crate::JMAP;
+
+use super::{STATE_ACCEPTED, STATE_DECLINED, STATE_PENDING};
+
+impl JMAP {
+    pub async fn share_invitation_set(
+        &self,
+        mut request: SetRequest<RequestArguments>,
+    ) -> Result<SetResponse, MethodError> {
+        let account_id = request.account_id.document_id();
+        let mut invitation_ids = self
+            .get_document_ids(account_id, Collection::ShareInvitation)
+            .await?
+            .unwrap_or_default();
+        let mut response = self
+            .prepare_set_response(&request, Collection::ShareInvitation)
+            .await?;
+        let will_destroy = request.unwrap_destroy();
+
+        // Invitations are only created by the server when a mailbox is shared
+        for (id, _) in request.unwrap_create() {
+            response.not_created.append(
+                id,
+                SetError::forbidden().with_description("Share invitations cannot be created."),
+            );
+        }
+
+        // Process updates
+        let mut changes = ChangeLogBuilder::new();
+        'update: for (id, object) in request.unwrap_update() {
+            // Make sure id won't be destroyed
+            if will_destroy.contains(&id) {
+                response.not_updated.append(id, SetError::will_destroy());
+                continue 'update;
+            }
+
+            let mut accept = None;
+            for (property, value) in object.properties {
+                match (&property, response.eval_object_references(value)) {
+                    (Property::State, Ok(MaybePatchValue::Value(Value::Text(state))))
+                        if state == STATE_ACCEPTED || state == STATE_DECLINED =>
+                    {
+                        accept = Some(state == STATE_ACCEPTED);
+                    }
+                    (_, Err(err)) => {
+                        response.not_updated.append(id, err);
+                        continue 'update;
+                    }
+                    _ => {
+                        response.not_updated.append(
+                            id,
+                            SetError::invalid_properties()
+                                .with_property(property.clone())
+                                .with_description("Field could not be set."),
+                        );
+                        continue 'update;
+                    }
+                }
+            }
+
+            let document_id = id.document_id();
+            let current = if let Some(current) = self
+                .get_property::<HashedValue<Object<Value>>>(
+                    account_id,
+                    Collection::ShareInvitation,
+                    document_id,
+                    Property::Value,
+                )
+                .await?
+            {
+                current
+            } else {
+                response.not_updated.append(id, SetError::not_found());
+                continue 'update;
+            };
+            let accept = if let Some(accept) = accept {
+                accept
+            } else {
+                response.updated.append(id, None);
+                continue 'update;
+            };
+            if !matches!(current.inner.get(&Property::State), Value::Text(state) if state == STATE_PENDING)
+            {
+                response.not_updated.append(
+                    id,
+                    SetError::forbidden()
+                        .with_property(Property::State)
+                        .with_description("This invitation has already been answered."),
+                );
+                continue 'update;
+            }
+
+            // Update the owner's mailbox before recording the answer
+            if let Err(err) = self
+                .share_invitation_respond(account_id, &current.inner, accept)
+                .await?
+            {
+                response.not_updated.append(id, err);
+                continue 'update;
+            }
+
+            let mut invitation = current.inner.clone();
+            invitation.set(
+                Property::State,
+                Value::Text(
+                    if accept {
+                        STATE_ACCEPTED
+                    } else {
+                        STATE_DECLINED
+                    }
+                    .to_string(),
+                ),
+            );
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(Collection::ShareInvitation)
+                .update_document(document_id)
+                .assert_value(Property::Value, &current)
+                .value(Property::Value, invitation, F_VALUE);
+            self.write_batch(batch).await?;
+            changes.log_update(Collection::ShareInvitation, document_id);
+            response.updated.append(id, None);
+        }
+
+        // Process deletions, pending invitations are declined
+        for id in will_destroy {
+            let document_id = id.document_id();
+            if invitation_ids.contains(document_id) {
+                if let Some(invitation) = self
+                    .get_property::<Object<Value>>(
+                        account_id,
+                        Collection::ShareInvitation,
+                        document_id,
+                        Property::Value,
+                    )
+                    .await?
+                    .filter(|invitation| {
+                        matches!(invitation.get(&Property::State), Value::Text(state) if state == STATE_PENDING)
+                    })
+                {
+                    if let Err(err) = self
+                        .share_invitation_respond(account_id, &invitation, false)
+                        .await?
+                    {
+                        response.not_destroyed.append(id, err);
+                        continue;
+                    }
+                }
+
+                let mut batch = BatchBuilder::new();
+                batch
+                    .with_account_id(account_id)
+                    .with_collection(Collection::ShareInvitation)
+                    .delete_document(document_id)
+                    .value(Property::Value, (), F_VALUE | F_CLEAR);
+                self.write_batch(batch).await?;
+                invitation_ids.remove(document_id);
+                changes.log_delete(Collection::ShareInvitation, document_id);
+                response.destroyed.push(id);
+            } else {
+                response.not_destroyed.append(id, SetError::not_found());
+            }
+        }
+
+        // Write changes
+        if !changes.is_empty() {
+            let change_id = self.commit_changes(account_id, changes).await?;
+            response.new_state = Some(change_id.into());
+            response.state_change = StateChange::new(account_id)
+                .with_change(DataType::ShareInvitation, change_id)
+                .into();
+        }
+
+        Ok(response)
+    }
+}
@@ -25,7 +25,7 @@ use std::borrow::Cow;
 
 use jmap_proto::types::{collection::Collection, id::Id, keyword::Keyword, property::Property};
 use mail_parser::MessageParser;
-use sieve::{Envelope, Event, Input, Mailbox, Recipient};
+use sieve::{runtime::Variable, Envelope, Event, Input, Mailbox, Recipient};
 use smtp::core::{NullIo, Session, SessionAddress};
 use store::{
     ahash::AHashSet,
@@ -33,8 +33,10 @@ use store::{
 };
 
 use crate::{
-    email::ingest::{IngestEmail, IngestedEmail},
+    collected_address::KNOWN_SENDERS_LIST,
+    email::ingest::{bimi_indicator, IngestEmail, IngestedEmail},
     mailbox::{INBOX_ID, TRASH_ID},
+    settings::policy::DomainPolicy,
     sieve::SeenIdHash,
     Bincode, IngestError, JMAP,
 };
@@ -49,6 +51,7 @@ struct SieveMessage<'x> {
 
 impl JMAP {
     #[allow(clippy::blocks_in_if_conditions)]
+    #[allow(clippy::too_many_arguments)]
     pub async fn sieve_script_ingest(
         &self,
         raw_message: &[u8],
@@ -57,6 +60,10 @@ impl JMAP {
         account_id: u32,
         account_name: &str,
         mut active_script: ActiveScript,
+        policy_domain: &str,
+        policy: &DomainPolicy,
+        forward_to: &[String],
+        forward_keep_copy: bool,
     ) -> Result<IngestedEmail, IngestError> {
         // Parse message
         let message = if let Some(message) = MessageParser::new().parse(raw_message) {
@@ -68,14 +75,29 @@ impl JMAP {
             });
         };
 
+        // Scripts can add headers, so the indicator is obtained from the delivered message
+        let bimi_indicator = bimi_indicator(raw_message, &self.config.mail_bimi_authserv_id);
+
         // Obtain mailboxIds
         let mailbox_ids = self
             .mailbox_get_or_create(account_id)
             .await
             .map_err(|_| IngestError::Temporary)?;
 
-        // Create Sieve instance
-        let mut instance = self.sieve_runtime.filter_parsed(message);
+        // Create Sieve instance, exposing the recipient detail as ${env.subaddress}
+        let mut instance = self.sieve_runtime.filter_parsed(message).with_vars_env(
+            [(
+                Cow::Borrowed("subaddress"),
+                Variable::from(
+                    self.directory
+                        .subaddress(envelope_to)
+                        .unwrap_or_default()
+                        .to_string(),
+                ),
+            )]
+            .into_iter()
+            .collect(),
+        );
 
         // Obtain mail from address
         let mail_from = if let Some(email) = self
@@ -338,20 +360,32 @@ impl JMAP {
                     } => {
                         input = true.into();
                         if let Some(message) = messages.get(message_id) {
+                            // Replies to the sender are always allowed, redirects are
+                            // subject to the forwarding restrictions of the domain policy
+                            let rcpts = match recipient {
+                                Recipient::Address(rcpt) => vec![rcpt],
+                                Recipient::Group(rcpts) => rcpts,
+                                Recipient::List(_) => {
+                                    // Not yet implemented
+                                    continue;
+                                }
+                            }
+                            .into_iter()
+                            .filter(|rcpt| {
+                                rcpt.eq_ignore_ascii_case(envelope_from)
+                                    || self.is_forward_allowed(policy_domain, policy, rcpt)
+                            })
+                            .map(SessionAddress::new)
+                            .collect::<Vec<_>>();
+                            if rcpts.is_empty() {
+                                continue;
+                            }
+
                             if message.raw_message.len() <= self.config.mail_max_size {
                                 let result = Session::<NullIo>::sieve(
-                                    self.smtp.clone(),
+                                    self.smtp.core(),
                                     SessionAddress::new(mail_from.clone()),
-                                    match recipient {
-                                        Recipient::Address(rcpt) => vec![SessionAddress::new(rcpt)],
-                                        Recipient::Group(rcpts) => {
-                                            rcpts.into_iter().map(SessionAddress::new).collect()
-                                        }
-                                        Recipient::List(_) => {
-                                            // Not yet implemented
-                                            continue;
-                                        }
-                                    },
+                                    rcpts,
                                     message.raw_message.to_vec(),
                                 )
                                 .queue_message()
@@ -381,10 +415,23 @@ impl JMAP {
                             continue;
                         }
                     }
-                    Event::ListContains { .. }
-                    | Event::Function { .. }
-                    | Event::Notify { .. }
-                    | Event::SetEnvelope { .. } => {
+                    Event::ListContains { lists, values, .. } => {
+                        // Only the auto-collected addresses list is available to user scripts
+                        input = false.into();
+                        if lists.iter().any(|list| list == KNOWN_SENDERS_LIST) {
+                            for value in &values {
+                                if self
+                                    .is_collected_address(account_id, value)
+                                    .await
+                                    .unwrap_or(false)
+                                {
+                                    input = true.into();
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    Event::Function { .. } | Event::Notify { .. } | Event::SetEnvelope { .. } => {
                         // Not allowed
                         input = false.into();
                     }
@@ -420,6 +467,26 @@ impl JMAP {
             messages[0].file_into.push(INBOX_ID);
         }
 
+        // Forward messages that were not discarded or rejected by the script, the
+        // implicit keep is dropped unless the account owner asked to keep a copy
+        if !do_discard
+            && !forward_to.is_empty()
+            && self
+                .forward_message(
+                    raw_message,
+                    envelope_from,
+                    account_name,
+                    envelope_to,
+                    forward_to,
+                )
+                .await
+            && !forward_keep_copy
+        {
+            messages[0]
+                .file_into
+                .retain(|mailbox_id| *mailbox_id != INBOX_ID);
+        }
+
         // Deliver messages
         let mut last_temp_error = None;
         let mut has_delivered = false;
@@ -451,6 +518,9 @@ impl JMAP {
                         mailbox_ids: sieve_message.file_into,
                         keywords: sieve_message.flags,
                         received_at: None,
+                        subaddress: self.directory.subaddress(envelope_to),
+                        bimi_indicator: bimi_indicator.clone(),
+                        is_delivered: true,
                         skip_duplicates: true,
                         encrypt: self.config.encrypt,
                     })
@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use jmap_proto::{
+    error::method::MethodError,
+    object::Object,
+    types::{collection::Collection, property::Property, value::Value},
+};
+
+use crate::{auth::AccessToken, JMAP};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SieveLimits {
+    pub max_scripts: usize,
+    pub max_total_size: usize,
+}
+
+impl SieveLimits {
+    pub fn parse(
+        settings: &utils::config::Config,
+        prefix: &str,
+        default: Option<SieveLimits>,
+    ) -> Result<Self, String> {
+        Ok(SieveLimits {
+            max_scripts: settings
+                .property((prefix, "max-scripts"))?
+                .or(default.map(|d| d.max_scripts))
+                .unwrap_or(256),
+            max_total_size: settings
+                .property((prefix, "max-total-size"))?
+                .or(default.map(|d| d.max_total_size))
+                .unwrap_or(1024 * 1024),
+        })
+    }
+
+    fn merge(self, other: SieveLimits) -> SieveLimits {
+        SieveLimits {
+            max_scripts: self.max_scripts.max(other.max_scripts),
+            max_total_size: self.max_total_size.max(other.max_total_size),
+        }
+    }
+}
+
+impl JMAP {
+    pub async fn sieve_limits(
+        &self,
+        access_token: &AccessToken,
+        account_id: u32,
+    ) -> Result<SieveLimits, MethodError> {
+        if self.config.sieve_limits_override.is_empty() {
+            return Ok(self.config.sieve_limits);
+        }
+
+        // Overrides apply to the owner of the account
+        let owner_token;
+        let access_token = if access_token.primary_id() == account_id {
+            access_token
+        } else if let Some(token) = self.get_access_token(account_id).await {
+            owner_token = token;
+            &owner_token
+        } else {
+            return Ok(self.config.sieve_limits);
+        };
+
+        // Account overrides take precedence over group overrides
+        if let Some(limits) = self.config.sieve_limits_override.get(&access_token.name) {
+            return Ok(*limits);
+        }
+        let mut result: Option<SieveLimits> = None;
+        for member_of in &access_token.member_of {
+            if let Some(limits) = self
+                .get_account_name(*member_of)
+                .await?
+                .and_then(|name| self.config.sieve_limits_override.get(&name))
+            {
+                result = Some(result.map_or(*limits, |result| result.merge(*limits)));
+            }
+        }
+
+        Ok(result.unwrap_or(self.config.sieve_limits))
+    }
+
+    pub async fn sieve_scripts_size(
+        &self,
+        account_id: u32,
+        exclude_id: Option<u32>,
+    ) -> Result<usize, MethodError> {
+        let document_ids = self
+            .get_document_ids(account_id, Collection::SieveScript)
+            .await?
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|document_id| Some(*document_id) != exclude_id);
+
+        Ok(self
+            .get_properties::<Object<Value>>(
+                account_id,
+                Collection::SieveScript,
+                document_ids,
+                Property::Value,
+            )
+            .await?
+            .into_iter()
+            .flatten()
+            .map(|script| match script.properties.get(&Property::Size) {
+                Some(Value::UnsignedInt(size)) => *size as usize,
+                _ => 0,
+            })
+            .sum())
+    }
+}
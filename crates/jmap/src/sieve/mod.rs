@@ -29,6 +29,7 @@ use store::{ahash::AHashSet, blake3, write::now};
 
 pub mod get;
 pub mod ingest;
+pub mod limits;
 pub mod query;
 pub mod set;
 pub mod validate;
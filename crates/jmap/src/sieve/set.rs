@@ -50,11 +50,12 @@ use store::{
     BlobKind,
 };
 
-use crate::{auth::AccessToken, JMAP};
+use crate::{auth::AccessToken, sieve::limits::SieveLimits, JMAP};
 
 struct SetContext<'x> {
     account_id: u32,
     account_quota: i64,
+    sieve_limits: SieveLimits,
     access_token: &'x AccessToken,
     response: SetResponse,
 }
@@ -85,6 +86,7 @@ impl JMAP {
         let mut ctx = SetContext {
             account_id,
             account_quota: self.get_quota(access_token, account_id).await?,
+            sieve_limits: self.sieve_limits(access_token, account_id).await?,
             access_token,
             response: self
                 .prepare_set_response(&request, Collection::SieveScript)
@@ -95,7 +97,7 @@ impl JMAP {
         // Process creates
         let mut changes = ChangeLogBuilder::new();
         for (id, object) in request.unwrap_create() {
-            if sieve_ids.len() as usize <= self.config.sieve_max_scripts {
+            if (sieve_ids.len() as usize) < ctx.sieve_limits.max_scripts {
                 match self.sieve_set_item(object, None, &ctx).await? {
                     Ok((builder, Some(blob))) => {
                         // Obtain document id
@@ -462,6 +464,20 @@ impl JMAP {
                     {
                         return Ok(Err(SetError::over_quota()));
                     }
+                    if ctx.sieve_limits.max_total_size > 0
+                        && bytes.len()
+                            + self
+                                .sieve_scripts_size(
+                                    ctx.account_id,
+                                    update.as_ref().map(|(document_id, _)| *document_id),
+                                )
+                                .await?
+                            > ctx.sieve_limits.max_total_size
+                    {
+                        return Ok(Err(SetError::over_quota().with_description(
+                            "The total size of sieve scripts exceeds the allowed limit.",
+                        )));
+                    }
 
                     // Compile script
                     match self.sieve_compiler.compile(&bytes) {
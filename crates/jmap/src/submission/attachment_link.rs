@@ -0,0 +1,405 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Write;
+
+use jmap_proto::{error::method::MethodError, types::collection::Collection};
+use mail_parser::{DateTime, MessageParser, PartType};
+use store::{
+    rand::{thread_rng, Rng},
+    write::{key::DeserializeBigEndian, now, BatchBuilder, Operation, ValueClass},
+    BlobKind, CustomValueKey, Deserialize, Serialize,
+};
+
+use crate::{auth::authenticate::AccountKey, Bincode, JMAP};
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct AttachmentLinkSettings {
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AttachmentLink {
+    pub secret: u64,
+    pub name: String,
+    pub content_type: String,
+    pub size: usize,
+    pub expires: u64,
+    pub password: Option<String>,
+}
+
+// Links are only stored once the rewritten message has been accepted for delivery
+pub struct PendingAttachmentLink {
+    link_id: u32,
+    contents: Vec<u8>,
+    link: AttachmentLink,
+}
+
+impl AttachmentLink {
+    pub fn blob_kind(account_id: u32, link_id: u32) -> BlobKind {
+        BlobKind::Linked {
+            account_id,
+            collection: Collection::AttachmentLink.into(),
+            document_id: link_id,
+        }
+    }
+}
+
+impl JMAP {
+    pub async fn attachment_link_settings(
+        &self,
+        account_id: u32,
+    ) -> Result<Option<AttachmentLinkSettings>, MethodError> {
+        self.store
+            .get_value::<Bincode<AttachmentLinkSettings>>(CustomValueKey {
+                value: AccountKey::attachment_link_settings(account_id),
+            })
+            .await
+            .map(|value| value.map(|value| value.inner))
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve attachment link preferences");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn set_attachment_link_settings(
+        &self,
+        account_id: u32,
+        settings: Option<AttachmentLinkSettings>,
+    ) -> Result<(), MethodError> {
+        let mut batch = BatchBuilder::new();
+        batch
+            .with_account_id(u32::MAX)
+            .with_collection(Collection::Principal)
+            .op(Operation::Value {
+                class: ValueClass::Custom {
+                    bytes: AccountKey::attachment_link_settings(account_id),
+                },
+                set: settings.map(|settings| Bincode::new(settings).serialize()),
+            });
+        self.write_batch(batch).await
+    }
+
+    // Replaces attachments above the configured threshold in an outgoing message
+    // with a link to a copy of the attachment, the stored message is not modified.
+    // The returned links have to be stored with `store_attachment_links`.
+    pub async fn rewrite_attachments(
+        &self,
+        account_id: u32,
+        raw_message: Vec<u8>,
+    ) -> Result<(Vec<u8>, Vec<PendingAttachmentLink>), MethodError> {
+        let base_url = match &self.config.attachment_link_url {
+            Some(url) if self.config.attachment_link_threshold > 0 => url.trim_end_matches('/'),
+            _ => return Ok((raw_message, Vec::new())),
+        };
+        let settings = if let Some(settings) = self.attachment_link_settings(account_id).await? {
+            settings
+        } else {
+            return Ok((raw_message, Vec::new()));
+        };
+        let message = if let Some(message) = MessageParser::new().parse(&raw_message) {
+            message
+        } else {
+            return Ok((raw_message, Vec::new()));
+        };
+
+        let expires = now() + self.config.attachment_link_expiry;
+        let mut replacements = Vec::new();
+        let mut links = Vec::new();
+        for (part_id, part) in message.parts.iter().enumerate() {
+            // The root part also holds the message headers
+            if part_id == 0 || !message.attachments.contains(&(part_id as u32)) {
+                continue;
+            }
+            let contents = match &part.body {
+                PartType::Binary(bytes) | PartType::InlineBinary(bytes) => bytes.as_ref(),
+                PartType::Text(text) | PartType::Html(text) => text.as_bytes(),
+                PartType::Message(_) | PartType::Multipart(_) => continue,
+            };
+            if contents.len() <= self.config.attachment_link_threshold {
+                continue;
+            }
+
+            let link = AttachmentLink {
+                secret: thread_rng().gen(),
+                name: part
+                    .attachment_name()
+                    .unwrap_or("attachment")
+                    .replace(['\r', '\n'], " "),
+                content_type: part
+                    .content_type()
+                    .map(|ct| {
+                        ct.subtype()
+                            .map(|st| format!("{}/{}", ct.ctype(), st))
+                            .unwrap_or_else(|| ct.ctype().to_string())
+                    })
+                    .unwrap_or_else(|| "application/octet-stream".to_string()),
+                size: contents.len(),
+                expires,
+                password: settings.password.clone(),
+            };
+            let link_id = self
+                .assign_document_id(account_id, Collection::AttachmentLink)
+                .await?;
+            let mut text = format!(
+                "The attachment \"{}\" ({}) was replaced with a download link:\r\n\r\n{}/{}\r\n\r\n",
+                link.name,
+                format_size(link.size),
+                base_url,
+                attachment_link_token(account_id, link_id, link.secret),
+            );
+            let _ = write!(
+                text,
+                "The link expires on {}.\r\n",
+                DateTime::from_timestamp(expires as i64).to_rfc822()
+            );
+            if link.password.is_some() {
+                text.push_str("A password is required to download the attachment.\r\n");
+            }
+
+            replacements.push((part.offset_header, part.offset_end, text));
+            links.push(PendingAttachmentLink {
+                link_id,
+                contents: contents.to_vec(),
+                link,
+            });
+        }
+
+        if replacements.is_empty() {
+            return Ok((raw_message, links));
+        }
+
+        let mut rewritten = Vec::with_capacity(raw_message.len());
+        let mut last_offset = 0;
+        for (offset_start, offset_end, text) in replacements {
+            rewritten.extend_from_slice(&raw_message[last_offset..offset_start]);
+            rewritten.extend_from_slice(
+                concat!(
+                    "Content-Type: text/plain; charset=utf-8\r\n",
+                    "Content-Disposition: inline\r\n",
+                    "Content-Transfer-Encoding: 8bit\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            rewritten.extend_from_slice(text.as_bytes());
+            // Keep the line break that precedes the next boundary
+            if raw_message[..offset_end].ends_with(b"\r\n") {
+                rewritten.extend_from_slice(b"\r\n");
+            } else if raw_message[..offset_end].ends_with(b"\n") {
+                rewritten.push(b'\n');
+            }
+            last_offset = offset_end;
+        }
+        rewritten.extend_from_slice(&raw_message[last_offset..]);
+
+        Ok((rewritten, links))
+    }
+
+    pub async fn store_attachment_links(
+        &self,
+        account_id: u32,
+        links: Vec<PendingAttachmentLink>,
+    ) -> Result<(), MethodError> {
+        for PendingAttachmentLink {
+            link_id,
+            contents,
+            link,
+        } in links
+        {
+            self.put_blob(&AttachmentLink::blob_kind(account_id, link_id), &contents)
+                .await?;
+            let mut batch = BatchBuilder::new();
+            batch
+                .with_account_id(account_id)
+                .with_collection(Collection::AttachmentLink)
+                .create_document(link_id)
+                .with_account_id(u32::MAX)
+                .with_collection(Collection::Principal)
+                .op(Operation::Value {
+                    class: ValueClass::Custom {
+                        bytes: AccountKey::attachment_link_expiry(
+                            link.expires,
+                            account_id,
+                            link_id,
+                        ),
+                    },
+                    set: Some(vec![]),
+                })
+                .op(Operation::Value {
+                    class: ValueClass::Custom {
+                        bytes: AccountKey::attachment_link(account_id, link_id),
+                    },
+                    set: Bincode::new(link).serialize().into(),
+                });
+            self.write_batch(batch).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_attachment_link(
+        &self,
+        account_id: u32,
+        link_id: u32,
+    ) -> Result<Option<AttachmentLink>, MethodError> {
+        self.store
+            .get_value::<Bincode<AttachmentLink>>(CustomValueKey {
+                value: AccountKey::attachment_link(account_id, link_id),
+            })
+            .await
+            .map(|value| value.map(|value| value.inner))
+            .map_err(|err| {
+                tracing::error!(event = "error",
+                    context = "store",
+                    account_id = account_id,
+                    error = ?err,
+                    "Failed to retrieve attachment link");
+                MethodError::ServerPartialFail
+            })
+    }
+
+    pub async fn attachment_links(
+        &self,
+        account_id: Option<u32>,
+    ) -> store::Result<Vec<(u32, u32, AttachmentLink)>> {
+        let (from_account_id, to_account_id) =
+            account_id.map_or((0, u32::MAX), |account_id| (account_id, account_id));
+        self.store
+            .iterate(
+                Vec::new(),
+                CustomValueKey {
+                    value: AccountKey::attachment_link(from_account_id, 0),
+                },
+                CustomValueKey {
+                    value: AccountKey::attachment_link(to_account_id, u32::MAX),
+                },
+                false,
+                true,
+                move |links, key, value| {
+                    // Skip the u32::MAX account prefix and the key type
+                    let offset = std::mem::size_of::<u32>() + 1;
+                    links.push((
+                        key.deserialize_be_u32(offset)?,
+                        key.deserialize_be_u32(offset + std::mem::size_of::<u32>())?,
+                        Bincode::<AttachmentLink>::deserialize(value)?.inner,
+                    ));
+                    Ok(true)
+                },
+            )
+            .await
+    }
+
+    pub async fn purge_attachment_links(&self) -> store::Result<()> {
+        // Only the links that are due are read, ordered by their expiry time
+        let expired = self
+            .store
+            .iterate(
+                Vec::new(),
+                CustomValueKey {
+                    value: AccountKey::attachment_link_expiry(0, 0, 0),
+                },
+                CustomValueKey {
+                    value: AccountKey::attachment_link_expiry(now(), u32::MAX, u32::MAX),
+                },
+                false,
+                true,
+                move |expired, key, _| {
+                    // Skip the u32::MAX account prefix and the key type
+                    let offset = std::mem::size_of::<u32>() + 1;
+                    expired.push((
+                        key.deserialize_be_u64(offset)?,
+                        key.deserialize_be_u32(offset + std::mem::size_of::<u64>())?,
+                        key.deserialize_be_u32(
+                            offset + std::mem::size_of::<u64>() + std::mem::size_of::<u32>(),
+                        )?,
+                    ));
+                    Ok(true)
+                },
+            )
+            .await?;
+
+        for links in expired.chunks(100) {
+            let mut batch = BatchBuilder::new();
+            for (expires, account_id, link_id) in links {
+                self.store
+                    .delete_blob(&AttachmentLink::blob_kind(*account_id, *link_id))
+                    .await?;
+                batch
+                    .with_account_id(*account_id)
+                    .with_collection(Collection::AttachmentLink)
+                    .delete_document(*link_id)
+                    .with_account_id(u32::MAX)
+                    .with_collection(Collection::Principal)
+                    .op(Operation::Value {
+                        class: ValueClass::Custom {
+                            bytes: AccountKey::attachment_link_expiry(
+                                *expires,
+                                *account_id,
+                                *link_id,
+                            ),
+                        },
+                        set: None,
+                    })
+                    .op(Operation::Value {
+                        class: ValueClass::Custom {
+                            bytes: AccountKey::attachment_link(*account_id, *link_id),
+                        },
+                        set: None,
+                    });
+            }
+            self.store.write(batch.build()).await?;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn attachment_link_token(account_id: u32, link_id: u32, secret: u64) -> String {
+    format!("{account_id:x}-{link_id:x}-{secret:016x}")
+}
+
+pub fn parse_attachment_link_token(token: &str) -> Option<(u32, u32, u64)> {
+    let mut parts = token.split('-');
+    let account_id = u32::from_str_radix(parts.next()?, 16).ok()?;
+    let link_id = u32::from_str_radix(parts.next()?, 16).ok()?;
+    let secret = u64::from_str_radix(parts.next()?, 16).ok()?;
+    if parts.next().is_none() {
+        Some((account_id, link_id, secret))
+    } else {
+        None
+    }
+}
+
+fn format_size(size: usize) -> String {
+    if size >= 1024 * 1024 {
+        format!("{:.1} MB", size as f64 / (1024.0 * 1024.0))
+    } else if size >= 1024 {
+        format!("{:.1} KB", size as f64 / 1024.0)
+    } else {
+        format!("{size} bytes")
+    }
+}
@@ -101,6 +101,7 @@ impl JMAP {
             let (result_tx, result_rx) = oneshot::channel();
             if self
                 .smtp
+                .core()
                 .queue
                 .tx
                 .send(queue::Event::Manage(QueueRequest::Status {
@@ -21,6 +21,43 @@
  * for more details.
 */
 
+pub mod attachment_link;
 pub mod get;
+pub mod preview;
 pub mod query;
+pub mod sent_copy;
 pub mod set;
+
+use std::sync::Arc;
+
+use smtp::core::{NullIo, Session, SessionData};
+use utils::listener::ServerInstance;
+
+use crate::JMAP;
+
+impl JMAP {
+    // Evaluates the SMTP message size limit that applies to messages
+    // submitted by the specified user through this listener.
+    pub async fn smtp_max_message_size(
+        &self,
+        instance: &Arc<ServerInstance>,
+        authenticated_as: &str,
+    ) -> usize {
+        let core = self.smtp.core();
+        let session = Session::<NullIo>::local(
+            core.clone(),
+            instance.clone(),
+            SessionData {
+                authenticated_as: authenticated_as.to_string(),
+                ..Default::default()
+            },
+        );
+        *core
+            .session
+            .config
+            .data
+            .max_message_size
+            .eval(&session)
+            .await
+    }
+}
@@ -0,0 +1,94 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use http_body_util::{BodyExt, Full};
+use hyper::{body::Bytes, header, StatusCode};
+use jmap_proto::error::request::RequestError;
+use mail_parser::MessageParser;
+use smtp::core::{NullIo, Session, SessionAddress, SessionData};
+use utils::listener::ServerInstance;
+
+use crate::{api::HttpResponse, auth::AccessToken, JMAP};
+
+impl JMAP {
+    // Returns a draft exactly as it would be sent by the account, after the
+    // header scrubbing, footers, return path rewriting and signatures required
+    // by the sender's domain have been applied. Signature values are redacted.
+    // Nothing is queued, stored or logged as a submission.
+    pub async fn submission_preview(
+        &self,
+        access_token: &AccessToken,
+        instance: &Arc<ServerInstance>,
+        raw_message: Vec<u8>,
+    ) -> Result<HttpResponse, RequestError> {
+        // Previews are only generated for the account's own addresses
+        let sender = MessageParser::new()
+            .parse(&raw_message)
+            .and_then(|message| {
+                message
+                    .from()
+                    .and_then(|from| from.first())
+                    .and_then(|addr| addr.address())
+                    .map(|addr| addr.trim().to_lowercase())
+            })
+            .ok_or_else(|| {
+                RequestError::blank(
+                    StatusCode::BAD_REQUEST.as_u16(),
+                    "Invalid message",
+                    "The message does not contain a valid From header.",
+                )
+            })?;
+        if !self
+            .directory
+            .emails_by_name(&access_token.name)
+            .await
+            .unwrap_or_default()
+            .iter()
+            .any(|email| email.eq_ignore_ascii_case(&sender))
+        {
+            return Err(RequestError::forbidden());
+        }
+
+        // The sender is set directly rather than through MAIL FROM, which would
+        // consume the rate limits and throttles of the account
+        let session = Session::<NullIo>::local(
+            self.smtp.core(),
+            instance.clone(),
+            SessionData::local(Some(SessionAddress::new(sender)), vec![], vec![]),
+        );
+        let preview = session.preview_message(raw_message).await;
+
+        Ok(hyper::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "message/rfc822")
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(
+                Full::new(Bytes::from(preview))
+                    .map_err(|never| match never {})
+                    .boxed(),
+            )
+            .unwrap())
+    }
+}
@@ -0,0 +1,159 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, Instant};
+
+use jmap_proto::types::{
+    collection::Collection, keyword::Keyword, property::Property, state::StateChange,
+    type_state::DataType,
+};
+use mail_parser::MessageParser;
+use smtp::tracking::find_message_id;
+use utils::map::ttl_dashmap::TtlMap;
+
+use crate::{email::ingest::IngestEmail, JMAP};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SentCopy {
+    Filed(u32),
+    Appended,
+}
+
+impl JMAP {
+    // Files a copy of a message submitted over SMTP into the sender's Sent mailbox,
+    // unless the client already appended its own copy over IMAP.
+    pub async fn file_sent_copy(&self, account: &str, raw_message: &[u8]) {
+        if !self.config.sent_copy {
+            return;
+        }
+        let account_id = match self.get_account_id(account).await {
+            Ok(account_id) => account_id,
+            Err(_) => return,
+        };
+        let key = find_message_id(raw_message).map(|message_id| (account_id, message_id));
+        if let Some(key) = &key {
+            if matches!(self.sent_copies.get_with_ttl(key), Some(SentCopy::Appended)) {
+                return;
+            }
+        }
+
+        let account_quota = match self.directory.principal(account).await {
+            Ok(Some(principal)) => principal.quota as i64,
+            _ => 0,
+        };
+        if self.mailbox_get_or_create(account_id).await.is_err() {
+            return;
+        }
+        let mailbox_id = match self.mailbox_get_by_role(account_id, "sent").await {
+            Ok(Some(mailbox_id)) => mailbox_id,
+            _ => return,
+        };
+
+        match self
+            .email_ingest(IngestEmail {
+                raw_message,
+                message: MessageParser::new().parse(raw_message),
+                account_id,
+                account_quota,
+                mailbox_ids: vec![mailbox_id],
+                keywords: vec![Keyword::Seen],
+                received_at: None,
+                subaddress: None,
+                bimi_indicator: None,
+                is_delivered: false,
+                skip_duplicates: false,
+                encrypt: self.config.encrypt && self.config.encrypt_append,
+            })
+            .await
+        {
+            Ok(email) => {
+                if let Some(key) = key {
+                    self.sent_copies.insert_with_ttl(
+                        key,
+                        SentCopy::Filed(email.id.document_id()),
+                        Instant::now() + Duration::from_secs(self.config.sent_copy_window),
+                    );
+                }
+                self.broadcast_state_change(
+                    StateChange::new(account_id)
+                        .with_change(DataType::Email, email.change_id)
+                        .with_change(DataType::Mailbox, email.change_id)
+                        .with_change(DataType::Thread, email.change_id),
+                )
+                .await;
+            }
+            Err(_) => {
+                tracing::warn!(
+                    context = "sent_copy",
+                    event = "error",
+                    account_id = account_id,
+                    "Failed to file copy of submitted message."
+                );
+            }
+        }
+    }
+
+    // Returns the copy filed on submission when the client appends the same message
+    // to the Sent mailbox, otherwise None and the append should proceed.
+    pub async fn claim_sent_copy(
+        &self,
+        account_id: u32,
+        mailbox_id: u32,
+        raw_message: &[u8],
+    ) -> Option<u32> {
+        if !self.config.sent_copy {
+            return None;
+        }
+        match self.mailbox_get_by_role(account_id, "sent").await {
+            Ok(Some(sent_id)) if sent_id == mailbox_id => (),
+            _ => return None,
+        }
+        let key = (account_id, find_message_id(raw_message)?);
+        match self.sent_copies.get_with_ttl(&key) {
+            Some(SentCopy::Filed(document_id)) => {
+                self.sent_copies.remove(&key);
+                self.get_tag(
+                    account_id,
+                    Collection::Email,
+                    Property::MailboxIds,
+                    mailbox_id,
+                )
+                .await
+                .ok()
+                .flatten()
+                .filter(|document_ids| document_ids.contains(document_id))
+                .map(|_| document_id)
+            }
+            Some(SentCopy::Appended) => None,
+            None => {
+                // The submission has not been filed yet, skip it when it arrives
+                self.sent_copies.insert_with_ttl(
+                    key,
+                    SentCopy::Appended,
+                    Instant::now() + Duration::from_secs(self.config.sent_copy_window),
+                );
+                None
+            }
+        }
+    }
+}
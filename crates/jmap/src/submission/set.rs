@@ -59,7 +59,7 @@ use store::{
 use tokio::sync::oneshot;
 use utils::{listener::ServerInstance, map::vec_map::VecMap};
 
-use crate::{identity::set::sanitize_email, JMAP};
+use crate::{collected_address::recipient_names, identity::set::sanitize_email, JMAP};
 
 pub static SCHEMA: &[IndexProperty] = &[
     IndexProperty::new(Property::UndoStatus).index_as(IndexAs::Text {
@@ -178,6 +178,7 @@ impl JMAP {
                     let (result_tx, result_rx) = oneshot::channel();
                     if self
                         .smtp
+                        .core()
                         .queue
                         .tx
                         .send(queue::Event::Manage(QueueRequest::Cancel {
@@ -571,7 +572,7 @@ impl JMAP {
         );
 
         // Obtain raw message
-        let message = if let Some(message) = self
+        let (message, attachment_links) = if let Some(message) = self
             .get_blob(
                 &BlobKind::LinkedMaildir {
                     account_id,
@@ -581,6 +582,8 @@ impl JMAP {
             )
             .await?
         {
+            // Replace large attachments with download links, if enabled
+            let (message, links) = self.rewrite_attachments(account_id, message).await?;
             if message.len() > self.config.mail_max_size {
                 return Ok(Err(SetError::new(SetErrorType::InvalidEmail)
                     .with_description(format!(
@@ -589,16 +592,23 @@ impl JMAP {
                     ))));
             }
 
-            message
+            (message, links)
         } else {
             return Ok(Err(SetError::invalid_properties()
                 .with_property(Property::EmailId)
                 .with_description("Blob for email not found.")));
         };
 
+        // Obtain recipient names for address auto-collection
+        let mut recipient_names = if self.config.auto_collect {
+            recipient_names(&message)
+        } else {
+            Default::default()
+        };
+
         // Begin local SMTP session
         let mut session =
-            Session::<NullIo>::local(self.smtp.clone(), instance.clone(), SessionData::default());
+            Session::<NullIo>::local(self.smtp.core(), instance.clone(), SessionData::default());
 
         // MAIL FROM
         let _ = session.handle_mail_from(mail_from).await;
@@ -610,6 +620,11 @@ impl JMAP {
                 ))));
         }
 
+        // Apply the message size limit of the submitting user
+        if let Some(name) = self.get_account_name(account_id).await? {
+            session.params.max_message_size = self.smtp_max_message_size(instance, &name).await;
+        }
+
         // RCPT TO
         let mut responses = Vec::new();
         let mut has_success = false;
@@ -625,10 +640,44 @@ impl JMAP {
 
         // DATA
         if has_success {
+            if message.len() >= session.params.max_message_size {
+                return Ok(Err(SetError::too_large().with_description(format!(
+                    "Message exceeds the maximum size of {} bytes.",
+                    session.params.max_message_size
+                ))));
+            }
             session.data.message = message;
             let response = session.queue_message().await;
             if let State::Accepted(queue_id) = session.state {
                 submission.append(Property::MessageId, queue_id);
+
+                // The message has been queued, make its attachment links available
+                if let Err(err) = self
+                    .store_attachment_links(account_id, attachment_links)
+                    .await
+                {
+                    tracing::error!(
+                        event = "error",
+                        context = "attachment_link",
+                        account_id = account_id,
+                        queue_id = queue_id,
+                        error = ?err,
+                        "Failed to store attachment links.");
+                }
+
+                // Add accepted recipients to the auto-collected addresses
+                self.collect_addresses(
+                    account_id,
+                    responses
+                        .iter()
+                        .filter(|(_, response)| response.is_none())
+                        .map(|(addr, _)| {
+                            (addr.clone(), recipient_names.remove(&addr.to_lowercase()))
+                        })
+                        .collect::<Vec<_>>(),
+                )
+                .await
+                .ok();
             } else {
                 return Ok(Err(SetError::new(SetErrorType::ForbiddenToSend)
                     .with_description(format!(
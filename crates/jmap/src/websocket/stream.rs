@@ -37,7 +37,13 @@ use tokio_tungstenite::WebSocketStream;
 use tungstenite::Message;
 use utils::{listener::ServerInstance, map::bitmap::Bitmap};
 
-use crate::{auth::AccessToken, JMAP};
+use crate::{
+    auth::{
+        sessions::{session_expired, session_revoked, SessionProtocol},
+        AccessToken,
+    },
+    JMAP,
+};
 
 impl JMAP {
     pub async fn handle_websocket_stream(
@@ -82,6 +88,15 @@ impl JMAP {
         let mut changes = WebSocketStateChange::new(None);
         let mut change_types: Bitmap<DataType> = Bitmap::new();
 
+        // Register live session
+        let live_session =
+            self.register_live_session(access_token.primary_id(), SessionProtocol::WebSocket, None);
+        let mut revoke_rx = Some(live_session.revoke_rx());
+        let session_expires = self
+            .config
+            .web_socket_max_duration
+            .map(|duration| Instant::now() + duration);
+
         loop {
             tokio::select! {
                 event = tokio::time::timeout(next_event, stream.next()) => {
@@ -89,12 +104,14 @@ impl JMAP {
                         Ok(Some(Ok(event))) => {
                             match event {
                                 Message::Text(text) => {
+                                    live_session.record_read(text.len());
                                     let response = match WebSocketMessage::parse(
                                         text.as_bytes(),
                                         self.config.request_max_calls,
                                         self.config.request_max_size,
                                     ) {
                                         Ok(WebSocketMessage::Request(request)) => {
+                                            live_session.set_memory(text.len());
                                             match self
                                                 .handle_request(
                                                     request.request,
@@ -127,6 +144,8 @@ impl JMAP {
                                         }
                                         Err(err) => err.to_json(),
                                     };
+                                    live_session.record_write(response.len());
+                                    live_session.set_memory(0);
                                     if let Err(err) = stream.send(Message::Text(response)).await {
                                         tracing::debug!(parent: &span, error = ?err, "Failed to send text message");
                                     }
@@ -187,13 +206,33 @@ impl JMAP {
                         break;
                     }
                 }
+                _ = session_revoked(&mut revoke_rx) => {
+                    tracing::debug!(
+                        parent: &span,
+                        event = "disconnect",
+                        "Disconnecting client, session revoked"
+                    );
+                    let _ = stream.close(None).await;
+                    break;
+                }
+                _ = session_expired(session_expires) => {
+                    tracing::debug!(
+                        parent: &span,
+                        event = "disconnect",
+                        "Disconnecting client, maximum session duration exceeded"
+                    );
+                    let _ = stream.close(None).await;
+                    break;
+                }
             }
 
             if !changes.changed.is_empty() {
                 // Send any queued changes
                 let elapsed = last_changes_sent.elapsed();
                 if elapsed >= throttle {
-                    if let Err(err) = stream.send(Message::Text(changes.to_json())).await {
+                    let changes_json = changes.to_json();
+                    live_session.record_write(changes_json.len());
+                    if let Err(err) = stream.send(Message::Text(changes_json)).await {
                         tracing::debug!(parent: &span, error = ?err, "Failed to send state change message");
                     }
                     changes.changed.clear();
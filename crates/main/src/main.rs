@@ -27,11 +27,12 @@ use directory::config::ConfigDirectory;
 use imap::core::{ImapSessionManager, IMAP};
 use jmap::{api::JmapSessionManager, services::IPC_CHANNEL_BUFFER, JMAP};
 use managesieve::core::ManageSieveSessionManager;
-use smtp::core::{SmtpSessionManager, SMTP};
+use smtp::core::{reload::SmtpHandle, SmtpSessionManager, SMTP};
 use tokio::sync::mpsc;
 use utils::{
+    auth_log::enable_auth_log,
     config::{Config, ServerProtocol},
-    enable_tracing, wait_for_shutdown, UnwrapFailure,
+    enable_tracing, wait_for_reload, wait_for_shutdown, UnwrapFailure,
 };
 
 #[cfg(not(target_env = "msvc"))]
@@ -43,9 +44,15 @@ static GLOBAL: Jemalloc = Jemalloc;
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    let config = Config::init();
+    let (config, args) = Config::init();
+    if args.check_config {
+        check_config(&config).await;
+    }
     let servers = config.parse_servers().failed("Invalid configuration");
     let directory = config.parse_directory().failed("Invalid configuration");
+    let shutdown_timeout = config
+        .property_or_static::<Duration>("server.shutdown.timeout", "30s")
+        .failed("Invalid configuration");
 
     // Bind ports and drop privileges
     servers.bind(&config);
@@ -59,12 +66,14 @@ async fn main() -> std::io::Result<()> {
         ),
     )
     .failed("Failed to enable tracing");
+    enable_auth_log(&config).failed("Invalid configuration");
 
     // Init servers
     let (delivery_tx, delivery_rx) = mpsc::channel(IPC_CHANNEL_BUFFER);
     let smtp = SMTP::init(&config, &servers, &directory, delivery_tx)
         .await
         .failed("Invalid configuration file");
+    let smtp = SmtpHandle::with_reload(smtp, args.source, config.clone(), directory.clone());
     let jmap = JMAP::init(&config, &directory, delivery_rx, smtp.clone())
         .await
         .failed("Invalid configuration file");
@@ -100,6 +109,22 @@ async fn main() -> std::io::Result<()> {
         schedule.spawn(shutdown_rx.clone());
     }
 
+    // Reload configuration on SIGHUP
+    let smtp_ = smtp.clone();
+    tokio::spawn(async move {
+        loop {
+            wait_for_reload().await;
+            if let Err(err) = smtp_.reload().await {
+                tracing::error!(
+                    context = "reload",
+                    event = "error",
+                    "Failed to reload configuration: {}",
+                    err
+                );
+            }
+        }
+    });
+
     // Wait for shutdown signal
     wait_for_shutdown(&format!(
         "Shutting down Stalwart Mail Server v{}...",
@@ -107,11 +132,64 @@ async fn main() -> std::io::Result<()> {
     ))
     .await;
 
-    // Stop services
+    // Stop accepting connections and wait for active sessions to finish,
+    // sessions hold a shutdown receiver until they are closed.
     let _ = shutdown_tx.send(true);
+    drop(shutdown_rx);
+    if tokio::time::timeout(shutdown_timeout, shutdown_tx.closed())
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            event = "shutdown",
+            "Timed out waiting for active sessions to finish."
+        );
+    }
 
-    // Wait for services to finish
-    tokio::time::sleep(Duration::from_secs(1)).await;
+    // Stop services
+    if tokio::time::timeout(Duration::from_secs(5), smtp.core().shutdown())
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            event = "shutdown",
+            "Timed out waiting for the queue manager to stop."
+        );
+    }
 
     Ok(())
 }
+
+async fn check_config(config: &Config) -> ! {
+    let mut errors = Vec::new();
+    let servers = config
+        .parse_servers()
+        .map_err(|err| errors.push(("server", err)))
+        .ok();
+    let directory = config
+        .parse_directory()
+        .map_err(|err| errors.push(("directory", err)))
+        .ok();
+
+    if let (Some(servers), Some(directory)) = (&servers, &directory) {
+        if let Err(err) = SMTP::check_config(config, &servers.inner, directory) {
+            errors.push(("smtp", err));
+        }
+        if let Err(err) = JMAP::check_config(config, directory) {
+            errors.push(("jmap", err));
+        }
+    }
+    if let Err(err) = IMAP::init(config).await {
+        errors.push(("imap", err));
+    }
+
+    if errors.is_empty() {
+        println!("Configuration is valid.");
+        std::process::exit(0);
+    } else {
+        for (component, err) in errors {
+            eprintln!("[{component}] {err}");
+        }
+        std::process::exit(1);
+    }
+}
@@ -23,6 +23,7 @@
 
 use imap::core::IMAP;
 use imap_proto::receiver::{self, Request};
+use jmap::JMAP;
 use jmap_proto::types::{collection::Collection, property::Property};
 use store::query::Filter;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
@@ -43,7 +44,12 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
         loop {
             match self.receiver.parse(&mut bytes) {
                 Ok(request) => {
-                    match request.validate_request(&self.imap, &self.state, self.stream.is_tls()) {
+                    match request.validate_request(
+                        &self.jmap,
+                        &self.imap,
+                        &self.state,
+                        self.stream.is_tls(),
+                    ) {
                         Ok(request) => {
                             requests.push(request);
                         }
@@ -180,6 +186,7 @@ impl<T: AsyncWrite + AsyncRead> Session<T> {
 trait ValidateRequest: Sized {
     fn validate_request(
         self,
+        jmap: &JMAP,
         imap: &IMAP,
         state: &State,
         is_tls: bool,
@@ -189,6 +196,7 @@ trait ValidateRequest: Sized {
 impl ValidateRequest for Request<Command> {
     fn validate_request(
         self,
+        jmap: &JMAP,
         imap: &IMAP,
         state: &State,
         is_tls: bool,
@@ -225,7 +233,7 @@ impl ValidateRequest for Request<Command> {
             | Command::Unauthenticate => {
                 if let State::Authenticated { access_token, .. } = state {
                     if imap
-                        .get_authenticated_limiter(access_token.primary_id())
+                        .get_authenticated_limiter(jmap, access_token.primary_id())
                         .lock()
                         .request_limiter
                         .is_allowed()
@@ -28,6 +28,7 @@ use imap_proto::{
     protocol::authenticate::Mechanism,
     receiver::{self, Request},
 };
+use jmap::auth::oauth::jwt::TokenProtocol;
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
 use tokio::io::{AsyncRead, AsyncWrite};
@@ -96,7 +97,7 @@ impl<T: AsyncRead + AsyncWrite + IsTls> Session<T> {
             Credentials::OAuthBearer { token } => {
                 match self
                     .jmap
-                    .validate_access_token("access_token", &token)
+                    .validate_access_token("access_token", &token, TokenProtocol::Sieve.into())
                     .await
                 {
                     Ok((account_id, _, _)) => self.jmap.get_access_token(account_id).await,
@@ -117,7 +118,7 @@ impl<T: AsyncRead + AsyncWrite + IsTls> Session<T> {
             // Enforce concurrency limits
             let in_flight = self
                 .imap
-                .get_authenticated_limiter(access_token.primary_id())
+                .get_authenticated_limiter(&self.jmap, access_token.primary_id())
                 .lock()
                 .concurrent_requests
                 .is_allowed();
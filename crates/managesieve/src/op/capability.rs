@@ -24,7 +24,7 @@
 use jmap::api::session::Capabilities;
 use tokio::io::{AsyncRead, AsyncWrite};
 
-use crate::core::{IsTls, Session, StatusResponse};
+use crate::core::{IsTls, Session, State, StatusResponse};
 
 impl<T: AsyncRead + AsyncWrite + IsTls> Session<T> {
     pub async fn handle_capability(&self, message: &'static str) -> super::OpResult {
@@ -66,6 +66,26 @@ impl<T: AsyncRead + AsyncWrite + IsTls> Session<T> {
                 response.extend_from_slice(sieve.max_redirects.to_string().as_bytes());
                 response.extend_from_slice(b"\"\r\n");
             }
+
+            // Script limits for the authenticated account
+            let limits = match &self.state {
+                State::Authenticated { access_token, .. } => self
+                    .jmap
+                    .sieve_limits(access_token, access_token.primary_id())
+                    .await
+                    .unwrap_or(self.jmap.config.sieve_limits),
+                State::NotAuthenticated { .. } => self.jmap.config.sieve_limits,
+            };
+            if limits.max_scripts > 0 {
+                response.extend_from_slice(b"\"MAXSCRIPTS\" \"");
+                response.extend_from_slice(limits.max_scripts.to_string().as_bytes());
+                response.extend_from_slice(b"\"\r\n");
+            }
+            if limits.max_total_size > 0 {
+                response.extend_from_slice(b"\"MAXTOTALSIZE\" \"");
+                response.extend_from_slice(limits.max_total_size.to_string().as_bytes());
+                response.extend_from_slice(b"\"\r\n");
+            }
         } else {
             response.extend_from_slice(b"\"SIEVE\" \"\"\r\n");
         }
@@ -43,7 +43,9 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
         // Validate name
         let access_token = self.state.access_token();
         let account_id = access_token.primary_id();
-        self.validate_name(account_id, &name).await?;
+        let document_id = self.validate_name(account_id, &name).await?;
+        self.validate_script_limits(account_id, document_id, size)
+            .await?;
 
         // Validate quota
         if access_token.quota == 0
@@ -60,18 +60,13 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
         {
             return Err(StatusResponse::no("Quota exceeded.").with_code(ResponseCode::Quota));
         }
-        if self
-            .jmap
-            .get_document_ids(account_id, Collection::SieveScript)
-            .await?
-            .map(|ids| ids.len() as usize)
-            .unwrap_or(0)
-            > self.jmap.config.sieve_max_scripts
-        {
-            return Err(
-                StatusResponse::no("Too many scripts.").with_code(ResponseCode::QuotaMaxScripts)
-            );
-        }
+
+        // Validate name
+        let document_id = self.validate_name(account_id, &name).await?;
+
+        // Check script limits
+        self.validate_script_limits(account_id, document_id, script.len())
+            .await?;
 
         // Compile script
         match self.jmap.sieve_compiler.compile(&script) {
@@ -87,8 +82,7 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
             }
         }
 
-        // Validate name
-        if let Some(document_id) = self.validate_name(account_id, &name).await? {
+        if let Some(document_id) = document_id {
             // Update blob
             self.jmap
                 .put_blob(
@@ -164,6 +158,42 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
         Ok(StatusResponse::ok("Success.").into_bytes())
     }
 
+    pub async fn validate_script_limits(
+        &self,
+        account_id: u32,
+        document_id: Option<u32>,
+        script_len: usize,
+    ) -> Result<(), StatusResponse> {
+        let access_token = self.state.access_token();
+        let limits = self.jmap.sieve_limits(access_token, account_id).await?;
+
+        if document_id.is_none()
+            && self
+                .jmap
+                .get_document_ids(account_id, Collection::SieveScript)
+                .await?
+                .map(|ids| ids.len() as usize)
+                .unwrap_or(0)
+                >= limits.max_scripts
+        {
+            Err(StatusResponse::no("Too many scripts.").with_code(ResponseCode::QuotaMaxScripts))
+        } else if limits.max_total_size > 0
+            && script_len
+                + self
+                    .jmap
+                    .sieve_scripts_size(account_id, document_id)
+                    .await?
+                > limits.max_total_size
+        {
+            Err(
+                StatusResponse::no("Total size of scripts exceeds the allowed limit.")
+                    .with_code(ResponseCode::QuotaMaxSize),
+            )
+        } else {
+            Ok(())
+        }
+    }
+
     pub async fn validate_name(
         &self,
         account_id: u32,
@@ -0,0 +1,580 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::fs;
+
+use crate::{
+    config::AnomalyAction,
+    core::AnomalyCore,
+    queue::{Message, Status, RCPT_OUTCOME_RECORDED},
+    usage::write_atomic,
+    webhook::{now, WebhookEventType},
+};
+
+// Number of past hours used to compute the outbound volume baseline
+const BASELINE_HOURS: u64 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AnomalySignal {
+    #[serde(rename = "new-location")]
+    NewLocation,
+    #[serde(rename = "volume-spike")]
+    VolumeSpike,
+    #[serde(rename = "bounce-rate")]
+    BounceRate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignalEntry {
+    pub signal: AnomalySignal,
+    pub time: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Containment {
+    pub action: AnomalyAction,
+    pub since: u64,
+    pub signals: Vec<AnomalySignal>,
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccountActivity {
+    pub locations: Vec<String>,
+    pub hour: u64,
+    pub hourly_messages: u64,
+    pub baseline: f64,
+    pub baseline_hours: u64,
+    pub outcomes_since: u64,
+    pub delivered: u64,
+    pub bounced: u64,
+    pub signals: Vec<SignalEntry>,
+    pub containment: Option<Containment>,
+    pub last_seen: u64,
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredEntry {
+    account: String,
+    activity: AccountActivity,
+}
+
+impl AccountActivity {
+    // Folds the message counts of the hours that have passed into the baseline,
+    // hours without any activity count as zero.
+    fn roll(&mut self, timestamp: u64) {
+        let hour = timestamp / 3600;
+        if self.hour != hour {
+            if self.hour != 0 {
+                let mut count = self.hourly_messages as f64;
+                for _ in 0..std::cmp::min(hour.saturating_sub(self.hour), BASELINE_HOURS) {
+                    self.baseline_hours = std::cmp::min(self.baseline_hours + 1, BASELINE_HOURS);
+                    self.baseline += (count - self.baseline) / self.baseline_hours as f64;
+                    count = 0.0;
+                }
+            }
+            self.hour = hour;
+            self.hourly_messages = 0;
+        }
+    }
+
+    fn add_signal(&mut self, signal: AnomalySignal, timestamp: u64) {
+        self.signals.push(SignalEntry {
+            signal,
+            time: timestamp,
+        });
+    }
+
+    pub fn is_contained(&self, action: AnomalyAction) -> bool {
+        self.containment
+            .as_ref()
+            .map_or(false, |containment| containment.action == action)
+    }
+}
+
+impl AnomalyCore {
+    // Records a successful login and raises a signal when it originates from a
+    // country or network that was not seen before for this account. Logins that
+    // cannot be located through GeoIP do not raise any signal.
+    pub async fn record_login(&self, account: &str, ip: IpAddr, secret: Option<String>) {
+        if !self.config.enable {
+            return;
+        }
+
+        let geo = self.geoip.lookup(ip);
+        let location = geo
+            .country
+            .or_else(|| geo.asn.map(|asn| format!("AS{asn}")));
+        self.record_location(account, location, secret).await;
+    }
+
+    pub async fn record_location(
+        &self,
+        account: &str,
+        location: Option<String>,
+        secret: Option<String>,
+    ) {
+        if !self.config.enable {
+            return;
+        }
+
+        let now = now();
+        let is_new = {
+            let mut activity = self.accounts.entry(account.to_lowercase()).or_default();
+            activity.last_seen = now;
+            if secret.is_some() {
+                activity.secret = secret;
+            }
+            let location = if let Some(location) = &location {
+                location
+            } else {
+                return;
+            };
+            if activity.locations.contains(location) {
+                false
+            } else {
+                // The first login establishes the baseline
+                let is_new = !activity.locations.is_empty();
+                if activity.locations.len() >= self.config.max_locations {
+                    activity.locations.remove(0);
+                }
+                activity.locations.push(location.clone());
+                if is_new {
+                    activity.add_signal(AnomalySignal::NewLocation, now);
+                }
+                is_new
+            }
+        };
+
+        if is_new {
+            tracing::info!(
+                context = "anomaly",
+                event = "new-location",
+                account = account,
+                location = location.as_deref().unwrap_or_default(),
+                "Login from a new location."
+            );
+            self.evaluate(account).await;
+        }
+    }
+
+    // Records a submitted message and raises a signal when the hourly volume
+    // exceeds the account's baseline by the configured factor.
+    pub async fn record_submission(&self, account: &str, sender: &str) {
+        if !self.config.enable {
+            return;
+        }
+
+        let now = now();
+        let account = account.to_lowercase();
+        if !sender.is_empty() {
+            self.senders.insert(sender.to_lowercase(), account.clone());
+        }
+        let is_spike = {
+            let mut activity = self.accounts.entry(account.clone()).or_default();
+            activity.last_seen = now;
+            activity.roll(now);
+            activity.hourly_messages += 1;
+            let threshold = std::cmp::max(
+                self.config.spike_min_messages,
+                (activity.baseline * self.config.spike_factor as f64).ceil() as u64,
+            );
+            if activity.hourly_messages == threshold + 1 {
+                activity.add_signal(AnomalySignal::VolumeSpike, now);
+                true
+            } else {
+                false
+            }
+        };
+
+        if is_spike {
+            tracing::info!(
+                context = "anomaly",
+                event = "volume-spike",
+                account = account,
+                "Outbound volume spike detected."
+            );
+            self.evaluate(&account).await;
+        }
+    }
+
+    // Records the final outcome of the recipients of a message sent by a local
+    // account and raises a signal when the bounce rate exceeds the threshold.
+    pub async fn record_outcomes(&self, message: &mut Message) {
+        if !self.config.enable {
+            return;
+        }
+
+        let mut delivered = 0;
+        let mut bounced = 0;
+        for rcpt in &mut message.recipients {
+            if rcpt.has_flag(RCPT_OUTCOME_RECORDED) {
+                continue;
+            }
+            let is_bounce = match (&rcpt.status, &message.domains[rcpt.domain_idx].status) {
+                (Status::Completed(_), _) => false,
+                (Status::PermanentFailure(_), _)
+                | (Status::Scheduled, Status::PermanentFailure(_)) => true,
+                _ => continue,
+            };
+            rcpt.flags |= RCPT_OUTCOME_RECORDED;
+            if is_bounce {
+                bounced += 1;
+            } else {
+                delivered += 1;
+            }
+        }
+        if delivered + bounced == 0 {
+            return;
+        }
+        let account = if let Some(account) = self.senders.get(&message.return_path_lcase) {
+            account.clone()
+        } else {
+            return;
+        };
+
+        let now = now();
+        let is_high_rate = if let Some(mut activity) = self.accounts.get_mut(&account) {
+            if activity.outcomes_since + self.config.window.as_secs() < now {
+                activity.outcomes_since = now;
+                activity.delivered = 0;
+                activity.bounced = 0;
+            }
+            let was_high_rate = self.is_high_bounce_rate(&activity);
+            activity.delivered += delivered;
+            activity.bounced += bounced;
+            if !was_high_rate && self.is_high_bounce_rate(&activity) {
+                activity.add_signal(AnomalySignal::BounceRate, now);
+                true
+            } else {
+                false
+            }
+        } else {
+            false
+        };
+
+        if is_high_rate {
+            tracing::info!(
+                context = "anomaly",
+                event = "bounce-rate",
+                account = account,
+                "High bounce rate detected."
+            );
+            self.evaluate(&account).await;
+        }
+    }
+
+    fn is_high_bounce_rate(&self, activity: &AccountActivity) -> bool {
+        activity.bounced >= self.config.bounce_min_messages
+            && activity.bounced * 100 / (activity.bounced + activity.delivered)
+                >= self.config.bounce_rate
+    }
+
+    // Flags the account and applies the containment action once enough distinct
+    // signals were raised within the configured window.
+    async fn evaluate(&self, account: &str) {
+        let now = now();
+        let window = self.config.window.as_secs();
+        let signals = {
+            let mut activity = if let Some(activity) = self.accounts.get_mut(account) {
+                activity
+            } else {
+                return;
+            };
+            activity.signals.retain(|entry| entry.time + window >= now);
+            if activity.containment.is_some() {
+                return;
+            }
+            let mut signals = Vec::new();
+            for entry in &activity.signals {
+                if !signals.contains(&entry.signal) {
+                    signals.push(entry.signal);
+                }
+            }
+            if signals.len() < self.config.min_signals {
+                return;
+            }
+            activity.containment = Some(Containment {
+                action: self.config.action,
+                since: now,
+                signals: signals.clone(),
+                secret: activity.secret.clone(),
+            });
+            signals
+        };
+
+        tracing::warn!(
+            context = "anomaly",
+            event = "compromised",
+            account = account,
+            action = self.config.action.as_str(),
+            signals = ?signals,
+            "Account flagged as possibly compromised."
+        );
+        self.webhook.publish(
+            WebhookEventType::AccountCompromised,
+            None,
+            serde_json::json!({
+                "account": account,
+                "action": self.config.action.as_str(),
+                "signals": signals,
+            }),
+        );
+
+        // Sessions opened with the compromised credentials are revoked
+        #[cfg(feature = "local_delivery")]
+        if self.config.action == AnomalyAction::PasswordReset
+            && self
+                .delivery_tx
+                .send(utils::ipc::DeliveryEvent::RevokeSessions {
+                    account: account.to_string(),
+                })
+                .await
+                .is_err()
+        {
+            tracing::warn!(
+                context = "anomaly",
+                event = "error",
+                account = account,
+                "Failed to revoke sessions, delivery channel closed."
+            );
+        }
+    }
+
+    pub fn is_contained(&self, account: &str, action: AnomalyAction) -> bool {
+        self.config.enable
+            && self
+                .accounts
+                .get(&account.to_lowercase())
+                .map_or(false, |activity| activity.is_contained(action))
+    }
+
+    // Returns false while the account is locked pending a password reset. The
+    // containment is lifted once the account's secrets no longer match the ones
+    // that were in use when it was flagged.
+    pub fn is_login_allowed(&self, account: &str, secret: Option<&str>) -> bool {
+        if !self.config.enable {
+            return true;
+        }
+        let account = account.to_lowercase();
+        let was_reset = if let Some(activity) = self.accounts.get(&account) {
+            match &activity.containment {
+                Some(containment) if containment.action == AnomalyAction::PasswordReset => {
+                    match (containment.secret.as_deref(), secret) {
+                        (Some(old_secret), Some(new_secret)) if old_secret != new_secret => true,
+                        _ => return false,
+                    }
+                }
+                _ => return true,
+            }
+        } else {
+            return true;
+        };
+
+        if was_reset {
+            tracing::info!(
+                context = "anomaly",
+                event = "password-reset",
+                account = account,
+                "Password changed for contained account."
+            );
+            self.clear(&account);
+        }
+        true
+    }
+
+    // Returns true if a throttled account is allowed to submit another message
+    pub fn is_submission_allowed(&self, account: &str) -> bool {
+        if !self.config.enable {
+            return true;
+        }
+        let now = now();
+        self.accounts
+            .get_mut(&account.to_lowercase())
+            .map_or(true, |mut activity| {
+                if activity.is_contained(AnomalyAction::Throttle) {
+                    activity.roll(now);
+                    activity.hourly_messages < self.config.throttle_messages
+                } else {
+                    true
+                }
+            })
+    }
+
+    pub fn list(&self) -> Vec<(String, Containment)> {
+        self.accounts
+            .iter()
+            .filter_map(|entry| {
+                entry
+                    .value()
+                    .containment
+                    .as_ref()
+                    .map(|containment| (entry.key().clone(), containment.clone()))
+            })
+            .collect()
+    }
+
+    // Lifts the containment of an account, returns false if it was not contained
+    pub fn clear(&self, account: &str) -> bool {
+        if let Some(mut activity) = self.accounts.get_mut(&account.to_lowercase()) {
+            activity.signals.clear();
+            if activity.containment.take().is_some() {
+                tracing::info!(
+                    context = "anomaly",
+                    event = "cleared",
+                    account = account,
+                    "Account containment lifted."
+                );
+                return true;
+            }
+        }
+        false
+    }
+
+    // Removes accounts that have not been seen within the expiry period,
+    // contained accounts are kept until they are cleared.
+    pub fn cleanup(&self) {
+        let expires = now().saturating_sub(self.config.expiry.as_secs());
+        self.accounts
+            .retain(|_, activity| activity.last_seen > expires || activity.containment.is_some());
+        let accounts = self.accounts.clone();
+        self.senders
+            .retain(|_, account| accounts.contains_key(account.as_str()));
+    }
+
+    pub async fn read_entries(&self) {
+        let path = if let Some(path) = self.config.path.as_ref().filter(|_| self.config.enable) {
+            path
+        } else {
+            return;
+        };
+
+        match fs::read(path).await {
+            Ok(bytes) => match serde_json::from_slice::<Vec<StoredEntry>>(&bytes) {
+                Ok(entries) => {
+                    for entry in entries {
+                        self.accounts.insert(entry.account, entry.activity);
+                    }
+                }
+                Err(err) => {
+                    tracing::error!(
+                        context = "anomaly",
+                        event = "error",
+                        path = %path.display(),
+                        reason = %err,
+                        "Failed to parse account activity entries."
+                    );
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => {
+                tracing::error!(
+                    context = "anomaly",
+                    event = "error",
+                    path = %path.display(),
+                    reason = %err,
+                    "Failed to read account activity entries."
+                );
+            }
+        }
+    }
+
+    pub async fn write_entries(&self) {
+        let path = if let Some(path) = self.config.path.as_ref().filter(|_| self.config.enable) {
+            path
+        } else {
+            return;
+        };
+
+        self.cleanup();
+        let entries = self
+            .accounts
+            .iter()
+            .map(|entry| StoredEntry {
+                account: entry.key().clone(),
+                activity: entry.value().clone(),
+            })
+            .collect::<Vec<_>>();
+        if let Err(err) =
+            write_atomic(path, &serde_json::to_vec(&entries).unwrap_or_default()).await
+        {
+            tracing::error!(
+                context = "anomaly",
+                event = "error",
+                path = %path.display(),
+                reason = %err,
+                "Failed to write account activity entries."
+            );
+        }
+    }
+
+    pub fn flush_frequency(&self) -> Option<Duration> {
+        self.config
+            .path
+            .as_ref()
+            .filter(|_| self.config.enable)
+            .map(|_| self.config.flush_frequency)
+    }
+}
+
+impl AnomalyAction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "none" | "alert" => Some(AnomalyAction::None),
+            "throttle" => Some(AnomalyAction::Throttle),
+            "password-reset" => Some(AnomalyAction::PasswordReset),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnomalyAction::None => "none",
+            AnomalyAction::Throttle => "throttle",
+            AnomalyAction::PasswordReset => "password-reset",
+        }
+    }
+}
+
+// Fingerprint of an account's secrets, used to detect password changes without
+// keeping the secrets themselves.
+pub fn secret_fingerprint(secrets: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    for secret in secrets {
+        hasher.update(secret.as_bytes());
+        hasher.update([0]);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .take(16)
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
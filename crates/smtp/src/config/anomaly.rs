@@ -0,0 +1,74 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use utils::config::Config;
+
+use super::{AnomalyAction, AnomalyConfig};
+
+pub trait ConfigAnomaly {
+    fn parse_anomaly(&self) -> super::Result<AnomalyConfig>;
+}
+
+impl ConfigAnomaly for Config {
+    fn parse_anomaly(&self) -> super::Result<AnomalyConfig> {
+        let action = self.value("anomaly.action").unwrap_or("throttle");
+        let bounce_rate = self.property("anomaly.bounce.rate")?.unwrap_or(30);
+        if !(1..=100).contains(&bounce_rate) {
+            return Err(format!(
+                "Invalid bounce rate {bounce_rate} for property \"anomaly.bounce.rate\", expected a percentage."
+            ));
+        }
+
+        Ok(AnomalyConfig {
+            enable: self.property("anomaly.enable")?.unwrap_or(false),
+            path: self.property("anomaly.path")?,
+            flush_frequency: self
+                .property("anomaly.flush-frequency")?
+                .unwrap_or(Duration::from_secs(60)),
+            expiry: self
+                .property("anomaly.expiry")?
+                .unwrap_or(Duration::from_secs(90 * 86400)),
+            window: self
+                .property("anomaly.window")?
+                .unwrap_or(Duration::from_secs(86400)),
+            min_signals: self
+                .property::<usize>("anomaly.min-signals")?
+                .unwrap_or(2)
+                .max(1),
+            action: AnomalyAction::parse(action).ok_or_else(|| {
+                format!("Invalid containment action {action:?} for property \"anomaly.action\".")
+            })?,
+            throttle_messages: self.property("anomaly.throttle.messages")?.unwrap_or(10),
+            max_locations: self
+                .property::<usize>("anomaly.login.max-locations")?
+                .unwrap_or(20)
+                .max(1),
+            spike_factor: self.property("anomaly.volume.factor")?.unwrap_or(10),
+            spike_min_messages: self.property("anomaly.volume.min-messages")?.unwrap_or(50),
+            bounce_rate,
+            bounce_min_messages: self.property("anomaly.bounce.min-messages")?.unwrap_or(20),
+        })
+    }
+}
@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::{sync::Arc, time::Duration};
+use std::{io::Cursor, sync::Arc, time::Duration};
 
 use mail_auth::{
     common::crypto::{Algorithm, Ed25519Key, HashAlgorithm, RsaKey, Sha256, SigningKey},
@@ -34,7 +34,7 @@ use utils::config::{
 };
 
 use super::{
-    if_block::ConfigIf, ArcAuthConfig, ArcSealer, ConfigContext, DkimAuthConfig,
+    if_block::ConfigIf, ArcAuthConfig, ArcSealer, BimiAuthConfig, ConfigContext, DkimAuthConfig,
     DkimCanonicalization, DkimSigner, DmarcAuthConfig, EnvelopeKey, IfBlock, IpRevAuthConfig,
     MailAuthConfig, SpfAuthConfig, VerifyStrategy,
 };
@@ -106,6 +106,25 @@ impl ConfigAuth for Config {
                     .parse_if_block("auth.iprev.verify", ctx, &envelope_conn_keys)?
                     .unwrap_or_else(|| IfBlock::new(VerifyStrategy::Relaxed)),
             },
+            bimi: BimiAuthConfig {
+                verify: self
+                    .parse_if_block("auth.bimi.verify", ctx, &envelope_sender_keys)?
+                    .unwrap_or_else(|| IfBlock::new(VerifyStrategy::Disable)),
+                trust_anchors: if self.value("auth.bimi.trust-anchors").is_some() {
+                    rustls_pemfile::certs(&mut Cursor::new(
+                        self.file_contents("auth.bimi.trust-anchors")?,
+                    ))
+                    .map_err(|err| {
+                        format!("Failed to read BIMI trust anchors from \"auth.bimi.trust-anchors\": {err}")
+                    })?
+                } else {
+                    vec![]
+                },
+                timeout: self
+                    .property("auth.bimi.timeout")?
+                    .unwrap_or_else(|| Duration::from_secs(10)),
+                max_size: self.property("auth.bimi.max-size")?.unwrap_or(1024 * 1024),
+            },
         })
     }
 
@@ -246,6 +265,20 @@ fn parse_signature<T: SigningKey, U: SigningKey<Hasher = Sha256>>(
         ];
     }
 
+    // Oversigned headers are listed once more than they can appear in the message,
+    // which prevents additional instances from being added after signing
+    for header in config
+        .values(("signature", id, "oversign"))
+        .filter(|(_, v)| !v.is_empty())
+        .map(|(_, v)| v.to_string())
+        .collect::<Vec<_>>()
+    {
+        if !headers.iter().any(|h| h.eq_ignore_ascii_case(&header)) {
+            headers.push(header.clone());
+        }
+        headers.push(header);
+    }
+
     let mut signer = mail_auth::dkim::DkimSigner::from_key(key_dkim)
         .domain(domain)
         .selector(selector)
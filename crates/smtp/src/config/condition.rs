@@ -191,6 +191,9 @@ impl ConfigCondition for Config {
                         | EnvelopeKey::SenderDomain
                         | EnvelopeKey::AuthenticatedAs
                         | EnvelopeKey::Mx
+                        | EnvelopeKey::Reputation
+                        | EnvelopeKey::Country
+                        | EnvelopeKey::Asn
                         | EnvelopeKey::LocalIp
                         | EnvelopeKey::RemoteIp,
                         _,
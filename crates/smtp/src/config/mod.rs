@@ -21,38 +21,56 @@
  * for more details.
 */
 
+pub mod anomaly;
 pub mod auth;
 pub mod condition;
+pub mod geoip;
 pub mod if_block;
+pub mod policy;
 pub mod queue;
 pub mod remote;
+pub mod replay;
 pub mod report;
+pub mod reputation;
 pub mod resolver;
 pub mod scripts;
 pub mod session;
+pub mod suppression;
 pub mod throttle;
+pub mod tracking;
+pub mod transport;
+pub mod unsubscribe;
+pub mod usage;
+pub mod webhook;
 
 use std::{
-    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     path::PathBuf,
     sync::{atomic::AtomicU64, Arc},
     time::Duration,
 };
 
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 use directory::{Directory, DirectoryConfig, Lookup};
 use mail_auth::{
     common::crypto::{Ed25519Key, RsaKey, Sha256},
     dkim::{Canonicalization, Done},
-    IpLookupStrategy,
+    IpLookupStrategy, Resolver, MX,
 };
 use mail_send::Credentials;
 use regex::Regex;
 use sieve::Sieve;
 use smtp_proto::MtPriority;
-use utils::config::{DynValue, Rate, Server, ServerProtocol};
+use utils::{
+    config::{certificate::ClientCertificateMap, DynValue, Rate, Server, ServerProtocol},
+    listener::limiter::ConcurrencyLimiter,
+};
 
-use crate::inbound::milter;
+use crate::{
+    inbound::milter,
+    usage::{UsagePeriod, UsageScope},
+    webhook::WebhookEventType,
+};
 
 #[derive(Debug)]
 pub struct Host {
@@ -150,6 +168,9 @@ pub enum EnvelopeKey {
     RemoteIp,
     LocalIp,
     Priority,
+    Reputation,
+    Country,
+    Asn,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -200,6 +221,9 @@ pub enum IpAddrMask {
 
 pub struct Connect {
     pub script: IfBlock<Option<Arc<Sieve>>>,
+    pub policy: IfBlock<Vec<MaybeDynValue<PolicyServer>>>,
+    pub greeting_delay: IfBlock<Option<Duration>>,
+    pub tarpit: IfBlock<Option<Duration>>,
 }
 
 pub struct Ehlo {
@@ -219,6 +243,9 @@ pub struct Extensions {
     pub future_release: IfBlock<Option<Duration>>,
     pub deliver_by: IfBlock<Option<Duration>>,
     pub mt_priority: IfBlock<Option<MtPriority>>,
+    pub xclient: IfBlock<bool>,
+    pub xforward: IfBlock<bool>,
+    pub limits: IfBlock<bool>,
 }
 
 pub struct Auth {
@@ -226,17 +253,22 @@ pub struct Auth {
     pub mechanisms: IfBlock<u64>,
     pub require: IfBlock<bool>,
     pub allow_plain_text: IfBlock<bool>,
+    pub client_cert: IfBlock<bool>,
+    pub client_cert_require: IfBlock<bool>,
+    pub client_cert_map: ClientCertificateMap,
     pub errors_max: IfBlock<usize>,
     pub errors_wait: IfBlock<Duration>,
 }
 
 pub struct Mail {
     pub script: IfBlock<Option<Arc<Sieve>>>,
+    pub policy: IfBlock<Vec<MaybeDynValue<PolicyServer>>>,
     pub rewrite: IfBlock<Option<DynValue<EnvelopeKey>>>,
 }
 
 pub struct Rcpt {
     pub script: IfBlock<Option<Arc<Sieve>>>,
+    pub policy: IfBlock<Vec<MaybeDynValue<PolicyServer>>>,
     pub relay: IfBlock<bool>,
     pub directory: IfBlock<Option<MaybeDynValue<dyn Directory>>>,
     pub rewrite: IfBlock<Option<DynValue<EnvelopeKey>>>,
@@ -244,28 +276,53 @@ pub struct Rcpt {
     // Errors
     pub errors_max: IfBlock<usize>,
     pub errors_wait: IfBlock<Duration>,
+    pub errors_tarpit: IfBlock<Option<Duration>>,
 
     // Limits
     pub max_recipients: IfBlock<usize>,
+    pub max_message_size: IfBlock<Option<usize>>,
+    pub retry_hint: IfBlock<Option<Duration>>,
 }
 
 pub struct Data {
     pub script: IfBlock<Option<Arc<Sieve>>>,
+    pub shadow_script: IfBlock<Option<Arc<Sieve>>>,
     pub pipe_commands: Vec<Pipe>,
     pub milters: Vec<Milter>,
+    pub filters: Vec<ContentFilter>,
+    pub footer: IfBlock<Option<Arc<Footer>>>,
 
     // Limits
     pub max_messages: IfBlock<usize>,
     pub max_message_size: IfBlock<usize>,
     pub max_received_headers: IfBlock<usize>,
+    pub max_trace_headers: IfBlock<usize>,
 
     // Headers
     pub add_received: IfBlock<bool>,
+    pub received_template: IfBlock<String>,
     pub add_received_spf: IfBlock<bool>,
     pub add_return_path: IfBlock<bool>,
     pub add_auth_results: IfBlock<bool>,
     pub add_message_id: IfBlock<bool>,
     pub add_date: IfBlock<bool>,
+    pub add_list_unsubscribe: IfBlock<bool>,
+
+    // Submission transformations
+    pub scrub_headers: IfBlock<Vec<String>>,
+    pub srs: Srs,
+}
+
+pub struct Srs {
+    pub enable: IfBlock<bool>,
+    pub domain: String,
+    pub keys: Vec<Vec<u8>>,
+}
+
+pub struct Footer {
+    pub id: String,
+    pub text: String,
+    pub html: Option<String>,
 }
 
 pub struct Pipe {
@@ -274,6 +331,43 @@ pub struct Pipe {
     pub timeout: IfBlock<Duration>,
 }
 
+pub struct ContentFilter {
+    pub id: String,
+    pub enable: IfBlock<bool>,
+    pub protocol: FilterProtocol,
+    pub timeout: Duration,
+    pub tempfail_on_error: bool,
+    pub max_response_size: usize,
+    pub deferred: Option<DeferredScanPolicy>,
+}
+
+pub enum FilterProtocol {
+    Command {
+        command: String,
+        arguments: Vec<String>,
+    },
+    Grpc {
+        url: String,
+        client: reqwest::Client,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct DeferredScanPolicy {
+    pub on_reject: DeferredScanAction,
+    pub on_tempfail: DeferredScanAction,
+    pub on_error: DeferredScanAction,
+    pub limiter: ConcurrencyLimiter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeferredScanAction {
+    Deliver,
+    Quarantine,
+    Bounce,
+    Discard,
+}
+
 pub struct Milter {
     pub enable: IfBlock<bool>,
     pub addrs: Vec<SocketAddr>,
@@ -289,6 +383,171 @@ pub struct Milter {
     pub protocol_version: milter::Version,
     pub flags_actions: Option<u32>,
     pub flags_protocol: Option<u32>,
+    pub reuse_connection: bool,
+    pub max_idle_connections: usize,
+    pub connections: parking_lot::Mutex<Vec<milter::MilterClient<tokio::net::TcpStream>>>,
+}
+
+pub struct PolicyServer {
+    pub id: String,
+    pub addrs: Vec<SocketAddr>,
+    pub hostname: String,
+    pub port: u16,
+    pub timeout: Duration,
+    pub tempfail_on_error: bool,
+    pub max_response_size: usize,
+}
+
+pub struct Transport {
+    pub id: String,
+    pub protocol: TransportProtocol,
+    pub timeout: Duration,
+}
+
+pub enum TransportProtocol {
+    Pipe {
+        command: String,
+        arguments: Vec<String>,
+        per_recipient: bool,
+        temporary_codes: Vec<u32>,
+        permanent_codes: Vec<u32>,
+    },
+    Unix {
+        path: PathBuf,
+        is_lmtp: bool,
+    },
+}
+
+pub struct UsageConfig {
+    pub path: Option<PathBuf>,
+    pub flush_frequency: Duration,
+    pub thresholds: Vec<u64>,
+    pub limits: Vec<UsageLimit>,
+}
+
+pub struct AnomalyConfig {
+    pub enable: bool,
+    pub path: Option<PathBuf>,
+    pub flush_frequency: Duration,
+    pub expiry: Duration,
+    pub window: Duration,
+    pub min_signals: usize,
+    pub action: AnomalyAction,
+    pub throttle_messages: u64,
+    pub max_locations: usize,
+    pub spike_factor: u64,
+    pub spike_min_messages: u64,
+    pub bounce_rate: u64,
+    pub bounce_min_messages: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum AnomalyAction {
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "throttle")]
+    Throttle,
+    #[serde(rename = "password-reset")]
+    PasswordReset,
+}
+
+pub struct GeoIpConfig {
+    pub country: Option<PathBuf>,
+    pub asn: Option<PathBuf>,
+    pub reload_frequency: Duration,
+}
+
+pub struct ReputationConfig {
+    pub enable: bool,
+    pub path: Option<PathBuf>,
+    pub flush_frequency: Duration,
+    pub expiry: Duration,
+    pub min_events: u64,
+    pub threshold_good: u64,
+    pub threshold_poor: u64,
+    pub max_entries: usize,
+    pub lookup: Option<ReputationLookup>,
+}
+
+pub struct ReputationLookup {
+    pub get: Arc<Lookup>,
+    pub record: Arc<Lookup>,
+}
+
+pub struct SuppressionConfig {
+    pub enable: bool,
+    pub path: Option<PathBuf>,
+    pub flush_frequency: Duration,
+    pub threshold: u32,
+    pub expiry: Duration,
+    pub lookup: Option<SuppressionLookup>,
+}
+
+pub struct SuppressionLookup {
+    pub get: Arc<Lookup>,
+    pub bounce: Arc<Lookup>,
+    pub set: Arc<Lookup>,
+    pub delete: Arc<Lookup>,
+}
+
+pub struct DkimReplayConfig {
+    pub enable: bool,
+    pub window: Duration,
+    pub max_networks: usize,
+}
+
+pub struct UsageLimit {
+    pub conditions: Conditions,
+    pub scope: UsageScope,
+    pub period: UsagePeriod,
+    pub action: UsageAction,
+    pub messages: Option<u64>,
+    pub recipients: Option<u64>,
+    pub size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageAction {
+    Reject,
+    Defer,
+    Alert,
+}
+
+pub struct DnsOverride {
+    pub id: String,
+    pub domains: Vec<String>,
+    pub mx: Option<Arc<Vec<MX>>>,
+    pub hosts: AHashMap<String, Vec<IpAddr>>,
+    pub resolver: Option<Resolver>,
+}
+
+pub struct TrackingConfig {
+    pub path: Option<PathBuf>,
+    pub retention: Duration,
+}
+
+pub struct UnsubscribeConfig {
+    pub url: String,
+    pub keys: Vec<Vec<u8>>,
+    pub expiry: Duration,
+}
+
+pub struct WebhookConfig {
+    pub path: PathBuf,
+    pub retention: Duration,
+    pub endpoints: Vec<Arc<WebhookEndpoint>>,
+}
+
+pub struct WebhookEndpoint {
+    pub id: String,
+    pub url: String,
+    pub timeout: Duration,
+    pub tls_allow_invalid_certs: bool,
+    pub events: AHashSet<WebhookEventType>,
+    pub headers: Vec<(String, String)>,
+    pub signature_keys: Vec<Vec<u8>>,
+    pub retry: Vec<Duration>,
+    pub expire: Duration,
 }
 
 pub struct SessionConfig {
@@ -296,6 +555,7 @@ pub struct SessionConfig {
     pub duration: IfBlock<Duration>,
     pub transfer_limit: IfBlock<usize>,
     pub throttle: SessionThrottle,
+    pub connections: ConnectionsConfig,
 
     pub connect: Connect,
     pub ehlo: Ehlo,
@@ -306,6 +566,12 @@ pub struct SessionConfig {
     pub extensions: Extensions,
 }
 
+pub struct ConnectionsConfig {
+    pub max_concurrent: u64,
+    pub max_concurrent_per_ip: u64,
+    pub exempt: Vec<IpAddrMask>,
+}
+
 pub struct SessionThrottle {
     pub connect: Vec<Throttle>,
     pub mail_from: Vec<Throttle>,
@@ -327,17 +593,28 @@ pub struct QueueConfig {
 
     // Schedule
     pub retry: IfBlock<Vec<Duration>>,
+    pub retry_class: Vec<(RetryClass, IfBlock<Vec<Duration>>)>,
+    pub retry_jitter: IfBlock<f64>,
+    pub retry_max: IfBlock<Option<Duration>>,
     pub notify: IfBlock<Vec<Duration>>,
     pub expire: IfBlock<Duration>,
+    pub dead_letter: Option<PathBuf>,
 
     // Outbound
     pub hostname: IfBlock<String>,
     pub next_hop: IfBlock<Option<RelayHost>>,
+    pub transport: IfBlock<Option<Arc<Transport>>>,
     pub max_mx: IfBlock<usize>,
+    pub max_rcpt: IfBlock<usize>,
+    pub batch_mx: IfBlock<bool>,
+    pub reject_invalid_mx: IfBlock<bool>,
     pub max_multihomed: IfBlock<usize>,
     pub ip_strategy: IfBlock<IpLookupStrategy>,
     pub source_ip: QueueOutboundSourceIp,
+    pub happy_eyeballs: QueueOutboundHappyEyeballs,
+    pub connection_reuse: QueueOutboundReuse,
     pub tls: QueueOutboundTls,
+    pub arc_seal: IfBlock<Option<MaybeDynValue<ArcSealer>>>,
     pub dsn: Dsn,
 
     // Timeouts
@@ -346,14 +623,49 @@ pub struct QueueConfig {
     // Throttle and Quotas
     pub throttle: QueueThrottle,
     pub quota: QueueQuotas,
+    pub backpressure: QueueBackpressure,
     pub management_lookup: Arc<dyn Directory>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RetryClass {
+    Connection,
+    Greeting,
+    Response,
+    RateLimit,
+    Dns,
+    Tls,
+}
+
+impl RetryClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RetryClass::Connection => "connection",
+            RetryClass::Greeting => "greeting",
+            RetryClass::Response => "response",
+            RetryClass::RateLimit => "rate-limit",
+            RetryClass::Dns => "dns",
+            RetryClass::Tls => "tls",
+        }
+    }
+}
+
 pub struct QueueOutboundSourceIp {
     pub ipv4: IfBlock<Vec<Ipv4Addr>>,
     pub ipv6: IfBlock<Vec<Ipv6Addr>>,
 }
 
+pub struct QueueOutboundHappyEyeballs {
+    pub delay: IfBlock<Option<Duration>>,
+    pub fallback_ttl: Duration,
+}
+
+pub struct QueueOutboundReuse {
+    pub max_messages: IfBlock<usize>,
+    pub max_age: IfBlock<Duration>,
+    pub idle_timeout: Duration,
+}
+
 pub struct ReportConfig {
     pub path: IfBlock<PathBuf>,
     pub hash: IfBlock<u64>,
@@ -393,6 +705,10 @@ pub struct Dsn {
     pub name: IfBlock<String>,
     pub address: IfBlock<String>,
     pub sign: IfBlock<Vec<MaybeDynValue<DkimSigner>>>,
+    pub verbose: IfBlock<bool>,
+    pub rate: IfBlock<Option<Rate>>,
+    pub total_rate: Option<Rate>,
+    pub suppress_auth_failed: IfBlock<bool>,
 }
 
 pub struct AggregateReport {
@@ -415,6 +731,7 @@ pub struct Report {
 
 pub struct QueueOutboundTls {
     pub dane: IfBlock<RequireOptional>,
+    pub dane_failure: IfBlock<DaneFailurePolicy>,
     pub mta_sts: IfBlock<RequireOptional>,
     pub start: IfBlock<RequireOptional>,
     pub invalid_certs: IfBlock<bool>,
@@ -451,6 +768,13 @@ pub struct QueueQuota {
     pub messages: Option<usize>,
 }
 
+#[derive(Debug, Default)]
+pub struct QueueBackpressure {
+    pub max_messages: Option<usize>,
+    pub max_size: Option<usize>,
+    pub max_lag: Option<Duration>,
+}
+
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum AggregateFrequency {
     Hourly,
@@ -467,7 +791,7 @@ pub struct TlsStrategy {
     pub tls: RequireOptional,
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum RequireOptional {
     #[default]
     Optional,
@@ -475,12 +799,21 @@ pub enum RequireOptional {
     Disable,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DaneFailurePolicy {
+    Defer,
+    #[default]
+    Bounce,
+    Deliver,
+}
+
 pub struct MailAuthConfig {
     pub dkim: DkimAuthConfig,
     pub arc: ArcAuthConfig,
     pub spf: SpfAuthConfig,
     pub dmarc: DmarcAuthConfig,
     pub iprev: IpRevAuthConfig,
+    pub bimi: BimiAuthConfig,
 }
 
 pub enum DkimSigner {
@@ -515,6 +848,13 @@ pub struct IpRevAuthConfig {
     pub verify: IfBlock<VerifyStrategy>,
 }
 
+pub struct BimiAuthConfig {
+    pub verify: IfBlock<VerifyStrategy>,
+    pub trust_anchors: Vec<Vec<u8>>,
+    pub timeout: Duration,
+    pub max_size: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct DkimCanonicalization {
     pub headers: Canonicalization,
@@ -534,6 +874,8 @@ pub struct ConfigContext<'x> {
     pub servers: &'x [Server],
     pub hosts: AHashMap<String, Host>,
     pub scripts: AHashMap<String, Arc<Sieve>>,
+    pub policies: AHashMap<String, Arc<PolicyServer>>,
+    pub transports: AHashMap<String, Arc<Transport>>,
     pub directory: DirectoryConfig,
     pub signers: AHashMap<String, Arc<DkimSigner>>,
     pub sealers: AHashMap<String, Arc<ArcSealer>>,
@@ -0,0 +1,63 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::ToSocketAddrs, sync::Arc};
+
+use utils::config::Config;
+
+use super::{ConfigContext, PolicyServer};
+
+pub trait ConfigPolicy {
+    fn parse_policy_servers(&self, ctx: &mut ConfigContext) -> super::Result<()>;
+    fn parse_policy_server(&self, id: &str) -> super::Result<PolicyServer>;
+}
+
+impl ConfigPolicy for Config {
+    fn parse_policy_servers(&self, ctx: &mut ConfigContext) -> super::Result<()> {
+        for id in self.sub_keys("policy") {
+            ctx.policies
+                .insert(id.to_string(), Arc::new(self.parse_policy_server(id)?));
+        }
+
+        Ok(())
+    }
+
+    fn parse_policy_server(&self, id: &str) -> super::Result<PolicyServer> {
+        let hostname = self.value_require(("policy", id, "hostname"))?.to_string();
+        let port = self.property_require(("policy", id, "port"))?;
+        Ok(PolicyServer {
+            id: id.to_string(),
+            addrs: format!("{}:{}", hostname, port)
+                .to_socket_addrs()
+                .map_err(|err| format!("Unable to resolve policy server {hostname}: {err}"))?
+                .collect(),
+            hostname,
+            port,
+            timeout: self.property_or_static(("policy", id, "timeout"), "10s")?,
+            tempfail_on_error: self
+                .property_or_static(("policy", id, "options.tempfail-on-error"), "false")?,
+            max_response_size: self
+                .property_or_static(("policy", id, "options.max-response-size"), "4096")?,
+        })
+    }
+}
@@ -106,6 +106,31 @@ impl ConfigQueue for Config {
                         Duration::from_secs(2 * 3600),
                     ])
                 }),
+            retry_class: [
+                RetryClass::Connection,
+                RetryClass::Greeting,
+                RetryClass::Response,
+                RetryClass::RateLimit,
+                RetryClass::Dns,
+                RetryClass::Tls,
+            ]
+            .into_iter()
+            .filter_map(|class| {
+                self.parse_if_block(
+                    ("queue.schedule.retry-class", class.as_str()),
+                    ctx,
+                    &host_envelope_keys,
+                )
+                .map(|schedule| schedule.map(|schedule| (class, schedule)))
+                .transpose()
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+            retry_jitter: self
+                .parse_if_block("queue.schedule.retry-jitter", ctx, &host_envelope_keys)?
+                .unwrap_or_default(),
+            retry_max: self
+                .parse_if_block("queue.schedule.retry-max", ctx, &host_envelope_keys)?
+                .unwrap_or_default(),
             notify: self
                 .parse_if_block("queue.schedule.notify", ctx, &rcpt_envelope_keys)?
                 .unwrap_or_else(|| {
@@ -117,17 +142,27 @@ impl ConfigQueue for Config {
             expire: self
                 .parse_if_block("queue.schedule.expire", ctx, &rcpt_envelope_keys)?
                 .unwrap_or_else(|| IfBlock::new(Duration::from_secs(5 * 86400))),
+            dead_letter: self.property("queue.dead-letter.path")?,
             hostname: self
                 .parse_if_block("queue.outbound.hostname", ctx, &sender_envelope_keys)?
                 .unwrap_or_else(|| IfBlock::new(default_hostname.to_string())),
             max_mx: self
                 .parse_if_block("queue.outbound.limits.mx", ctx, &rcpt_envelope_keys)?
                 .unwrap_or_else(|| IfBlock::new(5)),
+            max_rcpt: self
+                .parse_if_block("queue.outbound.limits.rcpt", ctx, &mx_envelope_keys)?
+                .unwrap_or_else(|| IfBlock::new(0)),
+            batch_mx: self
+                .parse_if_block("queue.outbound.batch-mx", ctx, &rcpt_envelope_keys)?
+                .unwrap_or_else(|| IfBlock::new(false)),
+            reject_invalid_mx: self
+                .parse_if_block("queue.outbound.reject-invalid-mx", ctx, &rcpt_envelope_keys)?
+                .unwrap_or_else(|| IfBlock::new(true)),
             max_multihomed: self
                 .parse_if_block("queue.outbound.limits.multihomed", ctx, &rcpt_envelope_keys)?
                 .unwrap_or_else(|| IfBlock::new(2)),
             ip_strategy: self
-                .parse_if_block("queue.outbound.ip-strategy", ctx, &sender_envelope_keys)?
+                .parse_if_block("queue.outbound.ip-strategy", ctx, &mx_envelope_keys)?
                 .unwrap_or_else(|| IfBlock::new(IpLookupStrategy::Ipv4thenIpv6)),
             source_ip: QueueOutboundSourceIp {
                 ipv4: self
@@ -137,11 +172,53 @@ impl ConfigQueue for Config {
                     .parse_if_block("queue.outbound.source-ip.v6", ctx, &mx_envelope_keys)?
                     .unwrap_or_else(|| IfBlock::new(Vec::new())),
             },
+            happy_eyeballs: QueueOutboundHappyEyeballs {
+                delay: self
+                    .parse_if_block(
+                        "queue.outbound.happy-eyeballs.delay",
+                        ctx,
+                        &mx_envelope_keys,
+                    )?
+                    .unwrap_or_else(|| IfBlock::new(Some(Duration::from_millis(250)))),
+                fallback_ttl: self
+                    .property("queue.outbound.happy-eyeballs.fallback-ttl")?
+                    .unwrap_or_else(|| Duration::from_secs(30 * 60)),
+            },
+            connection_reuse: QueueOutboundReuse {
+                max_messages: self
+                    .parse_if_block(
+                        "queue.outbound.connection-reuse.max-messages",
+                        ctx,
+                        &host_envelope_keys,
+                    )?
+                    .unwrap_or_else(|| IfBlock::new(10)),
+                max_age: self
+                    .parse_if_block(
+                        "queue.outbound.connection-reuse.max-age",
+                        ctx,
+                        &host_envelope_keys,
+                    )?
+                    .unwrap_or_else(|| IfBlock::new(Duration::from_secs(5 * 60))),
+                idle_timeout: self
+                    .property("queue.outbound.connection-reuse.idle-timeout")?
+                    .unwrap_or_else(|| Duration::from_secs(30)),
+            },
             next_hop: next_hop.into_relay_host(ctx)?,
+            transport: self
+                .parse_if_block::<Option<String>>(
+                    "queue.outbound.transport",
+                    ctx,
+                    &rcpt_envelope_keys,
+                )?
+                .unwrap_or_default()
+                .map_if_block(&ctx.transports, "queue.outbound.transport", "transport")?,
             tls: QueueOutboundTls {
                 dane: self
                     .parse_if_block("queue.outbound.tls.dane", ctx, &mx_envelope_keys)?
                     .unwrap_or_else(|| IfBlock::new(RequireOptional::Optional)),
+                dane_failure: self
+                    .parse_if_block("queue.outbound.tls.dane-failure", ctx, &mx_envelope_keys)?
+                    .unwrap_or_else(|| IfBlock::new(DaneFailurePolicy::Bounce)),
                 mta_sts: self
                     .parse_if_block("queue.outbound.tls.mta-sts", ctx, &rcpt_envelope_keys)?
                     .unwrap_or_else(|| IfBlock::new(RequireOptional::Optional)),
@@ -156,8 +233,21 @@ impl ConfigQueue for Config {
                     )?
                     .unwrap_or_else(|| IfBlock::new(false)),
             },
+            arc_seal: self
+                .parse_if_block::<Option<DynValue<EnvelopeKey>>>(
+                    "queue.outbound.arc.seal",
+                    ctx,
+                    &rcpt_envelope_keys,
+                )?
+                .unwrap_or_default()
+                .map_if_block(&ctx.sealers, "queue.outbound.arc.seal", "signature")?,
             throttle: self.parse_queue_throttle(ctx)?,
             quota: self.parse_queue_quota(ctx)?,
+            backpressure: QueueBackpressure {
+                max_messages: self.property("queue.backpressure.max-messages")?,
+                max_size: self.property("queue.backpressure.max-size")?,
+                max_lag: self.property("queue.backpressure.max-lag")?,
+            },
             timeout: QueueOutboundTimeout {
                 connect: self
                     .parse_if_block("queue.outbound.timeouts.connect", ctx, &host_envelope_keys)?
@@ -203,6 +293,20 @@ impl ConfigQueue for Config {
                     )?
                     .unwrap_or_default()
                     .map_if_block(&ctx.signers, "report.dsn.sign", "signature")?,
+                verbose: self
+                    .parse_if_block("report.dsn.verbose", ctx, &sender_envelope_keys)?
+                    .unwrap_or_else(|| IfBlock::new(false)),
+                rate: self
+                    .parse_if_block("report.dsn.limits.rate", ctx, &sender_envelope_keys)?
+                    .unwrap_or_default(),
+                total_rate: self.property("report.dsn.limits.total-rate")?,
+                suppress_auth_failed: self
+                    .parse_if_block(
+                        "report.dsn.suppress-auth-failed",
+                        ctx,
+                        &sender_envelope_keys,
+                    )?
+                    .unwrap_or_else(|| IfBlock::new(false)),
             },
             management_lookup: if let Some(id) = self.value("management.directory") {
                 ctx.directory
@@ -219,6 +323,26 @@ impl ConfigQueue for Config {
 
         if config.retry.has_empty_list() {
             Err("Property \"queue.schedule.retry\" cannot contain empty lists.".to_string())
+        } else if let Some((class, _)) = config
+            .retry_class
+            .iter()
+            .find(|(_, schedule)| schedule.has_empty_list())
+        {
+            Err(format!(
+                "Property \"queue.schedule.retry-class.{}\" cannot contain empty lists.",
+                class.as_str()
+            ))
+        } else if std::iter::once(&config.retry_jitter.default)
+            .chain(
+                config
+                    .retry_jitter
+                    .if_then
+                    .iter()
+                    .map(|if_then| &if_then.then),
+            )
+            .any(|jitter| !(0.0..=1.0).contains(jitter))
+        {
+            Err("Property \"queue.schedule.retry-jitter\" must be between 0 and 1.".to_string())
         } else if config.notify.has_empty_list() {
             Err("Property \"queue.schedule.notify\" cannot contain empty lists.".to_string())
         } else {
@@ -454,6 +578,21 @@ impl From<&Host> for RelayHost {
     }
 }
 
+impl ParseValue for DaneFailurePolicy {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "defer" => Ok(DaneFailurePolicy::Defer),
+            "bounce" => Ok(DaneFailurePolicy::Bounce),
+            "deliver" | "deliver-unauthenticated" => Ok(DaneFailurePolicy::Deliver),
+            _ => Err(format!(
+                "Invalid DANE failure policy {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
+}
+
 impl ParseValue for RequireOptional {
     fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
         match value {
@@ -0,0 +1,47 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use utils::config::Config;
+
+use super::DkimReplayConfig;
+
+pub trait ConfigDkimReplay {
+    fn parse_dkim_replay(&self) -> super::Result<DkimReplayConfig>;
+}
+
+impl ConfigDkimReplay for Config {
+    fn parse_dkim_replay(&self) -> super::Result<DkimReplayConfig> {
+        Ok(DkimReplayConfig {
+            enable: self.property("auth.dkim.replay.enable")?.unwrap_or(false),
+            window: self
+                .property("auth.dkim.replay.window")?
+                .unwrap_or(Duration::from_secs(3600)),
+            max_networks: self
+                .property::<usize>("auth.dkim.replay.max-networks")?
+                .unwrap_or(3)
+                .max(1),
+        })
+    }
+}
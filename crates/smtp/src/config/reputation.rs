@@ -0,0 +1,88 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use utils::config::{utils::AsKey, Config};
+
+use super::{ConfigContext, ReputationConfig, ReputationLookup};
+
+pub trait ConfigReputation {
+    fn parse_reputation(&self, ctx: &ConfigContext) -> super::Result<ReputationConfig>;
+}
+
+impl ConfigReputation for Config {
+    fn parse_reputation(&self, ctx: &ConfigContext) -> super::Result<ReputationConfig> {
+        let threshold_good = self
+            .property("session.reputation.threshold.good")?
+            .unwrap_or(10);
+        let threshold_poor = self
+            .property("session.reputation.threshold.poor")?
+            .unwrap_or(50);
+        if threshold_good > threshold_poor || threshold_poor > 100 {
+            return Err(format!(
+                concat!(
+                    "Invalid reputation thresholds good={} and poor={}, ",
+                    "expected percentages where 'good' is not above 'poor'."
+                ),
+                threshold_good, threshold_poor
+            ));
+        }
+
+        // Scores are kept in a database shared by all nodes when lookups are configured
+        let lookup = if self.value("session.reputation.lookup.get").is_some() {
+            let lookup = |name: &str| {
+                let key = ("session.reputation.lookup", name);
+                let id = self.value_require(key)?;
+                ctx.directory.lookups.get(id).cloned().ok_or_else(|| {
+                    format!("Lookup {id:?} not found for property {:?}.", key.as_key())
+                })
+            };
+            Some(ReputationLookup {
+                get: lookup("get")?,
+                record: lookup("record")?,
+            })
+        } else {
+            None
+        };
+
+        Ok(ReputationConfig {
+            enable: self.property("session.reputation.enable")?.unwrap_or(false),
+            path: self.property("session.reputation.path")?,
+            flush_frequency: self
+                .property("session.reputation.flush-frequency")?
+                .unwrap_or(Duration::from_secs(60)),
+            expiry: self
+                .property("session.reputation.expiry")?
+                .unwrap_or(Duration::from_secs(30 * 86400)),
+            min_events: self.property("session.reputation.min-events")?.unwrap_or(5),
+            threshold_good,
+            threshold_poor,
+            max_entries: self
+                .property("session.reputation.max-entries")?
+                .unwrap_or(100_000)
+                .max(1),
+            lookup,
+        })
+    }
+}
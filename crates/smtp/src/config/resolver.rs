@@ -21,23 +21,32 @@
  * for more details.
 */
 
-use std::io::Read;
+use std::{
+    io::Read,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 
+use ahash::AHashMap;
 use mail_auth::{
     common::lru::{DnsCache, LruCache},
     flate2::read::GzDecoder,
     hickory_resolver::{
-        config::{ResolverConfig, ResolverOpts},
+        config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
         system_conf::read_system_conf,
     },
-    Resolver,
+    Resolver, MX,
 };
 
 use crate::{core::Resolvers, outbound::dane::DnssecResolver};
 use utils::{config::Config, suffixlist::PublicSuffix};
 
+use super::DnsOverride;
+
 pub trait ConfigResolver {
     fn build_resolvers(&self) -> super::Result<Resolvers>;
+    fn parse_dns_overrides(&self, opts: &ResolverOpts) -> super::Result<Vec<DnsOverride>>;
+    fn parse_dns_override(&self, id: &str, opts: &ResolverOpts) -> super::Result<DnsOverride>;
     fn parse_public_suffix(&self) -> super::Result<PublicSuffix>;
 }
 
@@ -81,6 +90,8 @@ impl ConfigResolver for Config {
             }
         }
 
+        let overrides = self.parse_dns_overrides(&opts)?;
+
         Ok(Resolvers {
             dns: Resolver::with_capacities(
                 config,
@@ -92,8 +103,11 @@ impl ConfigResolver for Config {
                 capacities[4],
             )
             .map_err(|err| format!("Failed to build DNS resolver: {err}"))?,
-            dnssec: DnssecResolver::with_capacity(config_dnssec, opts_dnssec)
-                .map_err(|err| format!("Failed to build DNSSEC resolver: {err}"))?,
+            dnssec: DnssecResolver {
+                enabled: self.property_or_static("resolver.dnssec.enable", "true")?,
+                ..DnssecResolver::with_capacity(config_dnssec, opts_dnssec)
+                    .map_err(|err| format!("Failed to build DNSSEC resolver: {err}"))?
+            },
             cache: crate::core::DnsCache {
                 tlsa: LruCache::with_capacity(
                     self.property("resolver.cache.tlsa")?.unwrap_or(1024),
@@ -101,7 +115,108 @@ impl ConfigResolver for Config {
                 mta_sts: LruCache::with_capacity(
                     self.property("resolver.cache.mta-sts")?.unwrap_or(1024),
                 ),
+                bimi: LruCache::with_capacity(
+                    self.property("resolver.cache.bimi")?.unwrap_or(1024),
+                ),
+            },
+            overrides,
+        })
+    }
+
+    fn parse_dns_overrides(&self, opts: &ResolverOpts) -> super::Result<Vec<DnsOverride>> {
+        let mut overrides = Vec::new();
+        for id in self.sub_keys("resolver.override") {
+            overrides.push(self.parse_dns_override(id, opts)?);
+        }
+        Ok(overrides)
+    }
+
+    fn parse_dns_override(&self, id: &str, opts: &ResolverOpts) -> super::Result<DnsOverride> {
+        // Wildcards match any subdomain
+        let mut domains = Vec::new();
+        for (_, domain) in self.values(("resolver.override", id, "domains")) {
+            let domain = domain.trim().trim_end_matches('.').to_lowercase();
+            domains.push(
+                domain
+                    .strip_prefix('*')
+                    .map(|domain| domain.to_string())
+                    .unwrap_or(domain),
+            );
+        }
+
+        // Static MX hosts are tried in the order they are listed
+        let mut mx = Vec::new();
+        for (pos, (_, host)) in self.values(("resolver.override", id, "mx")).enumerate() {
+            mx.push(MX {
+                exchanges: vec![host.trim().trim_end_matches('.').to_lowercase()],
+                preference: (pos as u16 + 1) * 10,
+            });
+        }
+
+        // Hosts entries are read from the configuration and an optional file
+        let mut hosts = AHashMap::new();
+        for (key, line) in self.values(("resolver.override", id, "hosts")) {
+            parse_hosts_line(line, &mut hosts).map_err(|err| {
+                format!("Invalid hosts entry {line:?} for property {key:?}: {err}")
+            })?;
+        }
+        if let Some(file) = self.value(("resolver.override", id, "hosts-file")) {
+            let contents = std::fs::read_to_string(file)
+                .map_err(|err| format!("Failed to read hosts file {file:?}: {err}"))?;
+            for line in contents.lines() {
+                parse_hosts_line(line, &mut hosts)
+                    .map_err(|err| format!("Invalid hosts entry {line:?} in {file:?}: {err}"))?;
+            }
+        }
+
+        // Build a dedicated resolver for the listed name servers
+        let mut name_servers = NameServerConfigGroup::new();
+        for (key, addr) in self.values(("resolver.override", id, "nameservers")) {
+            let addr = addr
+                .parse::<SocketAddr>()
+                .or_else(|_| addr.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                .map_err(|_| format!("Invalid name server {addr:?} for property {key:?}."))?;
+            name_servers.merge(NameServerConfigGroup::from_ips_clear(
+                &[addr.ip()],
+                addr.port(),
+                true,
+            ));
+        }
+        let resolver = if !name_servers.is_empty() {
+            let capacity = self
+                .property(("resolver.override", id, "cache"))?
+                .unwrap_or(128);
+            Resolver::with_capacities(
+                ResolverConfig::from_parts(None, vec![], name_servers),
+                opts.clone(),
+                capacity,
+                capacity,
+                capacity,
+                capacity,
+                capacity,
+            )
+            .map_err(|err| format!("Failed to build DNS resolver for override {id:?}: {err}"))?
+            .into()
+        } else {
+            None
+        };
+
+        if domains.is_empty() && (!mx.is_empty() || resolver.is_some()) {
+            return Err(format!(
+                "DNS override {id:?} requires a list of domains to apply to."
+            ));
+        }
+
+        Ok(DnsOverride {
+            id: id.to_string(),
+            domains,
+            mx: if !mx.is_empty() {
+                Arc::new(mx).into()
+            } else {
+                None
             },
+            hosts,
+            resolver,
         })
     }
 
@@ -194,3 +309,28 @@ impl ConfigResolver for Config {
         Ok(PublicSuffix::default())
     }
 }
+
+// Parses a line in /etc/hosts format, e.g. "10.0.0.1 mx.example.org mx"
+fn parse_hosts_line(line: &str, hosts: &mut AHashMap<String, Vec<IpAddr>>) -> Result<(), String> {
+    let line = line.split('#').next().unwrap_or_default();
+    let mut parts = line.split_whitespace();
+    if let Some(ip) = parts.next() {
+        let ip = ip
+            .parse::<IpAddr>()
+            .map_err(|_| format!("invalid IP address {ip:?}"))?;
+        let mut has_names = false;
+        for name in parts {
+            let ips = hosts
+                .entry(name.trim_end_matches('.').to_lowercase())
+                .or_insert_with(Vec::new);
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+            has_names = true;
+        }
+        if !has_names {
+            return Err("missing host name".to_string());
+        }
+    }
+    Ok(())
+}
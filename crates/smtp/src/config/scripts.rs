@@ -188,6 +188,7 @@ impl ConfigSieve for Config {
             runtime,
             scripts: ctx.scripts.clone(),
             lookup: ctx.directory.lookups.clone(),
+            shadow: Default::default(),
             config: SieveConfig {
                 from_addr: self
                     .value("sieve.trusted.from-addr")
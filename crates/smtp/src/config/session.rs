@@ -31,9 +31,16 @@ use utils::config::{
     Config, DynValue,
 };
 
+// The authenticated identity is only disclosed when the template includes it
+pub const DEFAULT_RECEIVED_TEMPLATE: &str = concat!(
+    "from {{helo}} ({{ptr}} [{{ip}}]) {{tls}} {{client-cert}} ",
+    "by {{hostname}} (Stalwart SMTP) with {{protocol}} id {{id}}; {{date}}"
+);
+
 pub trait ConfigSession {
     fn parse_session_config(&self, ctx: &ConfigContext) -> super::Result<SessionConfig>;
     fn parse_session_throttle(&self, ctx: &ConfigContext) -> super::Result<SessionThrottle>;
+    fn parse_session_connections(&self) -> super::Result<ConnectionsConfig>;
     fn parse_session_connect(&self, ctx: &ConfigContext) -> super::Result<Connect>;
     fn parse_extensions(&self, ctx: &ConfigContext) -> super::Result<Extensions>;
     fn parse_session_ehlo(&self, ctx: &ConfigContext) -> super::Result<Ehlo>;
@@ -41,6 +48,7 @@ pub trait ConfigSession {
     fn parse_session_mail(&self, ctx: &ConfigContext) -> super::Result<Mail>;
     fn parse_session_rcpt(&self, ctx: &ConfigContext) -> super::Result<Rcpt>;
     fn parse_session_data(&self, ctx: &ConfigContext) -> super::Result<Data>;
+    fn parse_srs(&self, ctx: &ConfigContext, available_keys: &[EnvelopeKey]) -> super::Result<Srs>;
     fn parse_pipes(
         &self,
         ctx: &ConfigContext,
@@ -51,6 +59,12 @@ pub trait ConfigSession {
         ctx: &ConfigContext,
         available_keys: &[EnvelopeKey],
     ) -> super::Result<Vec<Milter>>;
+    fn parse_content_filters(
+        &self,
+        ctx: &ConfigContext,
+        available_keys: &[EnvelopeKey],
+    ) -> super::Result<Vec<ContentFilter>>;
+    fn parse_footers(&self) -> super::Result<AHashMap<String, Arc<Footer>>>;
 }
 
 impl ConfigSession for Config {
@@ -74,6 +88,7 @@ impl ConfigSession for Config {
                 .try_unwrap("session.timeout")
                 .unwrap_or_else(|_| IfBlock::new(Duration::from_secs(5 * 60))),
             throttle: self.parse_session_throttle(ctx)?,
+            connections: self.parse_session_connections()?,
             connect: self.parse_session_connect(ctx)?,
             ehlo: self.parse_session_ehlo(ctx)?,
             auth: self.parse_session_auth(ctx)?,
@@ -157,17 +172,47 @@ impl ConfigSession for Config {
         Ok(throttle)
     }
 
+    fn parse_session_connections(&self) -> super::Result<ConnectionsConfig> {
+        Ok(ConnectionsConfig {
+            max_concurrent: self.property("session.connections.max")?.unwrap_or(0),
+            max_concurrent_per_ip: self
+                .property("session.connections.max-per-ip")?
+                .unwrap_or(0),
+            exempt: self
+                .values("session.connections.exempt")
+                .map(|(key, value)| IpAddrMask::parse_value(key, value))
+                .collect::<super::Result<Vec<_>>>()?,
+        })
+    }
+
     fn parse_session_connect(&self, ctx: &ConfigContext) -> super::Result<Connect> {
         let available_keys = [
             EnvelopeKey::Listener,
             EnvelopeKey::RemoteIp,
             EnvelopeKey::LocalIp,
+            EnvelopeKey::Reputation,
+            EnvelopeKey::Country,
+            EnvelopeKey::Asn,
         ];
         Ok(Connect {
             script: self
                 .parse_if_block::<Option<String>>("session.connect.script", ctx, &available_keys)?
                 .unwrap_or_default()
                 .map_if_block(&ctx.scripts, "session.connect.script", "script")?,
+            policy: self
+                .parse_if_block::<Vec<DynValue<EnvelopeKey>>>(
+                    "session.connect.policy",
+                    ctx,
+                    &available_keys,
+                )?
+                .unwrap_or_default()
+                .map_if_block(&ctx.policies, "session.connect.policy", "policy")?,
+            greeting_delay: self
+                .parse_if_block("session.connect.greeting-delay", ctx, &available_keys)?
+                .unwrap_or_default(),
+            tarpit: self
+                .parse_if_block("session.connect.tarpit", ctx, &available_keys)?
+                .unwrap_or_default(),
         })
     }
 
@@ -176,6 +221,9 @@ impl ConfigSession for Config {
             EnvelopeKey::Listener,
             EnvelopeKey::RemoteIp,
             EnvelopeKey::LocalIp,
+            EnvelopeKey::Reputation,
+            EnvelopeKey::Country,
+            EnvelopeKey::Asn,
             EnvelopeKey::Sender,
             EnvelopeKey::SenderDomain,
             EnvelopeKey::AuthenticatedAs,
@@ -212,6 +260,15 @@ impl ConfigSession for Config {
             mt_priority: self
                 .parse_if_block("session.extensions.mt-priority", ctx, &available_keys)?
                 .unwrap_or_default(),
+            xclient: self
+                .parse_if_block("session.extensions.xclient", ctx, &available_keys)?
+                .unwrap_or_default(),
+            xforward: self
+                .parse_if_block("session.extensions.xforward", ctx, &available_keys)?
+                .unwrap_or_default(),
+            limits: self
+                .parse_if_block("session.extensions.limits", ctx, &available_keys)?
+                .unwrap_or_default(),
         })
     }
 
@@ -220,6 +277,9 @@ impl ConfigSession for Config {
             EnvelopeKey::Listener,
             EnvelopeKey::RemoteIp,
             EnvelopeKey::LocalIp,
+            EnvelopeKey::Reputation,
+            EnvelopeKey::Country,
+            EnvelopeKey::Asn,
         ];
 
         Ok(Ehlo {
@@ -241,6 +301,9 @@ impl ConfigSession for Config {
             EnvelopeKey::Listener,
             EnvelopeKey::RemoteIp,
             EnvelopeKey::LocalIp,
+            EnvelopeKey::Reputation,
+            EnvelopeKey::Country,
+            EnvelopeKey::Asn,
             EnvelopeKey::HeloDomain,
         ];
 
@@ -287,6 +350,13 @@ impl ConfigSession for Config {
             allow_plain_text: self
                 .parse_if_block("session.auth.allow-plain-text", ctx, &available_keys)?
                 .unwrap_or_else(|| IfBlock::new(false)),
+            client_cert: self
+                .parse_if_block("session.auth.client-cert.enable", ctx, &available_keys)?
+                .unwrap_or_else(|| IfBlock::new(false)),
+            client_cert_require: self
+                .parse_if_block("session.auth.client-cert.require", ctx, &available_keys)?
+                .unwrap_or_else(|| IfBlock::new(false)),
+            client_cert_map: self.parse_client_certificate_map()?,
         })
     }
 
@@ -296,6 +366,9 @@ impl ConfigSession for Config {
             EnvelopeKey::Listener,
             EnvelopeKey::RemoteIp,
             EnvelopeKey::LocalIp,
+            EnvelopeKey::Reputation,
+            EnvelopeKey::Country,
+            EnvelopeKey::Asn,
             EnvelopeKey::HeloDomain,
             EnvelopeKey::Sender,
             EnvelopeKey::SenderDomain,
@@ -305,6 +378,14 @@ impl ConfigSession for Config {
                 .parse_if_block::<Option<String>>("session.mail.script", ctx, &available_keys)?
                 .unwrap_or_default()
                 .map_if_block(&ctx.scripts, "session.mail.script", "script")?,
+            policy: self
+                .parse_if_block::<Vec<DynValue<EnvelopeKey>>>(
+                    "session.mail.policy",
+                    ctx,
+                    &available_keys,
+                )?
+                .unwrap_or_default()
+                .map_if_block(&ctx.policies, "session.mail.policy", "policy")?,
             rewrite: self
                 .parse_if_block::<Option<DynValue<EnvelopeKey>>>(
                     "session.mail.rewrite",
@@ -323,6 +404,9 @@ impl ConfigSession for Config {
             EnvelopeKey::Listener,
             EnvelopeKey::RemoteIp,
             EnvelopeKey::LocalIp,
+            EnvelopeKey::Reputation,
+            EnvelopeKey::Country,
+            EnvelopeKey::Asn,
             EnvelopeKey::HeloDomain,
         ];
         let available_keys_full = [
@@ -334,6 +418,9 @@ impl ConfigSession for Config {
             EnvelopeKey::Listener,
             EnvelopeKey::RemoteIp,
             EnvelopeKey::LocalIp,
+            EnvelopeKey::Reputation,
+            EnvelopeKey::Country,
+            EnvelopeKey::Asn,
             EnvelopeKey::HeloDomain,
         ];
         Ok(Rcpt {
@@ -341,6 +428,14 @@ impl ConfigSession for Config {
                 .parse_if_block::<Option<String>>("session.rcpt.script", ctx, &available_keys_full)?
                 .unwrap_or_default()
                 .map_if_block(&ctx.scripts, "session.rcpt.script", "script")?,
+            policy: self
+                .parse_if_block::<Vec<DynValue<EnvelopeKey>>>(
+                    "session.rcpt.policy",
+                    ctx,
+                    &available_keys_full,
+                )?
+                .unwrap_or_default()
+                .map_if_block(&ctx.policies, "session.rcpt.policy", "policy")?,
             relay: self
                 .parse_if_block("session.rcpt.relay", ctx, &available_keys_full)?
                 .unwrap_or_else(|| IfBlock::new(false)),
@@ -362,9 +457,18 @@ impl ConfigSession for Config {
             errors_wait: self
                 .parse_if_block("session.rcpt.errors.wait", ctx, &available_keys)?
                 .unwrap_or_else(|| IfBlock::new(Duration::from_secs(30))),
+            errors_tarpit: self
+                .parse_if_block("session.rcpt.errors.tarpit", ctx, &available_keys)?
+                .unwrap_or_default(),
             max_recipients: self
                 .parse_if_block("session.rcpt.max-recipients", ctx, &available_keys)?
                 .unwrap_or_else(|| IfBlock::new(100)),
+            max_message_size: self
+                .parse_if_block("session.rcpt.max-message-size", ctx, &available_keys_full)?
+                .unwrap_or_default(),
+            retry_hint: self
+                .parse_if_block("session.rcpt.retry-hint", ctx, &available_keys)?
+                .unwrap_or_default(),
             rewrite: self
                 .parse_if_block::<Option<DynValue<EnvelopeKey>>>(
                     "session.rcpt.rewrite",
@@ -383,6 +487,9 @@ impl ConfigSession for Config {
             EnvelopeKey::Listener,
             EnvelopeKey::RemoteIp,
             EnvelopeKey::LocalIp,
+            EnvelopeKey::Reputation,
+            EnvelopeKey::Country,
+            EnvelopeKey::Asn,
             EnvelopeKey::Priority,
             EnvelopeKey::HeloDomain,
         ];
@@ -391,6 +498,14 @@ impl ConfigSession for Config {
                 .parse_if_block::<Option<String>>("session.data.script", ctx, &available_keys)?
                 .unwrap_or_default()
                 .map_if_block(&ctx.scripts, "session.data.script", "script")?,
+            shadow_script: self
+                .parse_if_block::<Option<String>>(
+                    "session.data.shadow-script",
+                    ctx,
+                    &available_keys,
+                )?
+                .unwrap_or_default()
+                .map_if_block(&ctx.scripts, "session.data.shadow-script", "script")?,
             max_messages: self
                 .parse_if_block("session.data.limits.messages", ctx, &available_keys)?
                 .unwrap_or_else(|| IfBlock::new(10)),
@@ -400,9 +515,15 @@ impl ConfigSession for Config {
             max_received_headers: self
                 .parse_if_block("session.data.limits.received-headers", ctx, &available_keys)?
                 .unwrap_or_else(|| IfBlock::new(50)),
+            max_trace_headers: self
+                .parse_if_block("session.data.limits.trace-headers", ctx, &available_keys)?
+                .unwrap_or_default(),
             add_received: self
                 .parse_if_block("session.data.add-headers.received", ctx, &available_keys)?
                 .unwrap_or_else(|| IfBlock::new(true)),
+            received_template: self
+                .parse_if_block("session.data.received.template", ctx, &available_keys)?
+                .unwrap_or_else(|| IfBlock::new(DEFAULT_RECEIVED_TEMPLATE.to_string())),
             add_received_spf: self
                 .parse_if_block(
                     "session.data.add-headers.received-spf",
@@ -426,8 +547,50 @@ impl ConfigSession for Config {
             add_date: self
                 .parse_if_block("session.data.add-headers.date", ctx, &available_keys)?
                 .unwrap_or_else(|| IfBlock::new(true)),
+            add_list_unsubscribe: self
+                .parse_if_block(
+                    "session.data.add-headers.list-unsubscribe",
+                    ctx,
+                    &available_keys,
+                )?
+                .unwrap_or_else(|| IfBlock::new(false)),
+            scrub_headers: self
+                .parse_if_block("session.data.scrub-headers", ctx, &available_keys)?
+                .unwrap_or_default(),
+            srs: self.parse_srs(ctx, &available_keys)?,
             pipe_commands: self.parse_pipes(ctx, &available_keys)?,
             milters: self.parse_milters(ctx, &available_keys)?,
+            filters: self.parse_content_filters(ctx, &available_keys)?,
+            footer: self
+                .parse_if_block::<Option<String>>("session.data.footer", ctx, &available_keys)?
+                .unwrap_or_default()
+                .map_if_block(&self.parse_footers()?, "session.data.footer", "footer")?,
+        })
+    }
+
+    fn parse_srs(&self, ctx: &ConfigContext, available_keys: &[EnvelopeKey]) -> super::Result<Srs> {
+        let keys = self
+            .values("session.data.srs.keys")
+            .map(|(_, key)| key.as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let domain = self
+            .value("session.data.srs.domain")
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        if !keys.is_empty() && domain.is_empty() {
+            return Err(
+                "A domain is required for the Sender Rewriting Scheme (session.data.srs.domain)."
+                    .to_string(),
+            );
+        }
+
+        Ok(Srs {
+            enable: self
+                .parse_if_block("session.data.srs.enable", ctx, available_keys)?
+                .unwrap_or_else(|| IfBlock::new(false)),
+            domain,
+            keys,
         })
     }
 
@@ -511,10 +674,150 @@ impl ConfigSession for Config {
                     id,
                     "options.flags.protocol",
                 ))?,
+                reuse_connection: self.property_or_static(
+                    ("session.data.milter", id, "options.reuse-connection"),
+                    "false",
+                )?,
+                max_idle_connections: self.property_or_static(
+                    ("session.data.milter", id, "options.max-idle-connections"),
+                    "16",
+                )?,
+                connections: parking_lot::Mutex::new(Vec::new()),
             })
         }
         Ok(milters)
     }
+
+    fn parse_content_filters(
+        &self,
+        ctx: &ConfigContext,
+        available_keys: &[EnvelopeKey],
+    ) -> super::Result<Vec<ContentFilter>> {
+        let mut filters = Vec::new();
+        for id in self.sub_keys("session.data.filter") {
+            let timeout = self.property_or_static(("session.data.filter", id, "timeout"), "30s")?;
+            let protocol = match self.value_require(("session.data.filter", id, "type"))? {
+                "command" => FilterProtocol::Command {
+                    command: self
+                        .value_require(("session.data.filter", id, "command"))?
+                        .to_string(),
+                    arguments: self
+                        .values(("session.data.filter", id, "arguments"))
+                        .map(|(_, v)| v.to_string())
+                        .collect(),
+                },
+                "grpc" => {
+                    let url = self
+                        .value_require(("session.data.filter", id, "url"))?
+                        .trim_end_matches('/');
+                    let method = self
+                        .value(("session.data.filter", id, "method"))
+                        .unwrap_or("/mailfilter.v1.MessageFilter/Filter");
+                    FilterProtocol::Grpc {
+                        url: format!("{url}{method}"),
+                        client: reqwest::Client::builder()
+                            .user_agent(crate::USER_AGENT)
+                            .timeout(timeout)
+                            .http2_prior_knowledge()
+                            .danger_accept_invalid_certs(self.property_or_static(
+                                ("session.data.filter", id, "allow-invalid-certs"),
+                                "false",
+                            )?)
+                            .build()
+                            .map_err(|err| {
+                                format!("Failed to build gRPC client for filter {id:?}: {err}")
+                            })?,
+                    }
+                }
+                protocol => {
+                    return Err(format!(
+                        "Invalid content filter type {protocol:?} for filter {id:?}, expected 'command' or 'grpc'."
+                    ))
+                }
+            };
+
+            filters.push(ContentFilter {
+                id: id.to_string(),
+                enable: self
+                    .parse_if_block(("session.data.filter", id, "enable"), ctx, available_keys)?
+                    .unwrap_or_default(),
+                protocol,
+                timeout,
+                tempfail_on_error: self.property_or_static(
+                    ("session.data.filter", id, "options.tempfail-on-error"),
+                    "true",
+                )?,
+                max_response_size: self.property_or_static(
+                    ("session.data.filter", id, "options.max-response-size"),
+                    "52428800",
+                )?,
+                deferred: match self
+                    .value(("session.data.filter", id, "mode"))
+                    .unwrap_or("inline")
+                {
+                    "inline" => None,
+                    "deferred" => DeferredScanPolicy {
+                        on_reject: self.property_or_static(
+                            ("session.data.filter", id, "deferred.on-reject"),
+                            "quarantine",
+                        )?,
+                        on_tempfail: self.property_or_static(
+                            ("session.data.filter", id, "deferred.on-tempfail"),
+                            "quarantine",
+                        )?,
+                        on_error: self.property_or_static(
+                            ("session.data.filter", id, "deferred.on-error"),
+                            "quarantine",
+                        )?,
+                        limiter: ConcurrencyLimiter::new(self.property_or_static(
+                            ("session.data.filter", id, "deferred.max-concurrent"),
+                            "32",
+                        )?),
+                    }
+                    .into(),
+                    mode => {
+                        return Err(format!(
+                            "Invalid content filter mode {mode:?} for filter {id:?}, expected 'inline' or 'deferred'."
+                        ))
+                    }
+                },
+            });
+        }
+        Ok(filters)
+    }
+
+    fn parse_footers(&self) -> super::Result<AHashMap<String, Arc<Footer>>> {
+        let mut footers = AHashMap::new();
+        for id in self.sub_keys("footer") {
+            footers.insert(
+                id.to_string(),
+                Arc::new(Footer {
+                    id: id.to_string(),
+                    text: self.value_require(("footer", id, "text"))?.to_string(),
+                    html: self
+                        .value(("footer", id, "html"))
+                        .map(|html| html.to_string()),
+                }),
+            );
+        }
+        Ok(footers)
+    }
+}
+
+impl ParseValue for DeferredScanAction {
+    fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
+        match value {
+            "deliver" | "accept" => Ok(DeferredScanAction::Deliver),
+            "quarantine" | "hold" => Ok(DeferredScanAction::Quarantine),
+            "bounce" | "reject" => Ok(DeferredScanAction::Bounce),
+            "discard" => Ok(DeferredScanAction::Discard),
+            _ => Err(format!(
+                "Invalid deferred scan action {:?} for key {:?}.",
+                value,
+                key.as_key()
+            )),
+        }
+    }
 }
 
 struct Mechanism {
@@ -529,10 +832,10 @@ impl ParseValue for Mechanism {
                 "PLAIN" => AUTH_PLAIN,
                 "XOAUTH2" => AUTH_XOAUTH2,
                 "OAUTHBEARER" => AUTH_OAUTHBEARER,
-                /*"SCRAM-SHA-256-PLUS" => AUTH_SCRAM_SHA_256_PLUS,
                 "SCRAM-SHA-256" => AUTH_SCRAM_SHA_256,
-                "SCRAM-SHA-1-PLUS" => AUTH_SCRAM_SHA_1_PLUS,
                 "SCRAM-SHA-1" => AUTH_SCRAM_SHA_1,
+                /*"SCRAM-SHA-256-PLUS" => AUTH_SCRAM_SHA_256_PLUS,
+                "SCRAM-SHA-1-PLUS" => AUTH_SCRAM_SHA_1_PLUS,
                 "XOAUTH" => AUTH_XOAUTH,
                 "9798-M-DSA-SHA1" => AUTH_9798_M_DSA_SHA1,
                 "9798-M-ECDSA-SHA1" => AUTH_9798_M_ECDSA_SHA1,
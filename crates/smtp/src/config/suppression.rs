@@ -0,0 +1,71 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use utils::config::{utils::AsKey, Config};
+
+use super::{ConfigContext, SuppressionConfig, SuppressionLookup};
+
+pub trait ConfigSuppression {
+    fn parse_suppression(&self, ctx: &ConfigContext) -> super::Result<SuppressionConfig>;
+}
+
+impl ConfigSuppression for Config {
+    fn parse_suppression(&self, ctx: &ConfigContext) -> super::Result<SuppressionConfig> {
+        // Addresses are kept in a database shared by all nodes when lookups are configured
+        let lookup = if self.value("queue.suppression.lookup.get").is_some() {
+            let lookup = |name: &str| {
+                let key = ("queue.suppression.lookup", name);
+                let id = self.value_require(key)?;
+                ctx.directory.lookups.get(id).cloned().ok_or_else(|| {
+                    format!("Lookup {id:?} not found for property {:?}.", key.as_key())
+                })
+            };
+            Some(SuppressionLookup {
+                get: lookup("get")?,
+                bounce: lookup("bounce")?,
+                set: lookup("set")?,
+                delete: lookup("delete")?,
+            })
+        } else {
+            None
+        };
+
+        Ok(SuppressionConfig {
+            enable: self.property("queue.suppression.enable")?.unwrap_or(false),
+            path: self.property("queue.suppression.path")?,
+            flush_frequency: self
+                .property("queue.suppression.flush-frequency")?
+                .unwrap_or(Duration::from_secs(60)),
+            threshold: self
+                .property::<u32>("queue.suppression.threshold")?
+                .unwrap_or(3)
+                .max(1),
+            expiry: self
+                .property("queue.suppression.expiry")?
+                .unwrap_or(Duration::from_secs(30 * 86400)),
+            lookup,
+        })
+    }
+}
@@ -132,6 +132,9 @@ impl ParseValue for EnvelopeKey {
             "priority" => EnvelopeKey::Priority,
             "authenticated-as" => EnvelopeKey::AuthenticatedAs,
             "mx" => EnvelopeKey::Mx,
+            "reputation" => EnvelopeKey::Reputation,
+            "country" => EnvelopeKey::Country,
+            "asn" => EnvelopeKey::Asn,
             _ => {
                 return Err(format!(
                     "Invalid context key {:?} for property {:?}.",
@@ -0,0 +1,89 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use utils::config::Config;
+
+use super::{ConfigContext, Transport, TransportProtocol};
+
+pub trait ConfigTransport {
+    fn parse_transports(&self, ctx: &mut ConfigContext) -> super::Result<()>;
+    fn parse_transport(&self, id: &str) -> super::Result<Transport>;
+}
+
+impl ConfigTransport for Config {
+    fn parse_transports(&self, ctx: &mut ConfigContext) -> super::Result<()> {
+        for id in self.sub_keys("transport") {
+            ctx.transports
+                .insert(id.to_string(), Arc::new(self.parse_transport(id)?));
+        }
+
+        Ok(())
+    }
+
+    fn parse_transport(&self, id: &str) -> super::Result<Transport> {
+        let protocol = match self.value_require(("transport", id, "type"))? {
+            "pipe" => TransportProtocol::Pipe {
+                command: self.value_require(("transport", id, "command"))?.to_string(),
+                arguments: self
+                    .values(("transport", id, "arguments"))
+                    .map(|(_, v)| v.to_string())
+                    .collect(),
+                per_recipient: self
+                    .property_or_static(("transport", id, "per-recipient"), "true")?,
+                temporary_codes: self
+                    .properties::<u32>(("transport", id, "exit-codes.temporary"))
+                    .map(|r| r.map(|(_, v)| v))
+                    .collect::<super::Result<Vec<_>>>()?,
+                permanent_codes: self
+                    .properties::<u32>(("transport", id, "exit-codes.permanent"))
+                    .map(|r| r.map(|(_, v)| v))
+                    .collect::<super::Result<Vec<_>>>()?,
+            },
+            "unix" => TransportProtocol::Unix {
+                path: self.property_require(("transport", id, "path"))?,
+                is_lmtp: match self.value(("transport", id, "protocol")).unwrap_or("lmtp") {
+                    "lmtp" => true,
+                    "smtp" => false,
+                    protocol => {
+                        return Err(format!(
+                            "Invalid protocol {protocol:?} for transport {id:?}, expected \"lmtp\" or \"smtp\"."
+                        ))
+                    }
+                },
+            },
+            typ => {
+                return Err(format!(
+                    "Invalid type {typ:?} for transport {id:?}, expected \"pipe\" or \"unix\"."
+                ))
+            }
+        };
+
+        Ok(Transport {
+            id: id.to_string(),
+            protocol,
+            timeout: self.property_or_static(("transport", id, "timeout"), "5m")?,
+        })
+    }
+}
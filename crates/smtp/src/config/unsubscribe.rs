@@ -0,0 +1,65 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use utils::config::Config;
+
+use super::UnsubscribeConfig;
+
+pub trait ConfigUnsubscribe {
+    fn parse_unsubscribe(&self) -> super::Result<UnsubscribeConfig>;
+}
+
+impl ConfigUnsubscribe for Config {
+    fn parse_unsubscribe(&self) -> super::Result<UnsubscribeConfig> {
+        let mut keys = Vec::new();
+        for (_, key) in self.values("unsubscribe.signature.keys") {
+            keys.push(key.as_bytes().to_vec());
+        }
+
+        let url = self
+            .value("unsubscribe.url")
+            .unwrap_or_default()
+            .to_string();
+        if !url.is_empty() {
+            if !url.starts_with("https://") {
+                return Err(format!(
+                    "Invalid unsubscribe URL {url:?}, one-click unsubscribe requires HTTPS."
+                ));
+            } else if keys.is_empty() {
+                return Err(
+                    "At least one signature key is required for one-click unsubscribe.".to_string(),
+                );
+            }
+        }
+
+        Ok(UnsubscribeConfig {
+            url,
+            keys,
+            expiry: self
+                .property("unsubscribe.expiry")?
+                .unwrap_or(Duration::from_secs(30 * 86400)),
+        })
+    }
+}
@@ -0,0 +1,135 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use utils::config::{utils::AsKey, Config};
+
+use crate::usage::{UsagePeriod, UsageScope};
+
+use super::{
+    condition::ConfigCondition, Conditions, ConfigContext, EnvelopeKey, UsageAction, UsageConfig,
+    UsageLimit,
+};
+
+pub trait ConfigUsage {
+    fn parse_usage(&self, ctx: &ConfigContext) -> super::Result<UsageConfig>;
+    fn parse_usage_limit(
+        &self,
+        prefix: impl AsKey,
+        ctx: &ConfigContext,
+    ) -> super::Result<UsageLimit>;
+}
+
+impl ConfigUsage for Config {
+    fn parse_usage(&self, ctx: &ConfigContext) -> super::Result<UsageConfig> {
+        let mut limits = Vec::new();
+        for array_pos in self.sub_keys("usage.limit") {
+            limits.push(self.parse_usage_limit(("usage.limit", array_pos), ctx)?);
+        }
+
+        let mut thresholds = Vec::new();
+        for result in self.properties::<u64>("usage.notify.thresholds") {
+            let (key, threshold) = result?;
+            if (1..=100).contains(&threshold) {
+                thresholds.push(threshold);
+            } else {
+                return Err(format!(
+                    "Invalid threshold {threshold} for property {key:?}, expected a percentage."
+                ));
+            }
+        }
+
+        Ok(UsageConfig {
+            path: self.property("usage.path")?,
+            flush_frequency: self
+                .property("usage.flush-frequency")?
+                .unwrap_or(Duration::from_secs(60)),
+            thresholds,
+            limits,
+        })
+    }
+
+    fn parse_usage_limit(
+        &self,
+        prefix: impl AsKey,
+        ctx: &ConfigContext,
+    ) -> super::Result<UsageLimit> {
+        let prefix = prefix.as_key();
+        let scope = self.value_require((prefix.as_str(), "scope"))?;
+        let period = self.value_require((prefix.as_str(), "period"))?;
+        let action = self.value((prefix.as_str(), "action")).unwrap_or("reject");
+
+        let limit = UsageLimit {
+            conditions: if self.values((&prefix, "match")).next().is_some() {
+                self.parse_condition(
+                    (&prefix, "match"),
+                    ctx,
+                    &[
+                        EnvelopeKey::Sender,
+                        EnvelopeKey::SenderDomain,
+                        EnvelopeKey::AuthenticatedAs,
+                        EnvelopeKey::Listener,
+                        EnvelopeKey::RemoteIp,
+                        EnvelopeKey::LocalIp,
+                    ],
+                )?
+            } else {
+                Conditions {
+                    conditions: Vec::with_capacity(0),
+                }
+            },
+            scope: UsageScope::parse(scope).ok_or_else(|| {
+                format!("Invalid usage scope {scope:?} for property \"{prefix}.scope\".")
+            })?,
+            period: UsagePeriod::parse(period).ok_or_else(|| {
+                format!("Invalid usage period {period:?} for property \"{prefix}.period\".")
+            })?,
+            action: UsageAction::parse(action).ok_or_else(|| {
+                format!("Invalid usage action {action:?} for property \"{prefix}.action\".")
+            })?,
+            messages: self
+                .property::<u64>((prefix.as_str(), "messages"))?
+                .filter(|&v| v > 0),
+            recipients: self
+                .property::<u64>((prefix.as_str(), "recipients"))?
+                .filter(|&v| v > 0),
+            size: self
+                .property::<u64>((prefix.as_str(), "size"))?
+                .filter(|&v| v > 0),
+        };
+
+        // Validate
+        if limit.size.is_none() && limit.messages.is_none() && limit.recipients.is_none() {
+            Err(format!(
+                concat!(
+                    "Usage limit {:?} needs to define a valid ",
+                    "'size', 'messages' and/or 'recipients' property."
+                ),
+                prefix
+            ))
+        } else {
+            Ok(limit)
+        }
+    }
+}
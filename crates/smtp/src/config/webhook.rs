@@ -0,0 +1,108 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use ahash::AHashSet;
+use utils::config::Config;
+
+use crate::webhook::WebhookEventType;
+
+use super::{WebhookConfig, WebhookEndpoint};
+
+pub trait ConfigWebhook {
+    fn parse_webhooks(&self) -> super::Result<WebhookConfig>;
+    fn parse_webhook_endpoint(&self, id: &str) -> super::Result<WebhookEndpoint>;
+}
+
+impl ConfigWebhook for Config {
+    fn parse_webhooks(&self) -> super::Result<WebhookConfig> {
+        let mut endpoints = Vec::new();
+        for id in self.sub_keys("webhook.endpoint") {
+            endpoints.push(Arc::new(self.parse_webhook_endpoint(id)?));
+        }
+
+        Ok(WebhookConfig {
+            path: if !endpoints.is_empty() {
+                self.property_require("webhook.path")?
+            } else {
+                self.property("webhook.path")?.unwrap_or_default()
+            },
+            retention: self.property_or_static("webhook.retention", "7d")?,
+            endpoints,
+        })
+    }
+
+    fn parse_webhook_endpoint(&self, id: &str) -> super::Result<WebhookEndpoint> {
+        let mut events = AHashSet::new();
+        for (_, event) in self.values(("webhook.endpoint", id, "events")) {
+            events.insert(WebhookEventType::parse(event).ok_or_else(|| {
+                format!("Invalid webhook event type {event:?} for endpoint {id:?}.")
+            })?);
+        }
+
+        let mut headers = Vec::new();
+        for (_, header) in self.values(("webhook.endpoint", id, "headers")) {
+            if let Some((name, value)) = header.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            } else {
+                return Err(format!(
+                    "Invalid header {header:?} for webhook endpoint {id:?}, expected 'Name: value'."
+                ));
+            }
+        }
+
+        let mut signature_keys = Vec::new();
+        for (_, key) in self.values(("webhook.endpoint", id, "signature.keys")) {
+            signature_keys.push(key.as_bytes().to_vec());
+        }
+
+        let mut retry = Vec::new();
+        for result in self.properties::<Duration>(("webhook.endpoint", id, "retry")) {
+            retry.push(result?.1);
+        }
+        if retry.is_empty() {
+            retry = vec![
+                Duration::from_secs(60),
+                Duration::from_secs(5 * 60),
+                Duration::from_secs(30 * 60),
+                Duration::from_secs(2 * 60 * 60),
+            ];
+        }
+
+        Ok(WebhookEndpoint {
+            id: id.to_string(),
+            url: self
+                .value_require(("webhook.endpoint", id, "url"))?
+                .to_string(),
+            timeout: self.property_or_static(("webhook.endpoint", id, "timeout"), "30s")?,
+            tls_allow_invalid_certs: self
+                .property_or_static(("webhook.endpoint", id, "allow-invalid-certs"), "false")?,
+            expire: self.property_or_static(("webhook.endpoint", id, "expire"), "3d")?,
+            events,
+            headers,
+            signature_keys,
+            retry,
+        })
+    }
+}
@@ -0,0 +1,187 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+use utils::listener::limiter::InFlight;
+
+use crate::config::ConnectionsConfig;
+
+use super::Session;
+
+// Maximum time to wait for the TLS handshake of a rejected connection
+const REJECT_TLS_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+pub struct ConnectionLimiter {
+    pub max_concurrent: AtomicU64,
+    pub max_concurrent_per_ip: AtomicU64,
+    pub concurrent: Arc<AtomicU64>,
+    pub concurrent_per_ip: DashMap<IpAddr, Arc<AtomicU64>>,
+    pub rejected: AtomicU64,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ConnectionStatus {
+    pub concurrent: u64,
+    pub max_concurrent: u64,
+    pub max_concurrent_per_ip: u64,
+    pub rejected: u64,
+    pub top_ips: Vec<(IpAddr, u64)>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(config: &ConnectionsConfig) -> Self {
+        let limiter = ConnectionLimiter::default();
+        limiter.set_limits(config.max_concurrent, config.max_concurrent_per_ip);
+        limiter
+    }
+
+    // A limit of zero disables the limit.
+    pub fn set_limits(&self, max_concurrent: u64, max_concurrent_per_ip: u64) {
+        self.max_concurrent.store(max_concurrent, Ordering::Relaxed);
+        self.max_concurrent_per_ip
+            .store(max_concurrent_per_ip, Ordering::Relaxed);
+    }
+
+    pub fn is_allowed(&self, remote_ip: IpAddr) -> Result<Vec<InFlight>, &'static str> {
+        let mut in_flight = Vec::with_capacity(2);
+
+        let max_concurrent = self.max_concurrent.load(Ordering::Relaxed);
+        if max_concurrent > 0 {
+            in_flight.push(InFlight::acquire(&self.concurrent, max_concurrent).ok_or("global")?);
+        }
+
+        let max_concurrent_per_ip = self.max_concurrent_per_ip.load(Ordering::Relaxed);
+        if max_concurrent_per_ip > 0 {
+            let concurrent = self
+                .concurrent_per_ip
+                .entry(remote_ip)
+                .or_default()
+                .value()
+                .clone();
+            in_flight
+                .push(InFlight::acquire(&concurrent, max_concurrent_per_ip).ok_or("remote-ip")?);
+        }
+
+        Ok(in_flight)
+    }
+
+    pub fn status(&self, max_ips: usize) -> ConnectionStatus {
+        let mut top_ips = self
+            .concurrent_per_ip
+            .iter()
+            .filter_map(|entry| {
+                let concurrent = entry.value().load(Ordering::Relaxed);
+                if concurrent > 0 {
+                    Some((*entry.key(), concurrent))
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        top_ips.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_ips.truncate(max_ips);
+
+        ConnectionStatus {
+            concurrent: self.concurrent.load(Ordering::Relaxed),
+            max_concurrent: self.max_concurrent.load(Ordering::Relaxed),
+            max_concurrent_per_ip: self.max_concurrent_per_ip.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            top_ips,
+        }
+    }
+
+    pub fn cleanup(&self) {
+        self.concurrent_per_ip
+            .retain(|_, concurrent| concurrent.load(Ordering::Relaxed) > 0);
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Session<T> {
+    pub fn is_connection_allowed(&mut self) -> bool {
+        // Exempt allowlisted networks
+        let remote_ip = self.data.remote_ip;
+        if self
+            .core
+            .session
+            .config
+            .connections
+            .exempt
+            .iter()
+            .any(|network| network.matches(&remote_ip))
+        {
+            return true;
+        }
+
+        match self.core.session.connections.is_allowed(remote_ip) {
+            Ok(in_flight) => {
+                self.in_flight.extend(in_flight);
+                true
+            }
+            Err(limit) => {
+                self.core
+                    .session
+                    .connections
+                    .rejected
+                    .fetch_add(1, Ordering::Relaxed);
+                tracing::info!(
+                    parent: &self.span,
+                    context = "throttle",
+                    event = "too-many-connections",
+                    limit = limit,
+                    "Too many concurrent connections."
+                );
+                false
+            }
+        }
+    }
+}
+
+impl Session<TcpStream> {
+    pub async fn reject_connection(mut self, message: &[u8]) {
+        if self.instance.is_tls_implicit {
+            // Clients on implicit TLS listeners expect a handshake before any response
+            if let Ok(Ok(mut session)) =
+                tokio::time::timeout(REJECT_TLS_TIMEOUT, self.into_tls()).await
+            {
+                let _ = session.write(message).await;
+            }
+        } else {
+            let _ = self.write(message).await;
+        }
+    }
+}
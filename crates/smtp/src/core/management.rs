@@ -21,8 +21,14 @@
  * for more details.
 */
 
-use std::{borrow::Cow, fmt::Display, net::IpAddr, sync::Arc, time::Instant};
+use std::{
+    borrow::Cow,
+    fmt::Display,
+    net::IpAddr,
+    sync::{atomic::Ordering, Arc},
+};
 
+use base64::{engine::general_purpose::STANDARD, Engine};
 use directory::Type;
 use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full};
 use hyper::{
@@ -36,6 +42,10 @@ use hyper_util::rt::TokioIo;
 use mail_parser::{decoders::base64::base64_decode, DateTime};
 use mail_send::Credentials;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use smtp_proto::{
+    MAIL_RET_FULL, MAIL_RET_HDRS, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER,
+    RCPT_NOTIFY_SUCCESS,
+};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     sync::oneshot,
@@ -44,11 +54,15 @@ use tokio::{
 use utils::listener::{limiter::InFlight, SessionManager};
 
 use crate::{
-    queue::{self, instant_to_timestamp, InstantFromTimestamp, QueueId, Status},
+    anomaly::AnomalySignal,
+    queue::{self, modify::QueueModification, QueueId, Status, Timestamp},
     reporting::{
         self,
         scheduler::{ReportKey, ReportPolicy, ReportType, ReportValue},
     },
+    tracking::TrackingFilter,
+    usage::{Usage, UsageCounter, UsageKey, UsageScope},
+    webhook,
 };
 
 use super::{SmtpAdminSessionManager, SMTP};
@@ -58,8 +72,8 @@ pub enum QueueRequest {
     List {
         from: Option<String>,
         to: Option<String>,
-        before: Option<Instant>,
-        after: Option<Instant>,
+        before: Option<Timestamp>,
+        after: Option<Timestamp>,
         result_tx: oneshot::Sender<Vec<u64>>,
     },
     Status {
@@ -74,7 +88,16 @@ pub enum QueueRequest {
     Retry {
         queue_ids: Vec<QueueId>,
         item: Option<String>,
-        time: Instant,
+        time: Timestamp,
+        result_tx: oneshot::Sender<Vec<bool>>,
+    },
+    Modify {
+        queue_id: QueueId,
+        modifications: Vec<QueueModification>,
+        result_tx: oneshot::Sender<bool>,
+    },
+    Release {
+        queue_ids: Vec<QueueId>,
         result_tx: oneshot::Sender<Vec<bool>>,
     },
 }
@@ -114,6 +137,51 @@ pub struct Message {
     pub priority: i16,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub env_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub ret: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub history: Vec<Attempt>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct ReputationReport {
+    pub ip: IpAddr,
+    pub class: String,
+    pub score: u64,
+    pub events: u64,
+    pub accepted: u64,
+    pub auth_failures: u64,
+    pub spam: u64,
+    pub invalid_rcpts: u64,
+    pub last_seen: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SuppressionReport {
+    pub address: String,
+    pub bounces: u32,
+    pub reason: String,
+    pub last_bounce: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AnomalyReport {
+    pub account: String,
+    pub action: String,
+    pub since: u64,
+    pub signals: Vec<AnomalySignal>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UsageReport {
+    pub scope: String,
+    pub name: String,
+    pub hourly: Usage,
+    pub daily: Usage,
+    pub monthly: Usage,
+    pub total: Usage,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -123,6 +191,9 @@ pub struct Domain {
     pub recipients: Vec<Recipient>,
 
     pub retry_num: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub retry_class: Option<String>,
     #[serde(deserialize_with = "deserialize_maybe_datetime")]
     #[serde(serialize_with = "serialize_maybe_datetime")]
     pub next_retry: Option<DateTime>,
@@ -134,12 +205,33 @@ pub struct Domain {
     pub expires: DateTime,
 }
 
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Attempt {
+    pub domain: String,
+    #[serde(deserialize_with = "deserialize_datetime")]
+    #[serde(serialize_with = "serialize_datetime")]
+    pub time: DateTime,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    pub mx: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    pub remote_ip: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    #[serde(default)]
+    pub tls: String,
+    pub status: Status<String, String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Recipient {
     pub address: String,
     pub status: Status<String, String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub orcpt: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    pub notify: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -320,6 +412,7 @@ impl SMTP {
                 req.method(),
                 path.next().unwrap_or_default(),
                 path.next().unwrap_or_default(),
+                None,
             )
             .await)
     }
@@ -330,8 +423,22 @@ impl SMTP {
         method: &Method,
         path_1: &str,
         path_2: &str,
+        scope: Option<&[String]>,
     ) -> hyper::Response<BoxBody<Bytes, hyper::Error>> {
         let (status, response) = match (method, path_1, path_2) {
+            // Requests scoped to a set of domains are limited to the queue and usage endpoints
+            _ if scope.is_some()
+                && !matches!(
+                    (path_1, path_2),
+                    ("queue", "list" | "status" | "retry" | "cancel") | ("usage", "list" | "get")
+                ) =>
+            {
+                (
+                    StatusCode::FORBIDDEN,
+                    "{\"error\": \"forbidden\", \"details\": \"Operation not allowed.\"}"
+                        .to_string(),
+                )
+            }
             (&Method::GET, "queue", "list") => {
                 let mut from = None;
                 let mut to = None;
@@ -377,17 +484,30 @@ impl SMTP {
                 match error {
                     None => {
                         let (result_tx, result_rx) = oneshot::channel();
-                        self.send_queue_event(
-                            QueueRequest::List {
-                                from,
-                                to,
-                                before,
-                                after,
-                                result_tx,
-                            },
-                            result_rx,
-                        )
-                        .await
+                        let request = QueueRequest::List {
+                            from,
+                            to,
+                            before,
+                            after,
+                            result_tx,
+                        };
+                        if let Some(scope) = scope {
+                            match self.queue_request(request, result_rx).await {
+                                Some(queue_ids) => {
+                                    let in_scope = self.queue_ids_in_scope(&queue_ids, scope).await;
+                                    to_json_response(
+                                        queue_ids
+                                            .into_iter()
+                                            .zip(in_scope)
+                                            .filter_map(|(id, in_scope)| in_scope.then_some(id))
+                                            .collect::<Vec<_>>(),
+                                    )
+                                }
+                                None => queue_unavailable(),
+                            }
+                        } else {
+                            self.send_queue_event(request, result_rx).await
+                        }
                     }
                     Some(error) => error.into_bad_request(),
                 }
@@ -419,21 +539,33 @@ impl SMTP {
                 match error {
                     None => {
                         let (result_tx, result_rx) = oneshot::channel();
-                        self.send_queue_event(
-                            QueueRequest::Status {
-                                queue_ids,
-                                result_tx,
-                            },
-                            result_rx,
-                        )
-                        .await
+                        let request = QueueRequest::Status {
+                            queue_ids,
+                            result_tx,
+                        };
+                        if let Some(scope) = scope {
+                            // Messages outside the scope are reported as not found
+                            match self.queue_request(request, result_rx).await {
+                                Some(messages) => to_json_response(
+                                    messages
+                                        .into_iter()
+                                        .map(|message| {
+                                            message.filter(|message| message.is_in_scope(scope))
+                                        })
+                                        .collect::<Vec<_>>(),
+                                ),
+                                None => queue_unavailable(),
+                            }
+                        } else {
+                            self.send_queue_event(request, result_rx).await
+                        }
                     }
                     Some(error) => error.into_bad_request(),
                 }
             }
             (&Method::GET, "queue", "retry") => {
                 let mut queue_ids = Vec::new();
-                let mut time = Instant::now();
+                let mut time = Timestamp::now();
                 let mut item = None;
                 let mut error = None;
 
@@ -469,8 +601,27 @@ impl SMTP {
                     }
                 }
 
-                match error {
-                    None => {
+                match (error, scope) {
+                    (None, Some(scope)) => {
+                        let in_scope = self.queue_ids_in_scope(&queue_ids, scope).await;
+                        let (result_tx, result_rx) = oneshot::channel();
+                        match self
+                            .queue_request(
+                                QueueRequest::Retry {
+                                    queue_ids: scoped_ids(&queue_ids, &in_scope),
+                                    item,
+                                    time,
+                                    result_tx,
+                                },
+                                result_rx,
+                            )
+                            .await
+                        {
+                            Some(result) => to_json_response(scoped_results(&in_scope, result)),
+                            None => queue_unavailable(),
+                        }
+                    }
+                    (None, None) => {
                         let (result_tx, result_rx) = oneshot::channel();
                         self.send_queue_event(
                             QueueRequest::Retry {
@@ -483,7 +634,7 @@ impl SMTP {
                         )
                         .await
                     }
-                    Some(error) => error.into_bad_request(),
+                    (Some(error), _) => error.into_bad_request(),
                 }
             }
             (&Method::GET, "queue", "cancel") => {
@@ -514,8 +665,26 @@ impl SMTP {
                     }
                 }
 
-                match error {
-                    None => {
+                match (error, scope) {
+                    (None, Some(scope)) => {
+                        let in_scope = self.queue_ids_in_scope(&queue_ids, scope).await;
+                        let (result_tx, result_rx) = oneshot::channel();
+                        match self
+                            .queue_request(
+                                QueueRequest::Cancel {
+                                    queue_ids: scoped_ids(&queue_ids, &in_scope),
+                                    item,
+                                    result_tx,
+                                },
+                                result_rx,
+                            )
+                            .await
+                        {
+                            Some(result) => to_json_response(scoped_results(&in_scope, result)),
+                            None => queue_unavailable(),
+                        }
+                    }
+                    (None, None) => {
                         let (result_tx, result_rx) = oneshot::channel();
                         self.send_queue_event(
                             QueueRequest::Cancel {
@@ -527,6 +696,202 @@ impl SMTP {
                         )
                         .await
                     }
+                    (Some(error), _) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "queue", "release") => {
+                let mut queue_ids = Vec::new();
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "id" | "ids" => match value.parse_queue_ids() {
+                                Ok(ids) => {
+                                    queue_ids = ids;
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None if !queue_ids.is_empty() => {
+                        let (result_tx, result_rx) = oneshot::channel();
+                        self.send_queue_event(
+                            QueueRequest::Release {
+                                queue_ids,
+                                result_tx,
+                            },
+                            result_rx,
+                        )
+                        .await
+                    }
+                    None => "Missing id parameter.".to_string().into_bad_request(),
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "queue", "modify") => {
+                let mut queue_id = None;
+                let mut modifications = Vec::new();
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "id" => match value.parse::<QueueId>() {
+                                Ok(id) => {
+                                    queue_id = id.into();
+                                }
+                                Err(_) => {
+                                    error = format!("Failed to parse id {value:?}.").into();
+                                    break;
+                                }
+                            },
+                            "add-rcpt" | "remove-rcpt" => {
+                                if !value.contains('@') {
+                                    error = format!("Invalid recipient {value:?}.").into();
+                                    break;
+                                }
+                                modifications.push(if key == "add-rcpt" {
+                                    QueueModification::AddRecipient(value.into_owned())
+                                } else {
+                                    QueueModification::RemoveRecipient(value.into_owned())
+                                });
+                            }
+                            "add-header" | "replace-header" => {
+                                let (name, value) = match value.split_once(':') {
+                                    Some((name, value))
+                                        if !name.trim().is_empty()
+                                            && name
+                                                .trim()
+                                                .bytes()
+                                                .all(|ch| ch.is_ascii_graphic())
+                                            && !value.contains(|ch| ch == '\r' || ch == '\n') =>
+                                    {
+                                        (name.trim().to_string(), value.trim().to_string())
+                                    }
+                                    _ => {
+                                        error = format!("Invalid header {value:?}.").into();
+                                        break;
+                                    }
+                                };
+                                modifications.push(if key == "add-header" {
+                                    QueueModification::AddHeader { name, value }
+                                } else {
+                                    QueueModification::ReplaceHeader { name, value }
+                                });
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match (error, queue_id) {
+                    (None, Some(queue_id)) if !modifications.is_empty() => {
+                        let (result_tx, result_rx) = oneshot::channel();
+                        self.send_queue_event(
+                            QueueRequest::Modify {
+                                queue_id,
+                                modifications,
+                                result_tx,
+                            },
+                            result_rx,
+                        )
+                        .await
+                    }
+                    (None, Some(_)) => "No modifications requested.".to_string().into_bad_request(),
+                    (None, None) => "Missing id parameter.".to_string().into_bad_request(),
+                    (Some(error), _) => error.into_bad_request(),
+                }
+            }
+            (
+                &Method::GET,
+                "dead-letter",
+                action @ ("list" | "status" | "export" | "retry" | "cancel"),
+            ) => {
+                let mut queue_ids = Vec::new();
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "id" | "ids" if action != "list" => match value.parse_queue_ids() {
+                                Ok(ids) => {
+                                    queue_ids = ids;
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => {
+                        let response = match action {
+                            "list" => serde_json::to_string(&Response {
+                                data: self.queue.dead_letter_list().await,
+                            }),
+                            "status" => {
+                                let mut result = Vec::with_capacity(queue_ids.len());
+                                for queue_id in queue_ids {
+                                    result.push(
+                                        self.queue
+                                            .dead_letter_get(queue_id)
+                                            .await
+                                            .map(|message| Message::from(&message)),
+                                    );
+                                }
+                                serde_json::to_string(&Response { data: result })
+                            }
+                            "export" => {
+                                // Messages are not necessarily valid UTF-8, export them base64 encoded
+                                let mut result = Vec::with_capacity(queue_ids.len());
+                                for queue_id in queue_ids {
+                                    result.push(
+                                        self.queue
+                                            .dead_letter_export(queue_id)
+                                            .await
+                                            .map(|raw_message| STANDARD.encode(raw_message)),
+                                    );
+                                }
+                                serde_json::to_string(&Response { data: result })
+                            }
+                            "retry" => {
+                                let mut result = Vec::with_capacity(queue_ids.len());
+                                for queue_id in queue_ids {
+                                    result.push(self.queue.dead_letter_reinject(queue_id).await);
+                                }
+                                serde_json::to_string(&Response { data: result })
+                            }
+                            _ => {
+                                let mut result = Vec::with_capacity(queue_ids.len());
+                                for queue_id in queue_ids {
+                                    result.push(self.queue.dead_letter_remove(queue_id).await);
+                                }
+                                serde_json::to_string(&Response { data: result })
+                            }
+                        };
+                        (StatusCode::OK, response.unwrap_or_default())
+                    }
                     Some(error) => error.into_bad_request(),
                 }
             }
@@ -655,6 +1020,468 @@ impl SMTP {
                     Some(error) => error.into_bad_request(),
                 }
             }
+            (&Method::GET, "shadow", "report") => (
+                StatusCode::OK,
+                serde_json::to_string(&Response {
+                    data: self.sieve.shadow.summary(),
+                })
+                .unwrap_or_default(),
+            ),
+            (&Method::GET, "shadow", "reset") => (
+                StatusCode::OK,
+                serde_json::to_string(&Response {
+                    data: self.sieve.shadow.reset(),
+                })
+                .unwrap_or_default(),
+            ),
+            (&Method::GET, "metrics", "") => (
+                StatusCode::OK,
+                serde_json::to_string(&Response {
+                    data: utils::metrics::snapshot(),
+                })
+                .unwrap_or_default(),
+            ),
+            (&Method::GET, "connections", "status") => (
+                StatusCode::OK,
+                serde_json::to_string(&Response {
+                    data: self.session.connections.status(100),
+                })
+                .unwrap_or_default(),
+            ),
+            (&Method::GET, "connections", "set") => {
+                let mut max_concurrent = None;
+                let mut max_concurrent_per_ip = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        let limit = match value.parse::<u64>() {
+                            Ok(limit) => limit,
+                            Err(_) => {
+                                error = format!("Invalid value {value:?} for {key:?}.").into();
+                                break;
+                            }
+                        };
+                        match key.as_ref() {
+                            "max" => {
+                                max_concurrent = limit.into();
+                            }
+                            "max-per-ip" => {
+                                max_concurrent_per_ip = limit.into();
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => {
+                        let connections = &self.session.connections;
+                        connections.set_limits(
+                            max_concurrent.unwrap_or_else(|| {
+                                connections.max_concurrent.load(Ordering::Relaxed)
+                            }),
+                            max_concurrent_per_ip.unwrap_or_else(|| {
+                                connections.max_concurrent_per_ip.load(Ordering::Relaxed)
+                            }),
+                        );
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response {
+                                data: connections.status(100),
+                            })
+                            .unwrap_or_default(),
+                        )
+                    }
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "usage", "list") => {
+                let mut usage_scope = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "scope" => match UsageScope::parse(value.as_ref()) {
+                                Some(scope_) => {
+                                    usage_scope = scope_.into();
+                                }
+                                None => {
+                                    error = format!("Invalid scope {value:?}.").into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => (
+                        StatusCode::OK,
+                        serde_json::to_string(&Response {
+                            data: self
+                                .usage
+                                .list(usage_scope)
+                                .into_iter()
+                                .filter(|(key, _)| {
+                                    scope.map_or(true, |scope| key.is_in_scope(scope))
+                                })
+                                .map(|(key, counter)| UsageReport::new(key, counter))
+                                .collect::<Vec<_>>(),
+                        })
+                        .unwrap_or_default(),
+                    ),
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "usage", "get") => {
+                let mut usage_key = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "account" => {
+                                usage_key = UsageKey::Account(value.into_owned()).into();
+                            }
+                            "domain" => {
+                                usage_key = UsageKey::Domain(value.to_lowercase()).into();
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match (error, usage_key) {
+                    (None, Some(usage_key))
+                        if scope.map_or(false, |scope| !usage_key.is_in_scope(scope)) =>
+                    {
+                        (
+                            StatusCode::FORBIDDEN,
+                            "{\"error\": \"forbidden\", \"details\": \"Operation not allowed.\"}"
+                                .to_string(),
+                        )
+                    }
+                    (None, Some(usage_key)) => (
+                        StatusCode::OK,
+                        serde_json::to_string(&Response {
+                            data: self
+                                .usage
+                                .get(&usage_key)
+                                .map(|counter| UsageReport::new(usage_key, counter)),
+                        })
+                        .unwrap_or_default(),
+                    ),
+                    (None, None) => "Missing account or domain parameter."
+                        .to_string()
+                        .into_bad_request(),
+                    (Some(error), _) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "reputation", "get") => {
+                let mut ip = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "ip" => match value.parse::<IpAddr>() {
+                                Ok(value) => {
+                                    ip = value.into();
+                                }
+                                Err(_) => {
+                                    error = format!("Invalid IP address {value:?}.").into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match (error, ip) {
+                    (None, Some(ip)) => {
+                        let data = self
+                            .reputation
+                            .get(&ip)
+                            .await
+                            .map(|entry| ReputationReport {
+                                ip,
+                                class: self.reputation.classify(Some(&entry)).as_str().to_string(),
+                                score: entry.percent(),
+                                events: entry.events,
+                                accepted: entry.accepted,
+                                auth_failures: entry.auth_failures,
+                                spam: entry.spam,
+                                invalid_rcpts: entry.invalid_rcpts,
+                                last_seen: entry.last_seen,
+                            });
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data }).unwrap_or_default(),
+                        )
+                    }
+                    (None, None) => "Missing ip parameter.".to_string().into_bad_request(),
+                    (Some(error), _) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "suppression", "list") => (
+                StatusCode::OK,
+                serde_json::to_string(&Response {
+                    data: self
+                        .suppression
+                        .list()
+                        .into_iter()
+                        .map(|(address, entry)| SuppressionReport {
+                            address,
+                            bounces: entry.bounces,
+                            reason: entry.reason,
+                            last_bounce: entry.last_bounce,
+                        })
+                        .collect::<Vec<_>>(),
+                })
+                .unwrap_or_default(),
+            ),
+            (&Method::GET, "suppression", action @ ("add" | "remove")) => {
+                let mut address = None;
+                let mut reason = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "address" if value.contains('@') => {
+                                address = value.into_owned().into();
+                            }
+                            "address" => {
+                                error = format!("Invalid address {value:?}.").into();
+                                break;
+                            }
+                            "reason" if action == "add" => {
+                                reason = value.into_owned().into();
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match (error, address) {
+                    (None, Some(address)) => {
+                        let result = if action == "add" {
+                            self.suppression
+                                .suppress(
+                                    &address,
+                                    reason.unwrap_or_else(|| "Added by administrator".to_string()),
+                                )
+                                .await
+                        } else {
+                            self.suppression.remove(&address).await
+                        };
+                        (
+                            StatusCode::OK,
+                            serde_json::to_string(&Response { data: result }).unwrap_or_default(),
+                        )
+                    }
+                    (None, None) => "Missing address parameter.".to_string().into_bad_request(),
+                    (Some(error), _) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "anomaly", "list") => (
+                StatusCode::OK,
+                serde_json::to_string(&Response {
+                    data: self
+                        .anomaly
+                        .list()
+                        .into_iter()
+                        .map(|(account, containment)| AnomalyReport {
+                            account,
+                            action: containment.action.as_str().to_string(),
+                            since: containment.since,
+                            signals: containment.signals,
+                        })
+                        .collect::<Vec<_>>(),
+                })
+                .unwrap_or_default(),
+            ),
+            (&Method::GET, "anomaly", "clear") => {
+                let mut account = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "account" => {
+                                account = value.into_owned().into();
+                            }
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match (error, account) {
+                    (None, Some(account)) => (
+                        StatusCode::OK,
+                        serde_json::to_string(&Response {
+                            data: self.anomaly.clear(&account),
+                        })
+                        .unwrap_or_default(),
+                    ),
+                    (None, None) => "Missing account parameter.".to_string().into_bad_request(),
+                    (Some(error), _) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "webhook", "replay") => {
+                let mut endpoint_id = None;
+                let mut from = None;
+                let mut to = None;
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "id" => {
+                                if self.webhook.tx.contains_key(value.as_ref()) {
+                                    endpoint_id = value.into_owned().into();
+                                } else {
+                                    error = format!("Webhook endpoint {value:?} does not exist.")
+                                        .into();
+                                    break;
+                                }
+                            }
+                            "from" => match value.parse_date() {
+                                Ok(dt) => {
+                                    from = dt.into();
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
+                            "to" => match value.parse_date() {
+                                Ok(dt) => {
+                                    to = dt.into();
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None => {
+                        self.send_webhook_replay(
+                            endpoint_id.as_deref(),
+                            from.unwrap_or(0),
+                            to.unwrap_or(u64::MAX),
+                        )
+                        .await
+                    }
+                    Some(error) => error.into_bad_request(),
+                }
+            }
+            (&Method::GET, "tracking", "search") => {
+                let mut filter = TrackingFilter::default();
+                let mut error = None;
+
+                if let Some(query) = uri.query() {
+                    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                        match key.as_ref() {
+                            "queue-id" => match value.parse() {
+                                Ok(queue_id) => {
+                                    filter.queue_id = Some(queue_id);
+                                }
+                                Err(_) => {
+                                    error = format!("Failed to parse id {value:?}.").into();
+                                    break;
+                                }
+                            },
+                            "message-id" => {
+                                filter.message_id = value.into_owned().into();
+                            }
+                            "from" => {
+                                filter.from = value.to_lowercase().into();
+                            }
+                            "to" => {
+                                filter.to = value.to_lowercase().into();
+                            }
+                            "after" => match value.parse_date() {
+                                Ok(dt) => {
+                                    filter.after = dt.into();
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
+                            "before" => match value.parse_date() {
+                                Ok(dt) => {
+                                    filter.before = dt.into();
+                                }
+                                Err(reason) => {
+                                    error = reason.into();
+                                    break;
+                                }
+                            },
+                            "limit" => match value.parse() {
+                                Ok(limit) => {
+                                    filter.limit = limit;
+                                }
+                                Err(_) => {
+                                    error = format!("Invalid limit {value:?}.").into();
+                                    break;
+                                }
+                            },
+                            _ => {
+                                error = format!("Invalid parameter {key:?}.").into();
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                match error {
+                    None if self.tracking.is_enabled() => (
+                        StatusCode::OK,
+                        serde_json::to_string(&Response {
+                            data: self.tracking.search(&filter).await,
+                        })
+                        .unwrap_or_default(),
+                    ),
+                    None => (
+                        StatusCode::NOT_FOUND,
+                        "{\"error\": \"not-found\", \"details\": \"Message tracking is disabled.\"}"
+                            .to_string(),
+                    ),
+                    Some(error) => error.into_bad_request(),
+                }
+            }
             _ => (
                 StatusCode::NOT_FOUND,
                 format!(
@@ -680,13 +1507,17 @@ impl SMTP {
         request: QueueRequest,
         rx: oneshot::Receiver<T>,
     ) -> (StatusCode, String) {
+        match self.queue_request(request, rx).await {
+            Some(result) => to_json_response(result),
+            None => queue_unavailable(),
+        }
+    }
+
+    async fn queue_request<T>(&self, request: QueueRequest, rx: oneshot::Receiver<T>) -> Option<T> {
         match self.queue.tx.send(queue::Event::Manage(request)).await {
             Ok(_) => match rx.await {
                 Ok(result) => {
-                    return (
-                        StatusCode::OK,
-                        serde_json::to_string(&Response { data: result }).unwrap_or_default(),
-                    )
+                    return Some(result);
                 }
                 Err(_) => {
                     tracing::debug!(
@@ -705,11 +1536,26 @@ impl SMTP {
             }
         }
 
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "{\"error\": \"internal-error\", \"details\": \"Resource unavailable, try again later.\"}"
-                .to_string(),
+        None
+    }
+
+    async fn queue_ids_in_scope(&self, queue_ids: &[QueueId], scope: &[String]) -> Vec<bool> {
+        let (result_tx, result_rx) = oneshot::channel();
+        self.queue_request(
+            QueueRequest::Status {
+                queue_ids: queue_ids.to_vec(),
+                result_tx,
+            },
+            result_rx,
         )
+        .await
+        .map(|messages| {
+            messages
+                .into_iter()
+                .map(|message| message.map_or(false, |message| message.is_in_scope(scope)))
+                .collect()
+        })
+        .unwrap_or_else(|| vec![false; queue_ids.len()])
     }
 
     async fn send_report_event<T: Serialize>(
@@ -748,11 +1594,67 @@ impl SMTP {
                 .to_string(),
         )
     }
+
+    async fn send_webhook_replay(
+        &self,
+        endpoint_id: Option<&str>,
+        from: u64,
+        to: u64,
+    ) -> (StatusCode, String) {
+        let mut count = 0;
+
+        for (id, tx) in &self.webhook.tx {
+            if endpoint_id.map_or(false, |endpoint_id| endpoint_id != id) {
+                continue;
+            }
+            let (result_tx, result_rx) = oneshot::channel();
+            if tx
+                .send(webhook::Event::Replay {
+                    from,
+                    to,
+                    result_tx,
+                })
+                .await
+                .is_ok()
+            {
+                match result_rx.await {
+                    Ok(result) => {
+                        count += result;
+                        continue;
+                    }
+                    Err(_) => {
+                        tracing::debug!(
+                            context = "webhook",
+                            event = "recv-error",
+                            reason = "Failed to receive replay request response."
+                        );
+                    }
+                }
+            } else {
+                tracing::debug!(
+                    context = "webhook",
+                    event = "send-error",
+                    reason = "Failed to send replay request event."
+                );
+            }
+
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "{\"error\": \"internal-error\", \"details\": \"Resource unavailable, try again later.\"}"
+                    .to_string(),
+            );
+        }
+
+        (
+            StatusCode::OK,
+            serde_json::to_string(&Response { data: count }).unwrap_or_default(),
+        )
+    }
 }
 
 impl From<&queue::Message> for Message {
     fn from(message: &queue::Message) -> Self {
-        let now = Instant::now();
+        let now = Timestamp::now();
 
         Message {
             return_path: message.return_path.clone(),
@@ -760,6 +1662,13 @@ impl From<&queue::Message> for Message {
             size: message.size,
             priority: message.priority,
             env_id: message.env_id.clone(),
+            ret: if (message.flags & MAIL_RET_FULL) != 0 {
+                Some("full".to_string())
+            } else if (message.flags & MAIL_RET_HDRS) != 0 {
+                Some("hdrs".to_string())
+            } else {
+                None
+            },
             domains: message
                 .domains
                 .iter()
@@ -777,21 +1686,19 @@ impl From<&queue::Message> for Message {
                         }
                     },
                     retry_num: domain.retry.inner,
+                    retry_class: match &domain.status {
+                        Status::TemporaryFailure(err) => {
+                            err.retry_class().map(|class| class.as_str().to_string())
+                        }
+                        _ => None,
+                    },
                     next_retry: if domain.retry.due > now {
-                        DateTime::from_timestamp(instant_to_timestamp(now, domain.retry.due) as i64)
-                            .into()
+                        DateTime::from_timestamp(domain.retry.due.as_secs() as i64).into()
                     } else {
                         None
                     },
                     next_notify: if domain.notify.due > now {
-                        DateTime::from_timestamp(
-                            instant_to_timestamp(
-                                now,
-                                domain.notify.due,
-                            )
-                                as i64,
-                        )
-                        .into()
+                        DateTime::from_timestamp(domain.notify.due.as_secs() as i64).into()
                     } else {
                         None
                     },
@@ -814,11 +1721,35 @@ impl From<&queue::Message> for Message {
                                 }
                             },
                             orcpt: rcpt.orcpt.clone(),
+                            notify: [
+                                (RCPT_NOTIFY_SUCCESS, "success"),
+                                (RCPT_NOTIFY_DELAY, "delay"),
+                                (RCPT_NOTIFY_FAILURE, "failure"),
+                                (RCPT_NOTIFY_NEVER, "never"),
+                            ]
+                            .into_iter()
+                            .filter(|(flag, _)| (rcpt.flags & flag) != 0)
+                            .map(|(_, name)| name.to_string())
+                            .collect(),
                         })
                         .collect(),
-                    expires: DateTime::from_timestamp(
-                        instant_to_timestamp(now, domain.expires) as i64
-                    ),
+                    expires: DateTime::from_timestamp(domain.expires.as_secs() as i64),
+                })
+                .collect(),
+            history: message
+                .history
+                .iter()
+                .map(|entry| Attempt {
+                    domain: message
+                        .domains
+                        .get(entry.domain_idx)
+                        .map(|domain| domain.domain.clone())
+                        .unwrap_or_default(),
+                    time: DateTime::from_timestamp(entry.time as i64),
+                    mx: entry.mx.clone(),
+                    remote_ip: entry.remote_ip.clone(),
+                    tls: entry.tls.clone(),
+                    status: entry.status.clone(),
                 })
                 .collect(),
         }
@@ -861,23 +1792,30 @@ impl Display for ReportKey {
 }
 
 trait ParseValues {
-    fn parse_timestamp(&self) -> Result<Instant, String>;
+    fn parse_timestamp(&self) -> Result<Timestamp, String>;
+    fn parse_date(&self) -> Result<u64, String>;
     fn parse_queue_ids(&self) -> Result<Vec<QueueId>, String>;
     fn parse_report_ids(&self) -> Result<Vec<ReportKey>, String>;
 }
 
 impl ParseValues for Cow<'_, str> {
-    fn parse_timestamp(&self) -> Result<Instant, String> {
+    fn parse_timestamp(&self) -> Result<Timestamp, String> {
         if let Some(dt) = DateTime::parse_rfc3339(self.as_ref()) {
-            let instant = (dt.to_timestamp() as u64).to_instant();
-            if instant >= Instant::now() {
-                return Ok(instant);
+            let timestamp = Timestamp::from_secs(dt.to_timestamp() as u64);
+            if timestamp >= Timestamp::now() {
+                return Ok(timestamp);
             }
         }
 
         Err(format!("Invalid timestamp {self:?}."))
     }
 
+    fn parse_date(&self) -> Result<u64, String> {
+        DateTime::parse_rfc3339(self.as_ref())
+            .map(|dt| dt.to_timestamp() as u64)
+            .ok_or_else(|| format!("Invalid date {self:?}."))
+    }
+
     fn parse_queue_ids(&self) -> Result<Vec<QueueId>, String> {
         let mut ids = Vec::new();
         for id in self.split(',') {
@@ -924,6 +1862,33 @@ impl ParseValues for Cow<'_, str> {
     }
 }
 
+impl Message {
+    // Messages are scoped by sender domain only, a message addressed to a domain
+    // also carries the recipients of other tenants.
+    pub fn is_in_scope(&self, domains: &[String]) -> bool {
+        self.return_path
+            .rsplit_once('@')
+            .map_or(false, |(_, sender_domain)| {
+                domains
+                    .iter()
+                    .any(|domain| domain.eq_ignore_ascii_case(sender_domain))
+            })
+    }
+}
+
+impl UsageReport {
+    fn new(key: UsageKey, counter: UsageCounter) -> Self {
+        UsageReport {
+            scope: key.scope().as_str().to_string(),
+            name: key.name().to_string(),
+            hourly: counter.hourly,
+            daily: counter.daily,
+            monthly: counter.monthly,
+            total: counter.total,
+        }
+    }
+}
+
 trait BadRequest {
     fn into_bad_request(self) -> (StatusCode, String);
 }
@@ -940,6 +1905,38 @@ impl BadRequest for String {
     }
 }
 
+fn to_json_response<T: Serialize>(data: T) -> (StatusCode, String) {
+    (
+        StatusCode::OK,
+        serde_json::to_string(&Response { data }).unwrap_or_default(),
+    )
+}
+
+fn queue_unavailable() -> (StatusCode, String) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "{\"error\": \"internal-error\", \"details\": \"Resource unavailable, try again later.\"}"
+            .to_string(),
+    )
+}
+
+fn scoped_ids(queue_ids: &[QueueId], in_scope: &[bool]) -> Vec<QueueId> {
+    queue_ids
+        .iter()
+        .zip(in_scope)
+        .filter_map(|(id, in_scope)| in_scope.then_some(*id))
+        .collect()
+}
+
+fn scoped_results(in_scope: &[bool], results: Vec<bool>) -> Vec<bool> {
+    // Messages outside the scope are reported as not found
+    let mut results = results.into_iter();
+    in_scope
+        .iter()
+        .map(|in_scope| *in_scope && results.next().unwrap_or(false))
+        .collect()
+}
+
 fn is_zero(num: &i16) -> bool {
     *num == 0
 }
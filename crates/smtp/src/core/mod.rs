@@ -24,7 +24,10 @@
 use std::{
     hash::Hash,
     net::IpAddr,
-    sync::{atomic::AtomicU32, Arc},
+    sync::{
+        atomic::{AtomicU32, AtomicU64},
+        Arc,
+    },
     time::{Duration, Instant},
 };
 
@@ -32,6 +35,7 @@ use ahash::AHashMap;
 use dashmap::DashMap;
 use directory::{Directory, Lookup};
 use mail_auth::{common::lru::LruCache, IprevOutput, Resolver, SpfOutput};
+use parking_lot::RwLock;
 use sieve::{Runtime, Sieve};
 use smtp_proto::request::receiver::{
     BdatReceiver, DataReceiver, DummyDataReceiver, DummyLineReceiver, LineReceiver, RequestReceiver,
@@ -48,30 +52,48 @@ use utils::{
 };
 
 use crate::{
+    anomaly::AccountActivity,
     config::{
-        scripts::SieveContext, DkimSigner, MailAuthConfig, QueueConfig, ReportConfig,
-        SessionConfig, VerifyStrategy,
+        scripts::SieveContext, AnomalyConfig, DkimReplayConfig, DkimSigner, DnsOverride,
+        GeoIpConfig, MailAuthConfig, QueueConfig, ReportConfig, ReputationConfig, SessionConfig,
+        SuppressionConfig, TrackingConfig, UnsubscribeConfig, UsageConfig, VerifyStrategy,
+        WebhookConfig,
     },
-    inbound::auth::SaslToken,
+    geoip::{GeoIpDatabases, GeoIpInfo},
+    inbound::{auth::SaslToken, bimi::VmcStatus},
     outbound::{
         dane::{DnssecResolver, Tlsa},
         mta_sts,
+        pool::ConnectionPool,
     },
-    queue::{self, DomainPart, QueueId, QuotaLimiter},
+    queue::{self, DomainPart, QueueId, QueueLoad, QuotaLimiter},
+    replay::{DkimReplayEntry, DkimReplayKey},
     reporting,
+    reputation::ReputationEntry,
+    scripts::shadow::ShadowReport,
+    suppression::SuppressionEntry,
+    tracking::{self, index::TrackingIndex},
+    usage::{UsageCounter, UsageKey},
+    webhook,
 };
 
-use self::throttle::{Limiter, ThrottleKey, ThrottleKeyHasherBuilder};
+use self::{
+    connections::ConnectionLimiter,
+    reload::SmtpHandle,
+    throttle::{Limiter, ThrottleKey, ThrottleKeyHasherBuilder},
+};
 
+pub mod connections;
 pub mod if_block;
 pub mod management;
 pub mod params;
+pub mod reload;
 pub mod throttle;
 pub mod worker;
 
 #[derive(Clone)]
 pub struct SmtpSessionManager {
-    pub inner: Arc<SMTP>,
+    pub inner: SmtpHandle,
 }
 
 #[derive(Clone)]
@@ -80,8 +102,10 @@ pub struct SmtpAdminSessionManager {
 }
 
 impl SmtpSessionManager {
-    pub fn new(inner: Arc<SMTP>) -> Self {
-        Self { inner }
+    pub fn new(inner: impl Into<SmtpHandle>) -> Self {
+        Self {
+            inner: inner.into(),
+        }
     }
 }
 
@@ -92,22 +116,38 @@ impl SmtpAdminSessionManager {
 }
 
 pub struct SMTP {
-    pub worker_pool: rayon::ThreadPool,
+    pub worker_pool: Arc<rayon::ThreadPool>,
     pub session: SessionCore,
     pub queue: QueueCore,
-    pub resolvers: Resolvers,
+    pub resolvers: Arc<Resolvers>,
     pub mail_auth: MailAuthConfig,
     pub report: ReportCore,
     pub sieve: SieveCore,
+    pub webhook: Arc<WebhookCore>,
+    pub tracking: Arc<TrackingCore>,
+    pub usage: UsageCore,
+    pub reputation: ReputationCore,
+    pub suppression: SuppressionCore,
+    pub anomaly: AnomalyCore,
+    pub dkim_replay: DkimReplayCore,
+    pub unsubscribe: UnsubscribeConfig,
+    pub geoip: Arc<GeoIpCore>,
     #[cfg(feature = "local_delivery")]
     pub delivery_tx: mpsc::Sender<DeliveryEvent>,
 }
 
+impl std::fmt::Debug for SMTP {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SMTP").finish_non_exhaustive()
+    }
+}
+
 pub struct SieveCore {
     pub runtime: Runtime<SieveContext>,
     pub scripts: AHashMap<String, Arc<Sieve>>,
     pub lookup: AHashMap<String, Arc<Lookup>>,
     pub config: SieveConfig,
+    pub shadow: Arc<ShadowReport>,
 }
 
 pub struct SieveConfig {
@@ -122,25 +162,31 @@ pub struct Resolvers {
     pub dns: Resolver,
     pub dnssec: DnssecResolver,
     pub cache: DnsCache,
+    pub overrides: Vec<DnsOverride>,
 }
 
 pub struct DnsCache {
     pub tlsa: LruCache<String, Arc<Tlsa>>,
     pub mta_sts: LruCache<String, Arc<mta_sts::Policy>>,
+    pub bimi: LruCache<String, Arc<VmcStatus>>,
 }
 
 pub struct SessionCore {
     pub config: SessionConfig,
-    pub throttle: DashMap<ThrottleKey, Limiter, ThrottleKeyHasherBuilder>,
+    pub throttle: Arc<DashMap<ThrottleKey, Limiter, ThrottleKeyHasherBuilder>>,
+    pub connections: Arc<ConnectionLimiter>,
 }
 
 pub struct QueueCore {
     pub config: QueueConfig,
-    pub throttle: DashMap<ThrottleKey, Limiter, ThrottleKeyHasherBuilder>,
-    pub quota: DashMap<ThrottleKey, Arc<QuotaLimiter>, ThrottleKeyHasherBuilder>,
+    pub throttle: Arc<DashMap<ThrottleKey, Limiter, ThrottleKeyHasherBuilder>>,
+    pub quota: Arc<DashMap<ThrottleKey, Arc<QuotaLimiter>, ThrottleKeyHasherBuilder>>,
     pub tx: mpsc::Sender<queue::Event>,
-    pub id_seq: AtomicU32,
+    pub id_seq: Arc<AtomicU32>,
     pub connectors: TlsConnectors,
+    pub ipv6_fallback: Arc<DashMap<String, Instant>>,
+    pub connections: Arc<ConnectionPool>,
+    pub load: Arc<QueueLoad>,
 }
 
 pub struct ReportCore {
@@ -148,6 +194,54 @@ pub struct ReportCore {
     pub tx: mpsc::Sender<reporting::Event>,
 }
 
+pub struct WebhookCore {
+    pub config: WebhookConfig,
+    pub id_seq: AtomicU64,
+    pub tx: AHashMap<String, mpsc::Sender<webhook::Event>>,
+}
+
+pub struct TrackingCore {
+    pub config: TrackingConfig,
+    pub tx: mpsc::Sender<tracking::Event>,
+    pub index: Arc<parking_lot::RwLock<TrackingIndex>>,
+}
+
+pub struct UsageCore {
+    pub config: UsageConfig,
+    pub counters: Arc<DashMap<UsageKey, UsageCounter>>,
+}
+
+pub struct AnomalyCore {
+    pub config: AnomalyConfig,
+    pub accounts: Arc<DashMap<String, AccountActivity>>,
+    pub senders: Arc<DashMap<String, String>>,
+    pub webhook: Arc<WebhookCore>,
+    pub geoip: Arc<GeoIpCore>,
+    #[cfg(feature = "local_delivery")]
+    pub delivery_tx: mpsc::Sender<DeliveryEvent>,
+}
+
+pub struct GeoIpCore {
+    pub config: GeoIpConfig,
+    pub databases: RwLock<GeoIpDatabases>,
+}
+
+pub struct ReputationCore {
+    pub config: ReputationConfig,
+    pub entries: Arc<DashMap<IpAddr, ReputationEntry>>,
+}
+
+pub struct SuppressionCore {
+    pub config: SuppressionConfig,
+    pub entries: Arc<DashMap<String, SuppressionEntry>>,
+}
+
+pub struct DkimReplayCore {
+    pub config: DkimReplayConfig,
+    pub entries: Arc<DashMap<DkimReplayKey, DkimReplayEntry>>,
+    pub geoip: Arc<GeoIpCore>,
+}
+
 pub struct TlsConnectors {
     pub pki_verify: TlsConnector,
     pub dummy_verify: TlsConnector,
@@ -188,6 +282,7 @@ pub struct SessionData {
 
     pub authenticated_as: String,
     pub auth_errors: usize,
+    pub client_cert_identity: String,
 
     pub priority: i16,
     pub delivery_by: i64,
@@ -201,6 +296,8 @@ pub struct SessionData {
     pub spf_ehlo: Option<SpfOutput>,
     pub spf_mail_from: Option<SpfOutput>,
     pub dnsbl_error: Option<Vec<u8>>,
+    pub geo: GeoIpInfo,
+    pub reputation: Option<ReputationEntry>,
 }
 
 #[derive(Clone)]
@@ -216,6 +313,8 @@ pub struct SessionAddress {
 pub struct SessionParameters {
     // Global parameters
     pub timeout: Duration,
+    pub tarpit: Option<Duration>,
+    pub early_talker: bool,
 
     // Ehlo parameters
     pub ehlo_require: bool,
@@ -227,20 +326,29 @@ pub struct SessionParameters {
     pub auth_errors_max: usize,
     pub auth_errors_wait: Duration,
     pub auth_plain_text: bool,
+    pub auth_client_cert: bool,
+    pub auth_client_cert_require: bool,
 
     // Rcpt parameters
     pub rcpt_errors_max: usize,
     pub rcpt_errors_wait: Duration,
+    pub rcpt_errors_tarpit: Option<Duration>,
     pub rcpt_max: usize,
+    pub rcpt_retry_hint: Option<Duration>,
     pub rcpt_dsn: bool,
     pub can_expn: bool,
     pub can_vrfy: bool,
     pub max_message_size: usize,
+    pub declared_size: usize,
 
     // Mail authentication parameters
     pub iprev: VerifyStrategy,
     pub spf_ehlo: VerifyStrategy,
     pub spf_mail_from: VerifyStrategy,
+
+    // Trusted upstream parameters
+    pub xclient: bool,
+    pub xforward: bool,
 }
 
 impl SessionData {
@@ -253,6 +361,7 @@ impl SessionData {
             mail_from: None,
             rcpt_to: Vec::new(),
             authenticated_as: String::new(),
+            client_cert_identity: String::new(),
             priority: 0,
             valid_until: Instant::now(),
             rcpt_errors: 0,
@@ -266,6 +375,8 @@ impl SessionData {
             spf_ehlo: None,
             spf_mail_from: None,
             dnsbl_error: None,
+            geo: GeoIpInfo::default(),
+            reputation: None,
         }
     }
 }
@@ -374,6 +485,10 @@ impl crate::inbound::IsTls for NullIo {
     fn tls_version_and_cipher(&self) -> (&'static str, &'static str) {
         ("", "")
     }
+
+    fn peer_certificates(&self) -> Option<&[rustls::Certificate]> {
+        None
+    }
 }
 
 #[cfg(feature = "local_delivery")]
@@ -385,6 +500,8 @@ static ref SIEVE: Arc<ServerInstance> = Arc::new(utils::listener::ServerInstance
     hostname: "localhost".to_string(),
     data: "localhost".to_string(),
     tls_acceptor: None,
+    acme: vec![],
+    tenants: Default::default(),
     is_tls_implicit: true,
     limiter: utils::listener::limiter::ConcurrencyLimiter::new(0),
     shutdown_rx: tokio::sync::watch::channel(false).1,
@@ -421,6 +538,8 @@ impl Session<NullIo> {
             data,
             params: SessionParameters {
                 timeout: Default::default(),
+                tarpit: None,
+                early_talker: false,
                 ehlo_require: Default::default(),
                 ehlo_reject_non_fqdn: Default::default(),
                 auth_directory: Default::default(),
@@ -428,16 +547,23 @@ impl Session<NullIo> {
                 auth_errors_max: Default::default(),
                 auth_errors_wait: Default::default(),
                 auth_plain_text: false,
+                auth_client_cert: false,
+                auth_client_cert_require: false,
                 rcpt_errors_max: Default::default(),
                 rcpt_errors_wait: Default::default(),
+                rcpt_errors_tarpit: None,
                 rcpt_max: Default::default(),
+                rcpt_retry_hint: Default::default(),
                 rcpt_dsn: Default::default(),
                 max_message_size: Default::default(),
+                declared_size: Default::default(),
                 iprev: crate::config::VerifyStrategy::Disable,
                 spf_ehlo: crate::config::VerifyStrategy::Disable,
                 spf_mail_from: crate::config::VerifyStrategy::Disable,
                 can_expn: false,
                 can_vrfy: false,
+                xclient: false,
+                xforward: false,
             },
             in_flight: vec![],
         }
@@ -489,6 +615,7 @@ impl SessionData {
             message,
             authenticated_as: "local".into(),
             auth_errors: 0,
+            client_cert_identity: String::new(),
             priority: 0,
             delivery_by: 0,
             future_release: 0,
@@ -499,6 +626,8 @@ impl SessionData {
             spf_ehlo: None,
             spf_mail_from: None,
             dnsbl_error: None,
+            geo: GeoIpInfo::default(),
+            reputation: None,
         }
     }
 }
@@ -32,6 +32,7 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
         self.data.valid_until += *c.duration.eval(self).await;
 
         self.params.timeout = *c.timeout.eval(self).await;
+        self.params.tarpit = *c.connect.tarpit.eval(self).await;
         self.params.spf_ehlo = *self.core.mail_auth.spf.verify_ehlo.eval(self).await;
         self.params.spf_mail_from = *self.core.mail_auth.spf.verify_mail_from.eval(self).await;
         self.params.iprev = *self.core.mail_auth.iprev.verify.eval(self).await;
@@ -48,11 +49,17 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
         self.params.auth_errors_max = *ac.errors_max.eval(self).await;
         self.params.auth_errors_wait = *ac.errors_wait.eval(self).await;
         self.params.auth_plain_text = *ac.allow_plain_text.eval(self).await;
+        self.params.auth_client_cert = *ac.client_cert.eval(self).await;
+        self.params.auth_client_cert_require = *ac.client_cert_require.eval(self).await;
 
         // VRFY/EXPN parameters
         let ec = &self.core.session.config.extensions;
         self.params.can_expn = *ec.expn.eval(self).await;
         self.params.can_vrfy = *ec.vrfy.eval(self).await;
+
+        // XCLIENT/XFORWARD parameters
+        self.params.xclient = *ec.xclient.eval(self).await;
+        self.params.xforward = *ec.xforward.eval(self).await;
     }
 
     pub async fn eval_post_auth_params(&mut self) {
@@ -66,7 +73,9 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
         let rc = &self.core.session.config.rcpt;
         self.params.rcpt_errors_max = *rc.errors_max.eval(self).await;
         self.params.rcpt_errors_wait = *rc.errors_wait.eval(self).await;
+        self.params.rcpt_errors_tarpit = *rc.errors_tarpit.eval(self).await;
         self.params.rcpt_max = *rc.max_recipients.eval(self).await;
+        self.params.rcpt_retry_hint = *rc.retry_hint.eval(self).await;
         self.params.rcpt_dsn = *self.core.session.config.extensions.dsn.eval(self).await;
 
         self.params.max_message_size = *self
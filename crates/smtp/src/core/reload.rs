@@ -0,0 +1,193 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use ahash::AHashSet;
+use directory::DirectoryConfig;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use utils::config::{Config, ConfigSource};
+
+use crate::{queue, reporting};
+
+use super::SMTP;
+
+// Top-level configuration sections that are only read on startup. A reload
+// rebuilds the SMTP core (session and queue policies, reporting, sieve scripts
+// and DKIM signers), changes to listeners, certificates, OAuth or the JMAP and
+// IMAP settings are reported back as requiring a restart.
+static RESTART_REQUIRED: &[&str] = &[
+    "acme",
+    "certificate",
+    "directory",
+    "global",
+    "imap",
+    "jmap",
+    "oauth",
+    "resolver",
+    "server",
+    "store",
+    "tenant",
+    "tracing",
+    "webhook",
+];
+
+#[derive(Clone)]
+pub struct SmtpHandle {
+    core: Arc<RwLock<Arc<SMTP>>>,
+    reload: Option<Arc<ReloadContext>>,
+}
+
+struct ReloadContext {
+    source: ConfigSource,
+    directory: DirectoryConfig,
+    config: Mutex<Config>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ReloadResult {
+    pub restart_required: Vec<String>,
+}
+
+impl SmtpHandle {
+    pub fn new(core: Arc<SMTP>) -> Self {
+        SmtpHandle {
+            core: Arc::new(RwLock::new(core)),
+            reload: None,
+        }
+    }
+
+    pub fn with_reload(
+        core: Arc<SMTP>,
+        source: ConfigSource,
+        config: Config,
+        directory: DirectoryConfig,
+    ) -> Self {
+        SmtpHandle {
+            core: Arc::new(RwLock::new(core)),
+            reload: Some(Arc::new(ReloadContext {
+                source,
+                directory,
+                config: Mutex::new(config),
+            })),
+        }
+    }
+
+    pub fn core(&self) -> Arc<SMTP> {
+        self.core.read().clone()
+    }
+
+    // Re-reads the configuration files and replaces the active core. Sessions
+    // that are already in progress keep using the core they were created with.
+    pub async fn reload(&self) -> Result<ReloadResult, String> {
+        let reload = self
+            .reload
+            .as_ref()
+            .ok_or_else(|| "Configuration reload is not available.".to_string())?;
+        let mut current_config = reload.config.lock().await;
+
+        let config = reload.source.load()?;
+        let servers = config.parse_servers()?;
+        let core = self
+            .core()
+            .reload(&config, &servers.inner, &reload.directory)?;
+
+        // Hand over the new core to the queue and report managers
+        if core
+            .queue
+            .tx
+            .send(queue::Event::Reload(core.clone()))
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                context = "reload",
+                event = "error",
+                "Failed to notify queue manager of configuration reload."
+            );
+        }
+        if core
+            .report
+            .tx
+            .send(reporting::Event::Reload(core.clone()))
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                context = "reload",
+                event = "error",
+                "Failed to notify report manager of configuration reload."
+            );
+        }
+        *self.core.write() = core;
+
+        let result = ReloadResult {
+            restart_required: changed_sections(&current_config, &config)
+                .into_iter()
+                .filter(|section| RESTART_REQUIRED.contains(&section.as_str()))
+                .collect(),
+        };
+        *current_config = config;
+
+        tracing::info!(
+            context = "reload",
+            event = "success",
+            restart_required = ?result.restart_required,
+            "Configuration reloaded."
+        );
+
+        Ok(result)
+    }
+}
+
+impl From<Arc<SMTP>> for SmtpHandle {
+    fn from(core: Arc<SMTP>) -> Self {
+        SmtpHandle::new(core)
+    }
+}
+
+fn changed_sections(old: &Config, new: &Config) -> Vec<String> {
+    let mut sections = AHashSet::new();
+    for (key, value) in &old.keys {
+        if new.keys.get(key) != Some(value) {
+            sections.insert(section_name(key));
+        }
+    }
+    for key in new.keys.keys() {
+        if !old.keys.contains_key(key) {
+            sections.insert(section_name(key));
+        }
+    }
+
+    let mut sections = sections.into_iter().collect::<Vec<_>>();
+    sections.sort_unstable();
+    sections
+}
+
+fn section_name(key: &str) -> String {
+    key.split_once('.')
+        .map_or(key, |(section, _)| section)
+        .to_string()
+}
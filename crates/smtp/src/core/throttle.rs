@@ -22,7 +22,7 @@
 */
 
 use ::utils::listener::limiter::{ConcurrencyLimiter, RateLimiter};
-use dashmap::mapref::entry::Entry;
+use dashmap::{mapref::entry::Entry, DashMap};
 use tokio::io::{AsyncRead, AsyncWrite};
 use utils::config::{KeyLookup, Rate};
 
@@ -34,7 +34,7 @@ use std::{
 
 use crate::config::*;
 
-use super::Session;
+use super::{QueueCore, Session};
 
 #[derive(Debug)]
 pub struct Limiter {
@@ -294,32 +294,47 @@ impl<T: AsyncRead + AsyncWrite> Session<T> {
     }
 
     pub fn throttle_rcpt(&self, rcpt: &str, rate: &Rate, ctx: &str) -> bool {
-        let mut hasher = blake3::Hasher::new();
-        hasher.update(rcpt.as_bytes());
-        hasher.update(ctx.as_bytes());
-        hasher.update(&rate.period.as_secs().to_ne_bytes()[..]);
-        hasher.update(&rate.requests.to_ne_bytes()[..]);
-        let key = ThrottleKey {
-            hash: hasher.finalize().into(),
-        };
+        is_rate_allowed(&self.core.session.throttle, rcpt, rate, ctx)
+    }
+}
 
-        match self.core.session.throttle.entry(key) {
-            Entry::Occupied(mut e) => {
-                if let Some(limiter) = &mut e.get_mut().rate {
-                    limiter.is_allowed()
-                } else {
-                    false
-                }
-            }
-            Entry::Vacant(e) => {
-                let mut limiter = RateLimiter::new(rate.requests, rate.period);
-                limiter.is_allowed();
-                e.insert(Limiter {
-                    rate: limiter.into(),
-                    concurrency: None,
-                });
-                true
+impl QueueCore {
+    pub fn throttle_rcpt(&self, rcpt: &str, rate: &Rate, ctx: &str) -> bool {
+        is_rate_allowed(&self.throttle, rcpt, rate, ctx)
+    }
+}
+
+fn is_rate_allowed(
+    throttle: &DashMap<ThrottleKey, Limiter, ThrottleKeyHasherBuilder>,
+    rcpt: &str,
+    rate: &Rate,
+    ctx: &str,
+) -> bool {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(rcpt.as_bytes());
+    hasher.update(ctx.as_bytes());
+    hasher.update(&rate.period.as_secs().to_ne_bytes()[..]);
+    hasher.update(&rate.requests.to_ne_bytes()[..]);
+    let key = ThrottleKey {
+        hash: hasher.finalize().into(),
+    };
+
+    match throttle.entry(key) {
+        Entry::Occupied(mut e) => {
+            if let Some(limiter) = &mut e.get_mut().rate {
+                limiter.is_allowed()
+            } else {
+                false
             }
         }
+        Entry::Vacant(e) => {
+            let mut limiter = RateLimiter::new(rate.requests, rate.period);
+            limiter.is_allowed();
+            e.insert(Limiter {
+                rate: limiter.into(),
+                concurrency: None,
+            });
+            true
+        }
     }
 }
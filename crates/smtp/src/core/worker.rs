@@ -66,6 +66,10 @@ impl SMTP {
         self.queue.quota.retain(|_, v| {
             v.messages.load(Ordering::Relaxed) > 0 || v.size.load(Ordering::Relaxed) > 0
         });
+        self.session.connections.cleanup();
+        self.reputation.cleanup();
+        self.suppression.cleanup();
+        self.dkim_replay.cleanup();
     }
 }
 
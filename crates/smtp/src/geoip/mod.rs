@@ -0,0 +1,183 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::IpAddr,
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use maxminddb::{geoip2, Reader};
+use tokio::fs;
+
+use crate::{config::GeoIpConfig, core::GeoIpCore};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeoIpInfo {
+    pub country: Option<String>,
+    pub asn: Option<u32>,
+    pub asn_org: Option<String>,
+}
+
+#[derive(Default)]
+pub struct GeoIpDatabases {
+    country: Option<Arc<GeoIpDatabase>>,
+    asn: Option<Arc<GeoIpDatabase>>,
+}
+
+pub struct GeoIpDatabase {
+    reader: Reader<Vec<u8>>,
+    modified: Option<SystemTime>,
+}
+
+impl GeoIpCore {
+    pub async fn open(config: GeoIpConfig) -> Result<Self, String> {
+        let mut databases = GeoIpDatabases::default();
+        if let Some(path) = &config.country {
+            databases.country = GeoIpDatabase::open(path).await?.into();
+        }
+        if let Some(path) = &config.asn {
+            databases.asn = GeoIpDatabase::open(path).await?.into();
+        }
+
+        Ok(GeoIpCore {
+            config,
+            databases: RwLock::new(databases),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.country.is_some() || self.config.asn.is_some()
+    }
+
+    pub fn lookup(&self, ip: IpAddr) -> GeoIpInfo {
+        let (country_db, asn_db) = {
+            let databases = self.databases.read();
+            (databases.country.clone(), databases.asn.clone())
+        };
+        let mut info = GeoIpInfo::default();
+
+        if let Some(db) = country_db {
+            if let Ok(result) = db.reader.lookup::<geoip2::Country>(ip) {
+                info.country = result
+                    .country
+                    .and_then(|country| country.iso_code)
+                    .map(|code| code.to_string());
+            }
+        }
+        if let Some(db) = asn_db {
+            if let Ok(result) = db.reader.lookup::<geoip2::Asn>(ip) {
+                info.asn = result.autonomous_system_number;
+                info.asn_org = result
+                    .autonomous_system_organization
+                    .map(|org| org.to_string());
+            }
+        }
+
+        info
+    }
+
+    // Replaces the databases whose files were modified since they were loaded,
+    // the previous database is kept if the new one cannot be read.
+    pub async fn reload(&self) {
+        for (path, is_country) in [(&self.config.country, true), (&self.config.asn, false)] {
+            let path = if let Some(path) = path {
+                path
+            } else {
+                continue;
+            };
+            let modified = fs::metadata(path)
+                .await
+                .and_then(|metadata| metadata.modified())
+                .ok();
+            let current = {
+                let databases = self.databases.read();
+                if is_country {
+                    databases.country.clone()
+                } else {
+                    databases.asn.clone()
+                }
+            };
+            if modified.is_none() || current.map_or(false, |db| db.modified == modified) {
+                continue;
+            }
+
+            match GeoIpDatabase::open(path).await {
+                Ok(db) => {
+                    tracing::info!(
+                        context = "geoip",
+                        event = "reload",
+                        path = %path.display(),
+                        "GeoIP database reloaded."
+                    );
+                    let mut databases = self.databases.write();
+                    if is_country {
+                        databases.country = Some(db);
+                    } else {
+                        databases.asn = Some(db);
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        context = "geoip",
+                        event = "error",
+                        path = %path.display(),
+                        reason = %err,
+                        "Failed to reload GeoIP database."
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn reload_frequency(&self) -> Option<Duration> {
+        if self.is_enabled() {
+            Some(self.config.reload_frequency)
+        } else {
+            None
+        }
+    }
+}
+
+impl GeoIpDatabase {
+    async fn open(path: &Path) -> Result<Arc<Self>, String> {
+        let modified = fs::metadata(path)
+            .await
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let bytes = fs::read(path)
+            .await
+            .map_err(|err| format!("Failed to read GeoIP database {}: {}", path.display(), err))?;
+        let reader = Reader::from_source(bytes)
+            .map_err(|err| format!("Failed to parse GeoIP database {}: {}", path.display(), err))?;
+
+        Ok(Arc::new(GeoIpDatabase { reader, modified }))
+    }
+}
+
+impl GeoIpInfo {
+    pub fn is_empty(&self) -> bool {
+        self.country.is_none() && self.asn.is_none()
+    }
+}
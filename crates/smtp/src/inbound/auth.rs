@@ -21,16 +21,25 @@
  * for more details.
 */
 
+use directory::scram::{ScramAlgorithm, ScramServer};
+use mail_builder::encoders::base64::base64_encode;
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
-use smtp_proto::{IntoString, AUTH_LOGIN, AUTH_OAUTHBEARER, AUTH_PLAIN, AUTH_XOAUTH2};
+use smtp_proto::{
+    IntoString, AUTH_LOGIN, AUTH_OAUTHBEARER, AUTH_PLAIN, AUTH_SCRAM_SHA_1, AUTH_SCRAM_SHA_256,
+    AUTH_XOAUTH2,
+};
 use tokio::io::{AsyncRead, AsyncWrite};
+use utils::{auth_log::log_auth_failure, config::certificate::client_certificate_identities};
 
-use crate::core::Session;
+use crate::{anomaly::secret_fingerprint, core::Session, reputation::ReputationEvent};
+
+use super::IsTls;
 
 pub struct SaslToken {
     mechanism: u64,
     credentials: Credentials<String>,
+    scram: Option<ScramServer>,
 }
 
 impl SaslToken {
@@ -42,6 +51,7 @@ impl SaslToken {
                     username: String::new(),
                     secret: String::new(),
                 },
+                scram: None,
             }
             .into(),
             AUTH_OAUTHBEARER => SaslToken {
@@ -49,6 +59,7 @@ impl SaslToken {
                 credentials: Credentials::OAuthBearer {
                     token: String::new(),
                 },
+                scram: None,
             }
             .into(),
             AUTH_XOAUTH2 => SaslToken {
@@ -57,6 +68,21 @@ impl SaslToken {
                     username: String::new(),
                     secret: String::new(),
                 },
+                scram: None,
+            }
+            .into(),
+            AUTH_SCRAM_SHA_1 | AUTH_SCRAM_SHA_256 => SaslToken {
+                mechanism,
+                credentials: Credentials::Plain {
+                    username: String::new(),
+                    secret: String::new(),
+                },
+                scram: ScramServer::new(if mechanism == AUTH_SCRAM_SHA_256 {
+                    ScramAlgorithm::Sha256
+                } else {
+                    ScramAlgorithm::Sha1
+                })
+                .into(),
             }
             .into(),
             _ => None,
@@ -64,7 +90,7 @@ impl SaslToken {
     }
 }
 
-impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
+impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
     pub async fn handle_sasl_response(
         &mut self,
         token: &mut SaslToken,
@@ -82,6 +108,35 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                         return Ok(true);
                     }
                 }
+                (AUTH_SCRAM_SHA_1 | AUTH_SCRAM_SHA_256, _) => {
+                    if let Some(scram) = &token.scram {
+                        if let Some(authenticated_as) = scram.authenticated_as() {
+                            // Client acknowledged the server signature
+                            let authenticated_as = authenticated_as.to_string();
+                            #[cfg(feature = "local_delivery")]
+                            match self.requires_second_factor(&authenticated_as).await {
+                                Ok(false) => (),
+                                Ok(true) => {
+                                    log_auth_failure(self.data.remote_ip, &authenticated_as);
+                                    return self
+                                        .auth_error(
+                                            b"535 5.7.8 Account requires an app password or one-time code.\r\n",
+                                        )
+                                        .await;
+                                }
+                                Err(_) => {
+                                    self.write(b"454 4.7.0 Temporary authentication failure\r\n")
+                                        .await?;
+                                    return Ok(false);
+                                }
+                            }
+                            return self.auth_success(authenticated_as).await;
+                        } else if scram.is_initial() {
+                            self.write(b"334 \r\n").await?;
+                            return Ok(true);
+                        }
+                    }
+                }
                 _ => (),
             }
         } else if let Some(response) = base64_decode(response) {
@@ -123,6 +178,11 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
                             .await
                     };
                 }
+                (AUTH_SCRAM_SHA_1 | AUTH_SCRAM_SHA_256, _) => {
+                    if let Some(scram) = &mut token.scram {
+                        return self.handle_scram_response(scram, &response).await;
+                    }
+                }
                 (AUTH_OAUTHBEARER, Credentials::OAuthBearer { token: token_ }) => {
                     let response = response.into_string();
                     if response.contains("auth=") {
@@ -173,29 +233,139 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         self.auth_error(b"500 5.5.6 Invalid challenge.\r\n").await
     }
 
+    async fn handle_scram_response(
+        &mut self,
+        scram: &mut ScramServer,
+        response: &[u8],
+    ) -> Result<bool, ()> {
+        let mechanism = scram.algorithm().name();
+        let challenge = if scram.is_client_final() {
+            match scram.client_final(response) {
+                Ok(challenge) => challenge,
+                Err(err) => {
+                    tracing::debug!(
+                        parent: &self.span,
+                        context = "auth",
+                        event = "authenticate",
+                        mechanism = mechanism,
+                        result = "failed",
+                        reason = err
+                    );
+                    return self
+                        .auth_error(b"535 5.7.8 Authentication credentials invalid.\r\n")
+                        .await;
+                }
+            }
+        } else {
+            let username = match scram.client_first(response) {
+                Ok(username) => username.to_string(),
+                Err(err) => {
+                    tracing::debug!(
+                        parent: &self.span,
+                        context = "auth",
+                        event = "error",
+                        mechanism = mechanism,
+                        reason = err
+                    );
+                    return self.auth_error(b"500 5.5.6 Invalid challenge.\r\n").await;
+                }
+            };
+
+            // Obtain the SCRAM verifier for the account
+            let principal = match &self.params.auth_directory {
+                Some(lookup) => lookup.principal(&username).await,
+                None => Err(directory::DirectoryError::Unsupported),
+            };
+            match principal {
+                Ok(principal) => match scram.server_first(
+                    principal.and_then(|principal| principal.scram_verifier(scram.algorithm())),
+                ) {
+                    Ok(challenge) => challenge,
+                    Err(_) => {
+                        return self.auth_error(b"500 5.5.6 Invalid challenge.\r\n").await;
+                    }
+                },
+                Err(_) => {
+                    self.write(b"454 4.7.0 Temporary authentication failure\r\n")
+                        .await?;
+                    return Ok(false);
+                }
+            }
+        };
+
+        let mut buf = Vec::with_capacity(challenge.len() * 2);
+        buf.extend_from_slice(b"334 ");
+        buf.extend_from_slice(&base64_encode(&challenge).unwrap_or_default());
+        buf.extend_from_slice(b"\r\n");
+        self.write(&buf).await?;
+        Ok(true)
+    }
+
     pub async fn authenticate(&mut self, credentials: Credentials<String>) -> Result<bool, ()> {
         if let Some(lookup) = &self.params.auth_directory {
-            let authenticated_as = match &credentials {
-                Credentials::Plain { username, .. }
-                | Credentials::XOauth2 { username, .. }
-                | Credentials::OAuthBearer { token: username } => username.to_string(),
+            let username = match &credentials {
+                Credentials::Plain { username, .. } | Credentials::XOauth2 { username, .. } => {
+                    username.to_string()
+                }
+                Credentials::OAuthBearer { token } => {
+                    oauthbearer_authzid(token).unwrap_or_default()
+                }
             };
-            if let Ok(is_authenticated) =
-                lookup.authenticate(&credentials).await.map(|r| r.is_some())
-            {
+            let result = match credentials {
+                #[cfg(feature = "local_delivery")]
+                Credentials::OAuthBearer { token } => match bearer_token(&token) {
+                    Some(token) => self.validate_token(token.to_string()).await.map(|account| {
+                        account.filter(|account| username.is_empty() || account == &username)
+                    }),
+                    None => Ok(None),
+                },
+                #[cfg(feature = "local_delivery")]
+                Credentials::XOauth2 { username, secret } => self
+                    .validate_token(secret)
+                    .await
+                    .map(|account| account.filter(|account| account == &username)),
+                #[cfg(feature = "local_delivery")]
+                Credentials::Plain { username, secret } => {
+                    match self.authenticate_account(&username, &secret).await {
+                        Some(result) => result,
+                        None => {
+                            let authenticated_as = username.clone();
+                            lookup
+                                .authenticate(&Credentials::Plain { username, secret })
+                                .await
+                                .map(|r| r.map(|_| authenticated_as))
+                                .map_err(|_| ())
+                        }
+                    }
+                }
+                #[cfg(not(feature = "local_delivery"))]
+                credentials => {
+                    let authenticated_as = match &credentials {
+                        Credentials::Plain { username, .. }
+                        | Credentials::XOauth2 { username, .. }
+                        | Credentials::OAuthBearer { token: username } => username.to_string(),
+                    };
+                    lookup
+                        .authenticate(&credentials)
+                        .await
+                        .map(|r| r.map(|_| authenticated_as))
+                        .map_err(|_| ())
+                }
+            };
+
+            if let Ok(authenticated_as) = result {
                 tracing::debug!(
                     parent: &self.span,
                     context = "auth",
                     event = "authenticate",
-                    result = if is_authenticated {"success"} else {"failed"}
+                    result = if authenticated_as.is_some() {"success"} else {"failed"},
+                    country = self.data.geo.country.as_deref().unwrap_or_default(),
+                    asn = self.data.geo.asn.unwrap_or_default(),
                 );
-                return if is_authenticated {
-                    self.data.authenticated_as = authenticated_as;
-                    self.eval_post_auth_params().await;
-                    self.write(b"235 2.7.0 Authentication succeeded.\r\n")
-                        .await?;
-                    Ok(false)
+                return if let Some(authenticated_as) = authenticated_as {
+                    self.auth_success(authenticated_as).await
                 } else {
+                    log_auth_failure(self.data.remote_ip, &username);
                     self.auth_error(b"535 5.7.8 Authentication credentials invalid.\r\n")
                         .await
                 };
@@ -214,9 +384,181 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         Ok(false)
     }
 
+    pub async fn authenticate_client_cert(&mut self) {
+        let Some(cert) = self
+            .stream
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+        else {
+            return;
+        };
+
+        // Only certificates with an explicit identity or fingerprint mapping are accepted
+        let identities = client_certificate_identities(cert);
+        if let Some((identity, account)) = self
+            .core
+            .session
+            .config
+            .auth
+            .client_cert_map
+            .account(&identities)
+        {
+            tracing::debug!(
+                parent: &self.span,
+                context = "auth",
+                event = "client-cert",
+                identity = identity,
+                authenticated_as = account,
+            );
+            let identity = identity.to_string();
+            if self.accept_login(account.to_string()).await {
+                self.data.client_cert_identity = identity;
+            }
+        } else {
+            tracing::debug!(
+                parent: &self.span,
+                context = "auth",
+                event = "client-cert",
+                result = "unmapped",
+                identities = ?identities,
+            );
+        }
+    }
+
+    async fn auth_success(&mut self, authenticated_as: String) -> Result<bool, ()> {
+        if self.accept_login(authenticated_as).await {
+            self.write(b"235 2.7.0 Authentication succeeded.\r\n")
+                .await?;
+        } else {
+            self.write(b"535 5.7.8 Account locked, please contact your administrator.\r\n")
+                .await?;
+        }
+        Ok(false)
+    }
+
+    // Shared by all authentication mechanisms, including client certificates
+    async fn accept_login(&mut self, authenticated_as: String) -> bool {
+        // Accounts flagged as compromised need their password reset
+        let secret = if self.core.anomaly.config.enable {
+            match &self.params.auth_directory {
+                Some(lookup) => lookup
+                    .principal(&authenticated_as)
+                    .await
+                    .ok()
+                    .flatten()
+                    .map(|principal| secret_fingerprint(&principal.secrets)),
+                None => None,
+            }
+        } else {
+            None
+        };
+        if !self
+            .core
+            .anomaly
+            .is_login_allowed(&authenticated_as, secret.as_deref())
+        {
+            tracing::info!(
+                parent: &self.span,
+                context = "anomaly",
+                event = "locked",
+                account = authenticated_as,
+                "Login to contained account refused."
+            );
+            return false;
+        }
+        self.core
+            .anomaly
+            .record_login(&authenticated_as, self.data.remote_ip, secret)
+            .await;
+        self.data.authenticated_as = authenticated_as;
+        self.eval_post_auth_params().await;
+        true
+    }
+
+    #[cfg(feature = "local_delivery")]
+    async fn validate_token(&self, token: String) -> Result<Option<String>, ()> {
+        let (result_tx, result_rx) = tokio::sync::oneshot::channel();
+        if self
+            .core
+            .delivery_tx
+            .send(utils::ipc::DeliveryEvent::ValidateToken { token, result_tx })
+            .await
+            .is_ok()
+        {
+            if let Ok(result) = result_rx.await {
+                return Ok(result);
+            }
+        }
+
+        tracing::warn!(
+            parent: &self.span,
+            context = "auth",
+            event = "error",
+            "Failed to validate access token: delivery channel closed."
+        );
+        Err(())
+    }
+
+    // Plain credentials are verified by the JMAP server so that app passwords and
+    // second factors apply to SMTP AUTH. Returns None when no JMAP server is running.
+    #[cfg(feature = "local_delivery")]
+    async fn authenticate_account(
+        &self,
+        username: &str,
+        secret: &str,
+    ) -> Option<Result<Option<String>, ()>> {
+        let (result_tx, result_rx) = tokio::sync::oneshot::channel();
+        self.core
+            .delivery_tx
+            .send(utils::ipc::DeliveryEvent::Authenticate {
+                username: username.to_string(),
+                secret: secret.to_string(),
+                result_tx,
+            })
+            .await
+            .ok()?;
+
+        Some(result_rx.await.map_err(|_| {
+            tracing::warn!(
+                parent: &self.span,
+                context = "auth",
+                event = "error",
+                "Failed to authenticate account: delivery channel closed."
+            );
+        }))
+    }
+
+    #[cfg(feature = "local_delivery")]
+    async fn requires_second_factor(&self, account: &str) -> Result<bool, ()> {
+        let (result_tx, result_rx) = tokio::sync::oneshot::channel();
+        if self
+            .core
+            .delivery_tx
+            .send(utils::ipc::DeliveryEvent::RequiresSecondFactor {
+                account: account.to_string(),
+                result_tx,
+            })
+            .await
+            .is_err()
+        {
+            // Second factors are managed by the JMAP server
+            return Ok(false);
+        }
+
+        result_rx.await.map_err(|_| {
+            tracing::warn!(
+                parent: &self.span,
+                context = "auth",
+                event = "error",
+                "Failed to check second factor: delivery channel closed."
+            );
+        })
+    }
+
     pub async fn auth_error(&mut self, response: &[u8]) -> Result<bool, ()> {
         tokio::time::sleep(self.params.auth_errors_wait).await;
         self.data.auth_errors += 1;
+        self.record_reputation(ReputationEvent::AuthFailure).await;
         self.write(response).await?;
         if self.data.auth_errors < self.params.auth_errors_max {
             Ok(false)
@@ -233,3 +575,24 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         }
     }
 }
+
+#[cfg(feature = "local_delivery")]
+fn bearer_token(response: &str) -> Option<&str> {
+    // Extract the bearer token from an OAUTHBEARER GS2 message
+    response
+        .split('\x01')
+        .find_map(|part| part.strip_prefix("auth=Bearer "))
+        .map(|token| token.trim())
+        .filter(|token| !token.is_empty())
+}
+
+// Returns the authorization identity from the GS2 header of an OAUTHBEARER message
+pub fn oauthbearer_authzid(response: &str) -> Option<String> {
+    response
+        .split('\x01')
+        .next()?
+        .split(',')
+        .find_map(|part| part.strip_prefix("a="))
+        .filter(|authzid| !authzid.is_empty())
+        .map(|authzid| authzid.replace("=2C", ",").replace("=3D", "="))
+}
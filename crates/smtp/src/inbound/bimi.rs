@@ -0,0 +1,648 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    fmt::Write,
+    io::Cursor,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use mail_auth::{dmarc::Policy, AuthenticatedMessage, DmarcResult};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite};
+use x509_parser::{
+    extensions::GeneralName,
+    prelude::{FromDer, X509Certificate},
+};
+
+use crate::{
+    config::VerifyStrategy,
+    core::{Session, SMTP},
+    webhook::now,
+};
+
+use super::transform::remove_headers;
+
+#[cfg(feature = "test_mode")]
+pub static BIMI_TEST_RECORDS: parking_lot::Mutex<Vec<(String, String)>> =
+    parking_lot::Mutex::new(Vec::new());
+#[cfg(feature = "test_mode")]
+pub static BIMI_TEST_VMC: parking_lot::Mutex<Vec<u8>> = parking_lot::Mutex::new(Vec::new());
+#[cfg(feature = "test_mode")]
+pub static BIMI_TEST_INDICATOR: parking_lot::Mutex<Vec<u8>> = parking_lot::Mutex::new(Vec::new());
+
+// Extended key usage of Verified Mark Certificates (id-kp-BrandIndicatorforMessageIdentification)
+const BIMI_EKU_OID: &str = "1.3.6.1.5.5.7.3.31";
+
+// Logotype extension (id-pe-logotype) carrying the hash of the mark
+const LOGOTYPE_OID: &str = "1.3.6.1.5.5.7.1.12";
+
+// DER encoding of the id-sha256 object identifier
+const SHA256_OID_DER: &[u8] = &[
+    0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01,
+];
+
+// Time during which the outcome of a VMC validation is cached
+const VMC_CACHE_TTL: Duration = Duration::from_secs(86400);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BimiRecord {
+    pub location: Option<String>,
+    pub authority: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BimiResult {
+    Pass,
+    None,
+    Declined,
+    Skipped(String),
+    Fail(String),
+    TempError(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BimiAuthority {
+    Pass,
+    None,
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BimiOutput {
+    pub result: BimiResult,
+    pub domain: String,
+    pub selector: String,
+    pub location: Option<String>,
+    pub authority_uri: Option<String>,
+    pub authority: BimiAuthority,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VmcStatus {
+    Valid,
+    Invalid(String),
+}
+
+impl BimiRecord {
+    pub fn parse(record: &str) -> Option<Self> {
+        let mut has_version = false;
+        let mut location = None;
+        let mut authority = None;
+
+        for (pos, tag) in record.split(';').enumerate() {
+            let tag = tag.trim();
+            if tag.is_empty() {
+                continue;
+            }
+            let (name, value) = tag.split_once('=')?;
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "v" if pos == 0 => {
+                    has_version = value.eq_ignore_ascii_case("BIMI1");
+                }
+                "l" if is_valid_uri(value) => {
+                    location = Some(value.to_string()).filter(|v| !v.is_empty());
+                }
+                "a" if is_valid_uri(value) => {
+                    authority = Some(value.to_string()).filter(|v| !v.is_empty());
+                }
+                "l" | "a" => return None,
+                _ => (),
+            }
+        }
+
+        if has_version {
+            Some(BimiRecord {
+                location,
+                authority,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl BimiResult {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BimiResult::Pass => "pass",
+            BimiResult::None => "none",
+            BimiResult::Declined => "declined",
+            BimiResult::Skipped(_) => "skipped",
+            BimiResult::Fail(_) => "fail",
+            BimiResult::TempError(_) => "temperror",
+        }
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            BimiResult::Skipped(reason)
+            | BimiResult::Fail(reason)
+            | BimiResult::TempError(reason) => Some(reason.as_str()),
+            _ => None,
+        }
+    }
+}
+
+impl BimiAuthority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BimiAuthority::Pass => "pass",
+            BimiAuthority::None => "none",
+            BimiAuthority::Fail => "fail",
+        }
+    }
+}
+
+impl BimiOutput {
+    fn new(domain: &str, selector: &str, result: BimiResult) -> Self {
+        BimiOutput {
+            result,
+            domain: domain.to_string(),
+            selector: selector.to_string(),
+            location: None,
+            authority_uri: None,
+            authority: BimiAuthority::None,
+        }
+    }
+
+    // Writes the BIMI result as a separate Authentication-Results header
+    // followed by the BIMI-Location header when the indicator was validated.
+    pub fn write_header(&self, hostname: &str, headers: &mut Vec<u8>) {
+        let mut header = format!(
+            "Authentication-Results: {hostname};\r\n\tbimi={}",
+            self.result.as_str()
+        );
+        if let Some(reason) = self.result.reason() {
+            let _ = write!(
+                header,
+                " reason=\"{}\"",
+                reason
+                    .chars()
+                    .filter(|ch| *ch == ' ' || (ch.is_ascii_graphic() && *ch != '\\'))
+                    .map(|ch| if ch == '"' { '\'' } else { ch })
+                    .collect::<String>()
+            );
+        }
+        let _ = write!(
+            header,
+            " header.d={} header.selector={}",
+            self.domain, self.selector
+        );
+        if self.result == BimiResult::Pass {
+            let _ = write!(header, " policy.authority={}", self.authority.as_str());
+            if let Some(authority_uri) = &self.authority_uri {
+                let _ = write!(header, " policy.authority-uri={authority_uri}");
+            }
+            if let Some(location) = &self.location {
+                let _ = write!(header, " policy.indicator-uri={location}");
+            }
+        }
+        header.push_str("\r\n");
+
+        if let (BimiResult::Pass, Some(location)) = (&self.result, &self.location) {
+            let _ = write!(header, "BIMI-Location: v=BIMI1;\r\n\tl={location}");
+            if let Some(authority_uri) = &self.authority_uri {
+                let _ = write!(header, ";\r\n\ta={authority_uri}");
+            }
+            header.push_str("\r\n");
+        }
+
+        headers.extend_from_slice(header.as_bytes());
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Session<T> {
+    pub async fn verify_bimi(
+        &self,
+        strategy: VerifyStrategy,
+        message: &AuthenticatedMessage<'_>,
+        dmarc_result: Option<&DmarcResult>,
+        dmarc_policy: Option<&Policy>,
+    ) -> Option<BimiOutput> {
+        let domain = message
+            .from()
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.trim().to_lowercase())
+            .filter(|domain| {
+                !domain.is_empty()
+                    && domain
+                        .chars()
+                        .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '.'))
+            })?;
+        let selector = message
+            .raw_parsed_headers()
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(b"BIMI-Selector"))
+            .and_then(|(_, value)| parse_selector(std::str::from_utf8(value).ok()?))
+            .unwrap_or_else(|| "default".to_string());
+
+        // Indicators are only displayed for domains with an enforced DMARC policy
+        if !matches!(dmarc_result, Some(DmarcResult::Pass)) {
+            return BimiOutput::new(
+                &domain,
+                &selector,
+                BimiResult::Skipped("DMARC did not pass".to_string()),
+            )
+            .into();
+        } else if !matches!(dmarc_policy, Some(Policy::Quarantine | Policy::Reject)) {
+            return BimiOutput::new(
+                &domain,
+                &selector,
+                BimiResult::Skipped("DMARC policy is not enforced".to_string()),
+            )
+            .into();
+        }
+
+        // Lookup BIMI record
+        let record = match self.core.lookup_bimi_record(&domain, &selector).await {
+            Ok(Some(record)) => record,
+            Ok(None) => return BimiOutput::new(&domain, &selector, BimiResult::None).into(),
+            Err(result) => return BimiOutput::new(&domain, &selector, result).into(),
+        };
+        let mut output = BimiOutput::new(&domain, &selector, BimiResult::Pass);
+        let location = match record.location {
+            Some(location) if location.starts_with("https://") => location,
+            Some(_) => {
+                output.result = BimiResult::Fail("Indicator location is not HTTPS".to_string());
+                return output.into();
+            }
+            None => {
+                output.result = BimiResult::Declined;
+                return output.into();
+            }
+        };
+
+        // Validate the evidence document
+        match record.authority {
+            Some(authority_uri) if authority_uri.starts_with("https://") => {
+                match self
+                    .core
+                    .validate_vmc(&authority_uri, &location, &domain, &selector)
+                    .await
+                {
+                    Ok(VmcStatus::Valid) => {
+                        output.authority = BimiAuthority::Pass;
+                    }
+                    Ok(VmcStatus::Invalid(reason)) => {
+                        output.authority = BimiAuthority::Fail;
+                        output.result = BimiResult::Fail(reason);
+                    }
+                    Err(reason) => {
+                        output.result = BimiResult::TempError(reason);
+                    }
+                }
+                output.authority_uri = authority_uri.into();
+            }
+            Some(_) => {
+                output.authority = BimiAuthority::Fail;
+                output.result = BimiResult::Fail("Evidence location is not HTTPS".to_string());
+            }
+            None if strategy.is_strict() => {
+                output.result = BimiResult::Fail("Missing evidence document".to_string());
+            }
+            None => (),
+        }
+        output.location = location.into();
+
+        tracing::debug!(parent: &self.span,
+            context = "bimi",
+            event = "verify",
+            domain = domain,
+            selector = output.selector,
+            result = output.result.as_str(),
+            reason = output.result.reason().unwrap_or_default());
+
+        output.into()
+    }
+}
+
+impl SMTP {
+    pub async fn lookup_bimi_record(
+        &self,
+        domain: &str,
+        selector: &str,
+    ) -> Result<Option<BimiRecord>, BimiResult> {
+        let name = format!("{selector}._bimi.{domain}.");
+
+        #[cfg(not(feature = "test_mode"))]
+        let record = match self.resolvers.dns.txt_raw_lookup(name).await {
+            Ok(record) => record,
+            Err(mail_auth::Error::DnsRecordNotFound(_)) => return Ok(None),
+            Err(err) => return Err(BimiResult::TempError(err.to_string())),
+        };
+        #[cfg(feature = "test_mode")]
+        let record = match BIMI_TEST_RECORDS
+            .lock()
+            .iter()
+            .find(|(record_name, _)| record_name == &name)
+        {
+            Some((_, record)) => record.as_bytes().to_vec(),
+            None => return Ok(None),
+        };
+
+        std::str::from_utf8(&record)
+            .ok()
+            .and_then(BimiRecord::parse)
+            .map(Some)
+            .ok_or_else(|| BimiResult::Fail("Invalid BIMI record".to_string()))
+    }
+
+    pub async fn validate_vmc(
+        &self,
+        url: &str,
+        location: &str,
+        domain: &str,
+        selector: &str,
+    ) -> Result<VmcStatus, String> {
+        let key = format!("{selector}._bimi.{domain} {url} {location}");
+        if let Some(status) = self.resolvers.cache.bimi.get(&key) {
+            return Ok(status.as_ref().clone());
+        }
+
+        // Fetch evidence document and indicator
+        #[cfg(not(feature = "test_mode"))]
+        let (vmc, indicator) = (
+            self.fetch_bimi_document(url)
+                .await
+                .map_err(|err| format!("Failed to fetch evidence document: {err}"))?,
+            self.fetch_bimi_document(location)
+                .await
+                .map_err(|err| format!("Failed to fetch indicator: {err}"))?,
+        );
+        #[cfg(feature = "test_mode")]
+        let (vmc, indicator) = (
+            Some(BIMI_TEST_VMC.lock().clone()),
+            Some(BIMI_TEST_INDICATOR.lock().clone()),
+        );
+
+        let status = match (vmc, indicator) {
+            (Some(vmc), Some(indicator)) => match verify_vmc(
+                &vmc,
+                &indicator,
+                domain,
+                selector,
+                &self.mail_auth.bimi.trust_anchors,
+                now() as i64,
+            ) {
+                Ok(_) => VmcStatus::Valid,
+                Err(reason) => VmcStatus::Invalid(reason),
+            },
+            (None, _) => VmcStatus::Invalid("Evidence document is too large".to_string()),
+            (_, None) => VmcStatus::Invalid("Indicator is too large".to_string()),
+        };
+
+        Ok(self
+            .resolvers
+            .cache
+            .bimi
+            .insert(key, Arc::new(status), Instant::now() + VMC_CACHE_TTL)
+            .as_ref()
+            .clone())
+    }
+
+    // Fetches a sender chosen document, refusing non-public destinations and
+    // returning None when the document exceeds the maximum size.
+    #[cfg(not(feature = "test_mode"))]
+    async fn fetch_bimi_document(&self, url: &str) -> Result<Option<Vec<u8>>, String> {
+        let config = &self.mail_auth.bimi;
+        let url = reqwest::Url::parse(url).map_err(|err| err.to_string())?;
+        if !utils::ssrf::is_public_url(&url) {
+            return Err("Destination is not a public address".to_string());
+        }
+        let response = utils::ssrf::public_client(
+            reqwest::Client::builder()
+                .user_agent(crate::USER_AGENT)
+                .timeout(config.timeout),
+            3,
+        )
+        .build()
+        .map_err(|err| err.to_string())?
+        .get(url)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|err| err.to_string())?;
+
+        Ok(utils::ssrf::read_body(response, config.max_size).await.ok())
+    }
+}
+
+// Validates a PEM encoded Verified Mark Certificate chain against the configured
+// trust anchors, its validity period, usage, the domain it was issued for and
+// the hash of the indicator it certifies.
+pub fn verify_vmc(
+    pem: &[u8],
+    indicator: &[u8],
+    domain: &str,
+    selector: &str,
+    trust_anchors: &[Vec<u8>],
+    now: i64,
+) -> Result<(), String> {
+    let der_certs = rustls_pemfile::certs(&mut Cursor::new(pem))
+        .map_err(|err| format!("Failed to read evidence document: {err}"))?;
+    let mut certs = Vec::with_capacity(der_certs.len());
+    for der_cert in &der_certs {
+        certs.push(
+            X509Certificate::from_der(der_cert)
+                .map_err(|err| format!("Failed to parse certificate: {err}"))?
+                .1,
+        );
+    }
+    let leaf = certs
+        .first()
+        .ok_or_else(|| "No certificates found in evidence document".to_string())?;
+
+    // Validate validity period
+    for cert in &certs {
+        let validity = cert.validity();
+        if now < validity.not_before.timestamp() || now > validity.not_after.timestamp() {
+            return Err("Certificate is expired or not yet valid".to_string());
+        }
+    }
+
+    // Validate usage
+    if !leaf
+        .extended_key_usage()
+        .ok()
+        .flatten()
+        .map_or(false, |eku| {
+            eku.value
+                .other
+                .iter()
+                .any(|oid| oid.to_id_string() == BIMI_EKU_OID)
+        })
+    {
+        return Err("Certificate is not a Verified Mark Certificate".to_string());
+    }
+
+    // Validate domain name
+    let record_name = format!("{selector}._bimi.{domain}");
+    if !leaf
+        .subject_alternative_name()
+        .ok()
+        .flatten()
+        .map_or(false, |san| {
+            san.value.general_names.iter().any(|name| match name {
+                GeneralName::DNSName(name) => {
+                    let name = name.trim_end_matches('.').to_lowercase();
+                    name == domain
+                        || name == record_name
+                        || domain
+                            .strip_suffix(name.as_str())
+                            .map_or(false, |prefix| prefix.ends_with('.'))
+                }
+                _ => false,
+            })
+        })
+    {
+        return Err(format!("Certificate was not issued for {domain}"));
+    }
+
+    // Validate indicator
+    let indicator_hash = Sha256::digest(indicator);
+    let logotype_hashes = leaf
+        .extensions()
+        .iter()
+        .find(|ext| ext.oid.to_id_string() == LOGOTYPE_OID)
+        .map(|ext| logotype_hashes(ext.value))
+        .unwrap_or_default();
+    if logotype_hashes.is_empty() {
+        return Err("Certificate does not contain a logotype".to_string());
+    } else if !logotype_hashes.contains(&indicator_hash.as_slice()) {
+        return Err("Indicator does not match the certified logotype".to_string());
+    }
+
+    // Validate chain
+    for pair in certs.windows(2) {
+        if pair[0].issuer() != pair[1].subject()
+            || !is_certificate_authority(&pair[1])
+            || pair[0]
+                .verify_signature(Some(pair[1].public_key()))
+                .is_err()
+        {
+            return Err("Invalid certificate chain".to_string());
+        }
+    }
+    let (last_der, last) = (der_certs.last().unwrap(), certs.last().unwrap());
+    for anchor_der in trust_anchors {
+        if anchor_der == last_der {
+            return Ok(());
+        }
+        if let Ok((_, anchor)) = X509Certificate::from_der(anchor_der) {
+            if last.issuer() == anchor.subject()
+                && last.verify_signature(Some(anchor.public_key())).is_ok()
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    Err("Certificate chain is not issued by a trusted authority".to_string())
+}
+
+// Removes inbound BIMI headers and Authentication-Results headers claiming to
+// have been added by this server, so they are never mistaken for local results.
+pub fn strip_untrusted_headers(raw_message: &[u8], authserv_id: &str) -> Option<Vec<u8>> {
+    remove_headers(raw_message, |name, value| {
+        name.eq_ignore_ascii_case("BIMI-Location")
+            || name.eq_ignore_ascii_case("BIMI-Indicator")
+            || (name.eq_ignore_ascii_case("Authentication-Results")
+                && std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|value| value.split(';').next())
+                    .and_then(|value| value.split_ascii_whitespace().next())
+                    .map_or(false, |id| id.eq_ignore_ascii_case(authserv_id)))
+    })
+}
+
+fn is_certificate_authority(cert: &X509Certificate) -> bool {
+    cert.basic_constraints()
+        .ok()
+        .flatten()
+        .map_or(false, |bc| bc.value.ca)
+        && cert
+            .key_usage()
+            .ok()
+            .flatten()
+            .map_or(true, |ku| ku.value.key_cert_sign())
+}
+
+// Obtains the SHA-256 hashes listed in a logotype extension, which are
+// encoded as an AlgorithmIdentifier followed by an OCTET STRING.
+fn logotype_hashes(der: &[u8]) -> Vec<&[u8]> {
+    let mut hashes = Vec::new();
+    let mut pos = 0;
+    while let Some(offset) = der[pos..]
+        .windows(SHA256_OID_DER.len())
+        .position(|window| window == SHA256_OID_DER)
+    {
+        pos += offset + SHA256_OID_DER.len();
+        let mut hash = &der[pos..];
+        if let Some(params) = hash.strip_prefix(&[0x05, 0x00]) {
+            hash = params;
+        }
+        if let Some(hash) = hash.strip_prefix(&[0x04, 0x20]) {
+            if hash.len() >= 32 {
+                hashes.push(&hash[..32]);
+            }
+        }
+    }
+    hashes
+}
+
+fn is_valid_uri(value: &str) -> bool {
+    value
+        .chars()
+        .all(|ch| ch.is_ascii_graphic() && !matches!(ch, '"' | '\\' | '<' | '>'))
+}
+
+fn parse_selector(value: &str) -> Option<String> {
+    let record = value.split(';').map(|tag| tag.trim()).collect::<Vec<_>>();
+    if !record
+        .first()
+        .and_then(|tag| tag.split_once('='))
+        .map_or(false, |(name, value)| {
+            name.trim().eq_ignore_ascii_case("v") && value.trim().eq_ignore_ascii_case("BIMI1")
+        })
+    {
+        return None;
+    }
+
+    record.iter().skip(1).find_map(|tag| {
+        let (name, value) = tag.split_once('=')?;
+        let value = value.trim().to_lowercase();
+        if name.trim().eq_ignore_ascii_case("s")
+            && !value.is_empty()
+            && value
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '_' | '.'))
+        {
+            Some(value)
+        } else {
+            None
+        }
+    })
+}
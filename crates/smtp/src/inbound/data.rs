@@ -26,12 +26,13 @@ use std::{
     path::PathBuf,
     process::Stdio,
     sync::Arc,
-    time::{Duration, Instant, SystemTime},
+    time::{Duration, SystemTime},
 };
 
 use mail_auth::{
     common::{headers::HeaderWriter, verify::VerifySignature},
     dmarc, AuthenticatedMessage, AuthenticationResults, DkimResult, DmarcResult, ReceivedSpf,
+    SpfResult,
 };
 use mail_builder::headers::{date::Date, message_id::generate_message_id_header};
 use sieve::runtime::Variable;
@@ -45,12 +46,19 @@ use tokio::{
 
 use crate::{
     core::{Session, SessionAddress, State},
-    queue::{self, Message, SimpleEnvelope},
+    queue::{self, DomainPart, Message, SimpleEnvelope},
     reporting::analysis::AnalyzeReport,
-    scripts::{ScriptModification, ScriptResult},
+    reputation::ReputationEvent,
+    scripts::{shadow::Verdict, ScriptModification, ScriptResult},
+    tracking::{find_message_id, TrackingEvent, TrackingEventType},
+    unsubscribe::{find_list_id, is_bulk_message, UnsubscribeToken},
+    webhook::now,
 };
 
-use super::{AuthResult, IsTls};
+use super::{
+    bimi::strip_untrusted_headers, filter::DeferredScan, footer::render_template,
+    milter::Modification, AuthResult, IsTls,
+};
 
 impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
     pub async fn queue_message(&mut self) -> Cow<'static, [u8]> {
@@ -82,6 +90,22 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                 .into();
         }
 
+        // Trace headers limit
+        let max_trace_headers = *dc.max_trace_headers.eval(self).await;
+        if max_trace_headers > 0 {
+            let trace_headers = count_trace_headers(auth_message.raw_parsed_headers());
+            if trace_headers > max_trace_headers {
+                tracing::info!(parent: &self.span,
+                    context = "data",
+                    event = "too-many-trace-headers",
+                    return_path = self.data.mail_from.as_ref().unwrap().address,
+                    from = auth_message.from(),
+                    trace_headers = trace_headers,
+                    limit = max_trace_headers);
+                return (&b"554 5.4.6 Too many trace headers.\r\n"[..]).into();
+            }
+        }
+
         // Verify DKIM
         let dkim = *ac.dkim.verify.eval(self).await;
         let dmarc = *ac.dmarc.verify.eval(self).await;
@@ -132,6 +156,7 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
         } else {
             vec![]
         };
+        let is_dkim_replay = self.is_dkim_replay(&dkim_output);
 
         // Verify ARC
         let arc = *ac.arc.verify.eval(self).await;
@@ -279,6 +304,20 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
             _ => (None, None),
         };
 
+        // Verify BIMI
+        let bimi = *ac.bimi.verify.eval(self).await;
+        let bimi_output = if bimi.verify() {
+            self.verify_bimi(
+                bimi,
+                &auth_message,
+                dmarc_result.as_ref(),
+                dmarc_policy.as_ref(),
+            )
+            .await
+        } else {
+            None
+        };
+
         // Analyze reports
         if self.is_report() {
             self.core.analyze_report(raw_message.clone());
@@ -289,9 +328,14 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
         }
 
         // Run Milter filters
+        let mut quarantine = None;
         let mut edited_message = match self.run_milters(&auth_message).await {
             Ok(modifications) => {
                 if !modifications.is_empty() {
+                    quarantine = modifications.iter().find_map(|m| match m {
+                        Modification::Quarantine { reason } => Some(reason.clone()),
+                        _ => None,
+                    });
                     tracing::debug!(
                     parent: &self.span,
                     context = "milter",
@@ -403,9 +447,31 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
             }
         }
 
-        // Sieve filtering
+        // Content filters
         let mut headers = Vec::with_capacity(64);
-        if let Some(script) = dc.script.eval(self).await {
+        if !dc.filters.is_empty() {
+            match self
+                .run_content_filters(edited_message.as_ref().unwrap_or(&raw_message))
+                .await
+            {
+                Ok(outcome) => {
+                    if let Some(message) = outcome.message {
+                        edited_message = message.into();
+                    }
+                    if outcome.quarantine.is_some() {
+                        quarantine = outcome.quarantine;
+                    }
+                    headers = outcome.headers;
+                }
+                Err(response) => return response,
+            }
+        }
+
+        // Sieve filtering
+        let mut is_spam = false;
+        let script = dc.script.eval(self).await;
+        let shadow_script = dc.shadow_script.eval(self).await;
+        if script.is_some() || shadow_script.is_some() {
             let params = self
                 .build_script_parameters("data")
                 .with_message(edited_message.as_ref().unwrap_or(&raw_message).clone())
@@ -439,6 +505,7 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                         })
                         .collect::<Vec<_>>(),
                 )
+                .set_variable("dkim.replay", is_dkim_replay as u64)
                 .set_variable(
                     "dmarc.result",
                     dmarc_result
@@ -454,7 +521,23 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                         .unwrap_or_default(),
                 );
 
-            let modifications = match self.run_script(script.clone(), params).await {
+            let shadow_params = shadow_script
+                .as_ref()
+                .map(|script| (script.clone(), params.clone()));
+            let result = if let Some(script) = script {
+                self.run_script(script.clone(), params).await
+            } else {
+                ScriptResult::Accept {
+                    modifications: vec![],
+                }
+            };
+
+            // Evaluate the shadow script against the same input without affecting delivery
+            if let Some((script, params)) = shadow_params {
+                self.run_shadow_script(script, params, Verdict::from(&result));
+            }
+
+            let modifications = match result {
                 ScriptResult::Accept { modifications } => modifications,
                 ScriptResult::Replace {
                     message,
@@ -469,9 +552,11 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                         event = "reject",
                         reason = message);
 
+                    self.record_reputation(ReputationEvent::Spam).await;
                     return message.into_bytes().into();
                 }
                 ScriptResult::Discard => {
+                    self.record_reputation(ReputationEvent::Spam).await;
                     return (b"250 2.0.0 Message queued for delivery.\r\n"[..]).into();
                 }
             };
@@ -490,6 +575,9 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                     ScriptModification::SetEnvelope { name, value } => {
                         self.data.apply_envelope_modification(name, value);
                     }
+                    ScriptModification::SpamVerdict(verdict) => {
+                        is_spam = verdict;
+                    }
                 }
             }
         }
@@ -499,13 +587,104 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
         let rcpt_to = std::mem::take(&mut self.data.rcpt_to);
         let mut message = self.build_message(mail_from, rcpt_to).await;
 
+        // Remember unauthenticated messages that failed SPF or DMARC, bouncing them
+        // would send backscatter to the forged return path. Only DKIM signatures
+        // aligned with the return path vouch for it.
+        let psl = &self.core.sieve.runtime.context().psl;
+        let return_path_domain = psl.organizational_domain(&message.return_path_domain);
+        if self.data.authenticated_as.is_empty()
+            && !dkim_output.iter().any(|d| {
+                matches!(d.result(), DkimResult::Pass)
+                    && d.signature().map_or(false, |s| {
+                        psl.organizational_domain(&s.domain().to_lowercase()) == return_path_domain
+                    })
+            })
+            && (matches!(&dmarc_result, Some(DmarcResult::Fail(_)))
+                || self
+                    .data
+                    .spf_mail_from
+                    .as_ref()
+                    .map_or(false, |spf| matches!(spf.result(), SpfResult::Fail)))
+        {
+            message.flags |= queue::MAIL_AUTH_FAILED;
+        }
+
+        // Hold quarantined messages until they are released or expire
+        let mut deferred_filters = Vec::new();
+        let mut deferred_schedule = Vec::new();
+        let mut deferred_in_flight = Vec::new();
+        if let Some(reason) = quarantine {
+            tracing::info!(
+                parent: &self.span,
+                context = "data",
+                event = "quarantine",
+                id = message.id,
+                reason = reason,
+                "Message quarantined.");
+
+            for domain in &mut message.domains {
+                domain.retry.due = domain.expires;
+                domain.notify.due = domain.expires;
+            }
+
+            // Quarantined messages are delivered once released by an administrator
+            for rcpt in &mut message.recipients {
+                rcpt.flags |= queue::RCPT_QUARANTINED;
+            }
+        } else if dc.filters.iter().any(|f| f.deferred.is_some()) {
+            // Hold the message until the deferred content filters have scanned it
+            deferred_filters = self.deferred_content_filters().await;
+            if !deferred_filters.is_empty() {
+                // Limit the number of scans running in the background
+                for idx in &deferred_filters {
+                    if let Some(in_flight) = dc.filters[*idx]
+                        .deferred
+                        .as_ref()
+                        .and_then(|policy| policy.limiter.is_allowed())
+                    {
+                        deferred_in_flight.push(in_flight);
+                    } else {
+                        tracing::info!(
+                            parent: &self.span,
+                            context = "data",
+                            event = "deferred-scan",
+                            filter = &dc.filters[*idx].id,
+                            "Too many messages pending a deferred scan.");
+                        return (b"451 4.3.2 Too many messages pending content scanning, please try again later.\r\n"[..]).into();
+                    }
+                }
+
+                tracing::debug!(
+                    parent: &self.span,
+                    context = "data",
+                    event = "deferred-scan",
+                    id = message.id,
+                    filters = deferred_filters.len(),
+                    "Message held for deferred scanning.");
+
+                for domain in &mut message.domains {
+                    deferred_schedule.push((domain.retry.due, domain.notify.due));
+                    domain.retry.due = domain.expires;
+                    domain.notify.due = domain.expires;
+                }
+
+                // Scans pending when the server stops are resumed on restart
+                for rcpt in &mut message.recipients {
+                    rcpt.flags |= queue::RCPT_SCAN_PENDING;
+                }
+            }
+        }
+
         // Add Received header
         if *dc.add_received.eval(self).await {
-            self.write_received(&mut headers, message.id)
+            self.write_received(&mut headers, message.id).await
         }
 
         // Add authentication results header
         if *dc.add_auth_results.eval(self).await {
+            if let Some(bimi_output) = &bimi_output {
+                bimi_output.write_header(&self.instance.hostname, &mut headers);
+            }
             auth_results.write_header(&mut headers);
         }
 
@@ -523,6 +702,35 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
             }
         }
 
+        // Relayed messages are sealed when required by any of their routes
+        let mut arc_sealer = arc_sealer;
+        if arc_sealer.is_none() {
+            for domain in &message.domains {
+                let envelope = SimpleEnvelope::new(&message, &domain.domain);
+                arc_sealer = self
+                    .core
+                    .queue
+                    .config
+                    .arc_seal
+                    .eval_and_capture(&envelope)
+                    .await
+                    .into_value(&envelope);
+                if arc_sealer.is_some() {
+                    break;
+                }
+            }
+        }
+        let arc_output = match arc_output {
+            None if arc_sealer.is_some() => self
+                .core
+                .resolvers
+                .dns
+                .verify_arc(&auth_message)
+                .await
+                .into(),
+            arc_output => arc_output,
+        };
+
         // ARC Seal
         if let (Some(arc_sealer), Some(arc_output)) = (arc_sealer, &arc_output) {
             if !dkim_output.is_empty() && arc_output.can_be_sealed() {
@@ -561,37 +769,197 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
             headers.extend_from_slice(b">\r\n");
         }
 
-        // DKIM sign
+        // Remove headers that could be mistaken for results added by this server
         let raw_message = edited_message.unwrap_or(raw_message);
-        for signer in ac.dkim.sign.eval_and_capture(self).await.into_value(self) {
-            match signer.sign_chained(&[headers.as_ref(), &raw_message]) {
-                Ok(signature) => {
-                    signature.write_header(&mut headers);
-                }
-                Err(err) => {
-                    tracing::info!(parent: &self.span,
-                        context = "dkim",
-                        event = "sign-failed",
-                        return_path = message.return_path,
-                        "Failed to sign message: {}", err);
-                }
+        let raw_message = match strip_untrusted_headers(&raw_message, &self.instance.hostname) {
+            Some(message) => Arc::new(message),
+            None => raw_message,
+        };
+
+        // Add one-click unsubscribe headers to bulk messages
+        if self.core.unsubscribe.is_enabled()
+            && is_bulk_message(auth_message.raw_parsed_headers())
+            && *dc.add_list_unsubscribe.eval(self).await
+        {
+            // Links identify a single recipient, messages sent to multiple recipients are skipped
+            if let [rcpt] = message.recipients.as_slice() {
+                self.core.unsubscribe.write_headers(
+                    &UnsubscribeToken {
+                        queue_id: message.id,
+                        from: message.return_path.clone(),
+                        to: rcpt.address.clone(),
+                        list_id: find_list_id(auth_message.raw_parsed_headers()),
+                        message_id: find_message_id(&headers)
+                            .or_else(|| find_message_id(&raw_message)),
+                        expires: now() + self.core.unsubscribe.expiry.as_secs(),
+                    },
+                    &mut headers,
+                );
+            } else {
+                tracing::debug!(parent: &self.span,
+                    context = "unsubscribe",
+                    event = "skip",
+                    return_path = message.return_path,
+                    recipients = message.recipients.len(),
+                    "Unsubscribe headers not added to bulk message with multiple recipients.");
             }
         }
 
+        // Append footers, rewrite the return path and DKIM sign the message
+        let transformed = self.transform_message(&mut headers, raw_message).await;
+        let raw_message = transformed.raw_message;
+        if let Some(return_path) = transformed.return_path {
+            message.return_path_lcase = return_path.to_lowercase();
+            message.return_path_domain = message.return_path_lcase.domain_part().to_string();
+            message.return_path = return_path;
+        }
+
         // Update size
         message.size = raw_message.len() + headers.len();
 
+        // Verify sending limits
+        let usage_limits = match self
+            .check_usage_limits(message.size as u64, message.recipients.len() as u64)
+            .await
+        {
+            Ok(usage_limits) => usage_limits,
+            Err(response) => return response.into(),
+        };
+
+        // Throttle accounts contained after being flagged as compromised
+        if !self.data.authenticated_as.is_empty()
+            && !self
+                .core
+                .anomaly
+                .is_submission_allowed(&self.data.authenticated_as)
+        {
+            tracing::info!(
+                parent: &self.span,
+                context = "anomaly",
+                event = "throttle",
+                account = self.data.authenticated_as,
+                "Submission from contained account throttled."
+            );
+            return (b"451 4.7.1 Sending rate restricted, please try again later.\r\n"[..]).into();
+        }
+
         // Verify queue quota
         if self.core.queue.has_quota(&mut message).await {
             let queue_id = message.id;
+            let size = message.size as u64;
+            let num_recipients = message.recipients.len() as u64;
+            #[cfg(feature = "local_delivery")]
+            let recipients = if !self.data.authenticated_as.is_empty() {
+                message
+                    .recipients
+                    .iter()
+                    .map(|rcpt| rcpt.address_lcase.clone())
+                    .collect::<Vec<_>>()
+            } else {
+                vec![]
+            };
+            let queued_event = self.core.webhook.queued_event(&message);
+            let deferred_scan = (!deferred_filters.is_empty()).then(|| DeferredScan {
+                queue_id,
+                filters: deferred_filters,
+                message: [headers.as_slice(), raw_message.as_slice()].concat(),
+                sender: message.return_path.clone(),
+                recipients: message
+                    .recipients
+                    .iter()
+                    .map(|rcpt| rcpt.address.clone())
+                    .collect(),
+                remote_ip: self.data.remote_ip.to_string(),
+                helo_domain: self.data.helo_domain.clone(),
+                authenticated_as: self.data.authenticated_as.clone(),
+                hostname: self.instance.hostname.clone(),
+                schedule: deferred_schedule,
+                in_flight: deferred_in_flight,
+            });
+            let tracking_event = self.core.tracking.is_enabled().then(|| {
+                let message_id =
+                    find_message_id(&headers).or_else(|| find_message_id(&raw_message));
+                let mut event = TrackingEvent::new(TrackingEventType::Received, &message);
+                event.message_id = message_id.clone();
+                event.remote_ip = self.data.remote_ip.to_string().into();
+                event.details = if !self.data.authenticated_as.is_empty() {
+                    format!(
+                        "helo {} authenticated as {}",
+                        self.data.helo_domain, self.data.authenticated_as
+                    )
+                } else {
+                    format!("helo {}", self.data.helo_domain)
+                }
+                .into();
+                self.core.tracking.record(event);
+
+                let mut event = TrackingEvent::new(TrackingEventType::Queued, &message);
+                event.message_id = message_id;
+                event.details = format!("{} bytes", message.size).into();
+                event
+            });
             if self
                 .core
                 .queue
                 .queue_message(message, Some(&headers), &raw_message, &self.span)
                 .await
             {
+                if let Some(event) = tracking_event {
+                    self.core.tracking.record(event);
+                }
+                if let Some(event) = queued_event {
+                    self.core.webhook.publish_event(event);
+                }
+                if let Some(deferred_scan) = deferred_scan {
+                    deferred_scan.spawn(self.core.clone(), self.span.clone());
+                }
+                self.record_usage(size, num_recipients, &usage_limits).await;
+                if !self.data.authenticated_as.is_empty() {
+                    self.core
+                        .anomaly
+                        .record_submission(
+                            &self.data.authenticated_as,
+                            self.data
+                                .mail_from
+                                .as_ref()
+                                .map_or("", |mail_from| mail_from.address_lcase.as_str()),
+                        )
+                        .await;
+                }
+                self.record_reputation(if is_spam {
+                    ReputationEvent::Spam
+                } else {
+                    ReputationEvent::Accepted
+                })
+                .await;
                 self.state = State::Accepted(queue_id);
                 self.data.messages_sent += 1;
+
+                // Add recipients to the sender's auto-collected addresses
+                #[cfg(feature = "local_delivery")]
+                if !recipients.is_empty() {
+                    let _ = self
+                        .core
+                        .delivery_tx
+                        .send(utils::ipc::DeliveryEvent::CollectAddresses {
+                            account: self.data.authenticated_as.clone(),
+                            addresses: recipients,
+                        })
+                        .await;
+                }
+
+                // File a copy of the submission into the sender's Sent mailbox
+                #[cfg(feature = "local_delivery")]
+                if !self.data.authenticated_as.is_empty() {
+                    let _ = self
+                        .core
+                        .delivery_tx
+                        .send(utils::ipc::DeliveryEvent::FileSentCopy {
+                            account: self.data.authenticated_as.clone(),
+                            message: raw_message.clone(),
+                        })
+                        .await;
+                }
                 (b"250 2.0.0 Message queued for delivery.\r\n"[..]).into()
             } else {
                 (b"451 4.3.5 Unable to accept message at this time.\r\n"[..]).into()
@@ -625,11 +993,13 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
             return_path_domain: mail_from.domain,
             recipients: Vec::with_capacity(rcpt_to.len()),
             domains: Vec::with_capacity(3),
+            history: vec![],
             flags: mail_from.flags,
             priority: self.data.priority,
             size: 0,
             env_id: mail_from.dsn_info,
             queue_refs: Vec::with_capacity(0),
+            queue_load: None,
         });
 
         // Add recipients
@@ -656,12 +1026,14 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                 let (notify, expires) = if self.data.delivery_by == 0 {
                     (
                         queue::Schedule::later(future_release + *notify_intervals.first().unwrap()),
-                        Instant::now() + future_release + *config.expire.eval(&envelope).await,
+                        queue::Timestamp::now()
+                            + future_release
+                            + *config.expire.eval(&envelope).await,
                     )
                 } else if (message.flags & MAIL_BY_RETURN) != 0 {
                     (
                         queue::Schedule::later(future_release + *notify_intervals.first().unwrap()),
-                        Instant::now() + Duration::from_secs(self.data.delivery_by as u64),
+                        queue::Timestamp::now() + Duration::from_secs(self.data.delivery_by as u64),
                     )
                 } else {
                     let expire = *config.expire.eval(&envelope).await;
@@ -684,7 +1056,7 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                     let mut notify = queue::Schedule::later(future_release + notify);
                     notify.inner = (notify_intervals.len() - 1) as u32; // Disable further notification attempts
 
-                    (notify, Instant::now() + expire)
+                    (notify, queue::Timestamp::now() + expire)
                 };
 
                 message.domains.push(queue::Domain {
@@ -693,6 +1065,7 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                     expires,
                     status: queue::Status::Scheduled,
                     domain: rcpt.domain,
+                    class_attempt: 0,
                     disable_tls: false,
                     changed: false,
                 });
@@ -743,38 +1116,108 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
         }
     }
 
-    fn write_received(&self, headers: &mut Vec<u8>, id: u64) {
-        headers.extend_from_slice(b"Received: from ");
-        headers.extend_from_slice(self.data.helo_domain.as_bytes());
-        headers.extend_from_slice(b" (");
-        headers.extend_from_slice(
-            self.data
-                .iprev
-                .as_ref()
-                .and_then(|ir| ir.ptr.as_ref())
-                .and_then(|ptr| ptr.first().map(|s| s.strip_suffix('.').unwrap_or(s)))
-                .unwrap_or("unknown")
-                .as_bytes(),
-        );
-        headers.extend_from_slice(b" [");
-        headers.extend_from_slice(self.data.remote_ip.to_string().as_bytes());
-        headers.extend_from_slice(b"])\r\n\t");
-        self.stream.write_tls_header(headers);
-        headers.extend_from_slice(b"by ");
-        headers.extend_from_slice(self.instance.hostname.as_bytes());
-        headers.extend_from_slice(b" (Stalwart SMTP) with ");
-        headers.extend_from_slice(
-            match (self.stream.is_tls(), self.data.authenticated_as.is_empty()) {
-                (true, true) => b"ESMTPS",
-                (true, false) => b"ESMTPSA",
-                (false, true) => b"ESMTP",
-                (false, false) => b"ESMTPA",
-            },
+    async fn write_received(&self, headers: &mut Vec<u8>, id: u64) {
+        let template = self
+            .core
+            .session
+            .config
+            .data
+            .received_template
+            .eval(self)
+            .await;
+        let ptr = self
+            .data
+            .iprev
+            .as_ref()
+            .and_then(|ir| ir.ptr.as_ref())
+            .and_then(|ptr| ptr.first().map(|s| s.strip_suffix('.').unwrap_or(s)))
+            .unwrap_or("unknown");
+        let remote_ip = self.data.remote_ip.to_string();
+        let tls = if self.stream.is_tls() {
+            let (version, cipher) = self.stream.tls_version_and_cipher();
+            format!("(using {version} with cipher {cipher})")
+        } else {
+            String::new()
+        };
+        let client_cert = if !self.data.client_cert_identity.is_empty() {
+            format!(
+                "(client certificate {})",
+                comment_text(&self.data.client_cert_identity)
+            )
+        } else {
+            String::new()
+        };
+        let authenticated_as = if !self.data.authenticated_as.is_empty() {
+            format!(
+                "(authenticated as {})",
+                comment_text(&self.data.authenticated_as)
+            )
+        } else {
+            String::new()
+        };
+        let protocol = match (self.stream.is_tls(), self.data.authenticated_as.is_empty()) {
+            (true, true) => "ESMTPS",
+            (true, false) => "ESMTPSA",
+            (false, true) => "ESMTP",
+            (false, false) => "ESMTPA",
+        };
+        let id = format!("{id:X}");
+        let date = Date::now().to_rfc822();
+
+        let value = render_template(
+            template,
+            &[
+                ("helo", self.data.helo_domain.as_str()),
+                ("ptr", ptr),
+                ("ip", remote_ip.as_str()),
+                ("tls", tls.as_str()),
+                ("client-cert", client_cert.as_str()),
+                ("authenticated-as", authenticated_as.as_str()),
+                ("hostname", self.instance.hostname.as_str()),
+                ("protocol", protocol),
+                ("id", id.as_str()),
+                ("date", date.as_str()),
+            ],
+            false,
         );
-        headers.extend_from_slice(b" id ");
-        headers.extend_from_slice(format!("{id:X}").as_bytes());
-        headers.extend_from_slice(b";\r\n\t");
-        headers.extend_from_slice(Date::now().to_rfc822().as_bytes());
+
+        // Empty placeholders leave no gaps, long values are folded between words
+        headers.extend_from_slice(b"Received:");
+        let mut line_len = "Received:".len();
+        for word in value.split_ascii_whitespace() {
+            if line_len + word.len() >= 78 && line_len > "Received:".len() {
+                headers.extend_from_slice(b"\r\n\t");
+                line_len = 1;
+            } else {
+                headers.push(b' ');
+                line_len += 1;
+            }
+            headers.extend(word.bytes().filter(|ch| ch.is_ascii_graphic()));
+            line_len += word.len();
+        }
         headers.extend_from_slice(b"\r\n");
     }
 }
+
+// Values written inside header comments may not break out of the comment or the header
+fn comment_text(text: &str) -> String {
+    text.chars()
+        .filter(|ch| ch.is_ascii_graphic() && !matches!(ch, '(' | ')' | '\\'))
+        .collect()
+}
+
+fn count_trace_headers(headers: &[(&[u8], &[u8])]) -> usize {
+    headers
+        .iter()
+        .filter(|(name, _)| {
+            [
+                &b"Received"[..],
+                b"Return-Path",
+                b"Received-SPF",
+                b"Authentication-Results",
+            ]
+            .iter()
+            .any(|trace| name.eq_ignore_ascii_case(trace))
+        })
+        .count()
+}
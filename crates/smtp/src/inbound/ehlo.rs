@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::time::SystemTime;
+use std::{fmt::Write, time::SystemTime};
 
 use crate::{core::Session, scripts::ScriptResult};
 use mail_auth::spf::verify::HasLabels;
@@ -112,6 +112,14 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
             self.reset();
         }
 
+        // Client certificate authentication
+        if self.params.auth_client_cert
+            && self.stream.is_tls()
+            && self.data.authenticated_as.is_empty()
+        {
+            self.authenticate_client_cert().await;
+        }
+
         let mut response = EhloResponse::new(self.instance.hostname.as_str());
         response.capabilities =
             EXT_ENHANCED_STATUS_CODES | EXT_8BIT_MIME | EXT_BINARY_MIME | EXT_SMTP_UTF8;
@@ -207,6 +215,51 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
         // Generate response
         let mut buf = Vec::with_capacity(64);
         response.write(&mut buf).ok();
+
+        // Extensions not supported by the response builder are inserted after the greeting
+        let mut extensions = Vec::new();
+
+        // Limits (RFC 9422)
+        if *ec.limits.eval(self).await {
+            let mut limits = String::new();
+            let rcpt_max = *self
+                .core
+                .session
+                .config
+                .rcpt
+                .max_recipients
+                .eval(self)
+                .await;
+            if rcpt_max > 0 {
+                let _ = write!(limits, " RCPTMAX={rcpt_max}");
+            }
+            let mail_max = *dc.max_messages.eval(self).await;
+            if mail_max > 0 {
+                let _ = write!(limits, " MAILMAX={mail_max}");
+            }
+            if !limits.is_empty() {
+                extensions.extend_from_slice(format!("250-LIMITS{limits}\r\n").as_bytes());
+            }
+        }
+
+        // XCLIENT and XFORWARD are only advertised to trusted upstreams
+        if self.params.xclient {
+            extensions.extend_from_slice(
+                b"250-XCLIENT NAME ADDR PORT PROTO HELO LOGIN DESTADDR DESTPORT\r\n",
+            );
+        }
+        if self.params.xforward {
+            extensions
+                .extend_from_slice(b"250-XFORWARD NAME ADDR PORT PROTO HELO IDENT SOURCE\r\n");
+        }
+        if !extensions.is_empty() {
+            if let Some(pos) = buf.windows(2).position(|w| w == b"\r\n") {
+                let capabilities = buf.split_off(pos + 2);
+                buf.extend(extensions);
+                buf.extend(capabilities);
+            }
+        }
+
         self.write(&buf).await
     }
 }
@@ -0,0 +1,887 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, process::Stdio, sync::Arc, time::Instant};
+
+use reqwest::header::CONTENT_TYPE;
+use smtp_proto::Response;
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    process::Command,
+};
+use utils::listener::limiter::InFlight;
+
+use crate::{
+    config::{ContentFilter, DeferredScanAction, FilterProtocol},
+    core::{Session, SMTP},
+    queue::{
+        manager::Queue, ErrorDetails, Event, HostResponse, QueueId, ScanResult, ScanVerdict,
+        Timestamp, RCPT_SCAN_PENDING,
+    },
+};
+
+use super::IsTls;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterAction {
+    #[default]
+    Accept,
+    Reject,
+    TempFail,
+    Discard,
+    Quarantine,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FilterResponse {
+    pub action: FilterAction,
+    pub reply: Option<String>,
+    pub reason: Option<String>,
+    pub add_headers: Vec<(String, String)>,
+    pub replace: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Default)]
+pub struct FilterOutcome {
+    pub message: Option<Arc<Vec<u8>>>,
+    pub headers: Vec<u8>,
+    pub quarantine: Option<String>,
+}
+
+pub struct FilterRequest<'x> {
+    pub message: &'x [u8],
+    pub sender: &'x str,
+    pub recipients: Vec<&'x str>,
+    pub remote_ip: String,
+    pub helo_domain: &'x str,
+    pub authenticated_as: &'x str,
+}
+
+pub struct DeferredScan {
+    pub queue_id: QueueId,
+    pub filters: Vec<usize>,
+    pub message: Vec<u8>,
+    pub sender: String,
+    pub recipients: Vec<String>,
+    pub remote_ip: String,
+    pub helo_domain: String,
+    pub authenticated_as: String,
+    pub hostname: String,
+    pub schedule: Vec<(Timestamp, Timestamp)>,
+    pub in_flight: Vec<InFlight>,
+}
+
+impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
+    pub async fn run_content_filters(
+        &self,
+        message: &Arc<Vec<u8>>,
+    ) -> Result<FilterOutcome, Cow<'static, [u8]>> {
+        let mut outcome = FilterOutcome::default();
+
+        for filter in &self.core.session.config.data.filters {
+            if filter.deferred.is_some() || !*filter.enable.eval(self).await {
+                continue;
+            }
+
+            let current = outcome.message.as_ref().unwrap_or(message).clone();
+            let request = FilterRequest {
+                message: &current,
+                sender: self
+                    .data
+                    .mail_from
+                    .as_ref()
+                    .map_or("", |m| m.address.as_str()),
+                recipients: self
+                    .data
+                    .rcpt_to
+                    .iter()
+                    .map(|r| r.address.as_str())
+                    .collect(),
+                remote_ip: self.data.remote_ip.to_string(),
+                helo_domain: &self.data.helo_domain,
+                authenticated_as: &self.data.authenticated_as,
+            };
+
+            let result = match tokio::time::timeout(filter.timeout, filter.run(&request)).await {
+                Ok(result) => result,
+                Err(_) => Err("Filter timed out.".to_string()),
+            };
+
+            match result {
+                Ok(response) => {
+                    tracing::debug!(
+                        parent: &self.span,
+                        context = "filter",
+                        event = "response",
+                        filter = &filter.id,
+                        action = ?response.action,
+                        headers = response.add_headers.len(),
+                        replaced = response.replace.is_some(),
+                    );
+
+                    match response.action {
+                        FilterAction::Accept => (),
+                        FilterAction::Reject => {
+                            tracing::info!(
+                                parent: &self.span,
+                                context = "filter",
+                                event = "reject",
+                                filter = &filter.id,
+                                reason = response.reason.as_deref().unwrap_or_default(),
+                                "Content filter rejected message.");
+                            return Err(filter_reply(
+                                response.reply.as_deref(),
+                                "550 5.7.1 Message rejected by content filter.",
+                            )
+                            .into());
+                        }
+                        FilterAction::TempFail => {
+                            tracing::info!(
+                                parent: &self.span,
+                                context = "filter",
+                                event = "tempfail",
+                                filter = &filter.id,
+                                reason = response.reason.as_deref().unwrap_or_default(),
+                                "Content filter deferred message.");
+                            return Err(filter_reply(
+                                response.reply.as_deref(),
+                                "451 4.7.1 Message deferred by content filter.",
+                            )
+                            .into());
+                        }
+                        FilterAction::Discard => {
+                            tracing::info!(
+                                parent: &self.span,
+                                context = "filter",
+                                event = "discard",
+                                filter = &filter.id,
+                                reason = response.reason.as_deref().unwrap_or_default(),
+                                "Content filter discarded message.");
+                            return Err((b"250 2.0.0 Message queued for delivery.\r\n"[..]).into());
+                        }
+                        FilterAction::Quarantine => {
+                            outcome.quarantine = response
+                                .reason
+                                .unwrap_or_else(|| format!("Quarantined by filter {}", filter.id))
+                                .into();
+                        }
+                    }
+
+                    for (name, value) in response.add_headers {
+                        outcome.headers.extend_from_slice(name.as_bytes());
+                        outcome.headers.extend_from_slice(b": ");
+                        outcome.headers.extend_from_slice(value.as_bytes());
+                        outcome.headers.extend_from_slice(b"\r\n");
+                    }
+                    if let Some(replace) = response.replace {
+                        outcome.message = Arc::new(replace).into();
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        parent: &self.span,
+                        context = "filter",
+                        event = "error",
+                        filter = &filter.id,
+                        reason = %err,
+                        "Content filter failed.");
+
+                    if filter.tempfail_on_error {
+                        return Err(
+                            (&b"451 4.3.5 Unable to accept message at this time.\r\n"[..]).into(),
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    pub async fn deferred_content_filters(&self) -> Vec<usize> {
+        let mut filters = Vec::new();
+        for (idx, filter) in self.core.session.config.data.filters.iter().enumerate() {
+            if filter.deferred.is_some() && *filter.enable.eval(self).await {
+                filters.push(idx);
+            }
+        }
+        filters
+    }
+}
+
+impl DeferredScan {
+    pub fn spawn(self, core: Arc<SMTP>, span: tracing::Span) {
+        tokio::spawn(async move {
+            self.run(&core, &span).await;
+        });
+    }
+
+    // Messages that were still pending a scan when the queue was last stopped
+    // are scanned again, one at a time, with all the deferred filters.
+    pub async fn resume(core: &Arc<SMTP>, queue: &Queue) {
+        let filters = core
+            .session
+            .config
+            .data
+            .filters
+            .iter()
+            .enumerate()
+            .filter(|(_, filter)| filter.deferred.is_some())
+            .map(|(idx, _)| idx)
+            .collect::<Vec<_>>();
+        let mut pending = Vec::new();
+        for message in queue.messages.values().filter(|message| {
+            message
+                .recipients
+                .iter()
+                .any(|rcpt| rcpt.has_flag(RCPT_SCAN_PENDING))
+        }) {
+            pending.push((
+                DeferredScan {
+                    queue_id: message.id,
+                    filters: filters.clone(),
+                    message: Vec::new(),
+                    sender: message.return_path.clone(),
+                    recipients: message
+                        .recipients
+                        .iter()
+                        .map(|rcpt| rcpt.address.clone())
+                        .collect(),
+                    remote_ip: String::new(),
+                    helo_domain: String::new(),
+                    authenticated_as: String::new(),
+                    hostname: core
+                        .queue
+                        .config
+                        .hostname
+                        .eval(message.as_ref())
+                        .await
+                        .to_string(),
+                    schedule: Vec::new(),
+                    in_flight: Vec::new(),
+                },
+                message.path.clone(),
+                message.size,
+            ));
+        }
+        if pending.is_empty() {
+            return;
+        }
+
+        tracing::info!(
+            context = "filter",
+            event = "deferred-resume",
+            count = pending.len(),
+            "Resuming deferred content scans."
+        );
+        let core = core.clone();
+        tokio::spawn(async move {
+            let span = tracing::Span::current();
+            for (mut scan, path, size) in pending {
+                let mut message = vec![0u8; size];
+                match fs::File::open(&path).await {
+                    Ok(mut file) => {
+                        if let Err(err) = file.read_exact(&mut message).await {
+                            tracing::error!(
+                                context = "filter",
+                                event = "error",
+                                id = scan.queue_id,
+                                "Failed to read message file {}: {}",
+                                path.display(),
+                                err
+                            );
+                            continue;
+                        }
+                    }
+                    Err(err) => {
+                        tracing::error!(
+                            context = "filter",
+                            event = "error",
+                            id = scan.queue_id,
+                            "Failed to open message file {}: {}",
+                            path.display(),
+                            err
+                        );
+                        continue;
+                    }
+                }
+                scan.message = message;
+                scan.run(&core, &span).await;
+            }
+        });
+    }
+
+    async fn run(self, core: &SMTP, span: &tracing::Span) {
+        let verdict = self.scan(core, span).await;
+        if core
+            .queue
+            .tx
+            .send(Event::Scanned(ScanResult {
+                queue_id: self.queue_id,
+                schedule: self.schedule,
+                verdict,
+            }))
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                parent: span,
+                context = "filter",
+                event = "error",
+                id = self.queue_id,
+                "Queue channel closed, message will be scanned again on restart.");
+        }
+    }
+
+    async fn scan(&self, core: &SMTP, span: &tracing::Span) -> ScanVerdict {
+        let request = FilterRequest {
+            message: &self.message,
+            sender: &self.sender,
+            recipients: self.recipients.iter().map(|r| r.as_str()).collect(),
+            remote_ip: self.remote_ip.clone(),
+            helo_domain: &self.helo_domain,
+            authenticated_as: &self.authenticated_as,
+        };
+
+        for filter in self
+            .filters
+            .iter()
+            .filter_map(|idx| core.session.config.data.filters.get(*idx))
+        {
+            let policy = if let Some(policy) = &filter.deferred {
+                policy
+            } else {
+                continue;
+            };
+            let time = Instant::now();
+            let result = match tokio::time::timeout(filter.timeout, filter.run(&request)).await {
+                Ok(result) => result,
+                Err(_) => Err("Filter timed out.".to_string()),
+            };
+
+            let (action, reply, reason) = match result {
+                Ok(response) => {
+                    tracing::debug!(
+                        parent: span,
+                        context = "filter",
+                        event = "deferred-response",
+                        id = self.queue_id,
+                        filter = &filter.id,
+                        action = ?response.action,
+                        elapsed = ?time.elapsed(),
+                    );
+
+                    // The message has already been queued, so it can no longer be modified
+                    if !response.add_headers.is_empty() || response.replace.is_some() {
+                        tracing::debug!(
+                            parent: span,
+                            context = "filter",
+                            event = "ignored-modifications",
+                            id = self.queue_id,
+                            filter = &filter.id,
+                            "Deferred content filters cannot modify messages.");
+                    }
+
+                    match response.action {
+                        FilterAction::Accept => continue,
+                        FilterAction::Quarantine => (
+                            DeferredScanAction::Quarantine,
+                            response.reply,
+                            response.reason,
+                        ),
+                        FilterAction::Discard => {
+                            (DeferredScanAction::Discard, response.reply, response.reason)
+                        }
+                        FilterAction::Reject => (policy.on_reject, response.reply, response.reason),
+                        FilterAction::TempFail => {
+                            (policy.on_tempfail, response.reply, response.reason)
+                        }
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        parent: span,
+                        context = "filter",
+                        event = "error",
+                        id = self.queue_id,
+                        filter = &filter.id,
+                        reason = %err,
+                        "Deferred content filter failed.");
+                    (policy.on_error, None, err.into())
+                }
+            };
+
+            let reason = reason.unwrap_or_else(|| format!("Flagged by filter {}", filter.id));
+            match action {
+                DeferredScanAction::Deliver => (),
+                DeferredScanAction::Quarantine => return ScanVerdict::Quarantine(reason),
+                DeferredScanAction::Discard => return ScanVerdict::Discard(reason),
+                DeferredScanAction::Bounce => {
+                    return ScanVerdict::Bounce(HostResponse {
+                        hostname: ErrorDetails {
+                            entity: self.hostname.clone(),
+                            details: String::new(),
+                        },
+                        response: filter_response(
+                            reply.as_deref(),
+                            "550 5.7.1 Message rejected by content filter.",
+                        ),
+                    })
+                }
+            }
+        }
+
+        ScanVerdict::Deliver
+    }
+}
+
+impl ContentFilter {
+    pub async fn run(&self, request: &FilterRequest<'_>) -> Result<FilterResponse, String> {
+        match &self.protocol {
+            FilterProtocol::Command { command, arguments } => {
+                self.run_command(command, arguments, request).await
+            }
+            FilterProtocol::Grpc { url, client } => self.call_grpc(url, client, request).await,
+        }
+    }
+
+    async fn run_command(
+        &self,
+        command: &str,
+        arguments: &[String],
+        request: &FilterRequest<'_>,
+    ) -> Result<FilterResponse, String> {
+        let mut child = Command::new(command)
+            .args(arguments)
+            .env("SMTP_SENDER", request.sender)
+            .env("SMTP_RECIPIENTS", request.recipients.join(","))
+            .env("SMTP_REMOTE_IP", &request.remote_ip)
+            .env("SMTP_HELO_DOMAIN", request.helo_domain)
+            .env("SMTP_AUTHENTICATED_AS", request.authenticated_as)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| format!("Failed to spawn {command:?}: {err}"))?;
+
+        let mut stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| "Failed to open stdin.".to_string())?;
+        let mut stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| "Failed to open stdout.".to_string())?;
+
+        // Write and read concurrently to avoid deadlocks on large messages
+        let max_response_size = self.max_response_size;
+        let (write_result, read_result) = tokio::join!(
+            async move {
+                let result = stdin.write_all(request.message).await;
+                drop(stdin);
+                result
+            },
+            async move {
+                let mut output = Vec::new();
+                let mut buf = [0u8; 8192];
+                loop {
+                    match stdout.read(&mut buf).await {
+                        Ok(0) => break Ok(output),
+                        Ok(n) if output.len() + n <= max_response_size => {
+                            output.extend_from_slice(&buf[..n]);
+                        }
+                        Ok(_) => break Err("Filter response exceeds maximum size.".to_string()),
+                        Err(err) => break Err(format!("Failed to read filter output: {err}")),
+                    }
+                }
+            }
+        );
+        write_result.map_err(|err| format!("Failed to write message to filter: {err}"))?;
+        let output = read_result?;
+
+        let status = child
+            .wait()
+            .await
+            .map_err(|err| format!("Failed to wait for filter: {err}"))?;
+        if status.success() {
+            FilterResponse::parse_command_output(&output)
+        } else {
+            Err(format!("Filter exited with status {status}."))
+        }
+    }
+
+    async fn call_grpc(
+        &self,
+        url: &str,
+        client: &reqwest::Client,
+        request: &FilterRequest<'_>,
+    ) -> Result<FilterResponse, String> {
+        let response = client
+            .post(url)
+            .header(CONTENT_TYPE, "application/grpc")
+            .header("te", "trailers")
+            .body(grpc_frame(&request.encode()))
+            .send()
+            .await
+            .map_err(|err| format!("gRPC request failed: {err}"))?;
+
+        if !response.status().is_success() {
+            return Err(format!(
+                "gRPC request failed with status {}",
+                response.status()
+            ));
+        }
+
+        // Errors are returned as trailers-only responses
+        if let Some(status) = response
+            .headers()
+            .get("grpc-status")
+            .and_then(|v| v.to_str().ok())
+        {
+            if status != "0" {
+                return Err(format!(
+                    "gRPC call failed with status {status}: {}",
+                    response
+                        .headers()
+                        .get("grpc-message")
+                        .and_then(|v| v.to_str().ok())
+                        .unwrap_or_default()
+                ));
+            }
+        }
+
+        let body = response
+            .bytes()
+            .await
+            .map_err(|err| format!("Failed to read gRPC response: {err}"))?;
+        if body.len() > self.max_response_size + 5 {
+            return Err("gRPC response exceeds maximum size.".to_string());
+        }
+        match body.first() {
+            Some(0) if body.len() >= 5 => {
+                let len = u32::from_be_bytes(body[1..5].try_into().unwrap()) as usize;
+                body.get(5..5 + len)
+                    .ok_or_else(|| "Truncated gRPC response.".to_string())
+                    .and_then(FilterResponse::decode)
+            }
+            Some(_) => Err("Unsupported or compressed gRPC response.".to_string()),
+            None => Err("Empty gRPC response.".to_string()),
+        }
+    }
+}
+
+impl FilterResponse {
+    // Parses the output of a filter command, consisting of a block of
+    // directives followed by an optional blank line and a replacement message:
+    //
+    // Action: accept | reject | tempfail | discard | quarantine
+    // Reply: 550 5.7.1 Message contains a virus
+    // Reason: Eicar-Test-Signature
+    // Add-Header: X-Filter-Score: 3.5
+    pub fn parse_command_output(output: &[u8]) -> Result<Self, String> {
+        let mut response = FilterResponse::default();
+        let mut pos = 0;
+
+        while pos < output.len() {
+            let line_end = output[pos..]
+                .iter()
+                .position(|&ch| ch == b'\n')
+                .map_or(output.len(), |p| pos + p);
+            let line = std::str::from_utf8(&output[pos..line_end])
+                .map_err(|_| "Invalid UTF-8 in filter response.".to_string())?
+                .trim_end_matches('\r');
+            pos = line_end + 1;
+
+            if line.is_empty() {
+                if pos < output.len() {
+                    response.replace = output[pos..].to_vec().into();
+                }
+                break;
+            }
+
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| format!("Invalid filter response line {line:?}."))?;
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "action" => {
+                    response.action = FilterAction::parse(value)
+                        .ok_or_else(|| format!("Invalid filter action {value:?}."))?;
+                }
+                "reply" => {
+                    response.reply = value.to_string().into();
+                }
+                "reason" => {
+                    response.reason = value.to_string().into();
+                }
+                "add-header" => {
+                    let (name, value) = value
+                        .split_once(':')
+                        .ok_or_else(|| format!("Invalid header {value:?}."))?;
+                    response
+                        .add_headers
+                        .push((name.trim().to_string(), value.trim().to_string()));
+                }
+                _ => return Err(format!("Unknown filter directive {name:?}.")),
+            }
+        }
+
+        Ok(response)
+    }
+
+    // message FilterResponse {
+    //   Action action = 1;
+    //   string reply = 2;
+    //   string reason = 3;
+    //   repeated Header add_headers = 4;
+    //   bytes replace_message = 5;
+    // }
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let mut response = FilterResponse::default();
+        let mut reader = ProtoReader { bytes, pos: 0 };
+
+        while let Some((field, value)) = reader.next_field()? {
+            match (field, value) {
+                (1, ProtoValue::Varint(action)) => {
+                    response.action = match action {
+                        0 => FilterAction::Accept,
+                        1 => FilterAction::Reject,
+                        2 => FilterAction::TempFail,
+                        3 => FilterAction::Discard,
+                        4 => FilterAction::Quarantine,
+                        _ => return Err(format!("Invalid filter action {action}.")),
+                    };
+                }
+                (2, ProtoValue::Bytes(reply)) => {
+                    response.reply = proto_string(reply)?.into();
+                }
+                (3, ProtoValue::Bytes(reason)) => {
+                    response.reason = proto_string(reason)?.into();
+                }
+                (4, ProtoValue::Bytes(header)) => {
+                    let mut reader = ProtoReader {
+                        bytes: header,
+                        pos: 0,
+                    };
+                    let mut name = String::new();
+                    let mut value = String::new();
+                    while let Some((field, field_value)) = reader.next_field()? {
+                        match (field, field_value) {
+                            (1, ProtoValue::Bytes(bytes)) => name = proto_string(bytes)?,
+                            (2, ProtoValue::Bytes(bytes)) => value = proto_string(bytes)?,
+                            _ => (),
+                        }
+                    }
+                    if !name.is_empty() {
+                        response.add_headers.push((name, value));
+                    }
+                }
+                (5, ProtoValue::Bytes(message)) => {
+                    if !message.is_empty() {
+                        response.replace = message.to_vec().into();
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(response)
+    }
+}
+
+impl FilterRequest<'_> {
+    // message FilterRequest {
+    //   bytes message = 1;
+    //   string sender = 2;
+    //   repeated string recipients = 3;
+    //   string remote_ip = 4;
+    //   string helo_domain = 5;
+    //   string authenticated_as = 6;
+    // }
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(self.message.len() + 256);
+        write_proto_bytes(&mut buf, 1, self.message);
+        write_proto_bytes(&mut buf, 2, self.sender.as_bytes());
+        for rcpt in &self.recipients {
+            write_proto_bytes(&mut buf, 3, rcpt.as_bytes());
+        }
+        write_proto_bytes(&mut buf, 4, self.remote_ip.as_bytes());
+        write_proto_bytes(&mut buf, 5, self.helo_domain.as_bytes());
+        write_proto_bytes(&mut buf, 6, self.authenticated_as.as_bytes());
+        buf
+    }
+}
+
+impl FilterAction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "accept" => Some(FilterAction::Accept),
+            "reject" => Some(FilterAction::Reject),
+            "tempfail" => Some(FilterAction::TempFail),
+            "discard" => Some(FilterAction::Discard),
+            "quarantine" => Some(FilterAction::Quarantine),
+            _ => None,
+        }
+    }
+}
+
+enum ProtoValue<'x> {
+    Varint(u64),
+    Bytes(&'x [u8]),
+    Fixed,
+}
+
+struct ProtoReader<'x> {
+    bytes: &'x [u8],
+    pos: usize,
+}
+
+impl<'x> ProtoReader<'x> {
+    fn next_field(&mut self) -> Result<Option<(u64, ProtoValue<'x>)>, String> {
+        if self.pos >= self.bytes.len() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let value = match key & 0x07 {
+            0 => ProtoValue::Varint(self.varint()?),
+            1 => self.skip(8)?,
+            2 => {
+                let len = self.varint()? as usize;
+                let bytes = self
+                    .bytes
+                    .get(self.pos..self.pos + len)
+                    .ok_or_else(|| "Truncated protobuf message.".to_string())?;
+                self.pos += len;
+                ProtoValue::Bytes(bytes)
+            }
+            5 => self.skip(4)?,
+            wire_type => return Err(format!("Unsupported protobuf wire type {wire_type}.")),
+        };
+        Ok(Some((key >> 3, value)))
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = *self
+                .bytes
+                .get(self.pos)
+                .ok_or_else(|| "Truncated protobuf varint.".to_string())?;
+            self.pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("Invalid protobuf varint.".to_string())
+    }
+
+    fn skip(&mut self, len: usize) -> Result<ProtoValue<'x>, String> {
+        if self.pos + len <= self.bytes.len() {
+            self.pos += len;
+            Ok(ProtoValue::Fixed)
+        } else {
+            Err("Truncated protobuf message.".to_string())
+        }
+    }
+}
+
+fn write_proto_bytes(buf: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    if !bytes.is_empty() {
+        write_varint(buf, (field << 3) | 2);
+        write_varint(buf, bytes.len() as u64);
+        buf.extend_from_slice(bytes);
+    }
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn proto_string(bytes: &[u8]) -> Result<String, String> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| "Invalid UTF-8 in protobuf string.".to_string())
+}
+
+fn grpc_frame(message: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(message.len() + 5);
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message);
+    frame
+}
+
+fn filter_response(reply: Option<&str>, default: &str) -> Response<String> {
+    let reply = String::from_utf8(filter_reply(reply, default)).unwrap_or_default();
+    let reply = reply.trim_end();
+    let code = reply
+        .get(..3)
+        .and_then(|code| code.parse::<u16>().ok())
+        .unwrap_or(550);
+    let text = reply.get(4..).unwrap_or_default();
+
+    // Split the enhanced status code, if present
+    let (esc, message) = text
+        .split_once(' ')
+        .and_then(|(status, message)| {
+            let mut esc = [0u8; 3];
+            let mut parts = status.split('.');
+            for esc in esc.iter_mut() {
+                *esc = parts.next()?.parse().ok()?;
+            }
+            parts.next().is_none().then_some((esc, message))
+        })
+        .unwrap_or(([5, 7, 1], text));
+
+    Response {
+        code,
+        esc,
+        message: message.to_string(),
+    }
+}
+
+fn filter_reply(reply: Option<&str>, default: &str) -> Vec<u8> {
+    let mut reply = match reply {
+        Some(reply)
+            if reply.len() > 4
+                && reply.as_bytes()[..3].iter().all(|ch| ch.is_ascii_digit())
+                && reply.as_bytes()[3] == b' ' =>
+        {
+            reply.to_string()
+        }
+        Some(reply) if !reply.is_empty() => {
+            // Reuse the default status and enhanced status codes
+            let codes = default.splitn(3, ' ').take(2).collect::<Vec<_>>().join(" ");
+            format!("{codes} {reply}")
+        }
+        _ => default.to_string(),
+    };
+    if !reply.ends_with('\n') {
+        reply.push_str("\r\n");
+    }
+    reply.into_bytes()
+}
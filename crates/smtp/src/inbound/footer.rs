@@ -0,0 +1,339 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use mail_builder::encoders::base64::base64_encode_mime;
+use mail_parser::{
+    decoders::{base64::base64_decode, quoted_printable::quoted_printable_decode},
+    Encoding, MessageParser, MimeHeaders, PartType,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use utils::codec::html::html_escape;
+
+use crate::core::Session;
+
+use super::IsTls;
+
+impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
+    // Appends a footer to submitted messages, messages generated by Sieve scripts
+    // are left untouched. The footer required by the domain policy of the sender
+    // takes precedence over the one selected by "session.data.footer", so each
+    // message receives at most one footer.
+    pub async fn apply_footer(&self, raw_message: &Arc<Vec<u8>>) -> Option<Vec<u8>> {
+        if self.data.authenticated_as.is_empty() || self.instance.id == "sieve" {
+            return None;
+        }
+        let sender = self.data.mail_from.as_ref()?;
+
+        #[cfg(feature = "local_delivery")]
+        if let Some(result) = self.apply_policy_footer(raw_message).await {
+            tracing::debug!(
+                parent: &self.span,
+                context = "footer",
+                event = "append",
+                id = "domain-policy",
+                from = sender.address,
+            );
+            return Some(result);
+        }
+
+        let footer = self
+            .core
+            .session
+            .config
+            .data
+            .footer
+            .eval(self)
+            .await
+            .as_ref()?;
+
+        // Obtain the sender's attributes from the directory, local submissions
+        // are matched by their sender address
+        let directory = match &self.params.auth_directory {
+            Some(directory) => Some(directory.clone()),
+            None => self
+                .core
+                .session
+                .config
+                .auth
+                .directory
+                .eval_and_capture(self)
+                .await
+                .into_value(self),
+        };
+        let mut account = String::new();
+        let mut name = None;
+        if let Some(directory) = directory {
+            account = if self.data.authenticated_as != "local" {
+                self.data.authenticated_as.clone()
+            } else {
+                directory
+                    .names_by_email(&sender.address_lcase)
+                    .await
+                    .unwrap_or_default()
+                    .into_iter()
+                    .next()
+                    .unwrap_or_default()
+            };
+            if !account.is_empty() {
+                name = directory
+                    .principal(&account)
+                    .await
+                    .ok()
+                    .flatten()
+                    .and_then(|principal| principal.description);
+            }
+        }
+        let variables = [
+            ("email", sender.address.as_str()),
+            ("domain", sender.domain.as_str()),
+            ("account", account.as_str()),
+            ("name", name.as_deref().unwrap_or(account.as_str())),
+        ];
+
+        let text = render_template(&footer.text, &variables, false);
+        let html = footer
+            .html
+            .as_ref()
+            .map(|html| render_template(html, &variables, true));
+        let result = append_footer(raw_message, &text, html.as_deref());
+
+        tracing::debug!(
+            parent: &self.span,
+            context = "footer",
+            event = if result.is_some() { "append" } else { "skip" },
+            id = footer.id,
+            from = sender.address,
+        );
+
+        result
+    }
+
+    // Appends the compliance footer required by the domain policy of the sender,
+    // which is managed by the JMAP server
+    #[cfg(feature = "local_delivery")]
+    async fn apply_policy_footer(&self, raw_message: &Arc<Vec<u8>>) -> Option<Vec<u8>> {
+        let sender = self.data.mail_from.as_ref()?;
+        let (result_tx, result_rx) = tokio::sync::oneshot::channel();
+        self.core
+            .delivery_tx
+            .send(utils::ipc::DeliveryEvent::ApplyPolicyFooter {
+                sender: sender.address.clone(),
+                message: raw_message.clone(),
+                result_tx,
+            })
+            .await
+            .ok()?;
+        result_rx.await.ok().flatten()
+    }
+}
+
+// Replaces {{name}} placeholders with the sender's attributes, unknown
+// placeholders are left as they are.
+pub fn render_template(template: &str, variables: &[(&str, &str)], escape_html: bool) -> String {
+    let mut result = String::with_capacity(template.len() + 32);
+    let mut template = template;
+
+    while let Some(start) = template.find("{{") {
+        result.push_str(&template[..start]);
+        let rest = &template[start + 2..];
+        if let Some((name, value)) = rest.find("}}").and_then(|end| {
+            let name = rest[..end].trim();
+            variables
+                .iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| (&rest[..end], *value))
+        }) {
+            if escape_html {
+                result.push_str(&html_escape(value));
+            } else {
+                result.push_str(value);
+            }
+            template = &rest[name.len() + 2..];
+        } else {
+            result.push_str("{{");
+            template = rest;
+        }
+    }
+    result.push_str(template);
+
+    result
+}
+
+// Appends a footer to the text and HTML bodies of a message, returns None
+// when the message cannot be modified. When no HTML footer is provided,
+// the text footer is used in HTML bodies.
+pub fn append_footer(raw_message: &[u8], text: &str, html: Option<&str>) -> Option<Vec<u8>> {
+    let message = MessageParser::new().parse(raw_message)?;
+
+    // Signed or encrypted messages cannot be modified without invalidating them
+    let root = message.parts.first()?;
+    if root.is_content_type("multipart", "signed")
+        || root.is_content_type("multipart", "encrypted")
+        || root.is_content_type("application", "pkcs7-mime")
+        || root.is_content_type("application", "x-pkcs7-mime")
+    {
+        return None;
+    }
+
+    let mut part_ids = Vec::new();
+    for part_id in message.text_body.iter().chain(message.html_body.iter()) {
+        if !part_ids.contains(part_id) {
+            part_ids.push(*part_id);
+        }
+    }
+
+    let mut edits = Vec::with_capacity(part_ids.len());
+    for part_id in part_ids {
+        let part = &message.parts[part_id];
+        let is_html = match &part.body {
+            PartType::Text(_) => false,
+            PartType::Html(_) => true,
+            _ => continue,
+        };
+        if part
+            .content_disposition()
+            .map_or(false, |cd| cd.ctype().eq_ignore_ascii_case("attachment"))
+            || !part
+                .content_type()
+                .and_then(|ct| ct.attribute("charset"))
+                .map_or(true, |charset| {
+                    ["us-ascii", "utf-8", "utf8"]
+                        .iter()
+                        .any(|c| charset.eq_ignore_ascii_case(c))
+                })
+        {
+            continue;
+        }
+
+        let raw_body = raw_message.get(part.offset_body..part.offset_end)?;
+        let body = match part.encoding {
+            Encoding::None => raw_body.to_vec(),
+            Encoding::Base64 => base64_decode(raw_body)?,
+            Encoding::QuotedPrintable => quoted_printable_decode(raw_body)?,
+        };
+        let body = if let Ok(body) = String::from_utf8(body) {
+            body
+        } else {
+            continue;
+        };
+
+        let body = if is_html {
+            let footer = html.map(|html| html.to_string()).unwrap_or_else(|| {
+                format!(
+                    "<div>{}</div>",
+                    html_escape(text).replace('\r', "").replace('\n', "<br>")
+                )
+            });
+            if let Some(pos) = body.to_ascii_lowercase().rfind("</body>") {
+                format!("{}{}{}", &body[..pos], footer, &body[pos..])
+            } else {
+                format!("{body}{footer}")
+            }
+        } else {
+            let trimmed = body.trim_end();
+            format!(
+                "{}\r\n\r\n{}{}",
+                trimmed,
+                text.replace("\r\n", "\n").replace('\n', "\r\n"),
+                &body[trimmed.len()..]
+            )
+        };
+
+        let mut encoded = match part.encoding {
+            Encoding::None => body.into_bytes(),
+            Encoding::Base64 => {
+                let mut encoded = Vec::with_capacity(body.len() * 4 / 3 + 4);
+                base64_encode_mime(body.as_bytes(), &mut encoded, false).ok()?;
+                encoded
+            }
+            Encoding::QuotedPrintable => quoted_printable_encode(body.as_bytes()),
+        };
+
+        // Preserve the line break before the next boundary
+        if !raw_body.ends_with(b"\n") {
+            while encoded
+                .last()
+                .map_or(false, |ch| *ch == b'\n' || *ch == b'\r')
+            {
+                encoded.pop();
+            }
+        } else if !encoded.ends_with(b"\n") {
+            encoded.extend_from_slice(b"\r\n");
+        }
+
+        edits.push((part.offset_body, part.offset_end, encoded));
+    }
+
+    if edits.is_empty() {
+        return None;
+    }
+    edits.sort_unstable_by_key(|(start, _, _)| *start);
+
+    let mut output = Vec::with_capacity(raw_message.len() + text.len() * 4);
+    let mut last_offset = 0;
+    for (start, end, encoded) in edits {
+        output.extend_from_slice(raw_message.get(last_offset..start)?);
+        output.extend_from_slice(&encoded);
+        last_offset = end;
+    }
+    output.extend_from_slice(raw_message.get(last_offset..)?);
+
+    Some(output)
+}
+
+fn quoted_printable_encode(input: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() + input.len() / 2);
+    let mut line_len = 0;
+    let mut iter = input.iter().peekable();
+
+    while let Some(&ch) = iter.next() {
+        if ch == b'\n' || (ch == b'\r' && iter.peek() == Some(&&b'\n')) {
+            if ch == b'\r' {
+                iter.next();
+            }
+            output.extend_from_slice(b"\r\n");
+            line_len = 0;
+            continue;
+        }
+
+        // Trailing whitespace has to be encoded to survive transport
+        let needs_encoding = (!(b'!'..=b'~').contains(&ch) && ch != b' ')
+            || ch == b'='
+            || (ch == b' ' && matches!(iter.peek(), None | Some(b'\r' | b'\n')));
+        let len = if needs_encoding { 3 } else { 1 };
+        if line_len + len > 75 {
+            output.extend_from_slice(b"=\r\n");
+            line_len = 0;
+        }
+        if needs_encoding {
+            output.extend_from_slice(format!("={ch:02X}").as_bytes());
+        } else {
+            output.push(ch);
+        }
+        line_len += len;
+    }
+
+    output
+}
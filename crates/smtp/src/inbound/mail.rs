@@ -24,16 +24,18 @@
 use std::time::SystemTime;
 
 use mail_auth::{IprevOutput, IprevResult, SpfOutput, SpfResult};
-use smtp_proto::{MailFrom, MAIL_BY_NOTIFY, MAIL_BY_RETURN, MAIL_REQUIRETLS};
+use smtp_proto::{
+    MailFrom, MAIL_BY_NOTIFY, MAIL_BY_RETURN, MAIL_REQUIRETLS, MAIL_RET_FULL, MAIL_RET_HDRS,
+};
 use tokio::io::{AsyncRead, AsyncWrite};
 
 use crate::{
     core::{Session, SessionAddress},
-    queue::DomainPart,
+    queue::{backpressure::METRIC_BACKPRESSURE_DEFERRED, DomainPart},
     scripts::{ScriptModification, ScriptResult},
 };
 
-use super::IsTls;
+use super::{policy::PolicyStage, IsTls};
 
 impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
     pub async fn handle_mail_from(&mut self, from: MailFrom<String>) -> Result<(), ()> {
@@ -53,6 +55,16 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
             return self
                 .write(b"503 5.5.1 You must authenticate first.\r\n")
                 .await;
+        } else if self.params.auth_client_cert_require && self.data.client_cert_identity.is_empty()
+        {
+            return self
+                .write(b"530 5.7.0 A valid client certificate is required.\r\n")
+                .await;
+        } else if self.core.is_queue_backpressured(&self.span) {
+            utils::metrics::increment(METRIC_BACKPRESSURE_DEFERRED, 1);
+            return self
+                .write(b"452 4.3.1 Mail system full, try again later.\r\n")
+                .await;
         } else if self.data.iprev.is_none() && self.params.iprev.verify() {
             let iprev = self
                 .core
@@ -104,7 +116,7 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
             (String::new(), String::new(), String::new())
         };
 
-        let has_dsn = from.env_id.is_some();
+        let has_dsn = from.env_id.is_some() || (from.flags & (MAIL_RET_FULL | MAIL_RET_HDRS)) != 0;
         self.data.mail_from = SessionAddress {
             address,
             address_lcase,
@@ -147,6 +159,26 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
             }
         }
 
+        // Policy delegation
+        let policies = self
+            .core
+            .session
+            .config
+            .mail
+            .policy
+            .eval_and_capture(self)
+            .await
+            .into_value(self);
+        if let Err(message) = self.run_policies(PolicyStage::Mail, policies).await {
+            tracing::info!(parent: &self.span,
+                context = "policy",
+                event = "reject",
+                address = &self.data.mail_from.as_ref().unwrap().address,
+                reason = std::str::from_utf8(&message).unwrap_or_default());
+            self.data.mail_from = None;
+            return self.write(&message).await;
+        }
+
         // Address rewriting
         if let Some(new_address) = self
             .core
@@ -323,6 +355,7 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
                 event = "success",
                 address = &self.data.mail_from.as_ref().unwrap().address);
 
+            self.params.declared_size = from.size;
             self.eval_rcpt_params().await;
             self.write(b"250 2.1.0 OK\r\n").await
         } else {
@@ -321,6 +321,10 @@ impl<T: AsyncRead + AsyncWrite + Unpin> MilterClient<T> {
         self.write(Command::Quit).await
     }
 
+    pub async fn quit_new_connection(&mut self) -> super::Result<()> {
+        self.write(Command::QuitNewConnection).await
+    }
+
     async fn write(&mut self, action: Command<'_>) -> super::Result<()> {
         //let p = println!("Action: {}", action);
         tracing::trace!(parent: &self.span, context = "milter", event = "write", "action" = action.to_string());
@@ -369,4 +373,9 @@ impl<T: AsyncRead + AsyncWrite + Unpin> MilterClient<T> {
         self.version = version;
         self
     }
+
+    pub fn with_span(mut self, span: tracing::Span) -> Self {
+        self.span = span;
+        self
+    }
 }
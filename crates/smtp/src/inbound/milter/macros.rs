@@ -119,6 +119,14 @@ impl<'x> Macros<'x> {
         self.with_macro(b"{daemon_port}", daemon_port)
     }
 
+    pub fn with_interface_address(self, if_address: impl IntoMacroValue<'x>) -> Self {
+        self.with_macro(b"{if_addr}", if_address)
+    }
+
+    pub fn with_interface_name(self, if_name: impl IntoMacroValue<'x>) -> Self {
+        self.with_macro(b"{if_name}", if_name)
+    }
+
     pub fn with_mail_address(self, mail_address: impl IntoMacroValue<'x>) -> Self {
         self.with_macro(b"{mail_addr}", mail_address)
     }
@@ -148,7 +156,7 @@ impl<'x> Macros<'x> {
     }
 
     pub fn with_version(self, version: impl IntoMacroValue<'x>) -> Self {
-        self.with_macro(b"{v}", version)
+        self.with_macro(b"v", version)
     }
 }
 
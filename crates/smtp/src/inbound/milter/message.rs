@@ -23,9 +23,12 @@
 
 use std::borrow::Cow;
 
-use mail_auth::AuthenticatedMessage;
+use mail_auth::{AuthenticatedMessage, IprevResult};
 use smtp_proto::request::parser::Rfc5321Parser;
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
 
 use crate::{
     config::Milter,
@@ -35,7 +38,7 @@ use crate::{
     DAEMON_NAME,
 };
 
-use super::{Action, Error, Macros, Modification};
+use super::{Action, Error, Macros, Modification, Version};
 
 enum Rejection {
     Action(Action),
@@ -128,43 +131,95 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
         milter: &Milter,
         message: &AuthenticatedMessage<'_>,
     ) -> Result<Vec<Modification>, Rejection> {
-        // Build client
-        let client = MilterClient::connect(milter, self.span.clone()).await?;
-        if !milter.tls {
-            self.run(client, message).await
+        if milter.tls {
+            let mut client = MilterClient::connect(milter, self.span.clone())
+                .await?
+                .into_tls(
+                    if !milter.tls_allow_invalid_certs {
+                        &self.core.queue.connectors.pki_verify
+                    } else {
+                        &self.core.queue.connectors.dummy_verify
+                    },
+                    &milter.hostname,
+                )
+                .await?;
+            client.init().await?;
+            let result = self.run(&mut client, message).await;
+            let _ = client.quit().await;
+            return result;
+        }
+
+        // Try reusing an idle connection
+        if milter.reuse_connection {
+            let idle_client = milter.connections.lock().pop();
+            if let Some(client) = idle_client {
+                let mut client = client.with_span(self.span.clone());
+                match self.run(&mut client, message).await {
+                    Err(Rejection::Error(err)) => {
+                        tracing::debug!(
+                            parent: &self.span,
+                            milter.host = &milter.hostname,
+                            milter.port = &milter.port,
+                            context = "milter",
+                            event = "reuse-failed",
+                            reason = ?err,
+                            "Failed to reuse milter connection, reconnecting.");
+                    }
+                    result => {
+                        self.release_client(milter, client).await;
+                        return result;
+                    }
+                }
+            }
+        }
+
+        let mut client = MilterClient::connect(milter, self.span.clone()).await?;
+        client.init().await?;
+        match self.run(&mut client, message).await {
+            Err(Rejection::Error(err)) => Err(Rejection::Error(err)),
+            result => {
+                self.release_client(milter, client).await;
+                result
+            }
+        }
+    }
+
+    async fn release_client(&self, milter: &Milter, mut client: MilterClient<TcpStream>) {
+        let has_room = milter.reuse_connection
+            && matches!(client.version, Version::V6)
+            && milter.connections.lock().len() < milter.max_idle_connections;
+        if has_room {
+            // Ask the milter to reset its state and wait for a new connection,
+            // connections in excess of the idle limit are closed.
+            if client.quit_new_connection().await.is_ok() {
+                let mut connections = milter.connections.lock();
+                if connections.len() < milter.max_idle_connections {
+                    connections.push(client);
+                }
+            }
         } else {
-            self.run(
-                client
-                    .into_tls(
-                        if !milter.tls_allow_invalid_certs {
-                            &self.core.queue.connectors.pki_verify
-                        } else {
-                            &self.core.queue.connectors.dummy_verify
-                        },
-                        &milter.hostname,
-                    )
-                    .await?,
-                message,
-            )
-            .await
+            let _ = client.quit().await;
         }
     }
 
     async fn run<S: AsyncRead + AsyncWrite + Unpin>(
         &self,
-        mut client: MilterClient<S>,
+        client: &mut MilterClient<S>,
         message: &AuthenticatedMessage<'_>,
     ) -> Result<Vec<Modification>, Rejection> {
-        // Option negotiation
-        client.init().await?;
-
         // Connect stage
-        let client_ptr = self
+        let (client_ptr, is_verified) = self
             .data
             .iprev
             .as_ref()
-            .and_then(|ip_rev| ip_rev.ptr.as_ref())
-            .and_then(|ptrs| ptrs.first());
+            .map(|ip_rev| {
+                (
+                    ip_rev.ptr.as_ref().and_then(|ptrs| ptrs.first()),
+                    matches!(ip_rev.result(), IprevResult::Pass),
+                )
+            })
+            .unwrap_or((None, false));
+        let client_name = client_ptr.filter(|_| is_verified);
         client
             .connection(
                 client_ptr.unwrap_or(&self.data.helo_domain),
@@ -172,10 +227,19 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                 self.data.remote_port,
                 Macros::new()
                     .with_daemon_name(DAEMON_NAME)
+                    .with_version(DAEMON_NAME)
                     .with_local_hostname(&self.instance.hostname)
+                    .with_interface_name(&self.instance.hostname)
+                    .with_interface_address(self.data.local_ip)
+                    .with_daemon_address(self.data.local_ip)
+                    .with_validated_client_name(match client_name {
+                        Some(name) => format!("{} [{}]", name, self.data.remote_ip),
+                        None => format!("[{}]", self.data.remote_ip),
+                    })
                     .with_client_address(self.data.remote_ip)
                     .with_client_port(self.data.remote_port)
-                    .with_client_ptr(client_ptr.map(|p| p.as_str()).unwrap_or("unknown")),
+                    .with_client_ptr(client_ptr.map(|p| p.as_str()).unwrap_or("unknown"))
+                    .with_client_name(client_name.map(|p| p.as_str()).unwrap_or("unknown")),
             )
             .await?
             .assert_continue()?;
@@ -193,15 +257,19 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
             .assert_continue()?;
 
         // Mail from
-        let addr = &self.data.mail_from.as_ref().unwrap().address_lcase;
+        let mail_from = self.data.mail_from.as_ref().unwrap();
+        let addr = &mail_from.address_lcase;
+        let mut macros = Macros::new()
+            .with_mail_address(addr)
+            .with_mail_host(&mail_from.domain)
+            .with_mail_mailer("smtp");
+        if !self.data.authenticated_as.is_empty() {
+            macros = macros
+                .with_sasl_login_name(&self.data.authenticated_as)
+                .with_sasl_sender(&self.data.authenticated_as);
+        }
         client
-            .mail_from(
-                &format!("<{addr}>"),
-                None::<&[&str]>,
-                Macros::new()
-                    .with_mail_address(addr)
-                    .with_sasl_login_name(&self.data.authenticated_as),
-            )
+            .mail_from(&format!("<{addr}>"), None::<&[&str]>, macros)
             .await?
             .assert_continue()?;
 
@@ -211,7 +279,10 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                 .rcpt_to(
                     &format!("<{}>", rcpt.address_lcase),
                     None::<&[&str]>,
-                    Macros::new().with_rcpt_address(&rcpt.address_lcase),
+                    Macros::new()
+                        .with_rcpt_address(&rcpt.address_lcase)
+                        .with_rcpt_host(&rcpt.domain)
+                        .with_rcpt_mailer("smtp"),
                 )
                 .await?
                 .assert_continue()?;
@@ -235,9 +306,6 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
         let (action, modifications) = client.body(message.raw_message()).await?;
         action.assert_continue()?;
 
-        // Quit
-        let _ = client.quit().await;
-
         // Return modifications
         Ok(modifications)
     }
@@ -333,10 +401,10 @@ impl SessionData {
                     body.extend(value);
                 }
                 Modification::AddHeader { name, value } => {
-                    header_changes.push((0, name, value, false));
+                    header_changes.push(HeaderChange::Add { name, value });
                 }
                 Modification::InsertHeader { index, name, value } => {
-                    header_changes.push((index, name, value, false));
+                    header_changes.push(HeaderChange::Insert { index, name, value });
                     needs_rewrite = true;
                 }
                 Modification::ChangeHeader { index, name, value } => {
@@ -346,14 +414,17 @@ impl SessionData {
                             .iter()
                             .any(|(n, _)| n.eq_ignore_ascii_case(name.as_bytes()))
                     {
-                        header_changes.push((index, name, value, true));
+                        header_changes.push(HeaderChange::Change { index, name, value });
                         needs_rewrite = true;
                     } else {
-                        header_changes.push((0, name, value, false));
+                        header_changes.push(HeaderChange::Add { name, value });
                     }
                 }
                 Modification::Quarantine { reason } => {
-                    header_changes.push((0, "X-Quarantine".to_string(), reason, false));
+                    header_changes.push(HeaderChange::Add {
+                        name: "X-Quarantine".to_string(),
+                        value: reason,
+                    });
                 }
             }
         }
@@ -382,46 +453,52 @@ impl SessionData {
                 .iter()
                 .map(|(h, v)| (Cow::from(*h), Cow::from(*v)))
                 .collect::<Vec<_>>();
+            let mut added_headers = Vec::new();
 
             // Perform changes
-            for (index, header_name, header_value, is_change) in header_changes {
-                if is_change {
-                    let mut header_count = 0;
-                    for (pos, (name, value)) in headers.iter_mut().enumerate() {
-                        if name.eq_ignore_ascii_case(header_name.as_bytes()) {
-                            header_count += 1;
-                            if header_count == index {
-                                if !header_value.is_empty() {
-                                    *value = Cow::from(header_value.into_bytes());
-                                } else {
-                                    headers.remove(pos);
-                                }
-                                break;
-                            }
-                        }
+            for change in header_changes {
+                match change {
+                    HeaderChange::Add { name, value } => {
+                        added_headers
+                            .push((Cow::from(name.into_bytes()), Cow::from(value.into_bytes())));
                     }
-                } else {
-                    let mut header_pos = 0;
-                    if index > 0 {
+                    HeaderChange::Insert { index, name, value } => {
+                        // The index refers to the absolute position in the header list
+                        headers.insert(
+                            (index as usize).min(headers.len()),
+                            (Cow::from(name.into_bytes()), Cow::from(value.into_bytes())),
+                        );
+                    }
+                    HeaderChange::Change { index, name, value } => {
                         let mut header_count = 0;
-                        for (pos, (name, _)) in headers.iter().enumerate() {
-                            if name.eq_ignore_ascii_case(header_name.as_bytes()) {
-                                header_pos = pos;
+                        let mut header_pos = None;
+                        for (pos, (header_name, _)) in headers.iter().enumerate() {
+                            if header_name.eq_ignore_ascii_case(name.as_bytes()) {
                                 header_count += 1;
                                 if header_count == index {
+                                    header_pos = Some(pos);
                                     break;
                                 }
                             }
                         }
-                    }
 
-                    headers.insert(
-                        header_pos,
-                        (
-                            Cow::from(header_name.into_bytes()),
-                            Cow::from(header_value.into_bytes()),
-                        ),
-                    );
+                        match header_pos {
+                            Some(pos) if !value.is_empty() => {
+                                headers[pos].1 = Cow::from(value.into_bytes());
+                            }
+                            Some(pos) => {
+                                headers.remove(pos);
+                            }
+                            None if !value.is_empty() => {
+                                // Index past the last occurrence, add the header
+                                added_headers.push((
+                                    Cow::from(name.into_bytes()),
+                                    Cow::from(value.into_bytes()),
+                                ));
+                            }
+                            None => (),
+                        }
+                    }
                 }
             }
 
@@ -429,12 +506,13 @@ impl SessionData {
             let mut new_message = Vec::with_capacity(
                 new_body.len()
                     + message.raw_headers().len()
-                    + headers
+                    + added_headers
                         .iter()
+                        .chain(headers.iter())
                         .map(|(h, v)| h.len() + v.len() + 4)
                         .sum::<usize>(),
             );
-            for (header, value) in headers {
+            for (header, value) in added_headers.into_iter().chain(headers) {
                 new_message.extend_from_slice(header.as_ref());
                 if value.first().map_or(false, |c| c.is_ascii_whitespace()) {
                     new_message.extend_from_slice(b":");
@@ -455,15 +533,17 @@ impl SessionData {
                     + message.raw_headers().len()
                     + header_changes
                         .iter()
-                        .map(|(_, h, v, _)| h.len() + v.len() + 4)
+                        .map(|change| change.len() + 4)
                         .sum::<usize>(),
             );
-            for (_, header, value, _) in header_changes {
-                new_message.extend_from_slice(header.as_bytes());
-                new_message.extend_from_slice(b": ");
-                new_message.extend_from_slice(value.as_bytes());
-                if !value.ends_with('\n') {
-                    new_message.extend_from_slice(b"\r\n");
+            for change in header_changes {
+                if let HeaderChange::Add { name, value } = change {
+                    new_message.extend_from_slice(name.as_bytes());
+                    new_message.extend_from_slice(b": ");
+                    new_message.extend_from_slice(value.as_bytes());
+                    if !value.ends_with('\n') {
+                        new_message.extend_from_slice(b"\r\n");
+                    }
                 }
             }
             new_message.extend_from_slice(message.raw_headers());
@@ -473,6 +553,33 @@ impl SessionData {
     }
 }
 
+enum HeaderChange {
+    Add {
+        name: String,
+        value: String,
+    },
+    Insert {
+        index: u32,
+        name: String,
+        value: String,
+    },
+    Change {
+        index: u32,
+        name: String,
+        value: String,
+    },
+}
+
+impl HeaderChange {
+    fn len(&self) -> usize {
+        match self {
+            HeaderChange::Add { name, value }
+            | HeaderChange::Insert { name, value, .. }
+            | HeaderChange::Change { name, value, .. } => name.len() + value.len(),
+        }
+    }
+}
+
 impl Action {
     fn assert_continue(self) -> Result<(), Rejection> {
         match self {
@@ -31,19 +31,27 @@ use tokio_rustls::server::TlsStream;
 use crate::config::{ArcSealer, DkimSigner};
 
 pub mod auth;
+pub mod bimi;
 pub mod data;
 pub mod ehlo;
+pub mod filter;
+pub mod footer;
 pub mod mail;
 pub mod milter;
+pub mod policy;
 pub mod rcpt;
 pub mod session;
 pub mod spawn;
+pub mod srs;
+pub mod transform;
 pub mod vrfy;
+pub mod xclient;
 
 pub trait IsTls {
     fn is_tls(&self) -> bool;
     fn write_tls_header(&self, headers: &mut Vec<u8>);
     fn tls_version_and_cipher(&self) -> (&'static str, &'static str);
+    fn peer_certificates(&self) -> Option<&[rustls::Certificate]>;
 }
 
 impl IsTls for TcpStream {
@@ -56,6 +64,10 @@ impl IsTls for TcpStream {
     fn tls_version_and_cipher(&self) -> (&'static str, &'static str) {
         ("", "")
     }
+
+    fn peer_certificates(&self) -> Option<&[rustls::Certificate]> {
+        None
+    }
 }
 
 impl IsTls for TlsStream<TcpStream> {
@@ -64,34 +76,11 @@ impl IsTls for TlsStream<TcpStream> {
     }
 
     fn tls_version_and_cipher(&self) -> (&'static str, &'static str) {
-        let (_, conn) = self.get_ref();
-
-        (
-            match conn
-                .protocol_version()
-                .unwrap_or(rustls::ProtocolVersion::Unknown(0))
-            {
-                rustls::ProtocolVersion::SSLv2 => "SSLv2",
-                rustls::ProtocolVersion::SSLv3 => "SSLv3",
-                rustls::ProtocolVersion::TLSv1_0 => "TLSv1.0",
-                rustls::ProtocolVersion::TLSv1_1 => "TLSv1.1",
-                rustls::ProtocolVersion::TLSv1_2 => "TLSv1.2",
-                rustls::ProtocolVersion::TLSv1_3 => "TLSv1.3",
-                rustls::ProtocolVersion::DTLSv1_0 => "DTLSv1.0",
-                rustls::ProtocolVersion::DTLSv1_2 => "DTLSv1.2",
-                rustls::ProtocolVersion::DTLSv1_3 => "DTLSv1.3",
-                _ => "unknown",
-            },
-            match conn.negotiated_cipher_suite() {
-                Some(rustls::SupportedCipherSuite::Tls13(cs)) => {
-                    cs.common.suite.as_str().unwrap_or("unknown")
-                }
-                Some(rustls::SupportedCipherSuite::Tls12(cs)) => {
-                    cs.common.suite.as_str().unwrap_or("unknown")
-                }
-                None => "unknown",
-            },
-        )
+        tls_version_and_cipher(self.get_ref().1)
+    }
+
+    fn peer_certificates(&self) -> Option<&[rustls::Certificate]> {
+        self.get_ref().1.peer_certificates()
     }
 
     fn write_tls_header(&self, headers: &mut Vec<u8>) {
@@ -104,6 +93,58 @@ impl IsTls for TlsStream<TcpStream> {
     }
 }
 
+impl IsTls for tokio_rustls::client::TlsStream<TcpStream> {
+    fn is_tls(&self) -> bool {
+        true
+    }
+
+    fn tls_version_and_cipher(&self) -> (&'static str, &'static str) {
+        tls_version_and_cipher(self.get_ref().1)
+    }
+
+    fn peer_certificates(&self) -> Option<&[rustls::Certificate]> {
+        self.get_ref().1.peer_certificates()
+    }
+
+    fn write_tls_header(&self, headers: &mut Vec<u8>) {
+        let (version, cipher) = self.tls_version_and_cipher();
+        headers.extend_from_slice(b"(using ");
+        headers.extend_from_slice(version.as_bytes());
+        headers.extend_from_slice(b" with cipher ");
+        headers.extend_from_slice(cipher.as_bytes());
+        headers.extend_from_slice(b")\r\n\t");
+    }
+}
+
+fn tls_version_and_cipher(conn: &rustls::CommonState) -> (&'static str, &'static str) {
+    (
+        match conn
+            .protocol_version()
+            .unwrap_or(rustls::ProtocolVersion::Unknown(0))
+        {
+            rustls::ProtocolVersion::SSLv2 => "SSLv2",
+            rustls::ProtocolVersion::SSLv3 => "SSLv3",
+            rustls::ProtocolVersion::TLSv1_0 => "TLSv1.0",
+            rustls::ProtocolVersion::TLSv1_1 => "TLSv1.1",
+            rustls::ProtocolVersion::TLSv1_2 => "TLSv1.2",
+            rustls::ProtocolVersion::TLSv1_3 => "TLSv1.3",
+            rustls::ProtocolVersion::DTLSv1_0 => "DTLSv1.0",
+            rustls::ProtocolVersion::DTLSv1_2 => "DTLSv1.2",
+            rustls::ProtocolVersion::DTLSv1_3 => "DTLSv1.3",
+            _ => "unknown",
+        },
+        match conn.negotiated_cipher_suite() {
+            Some(rustls::SupportedCipherSuite::Tls13(cs)) => {
+                cs.common.suite.as_str().unwrap_or("unknown")
+            }
+            Some(rustls::SupportedCipherSuite::Tls12(cs)) => {
+                cs.common.suite.as_str().unwrap_or("unknown")
+            }
+            None => "unknown",
+        },
+    )
+}
+
 impl ArcSealer {
     pub fn seal<'x>(
         &self,
@@ -0,0 +1,316 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{borrow::Cow, fmt::Write, sync::Arc};
+
+use mail_auth::IprevResult;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::{config::PolicyServer, core::Session};
+
+use super::IsTls;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyStage {
+    Connect,
+    Mail,
+    Rcpt,
+}
+
+impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
+    pub async fn run_policies(
+        &self,
+        stage: PolicyStage,
+        servers: Vec<Arc<PolicyServer>>,
+    ) -> Result<(), Cow<'static, [u8]>> {
+        if servers.is_empty() {
+            return Ok(());
+        }
+
+        let request = self.build_policy_request(stage);
+        for server in servers {
+            match query_policy_server(&server, request.as_bytes()).await {
+                Ok(action) => {
+                    tracing::debug!(
+                        parent: &self.span,
+                        context = "policy",
+                        event = "response",
+                        server = &server.id,
+                        stage = stage.as_str(),
+                        action = &action,
+                    );
+
+                    if let Some(response) = self.policy_action(&server, &action) {
+                        return Err(response.into());
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        parent: &self.span,
+                        context = "policy",
+                        event = "error",
+                        server = &server.id,
+                        stage = stage.as_str(),
+                        reason = %err,
+                        "Policy server query failed.");
+
+                    if server.tempfail_on_error {
+                        return Err(
+                            (b"451 4.3.0 Unable to verify policy, try again later.\r\n"[..]).into(),
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn build_policy_request(&self, stage: PolicyStage) -> String {
+        let mut request = String::with_capacity(512);
+        let (client_ptr, is_verified) = self
+            .data
+            .iprev
+            .as_ref()
+            .map(|ip_rev| {
+                (
+                    ip_rev.ptr.as_ref().and_then(|ptrs| ptrs.first()),
+                    matches!(ip_rev.result(), IprevResult::Pass),
+                )
+            })
+            .unwrap_or((None, false));
+        let (tls_version, tls_cipher) = self.stream.tls_version_and_cipher();
+
+        let _ = write!(
+            request,
+            concat!(
+                "request=smtpd_access_policy\n",
+                "protocol_state={}\n",
+                "protocol_name=ESMTP\n",
+                "client_address={}\n",
+                "client_name={}\n",
+                "reverse_client_name={}\n",
+                "helo_name={}\n",
+                "sender={}\n",
+                "recipient={}\n",
+                "recipient_count={}\n",
+                "queue_id=\n",
+                "instance=\n",
+                "size=0\n",
+                "sasl_method=\n",
+                "sasl_username={}\n",
+                "sasl_sender=\n",
+                "encryption_protocol={}\n",
+                "encryption_cipher={}\n",
+                "server_address={}\n",
+                "\n"
+            ),
+            stage.as_str(),
+            self.data.remote_ip,
+            client_ptr
+                .filter(|_| is_verified)
+                .map(|p| p.as_str())
+                .unwrap_or("unknown"),
+            client_ptr.map(|p| p.as_str()).unwrap_or("unknown"),
+            policy_value(&self.data.helo_domain),
+            self.data
+                .mail_from
+                .as_ref()
+                .map(|m| policy_value(&m.address))
+                .unwrap_or_default(),
+            if stage == PolicyStage::Rcpt {
+                self.data
+                    .rcpt_to
+                    .last()
+                    .map(|r| policy_value(&r.address))
+                    .unwrap_or_default()
+            } else {
+                Cow::Borrowed("")
+            },
+            self.data.rcpt_to.len(),
+            policy_value(&self.data.authenticated_as),
+            tls_version,
+            tls_cipher,
+            self.data.local_ip,
+        );
+
+        request
+    }
+
+    fn policy_action(&self, server: &PolicyServer, action: &str) -> Option<Vec<u8>> {
+        let (verb, text) = action
+            .split_once(|c: char| c.is_ascii_whitespace())
+            .map(|(verb, text)| (verb, text.trim()))
+            .unwrap_or((action, ""));
+
+        match verb.to_ascii_uppercase().as_str() {
+            "OK" | "DUNNO" | "DEFER_IF_REJECT" => None,
+            "REJECT" => Some(policy_reply(
+                "554",
+                if !text.is_empty() {
+                    text
+                } else {
+                    "Access denied"
+                },
+            )),
+            "DEFER" | "DEFER_IF_PERMIT" => Some(policy_reply(
+                "450",
+                if !text.is_empty() {
+                    text
+                } else {
+                    "Try again later"
+                },
+            )),
+            "WARN" => {
+                tracing::info!(
+                    parent: &self.span,
+                    context = "policy",
+                    event = "warn",
+                    server = &server.id,
+                    reason = text,
+                );
+                None
+            }
+            code if code.len() == 3
+                && code.starts_with(['4', '5'])
+                && code.chars().all(|c| c.is_ascii_digit()) =>
+            {
+                Some(policy_reply(code, text))
+            }
+            _ => {
+                tracing::debug!(
+                    parent: &self.span,
+                    context = "policy",
+                    event = "unsupported",
+                    server = &server.id,
+                    action = action,
+                    "Unsupported policy action, ignoring."
+                );
+                None
+            }
+        }
+    }
+}
+
+async fn query_policy_server(server: &PolicyServer, request: &[u8]) -> std::io::Result<String> {
+    tokio::time::timeout(server.timeout, async {
+        let mut last_err =
+            std::io::Error::new(std::io::ErrorKind::NotFound, "No addresses to connect to");
+        for addr in &server.addrs {
+            match TcpStream::connect(addr).await {
+                Ok(mut stream) => {
+                    stream.write_all(request).await?;
+                    stream.flush().await?;
+                    return read_policy_response(&mut stream, server.max_response_size).await;
+                }
+                Err(err) => {
+                    last_err = err;
+                }
+            }
+        }
+        Err(last_err)
+    })
+    .await
+    .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "Policy server timed out"))?
+}
+
+async fn read_policy_response(
+    stream: &mut TcpStream,
+    max_response_size: usize,
+) -> std::io::Result<String> {
+    let mut response = Vec::with_capacity(128);
+    let mut buf = [0u8; 1024];
+
+    while !response.ends_with(b"\n\n") {
+        let bytes_read = stream.read(&mut buf).await?;
+        if bytes_read == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "Policy server closed the connection",
+            ));
+        }
+        response.extend_from_slice(&buf[..bytes_read]);
+        if response.len() > max_response_size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Policy server response too large",
+            ));
+        }
+    }
+
+    std::str::from_utf8(&response)
+        .ok()
+        .and_then(|response| {
+            response.lines().find_map(|line| {
+                line.strip_prefix("action=")
+                    .map(|action| action.trim().to_string())
+            })
+        })
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Policy server response is missing an action",
+            )
+        })
+}
+
+fn policy_reply(code: &str, text: &str) -> Vec<u8> {
+    let mut reply = String::with_capacity(text.len() + 16);
+    reply.push_str(code);
+    reply.push(' ');
+
+    // Add an enhanced status code if the policy server did not provide one
+    let status = text.split_once(' ').map_or(text, |(status, _)| status);
+    if status.split('.').count() != 3
+        || !status
+            .split('.')
+            .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()))
+    {
+        reply.push_str(&code[..1]);
+        reply.push_str(".7.1 ");
+    }
+    reply.push_str(text);
+    reply.push_str("\r\n");
+    reply.into_bytes()
+}
+
+fn policy_value(value: &str) -> Cow<'_, str> {
+    if value.contains(['\r', '\n']) {
+        Cow::Owned(value.replace(['\r', '\n'], ""))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+impl PolicyStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PolicyStage::Connect => "CONNECT",
+            PolicyStage::Mail => "MAIL",
+            PolicyStage::Rcpt => "RCPT",
+        }
+    }
+}
@@ -29,10 +29,12 @@ use tokio::io::{AsyncRead, AsyncWrite};
 use crate::{
     core::{Session, SessionAddress},
     queue::DomainPart,
+    reputation::ReputationEvent,
     scripts::{ScriptModification, ScriptResult},
+    webhook::now,
 };
 
-use super::IsTls;
+use super::{policy::PolicyStage, IsTls};
 
 impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
     pub async fn handle_rcpt_to(&mut self, to: RcptTo<String>) -> Result<(), ()> {
@@ -48,7 +50,10 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
         if self.data.mail_from.is_none() {
             return self.write(b"503 5.5.1 MAIL is required first.\r\n").await;
         } else if self.data.rcpt_to.len() >= self.params.rcpt_max {
-            return self.write(b"451 4.5.3 Too many recipients.\r\n").await;
+            // The remaining recipients can be sent right away in a new transaction
+            return self
+                .write(b"452 4.5.3 Too many recipients in this transaction.\r\n")
+                .await;
         }
 
         // Verify parameters
@@ -65,7 +70,7 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
 
         // Build RCPT
         let address_lcase = to.address.to_lowercase();
-        let rcpt = SessionAddress {
+        let mut rcpt = SessionAddress {
             domain: address_lcase.domain_part().to_string(),
             address_lcase,
             address: to.address,
@@ -73,11 +78,56 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
             dsn_info: to.orcpt,
         };
 
+        // Bounces sent to a rewritten return path are relayed to the original sender
+        let srs = &self.core.session.config.data.srs;
+        let mut is_srs_bounce = false;
+        if srs.is_enabled()
+            && rcpt.domain == srs.domain
+            && self
+                .data
+                .mail_from
+                .as_ref()
+                .map_or(false, |from| from.address.is_empty())
+        {
+            if let Some(address) = srs.reverse(&rcpt.address, now()) {
+                tracing::debug!(parent: &self.span,
+                    context = "srs",
+                    event = "reverse",
+                    address = &rcpt.address,
+                    original = &address);
+
+                rcpt.address_lcase = address.to_lowercase();
+                rcpt.domain = rcpt.address_lcase.domain_part().to_string();
+                rcpt.address = address;
+                is_srs_bounce = true;
+            }
+        }
+
         if self.data.rcpt_to.contains(&rcpt) {
             return self.write(b"250 2.1.5 OK\r\n").await;
         }
         self.data.rcpt_to.push(rcpt);
 
+        // Policy delegation
+        let policies = self
+            .core
+            .session
+            .config
+            .rcpt
+            .policy
+            .eval_and_capture(self)
+            .await
+            .into_value(self);
+        if let Err(message) = self.run_policies(PolicyStage::Rcpt, policies).await {
+            tracing::info!(parent: &self.span,
+                context = "policy",
+                event = "reject",
+                address = self.data.rcpt_to.last().unwrap().address,
+                reason = std::str::from_utf8(&message).unwrap_or_default());
+            self.data.rcpt_to.pop();
+            return self.write(&message).await;
+        }
+
         // Address rewriting and Sieve filtering
         let rcpt_script = self
             .core
@@ -154,6 +204,7 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
 
         // Verify address
         let rcpt = self.data.rcpt_to.last().unwrap();
+        let mut is_local_domain = false;
         if let Some(directory) = self
             .core
             .session
@@ -164,9 +215,15 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
             .await
             .into_value(self)
         {
-            if let Ok(is_local_domain) = directory.is_local_domain(&rcpt.domain).await {
-                if is_local_domain {
-                    if let Ok(is_local_address) = directory.rcpt(&rcpt.address_lcase).await {
+            if let Ok(is_local) = directory.is_local_domain(&rcpt.domain).await {
+                is_local_domain = is_local;
+                if is_local {
+                    let is_local_address = match directory.rcpt(&rcpt.address_lcase).await {
+                        #[cfg(feature = "local_delivery")]
+                        Ok(false) => self.is_masked_email(&rcpt.address_lcase).await,
+                        result => result.map_err(|_| ()),
+                    };
+                    if let Ok(is_local_address) = is_local_address {
                         if !is_local_address {
                             tracing::debug!(parent: &self.span,
                                             context = "rcpt", 
@@ -175,6 +232,8 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
                                             "Mailbox does not exist.");
 
                             self.data.rcpt_to.pop();
+                            self.record_reputation(ReputationEvent::InvalidRecipient)
+                                .await;
                             return self
                                 .rcpt_error(b"550 5.1.2 Mailbox does not exist.\r\n")
                                 .await;
@@ -188,10 +247,10 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
 
                         self.data.rcpt_to.pop();
                         return self
-                            .write(b"451 4.4.3 Unable to verify address at this time.\r\n")
+                            .rcpt_temp_error("451 4.4.3 Unable to verify address")
                             .await;
                     }
-                } else if !*self.core.session.config.rcpt.relay.eval(self).await {
+                } else if !is_srs_bounce && !*self.core.session.config.rcpt.relay.eval(self).await {
                     tracing::debug!(parent: &self.span,
                         context = "rcpt", 
                         event = "error",
@@ -210,10 +269,10 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
 
                 self.data.rcpt_to.pop();
                 return self
-                    .write(b"451 4.4.3 Unable to verify address at this time.\r\n")
+                    .rcpt_temp_error("451 4.4.3 Unable to verify address")
                     .await;
             }
-        } else if !*self.core.session.config.rcpt.relay.eval(self).await {
+        } else if !is_srs_bounce && !*self.core.session.config.rcpt.relay.eval(self).await {
             tracing::debug!(parent: &self.span,
                 context = "rcpt", 
                 event = "error",
@@ -224,6 +283,59 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
             return self.rcpt_error(b"550 5.1.2 Relay not allowed.\r\n").await;
         }
 
+        // Reject submissions to remote addresses that repeatedly hard-bounced
+        let rcpt = self.data.rcpt_to.last().unwrap();
+        let suppressed = if !is_local_domain && !self.data.authenticated_as.is_empty() {
+            self.core.suppression.get(&rcpt.address_lcase).await
+        } else {
+            None
+        };
+        if let Some(entry) = suppressed {
+            tracing::debug!(parent: &self.span,
+                context = "rcpt",
+                event = "suppressed",
+                address = &rcpt.address_lcase,
+                bounces = entry.bounces,
+                reason = &entry.reason,
+                "Recipient is on the suppression list.");
+
+            let response = format!(
+                "550 5.1.1 <{}> is suppressed after repeated delivery failures: {}\r\n",
+                rcpt.address,
+                entry.reason.replace(['\r', '\n'], " ")
+            );
+            self.data.rcpt_to.pop();
+            return self.rcpt_error(response.as_bytes()).await;
+        }
+
+        // Apply per-recipient message size limits
+        if let Some(max_message_size) = *self
+            .core
+            .session
+            .config
+            .rcpt
+            .max_message_size
+            .eval(self)
+            .await
+        {
+            if self.params.declared_size > max_message_size {
+                tracing::debug!(parent: &self.span,
+                    context = "rcpt",
+                    event = "error",
+                    address = &self.data.rcpt_to.last().unwrap().address_lcase,
+                    size = self.params.declared_size,
+                    max_size = max_message_size,
+                    "Message too big for recipient.");
+
+                self.data.rcpt_to.pop();
+                return self
+                    .rcpt_error(b"552 5.3.4 Message too big for recipient.\r\n")
+                    .await;
+            }
+            self.params.max_message_size =
+                std::cmp::min(self.params.max_message_size, max_message_size);
+        }
+
         if self.is_allowed().await {
             tracing::debug!(parent: &self.span,
                     context = "rcpt",
@@ -231,17 +343,54 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
                     address = &self.data.rcpt_to.last().unwrap().address);
         } else {
             self.data.rcpt_to.pop();
-            return self
-                .write(b"451 4.4.5 Rate limit exceeded, try again later.\r\n")
-                .await;
+            return self.rcpt_temp_error("451 4.4.5 Rate limit exceeded").await;
         }
 
         self.write(b"250 2.1.5 OK\r\n").await
     }
 
+    #[cfg(feature = "local_delivery")]
+    async fn is_masked_email(&self, address: &str) -> Result<bool, ()> {
+        let (result_tx, result_rx) = tokio::sync::oneshot::channel();
+        if self
+            .core
+            .delivery_tx
+            .send(utils::ipc::DeliveryEvent::LookupMaskedEmail {
+                address: address.to_string(),
+                result_tx,
+            })
+            .await
+            .is_ok()
+        {
+            // Disabled addresses are reported as non-existent
+            if let Ok(Some(status)) = result_rx.await {
+                return Ok(status == utils::ipc::MaskedEmailStatus::Enabled);
+            }
+        }
+
+        Err(())
+    }
+
+    async fn rcpt_temp_error(&mut self, response: &str) -> Result<(), ()> {
+        // Hint the client when the recipient is worth retrying
+        let response = if let Some(retry_hint) = self.params.rcpt_retry_hint {
+            format!(
+                "{response}, try again in {} seconds.\r\n",
+                retry_hint.as_secs()
+            )
+        } else {
+            format!("{response}, try again later.\r\n")
+        };
+        self.write(response.as_bytes()).await
+    }
+
     async fn rcpt_error(&mut self, response: &[u8]) -> Result<(), ()> {
         tokio::time::sleep(self.params.rcpt_errors_wait).await;
         self.data.rcpt_errors += 1;
+        if let Some(tarpit) = self.params.rcpt_errors_tarpit {
+            // Slow down the remainder of the session
+            self.params.tarpit = Some(self.params.tarpit.map_or(tarpit, |t| t.max(tarpit)));
+        }
         self.write(response).await?;
         if self.data.rcpt_errors < self.params.rcpt_errors_max {
             Ok(())
@@ -38,7 +38,11 @@ use crate::{
     core::{Session, State},
 };
 
-use super::{auth::SaslToken, IsTls};
+use super::{
+    auth::SaslToken,
+    xclient::{take_xclient, XLine},
+    IsTls,
+};
 
 impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
     pub async fn ingest(&mut self, bytes: &[u8]) -> Result<bool, ()> {
@@ -48,6 +52,20 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
         'outer: loop {
             match &mut state {
                 State::Request(receiver) => loop {
+                    // XCLIENT and XFORWARD are not known to the protocol parser
+                    match take_xclient(receiver, &mut iter) {
+                        Some(XLine::Complete(command, line)) => {
+                            self.handle_xclient(command, &line).await?;
+                            continue;
+                        }
+                        Some(XLine::Incomplete) => break 'outer,
+                        Some(XLine::TooLong) => {
+                            state = State::RequestTooLarge(DummyLineReceiver::default());
+                            continue 'outer;
+                        }
+                        None => (),
+                    }
+
                     match receiver.ingest(&mut iter, bytes) {
                         Ok(request) => match request {
                             Request::Rcpt { to } => {
@@ -57,6 +75,7 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                                 self.handle_mail_from(from).await?;
                             }
                             Request::Ehlo { host } => {
+                                self.check_early_talker(iter.len()).await?;
                                 if self.instance.protocol == ServerProtocol::Smtp {
                                     self.handle_ehlo(host).await?;
                                 } else {
@@ -64,6 +83,7 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                                 }
                             }
                             Request::Data => {
+                                self.check_early_talker(iter.len()).await?;
                                 if self.can_send_data().await? {
                                     self.write(b"354 Start mail input; end with <CRLF>.<CRLF>\r\n")
                                         .await?;
@@ -166,6 +186,7 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                                 .await?;
                             }
                             Request::Helo { host } => {
+                                self.check_early_talker(iter.len()).await?;
                                 if self.instance.protocol == ServerProtocol::Smtp
                                     && self.data.helo_domain.is_empty()
                                 {
@@ -180,6 +201,7 @@ impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
                                 }
                             }
                             Request::Lhlo { host } => {
+                                self.check_early_talker(iter.len()).await?;
                                 if self.instance.protocol == ServerProtocol::Lmtp {
                                     self.handle_ehlo(host).await?;
                                 } else {
@@ -356,8 +378,30 @@ impl<T: AsyncWrite + AsyncRead + Unpin> Session<T> {
         self.data.future_release = 0;
     }
 
+    // Clients must wait for the response to EHLO, HELO, LHLO and DATA
+    // before sending any further data, even when pipelining.
+    async fn check_early_talker(&mut self, bytes_pending: usize) -> Result<(), ()> {
+        if self.params.early_talker && bytes_pending > 0 {
+            tracing::debug!(parent: &self.span,
+                context = "session",
+                event = "early-talker",
+                "Client sent data without waiting for a response.");
+
+            let _ = self
+                .write(b"554 5.5.0 Protocol violation, disconnecting.\r\n")
+                .await;
+            Err(())
+        } else {
+            Ok(())
+        }
+    }
+
     #[inline(always)]
     pub async fn write(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        if let Some(tarpit) = self.params.tarpit {
+            tokio::time::sleep(tarpit).await;
+        }
+
         let err = match self.stream.write_all(bytes).await {
             Ok(_) => match self.stream.flush().await {
                 Ok(_) => {
@@ -443,6 +487,15 @@ impl<T: AsyncRead + AsyncWrite> KeyLookup for Session<T> {
             EnvelopeKey::LocalIp => self.data.local_ip.to_string().into(),
             EnvelopeKey::Priority => self.data.priority.to_string().into(),
             EnvelopeKey::Mx => "".into(),
+            EnvelopeKey::Reputation => self.reputation_class().as_str().into(),
+            EnvelopeKey::Country => self.data.geo.country.as_deref().unwrap_or_default().into(),
+            EnvelopeKey::Asn => self
+                .data
+                .geo
+                .asn
+                .map(|asn| asn.to_string())
+                .unwrap_or_default()
+                .into(),
         }
     }
 
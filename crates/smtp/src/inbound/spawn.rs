@@ -31,18 +31,19 @@ use tokio_rustls::server::TlsStream;
 use utils::listener::SessionManager;
 
 use crate::{
-    core::{Session, SessionData, SessionParameters, SmtpSessionManager, State},
+    core::{Session, SessionData, SessionParameters, SmtpSessionManager, State, SMTP},
     queue, reporting,
     scripts::ScriptResult,
+    webhook,
 };
 
-use super::IsTls;
+use super::{policy::PolicyStage, IsTls};
 
 impl SessionManager for SmtpSessionManager {
     fn spawn(&self, session: utils::listener::SessionData<TcpStream>) {
         // Create session
         let mut session = Session {
-            core: self.inner.clone(),
+            core: self.inner.core(),
             instance: session.instance,
             state: State::default(),
             span: session.span,
@@ -52,7 +53,31 @@ impl SessionManager for SmtpSessionManager {
             params: SessionParameters::default(),
         };
 
+        // Enrich the session with the client's country and network
+        if session.core.geoip.is_enabled() {
+            session.data.geo = session.core.geoip.lookup(session.data.remote_ip);
+            tracing::debug!(
+                parent: &session.span,
+                context = "geoip",
+                event = "lookup",
+                country = session.data.geo.country.as_deref().unwrap_or_default(),
+                asn = session.data.geo.asn.unwrap_or_default(),
+                asn_org = session.data.geo.asn_org.as_deref().unwrap_or_default(),
+            );
+        }
+
         tokio::spawn(async move {
+            // Enforce connection limits
+            if !session.is_connection_allowed() {
+                session
+                    .reject_connection(
+                        b"421 4.7.0 Too many concurrent connections, try again later.\r\n",
+                    )
+                    .await;
+                return;
+            }
+            session.load_reputation().await;
+
             // Enforce throttle
             if session.is_allowed().await {
                 if session.instance.is_tls_implicit {
@@ -69,14 +94,27 @@ impl SessionManager for SmtpSessionManager {
     }
 
     fn shutdown(&self) {
-        // We spawn to avoid using async_trait
-        let core = self.inner.clone();
-        tokio::spawn(async move {
-            let _ = core.queue.tx.send(queue::Event::Stop).await;
-            let _ = core.report.tx.send(reporting::Event::Stop).await;
-            #[cfg(feature = "local_delivery")]
-            let _ = core.delivery_tx.send(utils::ipc::DeliveryEvent::Stop).await;
-        });
+        // Background services are stopped with SMTP::shutdown once
+        // all sessions have been drained
+    }
+}
+
+impl SMTP {
+    pub async fn shutdown(&self) {
+        let _ = self.queue.tx.send(queue::Event::Stop).await;
+        let _ = self.report.tx.send(reporting::Event::Stop).await;
+        for tx in self.webhook.tx.values() {
+            let _ = tx.send(webhook::Event::Stop).await;
+        }
+        self.usage.write_counters().await;
+        self.reputation.write_entries().await;
+        self.suppression.write_entries().await;
+        self.anomaly.write_entries().await;
+        #[cfg(feature = "local_delivery")]
+        let _ = self.delivery_tx.send(utils::ipc::DeliveryEvent::Stop).await;
+
+        // Wait for the queue manager to persist pending changes
+        self.queue.tx.closed().await;
     }
 }
 
@@ -130,6 +168,58 @@ impl<T: AsyncRead + AsyncWrite + IsTls + Unpin> Session<T> {
             }
         }
 
+        // Policy delegation
+        let policies = self
+            .core
+            .session
+            .config
+            .connect
+            .policy
+            .eval_and_capture(self)
+            .await
+            .into_value(self);
+        if let Err(message) = self.run_policies(PolicyStage::Connect, policies).await {
+            tracing::debug!(parent: &self.span,
+                    context = "connect",
+                    event = "policy-reject",
+                    reason = std::str::from_utf8(&message).unwrap_or_default());
+
+            let _ = self.write(&message).await;
+            return false;
+        }
+
+        // Early talker detection
+        if let Some(greeting_delay) = *self
+            .core
+            .session
+            .config
+            .connect
+            .greeting_delay
+            .eval(self)
+            .await
+        {
+            self.params.early_talker = true;
+            let mut buf = [0u8; 128];
+            match tokio::time::timeout(greeting_delay, self.read(&mut buf)).await {
+                Ok(Ok(bytes_read)) if bytes_read > 0 => {
+                    tracing::debug!(parent: &self.span,
+                        context = "connect",
+                        event = "early-talker",
+                        "Client sent data before the greeting.");
+
+                    let _ = self
+                        .write(b"554 5.5.0 Protocol violation, disconnecting.\r\n")
+                        .await;
+                    return false;
+                }
+                Ok(_) => {
+                    // Connection closed or errored
+                    return false;
+                }
+                Err(_) => (),
+            }
+        }
+
         let instance = self.instance.clone();
         if self.write(instance.data.as_bytes()).await.is_err() {
             return false;
@@ -141,6 +231,7 @@ impl<T: AsyncRead + AsyncWrite + IsTls + Unpin> Session<T> {
     pub async fn handle_conn_(&mut self) -> bool {
         let mut buf = vec![0; 8192];
         let mut shutdown_rx = self.instance.shutdown_rx.clone();
+        let mut is_draining = false;
 
         loop {
             tokio::select! {
@@ -153,7 +244,18 @@ impl<T: AsyncRead + AsyncWrite + IsTls + Unpin> Session<T> {
                                     if Instant::now() < self.data.valid_until && bytes_read <= self.data.bytes_left  {
                                         self.data.bytes_left -= bytes_read;
                                         match self.ingest(&buf[..bytes_read]).await {
-                                            Ok(true) => (),
+                                            Ok(true) => {
+                                                if is_draining && self.data.mail_from.is_none() {
+                                                    tracing::debug!(
+                                                        parent: &self.span,
+                                                        event = "disconnect",
+                                                        reason = "shutdown",
+                                                        "Transaction completed, server shutting down."
+                                                    );
+                                                    self.write(b"421 4.3.0 Server shutting down.\r\n").await.ok();
+                                                    break;
+                                                }
+                                            }
                                             Ok(false) => {
                                                 return true;
                                             }
@@ -214,15 +316,25 @@ impl<T: AsyncRead + AsyncWrite + IsTls + Unpin> Session<T> {
                             }
                         }
                 },
-                _ = shutdown_rx.changed() => {
-                    tracing::debug!(
-                        parent: &self.span,
-                        event = "disconnect",
-                        reason = "shutdown",
-                        "Server shutting down."
-                    );
-                    self.write(b"421 4.3.0 Server shutting down.\r\n").await.ok();
-                    break;
+                _ = shutdown_rx.changed(), if !is_draining => {
+                    if self.data.mail_from.is_none() {
+                        tracing::debug!(
+                            parent: &self.span,
+                            event = "disconnect",
+                            reason = "shutdown",
+                            "Server shutting down."
+                        );
+                        self.write(b"421 4.3.0 Server shutting down.\r\n").await.ok();
+                        break;
+                    } else {
+                        // Let the client finish the current transaction
+                        tracing::debug!(
+                            parent: &self.span,
+                            event = "drain",
+                            "Server shutting down, waiting for transaction to complete."
+                        );
+                        is_draining = true;
+                    }
                 }
             };
         }
@@ -0,0 +1,113 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::config::Srs;
+
+// Rewritten addresses are accepted for bounces during this number of days
+const SRS_MAX_AGE: u64 = 21;
+
+const BASE32: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+impl Srs {
+    pub fn is_enabled(&self) -> bool {
+        !self.domain.is_empty() && !self.keys.is_empty()
+    }
+
+    // Rewrites a return path as SRS0=HHHHHH=TT=domain=local@srs-domain, addresses
+    // are signed with the first key while older keys are only used for verification.
+    pub fn forward(&self, address: &str, now: u64) -> Option<String> {
+        let (local_part, domain) = address.rsplit_once('@')?;
+        let day = (now / 86400) % 1024;
+        let timestamp = [
+            BASE32[(day >> 5) as usize] as char,
+            BASE32[(day & 31) as usize] as char,
+        ]
+        .iter()
+        .collect::<String>();
+        let hash = srs_hash(self.keys.first()?, &timestamp, domain, local_part)?;
+
+        Some(format!(
+            "SRS0={hash}={timestamp}={domain}={local_part}@{}",
+            self.domain
+        ))
+    }
+
+    // Obtains the original return path of a bounce addressed to a rewritten address
+    pub fn reverse(&self, address: &str, now: u64) -> Option<String> {
+        let (local_part, domain) = address.rsplit_once('@')?;
+        if !domain.eq_ignore_ascii_case(&self.domain) {
+            return None;
+        }
+        let mut parts = local_part.splitn(5, '=');
+        if !parts.next()?.eq_ignore_ascii_case("SRS0") {
+            return None;
+        }
+        let (hash, timestamp, domain, local_part) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+
+        // Validate timestamp
+        if timestamp.len() != 2 {
+            return None;
+        }
+        let mut day = 0;
+        for ch in timestamp.bytes() {
+            day = (day << 5)
+                | BASE32
+                    .iter()
+                    .position(|b32| b32.eq_ignore_ascii_case(&ch))? as u64;
+        }
+        if ((now / 86400) + 1024 - day) % 1024 > SRS_MAX_AGE {
+            return None;
+        }
+
+        // Validate hash
+        if self.keys.iter().any(|key| {
+            srs_hash(key, timestamp, domain, local_part)
+                .map_or(false, |expected| expected.eq_ignore_ascii_case(hash))
+        }) && !domain.is_empty()
+            && !local_part.is_empty()
+        {
+            Some(format!("{local_part}@{domain}"))
+        } else {
+            None
+        }
+    }
+}
+
+// Hashes are case-insensitive as some MTAs lowercase the local part
+fn srs_hash(key: &[u8], timestamp: &str, domain: &str, local_part: &str) -> Option<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).ok()?;
+    mac.update(timestamp.to_ascii_uppercase().as_bytes());
+    mac.update(domain.to_lowercase().as_bytes());
+    mac.update(local_part.to_lowercase().as_bytes());
+    let hash = mac.finalize().into_bytes();
+    let value = u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]);
+    Some(
+        (0..6)
+            .map(|pos| BASE32[((value >> (27 - pos * 5)) & 31) as usize] as char)
+            .collect(),
+    )
+}
@@ -0,0 +1,245 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{ops::Range, sync::Arc};
+
+use mail_auth::common::headers::HeaderWriter;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{core::Session, webhook::now};
+
+use super::IsTls;
+
+pub struct TransformedMessage {
+    pub raw_message: Arc<Vec<u8>>,
+    pub return_path: Option<String>,
+}
+
+impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
+    // Applies the transformations performed on submitted messages right before they
+    // are queued. This stage has no side effects, which allows previewing messages.
+    pub async fn transform_message(
+        &self,
+        headers: &mut Vec<u8>,
+        raw_message: Arc<Vec<u8>>,
+    ) -> TransformedMessage {
+        // Remove headers that should not leave the organization
+        let scrub_headers = self.core.session.config.data.scrub_headers.eval(self).await;
+        let raw_message = if !scrub_headers.is_empty() {
+            match remove_headers(&raw_message, |name, _| {
+                scrub_headers
+                    .iter()
+                    .any(|scrub_header| scrub_header.eq_ignore_ascii_case(name))
+            }) {
+                Some(message) => Arc::new(message),
+                None => raw_message,
+            }
+        } else {
+            raw_message
+        };
+
+        // Append the footer of the sender's domain before signing the message
+        let raw_message = if let Some(message) = self.apply_footer(&raw_message).await {
+            Arc::new(message)
+        } else {
+            raw_message
+        };
+
+        // Rewrite the return path of messages sent on behalf of remote domains
+        let return_path = self.srs_return_path().await;
+
+        // DKIM sign
+        for signer in self
+            .core
+            .mail_auth
+            .dkim
+            .sign
+            .eval_and_capture(self)
+            .await
+            .into_value(self)
+        {
+            match signer.sign_chained(&[headers.as_ref(), &raw_message]) {
+                Ok(signature) => {
+                    signature.write_header(headers);
+                }
+                Err(err) => {
+                    tracing::info!(parent: &self.span,
+                        context = "dkim",
+                        event = "sign-failed",
+                        return_path = self.data.mail_from.as_ref().map_or("", |from| from.address.as_str()),
+                        "Failed to sign message: {}", err);
+                }
+            }
+        }
+
+        TransformedMessage {
+            raw_message,
+            return_path,
+        }
+    }
+
+    async fn srs_return_path(&self) -> Option<String> {
+        let srs = &self.core.session.config.data.srs;
+        let mail_from = self.data.mail_from.as_ref()?;
+        if !srs.is_enabled()
+            || mail_from.address.is_empty()
+            || mail_from.domain == srs.domain
+            || !*srs.enable.eval(self).await
+        {
+            return None;
+        }
+
+        // Senders of local domains are not rewritten
+        let directory = self
+            .core
+            .session
+            .config
+            .rcpt
+            .directory
+            .eval_and_capture(self)
+            .await
+            .into_value(self)?;
+        if directory
+            .is_local_domain(&mail_from.domain)
+            .await
+            .unwrap_or(true)
+        {
+            return None;
+        }
+
+        srs.forward(&mail_from.address, now())
+    }
+
+    // Returns the message exactly as it would be queued by this session, excluding
+    // the trace headers that depend on the queue. Signature values are removed,
+    // otherwise previews could be used to sign arbitrary messages.
+    pub async fn preview_message(&self, raw_message: Vec<u8>) -> Vec<u8> {
+        let mut headers = Vec::with_capacity(256);
+        let transformed = self
+            .transform_message(&mut headers, Arc::new(raw_message))
+            .await;
+        let return_path = transformed
+            .return_path
+            .as_deref()
+            .or_else(|| {
+                self.data
+                    .mail_from
+                    .as_ref()
+                    .map(|from| from.address.as_str())
+            })
+            .unwrap_or_default();
+
+        let mut preview = Vec::with_capacity(
+            return_path.len() + headers.len() + transformed.raw_message.len() + 16,
+        );
+        preview.extend_from_slice(b"Return-Path: <");
+        preview.extend_from_slice(return_path.as_bytes());
+        preview.extend_from_slice(b">\r\n");
+        for field in header_fields(&headers) {
+            let field = &headers[field];
+            if field
+                .get(..15)
+                .map_or(false, |name| name.eq_ignore_ascii_case(b"DKIM-Signature:"))
+            {
+                redact_signature(field, &mut preview);
+            } else {
+                preview.extend_from_slice(field);
+            }
+        }
+        preview.extend_from_slice(&transformed.raw_message);
+        preview
+    }
+}
+
+// Removes the header fields matching a filter, returns None when no fields were removed.
+pub fn remove_headers(
+    raw_message: &[u8],
+    mut filter: impl FnMut(&str, &[u8]) -> bool,
+) -> Option<Vec<u8>> {
+    let mut remove = Vec::new();
+    for field in header_fields(raw_message) {
+        let bytes = &raw_message[field.clone()];
+        if let Some(colon) = bytes.iter().position(|&ch| ch == b':') {
+            let name = std::str::from_utf8(&bytes[..colon])
+                .unwrap_or_default()
+                .trim();
+            if filter(name, &bytes[colon + 1..]) {
+                remove.push(field);
+            }
+        }
+    }
+
+    if !remove.is_empty() {
+        let mut message = Vec::with_capacity(raw_message.len());
+        let mut last = 0;
+        for field in remove {
+            message.extend_from_slice(&raw_message[last..field.start]);
+            last = field.end;
+        }
+        message.extend_from_slice(&raw_message[last..]);
+        Some(message)
+    } else {
+        None
+    }
+}
+
+// Obtains the position of each header field, including its folded lines
+fn header_fields(raw_message: &[u8]) -> Vec<Range<usize>> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while pos < raw_message.len() && !matches!(raw_message[pos], b'\r' | b'\n') {
+        let mut end = pos;
+        loop {
+            end = raw_message[end..]
+                .iter()
+                .position(|&ch| ch == b'\n')
+                .map_or(raw_message.len(), |offset| end + offset + 1);
+            if end >= raw_message.len() || !matches!(raw_message[end], b' ' | b'\t') {
+                break;
+            }
+        }
+        fields.push(pos..end);
+        pos = end;
+    }
+    fields
+}
+
+// Writes a DKIM-Signature header with an empty b= tag
+fn redact_signature(field: &[u8], output: &mut Vec<u8>) {
+    let field = String::from_utf8_lossy(field);
+    for (pos, tag) in field.split(';').enumerate() {
+        if pos > 0 {
+            output.push(b';');
+        }
+        let value = tag.trim_start();
+        if value.starts_with("b=") {
+            output.extend_from_slice(tag[..tag.len() - value.len()].as_bytes());
+            output.extend_from_slice(b"b=");
+            if tag.ends_with('\n') {
+                output.extend_from_slice(b"\r\n");
+            }
+        } else {
+            output.extend_from_slice(tag.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,285 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, slice::Iter, sync::Arc};
+
+use mail_auth::{IprevOutput, IprevResult};
+use smtp_proto::request::receiver::{RequestReceiver, MAX_LINE_LENGTH};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::core::Session;
+
+use super::IsTls;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XCommand {
+    XClient,
+    XForward,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum XLine {
+    Complete(XCommand, String),
+    Incomplete,
+    TooLong,
+}
+
+// Attributes set to None were sent as [UNAVAILABLE] or [TEMPUNAVAIL]
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ClientAttributes {
+    pub name: Option<Option<String>>,
+    pub addr: Option<Option<IpAddr>>,
+    pub port: Option<Option<u16>>,
+    pub helo: Option<Option<String>>,
+    pub login: Option<Option<String>>,
+    pub dest_addr: Option<Option<IpAddr>>,
+}
+
+impl<T: AsyncWrite + AsyncRead + IsTls + Unpin> Session<T> {
+    pub async fn handle_xclient(&mut self, command: XCommand, line: &str) -> Result<(), ()> {
+        let is_allowed = match command {
+            XCommand::XClient => self.params.xclient,
+            XCommand::XForward => self.params.xforward,
+        };
+        if !is_allowed {
+            tracing::debug!(parent: &self.span,
+                context = "xclient",
+                event = "reject",
+                reason = "unauthorized",
+                command = ?command);
+
+            return self
+                .write(b"550 5.7.0 Insufficient authorization.\r\n")
+                .await;
+        } else if self.data.mail_from.is_some() {
+            return self
+                .write(b"503 5.5.1 Mail transaction in progress.\r\n")
+                .await;
+        }
+        let attributes = if let Some(attributes) = ClientAttributes::parse(command, line) {
+            attributes
+        } else {
+            return self
+                .write(b"501 5.5.4 Bad command parameter syntax.\r\n")
+                .await;
+        };
+
+        tracing::debug!(parent: &self.span,
+            context = "xclient",
+            event = "success",
+            command = ?command,
+            attributes = ?attributes);
+
+        // Replace the client attributes with the ones of the original client
+        if let Some(addr) = attributes.addr {
+            if let Some(addr) = addr {
+                self.data.remote_ip = addr;
+                if self.core.geoip.is_enabled() {
+                    self.data.geo = self.core.geoip.lookup(addr);
+                }
+            }
+            self.data.iprev = None;
+            self.data.spf_ehlo = None;
+        }
+        if let Some(port) = attributes.port {
+            self.data.remote_port = port.unwrap_or_default();
+        }
+        if let Some(Some(addr)) = attributes.dest_addr {
+            self.data.local_ip = addr;
+        }
+        match attributes.name {
+            Some(Some(name)) => {
+                self.data.iprev = Some(IprevOutput {
+                    result: IprevResult::Pass,
+                    ptr: Some(Arc::new(vec![name])),
+                });
+            }
+            Some(None) => {
+                self.data.iprev = None;
+            }
+            None => (),
+        }
+        match attributes.helo {
+            Some(helo) => {
+                self.data.helo_domain = helo.unwrap_or_default();
+                self.data.spf_ehlo = None;
+            }
+            None if command == XCommand::XClient => {
+                self.data.helo_domain.clear();
+                self.data.spf_ehlo = None;
+            }
+            None => (),
+        }
+
+        match command {
+            XCommand::XClient => {
+                // XCLIENT starts a new session on behalf of the original client
+                if let Some(login) = attributes.login {
+                    self.data.authenticated_as = login.unwrap_or_default();
+                }
+                let (xclient, xforward) = (self.params.xclient, self.params.xforward);
+                self.data.valid_until = std::time::Instant::now();
+                self.eval_session_params().await;
+                self.params.xclient = xclient;
+                self.params.xforward = xforward;
+                self.reset();
+
+                if self.is_allowed().await {
+                    let instance = self.instance.clone();
+                    self.write(instance.data.as_bytes()).await
+                } else {
+                    let _ = self
+                        .write(b"421 4.7.0 Rate limit exceeded, try again later.\r\n")
+                        .await;
+                    Err(())
+                }
+            }
+            XCommand::XForward => self.write(b"250 2.0.0 OK\r\n").await,
+        }
+    }
+}
+
+impl ClientAttributes {
+    pub fn parse(command: XCommand, line: &str) -> Option<Self> {
+        let mut attributes = ClientAttributes::default();
+        let mut params = line.split_ascii_whitespace().skip(1).peekable();
+        params.peek()?;
+
+        for param in params {
+            let (name, value) = param.split_once('=')?;
+            let value = if value.eq_ignore_ascii_case("[UNAVAILABLE]")
+                || value.eq_ignore_ascii_case("[TEMPUNAVAIL]")
+            {
+                None
+            } else {
+                Some(xtext_decode(value)?)
+            };
+
+            match (name.to_ascii_uppercase().as_str(), command) {
+                ("NAME", _) => {
+                    attributes.name = Some(value);
+                }
+                ("ADDR", _) => {
+                    attributes.addr = Some(parse_value(value, parse_addr)?);
+                }
+                ("PORT", _) => {
+                    attributes.port = Some(parse_value(value, |port| port.parse().ok())?);
+                }
+                ("HELO", _) => {
+                    attributes.helo = Some(value);
+                }
+                ("PROTO", _) => {
+                    if !value.map_or(true, |proto| {
+                        proto.eq_ignore_ascii_case("SMTP") || proto.eq_ignore_ascii_case("ESMTP")
+                    }) {
+                        return None;
+                    }
+                }
+                ("LOGIN", XCommand::XClient) => {
+                    attributes.login = Some(value);
+                }
+                ("DESTADDR", XCommand::XClient) => {
+                    attributes.dest_addr = Some(parse_value(value, parse_addr)?);
+                }
+                ("DESTPORT", XCommand::XClient) => {
+                    parse_value(value, |port| port.parse::<u16>().ok())?;
+                }
+                ("IDENT" | "SOURCE", XCommand::XForward) => (),
+                _ => return None,
+            }
+        }
+
+        Some(attributes)
+    }
+}
+
+// Extracts XCLIENT and XFORWARD commands ahead of the protocol parser,
+// returns None when the pending line holds any other command.
+pub fn take_xclient(receiver: &mut RequestReceiver, iter: &mut Iter<'_, u8>) -> Option<XLine> {
+    let pending = iter.as_slice();
+    let mut prefix = Vec::with_capacity(9);
+    prefix.extend(
+        receiver
+            .buf
+            .iter()
+            .chain(pending.iter())
+            .take(9)
+            .map(|ch| ch.to_ascii_uppercase()),
+    );
+    let command = if prefix.starts_with(b"XCLIENT ") {
+        XCommand::XClient
+    } else if prefix.starts_with(b"XFORWARD ") {
+        XCommand::XForward
+    } else {
+        return None;
+    };
+
+    if let Some(pos) = pending.iter().position(|&ch| ch == b'\n') {
+        let mut line = std::mem::take(&mut receiver.buf);
+        line.extend_from_slice(&pending[..pos]);
+        iter.nth(pos);
+        Some(XLine::Complete(
+            command,
+            String::from_utf8_lossy(&line).trim_end().to_string(),
+        ))
+    } else if receiver.buf.len() + pending.len() < MAX_LINE_LENGTH {
+        receiver.buf.extend_from_slice(pending);
+        for _ in iter.by_ref() {}
+        Some(XLine::Incomplete)
+    } else {
+        receiver.buf.clear();
+        Some(XLine::TooLong)
+    }
+}
+
+fn parse_value<T>(
+    value: Option<String>,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Option<Option<T>> {
+    match value {
+        Some(value) => parse(&value).map(Some),
+        None => Some(None),
+    }
+}
+
+fn parse_addr(addr: &str) -> Option<IpAddr> {
+    addr.get(..5)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("IPV6:"))
+        .map_or(addr, |_| &addr[5..])
+        .parse()
+        .ok()
+}
+
+fn xtext_decode(value: &str) -> Option<String> {
+    let mut result = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+    while let Some(ch) = bytes.next() {
+        if ch == b'+' {
+            let hex = [bytes.next()?, bytes.next()?];
+            result.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            result.push(ch);
+        }
+    }
+    String::from_utf8(result).ok()
+}
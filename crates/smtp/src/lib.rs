@@ -22,36 +22,71 @@
 */
 
 use crate::core::{
-    throttle::ThrottleKeyHasherBuilder, QueueCore, ReportCore, SessionCore, TlsConnectors, SMTP,
+    connections::ConnectionLimiter, throttle::ThrottleKeyHasherBuilder, AnomalyCore,
+    DkimReplayCore, GeoIpCore, QueueCore, ReportCore, ReputationCore, SessionCore, SieveCore,
+    SuppressionCore, TlsConnectors, TrackingCore, UsageCore, WebhookCore, SMTP,
 };
 use std::sync::Arc;
 
+use ahash::AHashMap;
 use config::{
-    auth::ConfigAuth, queue::ConfigQueue, remote::ConfigHost, report::ConfigReport,
-    resolver::ConfigResolver, scripts::ConfigSieve, session::ConfigSession, ConfigContext, Host,
+    anomaly::ConfigAnomaly, auth::ConfigAuth, geoip::ConfigGeoIp, policy::ConfigPolicy,
+    queue::ConfigQueue, remote::ConfigHost, replay::ConfigDkimReplay, report::ConfigReport,
+    reputation::ConfigReputation, resolver::ConfigResolver, scripts::ConfigSieve,
+    session::ConfigSession, suppression::ConfigSuppression, tracking::ConfigTracking,
+    transport::ConfigTransport, unsubscribe::ConfigUnsubscribe, usage::ConfigUsage,
+    webhook::ConfigWebhook, AnomalyConfig, ConfigContext, DkimReplayConfig, Host, MailAuthConfig,
+    QueueConfig, ReportConfig, ReputationConfig, SessionConfig, SuppressionConfig,
+    UnsubscribeConfig, UsageConfig,
 };
 use dashmap::DashMap;
 use directory::DirectoryConfig;
 use mail_send::smtp::tls::build_tls_connector;
-use queue::manager::SpawnQueue;
+use outbound::pool::ConnectionPool;
+use queue::{manager::SpawnQueue, QueueLoad};
 use reporting::scheduler::SpawnReport;
 use tokio::sync::mpsc;
+use tracking::manager::{SpawnTracking, TrackingLog};
 use utils::{
-    config::{Config, ServerProtocol, Servers},
+    config::{Config, Server, ServerProtocol, Servers},
     UnwrapFailure,
 };
+use webhook::manager::{EndpointQueue, SpawnWebhook};
 
+pub mod anomaly;
 pub mod config;
 pub mod core;
+pub mod geoip;
 pub mod inbound;
 pub mod outbound;
 pub mod queue;
+pub mod replay;
 pub mod reporting;
+pub mod reputation;
 pub mod scripts;
+pub mod suppression;
+pub mod tracking;
+pub mod unsubscribe;
+pub mod usage;
+pub mod webhook;
 
 pub static USER_AGENT: &str = concat!("StalwartSMTP/", env!("CARGO_PKG_VERSION"),);
 pub static DAEMON_NAME: &str = concat!("Stalwart SMTP v", env!("CARGO_PKG_VERSION"),);
 
+struct CoreConfig {
+    session: SessionConfig,
+    queue: QueueConfig,
+    mail_auth: MailAuthConfig,
+    report: ReportConfig,
+    sieve: SieveCore,
+    usage: UsageConfig,
+    reputation: ReputationConfig,
+    suppression: SuppressionConfig,
+    anomaly: AnomalyConfig,
+    dkim_replay: DkimReplayConfig,
+    unsubscribe: UnsubscribeConfig,
+}
+
 impl SMTP {
     pub async fn init(
         config: &Config,
@@ -60,95 +95,162 @@ impl SMTP {
         #[cfg(feature = "local_delivery")] delivery_tx: mpsc::Sender<utils::ipc::DeliveryEvent>,
     ) -> Result<Arc<Self>, String> {
         // Read configuration parameters
-        let mut config_ctx = ConfigContext::new(&servers.inner);
-        config_ctx.directory = directory.clone();
-
-        // Parse remote hosts
-        config.parse_remote_hosts(&mut config_ctx)?;
-
-        // Add local delivery host
-        #[cfg(feature = "local_delivery")]
-        {
-            config_ctx.hosts.insert(
-                "local".to_string(),
-                Host {
-                    address: String::new(),
-                    port: 0,
-                    protocol: ServerProtocol::Jmap,
-                    concurrency: Default::default(),
-                    timeout: Default::default(),
-                    tls_implicit: Default::default(),
-                    tls_allow_invalid_certs: Default::default(),
-                    username: Default::default(),
-                    secret: Default::default(),
-                },
-            );
-        }
-
-        // Parse configuration
-        config.parse_signatures(&mut config_ctx)?;
-        let sieve_config = config.parse_sieve(&mut config_ctx)?;
-        let session_config = config.parse_session_config(&config_ctx)?;
-        let queue_config = config.parse_queue(&config_ctx)?;
-        let mail_auth_config = config.parse_mail_auth(&config_ctx)?;
-        let report_config = config.parse_reports(&config_ctx)?;
+        let core_config = Self::parse_config(config, &servers.inner, directory)?;
+        let webhook_config = config.parse_webhooks()?;
+        let tracking_config = config.parse_tracking()?;
+        let geoip = Arc::new(GeoIpCore::open(config.parse_geoip()?).await?);
 
         // Build core
         let (queue_tx, queue_rx) = mpsc::channel(1024);
         let (report_tx, report_rx) = mpsc::channel(1024);
+        let (tracking_tx, tracking_rx) = mpsc::channel(1024);
+        let mut webhook_tx = AHashMap::with_capacity(webhook_config.endpoints.len());
+        let mut webhook_rx = Vec::with_capacity(webhook_config.endpoints.len());
+        for endpoint in &webhook_config.endpoints {
+            let (tx, rx) = mpsc::channel(1024);
+            webhook_tx.insert(endpoint.id.clone(), tx);
+            webhook_rx.push((endpoint.clone(), rx));
+        }
+        let webhook = Arc::new(WebhookCore {
+            config: webhook_config,
+            id_seq: 0.into(),
+            tx: webhook_tx,
+        });
         let core = Arc::new(SMTP {
-            worker_pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(
-                    config
-                        .property::<usize>("global.thread-pool")?
-                        .filter(|v| *v > 0)
-                        .unwrap_or_else(num_cpus::get),
-                )
-                .build()
-                .unwrap(),
-            resolvers: config.build_resolvers().failed("Failed to build resolvers"),
+            worker_pool: Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(
+                        config
+                            .property::<usize>("global.thread-pool")?
+                            .filter(|v| *v > 0)
+                            .unwrap_or_else(num_cpus::get),
+                    )
+                    .build()
+                    .unwrap(),
+            ),
+            resolvers: Arc::new(config.build_resolvers().failed("Failed to build resolvers")),
             session: SessionCore {
-                config: session_config,
-                throttle: DashMap::with_capacity_and_hasher_and_shard_amount(
+                connections: Arc::new(ConnectionLimiter::new(&core_config.session.connections)),
+                config: core_config.session,
+                throttle: Arc::new(DashMap::with_capacity_and_hasher_and_shard_amount(
                     config.property("global.shared-map.capacity")?.unwrap_or(2),
                     ThrottleKeyHasherBuilder::default(),
                     config
                         .property::<u64>("global.shared-map.shard")?
                         .unwrap_or(32)
                         .next_power_of_two() as usize,
-                ),
+                )),
             },
             queue: QueueCore {
-                config: queue_config,
-                throttle: DashMap::with_capacity_and_hasher_and_shard_amount(
+                config: core_config.queue,
+                throttle: Arc::new(DashMap::with_capacity_and_hasher_and_shard_amount(
                     config.property("global.shared-map.capacity")?.unwrap_or(2),
                     ThrottleKeyHasherBuilder::default(),
                     config
                         .property::<u64>("global.shared-map.shard")?
                         .unwrap_or(32)
                         .next_power_of_two() as usize,
-                ),
-                id_seq: 0.into(),
-                quota: DashMap::with_capacity_and_hasher_and_shard_amount(
+                )),
+                id_seq: Arc::new(0.into()),
+                quota: Arc::new(DashMap::with_capacity_and_hasher_and_shard_amount(
                     config.property("global.shared-map.capacity")?.unwrap_or(2),
                     ThrottleKeyHasherBuilder::default(),
                     config
                         .property::<u64>("global.shared-map.shard")?
                         .unwrap_or(32)
                         .next_power_of_two() as usize,
-                ),
+                )),
                 tx: queue_tx,
                 connectors: TlsConnectors {
                     pki_verify: build_tls_connector(false),
                     dummy_verify: build_tls_connector(true),
                 },
+                ipv6_fallback: Arc::new(DashMap::new()),
+                connections: Arc::new(ConnectionPool::default()),
+                load: Arc::new(QueueLoad::default()),
             },
             report: ReportCore {
                 tx: report_tx,
-                config: report_config,
+                config: core_config.report,
+            },
+            mail_auth: core_config.mail_auth,
+            sieve: core_config.sieve,
+            webhook: webhook.clone(),
+            tracking: Arc::new(TrackingCore {
+                config: tracking_config,
+                tx: tracking_tx,
+                index: Default::default(),
+            }),
+            usage: UsageCore {
+                config: core_config.usage,
+                counters: Arc::new(DashMap::with_capacity_and_hasher_and_shard_amount(
+                    config.property("global.shared-map.capacity")?.unwrap_or(2),
+                    Default::default(),
+                    config
+                        .property::<u64>("global.shared-map.shard")?
+                        .unwrap_or(32)
+                        .next_power_of_two() as usize,
+                )),
+            },
+            reputation: ReputationCore {
+                config: core_config.reputation,
+                entries: Arc::new(DashMap::with_capacity_and_hasher_and_shard_amount(
+                    config.property("global.shared-map.capacity")?.unwrap_or(2),
+                    Default::default(),
+                    config
+                        .property::<u64>("global.shared-map.shard")?
+                        .unwrap_or(32)
+                        .next_power_of_two() as usize,
+                )),
+            },
+            suppression: SuppressionCore {
+                config: core_config.suppression,
+                entries: Arc::new(DashMap::with_capacity_and_hasher_and_shard_amount(
+                    config.property("global.shared-map.capacity")?.unwrap_or(2),
+                    Default::default(),
+                    config
+                        .property::<u64>("global.shared-map.shard")?
+                        .unwrap_or(32)
+                        .next_power_of_two() as usize,
+                )),
             },
-            mail_auth: mail_auth_config,
-            sieve: sieve_config,
+            anomaly: AnomalyCore {
+                config: core_config.anomaly,
+                accounts: Arc::new(DashMap::with_capacity_and_hasher_and_shard_amount(
+                    config.property("global.shared-map.capacity")?.unwrap_or(2),
+                    Default::default(),
+                    config
+                        .property::<u64>("global.shared-map.shard")?
+                        .unwrap_or(32)
+                        .next_power_of_two() as usize,
+                )),
+                senders: Arc::new(DashMap::with_capacity_and_hasher_and_shard_amount(
+                    config.property("global.shared-map.capacity")?.unwrap_or(2),
+                    Default::default(),
+                    config
+                        .property::<u64>("global.shared-map.shard")?
+                        .unwrap_or(32)
+                        .next_power_of_two() as usize,
+                )),
+                webhook,
+                geoip: geoip.clone(),
+                #[cfg(feature = "local_delivery")]
+                delivery_tx: delivery_tx.clone(),
+            },
+            dkim_replay: DkimReplayCore {
+                config: core_config.dkim_replay,
+                entries: Arc::new(DashMap::with_capacity_and_hasher_and_shard_amount(
+                    config.property("global.shared-map.capacity")?.unwrap_or(2),
+                    Default::default(),
+                    config
+                        .property::<u64>("global.shared-map.shard")?
+                        .unwrap_or(32)
+                        .next_power_of_two() as usize,
+                )),
+                geoip: geoip.clone(),
+            },
+            unsubscribe: core_config.unsubscribe,
+            geoip,
             #[cfg(feature = "local_delivery")]
             delivery_tx,
         });
@@ -159,6 +261,224 @@ impl SMTP {
         // Spawn report manager
         report_rx.spawn(core.clone(), core.report.read_reports().await);
 
+        // Spawn webhook managers
+        for (endpoint, rx) in webhook_rx {
+            rx.spawn(EndpointQueue::new(endpoint, &core.webhook.config).await);
+        }
+
+        // Spawn tracking manager
+        if let Some(log) = TrackingLog::new(&core.tracking) {
+            tracking_rx.spawn(log);
+        }
+
+        // Load usage counters and persist them periodically
+        core.usage.read_counters().await;
+        if let Some(flush_frequency) = core.usage.flush_frequency() {
+            let core = core.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(flush_frequency).await;
+                    core.usage.write_counters().await;
+                }
+            });
+        }
+
+        // Load client reputation entries and persist them periodically
+        core.reputation.read_entries().await;
+        if let Some(flush_frequency) = core.reputation.flush_frequency() {
+            let core = core.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(flush_frequency).await;
+                    core.reputation.write_entries().await;
+                }
+            });
+        }
+
+        // Load the suppression list and persist it periodically
+        core.suppression.read_entries().await;
+        if let Some(flush_frequency) = core.suppression.flush_frequency() {
+            let core = core.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(flush_frequency).await;
+                    core.suppression.write_entries().await;
+                }
+            });
+        }
+
+        // Load account activity and persist it periodically
+        core.anomaly.read_entries().await;
+        if let Some(flush_frequency) = core.anomaly.flush_frequency() {
+            let core = core.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(flush_frequency).await;
+                    core.anomaly.write_entries().await;
+                }
+            });
+        }
+
+        // Reload the GeoIP databases when their files are updated
+        if let Some(reload_frequency) = core.geoip.reload_frequency() {
+            let core = core.clone();
+            tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(reload_frequency).await;
+                    core.geoip.reload().await;
+                }
+            });
+        }
+
         Ok(core)
     }
+
+    // Builds a new core from an updated configuration. Throttles, quotas, usage
+    // counters, reputation entries, suppressed addresses, account activity, seen DKIM signatures, GeoIP databases and the
+    // queue, report, webhook and tracking channels are shared with the current core so that in-flight
+    // sessions and queued messages are unaffected.
+    pub fn reload(
+        &self,
+        config: &Config,
+        servers: &[Server],
+        directory: &DirectoryConfig,
+    ) -> Result<Arc<Self>, String> {
+        let core_config = Self::parse_config(config, servers, directory)?;
+
+        // Connection counters are kept, but the limits are taken from the new configuration
+        self.session.connections.set_limits(
+            core_config.session.connections.max_concurrent,
+            core_config.session.connections.max_concurrent_per_ip,
+        );
+
+        Ok(Arc::new(SMTP {
+            worker_pool: self.worker_pool.clone(),
+            resolvers: self.resolvers.clone(),
+            session: SessionCore {
+                config: core_config.session,
+                throttle: self.session.throttle.clone(),
+                connections: self.session.connections.clone(),
+            },
+            queue: QueueCore {
+                config: core_config.queue,
+                throttle: self.queue.throttle.clone(),
+                id_seq: self.queue.id_seq.clone(),
+                quota: self.queue.quota.clone(),
+                tx: self.queue.tx.clone(),
+                connectors: TlsConnectors {
+                    pki_verify: build_tls_connector(false),
+                    dummy_verify: build_tls_connector(true),
+                },
+                ipv6_fallback: self.queue.ipv6_fallback.clone(),
+                connections: self.queue.connections.clone(),
+                load: self.queue.load.clone(),
+            },
+            report: ReportCore {
+                tx: self.report.tx.clone(),
+                config: core_config.report,
+            },
+            mail_auth: core_config.mail_auth,
+            sieve: SieveCore {
+                shadow: self.sieve.shadow.clone(),
+                ..core_config.sieve
+            },
+            webhook: self.webhook.clone(),
+            tracking: self.tracking.clone(),
+            usage: UsageCore {
+                config: core_config.usage,
+                counters: self.usage.counters.clone(),
+            },
+            reputation: ReputationCore {
+                config: core_config.reputation,
+                entries: self.reputation.entries.clone(),
+            },
+            suppression: SuppressionCore {
+                config: core_config.suppression,
+                entries: self.suppression.entries.clone(),
+            },
+            anomaly: AnomalyCore {
+                config: core_config.anomaly,
+                accounts: self.anomaly.accounts.clone(),
+                senders: self.anomaly.senders.clone(),
+                webhook: self.webhook.clone(),
+                geoip: self.geoip.clone(),
+                #[cfg(feature = "local_delivery")]
+                delivery_tx: self.delivery_tx.clone(),
+            },
+            dkim_replay: DkimReplayCore {
+                config: core_config.dkim_replay,
+                entries: self.dkim_replay.entries.clone(),
+                geoip: self.geoip.clone(),
+            },
+            unsubscribe: core_config.unsubscribe,
+            geoip: self.geoip.clone(),
+            #[cfg(feature = "local_delivery")]
+            delivery_tx: self.delivery_tx.clone(),
+        }))
+    }
+
+    // Parses and validates the configuration without starting any services.
+    pub fn check_config(
+        config: &Config,
+        servers: &[Server],
+        directory: &DirectoryConfig,
+    ) -> Result<(), String> {
+        Self::parse_config(config, servers, directory)?;
+        config.parse_webhooks()?;
+        config.parse_tracking()?;
+        config.parse_geoip()?;
+        config.build_resolvers()?;
+        Ok(())
+    }
+
+    fn parse_config(
+        config: &Config,
+        servers: &[Server],
+        directory: &DirectoryConfig,
+    ) -> Result<CoreConfig, String> {
+        let mut config_ctx = ConfigContext::new(servers);
+        config_ctx.directory = directory.clone();
+
+        // Parse remote hosts
+        config.parse_remote_hosts(&mut config_ctx)?;
+
+        // Add local delivery host
+        #[cfg(feature = "local_delivery")]
+        {
+            config_ctx.hosts.insert(
+                "local".to_string(),
+                Host {
+                    address: String::new(),
+                    port: 0,
+                    protocol: ServerProtocol::Jmap,
+                    concurrency: Default::default(),
+                    timeout: Default::default(),
+                    tls_implicit: Default::default(),
+                    tls_allow_invalid_certs: Default::default(),
+                    username: Default::default(),
+                    secret: Default::default(),
+                },
+            );
+        }
+
+        // Parse configuration
+        config.parse_signatures(&mut config_ctx)?;
+        config.parse_policy_servers(&mut config_ctx)?;
+        config.parse_transports(&mut config_ctx)?;
+        let sieve = config.parse_sieve(&mut config_ctx)?;
+
+        Ok(CoreConfig {
+            session: config.parse_session_config(&config_ctx)?,
+            queue: config.parse_queue(&config_ctx)?,
+            mail_auth: config.parse_mail_auth(&config_ctx)?,
+            report: config.parse_reports(&config_ctx)?,
+            sieve,
+            usage: config.parse_usage(&config_ctx)?,
+            reputation: config.parse_reputation(&config_ctx)?,
+            suppression: config.parse_suppression(&config_ctx)?,
+            anomaly: config.parse_anomaly()?,
+            dkim_replay: config.parse_dkim_replay()?,
+            unsubscribe: config.parse_unsubscribe()?,
+        })
+    }
 }
@@ -0,0 +1,169 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use utils::listener::limiter::InFlight;
+
+use crate::{
+    config::RequireOptional,
+    core::SMTP,
+    queue::{Domain, Message, QueueEnvelope, Status, Timestamp},
+};
+
+use super::lookup::ToNextHop;
+
+#[derive(PartialEq, Eq)]
+struct BatchKey {
+    mx: Vec<(u16, Vec<String>)>,
+    max_mx: usize,
+    start_tls: RequireOptional,
+    dane: RequireOptional,
+    disable_tls: bool,
+}
+
+impl SMTP {
+    // Groups the domains due for delivery that are served by the same MX hosts, so the
+    // recipients of each group are delivered in a single transaction. Returns, for each
+    // domain, the index of the domain whose delivery attempt carries its recipients.
+    pub async fn batch_domains(
+        &self,
+        message: &Message,
+        domains: &[Domain],
+        in_flight: &mut Vec<InFlight>,
+        span: &tracing::Span,
+    ) -> Vec<Option<usize>> {
+        let queue_config = &self.queue.config;
+        let no_ip = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0));
+        let mut batches = vec![None; domains.len()];
+        let mut leaders: Vec<(usize, BatchKey)> = Vec::new();
+
+        for (domain_idx, domain) in domains.iter().enumerate() {
+            if !matches!(&domain.status, Status::Scheduled | Status::TemporaryFailure(_)
+                if domain.retry.due <= Timestamp::now())
+            {
+                continue;
+            }
+
+            let envelope = QueueEnvelope {
+                message,
+                domain: &domain.domain,
+                mx: "",
+                remote_ip: no_ip,
+                local_ip: no_ip,
+            };
+            if !*queue_config.batch_mx.eval(&envelope).await
+                || queue_config.transport.eval(&envelope).await.is_some()
+                || queue_config.next_hop.eval(&envelope).await.is_some()
+            {
+                continue;
+            }
+
+            // Domains publishing a null MX are left to fail on their own
+            let max_mx = *queue_config.max_mx.eval(&envelope).await;
+            let mx_list = match self.mx_lookup(&domain.domain).await {
+                Ok(mx_list) if mx_list.to_remote_hosts(&domain.domain, max_mx).is_some() => mx_list,
+                _ => continue,
+            };
+            let mut mx = mx_list
+                .iter()
+                .map(|mx| {
+                    let mut exchanges = mx
+                        .exchanges
+                        .iter()
+                        .map(|host| host.trim_end_matches('.').to_lowercase())
+                        .collect::<Vec<_>>();
+                    exchanges.sort_unstable();
+                    (mx.preference, exchanges)
+                })
+                .collect::<Vec<_>>();
+            mx.sort_unstable();
+            let key = BatchKey {
+                mx,
+                max_mx,
+                start_tls: *queue_config.tls.start.eval(&envelope).await,
+                dane: *queue_config.tls.dane.eval(&envelope).await,
+                disable_tls: domain.disable_tls,
+            };
+
+            let leader_idx = if let Some((leader_idx, _)) =
+                leaders.iter().find(|(_, leader_key)| leader_key == &key)
+            {
+                *leader_idx
+            } else {
+                leaders.push((domain_idx, key));
+                continue;
+            };
+
+            // MTA-STS policies are only enforced for the first domain of a batch,
+            // domains publishing a policy are delivered separately.
+            match *queue_config.tls.mta_sts.eval(&envelope).await {
+                RequireOptional::Require => continue,
+                RequireOptional::Optional
+                    if self
+                        .lookup_mta_sts_policy(
+                            &domain.domain,
+                            *queue_config.timeout.mta_sts.eval(&envelope).await,
+                        )
+                        .await
+                        .is_ok() =>
+                {
+                    continue;
+                }
+                _ => (),
+            }
+
+            // Throttle recipient domain
+            let mut domain_in_flight = Vec::new();
+            let mut is_allowed = true;
+            for throttle in &queue_config.throttle.rcpt {
+                if self
+                    .queue
+                    .is_allowed(throttle, &envelope, &mut domain_in_flight, span)
+                    .await
+                    .is_err()
+                {
+                    is_allowed = false;
+                    break;
+                }
+            }
+            if !is_allowed {
+                continue;
+            }
+
+            tracing::debug!(
+                parent: span,
+                context = "queue",
+                event = "batch",
+                domain = domain.domain,
+                with = domains[leader_idx].domain,
+                "Delivering recipients together with domain sharing the same MX hosts."
+            );
+
+            in_flight.extend(domain_in_flight);
+            batches[domain_idx] = Some(leader_idx);
+        }
+
+        batches
+    }
+}
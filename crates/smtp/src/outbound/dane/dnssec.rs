@@ -46,6 +46,7 @@ impl DnssecResolver {
     ) -> Result<Self, ResolveError> {
         Ok(Self {
             resolver: AsyncResolver::tokio(config, options),
+            enabled: true,
         })
     }
 }
@@ -55,6 +56,11 @@ impl Resolvers {
         &self,
         key: impl IntoFqdn<'x>,
     ) -> mail_auth::Result<Option<Arc<Tlsa>>> {
+        // Without DNSSEC validation TLSA records can't be trusted
+        if !self.dnssec.enabled {
+            return Ok(None);
+        }
+
         let key = key.into_fqdn();
         if let Some(value) = self.cache.tlsa.get(key.as_ref()) {
             return Ok(Some(value));
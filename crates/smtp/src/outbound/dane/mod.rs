@@ -28,6 +28,7 @@ pub mod verify;
 
 pub struct DnssecResolver {
     pub resolver: TokioAsyncResolver,
+    pub enabled: bool,
 }
 
 #[derive(Debug, Hash, PartialEq, Eq)]
@@ -22,35 +22,37 @@
 */
 
 use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr},
     sync::Arc,
-    time::{Duration, Instant},
+    time::{Instant, SystemTime},
 };
 
 use mail_auth::{
     mta_sts::TlsRpt,
     report::tlsrpt::{FailureDetails, ResultType},
 };
-use mail_send::SmtpClient;
 use smtp_proto::MAIL_REQUIRETLS;
 use utils::config::ServerProtocol;
 
 use crate::{
-    config::{AggregateFrequency, TlsStrategy},
+    config::{AggregateFrequency, DaneFailurePolicy, TlsStrategy},
     core::SMTP,
-    queue::ErrorDetails,
+    inbound::IsTls,
+    queue::{ErrorDetails, HistoryEntry, Recipient},
     reporting::{tls::TlsRptOptions, PolicyType, TlsEvent},
 };
 
 use super::{
-    lookup::ToNextHop,
+    happy_eyeballs::{connect_race, ConnectTarget},
+    lookup::{is_valid_mx, ToNextHop},
     mta_sts,
+    pool::{ConnectionInfo, ConnectionKey, ConnectionReuse, SmtpConnection},
     session::{read_greeting, say_helo, try_start_tls, SessionParams, StartTlsResult},
     NextHop,
 };
 use crate::queue::{
-    manager::Queue, throttle, DeliveryAttempt, Domain, Error, Event, OnHold, QueueEnvelope,
-    Schedule, Status, WorkerResult,
+    manager::Queue, retry::RetryPolicy, throttle, DeliveryAttempt, Domain, Error, Event, OnHold,
+    QueueEnvelope, Schedule, Status, Timestamp, WorkerResult,
 };
 
 impl DeliveryAttempt {
@@ -58,13 +60,18 @@ impl DeliveryAttempt {
         // Check that the message still has recipients to be delivered
         let has_pending_delivery = self.has_pending_delivery();
 
-        // Send any due Delivery Status Notifications
-        core.queue.send_dsn(&mut self).await;
+        // Publish delivery events and send any due Delivery Status Notifications
+        core.anomaly.record_outcomes(&mut self.message).await;
+        core.suppression.record_outcomes(&mut self.message).await;
+        core.webhook.publish_delivery_status(&mut self.message);
+        if let Some(dsn_id) = core.queue.send_dsn(&mut self).await {
+            core.tracking.track_dsn(&self.message, dsn_id);
+        }
 
         if has_pending_delivery {
             // Re-queue the message if its not yet due for delivery
             let due = self.message.next_delivery_event();
-            if due > Instant::now() {
+            if due > Timestamp::now() {
                 // Save changes to disk
                 self.message.save_changes().await;
 
@@ -76,7 +83,9 @@ impl DeliveryAttempt {
             }
         } else {
             // All message recipients expired, do not re-queue. (DSN has been already sent)
-            self.message.remove().await;
+            if !self.message.move_to_dead_letter(&core.queue).await {
+                self.message.remove().await;
+            }
             return;
         }
 
@@ -98,7 +107,7 @@ impl DeliveryAttempt {
                 match err {
                     throttle::Error::Concurrency { limiter } => {
                         queue.on_hold(OnHold {
-                            next_due: self.message.next_event_after(Instant::now()),
+                            next_due: self.message.next_event_after(Timestamp::now()),
                             limiters: vec![limiter],
                             message: self.message,
                         });
@@ -121,14 +130,36 @@ impl DeliveryAttempt {
 
             let mut domains = std::mem::take(&mut self.message.domains);
             let mut recipients = std::mem::take(&mut self.message.recipients);
+            let mut history = Vec::new();
+
+            // Deliver together the recipients of domains sharing the same MX hosts
+            let mut batch_in_flight = Vec::new();
+            let batches = core
+                .batch_domains(&self.message, &domains, &mut batch_in_flight, &self.span)
+                .await;
+
             'next_domain: for (domain_idx, domain) in domains.iter_mut().enumerate() {
                 // Only process domains due for delivery
                 if !matches!(&domain.status, Status::Scheduled | Status::TemporaryFailure(_)
-                if domain.retry.due <= Instant::now())
+                if domain.retry.due <= Timestamp::now())
                 {
                     continue;
                 }
 
+                // Batched domains are delivered along with the first domain of their batch
+                if batches[domain_idx].is_some() {
+                    continue;
+                }
+                let batch = std::iter::once(domain_idx)
+                    .chain(
+                        batches
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, leader_idx)| **leader_idx == Some(domain_idx))
+                            .map(|(idx, _)| idx),
+                    )
+                    .collect::<Vec<_>>();
+
                 // Create new span for domain
                 let span = tracing::info_span!(
                     parent: &self.span,
@@ -137,6 +168,10 @@ impl DeliveryAttempt {
                     attempt_number = domain.retry.inner,
                 );
 
+                // Record delivery attempt
+                history.push(HistoryEntry::new(domain_idx));
+                let attempt = history.last_mut().unwrap();
+
                 // Build envelope
                 let mut envelope = QueueEnvelope {
                     message: self.message.as_ref(),
@@ -159,6 +194,38 @@ impl DeliveryAttempt {
                     }
                 }
 
+                // Deliver through a local transport
+                if let Some(transport) = queue_config.transport.eval(&envelope).await {
+                    attempt.mx = transport.id.clone();
+                    let params = SessionParams {
+                        span: &span,
+                        credentials: None,
+                        is_smtp: false,
+                        hostname: &transport.id,
+                        local_hostname: queue_config.hostname.eval(&envelope).await,
+                        timeout_ehlo: *queue_config.timeout.ehlo.eval(&envelope).await,
+                        timeout_mail: *queue_config.timeout.mail.eval(&envelope).await,
+                        timeout_rcpt: *queue_config.timeout.rcpt.eval(&envelope).await,
+                        timeout_data: *queue_config.timeout.data.eval(&envelope).await,
+                        max_rcpt: 0,
+                        reuse: None,
+                    };
+                    let delivery_result = self
+                        .message
+                        .deliver_transport(
+                            transport,
+                            recipients
+                                .iter_mut()
+                                .filter(|r| batch.contains(&r.domain_idx)),
+                            params,
+                        )
+                        .await;
+
+                    // Update status for the current domain and continue with the next one
+                    domain.set_status(delivery_result, &queue_config.retry_policy(&envelope).await);
+                    continue 'next_domain;
+                }
+
                 // Obtain next hop
                 let (mut remote_hosts, is_smtp) = match queue_config.next_hop.eval(&envelope).await
                 {
@@ -168,15 +235,19 @@ impl DeliveryAttempt {
                         let delivery_result = self
                             .message
                             .deliver_local(
-                                recipients.iter_mut().filter(|r| r.domain_idx == domain_idx),
+                                recipients
+                                    .iter_mut()
+                                    .filter(|r| batch.contains(&r.domain_idx)),
                                 &core.delivery_tx,
                                 &span,
                             )
                             .await;
 
                         // Update status for the current domain and continue with the next one
-                        domain
-                            .set_status(delivery_result, queue_config.retry.eval(&envelope).await);
+                        domain.set_status(
+                            delivery_result,
+                            &queue_config.retry_policy(&envelope).await,
+                        );
                         continue 'next_domain;
                     }
                     Some(next_hop) => (
@@ -294,7 +365,7 @@ impl DeliveryAttempt {
                                     "Failed to retrieve MTA-STS policy: {}",
                                     err
                                 );
-                                domain.set_status(err, queue_config.retry.eval(&envelope).await);
+                                domain.set_status(err, &queue_config.retry_policy(&envelope).await);
                                 continue 'next_domain;
                             } else {
                                 tracing::debug!(
@@ -317,7 +388,7 @@ impl DeliveryAttempt {
                 let mx_list;
                 if is_smtp && remote_hosts.is_empty() {
                     // Lookup MX
-                    mx_list = match core.resolvers.dns.mx_lookup(&domain.domain).await {
+                    mx_list = match core.mx_lookup(&domain.domain).await {
                         Ok(mx) => mx,
                         Err(err) => {
                             tracing::info!(
@@ -326,7 +397,7 @@ impl DeliveryAttempt {
                                 event = "mx-lookup-failed",
                                 reason = %err,
                             );
-                            domain.set_status(err, queue_config.retry.eval(&envelope).await);
+                            domain.set_status(err, &queue_config.retry_policy(&envelope).await);
                             continue 'next_domain;
                         }
                     };
@@ -340,16 +411,48 @@ impl DeliveryAttempt {
                             parent: &span,
                             context = "dns",
                             event = "null-mx",
-                            reason = "Domain does not accept messages (mull MX)",
+                            reason = "Domain does not accept messages (null MX)",
                         );
                         domain.set_status(
-                            Status::PermanentFailure(Error::DnsError(
-                                "Domain does not accept messages (null MX)".to_string(),
-                            )),
-                            queue_config.retry.eval(&envelope).await,
+                            Status::PermanentFailure(Error::NullMx),
+                            &queue_config.retry_policy(&envelope).await,
                         );
                         continue 'next_domain;
                     }
+
+                    // Discard IP literals and loopback hosts published as MX targets
+                    if *queue_config.reject_invalid_mx.eval(&envelope).await {
+                        remote_hosts.retain(|remote_host| {
+                            if is_valid_mx(remote_host.hostname()) {
+                                true
+                            } else {
+                                tracing::debug!(
+                                    parent: &span,
+                                    context = "dns",
+                                    event = "invalid-mx",
+                                    mx = remote_host.hostname(),
+                                    "Ignoring invalid MX target."
+                                );
+                                false
+                            }
+                        });
+
+                        if remote_hosts.is_empty() {
+                            tracing::info!(
+                                parent: &span,
+                                context = "dns",
+                                event = "invalid-mx",
+                                reason = "No valid MX hosts found",
+                            );
+                            domain.set_status(
+                                Status::PermanentFailure(Error::DnsError(
+                                    "MX records point to invalid hosts".to_string(),
+                                )),
+                                &queue_config.retry_policy(&envelope).await,
+                            );
+                            continue 'next_domain;
+                        }
+                    }
                 }
 
                 // Try delivering message
@@ -358,6 +461,9 @@ impl DeliveryAttempt {
                 'next_host: for remote_host in &remote_hosts {
                     // Validate MTA-STS
                     envelope.mx = remote_host.hostname();
+                    attempt.mx = envelope.mx.to_string();
+                    attempt.remote_ip.clear();
+                    attempt.tls.clear();
                     if let Some(mta_sts_policy) = &mta_sts_policy {
                         if !mta_sts_policy.verify(envelope.mx) {
                             // Report MTA-STS failed verification
@@ -557,18 +663,26 @@ impl DeliveryAttempt {
                     };
 
                     // Try each IP address
-                    'next_ip: for remote_ip in resolve_result.remote_ips {
+                    let mut raced_ips = Vec::new();
+                    let mut ipv6_failed = false;
+                    'next_ip: for (ip_pos, remote_ip) in
+                        resolve_result.remote_ips.iter().copied().enumerate()
+                    {
+                        // Skip addresses already attempted as a fallback
+                        if raced_ips.contains(&remote_ip) {
+                            continue 'next_ip;
+                        }
+
                         // Set source IP, if any
-                        let source_ip = if remote_ip.is_ipv4() {
-                            resolve_result.source_ipv4
-                        } else {
-                            resolve_result.source_ipv6
-                        };
+                        let mut remote_ip = remote_ip;
+                        let mut source_ip = resolve_result.source_ip(remote_ip);
                         envelope.local_ip = source_ip.unwrap_or(no_ip);
 
                         // Throttle remote host
                         let mut in_flight_host = Vec::new();
                         envelope.remote_ip = remote_ip;
+                        attempt.remote_ip = remote_ip.to_string();
+                        attempt.tls.clear();
                         for throttle in &queue_config.throttle.host {
                             if let Err(err) = core
                                 .queue
@@ -580,21 +694,208 @@ impl DeliveryAttempt {
                             }
                         }
 
-                        // Connect
-                        let mut smtp_client = match if let Some(ip_addr) = source_ip {
-                            SmtpClient::connect_using(
-                                ip_addr,
-                                SocketAddr::new(remote_ip, remote_host.port()),
-                                *queue_config.timeout.connect.eval(&envelope).await,
-                            )
-                            .await
+                        // Reuse an idle connection to this host, if available
+                        let is_strict_tls = tls_strategy.is_tls_required()
+                            || (self.message.flags & MAIL_REQUIRETLS) != 0
+                            || mta_sts_policy.is_some()
+                            || dane_policy.is_some();
+                        let pki_verify =
+                            !(allow_invalid_certs || remote_host.allow_invalid_certs());
+                        let max_messages = if remote_host.credentials().is_none() {
+                            *queue_config
+                                .connection_reuse
+                                .max_messages
+                                .eval(&envelope)
+                                .await
                         } else {
-                            SmtpClient::connect(
-                                SocketAddr::new(remote_ip, remote_host.port()),
-                                *queue_config.timeout.connect.eval(&envelope).await,
-                            )
-                            .await
-                        } {
+                            1
+                        };
+                        let max_age = *queue_config.connection_reuse.max_age.eval(&envelope).await;
+                        if max_messages > 1 {
+                            let key = ConnectionKey {
+                                hostname: envelope.mx.to_string(),
+                                remote_ip,
+                                port: remote_host.port(),
+                            };
+                            if let Some(mut connection) =
+                                core.queue.connections.checkout(&key, |connection| {
+                                    connection.is_compatible(
+                                        is_strict_tls,
+                                        dane_policy.is_some(),
+                                        pki_verify,
+                                    )
+                                })
+                            {
+                                if connection
+                                    .reset(*queue_config.timeout.mail.eval(&envelope).await)
+                                    .await
+                                {
+                                    tracing::debug!(
+                                        parent: &span,
+                                        context = "connect",
+                                        event = "reuse",
+                                        mx = envelope.mx,
+                                        remote_ip = %remote_ip,
+                                        messages = connection.info.messages,
+                                    );
+
+                                    envelope.local_ip = connection.info.local_ip;
+                                    if let SmtpConnection::Tls(smtp_client) =
+                                        &connection.smtp_client
+                                    {
+                                        attempt.set_tls(&smtp_client.stream);
+                                    }
+
+                                    // Report each reused session as a new connection would
+                                    let tls_result = if connection.is_tls() {
+                                        Some(None)
+                                    } else if tls_strategy.try_start_tls() && !domain.disable_tls {
+                                        Some(
+                                            FailureDetails::new(ResultType::StartTlsNotSupported)
+                                                .with_receiving_mx_hostname(envelope.mx)
+                                                .with_receiving_ip(remote_ip)
+                                                .with_failure_reason_code(
+                                                    "STARTTLS was not advertised by host",
+                                                )
+                                                .into(),
+                                        )
+                                    } else {
+                                        None
+                                    };
+                                    if let (Some(tls_report), Some(failure)) =
+                                        (&tls_report, tls_result)
+                                    {
+                                        core.schedule_report(TlsEvent {
+                                            policy: (&mta_sts_policy, &dane_policy).into(),
+                                            domain: envelope.domain.to_string(),
+                                            failure,
+                                            tls_record: tls_report.record.clone(),
+                                            interval: tls_report.interval,
+                                        })
+                                        .await;
+                                    }
+                                    let params = SessionParams {
+                                        span: &span,
+                                        credentials: None,
+                                        is_smtp: remote_host.is_smtp(),
+                                        hostname: envelope.mx,
+                                        local_hostname: queue_config.hostname.eval(&envelope).await,
+                                        timeout_ehlo: *queue_config
+                                            .timeout
+                                            .ehlo
+                                            .eval(&envelope)
+                                            .await,
+                                        timeout_mail: *queue_config
+                                            .timeout
+                                            .mail
+                                            .eval(&envelope)
+                                            .await,
+                                        timeout_rcpt: *queue_config
+                                            .timeout
+                                            .rcpt
+                                            .eval(&envelope)
+                                            .await,
+                                        timeout_data: *queue_config
+                                            .timeout
+                                            .data
+                                            .eval(&envelope)
+                                            .await,
+                                        max_rcpt: *queue_config.max_rcpt.eval(&envelope).await,
+                                        reuse: Some(ConnectionReuse {
+                                            pool: &core.queue.connections,
+                                            key,
+                                            info: connection.info.clone(),
+                                            max_messages,
+                                            max_age,
+                                            idle_timeout: queue_config
+                                                .connection_reuse
+                                                .idle_timeout,
+                                        }),
+                                    };
+                                    let delivery_result = connection
+                                        .deliver(
+                                            &self.message,
+                                            recipients
+                                                .iter_mut()
+                                                .filter(|r| batch.contains(&r.domain_idx)),
+                                            params,
+                                        )
+                                        .await;
+
+                                    // Update status for the current domain and continue with the next one
+                                    domain.set_status(
+                                        delivery_result,
+                                        &queue_config.retry_policy(&envelope).await,
+                                    );
+                                    continue 'next_domain;
+                                } else {
+                                    tracing::debug!(
+                                        parent: &span,
+                                        context = "connect",
+                                        event = "stale",
+                                        mx = envelope.mx,
+                                        remote_ip = %remote_ip,
+                                        "Idle connection is no longer usable."
+                                    );
+                                }
+                            }
+                        }
+
+                        // Connect, racing the next address of the other family if
+                        // the first one does not answer in time
+                        let connect_timeout = *queue_config.timeout.connect.eval(&envelope).await;
+                        let primary = ConnectTarget {
+                            remote_ip,
+                            source_ip,
+                            port: remote_host.port(),
+                        };
+                        let fallback = if let Some(delay) =
+                            *queue_config.happy_eyeballs.delay.eval(&envelope).await
+                        {
+                            resolve_result.remote_ips[ip_pos + 1..]
+                                .iter()
+                                .find(|ip| {
+                                    ip.is_ipv6() != remote_ip.is_ipv6() && !raced_ips.contains(*ip)
+                                })
+                                .map(|ip| {
+                                    (
+                                        delay,
+                                        ConnectTarget {
+                                            remote_ip: *ip,
+                                            source_ip: resolve_result.source_ip(*ip),
+                                            port: remote_host.port(),
+                                        },
+                                    )
+                                })
+                        } else {
+                            None
+                        };
+                        let (result, used_fallback) = if let Some((delay, fallback)) = &fallback {
+                            raced_ips.push(fallback.remote_ip);
+                            connect_race(&primary, fallback, *delay, connect_timeout).await
+                        } else {
+                            (primary.connect(connect_timeout).await, false)
+                        };
+                        if remote_ip.is_ipv6() && (used_fallback || result.is_err()) {
+                            ipv6_failed = true;
+                        }
+                        if let (Some((_, fallback)), true) = (&fallback, used_fallback) {
+                            tracing::debug!(
+                                parent: &span,
+                                context = "connect",
+                                event = "fallback",
+                                mx = envelope.mx,
+                                remote_ip = %remote_ip,
+                                fallback_ip = %fallback.remote_ip,
+                            );
+
+                            remote_ip = fallback.remote_ip;
+                            source_ip = fallback.source_ip;
+                            envelope.local_ip = source_ip.unwrap_or(no_ip);
+                            envelope.remote_ip = remote_ip;
+                            attempt.remote_ip = remote_ip.to_string();
+                        }
+                        let mut smtp_client = match result {
                             Ok(smtp_client) => {
                                 tracing::debug!(
                                     parent: &span,
@@ -605,6 +906,8 @@ impl DeliveryAttempt {
                                     remote_ip = %remote_ip,
                                     remote_port = remote_host.port(),
                                 );
+                                core.queue
+                                    .record_connection(envelope.mx, remote_ip, ipv6_failed);
 
                                 smtp_client
                             }
@@ -622,7 +925,7 @@ impl DeliveryAttempt {
                         };
 
                         // Obtail session parameters
-                        let params = SessionParams {
+                        let mut params = SessionParams {
                             span: &span,
                             credentials: remote_host.credentials(),
                             is_smtp: remote_host.is_smtp(),
@@ -632,19 +935,33 @@ impl DeliveryAttempt {
                             timeout_mail: *queue_config.timeout.mail.eval(&envelope).await,
                             timeout_rcpt: *queue_config.timeout.rcpt.eval(&envelope).await,
                             timeout_data: *queue_config.timeout.data.eval(&envelope).await,
+                            max_rcpt: *queue_config.max_rcpt.eval(&envelope).await,
+                            reuse: (max_messages > 1).then(|| ConnectionReuse {
+                                pool: &core.queue.connections,
+                                key: ConnectionKey {
+                                    hostname: envelope.mx.to_string(),
+                                    remote_ip,
+                                    port: remote_host.port(),
+                                },
+                                info: ConnectionInfo {
+                                    local_ip: envelope.local_ip,
+                                    pki_verified: pki_verify,
+                                    dane_verified: dane_policy.is_some(),
+                                    messages: 0,
+                                    created: Instant::now(),
+                                },
+                                max_messages,
+                                max_age,
+                                idle_timeout: queue_config.connection_reuse.idle_timeout,
+                            }),
                         };
 
                         // Prepare TLS connector
-                        let is_strict_tls = tls_strategy.is_tls_required()
-                            || (self.message.flags & MAIL_REQUIRETLS) != 0
-                            || mta_sts_policy.is_some()
-                            || dane_policy.is_some();
-                        let tls_connector =
-                            if allow_invalid_certs || remote_host.allow_invalid_certs() {
-                                &core.queue.connectors.dummy_verify
-                            } else {
-                                &core.queue.connectors.pki_verify
-                            };
+                        let tls_connector = if pki_verify {
+                            &core.queue.connectors.pki_verify
+                        } else {
+                            &core.queue.connectors.dummy_verify
+                        };
 
                         let delivery_result = if !remote_host.implicit_tls() {
                             // Read greeting
@@ -666,7 +983,7 @@ impl DeliveryAttempt {
 
                             // Say EHLO
                             let capabilties = match say_helo(&mut smtp_client, &params).await {
-                                Ok(capabilities) => capabilities,
+                                Ok((capabilities, _)) => capabilities,
                                 Err(status) => {
                                     tracing::info!(
                                         parent: &span,
@@ -702,8 +1019,10 @@ impl DeliveryAttempt {
                                             protocol = ?smtp_client.tls_connection().protocol_version(),
                                             cipher = ?smtp_client.tls_connection().negotiated_cipher_suite(),
                                         );
+                                        attempt.set_tls(&smtp_client.stream);
 
                                         // Verify DANE
+                                        let mut dane_failed = false;
                                         if let Some(dane_policy) = &dane_policy {
                                             if let Err(status) = dane_policy.verify(
                                                 &span,
@@ -730,13 +1049,46 @@ impl DeliveryAttempt {
                                                     .await;
                                                 }
 
-                                                last_status = status;
-                                                continue 'next_host;
+                                                match *queue_config
+                                                    .tls
+                                                    .dane_failure
+                                                    .eval(&envelope)
+                                                    .await
+                                                {
+                                                    DaneFailurePolicy::Bounce => {
+                                                        last_status = status;
+                                                        continue 'next_host;
+                                                    }
+                                                    DaneFailurePolicy::Defer => {
+                                                        last_status = match status {
+                                                            Status::PermanentFailure(err) => {
+                                                                Status::TemporaryFailure(err)
+                                                            }
+                                                            status => status,
+                                                        };
+                                                        continue 'next_host;
+                                                    }
+                                                    DaneFailurePolicy::Deliver => {
+                                                        tracing::info!(
+                                                            parent: &span,
+                                                            context = "dane",
+                                                            event = "deliver-unauthenticated",
+                                                            mx = envelope.mx,
+                                                            "DANE authentication failed, delivering without authentication."
+                                                        );
+                                                        dane_failed = true;
+                                                        if let Some(reuse) = &mut params.reuse {
+                                                            reuse.info.dane_verified = false;
+                                                        }
+                                                    }
+                                                }
                                             }
                                         }
 
-                                        // Report TLS success
-                                        if let Some(tls_report) = &tls_report {
+                                        // Report TLS success unless a DANE failure was reported
+                                        if let Some(tls_report) =
+                                            tls_report.as_ref().filter(|_| !dane_failed)
+                                        {
                                             core.schedule_report(TlsEvent {
                                                 policy: (&mta_sts_policy, &dane_policy).into(),
                                                 domain: envelope.domain.to_string(),
@@ -753,7 +1105,7 @@ impl DeliveryAttempt {
                                                 smtp_client,
                                                 recipients
                                                     .iter_mut()
-                                                    .filter(|r| r.domain_idx == domain_idx),
+                                                    .filter(|r| batch.contains(&r.domain_idx)),
                                                 params,
                                             )
                                             .await
@@ -806,7 +1158,7 @@ impl DeliveryAttempt {
                                                     smtp_client,
                                                     recipients
                                                         .iter_mut()
-                                                        .filter(|r| r.domain_idx == domain_idx),
+                                                        .filter(|r| batch.contains(&r.domain_idx)),
                                                     params,
                                                 )
                                                 .await
@@ -866,7 +1218,7 @@ impl DeliveryAttempt {
                                         smtp_client,
                                         recipients
                                             .iter_mut()
-                                            .filter(|r| r.domain_idx == domain_idx),
+                                            .filter(|r| batch.contains(&r.domain_idx)),
                                         params,
                                     )
                                     .await
@@ -876,7 +1228,10 @@ impl DeliveryAttempt {
                             smtp_client.timeout = *queue_config.timeout.tls.eval(&envelope).await;
                             let mut smtp_client =
                                 match smtp_client.into_tls(tls_connector, envelope.mx).await {
-                                    Ok(smtp_client) => smtp_client,
+                                    Ok(smtp_client) => {
+                                        attempt.set_tls(&smtp_client.stream);
+                                        smtp_client
+                                    }
                                     Err(error) => {
                                         tracing::info!(
                                             parent: &span,
@@ -912,28 +1267,77 @@ impl DeliveryAttempt {
                             self.message
                                 .deliver(
                                     smtp_client,
-                                    recipients.iter_mut().filter(|r| r.domain_idx == domain_idx),
+                                    recipients
+                                        .iter_mut()
+                                        .filter(|r| batch.contains(&r.domain_idx)),
                                     params,
                                 )
                                 .await
                         };
 
                         // Update status for the current domain and continue with the next one
-                        domain
-                            .set_status(delivery_result, queue_config.retry.eval(&envelope).await);
+                        domain.set_status(
+                            delivery_result,
+                            &queue_config.retry_policy(&envelope).await,
+                        );
                         continue 'next_domain;
                     }
                 }
 
                 // Update status
                 domain.disable_tls = disable_tls;
-                domain.set_status(last_status, queue_config.retry.eval(&envelope).await);
+                domain.set_status(last_status, &queue_config.retry_policy(&envelope).await);
+            }
+
+            // Batched domains share the outcome of the first domain of their batch
+            for (domain_idx, leader_idx) in batches.iter().enumerate() {
+                if let Some(leader_idx) = *leader_idx {
+                    let status = domains[leader_idx].status.clone();
+                    let disable_tls = domains[leader_idx].disable_tls;
+                    let domain = &mut domains[domain_idx];
+                    let envelope = QueueEnvelope {
+                        message: self.message.as_ref(),
+                        domain: &domain.domain,
+                        mx: "",
+                        remote_ip: no_ip,
+                        local_ip: no_ip,
+                    };
+                    let retry = queue_config.retry_policy(&envelope).await;
+                    domain.disable_tls = disable_tls;
+                    domain.set_status(status, &retry);
+
+                    if let Some(entry) = history
+                        .iter()
+                        .find(|entry| entry.domain_idx == leader_idx)
+                        .cloned()
+                    {
+                        history.push(HistoryEntry {
+                            domain_idx,
+                            ..entry
+                        });
+                    }
+                }
+            }
+            drop(batch_in_flight);
+
+            // Record the outcome of each attempt
+            for mut entry in history {
+                if entry.set_status(&domains[entry.domain_idx], &recipients) {
+                    core.tracking
+                        .track_attempt(&self.message, &entry, &recipients);
+                    self.message.history.push(entry);
+                }
             }
             self.message.domains = domains;
             self.message.recipients = recipients;
 
-            // Send Delivery Status Notifications
-            core.queue.send_dsn(&mut self).await;
+            // Publish delivery events and send Delivery Status Notifications
+            core.anomaly.record_outcomes(&mut self.message).await;
+            core.suppression.record_outcomes(&mut self.message).await;
+            core.webhook.publish_delivery_status(&mut self.message);
+            if let Some(dsn_id) = core.queue.send_dsn(&mut self).await {
+                core.tracking.track_dsn(&self.message, dsn_id);
+            }
 
             // Notify queue manager
             let span = self.span;
@@ -953,7 +1357,7 @@ impl DeliveryAttempt {
                 );
 
                 WorkerResult::OnHold(OnHold {
-                    next_due: self.message.next_event_after(Instant::now()),
+                    next_due: self.message.next_event_after(Timestamp::now()),
                     limiters: on_hold,
                     message: self.message,
                 })
@@ -1000,7 +1404,7 @@ impl DeliveryAttempt {
 
     /// Marks as failed all domains that reached their expiration time
     pub fn has_pending_delivery(&mut self) -> bool {
-        let now = Instant::now();
+        let now = Timestamp::now();
         let mut has_pending_delivery = false;
         let span = self.span.clone();
 
@@ -1056,21 +1460,84 @@ impl DeliveryAttempt {
     }
 }
 
+impl HistoryEntry {
+    pub fn new(domain_idx: usize) -> Self {
+        HistoryEntry {
+            domain_idx,
+            time: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            mx: String::new(),
+            remote_ip: String::new(),
+            tls: String::new(),
+            status: Status::Scheduled,
+            changed: true,
+        }
+    }
+
+    fn set_tls(&mut self, stream: &impl IsTls) {
+        let (version, cipher) = stream.tls_version_and_cipher();
+        self.tls = format!("{version} {cipher}");
+    }
+
+    // Returns false when no delivery was attempted (e.g. throttled domains)
+    fn set_status(&mut self, domain: &Domain, recipients: &[Recipient]) -> bool {
+        self.status = match &domain.status {
+            Status::Completed(_) => {
+                let mut status = Status::Completed(String::new());
+                for rcpt in recipients
+                    .iter()
+                    .filter(|rcpt| rcpt.domain_idx == self.domain_idx)
+                {
+                    match &rcpt.status {
+                        Status::Completed(response) => {
+                            status = Status::Completed(response.response.to_string());
+                            break;
+                        }
+                        Status::TemporaryFailure(response)
+                            if matches!(status, Status::Completed(_)) =>
+                        {
+                            status = Status::TemporaryFailure(response.response.to_string());
+                        }
+                        Status::PermanentFailure(response)
+                            if matches!(status, Status::Completed(_)) =>
+                        {
+                            status = Status::PermanentFailure(response.response.to_string());
+                        }
+                        _ => (),
+                    }
+                }
+                status
+            }
+            Status::TemporaryFailure(Error::ConcurrencyLimited | Error::RateLimited)
+            | Status::Scheduled => return false,
+            Status::TemporaryFailure(err) => Status::TemporaryFailure(err.to_string()),
+            Status::PermanentFailure(err) => Status::PermanentFailure(err.to_string()),
+        };
+        true
+    }
+}
+
 impl Domain {
-    pub fn set_status(&mut self, status: impl Into<Status<(), Error>>, schedule: &[Duration]) {
-        self.status = status.into();
+    pub fn set_status(&mut self, status: impl Into<Status<(), Error>>, retry: &RetryPolicy) {
+        let status = status.into();
+        if status.retry_class() != self.status.retry_class() {
+            // Start the schedule of the new class from its first interval
+            self.class_attempt = 0;
+        }
+        self.status = status;
         self.changed = true;
         if matches!(
             &self.status,
             Status::TemporaryFailure(_) | Status::Scheduled
         ) {
-            self.retry(schedule);
+            self.retry(retry);
         }
     }
 
-    pub fn retry(&mut self, schedule: &[Duration]) {
-        self.retry.due =
-            Instant::now() + schedule[std::cmp::min(self.retry.inner as usize, schedule.len() - 1)];
+    pub fn retry(&mut self, retry: &RetryPolicy) {
+        self.retry.due = Timestamp::now() + retry.interval(&self.status, self.class_attempt);
         self.retry.inner += 1;
+        self.class_attempt += 1;
     }
 }
@@ -0,0 +1,120 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use mail_send::SmtpClient;
+use tokio::net::TcpStream;
+
+use crate::core::QueueCore;
+
+pub struct ConnectTarget {
+    pub remote_ip: IpAddr,
+    pub source_ip: Option<IpAddr>,
+    pub port: u16,
+}
+
+impl ConnectTarget {
+    pub async fn connect(&self, timeout: Duration) -> mail_send::Result<SmtpClient<TcpStream>> {
+        let remote_addr = SocketAddr::new(self.remote_ip, self.port);
+        if let Some(source_ip) = self.source_ip {
+            SmtpClient::connect_using(source_ip, remote_addr, timeout).await
+        } else {
+            SmtpClient::connect(remote_addr, timeout).await
+        }
+    }
+}
+
+// Connects to the primary address and, if it has not succeeded after the
+// connection attempt delay (RFC 8305), races it against the fallback address.
+// Returns the winning connection and whether it was made to the fallback address.
+pub async fn connect_race(
+    primary: &ConnectTarget,
+    fallback: &ConnectTarget,
+    delay: Duration,
+    timeout: Duration,
+) -> (mail_send::Result<SmtpClient<TcpStream>>, bool) {
+    let primary_conn = primary.connect(timeout);
+    tokio::pin!(primary_conn);
+
+    let primary_err = match tokio::time::timeout(delay, &mut primary_conn).await {
+        Ok(Ok(smtp_client)) => return (Ok(smtp_client), false),
+        Ok(Err(err)) => Some(err),
+        Err(_) => None,
+    };
+
+    let fallback_conn = fallback.connect(timeout);
+    tokio::pin!(fallback_conn);
+
+    if let Some(err) = primary_err {
+        // The primary address failed before the delay expired
+        return match fallback_conn.await {
+            Ok(smtp_client) => (Ok(smtp_client), true),
+            Err(_) => (Err(err), false),
+        };
+    }
+
+    // Whichever attempt finishes first wins, unless it failed
+    let first_result = tokio::select! {
+        result = &mut primary_conn => Ok(result),
+        result = &mut fallback_conn => Err(result),
+    };
+    match first_result {
+        Ok(Ok(smtp_client)) => (Ok(smtp_client), false),
+        Ok(Err(err)) => match fallback_conn.await {
+            Ok(smtp_client) => (Ok(smtp_client), true),
+            Err(_) => (Err(err), false),
+        },
+        Err(Ok(smtp_client)) => (Ok(smtp_client), true),
+        Err(Err(_)) => (primary_conn.await, false),
+    }
+}
+
+impl QueueCore {
+    // Hosts with a recently broken IPv6 path are contacted over IPv4 first
+    pub fn prefer_ipv4(&self, hostname: &str) -> bool {
+        if let Some(until) = self.ipv6_fallback.get(hostname) {
+            if *until > Instant::now() {
+                return true;
+            }
+        } else {
+            return false;
+        }
+        self.ipv6_fallback.remove(hostname);
+        false
+    }
+
+    pub fn record_connection(&self, hostname: &str, remote_ip: IpAddr, ipv6_failed: bool) {
+        if remote_ip.is_ipv6() {
+            self.ipv6_fallback.remove(hostname);
+        } else if ipv6_failed && !self.config.happy_eyeballs.fallback_ttl.is_zero() {
+            self.ipv6_fallback.insert(
+                hostname.to_string(),
+                Instant::now() + self.config.happy_eyeballs.fallback_ttl,
+            );
+        }
+    }
+}
@@ -27,6 +27,7 @@ use utils::ipc::{DeliveryEvent, DeliveryResult, IngestMessage};
 
 use crate::queue::{
     Error, ErrorDetails, HostResponse, Message, Recipient, Status, RCPT_STATUS_CHANGED,
+    RCPT_SUPPRESSED,
 };
 
 impl Message {
@@ -118,6 +119,26 @@ impl Message {
                     });
                     total_completed += 1;
                 }
+                DeliveryResult::Suppressed { reason } => {
+                    tracing::info!(
+                        parent: span,
+                        context = "deliver_local",
+                        event = "suppressed",
+                        rcpt = rcpt.address,
+                        reason = reason.as_ref(),
+                    );
+
+                    rcpt.flags |= RCPT_SUPPRESSED;
+                    rcpt.status = Status::Completed(HostResponse {
+                        hostname: "localhost".to_string(),
+                        response: Response {
+                            code: 250,
+                            esc: [2, 1, 5],
+                            message: reason.into_owned(),
+                        },
+                    });
+                    total_completed += 1;
+                }
                 DeliveryResult::TemporaryFailure { reason } => {
                     tracing::info!(
                         parent: span,
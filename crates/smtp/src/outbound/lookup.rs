@@ -28,8 +28,8 @@ use rand::{seq::SliceRandom, Rng};
 use utils::config::KeyLookup;
 
 use crate::{
-    config::EnvelopeKey,
-    core::SMTP,
+    config::{DnsOverride, EnvelopeKey},
+    core::{Resolvers, SMTP},
     queue::{Error, ErrorDetails, Status},
 };
 
@@ -41,7 +41,29 @@ pub struct IpLookupResult {
     pub remote_ips: Vec<IpAddr>,
 }
 
+impl IpLookupResult {
+    pub fn source_ip(&self, remote_ip: IpAddr) -> Option<IpAddr> {
+        if remote_ip.is_ipv4() {
+            self.source_ipv4
+        } else {
+            self.source_ipv6
+        }
+    }
+}
+
 impl SMTP {
+    pub async fn mx_lookup(&self, domain: &str) -> mail_auth::Result<Arc<Vec<MX>>> {
+        if let Some(dns_override) = self.resolvers.dns_override(domain) {
+            if let Some(mx) = &dns_override.mx {
+                return Ok(mx.clone());
+            } else if let Some(resolver) = &dns_override.resolver {
+                return resolver.mx_lookup(domain).await;
+            }
+        }
+
+        self.resolvers.dns.mx_lookup(domain).await
+    }
+
     pub async fn ip_lookup(
         &self,
         key: &str,
@@ -54,8 +76,26 @@ impl SMTP {
             IpLookupStrategy::Ipv4thenIpv6 => (true, true, true),
             IpLookupStrategy::Ipv6thenIpv4 => (true, true, false),
         };
+
+        // Static host entries take precedence over DNS
+        if let Some(ips) = self.resolvers.host_override(key) {
+            let mut ips = ips
+                .iter()
+                .filter(|ip| if ip.is_ipv4() { has_ipv4 } else { has_ipv6 })
+                .copied()
+                .collect::<Vec<_>>();
+            ips.sort_by_key(|ip| ip.is_ipv4() != v4_first);
+            ips.truncate(max_results);
+            return Ok(ips);
+        }
+
+        let resolver = self
+            .resolvers
+            .dns_override(key)
+            .and_then(|dns_override| dns_override.resolver.as_ref())
+            .unwrap_or(&self.resolvers.dns);
         let ipv4_addrs = if has_ipv4 {
-            match self.resolvers.dns.ipv4_lookup(key).await {
+            match resolver.ipv4_lookup(key).await {
                 Ok(addrs) => addrs,
                 Err(_) if has_ipv6 => Arc::new(Vec::new()),
                 Err(err) => return Err(err),
@@ -65,7 +105,7 @@ impl SMTP {
         };
 
         if has_ipv6 {
-            let ipv6_addrs = match self.resolvers.dns.ipv6_lookup(key).await {
+            let ipv6_addrs = match resolver.ipv6_lookup(key).await {
                 Ok(addrs) => addrs,
                 Err(_) if !ipv4_addrs.is_empty() => Arc::new(Vec::new()),
                 Err(err) => return Err(err),
@@ -103,7 +143,7 @@ impl SMTP {
         envelope: &impl KeyLookup<Key = EnvelopeKey>,
         max_multihomed: usize,
     ) -> Result<IpLookupResult, Status<(), Error>> {
-        let remote_ips = self
+        let mut remote_ips = self
             .ip_lookup(
                 remote_host.fqdn_hostname().as_ref(),
                 *self.queue.config.ip_strategy.eval(envelope).await,
@@ -125,6 +165,11 @@ impl SMTP {
             })?;
 
         if !remote_ips.is_empty() {
+            // Try IPv4 first on hosts where IPv6 recently had to fall back
+            if self.queue.prefer_ipv4(remote_host.hostname()) {
+                remote_ips.sort_by_key(|ip| !ip.is_ipv4());
+            }
+
             let mut result = IpLookupResult {
                 source_ipv4: None,
                 source_ipv6: None,
@@ -169,6 +214,42 @@ impl SMTP {
     }
 }
 
+impl Resolvers {
+    // Returns the first override that applies to a domain name
+    pub fn dns_override(&self, domain: &str) -> Option<&DnsOverride> {
+        if self.overrides.is_empty() {
+            return None;
+        }
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        self.overrides
+            .iter()
+            .find(|dns_override| dns_override.matches(&domain))
+    }
+
+    pub fn host_override(&self, host: &str) -> Option<&[IpAddr]> {
+        if self.overrides.is_empty() {
+            return None;
+        }
+        let host = host.trim_end_matches('.').to_lowercase();
+        self.overrides
+            .iter()
+            .find_map(|dns_override| dns_override.hosts.get(&host))
+            .map(|ips| ips.as_slice())
+    }
+}
+
+impl DnsOverride {
+    pub fn matches(&self, domain: &str) -> bool {
+        self.domains.iter().any(|entry| {
+            if entry.starts_with('.') {
+                domain.ends_with(entry.as_str())
+            } else {
+                domain == entry
+            }
+        })
+    }
+}
+
 pub trait ToNextHop {
     fn to_remote_hosts<'x, 'y: 'x>(
         &'x self,
@@ -192,6 +273,9 @@ impl ToNextHop for Vec<MX> {
                     let mut slice = mx.exchanges.iter().collect::<Vec<_>>();
                     slice.shuffle(&mut rand::thread_rng());
                     for remote_host in slice {
+                        if is_null_mx(remote_host) {
+                            continue;
+                        }
                         remote_hosts.push(NextHop::MX(remote_host.as_str()));
                         if remote_hosts.len() == max_mx {
                             break 'outer;
@@ -199,8 +283,11 @@ impl ToNextHop for Vec<MX> {
                     }
                 } else if let Some(remote_host) = mx.exchanges.first() {
                     // Check for Null MX
-                    if mx.preference == 0 && remote_host == "." {
-                        return None;
+                    if is_null_mx(remote_host) {
+                        if self.len() == 1 {
+                            return None;
+                        }
+                        continue;
                     }
                     remote_hosts.push(NextHop::MX(remote_host.as_str()));
                     if remote_hosts.len() == max_mx {
@@ -208,7 +295,11 @@ impl ToNextHop for Vec<MX> {
                     }
                 }
             }
-            remote_hosts.into()
+            if !remote_hosts.is_empty() {
+                remote_hosts.into()
+            } else {
+                None
+            }
         } else {
             // If an empty list of MXs is returned, the address is treated as if it was
             // associated with an implicit MX RR with a preference of 0, pointing to that host.
@@ -216,3 +307,28 @@ impl ToNextHop for Vec<MX> {
         }
     }
 }
+
+#[inline(always)]
+fn is_null_mx(host: &str) -> bool {
+    host.is_empty() || host == "."
+}
+
+pub fn is_valid_mx(host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    let literal = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    let literal = literal
+        .strip_prefix("IPv6:")
+        .or_else(|| literal.strip_prefix("ipv6:"))
+        .unwrap_or(literal);
+
+    // MX targets must be hostnames (RFC 5321 section 5.1), not IP literals or loopback names
+    !host.is_empty()
+        && literal.parse::<IpAddr>().is_err()
+        && !host.eq_ignore_ascii_case("localhost")
+        && !host
+            .rsplit_once('.')
+            .is_some_and(|(_, tld)| tld.eq_ignore_ascii_case("localhost"))
+}
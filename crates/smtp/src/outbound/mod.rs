@@ -32,13 +32,17 @@ use crate::{
     queue::{DeliveryAttempt, Error, ErrorDetails, HostResponse, Message, Status},
 };
 
+pub mod batch;
 pub mod dane;
 pub mod delivery;
+pub mod happy_eyeballs;
 #[cfg(feature = "local_delivery")]
 pub mod local;
 pub mod lookup;
 pub mod mta_sts;
+pub mod pool;
 pub mod session;
+pub mod transport;
 
 impl Status<(), Error> {
     pub fn from_smtp_error(hostname: &str, command: &str, err: mail_send::Error) -> Self {
@@ -0,0 +1,273 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+use mail_send::{smtp::AssertReply, SmtpClient};
+use smtp_proto::EhloResponse;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, UnixStream},
+};
+use tokio_rustls::client::TlsStream;
+
+use crate::queue::{Error, Message, Recipient, Status};
+
+use super::session::{quit, ServerLimits, SessionParams};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConnectionKey {
+    pub hostname: String,
+    pub remote_ip: IpAddr,
+    pub port: u16,
+}
+
+pub enum SmtpConnection {
+    Plain(SmtpClient<TcpStream>),
+    Tls(SmtpClient<TlsStream<TcpStream>>),
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub local_ip: IpAddr,
+    pub pki_verified: bool,
+    pub dane_verified: bool,
+    pub messages: usize,
+    pub created: Instant,
+}
+
+pub struct IdleConnection {
+    pub id: u64,
+    pub smtp_client: SmtpConnection,
+    pub capabilities: EhloResponse<String>,
+    pub limits: ServerLimits,
+    pub info: ConnectionInfo,
+}
+
+#[derive(Default)]
+pub struct ConnectionPool {
+    connections: DashMap<ConnectionKey, Vec<IdleConnection>>,
+    id_seq: AtomicU64,
+}
+
+pub struct ConnectionReuse<'x> {
+    pub pool: &'x Arc<ConnectionPool>,
+    pub key: ConnectionKey,
+    pub info: ConnectionInfo,
+    pub max_messages: usize,
+    pub max_age: Duration,
+    pub idle_timeout: Duration,
+}
+
+pub trait ReusableStream: AsyncRead + AsyncWrite + Unpin + Sized {
+    fn into_connection(smtp_client: SmtpClient<Self>) -> Result<SmtpConnection, SmtpClient<Self>>;
+}
+
+impl ConnectionPool {
+    // Takes the most recently used idle connection that satisfies the filter
+    pub fn checkout(
+        &self,
+        key: &ConnectionKey,
+        filter: impl Fn(&IdleConnection) -> bool,
+    ) -> Option<IdleConnection> {
+        let mut connections = self.connections.get_mut(key)?;
+        let pos = connections.iter().rposition(filter)?;
+        Some(connections.remove(pos))
+    }
+
+    pub fn release(
+        self: &Arc<Self>,
+        key: ConnectionKey,
+        connection: IdleConnection,
+        idle_timeout: Duration,
+    ) {
+        let id = connection.id;
+        self.connections
+            .entry(key.clone())
+            .or_default()
+            .push(connection);
+
+        // Close the connection if it is not used again within the idle timeout
+        let pool = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(idle_timeout).await;
+            if let Some(connection) = pool.take(&key, id) {
+                connection.quit().await;
+            }
+        });
+    }
+
+    fn take(&self, key: &ConnectionKey, id: u64) -> Option<IdleConnection> {
+        let connection = {
+            let mut connections = self.connections.get_mut(key)?;
+            let pos = connections.iter().position(|c| c.id == id)?;
+            connections.swap_remove(pos)
+        };
+        self.connections
+            .remove_if(key, |_, connections| connections.is_empty());
+        Some(connection)
+    }
+
+    pub fn len(&self) -> usize {
+        self.connections
+            .iter()
+            .map(|connections| connections.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl IdleConnection {
+    pub fn is_tls(&self) -> bool {
+        matches!(self.smtp_client, SmtpConnection::Tls(_))
+    }
+
+    // Returns true if the connection provides the protections required by the delivery
+    pub fn is_compatible(&self, require_tls: bool, require_dane: bool, require_pki: bool) -> bool {
+        (!require_tls || self.is_tls())
+            && (!require_dane || self.info.dane_verified)
+            && (!require_pki || self.info.pki_verified)
+    }
+
+    // Resets the session state, which also verifies that the connection is still alive
+    pub async fn reset(&mut self, timeout: Duration) -> bool {
+        match &mut self.smtp_client {
+            SmtpConnection::Plain(smtp_client) => reset(smtp_client, timeout).await,
+            SmtpConnection::Tls(smtp_client) => reset(smtp_client, timeout).await,
+        }
+    }
+
+    pub async fn deliver(
+        self,
+        message: &Message,
+        recipients: impl Iterator<Item = &mut Recipient>,
+        params: SessionParams<'_>,
+    ) -> Status<(), Error> {
+        match self.smtp_client {
+            SmtpConnection::Plain(smtp_client) => {
+                message
+                    .deliver_transaction(
+                        smtp_client,
+                        self.capabilities,
+                        self.limits,
+                        recipients,
+                        params,
+                    )
+                    .await
+            }
+            SmtpConnection::Tls(smtp_client) => {
+                message
+                    .deliver_transaction(
+                        smtp_client,
+                        self.capabilities,
+                        self.limits,
+                        recipients,
+                        params,
+                    )
+                    .await
+            }
+        }
+    }
+
+    pub async fn quit(self) {
+        match self.smtp_client {
+            SmtpConnection::Plain(smtp_client) => quit(smtp_client).await,
+            SmtpConnection::Tls(smtp_client) => quit(smtp_client).await,
+        }
+    }
+}
+
+impl ConnectionReuse<'_> {
+    // Returns the connection to the pool unless it reached its limits
+    pub async fn release<T: ReusableStream>(
+        mut self,
+        smtp_client: SmtpClient<T>,
+        capabilities: EhloResponse<String>,
+        limits: ServerLimits,
+        transactions: usize,
+    ) {
+        self.info.messages += transactions;
+        if self.info.messages >= self.max_messages
+            || (limits.mail_max > 0 && self.info.messages >= limits.mail_max)
+            || self.info.created.elapsed() >= self.max_age
+        {
+            quit(smtp_client).await;
+            return;
+        }
+
+        match T::into_connection(smtp_client) {
+            Ok(smtp_client) => {
+                let connection = IdleConnection {
+                    id: self.pool.id_seq.fetch_add(1, Ordering::Relaxed),
+                    smtp_client,
+                    capabilities,
+                    limits,
+                    info: self.info,
+                };
+                self.pool.release(self.key, connection, self.idle_timeout);
+            }
+            Err(smtp_client) => quit(smtp_client).await,
+        }
+    }
+}
+
+async fn reset<T: AsyncRead + AsyncWrite + Unpin>(
+    smtp_client: &mut SmtpClient<T>,
+    timeout: Duration,
+) -> bool {
+    smtp_client.timeout = timeout;
+    smtp_client
+        .cmd(b"RSET\r\n")
+        .await
+        .and_then(|r| r.assert_positive_completion())
+        .is_ok()
+}
+
+impl ReusableStream for TcpStream {
+    fn into_connection(smtp_client: SmtpClient<Self>) -> Result<SmtpConnection, SmtpClient<Self>> {
+        Ok(SmtpConnection::Plain(smtp_client))
+    }
+}
+
+impl ReusableStream for TlsStream<TcpStream> {
+    fn into_connection(smtp_client: SmtpClient<Self>) -> Result<SmtpConnection, SmtpClient<Self>> {
+        Ok(SmtpConnection::Tls(smtp_client))
+    }
+}
+
+impl ReusableStream for UnixStream {
+    fn into_connection(smtp_client: SmtpClient<Self>) -> Result<SmtpConnection, SmtpClient<Self>> {
+        Err(smtp_client)
+    }
+}
@@ -23,9 +23,9 @@
 
 use mail_send::{smtp::AssertReply, Credentials, SmtpClient};
 use smtp_proto::{
-    EhloResponse, Response, Severity, EXT_CHUNKING, EXT_DSN, EXT_REQUIRE_TLS, EXT_SIZE,
-    EXT_SMTP_UTF8, EXT_START_TLS, MAIL_REQUIRETLS, MAIL_RET_FULL, MAIL_RET_HDRS, MAIL_SMTPUTF8,
-    RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
+    EhloResponse, Response, Severity, EXT_CHUNKING, EXT_DSN, EXT_PIPELINING, EXT_REQUIRE_TLS,
+    EXT_SIZE, EXT_SMTP_UTF8, EXT_START_TLS, MAIL_REQUIRETLS, MAIL_RET_FULL, MAIL_RET_HDRS,
+    MAIL_SMTPUTF8, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
 };
 use std::fmt::Write;
 use std::time::Duration;
@@ -38,11 +38,13 @@ use tokio_rustls::{client::TlsStream, TlsConnector};
 
 use crate::{
     config::{RequireOptional, TlsStrategy},
-    queue::{ErrorDetails, HostResponse, RCPT_STATUS_CHANGED},
+    queue::{ErrorDetails, HostResponse, RCPT_DSN_RELAYED, RCPT_STATUS_CHANGED},
 };
 
 use crate::queue::{Error, Message, Recipient, Status};
 
+use super::pool::{ConnectionReuse, ReusableStream};
+
 pub struct SessionParams<'x> {
     pub span: &'x tracing::Span,
     pub hostname: &'x str,
@@ -53,18 +55,30 @@ pub struct SessionParams<'x> {
     pub timeout_mail: Duration,
     pub timeout_rcpt: Duration,
     pub timeout_data: Duration,
+    pub max_rcpt: usize,
+    pub reuse: Option<ConnectionReuse<'x>>,
+}
+
+// Limits advertised by the remote server through the LIMITS extension (RFC 9422),
+// zero means that no limit was advertised.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ServerLimits {
+    pub rcpt_max: usize,
+    pub mail_max: usize,
 }
 
+const MAX_EHLO_LENGTH: usize = 4096;
+
 impl Message {
-    pub async fn deliver<T: AsyncRead + AsyncWrite + Unpin>(
+    pub async fn deliver<T: ReusableStream>(
         &self,
         mut smtp_client: SmtpClient<T>,
         recipients: impl Iterator<Item = &mut Recipient>,
         params: SessionParams<'_>,
     ) -> Status<(), Error> {
         // Obtain capabilities
-        let mut capabilities = match say_helo(&mut smtp_client, &params).await {
-            Ok(capabilities) => capabilities,
+        let (mut capabilities, mut limits) = match say_helo(&mut smtp_client, &params).await {
+            Ok(response) => response,
             Err(status) => {
                 tracing::info!(
                     parent: params.span,
@@ -93,8 +107,8 @@ impl Message {
             }
 
             // Refresh capabilities
-            capabilities = match say_helo(&mut smtp_client, &params).await {
-                Ok(capabilities) => capabilities,
+            (capabilities, limits) = match say_helo(&mut smtp_client, &params).await {
+                Ok(response) => response,
                 Err(status) => {
                     tracing::info!(
                         parent: params.span,
@@ -109,30 +123,22 @@ impl Message {
             };
         }
 
-        // MAIL FROM
-        smtp_client.timeout = params.timeout_mail;
-        let cmd = self.build_mail_from(&capabilities);
-        if let Err(err) = smtp_client
-            .cmd(cmd.as_bytes())
+        self.deliver_transaction(smtp_client, capabilities, limits, recipients, params)
             .await
-            .and_then(|r| r.assert_positive_completion())
-        {
-            tracing::info!(
-                parent: params.span,
-                context = "sender",
-                event = "rejected",
-                mx = &params.hostname,
-                reason = %err,
-            );
-            quit(smtp_client).await;
-            return Status::from_smtp_error(params.hostname, &cmd, err);
-        }
+    }
 
-        // RCPT TO
+    pub async fn deliver_transaction<T: ReusableStream>(
+        &self,
+        mut smtp_client: SmtpClient<T>,
+        capabilities: EhloResponse<String>,
+        limits: ServerLimits,
+        recipients: impl Iterator<Item = &mut Recipient>,
+        params: SessionParams<'_>,
+    ) -> Status<(), Error> {
+        // Prepare recipients
         let mut total_rcpt = 0;
         let mut total_completed = 0;
-        let mut accepted_rcpts = Vec::new();
-        smtp_client.timeout = params.timeout_rcpt;
+        let mut pending_rcpts = Vec::new();
         for rcpt in recipients {
             total_rcpt += 1;
             if matches!(
@@ -142,9 +148,145 @@ impl Message {
                 total_completed += 1;
                 continue;
             }
-
             let cmd = self.build_rcpt_to(rcpt, &capabilities);
-            match smtp_client.cmd(cmd.as_bytes()).await {
+            pending_rcpts.push((rcpt, cmd));
+        }
+
+        // Split the recipients into as many transactions as the limits
+        // advertised by the remote server (RFC 9422) and the configured
+        // per-destination recipient cap allow
+        let rcpt_max = [limits.rcpt_max, params.max_rcpt]
+            .into_iter()
+            .filter(|max| *max > 0)
+            .min()
+            .unwrap_or(usize::MAX);
+        let mut transactions = 0;
+        let mut is_open = false;
+        let mut pending_rcpts = pending_rcpts.into_iter().peekable();
+        while pending_rcpts.peek().is_some() {
+            if limits.mail_max > 0 && transactions >= limits.mail_max {
+                tracing::debug!(
+                    parent: params.span,
+                    context = "limits",
+                    event = "mail-max",
+                    mx = &params.hostname,
+                    transactions = transactions,
+                    pending = pending_rcpts.len(),
+                    "Transaction limit reached, remaining recipients will be retried later."
+                );
+                break;
+            }
+
+            // Transactions without accepted recipients have to be reset first
+            if is_open {
+                smtp_client.timeout = params.timeout_mail;
+                if let Err(err) = smtp_client
+                    .cmd(b"RSET\r\n")
+                    .await
+                    .and_then(|r| r.assert_positive_completion())
+                {
+                    quit(smtp_client).await;
+                    return Status::from_smtp_error(params.hostname, "RSET", err);
+                }
+            }
+
+            let rcpts = pending_rcpts.by_ref().take(rcpt_max).collect::<Vec<_>>();
+            transactions += 1;
+            match self
+                .send_transaction(
+                    &mut smtp_client,
+                    &capabilities,
+                    rcpts,
+                    &params,
+                    &mut total_completed,
+                )
+                .await
+            {
+                Ok(is_sent) => {
+                    is_open = !is_sent;
+                }
+                Err(status) => {
+                    quit(smtp_client).await;
+                    return status;
+                }
+            }
+        }
+
+        // Keep the connection open for further messages to the same host
+        if let Some(reuse) = params.reuse {
+            reuse
+                .release(smtp_client, capabilities, limits, transactions)
+                .await;
+        } else {
+            quit(smtp_client).await;
+        }
+        if total_completed == total_rcpt {
+            Status::Completed(())
+        } else {
+            Status::Scheduled
+        }
+    }
+
+    // Sends MAIL FROM, RCPT TO and DATA for a subset of the recipients,
+    // returns false when no recipients were accepted and DATA was not sent.
+    async fn send_transaction<T: ReusableStream>(
+        &self,
+        smtp_client: &mut SmtpClient<T>,
+        capabilities: &EhloResponse<String>,
+        pending_rcpts: Vec<(&mut Recipient, String)>,
+        params: &SessionParams<'_>,
+        total_completed: &mut usize,
+    ) -> Result<bool, Status<(), Error>> {
+        // MAIL FROM, followed by all RCPT TO commands when pipelining is supported
+        smtp_client.timeout = params.timeout_mail;
+        let cmd = self.build_mail_from(capabilities);
+        let is_pipelining = capabilities.has_capability(EXT_PIPELINING);
+        let mut rcpt_responses = Vec::new().into_iter();
+        let mail_result = if is_pipelining {
+            let mut cmds = cmd.clone();
+            for (_, rcpt_cmd) in &pending_rcpts {
+                cmds.push_str(rcpt_cmd);
+            }
+
+            // Responses may arrive in a single packet, read them all at once
+            match write_chunks(smtp_client, &[cmds.as_bytes()]).await {
+                Ok(_) => read_responses(smtp_client, pending_rcpts.len() + 1)
+                    .await
+                    .and_then(|responses| {
+                        let mut responses = responses.into_iter();
+                        let mail_response =
+                            responses.next().ok_or(mail_send::Error::UnparseableReply)?;
+                        rcpt_responses = responses;
+                        Ok(mail_response)
+                    }),
+                Err(err) => Err(err),
+            }
+        } else {
+            smtp_client.cmd(cmd.as_bytes()).await
+        };
+        if let Err(err) = mail_result.and_then(|r| r.assert_positive_completion()) {
+            tracing::info!(
+                parent: params.span,
+                context = "sender",
+                event = "rejected",
+                mx = &params.hostname,
+                reason = %err,
+            );
+            return Err(Status::from_smtp_error(params.hostname, &cmd, err));
+        }
+
+        // RCPT TO
+        let mut accepted_rcpts = Vec::new();
+        smtp_client.timeout = params.timeout_rcpt;
+        for (rcpt, cmd) in pending_rcpts {
+            let result = if is_pipelining {
+                rcpt_responses
+                    .next()
+                    .ok_or(mail_send::Error::UnparseableReply)
+            } else {
+                smtp_client.cmd(cmd.as_bytes()).await
+            };
+            match result {
                 Ok(response) => match response.severity() {
                     Severity::PositiveCompletion => {
                         accepted_rcpts.push((
@@ -174,7 +316,7 @@ impl Message {
                         };
                         rcpt.flags |= RCPT_STATUS_CHANGED;
                         rcpt.status = if severity == Severity::PermanentNegativeCompletion {
-                            total_completed += 1;
+                            *total_completed += 1;
                             Status::PermanentFailure(response)
                         } else {
                             Status::TemporaryFailure(response)
@@ -192,164 +334,146 @@ impl Message {
                     );
 
                     // Something went wrong, abort.
-                    quit(smtp_client).await;
-                    return Status::from_smtp_error(params.hostname, "", err);
+                    return Err(Status::from_smtp_error(params.hostname, "", err));
                 }
             }
         }
 
         // Send message
-        if !accepted_rcpts.is_empty() {
-            let bdat_cmd = if capabilities.has_capability(EXT_CHUNKING) {
-                format!("BDAT {} LAST\r\n", self.size).into()
-            } else {
-                None
-            };
-
-            if let Err(status) = send_message(&mut smtp_client, self, &bdat_cmd, &params).await {
-                tracing::info!(
-                    parent: params.span,
-                    context = "message",
-                    event = "rejected",
-                    mx = &params.hostname,
-                    reason = %status,
-                );
-
-                quit(smtp_client).await;
-                return status;
-            }
+        if accepted_rcpts.is_empty() {
+            return Ok(false);
+        }
+        let bdat_cmd = if capabilities.has_capability(EXT_CHUNKING) {
+            format!("BDAT {} LAST\r\n", self.size).into()
+        } else {
+            None
+        };
 
-            if params.is_smtp {
-                // Handle SMTP response
-                match read_smtp_data_respone(&mut smtp_client, params.hostname, &bdat_cmd).await {
-                    Ok(response) => {
-                        // Mark recipients as delivered
-                        if response.code() == 250 {
-                            for (rcpt, status) in accepted_rcpts {
-                                tracing::info!(
-                                    parent: params.span,
-                                    context = "rcpt",
-                                    event = "delivered",
-                                    rcpt = rcpt.address,
-                                    mx = &params.hostname,
-                                    response = %status,
-                                );
+        if let Err(status) = send_message(smtp_client, self, &bdat_cmd, params).await {
+            tracing::info!(
+                parent: params.span,
+                context = "message",
+                event = "rejected",
+                mx = &params.hostname,
+                reason = %status,
+            );
+            return Err(status);
+        }
 
-                                rcpt.status = status;
-                                rcpt.flags |= RCPT_STATUS_CHANGED;
-                                total_completed += 1;
-                            }
-                        } else {
+        if params.is_smtp {
+            // Handle SMTP response
+            match read_smtp_data_respone(smtp_client, params.hostname, &bdat_cmd).await {
+                Ok(response) => {
+                    // Mark recipients as delivered
+                    if response.code() == 250 {
+                        for (rcpt, status) in accepted_rcpts {
                             tracing::info!(
                                 parent: params.span,
-                                context = "message",
-                                event = "rejected",
+                                context = "rcpt",
+                                event = "delivered",
+                                rcpt = rcpt.address,
                                 mx = &params.hostname,
-                                reason = %response,
+                                response = %status,
                             );
 
-                            quit(smtp_client).await;
-                            return Status::from_smtp_error(
-                                params.hostname,
-                                bdat_cmd.as_deref().unwrap_or("DATA"),
-                                mail_send::Error::UnexpectedReply(response),
-                            );
-                        }
-                    }
-                    Err(status) => {
-                        tracing::info!(
-                            parent: params.span,
-                            context = "message",
-                            event = "failed",
-                            mx = &params.hostname,
-                            reason = %status,
-                        );
-
-                        quit(smtp_client).await;
-                        return status;
-                    }
-                }
-            } else {
-                // Handle LMTP responses
-                match read_lmtp_data_respone(
-                    &mut smtp_client,
-                    params.hostname,
-                    accepted_rcpts.len(),
-                )
-                .await
-                {
-                    Ok(responses) => {
-                        for ((rcpt, _), response) in accepted_rcpts.into_iter().zip(responses) {
+                            rcpt.status = status;
                             rcpt.flags |= RCPT_STATUS_CHANGED;
-                            rcpt.status = match response.severity() {
-                                Severity::PositiveCompletion => {
-                                    tracing::info!(
-                                        parent: params.span,
-                                        context = "rcpt",
-                                        event = "delivered",
-                                        rcpt = rcpt.address,
-                                        mx = &params.hostname,
-                                        response = %response,
-                                    );
-
-                                    total_completed += 1;
-                                    Status::Completed(HostResponse {
-                                        hostname: params.hostname.to_string(),
-                                        response,
-                                    })
-                                }
-                                severity => {
-                                    tracing::info!(
-                                        parent: params.span,
-                                        context = "rcpt",
-                                        event = "rejected",
-                                        rcpt = rcpt.address,
-                                        mx = &params.hostname,
-                                        reason = %response,
-                                    );
-
-                                    let response = HostResponse {
-                                        hostname: ErrorDetails {
-                                            entity: params.hostname.to_string(),
-                                            details: bdat_cmd
-                                                .as_deref()
-                                                .unwrap_or("DATA")
-                                                .to_string(),
-                                        },
-                                        response,
-                                    };
-                                    if severity == Severity::PermanentNegativeCompletion {
-                                        total_completed += 1;
-                                        Status::PermanentFailure(response)
-                                    } else {
-                                        Status::TemporaryFailure(response)
-                                    }
-                                }
-                            };
+                            if !capabilities.has_capability(EXT_DSN) {
+                                // The next hop cannot honor the DSN request
+                                rcpt.flags |= RCPT_DSN_RELAYED;
+                            }
+                            *total_completed += 1;
                         }
-                    }
-                    Err(status) => {
+                    } else {
                         tracing::info!(
                             parent: params.span,
                             context = "message",
                             event = "rejected",
                             mx = &params.hostname,
-                            reason = %status,
+                            reason = %response,
                         );
+                        return Err(Status::from_smtp_error(
+                            params.hostname,
+                            bdat_cmd.as_deref().unwrap_or("DATA"),
+                            mail_send::Error::UnexpectedReply(response),
+                        ));
+                    }
+                }
+                Err(status) => {
+                    tracing::info!(
+                        parent: params.span,
+                        context = "message",
+                        event = "failed",
+                        mx = &params.hostname,
+                        reason = %status,
+                    );
+                    return Err(status);
+                }
+            }
+        } else {
+            // Handle LMTP responses
+            match read_lmtp_data_respone(smtp_client, params.hostname, accepted_rcpts.len()).await {
+                Ok(responses) => {
+                    for ((rcpt, _), response) in accepted_rcpts.into_iter().zip(responses) {
+                        rcpt.flags |= RCPT_STATUS_CHANGED;
+                        rcpt.status = match response.severity() {
+                            Severity::PositiveCompletion => {
+                                tracing::info!(
+                                    parent: params.span,
+                                    context = "rcpt",
+                                    event = "delivered",
+                                    rcpt = rcpt.address,
+                                    mx = &params.hostname,
+                                    response = %response,
+                                );
+
+                                *total_completed += 1;
+                                Status::Completed(HostResponse {
+                                    hostname: params.hostname.to_string(),
+                                    response,
+                                })
+                            }
+                            severity => {
+                                tracing::info!(
+                                    parent: params.span,
+                                    context = "rcpt",
+                                    event = "rejected",
+                                    rcpt = rcpt.address,
+                                    mx = &params.hostname,
+                                    reason = %response,
+                                );
 
-                        quit(smtp_client).await;
-                        return status;
+                                let response = HostResponse {
+                                    hostname: ErrorDetails {
+                                        entity: params.hostname.to_string(),
+                                        details: bdat_cmd.as_deref().unwrap_or("DATA").to_string(),
+                                    },
+                                    response,
+                                };
+                                if severity == Severity::PermanentNegativeCompletion {
+                                    *total_completed += 1;
+                                    Status::PermanentFailure(response)
+                                } else {
+                                    Status::TemporaryFailure(response)
+                                }
+                            }
+                        };
                     }
                 }
+                Err(status) => {
+                    tracing::info!(
+                        parent: params.span,
+                        context = "message",
+                        event = "rejected",
+                        mx = &params.hostname,
+                        reason = %status,
+                    );
+                    return Err(status);
+                }
             }
         }
 
-        quit(smtp_client).await;
-        if total_completed == total_rcpt {
-            Status::Completed(())
-        } else {
-            Status::Scheduled
-        }
+        Ok(true)
     }
 
     fn build_mail_from(&self, capabilities: &EhloResponse<String>) -> String {
@@ -371,7 +495,8 @@ impl Message {
                 mail_from.push_str(" RET=HDRS");
             }
             if let Some(env_id) = &self.env_id {
-                let _ = write!(mail_from, " ENVID={env_id}");
+                mail_from.push_str(" ENVID=");
+                write_xtext(&mut mail_from, env_id);
             }
         }
 
@@ -408,6 +533,10 @@ impl Message {
             } else if rcpt.has_flag(RCPT_NOTIFY_NEVER) {
                 rcpt_to.push_str(" NOTIFY=NEVER");
             }
+            if let Some(orcpt) = &rcpt.orcpt {
+                rcpt_to.push_str(" ORCPT=rfc822;");
+                write_xtext(&mut rcpt_to, orcpt);
+            }
         }
         rcpt_to.push_str("\r\n");
         rcpt_to
@@ -419,6 +548,17 @@ impl Message {
     }
 }
 
+// Encodes a DSN parameter value as xtext (RFC 3461, section 4)
+fn write_xtext(buf: &mut String, value: &str) {
+    for ch in value.bytes() {
+        if (33..=126).contains(&ch) && ch != b'+' && ch != b'=' {
+            buf.push(char::from(ch));
+        } else {
+            let _ = write!(buf, "+{ch:02X}");
+        }
+    }
+}
+
 impl Recipient {
     #[inline(always)]
     pub fn has_flag(&self, flag: u64) -> bool {
@@ -476,9 +616,17 @@ pub async fn read_greeting<T: AsyncRead + AsyncWrite + Unpin>(
 ) -> Result<(), Status<(), Error>> {
     tokio::time::timeout(smtp_client.timeout, smtp_client.read())
         .await
-        .map_err(|_| Status::timeout(hostname, "reading greeting"))?
-        .and_then(|r| r.assert_code(220))
-        .map_err(|err| Status::from_smtp_error(hostname, "", err))
+        .map_err(|_| Status::timeout(hostname, "reading greeting"))
+        .and_then(|result| {
+            result
+                .and_then(|r| r.assert_code(220))
+                .map_err(|err| Status::from_smtp_error(hostname, "", err))
+        })
+        .map_err(|status| match status {
+            Status::TemporaryFailure(err) => Status::TemporaryFailure(Error::Greeting(err.into())),
+            Status::PermanentFailure(err) => Status::PermanentFailure(Error::Greeting(err.into())),
+            status => status,
+        })
 }
 
 pub async fn read_smtp_data_respone<T: AsyncRead + AsyncWrite + Unpin>(
@@ -570,10 +718,21 @@ pub async fn send_message<T: AsyncRead + AsyncWrite + Unpin>(
     })
 }
 
+async fn read_responses<T: AsyncRead + AsyncWrite + Unpin>(
+    smtp_client: &mut SmtpClient<T>,
+    num_responses: usize,
+) -> Result<Vec<Response<String>>, mail_send::Error> {
+    tokio::time::timeout(smtp_client.timeout, async {
+        smtp_client.read_many(num_responses).await
+    })
+    .await
+    .map_err(|_| mail_send::Error::Timeout)?
+}
+
 pub async fn say_helo<T: AsyncRead + AsyncWrite + Unpin>(
     smtp_client: &mut SmtpClient<T>,
     params: &SessionParams<'_>,
-) -> Result<EhloResponse<String>, Status<(), Error>> {
+) -> Result<(EhloResponse<String>, ServerLimits), Status<(), Error>> {
     let cmd = if params.is_smtp {
         format!("EHLO {}\r\n", params.local_hostname)
     } else {
@@ -582,13 +741,87 @@ pub async fn say_helo<T: AsyncRead + AsyncWrite + Unpin>(
     tokio::time::timeout(params.timeout_ehlo, async {
         smtp_client.stream.write_all(cmd.as_bytes()).await?;
         smtp_client.stream.flush().await?;
-        smtp_client.read_ehlo().await
+        read_ehlo(smtp_client).await
     })
     .await
     .map_err(|_| Status::timeout(params.hostname, "reading EHLO response"))?
     .map_err(|err| Status::from_smtp_error(params.hostname, &cmd, err))
 }
 
+// Reads the EHLO response keeping the LIMITS keyword, which is not
+// exposed by the parser.
+async fn read_ehlo<T: AsyncRead + AsyncWrite + Unpin>(
+    smtp_client: &mut SmtpClient<T>,
+) -> Result<(EhloResponse<String>, ServerLimits), mail_send::Error> {
+    let mut buf = [0u8; 1024];
+    let mut response = Vec::with_capacity(1024);
+
+    loop {
+        let br = smtp_client.stream.read(&mut buf).await?;
+        if br == 0 || response.len() + br > MAX_EHLO_LENGTH {
+            return Err(mail_send::Error::UnparseableReply);
+        }
+        response.extend_from_slice(&buf[..br]);
+
+        match EhloResponse::parse(&mut response.iter()) {
+            Ok(ehlo) => return Ok((ehlo, ServerLimits::parse(&response))),
+            Err(smtp_proto::Error::NeedsMoreData { .. }) => (),
+            Err(smtp_proto::Error::InvalidResponse { code }) => {
+                if is_complete_response(&response) {
+                    return Err(mail_send::Error::UnexpectedReply(Response {
+                        code,
+                        esc: [0, 0, 0],
+                        message: response_text(&response),
+                    }));
+                }
+            }
+            Err(_) => return Err(mail_send::Error::UnparseableReply),
+        }
+    }
+}
+
+fn is_complete_response(response: &[u8]) -> bool {
+    response.ends_with(b"\n")
+        && response[..response.len() - 1]
+            .split(|&ch| ch == b'\n')
+            .last()
+            .map_or(false, |line| line.get(3) == Some(&b' '))
+}
+
+fn response_text(response: &[u8]) -> String {
+    String::from_utf8_lossy(response)
+        .lines()
+        .filter_map(|line| line.get(4..))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl ServerLimits {
+    pub fn parse(response: &[u8]) -> Self {
+        let mut limits = ServerLimits::default();
+        for line in String::from_utf8_lossy(response).lines() {
+            let mut params = line.get(4..).unwrap_or_default().split_ascii_whitespace();
+            if !params
+                .next()
+                .map_or(false, |keyword| keyword.eq_ignore_ascii_case("LIMITS"))
+            {
+                continue;
+            }
+            for param in params {
+                if let Some((key, value)) = param.split_once('=') {
+                    let value = value.parse::<usize>().unwrap_or(0);
+                    if key.eq_ignore_ascii_case("RCPTMAX") {
+                        limits.rcpt_max = value;
+                    } else if key.eq_ignore_ascii_case("MAILMAX") {
+                        limits.mail_max = value;
+                    }
+                }
+            }
+        }
+        limits
+    }
+}
+
 pub async fn quit<T: AsyncRead + AsyncWrite + Unpin>(mut smtp_client: SmtpClient<T>) {
     let _ = tokio::time::timeout(Duration::from_secs(10), async {
         if smtp_client.stream.write_all(b"QUIT\r\n").await.is_ok()
@@ -0,0 +1,465 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::process::Stdio;
+
+use mail_send::SmtpClient;
+use smtp_proto::Response;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::UnixStream,
+    process::Command,
+};
+
+use crate::{
+    config::{Transport, TransportProtocol},
+    queue::{Error, ErrorDetails, HostResponse, Message, Recipient, Status, RCPT_STATUS_CHANGED},
+};
+
+use super::session::{read_greeting, SessionParams};
+
+impl Message {
+    pub async fn deliver_transport(
+        &self,
+        transport: &Transport,
+        recipients: impl Iterator<Item = &mut Recipient>,
+        params: SessionParams<'_>,
+    ) -> Status<(), Error> {
+        match &transport.protocol {
+            TransportProtocol::Pipe { .. } => {
+                self.deliver_pipe(transport, recipients, params.span).await
+            }
+            TransportProtocol::Unix { path, is_lmtp } => {
+                // Connect to the socket
+                let stream = match tokio::time::timeout(
+                    transport.timeout,
+                    UnixStream::connect(path),
+                )
+                .await
+                {
+                    Ok(Ok(stream)) => stream,
+                    Ok(Err(err)) => {
+                        tracing::info!(
+                            parent: params.span,
+                            context = "transport",
+                            event = "failed",
+                            transport = transport.id,
+                            reason = %err,
+                        );
+                        return Status::TemporaryFailure(Error::ConnectionError(ErrorDetails {
+                            entity: transport.id.clone(),
+                            details: format!("Failed to connect to {}: {}", path.display(), err),
+                        }));
+                    }
+                    Err(_) => {
+                        return Status::timeout(&transport.id, "connecting");
+                    }
+                };
+
+                // Read greeting and deliver message
+                let mut smtp_client = SmtpClient {
+                    stream,
+                    timeout: transport.timeout,
+                };
+                if let Err(status) = read_greeting(&mut smtp_client, &transport.id).await {
+                    tracing::info!(
+                        parent: params.span,
+                        context = "greeting",
+                        event = "invalid",
+                        transport = transport.id,
+                        status = %status,
+                    );
+                    return status;
+                }
+                self.deliver(
+                    smtp_client,
+                    recipients,
+                    SessionParams {
+                        is_smtp: !is_lmtp,
+                        hostname: &transport.id,
+                        ..params
+                    },
+                )
+                .await
+            }
+        }
+    }
+
+    async fn deliver_pipe(
+        &self,
+        transport: &Transport,
+        recipients: impl Iterator<Item = &mut Recipient>,
+        span: &tracing::Span,
+    ) -> Status<(), Error> {
+        let TransportProtocol::Pipe {
+            command,
+            per_recipient,
+            ..
+        } = &transport.protocol
+        else {
+            unreachable!()
+        };
+
+        // Prepare recipients list
+        let mut total_rcpt = 0;
+        let mut total_completed = 0;
+        let mut pending_recipients = Vec::new();
+        for rcpt in recipients {
+            total_rcpt += 1;
+            if matches!(
+                &rcpt.status,
+                Status::Completed(_) | Status::PermanentFailure(_)
+            ) {
+                total_completed += 1;
+                continue;
+            }
+            pending_recipients.push(rcpt);
+        }
+        if pending_recipients.is_empty() {
+            return Status::Completed(());
+        }
+
+        // Read message from disk
+        let mut raw_message = vec![0u8; self.size];
+        match fs::File::open(&self.path).await {
+            Ok(mut file) => {
+                if let Err(err) = file.read_exact(&mut raw_message).await {
+                    tracing::error!(parent: span,
+                        context = "queue",
+                        event = "error",
+                        "Failed to read {} bytes file {} from disk: {}",
+                        self.size,
+                        self.path.display(),
+                        err);
+                    return Status::TemporaryFailure(Error::Io("Queue system error.".to_string()));
+                }
+            }
+            Err(err) => {
+                tracing::error!(parent: span,
+                    context = "queue",
+                    event = "error",
+                    "Failed to open message file {}: {}",
+                    self.path.display(),
+                    err);
+                return Status::TemporaryFailure(Error::Io("Queue system error.".to_string()));
+            }
+        }
+
+        // Run the command once per recipient or once for all recipients
+        let batches = if *per_recipient {
+            pending_recipients
+                .into_iter()
+                .map(|rcpt| vec![rcpt])
+                .collect::<Vec<_>>()
+        } else {
+            vec![pending_recipients]
+        };
+        for batch in batches {
+            let addresses = batch
+                .iter()
+                .map(|rcpt| rcpt.address.as_str())
+                .collect::<Vec<_>>();
+            let response = self.run_pipe(transport, &addresses, &raw_message).await;
+
+            for rcpt in batch {
+                rcpt.flags |= RCPT_STATUS_CHANGED;
+                rcpt.status = match response.code {
+                    200..=299 => {
+                        tracing::info!(
+                            parent: span,
+                            context = "transport",
+                            event = "delivered",
+                            transport = transport.id,
+                            rcpt = rcpt.address,
+                        );
+                        total_completed += 1;
+                        Status::Completed(HostResponse {
+                            hostname: transport.id.clone(),
+                            response: response.clone(),
+                        })
+                    }
+                    500..=599 => {
+                        tracing::info!(
+                            parent: span,
+                            context = "transport",
+                            event = "rejected",
+                            transport = transport.id,
+                            rcpt = rcpt.address,
+                            reason = response.message,
+                        );
+                        total_completed += 1;
+                        Status::PermanentFailure(HostResponse {
+                            hostname: ErrorDetails {
+                                entity: transport.id.clone(),
+                                details: format!("{} <{}>", command, rcpt.address),
+                            },
+                            response: response.clone(),
+                        })
+                    }
+                    _ => {
+                        tracing::info!(
+                            parent: span,
+                            context = "transport",
+                            event = "deferred",
+                            transport = transport.id,
+                            rcpt = rcpt.address,
+                            reason = response.message,
+                        );
+                        Status::TemporaryFailure(HostResponse {
+                            hostname: ErrorDetails {
+                                entity: transport.id.clone(),
+                                details: format!("{} <{}>", command, rcpt.address),
+                            },
+                            response: response.clone(),
+                        })
+                    }
+                };
+            }
+        }
+
+        if total_completed == total_rcpt {
+            Status::Completed(())
+        } else {
+            Status::Scheduled
+        }
+    }
+
+    async fn run_pipe(
+        &self,
+        transport: &Transport,
+        recipients: &[&str],
+        raw_message: &[u8],
+    ) -> Response<String> {
+        let TransportProtocol::Pipe {
+            command,
+            arguments,
+            temporary_codes,
+            permanent_codes,
+            ..
+        } = &transport.protocol
+        else {
+            unreachable!()
+        };
+
+        let args = match expand_pipe_arguments(arguments, &self.return_path, recipients) {
+            Ok(args) => args,
+            Err(argument) => {
+                return Response {
+                    code: 550,
+                    esc: [5, 1, 3],
+                    message: format!("Refusing to pass {argument:?} as a command option."),
+                }
+            }
+        };
+
+        let mut child = match Command::new(command)
+            .args(&args)
+            .env("SMTP_SENDER", &self.return_path)
+            .env("SMTP_RECIPIENTS", recipients.join(","))
+            .env("SMTP_QUEUE_ID", self.id.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(err) => {
+                return Response {
+                    code: 451,
+                    esc: [4, 3, 0],
+                    message: format!("Failed to spawn {command:?}: {err}"),
+                }
+            }
+        };
+        let (Some(mut stdin), Some(mut stderr)) = (child.stdin.take(), child.stderr.take()) else {
+            return Response {
+                code: 451,
+                esc: [4, 3, 0],
+                message: "Failed to open pipes.".to_string(),
+            };
+        };
+
+        // Write and read concurrently to avoid deadlocks on large messages
+        let result = tokio::time::timeout(transport.timeout, async {
+            let (_, error) = tokio::join!(
+                async move {
+                    // Commands are free to exit without consuming their input
+                    let _ = stdin.write_all(raw_message).await;
+                    drop(stdin);
+                },
+                async move {
+                    let mut error = Vec::new();
+                    let _ = stderr.read_to_end(&mut error).await;
+                    error
+                }
+            );
+            (child.wait().await, error)
+        })
+        .await;
+
+        match result {
+            Ok((Ok(status), error)) => {
+                let reason = String::from_utf8_lossy(&error)
+                    .lines()
+                    .map(|line| line.trim())
+                    .find(|line| !line.is_empty())
+                    .map(|line| line.to_string());
+                match status.code() {
+                    Some(0) => Response {
+                        code: 250,
+                        esc: [2, 0, 0],
+                        message: reason.unwrap_or_else(|| "Delivered".to_string()),
+                    },
+                    Some(code) => {
+                        let code = code as u32;
+                        let is_temporary = if temporary_codes.contains(&code) {
+                            true
+                        } else if permanent_codes.contains(&code) {
+                            false
+                        } else {
+                            is_temporary_exit_code(code)
+                        };
+                        let reason =
+                            reason.unwrap_or_else(|| format!("Command exited with code {code}."));
+                        let [subject, detail] = exit_code_esc(code);
+                        if is_temporary {
+                            Response {
+                                code: 451,
+                                esc: [4, subject, detail],
+                                message: reason,
+                            }
+                        } else {
+                            Response {
+                                code: 550,
+                                esc: [5, subject, detail],
+                                message: reason,
+                            }
+                        }
+                    }
+                    None => Response {
+                        code: 451,
+                        esc: [4, 3, 0],
+                        message: "Command terminated by signal.".to_string(),
+                    },
+                }
+            }
+            Ok((Err(err), _)) => Response {
+                code: 451,
+                esc: [4, 3, 0],
+                message: format!("Failed to wait for {command:?}: {err}"),
+            },
+            Err(_) => Response {
+                code: 451,
+                esc: [4, 4, 7],
+                message: "Command timed out.".to_string(),
+            },
+        }
+    }
+}
+
+// Exit codes from sysexits.h that indicate a transient condition
+fn is_temporary_exit_code(code: u32) -> bool {
+    matches!(code, 71 | 74 | 75 | 78)
+}
+
+// Maps sysexits.h exit codes to enhanced status code subjects and details
+fn exit_code_esc(code: u32) -> [u8; 2] {
+    match code {
+        64 => [5, 4], // EX_USAGE
+        65 => [6, 0], // EX_DATAERR
+        66 => [1, 1], // EX_NOINPUT
+        67 => [1, 1], // EX_NOUSER
+        68 => [1, 2], // EX_NOHOST
+        69 => [3, 0], // EX_UNAVAILABLE
+        70 => [3, 0], // EX_SOFTWARE
+        71 => [3, 0], // EX_OSERR
+        72 => [3, 0], // EX_OSFILE
+        73 => [2, 0], // EX_CANTCREAT
+        74 => [3, 0], // EX_IOERR
+        75 => [3, 0], // EX_TEMPFAIL
+        76 => [5, 0], // EX_PROTOCOL
+        77 => [7, 1], // EX_NOPERM
+        78 => [3, 5], // EX_CONFIG
+        _ => [0, 0],
+    }
+}
+
+// Expands the placeholders in the configured pipe arguments, a standalone
+// ${recipient} expands to one argument per recipient. Addresses are
+// attacker controlled, so expansions that would turn an argument into a
+// command line option are rejected and the offending argument is returned.
+pub fn expand_pipe_arguments(
+    arguments: &[String],
+    sender: &str,
+    recipients: &[&str],
+) -> Result<Vec<String>, String> {
+    let (user, domain) = recipients
+        .first()
+        .and_then(|rcpt| rcpt.rsplit_once('@'))
+        .unwrap_or_default();
+    let mut args = Vec::with_capacity(arguments.len());
+    for argument in arguments {
+        let is_option = argument.starts_with('-');
+        if argument == "${recipient}" {
+            for rcpt in recipients {
+                if rcpt.starts_with('-') {
+                    return Err(rcpt.to_string());
+                }
+                args.push(rcpt.to_string());
+            }
+        } else {
+            // Substituted values are never scanned for placeholders again
+            let mut expanded = String::with_capacity(argument.len());
+            let mut rest = argument.as_str();
+            while let Some(start) = rest.find("${") {
+                expanded.push_str(&rest[..start]);
+                rest = &rest[start..];
+                let value = rest.find('}').and_then(|end| {
+                    let value = match &rest[2..end] {
+                        "sender" => sender.to_string(),
+                        "recipient" => recipients.join(","),
+                        "user" => user.to_string(),
+                        "domain" => domain.to_string(),
+                        _ => return None,
+                    };
+                    Some((value, end + 1))
+                });
+                if let Some((value, len)) = value {
+                    expanded.push_str(&value);
+                    rest = &rest[len..];
+                } else {
+                    expanded.push_str("${");
+                    rest = &rest[2..];
+                }
+            }
+            expanded.push_str(rest);
+            if !is_option && expanded.starts_with('-') {
+                return Err(expanded);
+            }
+            args.push(expanded);
+        }
+    }
+    Ok(args)
+}
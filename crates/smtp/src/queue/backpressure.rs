@@ -0,0 +1,186 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::{atomic::Ordering, Arc},
+    time::Duration,
+};
+
+use utils::metrics;
+
+use crate::{
+    core::{QueueCore, SMTP},
+    webhook::WebhookEventType,
+};
+
+use super::{manager::Queue, Message, QueueLoad, QueueLoadRef, Timestamp};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackpressureReason {
+    Messages,
+    Size,
+    Lag,
+}
+
+pub const METRIC_QUEUE_MESSAGES: &str = "queue.messages";
+pub const METRIC_QUEUE_SIZE: &str = "queue.size";
+pub const METRIC_QUEUE_LAG: &str = "queue.lag-seconds";
+pub const METRIC_BACKPRESSURE_ACTIVE: &str = "queue.backpressure.active";
+pub const METRIC_BACKPRESSURE_DEFERRED: &str = "queue.backpressure.deferred";
+
+impl QueueLoad {
+    pub fn track(self: &Arc<Self>, message: &Message) -> QueueLoadRef {
+        self.messages.fetch_add(1, Ordering::Relaxed);
+        self.size.fetch_add(message.size, Ordering::Relaxed);
+        metrics::increment(METRIC_QUEUE_MESSAGES, 1);
+        metrics::increment(METRIC_QUEUE_SIZE, message.size as u64);
+
+        QueueLoadRef {
+            size: message.size,
+            load: self.clone(),
+        }
+    }
+
+    pub fn messages(&self) -> usize {
+        self.messages.load(Ordering::Relaxed)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    // How long the most overdue delivery has been waiting past its scheduled time
+    pub fn lag(&self) -> Duration {
+        Duration::from_millis(self.lag.load(Ordering::Relaxed))
+    }
+
+    pub fn set_lag(&self, lag: Duration) {
+        self.lag.store(lag.as_millis() as u64, Ordering::Relaxed);
+        metrics::set(METRIC_QUEUE_LAG, lag.as_secs());
+    }
+}
+
+impl Drop for QueueLoadRef {
+    fn drop(&mut self) {
+        self.load.messages.fetch_sub(1, Ordering::Relaxed);
+        self.load.size.fetch_sub(self.size, Ordering::Relaxed);
+        metrics::decrement(METRIC_QUEUE_MESSAGES, 1);
+        metrics::decrement(METRIC_QUEUE_SIZE, self.size as u64);
+    }
+}
+
+impl Queue {
+    // Messages waiting past their due time are either on hold waiting for a
+    // concurrency slot or scheduled but not yet picked up by the manager.
+    pub fn backlog_lag(&self) -> Duration {
+        let now = Timestamp::now();
+        self.on_hold
+            .iter()
+            .filter_map(|on_hold| self.messages.get(&on_hold.message))
+            .map(|message| message.next_delivery_event())
+            .chain(self.scheduled.peek().map(|schedule| schedule.due))
+            .filter(|due| *due <= now)
+            .min()
+            .map_or(Duration::ZERO, |due| now.duration_since(due))
+    }
+}
+
+impl QueueCore {
+    pub fn backpressure(&self) -> Option<BackpressureReason> {
+        let config = &self.config.backpressure;
+        if config
+            .max_messages
+            .map_or(false, |max| self.load.messages() >= max)
+        {
+            Some(BackpressureReason::Messages)
+        } else if config.max_size.map_or(false, |max| self.load.size() >= max) {
+            Some(BackpressureReason::Size)
+        } else if config.max_lag.map_or(false, |max| self.load.lag() > max) {
+            Some(BackpressureReason::Lag)
+        } else {
+            None
+        }
+    }
+}
+
+impl SMTP {
+    pub fn is_queue_backpressured(&self, span: &tracing::Span) -> bool {
+        let reason = self.queue.backpressure();
+        let deferring = reason.is_some();
+
+        // Only alert when the queue enters or leaves the backpressure state
+        if self.queue.load.deferring.swap(deferring, Ordering::Relaxed) != deferring {
+            let messages = self.queue.load.messages();
+            let size = self.queue.load.size();
+            let lag = self.queue.load.lag().as_secs();
+            metrics::set(METRIC_BACKPRESSURE_ACTIVE, deferring as u64);
+
+            if let Some(reason) = reason {
+                tracing::warn!(
+                    parent: span,
+                    context = "queue",
+                    event = "backpressure",
+                    reason = reason.as_str(),
+                    messages = messages,
+                    size = size,
+                    lag = lag,
+                    "Queue limits exceeded, deferring inbound messages."
+                );
+            } else {
+                tracing::info!(
+                    parent: span,
+                    context = "queue",
+                    event = "backpressure-released",
+                    messages = messages,
+                    size = size,
+                    lag = lag,
+                    "Queue back within limits, accepting inbound messages."
+                );
+            }
+
+            self.webhook.publish(
+                WebhookEventType::QueueBackpressure,
+                None,
+                serde_json::json!({
+                    "active": deferring,
+                    "reason": reason.map(|r| r.as_str()),
+                    "messages": messages,
+                    "size": size,
+                    "lag": lag,
+                }),
+            );
+        }
+
+        deferring
+    }
+}
+
+impl BackpressureReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackpressureReason::Messages => "messages",
+            BackpressureReason::Size => "size",
+            BackpressureReason::Lag => "lag",
+        }
+    }
+}
@@ -0,0 +1,239 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use mail_auth::common::base32::Base32Reader;
+use tokio::fs;
+
+use crate::core::QueueCore;
+
+use super::{
+    Message, QueueId, Schedule, SimpleEnvelope, Status, Timestamp, RCPT_DSN_SENT, RCPT_EVENT_SENT,
+    RCPT_STATUS_CHANGED,
+};
+
+impl Message {
+    // Moves an expired message, including its delivery history, to the dead-letter
+    // archive. Returns false when no archive is configured or the move failed.
+    pub async fn move_to_dead_letter(&mut self, core: &QueueCore) -> bool {
+        let dead_letter = if let Some(dead_letter) = &core.config.dead_letter {
+            dead_letter
+        } else {
+            return false;
+        };
+
+        // Persist the final delivery status before archiving
+        for rcpt in &mut self.recipients {
+            rcpt.flags |= RCPT_STATUS_CHANGED;
+        }
+        self.save_changes().await;
+
+        let _ = fs::create_dir_all(dead_letter).await;
+        let path = dead_letter.join(self.file_name());
+        if let Err(err) = fs::rename(&self.path, &path).await {
+            // Fallback for archives located on a different file system
+            if let Err(err) = fs::copy(&self.path, &path).await {
+                tracing::error!(
+                    context = "queue",
+                    event = "error",
+                    "Failed to move {} to dead-letter archive: {}",
+                    self.path.display(),
+                    err
+                );
+                return false;
+            }
+            tracing::debug!(
+                context = "queue",
+                event = "dead-letter",
+                "Rename of {} failed ({}), message copied instead.",
+                self.path.display(),
+                err
+            );
+            self.remove().await;
+        }
+
+        tracing::info!(
+            context = "queue",
+            event = "dead-letter",
+            id = self.id,
+            path = %path.display(),
+            "Expired message moved to dead-letter archive."
+        );
+
+        self.path = path;
+        true
+    }
+}
+
+impl QueueCore {
+    pub async fn dead_letter_list(&self) -> Vec<QueueId> {
+        let mut result = Vec::new();
+        if let Some(dead_letter) = &self.config.dead_letter {
+            if let Ok(mut dir) = fs::read_dir(dead_letter).await {
+                while let Ok(Some(file)) = dir.next_entry().await {
+                    if let Some(id) = dead_letter_id(&file.path()) {
+                        result.push(id);
+                    }
+                }
+            }
+        }
+        result.sort_unstable_by_key(|id| *id & 0xFFFFFFFF);
+        result
+    }
+
+    pub async fn dead_letter_get(&self, id: QueueId) -> Option<Message> {
+        Message::from_path(self.dead_letter_path(id).await?)
+            .await
+            .map_err(|err| {
+                tracing::warn!(
+                    context = "queue",
+                    event = "error",
+                    "Failed to read dead-letter message: {}",
+                    err
+                );
+            })
+            .ok()
+    }
+
+    // Returns the raw message as it was originally queued
+    pub async fn dead_letter_export(&self, id: QueueId) -> Option<Vec<u8>> {
+        let message = self.dead_letter_get(id).await?;
+        let mut contents = fs::read(&message.path).await.ok()?;
+        if contents.len() >= message.size {
+            contents.truncate(message.size);
+            Some(contents)
+        } else {
+            None
+        }
+    }
+
+    pub async fn dead_letter_remove(&self, id: QueueId) -> bool {
+        if let Some(path) = self.dead_letter_path(id).await {
+            fs::remove_file(&path).await.is_ok()
+        } else {
+            false
+        }
+    }
+
+    // Schedules all failed recipients of an archived message for delivery again
+    pub async fn dead_letter_reinject(&self, id: QueueId) -> bool {
+        let mut message = if let Some(message) = self.dead_letter_get(id).await {
+            Box::new(message)
+        } else {
+            return false;
+        };
+        let raw_message = match fs::read(&message.path).await {
+            Ok(mut raw_message) if raw_message.len() >= message.size => {
+                raw_message.truncate(message.size);
+                raw_message
+            }
+            _ => return false,
+        };
+        let dead_letter_path = std::mem::take(&mut message.path);
+
+        // Evaluate the expiration time of each failed domain
+        let mut expires = Vec::with_capacity(message.domains.len());
+        for domain in &message.domains {
+            expires.push(if matches!(domain.status, Status::PermanentFailure(_)) {
+                Some(
+                    *self
+                        .config
+                        .expire
+                        .eval(&SimpleEnvelope::new(message.as_ref(), &domain.domain))
+                        .await,
+                )
+            } else {
+                None
+            });
+        }
+        if expires.iter().all(|expires| expires.is_none()) {
+            return false;
+        }
+
+        for (domain_idx, (domain, expires)) in message.domains.iter_mut().zip(expires).enumerate() {
+            if let Some(expires) = expires {
+                domain.status = Status::Scheduled;
+                domain.retry = Schedule::now();
+                domain.notify = Schedule::later(expires + Duration::from_secs(10));
+                domain.expires = Timestamp::now() + expires;
+                domain.changed = true;
+
+                for rcpt in &mut message.recipients {
+                    if rcpt.domain_idx == domain_idx && !matches!(rcpt.status, Status::Completed(_))
+                    {
+                        rcpt.status = Status::Scheduled;
+                        rcpt.flags &= !(RCPT_DSN_SENT | RCPT_STATUS_CHANGED | RCPT_EVENT_SENT);
+                    }
+                }
+            }
+        }
+
+        let span = tracing::info_span!("reinject", "id" = id);
+        if !self.has_quota(&mut message).await {
+            tracing::info!(
+                parent: &span,
+                context = "queue",
+                event = "quota-exceeded",
+                "Queue quota exceeded, dead-letter message not re-injected."
+            );
+            return false;
+        }
+        if self.queue_message(message, None, &raw_message, &span).await {
+            let _ = fs::remove_file(&dead_letter_path).await;
+            true
+        } else {
+            false
+        }
+    }
+
+    async fn dead_letter_path(&self, id: QueueId) -> Option<PathBuf> {
+        let mut dir = fs::read_dir(self.config.dead_letter.as_ref()?).await.ok()?;
+        while let Ok(Some(file)) = dir.next_entry().await {
+            let path = file.path();
+            if dead_letter_id(&path) == Some(id) {
+                return Some(path);
+            }
+        }
+        None
+    }
+}
+
+fn dead_letter_id(path: &Path) -> Option<QueueId> {
+    let (file_name, extension) = path.file_name()?.to_str()?.rsplit_once('.')?;
+    if extension != "msg" {
+        return None;
+    }
+    let mut id = [0u8; std::mem::size_of::<u64>()];
+    for (pos, byte) in Base32Reader::new(file_name.as_bytes()).enumerate() {
+        if let Some(id_byte) = id.get_mut(pos) {
+            *id_byte = byte;
+        } else if pos >= 12 {
+            return None;
+        }
+    }
+    Some(u64::from_le_bytes(id))
+}
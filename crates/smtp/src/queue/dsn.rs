@@ -30,7 +30,7 @@ use smtp_proto::{
     Response, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
 };
 use std::fmt::Write;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 
@@ -38,15 +38,44 @@ use crate::config::QueueConfig;
 use crate::core::QueueCore;
 
 use super::{
-    instant_to_timestamp, DeliveryAttempt, Domain, Error, ErrorDetails, HostResponse, Message,
-    Recipient, SimpleEnvelope, Status, RCPT_DSN_SENT, RCPT_STATUS_CHANGED,
+    DeliveryAttempt, Domain, Error, ErrorDetails, HostResponse, Message, QueueId, Recipient,
+    SimpleEnvelope, Status, Timestamp, MAIL_AUTH_FAILED, RCPT_DSN_RELAYED, RCPT_DSN_SENT,
+    RCPT_STATUS_CHANGED,
 };
 
+pub const METRIC_DSN_SENT: &str = "dsn.sent";
+pub const METRIC_DSN_SUPPRESSED_AUTH_FAILED: &str = "dsn.suppressed.auth-failed";
+pub const METRIC_DSN_SUPPRESSED_RATE_LIMITED: &str = "dsn.suppressed.rate-limited";
+pub const METRIC_DSN_SUPPRESSED_TOTAL_RATE_LIMITED: &str = "dsn.suppressed.total-rate-limited";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DsnSuppression {
+    AuthFailed,
+    RateLimited,
+    TotalRateLimited,
+}
+
 impl QueueCore {
-    pub async fn send_dsn(&self, attempt: &mut DeliveryAttempt) {
+    // Returns the queue id of the DSN message, if one was sent
+    pub async fn send_dsn(&self, attempt: &mut DeliveryAttempt) -> Option<QueueId> {
         if !attempt.message.return_path.is_empty() {
             if let Some(dsn) = attempt.build_dsn(&self.config).await {
+                if let Some(reason) = self.is_dsn_suppressed(&attempt.message).await {
+                    tracing::info!(
+                        parent: &attempt.span,
+                        context = "queue",
+                        event = "dsn-suppressed",
+                        id = attempt.message.id,
+                        to = attempt.message.return_path,
+                        reason = reason.as_str(),
+                        "DSN suppressed to avoid backscatter."
+                    );
+                    utils::metrics::increment(reason.metric(), 1);
+                    return None;
+                }
+
                 let mut dsn_message = Message::new_boxed("", "", "");
+                dsn_message.id = self.queue_id();
                 dsn_message
                     .add_recipient_parts(
                         &attempt.message.return_path,
@@ -61,23 +90,71 @@ impl QueueCore {
                     .message
                     .sign(&self.config.dsn.sign, &dsn, &attempt.span)
                     .await;
-                self.queue_message(dsn_message, signature.as_deref(), &dsn, &attempt.span)
-                    .await;
+                let dsn_id = dsn_message.id;
+                if self
+                    .queue_message(dsn_message, signature.as_deref(), &dsn, &attempt.span)
+                    .await
+                {
+                    utils::metrics::increment(METRIC_DSN_SENT, 1);
+                    return Some(dsn_id);
+                }
             }
         } else {
             attempt.handle_double_bounce();
         }
+
+        None
+    }
+
+    async fn is_dsn_suppressed(&self, message: &Message) -> Option<DsnSuppression> {
+        let config = &self.config.dsn;
+        if (message.flags & MAIL_AUTH_FAILED) != 0
+            && *config.suppress_auth_failed.eval(message).await
+        {
+            return Some(DsnSuppression::AuthFailed);
+        }
+        if let Some(rate) = config.rate.eval(message).await {
+            if !self.throttle_rcpt(&message.return_path_lcase, rate, "dsn") {
+                return Some(DsnSuppression::RateLimited);
+            }
+        }
+        if let Some(rate) = &config.total_rate {
+            if !self.throttle_rcpt("", rate, "dsn") {
+                return Some(DsnSuppression::TotalRateLimited);
+            }
+        }
+
+        None
+    }
+}
+
+impl DsnSuppression {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DsnSuppression::AuthFailed => "auth-failed",
+            DsnSuppression::RateLimited => "rate-limited",
+            DsnSuppression::TotalRateLimited => "total-rate-limited",
+        }
+    }
+
+    pub fn metric(&self) -> &'static str {
+        match self {
+            DsnSuppression::AuthFailed => METRIC_DSN_SUPPRESSED_AUTH_FAILED,
+            DsnSuppression::RateLimited => METRIC_DSN_SUPPRESSED_RATE_LIMITED,
+            DsnSuppression::TotalRateLimited => METRIC_DSN_SUPPRESSED_TOTAL_RATE_LIMITED,
+        }
     }
 }
 
 impl DeliveryAttempt {
     pub async fn build_dsn(&mut self, config: &QueueConfig) -> Option<Vec<u8>> {
-        let now = Instant::now();
+        let now = Timestamp::now();
 
         let mut txt_success = String::new();
         let mut txt_delay = String::new();
         let mut txt_failed = String::new();
         let mut dsn = String::new();
+        let mut failed_domains = vec![false; self.message.domains.len()];
 
         for rcpt in &mut self.message.recipients {
             if rcpt.has_flag(RCPT_DSN_SENT | RCPT_NOTIFY_NEVER) {
@@ -91,7 +168,11 @@ impl DeliveryAttempt {
                         continue;
                     }
                     rcpt.write_dsn(&mut dsn);
-                    rcpt.status.write_dsn(&mut dsn);
+                    if rcpt.has_flag(RCPT_DSN_RELAYED) {
+                        rcpt.status.write_dsn_relayed(&mut dsn);
+                    } else {
+                        rcpt.status.write_dsn(&mut dsn);
+                    }
                     response.write_dsn_text(&rcpt.address, &mut txt_success);
                 }
                 Status::TemporaryFailure(response)
@@ -154,6 +235,9 @@ impl DeliveryAttempt {
                 _ => continue,
             }
 
+            if !matches!(rcpt.status, Status::Completed(_)) {
+                failed_domains[rcpt.domain_idx] = true;
+            }
             dsn.push_str("\r\n");
         }
 
@@ -221,6 +305,11 @@ impl DeliveryAttempt {
             txt.push_str("\r\n");
         }
 
+        // Include previous delivery attempts for the failed domains
+        if (has_delay || has_failure) && *config.dsn.verbose.eval(self.message.as_ref()).await {
+            self.message.write_dsn_history(&failed_domains, &mut txt);
+        }
+
         // Update next delay notification time
         if has_delay {
             let mut domains = std::mem::take(&mut self.message.domains);
@@ -239,7 +328,7 @@ impl DeliveryAttempt {
                         .get((domain.notify.inner + 1) as usize)
                     {
                         domain.notify.inner += 1;
-                        domain.notify.due = Instant::now() + *next_notify;
+                        domain.notify.due = Timestamp::now() + *next_notify;
                     } else {
                         domain.notify.due = domain.expires + Duration::from_secs(10);
                     }
@@ -372,7 +461,7 @@ impl DeliveryAttempt {
             }
         }
 
-        let now = Instant::now();
+        let now = Timestamp::now();
         for domain in &mut message.domains {
             if domain.notify.due <= now {
                 domain.notify.due = domain.expires + Duration::from_secs(10);
@@ -477,11 +566,60 @@ impl Error {
             Error::Io(err) => {
                 let _ = write!(dsn, "<{addr}> (queue error: {err})\r\n");
             }
+            Error::NullMx => {
+                let _ = write!(
+                    dsn,
+                    "<{addr}> (domain '{domain}' does not accept messages, null MX)\r\n",
+                );
+            }
+            Error::Greeting(err) => {
+                err.write_dsn_text(addr, domain, dsn);
+            }
         }
     }
 }
 
 impl Message {
+    fn write_dsn_history(&self, domains: &[bool], txt: &mut String) {
+        let mut has_history = false;
+        for entry in &self.history {
+            let domain = match self.domains.get(entry.domain_idx) {
+                Some(domain) if domains[entry.domain_idx] => domain,
+                _ => continue,
+            };
+            let (result, response) = match &entry.status {
+                Status::Completed(response) => ("delivered", response),
+                Status::TemporaryFailure(response) => ("temporary failure", response),
+                Status::PermanentFailure(response) => ("permanent failure", response),
+                Status::Scheduled => continue,
+            };
+            if !has_history {
+                txt.push_str("    ----- Delivery attempts -----\r\n");
+                has_history = true;
+            }
+
+            let _ = write!(
+                txt,
+                "{} '{}'",
+                DateTime::from_timestamp(entry.time as i64).to_rfc822(),
+                domain.domain
+            );
+            if !entry.mx.is_empty() {
+                let _ = write!(txt, " via '{}'", entry.mx);
+                if !entry.remote_ip.is_empty() {
+                    let _ = write!(txt, " [{}]", entry.remote_ip);
+                }
+            }
+            if !entry.tls.is_empty() {
+                let _ = write!(txt, " using {}", entry.tls);
+            }
+            let _ = write!(txt, ": {result} ({response})\r\n");
+        }
+        if has_history {
+            txt.push_str("\r\n");
+        }
+    }
+
     fn write_dsn_headers(&self, dsn: &mut String, reporting_mta: &str) {
         let _ = write!(dsn, "Reporting-MTA: dns;{reporting_mta}\r\n");
         dsn.push_str("Arrival-Date: ");
@@ -505,13 +643,9 @@ impl Recipient {
 
 impl Domain {
     fn write_dsn_will_retry_until(&self, dsn: &mut String) {
-        let now = Instant::now();
-        if self.expires > now {
+        if self.expires > Timestamp::now() {
             dsn.push_str("Will-Retry-Until: ");
-            dsn.push_str(
-                &DateTime::from_timestamp(instant_to_timestamp(now, self.expires) as i64)
-                    .to_rfc822(),
-            );
+            dsn.push_str(&DateTime::from_timestamp(self.expires.as_secs() as i64).to_rfc822());
             dsn.push_str("\r\n");
         }
     }
@@ -555,6 +689,14 @@ impl Status<HostResponse<String>, HostResponse<ErrorDetails>> {
         self.write_dsn_remote_mta(dsn);
     }
 
+    // Delivered to a host that does not support DSN, no further notifications will follow
+    fn write_dsn_relayed(&self, dsn: &mut String) {
+        dsn.push_str("Action: relayed\r\n");
+        self.write_dsn_status(dsn);
+        self.write_dsn_diagnostic(dsn);
+        self.write_dsn_remote_mta(dsn);
+    }
+
     fn write_dsn_status(&self, dsn: &mut String) {
         dsn.push_str("Status: ");
         if let Status::Completed(HostResponse { response, .. })
@@ -605,8 +747,12 @@ impl Status<(), Error> {
     fn write_dsn_status(&self, dsn: &mut String) {
         if let Status::PermanentFailure(err) | Status::TemporaryFailure(err) = self {
             dsn.push_str("Status: ");
+            let err = err.inner();
             if let Error::UnexpectedResponse(response) = err {
                 response.response.write_dsn_status(dsn);
+            } else if let Error::NullMx = err {
+                // RFC 7505: 5.1.10 Recipient address has null MX
+                dsn.push_str("5.1.10");
             } else {
                 dsn.push_str(if matches!(self, Status::PermanentFailure(_)) {
                     "5.0.0"
@@ -620,7 +766,7 @@ impl Status<(), Error> {
 
     fn write_dsn_remote_mta(&self, dsn: &mut String) {
         if let Status::PermanentFailure(err) | Status::TemporaryFailure(err) = self {
-            match err {
+            match err.inner() {
                 Error::UnexpectedResponse(HostResponse {
                     hostname: details, ..
                 })
@@ -637,10 +783,10 @@ impl Status<(), Error> {
     }
 
     fn write_dsn_diagnostic(&self, dsn: &mut String) {
-        if let Status::PermanentFailure(Error::UnexpectedResponse(response))
-        | Status::TemporaryFailure(Error::UnexpectedResponse(response)) = self
-        {
-            response.response.write_dsn_diagnostic(dsn);
+        if let Status::PermanentFailure(err) | Status::TemporaryFailure(err) = self {
+            if let Error::UnexpectedResponse(response) = err.inner() {
+                response.response.write_dsn_diagnostic(dsn);
+            }
         }
     }
 }
@@ -24,20 +24,24 @@
 use std::{
     collections::BinaryHeap,
     sync::{atomic::Ordering, Arc},
-    time::{Duration, Instant},
+    time::Duration,
 };
 
 use ahash::AHashMap;
 use smtp_proto::Response;
 use tokio::sync::mpsc;
 
-use crate::core::{
-    management::{self},
-    QueueCore, SMTP,
+use crate::{
+    core::{
+        management::{self},
+        QueueCore, SMTP,
+    },
+    inbound::filter::DeferredScan,
 };
 
 use super::{
-    DeliveryAttempt, Event, HostResponse, Message, OnHold, QueueId, Schedule, Status, WorkerResult,
+    DeliveryAttempt, ErrorDetails, Event, HostResponse, Message, OnHold, QueueId, ScanResult,
+    ScanVerdict, Schedule, Status, Timestamp, WorkerResult, RCPT_QUARANTINED, RCPT_SCAN_PENDING,
     RCPT_STATUS_CHANGED,
 };
 
@@ -51,8 +55,10 @@ pub struct Queue {
 }
 
 impl SpawnQueue for mpsc::Receiver<Event> {
-    fn spawn(mut self, core: Arc<SMTP>, mut queue: Queue) {
+    fn spawn(mut self, mut core: Arc<SMTP>, mut queue: Queue) {
         tokio::spawn(async move {
+            DeferredScan::resume(&core, &queue).await;
+
             loop {
                 let result = tokio::time::timeout(queue.wake_up_time(), self.recv()).await;
 
@@ -63,6 +69,9 @@ impl SpawnQueue for mpsc::Receiver<Event> {
                         .await;
                 }
 
+                // Measure how far behind schedule deliveries are
+                core.queue.load.set_lag(queue.backlog_lag());
+
                 match result {
                     Ok(Some(event)) => match event {
                         Event::Queue(item) => {
@@ -73,7 +82,7 @@ impl SpawnQueue for mpsc::Receiver<Event> {
                                     .await;
                             }
 
-                            if item.due <= Instant::now() {
+                            if item.due <= Timestamp::now() {
                                 DeliveryAttempt::from(item.inner)
                                     .try_deliver(core.clone(), &mut queue)
                                     .await;
@@ -97,6 +106,9 @@ impl SpawnQueue for mpsc::Receiver<Event> {
                                     queue.on_hold(on_hold);
                                 }
                             }
+
+                            // Lift the intake backpressure as soon as the queue drains
+                            core.is_queue_backpressured(&tracing::Span::current());
                         }
                         Event::Manage(request) => match request {
                             management::QueueRequest::List {
@@ -285,8 +297,60 @@ impl SpawnQueue for mpsc::Receiver<Event> {
                                 }
                                 let _ = result_tx.send(result);
                             }
+                            management::QueueRequest::Modify {
+                                queue_id,
+                                modifications,
+                                result_tx,
+                            } => {
+                                let mut found = false;
+                                if let Some(message) = queue.messages.get_mut(&queue_id) {
+                                    match message.modify(&core, &modifications).await {
+                                        Ok(_) => {
+                                            found = true;
+                                            if let Some(next_event) = message.next_event() {
+                                                queue.scheduled.push(Schedule {
+                                                    due: next_event,
+                                                    inner: queue_id,
+                                                });
+                                            }
+                                        }
+                                        Err(reason) => {
+                                            tracing::info!(
+                                                context = "queue",
+                                                event = "modify-failed",
+                                                id = queue_id,
+                                                reason = reason,
+                                                "Failed to modify queued message."
+                                            );
+                                        }
+                                    }
+                                }
+                                let _ = result_tx.send(found);
+                            }
+                            management::QueueRequest::Release {
+                                queue_ids,
+                                result_tx,
+                            } => {
+                                let mut result = Vec::with_capacity(queue_ids.len());
+                                for queue_id in &queue_ids {
+                                    result.push(queue.release(*queue_id).await);
+                                }
+                                let _ = result_tx.send(result);
+                            }
                         },
-                        Event::Stop => break,
+                        Event::Scanned(result) => {
+                            queue.scanned(result).await;
+                        }
+                        Event::Reload(new_core) => {
+                            core = new_core;
+                        }
+                        Event::Stop => {
+                            // Persist any pending schedule changes before exiting
+                            for message in queue.messages.values_mut() {
+                                message.save_changes().await;
+                            }
+                            break;
+                        }
                     },
                     Ok(None) => break,
                     Err(_) => (),
@@ -314,9 +378,191 @@ impl Queue {
         self.messages.insert(message.message.id, message.message);
     }
 
+    pub async fn scanned(&mut self, result: ScanResult) {
+        let queue_id = result.queue_id;
+        let message = if let Some(message) = self.messages.get_mut(&queue_id) {
+            message
+        } else {
+            tracing::debug!(
+                context = "queue",
+                event = "scan-result",
+                id = queue_id,
+                "Message is no longer queued, ignoring scan result."
+            );
+            return;
+        };
+
+        // The scan is complete, the message is not scanned again on restart
+        for rcpt in &mut message.recipients {
+            if rcpt.has_flag(RCPT_SCAN_PENDING) {
+                rcpt.flags &= !RCPT_SCAN_PENDING;
+                rcpt.flags |= RCPT_STATUS_CHANGED;
+            }
+        }
+
+        match result.verdict {
+            ScanVerdict::Deliver => {
+                // Restore the original schedule, scans resumed after a restart
+                // are delivered right away.
+                let mut schedule = result.schedule.into_iter();
+                for domain in &mut message.domains {
+                    let (retry_due, notify_due) = schedule
+                        .next()
+                        .unwrap_or_else(|| (Timestamp::now(), domain.notify.due));
+                    if matches!(
+                        domain.status,
+                        Status::Scheduled | Status::TemporaryFailure(_)
+                    ) {
+                        domain.retry.due = retry_due;
+                        domain.notify.due = notify_due;
+                        domain.changed = true;
+                    }
+                }
+
+                tracing::debug!(
+                    context = "queue",
+                    event = "scan-accept",
+                    id = queue_id,
+                    "Message released after deferred scanning."
+                );
+            }
+            ScanVerdict::Quarantine(reason) => {
+                tracing::info!(
+                    context = "queue",
+                    event = "quarantine",
+                    id = queue_id,
+                    reason = reason,
+                    "Message quarantined after deferred scanning."
+                );
+                for rcpt in &mut message.recipients {
+                    if matches!(rcpt.status, Status::Scheduled | Status::TemporaryFailure(_)) {
+                        rcpt.flags |= RCPT_QUARANTINED | RCPT_STATUS_CHANGED;
+                    }
+                }
+                message.save_changes().await;
+                return;
+            }
+            ScanVerdict::Discard(reason) => {
+                tracing::info!(
+                    context = "queue",
+                    event = "discard",
+                    id = queue_id,
+                    reason = reason,
+                    "Message discarded after deferred scanning."
+                );
+                if let Some(message) = self.messages.remove(&queue_id) {
+                    message.remove().await;
+                }
+                self.on_hold.retain(|oh| oh.message != queue_id);
+                return;
+            }
+            ScanVerdict::Bounce(response) => {
+                tracing::info!(
+                    context = "queue",
+                    event = "bounce",
+                    id = queue_id,
+                    reason = response.response.message,
+                    "Message bounced after deferred scanning."
+                );
+
+                // Fail all pending recipients, the DSN is sent on the next delivery attempt
+                for rcpt in &mut message.recipients {
+                    if matches!(rcpt.status, Status::Scheduled | Status::TemporaryFailure(_)) {
+                        rcpt.flags |= RCPT_STATUS_CHANGED;
+                        rcpt.status = Status::PermanentFailure(HostResponse {
+                            hostname: ErrorDetails {
+                                entity: response.hostname.entity.clone(),
+                                details: response.hostname.details.clone(),
+                            },
+                            response: Response {
+                                code: response.response.code,
+                                esc: response.response.esc,
+                                message: response.response.message.clone(),
+                            },
+                        });
+                    }
+                }
+                for domain in &mut message.domains {
+                    if matches!(
+                        domain.status,
+                        Status::Scheduled | Status::TemporaryFailure(_)
+                    ) {
+                        domain.status = Status::Completed(());
+                        domain.changed = true;
+                    }
+                }
+                message.save_changes().await;
+                self.on_hold.retain(|oh| oh.message != queue_id);
+                self.scheduled.push(Schedule {
+                    due: Timestamp::now(),
+                    inner: queue_id,
+                });
+                return;
+            }
+        }
+
+        message.save_changes().await;
+        self.on_hold.retain(|oh| oh.message != queue_id);
+        if let Some(next_event) = message.next_event() {
+            self.scheduled.push(Schedule {
+                due: next_event,
+                inner: queue_id,
+            });
+        }
+    }
+
+    pub async fn release(&mut self, queue_id: QueueId) -> bool {
+        let message = if let Some(message) = self.messages.get_mut(&queue_id) {
+            message
+        } else {
+            return false;
+        };
+
+        let mut found = false;
+        for rcpt in &mut message.recipients {
+            if rcpt.has_flag(RCPT_QUARANTINED) {
+                rcpt.flags &= !RCPT_QUARANTINED;
+                rcpt.flags |= RCPT_STATUS_CHANGED;
+                found = true;
+            }
+        }
+        if !found {
+            return false;
+        }
+
+        // Released messages are delivered right away
+        let now = Timestamp::now();
+        for domain in &mut message.domains {
+            if matches!(
+                domain.status,
+                Status::Scheduled | Status::TemporaryFailure(_)
+            ) {
+                domain.retry.due = now;
+                domain.changed = true;
+            }
+        }
+
+        tracing::info!(
+            context = "queue",
+            event = "release",
+            id = queue_id,
+            "Quarantined message released."
+        );
+
+        message.save_changes().await;
+        self.on_hold.retain(|oh| oh.message != queue_id);
+        if let Some(next_event) = message.next_event() {
+            self.scheduled.push(Schedule {
+                due: next_event,
+                inner: queue_id,
+            });
+        }
+        true
+    }
+
     pub fn next_due(&mut self) -> Option<Box<Message>> {
         let item = self.scheduled.peek()?;
-        if item.due <= Instant::now() {
+        if item.due <= Timestamp::now() {
             self.scheduled
                 .pop()
                 .and_then(|i| self.messages.remove(&i.inner))
@@ -326,7 +572,7 @@ impl Queue {
     }
 
     pub fn next_on_hold(&mut self) -> Option<Box<Message>> {
-        let now = Instant::now();
+        let now = Timestamp::now();
         self.on_hold
             .iter()
             .position(|o| {
@@ -343,7 +589,7 @@ impl Queue {
             .peek()
             .map(|item| {
                 item.due
-                    .checked_duration_since(Instant::now())
+                    .checked_duration_since(Timestamp::now())
                     .unwrap_or(self.short_wait)
             })
             .unwrap_or(self.long_wait)
@@ -351,8 +597,8 @@ impl Queue {
 }
 
 impl Message {
-    pub fn next_event(&self) -> Option<Instant> {
-        let mut next_event = Instant::now();
+    pub fn next_event(&self) -> Option<Timestamp> {
+        let mut next_event = Timestamp::now();
         let mut has_events = false;
 
         for domain in &self.domains {
@@ -380,8 +626,8 @@ impl Message {
         }
     }
 
-    pub fn next_delivery_event(&self) -> Instant {
-        let mut next_delivery = Instant::now();
+    pub fn next_delivery_event(&self) -> Timestamp {
+        let mut next_delivery = Timestamp::now();
 
         for (pos, domain) in self
             .domains
@@ -397,7 +643,7 @@ impl Message {
         next_delivery
     }
 
-    pub fn next_event_after(&self, instant: Instant) -> Option<Instant> {
+    pub fn next_event_after(&self, time: Timestamp) -> Option<Timestamp> {
         let mut next_event = None;
 
         for domain in &self.domains {
@@ -405,21 +651,21 @@ impl Message {
                 domain.status,
                 Status::Scheduled | Status::TemporaryFailure(_)
             ) {
-                if domain.retry.due > instant
+                if domain.retry.due > time
                     && next_event
                         .as_ref()
                         .map_or(true, |ne| domain.retry.due.lt(ne))
                 {
                     next_event = domain.retry.due.into();
                 }
-                if domain.notify.due > instant
+                if domain.notify.due > time
                     && next_event
                         .as_ref()
                         .map_or(true, |ne| domain.notify.due.lt(ne))
                 {
                     next_event = domain.notify.due.into();
                 }
-                if domain.expires > instant
+                if domain.expires > time
                     && next_event.as_ref().map_or(true, |ne| domain.expires.lt(ne))
                 {
                     next_event = domain.expires.into();
@@ -511,6 +757,7 @@ impl QueueCore {
                 Ok(Ok(mut message)) => {
                     // Reserve quota
                     self.has_quota(&mut message).await;
+                    message.queue_load = self.load.track(&message).into();
 
                     // Schedule message
                     queue.schedule(Schedule {
@@ -521,7 +768,7 @@ impl QueueCore {
                                 "No due events found for message {}",
                                 message.path.display()
                             );
-                            Instant::now()
+                            Timestamp::now()
                         }),
                         inner: Box::new(message),
                     });
@@ -24,8 +24,12 @@
 use std::{
     fmt::Display,
     net::{IpAddr, Ipv4Addr},
+    ops::{Add, AddAssign, Sub},
     path::PathBuf,
-    sync::{atomic::AtomicUsize, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize},
+        Arc,
+    },
     time::{Duration, Instant, SystemTime},
 };
 
@@ -36,11 +40,18 @@ use utils::{
     listener::limiter::{ConcurrencyLimiter, InFlight},
 };
 
-use crate::{config::EnvelopeKey, core::management};
+use crate::{
+    config::EnvelopeKey,
+    core::{management, SMTP},
+};
 
+pub mod backpressure;
+pub mod dead_letter;
 pub mod dsn;
 pub mod manager;
+pub mod modify;
 pub mod quota;
+pub mod retry;
 pub mod serialize;
 pub mod spool;
 pub mod throttle;
@@ -52,6 +63,8 @@ pub enum Event {
     Queue(Schedule<Box<Message>>),
     Manage(management::QueueRequest),
     Done(WorkerResult),
+    Scanned(ScanResult),
+    Reload(Arc<SMTP>),
     Stop,
 }
 
@@ -62,19 +75,40 @@ pub enum WorkerResult {
     OnHold(OnHold<Box<Message>>),
 }
 
+#[derive(Debug)]
+pub struct ScanResult {
+    pub queue_id: QueueId,
+    pub schedule: Vec<(Timestamp, Timestamp)>,
+    pub verdict: ScanVerdict,
+}
+
+#[derive(Debug)]
+pub enum ScanVerdict {
+    Deliver,
+    Quarantine(String),
+    Bounce(HostResponse<ErrorDetails>),
+    Discard(String),
+}
+
 #[derive(Debug)]
 pub struct OnHold<T> {
-    pub next_due: Option<Instant>,
+    pub next_due: Option<Timestamp>,
     pub limiters: Vec<ConcurrencyLimiter>,
     pub message: T,
 }
 
 #[derive(Debug)]
 pub struct Schedule<T> {
-    pub due: Instant,
+    pub due: Timestamp,
     pub inner: T,
 }
 
+// Wall-clock time in milliseconds since the UNIX epoch. Queue schedules are kept
+// as timestamps rather than monotonic instants so they can be persisted with the
+// message and compared consistently across restarts and cluster nodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(u64);
+
 #[derive(Debug)]
 pub struct Message {
     pub id: QueueId,
@@ -86,6 +120,7 @@ pub struct Message {
     pub return_path_domain: String,
     pub recipients: Vec<Recipient>,
     pub domains: Vec<Domain>,
+    pub history: Vec<HistoryEntry>,
 
     pub flags: u64,
     pub env_id: Option<String>,
@@ -93,6 +128,7 @@ pub struct Message {
 
     pub size: usize,
     pub queue_refs: Vec<UsedQuota>,
+    pub queue_load: Option<QueueLoadRef>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -100,8 +136,10 @@ pub struct Domain {
     pub domain: String,
     pub retry: Schedule<u32>,
     pub notify: Schedule<u32>,
-    pub expires: Instant,
+    pub expires: Timestamp,
     pub status: Status<(), Error>,
+    // Attempts made since the retry class of the status last changed
+    pub class_attempt: u32,
     pub disable_tls: bool,
     pub changed: bool,
 }
@@ -116,10 +154,30 @@ pub struct Recipient {
     pub orcpt: Option<String>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub domain_idx: usize,
+    pub time: u64,
+    pub mx: String,
+    pub remote_ip: String,
+    pub tls: String,
+    pub status: Status<String, String>,
+    pub changed: bool,
+}
+
 pub const RCPT_DSN_SENT: u64 = 1 << 32;
 pub const RCPT_STATUS_CHANGED: u64 = 2 << 32;
+pub const RCPT_EVENT_SENT: u64 = 4 << 32;
+pub const RCPT_DSN_RELAYED: u64 = 8 << 32;
+pub const RCPT_OUTCOME_RECORDED: u64 = 16 << 32;
+pub const RCPT_SUPPRESSED: u64 = 32 << 32;
+pub const RCPT_BOUNCE_RECORDED: u64 = 64 << 32;
+pub const RCPT_SCAN_PENDING: u64 = 128 << 32;
+pub const RCPT_QUARANTINED: u64 = 256 << 32;
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub const MAIL_AUTH_FAILED: u64 = 1 << 32;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Status<T, E> {
     #[serde(rename = "scheduled")]
     Scheduled,
@@ -131,13 +189,13 @@ pub enum Status<T, E> {
     PermanentFailure(E),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HostResponse<T> {
     pub hostname: T,
     pub response: Response<String>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     DnsError(String),
     UnexpectedResponse(HostResponse<ErrorDetails>),
@@ -148,9 +206,22 @@ pub enum Error {
     RateLimited,
     ConcurrencyLimited,
     Io(String),
+    NullMx,
+    // Errors that occurred while waiting for the greeting of the remote host
+    Greeting(Box<Error>),
 }
 
-#[derive(Debug, PartialEq, Eq)]
+impl Error {
+    // Returns the error without the stage it occurred in
+    pub fn inner(&self) -> &Error {
+        match self {
+            Error::Greeting(err) => err.inner(),
+            err => err,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ErrorDetails {
     pub entity: String,
     pub details: String,
@@ -185,6 +256,28 @@ impl PartialEq for UsedQuota {
 
 impl Eq for UsedQuota {}
 
+#[derive(Debug, Default)]
+pub struct QueueLoad {
+    pub messages: AtomicUsize,
+    pub size: AtomicUsize,
+    pub lag: AtomicU64,
+    pub deferring: AtomicBool,
+}
+
+#[derive(Debug)]
+pub struct QueueLoadRef {
+    size: usize,
+    load: Arc<QueueLoad>,
+}
+
+impl PartialEq for QueueLoadRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.size == other.size
+    }
+}
+
+impl Eq for QueueLoadRef {}
+
 impl<T> Ord for Schedule<T> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         other.due.cmp(&self.due)
@@ -208,14 +301,14 @@ impl<T> Eq for Schedule<T> {}
 impl<T: Default> Schedule<T> {
     pub fn now() -> Self {
         Schedule {
-            due: Instant::now(),
+            due: Timestamp::now(),
             inner: T::default(),
         }
     }
 
     pub fn later(duration: Duration) -> Self {
         Schedule {
-            due: Instant::now() + duration,
+            due: Timestamp::now() + duration,
             inner: T::default(),
         }
     }
@@ -363,28 +456,66 @@ impl<'x> KeyLookup for RecipientDomain<'x> {
     }
 }
 
-#[inline(always)]
-pub fn instant_to_timestamp(now: Instant, time: Instant) -> u64 {
-    SystemTime::now()
-        .duration_since(SystemTime::UNIX_EPOCH)
-        .map_or(0, |d| d.as_secs())
-        + time.checked_duration_since(now).map_or(0, |d| d.as_secs())
+impl Timestamp {
+    #[inline(always)]
+    pub fn now() -> Self {
+        Timestamp(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+        )
+    }
+
+    #[inline(always)]
+    pub fn from_secs(secs: u64) -> Self {
+        Timestamp(secs.saturating_mul(1000))
+    }
+
+    #[inline(always)]
+    pub fn as_secs(&self) -> u64 {
+        self.0 / 1000
+    }
+
+    #[inline(always)]
+    pub fn duration_since(&self, earlier: Timestamp) -> Duration {
+        Duration::from_millis(self.0.saturating_sub(earlier.0))
+    }
+
+    #[inline(always)]
+    pub fn checked_duration_since(&self, earlier: Timestamp) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration::from_millis)
+    }
+}
+
+impl Add<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        Timestamp(self.0.saturating_add(rhs.as_millis() as u64))
+    }
 }
 
-pub trait InstantFromTimestamp {
-    fn to_instant(&self) -> Instant;
+impl AddAssign<Duration> for Timestamp {
+    fn add_assign(&mut self, rhs: Duration) {
+        *self = *self + rhs;
+    }
 }
 
-impl InstantFromTimestamp for u64 {
-    fn to_instant(&self) -> Instant {
-        let timestamp = *self;
-        let current_timestamp = SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)
-            .map_or(0, |d| d.as_secs());
-        if timestamp > current_timestamp {
-            Instant::now() + Duration::from_secs(timestamp - current_timestamp)
+impl Sub<Duration> for Timestamp {
+    type Output = Timestamp;
+
+    fn sub(self, rhs: Duration) -> Self::Output {
+        Timestamp(self.0.saturating_sub(rhs.as_millis() as u64))
+    }
+}
+
+impl From<Instant> for Timestamp {
+    fn from(instant: Instant) -> Self {
+        let now = Instant::now();
+        if instant > now {
+            Timestamp::now() + (instant - now)
         } else {
-            Instant::now()
+            Timestamp::now() - (now - instant)
         }
     }
 }
@@ -453,6 +584,10 @@ impl Display for Error {
             Error::Io(err) => {
                 write!(f, "Queue error: {err}")
             }
+            Error::NullMx => {
+                write!(f, "Domain does not accept messages (null MX)")
+            }
+            Error::Greeting(err) => err.fmt(f),
         }
     }
 }
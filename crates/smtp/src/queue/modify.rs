@@ -0,0 +1,315 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Display;
+
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::core::SMTP;
+
+use super::{Message, Status};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueueModification {
+    AddRecipient(String),
+    RemoveRecipient(String),
+    AddHeader { name: String, value: String },
+    ReplaceHeader { name: String, value: String },
+}
+
+impl Message {
+    // Applies administrative changes to a queued message and rewrites its queue file.
+    pub async fn modify(
+        &mut self,
+        core: &SMTP,
+        modifications: &[QueueModification],
+    ) -> Result<(), String> {
+        let span = tracing::info_span!("modify", "id" = self.id);
+
+        // Validate envelope changes before applying them
+        let mut pending = self
+            .recipients
+            .iter()
+            .filter(|rcpt| matches!(rcpt.status, Status::Scheduled | Status::TemporaryFailure(_)))
+            .map(|rcpt| rcpt.address_lcase.clone())
+            .collect::<Vec<_>>();
+        for modification in modifications {
+            match modification {
+                QueueModification::AddRecipient(address) => {
+                    pending.push(address.to_lowercase());
+                }
+                QueueModification::RemoveRecipient(address) => {
+                    let address_lcase = address.to_lowercase();
+                    if !pending.iter().any(|rcpt| rcpt == &address_lcase) {
+                        return Err(format!("No pending recipient {address:?} found."));
+                    }
+                    pending.retain(|rcpt| rcpt != &address_lcase);
+                }
+                _ => (),
+            }
+        }
+        if pending.is_empty() {
+            return Err("Message has no pending recipients left.".to_string());
+        }
+        let raw_message = fs::read(&self.path)
+            .await
+            .map_err(|err| format!("Failed to read {}: {}", self.path.display(), err))?;
+        let raw_message = raw_message
+            .get(..self.size)
+            .ok_or_else(|| format!("Queue file {} is truncated.", self.path.display()))?;
+
+        // Apply envelope changes
+        for modification in modifications {
+            match modification {
+                QueueModification::AddRecipient(address) => {
+                    let address_lcase = address.to_lowercase();
+                    if !self
+                        .recipients
+                        .iter()
+                        .any(|rcpt| rcpt.address_lcase == address_lcase)
+                    {
+                        self.add_recipient(address.as_str(), &core.queue.config)
+                            .await;
+                    }
+                }
+                QueueModification::RemoveRecipient(address) => {
+                    let address_lcase = address.to_lowercase();
+                    self.recipients.retain(|rcpt| {
+                        rcpt.address_lcase != address_lcase
+                            || !matches!(
+                                rcpt.status,
+                                Status::Scheduled | Status::TemporaryFailure(_)
+                            )
+                    });
+                }
+                _ => (),
+            }
+        }
+        self.remove_unused_domains();
+
+        // Apply header changes
+        let mut message = rewrite_headers(raw_message, modifications);
+
+        // Signatures covering any of the modified headers were removed, sign again
+        if message.resign {
+            if let Some(signature) = self
+                .sign(&core.mail_auth.dkim.sign, &message.contents, &span)
+                .await
+            {
+                message.contents.splice(0..0, signature);
+            }
+        }
+
+        self.write_contents(&message.contents).await?;
+
+        tracing::info!(
+            parent: &span,
+            context = "queue",
+            event = "modified",
+            id = self.id,
+            resigned = message.resign,
+            modifications = ?modifications,
+            "Queued message modified by administrator."
+        );
+
+        Ok(())
+    }
+
+    fn remove_unused_domains(&mut self) {
+        let mut domain_map = vec![None; self.domains.len()];
+        let mut domain_idx = 0;
+        for (pos, mapped_idx) in domain_map.iter_mut().enumerate() {
+            if self.recipients.iter().any(|rcpt| rcpt.domain_idx == pos) {
+                *mapped_idx = Some(domain_idx);
+                domain_idx += 1;
+            }
+        }
+        let mut pos = 0;
+        self.domains.retain(|_| {
+            pos += 1;
+            domain_map[pos - 1].is_some()
+        });
+        for rcpt in &mut self.recipients {
+            rcpt.domain_idx = domain_map[rcpt.domain_idx].unwrap_or_default();
+        }
+        self.history.retain_mut(|entry| {
+            if let Some(domain_idx) = domain_map[entry.domain_idx] {
+                entry.domain_idx = domain_idx;
+                true
+            } else {
+                false
+            }
+        });
+    }
+
+    // Replaces the queue file with new contents and up-to-date metadata
+    async fn write_contents(&mut self, contents: &[u8]) -> Result<(), String> {
+        self.size = contents.len();
+        let mut path = self.path.clone();
+        path.set_file_name(self.file_name());
+        let tmp_path = path.with_extension("tmp");
+
+        let mut file = fs::File::create(&tmp_path)
+            .await
+            .map_err(|err| file_error("create", &tmp_path, err))?;
+        for bytes in [contents, &self.serialize()] {
+            file.write_all(bytes)
+                .await
+                .map_err(|err| file_error("write to", &tmp_path, err))?;
+        }
+        file.flush()
+            .await
+            .map_err(|err| file_error("flush", &tmp_path, err))?;
+        fs::rename(&tmp_path, &path)
+            .await
+            .map_err(|err| file_error("rename", &tmp_path, err))?;
+
+        if path != self.path {
+            self.remove().await;
+            self.path = path;
+        }
+
+        // Changes are included in the new metadata
+        for domain in &mut self.domains {
+            domain.changed = false;
+        }
+        for rcpt in &mut self.recipients {
+            rcpt.flags &= !super::RCPT_STATUS_CHANGED;
+        }
+        for entry in &mut self.history {
+            entry.changed = false;
+        }
+
+        Ok(())
+    }
+}
+
+struct RewrittenMessage {
+    contents: Vec<u8>,
+    resign: bool,
+}
+
+fn rewrite_headers(raw_message: &[u8], modifications: &[QueueModification]) -> RewrittenMessage {
+    let mut contents = Vec::with_capacity(raw_message.len() + 256);
+    let mut modified_names = Vec::new();
+    let mut replaced_names = Vec::new();
+    for modification in modifications {
+        match modification {
+            QueueModification::AddHeader { name, value } => {
+                modified_names.push(name.as_str());
+                write_header(&mut contents, name, value);
+            }
+            QueueModification::ReplaceHeader { name, value } => {
+                modified_names.push(name.as_str());
+                replaced_names.push(name.as_str());
+                write_header(&mut contents, name, value);
+            }
+            _ => (),
+        }
+    }
+    if modified_names.is_empty() {
+        return RewrittenMessage {
+            contents: raw_message.to_vec(),
+            resign: false,
+        };
+    }
+
+    let mut resign = false;
+    let (headers, body) = split_headers(raw_message);
+    for header in headers {
+        let (name, value) = header
+            .iter()
+            .position(|&ch| ch == b':')
+            .map(|pos| (&header[..pos], &header[pos + 1..]))
+            .unwrap_or((header, b""));
+        let name = std::str::from_utf8(name).unwrap_or_default().trim();
+
+        if replaced_names
+            .iter()
+            .any(|replaced| replaced.eq_ignore_ascii_case(name))
+        {
+            continue;
+        } else if name.eq_ignore_ascii_case("DKIM-Signature") && signs_any(value, &modified_names) {
+            resign = true;
+            continue;
+        }
+        contents.extend_from_slice(header);
+    }
+    contents.extend_from_slice(body);
+
+    RewrittenMessage { contents, resign }
+}
+
+fn write_header(buf: &mut Vec<u8>, name: &str, value: &str) {
+    buf.extend_from_slice(name.trim().as_bytes());
+    buf.extend_from_slice(b": ");
+    buf.extend_from_slice(value.trim().as_bytes());
+    buf.extend_from_slice(b"\r\n");
+}
+
+// Splits a message into its header fields, including any folded lines, and the body
+fn split_headers(raw_message: &[u8]) -> (Vec<&[u8]>, &[u8]) {
+    let mut headers: Vec<&[u8]> = Vec::new();
+    let mut header_start = None;
+    let mut pos = 0;
+
+    while pos < raw_message.len() {
+        let line_end = raw_message[pos..]
+            .iter()
+            .position(|&ch| ch == b'\n')
+            .map_or(raw_message.len(), |end| pos + end + 1);
+        let line = &raw_message[pos..line_end];
+
+        if line == b"\r\n" || line == b"\n" {
+            break;
+        } else if !matches!(line.first(), Some(b' ' | b'\t')) {
+            if let Some(start) = header_start {
+                headers.push(&raw_message[start..pos]);
+            }
+            header_start = Some(pos);
+        }
+        pos = line_end;
+    }
+    if let Some(start) = header_start {
+        headers.push(&raw_message[start..pos]);
+    }
+
+    (headers, &raw_message[pos..])
+}
+
+// Returns true if the DKIM-Signature h= tag covers any of the header names
+fn signs_any(signature: &[u8], names: &[&str]) -> bool {
+    std::str::from_utf8(signature)
+        .unwrap_or_default()
+        .split(';')
+        .filter_map(|tag| tag.trim().strip_prefix("h="))
+        .flat_map(|signed| signed.split(':'))
+        .any(|signed| {
+            let signed = signed.trim();
+            names.iter().any(|name| name.eq_ignore_ascii_case(signed))
+        })
+}
+
+fn file_error(action: &str, path: &std::path::Path, err: impl Display) -> String {
+    format!("Failed to {} {}: {}", action, path.display(), err)
+}
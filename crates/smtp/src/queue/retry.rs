@@ -0,0 +1,119 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use rand::Rng;
+use utils::config::KeyLookup;
+
+use crate::config::{EnvelopeKey, QueueConfig, RetryClass};
+
+use super::{Error, Status};
+
+// Retry schedule in effect for a domain, with the per error class overrides
+// evaluated for the same envelope.
+#[derive(Debug, Default)]
+pub struct RetryPolicy<'x> {
+    pub schedule: &'x [Duration],
+    pub overrides: Vec<(RetryClass, &'x [Duration])>,
+    pub jitter: f64,
+    pub max: Option<Duration>,
+}
+
+impl QueueConfig {
+    pub async fn retry_policy(
+        &self,
+        envelope: &impl KeyLookup<Key = EnvelopeKey>,
+    ) -> RetryPolicy<'_> {
+        let mut overrides = Vec::with_capacity(self.retry_class.len());
+        for (class, schedule) in &self.retry_class {
+            overrides.push((*class, schedule.eval(envelope).await.as_slice()));
+        }
+
+        RetryPolicy {
+            schedule: self.retry.eval(envelope).await,
+            overrides,
+            jitter: *self.retry_jitter.eval(envelope).await,
+            max: *self.retry_max.eval(envelope).await,
+        }
+    }
+}
+
+impl RetryPolicy<'_> {
+    // Returns the interval to wait before the given attempt number, using the
+    // schedule configured for the class of the error that caused the failure.
+    pub fn interval(&self, status: &Status<(), Error>, attempt: u32) -> Duration {
+        let schedule = status
+            .retry_class()
+            .and_then(|class| {
+                self.overrides
+                    .iter()
+                    .find_map(|(c, schedule)| (*c == class).then_some(*schedule))
+            })
+            .unwrap_or(self.schedule);
+        let mut interval = schedule
+            .get(std::cmp::min(
+                attempt as usize,
+                schedule.len().saturating_sub(1),
+            ))
+            .copied()
+            .unwrap_or_default();
+
+        if self.jitter > 0.0 {
+            interval =
+                interval.mul_f64(1.0 + rand::thread_rng().gen_range(-self.jitter..=self.jitter));
+        }
+        if let Some(max) = self.max {
+            interval = std::cmp::min(interval, max);
+        }
+
+        interval
+    }
+}
+
+impl Error {
+    pub fn retry_class(&self) -> Option<RetryClass> {
+        match self {
+            Error::ConnectionError(_) | Error::Io(_) => RetryClass::Connection.into(),
+            Error::UnexpectedResponse(response) if response.response.esc == [4, 7, 28] => {
+                RetryClass::RateLimit.into()
+            }
+            Error::UnexpectedResponse(_) => RetryClass::Response.into(),
+            Error::RateLimited | Error::ConcurrencyLimited => RetryClass::RateLimit.into(),
+            Error::DnsError(_) | Error::NullMx => RetryClass::Dns.into(),
+            Error::TlsError(_) | Error::DaneError(_) | Error::MtaStsError(_) => {
+                RetryClass::Tls.into()
+            }
+            Error::Greeting(_) => RetryClass::Greeting.into(),
+        }
+    }
+}
+
+impl Status<(), Error> {
+    pub fn retry_class(&self) -> Option<RetryClass> {
+        match self {
+            Status::TemporaryFailure(err) => err.retry_class(),
+            _ => None,
+        }
+    }
+}
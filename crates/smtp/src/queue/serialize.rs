@@ -23,17 +23,17 @@
 
 use mail_auth::common::base32::Base32Reader;
 use smtp_proto::Response;
+use std::fmt::Write;
 use std::io::SeekFrom;
 use std::path::PathBuf;
 use std::slice::Iter;
-use std::{fmt::Write, time::Instant};
 use tokio::fs;
 use tokio::fs::File;
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
 use super::{
-    instant_to_timestamp, Domain, DomainPart, Error, ErrorDetails, HostResponse,
-    InstantFromTimestamp, Message, Recipient, Schedule, Status, RCPT_STATUS_CHANGED,
+    Domain, DomainPart, Error, ErrorDetails, HistoryEntry, HostResponse, Message, Recipient,
+    Schedule, Status, Timestamp, RCPT_STATUS_CHANGED,
 };
 
 pub trait QueueSerializer: Sized {
@@ -59,11 +59,10 @@ impl Message {
         self.priority.serialize(&mut buf);
 
         // Serialize domains
-        let now = Instant::now();
         self.domains.len().serialize(&mut buf);
         for domain in &self.domains {
             domain.domain.serialize(&mut buf);
-            (instant_to_timestamp(now, domain.expires) as usize).serialize(&mut buf);
+            domain.expires.serialize(&mut buf);
         }
 
         // Serialize recipients
@@ -76,7 +75,7 @@ impl Message {
 
         // Serialize domain status
         for (idx, domain) in self.domains.iter().enumerate() {
-            domain.serialize(idx, now, &mut buf);
+            domain.serialize(idx, &mut buf);
         }
 
         // Serialize recipient status
@@ -84,17 +83,21 @@ impl Message {
             rcpt.serialize(idx, &mut buf);
         }
 
+        // Serialize delivery history
+        for (idx, entry) in self.history.iter().enumerate() {
+            entry.serialize(idx, &mut buf);
+        }
+
         buf.into_bytes()
     }
 
     pub fn serialize_changes(&mut self) -> Vec<u8> {
-        let now = Instant::now();
         let mut buf = String::with_capacity(128);
 
         for (idx, domain) in self.domains.iter_mut().enumerate() {
             if domain.changed {
                 domain.changed = false;
-                domain.serialize(idx, now, &mut buf);
+                domain.serialize(idx, &mut buf);
             }
         }
 
@@ -105,6 +108,13 @@ impl Message {
             }
         }
 
+        for (idx, entry) in self.history.iter_mut().enumerate() {
+            if entry.changed {
+                entry.changed = false;
+                entry.serialize(idx, &mut buf);
+            }
+        }
+
         buf.into_bytes()
     }
 
@@ -198,7 +208,9 @@ impl Message {
             size: 0,
             recipients: vec![],
             domains: vec![],
+            history: vec![],
             queue_refs: vec![],
+            queue_load: None,
         };
 
         // Deserialize domains
@@ -207,10 +219,11 @@ impl Message {
         for _ in 0..num_domains {
             message.domains.push(Domain {
                 domain: String::deserialize(&mut bytes)?,
-                expires: Instant::deserialize(&mut bytes)?,
+                expires: Timestamp::deserialize(&mut bytes)?,
                 retry: Schedule::now(),
                 notify: Schedule::now(),
                 status: Status::Scheduled,
+                class_attempt: 0,
                 disable_tls: false,
                 changed: false,
             });
@@ -250,6 +263,9 @@ impl Message {
                         Schedule::deserialize(&mut bytes),
                         Status::deserialize(&mut bytes),
                     ) {
+                        // Messages queued before retry classes were tracked
+                        // continue with their current schedule position
+                        domain.class_attempt = retry.inner;
                         domain.retry = retry;
                         domain.notify = notify;
                         domain.status = status;
@@ -257,6 +273,15 @@ impl Message {
                         break;
                     }
                 }
+                b'A' => {
+                    if let (Some(domain), Some(class_attempt)) =
+                        (message.domains.get_mut(idx), usize::deserialize(&mut bytes))
+                    {
+                        domain.class_attempt = class_attempt as u32;
+                    } else {
+                        break;
+                    }
+                }
                 b'R' => {
                     if let (Some(rcpt), Some(flags), Some(status)) = (
                         message.recipients.get_mut(idx),
@@ -269,6 +294,35 @@ impl Message {
                         break;
                     }
                 }
+                b'H' if idx == message.history.len() => {
+                    if let (
+                        Some(domain_idx),
+                        Some(time),
+                        Some(mx),
+                        Some(remote_ip),
+                        Some(tls),
+                        Some(status),
+                    ) = (
+                        usize::deserialize(&mut bytes),
+                        usize::deserialize(&mut bytes),
+                        String::deserialize(&mut bytes),
+                        String::deserialize(&mut bytes),
+                        String::deserialize(&mut bytes),
+                        Status::deserialize(&mut bytes),
+                    ) {
+                        message.history.push(HistoryEntry {
+                            domain_idx,
+                            time: time as u64,
+                            mx,
+                            remote_ip,
+                            tls,
+                            status,
+                            changed: false,
+                        });
+                    } else {
+                        break;
+                    }
+                }
                 _ => break,
             }
         }
@@ -449,30 +503,25 @@ impl QueueSerializer for &str {
     }
 }
 
-impl QueueSerializer for Instant {
+impl QueueSerializer for Timestamp {
     fn serialize(&self, buf: &mut String) {
-        let _ = write!(buf, "{} ", instant_to_timestamp(Instant::now(), *self),);
+        let _ = write!(buf, "{} ", self.as_secs());
     }
 
     fn deserialize(bytes: &mut Iter<'_, u8>) -> Option<Self> {
-        (usize::deserialize(bytes)? as u64).to_instant().into()
+        Timestamp::from_secs(usize::deserialize(bytes)? as u64).into()
     }
 }
 
 impl QueueSerializer for Schedule<u32> {
     fn serialize(&self, buf: &mut String) {
-        let _ = write!(
-            buf,
-            "{} {} ",
-            self.inner,
-            instant_to_timestamp(Instant::now(), self.due),
-        );
+        let _ = write!(buf, "{} {} ", self.inner, self.due.as_secs());
     }
 
     fn deserialize(bytes: &mut Iter<'_, u8>) -> Option<Self> {
         Schedule {
             inner: usize::deserialize(bytes)? as u32,
-            due: Instant::deserialize(bytes)?,
+            due: Timestamp::deserialize(bytes)?,
         }
         .into()
     }
@@ -515,6 +564,13 @@ impl QueueSerializer for Error {
                 buf.push('8');
                 e.serialize(buf);
             }
+            Error::NullMx => {
+                buf.push('9');
+            }
+            Error::Greeting(e) => {
+                buf.push('G');
+                e.serialize(buf);
+            }
         }
     }
 
@@ -529,6 +585,8 @@ impl QueueSerializer for Error {
             b'6' => Error::RateLimited.into(),
             b'7' => Error::ConcurrencyLimited.into(),
             b'8' => Error::Io(String::deserialize(bytes)?).into(),
+            b'9' => Error::NullMx.into(),
+            b'G' => Error::Greeting(Box::new(Error::deserialize(bytes)?)).into(),
             _ => None,
         }
     }
@@ -543,17 +601,18 @@ impl QueueSerializer for () {
 }
 
 impl Domain {
-    fn serialize(&self, idx: usize, now: Instant, buf: &mut String) {
+    fn serialize(&self, idx: usize, buf: &mut String) {
         let _ = write!(
             buf,
             "D{} {} {} {} {} ",
             idx,
             self.retry.inner,
-            instant_to_timestamp(now, self.retry.due),
+            self.retry.due.as_secs(),
             self.notify.inner,
-            instant_to_timestamp(now, self.notify.due)
+            self.notify.due.as_secs()
         );
         self.status.serialize(buf);
+        let _ = write!(buf, "A{} {} ", idx, self.class_attempt);
     }
 }
 
@@ -563,3 +622,13 @@ impl Recipient {
         self.status.serialize(buf);
     }
 }
+
+impl HistoryEntry {
+    fn serialize(&self, idx: usize, buf: &mut String) {
+        let _ = write!(buf, "H{} {} {} ", idx, self.domain_idx, self.time);
+        self.mx.serialize(buf);
+        self.remote_ip.serialize(buf);
+        self.tls.serialize(buf);
+        self.status.serialize(buf);
+    }
+}
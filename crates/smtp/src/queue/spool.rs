@@ -26,7 +26,6 @@ use mail_auth::common::base32::Base32Writer;
 use mail_auth::common::headers::Writer;
 use std::path::PathBuf;
 use std::sync::atomic::Ordering;
-use std::time::Instant;
 use std::time::{Duration, SystemTime};
 use tokio::fs::OpenOptions;
 use tokio::{fs, io::AsyncWriteExt};
@@ -34,7 +33,7 @@ use tokio::{fs, io::AsyncWriteExt};
 use crate::config::QueueConfig;
 use crate::core::QueueCore;
 
-use super::{Domain, Event, Message, Recipient, Schedule, SimpleEnvelope, Status};
+use super::{Domain, Event, Message, Recipient, Schedule, SimpleEnvelope, Status, Timestamp};
 
 impl QueueCore {
     pub async fn queue_message(
@@ -61,11 +60,7 @@ impl QueueCore {
         let _ = fs::create_dir(&message.path).await;
 
         // Encode file name
-        let mut encoder = Base32Writer::with_capacity(20);
-        encoder.write(&message.id.to_le_bytes()[..]);
-        encoder.write(&(message.size as u32).to_le_bytes()[..]);
-        let mut file = encoder.finalize();
-        file.push_str(".msg");
+        let file = message.file_name();
         message.path.push(file);
 
         // Serialize metadata
@@ -135,6 +130,11 @@ impl QueueCore {
             "Message queued for delivery."
         );
 
+        // Account the message towards the queue load
+        if message.queue_load.is_none() {
+            message.queue_load = self.load.track(&message).into();
+        }
+
         // Queue the message
         if self
             .tx
@@ -184,14 +184,26 @@ impl Message {
             return_path_domain: return_path_domain.into(),
             recipients: Vec::with_capacity(1),
             domains: Vec::with_capacity(1),
+            history: vec![],
             flags: 0,
             env_id: None,
             priority: 0,
             size: 0,
             queue_refs: vec![],
+            queue_load: None,
         })
     }
 
+    // The file name encodes the queue id and the size of the message contents
+    pub fn file_name(&self) -> String {
+        let mut encoder = Base32Writer::with_capacity(20);
+        encoder.write(&self.id.to_le_bytes()[..]);
+        encoder.write(&(self.size as u32).to_le_bytes()[..]);
+        let mut file = encoder.finalize();
+        file.push_str(".msg");
+        file
+    }
+
     pub async fn add_recipient_parts(
         &mut self,
         rcpt: impl Into<String>,
@@ -213,8 +225,9 @@ impl Message {
                     domain: rcpt_domain,
                     retry: Schedule::now(),
                     notify: Schedule::later(expires + Duration::from_secs(10)),
-                    expires: Instant::now() + expires,
+                    expires: Timestamp::now() + expires,
                     status: Status::Scheduled,
+                    class_attempt: 0,
                     disable_tls: false,
                     changed: false,
                 });
@@ -21,8 +21,6 @@
  * for more details.
 */
 
-use std::time::Instant;
-
 use dashmap::mapref::entry::Entry;
 use utils::{
     config::KeyLookup,
@@ -34,12 +32,12 @@ use crate::{
     core::{throttle::Limiter, QueueCore},
 };
 
-use super::{Domain, Status};
+use super::{Domain, Status, Timestamp};
 
 #[derive(Debug)]
 pub enum Error {
     Concurrency { limiter: ConcurrencyLimiter },
-    Rate { retry_at: Instant },
+    Rate { retry_at: Timestamp },
 }
 
 impl QueueCore {
@@ -81,7 +79,7 @@ impl QueueCore {
                                 "Queue rate limit exceeded."
                             );
                             return Err(Error::Rate {
-                                retry_at: limiter.retry_at(),
+                                retry_at: limiter.retry_at().into(),
                             });
                         }
                     }
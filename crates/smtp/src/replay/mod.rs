@@ -0,0 +1,152 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::net::IpAddr;
+
+use mail_auth::{DkimOutput, DkimResult};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    anomaly::location_of,
+    core::{DkimReplayCore, Session},
+    webhook::now,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DkimReplayKey {
+    pub domain: String,
+    pub selector: String,
+    pub body_hash: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DkimReplayEntry {
+    pub networks: Vec<String>,
+    pub count: u64,
+    pub first_seen: u64,
+    pub last_seen: u64,
+}
+
+impl DkimReplayEntry {
+    pub fn is_replayed(&self, max_networks: usize) -> bool {
+        self.networks.len() > max_networks
+    }
+}
+
+impl DkimReplayCore {
+    // Records a passing signature and returns whether it has been seen
+    // from more unrelated networks than allowed within the current window.
+    pub fn record(&self, domain: &str, selector: &str, body_hash: &[u8], ip: IpAddr) -> bool {
+        if !self.config.enable {
+            return false;
+        }
+
+        let now = now();
+        let network = self.network_of(ip);
+        let mut entry = self
+            .entries
+            .entry(DkimReplayKey {
+                domain: domain.to_lowercase(),
+                selector: selector.to_lowercase(),
+                body_hash: body_hash.to_vec(),
+            })
+            .or_default();
+        if entry.first_seen + self.config.window.as_secs() < now {
+            *entry = DkimReplayEntry {
+                first_seen: now,
+                ..Default::default()
+            };
+        }
+        entry.count += 1;
+        entry.last_seen = now;
+
+        // Stop tracking networks once the signature is known to be replayed
+        if entry.networks.len() <= self.config.max_networks && !entry.networks.contains(&network) {
+            entry.networks.push(network);
+        }
+
+        entry.is_replayed(self.config.max_networks)
+    }
+
+    pub fn get(&self, domain: &str, selector: &str, body_hash: &[u8]) -> Option<DkimReplayEntry> {
+        self.entries
+            .get(&DkimReplayKey {
+                domain: domain.to_lowercase(),
+                selector: selector.to_lowercase(),
+                body_hash: body_hash.to_vec(),
+            })
+            .map(|entry| entry.clone())
+    }
+
+    // Networks are identified by their autonomous system when the GeoIP
+    // database is available, otherwise by their address prefix.
+    fn network_of(&self, ip: IpAddr) -> String {
+        self.geoip
+            .lookup(ip)
+            .asn
+            .map(|asn| format!("AS{asn}"))
+            .unwrap_or_else(|| location_of(ip))
+    }
+
+    // Removes signatures that were first seen outside the current window
+    pub fn cleanup(&self) {
+        let expires = now().saturating_sub(self.config.window.as_secs());
+        self.entries.retain(|_, entry| entry.first_seen >= expires);
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Session<T> {
+    pub fn is_dkim_replay(&self, dkim_output: &[DkimOutput<'_>]) -> bool {
+        if !self.core.dkim_replay.config.enable || !self.data.authenticated_as.is_empty() {
+            return false;
+        }
+
+        let mut is_replay = false;
+        for signature in dkim_output.iter().filter_map(|output| {
+            if matches!(output.result(), DkimResult::Pass) {
+                output.signature()
+            } else {
+                None
+            }
+        }) {
+            if self.core.dkim_replay.record(
+                signature.domain(),
+                signature.selector(),
+                &signature.bh,
+                self.data.remote_ip,
+            ) {
+                tracing::info!(
+                    parent: &self.span,
+                    context = "dkim",
+                    event = "replay",
+                    domain = signature.domain(),
+                    selector = signature.selector(),
+                    remote_ip = %self.data.remote_ip,
+                    "DKIM signature replayed from an unrelated network."
+                );
+                is_replay = true;
+            }
+        }
+        is_replay
+    }
+}
@@ -40,7 +40,7 @@ use tokio::{
 use crate::{
     config::AggregateFrequency,
     core::{Session, SMTP},
-    queue::{DomainPart, InstantFromTimestamp, RecipientDomain, Schedule},
+    queue::{DomainPart, RecipientDomain, Schedule, Timestamp},
 };
 
 use super::{
@@ -472,7 +472,7 @@ impl Scheduler {
                 let deliver_at = created + event.interval.as_secs();
 
                 self.main.push(Schedule {
-                    due: deliver_at.to_instant(),
+                    due: Timestamp::from_secs(deliver_at),
                     inner: e.key().clone(),
                 });
                 let path = core
@@ -47,7 +47,7 @@ use tokio::{
 use crate::{
     config::AggregateFrequency,
     core::{management::ReportRequest, worker::SpawnCleanup, ReportCore, SMTP},
-    queue::{InstantFromTimestamp, RecipientDomain, Schedule},
+    queue::{RecipientDomain, Schedule, Timestamp},
 };
 
 use super::{dmarc::GenerateDmarcReport, tls::GenerateTlsReport, Event};
@@ -83,7 +83,7 @@ pub struct ReportPolicy<T> {
 }
 
 impl SpawnReport for mpsc::Receiver<Event> {
-    fn spawn(mut self, core: Arc<SMTP>, mut scheduler: Scheduler) {
+    fn spawn(mut self, mut core: Arc<SMTP>, mut scheduler: Scheduler) {
         tokio::spawn(async move {
             let mut last_cleanup = Instant::now();
 
@@ -156,6 +156,9 @@ impl SpawnReport for mpsc::Receiver<Event> {
                                 let _ = result_tx.send(result);
                             }
                         },
+                        Event::Reload(new_core) => {
+                            core = new_core;
+                        }
                         Event::Stop => break,
                     },
                     Ok(None) => break,
@@ -324,7 +327,7 @@ impl ReportCore {
 impl Scheduler {
     pub fn next_due(&mut self) -> Option<(ReportKey, ReportValue)> {
         let item = self.main.peek()?;
-        if item.due <= Instant::now() {
+        if item.due <= Timestamp::now() {
             let item = self.main.pop().unwrap();
             self.reports
                 .remove(&item.inner)
@@ -339,7 +342,7 @@ impl Scheduler {
             .peek()
             .map(|item| {
                 item.due
-                    .checked_duration_since(Instant::now())
+                    .checked_duration_since(Timestamp::now())
                     .unwrap_or(self.short_wait)
             })
             .unwrap_or(self.long_wait)
@@ -434,7 +437,7 @@ impl Scheduler {
                     }),
                 );
                 self.main.push(Schedule {
-                    due: (created + deliver_at.as_secs()).to_instant(),
+                    due: Timestamp::from_secs(created + deliver_at.as_secs()),
                     inner: key,
                 });
             }
@@ -450,7 +453,7 @@ impl Scheduler {
                 }
                 Entry::Vacant(e) => {
                     self.main.push(Schedule {
-                        due: (created + deliver_at.as_secs()).to_instant(),
+                        due: Timestamp::from_secs(created + deliver_at.as_secs()),
                         inner: e.key().clone(),
                     });
                     e.insert(ReportType::Tls(ReportPath {
@@ -42,7 +42,7 @@ use crate::{
     config::AggregateFrequency,
     core::SMTP,
     outbound::mta_sts::{Mode, MxPattern},
-    queue::{InstantFromTimestamp, RecipientDomain, Schedule},
+    queue::{RecipientDomain, Schedule, Timestamp},
     USER_AGENT,
 };
 
@@ -330,7 +330,7 @@ impl Scheduler {
                 let deliver_at = created + event.interval.as_secs();
 
                 self.main.push(Schedule {
-                    due: deliver_at.to_instant(),
+                    due: Timestamp::from_secs(deliver_at),
                     inner: e.key().clone(),
                 });
                 let domain = e.key().domain_name().to_string();
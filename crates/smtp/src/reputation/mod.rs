@@ -0,0 +1,347 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, time::Duration};
+
+use directory::DatabaseColumn;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncRead, io::AsyncWrite};
+
+use crate::{
+    core::{ReputationCore, Session},
+    suppression::column_integer,
+    usage::write_atomic,
+    webhook::now,
+};
+
+// Number of events after which older outcomes start to decay
+const MAX_HISTORY: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReputationEvent {
+    Accepted,
+    AuthFailure,
+    Spam,
+    InvalidRecipient,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReputationClass {
+    Unknown,
+    Good,
+    Neutral,
+    Poor,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReputationEntry {
+    pub score: f64,
+    pub events: u64,
+    pub accepted: u64,
+    pub auth_failures: u64,
+    pub spam: u64,
+    pub invalid_rcpts: u64,
+    pub last_seen: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredEntry {
+    ip: IpAddr,
+    entry: ReputationEntry,
+}
+
+impl ReputationEntry {
+    // The score is a moving average of the penalties of past outcomes, where
+    // 0 means that every message was accepted and 1 that every event was abusive.
+    pub fn add(&mut self, event: ReputationEvent, timestamp: u64) {
+        match event {
+            ReputationEvent::Accepted => self.accepted += 1,
+            ReputationEvent::AuthFailure => self.auth_failures += 1,
+            ReputationEvent::Spam => self.spam += 1,
+            ReputationEvent::InvalidRecipient => self.invalid_rcpts += 1,
+        }
+        self.events += 1;
+        self.score +=
+            (event.penalty() - self.score) / std::cmp::min(self.events, MAX_HISTORY) as f64;
+        self.last_seen = timestamp;
+    }
+
+    pub fn percent(&self) -> u64 {
+        (self.score * 100.0).round() as u64
+    }
+
+    // Rows contain the score followed by the event count, the per-outcome
+    // counters and the last time the client was seen.
+    fn from_row(row: Vec<DatabaseColumn<'static>>) -> Option<Self> {
+        let mut row = row.into_iter();
+        let score = match row.next()? {
+            DatabaseColumn::Float(score) => score,
+            DatabaseColumn::Integer(score) => score as f64,
+            DatabaseColumn::Text(score) => score.parse().ok()?,
+            _ => return None,
+        };
+        let mut next = || {
+            row.next()
+                .and_then(column_integer)
+                .unwrap_or_default()
+                .max(0) as u64
+        };
+
+        Some(ReputationEntry {
+            score,
+            events: next(),
+            accepted: next(),
+            auth_failures: next(),
+            spam: next(),
+            invalid_rcpts: next(),
+            last_seen: next(),
+        })
+    }
+}
+
+impl ReputationEvent {
+    pub fn penalty(&self) -> f64 {
+        match self {
+            ReputationEvent::Accepted => 0.0,
+            ReputationEvent::AuthFailure | ReputationEvent::Spam => 1.0,
+            ReputationEvent::InvalidRecipient => 0.5,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReputationEvent::Accepted => "accepted",
+            ReputationEvent::AuthFailure => "auth-failure",
+            ReputationEvent::Spam => "spam",
+            ReputationEvent::InvalidRecipient => "invalid-rcpt",
+        }
+    }
+}
+
+impl ReputationClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReputationClass::Unknown => "unknown",
+            ReputationClass::Good => "good",
+            ReputationClass::Neutral => "neutral",
+            ReputationClass::Poor => "poor",
+        }
+    }
+}
+
+impl ReputationCore {
+    pub async fn record(&self, ip: IpAddr, event: ReputationEvent) -> Option<ReputationEntry> {
+        if !self.config.enable {
+            None
+        } else if let Some(lookup) = &self.config.lookup {
+            // The query updates the moving average and returns the updated entry
+            match lookup
+                .record
+                .query(&[
+                    ip.to_string().into(),
+                    event.as_str().into(),
+                    event.penalty().into(),
+                    (MAX_HISTORY as i64).into(),
+                    now().into(),
+                ])
+                .await
+            {
+                Some(row) => ReputationEntry::from_row(row),
+                None => {
+                    tracing::warn!(
+                        context = "reputation",
+                        event = "error",
+                        remote_ip = %ip,
+                        "Failed to record client outcome."
+                    );
+                    None
+                }
+            }
+        } else {
+            if !self.entries.contains_key(&ip) && self.entries.len() >= self.config.max_entries {
+                self.evict();
+            }
+            let mut entry = self.entries.entry(ip).or_default();
+            entry.add(event, now());
+            Some(entry.clone())
+        }
+    }
+
+    pub async fn get(&self, ip: &IpAddr) -> Option<ReputationEntry> {
+        if !self.config.enable {
+            None
+        } else if let Some(lookup) = &self.config.lookup {
+            ReputationEntry::from_row(lookup.get.query(&[ip.to_string().into()]).await?)
+        } else {
+            self.entries.get(ip).map(|entry| entry.clone())
+        }
+    }
+
+    pub fn classify(&self, entry: Option<&ReputationEntry>) -> ReputationClass {
+        match entry {
+            Some(entry) if self.config.enable && entry.events >= self.config.min_events => {
+                let percent = entry.percent();
+                if percent <= self.config.threshold_good {
+                    ReputationClass::Good
+                } else if percent >= self.config.threshold_poor {
+                    ReputationClass::Poor
+                } else {
+                    ReputationClass::Neutral
+                }
+            }
+            _ => ReputationClass::Unknown,
+        }
+    }
+
+    // Removes clients that have not been seen within the expiry period
+    pub fn cleanup(&self) {
+        let expires = now().saturating_sub(self.config.expiry.as_secs());
+        self.entries.retain(|_, entry| entry.last_seen > expires);
+    }
+
+    // Drops the least recently seen tenth of the clients to make room for new ones
+    fn evict(&self) {
+        let mut last_seen = self
+            .entries
+            .iter()
+            .map(|entry| entry.last_seen)
+            .collect::<Vec<_>>();
+        if last_seen.is_empty() {
+            return;
+        }
+        let pos = last_seen.len() / 10;
+        let (_, cutoff, _) = last_seen.select_nth_unstable(pos);
+        let cutoff = *cutoff;
+        self.entries.retain(|_, entry| entry.last_seen > cutoff);
+    }
+
+    pub async fn read_entries(&self) {
+        let path = if let Some(path) = self.local_path() {
+            path
+        } else {
+            return;
+        };
+
+        match fs::read(path).await {
+            Ok(bytes) => match serde_json::from_slice::<Vec<StoredEntry>>(&bytes) {
+                Ok(entries) => {
+                    for entry in entries.into_iter().take(self.config.max_entries) {
+                        self.entries.insert(entry.ip, entry.entry);
+                    }
+                }
+                Err(err) => {
+                    tracing::error!(
+                        context = "reputation",
+                        event = "error",
+                        path = %path.display(),
+                        reason = %err,
+                        "Failed to parse reputation entries."
+                    );
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => {
+                tracing::error!(
+                    context = "reputation",
+                    event = "error",
+                    path = %path.display(),
+                    reason = %err,
+                    "Failed to read reputation entries."
+                );
+            }
+        }
+    }
+
+    pub async fn write_entries(&self) {
+        let path = if let Some(path) = self.local_path() {
+            path
+        } else {
+            return;
+        };
+
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| StoredEntry {
+                ip: *entry.key(),
+                entry: entry.value().clone(),
+            })
+            .collect::<Vec<_>>();
+        if let Err(err) =
+            write_atomic(path, &serde_json::to_vec(&entries).unwrap_or_default()).await
+        {
+            tracing::error!(
+                context = "reputation",
+                event = "error",
+                path = %path.display(),
+                reason = %err,
+                "Failed to write reputation entries."
+            );
+        }
+    }
+
+    pub fn flush_frequency(&self) -> Option<Duration> {
+        self.local_path().map(|_| self.config.flush_frequency)
+    }
+
+    fn local_path(&self) -> Option<&std::path::PathBuf> {
+        self.config
+            .path
+            .as_ref()
+            .filter(|_| self.config.enable && self.config.lookup.is_none())
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Session<T> {
+    // Fetches the reputation of the client once per connection, policies and
+    // scripts are evaluated against this copy.
+    pub async fn load_reputation(&mut self) {
+        if self.core.reputation.config.enable {
+            self.data.reputation = self.core.reputation.get(&self.data.remote_ip).await;
+        }
+    }
+
+    pub async fn record_reputation(&mut self, event: ReputationEvent) {
+        if self.core.reputation.config.enable {
+            tracing::debug!(
+                parent: &self.span,
+                context = "reputation",
+                event = event.as_str(),
+                remote_ip = %self.data.remote_ip,
+                "Recorded client outcome."
+            );
+            if let Some(entry) = self
+                .core
+                .reputation
+                .record(self.data.remote_ip, event)
+                .await
+            {
+                self.data.reputation = entry.into();
+            }
+        }
+    }
+
+    pub fn reputation_class(&self) -> ReputationClass {
+        self.core.reputation.classify(self.data.reputation.as_ref())
+    }
+}
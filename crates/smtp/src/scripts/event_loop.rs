@@ -37,10 +37,13 @@ use tokio::runtime::Handle;
 
 use crate::{
     core::SMTP,
-    queue::{DomainPart, InstantFromTimestamp, Message},
+    queue::{DomainPart, Message, Timestamp},
 };
 
-use super::{plugins::PluginContext, ScriptModification, ScriptParameters, ScriptResult};
+use super::{
+    plugins::{has_side_effects, PluginContext},
+    ScriptModification, ScriptParameters, ScriptResult,
+};
 
 impl SMTP {
     pub fn run_script_blocking(
@@ -51,6 +54,7 @@ impl SMTP {
         span: tracing::Span,
     ) -> ScriptResult {
         // Create filter instance
+        let dry_run = params.dry_run;
         let mut instance = self
             .sieve
             .runtime
@@ -114,6 +118,9 @@ impl SMTP {
                             }
                         }
                     }
+                    Event::Function { id, .. } if dry_run && has_side_effects(id) => {
+                        input = false.into();
+                    }
                     Event::Function { id, arguments } => {
                         input = self.run_plugin_blocking(
                             id,
@@ -139,6 +146,9 @@ impl SMTP {
                         reject_reason = reason.into();
                         input = true.into();
                     }
+                    Event::SendMessage { .. } if dry_run => {
+                        input = true.into();
+                    }
                     Event::SendMessage {
                         recipient,
                         notify,
@@ -245,7 +255,7 @@ impl SMTP {
                                 if trace {
                                     message.flags |= MAIL_BY_TRACE;
                                 }
-                                let alimit = (alimit as u64).to_instant();
+                                let alimit = Timestamp::from_secs(alimit as u64);
                                 match mode {
                                     ByMode::Notify => {
                                         for domain in &mut message.domains {
@@ -74,6 +74,22 @@ impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
             .set_variable("tls.version", tls_version)
             .set_variable("tls.cipher", tls_cipher)
             .set_variable("stage", stage);
+        if self.core.reputation.config.enable {
+            let entry = self.data.reputation.as_ref();
+            params = params
+                .set_variable("reputation", self.reputation_class().as_str())
+                .set_variable("reputation.score", entry.map_or(0, |entry| entry.percent()))
+                .set_variable("reputation.events", entry.map_or(0, |entry| entry.events));
+        }
+        if let Some(country) = &self.data.geo.country {
+            params = params.set_variable("geoip.country", country.clone());
+        }
+        if let Some(asn) = self.data.geo.asn {
+            params = params.set_variable("geoip.asn", asn as u64);
+        }
+        if let Some(asn_org) = &self.data.geo.asn_org {
+            params = params.set_variable("geoip.asn-org", asn_org.clone());
+        }
         if let Some(ip_rev) = &self.data.iprev {
             params = params.set_variable("iprev.result", ip_rev.result().as_str());
             if let Some(ptr) = ip_rev.ptr.as_ref().and_then(|addrs| addrs.first()) {
@@ -31,6 +31,7 @@ pub mod event_loop;
 pub mod exec;
 pub mod functions;
 pub mod plugins;
+pub mod shadow;
 
 #[derive(Debug)]
 pub enum ScriptResult {
@@ -55,12 +56,15 @@ pub enum ScriptModification {
         name: Arc<String>,
         value: Arc<String>,
     },
+    SpamVerdict(bool),
 }
 
+#[derive(Clone)]
 pub struct ScriptParameters {
     message: Option<Arc<Vec<u8>>>,
     variables: AHashMap<Cow<'static, str>, Variable>,
     envelope: Vec<(Envelope, Variable)>,
+    dry_run: bool,
     #[cfg(feature = "test_mode")]
     expected_variables: Option<AHashMap<String, Variable>>,
 }
@@ -71,6 +75,7 @@ impl ScriptParameters {
             variables: AHashMap::with_capacity(10),
             envelope: Vec::with_capacity(6),
             message: None,
+            dry_run: false,
             #[cfg(feature = "test_mode")]
             expected_variables: None,
         }
@@ -92,6 +97,16 @@ impl ScriptParameters {
         self
     }
 
+    // Dry runs do not send messages or run plugins with side effects
+    pub fn with_dry_run(mut self) -> Self {
+        self.dry_run = true;
+        #[cfg(feature = "test_mode")]
+        {
+            self.expected_variables = None;
+        }
+        self
+    }
+
     #[cfg(feature = "test_mode")]
     pub fn with_expected_variables(
         mut self,
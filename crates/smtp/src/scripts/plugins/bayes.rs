@@ -29,29 +29,13 @@ use nlp::{
     },
     tokenizers::osb::{OsbToken, OsbTokenizer},
 };
-use sieve::{runtime::Variable, FunctionMap};
+use sieve::runtime::Variable;
 use tokio::runtime::Handle;
 
-use crate::config::scripts::SieveContext;
+use crate::core::SMTP;
 
 use super::PluginContext;
 
-pub fn register_train(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("bayes_train", plugin_id, 3);
-}
-
-pub fn register_untrain(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("bayes_untrain", plugin_id, 3);
-}
-
-pub fn register_classify(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("bayes_classify", plugin_id, 3);
-}
-
-pub fn register_is_balanced(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("bayes_is_balanced", plugin_id, 3);
-}
-
 pub fn exec_train(ctx: PluginContext<'_>) -> Variable {
     train(ctx, true)
 }
@@ -77,72 +61,92 @@ fn train(ctx: PluginContext<'_>, is_train: bool) -> Variable {
     };
     let text = ctx.arguments[1].to_string();
     let is_spam = ctx.arguments[2].to_bool();
-    if text.is_empty() {
-        return false.into();
-    }
-    let handle = ctx.handle;
-    let ctx = ctx.core.sieve.runtime.context();
 
-    // Train the model
-    let mut model = BayesModel::default();
-    model.train(
-        OsbTokenizer::new(BayesTokenizer::new(text.as_ref(), &ctx.psl), 5),
-        is_spam,
-    );
-    if model.weights.is_empty() {
-        return false.into();
-    }
+    ctx.handle
+        .block_on(
+            ctx.core
+                .bayes_train(lookup_train, text.as_ref(), is_spam, is_train, span),
+        )
+        .into()
+}
 
-    tracing::debug!(
-        parent: span,
-        context = "sieve:bayes_train",
-        event = "train",
-        is_spam = is_spam,
-        num_tokens = model.weights.len(),
-    );
+impl SMTP {
+    pub async fn bayes_train(
+        &self,
+        lookup_train: &Lookup,
+        text: &str,
+        is_spam: bool,
+        is_train: bool,
+        span: &tracing::Span,
+    ) -> bool {
+        if text.is_empty() {
+            return false;
+        }
+        let ctx = self.sieve.runtime.context();
+
+        // Train the model
+        let mut model = BayesModel::default();
+        model.train(
+            OsbTokenizer::new(BayesTokenizer::new(text, &ctx.psl), 5),
+            is_spam,
+        );
+        if model.weights.is_empty() {
+            return false;
+        }
+
+        tracing::debug!(
+            parent: span,
+            context = "sieve:bayes_train",
+            event = "train",
+            is_spam = is_spam,
+            num_tokens = model.weights.len(),
+        );
 
-    // Update weight and invalidate cache
-    for (hash, weights) in model.weights {
-        let (s_weight, h_weight) = if is_train {
-            (weights.spam as i64, weights.ham as i64)
+        // Update weight and invalidate cache
+        for (hash, weights) in model.weights {
+            let (s_weight, h_weight) = if is_train {
+                (weights.spam as i64, weights.ham as i64)
+            } else {
+                (-(weights.spam as i64), -(weights.ham as i64))
+            };
+            if lookup_train
+                .lookup(&[
+                    hash.h1.into(),
+                    hash.h2.into(),
+                    s_weight.into(),
+                    h_weight.into(),
+                ])
+                .await
+                .is_none()
+            {
+                return false;
+            }
+            ctx.bayes_cache.invalidate(&hash);
+        }
+
+        // Update training counts
+        let train_val = if is_train { 1i64 } else { -1i64 };
+        let (spam_count, ham_count) = if is_spam {
+            (train_val, 0i64)
         } else {
-            (-(weights.spam as i64), -(weights.ham as i64))
+            (0i64, train_val)
         };
-        if handle
-            .block_on(lookup_train.lookup(&[
-                hash.h1.into(),
-                hash.h2.into(),
-                s_weight.into(),
-                h_weight.into(),
-            ]))
+        if lookup_train
+            .query(&[
+                0i64.into(),
+                0i64.into(),
+                spam_count.into(),
+                ham_count.into(),
+            ])
+            .await
             .is_none()
         {
-            return false.into();
+            return false;
         }
-        ctx.bayes_cache.invalidate(&hash);
-    }
+        ctx.bayes_cache.invalidate(&TokenHash::default());
 
-    // Update training counts
-    let train_val = if is_train { 1i64 } else { -1i64 };
-    let (spam_count, ham_count) = if is_spam {
-        (train_val, 0i64)
-    } else {
-        (0i64, train_val)
-    };
-    if handle
-        .block_on(lookup_train.query(&[
-            0i64.into(),
-            0i64.into(),
-            spam_count.into(),
-            ham_count.into(),
-        ]))
-        .is_none()
-    {
-        return false.into();
+        true
     }
-    ctx.bayes_cache.invalidate(&TokenHash::default());
-
-    true.into()
 }
 
 pub fn exec_classify(ctx: PluginContext<'_>) -> Variable {
@@ -24,20 +24,10 @@
 use std::net::IpAddr;
 
 use mail_auth::{Error, IpLookupStrategy};
-use sieve::{runtime::Variable, FunctionMap};
-
-use crate::config::scripts::SieveContext;
+use sieve::runtime::Variable;
 
 use super::PluginContext;
 
-pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("dns_query", plugin_id, 2);
-}
-
-pub fn register_exists(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("dns_exists", plugin_id, 2);
-}
-
 pub fn exec(ctx: PluginContext<'_>) -> Variable {
     let entry = ctx.arguments[0].to_string();
     let record_type = ctx.arguments[1].to_string();
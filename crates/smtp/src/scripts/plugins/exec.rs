@@ -23,16 +23,10 @@
 
 use std::process::Command;
 
-use sieve::{runtime::Variable, FunctionMap};
-
-use crate::config::scripts::SieveContext;
+use sieve::runtime::Variable;
 
 use super::PluginContext;
 
-pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("exec", plugin_id, 2);
-}
-
 pub fn exec(ctx: PluginContext<'_>) -> Variable {
     let span = ctx.span;
     let mut arguments = ctx.arguments.into_iter();
@@ -21,16 +21,12 @@
  * for more details.
 */
 
-use sieve::{runtime::Variable, FunctionMap};
+use sieve::runtime::Variable;
 
-use crate::{config::scripts::SieveContext, scripts::ScriptModification};
+use crate::scripts::ScriptModification;
 
 use super::PluginContext;
 
-pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("add_header", plugin_id, 2);
-}
-
 pub fn exec(ctx: PluginContext<'_>) -> Variable {
     if let (Variable::String(name), Variable::String(value)) =
         (&ctx.arguments[0], &ctx.arguments[1])
@@ -45,3 +41,9 @@ pub fn exec(ctx: PluginContext<'_>) -> Variable {
     }
     .into()
 }
+
+pub fn exec_spam_verdict(ctx: PluginContext<'_>) -> Variable {
+    ctx.modifications
+        .push(ScriptModification::SpamVerdict(ctx.arguments[0].to_bool()));
+    true.into()
+}
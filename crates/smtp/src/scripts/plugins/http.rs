@@ -24,16 +24,10 @@
 use std::time::Duration;
 
 use reqwest::redirect::Policy;
-use sieve::{runtime::Variable, FunctionMap};
-
-use crate::config::scripts::SieveContext;
+use sieve::runtime::Variable;
 
 use super::PluginContext;
 
-pub fn register_header(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("http_header", plugin_id, 4);
-}
-
 pub fn exec_header(ctx: PluginContext<'_>) -> Variable {
     let url = ctx.arguments[0].to_string();
     let header = ctx.arguments[1].to_string();
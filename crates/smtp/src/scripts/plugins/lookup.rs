@@ -29,31 +29,12 @@ use std::{
 
 use directory::DatabaseColumn;
 use mail_auth::flate2;
-use sieve::{runtime::Variable, FunctionMap};
+use sieve::runtime::Variable;
 
-use crate::{
-    config::scripts::{RemoteList, SieveContext},
-    USER_AGENT,
-};
+use crate::{config::scripts::RemoteList, USER_AGENT};
 
 use super::PluginContext;
 
-pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("lookup", plugin_id, 2);
-}
-
-pub fn register_map(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("lookup_map", plugin_id, 2);
-}
-
-pub fn register_remote(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("lookup_remote", plugin_id, 3);
-}
-
-pub fn register_local_domain(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("is_local_domain", plugin_id, 2);
-}
-
 pub fn exec(ctx: PluginContext<'_>) -> Variable {
     let lookup_id = ctx.arguments[0].to_string();
     let span = ctx.span;
@@ -38,7 +38,6 @@ use crate::{config::scripts::SieveContext, core::SMTP};
 
 use super::ScriptModification;
 
-type RegisterPluginFnc = fn(u32, &mut FunctionMap<SieveContext>) -> ();
 type ExecPluginFnc = fn(PluginContext<'_>) -> Variable;
 
 pub struct PluginContext<'x> {
@@ -50,41 +49,56 @@ pub struct PluginContext<'x> {
     pub arguments: Vec<Variable>,
 }
 
-const PLUGINS_EXEC: [ExecPluginFnc; 15] = [
-    query::exec,
-    exec::exec,
-    lookup::exec,
-    lookup::exec_map,
-    lookup::exec_remote,
-    lookup::exec_local_domain,
-    dns::exec,
-    dns::exec_exists,
-    http::exec_header,
-    bayes::exec_train,
-    bayes::exec_untrain,
-    bayes::exec_classify,
-    bayes::exec_is_balanced,
-    pyzor::exec,
-    headers::exec,
+struct Plugin {
+    name: &'static str,
+    num_args: u32,
+    exec: ExecPluginFnc,
+}
+
+const PLUGINS: [Plugin; 16] = [
+    Plugin::new("query", 3, query::exec),
+    Plugin::new("exec", 2, exec::exec),
+    Plugin::new("lookup", 2, lookup::exec),
+    Plugin::new("lookup_map", 2, lookup::exec_map),
+    Plugin::new("lookup_remote", 3, lookup::exec_remote),
+    Plugin::new("is_local_domain", 2, lookup::exec_local_domain),
+    Plugin::new("dns_query", 2, dns::exec),
+    Plugin::new("dns_exists", 2, dns::exec_exists),
+    Plugin::new("http_header", 4, http::exec_header),
+    Plugin::new("bayes_train", 3, bayes::exec_train),
+    Plugin::new("bayes_untrain", 3, bayes::exec_untrain),
+    Plugin::new("bayes_classify", 3, bayes::exec_classify),
+    Plugin::new("bayes_is_balanced", 3, bayes::exec_is_balanced),
+    Plugin::new("pyzor_check", 2, pyzor::exec),
+    Plugin::new("add_header", 2, headers::exec),
+    Plugin::new("spam_verdict", 1, headers::exec_spam_verdict),
 ];
-const PLUGINS_REGISTER: [RegisterPluginFnc; 15] = [
-    query::register,
-    exec::register,
-    lookup::register,
-    lookup::register_map,
-    lookup::register_remote,
-    lookup::register_local_domain,
-    dns::register,
-    dns::register_exists,
-    http::register_header,
-    bayes::register_train,
-    bayes::register_untrain,
-    bayes::register_classify,
-    bayes::register_is_balanced,
-    pyzor::register,
-    headers::register,
+
+// Plugins that write to a store or run external commands, skipped during dry runs
+const PLUGINS_WITH_SIDE_EFFECTS: [&str; 5] = [
+    "query",
+    "exec",
+    "lookup_map",
+    "bayes_train",
+    "bayes_untrain",
 ];
 
+impl Plugin {
+    const fn new(name: &'static str, num_args: u32, exec: ExecPluginFnc) -> Self {
+        Plugin {
+            name,
+            num_args,
+            exec,
+        }
+    }
+}
+
+pub fn has_side_effects(id: u32) -> bool {
+    PLUGINS.get(id as usize).map_or(false, |plugin| {
+        PLUGINS_WITH_SIDE_EFFECTS.contains(&plugin.name)
+    })
+}
+
 pub trait RegisterSievePlugins {
     fn register_plugins(self) -> Self;
 }
@@ -93,11 +107,11 @@ impl RegisterSievePlugins for FunctionMap<SieveContext> {
     fn register_plugins(mut self) -> Self {
         #[cfg(feature = "test_mode")]
         {
-            self.set_external_function("print", PLUGINS_EXEC.len() as u32, 1)
+            self.set_external_function("print", PLUGINS.len() as u32, 1)
         }
 
-        for (i, fnc) in PLUGINS_REGISTER.iter().enumerate() {
-            fnc(i as u32, &mut self);
+        for (i, plugin) in PLUGINS.iter().enumerate() {
+            self.set_external_function(plugin.name, i as u32, plugin.num_args);
         }
         self
     }
@@ -106,13 +120,13 @@ impl RegisterSievePlugins for FunctionMap<SieveContext> {
 impl SMTP {
     pub fn run_plugin_blocking(&self, id: u32, ctx: PluginContext<'_>) -> Input {
         #[cfg(feature = "test_mode")]
-        if id == PLUGINS_EXEC.len() as u32 {
+        if id == PLUGINS.len() as u32 {
             return test_print(ctx);
         }
 
-        PLUGINS_EXEC
+        PLUGINS
             .get(id as usize)
-            .map(|fnc| fnc(ctx))
+            .map(|plugin| (plugin.exec)(ctx))
             .unwrap_or_default()
             .into()
     }
@@ -21,9 +21,7 @@
  * for more details.
 */
 
-use sieve::{runtime::Variable, FunctionMap};
-
-use crate::config::scripts::SieveContext;
+use sieve::runtime::Variable;
 
 use super::PluginContext;
 
@@ -50,10 +48,6 @@ struct PyzorResponse {
     wl_count: u64,
 }
 
-pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("pyzor_check", plugin_id, 2);
-}
-
 pub fn exec(ctx: PluginContext<'_>) -> Variable {
     // Make sure there is at least one text part
     if !ctx
@@ -21,16 +21,11 @@
  * for more details.
 */
 
-use crate::config::scripts::SieveContext;
 use directory::DatabaseColumn;
-use sieve::{runtime::Variable, FunctionMap};
+use sieve::runtime::Variable;
 
 use super::PluginContext;
 
-pub fn register(plugin_id: u32, fnc_map: &mut FunctionMap<SieveContext>) {
-    fnc_map.set_external_function("query", plugin_id, 3);
-}
-
 pub fn exec(ctx: PluginContext<'_>) -> Variable {
     let span = ctx.span;
 
@@ -0,0 +1,181 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use serde::Serialize;
+use sieve::Sieve;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    runtime::Handle,
+};
+
+use crate::{core::Session, inbound::IsTls};
+
+use super::{ScriptParameters, ScriptResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accept = 0,
+    Reject = 1,
+    Discard = 2,
+}
+
+const VERDICTS: [Verdict; 3] = [Verdict::Accept, Verdict::Reject, Verdict::Discard];
+
+#[derive(Debug, Default)]
+pub struct ShadowReport {
+    // Indexed by active verdict * 3 + shadow verdict
+    counts: [AtomicU64; 9],
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShadowSummary {
+    pub total: u64,
+    pub agreed: u64,
+    pub disagreed: u64,
+    #[serde(rename = "disagreementRate")]
+    pub disagreement_rate: f64,
+    pub disagreements: Vec<ShadowDisagreement>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ShadowDisagreement {
+    pub active: Verdict,
+    pub shadow: Verdict,
+    pub count: u64,
+}
+
+impl<T: AsyncWrite + AsyncRead + Unpin + IsTls> Session<T> {
+    pub fn run_shadow_script(&self, script: Arc<Sieve>, params: ScriptParameters, active: Verdict) {
+        let core = self.core.clone();
+        let span = self.span.clone();
+        let handle = Handle::current();
+
+        // Shadow scripts never delay or alter the transaction
+        tokio::spawn(async move {
+            let core_ = core.clone();
+            let span_ = span.clone();
+            let shadow = match core
+                .spawn_worker(move || {
+                    core_.run_script_blocking(script, params.with_dry_run(), handle, span_)
+                })
+                .await
+            {
+                Some(result) => Verdict::from(&result),
+                None => return,
+            };
+
+            if !core.sieve.shadow.record(active, shadow) {
+                tracing::info!(parent: &span,
+                    context = "sieve",
+                    event = "shadow-disagreement",
+                    active = active.as_str(),
+                    shadow = shadow.as_str(),
+                    "Shadow script verdict differs from active policy.");
+            }
+        });
+    }
+}
+
+impl ShadowReport {
+    pub fn record(&self, active: Verdict, shadow: Verdict) -> bool {
+        self.counts[active as usize * 3 + shadow as usize].fetch_add(1, Ordering::Relaxed);
+        active == shadow
+    }
+
+    pub fn summary(&self) -> ShadowSummary {
+        let mut total = 0;
+        let mut agreed = 0;
+        let mut disagreements = Vec::new();
+
+        for active in VERDICTS {
+            for shadow in VERDICTS {
+                let count =
+                    self.counts[active as usize * 3 + shadow as usize].load(Ordering::Relaxed);
+                total += count;
+                if active == shadow {
+                    agreed += count;
+                } else if count > 0 {
+                    disagreements.push(ShadowDisagreement {
+                        active,
+                        shadow,
+                        count,
+                    });
+                }
+            }
+        }
+
+        ShadowSummary {
+            total,
+            agreed,
+            disagreed: total - agreed,
+            disagreement_rate: if total > 0 {
+                (total - agreed) as f64 / total as f64
+            } else {
+                0.0
+            },
+            disagreements,
+        }
+    }
+
+    pub fn reset(&self) -> ShadowSummary {
+        let summary = self.summary();
+        for count in &self.counts {
+            count.store(0, Ordering::Relaxed);
+        }
+        summary
+    }
+}
+
+impl Verdict {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verdict::Accept => "accept",
+            Verdict::Reject => "reject",
+            Verdict::Discard => "discard",
+        }
+    }
+}
+
+impl From<&ScriptResult> for Verdict {
+    fn from(result: &ScriptResult) -> Self {
+        match result {
+            ScriptResult::Accept { .. } | ScriptResult::Replace { .. } => Verdict::Accept,
+            ScriptResult::Reject(_) => Verdict::Reject,
+            ScriptResult::Discard => Verdict::Discard,
+        }
+    }
+}
+
+impl Serialize for Verdict {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.as_str())
+    }
+}
@@ -0,0 +1,329 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use directory::DatabaseColumn;
+use serde::{Deserialize, Serialize};
+use smtp_proto::Response;
+use tokio::fs;
+
+use crate::{
+    core::SuppressionCore,
+    queue::{Error, Message, Status, RCPT_BOUNCE_RECORDED},
+    usage::write_atomic,
+    webhook::now,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SuppressionEntry {
+    pub bounces: u32,
+    pub reason: String,
+    pub last_bounce: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredEntry {
+    address: String,
+    entry: SuppressionEntry,
+}
+
+impl SuppressionCore {
+    // Counts hard bounces and clears the history of addresses that accepted a message
+    pub async fn record_outcomes(&self, message: &mut Message) {
+        if !self.config.enable {
+            return;
+        }
+
+        for rcpt in &mut message.recipients {
+            if rcpt.has_flag(RCPT_BOUNCE_RECORDED) {
+                continue;
+            }
+            let reason = match (&rcpt.status, &message.domains[rcpt.domain_idx].status) {
+                (Status::Completed(_), _) => None,
+                (Status::PermanentFailure(response), _) => {
+                    if is_hard_bounce(&response.response) {
+                        format!(
+                            "{} {}.{}.{} {}",
+                            response.response.code,
+                            response.response.esc[0],
+                            response.response.esc[1],
+                            response.response.esc[2],
+                            response.response.message
+                        )
+                        .into()
+                    } else {
+                        None
+                    }
+                }
+                (Status::Scheduled, Status::PermanentFailure(Error::NullMx)) => {
+                    Error::NullMx.to_string().into()
+                }
+                (Status::Scheduled, Status::PermanentFailure(_)) => None,
+                _ => continue,
+            };
+            rcpt.flags |= RCPT_BOUNCE_RECORDED;
+
+            if let Some(reason) = reason {
+                self.record_bounce(&rcpt.address_lcase, reason).await;
+            } else if matches!(rcpt.status, Status::Completed(_)) {
+                self.record_delivery(&rcpt.address_lcase).await;
+            }
+        }
+    }
+
+    pub async fn record_bounce(&self, address: &str, reason: String) {
+        let bounces = if let Some(lookup) = &self.config.lookup {
+            // The query increments the bounce count and returns the updated value
+            match lookup
+                .bounce
+                .query(&[address.into(), reason.as_str().into(), now().into()])
+                .await
+            {
+                Some(row) => row.into_iter().next().and_then(column_integer),
+                None => {
+                    tracing::warn!(
+                        context = "suppression",
+                        event = "error",
+                        address = address,
+                        "Failed to record bounce."
+                    );
+                    return;
+                }
+            }
+        } else {
+            let mut entry = self.entries.entry(address.to_string()).or_default();
+            entry.bounces += 1;
+            entry.reason = reason.clone();
+            entry.last_bounce = now();
+            Some(entry.bounces as i64)
+        };
+
+        if bounces == Some(self.config.threshold as i64) {
+            tracing::info!(
+                context = "suppression",
+                event = "suppressed",
+                address = address,
+                bounces = self.config.threshold,
+                reason = %reason,
+                "Address added to the suppression list."
+            );
+        }
+    }
+
+    async fn record_delivery(&self, address: &str) {
+        if let Some(lookup) = &self.config.lookup {
+            if self
+                .fetch(address)
+                .await
+                .map_or(false, |entry| entry.bounces < self.config.threshold)
+            {
+                lookup.delete.query(&[address.into()]).await;
+            }
+        } else {
+            self.entries
+                .remove_if(address, |_, entry| entry.bounces < self.config.threshold);
+        }
+    }
+
+    async fn fetch(&self, address: &str) -> Option<SuppressionEntry> {
+        if let Some(lookup) = &self.config.lookup {
+            let mut row = lookup.get.query(&[address.into()]).await?.into_iter();
+            Some(SuppressionEntry {
+                bounces: column_integer(row.next()?)? as u32,
+                reason: match row.next() {
+                    Some(DatabaseColumn::Text(reason)) => reason.into_owned(),
+                    _ => String::new(),
+                },
+                last_bounce: row.next().and_then(column_integer).unwrap_or_default() as u64,
+            })
+        } else {
+            self.entries.get(address).map(|entry| entry.clone())
+        }
+    }
+
+    // Returns the entry of an address if it is currently suppressed
+    pub async fn get(&self, address: &str) -> Option<SuppressionEntry> {
+        if self.config.enable {
+            self.fetch(address)
+                .await
+                .filter(|entry| self.is_suppressed(entry))
+        } else {
+            None
+        }
+    }
+
+    // Lists the addresses kept in memory, when the list is stored in a shared
+    // database it has to be queried directly.
+    pub fn list(&self) -> Vec<(String, SuppressionEntry)> {
+        let mut entries = self
+            .entries
+            .iter()
+            .filter(|entry| self.is_suppressed(entry.value()))
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        entries
+    }
+
+    pub async fn suppress(&self, address: &str, reason: String) -> bool {
+        let address = address.to_lowercase();
+        if let Some(lookup) = &self.config.lookup {
+            lookup
+                .set
+                .query(&[
+                    address.into(),
+                    (self.config.threshold as i64).into(),
+                    reason.into(),
+                    now().into(),
+                ])
+                .await
+                .is_some()
+        } else {
+            self.entries.insert(
+                address,
+                SuppressionEntry {
+                    bounces: self.config.threshold,
+                    reason,
+                    last_bounce: now(),
+                },
+            );
+            true
+        }
+    }
+
+    pub async fn remove(&self, address: &str) -> bool {
+        let address = address.to_lowercase();
+        if let Some(lookup) = &self.config.lookup {
+            self.fetch(&address).await.is_some()
+                && lookup.delete.query(&[address.into()]).await.is_some()
+        } else {
+            self.entries.remove(&address).is_some()
+        }
+    }
+
+    fn is_suppressed(&self, entry: &SuppressionEntry) -> bool {
+        entry.bounces >= self.config.threshold
+            && entry.last_bounce + self.config.expiry.as_secs() > now()
+    }
+
+    // Removes addresses that have not bounced within the expiry period
+    pub fn cleanup(&self) {
+        let expires = now().saturating_sub(self.config.expiry.as_secs());
+        self.entries.retain(|_, entry| entry.last_bounce > expires);
+    }
+
+    pub async fn read_entries(&self) {
+        let path = if let Some(path) = self.local_path() {
+            path
+        } else {
+            return;
+        };
+
+        match fs::read(path).await {
+            Ok(bytes) => match serde_json::from_slice::<Vec<StoredEntry>>(&bytes) {
+                Ok(entries) => {
+                    for entry in entries {
+                        self.entries.insert(entry.address, entry.entry);
+                    }
+                }
+                Err(err) => {
+                    tracing::error!(
+                        context = "suppression",
+                        event = "error",
+                        path = %path.display(),
+                        reason = %err,
+                        "Failed to parse suppression list."
+                    );
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => {
+                tracing::error!(
+                    context = "suppression",
+                    event = "error",
+                    path = %path.display(),
+                    reason = %err,
+                    "Failed to read suppression list."
+                );
+            }
+        }
+    }
+
+    pub async fn write_entries(&self) {
+        let path = if let Some(path) = self.local_path() {
+            path
+        } else {
+            return;
+        };
+
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| StoredEntry {
+                address: entry.key().clone(),
+                entry: entry.value().clone(),
+            })
+            .collect::<Vec<_>>();
+        if let Err(err) =
+            write_atomic(path, &serde_json::to_vec(&entries).unwrap_or_default()).await
+        {
+            tracing::error!(
+                context = "suppression",
+                event = "error",
+                path = %path.display(),
+                reason = %err,
+                "Failed to write suppression list."
+            );
+        }
+    }
+
+    pub fn flush_frequency(&self) -> Option<Duration> {
+        self.local_path().map(|_| self.config.flush_frequency)
+    }
+
+    fn local_path(&self) -> Option<&std::path::PathBuf> {
+        self.config
+            .path
+            .as_ref()
+            .filter(|_| self.config.enable && self.config.lookup.is_none())
+    }
+}
+
+pub(crate) fn column_integer(column: DatabaseColumn) -> Option<i64> {
+    match column {
+        DatabaseColumn::Integer(value) => Some(value),
+        DatabaseColumn::Text(value) => value.parse().ok(),
+        _ => None,
+    }
+}
+
+// Only rejections of the address itself count towards suppression, other
+// permanent failures (content, policy) do not imply that the mailbox is invalid.
+fn is_hard_bounce(response: &Response<String>) -> bool {
+    if response.esc[0] != 0 {
+        response.esc[0] == 5 && response.esc[1] == 1
+    } else {
+        matches!(response.code, 550 | 551 | 553)
+    }
+}
@@ -0,0 +1,184 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{io::SeekFrom, ops::RangeInclusive, path::Path};
+
+use ahash::{AHashMap, AHashSet};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncBufReadExt, AsyncSeekExt, BufReader},
+};
+
+use crate::queue::QueueId;
+
+use super::{TrackingEvent, TrackingFilter};
+
+#[derive(Debug, Default)]
+pub struct TrackingIndex {
+    pub days: AHashMap<u64, DayIndex>,
+}
+
+// Offsets of the events in a daily log, keyed by the fields that can be searched
+#[derive(Debug, Default)]
+pub struct DayIndex {
+    queue_ids: AHashMap<QueueId, Vec<u64>>,
+    message_ids: AHashMap<String, AHashSet<QueueId>>,
+    addresses: AHashMap<String, Vec<u64>>,
+}
+
+impl DayIndex {
+    pub async fn build(path: &Path) -> std::io::Result<Self> {
+        let bytes = fs::read(path).await?;
+        let mut index = DayIndex::default();
+        let mut offset = 0;
+
+        // Partially written lines are skipped
+        for line in bytes.split(|&ch| ch == b'\n') {
+            if let Ok(event) = serde_json::from_slice::<TrackingEvent>(line) {
+                index.insert(&event, offset);
+            }
+            offset += line.len() as u64 + 1;
+        }
+
+        Ok(index)
+    }
+
+    pub fn insert(&mut self, event: &TrackingEvent, offset: u64) {
+        self.queue_ids
+            .entry(event.queue_id)
+            .or_default()
+            .push(offset);
+        if let Some(message_id) = &event.message_id {
+            self.message_ids
+                .entry(message_id.clone())
+                .or_default()
+                .insert(event.queue_id);
+        }
+        for address in std::iter::once(&event.from).chain(event.to.iter()) {
+            let offsets = self.addresses.entry(address.clone()).or_default();
+            if offsets.last() != Some(&offset) {
+                offsets.push(offset);
+            }
+        }
+    }
+}
+
+impl TrackingIndex {
+    // Returns the days within the range along with the offsets of the events
+    // that can match the filter, or None when the whole log has to be read.
+    pub fn candidates(
+        &self,
+        filter: &TrackingFilter,
+        days: RangeInclusive<u64>,
+    ) -> Vec<(u64, Option<Vec<u64>>)> {
+        // Resolve the Message-ID to the queue ids it was queued under
+        let mut queue_ids = filter
+            .queue_id
+            .map(|queue_id| AHashSet::from_iter([queue_id]));
+        if let Some(message_id) = &filter.message_id {
+            let message_id = message_id.trim_start_matches('<').trim_end_matches('>');
+            let mut message_queue_ids = AHashSet::new();
+            for (_, index) in self.days.iter().filter(|(day, _)| days.contains(day)) {
+                if let Some(ids) = index.message_ids.get(message_id) {
+                    message_queue_ids.extend(ids);
+                }
+            }
+            queue_ids = Some(match queue_ids {
+                Some(queue_ids) => queue_ids
+                    .intersection(&message_queue_ids)
+                    .copied()
+                    .collect(),
+                None => message_queue_ids,
+            });
+        }
+        let address = filter.to.as_ref().or(filter.from.as_ref());
+
+        let mut candidates = Vec::new();
+        for (day, index) in self.days.iter().filter(|(day, _)| days.contains(day)) {
+            let offsets = if let Some(queue_ids) = &queue_ids {
+                Some(
+                    queue_ids
+                        .iter()
+                        .filter_map(|queue_id| index.queue_ids.get(queue_id))
+                        .flatten()
+                        .copied()
+                        .collect::<Vec<_>>(),
+                )
+            } else {
+                address.map(|address| index.addresses.get(address).cloned().unwrap_or_default())
+            };
+            if offsets.as_ref().map_or(true, |offsets| !offsets.is_empty()) {
+                candidates.push((*day, offsets));
+            }
+        }
+        candidates
+    }
+}
+
+pub async fn read_events_at(
+    path: &Path,
+    mut offsets: Vec<u64>,
+    filter: &TrackingFilter,
+    events: &mut Vec<TrackingEvent>,
+) {
+    offsets.sort_unstable();
+    offsets.dedup();
+
+    let mut reader = match File::open(path).await {
+        Ok(file) => BufReader::new(file),
+        Err(err) => {
+            tracing::warn!(
+                context = "tracking",
+                event = "error",
+                path = %path.display(),
+                reason = %err,
+                "Failed to open tracking log."
+            );
+            return;
+        }
+    };
+    let mut line = Vec::new();
+    for offset in offsets {
+        line.clear();
+        if let Err(err) = async {
+            reader.seek(SeekFrom::Start(offset)).await?;
+            reader.read_until(b'\n', &mut line).await
+        }
+        .await
+        {
+            tracing::warn!(
+                context = "tracking",
+                event = "error",
+                path = %path.display(),
+                reason = %err,
+                "Failed to read tracking log."
+            );
+            return;
+        }
+        if let Ok(event) = serde_json::from_slice::<TrackingEvent>(&line) {
+            if event.matches(filter) {
+                events.push(event);
+            }
+        }
+    }
+}
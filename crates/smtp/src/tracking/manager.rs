@@ -0,0 +1,217 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::RwLock;
+
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::mpsc,
+};
+
+use crate::{core::TrackingCore, webhook::now};
+
+use super::{
+    file_name,
+    index::{DayIndex, TrackingIndex},
+    list_days, Event, TrackingEvent, DAY,
+};
+
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(3600);
+
+pub struct TrackingLog {
+    path: PathBuf,
+    retention: Duration,
+    index: Arc<RwLock<TrackingIndex>>,
+    file: Option<TrackingFile>,
+}
+
+struct TrackingFile {
+    day: u64,
+    file: File,
+    len: u64,
+}
+
+pub trait SpawnTracking {
+    fn spawn(self, log: TrackingLog);
+}
+
+impl SpawnTracking for mpsc::Receiver<Event> {
+    fn spawn(mut self, mut log: TrackingLog) {
+        tokio::spawn(async move {
+            let mut last_maintenance = Instant::now();
+            log.maintenance().await;
+            log.load_index().await;
+
+            loop {
+                match tokio::time::timeout(MAINTENANCE_INTERVAL, self.recv()).await {
+                    Ok(Some(Event::Record(event))) => {
+                        log.append(&event).await;
+                    }
+                    Ok(Some(Event::Flush(tx))) => {
+                        let _ = tx.send(());
+                    }
+                    Ok(Some(Event::Stop)) | Ok(None) => break,
+                    Err(_) => (),
+                }
+
+                if last_maintenance.elapsed() >= MAINTENANCE_INTERVAL {
+                    log.maintenance().await;
+                    last_maintenance = Instant::now();
+                }
+            }
+        });
+    }
+}
+
+impl TrackingLog {
+    pub fn new(core: &TrackingCore) -> Option<Self> {
+        core.config.path.as_ref().map(|path| TrackingLog {
+            path: path.clone(),
+            retention: core.config.retention,
+            index: core.index.clone(),
+            file: None,
+        })
+    }
+
+    // Indexes the logs written before the server was started
+    async fn load_index(&mut self) {
+        for day in list_days(&self.path).await {
+            let path = self.path.join(file_name(day));
+            match DayIndex::build(&path).await {
+                Ok(index) => {
+                    self.index.write().days.insert(day, index);
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        context = "tracking",
+                        event = "error",
+                        path = %path.display(),
+                        reason = %err,
+                        "Failed to index tracking log."
+                    );
+                }
+            }
+        }
+    }
+
+    async fn append(&mut self, event: &TrackingEvent) {
+        let mut line = serde_json::to_vec(event).unwrap_or_default();
+        line.push(b'\n');
+
+        // Events are appended to one file per day
+        let day = event.time / DAY;
+        if self.file.as_ref().map_or(true, |file| file.day != day) {
+            let _ = fs::create_dir_all(&self.path).await;
+            match OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path.join(file_name(day)))
+                .await
+            {
+                Ok(file) => {
+                    // Events are indexed by their offset within the file
+                    let len = match file.metadata().await {
+                        Ok(metadata) => metadata.len(),
+                        Err(err) => {
+                            tracing::error!(
+                                context = "tracking",
+                                event = "error",
+                                path = %self.path.display(),
+                                reason = %err,
+                                "Failed to open tracking log."
+                            );
+                            return;
+                        }
+                    };
+                    self.file = Some(TrackingFile { day, file, len });
+                }
+                Err(err) => {
+                    tracing::error!(
+                        context = "tracking",
+                        event = "error",
+                        path = %self.path.display(),
+                        reason = %err,
+                        "Failed to open tracking log."
+                    );
+                    return;
+                }
+            }
+        }
+
+        if let Some(file) = &mut self.file {
+            // Flush every event so that searches see it right away
+            let result = match file.file.write_all(&line).await {
+                Ok(_) => file.file.flush().await,
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(_) => {
+                    self.index
+                        .write()
+                        .days
+                        .entry(day)
+                        .or_default()
+                        .insert(event, file.len);
+                    file.len += line.len() as u64;
+                }
+                Err(err) => {
+                    tracing::error!(
+                        context = "tracking",
+                        event = "error",
+                        path = %self.path.display(),
+                        reason = %err,
+                        "Failed to write to tracking log."
+                    );
+                    self.file = None;
+                }
+            }
+        }
+    }
+
+    async fn maintenance(&mut self) {
+        // Purge logs older than the retention period
+        let oldest_day = now().saturating_sub(self.retention.as_secs()) / DAY;
+        for day in list_days(&self.path).await {
+            if day >= oldest_day {
+                break;
+            }
+            self.index.write().days.remove(&day);
+            if let Err(err) = fs::remove_file(self.path.join(file_name(day))).await {
+                tracing::warn!(
+                    context = "tracking",
+                    event = "error",
+                    path = %self.path.display(),
+                    reason = %err,
+                    "Failed to purge tracking log."
+                );
+            }
+        }
+    }
+}
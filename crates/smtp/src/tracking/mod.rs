@@ -0,0 +1,347 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    sync::{mpsc, oneshot},
+};
+
+use crate::{
+    core::TrackingCore,
+    queue::{HistoryEntry, Message, QueueId, Recipient, Status, RCPT_DSN_SENT, RCPT_SUPPRESSED},
+    tracking::index::read_events_at,
+    webhook::now,
+};
+
+pub mod index;
+pub mod manager;
+
+pub const DAY: u64 = 86400;
+
+#[derive(Debug)]
+pub enum Event {
+    Record(Box<TrackingEvent>),
+    Flush(oneshot::Sender<()>),
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrackingEventType {
+    #[serde(rename = "received")]
+    Received,
+    #[serde(rename = "queued")]
+    Queued,
+    #[serde(rename = "delivered")]
+    Delivered,
+    #[serde(rename = "filed")]
+    Filed,
+    #[serde(rename = "suppressed")]
+    Suppressed,
+    #[serde(rename = "deferred")]
+    Deferred,
+    #[serde(rename = "failed")]
+    Failed,
+    #[serde(rename = "dsn")]
+    Dsn,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackingEvent {
+    pub time: u64,
+    #[serde(rename = "type")]
+    pub typ: TrackingEventType,
+    #[serde(rename = "queueId")]
+    pub queue_id: QueueId,
+    #[serde(rename = "messageId")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub message_id: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+    #[serde(rename = "remoteIp")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub remote_ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub mx: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub details: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct TrackingFilter {
+    pub queue_id: Option<QueueId>,
+    pub message_id: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub after: Option<u64>,
+    pub before: Option<u64>,
+    pub limit: usize,
+}
+
+impl TrackingCore {
+    pub fn is_enabled(&self) -> bool {
+        self.config.path.is_some()
+    }
+
+    pub fn record(&self, event: TrackingEvent) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        // Tracking is best effort, never block the caller
+        match self.tx.try_send(Event::Record(Box::new(event))) {
+            Ok(_) => (),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                tracing::debug!(
+                    context = "tracking",
+                    event = "queue-full",
+                    "Tracking channel full, event discarded."
+                );
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                tracing::warn!(
+                    context = "tracking",
+                    event = "error",
+                    "Tracking channel closed, event discarded."
+                );
+            }
+        }
+    }
+
+    // Records the outcome of a delivery attempt for each recipient of the
+    // attempted domain. Recipients that already had a DSN processed were
+    // completed or failed on an earlier attempt and are not reported again.
+    pub fn track_attempt(&self, message: &Message, entry: &HistoryEntry, recipients: &[Recipient]) {
+        if !self.is_enabled() {
+            return;
+        }
+
+        for rcpt in recipients
+            .iter()
+            .filter(|rcpt| rcpt.domain_idx == entry.domain_idx && !rcpt.has_flag(RCPT_DSN_SENT))
+        {
+            let (typ, details) = match (&rcpt.status, &entry.status) {
+                // Duplicate copies discarded by the local delivery agent
+                (Status::Completed(response), _) if rcpt.has_flag(RCPT_SUPPRESSED) => {
+                    (TrackingEventType::Suppressed, response.response.to_string())
+                }
+                // Only local deliveries complete without an MX or transport
+                (Status::Completed(response), _) if entry.mx.is_empty() => {
+                    (TrackingEventType::Filed, response.response.to_string())
+                }
+                (Status::Completed(response), _) => {
+                    (TrackingEventType::Delivered, response.response.to_string())
+                }
+                (Status::TemporaryFailure(response), _) => {
+                    (TrackingEventType::Deferred, response.response.to_string())
+                }
+                (Status::PermanentFailure(response), _) => {
+                    (TrackingEventType::Failed, response.response.to_string())
+                }
+                (Status::Scheduled, Status::TemporaryFailure(reason)) => {
+                    (TrackingEventType::Deferred, reason.clone())
+                }
+                (Status::Scheduled, Status::PermanentFailure(reason)) => {
+                    (TrackingEventType::Failed, reason.clone())
+                }
+                _ => continue,
+            };
+
+            let mut event = TrackingEvent::new(typ, message);
+            event.time = entry.time;
+            event.to = vec![rcpt.address_lcase.clone()];
+            event.remote_ip = Some(entry.remote_ip.clone()).filter(|ip| !ip.is_empty());
+            event.mx = Some(entry.mx.clone()).filter(|mx| !mx.is_empty());
+            event.details = details.into();
+            self.record(event);
+        }
+    }
+
+    pub fn track_dsn(&self, message: &Message, dsn_id: QueueId) {
+        if self.is_enabled() {
+            let mut event = TrackingEvent::new(TrackingEventType::Dsn, message);
+            event.to = vec![message.return_path_lcase.clone()];
+            event.details = format!("DSN queued with id {dsn_id}").into();
+            self.record(event);
+        }
+    }
+
+    // Waits until the events recorded so far have been written
+    pub async fn flush(&self) {
+        if self.is_enabled() {
+            let (tx, rx) = oneshot::channel();
+            if self.tx.send(Event::Flush(tx)).await.is_ok() {
+                let _ = rx.await;
+            }
+        }
+    }
+
+    pub async fn search(&self, filter: &TrackingFilter) -> Vec<TrackingEvent> {
+        let path = if let Some(path) = &self.config.path {
+            path
+        } else {
+            return vec![];
+        };
+
+        // Only read the events referenced by the index, whole logs are read
+        // when searching by time range alone.
+        let mut events = Vec::new();
+        let first_day = filter.after.map_or(0, |after| after / DAY);
+        let last_day = filter.before.map_or(u64::MAX, |before| before / DAY);
+        let candidates = self.index.read().candidates(filter, first_day..=last_day);
+        for (day, offsets) in candidates {
+            let path = path.join(file_name(day));
+            if let Some(offsets) = offsets {
+                read_events_at(&path, offsets, filter, &mut events).await;
+            } else {
+                read_events(&path, filter, &mut events).await;
+            }
+        }
+
+        events.sort_by_key(|event| event.time);
+        if filter.limit > 0 && events.len() > filter.limit {
+            events.drain(..events.len() - filter.limit);
+        }
+        events
+    }
+}
+
+impl TrackingEvent {
+    pub fn new(typ: TrackingEventType, message: &Message) -> Self {
+        TrackingEvent {
+            time: now(),
+            typ,
+            queue_id: message.id,
+            message_id: None,
+            from: message.return_path_lcase.clone(),
+            to: message
+                .recipients
+                .iter()
+                .map(|rcpt| rcpt.address_lcase.clone())
+                .collect(),
+            remote_ip: None,
+            mx: None,
+            details: None,
+        }
+    }
+
+    pub(crate) fn matches(&self, filter: &TrackingFilter) -> bool {
+        filter
+            .queue_id
+            .map_or(true, |queue_id| self.queue_id == queue_id)
+            && filter.after.map_or(true, |after| self.time >= after)
+            && filter.before.map_or(true, |before| self.time <= before)
+            && filter.from.as_ref().map_or(true, |from| &self.from == from)
+            && filter
+                .to
+                .as_ref()
+                .map_or(true, |to| self.to.iter().any(|rcpt| rcpt == to))
+    }
+}
+
+// Returns the Message-ID of a message without the angle brackets
+pub fn find_message_id(headers: &[u8]) -> Option<String> {
+    let mut value: Option<String> = None;
+
+    for line in headers.split(|&ch| ch == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        } else if line[0] == b' ' || line[0] == b'\t' {
+            // Folded header
+            if let Some(value) = &mut value {
+                value.push_str(std::str::from_utf8(line).unwrap_or_default());
+            }
+        } else if value.is_some() {
+            break;
+        } else if line.len() > 11 && line[..11].eq_ignore_ascii_case(b"message-id:") {
+            value = Some(
+                std::str::from_utf8(&line[11..])
+                    .unwrap_or_default()
+                    .to_string(),
+            );
+        }
+    }
+
+    value
+        .map(|value| {
+            value
+                .trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string()
+        })
+        .filter(|value| !value.is_empty())
+}
+
+pub fn file_name(day: u64) -> String {
+    format!("{day:08}.jsonl")
+}
+
+pub async fn list_days(path: &Path) -> Vec<u64> {
+    let mut days = Vec::new();
+    if let Ok(mut dir) = fs::read_dir(path).await {
+        while let Ok(Some(entry)) = dir.next_entry().await {
+            if let Some(day) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(".jsonl"))
+                .and_then(|day| day.parse::<u64>().ok())
+            {
+                days.push(day);
+            }
+        }
+    }
+    days.sort_unstable();
+    days
+}
+
+async fn read_events(path: &Path, filter: &TrackingFilter, events: &mut Vec<TrackingEvent>) {
+    match fs::read(path).await {
+        Ok(bytes) => {
+            // Partially written lines are skipped
+            for line in bytes.split(|&ch| ch == b'\n') {
+                if let Ok(event) = serde_json::from_slice::<TrackingEvent>(line) {
+                    if event.matches(filter) {
+                        events.push(event);
+                    }
+                }
+            }
+        }
+        Err(err) => {
+            tracing::warn!(
+                context = "tracking",
+                event = "error",
+                path = %path.display(),
+                reason = %err,
+                "Failed to read tracking log."
+            );
+        }
+    }
+}
@@ -0,0 +1,201 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    config::UnsubscribeConfig,
+    core::SMTP,
+    queue::QueueId,
+    webhook::{now, WebhookEventType},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnsubscribeToken {
+    #[serde(rename = "q")]
+    pub queue_id: QueueId,
+    #[serde(rename = "f")]
+    pub from: String,
+    #[serde(rename = "t")]
+    pub to: String,
+    #[serde(rename = "l")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub list_id: Option<String>,
+    #[serde(rename = "m")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub message_id: Option<String>,
+    #[serde(rename = "e")]
+    pub expires: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsubscribeError {
+    Disabled,
+    Invalid,
+    Expired,
+}
+
+impl UnsubscribeConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.url.is_empty() && !self.keys.is_empty()
+    }
+
+    // Tokens are signed with the first key, older keys are only used
+    // for verification so that existing links survive a key rotation.
+    pub fn sign(&self, token: &UnsubscribeToken) -> Option<String> {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(token).ok()?);
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.keys.first()?).ok()?;
+        mac.update(payload.as_bytes());
+        Some(format!(
+            "{payload}.{}",
+            URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+        ))
+    }
+
+    pub fn verify(&self, token: &str) -> Result<UnsubscribeToken, UnsubscribeError> {
+        if !self.is_enabled() {
+            return Err(UnsubscribeError::Disabled);
+        }
+        let (payload, signature) = token.split_once('.').ok_or(UnsubscribeError::Invalid)?;
+        let signature = URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| UnsubscribeError::Invalid)?;
+        if !self.keys.iter().any(|key| {
+            Hmac::<Sha256>::new_from_slice(key).map_or(false, |mut mac| {
+                mac.update(payload.as_bytes());
+                mac.verify_slice(&signature).is_ok()
+            })
+        }) {
+            return Err(UnsubscribeError::Invalid);
+        }
+
+        let token = URL_SAFE_NO_PAD
+            .decode(payload)
+            .ok()
+            .and_then(|payload| serde_json::from_slice::<UnsubscribeToken>(&payload).ok())
+            .ok_or(UnsubscribeError::Invalid)?;
+        if token.expires >= now() {
+            Ok(token)
+        } else {
+            Err(UnsubscribeError::Expired)
+        }
+    }
+
+    // Builds the RFC 8058 one-click unsubscribe headers for a recipient
+    pub fn write_headers(&self, token: &UnsubscribeToken, headers: &mut Vec<u8>) -> bool {
+        if let Some(token) = self.sign(token) {
+            headers.extend_from_slice(b"List-Unsubscribe: <");
+            headers.extend_from_slice(self.url.trim_end_matches('/').as_bytes());
+            headers.push(b'/');
+            headers.extend_from_slice(token.as_bytes());
+            headers
+                .extend_from_slice(b">\r\nList-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n");
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl SMTP {
+    pub async fn process_unsubscribe(
+        &self,
+        token: &str,
+    ) -> Result<UnsubscribeToken, UnsubscribeError> {
+        match self.unsubscribe.verify(token) {
+            Ok(token) => {
+                tracing::info!(
+                    context = "unsubscribe",
+                    event = "success",
+                    from = token.from,
+                    to = token.to,
+                    list_id = token.list_id.as_deref().unwrap_or_default(),
+                    "Recipient unsubscribed."
+                );
+
+                self.webhook.publish(
+                    WebhookEventType::ListUnsubscribe,
+                    token.queue_id.into(),
+                    serde_json::json!({
+                        "from": token.from,
+                        "to": token.to,
+                        "listId": token.list_id,
+                        "messageId": token.message_id,
+                    }),
+                );
+
+                Ok(token)
+            }
+            Err(err) => {
+                tracing::debug!(
+                    context = "unsubscribe",
+                    event = "error",
+                    reason = ?err,
+                    "Failed to process unsubscribe request."
+                );
+                Err(err)
+            }
+        }
+    }
+}
+
+// Returns whether the message is flagged as bulk and does not provide its own unsubscribe headers
+pub fn is_bulk_message(headers: &[(&[u8], &[u8])]) -> bool {
+    let mut is_bulk = false;
+    for (name, value) in headers {
+        if name.eq_ignore_ascii_case(b"List-Unsubscribe")
+            || name.eq_ignore_ascii_case(b"List-Unsubscribe-Post")
+        {
+            return false;
+        } else if name.eq_ignore_ascii_case(b"Precedence") {
+            is_bulk = std::str::from_utf8(value).map_or(false, |value| {
+                matches!(value.trim().to_ascii_lowercase().as_str(), "bulk" | "list")
+            });
+        }
+    }
+    is_bulk
+}
+
+pub fn find_list_id(headers: &[(&[u8], &[u8])]) -> Option<String> {
+    headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(b"List-Id"))
+        .and_then(|(_, value)| {
+            let value = std::str::from_utf8(value).ok()?;
+            let value = value
+                .rsplit_once('<')
+                .and_then(|(_, value)| value.split_once('>'))
+                .map_or(value, |(value, _)| value)
+                .trim();
+            if !value.is_empty() {
+                Some(value.to_string())
+            } else {
+                None
+            }
+        })
+}
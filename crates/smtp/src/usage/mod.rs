@@ -0,0 +1,504 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{path::Path, time::Duration};
+
+use mail_parser::DateTime;
+use serde::{Deserialize, Serialize};
+use tokio::{fs, io::AsyncRead, io::AsyncWrite};
+
+use crate::{
+    config::{UsageAction, UsageLimit},
+    core::{Session, UsageCore},
+    webhook::{now, WebhookEventType},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum UsageKey {
+    #[serde(rename = "account")]
+    Account(String),
+    #[serde(rename = "domain")]
+    Domain(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageScope {
+    Account,
+    Domain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsagePeriod {
+    Hourly,
+    Daily,
+    Monthly,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub messages: u64,
+    #[serde(default)]
+    pub recipients: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsageCounter {
+    #[serde(default)]
+    pub hour: u64,
+    pub day: u64,
+    pub month: u64,
+    #[serde(default)]
+    pub hourly: Usage,
+    pub daily: Usage,
+    pub monthly: Usage,
+    pub total: Usage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UsageEntry {
+    key: UsageKey,
+    counter: UsageCounter,
+}
+
+impl UsageCounter {
+    // Resets the hourly, daily and monthly counters when a new period has started
+    pub fn roll(&mut self, timestamp: u64) {
+        let hour = timestamp / 3600;
+        if self.hour != hour {
+            self.hour = hour;
+            self.hourly = Usage::default();
+        }
+        let day = timestamp / 86400;
+        if self.day != day {
+            self.day = day;
+            self.daily = Usage::default();
+        }
+        let month = month_of(timestamp);
+        if self.month != month {
+            self.month = month;
+            self.monthly = Usage::default();
+        }
+    }
+
+    pub fn add(&mut self, size: u64, recipients: u64) {
+        for usage in [
+            &mut self.hourly,
+            &mut self.daily,
+            &mut self.monthly,
+            &mut self.total,
+        ] {
+            usage.messages += 1;
+            usage.recipients += recipients;
+            usage.size += size;
+        }
+    }
+
+    pub fn period(&self, period: UsagePeriod) -> &Usage {
+        match period {
+            UsagePeriod::Hourly => &self.hourly,
+            UsagePeriod::Daily => &self.daily,
+            UsagePeriod::Monthly => &self.monthly,
+        }
+    }
+}
+
+impl UsageLimit {
+    pub fn is_exceeded(&self, usage: &Usage, size: u64, recipients: u64) -> bool {
+        self.messages.map_or(false, |max| usage.messages + 1 > max)
+            || self
+                .recipients
+                .map_or(false, |max| usage.recipients + recipients > max)
+            || self.size.map_or(false, |max| usage.size + size > max)
+    }
+}
+
+impl UsageCore {
+    pub fn get(&self, key: &UsageKey) -> Option<UsageCounter> {
+        self.counters.get(key).map(|counter| {
+            let mut counter = counter.clone();
+            counter.roll(now());
+            counter
+        })
+    }
+
+    pub fn list(&self, scope: Option<UsageScope>) -> Vec<(UsageKey, UsageCounter)> {
+        let now = now();
+        self.counters
+            .iter()
+            .filter(|entry| scope.map_or(true, |scope| entry.key().scope() == scope))
+            .map(|entry| {
+                let mut counter = entry.value().clone();
+                counter.roll(now);
+                (entry.key().clone(), counter)
+            })
+            .collect()
+    }
+
+    pub async fn read_counters(&self) {
+        let path = if let Some(path) = &self.config.path {
+            path
+        } else {
+            return;
+        };
+
+        match fs::read(path).await {
+            Ok(bytes) => match serde_json::from_slice::<Vec<UsageEntry>>(&bytes) {
+                Ok(entries) => {
+                    for entry in entries {
+                        self.counters.insert(entry.key, entry.counter);
+                    }
+                }
+                Err(err) => {
+                    tracing::error!(
+                        context = "usage",
+                        event = "error",
+                        path = %path.display(),
+                        reason = %err,
+                        "Failed to parse usage counters."
+                    );
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => {
+                tracing::error!(
+                    context = "usage",
+                    event = "error",
+                    path = %path.display(),
+                    reason = %err,
+                    "Failed to read usage counters."
+                );
+            }
+        }
+    }
+
+    pub async fn write_counters(&self) {
+        let path = if let Some(path) = &self.config.path {
+            path
+        } else {
+            return;
+        };
+
+        let entries = self
+            .counters
+            .iter()
+            .map(|entry| UsageEntry {
+                key: entry.key().clone(),
+                counter: entry.value().clone(),
+            })
+            .collect::<Vec<_>>();
+        if let Err(err) =
+            write_atomic(path, &serde_json::to_vec(&entries).unwrap_or_default()).await
+        {
+            tracing::error!(
+                context = "usage",
+                event = "error",
+                path = %path.display(),
+                reason = %err,
+                "Failed to write usage counters."
+            );
+        }
+    }
+
+    pub fn flush_frequency(&self) -> Option<Duration> {
+        self.config
+            .path
+            .as_ref()
+            .map(|_| self.config.flush_frequency)
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite> Session<T> {
+    // Returns the usage limits that apply to this submission, or an error
+    // response if any of them would be exceeded by the message.
+    pub async fn check_usage_limits(
+        &self,
+        size: u64,
+        recipients: u64,
+    ) -> Result<Vec<usize>, &'static [u8]> {
+        let limits = &self.core.usage.config.limits;
+        if limits.is_empty() || self.data.authenticated_as.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let now = now();
+        let mut applicable = Vec::with_capacity(limits.len());
+        for (pos, limit) in limits.iter().enumerate() {
+            if !limit.conditions.conditions.is_empty() && !limit.conditions.eval(self).await {
+                continue;
+            }
+            let key = if let Some(key) = self.usage_key(limit.scope) {
+                key
+            } else {
+                continue;
+            };
+            if let Some(counter) = self.core.usage.counters.get(&key) {
+                let mut counter = counter.clone();
+                counter.roll(now);
+                if limit.is_exceeded(counter.period(limit.period), size, recipients) {
+                    tracing::info!(
+                        parent: &self.span,
+                        context = "usage",
+                        event = "limit-exceeded",
+                        scope = limit.scope.as_str(),
+                        period = limit.period.as_str(),
+                        action = limit.action.as_str(),
+                        key = key.name(),
+                        "Sending limit exceeded."
+                    );
+                    self.core.webhook.publish(
+                        WebhookEventType::UsageExceeded,
+                        None,
+                        serde_json::json!({
+                            "scope": limit.scope.as_str(),
+                            "name": key.name(),
+                            "period": limit.period.as_str(),
+                            "action": limit.action.as_str(),
+                            "remoteIp": self.data.remote_ip.to_string(),
+                            "messages": counter.period(limit.period).messages,
+                            "recipients": counter.period(limit.period).recipients,
+                            "size": counter.period(limit.period).size,
+                        }),
+                    );
+                    match limit.action {
+                        UsageAction::Reject => {
+                            return Err(match limit.period {
+                                UsagePeriod::Hourly => {
+                                    &b"550 5.4.5 Hourly sending limit exceeded.\r\n"[..]
+                                }
+                                UsagePeriod::Daily => {
+                                    &b"550 5.4.5 Daily sending limit exceeded.\r\n"[..]
+                                }
+                                UsagePeriod::Monthly => {
+                                    &b"550 5.4.5 Monthly sending limit exceeded.\r\n"[..]
+                                }
+                            });
+                        }
+                        UsageAction::Defer => {
+                            return Err(match limit.period {
+                                UsagePeriod::Hourly => &concat!(
+                                    "451 4.4.5 Hourly sending limit exceeded, ",
+                                    "please try again later.\r\n"
+                                )
+                                .as_bytes()[..],
+                                UsagePeriod::Daily => &concat!(
+                                    "451 4.4.5 Daily sending limit exceeded, ",
+                                    "please try again later.\r\n"
+                                )
+                                .as_bytes()[..],
+                                UsagePeriod::Monthly => &concat!(
+                                    "451 4.4.5 Monthly sending limit exceeded, ",
+                                    "please try again later.\r\n"
+                                )
+                                .as_bytes()[..],
+                            });
+                        }
+                        UsageAction::Alert => (),
+                    }
+                }
+            }
+            applicable.push(pos);
+        }
+
+        Ok(applicable)
+    }
+
+    // Updates the usage counters after a message has been queued and notifies
+    // any configured thresholds that were crossed.
+    pub async fn record_usage(&self, size: u64, recipients: u64, limits: &[usize]) {
+        if self.data.authenticated_as.is_empty() {
+            return;
+        }
+
+        let now = now();
+        for scope in [UsageScope::Account, UsageScope::Domain] {
+            let key = if let Some(key) = self.usage_key(scope) {
+                key
+            } else {
+                continue;
+            };
+            let (before, after) = {
+                let mut counter = self.core.usage.counters.entry(key.clone()).or_default();
+                counter.roll(now);
+                let before = counter.clone();
+                counter.add(size, recipients);
+                (before, counter.clone())
+            };
+
+            for limit in limits
+                .iter()
+                .filter_map(|pos| self.core.usage.config.limits.get(*pos))
+                .filter(|limit| limit.scope == scope)
+            {
+                for (metric, used_before, used_after, max) in [
+                    (
+                        "messages",
+                        before.period(limit.period).messages,
+                        after.period(limit.period).messages,
+                        limit.messages,
+                    ),
+                    (
+                        "recipients",
+                        before.period(limit.period).recipients,
+                        after.period(limit.period).recipients,
+                        limit.recipients,
+                    ),
+                    (
+                        "size",
+                        before.period(limit.period).size,
+                        after.period(limit.period).size,
+                        limit.size,
+                    ),
+                ] {
+                    let max = if let Some(max) = max {
+                        max
+                    } else {
+                        continue;
+                    };
+                    for &threshold in &self.core.usage.config.thresholds {
+                        let trigger = max * threshold / 100;
+                        if used_before < trigger && used_after >= trigger {
+                            self.core.webhook.publish(
+                                WebhookEventType::UsageThreshold,
+                                None,
+                                serde_json::json!({
+                                    "scope": scope.as_str(),
+                                    "name": key.name(),
+                                    "period": limit.period.as_str(),
+                                    "metric": metric,
+                                    "threshold": threshold,
+                                    "used": used_after,
+                                    "limit": max,
+                                }),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn usage_key(&self, scope: UsageScope) -> Option<UsageKey> {
+        match scope {
+            UsageScope::Account => Some(UsageKey::Account(self.data.authenticated_as.clone())),
+            UsageScope::Domain => self
+                .data
+                .mail_from
+                .as_ref()
+                .filter(|mail_from| !mail_from.domain.is_empty())
+                .map(|mail_from| UsageKey::Domain(mail_from.domain.clone())),
+        }
+    }
+}
+
+impl UsageKey {
+    pub fn scope(&self) -> UsageScope {
+        match self {
+            UsageKey::Account(_) => UsageScope::Account,
+            UsageKey::Domain(_) => UsageScope::Domain,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            UsageKey::Account(name) | UsageKey::Domain(name) => name,
+        }
+    }
+
+    pub fn is_in_scope(&self, domains: &[String]) -> bool {
+        let domain = match self {
+            UsageKey::Account(name) => name.rsplit_once('@').map_or("", |(_, domain)| domain),
+            UsageKey::Domain(domain) => domain,
+        };
+        domains.iter().any(|d| d.eq_ignore_ascii_case(domain))
+    }
+}
+
+impl UsageScope {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "account" => Some(UsageScope::Account),
+            "domain" => Some(UsageScope::Domain),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UsageScope::Account => "account",
+            UsageScope::Domain => "domain",
+        }
+    }
+}
+
+impl UsagePeriod {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "hourly" | "hour" => Some(UsagePeriod::Hourly),
+            "daily" | "day" => Some(UsagePeriod::Daily),
+            "monthly" | "month" => Some(UsagePeriod::Monthly),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UsagePeriod::Hourly => "hourly",
+            UsagePeriod::Daily => "daily",
+            UsagePeriod::Monthly => "monthly",
+        }
+    }
+}
+
+impl UsageAction {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "reject" => Some(UsageAction::Reject),
+            "defer" => Some(UsageAction::Defer),
+            "alert" => Some(UsageAction::Alert),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UsageAction::Reject => "reject",
+            UsageAction::Defer => "defer",
+            UsageAction::Alert => "alert",
+        }
+    }
+}
+
+fn month_of(timestamp: u64) -> u64 {
+    let dt = DateTime::from_timestamp(timestamp as i64);
+    dt.year as u64 * 12 + dt.month as u64
+}
+
+pub(crate) async fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent).await;
+    }
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, bytes).await?;
+    fs::rename(&tmp_path, path).await
+}
@@ -0,0 +1,439 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+
+use ahash::AHashSet;
+use reqwest::header::CONTENT_TYPE;
+use tokio::{fs, sync::mpsc};
+
+use crate::{
+    config::{WebhookConfig, WebhookEndpoint},
+    USER_AGENT,
+};
+
+use super::{
+    now,
+    signature::{sign_payload, SIGNATURE_HEADER},
+    Event, PendingEvent, WebhookEvent, DELIVERED_DIR, FAILED_DIR, PENDING_DIR,
+};
+
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(3600);
+
+pub struct EndpointQueue {
+    endpoint: Arc<WebhookEndpoint>,
+    path: PathBuf,
+    retention: Duration,
+    client: Option<reqwest::Client>,
+    pending: BTreeMap<u64, PendingEvent>,
+    backoff_until: u64,
+}
+
+enum PostError {
+    Transport(reqwest::Error),
+    Status(reqwest::StatusCode),
+}
+
+impl SpawnWebhook for mpsc::Receiver<Event> {
+    fn spawn(mut self, mut queue: EndpointQueue) {
+        tokio::spawn(async move {
+            let mut last_maintenance = Instant::now();
+
+            loop {
+                match tokio::time::timeout(queue.wake_up_time(), self.recv()).await {
+                    Ok(Some(event)) => match event {
+                        Event::Publish(event) => {
+                            queue.publish(*event).await;
+                        }
+                        Event::Replay {
+                            from,
+                            to,
+                            result_tx,
+                        } => {
+                            let _ = result_tx.send(queue.replay(from, to).await);
+                        }
+                        Event::Stop => break,
+                    },
+                    Ok(None) => break,
+                    Err(_) => (),
+                }
+
+                queue.deliver_due().await;
+
+                if last_maintenance.elapsed() >= MAINTENANCE_INTERVAL {
+                    queue.maintenance().await;
+                    last_maintenance = Instant::now();
+                }
+            }
+        });
+    }
+}
+
+impl EndpointQueue {
+    pub async fn new(endpoint: Arc<WebhookEndpoint>, config: &WebhookConfig) -> Self {
+        let path = config.path.join(&endpoint.id);
+        for dir in [PENDING_DIR, DELIVERED_DIR, FAILED_DIR] {
+            if let Err(err) = fs::create_dir_all(path.join(dir)).await {
+                tracing::error!(
+                    context = "webhook",
+                    event = "error",
+                    endpoint = &endpoint.id,
+                    reason = %err,
+                    "Failed to create webhook directory."
+                );
+            }
+        }
+
+        let client = match reqwest::Client::builder()
+            .user_agent(USER_AGENT)
+            .timeout(endpoint.timeout)
+            .danger_accept_invalid_certs(endpoint.tls_allow_invalid_certs)
+            .build()
+        {
+            Ok(client) => Some(client),
+            Err(err) => {
+                tracing::error!(
+                    context = "webhook",
+                    event = "error",
+                    endpoint = &endpoint.id,
+                    reason = %err,
+                    "Failed to build HTTP client."
+                );
+                None
+            }
+        };
+
+        let mut queue = EndpointQueue {
+            endpoint,
+            path,
+            retention: config.retention,
+            client,
+            pending: BTreeMap::new(),
+            backoff_until: 0,
+        };
+        queue.read_pending().await;
+        queue
+    }
+
+    async fn read_pending(&mut self) {
+        let path = self.path.join(PENDING_DIR);
+        let mut dir = match fs::read_dir(&path).await {
+            Ok(dir) => dir,
+            Err(_) => return,
+        };
+
+        while let Ok(Some(entry)) = dir.next_entry().await {
+            let file = entry.path();
+            if file.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            match PendingEvent::read(&file).await {
+                Ok(event) => {
+                    self.pending.entry(event.event.id).or_insert(event);
+                }
+                Err(err) => {
+                    tracing::warn!(
+                        context = "webhook",
+                        event = "error",
+                        endpoint = &self.endpoint.id,
+                        reason = %err,
+                        "Failed to read pending webhook event."
+                    );
+                }
+            }
+        }
+    }
+
+    async fn publish(&mut self, event: PendingEvent) {
+        // Persist the event before attempting delivery
+        if let Err(err) = event.write(&self.path.join(PENDING_DIR)).await {
+            tracing::error!(
+                context = "webhook",
+                event = "error",
+                endpoint = &self.endpoint.id,
+                id = event.event.id,
+                reason = %err,
+                "Failed to write webhook event to disk."
+            );
+        }
+        self.pending.insert(event.event.id, event);
+    }
+
+    fn wake_up_time(&self) -> Duration {
+        // Only the oldest event of each message can be delivered next
+        let now = now();
+        let mut seen = AHashSet::new();
+        self.pending
+            .values()
+            .filter(|event| {
+                event
+                    .event
+                    .message_id
+                    .map_or(true, |message_id| seen.insert(message_id))
+            })
+            .map(|event| std::cmp::max(event.due, self.backoff_until))
+            .min()
+            .map(|due| Duration::from_secs(due.saturating_sub(now)))
+            .unwrap_or(MAINTENANCE_INTERVAL)
+            .min(MAINTENANCE_INTERVAL)
+    }
+
+    async fn deliver_due(&mut self) {
+        let now = now();
+        if self.pending.is_empty() || self.backoff_until > now {
+            return;
+        }
+        let client = if let Some(client) = &self.client {
+            client
+        } else {
+            return;
+        };
+
+        // Events for the same message are delivered in order, so once an event
+        // is waiting to be retried all later events for that message wait too.
+        let mut blocked = AHashSet::new();
+        let ids = self.pending.keys().copied().collect::<Vec<_>>();
+
+        for id in ids {
+            let pending = self.pending.get_mut(&id).unwrap();
+            let message_id = pending.event.message_id;
+            if message_id.map_or(false, |id| blocked.contains(&id)) {
+                continue;
+            } else if pending.due > now {
+                if let Some(message_id) = message_id {
+                    blocked.insert(message_id);
+                }
+                continue;
+            }
+
+            match post_event(client, &self.endpoint, &pending.event).await {
+                Ok(_) => {
+                    tracing::debug!(
+                        context = "webhook",
+                        event = "delivered",
+                        endpoint = &self.endpoint.id,
+                        id = id,
+                        event_type = pending.event.typ.as_str(),
+                    );
+
+                    let pending = self.pending.remove(&id).unwrap();
+                    let file_name = pending.file_name();
+                    if let Err(err) = fs::rename(
+                        self.path.join(PENDING_DIR).join(&file_name),
+                        self.path.join(DELIVERED_DIR).join(&file_name),
+                    )
+                    .await
+                    {
+                        tracing::warn!(
+                            context = "webhook",
+                            event = "error",
+                            endpoint = &self.endpoint.id,
+                            id = id,
+                            reason = %err,
+                            "Failed to archive delivered webhook event."
+                        );
+                    }
+                }
+                Err(err) => {
+                    pending.attempts += 1;
+                    if let Some(message_id) = message_id {
+                        blocked.insert(message_id);
+                    }
+
+                    let is_transport_error = matches!(err, PostError::Transport(_));
+                    let retry_in = self.endpoint.retry[std::cmp::min(
+                        pending.attempts as usize - 1,
+                        self.endpoint.retry.len() - 1,
+                    )]
+                    .as_secs();
+
+                    if pending.event.created + self.endpoint.expire.as_secs() <= now {
+                        tracing::warn!(
+                            context = "webhook",
+                            event = "expired",
+                            endpoint = &self.endpoint.id,
+                            id = id,
+                            attempts = pending.attempts,
+                            reason = %err,
+                            "Webhook event expired after too many failed attempts."
+                        );
+
+                        let pending = self.pending.remove(&id).unwrap();
+                        let file_name = pending.file_name();
+                        let _ = pending.write(&self.path.join(FAILED_DIR)).await;
+                        let _ = fs::remove_file(self.path.join(PENDING_DIR).join(file_name)).await;
+                    } else {
+                        tracing::debug!(
+                            context = "webhook",
+                            event = "retry",
+                            endpoint = &self.endpoint.id,
+                            id = id,
+                            attempts = pending.attempts,
+                            reason = %err,
+                        );
+
+                        pending.due = now + retry_in;
+                        if let Err(err) = pending.write(&self.path.join(PENDING_DIR)).await {
+                            tracing::warn!(
+                                context = "webhook",
+                                event = "error",
+                                endpoint = &self.endpoint.id,
+                                id = id,
+                                reason = %err,
+                                "Failed to update webhook retry state."
+                            );
+                        }
+                    }
+
+                    // The endpoint is unreachable, do not attempt delivering other events
+                    if is_transport_error {
+                        self.backoff_until = now + retry_in;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn replay(&mut self, from: u64, to: u64) -> usize {
+        let mut count = 0;
+        let now = now();
+
+        for dir in [DELIVERED_DIR, FAILED_DIR] {
+            let mut entries = match fs::read_dir(self.path.join(dir)).await {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                let file = entry.path();
+                if file.extension().map_or(true, |ext| ext != "json") {
+                    continue;
+                }
+                match PendingEvent::read(&file).await {
+                    Ok(mut pending) if (from..=to).contains(&pending.event.created) => {
+                        pending.attempts = 0;
+                        pending.due = now;
+                        if pending.write(&self.path.join(PENDING_DIR)).await.is_ok() {
+                            let _ = fs::remove_file(&file).await;
+                            self.pending.insert(pending.event.id, pending);
+                            count += 1;
+                        }
+                    }
+                    Ok(_) => (),
+                    Err(err) => {
+                        tracing::warn!(
+                            context = "webhook",
+                            event = "error",
+                            endpoint = &self.endpoint.id,
+                            reason = %err,
+                            "Failed to read archived webhook event."
+                        );
+                    }
+                }
+            }
+        }
+
+        if count > 0 {
+            self.backoff_until = 0;
+            tracing::info!(
+                context = "webhook",
+                event = "replay",
+                endpoint = &self.endpoint.id,
+                from = from,
+                to = to,
+                count = count,
+                "Webhook events scheduled for redelivery."
+            );
+        }
+
+        count
+    }
+
+    async fn maintenance(&mut self) {
+        // Pick up any events that could not be handed over through the channel
+        self.read_pending().await;
+
+        // Remove archived events past their retention period
+        let expired_before = SystemTime::now() - self.retention;
+        for dir in [DELIVERED_DIR, FAILED_DIR] {
+            let mut entries = match fs::read_dir(self.path.join(dir)).await {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+
+            while let Ok(Some(entry)) = entries.next_entry().await {
+                if entry
+                    .metadata()
+                    .await
+                    .and_then(|metadata| metadata.modified())
+                    .map_or(false, |modified| modified < expired_before)
+                {
+                    let _ = fs::remove_file(entry.path()).await;
+                }
+            }
+        }
+    }
+}
+
+async fn post_event(
+    client: &reqwest::Client,
+    endpoint: &WebhookEndpoint,
+    event: &WebhookEvent,
+) -> Result<(), PostError> {
+    let body = serde_json::to_vec(event).unwrap_or_default();
+    let mut request = client
+        .post(&endpoint.url)
+        .header(CONTENT_TYPE, "application/json")
+        .header("X-Webhook-Id", event.id.to_string());
+    for (name, value) in &endpoint.headers {
+        request = request.header(name, value);
+    }
+    if let Some(signature) = sign_payload(&endpoint.signature_keys, now(), &body) {
+        request = request.header(SIGNATURE_HEADER, signature);
+    }
+
+    match request.body(body).send().await {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => Err(PostError::Status(response.status())),
+        Err(err) => Err(PostError::Transport(err)),
+    }
+}
+
+impl std::fmt::Display for PostError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PostError::Transport(err) => write!(f, "HTTP request failed: {err}"),
+            PostError::Status(status) => write!(f, "Unexpected HTTP status {status}"),
+        }
+    }
+}
+
+pub trait SpawnWebhook {
+    fn spawn(self, queue: EndpointQueue);
+}
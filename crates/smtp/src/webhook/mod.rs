@@ -0,0 +1,333 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::SystemTime,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    sync::{mpsc, oneshot},
+};
+
+use crate::{
+    core::WebhookCore,
+    queue::{Message, QueueId, Status, RCPT_EVENT_SENT},
+};
+
+pub mod manager;
+pub mod signature;
+
+pub const PENDING_DIR: &str = "pending";
+pub const DELIVERED_DIR: &str = "delivered";
+pub const FAILED_DIR: &str = "failed";
+
+#[derive(Debug)]
+pub enum Event {
+    Publish(Box<PendingEvent>),
+    Replay {
+        from: u64,
+        to: u64,
+        result_tx: oneshot::Sender<usize>,
+    },
+    Stop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum WebhookEventType {
+    #[serde(rename = "message.queued")]
+    MessageQueued,
+    #[serde(rename = "delivery.completed")]
+    DeliveryCompleted,
+    #[serde(rename = "delivery.deferred")]
+    DeliveryDeferred,
+    #[serde(rename = "delivery.failed")]
+    DeliveryFailed,
+    #[serde(rename = "usage.threshold")]
+    UsageThreshold,
+    #[serde(rename = "usage.exceeded")]
+    UsageExceeded,
+    #[serde(rename = "account.compromised")]
+    AccountCompromised,
+    #[serde(rename = "list.unsubscribe")]
+    ListUnsubscribe,
+    #[serde(rename = "queue.backpressure")]
+    QueueBackpressure,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookEvent {
+    pub id: u64,
+    pub created: u64,
+    #[serde(rename = "type")]
+    pub typ: WebhookEventType,
+    #[serde(rename = "messageId")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub message_id: Option<QueueId>,
+    pub data: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingEvent {
+    pub event: WebhookEvent,
+    pub attempts: u32,
+    pub due: u64,
+}
+
+impl WebhookCore {
+    pub fn publish(
+        &self,
+        typ: WebhookEventType,
+        message_id: Option<QueueId>,
+        data: serde_json::Value,
+    ) {
+        if !self.config.endpoints.is_empty() {
+            self.publish_event(self.new_event(typ, message_id, data));
+        }
+    }
+
+    // Never blocks the caller, events are persisted by the endpoint manager
+    // before attempting delivery. When the channel is full the event is written
+    // to the pending directory in the background and picked up by the next rescan.
+    pub fn publish_event(&self, event: WebhookEvent) {
+        for endpoint in &self.config.endpoints {
+            if !endpoint.events.is_empty() && !endpoint.events.contains(&event.typ) {
+                continue;
+            }
+
+            let pending = PendingEvent {
+                due: event.created,
+                event: event.clone(),
+                attempts: 0,
+            };
+            let pending = match self.tx.get(&endpoint.id) {
+                Some(tx) => match tx.try_send(Event::Publish(Box::new(pending))) {
+                    Ok(_) => continue,
+                    Err(mpsc::error::TrySendError::Full(Event::Publish(pending))) => {
+                        tracing::debug!(
+                            context = "webhook",
+                            event = "queue-full",
+                            endpoint = &endpoint.id,
+                            "Webhook channel full, event will be delivered after the next rescan."
+                        );
+                        pending
+                    }
+                    Err(mpsc::error::TrySendError::Closed(Event::Publish(pending))) => {
+                        tracing::warn!(
+                            context = "webhook",
+                            event = "error",
+                            endpoint = &endpoint.id,
+                            "Webhook channel closed: Event stored but won't be sent until next restart."
+                        );
+                        pending
+                    }
+                    Err(_) => continue,
+                },
+                None => Box::new(pending),
+            };
+
+            let path = endpoint_path(&self.config.path, &endpoint.id, PENDING_DIR);
+            let endpoint_id = endpoint.id.clone();
+            tokio::spawn(async move {
+                if let Err(err) = pending.write(&path).await {
+                    tracing::error!(
+                        context = "webhook",
+                        event = "error",
+                        endpoint = endpoint_id,
+                        reason = %err,
+                        "Failed to write webhook event to disk."
+                    );
+                }
+            });
+        }
+    }
+
+    pub fn new_event(
+        &self,
+        typ: WebhookEventType,
+        message_id: Option<QueueId>,
+        data: serde_json::Value,
+    ) -> WebhookEvent {
+        WebhookEvent {
+            id: self.event_id(),
+            created: now(),
+            typ,
+            message_id,
+            data,
+        }
+    }
+
+    // The event is created before the message is queued so that it is ordered
+    // ahead of any delivery events, and published once queueing succeeds.
+    pub fn queued_event(&self, message: &Message) -> Option<WebhookEvent> {
+        if self.config.endpoints.is_empty() {
+            return None;
+        }
+
+        Some(self.new_event(
+            WebhookEventType::MessageQueued,
+            message.id.into(),
+            serde_json::json!({
+                "from": message.return_path,
+                "to": message.recipients.iter().map(|r| r.address.as_str()).collect::<Vec<_>>(),
+                "size": message.size,
+            }),
+        ))
+    }
+
+    pub fn publish_delivery_status(&self, message: &mut Message) {
+        if self.config.endpoints.is_empty() {
+            return;
+        }
+
+        for rcpt in &mut message.recipients {
+            if rcpt.has_flag(RCPT_EVENT_SENT) {
+                continue;
+            }
+            let domain = &message.domains[rcpt.domain_idx];
+            let (typ, status) = match &rcpt.status {
+                Status::Completed(_) => {
+                    (WebhookEventType::DeliveryCompleted, rcpt.status.to_string())
+                }
+                Status::PermanentFailure(_) => {
+                    (WebhookEventType::DeliveryFailed, rcpt.status.to_string())
+                }
+                Status::TemporaryFailure(_) if domain.changed => {
+                    (WebhookEventType::DeliveryDeferred, rcpt.status.to_string())
+                }
+                Status::Scheduled if domain.changed => match &domain.status {
+                    Status::PermanentFailure(_) => {
+                        (WebhookEventType::DeliveryFailed, domain.status.to_string())
+                    }
+                    Status::TemporaryFailure(_) => (
+                        WebhookEventType::DeliveryDeferred,
+                        domain.status.to_string(),
+                    ),
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            if typ != WebhookEventType::DeliveryDeferred {
+                rcpt.flags |= RCPT_EVENT_SENT;
+            }
+
+            self.publish(
+                typ,
+                message.id.into(),
+                serde_json::json!({
+                    "from": message.return_path,
+                    "to": rcpt.address,
+                    "domain": domain.domain,
+                    "status": status,
+                }),
+            );
+        }
+    }
+
+    pub fn event_id(&self) -> u64 {
+        // Event ids are strictly increasing and sortable by creation time
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64)
+            << 12;
+        let mut last_id = self.id_seq.load(Ordering::Relaxed);
+        loop {
+            let next_id = std::cmp::max(now, last_id + 1);
+            match self.id_seq.compare_exchange_weak(
+                last_id,
+                next_id,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return next_id,
+                Err(id) => last_id = id,
+            }
+        }
+    }
+}
+
+impl PendingEvent {
+    pub fn file_name(&self) -> String {
+        format!("{:020}.json", self.event.id)
+    }
+
+    pub async fn write(&self, path: &Path) -> std::io::Result<()> {
+        let _ = fs::create_dir_all(path).await;
+        let tmp_path = path.join(format!("{:020}.tmp", self.event.id));
+        fs::write(&tmp_path, serde_json::to_vec(self).unwrap_or_default()).await?;
+        fs::rename(&tmp_path, path.join(self.file_name())).await
+    }
+
+    pub async fn read(path: &Path) -> Result<Self, String> {
+        let bytes = fs::read(path)
+            .await
+            .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+        serde_json::from_slice(&bytes)
+            .map_err(|err| format!("Failed to parse {}: {}", path.display(), err))
+    }
+}
+
+impl WebhookEventType {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "message.queued" => Some(WebhookEventType::MessageQueued),
+            "delivery.completed" => Some(WebhookEventType::DeliveryCompleted),
+            "delivery.deferred" => Some(WebhookEventType::DeliveryDeferred),
+            "delivery.failed" => Some(WebhookEventType::DeliveryFailed),
+            "usage.threshold" => Some(WebhookEventType::UsageThreshold),
+            "usage.exceeded" => Some(WebhookEventType::UsageExceeded),
+            "account.compromised" => Some(WebhookEventType::AccountCompromised),
+            "list.unsubscribe" => Some(WebhookEventType::ListUnsubscribe),
+            "queue.backpressure" => Some(WebhookEventType::QueueBackpressure),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEventType::MessageQueued => "message.queued",
+            WebhookEventType::DeliveryCompleted => "delivery.completed",
+            WebhookEventType::DeliveryDeferred => "delivery.deferred",
+            WebhookEventType::DeliveryFailed => "delivery.failed",
+            WebhookEventType::UsageThreshold => "usage.threshold",
+            WebhookEventType::UsageExceeded => "usage.exceeded",
+            WebhookEventType::AccountCompromised => "account.compromised",
+            WebhookEventType::ListUnsubscribe => "list.unsubscribe",
+            WebhookEventType::QueueBackpressure => "queue.backpressure",
+        }
+    }
+}
+
+pub fn endpoint_path(base: &Path, endpoint_id: &str, dir: &str) -> PathBuf {
+    base.join(endpoint_id).join(dir)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
@@ -0,0 +1,51 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::fmt::Write;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+// Signs the payload with every configured key so receivers can keep
+// verifying with the old key while the new one is being rolled out.
+pub fn sign_payload(keys: &[Vec<u8>], timestamp: u64, payload: &[u8]) -> Option<String> {
+    if keys.is_empty() {
+        return None;
+    }
+
+    let mut header = format!("t={timestamp}");
+    for key in keys {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).ok()?;
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(payload);
+        header.push_str(",v1=");
+        for byte in mac.finalize().into_bytes() {
+            let _ = write!(header, "{byte:02x}");
+        }
+    }
+
+    Some(header)
+}
@@ -30,6 +30,12 @@ pub const BIT_CLEAR: u8 = 0;
 
 pub const IS_BITLIST: u8 = 0;
 pub const IS_BITMAP: u8 = 1;
+pub const IS_VERSIONED_BITMAP: u8 = 2;
+
+// Serialization formats of stored bitmaps. Values written before versioning was
+// introduced (IS_BITMAP header) are read as BITMAP_V1.
+pub const BITMAP_V1: u8 = 1;
+pub const BITMAP_VERSION: u8 = BITMAP_V1;
 
 #[inline(always)]
 pub fn deserialize_bitlist(bm: &mut RoaringBitmap, bytes: &[u8]) {
@@ -57,13 +63,49 @@ pub fn deserialize_bitlist(bm: &mut RoaringBitmap, bytes: &[u8]) {
 
 #[inline(always)]
 pub fn deserialize_bitmap(bytes: &[u8]) -> Option<RoaringBitmap> {
-    RoaringBitmap::deserialize_unchecked_from(&bytes[1..]).ok()
+    match *bytes.first()? {
+        IS_BITMAP => deserialize_bitmap_version(BITMAP_V1, &bytes[1..]),
+        IS_VERSIONED_BITMAP => deserialize_bitmap_version(*bytes.get(1)?, &bytes[2..]),
+        _ => None,
+    }
 }
 
+#[inline(always)]
+fn deserialize_bitmap_version(version: u8, bytes: &[u8]) -> Option<RoaringBitmap> {
+    match version {
+        BITMAP_V1 => RoaringBitmap::deserialize_unchecked_from(bytes).ok(),
+        _ => None,
+    }
+}
+
+#[inline(always)]
+pub fn serialize_bitmap(bm: &RoaringBitmap) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(bm.serialized_size() + 2);
+    bytes.push(IS_VERSIONED_BITMAP);
+    bytes.push(BITMAP_VERSION);
+    bm.serialize_into(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+/// Returns true if a stored bitmap was written using an older format version
+/// and should be rewritten.
+#[inline(always)]
+pub fn is_outdated_bitmap(bytes: &[u8]) -> bool {
+    match bytes.first() {
+        Some(&IS_BITMAP) => true,
+        Some(&IS_VERSIONED_BITMAP) => bytes.get(1).map_or(false, |v| *v < BITMAP_VERSION),
+        _ => false,
+    }
+}
+
+/// Merge operand that leaves the bitmap unchanged, used to force a full merge
+/// which rewrites the stored value using the current format version.
+pub const UPGRADE_OPERAND: &[u8] = &[IS_BITLIST];
+
 impl Deserialize for RoaringBitmap {
     fn deserialize(bytes: &[u8]) -> Option<Self> {
         match *bytes.first()? {
-            IS_BITMAP => deserialize_bitmap(bytes),
+            IS_BITMAP | IS_VERSIONED_BITMAP => deserialize_bitmap(bytes),
             IS_BITLIST => {
                 let mut bm = RoaringBitmap::new();
                 deserialize_bitlist(&mut bm, bytes);
@@ -76,10 +118,7 @@ impl Deserialize for RoaringBitmap {
 
 impl Serialize for RoaringBitmap {
     fn serialize(self) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity(self.serialized_size() + 1);
-        bytes.push(IS_BITMAP);
-        let _ = self.serialize_into(&mut bytes);
-        bytes
+        serialize_bitmap(&self).unwrap_or_default()
     }
 }
 
@@ -210,7 +249,7 @@ pub fn bitmap_merge<'x>(
 
     for op in operands.into_iter() {
         match *op.first()? {
-            IS_BITMAP => {
+            IS_BITMAP | IS_VERSIONED_BITMAP => {
                 if let Some(union_bm) = deserialize_bitmap(op) {
                     if !bm.is_empty() {
                         bm |= union_bm;
@@ -232,10 +271,7 @@ pub fn bitmap_merge<'x>(
         }
     }
 
-    let mut bytes = Vec::with_capacity(bm.serialized_size() + 1);
-    bytes.push(IS_BITMAP);
-    bm.serialize_into(&mut bytes).ok()?;
-    Some(bytes)
+    serialize_bitmap(&bm)
 }
 
 #[cfg(test)]
@@ -323,4 +359,42 @@ mod tests {
             .unwrap()
         );
     }
+
+    #[test]
+    fn bitmap_versioning() {
+        let bm = RoaringBitmap::from_iter([1, 2, 3, 100_000]);
+
+        // Legacy unversioned bitmaps are still readable and flagged for upgrade
+        let mut legacy = vec![IS_BITMAP];
+        bm.serialize_into(&mut legacy).unwrap();
+        assert!(is_outdated_bitmap(&legacy));
+        assert_eq!(RoaringBitmap::deserialize(&legacy).unwrap(), bm);
+
+        // A no-op merge rewrites the value using the current version
+        let upgraded = bitmap_merge(Some(&legacy), 1, [UPGRADE_OPERAND]).unwrap();
+        assert_eq!(&upgraded[..2], &[IS_VERSIONED_BITMAP, BITMAP_VERSION]);
+        assert!(!is_outdated_bitmap(&upgraded));
+        assert_eq!(RoaringBitmap::deserialize(&upgraded).unwrap(), bm);
+        assert_eq!(bm.clone().serialize(), upgraded);
+
+        // Legacy and versioned operands can be mixed
+        assert_eq!(
+            RoaringBitmap::from_iter([1, 2, 3, 5, 100_000]),
+            RoaringBitmap::deserialize(
+                &bitmap_merge(
+                    Some(&legacy),
+                    2,
+                    [
+                        RoaringBitmap::from_iter([5]).serialize().as_ref(),
+                        UPGRADE_OPERAND
+                    ]
+                )
+                .unwrap()
+            )
+            .unwrap()
+        );
+
+        // Bitlists are never upgraded
+        assert!(!is_outdated_bitmap(&set_bit(1)));
+    }
 }
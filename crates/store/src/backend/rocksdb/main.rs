@@ -24,11 +24,17 @@
 use std::path::PathBuf;
 
 use roaring::RoaringBitmap;
-use rocksdb::{ColumnFamilyDescriptor, MergeOperands, OptimisticTransactionDB, Options};
+use rocksdb::{
+    AsColumnFamilyRef, ColumnFamilyDescriptor, IteratorMode, MergeOperands,
+    OptimisticTransactionDB, Options,
+};
 
 use crate::{Deserialize, Error, Store};
 
-use super::{CF_BITMAPS, CF_BLOBS, CF_INDEXES, CF_LOGS, CF_VALUES};
+use super::{
+    bitmap::{is_outdated_bitmap, UPGRADE_OPERAND},
+    CF_BITMAPS, CF_BLOBS, CF_INDEXES, CF_LOGS, CF_VALUES,
+};
 
 impl Store {
     pub fn open() -> crate::Result<Self> {
@@ -90,14 +96,53 @@ impl Store {
         db_opts.create_missing_column_families(true);
         db_opts.create_if_missing(true);
 
-        Ok(Store {
+        let store = Store {
             db: OptimisticTransactionDB::open_cf_descriptors(
                 &db_opts,
                 idx_path,
                 vec![cf_bitmaps, cf_values, cf_indexes, cf_blobs, cf_log],
             )
             .map_err(|e| Error::InternalError(e.into_string()))?,
-        })
+        };
+
+        // Rewrite bitmaps stored by older versions before serving any requests
+        let total_upgraded = store.upgrade_bitmaps()?;
+        if total_upgraded > 0 {
+            tracing::info!(
+                context = "store",
+                event = "upgrade",
+                total = total_upgraded,
+                "Upgraded bitmaps to the current format version."
+            );
+        }
+
+        Ok(store)
+    }
+
+    /// Rewrites all bitmaps stored using an older format version. Runs on startup,
+    /// reads never modify stored bitmaps.
+    pub fn upgrade_bitmaps(&self) -> crate::Result<usize> {
+        let cf_handle = self.db.cf_handle(CF_BITMAPS).unwrap();
+        let mut total_upgraded = 0;
+
+        for result in self.db.iterator_cf(&cf_handle, IteratorMode::Start) {
+            let (key, value) = result
+                .map_err(|err| Error::InternalError(format!("iterator_cf failed: {}", err)))?;
+            if is_outdated_bitmap(&value) {
+                self.upgrade_bitmap(&cf_handle, &key)?;
+                total_upgraded += 1;
+            }
+        }
+
+        Ok(total_upgraded)
+    }
+
+    fn upgrade_bitmap(&self, cf_handle: impl AsColumnFamilyRef, key: &[u8]) -> crate::Result<()> {
+        // Merge operands are applied in order, so this is safe to run
+        // concurrently with other writes.
+        self.db
+            .merge_cf(&cf_handle, key, UPGRADE_OPERAND)
+            .map_err(|err| Error::InternalError(format!("merge_cf failed: {}", err)))
     }
 
     pub fn close(&self) -> crate::Result<()> {
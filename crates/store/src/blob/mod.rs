@@ -46,6 +46,14 @@ pub struct BlobPaths {
     path_other: PathBuf,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlobMetadata {
+    pub size: u64,
+    pub modified: Option<u64>,
+    // Changes whenever the blob is rewritten, even if its id is reused
+    pub version: Option<String>,
+}
+
 impl BlobStore {
     pub async fn new(config: &Config) -> crate::Result<Self> {
         match config.value_require("store.blob.type")? {
@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::{io::SeekFrom, ops::Range};
+use std::{io::SeekFrom, ops::Range, time::UNIX_EPOCH};
 
 use tokio::{
     fs::{self, File},
@@ -30,7 +30,7 @@ use tokio::{
 
 use crate::{BlobKind, Store};
 
-use super::{get_local_path, get_s3_path, BlobStore};
+use super::{get_local_path, get_s3_path, BlobMetadata, BlobStore};
 
 impl Store {
     pub async fn get_blob(
@@ -98,4 +98,69 @@ impl Store {
             }
         }
     }
+
+    pub async fn get_blob_metadata(&self, kind: &BlobKind) -> crate::Result<Option<BlobMetadata>> {
+        match &self.blob {
+            BlobStore::Local(base_path) => {
+                match fs::metadata(get_local_path(base_path, kind)).await {
+                    Ok(metadata) => {
+                        let modified = metadata
+                            .modified()
+                            .ok()
+                            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok());
+                        Ok(Some(BlobMetadata {
+                            size: metadata.len(),
+                            modified: modified.map(|modified| modified.as_secs()),
+                            version: modified.map(|modified| format!("{:x}", modified.as_nanos())),
+                        }))
+                    }
+                    Err(_) => Ok(None),
+                }
+            }
+            BlobStore::Remote(bucket) => match bucket.head_object(get_s3_path(kind)).await {
+                Ok((result, code)) if (200..300).contains(&code) => Ok(Some(BlobMetadata {
+                    size: result.content_length.unwrap_or_default() as u64,
+                    modified: None,
+                    version: result
+                        .e_tag
+                        .map(|e_tag| e_tag.trim_matches('"').to_string()),
+                })),
+                Ok((_, 404)) => Ok(None),
+                Ok((_, code)) => Err(crate::Error::InternalError(format!(
+                    "S3 error code {}",
+                    code
+                ))),
+                Err(err) => Err(err.into()),
+            },
+        }
+    }
+
+    pub async fn check_blob_store(&self) -> crate::Result<()> {
+        match &self.blob {
+            BlobStore::Local(base_path) => {
+                // Blob directories are created lazily, make sure the root is usable
+                let root_path = base_path
+                    .path_email
+                    .parent()
+                    .unwrap_or(&base_path.path_email);
+                fs::create_dir_all(root_path).await?;
+                if fs::metadata(root_path).await?.is_dir() {
+                    Ok(())
+                } else {
+                    Err(crate::Error::InternalError(format!(
+                        "Blob path {} is not a directory",
+                        root_path.display()
+                    )))
+                }
+            }
+            BlobStore::Remote(bucket) => match bucket.head_object("/.healthcheck").await {
+                Ok((_, code)) if (200..300).contains(&code) || code == 404 => Ok(()),
+                Ok((_, code)) => Err(crate::Error::InternalError(format!(
+                    "S3 error code {}",
+                    code
+                ))),
+                Err(err) => Err(err.into()),
+            },
+        }
+    }
 }
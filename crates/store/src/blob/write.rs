@@ -81,7 +81,13 @@ impl Store {
 
                     if fs::metadata(&src_path).await.is_ok() {
                         fs::create_dir_all(dest_path.parent().unwrap()).await?;
-                        fs::copy(src_path, dest_path).await?;
+
+                        // Message blobs are never modified, share them using a hard link
+                        if !matches!(src, BlobKind::LinkedMaildir { .. })
+                            || fs::hard_link(&src_path, &dest_path).await.is_err()
+                        {
+                            fs::copy(src_path, dest_path).await?;
+                        }
                         Ok(true)
                     } else {
                         Ok(false)
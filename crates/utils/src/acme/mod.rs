@@ -0,0 +1,454 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    io::Cursor,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
+    time::{Duration, SystemTime},
+};
+
+use ahash::AHashMap;
+use rustls::{
+    sign::{any_supported_type, CertifiedKey},
+    Certificate, PrivateKey,
+};
+use rustls_pemfile::{certs, read_one, Item};
+use tokio::{process::Command, sync::watch};
+use x509_parser::{certificate::X509Certificate, prelude::FromDer};
+
+use self::{jose::AccountKey, order::AcmeClient};
+
+pub mod jose;
+pub mod order;
+
+pub const LETS_ENCRYPT_PRODUCTION: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub struct AcmeProvider {
+    pub id: String,
+    pub directory_url: String,
+    pub contact: Vec<String>,
+    pub domains: Vec<String>,
+    pub challenge: ChallengeType,
+    pub cache_path: PathBuf,
+    pub renew_before: Duration,
+    cert: RwLock<Option<Arc<CertifiedKey>>>,
+    not_after: AtomicU64,
+    http_tokens: RwLock<AHashMap<String, String>>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ChallengeType {
+    Http01,
+    Dns01 {
+        command: String,
+        arguments: Vec<String>,
+        propagation_delay: Duration,
+    },
+}
+
+impl std::fmt::Debug for AcmeProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AcmeProvider")
+            .field("id", &self.id)
+            .field("directory_url", &self.directory_url)
+            .field("domains", &self.domains)
+            .field("challenge", &self.challenge)
+            .finish()
+    }
+}
+
+impl AcmeProvider {
+    pub fn new(
+        id: String,
+        directory_url: String,
+        contact: Vec<String>,
+        domains: Vec<String>,
+        challenge: ChallengeType,
+        cache_path: PathBuf,
+        renew_before: Duration,
+    ) -> Self {
+        AcmeProvider {
+            id,
+            directory_url,
+            contact,
+            domains,
+            challenge,
+            cache_path,
+            renew_before,
+            cert: RwLock::new(None),
+            not_after: AtomicU64::new(0),
+            http_tokens: RwLock::new(AHashMap::new()),
+        }
+    }
+
+    pub fn certificate(&self) -> Option<Arc<CertifiedKey>> {
+        self.cert.read().ok()?.clone()
+    }
+
+    pub fn has_domain(&self, name: &str) -> bool {
+        self.domains.iter().any(|domain| {
+            if let Some(suffix) = domain.strip_prefix("*.") {
+                name.split_once('.')
+                    .map_or(false, |(_, name)| name.eq_ignore_ascii_case(suffix))
+            } else {
+                domain.eq_ignore_ascii_case(name)
+            }
+        })
+    }
+
+    pub fn http_challenge(&self, token: &str) -> Option<String> {
+        self.http_tokens.read().ok()?.get(token).cloned()
+    }
+
+    pub fn spawn(self: Arc<Self>, mut shutdown_rx: watch::Receiver<bool>) {
+        tokio::spawn(async move {
+            if let Err(err) = self.load_cache().await {
+                tracing::warn!(
+                    context = "acme",
+                    event = "error",
+                    id = self.id,
+                    reason = err,
+                    "Failed to load cached certificate."
+                );
+            }
+
+            let mut next_attempt = self.time_to_renewal();
+            loop {
+                if tokio::time::timeout(next_attempt, shutdown_rx.changed())
+                    .await
+                    .is_ok()
+                {
+                    tracing::debug!(context = "acme", id = self.id, "ACME task exiting.");
+                    return;
+                }
+
+                tracing::info!(
+                    context = "acme",
+                    event = "renew",
+                    id = self.id,
+                    domains = ?self.domains,
+                    "Requesting certificate."
+                );
+                next_attempt = match self.renew().await {
+                    Ok(_) => {
+                        tracing::info!(
+                            context = "acme",
+                            event = "success",
+                            id = self.id,
+                            "Certificate installed."
+                        );
+                        self.time_to_renewal()
+                    }
+                    Err(err) => {
+                        tracing::warn!(
+                            context = "acme",
+                            event = "error",
+                            id = self.id,
+                            reason = err,
+                            "Failed to obtain certificate."
+                        );
+                        RETRY_INTERVAL
+                    }
+                };
+            }
+        });
+    }
+
+    fn time_to_renewal(&self) -> Duration {
+        let renew_at = self
+            .not_after
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.renew_before.as_secs());
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Duration::from_secs(renew_at.saturating_sub(now))
+    }
+
+    async fn load_cache(&self) -> Result<(), String> {
+        let cert_path = self.cache_file("cert.pem");
+        let key_path = self.cache_file("key.pem");
+        if cert_path.exists() && key_path.exists() {
+            let cert = tokio::fs::read(&cert_path)
+                .await
+                .map_err(|err| format!("Failed to read {}: {err}", cert_path.display()))?;
+            let key = tokio::fs::read(&key_path)
+                .await
+                .map_err(|err| format!("Failed to read {}: {err}", key_path.display()))?;
+            self.install(&cert, &key)?;
+        }
+
+        Ok(())
+    }
+
+    async fn renew(&self) -> Result<(), String> {
+        tokio::fs::create_dir_all(&self.cache_path)
+            .await
+            .map_err(|err| format!("Failed to create {}: {err}", self.cache_path.display()))?;
+
+        // Load or create the account key
+        let key_path = self.cache_file("account.key");
+        let key = if key_path.exists() {
+            AccountKey::from_pkcs8(
+                &tokio::fs::read(&key_path)
+                    .await
+                    .map_err(|err| format!("Failed to read {}: {err}", key_path.display()))?,
+            )?
+        } else {
+            let key = AccountKey::generate()?;
+            write_private(&key_path, key.pkcs8()).await?;
+            key
+        };
+
+        // Place order
+        let mut client = AcmeClient::new(&self.directory_url, key, &self.contact).await?;
+        let (order_url, order) = client.new_order(&self.domains).await?;
+
+        // Complete challenges
+        for url in &order.authorizations {
+            let authorization = client.authorization(url).await?;
+            if authorization.status == order::Status::Valid {
+                continue;
+            }
+
+            let typ = match &self.challenge {
+                ChallengeType::Http01 => "http-01",
+                ChallengeType::Dns01 { .. } => "dns-01",
+            };
+            let challenge = authorization
+                .challenges
+                .iter()
+                .find(|challenge| challenge.typ == typ)
+                .ok_or_else(|| {
+                    format!(
+                        "No {typ} challenge offered for {}.",
+                        authorization.identifier.value
+                    )
+                })?;
+            let domain = authorization.identifier.value.as_str();
+
+            self.present_challenge(&client, domain, &challenge.token)
+                .await?;
+            let result = async {
+                client.trigger_challenge(&challenge.url).await?;
+                client.wait_for_authorization(url).await
+            }
+            .await;
+            self.cleanup_challenge(&client, domain, &challenge.token)
+                .await;
+            result?;
+        }
+
+        // Generate key pair and request certificate
+        let mut params = rcgen::CertificateParams::new(self.domains.clone());
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+        let csr = rcgen::Certificate::from_params(params)
+            .map_err(|err| format!("Failed to generate key pair: {err}"))?;
+        let cert = client
+            .finalize(
+                &order_url,
+                &order.finalize,
+                &csr.serialize_request_der()
+                    .map_err(|err| format!("Failed to generate CSR: {err}"))?,
+            )
+            .await?;
+        let key = csr.serialize_private_key_pem();
+
+        // Install and cache certificate
+        self.install(cert.as_bytes(), key.as_bytes())?;
+        write_private(&self.cache_file("key.pem"), key.as_bytes()).await?;
+        write_private(&self.cache_file("cert.pem"), cert.as_bytes()).await
+    }
+
+    async fn present_challenge(
+        &self,
+        client: &AcmeClient,
+        domain: &str,
+        token: &str,
+    ) -> Result<(), String> {
+        match &self.challenge {
+            ChallengeType::Http01 => {
+                if let Ok(mut tokens) = self.http_tokens.write() {
+                    tokens.insert(token.to_string(), client.key().key_authorization(token));
+                }
+                Ok(())
+            }
+            ChallengeType::Dns01 {
+                propagation_delay, ..
+            } => {
+                self.run_dns_command("present", domain, &client.key().dns_txt_value(token))
+                    .await?;
+                tokio::time::sleep(*propagation_delay).await;
+                Ok(())
+            }
+        }
+    }
+
+    async fn cleanup_challenge(&self, client: &AcmeClient, domain: &str, token: &str) {
+        match &self.challenge {
+            ChallengeType::Http01 => {
+                if let Ok(mut tokens) = self.http_tokens.write() {
+                    tokens.remove(token);
+                }
+            }
+            ChallengeType::Dns01 { .. } => {
+                if let Err(err) = self
+                    .run_dns_command("cleanup", domain, &client.key().dns_txt_value(token))
+                    .await
+                {
+                    tracing::debug!(
+                        context = "acme",
+                        event = "error",
+                        id = self.id,
+                        reason = err,
+                        "Failed to remove DNS challenge."
+                    );
+                }
+            }
+        }
+    }
+
+    async fn run_dns_command(&self, action: &str, domain: &str, value: &str) -> Result<(), String> {
+        let ChallengeType::Dns01 {
+            command, arguments, ..
+        } = &self.challenge
+        else {
+            return Ok(());
+        };
+        let name = format!("_acme-challenge.{}", domain.trim_start_matches("*."));
+        let status = Command::new(command)
+            .args(arguments.iter().map(|argument| {
+                argument
+                    .replace("${action}", action)
+                    .replace("${name}", &name)
+                    .replace("${value}", value)
+            }))
+            .env("ACME_ACTION", action)
+            .env("ACME_NAME", &name)
+            .env("ACME_VALUE", value)
+            .kill_on_drop(true)
+            .status()
+            .await
+            .map_err(|err| format!("Failed to run {command:?}: {err}"))?;
+
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!(
+                "DNS command {command:?} exited with status {status}."
+            ))
+        }
+    }
+
+    fn install(&self, cert: &[u8], key: &[u8]) -> Result<(), String> {
+        let certs = certs(&mut Cursor::new(cert))
+            .map_err(|err| format!("Failed to read certificate: {err}"))?
+            .into_iter()
+            .map(Certificate)
+            .collect::<Vec<_>>();
+        let not_after = certs
+            .first()
+            .and_then(|cert| X509Certificate::from_der(&cert.0).ok())
+            .map(|(_, cert)| cert.validity().not_after.timestamp())
+            .ok_or_else(|| "Failed to parse certificate.".to_string())?;
+        let key = match read_one(&mut Cursor::new(key))
+            .map_err(|err| format!("Failed to read private key: {err}"))?
+        {
+            Some(Item::PKCS8Key(key) | Item::ECKey(key) | Item::RSAKey(key)) => PrivateKey(key),
+            _ => return Err("Private key not found.".to_string()),
+        };
+
+        let cert = Arc::new(CertifiedKey {
+            cert: certs,
+            key: any_supported_type(&key)
+                .map_err(|err| format!("Failed to sign certificate: {err}"))?,
+            ocsp: None,
+            sct_list: None,
+        });
+        if let Ok(mut current) = self.cert.write() {
+            *current = Some(cert);
+        }
+        self.not_after
+            .store(not_after.max(0) as u64, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn cache_file(&self, name: &str) -> PathBuf {
+        let mut path = self.cache_path.clone();
+        path.push(format!("{}.{}", self.id, name));
+        path
+    }
+}
+
+async fn write_private(path: &PathBuf, contents: &[u8]) -> Result<(), String> {
+    tokio::fs::write(path, contents)
+        .await
+        .map_err(|err| format!("Failed to write {}: {err}", path.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        tokio::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+            .await
+            .map_err(|err| format!("Failed to set permissions on {}: {err}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use super::{AcmeProvider, ChallengeType};
+
+    #[test]
+    fn domain_matching() {
+        let provider = AcmeProvider::new(
+            "test".to_string(),
+            super::LETS_ENCRYPT_PRODUCTION.to_string(),
+            vec![],
+            vec!["mail.example.org".to_string(), "*.example.com".to_string()],
+            ChallengeType::Http01,
+            PathBuf::from("/tmp"),
+            Duration::from_secs(30 * 86400),
+        );
+
+        assert!(provider.has_domain("mail.example.org"));
+        assert!(provider.has_domain("MAIL.example.org"));
+        assert!(provider.has_domain("imap.example.com"));
+        assert!(!provider.has_domain("example.com"));
+        assert!(!provider.has_domain("a.b.example.com"));
+        assert!(!provider.has_domain("imap.example.org"));
+        assert!(provider.certificate().is_none());
+        assert_eq!(provider.time_to_renewal(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,301 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use reqwest::{header::CONTENT_TYPE, Method, Response};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::jose::AccountKey;
+
+const JOSE_JSON: &str = "application/jose+json";
+const MAX_POLL_ATTEMPTS: usize = 30;
+
+pub struct AcmeClient {
+    client: reqwest::Client,
+    key: AccountKey,
+    kid: String,
+    nonce: String,
+    directory: Directory,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Directory {
+    pub new_nonce: String,
+    pub new_account: String,
+    pub new_order: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Order {
+    pub status: Status,
+    pub authorizations: Vec<String>,
+    pub finalize: String,
+    pub certificate: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Authorization {
+    pub status: Status,
+    pub identifier: Identifier,
+    pub challenges: Vec<Challenge>,
+    #[serde(default)]
+    pub wildcard: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Identifier {
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Challenge {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub url: String,
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    Ready,
+    Processing,
+    Valid,
+    Invalid,
+    Expired,
+    Revoked,
+    Deactivated,
+}
+
+impl AcmeClient {
+    pub async fn new(
+        directory_url: &str,
+        key: AccountKey,
+        contact: &[String],
+    ) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .user_agent(concat!("Stalwart/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|err| format!("Failed to create HTTP client: {err}"))?;
+
+        // Fetch directory and initial nonce
+        let directory = client
+            .get(directory_url)
+            .send()
+            .await
+            .map_err(|err| format!("Failed to fetch ACME directory: {err}"))?
+            .json::<Directory>()
+            .await
+            .map_err(|err| format!("Failed to parse ACME directory: {err}"))?;
+        let nonce = replay_nonce(
+            &client
+                .head(&directory.new_nonce)
+                .send()
+                .await
+                .map_err(|err| format!("Failed to obtain ACME nonce: {err}"))?,
+        )?;
+
+        let mut client = AcmeClient {
+            client,
+            key,
+            kid: String::new(),
+            nonce,
+            directory,
+        };
+
+        // Create or look up the account, its URL is used as the key id
+        let response = client
+            .post(
+                &client.directory.new_account.clone(),
+                Some(&json!({
+                    "termsOfServiceAgreed": true,
+                    "contact": contact,
+                })),
+            )
+            .await?;
+        client.kid = location(&response)?;
+
+        Ok(client)
+    }
+
+    pub fn key(&self) -> &AccountKey {
+        &self.key
+    }
+
+    pub async fn new_order(&mut self, domains: &[String]) -> Result<(String, Order), String> {
+        let identifiers = domains
+            .iter()
+            .map(|domain| json!({"type": "dns", "value": domain}))
+            .collect::<Vec<_>>();
+        let response = self
+            .post(
+                &self.directory.new_order.clone(),
+                Some(&json!({ "identifiers": identifiers })),
+            )
+            .await?;
+        let url = location(&response)?;
+        Ok((url, parse_json(response).await?))
+    }
+
+    pub async fn order(&mut self, url: &str) -> Result<Order, String> {
+        parse_json(self.post(url, None).await?).await
+    }
+
+    pub async fn authorization(&mut self, url: &str) -> Result<Authorization, String> {
+        parse_json(self.post(url, None).await?).await
+    }
+
+    pub async fn trigger_challenge(&mut self, url: &str) -> Result<(), String> {
+        self.post(url, Some(&json!({}))).await.map(|_| ())
+    }
+
+    pub async fn wait_for_authorization(&mut self, url: &str) -> Result<(), String> {
+        for _ in 0..MAX_POLL_ATTEMPTS {
+            match self.authorization(url).await?.status {
+                Status::Valid => return Ok(()),
+                Status::Pending | Status::Processing => {
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                }
+                status => {
+                    return Err(format!(
+                        "Authorization {url} failed with status {status:?}."
+                    ))
+                }
+            }
+        }
+
+        Err(format!("Timed out waiting for authorization {url}."))
+    }
+
+    pub async fn finalize(
+        &mut self,
+        order_url: &str,
+        finalize_url: &str,
+        csr: &[u8],
+    ) -> Result<String, String> {
+        self.post(
+            finalize_url,
+            Some(&json!({ "csr": URL_SAFE_NO_PAD.encode(csr) })),
+        )
+        .await?;
+
+        // Wait for the certificate to be issued
+        for _ in 0..MAX_POLL_ATTEMPTS {
+            let order = self.order(order_url).await?;
+            match order.status {
+                Status::Valid => {
+                    let url = order
+                        .certificate
+                        .ok_or_else(|| "Order is missing the certificate URL.".to_string())?;
+                    return self
+                        .post(&url, None)
+                        .await?
+                        .text()
+                        .await
+                        .map_err(|err| format!("Failed to download certificate: {err}"));
+                }
+                Status::Pending | Status::Ready | Status::Processing => {
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                }
+                status => return Err(format!("Order {order_url} failed with status {status:?}.")),
+            }
+        }
+
+        Err(format!("Timed out waiting for order {order_url}."))
+    }
+
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<Response, String> {
+        let mut retries = 0;
+
+        loop {
+            let body = self.key.sign(
+                url,
+                &self.nonce,
+                (!self.kid.is_empty()).then_some(self.kid.as_str()),
+                payload,
+            )?;
+            let response = self
+                .client
+                .request(Method::POST, url)
+                .header(CONTENT_TYPE, JOSE_JSON)
+                .body(body.to_string())
+                .send()
+                .await
+                .map_err(|err| format!("ACME request to {url} failed: {err}"))?;
+
+            if let Ok(nonce) = replay_nonce(&response) {
+                self.nonce = nonce;
+            }
+
+            if response.status().is_success() {
+                return Ok(response);
+            }
+
+            let status = response.status();
+            let problem = response.json::<Value>().await.unwrap_or_default();
+            let problem_type = problem["type"].as_str().unwrap_or_default();
+
+            // Nonces can expire, retry once with a fresh one
+            if problem_type == "urn:ietf:params:acme:error:badNonce" && retries == 0 {
+                retries += 1;
+                continue;
+            }
+
+            return Err(format!(
+                "ACME request to {url} failed with status {status}: {}",
+                problem["detail"].as_str().unwrap_or(problem_type)
+            ));
+        }
+    }
+}
+
+fn replay_nonce(response: &Response) -> Result<String, String> {
+    response
+        .headers()
+        .get("replay-nonce")
+        .and_then(|nonce| nonce.to_str().ok())
+        .map(|nonce| nonce.to_string())
+        .ok_or_else(|| "ACME server did not return a nonce.".to_string())
+}
+
+fn location(response: &Response) -> Result<String, String> {
+    response
+        .headers()
+        .get("location")
+        .and_then(|location| location.to_str().ok())
+        .map(|location| location.to_string())
+        .ok_or_else(|| "ACME server did not return a location.".to_string())
+}
+
+async fn parse_json<T: serde::de::DeserializeOwned>(response: Response) -> Result<T, String> {
+    response
+        .json::<T>()
+        .await
+        .map_err(|err| format!("Failed to parse ACME response: {err}"))
+}
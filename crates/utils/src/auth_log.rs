@@ -0,0 +1,130 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{fmt::Display, io::Write, path::Path, sync::OnceLock};
+
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+
+use crate::config::Config;
+
+// Account names are supplied by the client, long names are truncated so
+// that entries remain a single short line.
+const MAX_ACCOUNT_LEN: usize = 128;
+
+static AUTH_LOG: OnceLock<AuthLog> = OnceLock::new();
+
+struct AuthLog {
+    writer: NonBlocking,
+    _guard: WorkerGuard,
+}
+
+// Opens the authentication failure log, entries are appended to the file
+// configured in "global.auth-log.path" using a stable single-line format
+// suitable for tools such as fail2ban.
+pub fn enable_auth_log(config: &Config) -> crate::config::Result<()> {
+    let path = if let Some(path) = config.value("global.auth-log.path") {
+        Path::new(path)
+    } else {
+        return Ok(());
+    };
+    let (dir, file_name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(file_name)) => (
+            if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            },
+            file_name,
+        ),
+        _ => {
+            return Err(format!(
+                "Invalid path {:?} for property \"global.auth-log.path\".",
+                path.display()
+            ))
+        }
+    };
+    let (writer, guard) =
+        tracing_appender::non_blocking(tracing_appender::rolling::never(dir, file_name));
+    let _ = AUTH_LOG.set(AuthLog {
+        writer,
+        _guard: guard,
+    });
+
+    Ok(())
+}
+
+pub fn log_auth_failure(remote_ip: impl Display, account: &str) {
+    if let Some(log) = AUTH_LOG.get() {
+        let _ = log.writer.clone().write_all(
+            format_auth_failure(
+                &chrono::Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                remote_ip,
+                account,
+            )
+            .as_bytes(),
+        );
+    }
+}
+
+fn format_auth_failure(timestamp: &str, remote_ip: impl Display, account: &str) -> String {
+    // Quotes and control characters are escaped to prevent log injection
+    let account = account.chars().take(MAX_ACCOUNT_LEN).collect::<String>();
+    format!("{timestamp} authentication failure; remote-ip={remote_ip} account={account:?}\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use super::format_auth_failure;
+
+    #[test]
+    fn auth_failure_format() {
+        let ip: IpAddr = "192.168.1.10".parse().unwrap();
+        assert_eq!(
+            format_auth_failure("2023-10-15T09:23:14Z", ip, "john@example.org"),
+            concat!(
+                "2023-10-15T09:23:14Z authentication failure; ",
+                "remote-ip=192.168.1.10 account=\"john@example.org\"\n"
+            )
+        );
+
+        // Client supplied names cannot forge additional entries
+        assert_eq!(
+            format_auth_failure(
+                "2023-10-15T09:23:14Z",
+                ip,
+                "x\"\n2023-10-15T09:23:14Z authentication failure; remote-ip=10.0.0.1"
+            ),
+            concat!(
+                "2023-10-15T09:23:14Z authentication failure; remote-ip=192.168.1.10 ",
+                "account=\"x\\\"\\n2023-10-15T09:23:14Z authentication failure; ",
+                "remote-ip=10.0.0.1\"\n"
+            )
+        );
+        assert_eq!(
+            format_auth_failure("2023-10-15T09:23:14Z", ip, &"a".repeat(500)).len(),
+            format_auth_failure("2023-10-15T09:23:14Z", ip, &"a".repeat(128)).len()
+        );
+    }
+}
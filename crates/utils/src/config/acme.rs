@@ -0,0 +1,94 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use crate::acme::{AcmeProvider, ChallengeType, LETS_ENCRYPT_PRODUCTION};
+
+use super::Config;
+
+impl Config {
+    pub fn parse_acme_providers(&self) -> super::Result<Vec<Arc<AcmeProvider>>> {
+        let mut providers = Vec::new();
+        for id in self.sub_keys("acme") {
+            providers.push(Arc::new(self.parse_acme_provider(id)?));
+        }
+
+        Ok(providers)
+    }
+
+    fn parse_acme_provider(&self, id: &str) -> super::Result<AcmeProvider> {
+        let domains = self
+            .values(("acme", id, "domains"))
+            .map(|(_, domain)| domain.trim().to_lowercase())
+            .collect::<Vec<_>>();
+        if domains.is_empty() {
+            return Err(format!("No domains defined for ACME provider {id:?}."));
+        }
+
+        let challenge = match self.value(("acme", id, "challenge")).unwrap_or("http-01") {
+            "http-01" => {
+                if let Some(domain) = domains.iter().find(|domain| domain.starts_with("*.")) {
+                    return Err(format!(
+                        "Wildcard domain {domain:?} for ACME provider {id:?} requires the dns-01 challenge."
+                    ));
+                }
+                ChallengeType::Http01
+            }
+            "dns-01" => ChallengeType::Dns01 {
+                command: self.value_require(("acme", id, "dns.command"))?.to_string(),
+                arguments: self
+                    .values(("acme", id, "dns.arguments"))
+                    .map(|(_, v)| v.to_string())
+                    .collect(),
+                propagation_delay: self
+                    .property_or_static(("acme", id, "dns.propagation-delay"), "30s")?,
+            },
+            challenge => {
+                return Err(format!(
+                    "Invalid challenge {challenge:?} for ACME provider {id:?}, expected \"http-01\" or \"dns-01\"."
+                ))
+            }
+        };
+
+        Ok(AcmeProvider::new(
+            id.to_string(),
+            self.value(("acme", id, "directory"))
+                .unwrap_or(LETS_ENCRYPT_PRODUCTION)
+                .to_string(),
+            self.values(("acme", id, "contact"))
+                .map(|(_, contact)| {
+                    if contact.starts_with("mailto:") {
+                        contact.to_string()
+                    } else {
+                        format!("mailto:{contact}")
+                    }
+                })
+                .collect(),
+            domains,
+            challenge,
+            self.property_require(("acme", id, "cache"))?,
+            self.property_or_static(("acme", id, "renew-before"), "30d")?,
+        ))
+    }
+}
@@ -23,31 +23,130 @@
 
 use std::{io::Cursor, sync::Arc};
 
+use ahash::AHashMap;
 use rustls::{
-    server::{ClientHello, ResolvesServerCert, ResolvesServerCertUsingSni},
+    server::{ClientHello, ResolvesServerCert},
     sign::CertifiedKey,
     version::{TLS12, TLS13},
-    Certificate, PrivateKey, SupportedProtocolVersion,
+    Certificate, PrivateKey, RootCertStore, SupportedProtocolVersion,
 };
 use rustls_pemfile::{certs, read_one, Item};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
 
-use super::Config;
+use crate::acme::AcmeProvider;
+
+use super::{utils::AsKey, Config};
 
 pub static TLS13_VERSION: &[&SupportedProtocolVersion] = &[&TLS13];
 pub static TLS12_VERSION: &[&SupportedProtocolVersion] = &[&TLS12];
 
 pub struct CertificateResolver {
-    pub resolver: Option<ResolvesServerCertUsingSni>,
+    pub sni: AHashMap<String, Arc<CertifiedKey>>,
     pub default_cert: Option<Arc<CertifiedKey>>,
+    pub acme: Vec<Arc<AcmeProvider>>,
+}
+
+/// Looks up a server name in a map of SNI subjects, trying an exact match first
+/// and then a wildcard entry (`*.example.org`) for the parent domain.
+pub fn sni_lookup<'x, T>(map: &'x AHashMap<String, T>, name: &str) -> Option<&'x T> {
+    if map.is_empty() {
+        return None;
+    }
+    let name = name.trim_end_matches('.').to_lowercase();
+    map.get(&name).or_else(|| {
+        name.split_once('.')
+            .and_then(|(_, parent)| map.get(&format!("*.{parent}")))
+    })
 }
 
 impl ResolvesServerCert for CertificateResolver {
     fn resolve(&self, hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
-        self.resolver
-            .as_ref()
-            .and_then(|r| r.resolve(hello))
+        // Certificates obtained via ACME take precedence for their domains
+        if let Some(name) = hello.server_name() {
+            if let Some(cert) = self
+                .acme
+                .iter()
+                .filter(|provider| provider.has_domain(name))
+                .find_map(|provider| provider.certificate())
+            {
+                return Some(cert);
+            }
+        }
+
+        hello
+            .server_name()
+            .and_then(|name| sni_lookup(&self.sni, name))
+            .cloned()
             .or_else(|| self.default_cert.clone())
+            .or_else(|| self.acme.iter().find_map(|provider| provider.certificate()))
+    }
+}
+
+/// Explicit mapping of client certificate identities and fingerprints to accounts.
+/// Certificates are only accepted as credentials when listed here, a CA-signed
+/// certificate alone does not grant access to any account.
+#[derive(Debug, Clone, Default)]
+pub struct ClientCertificateMap {
+    accounts: AHashMap<String, String>,
+}
+
+impl ClientCertificateMap {
+    /// Returns the first identity with an explicit mapping and its account name.
+    pub fn account<'x>(&'x self, identities: &'x [String]) -> Option<(&'x str, &'x str)> {
+        identities.iter().find_map(|identity| {
+            self.accounts
+                .get(identity)
+                .map(|account| (identity.as_str(), account.as_str()))
+        })
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+}
+
+/// Returns the identities asserted by a client certificate, in order of preference:
+/// the SHA-256 fingerprint, e-mail addresses from the subject alternative names and
+/// the subject, followed by DNS names and the subject common name.
+pub fn client_certificate_identities(cert: &Certificate) -> Vec<String> {
+    let mut emails = vec![certificate_fingerprint(cert)];
+    let mut names = Vec::new();
+
+    if let Ok((_, cert)) = X509Certificate::from_der(&cert.0) {
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::RFC822Name(email) => emails.push(email.to_lowercase()),
+                    GeneralName::DNSName(name) => names.push(name.to_lowercase()),
+                    _ => (),
+                }
+            }
+        }
+        for email in cert.subject().iter_email() {
+            if let Ok(email) = email.as_str() {
+                emails.push(email.to_lowercase());
+            }
+        }
+        for cn in cert.subject().iter_common_name() {
+            if let Ok(cn) = cn.as_str() {
+                names.push(cn.to_lowercase());
+            }
+        }
+    }
+
+    emails.extend(names);
+    emails.dedup();
+    emails
+}
+
+pub fn certificate_fingerprint(cert: &Certificate) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, &cert.0);
+    let mut fingerprint = String::with_capacity(7 + digest.as_ref().len() * 2);
+    fingerprint.push_str("sha256:");
+    for byte in digest.as_ref() {
+        fingerprint.push_str(&format!("{byte:02x}"));
+    }
+    fingerprint
 }
 
 impl Config {
@@ -73,6 +172,49 @@ impl Config {
         }
     }
 
+    pub fn parse_client_certificate_map(&self) -> super::Result<ClientCertificateMap> {
+        let mut accounts = AHashMap::new();
+        for id in self.sub_keys("server.tls.client-auth.map") {
+            let identity = if let Some(fingerprint) =
+                self.value(("server.tls.client-auth.map", id, "fingerprint"))
+            {
+                format!(
+                    "sha256:{}",
+                    fingerprint.replace(':', "").trim().to_ascii_lowercase()
+                )
+            } else {
+                self.value_require(("server.tls.client-auth.map", id, "identity"))?
+                    .trim()
+                    .to_lowercase()
+            };
+            accounts.insert(
+                identity,
+                self.value_require(("server.tls.client-auth.map", id, "account"))?
+                    .to_string(),
+            );
+        }
+
+        Ok(ClientCertificateMap { accounts })
+    }
+
+    pub fn rustls_root_store(&self, key: impl AsKey) -> super::Result<RootCertStore> {
+        let key = key.as_key();
+        let mut roots = RootCertStore::empty();
+        for cert in certs(&mut Cursor::new(self.file_contents(key.as_str())?))
+            .map_err(|err| format!("Failed to read certificates in {key:?}: {err}"))?
+        {
+            roots
+                .add(&Certificate(cert))
+                .map_err(|err| format!("Failed to add certificate in {key:?}: {err}"))?;
+        }
+
+        if !roots.is_empty() {
+            Ok(roots)
+        } else {
+            Err(format!("No certificates found in {key:?}."))
+        }
+    }
+
     pub fn rustls_private_key(&self, cert_id: &str) -> super::Result<PrivateKey> {
         match read_one(&mut Cursor::new(self.file_contents((
             "certificate",
@@ -95,3 +237,26 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ahash::AHashMap;
+
+    use super::sni_lookup;
+
+    #[test]
+    fn sni_wildcard_lookup() {
+        let mut map = AHashMap::new();
+        map.insert("mail.example.org".to_string(), 1);
+        map.insert("*.example.org".to_string(), 2);
+        map.insert("*.example.com".to_string(), 3);
+
+        assert_eq!(sni_lookup(&map, "mail.example.org"), Some(&1));
+        assert_eq!(sni_lookup(&map, "MAIL.Example.org."), Some(&1));
+        assert_eq!(sni_lookup(&map, "imap.example.org"), Some(&2));
+        assert_eq!(sni_lookup(&map, "imap.example.com"), Some(&3));
+        assert_eq!(sni_lookup(&map, "example.com"), None);
+        assert_eq!(sni_lookup(&map, "a.b.example.com"), None);
+        assert_eq!(sni_lookup(&map, "example.net"), None);
+    }
+}
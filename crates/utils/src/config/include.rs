@@ -0,0 +1,301 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use ahash::AHashMap;
+
+use super::{Config, ReplaceMacros, Result};
+
+// Configuration files are composed in the following order:
+//
+// 1. The main configuration file.
+// 2. Files listed under 'include.files' and every '*.toml' file in the
+//    directories listed under 'include.directories', recursively and in the
+//    order they were declared. Directory entries are sorted by file name.
+//    Defining the same key in more than one of these files is an error.
+// 3. Overlays listed under 'include.overlays' followed by the overlays passed
+//    on the command line. Keys defined in an overlay replace any previous
+//    value, including whole arrays.
+//
+// Macros are replaced once all files have been loaded.
+
+#[derive(Default)]
+struct Loader {
+    macros: AHashMap<String, String>,
+    overlays: Vec<PathBuf>,
+    stack: Vec<PathBuf>,
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>, overlays: &[PathBuf]) -> Result<Self> {
+        let mut loader = Loader::default();
+        let mut config = Config::default();
+        loader.include(&mut config, path.as_ref())?;
+
+        // Apply overlays
+        let mut overlay_files = std::mem::take(&mut loader.overlays);
+        overlay_files.extend(overlays.iter().cloned());
+        for overlay_file in overlay_files {
+            let mut overlay = Config::default();
+            loader.include(&mut overlay, &overlay_file)?;
+            config.apply_overlay(overlay);
+        }
+
+        // Replace macros
+        for (key, value) in &mut config.keys {
+            value.replace_macros(key, &loader.macros)?;
+        }
+
+        Ok(config)
+    }
+
+    pub fn apply_overlay(&mut self, overlay: Config) {
+        // Arrays are replaced as a whole
+        let mut arrays = Vec::new();
+        for key in overlay.keys.keys() {
+            if let Some(prefix) = array_prefix(key) {
+                if !arrays.contains(&prefix) {
+                    arrays.push(prefix);
+                }
+            }
+        }
+        if !arrays.is_empty() {
+            self.keys.retain(|key, _| {
+                array_prefix(key).map_or(true, |prefix| !arrays.contains(&prefix))
+            });
+        }
+
+        self.keys.extend(overlay.keys);
+    }
+
+    pub fn to_effective_config(&self) -> String {
+        let mut result = String::with_capacity(self.keys.len() * 32);
+        for (key, value) in &self.keys {
+            let _ = write!(result, "{key} = \"");
+            for ch in value.chars() {
+                match ch {
+                    '\\' => result.push_str("\\\\"),
+                    '\"' => result.push_str("\\\""),
+                    '\n' => result.push_str("\\n"),
+                    '\r' => result.push_str("\\r"),
+                    '\t' => result.push_str("\\t"),
+                    ch => result.push(ch),
+                }
+            }
+            result.push_str("\"\n");
+        }
+        result
+    }
+}
+
+impl Loader {
+    fn include(&mut self, config: &mut Config, path: &Path) -> Result<()> {
+        let canonical_path = path
+            .canonicalize()
+            .map_err(|err| format!("Could not read configuration file {path:?}: {err}"))?;
+        if self.stack.contains(&canonical_path) {
+            let mut chain = String::new();
+            for file in &self.stack {
+                let _ = write!(chain, "{} -> ", file.display());
+            }
+            return Err(format!(
+                "Circular include detected: {chain}{}",
+                canonical_path.display()
+            ));
+        }
+
+        let mut file = Config::default();
+        file.parse(
+            &std::fs::read_to_string(&canonical_path)
+                .map_err(|err| format!("Could not read configuration file {path:?}: {err}"))?,
+        )
+        .map_err(|err| format!("Invalid configuration file {path:?}: {err}"))?;
+
+        // Extract macros and includes
+        let base_dir = canonical_path
+            .parent()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_default();
+        let mut files = Vec::new();
+        let mut directories = Vec::new();
+        file.keys.retain(|key, value| {
+            if let Some(macro_name) = key.strip_prefix("macros.") {
+                self.macros
+                    .insert(macro_name.to_ascii_lowercase(), std::mem::take(value));
+                false
+            } else {
+                true
+            }
+        });
+        for (key, mut value) in std::mem::take(&mut file.keys) {
+            if is_include_key(&key, "include.files") {
+                value.replace_macros(&key, &self.macros)?;
+                files.push(base_dir.join(value));
+            } else if is_include_key(&key, "include.directories") {
+                value.replace_macros(&key, &self.macros)?;
+                directories.push(base_dir.join(value));
+            } else if is_include_key(&key, "include.overlays") {
+                value.replace_macros(&key, &self.macros)?;
+                self.overlays.push(base_dir.join(value));
+            } else if config.keys.contains_key(&key) {
+                return Err(format!(
+                    "Duplicate key {key:?} in configuration file {path:?}."
+                ));
+            } else {
+                config.keys.insert(key, value);
+            }
+        }
+
+        // Include files
+        self.stack.push(canonical_path);
+        for file in files {
+            self.include(config, &file)?;
+        }
+        for directory in directories {
+            let mut entries = std::fs::read_dir(&directory)
+                .map_err(|err| {
+                    format!("Could not read configuration directory {directory:?}: {err}")
+                })?
+                .filter_map(|entry| {
+                    let path = entry.ok()?.path();
+                    if path.is_file() && path.extension().map_or(false, |ext| ext == "toml") {
+                        Some(path)
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+            entries.sort();
+            for entry in entries {
+                self.include(config, &entry)?;
+            }
+        }
+        self.stack.pop();
+
+        Ok(())
+    }
+}
+
+fn is_include_key(key: &str, prefix: &str) -> bool {
+    key.strip_prefix(prefix)
+        .map_or(false, |rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+// Returns the key of the array a key belongs to, if any.
+fn array_prefix(key: &str) -> Option<&str> {
+    let mut pos = 0;
+    for part in key.split('.') {
+        if pos > 0 && part.len() == 4 && part.bytes().all(|ch| ch.is_ascii_digit()) {
+            return Some(&key[..pos - 1]);
+        }
+        pos += part.len() + 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::config::Config;
+
+    #[test]
+    fn config_includes() {
+        let dir = std::env::temp_dir().join("stalwart_config_include_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("domains.d")).unwrap();
+        fs::create_dir_all(dir.join("env")).unwrap();
+
+        fs::write(
+            dir.join("config.toml"),
+            concat!(
+                "[macros]\nenv = \"production\"\n\n",
+                "[include]\nfiles = [\"common.toml\"]\n",
+                "directories = [\"domains.d\"]\n",
+                "overlays = [\"env/%{env}%.toml\"]\n\n",
+                "[server]\nhostname = \"mx.example.org\"\n",
+            ),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("common.toml"),
+            "[queue]\nretry = [\"1m\", \"5m\", \"10m\"]\nthreads = 4\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("domains.d").join("b.toml"),
+            "[domain.\"b.org\"]\ndkim = \"rsa\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("domains.d").join("a.toml"),
+            "[domain.\"a.org\"]\ndkim = \"ed25519\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("env").join("production.toml"),
+            "[queue]\nretry = [\"30s\"]\n\n[server]\nhostname = \"mx.prod.example.org\"\n",
+        )
+        .unwrap();
+        fs::write(dir.join("overlay.toml"), "queue.threads = 8\n").unwrap();
+
+        let config = Config::load(dir.join("config.toml"), &[dir.join("overlay.toml")]).unwrap();
+        assert_eq!(
+            config.to_effective_config(),
+            concat!(
+                "domain.a.org.dkim = \"ed25519\"\n",
+                "domain.b.org.dkim = \"rsa\"\n",
+                "queue.retry.0000 = \"30s\"\n",
+                "queue.threads = \"8\"\n",
+                "server.hostname = \"mx.prod.example.org\"\n",
+            )
+        );
+
+        // Duplicate keys across included files
+        fs::write(
+            dir.join("domains.d").join("c.toml"),
+            "[domain.\"a.org\"]\ndkim = \"rsa\"\n",
+        )
+        .unwrap();
+        assert!(Config::load(dir.join("config.toml"), &[])
+            .unwrap_err()
+            .contains("Duplicate key"));
+        fs::remove_file(dir.join("domains.d").join("c.toml")).unwrap();
+
+        // Circular includes
+        fs::write(
+            dir.join("common.toml"),
+            "[include]\nfiles = [\"config.toml\"]\n",
+        )
+        .unwrap();
+        assert!(Config::load(dir.join("config.toml"), &[])
+            .unwrap_err()
+            .contains("Circular include"));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -23,6 +23,7 @@
 
 use std::{net::SocketAddr, sync::Arc};
 
+use ahash::AHashMap;
 use rustls::{
     cipher_suite::{
         TLS13_AES_128_GCM_SHA256, TLS13_AES_256_GCM_SHA384, TLS13_CHACHA20_POLY1305_SHA256,
@@ -30,13 +31,13 @@ use rustls::{
         TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256, TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
         TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384, TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256,
     },
-    server::{NoClientAuth, ResolvesServerCertUsingSni},
+    server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, NoClientAuth},
     sign::{any_supported_type, CertifiedKey},
     ServerConfig, SupportedCipherSuite, ALL_CIPHER_SUITES, ALL_KX_GROUPS, ALL_VERSIONS,
 };
 use tokio::net::TcpSocket;
 
-use crate::UnwrapFailure;
+use crate::{acme::AcmeProvider, UnwrapFailure};
 
 use super::{
     certificate::{CertificateResolver, TLS12_VERSION, TLS13_VERSION},
@@ -46,9 +47,10 @@ use super::{
 
 impl Config {
     pub fn parse_servers(&self) -> super::Result<Servers> {
+        let acme = self.parse_acme_providers()?;
         let mut servers: Vec<Server> = Vec::new();
         for (internal_id, id) in self.sub_keys("server.listener").enumerate() {
-            let mut server = self.parse_server(id)?;
+            let mut server = self.parse_server(id, &acme)?;
             if !servers.iter().any(|s| s.id == server.id) {
                 server.internal_id = internal_id as u16;
                 servers.push(server);
@@ -57,15 +59,32 @@ impl Config {
             }
         }
 
+        // Make sure that no two listeners bind to the same address
+        let mut bound_addrs = AHashMap::new();
+        for server in &servers {
+            for listener in &server.listeners {
+                if let Some(other_id) = bound_addrs.insert(listener.addr, &server.id) {
+                    return Err(format!(
+                        "Listener {:?} binds to {} which is already in use by listener {:?}.",
+                        server.id, listener.addr, other_id
+                    ));
+                }
+            }
+        }
+
         if !servers.is_empty() {
-            Ok(Servers { inner: servers })
+            Ok(Servers {
+                inner: servers,
+                acme,
+            })
         } else {
             Err("No server directives found in config file.".to_string())
         }
     }
 
-    fn parse_server(&self, id: &str) -> super::Result<Server> {
+    fn parse_server(&self, id: &str, acme: &[Arc<AcmeProvider>]) -> super::Result<Server> {
         // Build TLS config
+        let mut tenants = AHashMap::new();
         let (tls, tls_implicit) = if self
             .property_or_default(("server.listener", id, "tls.enable"), "server.tls.enable")?
             .unwrap_or(false)
@@ -96,66 +115,105 @@ impl Config {
                 ciphers.push(protocol.parse_key(key)?);
             }
 
-            // Obtain default certificate
-            let cert_id = self
-                .value_or_default(
-                    ("server.listener", id, "tls.certificate"),
-                    "server.tls.certificate",
-                )
-                .ok_or_else(|| format!("Undefined certificate id for listener {id:?}."))?;
-            let cert = self.rustls_certificate(cert_id)?;
-            let pki = self.rustls_private_key(cert_id)?;
+            // Obtain default certificate, optional when certificates are obtained via ACME
+            let cert_id = self.value_or_default(
+                ("server.listener", id, "tls.certificate"),
+                "server.tls.certificate",
+            );
+            let default_key = match cert_id {
+                Some(cert_id) => Some((
+                    self.rustls_certificate(cert_id)?,
+                    self.rustls_private_key(cert_id)?,
+                )),
+                None if !acme.is_empty() => None,
+                None => return Err(format!("Undefined certificate id for listener {id:?}.")),
+            };
 
             // Add SNI certificates
-            let mut resolver = ResolvesServerCertUsingSni::new();
-            let mut has_sni = false;
+            let mut sni = AHashMap::new();
             for (key, value) in
                 self.values_or_default(("server.listener", id, "tls.sni"), "server.tls.sni")
             {
                 if let Some(prefix) = key.strip_suffix(".subject") {
-                    has_sni = true;
-                    resolver
-                        .add(
-                            value,
-                            match self.value((prefix, "certificate")) {
-                                Some(sni_cert_id) if sni_cert_id != cert_id => CertifiedKey {
-                                    cert: self.rustls_certificate(sni_cert_id)?,
-                                    key: any_supported_type(&self.rustls_private_key(sni_cert_id)?)
-                                        .map_err(|err| {
-                                            format!(
-                                                "Failed to sign SNI certificate for {key:?}: {err}",
-                                            )
-                                        })?,
-                                    ocsp: None,
-                                    sct_list: None,
-                                },
-                                _ => CertifiedKey {
-                                    cert: cert.clone(),
-                                    key:
-                                        any_supported_type(&pki).map_err(|err| {
-                                            format!(
-                                                "Failed to sign SNI certificate for {key:?}: {err}",
-                                            )
-                                        })?,
-                                    ocsp: None,
-                                    sct_list: None,
-                                },
-                            },
-                        )
-                        .map_err(|err| {
-                            format!("Failed to add SNI certificate for {key:?}: {err}")
-                        })?;
+                    let subject = value.trim().trim_end_matches('.').to_lowercase();
+                    if !is_valid_sni_subject(&subject) {
+                        return Err(format!(
+                            "Invalid SNI subject {value:?} for listener {id:?}."
+                        ));
+                    }
+                    let (cert, pki) = match (self.value((prefix, "certificate")), &default_key) {
+                        (Some(sni_cert_id), _) if Some(sni_cert_id) != cert_id => (
+                            self.rustls_certificate(sni_cert_id)?,
+                            self.rustls_private_key(sni_cert_id)?,
+                        ),
+                        (_, Some((cert, pki))) => (cert.clone(), pki.clone()),
+                        _ => {
+                            return Err(format!("Undefined certificate id for SNI entry {key:?}."))
+                        }
+                    };
+                    if let Some(tenant) = self.value((prefix, "tenant")) {
+                        tenants.insert(subject.clone(), tenant.trim().to_lowercase());
+                    }
+                    sni.insert(
+                        subject,
+                        Arc::new(CertifiedKey {
+                            cert,
+                            key: any_supported_type(&pki).map_err(|err| {
+                                format!("Failed to sign SNI certificate for {key:?}: {err}",)
+                            })?,
+                            ocsp: None,
+                            sct_list: None,
+                        }),
+                    );
                 }
             }
 
             // Add default certificate
-            let default_cert = Some(Arc::new(CertifiedKey {
-                cert,
-                key: any_supported_type(&pki)
-                    .map_err(|err| format!("Failed to sign certificate id {cert_id:?}: {err}"))?,
-                ocsp: None,
-                sct_list: None,
-            }));
+            let default_cert = match default_key {
+                Some((cert, pki)) => Some(Arc::new(CertifiedKey {
+                    cert,
+                    key: any_supported_type(&pki).map_err(|err| {
+                        format!(
+                            "Failed to sign certificate id {:?}: {err}",
+                            cert_id.unwrap_or_default()
+                        )
+                    })?,
+                    ocsp: None,
+                    sct_list: None,
+                })),
+                None => None,
+            };
+
+            // Build client certificate verifier
+            let client_verifier = match self
+                .value_or_default(
+                    ("server.listener", id, "tls.client-auth.mode"),
+                    "server.tls.client-auth.mode",
+                )
+                .unwrap_or("disable")
+            {
+                "disable" => NoClientAuth::boxed(),
+                mode @ ("optional" | "require") => {
+                    let roots = if self
+                        .value(("server.listener", id, "tls.client-auth.ca"))
+                        .is_some()
+                    {
+                        self.rustls_root_store(("server.listener", id, "tls.client-auth.ca"))?
+                    } else {
+                        self.rustls_root_store("server.tls.client-auth.ca")?
+                    };
+                    if mode == "require" {
+                        AllowAnyAuthenticatedClient::new(roots).boxed()
+                    } else {
+                        AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+                    }
+                }
+                mode => {
+                    return Err(format!(
+                        "Invalid client authentication mode {mode:?} for listener {id:?}."
+                    ))
+                }
+            };
 
             // Build server config
             let mut config = ServerConfig::builder()
@@ -173,10 +231,11 @@ impl Config {
                     TLS12_VERSION
                 })
                 .map_err(|err| format!("Failed to build TLS config: {err}"))?
-                .with_client_cert_verifier(NoClientAuth::boxed())
+                .with_client_cert_verifier(client_verifier)
                 .with_cert_resolver(Arc::new(CertificateResolver {
-                    resolver: if has_sni { resolver.into() } else { None },
+                    sni,
                     default_cert,
+                    acme: acme.to_vec(),
                 }));
 
             //config.key_log = Arc::new(KeyLogger::default());
@@ -270,6 +329,8 @@ impl Config {
 
         Ok(Server {
             id: id.to_string(),
+            acme: acme.to_vec(),
+            tenants,
             internal_id: 0,
             hostname: self
                 .value_or_default(("server.listener", id, "hostname"), "server.hostname")
@@ -308,6 +369,18 @@ impl Config {
     }
 }
 
+fn is_valid_sni_subject(subject: &str) -> bool {
+    let name = subject.strip_prefix("*.").unwrap_or(subject);
+    !name.is_empty()
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+        })
+}
+
 impl ParseValue for ServerProtocol {
     fn parse_value(key: impl AsKey, value: &str) -> super::Result<Self> {
         if value.eq_ignore_ascii_case("smtp") {
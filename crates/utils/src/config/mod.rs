@@ -21,9 +21,11 @@
  * for more details.
 */
 
+pub mod acme;
 pub mod certificate;
 pub mod cron;
 pub mod dynvalue;
+pub mod include;
 pub mod listener;
 pub mod parser;
 pub mod utils;
@@ -33,14 +35,16 @@ use std::{
     collections::BTreeMap,
     fmt::Display,
     net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
     time::Duration,
 };
 
-use ahash::{AHashMap, AHashSet};
+use ahash::AHashMap;
 use rustls::ServerConfig;
 use tokio::net::TcpSocket;
 
-use crate::{failed, UnwrapFailure};
+use crate::{acme::AcmeProvider, failed, UnwrapFailure};
 
 use self::utils::ParseValue;
 
@@ -60,10 +64,13 @@ pub struct Server {
     pub tls: Option<ServerConfig>,
     pub tls_implicit: bool,
     pub max_connections: u64,
+    pub acme: Vec<Arc<AcmeProvider>>,
+    pub tenants: AHashMap<String, String>,
 }
 
 pub struct Servers {
     pub inner: Vec<Server>,
+    pub acme: Vec<Arc<AcmeProvider>>,
 }
 
 #[derive(Debug)]
@@ -142,81 +149,102 @@ impl Display for ServerProtocol {
 
 pub type Result<T> = std::result::Result<T, String>;
 
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    pub path: PathBuf,
+    pub overlays: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigArgs {
+    pub source: ConfigSource,
+    pub check_config: bool,
+}
+
+impl ConfigSource {
+    pub fn load(&self) -> Result<Config> {
+        Config::load(&self.path, &self.overlays)
+    }
+}
+
 impl Config {
-    pub fn init() -> Self {
+    pub fn init() -> (Self, ConfigArgs) {
         let mut config_path = None;
-        let mut found_param = false;
-
-        for arg in std::env::args().skip(1) {
-            if let Some((key, value)) = arg.split_once('=') {
-                if key.starts_with("--config") {
-                    config_path = value.trim().to_string().into();
-                    break;
-                } else {
+        let mut overlays = Vec::new();
+        let mut print_config = false;
+        let mut check_config = false;
+        let mut args = std::env::args().skip(1);
+
+        while let Some(arg) = args.next() {
+            let (key, value) = if let Some((key, value)) = arg.split_once('=') {
+                (key.to_string(), Some(value.trim().to_string()))
+            } else {
+                (arg, None)
+            };
+
+            match key.as_str() {
+                "--config" => {
+                    config_path = value
+                        .or_else(|| args.next())
+                        .failed("Missing parameter --config=<path-to-config>.")
+                        .into();
+                }
+                "--overlay" => {
+                    overlays.push(PathBuf::from(
+                        value
+                            .or_else(|| args.next())
+                            .failed("Missing parameter --overlay=<path-to-overlay>."),
+                    ));
+                }
+                "--print-effective-config" if value.is_none() => {
+                    print_config = true;
+                }
+                "--check-config" if value.is_none() => {
+                    check_config = true;
+                }
+                _ => {
                     failed(&format!("Invalid command line argument: {key}"));
                 }
-            } else if found_param {
-                config_path = arg.into();
-                break;
-            } else if arg.starts_with("--config") {
-                found_param = true;
-            } else {
-                failed(&format!("Invalid command line argument: {arg}"));
             }
         }
 
-        // Read main configuration file
-        let mut config = Config::default();
-        config
-            .parse(
-                &std::fs::read_to_string(
-                    config_path.failed("Missing parameter --config=<path-to-config>."),
-                )
-                .failed("Could not read configuration file"),
-            )
-            .failed("Invalid configuration file");
-
-        // Extract macros and includes
-        let mut keys = BTreeMap::new();
-        let mut includes = AHashSet::new();
-        let mut macros = AHashMap::new();
-
-        for (key, value) in config.keys {
-            if let Some(macro_name) = key.strip_prefix("macros.") {
-                macros.insert(macro_name.to_ascii_lowercase(), value);
-            } else if key.starts_with("include.files.") {
-                includes.insert(value);
-            } else {
-                keys.insert(key, value);
+        // Read configuration files
+        let source = ConfigSource {
+            path: config_path
+                .failed("Missing parameter --config=<path-to-config>.")
+                .into(),
+            overlays,
+        };
+        let config = match source.load() {
+            Ok(config) => config,
+            Err(err) if check_config => {
+                eprintln!("[config] {err}");
+                std::process::exit(1);
             }
-        }
-
-        // Include files
-        config.keys = keys;
-        for mut include in includes {
-            include.replace_macros("include.files", &macros);
-            config
-                .parse(&std::fs::read_to_string(&include).failed(&format!(
-                    "Could not read included configuration file {include:?}"
-                )))
-                .failed(&format!("Invalid included configuration file {include:?}"));
-        }
+            Err(err) => failed(&format!("Failed to load configuration: {err}")),
+        };
 
-        // Replace macros
-        for (key, value) in &mut config.keys {
-            value.replace_macros(key, &macros);
+        if print_config {
+            print!("{}", config.to_effective_config());
+            std::process::exit(0);
         }
 
-        config
+        (
+            config,
+            ConfigArgs {
+                source,
+                check_config,
+            },
+        )
     }
 }
 
 trait ReplaceMacros: Sized {
-    fn replace_macros(&mut self, key: &str, macros: &AHashMap<String, String>);
+    fn replace_macros(&mut self, key: &str, macros: &AHashMap<String, String>) -> Result<()>;
 }
 
 impl ReplaceMacros for String {
-    fn replace_macros(&mut self, key: &str, macros: &AHashMap<String, String>) {
+    fn replace_macros(&mut self, key: &str, macros: &AHashMap<String, String>) -> Result<()> {
         if self.contains("%{") {
             let mut result = String::with_capacity(self.len());
             let mut value = self.as_str();
@@ -227,16 +255,23 @@ impl ReplaceMacros for String {
                         result.push_str(suffix);
                     }
                     if let Some((macro_name, rest)) = macro_name.split_once("}%") {
-                        if let Some(macro_value) = macros.get(&macro_name.to_ascii_lowercase()) {
+                        if let Some(var_name) = macro_name.strip_prefix("env:") {
+                            result.push_str(&std::env::var(var_name).map_err(|_| {
+                                format!(
+                                    "Environment variable {var_name:?} for key {key:?} is not set"
+                                )
+                            })?);
+                            value = rest;
+                        } else if let Some(macro_value) =
+                            macros.get(&macro_name.to_ascii_lowercase())
+                        {
                             result.push_str(macro_value);
                             value = rest;
                         } else {
-                            failed(&format!("Unknown macro {macro_name:?} for key {key:?}"));
+                            return Err(format!("Unknown macro {macro_name:?} for key {key:?}"));
                         }
                     } else {
-                        failed(&format!(
-                            "Unterminated macro name {value:?} for key {key:?}"
-                        ));
+                        return Err(format!("Unterminated macro name {value:?} for key {key:?}"));
                     }
                 } else {
                     result.push_str(value);
@@ -246,5 +281,7 @@ impl ReplaceMacros for String {
 
             *self = result;
         }
+
+        Ok(())
     }
 }
@@ -21,7 +21,7 @@
  * for more details.
 */
 
-use std::{borrow::Cow, path::PathBuf};
+use std::{borrow::Cow, path::PathBuf, sync::Arc};
 
 use tokio::{fs, io::AsyncReadExt, sync::oneshot};
 
@@ -31,9 +31,49 @@ pub enum DeliveryEvent {
         message: IngestMessage,
         result_tx: oneshot::Sender<Vec<DeliveryResult>>,
     },
+    ValidateToken {
+        token: String,
+        result_tx: oneshot::Sender<Option<String>>,
+    },
+    Authenticate {
+        username: String,
+        secret: String,
+        result_tx: oneshot::Sender<Option<String>>,
+    },
+    RequiresSecondFactor {
+        account: String,
+        result_tx: oneshot::Sender<bool>,
+    },
+    LookupMaskedEmail {
+        address: String,
+        result_tx: oneshot::Sender<Option<MaskedEmailStatus>>,
+    },
+    CollectAddresses {
+        account: String,
+        addresses: Vec<String>,
+    },
+    FileSentCopy {
+        account: String,
+        message: Arc<Vec<u8>>,
+    },
+    ApplyPolicyFooter {
+        sender: String,
+        message: Arc<Vec<u8>>,
+        result_tx: oneshot::Sender<Option<Vec<u8>>>,
+    },
+    RevokeSessions {
+        account: String,
+    },
     Stop,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaskedEmailStatus {
+    Enabled,
+    Disabled,
+    NotFound,
+}
+
 #[derive(Debug)]
 pub struct IngestMessage {
     pub sender_address: String,
@@ -52,6 +92,9 @@ pub enum DeliveryResult {
         code: [u8; 3],
         reason: Cow<'static, str>,
     },
+    Suppressed {
+        reason: Cow<'static, str>,
+    },
 }
 
 impl IngestMessage {
@@ -21,17 +21,27 @@
  * for more details.
 */
 
-use std::collections::HashMap;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, OnceLock},
+};
 
 use config::Config;
 
+pub mod acme;
+pub mod auth_log;
 pub mod codec;
 pub mod config;
 pub mod ipc;
 pub mod listener;
 pub mod map;
+pub mod metrics;
+pub mod rolling;
+pub mod ssrf;
 pub mod suffixlist;
+pub mod syslog;
 
+use config::utils::AsKey;
 use opentelemetry::{
     sdk::{
         trace::{self, Sampler},
@@ -41,8 +51,12 @@ use opentelemetry::{
 };
 use opentelemetry_otlp::WithExportConfig;
 use opentelemetry_semantic_conventions::resource::{SERVICE_NAME, SERVICE_VERSION};
+use rolling::{RollingFile, Rotation};
+use serde::Serialize;
 use tracing_appender::non_blocking::WorkerGuard;
-use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, EnvFilter};
+use tracing_subscriber::{
+    prelude::__tracing_subscriber_SubscriberExt, reload, EnvFilter, Registry,
+};
 
 pub trait UnwrapFailure<T> {
     fn failed(self, action: &str) -> T;
@@ -84,43 +98,61 @@ pub fn failed(message: &str) -> ! {
 }
 
 pub fn enable_tracing(config: &Config, message: &str) -> config::Result<Option<WorkerGuard>> {
-    let level = config.value("global.tracing.level").unwrap_or("info");
-    let env_filter = EnvFilter::builder()
-        .parse(format!(
-            "smtp={level},imap={level},jmap={level},store={level},utils={level},directory={level}"
-        ))
-        .failed("Failed to log level");
+    // The filter can be replaced at runtime to change the log levels
+    let levels = TracingLevels::parse(config)?;
+    let (env_filter, reload_handle) = reload::Layer::new(
+        EnvFilter::builder()
+            .parse(levels.directives())
+            .failed("Failed to log level"),
+    );
+    let _ = TRACING_FILTER.set(TracingFilter {
+        handle: reload_handle,
+        levels: Mutex::new(levels),
+    });
+
     let result = match config.value("global.tracing.method").unwrap_or_default() {
         "log" => {
             let path = config.value_require("global.tracing.path")?;
             let prefix = config.value_require("global.tracing.prefix")?;
-            let file_appender = match config.value("global.tracing.rotate").unwrap_or("daily") {
-                "daily" => tracing_appender::rolling::daily(path, prefix),
-                "hourly" => tracing_appender::rolling::hourly(path, prefix),
-                "minutely" => tracing_appender::rolling::minutely(path, prefix),
-                "never" => tracing_appender::rolling::never(path, prefix),
+            let rotation = match config.value("global.tracing.rotate").unwrap_or("daily") {
+                "daily" => Rotation::Daily,
+                "hourly" => Rotation::Hourly,
+                "minutely" => Rotation::Minutely,
+                "never" => Rotation::Never,
                 rotate => {
                     return Err(format!("Unsupported log rotation strategy {rotate:?}"));
                 }
             };
+            let file_appender = RollingFile::new(
+                path,
+                prefix,
+                rotation,
+                config.property("global.tracing.max-size")?,
+                config.property("global.tracing.max-files")?,
+            )
+            .map_err(|err| format!("Failed to open log file in {path:?}: {err}"))?;
 
             let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
             tracing::subscriber::set_global_default(
-                tracing_subscriber::FmtSubscriber::builder()
-                    .with_env_filter(env_filter)
-                    .with_writer(non_blocking)
-                    .with_ansi(config.property_or_static("global.tracing.ansi", "true")?)
-                    .finish(),
+                tracing_subscriber::Registry::default()
+                    .with(env_filter)
+                    .with(
+                        tracing_subscriber::fmt::layer()
+                            .with_writer(non_blocking)
+                            .with_ansi(config.property_or_static("global.tracing.ansi", "true")?),
+                    ),
             )
             .failed("Failed to set subscriber");
             Ok(guard.into())
         }
         "stdout" => {
             tracing::subscriber::set_global_default(
-                tracing_subscriber::FmtSubscriber::builder()
-                    .with_env_filter(env_filter)
-                    .with_ansi(config.property_or_static("global.tracing.ansi", "true")?)
-                    .finish(),
+                tracing_subscriber::Registry::default()
+                    .with(env_filter)
+                    .with(
+                        tracing_subscriber::fmt::layer()
+                            .with_ansi(config.property_or_static("global.tracing.ansi", "true")?),
+                    ),
             )
             .failed("Failed to set subscriber");
 
@@ -175,19 +207,29 @@ pub fn enable_tracing(config: &Config, message: &str) -> config::Result<Option<W
 
             tracing::subscriber::set_global_default(
                 tracing_subscriber::Registry::default()
-                    .with(tracing_opentelemetry::layer().with_tracer(tracer))
-                    .with(env_filter),
+                    .with(env_filter)
+                    .with(tracing_opentelemetry::layer().with_tracer(tracer)),
+            )
+            .failed("Failed to set subscriber");
+
+            Ok(None)
+        }
+        "syslog" => {
+            tracing::subscriber::set_global_default(
+                tracing_subscriber::Registry::default()
+                    .with(env_filter)
+                    .with(syslog::SyslogLayer::new(config)?),
             )
             .failed("Failed to set subscriber");
 
             Ok(None)
         }
         #[cfg(unix)]
-        "journal" => {
+        "journal" | "journald" => {
             tracing::subscriber::set_global_default(
                 tracing_subscriber::Registry::default()
-                    .with(tracing_journald::layer().failed("Failed to configure journal"))
-                    .with(env_filter),
+                    .with(env_filter)
+                    .with(tracing_journald::layer().failed("Failed to configure journal")),
             )
             .failed("Failed to set subscriber");
 
@@ -201,6 +243,131 @@ pub fn enable_tracing(config: &Config, message: &str) -> config::Result<Option<W
     result
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TracingLevels {
+    pub level: String,
+    pub contexts: BTreeMap<String, String>,
+}
+
+struct TracingFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    levels: Mutex<TracingLevels>,
+}
+
+static TRACING_FILTER: OnceLock<TracingFilter> = OnceLock::new();
+
+impl TracingLevels {
+    // Reads the default level and the optional per-context overrides in "global.tracing.levels"
+    fn parse(config: &Config) -> config::Result<Self> {
+        let mut levels = TracingLevels {
+            level: config
+                .value("global.tracing.level")
+                .unwrap_or("info")
+                .to_string(),
+            contexts: BTreeMap::new(),
+        };
+        if !is_valid_level(&levels.level) {
+            return Err(format!(
+                "Invalid log level {:?} for property \"global.tracing.level\".",
+                levels.level
+            ));
+        }
+        for context in config.sub_keys("global.tracing.levels") {
+            let key = ("global.tracing.levels", context);
+            levels
+                .set(context, config.value_require(key)?)
+                .map_err(|err| format!("{err} Found in property {:?}.", key.as_key()))?;
+        }
+
+        Ok(levels)
+    }
+
+    fn set(&mut self, context: &str, level: &str) -> Result<(), String> {
+        if !is_valid_level(level) {
+            return Err(format!("Invalid log level {level:?}."));
+        }
+        if context == "default" {
+            self.level = level.to_string();
+        } else if context_targets(context).is_some() {
+            self.contexts.insert(context.to_string(), level.to_string());
+        } else {
+            return Err(format!("Invalid tracing context {context:?}."));
+        }
+        Ok(())
+    }
+
+    fn directives(&self) -> String {
+        let mut directives = [
+            "smtp",
+            "imap",
+            "jmap",
+            "managesieve",
+            "store",
+            "utils",
+            "directory",
+        ]
+        .iter()
+        .map(|target| format!("{target}={}", self.level))
+        .collect::<Vec<_>>();
+        for (context, level) in &self.contexts {
+            for target in context_targets(context).unwrap_or_default() {
+                directives.push(format!("{target}={level}"));
+            }
+        }
+        directives.join(",")
+    }
+}
+
+// Returns the current log levels
+pub fn tracing_levels() -> Option<TracingLevels> {
+    TRACING_FILTER
+        .get()
+        .map(|filter| filter.levels.lock().unwrap().clone())
+}
+
+// Changes the log level of a context, or the default level if the context is
+// "default", without restarting the server.
+pub fn set_tracing_level(context: &str, level: &str) -> Result<TracingLevels, String> {
+    let filter = TRACING_FILTER
+        .get()
+        .ok_or_else(|| "Tracing is not enabled.".to_string())?;
+    let mut levels = filter.levels.lock().unwrap();
+    let mut new_levels = levels.clone();
+    new_levels.set(context, level)?;
+    let env_filter = EnvFilter::builder()
+        .parse(new_levels.directives())
+        .map_err(|err| err.to_string())?;
+    filter
+        .handle
+        .reload(env_filter)
+        .map_err(|err| err.to_string())?;
+    tracing::info!(
+        context = "tracing",
+        event = "level",
+        target_context = context,
+        level = level,
+        "Log level changed."
+    );
+    *levels = new_levels;
+    Ok(levels.clone())
+}
+
+fn context_targets(context: &str) -> Option<Vec<&str>> {
+    Some(match context {
+        "queue" => vec!["smtp::queue", "smtp::outbound"],
+        "report" => vec!["smtp::reporting"],
+        "sieve" => vec!["smtp::scripts", "jmap::sieve"],
+        "smtp" | "imap" | "jmap" | "managesieve" | "store" | "utils" | "directory" => {
+            vec![context]
+        }
+        _ => return None,
+    })
+}
+
+fn is_valid_level(level: &str) -> bool {
+    matches!(level, "trace" | "debug" | "info" | "warn" | "error" | "off")
+}
+
 pub async fn wait_for_shutdown(message: &str) {
     #[cfg(not(target_env = "msvc"))]
     {
@@ -227,3 +394,19 @@ pub async fn wait_for_shutdown(message: &str) {
 
     tracing::info!(message);
 }
+
+pub async fn wait_for_reload() {
+    #[cfg(not(target_env = "msvc"))]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut h_hup = signal(SignalKind::hangup()).failed("start signal handler");
+        h_hup.recv().await;
+        tracing::debug!("Received SIGHUP.");
+    }
+
+    #[cfg(target_env = "msvc")]
+    {
+        std::future::pending::<()>().await;
+    }
+}
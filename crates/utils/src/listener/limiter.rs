@@ -79,6 +79,21 @@ impl RateLimiter {
         }
     }
 
+    pub fn is_allowed_many(&mut self, count: u64) -> bool {
+        // Check rate limit
+        if self.last_refill.elapsed() >= self.max_interval {
+            self.last_refill = Instant::now();
+            self.tokens = self.max_requests;
+        }
+
+        if self.tokens >= count {
+            self.tokens -= count;
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn is_allowed_soft(&self) -> bool {
         self.tokens >= 1 || self.last_refill.elapsed() >= self.max_interval
     }
@@ -91,6 +106,12 @@ impl RateLimiter {
                 .unwrap_or_default())
     }
 
+    pub fn retry_in(&self) -> Duration {
+        self.max_interval
+            .checked_sub(self.last_refill.elapsed())
+            .unwrap_or_default()
+    }
+
     pub fn elapsed(&self) -> Duration {
         self.last_refill.elapsed()
     }
@@ -135,6 +156,17 @@ impl ConcurrencyLimiter {
 }
 
 impl InFlight {
+    pub fn acquire(concurrent: &Arc<AtomicU64>, max_concurrent: u64) -> Option<Self> {
+        if concurrent.fetch_add(1, Ordering::Relaxed) < max_concurrent {
+            Some(InFlight {
+                concurrent: concurrent.clone(),
+            })
+        } else {
+            concurrent.fetch_sub(1, Ordering::Relaxed);
+            None
+        }
+    }
+
     pub fn num_concurrent(&self) -> u64 {
         self.concurrent.load(Ordering::Relaxed)
     }
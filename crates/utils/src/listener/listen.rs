@@ -31,7 +31,7 @@ use tokio_rustls::{server::TlsStream, TlsAcceptor};
 use tracing::Span;
 
 use crate::{
-    config::{Config, Listener, Server, ServerProtocol, Servers},
+    config::{certificate::sni_lookup, Config, Listener, Server, ServerProtocol, Servers},
     failed,
     listener::SessionData,
     UnwrapFailure,
@@ -54,6 +54,8 @@ impl Server {
             hostname: self.hostname,
             tls_acceptor: self.tls.map(|config| TlsAcceptor::from(Arc::new(config))),
             is_tls_implicit: self.tls_implicit,
+            acme: self.acme,
+            tenants: self.tenants,
             limiter: ConcurrencyLimiter::new(self.max_connections),
             shutdown_rx,
         });
@@ -218,6 +220,9 @@ impl Servers {
     ) -> (watch::Sender<bool>, watch::Receiver<bool>) {
         // Spawn listeners
         let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        for provider in self.acme {
+            provider.spawn(shutdown_rx.clone());
+        }
         for server in self.inner {
             spawn(server, shutdown_rx.clone());
         }
@@ -235,6 +240,16 @@ impl Listener {
 }
 
 impl ServerInstance {
+    /// Returns the tenant associated with the SNI hostname requested by the client.
+    pub fn tenant<T>(&self, stream: &TlsStream<T>) -> Option<&str> {
+        stream
+            .get_ref()
+            .1
+            .server_name()
+            .and_then(|name| sni_lookup(&self.tenants, name))
+            .map(|tenant| tenant.as_str())
+    }
+
     pub async fn tls_accept(
         &self,
         stream: TcpStream,
@@ -248,6 +263,8 @@ impl ServerInstance {
                     event = "handshake",
                     version = ?stream.get_ref().1.protocol_version().unwrap_or(rustls::ProtocolVersion::TLSv1_3),
                     cipher = ?stream.get_ref().1.negotiated_cipher_suite().unwrap_or(rustls::cipher_suite::TLS13_AES_128_GCM_SHA256),
+                    sni = stream.get_ref().1.server_name().unwrap_or_default(),
+                    tenant = self.tenant(&stream).unwrap_or_default(),
                 );
                 Ok(stream)
             }
@@ -23,6 +23,7 @@
 
 use std::{net::IpAddr, sync::Arc};
 
+use ahash::AHashMap;
 use tokio::{
     io::{AsyncRead, AsyncWrite},
     net::TcpStream,
@@ -30,7 +31,7 @@ use tokio::{
 };
 use tokio_rustls::TlsAcceptor;
 
-use crate::config::ServerProtocol;
+use crate::{acme::AcmeProvider, config::ServerProtocol};
 
 use self::limiter::{ConcurrencyLimiter, InFlight};
 
@@ -45,6 +46,8 @@ pub struct ServerInstance {
     pub data: String,
     pub tls_acceptor: Option<TlsAcceptor>,
     pub is_tls_implicit: bool,
+    pub acme: Vec<Arc<AcmeProvider>>,
+    pub tenants: AHashMap<String, String>,
     pub limiter: ConcurrencyLimiter,
     pub shutdown_rx: watch::Receiver<bool>,
 }
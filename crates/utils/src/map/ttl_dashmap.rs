@@ -20,6 +20,16 @@ pub struct LruItem<V> {
     valid_until: Instant,
 }
 
+impl<V> LruItem<V> {
+    pub fn item(&self) -> &V {
+        &self.item
+    }
+
+    pub fn valid_until(&self) -> Instant {
+        self.valid_until
+    }
+}
+
 pub trait TtlMap<K, V>: Sized {
     fn with_capacity(capacity: usize, shard_amount: usize) -> Self;
     fn get_with_ttl<Q: ?Sized>(&self, name: &Q) -> Option<V>
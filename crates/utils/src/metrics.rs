@@ -0,0 +1,93 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        OnceLock,
+    },
+};
+
+use dashmap::DashMap;
+
+// Process-wide counters and gauges shared by all services, a snapshot is
+// returned by the "/admin/metrics" management endpoint.
+static METRICS: OnceLock<DashMap<&'static str, AtomicU64>> = OnceLock::new();
+
+fn metrics() -> &'static DashMap<&'static str, AtomicU64> {
+    METRICS.get_or_init(DashMap::new)
+}
+
+pub fn increment(name: &'static str, value: u64) {
+    metrics()
+        .entry(name)
+        .or_default()
+        .fetch_add(value, Ordering::Relaxed);
+}
+
+pub fn decrement(name: &'static str, value: u64) {
+    let _ = metrics().entry(name).or_default().fetch_update(
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+        |current| Some(current.saturating_sub(value)),
+    );
+}
+
+pub fn set(name: &'static str, value: u64) {
+    metrics()
+        .entry(name)
+        .or_default()
+        .store(value, Ordering::Relaxed);
+}
+
+pub fn get(name: &str) -> u64 {
+    metrics()
+        .get(name)
+        .map_or(0, |value| value.load(Ordering::Relaxed))
+}
+
+pub fn snapshot() -> BTreeMap<&'static str, u64> {
+    metrics()
+        .iter()
+        .map(|entry| (*entry.key(), entry.value().load(Ordering::Relaxed)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn metrics() {
+        super::increment("test.counter", 2);
+        super::increment("test.counter", 3);
+        super::set("test.gauge", 10);
+        super::decrement("test.gauge", 4);
+        super::decrement("test.empty", 4);
+
+        assert_eq!(super::get("test.counter"), 5);
+        assert_eq!(super::get("test.gauge"), 6);
+        assert_eq!(super::get("test.empty"), 0);
+        assert_eq!(super::get("test.unknown"), 0);
+        assert_eq!(super::snapshot().get("test.gauge"), Some(&6));
+    }
+}
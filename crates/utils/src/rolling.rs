@@ -0,0 +1,253 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Minutely,
+    Hourly,
+    Daily,
+    Never,
+}
+
+// Log file writer that starts a new file when the time period changes or the
+// current file reaches its maximum size, and removes the oldest files once
+// more than the allowed number of files exist.
+pub struct RollingFile {
+    dir: PathBuf,
+    prefix: String,
+    rotation: Rotation,
+    max_size: Option<u64>,
+    max_files: Option<usize>,
+    file: Option<File>,
+    period: String,
+    index: u32,
+    size: u64,
+}
+
+impl RollingFile {
+    pub fn new(
+        dir: impl AsRef<Path>,
+        prefix: impl Into<String>,
+        rotation: Rotation,
+        max_size: Option<u64>,
+        max_files: Option<usize>,
+    ) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut writer = RollingFile {
+            dir,
+            prefix: prefix.into(),
+            rotation,
+            max_size,
+            max_files,
+            file: None,
+            period: String::new(),
+            index: 0,
+            size: 0,
+        };
+        writer.open(0)?;
+        Ok(writer)
+    }
+
+    fn current_period(&self) -> String {
+        let format = match self.rotation {
+            Rotation::Minutely => "%Y-%m-%d-%H-%M",
+            Rotation::Hourly => "%Y-%m-%d-%H",
+            Rotation::Daily => "%Y-%m-%d",
+            Rotation::Never => return String::new(),
+        };
+        chrono::Utc::now().format(format).to_string()
+    }
+
+    fn file_name(&self, index: u32) -> String {
+        let mut name = self.prefix.clone();
+        if !self.period.is_empty() {
+            name.push('.');
+            name.push_str(&self.period);
+        }
+        if index > 0 {
+            name.push('.');
+            name.push_str(&index.to_string());
+        }
+        name
+    }
+
+    // Opens the first file of the current period that has room left
+    fn open(&mut self, mut index: u32) -> io::Result<()> {
+        self.period = self.current_period();
+        loop {
+            let path = self.dir.join(self.file_name(index));
+            let size = fs::metadata(&path).map_or(0, |metadata| metadata.len());
+            if self.max_size.map_or(true, |max_size| size < max_size) {
+                self.file = Some(OpenOptions::new().create(true).append(true).open(path)?);
+                self.index = index;
+                self.size = size;
+                break;
+            }
+            index += 1;
+        }
+        self.purge();
+        Ok(())
+    }
+
+    fn purge(&self) {
+        let max_files = if let Some(max_files) = self.max_files {
+            max_files
+        } else {
+            return;
+        };
+        let mut files = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| {
+                    let entry = entry.ok()?;
+                    let name = entry.file_name();
+                    let name = name.to_str()?;
+                    if name == self.prefix
+                        || name
+                            .strip_prefix(&self.prefix)
+                            .map_or(false, |suffix| suffix.starts_with('.'))
+                    {
+                        let modified = entry.metadata().ok()?.modified().ok()?;
+                        Some((modified, entry.path()))
+                    } else {
+                        None
+                    }
+                })
+                .collect::<Vec<_>>(),
+            Err(_) => return,
+        };
+        if files.len() > max_files {
+            // Oldest files first, the file being written to is never removed
+            let excess = files.len() - max_files;
+            let current = self.dir.join(self.file_name(self.index));
+            files.sort_unstable();
+            for (_, path) in files
+                .into_iter()
+                .filter(|(_, path)| path != &current)
+                .take(excess)
+            {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+
+    fn rotate_if_needed(&mut self, len: usize) -> io::Result<()> {
+        if self.rotation != Rotation::Never && self.current_period() != self.period {
+            self.open(0)
+        } else if self.max_size.map_or(false, |max_size| {
+            self.size > 0 && self.size + len as u64 > max_size
+        }) {
+            let index = self.index + 1;
+            self.open(index)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Write for RollingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.rotate_if_needed(buf.len())?;
+        let written = match self.file.as_mut() {
+            Some(file) => file.write(buf)?,
+            None => return Err(io::Error::new(io::ErrorKind::Other, "Log file not open")),
+        };
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write};
+
+    use super::{RollingFile, Rotation};
+
+    #[test]
+    fn rolling_file() {
+        let dir = std::env::temp_dir().join("stalwart_rolling_file_test");
+        let _ = fs::remove_dir_all(&dir);
+
+        // Files are rotated once they reach their maximum size
+        let mut writer =
+            RollingFile::new(&dir, "test.log", Rotation::Never, Some(10), None).unwrap();
+        for _ in 0..5 {
+            writer.write_all(b"12345678\n").unwrap();
+        }
+        writer.flush().unwrap();
+        let mut files = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(
+            files,
+            [
+                "test.log",
+                "test.log.1",
+                "test.log.2",
+                "test.log.3",
+                "test.log.4"
+            ]
+        );
+        assert_eq!(fs::read(dir.join("test.log.2")).unwrap(), b"12345678\n");
+
+        // Writing resumes on the first file with room left
+        drop(writer);
+        let mut writer =
+            RollingFile::new(&dir, "test.log", Rotation::Never, Some(20), Some(2)).unwrap();
+        writer.write_all(b"12345678\n").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(
+            fs::read(dir.join("test.log")).unwrap(),
+            b"12345678\n12345678\n"
+        );
+
+        // Only the most recent files are kept
+        let files = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(files, 2);
+        assert!(dir.join("test.log").exists());
+
+        // Time based rotation includes the period in the file name
+        let writer = RollingFile::new(&dir, "daily.log", Rotation::Daily, None, None).unwrap();
+        assert_eq!(
+            writer.file_name(0),
+            format!("daily.log.{}", chrono::Utc::now().format("%Y-%m-%d"))
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
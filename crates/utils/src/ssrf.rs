@@ -0,0 +1,152 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect, ClientBuilder, Response, Url,
+};
+
+// Resolver that only returns publicly routable addresses, so that the address
+// checked is the one connected to and DNS rebinding cannot reach internal hosts.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect::<Vec<SocketAddr>>();
+            if !addrs.is_empty() {
+                Ok(Box::new(addrs.into_iter()) as Addrs)
+            } else {
+                Err(format!("{} does not resolve to a public address", name.as_str()).into())
+            }
+        })
+    }
+}
+
+// Restricts a client to public destinations, including the targets of redirects.
+pub fn public_client(builder: ClientBuilder, max_redirects: usize) -> ClientBuilder {
+    builder
+        .dns_resolver(Arc::new(PublicResolver))
+        .redirect(redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= max_redirects {
+                attempt.error("too many redirects")
+            } else if !is_public_url(attempt.url()) {
+                attempt.error("redirect to a non-public address")
+            } else {
+                attempt.follow()
+            }
+        }))
+}
+
+// URLs with IP literals bypass the resolver, so they are checked here before connecting.
+pub fn is_public_url(url: &Url) -> bool {
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+    let host = if let Some(host) = url.host_str() {
+        host.trim_start_matches('[').trim_end_matches(']')
+    } else {
+        return false;
+    };
+    match host.parse::<IpAddr>() {
+        Ok(ip) => is_public_ip(ip),
+        Err(_) => {
+            let host = host.trim_end_matches('.').to_ascii_lowercase();
+            (host != "localhost" && !host.ends_with(".localhost")) || cfg!(feature = "test_mode")
+        }
+    }
+}
+
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_ipv4(ip),
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                is_public_ipv4(ip)
+            } else {
+                is_public_ipv6(ip)
+            }
+        }
+    }
+}
+
+fn is_public_ipv4(ip: Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    (ip.is_loopback() && cfg!(feature = "test_mode"))
+        || !(ip.is_loopback()
+            || ip.is_private()
+            || ip.is_link_local()
+            || ip.is_unspecified()
+            || ip.is_broadcast()
+            || ip.is_multicast()
+            || ip.is_documentation()
+            || octets[0] == 0
+            || octets[0] >= 240
+            || (octets[0] == 100 && (octets[1] & 0xc0) == 64)
+            || (octets[0] == 192 && octets[1] == 0 && octets[2] == 0)
+            || (octets[0] == 198 && (octets[1] & 0xfe) == 18))
+}
+
+fn is_public_ipv6(ip: Ipv6Addr) -> bool {
+    let segments = ip.segments();
+    (ip.is_loopback() && cfg!(feature = "test_mode"))
+        || !(ip.is_loopback()
+            || ip.is_unspecified()
+            || ip.is_multicast()
+            || (segments[0] & 0xfe00) == 0xfc00
+            || (segments[0] & 0xffc0) == 0xfe80
+            || (segments[0] == 0x2001 && segments[1] == 0x0db8)
+            || (segments[0] == 0x0064 && segments[1] == 0xff9b)
+            || (segments[0] == 0
+                && segments[1] == 0
+                && segments[2] == 0
+                && segments[3] == 0
+                && segments[4] == 0
+                && segments[5] == 0))
+}
+
+// Reads a response body, stopping as soon as it exceeds the maximum size.
+pub async fn read_body(mut response: Response, max_size: usize) -> Result<Vec<u8>, String> {
+    if response
+        .content_length()
+        .map_or(false, |size| size as usize > max_size)
+    {
+        return Err("Response is too large".to_string());
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|err| err.to_string())? {
+        if bytes.len() + chunk.len() > max_size {
+            return Err("Response is too large".to_string());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
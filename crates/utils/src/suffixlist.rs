@@ -36,6 +36,28 @@ impl PublicSuffix {
             || (!self.exceptions.contains(suffix)
                 && self.wildcards.iter().any(|w| suffix.ends_with(w)))
     }
+
+    // Returns the registered domain, one label below the longest public suffix.
+    // The top level domain is always considered a public suffix.
+    pub fn organizational_domain<'x>(&self, domain: &'x str) -> &'x str {
+        // Offsets of each suffix, from the top level domain to the full domain
+        let mut starts = domain
+            .match_indices('.')
+            .map(|(pos, _)| pos + 1)
+            .collect::<Vec<_>>();
+        starts.reverse();
+        starts.push(0);
+
+        let mut public_labels = 1;
+        for (idx, start) in starts.iter().enumerate().skip(1) {
+            if self.contains(&domain[*start..]) {
+                public_labels = idx + 1;
+            }
+        }
+        starts
+            .get(public_labels)
+            .map_or(domain, |start| &domain[*start..])
+    }
 }
 
 impl From<&str> for PublicSuffix {
@@ -57,3 +79,26 @@ impl From<&str> for PublicSuffix {
         ps
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::PublicSuffix;
+
+    #[test]
+    fn organizational_domain() {
+        let psl = PublicSuffix::from("com\nco.uk\n*.ck\n!www.ck\n");
+        for (domain, expected) in [
+            ("example.com", "example.com"),
+            ("mail.example.com", "example.com"),
+            ("a.b.example.co.uk", "example.co.uk"),
+            ("example.co.uk", "example.co.uk"),
+            ("co.uk", "co.uk"),
+            ("com", "com"),
+            ("mail.example.org", "example.org"),
+            ("mail.foo.ck", "mail.foo.ck"),
+            ("www.ck", "www.ck"),
+        ] {
+            assert_eq!(psl.organizational_domain(domain), expected, "{domain}");
+        }
+    }
+}
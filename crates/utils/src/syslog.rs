@@ -0,0 +1,432 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of the Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    fmt::{Debug, Write},
+    time::{Duration, Instant},
+};
+
+use chrono::SecondsFormat;
+use mail_send::smtp::tls::build_tls_connector;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, UdpSocket},
+    sync::mpsc,
+};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+use crate::config::Config;
+
+// Private enterprise number reserved for documentation (RFC 5612)
+const SD_ID: &str = "tracing@32473";
+const WARNING_INTERVAL: Duration = Duration::from_secs(60);
+
+pub struct SyslogLayer {
+    tx: mpsc::Sender<Vec<u8>>,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+    proc_id: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogTransport {
+    Udp {
+        address: String,
+    },
+    Tcp {
+        address: String,
+    },
+    Tls {
+        address: String,
+        allow_invalid_certs: bool,
+    },
+}
+
+#[derive(Default)]
+struct Fields {
+    message: Option<String>,
+    values: Vec<(&'static str, String)>,
+}
+
+enum Connection {
+    Udp(UdpSocket),
+    Stream(Box<dyn AsyncWrite + Send + Unpin>),
+}
+
+impl SyslogLayer {
+    pub fn new(config: &Config) -> super::config::Result<Self> {
+        let address = config.value_require("global.tracing.address")?.to_string();
+        let transport = match config.value("global.tracing.transport").unwrap_or("udp") {
+            "udp" => SyslogTransport::Udp { address },
+            "tcp" => SyslogTransport::Tcp { address },
+            "tls" => SyslogTransport::Tls {
+                address,
+                allow_invalid_certs: config
+                    .property_or_static("global.tracing.allow-invalid-certs", "false")?,
+            },
+            transport => {
+                return Err(format!("Unsupported syslog transport {transport:?}"));
+            }
+        };
+        let facility = config.value("global.tracing.facility").unwrap_or("mail");
+        let facility = parse_facility(facility)
+            .ok_or_else(|| format!("Invalid syslog facility {facility:?}"))?;
+
+        let (tx, rx) = mpsc::channel(1024);
+        tokio::spawn(transport.run(rx));
+
+        Ok(SyslogLayer {
+            tx,
+            facility,
+            hostname: config
+                .value("global.tracing.hostname")
+                .or_else(|| config.value("server.hostname"))
+                .unwrap_or("-")
+                .to_string(),
+            app_name: config
+                .value("global.tracing.app-name")
+                .unwrap_or("stalwart-mail")
+                .to_string(),
+            proc_id: std::process::id(),
+        })
+    }
+
+    fn format(&self, level: &Level, timestamp: &str, fields: &Fields) -> String {
+        let severity = match *level {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            Level::DEBUG | Level::TRACE => 7,
+        };
+        let msg_id = fields
+            .values
+            .iter()
+            .find(|(name, _)| *name == "context")
+            .map(|(_, value)| value.as_str())
+            .filter(|value| (1..=32).contains(&value.len()) && is_printable(value))
+            .unwrap_or("-");
+
+        let mut message = format!(
+            "<{}>1 {} {} {} {} {} ",
+            self.facility as u16 * 8 + severity,
+            timestamp,
+            self.hostname,
+            self.app_name,
+            self.proc_id,
+            msg_id,
+        );
+        if fields.values.is_empty() {
+            message.push('-');
+        } else {
+            message.push('[');
+            message.push_str(SD_ID);
+            for (name, value) in &fields.values {
+                let _ = write!(message, " {}=\"", sd_name(name));
+                for ch in value.chars() {
+                    if matches!(ch, '"' | '\\' | ']') {
+                        message.push('\\');
+                    }
+                    message.push(ch);
+                }
+                message.push('"');
+            }
+            message.push(']');
+        }
+        if let Some(text) = &fields.message {
+            message.push(' ');
+            message.push_str(text);
+        }
+        message
+    }
+}
+
+impl<S> Layer<S> for SyslogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            span.extensions_mut().insert(fields);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(fields) = span.extensions_mut().get_mut::<Fields>() {
+                values.record(fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        // Delivery errors are not sent back to the server that caused them
+        if event.metadata().target() == module_path!() {
+            return;
+        }
+
+        // Span fields are carried over as structured data, the event's own fields last
+        let mut fields = Fields::default();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(span_fields) = span.extensions().get::<Fields>() {
+                    fields.values.extend(span_fields.values.iter().cloned());
+                }
+            }
+        }
+        event.record(&mut fields);
+
+        let message = self.format(
+            event.metadata().level(),
+            &chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            &fields,
+        );
+        let _ = self.tx.try_send(message.into_bytes());
+    }
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = Some(value.to_string());
+        } else {
+            self.values.push((field.name(), value.to_string()));
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        if field.name() == "message" {
+            self.message = Some(format!("{value:?}"));
+        } else {
+            self.values.push((field.name(), format!("{value:?}")));
+        }
+    }
+}
+
+impl SyslogTransport {
+    async fn run(self, mut rx: mpsc::Receiver<Vec<u8>>) {
+        let mut connection = None;
+        let mut dropped = 0u64;
+        let mut last_warning: Option<Instant> = None;
+
+        while let Some(message) = rx.recv().await {
+            // Reconnect once per message, messages are dropped while the server is unreachable
+            let mut last_err = None;
+            for _ in 0..2 {
+                if connection.is_none() {
+                    match self.connect().await {
+                        Ok(conn) => connection = Some(conn),
+                        Err(err) => {
+                            last_err = Some(err);
+                            break;
+                        }
+                    }
+                }
+                let result = match connection.as_mut().unwrap() {
+                    Connection::Udp(socket) => socket.send(&message).await.map(|_| ()),
+                    Connection::Stream(stream) => {
+                        // Octet counting framing (RFC 6587)
+                        let mut frame = format!("{} ", message.len()).into_bytes();
+                        frame.extend_from_slice(&message);
+                        match stream.write_all(&frame).await {
+                            Ok(_) => stream.flush().await,
+                            Err(err) => Err(err),
+                        }
+                    }
+                };
+                match result {
+                    Ok(_) => {
+                        last_err = None;
+                        break;
+                    }
+                    Err(err) => {
+                        last_err = Some(err);
+                        connection = None;
+                    }
+                }
+            }
+
+            // Report dropped messages at most once per interval
+            if let Some(err) = last_err {
+                dropped += 1;
+                if last_warning.map_or(true, |last| last.elapsed() >= WARNING_INTERVAL) {
+                    tracing::warn!(
+                        context = "syslog",
+                        event = "error",
+                        address = self.address(),
+                        dropped = dropped,
+                        reason = %err,
+                        "Failed to deliver messages to syslog server."
+                    );
+                    last_warning = Some(Instant::now());
+                    dropped = 0;
+                }
+            }
+        }
+    }
+
+    fn address(&self) -> &str {
+        match self {
+            SyslogTransport::Udp { address }
+            | SyslogTransport::Tcp { address }
+            | SyslogTransport::Tls { address, .. } => address,
+        }
+    }
+
+    async fn connect(&self) -> std::io::Result<Connection> {
+        match self {
+            SyslogTransport::Udp { address } => {
+                let socket = UdpSocket::bind(if address.starts_with('[') {
+                    "[::]:0"
+                } else {
+                    "0.0.0.0:0"
+                })
+                .await?;
+                socket.connect(address).await?;
+                Ok(Connection::Udp(socket))
+            }
+            SyslogTransport::Tcp { address } => Ok(Connection::Stream(Box::new(
+                TcpStream::connect(address).await?,
+            ))),
+            SyslogTransport::Tls {
+                address,
+                allow_invalid_certs,
+            } => {
+                let host = address
+                    .rsplit_once(':')
+                    .map_or(address.as_str(), |(host, _)| host)
+                    .trim_start_matches('[')
+                    .trim_end_matches(']');
+                let server_name = rustls::ServerName::try_from(host).map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("Invalid syslog server name {host:?}"),
+                    )
+                })?;
+                let stream = build_tls_connector(*allow_invalid_certs)
+                    .connect(server_name, TcpStream::connect(address).await?)
+                    .await?;
+                Ok(Connection::Stream(Box::new(stream)))
+            }
+        }
+    }
+}
+
+fn parse_facility(value: &str) -> Option<u8> {
+    Some(match value {
+        "kern" => 0,
+        "user" => 1,
+        "mail" => 2,
+        "daemon" => 3,
+        "auth" => 4,
+        "syslog" => 5,
+        "lpr" => 6,
+        "news" => 7,
+        "uucp" => 8,
+        "cron" => 9,
+        "authpriv" => 10,
+        "ftp" => 11,
+        "local0" => 16,
+        "local1" => 17,
+        "local2" => 18,
+        "local3" => 19,
+        "local4" => 20,
+        "local5" => 21,
+        "local6" => 22,
+        "local7" => 23,
+        _ => return None,
+    })
+}
+
+fn is_printable(value: &str) -> bool {
+    value.bytes().all(|ch| (33..=126).contains(&ch))
+}
+
+// SD-NAMEs are limited to 32 printable characters other than '=', ' ', ']' and '"'
+fn sd_name(name: &str) -> String {
+    name.chars()
+        .take(32)
+        .map(|ch| {
+            if ch.is_ascii_graphic() && !matches!(ch, '=' | ']' | '"') {
+                ch
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+    use tracing::Level;
+
+    use super::{parse_facility, Fields, SyslogLayer};
+
+    #[test]
+    fn syslog_format() {
+        let layer = SyslogLayer {
+            tx: mpsc::channel(1).0,
+            facility: parse_facility("mail").unwrap(),
+            hostname: "mx.example.org".to_string(),
+            app_name: "stalwart-mail".to_string(),
+            proc_id: 1234,
+        };
+
+        assert_eq!(
+            layer.format(
+                &Level::INFO,
+                "2023-10-15T09:23:14.000000Z",
+                &Fields {
+                    message: Some("Message queued.".to_string()),
+                    values: vec![
+                        ("remote.ip", "192.0.2.1".to_string()),
+                        ("context", "queue".to_string()),
+                        ("reason", "say \"hi\" [x]".to_string()),
+                    ],
+                }
+            ),
+            concat!(
+                "<22>1 2023-10-15T09:23:14.000000Z mx.example.org stalwart-mail 1234 queue ",
+                "[tracing@32473 remote.ip=\"192.0.2.1\" context=\"queue\" ",
+                "reason=\"say \\\"hi\\\" [x\\]\"] Message queued."
+            )
+        );
+
+        assert_eq!(
+            layer.format(
+                &Level::ERROR,
+                "2023-10-15T09:23:14.000000Z",
+                &Fields::default()
+            ),
+            "<19>1 2023-10-15T09:23:14.000000Z mx.example.org stalwart-mail 1234 - -"
+        );
+    }
+}
@@ -0,0 +1,356 @@
+(function () {
+  "use strict";
+
+  var credentials = sessionStorage.getItem("credentials");
+  var identity = JSON.parse(sessionStorage.getItem("identity") || "null");
+
+  function $(id) {
+    return document.getElementById(id);
+  }
+
+  function escape(value) {
+    return String(value === undefined || value === null ? "" : value).replace(/[&<>"']/g, function (c) {
+      return { "&": "&amp;", "<": "&lt;", ">": "&gt;", '"': "&quot;", "'": "&#39;" }[c];
+    });
+  }
+
+  function formatSize(bytes) {
+    var units = ["B", "KB", "MB", "GB", "TB"];
+    var i = 0;
+    while (bytes >= 1024 && i < units.length - 1) {
+      bytes /= 1024;
+      i++;
+    }
+    return (i === 0 ? bytes : bytes.toFixed(1)) + " " + units[i];
+  }
+
+  function setStatus(message, ok) {
+    var status = $("status");
+    status.textContent = message || "";
+    status.className = "status " + (ok ? "ok" : "fail");
+  }
+
+  function request(path, params) {
+    var query = params ? new URLSearchParams(params).toString() : "";
+    return fetch(path + (query ? "?" + query : ""), {
+      headers: { Authorization: "Basic " + credentials },
+    }).then(function (response) {
+      return response.text().then(function (text) {
+        var body = text ? JSON.parse(text) : null;
+        if (response.status === 401) {
+          logout();
+          throw new Error("Authentication required.");
+        } else if (!response.ok) {
+          throw new Error(
+            (body && (body.detail || body.details || body.title || body.error)) ||
+              "Request failed with status " + response.status + "."
+          );
+        }
+        return body;
+      });
+    });
+  }
+
+  function can(permission) {
+    return !!identity && identity.permissions.indexOf(permission) !== -1;
+  }
+
+  function applyPermissions() {
+    document.querySelectorAll("[data-permission]").forEach(function (element) {
+      element.hidden = !can(element.dataset.permission);
+    });
+    $("whoami").textContent = identity
+      ? identity.name + " (" + identity.role + (identity.domains ? ": " + identity.domains.join(", ") : "") + ")"
+      : "";
+  }
+
+  function adminPath() {
+    return "/admin/" + Array.prototype.map.call(arguments, encodeURIComponent).join("/");
+  }
+
+  function action(promise, message) {
+    return promise
+      .then(function (result) {
+        setStatus(message, true);
+        return result;
+      })
+      .catch(function (err) {
+        setStatus(err.message, false);
+      });
+  }
+
+  function formValues(form) {
+    var values = {};
+    new FormData(form).forEach(function (value, key) {
+      if (value !== "") {
+        values[key] = value;
+      }
+    });
+    return values;
+  }
+
+  // Accounts and domains
+
+  function usageRows(scope, withActions) {
+    return request("/admin/usage/list", { scope: scope }).then(function (result) {
+      return result.data
+        .map(function (usage) {
+          return (
+            "<tr><td>" + escape(usage.name) + "</td>" +
+            "<td>" + usage.daily.messages + "</td>" +
+            "<td>" + formatSize(usage.daily.size) + "</td>" +
+            "<td>" + usage.monthly.messages + "</td>" +
+            "<td>" + formatSize(usage.monthly.size) + "</td>" +
+            (withActions
+              ? '<td><button class="danger" data-delete="' + escape(usage.name) + '">Delete</button></td>'
+              : "") +
+            "</tr>"
+          );
+        })
+        .join("");
+    });
+  }
+
+  function loadAccounts() {
+    return usageRows("account", can("account-manage")).then(function (rows) {
+      $("accounts-list").innerHTML = rows;
+    });
+  }
+
+  function loadDomains() {
+    return usageRows("domain", false).then(function (rows) {
+      $("domains-list").innerHTML = rows;
+    });
+  }
+
+  function deleteAccount(name) {
+    if (confirm("Delete account " + name + " and all its data?")) {
+      action(request(adminPath("account", "delete", name)), "Account " + name + " deleted.").then(loadAccounts);
+    }
+  }
+
+  // Queue
+
+  function loadQueue(filter) {
+    return request("/admin/queue/list", filter).then(function (result) {
+      if (!result.data.length) {
+        $("queue-list").innerHTML = '<tr><td colspan="6">The queue is empty.</td></tr>';
+        return;
+      }
+      var ids = result.data;
+      return request("/admin/queue/status", { ids: ids.join(",") }).then(function (status) {
+        $("queue-list").innerHTML = status.data
+          .map(function (message, i) {
+            if (!message) {
+              return "";
+            }
+            return (
+              "<tr><td>" + ids[i] + "</td>" +
+              "<td>" + escape(message.return_path || "<>") + "</td>" +
+              "<td>" + escape(message.created) + "</td>" +
+              "<td>" + formatSize(message.size) + "</td>" +
+              "<td>" +
+              message.domains
+                .map(function (domain) {
+                  return escape(domain.name) + " (" + escape(typeof domain.status === "string" ? domain.status : Object.keys(domain.status)[0]) + ")";
+                })
+                .join("<br>") +
+              "</td>" +
+              "<td>" +
+              (can("queue-retry") ? '<button class="secondary" data-retry="' + ids[i] + '">Retry</button>' : "") +
+              (can("queue-cancel") ? '<button class="danger" data-cancel="' + ids[i] + '">Cancel</button>' : "") +
+              "</td></tr>"
+            );
+          })
+          .join("");
+      });
+    });
+  }
+
+  // Reports
+
+  function loadReports(filter) {
+    return request("/admin/report/list", filter).then(function (result) {
+      if (!result.data.length) {
+        $("reports-list").innerHTML = '<tr><td colspan="6">No reports are scheduled.</td></tr>';
+        return;
+      }
+      var ids = result.data;
+      return request("/admin/report/status", { ids: ids.join(",") }).then(function (status) {
+        $("reports-list").innerHTML = status.data
+          .map(function (report, i) {
+            if (!report) {
+              return "";
+            }
+            return (
+              "<tr><td>" + escape(report.domain) + "</td>" +
+              "<td>" + escape(report.type) + "</td>" +
+              "<td>" + escape(report.range_from) + "</td>" +
+              "<td>" + escape(report.range_to) + "</td>" +
+              "<td>" + formatSize(report.size) + "</td>" +
+              '<td><button class="danger" data-report-cancel="' + escape(ids[i]) + '">Cancel</button></td></tr>'
+            );
+          })
+          .join("");
+      });
+    });
+  }
+
+  // Server
+
+  function loadServer() {
+    return fetch("/readyz")
+      .then(function (response) {
+        return response.json();
+      })
+      .then(function (report) {
+        $("health-list").innerHTML = Object.keys(report.components || {})
+          .map(function (name) {
+            var component = report.components[name];
+            return (
+              "<tr><td>" + escape(name) + "</td>" +
+              '<td class="' + escape(component.status) + '">' + escape(component.status) + "</td>" +
+              "<td>" + component.latencyMs + " ms</td>" +
+              "<td>" + escape(component.error) + "</td></tr>"
+            );
+          })
+          .join("");
+      });
+  }
+
+  var views = {
+    accounts: loadAccounts,
+    domains: loadDomains,
+    queue: function () {
+      return loadQueue(formValues($("queue-filter")));
+    },
+    reports: function () {
+      return loadReports(formValues($("report-filter")));
+    },
+    server: loadServer,
+  };
+
+  function showView() {
+    var name = location.hash.substring(1);
+    var allowed = Array.prototype.filter
+      .call(document.querySelectorAll("nav a[data-view]"), function (link) {
+        return !link.hidden;
+      })
+      .map(function (link) {
+        return link.dataset.view;
+      });
+    if (allowed.indexOf(name) === -1) {
+      name = allowed[0];
+    }
+    document.querySelectorAll(".view").forEach(function (view) {
+      view.hidden = view.id !== "view-" + name;
+    });
+    document.querySelectorAll("nav a[data-view]").forEach(function (link) {
+      link.classList.toggle("active", link.dataset.view === name);
+    });
+    setStatus("");
+    views[name]().catch(function (err) {
+      setStatus(err.message, false);
+    });
+  }
+
+  function login() {
+    applyPermissions();
+    $("login").hidden = true;
+    $("console").hidden = false;
+    showView();
+  }
+
+  function logout() {
+    credentials = null;
+    identity = null;
+    sessionStorage.removeItem("credentials");
+    sessionStorage.removeItem("identity");
+    $("console").hidden = true;
+    $("login").hidden = false;
+  }
+
+  $("login-form").addEventListener("submit", function (e) {
+    e.preventDefault();
+    credentials = btoa(unescape(encodeURIComponent($("login-user").value + ":" + $("login-secret").value)));
+    request("/admin/whoami")
+      .then(function (result) {
+        identity = result;
+        sessionStorage.setItem("credentials", credentials);
+        sessionStorage.setItem("identity", JSON.stringify(identity));
+        $("login-error").textContent = "";
+        login();
+      })
+      .catch(function (err) {
+        logout();
+        $("login-error").textContent = err.message;
+      });
+  });
+
+  $("logout").addEventListener("click", function (e) {
+    e.preventDefault();
+    logout();
+  });
+
+  $("account-rename").addEventListener("submit", function (e) {
+    e.preventDefault();
+    var values = formValues(e.target);
+    action(request(adminPath("account", "rename", values.from, values.to)), "Account renamed.").then(loadAccounts);
+  });
+
+  $("account-delete").addEventListener("submit", function (e) {
+    e.preventDefault();
+    deleteAccount(formValues(e.target).name);
+  });
+
+  $("catch-all-claim").addEventListener("submit", function (e) {
+    e.preventDefault();
+    var values = formValues(e.target);
+    action(request(adminPath("catch-all", "claim", values.account, values.id, values.to)), "Message claimed.");
+  });
+
+  $("queue-filter").addEventListener("submit", function (e) {
+    e.preventDefault();
+    views.queue();
+  });
+
+  $("report-filter").addEventListener("submit", function (e) {
+    e.preventDefault();
+    views.reports();
+  });
+
+  $("config-reload").addEventListener("click", function () {
+    action(request("/admin/config/reload"), "Configuration reloaded.").then(function (result) {
+      if (result && result.restart_required && result.restart_required.length) {
+        setStatus("Configuration reloaded. Restart required for: " + result.restart_required.join(", "), true);
+      }
+    });
+  });
+
+  $("blob-purge").addEventListener("click", function () {
+    action(request("/admin/blob/purge"), "Temporary blobs purged.");
+  });
+
+  document.addEventListener("click", function (e) {
+    var data = e.target.dataset || {};
+    if (data.delete) {
+      deleteAccount(data.delete);
+    } else if (data.retry) {
+      action(request("/admin/queue/retry", { id: data.retry }), "Delivery rescheduled.").then(views.queue);
+    } else if (data.cancel && confirm("Cancel delivery of message " + data.cancel + "?")) {
+      action(request("/admin/queue/cancel", { id: data.cancel }), "Delivery cancelled.").then(views.queue);
+    } else if (data.reportCancel) {
+      action(request("/admin/report/cancel", { id: data.reportCancel }), "Report cancelled.").then(views.reports);
+    }
+  });
+
+  window.addEventListener("hashchange", function () {
+    if (credentials) {
+      showView();
+    }
+  });
+
+  if (credentials) {
+    login();
+  }
+})();
@@ -478,25 +478,35 @@ fn address_mappings() {
     catch-all = true
     subaddressing = true
     expected-sub = "john.doe@example.org"
+    expected-detail = "alias"
     expected-catch = "@example.org"
 
     [disable]
     catch-all = false
     subaddressing = false
     expected-sub = "john.doe+alias@example.org"
+    expected-detail = false
     expected-catch = false
 
     [custom]
     catch-all = { map = "(.+)@(.+)$", to = "info@${2}" }
     subaddressing = { map = "^([^.]+)\.([^.]+)@(.+)$", to = "${2}@${3}" }
     expected-sub = "doe+alias@example.org"
+    expected-detail = false
     expected-catch = "info@example.org"
+
+    [separator]
+    catch-all = false
+    subaddressing = { map = "^([^.]+)\.([^@]+)@(.+)$", to = "${1}@${3}" }
+    expected-sub = "john@example.org"
+    expected-detail = "doe+alias"
+    expected-catch = false
     "#;
 
     let config = utils::config::Config::new(MAPPINGS).unwrap();
     const ADDR: &str = "john.doe+alias@example.org";
 
-    for test in ["enable", "disable", "custom"] {
+    for test in ["enable", "disable", "custom", "separator"] {
         let catch_all = AddressMapping::from_config(&config, (test, "catch-all")).unwrap();
         let subaddressing = AddressMapping::from_config(&config, (test, "subaddressing")).unwrap();
 
@@ -506,6 +516,15 @@ fn address_mappings() {
             "failed subaddress for {test:?}"
         );
 
+        assert_eq!(
+            subaddressing.to_subaddress_detail(ADDR),
+            config
+                .property_require::<Option<String>>((test, "expected-detail"))
+                .unwrap()
+                .as_deref(),
+            "failed subaddress detail for {test:?}"
+        );
+
         assert_eq!(
             catch_all.to_catch_all(ADDR),
             config
@@ -516,3 +535,71 @@ fn address_mappings() {
         );
     }
 }
+
+#[tokio::test]
+async fn tenant_directory() {
+    const TENANT_CONFIG: &str = r#"
+    [directory."default"]
+    type = "memory"
+
+    [[directory."default".users]]
+    name = "john"
+    secret = "12345"
+    email = ["john@example.org"]
+
+    [directory."default".lookup]
+    domains = ["example.org"]
+
+    [directory."acme"]
+    type = "memory"
+
+    [[directory."acme".users]]
+    name = "jane@acme.org"
+    secret = "abcde"
+    email = ["jane@acme.org"]
+
+    [directory."acme".lookup]
+    domains = ["acme.org"]
+    "#;
+
+    let directories = utils::config::Config::new(TENANT_CONFIG)
+        .unwrap()
+        .parse_directory()
+        .unwrap()
+        .directories;
+    let directory =
+        directory::tenant::TenantDirectory::new(directories.get("default").unwrap().clone())
+            .with_domain("acme.org", directories.get("acme").unwrap().clone());
+
+    // Principals are resolved from the directory owning the domain
+    assert!(directory.principal("john").await.unwrap().is_some());
+    assert!(directory
+        .principal("jane@acme.org")
+        .await
+        .unwrap()
+        .is_some());
+    assert!(directory
+        .authenticate(&Credentials::Plain {
+            username: "jane@acme.org".to_string(),
+            secret: "abcde".to_string()
+        })
+        .await
+        .unwrap()
+        .is_some());
+    assert!(directory
+        .authenticate(&Credentials::Plain {
+            username: "john@acme.org".to_string(),
+            secret: "12345".to_string()
+        })
+        .await
+        .unwrap()
+        .is_none());
+
+    // Domains and recipients are isolated between tenants
+    assert!(directory.is_local_domain("example.org").await.unwrap());
+    assert!(directory.is_local_domain("acme.org").await.unwrap());
+    assert!(!directory.is_local_domain("other.org").await.unwrap());
+    assert!(directory.rcpt("jane@acme.org").await.unwrap());
+    assert!(directory.rcpt("john@example.org").await.unwrap());
+    assert!(!directory.rcpt("john@acme.org").await.unwrap());
+}
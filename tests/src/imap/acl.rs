@@ -325,4 +325,57 @@ pub async fn test(mut imap_john: &mut ImapConnection, _imap_check: &mut ImapConn
         .assert_read(Type::Tagged, ResponseType::Ok)
         .await
         .assert_count("Shared Folders", 3);
+
+    // Publish Jane's Inbox to all users in the domain except Bill
+    imap_jane.send("SETACL INBOX authenticated lr").await;
+    imap_jane.assert_read(Type::Tagged, ResponseType::Ok).await;
+    imap_jane.send("SETACL INBOX -foobar@example.com lr").await;
+    imap_jane.assert_read(Type::Tagged, ResponseType::Ok).await;
+    imap_jane.send("GETACL INBOX").await;
+    imap_jane
+        .assert_read(Type::Tagged, ResponseType::Ok)
+        .await
+        .assert_contains("\"authenticated\" rl")
+        .assert_contains("\"-foobar@example.com\" rl");
+    imap_bill.send("LIST \"\" \"*\"").await;
+    imap_bill
+        .assert_read(Type::Tagged, ResponseType::Ok)
+        .await
+        .assert_count("Shared Folders", 0);
+
+    // Removing the negative rights gives Bill access through the domain-wide grant
+    imap_jane.send("DELETEACL INBOX -foobar@example.com").await;
+    imap_jane.assert_read(Type::Tagged, ResponseType::Ok).await;
+    imap_bill.send("LIST \"\" \"*\"").await;
+    imap_bill
+        .assert_read(Type::Tagged, ResponseType::Ok)
+        .await
+        .assert_contains("Shared Folders/jane.smith@example.com/Inbox");
+    imap_bill
+        .send("MYRIGHTS \"Shared Folders/jane.smith@example.com/Inbox\"")
+        .await;
+    imap_bill
+        .assert_read(Type::Tagged, ResponseType::Ok)
+        .await
+        .assert_equals("* MYRIGHTS \"Shared Folders/jane.smith@example.com/Inbox\" rl");
+
+    // Denied rights override John's own grant
+    imap_jane.send("SETACL INBOX -jdoe@example.com lr").await;
+    imap_jane.assert_read(Type::Tagged, ResponseType::Ok).await;
+    imap_john.send("LIST \"\" \"*\"").await;
+    imap_john
+        .assert_read(Type::Tagged, ResponseType::Ok)
+        .await
+        .assert_count("Shared Folders", 0);
+    imap_jane.send("DELETEACL INBOX -jdoe@example.com").await;
+    imap_jane.assert_read(Type::Tagged, ResponseType::Ok).await;
+
+    // Revoke the domain-wide grant
+    imap_jane.send("DELETEACL INBOX authenticated").await;
+    imap_jane.assert_read(Type::Tagged, ResponseType::Ok).await;
+    imap_bill.send("LIST \"\" \"*\"").await;
+    imap_bill
+        .assert_read(Type::Tagged, ResponseType::Ok)
+        .await
+        .assert_count("Shared Folders", 0);
 }
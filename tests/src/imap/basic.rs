@@ -21,11 +21,15 @@
  * for more details.
 */
 
-use imap::op::authenticate::decode_challenge_oauth;
+use base64::{engine::general_purpose, Engine};
+use directory::scram::ScramAlgorithm;
+use imap::op::authenticate::{decode_challenge_oauth, decode_challenge_xoauth2};
 use imap_proto::ResponseType;
 use mail_parser::decoders::base64::base64_decode;
 use mail_send::Credentials;
 
+use crate::smtp::inbound::auth::scram_client_final;
+
 use super::{AssertResult, ImapConnection, Type};
 
 pub async fn test(imap: &mut ImapConnection, _imap_check: &mut ImapConnection) {
@@ -52,6 +56,47 @@ pub async fn test(imap: &mut ImapConnection, _imap_check: &mut ImapConnection) {
     imap.assert_read(Type::Continuation, ResponseType::Ok).await;
     imap.send_untagged("AGJvYXR5AG1jYm9hdGZhY2U=").await;
     imap.assert_read(Type::Tagged, ResponseType::No).await;
+
+    // SCRAM-SHA-256 with an invalid password should fail
+    let client_first_bare = "n=jdoe@example.com,r=fyko+d2lbbFgONRv9qkxdawL";
+    imap.send(&format!(
+        "AUTHENTICATE SCRAM-SHA-256 {}",
+        general_purpose::STANDARD.encode(format!("n,,{client_first_bare}"))
+    ))
+    .await;
+    let server_first = imap.assert_read(Type::Continuation, ResponseType::Ok).await;
+    let (client_final, _) = scram_client_final(
+        ScramAlgorithm::Sha256,
+        "wrong",
+        client_first_bare,
+        &server_first,
+    );
+    imap.send_untagged(&general_purpose::STANDARD.encode(client_final))
+        .await;
+    imap.assert_read(Type::Tagged, ResponseType::No).await;
+
+    // Successful SCRAM-SHA-256 authentication
+    imap.send("AUTHENTICATE SCRAM-SHA-256").await;
+    imap.assert_read(Type::Continuation, ResponseType::Ok).await;
+    let client_first_bare = "n=jdoe@example.com,r=rOprNGfwEbeRWgbNEkqO";
+    imap.send_untagged(&general_purpose::STANDARD.encode(format!("n,,{client_first_bare}")))
+        .await;
+    let server_first = imap.assert_read(Type::Continuation, ResponseType::Ok).await;
+    let (client_final, server_final) = scram_client_final(
+        ScramAlgorithm::Sha256,
+        "secret",
+        client_first_bare,
+        &server_first,
+    );
+    imap.send_untagged(&general_purpose::STANDARD.encode(client_final))
+        .await;
+    imap.assert_read(Type::Continuation, ResponseType::Ok)
+        .await
+        .assert_contains(&general_purpose::STANDARD.encode(server_final));
+    imap.send_untagged("").await;
+    imap.assert_read(Type::Tagged, ResponseType::Ok).await;
+    imap.send("UNAUTHENTICATE").await;
+    imap.assert_read(Type::Tagged, ResponseType::Ok).await;
 }
 
 #[test]
@@ -73,4 +118,12 @@ fn decode_challenge() {
         )
         .unwrap()
     );
+    assert_eq!(
+        decode_challenge_xoauth2(b"user=someuser@example.com\x01auth=Bearer ya29.vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg\x01\x01")
+            .unwrap(),
+        Credentials::XOauth2 {
+            username: "someuser@example.com".to_string(),
+            secret: "ya29.vF9dft4qmTc2Nvb3RlckBhdHRhdmlzdGEuY29tCg".to_string()
+        }
+    );
 }
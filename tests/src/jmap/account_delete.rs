@@ -0,0 +1,105 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::JMAP;
+use jmap_client::{client::Client, mailbox::Role, principal::ACL};
+use jmap_proto::types::id::Id;
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{jmap_json_request, mailbox::destroy_all_mailboxes},
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running account deletion tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "owner@example.com", "12345", "Mailbox Owner").await;
+    create_test_user_with_email(directory, "deleted@example.com", "12345", "Deleted User").await;
+    let owner_id = server.get_account_id("owner@example.com").await.unwrap();
+    let account_id = server.get_account_id("deleted@example.com").await.unwrap();
+
+    // Share a mailbox with the account to be deleted
+    admin_client.set_default_account_id(Id::from(owner_id).to_string());
+    let mailbox_id = admin_client
+        .mailbox_create("Shared", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+    admin_client
+        .mailbox_update_acl(&mailbox_id, "deleted@example.com", [ACL::Read])
+        .await
+        .unwrap();
+
+    // Deleting the account removes the directory entry, the name mapping and any grants
+    server
+        .delete_account("deleted@example.com", account_id)
+        .await
+        .unwrap();
+    assert_eq!(
+        server
+            .try_get_account_id("deleted@example.com")
+            .await
+            .unwrap(),
+        None
+    );
+    assert!(directory
+        .emails_by_name("deleted@example.com")
+        .await
+        .unwrap()
+        .is_empty());
+    let response = jmap_json_request(
+        r#"[[
+            "Mailbox/get",
+            {
+             "accountId": "$$",
+             "ids": ["%%"],
+             "properties": ["shareWith"]
+            },
+            "R1"
+           ]]"#
+        .replace("$$", &Id::from(owner_id).to_string())
+        .replace("%%", &mailbox_id),
+        "owner@example.com",
+        "12345",
+    )
+    .await;
+    assert!(
+        response
+            .pointer("/methodResponses/0/1/list/0/shareWith")
+            .and_then(|v| v.as_object())
+            .map_or(true, |share_with| share_with.is_empty()),
+        "Response: {response:?}"
+    );
+
+    // Deleting again is a no-op, so a failed deletion can be retried
+    server
+        .delete_account("deleted@example.com", account_id)
+        .await
+        .unwrap();
+
+    // Empty store
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
@@ -0,0 +1,105 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::JMAP;
+use jmap_client::client::Client;
+use jmap_proto::types::id::Id;
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{jmap_json_request, mailbox::destroy_all_mailboxes},
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running account rename tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "old.name@example.com", "12345", "Renamed User").await;
+    let account_id = server.get_account_id("old.name@example.com").await.unwrap();
+    admin_client.set_default_account_id(Id::from(account_id).to_string());
+    let identity_id = admin_client
+        .identity_create("Renamed User", "old.name@example.com")
+        .await
+        .unwrap()
+        .take_id();
+
+    // Renaming the account changes its primary address, the old one is kept as an alias
+    server
+        .rename_account("new.name@example.com", "old.name@example.com", account_id)
+        .await
+        .unwrap();
+    assert_eq!(
+        server.get_account_id("new.name@example.com").await.unwrap(),
+        account_id
+    );
+    assert_eq!(
+        directory
+            .emails_by_name("new.name@example.com")
+            .await
+            .unwrap(),
+        vec![
+            "new.name@example.com".to_string(),
+            "old.name@example.com".to_string()
+        ]
+    );
+
+    // Identities using the old primary address are rewritten
+    let response = jmap_json_request(
+        r#"[[
+            "Identity/get",
+            {
+             "accountId": "$$",
+             "ids": ["%%"]
+            },
+            "R1"
+           ]]"#
+        .replace("$$", &Id::from(account_id).to_string())
+        .replace("%%", &identity_id),
+        "new.name@example.com",
+        "12345",
+    )
+    .await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/list/0/email")
+            .and_then(|v| v.as_str()),
+        Some("new.name@example.com"),
+        "Response: {response:?}"
+    );
+
+    // The alias is removed once the grace period expires
+    server.expire_renamed_aliases().await.unwrap();
+    assert_eq!(
+        directory
+            .emails_by_name("new.name@example.com")
+            .await
+            .unwrap(),
+        vec!["new.name@example.com".to_string()]
+    );
+
+    // Empty store
+    admin_client.identity_destroy(&identity_id).await.unwrap();
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
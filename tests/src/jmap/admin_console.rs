@@ -0,0 +1,129 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use jmap::{
+    auth::tenant::{AdminPermission, AdminRole, Tenants},
+    JMAP,
+};
+use jmap_client::client::Client;
+use utils::config::Config;
+
+pub async fn test(_server: Arc<JMAP>, _client: &mut Client) {
+    println!("Running admin console tests...");
+
+    // Console assets are served without authentication
+    for (path, content_type, contains) in [
+        ("admin", "text/html", "<script src=\"/admin/ui/admin.js\">"),
+        ("admin/", "text/html", "<script src=\"/admin/ui/admin.js\">"),
+        (
+            "admin/ui/admin.js",
+            "application/javascript",
+            "/admin/usage/list",
+        ),
+        ("admin/ui/admin.css", "text/css", "nav"),
+    ] {
+        let (code, headers, body) = http_get(path).await;
+        assert_eq!(code, 200, "{path}");
+        assert!(headers.starts_with(content_type), "{path}: {headers}");
+        assert!(body.contains(contains), "{path}: {body}");
+    }
+
+    // Admin API calls still require authentication
+    let (code, _, _) = http_get("admin/usage/list").await;
+    assert_eq!(code, 401);
+    let (code, _, _) = http_get("admin/ui/unknown.js").await;
+    assert_eq!(code, 401);
+}
+
+#[test]
+fn admin_role_scope() {
+    let tenants = Tenants::parse(
+        &Config::new(
+            r#"[tenant."example"]
+domains = ["example.org"]
+admins = ["admin@example.org"]
+"#,
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let (tenant, role) = tenants.by_role_member("admin@example.org").unwrap();
+    assert_eq!(role, AdminRole::DomainAdmin);
+
+    // Tenant admins can only manage accounts of their own domains
+    for (permission, account_name, expected) in [
+        (
+            AdminPermission::AccountManage,
+            Some("jdoe@example.org"),
+            true,
+        ),
+        (
+            AdminPermission::AccountManage,
+            Some("jdoe@foobar.org"),
+            false,
+        ),
+        (AdminPermission::AccountManage, None, false),
+        (AdminPermission::QueueCancel, None, false),
+        (AdminPermission::ServerManage, None, false),
+    ] {
+        assert_eq!(
+            role.is_allowed(permission, Some(tenant), account_name),
+            expected,
+            "{permission:?} {account_name:?}"
+        );
+    }
+
+    // Unscoped roles can perform global operations
+    assert!(AdminRole::SuperAdmin.is_allowed(AdminPermission::AccountManage, None, None));
+    assert!(AdminRole::SuperAdmin.is_allowed(
+        AdminPermission::AccountManage,
+        None,
+        Some("jdoe@foobar.org")
+    ));
+    assert!(AdminRole::SuperAdmin.is_allowed(AdminPermission::ServerManage, None, None));
+}
+
+async fn http_get(path: &str) -> (u16, String, String) {
+    let response = reqwest::Client::builder()
+        .timeout(Duration::from_millis(1000))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .get(format!("https://127.0.0.1:8899/{path}"))
+        .send()
+        .await
+        .unwrap();
+
+    (
+        response.status().as_u16(),
+        response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default()
+            .to_string(),
+        response.text().await.unwrap(),
+    )
+}
@@ -0,0 +1,258 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use jmap::{submission::attachment_link::AttachmentLink, JMAP};
+use jmap_client::{client::Client, email_submission::query::Filter, mailbox::Role};
+use jmap_proto::types::id::Id;
+use reqwest::{header, Method, StatusCode};
+use serde_json::json;
+use store::BlobKind;
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{
+        email_submission::{expect_message_delivery, expect_nothing, spawn_mock_smtp_server},
+        mailbox::destroy_all_mailboxes,
+        settings::settings_request,
+    },
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running attachment link tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "jdoe@example.com", "12345", "John Doe").await;
+    let account_id = server.get_account_id("jdoe@example.com").await.unwrap();
+    let login = "jdoe@example.com";
+
+    // Start mock SMTP server
+    let (mut smtp_rx, smtp_settings) = spawn_mock_smtp_server();
+    server.smtp.resolvers.dns.ipv4_add(
+        "localhost",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + std::time::Duration::from_secs(10),
+    );
+
+    // Enable attachment links with a password
+    let (code, response) = settings_request(
+        Method::PUT,
+        "attachment-links",
+        login,
+        "12345",
+        json!({"enabled": true, "password": "open sesame"}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    let (_, response) = settings_request(Method::GET, "", login, "12345", None).await;
+    assert_eq!(response["attachmentLinks"]["enabled"], true, "{response}");
+    assert_eq!(
+        response["attachmentLinks"]["hasPassword"], true,
+        "{response}"
+    );
+    assert!(
+        response["attachmentLinks"].get("password").is_none(),
+        "{response}"
+    );
+
+    // Import a message with a large and a small attachment
+    admin_client.set_default_account_id(Id::from(account_id).to_string());
+    let identity_id = admin_client
+        .identity_create("John Doe", "jdoe@example.com")
+        .await
+        .unwrap()
+        .take_id();
+    let mailbox_id = admin_client
+        .mailbox_create("Attachment links", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+    let large_attachment = "0123456789abcdef".repeat(128);
+    let message = format!(
+        concat!(
+            "From: jdoe@example.com\r\n",
+            "To: jane_smith@remote.org\r\n",
+            "Subject: Quarterly report\r\n",
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: multipart/mixed; boundary=\"XYZ\"\r\n",
+            "\r\n",
+            "--XYZ\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "Please find the report attached.\r\n",
+            "--XYZ\r\n",
+            "Content-Type: application/octet-stream\r\n",
+            "Content-Disposition: attachment; filename=\"report.bin\"\r\n",
+            "\r\n",
+            "{}\r\n",
+            "--XYZ\r\n",
+            "Content-Type: text/plain\r\n",
+            "Content-Disposition: attachment; filename=\"notes.txt\"\r\n",
+            "\r\n",
+            "Small notes\r\n",
+            "--XYZ--\r\n"
+        ),
+        large_attachment
+    );
+    let email_id = admin_client
+        .email_import(
+            message.as_bytes().to_vec(),
+            [&mailbox_id],
+            None::<Vec<&str>>,
+            None,
+        )
+        .await
+        .unwrap()
+        .take_id();
+
+    // The large attachment is replaced with a link
+    admin_client
+        .email_submission_create(&email_id, &identity_id)
+        .await
+        .unwrap();
+    let message = expect_message_delivery(&mut smtp_rx).await;
+    assert!(!message.message.contains(&large_attachment));
+    for needle in [
+        "Please find the report attached.",
+        "The attachment \"report.bin\" (2.0 KB) was replaced with a download link:",
+        "A password is required to download the attachment.",
+        "filename=\"notes.txt\"",
+        "Small notes",
+        "--XYZ--",
+    ] {
+        assert!(
+            message.message.contains(needle),
+            "{needle:?} not found in {}",
+            message.message
+        );
+    }
+    let url = message
+        .message
+        .split_whitespace()
+        .find(|word| word.starts_with("https://127.0.0.1:8899/attachment/"))
+        .unwrap_or_else(|| panic!("Link not found in {}", message.message))
+        .to_string();
+    expect_nothing(&mut smtp_rx).await;
+
+    // The stored message keeps the original attachment
+    let stored = server
+        .get_blob(
+            &BlobKind::LinkedMaildir {
+                account_id,
+                document_id: Id::from_bytes(email_id.as_bytes()).unwrap().document_id(),
+            },
+            0..u32::MAX,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&stored).contains(&large_attachment));
+
+    // Download the attachment
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(5000))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default();
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Enter the password to download report.bin."));
+    let response = client
+        .post(&url)
+        .form(&[("password", "wrong password")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = client
+        .post(&url)
+        .form(&[("password", "open sesame")])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+        "attachment; filename=\"report.bin\""
+    );
+    assert_eq!(response.text().await.unwrap(), large_attachment);
+    let response = client.get(format!("{url}0")).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    // Messages are sent unmodified when the feature is disabled
+    let (code, response) = settings_request(
+        Method::PUT,
+        "attachment-links",
+        login,
+        "12345",
+        json!({"enabled": false}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    admin_client
+        .email_submission_create(&email_id, &identity_id)
+        .await
+        .unwrap();
+    let message = expect_message_delivery(&mut smtp_rx).await;
+    assert!(message.message.contains(&large_attachment));
+    expect_nothing(&mut smtp_rx).await;
+    smtp_settings.lock().do_stop = true;
+
+    // Expired links are purged
+    let links = server.attachment_links(account_id.into()).await.unwrap();
+    assert_eq!(links.len(), 1);
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::GONE);
+    server.purge_attachment_links().await.unwrap();
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert!(server
+        .get_blob(
+            &AttachmentLink::blob_kind(account_id, links[0].1),
+            0..u32::MAX
+        )
+        .await
+        .unwrap()
+        .is_none());
+
+    // Empty store
+    admin_client.identity_destroy(&identity_id).await.unwrap();
+    for id in admin_client
+        .email_submission_query(None::<Filter>, None::<Vec<_>>)
+        .await
+        .unwrap()
+        .take_ids()
+    {
+        admin_client.email_submission_destroy(&id).await.unwrap();
+    }
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
@@ -30,6 +30,9 @@ use jmap_client::{
     mailbox::{self},
 };
 use jmap_proto::types::id::Id;
+use reqwest::header;
+use serde_json::{json, Value};
+use utils::listener::limiter::RateLimiter;
 
 use crate::{
     directory::sql::{create_test_user_with_email, link_test_address},
@@ -95,6 +98,24 @@ pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
         }
     }
 
+    // Rate limited responses include a Retry-After header
+    let response = reqwest::Client::builder()
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap()
+        .get("https://127.0.0.1:8899/.well-known/jmap")
+        .basic_auth("not_an_account@example.com", Some("brute_force"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 429);
+    assert!(response
+        .headers()
+        .get(header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .map_or(false, |value| value > 0));
+
     // Limit should be restored after 1 second
     tokio::time::sleep(Duration::from_millis(1500)).await;
 
@@ -199,6 +220,61 @@ pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
         client.upload(None, b"sleep".to_vec(), None).await,
         Err(jmap_client::Error::Problem(err)) if err.status() == Some(400)));
 
+    // Method calls are rate limited per account
+    server
+        .rate_limit_auth
+        .get(&server.get_account_id("jdoe@example.com").await.unwrap())
+        .unwrap()
+        .lock()
+        .method_limiter = RateLimiter::new(1, Duration::from_secs(60));
+    client
+        .mailbox_query(None::<mailbox::query::Filter>, None::<Vec<_>>)
+        .await
+        .unwrap();
+    assert!(matches!(
+        client
+            .mailbox_query(None::<mailbox::query::Filter>, None::<Vec<_>>)
+            .await,
+            Err(jmap_client::Error::Problem(err)) if err.status() == Some(429)));
+    server.rate_limit_auth.clear();
+
+    // Requests exceeding their cost budget should not process any further calls
+    for (num_ids, expect_echo) in [(600, true), (100001, false)] {
+        let response = reqwest::Client::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap()
+            .post("https://127.0.0.1:8899/jmap")
+            .basic_auth("jdoe@example.com", Some("12345"))
+            .body(
+                json!({
+                    "using": ["urn:ietf:params:jmap:core", "urn:ietf:params:jmap:mail"],
+                    "methodCalls": [
+                        ["Mailbox/get", {
+                            "accountId": account_id,
+                            "ids": (0..num_ids).map(|id| Id::from(id as u64).to_string()).collect::<Vec<_>>()
+                        }, "0"],
+                        ["Core/echo", {"hello": true}, "1"]
+                    ]
+                })
+                .to_string(),
+            )
+            .send()
+            .await
+            .unwrap()
+            .json::<Value>()
+            .await
+            .unwrap();
+        let responses = &response["methodResponses"];
+        assert_eq!(responses[0][0], "error", "{response}");
+        assert_eq!(responses[0][1]["type"], "requestTooLarge", "{response}");
+        if expect_echo {
+            assert_eq!(responses[1][0], "Core/echo", "{response}");
+        } else {
+            assert_eq!(responses[1][1]["type"], "requestTooLarge", "{response}");
+        }
+    }
+
     // Destroy test accounts
     admin_client.set_default_account_id(&account_id);
     destroy_all_mailboxes(admin_client).await;
@@ -26,9 +26,12 @@ use std::{
     time::{Duration, Instant},
 };
 
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use bytes::Bytes;
 use jmap::{
-    auth::oauth::{DeviceAuthResponse, ErrorType, OAuthMetadata, TokenResponse},
+    auth::oauth::{
+        jwt::TokenProtocol, DeviceAuthResponse, ErrorType, OAuthMetadata, TokenResponse,
+    },
     JMAP,
 };
 use jmap_client::{
@@ -36,11 +39,14 @@ use jmap_client::{
     mailbox::query::Filter,
 };
 use jmap_proto::types::id::Id;
-use reqwest::{header, redirect::Policy};
+use reqwest::{header, redirect::Policy, Method};
 use serde::de::DeserializeOwned;
 use store::ahash::AHashMap;
 
-use crate::{directory::sql::create_test_user_with_email, jmap::mailbox::destroy_all_mailboxes};
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{mailbox::destroy_all_mailboxes, settings::settings_request},
+};
 
 pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
     println!("Running OAuth tests...");
@@ -85,10 +91,49 @@ pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
         "code".to_string(),
         parse_code_input(get_bytes(&auth_endpoint).await),
     );
+
+    // Denying consent should redirect with an access_denied code
+    let consent_page = post_bytes(&metadata.authorization_endpoint, &auth_request).await;
+    let consent_request = AHashMap::from_iter([
+        ("code".to_string(), parse_code_input(consent_page.clone())),
+        ("consent".to_string(), parse_consent_input(consent_page)),
+        ("action".to_string(), "deny".to_string()),
+    ]);
+    assert_eq!(
+        post_expect_redirect(&metadata.authorization_endpoint, &consent_request).await,
+        "https://localhost?error=access_denied&state=xyz"
+    );
+
+    // Allow access to the client
+    auth_request.insert(
+        "code".to_string(),
+        parse_code_input(get_bytes(&auth_endpoint).await),
+    );
+    let consent_page = post_bytes(&metadata.authorization_endpoint, &auth_request).await;
+    assert!(String::from_utf8_lossy(&consent_page).contains("OAuthyMcOAuthFace"));
+    let consent_request = AHashMap::from_iter([
+        ("code".to_string(), parse_code_input(consent_page.clone())),
+        ("consent".to_string(), parse_consent_input(consent_page)),
+        ("action".to_string(), "allow".to_string()),
+    ]);
     let code = parse_code_redirect(
+        post_expect_redirect(&metadata.authorization_endpoint, &consent_request).await,
+        "xyz",
+    );
+
+    // Consent is remembered for subsequent authorizations
+    auth_request.insert(
+        "code".to_string(),
+        parse_code_input(get_bytes(&auth_endpoint).await),
+    );
+    parse_code_redirect(
         post_expect_redirect(&metadata.authorization_endpoint, &auth_request).await,
         "xyz",
     );
+    let (status, consents) =
+        settings_request(Method::GET, "consents", "jdoe@example.com", "12345", None).await;
+    assert_eq!(status, 200, "{consents}");
+    assert_eq!(consents[0]["clientId"], "OAuthyMcOAuthFace", "{consents}");
 
     // Both client_id and redirect_uri have to match
     let mut token_params = AHashMap::from_iter([
@@ -134,6 +179,112 @@ pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
         .ids()
         .is_empty());
 
+    // Access tokens are signed JWTs published through the JWKS endpoint
+    assert_eq!(token.split('.').count(), 3, "{}", token);
+    let claims = server.validate_jwt(&token).await.unwrap();
+    assert_eq!(
+        Id::from(claims.sub.parse::<u32>().unwrap()).to_string(),
+        john_id
+    );
+    assert_eq!(claims.client_id, "OAuthyMcOAuthFace");
+    assert!(metadata.jwks_uri.ends_with("/auth/jwks.json"));
+    let jwks: serde_json::Value = get(&metadata.jwks_uri).await;
+    let kid = jwt_kid(&token);
+    assert!(jwks["keys"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|key| key["kid"].as_str() == Some(&kid)));
+
+    // Tampered tokens are rejected
+    let (message, _) = token.rsplit_once('.').unwrap();
+    assert!(server
+        .validate_jwt(&format!("{message}.AAAA"))
+        .await
+        .is_err());
+
+    // Rotating the signing key keeps previously issued tokens valid
+    let new_kid = server
+        .rotate_jwt_keys()
+        .await
+        .unwrap()
+        .current()
+        .unwrap()
+        .kid
+        .clone();
+    assert_ne!(kid, new_kid);
+    assert!(server.validate_jwt(&token).await.is_ok());
+    let new_token = server
+        .encode_jwt(claims.sub.parse().unwrap(), "OAuthyMcOAuthFace", None, 3600)
+        .await
+        .unwrap();
+    assert_eq!(jwt_kid(&new_token), new_kid);
+    let jwks: serde_json::Value = get(&metadata.jwks_uri).await;
+    assert!(jwks["keys"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|key| key["kid"].as_str() == Some(&kid)));
+
+    // Tokens scoped to a protocol are rejected by the other protocols
+    let imap_token = server
+        .encode_jwt(
+            claims.sub.parse().unwrap(),
+            "OAuthyMcOAuthFace",
+            "imap offline_access".into(),
+            3600,
+        )
+        .await
+        .unwrap();
+    assert!(server
+        .validate_access_token("access_token", &imap_token, TokenProtocol::Imap.into())
+        .await
+        .is_ok());
+    assert!(server
+        .validate_access_token("access_token", &imap_token, TokenProtocol::Jmap.into())
+        .await
+        .is_err());
+    assert!(server
+        .validate_access_token("access_token", &new_token, TokenProtocol::Smtp.into())
+        .await
+        .is_ok());
+
+    // Unknown signing keys do not trigger a reload of the key set every time
+    let (_, message) = new_token.split_once('.').unwrap();
+    let forged_header = URL_SAFE_NO_PAD.encode(format!(
+        "{{\"alg\":\"ES256\",\"typ\":\"JWT\",\"kid\":\"{:016x}\"}}",
+        u64::MAX
+    ));
+    let keys = server.jwt_keys.read().clone();
+    for _ in 0..3 {
+        assert_eq!(
+            server
+                .validate_jwt(&format!("{forged_header}.{message}"))
+                .await
+                .unwrap_err(),
+            "Unknown signing key."
+        );
+    }
+    assert!(Arc::ptr_eq(&keys, &server.jwt_keys.read()));
+
+    // Revoking consent invalidates the tokens issued to the client
+    assert_eq!(
+        settings_request(
+            Method::DELETE,
+            "consents/OAuthyMcOAuthFace",
+            "jdoe@example.com",
+            "12345",
+            None
+        )
+        .await
+        .0,
+        200
+    );
+    assert_unauthorized("https://127.0.0.1:8899", &token).await;
+    let (_, consents) =
+        settings_request(Method::GET, "consents", "jdoe@example.com", "12345", None).await;
+    assert_eq!(consents, serde_json::json!([]));
+
     // ------------------------
     // Device code flow
     // ------------------------
@@ -144,6 +295,23 @@ pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
         post(&metadata.device_authorization_endpoint, &device_code_params).await;
     //println!("Device response: {:#?}", device_response);
 
+    // Codes are persisted and can be looked up by device or user code
+    let oauth_code = server
+        .get_oauth_code(&device_response.device_code)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(oauth_code.client_id, "1234");
+    assert_eq!(
+        server
+            .get_oauth_code(&device_response.user_code)
+            .await
+            .unwrap()
+            .unwrap()
+            .status,
+        oauth_code.status
+    );
+
     // Status should be pending
     let mut token_params = AHashMap::from_iter([
         ("client_id".to_string(), "1234".to_string()),
@@ -163,6 +331,27 @@ pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
         }
     );
 
+    // Polling faster than the advertised interval should return slow_down
+    assert_eq!(
+        post::<TokenResponse>(&metadata.token_endpoint, &token_params).await,
+        TokenResponse::Error {
+            error: ErrorType::SlowDown
+        }
+    );
+
+    // The verification page is pre-filled with the user code and can be shared as a QR code
+    let html =
+        String::from_utf8_lossy(&get_bytes(&device_response.verification_uri_complete).await)
+            .into_owned();
+    assert!(
+        html.contains(&format!("value=\"{}\"", device_response.user_code)),
+        "{html}"
+    );
+    let qr_code =
+        String::from_utf8_lossy(&get_bytes(&device_response.verification_uri_complete_qr).await)
+            .into_owned();
+    assert!(qr_code.contains("<svg"), "{qr_code}");
+
     // Invalidate the code by having too many unsuccessful attempts
     assert_client_auth(
         "jdoe@example.com",
@@ -208,6 +397,12 @@ pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
             error: ErrorType::ExpiredToken
         }
     );
+    assert!(server
+        .get_oauth_code(&device_response.device_code)
+        .await
+        .unwrap()
+        .is_none());
+    server.purge_oauth_codes().await.unwrap();
 
     // Authenticate account using a valid code
     let device_response: DeviceAuthResponse =
@@ -406,6 +601,13 @@ async fn assert_unauthorized(base_url: &str, token: &str) {
     }
 }
 
+fn jwt_kid(token: &str) -> String {
+    let header = token.split('.').next().unwrap();
+    let header: serde_json::Value =
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).unwrap()).unwrap();
+    header["kid"].as_str().unwrap().to_string()
+}
+
 fn parse_code_input(bytes: Bytes) -> String {
     let html = String::from_utf8_lossy(&bytes).into_owned();
     if let Some((_, code)) = html.split_once("name=\"code\" value=\"") {
@@ -416,6 +618,16 @@ fn parse_code_input(bytes: Bytes) -> String {
     panic!("Could not parse code input: {}", html);
 }
 
+fn parse_consent_input(bytes: Bytes) -> String {
+    let html = String::from_utf8_lossy(&bytes).into_owned();
+    if let Some((_, code)) = html.split_once("name=\"consent\" value=\"") {
+        if let Some((code, _)) = code.split_once('\"') {
+            return code.to_string();
+        }
+    }
+    panic!("Could not parse consent input: {}", html);
+}
+
 fn parse_code_redirect(uri: String, state: &str) -> String {
     if let Some(code) = uri.strip_prefix("https://localhost?code=") {
         if let Some(code) = code.strip_suffix(&format!("&state={}", state)) {
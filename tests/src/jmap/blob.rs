@@ -21,12 +21,15 @@
  * for more details.
 */
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
+use base64::{engine::general_purpose, Engine};
 use jmap::{mailbox::INBOX_ID, JMAP};
 use jmap_client::client::Client;
-use jmap_proto::types::id::Id;
+use jmap_proto::types::{blob::BlobId, id::Id};
+use reqwest::header;
 use serde_json::Value;
+use store::{write::now, BlobKind};
 
 use crate::{
     directory::sql::create_test_user_with_email,
@@ -88,6 +91,103 @@ pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
         response
     );
 
+    // The session should reflect the remaining upload quota
+    let session = download("jmap/session", &[])
+        .await
+        .json::<Value>()
+        .await
+        .unwrap();
+    let upload_caps = session
+        .pointer(&format!(
+            "/accounts/{account_id}/accountCapabilities/urn:stalwart:jmap:upload"
+        ))
+        .unwrap_or_else(|| panic!("Missing upload capabilities: {session:?}"));
+    for (property, expected) in [
+        ("maxTemporaryBlobs", 3),
+        ("remainingTemporaryBlobs", 2),
+        ("maxTemporaryBytes", 50000),
+        ("remainingTemporaryBytes", 50000 - 95),
+        ("temporaryBlobTtl", 60),
+    ] {
+        assert_eq!(
+            upload_caps.get(property).and_then(|v| v.as_u64()),
+            Some(expected),
+            "Property {property:?} Capabilities: {upload_caps:?}"
+        );
+    }
+
+    // Temporary blobs older than the TTL should no longer be accessible
+    let expired_blob = BlobKind::Temporary {
+        account_id: account_id.document_id(),
+        timestamp: now() - 3600,
+        seq: 0,
+    };
+    assert!(server.is_expired_blob(&expired_blob));
+    assert!(!server.is_expired_blob(&BlobId::temporary(account_id.document_id()).kind));
+    assert_eq!(
+        server.get_blob(&expired_blob, 0..u32::MAX).await.unwrap(),
+        None
+    );
+
+    // Blob/upload with digest validation
+    let response = jmap_json_request(
+        r#"[[
+            "Blob/upload",
+            {
+             "accountId": "$$",
+             "create": {
+              "valid": {
+               "data" : [
+               {
+                "data:asBase64": "aGVsbG8g"
+               },
+               {
+                "data:asBase64": "d29y\nbGQ="
+               }
+              ],
+              "digest:sha-256": "uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek="
+              },
+              "invalid": {
+               "data" : [
+               {
+                "data:asText": "hello world!"
+               }
+              ],
+              "digest:sha-256": "uU0nuZNNPgilLlLX2n2r+sSE7+N6U4DukIj3rOLvzek="
+              }
+             }
+            },
+            "R1"
+           ]]"#
+        .replace("$$", &account_id.to_string()),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/created/valid/size")
+            .and_then(|v| v.as_i64())
+            .unwrap_or_default(),
+        11,
+        "Response: {:?}",
+        response
+    );
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/notCreated/invalid/properties/0")
+            .and_then(|v| v.as_str())
+            .unwrap_or_default(),
+        "digest:sha-256",
+        "Response: {:?}",
+        response
+    );
+    server
+        .store
+        .delete_account_blobs(account_id.document_id())
+        .await
+        .unwrap();
+
     // Blob/get simple test
     let blob_id = jmap_json_request(
         r#"[[
@@ -116,6 +216,73 @@ pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
     .unwrap()
     .to_string();
 
+    // Conditional and range downloads
+    let download_url = format!("jmap/download/{account_id}/{blob_id}/fox.txt");
+    let response = download(&download_url, &[]).await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+    assert!(response.headers().contains_key(header::LAST_MODIFIED));
+    let etag = response.headers()[header::ETAG]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(etag.starts_with(&format!("\"{blob_id}-")), "{etag}");
+    let last_modified = response.headers()[header::LAST_MODIFIED]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert_eq!(
+        response.text().await.unwrap(),
+        "The quick brown fox jumped over the lazy dog."
+    );
+    for (headers, status) in [
+        (vec![("if-none-match", etag.as_str())], 304),
+        (vec![("if-none-match", "\"other\", *")], 304),
+        (vec![("if-none-match", "\"other\"")], 200),
+        (vec![("if-modified-since", last_modified.as_str())], 304),
+        (
+            vec![("if-modified-since", "Thu, 01 Jan 1970 00:00:00 GMT")],
+            200,
+        ),
+    ] {
+        assert_eq!(
+            download(&download_url, &headers).await.status().as_u16(),
+            status,
+            "{headers:?}"
+        );
+    }
+    for (range, expected_range, expected_body) in [
+        ("bytes=4-8", "bytes 4-8/45", "quick"),
+        ("bytes=41-", "bytes 41-44/45", "dog."),
+        ("bytes=-4", "bytes 41-44/45", "dog."),
+        ("bytes=41-1000", "bytes 41-44/45", "dog."),
+    ] {
+        let response = download(&download_url, &[("range", range)]).await;
+        assert_eq!(response.status().as_u16(), 206, "{range}");
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            expected_range,
+            "{range}"
+        );
+        assert_eq!(response.text().await.unwrap(), expected_body, "{range}");
+    }
+    let response = download(&download_url, &[("range", "bytes=100-")]).await;
+    assert_eq!(response.status().as_u16(), 416);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */45");
+    let response = download(
+        &download_url,
+        &[("range", "bytes=0-2"), ("if-range", "\"stale\"")],
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+    let response = download(
+        &download_url,
+        &[("range", "bytes=0-2"), ("if-range", etag.as_str())],
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 206);
+    assert_eq!(response.text().await.unwrap(), "The");
+
     let response = jmap_json_request(
         r#"[
             [
@@ -507,3 +674,23 @@ pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
     destroy_all_mailboxes(admin_client).await;
     server.store.assert_is_empty().await;
 }
+
+async fn download(path: &str, headers: &[(&str, &str)]) -> reqwest::Response {
+    let mut request = reqwest::Client::builder()
+        .timeout(Duration::from_millis(1000))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .get(format!("https://127.0.0.1:8899/{path}"))
+        .header(
+            header::AUTHORIZATION,
+            format!(
+                "Basic {}",
+                general_purpose::STANDARD.encode("jdoe@example.com:12345")
+            ),
+        );
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.send().await.unwrap()
+}
@@ -0,0 +1,231 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::{collected_address::first_contact::with_first_contact_header, JMAP};
+use jmap_proto::types::id::Id;
+
+use crate::{directory::sql::create_test_user_with_email, jmap::jmap_json_request};
+
+pub async fn test(server: Arc<JMAP>) {
+    println!("Running Collected Address and first contact tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "jdoe@example.com", "12345", "John Doe").await;
+    let account_id = server.get_account_id("jdoe@example.com").await.unwrap();
+
+    // Collect addresses as if several messages were sent
+    server
+        .collect_addresses(
+            account_id,
+            [
+                (
+                    "Jane.Smith@example.org".to_string(),
+                    Some("Jane Smith".to_string()),
+                ),
+                ("bill@example.net".to_string(), None),
+            ],
+        )
+        .await
+        .unwrap();
+    server
+        .collect_addresses(
+            account_id,
+            [
+                ("jane.smith@example.org".to_string(), None),
+                ("jane.smith@example.org".to_string(), None),
+                ("not-an-address".to_string(), None),
+            ],
+        )
+        .await
+        .unwrap();
+    assert!(server
+        .is_collected_address(account_id, "JANE.SMITH@example.org")
+        .await
+        .unwrap());
+    assert!(!server
+        .is_collected_address(account_id, "not-an-address")
+        .await
+        .unwrap());
+
+    // Addresses are ranked by frequency and names are preserved
+    let account_id = Id::from(account_id);
+    assert_eq!(
+        autocomplete(&account_id, None).await,
+        vec![
+            (
+                "jane.smith@example.org".to_string(),
+                Some("Jane Smith".to_string()),
+                2
+            ),
+            ("bill@example.net".to_string(), None, 1)
+        ]
+    );
+
+    // Filter by name or address
+    assert_eq!(
+        autocomplete(&account_id, Some("smith")).await,
+        vec![(
+            "jane.smith@example.org".to_string(),
+            Some("Jane Smith".to_string()),
+            2
+        )]
+    );
+    assert_eq!(
+        autocomplete(&account_id, Some("EXAMPLE.NET")).await,
+        vec![("bill@example.net".to_string(), None, 1)]
+    );
+    assert_eq!(autocomplete(&account_id, Some("nobody")).await, vec![]);
+
+    // Senders are flagged until they have written to the account once
+    let account_id = account_id.document_id();
+    let message = concat!(
+        "From: Stranger <Stranger@remote.org>\r\n",
+        "To: jdoe@example.com\r\n",
+        "Subject: Hello\r\n",
+        "\r\n",
+        "Hi there\r\n"
+    );
+    assert_eq!(
+        server
+            .first_contact_sender(account_id, message.as_bytes(), "bounces@remote.org")
+            .await
+            .unwrap()
+            .as_deref(),
+        Some("stranger@remote.org")
+    );
+    server
+        .add_known_sender(account_id, "stranger@remote.org")
+        .await
+        .unwrap();
+    assert_eq!(
+        server
+            .first_contact_sender(account_id, message.as_bytes(), "bounces@remote.org")
+            .await
+            .unwrap(),
+        None
+    );
+
+    // Addresses the user has written to are trusted, bounces are never flagged
+    for (message, envelope_from) in [
+        (
+            "From: jane.smith@example.org\r\n\r\nHi\r\n",
+            "jane.smith@example.org",
+        ),
+        ("Subject: Delivery failure\r\n\r\nBounce\r\n", ""),
+    ] {
+        assert_eq!(
+            server
+                .first_contact_sender(account_id, message.as_bytes(), envelope_from)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    // Existing first-contact headers are replaced
+    assert_eq!(
+        String::from_utf8(with_first_contact_header(
+            concat!(
+                "X-First-Contact: no\r\n",
+                "From: Stranger <stranger@remote.org>\r\n",
+                "x-first-contact : no,\r\n",
+                " really\r\n",
+                "Subject: Hello\r\n",
+                "\tworld\r\n",
+                "\r\n",
+                "X-First-Contact: no\r\n"
+            )
+            .as_bytes(),
+            true
+        ))
+        .unwrap(),
+        concat!(
+            "X-First-Contact: yes\r\n",
+            "From: Stranger <stranger@remote.org>\r\n",
+            "Subject: Hello\r\n",
+            "\tworld\r\n",
+            "\r\n",
+            "X-First-Contact: no\r\n"
+        )
+    );
+
+    // Concurrent submissions must not lose counter updates
+    futures::future::join_all(
+        (0..4).map(|_| {
+            server.collect_addresses(account_id, [("bill@example.net".to_string(), None)])
+        }),
+    )
+    .await
+    .into_iter()
+    .for_each(|result| result.unwrap());
+    assert_eq!(
+        server
+            .collected_addresses(account_id)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|(address, _)| address == "bill@example.net")
+            .unwrap()
+            .1
+            .times_used,
+        5
+    );
+
+    // Cleanup
+    server.store.assert_is_empty().await;
+}
+
+async fn autocomplete(account_id: &Id, text: Option<&str>) -> Vec<(String, Option<String>, u64)> {
+    let response = jmap_json_request(
+        r#"[[
+            "CollectedAddress/get",
+            {
+             "accountId": "$$",
+             "text": %%
+            },
+            "R1"
+           ]]"#
+        .replace("$$", &account_id.to_string())
+        .replace(
+            "%%",
+            &text.map_or_else(|| "null".to_string(), |text| format!("{text:?}")),
+        ),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await;
+    response
+        .pointer("/methodResponses/0/1/list")
+        .and_then(|v| v.as_array())
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+        .iter()
+        .map(|address| {
+            (
+                address["email"].as_str().unwrap().to_string(),
+                address["name"].as_str().map(|name| name.to_string()),
+                address["timesUsed"].as_u64().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>()
+}
@@ -0,0 +1,171 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{io::Write, sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose, Engine};
+use jmap::{api::compression::ContentEncoding, JMAP};
+use jmap_client::client::Client;
+use reqwest::{header, Method};
+use serde_json::Value;
+
+use crate::directory::sql::create_test_user_with_email;
+
+pub async fn test(server: Arc<JMAP>, _client: &mut Client) {
+    println!("Running HTTP compression tests...");
+
+    create_test_user_with_email(
+        server.directory.as_ref(),
+        "zip@example.com",
+        "zip_secret",
+        "Zip Zipper",
+    )
+    .await;
+
+    // Responses are compressed using the preferred encoding
+    for (accept, expected) in [
+        ("zstd", Some(ContentEncoding::Zstd)),
+        ("gzip, br;q=0.9", Some(ContentEncoding::Gzip)),
+        ("gzip;q=0.5, br", Some(ContentEncoding::Brotli)),
+        ("*", Some(ContentEncoding::Zstd)),
+        ("identity", None),
+        ("br;q=0", None),
+    ] {
+        let response = http_request(
+            Method::GET,
+            ".well-known/jmap",
+            &[(header::ACCEPT_ENCODING.as_str(), accept)],
+            None,
+        )
+        .await;
+        assert_eq!(response.status().as_u16(), 200, "{accept}");
+        let encoding = response
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|v| v.to_str().unwrap().to_string());
+        assert_eq!(
+            encoding.as_deref(),
+            expected.map(|e| e.as_str()),
+            "{accept}"
+        );
+        let body = response.bytes().await.unwrap();
+        let body = if let Some(expected) = expected {
+            expected.decompress(&body, 10_000_000).unwrap()
+        } else {
+            body.to_vec()
+        };
+        let session: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(session["username"], "zip@example.com", "{accept}");
+    }
+
+    // Compressed requests are accepted
+    let request = serde_json::json!({
+        "using": ["urn:ietf:params:jmap:core"],
+        "methodCalls": [["Core/echo", {"hello": "compressed world"}, "c1"]]
+    })
+    .to_string();
+    for encoding in [
+        ContentEncoding::Gzip,
+        ContentEncoding::Brotli,
+        ContentEncoding::Zstd,
+    ] {
+        let response = http_request(
+            Method::POST,
+            "jmap",
+            &[
+                (header::CONTENT_ENCODING.as_str(), encoding.as_str()),
+                (header::CONTENT_TYPE.as_str(), "application/json"),
+            ],
+            encoding.compress(request.as_bytes()).unwrap().into(),
+        )
+        .await;
+        assert_eq!(response.status().as_u16(), 200, "{encoding:?}");
+        let response: Value = response.json().await.unwrap();
+        assert_eq!(
+            response["methodResponses"][0][1]["hello"], "compressed world",
+            "{encoding:?}"
+        );
+    }
+
+    // Decompression bombs are rejected
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&vec![b' '; 20_000_000]).unwrap();
+    let bomb = encoder.finish().unwrap();
+    assert!(bomb.len() < 100_000);
+    for credentials in ["zip@example.com:zip_secret", "admin:secret"] {
+        let response = http_request_as(
+            credentials,
+            Method::POST,
+            "jmap",
+            &[(header::CONTENT_ENCODING.as_str(), "gzip")],
+            Some(bomb.clone()),
+        )
+        .await;
+        assert_eq!(response.status().as_u16(), 400, "{credentials}");
+    }
+
+    // Unsupported encodings are rejected
+    let response = http_request(
+        Method::POST,
+        "jmap",
+        &[(header::CONTENT_ENCODING.as_str(), "compress")],
+        request.into_bytes().into(),
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+async fn http_request(
+    method: Method,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Option<Vec<u8>>,
+) -> reqwest::Response {
+    http_request_as("zip@example.com:zip_secret", method, path, headers, body).await
+}
+
+async fn http_request_as(
+    credentials: &str,
+    method: Method,
+    path: &str,
+    headers: &[(&str, &str)],
+    body: Option<Vec<u8>>,
+) -> reqwest::Response {
+    let mut request = reqwest::Client::builder()
+        .timeout(Duration::from_millis(5000))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .request(method, format!("https://127.0.0.1:8899/{path}"))
+        .header(
+            header::AUTHORIZATION,
+            format!("Basic {}", general_purpose::STANDARD.encode(credentials)),
+        );
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    if let Some(body) = body {
+        request = request.body(body);
+    }
+    request.send().await.unwrap()
+}
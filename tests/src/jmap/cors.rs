@@ -0,0 +1,208 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+use hyper::header::{HeaderMap, HeaderValue};
+use jmap::{
+    api::{config::parse_trusted_proxies, cors::HttpCors},
+    auth::forwarded::forwarded_client_ip,
+    JMAP,
+};
+use jmap_client::client::Client;
+use reqwest::{header, Method};
+use smtp::config::IpAddrMask;
+use utils::config::{utils::ParseValue, Config};
+
+pub async fn test(_server: Arc<JMAP>, _client: &mut Client) {
+    println!("Running CORS and proxy tests...");
+
+    // Preflight requests are answered without authentication
+    let response = http_request(
+        Method::OPTIONS,
+        "jmap",
+        &[
+            ("Origin", "https://webmail.example.org"),
+            ("Access-Control-Request-Method", "POST"),
+        ],
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 204);
+    let headers = response.headers();
+    assert_eq!(
+        headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://webmail.example.org"
+    );
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+    assert_eq!(
+        headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+        "Authorization, Content-Type, Accept"
+    );
+    assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "86400");
+
+    // Regular responses carry the allowed origin
+    let response = http_request(
+        Method::GET,
+        ".well-known/oauth-authorization-server",
+        &[("Origin", "https://webmail.example.org")],
+    )
+    .await;
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "https://webmail.example.org"
+    );
+    assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_METHODS));
+
+    // Unknown origins and non-JMAP paths do not get CORS headers
+    let response = http_request(
+        Method::OPTIONS,
+        "jmap",
+        &[
+            ("Origin", "https://evil.example.com"),
+            ("Access-Control-Request-Method", "POST"),
+        ],
+    )
+    .await;
+    assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    let response = http_request(
+        Method::GET,
+        "admin",
+        &[("Origin", "https://webmail.example.org")],
+    )
+    .await;
+    assert!(!response
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+    // Wildcard origins cannot be combined with credentials
+    for (origins, allow_credentials, is_valid) in [
+        ("[\"*\"]", true, false),
+        ("[\"*\"]", false, true),
+        ("[\"https://webmail.example.org\"]", true, true),
+    ] {
+        let config = Config::new(&format!(
+            "[jmap.http.cors]\nallowed-origins = {origins}\nallow-credentials = {allow_credentials}\n"
+        ))
+        .unwrap();
+        assert_eq!(
+            HttpCors::parse(&config).is_ok(),
+            is_valid,
+            "{origins} {allow_credentials}"
+        );
+    }
+    let config = Config::new("[jmap.http.cors]\nallowed-origins = [\"*\"]\n").unwrap();
+    assert_eq!(
+        HttpCors::parse(&config)
+            .unwrap()
+            .unwrap()
+            .allow_origin("https://evil.example.com")
+            .unwrap(),
+        "*"
+    );
+
+    // The deprecated use-forwarded setting trusts all peers until proxies are listed
+    let any_peer: IpAddr = "192.0.2.1".parse().unwrap();
+    for (config, is_trusted) in [
+        ("[jmap.rate-limit]\nuse-forwarded = true\n", true),
+        (
+            "[jmap.rate-limit]\nuse-forwarded = true\n[jmap.http]\ntrusted-proxies = [\"10.0.0.1\"]\n",
+            false,
+        ),
+        ("[jmap.rate-limit]\nuse-forwarded = false\n", false),
+    ] {
+        let trusted = parse_trusted_proxies(&Config::new(config).unwrap()).unwrap();
+        assert_eq!(
+            trusted.iter().any(|mask| mask.matches(&any_peer)),
+            is_trusted,
+            "{config}"
+        );
+    }
+
+    // Forwarded headers are only honored from trusted proxies
+    let trusted = ["10.0.0.0/8", "2001:db8::1"]
+        .into_iter()
+        .map(|mask| IpAddrMask::parse_value("trusted-proxies", mask).unwrap())
+        .collect::<Vec<_>>();
+    let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+    let untrusted: IpAddr = "192.0.2.1".parse().unwrap();
+    for (headers, peer, expected) in [
+        (
+            vec![("x-forwarded-for", "203.0.113.7")],
+            proxy,
+            "203.0.113.7",
+        ),
+        (
+            vec![("x-forwarded-for", "203.0.113.7")],
+            untrusted,
+            "192.0.2.1",
+        ),
+        (
+            vec![("x-forwarded-for", "198.51.100.1, 203.0.113.7, 10.0.0.2")],
+            proxy,
+            "203.0.113.7",
+        ),
+        (
+            vec![(
+                "forwarded",
+                "for=198.51.100.1;proto=https, for=\"[2001:db8::1]:4711\"",
+            )],
+            proxy,
+            "198.51.100.1",
+        ),
+        (
+            vec![("forwarded", "for=\"203.0.113.7:8080\";by=10.0.0.1")],
+            proxy,
+            "203.0.113.7",
+        ),
+        (vec![("forwarded", "for=unknown")], proxy, "10.0.0.1"),
+        (vec![], proxy, "10.0.0.1"),
+    ] {
+        let mut header_map = HeaderMap::new();
+        for (name, value) in &headers {
+            header_map.append(*name, HeaderValue::from_static(*value));
+        }
+        assert_eq!(
+            forwarded_client_ip(&header_map, peer, &trusted),
+            expected.parse::<IpAddr>().unwrap(),
+            "{headers:?}"
+        );
+    }
+}
+
+async fn http_request(method: Method, path: &str, headers: &[(&str, &str)]) -> reqwest::Response {
+    let mut request = reqwest::Client::builder()
+        .timeout(Duration::from_millis(1000))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .request(method, format!("https://127.0.0.1:8899/{path}"));
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.send().await.unwrap()
+}
@@ -0,0 +1,233 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::{services::dedup::delivery_fingerprint, JMAP};
+use jmap_client::client::Client;
+use jmap_proto::types::{collection::Collection, id::Id};
+use reqwest::Method;
+use serde_json::json;
+use smtp::tracking::{TrackingEventType, TrackingFilter};
+
+use crate::{
+    directory::sql::{create_test_user_with_email, link_test_address},
+    jmap::{delivery::SmtpConnection, mailbox::destroy_all_mailboxes, settings::settings_request},
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running delivery deduplication tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "dedup@example.com", "dedup123", "Dedup Doe").await;
+    link_test_address(
+        directory,
+        "dedup@example.com",
+        "dedup.alias@example.com",
+        "alias",
+    )
+    .await;
+    let account_id = server.get_account_id("dedup@example.com").await.unwrap();
+    let login = "dedup@example.com";
+
+    // Deduplication is disabled by default
+    let (code, response) = settings_request(Method::GET, "", login, "dedup123", None).await;
+    assert_eq!(code, 200, "{response}");
+    assert_eq!(
+        response["deduplication"],
+        json!({"enabled": false, "window": null}),
+        "{response}"
+    );
+
+    // Invalid windows are rejected
+    for window in [0, 86400 * 30] {
+        let (code, response) = settings_request(
+            Method::PUT,
+            "deduplication",
+            login,
+            "dedup123",
+            json!({"enabled": true, "window": window}).into(),
+        )
+        .await;
+        assert_eq!(code, 400, "{response}");
+    }
+
+    // Enable deduplication
+    let (code, response) = settings_request(
+        Method::PUT,
+        "deduplication",
+        login,
+        "dedup123",
+        json!({"enabled": true, "window": 600}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    let (_, response) = settings_request(Method::GET, "", login, "dedup123", None).await;
+    assert_eq!(
+        response["deduplication"],
+        json!({"enabled": true, "window": 600}),
+        "{response}"
+    );
+
+    // The same message arriving through an alias is suppressed
+    let message = concat!(
+        "From: john@remote.org\r\n",
+        "To: dedup@example.com\r\n",
+        "Cc: dedup.alias@example.com\r\n",
+        "Message-ID: <dedup-1@remote.org>\r\n",
+        "Subject: Hello\r\n",
+        "\r\n",
+        "Test message.\r\n"
+    );
+    let fingerprint = delivery_fingerprint(message.as_bytes(), "list@remote.org").unwrap();
+    let mut lmtp = SmtpConnection::connect().await;
+    for rcpt in ["dedup@example.com", "dedup.alias@example.com"] {
+        lmtp.ingest("list@remote.org", &[rcpt], message).await;
+    }
+    assert_eq!(
+        server
+            .get_document_ids(account_id, Collection::Email)
+            .await
+            .unwrap()
+            .unwrap_or_default()
+            .len(),
+        1
+    );
+    assert!(server
+        .is_duplicate_delivery(account_id, &fingerprint)
+        .await
+        .unwrap());
+
+    // Suppressed copies are recorded in the tracking log
+    let events = tracked_events(&server, "dedup-1@remote.org").await;
+    assert_eq!(
+        events,
+        vec![
+            (TrackingEventType::Filed, "dedup@example.com".to_string()),
+            (
+                TrackingEventType::Suppressed,
+                "dedup.alias@example.com".to_string()
+            )
+        ]
+    );
+
+    // Reusing the Message-ID with a different body or sender does not suppress delivery
+    lmtp.ingest(
+        "list@remote.org",
+        &["dedup.alias@example.com"],
+        concat!(
+            "From: john@remote.org\r\n",
+            "To: dedup.alias@example.com\r\n",
+            "Message-ID: <dedup-1@remote.org>\r\n",
+            "Subject: Hello\r\n",
+            "\r\n",
+            "Another message.\r\n"
+        ),
+    )
+    .await;
+    lmtp.ingest("mallory@evil.org", &["dedup.alias@example.com"], message)
+        .await;
+    assert_eq!(
+        server
+            .get_document_ids(account_id, Collection::Email)
+            .await
+            .unwrap()
+            .unwrap_or_default()
+            .len(),
+        3
+    );
+
+    // Reservations are atomic, only the first concurrent attempt succeeds
+    let expires = store::write::now() + 600;
+    let (first, second) = tokio::join!(
+        server.reserve_delivery(account_id, "concurrent", expires),
+        server.reserve_delivery(account_id, "concurrent", expires)
+    );
+    let mut results = [first.unwrap(), second.unwrap()];
+    results.sort();
+    assert_eq!(results, [None, Some(expires)]);
+    server
+        .record_delivery(account_id, "concurrent", 0)
+        .await
+        .unwrap();
+
+    // Disable deduplication
+    let (code, response) = settings_request(
+        Method::PUT,
+        "deduplication",
+        login,
+        "dedup123",
+        json!({"enabled": false}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    lmtp.ingest("list@remote.org", &["dedup.alias@example.com"], message)
+        .await;
+    lmtp.quit().await;
+    assert_eq!(
+        tracked_events(&server, "dedup-1@remote.org").await.last(),
+        Some(&(
+            TrackingEventType::Filed,
+            "dedup.alias@example.com".to_string()
+        ))
+    );
+
+    // Expired entries are purged
+    for (_, fingerprint, _) in server.delivered_messages(account_id.into()).await.unwrap() {
+        server
+            .record_delivery(account_id, &fingerprint, 0)
+            .await
+            .unwrap();
+    }
+    server.purge_delivered_messages().await.unwrap();
+    assert!(server
+        .delivered_messages(account_id.into())
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Empty store
+    admin_client.set_default_account_id(Id::from(account_id).to_string());
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
+
+async fn tracked_events(server: &JMAP, message_id: &str) -> Vec<(TrackingEventType, String)> {
+    server
+        .smtp
+        .core()
+        .tracking
+        .search(&TrackingFilter {
+            message_id: message_id.to_string().into(),
+            ..Default::default()
+        })
+        .await
+        .into_iter()
+        .filter(|event| {
+            matches!(
+                event.typ,
+                TrackingEventType::Filed | TrackingEventType::Suppressed
+            )
+        })
+        .map(|event| (event.typ, event.to.into_iter().next().unwrap_or_default()))
+        .collect()
+}
@@ -0,0 +1,250 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::{
+    mailbox::INBOX_ID,
+    mailing_list::digest::{is_newsletter, DIGEST_ROLE},
+    JMAP,
+};
+use jmap_client::client::Client;
+use jmap_proto::types::{collection::Collection, id::Id, property::Property};
+use reqwest::Method;
+use serde_json::{json, Value};
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{
+        delivery::SmtpConnection, jmap_json_request, mailbox::destroy_all_mailboxes,
+        mailing_list::deliver_authenticated, settings::settings_request,
+    },
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running newsletter digest tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "jdoe@example.com", "12345", "John Doe").await;
+    let account_id = server.get_account_id("jdoe@example.com").await.unwrap();
+    let account_id_str = Id::from(account_id).to_string();
+    let login = "jdoe@example.com";
+
+    // Classify newsletters
+    for (message, expected) in [
+        ("List-Id: <news.example.org>\r\n\r\nHi\r\n", true),
+        (
+            "List-Unsubscribe: <mailto:leave@example.org>\r\n\r\nHi\r\n",
+            true,
+        ),
+        ("Precedence: Bulk\r\n\r\nHi\r\n", true),
+        ("Precedence: first-class\r\n\r\nHi\r\n", false),
+        ("Subject: Hi\r\n\r\nHi\r\n", false),
+    ] {
+        assert_eq!(is_newsletter(message.as_bytes()), expected, "{message}");
+    }
+
+    // Register a list and exclude it from the digest
+    deliver_authenticated(
+        &server,
+        concat!(
+            "From: team@excluded.example.org\r\n",
+            "To: jdoe@example.com\r\n",
+            "List-Id: <excluded.example.org>\r\n",
+            "Subject: Excluded list\r\n",
+            "\r\n",
+            "Test message.\r\n"
+        ),
+    )
+    .await;
+    let response = jmap_request(json!([[
+        "MailingList/set",
+        {
+            "accountId": account_id_str,
+            "update": {"excluded.example.org": {"digest": false}}
+        },
+        "R1"
+    ]]))
+    .await;
+    assert_eq!(
+        response.pointer("/methodResponses/0/1/updated"),
+        Some(&json!(["excluded.example.org"])),
+        "{response}"
+    );
+
+    // Enable digest mode
+    let (code, response) = settings_request(
+        Method::PUT,
+        "digest",
+        login,
+        "12345",
+        json!({"enabled": true}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    let (_, response) = settings_request(Method::GET, "", login, "12345", None).await;
+    assert_eq!(response["digest"]["enabled"], true, "{response}");
+
+    // Newsletters are held, other messages are delivered to the Inbox
+    let mut lmtp = SmtpConnection::connect().await;
+    for (from, headers, subject) in [
+        (
+            "news@news.example.org",
+            concat!(
+                "List-Id: Weekly News <news.example.org>\r\n",
+                "List-Unsubscribe: <https://news.example.org/unsub>\r\n"
+            ),
+            "Weekly news",
+        ),
+        (
+            "offers@shop.example.org",
+            "Precedence: bulk\r\n",
+            "Big sale",
+        ),
+        (
+            "team@excluded.example.org",
+            "List-Id: <excluded.example.org>\r\n",
+            "Excluded list again",
+        ),
+        ("bill@remote.org", "", "Personal message"),
+    ] {
+        lmtp.ingest(
+            from,
+            &["jdoe@example.com"],
+            &format!(
+                concat!(
+                    "From: {}\r\n",
+                    "To: jdoe@example.com\r\n",
+                    "{}",
+                    "Subject: {}\r\n",
+                    "\r\n",
+                    "Content of {}.\r\n"
+                ),
+                from, headers, subject, subject
+            ),
+        )
+        .await;
+    }
+    lmtp.quit().await;
+    let digest_id = server
+        .mailbox_get_by_role(account_id, DIGEST_ROLE)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(mailbox_count(&server, account_id, digest_id).await, 2);
+    assert_eq!(mailbox_count(&server, account_id, INBOX_ID).await, 3);
+    assert_eq!(server.digest_items(account_id).await.unwrap().len(), 2);
+
+    // Send the digest
+    server.send_digests().await.unwrap();
+    assert_eq!(mailbox_count(&server, account_id, INBOX_ID).await, 4);
+    assert_eq!(mailbox_count(&server, account_id, digest_id).await, 2);
+    assert!(server.digest_items(account_id).await.unwrap().is_empty());
+    let response = jmap_request(json!([
+        [
+            "Email/query",
+            {
+                "accountId": account_id_str,
+                "filter": {"subject": "Your daily digest"}
+            },
+            "R1"
+        ],
+        [
+            "Email/get",
+            {
+                "accountId": account_id_str,
+                "#ids": {"resultOf": "R1", "name": "Email/query", "path": "/ids"},
+                "properties": ["subject", "bodyValues", "textBody"],
+                "fetchTextBodyValues": true
+            },
+            "R2"
+        ]
+    ]))
+    .await;
+    let email = response
+        .pointer("/methodResponses/1/1/list/0")
+        .unwrap_or_else(|| panic!("Response: {response:?}"));
+    assert_eq!(
+        email["subject"], "Your daily digest: 2 new messages",
+        "{response}"
+    );
+    let body = email["bodyValues"]
+        .as_object()
+        .and_then(|values| values.values().next())
+        .and_then(|value| value["value"].as_str())
+        .unwrap_or_else(|| panic!("Response: {response:?}"));
+    for needle in [
+        "Weekly News <news.example.org>",
+        "* Weekly news",
+        "From: news@news.example.org",
+        "Content of Weekly news.",
+        "Unsubscribe: https://news.example.org/unsub",
+        "Other newsletters",
+        "* Big sale",
+    ] {
+        assert!(body.contains(needle), "{needle:?} not found in {body}");
+    }
+    assert!(!body.contains("Excluded"), "{body}");
+
+    // Nothing is sent when there are no held messages
+    server.send_digests().await.unwrap();
+    assert_eq!(mailbox_count(&server, account_id, INBOX_ID).await, 4);
+
+    // Empty store
+    let (code, response) = settings_request(
+        Method::PUT,
+        "digest",
+        login,
+        "12345",
+        json!({"enabled": false}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    assert!(!server.digest_enabled(account_id).await.unwrap());
+    for list_id in ["news.example.org", "excluded.example.org"] {
+        server
+            .set_mailing_list(account_id, list_id, None)
+            .await
+            .unwrap();
+    }
+    admin_client.set_default_account_id(Id::from(account_id).to_string());
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
+
+async fn mailbox_count(server: &JMAP, account_id: u32, mailbox_id: u32) -> u64 {
+    server
+        .get_tag(
+            account_id,
+            Collection::Email,
+            Property::MailboxIds,
+            mailbox_id,
+        )
+        .await
+        .unwrap()
+        .unwrap_or_default()
+        .len()
+}
+
+async fn jmap_request(body: Value) -> Value {
+    jmap_json_request(body.to_string(), "jdoe@example.com", "12345").await
+}
@@ -0,0 +1,505 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose, Engine};
+use jmap::{auth::authenticate::AccountKey, settings::policy::VACATION_REPLY_INTERVAL, JMAP};
+use jmap_client::client::Client;
+use jmap_proto::types::{collection::Collection, id::Id};
+use reqwest::{header, Method};
+use serde_json::{json, Value};
+use smtp::inbound::footer::append_footer;
+use store::{
+    write::{now, BatchBuilder, Operation, ValueClass},
+    CustomValueKey, Serialize,
+};
+use tokio::sync::oneshot;
+use utils::ipc::DeliveryEvent;
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{
+        delivery::SmtpConnection,
+        email_submission::{
+            assert_message_delivery, expect_nothing, spawn_mock_smtp_server, MockMessage,
+        },
+        mailbox::destroy_all_mailboxes,
+        settings::settings_request,
+    },
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running domain policy tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "paul@example.com", "pau123", "Paul Doe").await;
+    let account_id = server.get_account_id("paul@example.com").await.unwrap();
+
+    // Start mock SMTP server
+    let (mut smtp_rx, smtp_settings) = spawn_mock_smtp_server();
+    server.smtp.resolvers.dns.ipv4_add(
+        "localhost",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+
+    // Policies are empty by default and can only be managed by administrators
+    let (code, response) = admin_request(Method::GET, "policy/get/example.com", None).await;
+    assert_eq!(code, 200, "{response}");
+    assert_eq!(response["blockExternalForwarding"], false, "{response}");
+    assert_eq!(response["footer"], Value::Null, "{response}");
+    let (code, _) = settings_request(Method::GET, "", "paul@example.com", "pau123", None).await;
+    assert_eq!(code, 200);
+    assert_eq!(
+        policy_request_as("paul@example.com", "pau123", "policy/get/example.com").await,
+        401
+    );
+
+    // Invalid policies are rejected
+    let (code, response) = admin_request(
+        Method::POST,
+        "policy/set/example.com",
+        json!({"vacation": {"textBody": " "}}).into(),
+    )
+    .await;
+    assert_eq!(code, 400, "{response}");
+
+    // Forwarding outside the organization is blocked, a copy is still kept
+    let (code, response) = settings_request(
+        Method::PUT,
+        "forwarding",
+        "paul@example.com",
+        "pau123",
+        json!({"addresses": ["paul@remote.org"], "keepCopy": true}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    let (code, response) = admin_request(
+        Method::POST,
+        "policy/set/example.com",
+        json!({"blockExternalForwarding": true}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.ingest(
+        "bill@remote.org",
+        &["paul@example.com"],
+        concat!(
+            "From: bill@remote.org\r\n",
+            "To: paul@example.com\r\n",
+            "Subject: Quarterly results\r\n",
+            "\r\n",
+            "Please do not share.\r\n"
+        ),
+    )
+    .await;
+    expect_nothing(&mut smtp_rx).await;
+    assert_eq!(email_count(&server, account_id).await, 1);
+
+    // Forwarding resumes once the restriction is lifted
+    let (code, response) = admin_request(
+        Method::POST,
+        "policy/set/example.com",
+        json!({"blockExternalForwarding": false}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    lmtp.ingest(
+        "bill@remote.org",
+        &["paul@example.com"],
+        concat!(
+            "From: bill@remote.org\r\n",
+            "To: paul@example.com\r\n",
+            "Subject: Lunch\r\n",
+            "\r\n",
+            "Pizza?\r\n"
+        ),
+    )
+    .await;
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new("<paul@example.com>", ["<paul@remote.org>"], "@Pizza?"),
+    )
+    .await;
+    let (code, _) = settings_request(
+        Method::PUT,
+        "forwarding",
+        "paul@example.com",
+        "pau123",
+        json!({"addresses": []}).into(),
+    )
+    .await;
+    assert_eq!(code, 200);
+
+    // Scheduled responses are not sent outside their date range
+    let (code, response) = admin_request(
+        Method::POST,
+        "policy/set/example.com",
+        json!({"vacation": {
+            "textBody": "Our offices are closed until January.",
+            "fromDate": now() + 86400
+        }})
+        .into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    lmtp.ingest(
+        "jane@remote.org",
+        &["paul@example.com"],
+        &message("jane@remote.org", "Meeting", ""),
+    )
+    .await;
+    expect_nothing(&mut smtp_rx).await;
+
+    // Blanket out of office responses are sent once per sender
+    let (code, response) = admin_request(
+        Method::POST,
+        "policy/set/example.com",
+        json!({"vacation": {
+            "subject": "Office closed",
+            "textBody": "Our offices are closed until January."
+        }})
+        .into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    lmtp.ingest(
+        "jane@remote.org",
+        &["paul@example.com"],
+        &message("jane@remote.org", "Meeting", ""),
+    )
+    .await;
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new("<>", ["<jane@remote.org>"], "@Office closed"),
+    )
+    .await;
+    lmtp.ingest(
+        "jane@remote.org",
+        &["paul@example.com"],
+        &message("jane@remote.org", "Meeting", ""),
+    )
+    .await;
+    expect_nothing(&mut smtp_rx).await;
+
+    // Automatic messages are never replied to
+    lmtp.ingest(
+        "alerts@remote.org",
+        &["paul@example.com"],
+        &message(
+            "alerts@remote.org",
+            "Disk full",
+            "Auto-Submitted: auto-generated\r\n",
+        ),
+    )
+    .await;
+    expect_nothing(&mut smtp_rx).await;
+    smtp_settings.lock().do_stop = true;
+    lmtp.ingest(
+        "john@remote.org",
+        &["paul@example.com"],
+        &message("john@remote.org", "Hello", ""),
+    )
+    .await;
+    lmtp.quit().await;
+    assert_message_delivery(
+        &mut smtp_rx,
+        MockMessage::new("<>", ["<john@remote.org>"], "@closed until January"),
+    )
+    .await;
+
+    // Reply history is purged once the reply interval has elapsed
+    let recent_key = AccountKey::policy_vacation_reply(account_id, "john@remote.org");
+    let expired_key = AccountKey::policy_vacation_reply(account_id, "jane@remote.org");
+    let mut batch = BatchBuilder::new();
+    batch
+        .with_account_id(u32::MAX)
+        .with_collection(Collection::Principal)
+        .op(Operation::Value {
+            class: ValueClass::Custom {
+                bytes: expired_key.clone(),
+            },
+            set: (now() - VACATION_REPLY_INTERVAL - 1).serialize().into(),
+        });
+    server.store.write(batch.build()).await.unwrap();
+    server.purge_policy_vacation_replies().await.unwrap();
+    for (key, expect_exists) in [(recent_key, true), (expired_key, false)] {
+        assert_eq!(
+            server
+                .store
+                .get_value::<u64>(CustomValueKey { value: key })
+                .await
+                .unwrap()
+                .is_some(),
+            expect_exists
+        );
+    }
+
+    // Compliance footers are added to text and HTML bodies on submission
+    let (code, response) = admin_request(
+        Method::POST,
+        "policy/set/example.com",
+        json!({"footer": "  This message is confidential.\n"}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    let (_, response) = admin_request(Method::GET, "policy/get/example.com", None).await;
+    assert_eq!(response["footer"], "This message is confidential.");
+    let message = concat!(
+        "From: paul@example.com\r\n",
+        "To: jane@remote.org\r\n",
+        "Subject: Report\r\n",
+        "Content-Type: multipart/alternative; boundary=\"b1\"\r\n",
+        "\r\n",
+        "--b1\r\n",
+        "Content-Type: text/plain; charset=utf-8\r\n",
+        "\r\n",
+        "See attached.\r\n",
+        "--b1\r\n",
+        "Content-Type: text/html; charset=utf-8\r\n",
+        "Content-Transfer-Encoding: base64\r\n",
+        "\r\n",
+        "PGh0bWw+PGJvZHk+PHA+U2VlIGF0dGFjaGVkLjwvcD48L2JvZHk+PC9odG1sPg==\r\n",
+        "--b1--\r\n"
+    );
+    let result = String::from_utf8(
+        server
+            .policy_footer("Paul@Example.com", message.as_bytes())
+            .await
+            .unwrap()
+            .unwrap(),
+    )
+    .unwrap();
+    assert!(
+        result.contains("See attached.\r\n\r\nThis message is confidential.\r\n--b1\r\n"),
+        "{result}"
+    );
+    let html = result
+        .split("base64\r\n\r\n")
+        .nth(1)
+        .and_then(|part| part.split("--b1--").next())
+        .unwrap()
+        .replace("\r\n", "");
+    assert_eq!(
+        String::from_utf8(general_purpose::STANDARD.decode(html).unwrap()).unwrap(),
+        "<html><body><p>See attached.</p><div>This message is confidential.</div></body></html>"
+    );
+
+    // Senders from other domains are not affected
+    assert_eq!(
+        server
+            .policy_footer("jane@remote.org", message.as_bytes())
+            .await
+            .unwrap(),
+        None
+    );
+
+    // The SMTP server obtains the footer from the JMAP server
+    for (sender, expected) in [
+        ("paul@example.com", Some(result.as_bytes())),
+        ("jane@remote.org", None),
+    ] {
+        let (result_tx, result_rx) = oneshot::channel();
+        server
+            .smtp
+            .core()
+            .delivery_tx
+            .send(DeliveryEvent::ApplyPolicyFooter {
+                sender: sender.to_string(),
+                message: Arc::new(message.as_bytes().to_vec()),
+                result_tx,
+            })
+            .await
+            .unwrap();
+        assert_eq!(result_rx.await.unwrap().as_deref(), expected, "{sender}");
+    }
+
+    // Preview the message as it would be sent by the account
+    let (code, preview) = preview_request("paul@example.com", "pau123", message).await;
+    assert_eq!(code, 200, "{preview}");
+    assert!(
+        preview.contains("See attached.\r\n\r\nThis message is confidential.\r\n--b1\r\n"),
+        "{preview}"
+    );
+    // Only signatures are prepended, the body matches the submitted message
+    assert!(preview.ends_with(&result), "{preview}");
+    assert_eq!(
+        preview.matches("This message is confidential.").count(),
+        1,
+        "{preview}"
+    );
+    let (code, _) = preview_request(
+        "paul@example.com",
+        "pau123",
+        &message.replace("paul@example.com", "john@example.com"),
+    )
+    .await;
+    assert_eq!(code, 403);
+    let (code, _) = preview_request("paul@example.com", "pau123", "Subject: test\r\n\r\n").await;
+    assert_eq!(code, 400);
+
+    // Signed messages and quoted-printable bodies
+    assert_eq!(
+        append_footer(
+            concat!(
+                "Content-Type: multipart/signed; boundary=\"b1\"\r\n",
+                "\r\n",
+                "--b1\r\n",
+                "Content-Type: text/plain\r\n",
+                "\r\n",
+                "Signed.\r\n",
+                "--b1--\r\n"
+            )
+            .as_bytes(),
+            "Footer",
+            None
+        ),
+        None
+    );
+    assert_eq!(
+        String::from_utf8(
+            append_footer(
+                concat!(
+                    "Content-Type: text/plain; charset=utf-8\r\n",
+                    "Content-Transfer-Encoding: quoted-printable\r\n",
+                    "\r\n",
+                    "Caf=C3=A9\r\n"
+                )
+                .as_bytes(),
+                "Confidential = private",
+                None
+            )
+            .unwrap()
+        )
+        .unwrap(),
+        concat!(
+            "Content-Type: text/plain; charset=utf-8\r\n",
+            "Content-Transfer-Encoding: quoted-printable\r\n",
+            "\r\n",
+            "Caf=C3=A9\r\n",
+            "\r\n",
+            "Confidential =3D private\r\n"
+        )
+    );
+
+    // Remove the policy
+    let (code, response) = admin_request(Method::GET, "policy/delete/example.com", None).await;
+    assert_eq!(code, 200, "{response}");
+    let (_, response) = admin_request(Method::GET, "policy/get/example.com", None).await;
+    assert_eq!(response["footer"], Value::Null, "{response}");
+
+    // Empty store
+    admin_client.set_default_account_id(Id::from(account_id).to_string());
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
+
+fn message(from: &str, subject: &str, headers: &str) -> String {
+    format!(
+        concat!(
+            "From: {}\r\n",
+            "To: paul@example.com\r\n",
+            "{}",
+            "Subject: {}\r\n",
+            "\r\n",
+            "Test message.\r\n"
+        ),
+        from, headers, subject
+    )
+}
+
+async fn email_count(server: &JMAP, account_id: u32) -> usize {
+    server
+        .get_document_ids(account_id, Collection::Email)
+        .await
+        .unwrap()
+        .unwrap_or_default()
+        .len() as usize
+}
+
+async fn admin_request(method: Method, path: &str, body: Option<Value>) -> (u16, Value) {
+    let response = http_request(method, path, "admin", "secret", body).await;
+    (
+        response.status().as_u16(),
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap_or_default(),
+    )
+}
+
+async fn policy_request_as(login: &str, secret: &str, path: &str) -> u16 {
+    http_request(Method::GET, path, login, secret, None)
+        .await
+        .status()
+        .as_u16()
+}
+
+async fn http_request(
+    method: Method,
+    path: &str,
+    login: &str,
+    secret: &str,
+    body: Option<Value>,
+) -> reqwest::Response {
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        header::HeaderValue::from_str(&format!(
+            "Basic {}",
+            general_purpose::STANDARD.encode(format!("{login}:{secret}"))
+        ))
+        .unwrap(),
+    );
+
+    let mut request = reqwest::Client::builder()
+        .timeout(Duration::from_millis(5000))
+        .danger_accept_invalid_certs(true)
+        .default_headers(headers)
+        .build()
+        .unwrap_or_default()
+        .request(method, format!("https://127.0.0.1:8899/admin/{path}"));
+    if let Some(body) = body {
+        request = request.body(body.to_string());
+    }
+    request.send().await.unwrap()
+}
+
+async fn preview_request(login: &str, secret: &str, message: &str) -> (u16, String) {
+    let response = reqwest::Client::builder()
+        .timeout(Duration::from_millis(5000))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .post("https://127.0.0.1:8899/settings/preview")
+        .basic_auth(login, Some(secret))
+        .body(message.to_string())
+        .send()
+        .await
+        .unwrap();
+    (
+        response.status().as_u16(),
+        String::from_utf8(response.bytes().await.unwrap().to_vec()).unwrap(),
+    )
+}
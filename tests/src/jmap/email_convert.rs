@@ -0,0 +1,156 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::JMAP;
+use jmap_client::{client::Client, mailbox::Role};
+use jmap_proto::types::id::Id;
+use mail_parser::decoders::html::{html_to_text, text_to_html};
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{jmap_json_request, mailbox::destroy_all_mailboxes},
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running body conversion tests...");
+
+    // Create a test account
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "jdoe@example.com", "12345", "John Doe").await;
+    let account_id = Id::from(server.get_account_id("jdoe@example.com").await.unwrap());
+    admin_client.set_default_account_id(account_id.to_string());
+    let mailbox_id = admin_client
+        .mailbox_create("Conversion tests", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+
+    // Import an HTML-only and a text-only message
+    let mut email_ids = Vec::new();
+    for (content_type, body) in [
+        (
+            "text/html",
+            "<html><body><p>Hello &amp; welcome</p><blockquote>Earlier</blockquote></body></html>",
+        ),
+        ("text/plain", "Hello & welcome\r\n> Earlier"),
+    ] {
+        email_ids.push(
+            admin_client
+                .email_import(
+                    format!(
+                        concat!(
+                            "From: john@example.org\r\n",
+                            "To: jdoe@example.com\r\n",
+                            "Subject: Conversion\r\n",
+                            "Content-Type: {}; charset=utf-8\r\n",
+                            "\r\n",
+                            "{}\r\n"
+                        ),
+                        content_type, body
+                    )
+                    .into_bytes(),
+                    [&mailbox_id],
+                    None::<Vec<&str>>,
+                    None,
+                )
+                .await
+                .unwrap()
+                .take_id(),
+        );
+    }
+
+    // Without conversion, the original parts are returned
+    let response = email_get(&account_id, &email_ids, false).await;
+    for (email, expected) in [("0", "text/html"), ("1", "text/plain")] {
+        for body in ["textBody", "htmlBody"] {
+            assert_eq!(
+                response.pointer(&format!("/methodResponses/0/1/list/{email}/{body}/0/type")),
+                Some(&serde_json::Value::from(expected)),
+                "{response}"
+            );
+        }
+    }
+
+    // With conversion, synthetic parts are returned
+    let html = concat!(
+        "<html><body><p>Hello &amp; welcome</p>",
+        "<blockquote>Earlier</blockquote></body></html>\n"
+    );
+    let text = "Hello & welcome\n> Earlier\n";
+    let response = email_get(&account_id, &email_ids, true).await;
+    for (email, body, part_id, content_type, value) in [
+        ("0", "textBody", "1.text", "text/plain", html_to_text(html)),
+        ("0", "htmlBody", "1", "text/html", html.to_string()),
+        ("1", "textBody", "1", "text/plain", text.to_string()),
+        ("1", "htmlBody", "1.html", "text/html", text_to_html(text)),
+    ] {
+        let email = response
+            .pointer(&format!("/methodResponses/0/1/list/{email}"))
+            .unwrap();
+        let part = email.pointer(&format!("/{body}/0")).unwrap();
+        assert_eq!(part["partId"], part_id, "{email}");
+        assert_eq!(part["type"], content_type, "{email}");
+        if part_id.contains('.') {
+            assert_eq!(
+                part["size"].as_u64().unwrap() as usize,
+                value.len(),
+                "{email}"
+            );
+        }
+        assert_eq!(
+            email.pointer(&format!("/bodyValues/{part_id}/value")),
+            Some(&serde_json::Value::from(value)),
+            "{email}"
+        );
+    }
+
+    // Converted parts are cached
+    assert!(!server.converted_parts.is_empty());
+
+    // Empty store
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
+
+async fn email_get(account_id: &Id, email_ids: &[String], convert: bool) -> serde_json::Value {
+    jmap_json_request(
+        serde_json::json!([[
+            "Email/get",
+            {
+                "accountId": account_id.to_string(),
+                "ids": email_ids,
+                "properties": ["textBody", "htmlBody", "bodyValues"],
+                "fetchTextBodyValues": true,
+                "fetchHTMLBodyValues": true,
+                "convertBodyParts": convert
+            },
+            "0"
+        ]])
+        .to_string(),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await
+}
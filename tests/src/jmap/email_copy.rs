@@ -112,6 +112,101 @@ pub async fn test(server: Arc<JMAP>, client: &mut Client) {
         .unwrap()
         .is_none());
 
+    // Original keywords should be preserved unless overridden
+    let ac1_email_id = client
+        .email_import(
+            concat!(
+                "From: bill@example.com\r\n",
+                "To: jdoe@example.com\r\n",
+                "Subject: TPS Report (2nd notice)\r\n",
+                "\r\n",
+                "Did you get the memo?"
+            )
+            .as_bytes()
+            .to_vec(),
+            [&ac1_mailbox_id],
+            Some(["$seen"]),
+            None,
+        )
+        .await
+        .unwrap()
+        .take_id();
+    let mut request = client.build();
+    let copy_request = request.copy_email(Id::new(1).to_string());
+    copy_request
+        .create(&ac1_email_id)
+        .mailbox_id(&ac2_mailbox_id, true);
+    copy_request
+        .create(Id::from_parts(0, 1000).to_string())
+        .mailbox_id(&ac2_mailbox_id, true);
+    let mut response = request
+        .send()
+        .await
+        .unwrap()
+        .method_response_by_pos(0)
+        .unwrap_copy_email()
+        .unwrap();
+    let ac2_email_id = response.created(&ac1_email_id).unwrap().take_id();
+    assert!(response
+        .created(&Id::from_parts(0, 1000).to_string())
+        .is_err());
+    let email = client
+        .set_default_account_id(Id::new(2).to_string())
+        .email_get(&ac2_email_id, None::<Vec<_>>)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(email.keywords(), &["$seen"]);
+
+    // Keyword patches are applied on top of the original keywords
+    let mut request = client.build();
+    request
+        .copy_email(Id::new(1).to_string())
+        .create(&ac1_email_id)
+        .mailbox_id(&ac2_mailbox_id, true)
+        .keyword("$flagged", true);
+    let ac2_email_id = request
+        .send()
+        .await
+        .unwrap()
+        .method_response_by_pos(0)
+        .unwrap_copy_email()
+        .unwrap()
+        .created(&ac1_email_id)
+        .unwrap()
+        .take_id();
+    let email = client
+        .email_get(&ac2_email_id, None::<Vec<_>>)
+        .await
+        .unwrap()
+        .unwrap();
+    let mut keywords = email.keywords();
+    keywords.sort_unstable();
+    assert_eq!(keywords, ["$flagged", "$seen"]);
+
+    // Failed copies should not destroy the original message
+    let mut request = client.build();
+    request
+        .copy_email(Id::new(1).to_string())
+        .on_success_destroy_original(true)
+        .create(&ac1_email_id)
+        .mailbox_id(&Id::new(1000).to_string(), true);
+    assert!(request
+        .send()
+        .await
+        .unwrap()
+        .method_response_by_pos(0)
+        .unwrap_copy_email()
+        .unwrap()
+        .created(&ac1_email_id)
+        .is_err());
+    assert!(client
+        .set_default_account_id(Id::new(1).to_string())
+        .email_get(&ac1_email_id, None::<Vec<_>>)
+        .await
+        .unwrap()
+        .is_some());
+
     // Empty store
     destroy_all_mailboxes(client).await;
     client.set_default_account_id(Id::new(2).to_string());
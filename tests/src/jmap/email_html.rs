@@ -0,0 +1,332 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use ahash::AHashMap;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use jmap::{
+    email::html::{sanitize_html, sign_image_url, ImageProxy, TRACKING_PARAMETERS},
+    JMAP,
+};
+use jmap_client::{client::Client, mailbox::Role};
+use jmap_proto::types::{collection::Collection, id::Id};
+use reqwest::StatusCode;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use utils::ipc::DeliveryResult;
+
+use crate::{directory::sql::create_test_user_with_email, jmap::mailbox::destroy_all_mailboxes};
+
+const IMAGE: &[u8] = b"GIF89a\x01\x00\x01\x00\x80\x00\x00\xff\xff\xff\x00\x00\x00;";
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running HTML sanitization tests...");
+
+    // Sanitize HTML
+    let image_proxy = ImageProxy {
+        base_url: "https://mail.example.org".to_string(),
+        key: b"secret".to_vec(),
+        strip_parameters: TRACKING_PARAMETERS.iter().map(|p| p.to_string()).collect(),
+    };
+    let html = sanitize_html(
+        concat!(
+            "<html><head><style>body { color: red } p, .note { margin: 0; ",
+            "background: url(https://tracker.example.org/open.gif) } ",
+            "@import url(https://example.org/evil.css); ",
+            "@media (max-width: 600px) { html body p { position: fixed; font-size: 12px } }",
+            "a[title=\"x,}\"] { font-family: \"Segoe UI; }\" } ",
+            ".q { content: \"/*\"; color: green } ",
+            ".u { font-family: \"open\n; color: red }",
+            "</style><script>alert(1)</script></head>",
+            "<body><p onclick=\"steal()\" style=\"color: blue; background-image: url(x)\">Hi</p>",
+            "<form action=\"https://phish.example.org\"><input name=\"password\"></form>",
+            "<a href=\"javascript:alert(1)\">bad</a><a href=\"https://example.org\">good</a>",
+            "<img src=\"cid:logo@example.org\">",
+            "<img src=\"cid:missing@example.org\">",
+            "<img src=\"https://cdn.example.org/pic.png?id=5&utm_source=news&fbclid=abc\">",
+            "</body></html>"
+        ),
+        "msg",
+        AHashMap::from_iter([(
+            "logo@example.org".to_string(),
+            "https://mail.example.org/jmap/download/a/b/logo.png".to_string(),
+        )]),
+        image_proxy.clone().into(),
+    );
+    for needle in [
+        "<div class=\"msg\"><style>",
+        ".msg{color: red}",
+        ".msg p, .msg .note{margin: 0}",
+        "@media (max-width: 600px){.msg p{font-size: 12px}}",
+        ".msg a[title=\"x,}\"]{font-family: \"Segoe UI; }\"}",
+        ".msg .q{content: \"/*\"; color: green}",
+        ".msg .u{color: red}",
+        "<p style=\"color: blue\">Hi</p>",
+        "href=\"https://example.org\"",
+        "rel=\"noopener noreferrer\"",
+        "target=\"_blank\"",
+        "<img src=\"https://mail.example.org/jmap/download/a/b/logo.png\">",
+        &format!(
+            "<img src=\"https://mail.example.org/image-proxy/{}?url={}\">",
+            sign_image_url(b"secret", "https://cdn.example.org/pic.png?id=5"),
+            "https%3A%2F%2Fcdn.example.org%2Fpic.png%3Fid%3D5"
+        ),
+    ] {
+        assert!(html.contains(needle), "{needle:?} not found in {html}");
+    }
+    for needle in [
+        "script",
+        "alert",
+        "onclick",
+        "steal",
+        "<form",
+        "<input",
+        "tracker.example.org",
+        "evil.css",
+        "fixed",
+        "missing@example.org",
+        "utm_source",
+        "\"open",
+    ] {
+        assert!(!html.contains(needle), "{needle:?} found in {html}");
+    }
+
+    // Create a test account
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "jdoe@example.com", "12345", "John Doe").await;
+    let account_id = server.get_account_id("jdoe@example.com").await.unwrap();
+    admin_client.set_default_account_id(Id::from(account_id).to_string());
+    let mailbox_id = admin_client
+        .mailbox_create("HTML tests", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+    let message = format!(
+        concat!(
+            "From: news@example.org\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: Newsletter\r\n",
+            "MIME-Version: 1.0\r\n",
+            "Content-Type: multipart/related; boundary=\"XYZ\"\r\n",
+            "\r\n",
+            "--XYZ\r\n",
+            "Content-Type: text/html; charset=utf-8\r\n",
+            "\r\n",
+            "<html><body><p>News</p><img src=\"cid:logo@example.org\">",
+            "<img src=\"http://127.0.0.1:9944/pixel.gif?utm_campaign=open\">",
+            "<img src=\"http://127.0.0.1:9944/page.html\"><script>x()</script>",
+            "</body></html>\r\n",
+            "--XYZ\r\n",
+            "Content-Type: image/gif\r\n",
+            "Content-ID: <logo@example.org>\r\n",
+            "Content-Disposition: inline; filename=\"logo.gif\"\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "{}\r\n",
+            "--XYZ--\r\n"
+        ),
+        STANDARD.encode(IMAGE)
+    );
+    assert!(matches!(
+        server
+            .deliver_to_account(
+                message.as_bytes(),
+                "news@example.org",
+                "jdoe@example.com",
+                "jdoe@example.com",
+            )
+            .await,
+        DeliveryResult::Success
+    ));
+    let email_id = Id::from_parts(
+        0,
+        server
+            .get_document_ids(account_id, Collection::Email)
+            .await
+            .unwrap()
+            .unwrap()
+            .min()
+            .unwrap(),
+    );
+
+    // Fetch the sanitized HTML part
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(5000))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default();
+    let url = format!(
+        "https://127.0.0.1:8899/jmap/html/{}/{}/1?scope=news",
+        Id::from(account_id),
+        email_id
+    );
+    let response = client
+        .get(&url)
+        .basic_auth("jdoe@example.com", Some("12345"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = response.text().await.unwrap();
+    assert!(html.starts_with("<div class=\"news\">"), "{html}");
+    assert!(html.contains("<p>News</p>"), "{html}");
+    assert!(!html.contains("script"), "{html}");
+    assert!(!html.contains("utm_campaign"), "{html}");
+    let image_urls = html
+        .split("src=\"")
+        .skip(1)
+        .filter_map(|src| {
+            src.split_once('"')
+                .map(|(url, _)| url.replace("&amp;", "&"))
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(image_urls.len(), 3, "{html}");
+
+    // Non-HTML parts and unauthorized requests are rejected
+    for (url, login) in [
+        (url.replace("/1?", "/2?"), "jdoe@example.com"),
+        (url.clone(), "jane@example.com"),
+    ] {
+        let response = client
+            .get(&url)
+            .basic_auth(login, Some("12345"))
+            .send()
+            .await
+            .unwrap();
+        assert_ne!(response.status(), StatusCode::OK, "{url}");
+    }
+
+    // Download the inline image
+    assert!(
+        image_urls[0].starts_with("https://127.0.0.1:8899/jmap/download/"),
+        "{image_urls:?}"
+    );
+    let response = client
+        .get(&image_urls[0])
+        .basic_auth("jdoe@example.com", Some("12345"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.bytes().await.unwrap().as_ref(), IMAGE);
+
+    // Fetch remote images through the proxy
+    spawn_mock_http_server().await;
+    assert!(
+        image_urls[1].starts_with("https://127.0.0.1:8899/image-proxy/"),
+        "{image_urls:?}"
+    );
+    let response = client.get(&image_urls[1]).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("content-type").unwrap(), "image/gif");
+    assert_eq!(response.bytes().await.unwrap().as_ref(), IMAGE);
+
+    // Only images are proxied
+    let response = client.get(&image_urls[2]).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Tampered and private URLs are rejected
+    let key = server.config.html_image_proxy_key.as_bytes();
+    for url in [
+        image_urls[1].replace("pixel.gif", "other.gif"),
+        format!(
+            "https://127.0.0.1:8899/image-proxy/{}?url=http%3A%2F%2F10.0.0.1%2Fpixel.gif",
+            sign_image_url(key, "http://10.0.0.1/pixel.gif")
+        ),
+        format!(
+            "https://127.0.0.1:8899/image-proxy/{}?url=file%3A%2F%2F%2Fetc%2Fpasswd",
+            sign_image_url(key, "file:///etc/passwd")
+        ),
+    ] {
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{url}");
+    }
+
+    // Remote images of imported messages are not proxied
+    let email_id = admin_client
+        .email_import(message.into_bytes(), [&mailbox_id], None::<Vec<&str>>, None)
+        .await
+        .unwrap()
+        .take_id();
+    let html = client
+        .get(format!(
+            "https://127.0.0.1:8899/jmap/html/{}/{}/1",
+            Id::from(account_id),
+            email_id
+        ))
+        .basic_auth("jdoe@example.com", Some("12345"))
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        html.contains("src=\"http://127.0.0.1:9944/pixel.gif?utm_campaign=open\""),
+        "{html}"
+    );
+    assert!(!html.contains("/image-proxy/"), "{html}");
+
+    // Empty store
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
+
+async fn spawn_mock_http_server() {
+    let listener = TcpListener::bind("127.0.0.1:9944")
+        .await
+        .unwrap_or_else(|e| {
+            panic!("Failed to bind mock HTTP server to 127.0.0.1:9944: {}", e);
+        });
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut buf = vec![0u8; 1024];
+            let len = stream.read(&mut buf).await.unwrap_or_default();
+            let request = String::from_utf8_lossy(&buf[..len]);
+            let (content_type, body) = if request.starts_with("GET /pixel.gif ") {
+                ("image/gif", IMAGE)
+            } else {
+                ("text/html", &b"<html></html>"[..])
+            };
+            let _ = stream
+                .write_all(
+                    format!(
+                        concat!(
+                            "HTTP/1.1 200 OK\r\n",
+                            "Content-Type: {}\r\n",
+                            "Content-Length: {}\r\n",
+                            "Connection: close\r\n\r\n"
+                        ),
+                        content_type,
+                        body.len()
+                    )
+                    .as_bytes(),
+                )
+                .await;
+            let _ = stream.write_all(body).await;
+        }
+    });
+}
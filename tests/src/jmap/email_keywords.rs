@@ -0,0 +1,289 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::{mailbox::INBOX_ID, JMAP};
+use jmap_client::client::Client;
+use jmap_proto::types::id::Id;
+use serde_json::Value;
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{jmap_json_request, mailbox::destroy_all_mailboxes},
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running Keyword registry tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "jdoe@example.com", "12345", "John Doe").await;
+    let account_id = Id::from(server.get_account_id("jdoe@example.com").await.unwrap());
+    let inbox_id = Id::from(INBOX_ID).to_string();
+
+    // Create messages with custom keywords
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Email/set",
+            {
+             "accountId": "$$",
+             "create": {
+              "m1": {
+               "mailboxIds": { "%%": true },
+               "keywords": { "work": true, "$seen": true },
+               "subject": "Quarterly report"
+              },
+              "m2": {
+               "mailboxIds": { "%%": true },
+               "keywords": { "work": true },
+               "subject": "Meeting notes"
+              },
+              "m3": {
+               "mailboxIds": { "%%": true },
+               "keywords": { "personal": true },
+               "subject": "Dinner plans"
+              }
+             }
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &inbox_id),
+    )
+    .await;
+    let email_id = response
+        .pointer("/methodResponses/0/1/created/m3/id")
+        .and_then(|v| v.as_str())
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+        .to_string();
+
+    // The registry should list both keywords with their counts
+    assert_eq!(
+        keyword_counts(&account_id).await,
+        vec![("personal".to_string(), 1, 1), ("work".to_string(), 2, 1)]
+    );
+
+    // Adding a keyword to an existing message updates the counts
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Email/set",
+            {
+             "accountId": "$$",
+             "update": {
+              "%%": {
+               "keywords/work": true
+              }
+             }
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &email_id),
+    )
+    .await;
+    assert!(
+        response
+            .pointer(&format!("/methodResponses/0/1/updated/{email_id}"))
+            .is_some(),
+        "Response: {response:?}"
+    );
+    assert_eq!(
+        keyword_counts(&account_id).await,
+        vec![("personal".to_string(), 1, 1), ("work".to_string(), 3, 2)]
+    );
+
+    // Rename a keyword across all messages
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Keyword/rename",
+            {
+             "accountId": "$$",
+             "rename": {
+              "work": "projects",
+              "$seen": "read",
+              "missing": "other"
+             }
+            },
+            "R1"
+           ]]"#,
+    )
+    .await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/renamed/work")
+            .and_then(|v| v.as_u64()),
+        Some(3),
+        "Response: {response:?}"
+    );
+    for (keyword, error) in [("$seen", "invalidProperties"), ("missing", "notFound")] {
+        assert_eq!(
+            response
+                .pointer(&format!("/methodResponses/0/1/notRenamed/{keyword}/type"))
+                .and_then(|v| v.as_str()),
+            Some(error),
+            "Response: {response:?}"
+        );
+    }
+    assert_ne!(
+        response.pointer("/methodResponses/0/1/oldState"),
+        response.pointer("/methodResponses/0/1/newState"),
+        "Response: {response:?}"
+    );
+
+    // The old keyword should no longer be reported
+    assert_eq!(
+        keyword_counts(&account_id).await,
+        vec![
+            ("personal".to_string(), 1, 1),
+            ("projects".to_string(), 3, 2)
+        ]
+    );
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Keyword/get",
+            {
+             "accountId": "$$",
+             "ids": ["projects", "work"]
+            },
+            "R1"
+           ]]"#,
+    )
+    .await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/notFound/0")
+            .and_then(|v| v.as_str()),
+        Some("work"),
+        "Response: {response:?}"
+    );
+
+    // Messages should be searchable by the new keyword
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Email/query",
+            {
+             "accountId": "$$",
+             "filter": { "hasKeyword": "projects" }
+            },
+            "R1"
+           ]]"#,
+    )
+    .await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/ids")
+            .and_then(|v| v.as_array())
+            .map(|ids| ids.len()),
+        Some(3),
+        "Response: {response:?}"
+    );
+
+    // Removing a keyword from its last message removes it from the registry
+    assert_eq!(
+        server
+            .keyword_registry(account_id.document_id())
+            .await
+            .unwrap(),
+        vec!["personal".to_string(), "projects".to_string()]
+    );
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Email/set",
+            {
+             "accountId": "$$",
+             "update": {
+              "%%": {
+               "keywords/personal": null
+              }
+             }
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &email_id),
+    )
+    .await;
+    assert!(
+        response
+            .pointer(&format!("/methodResponses/0/1/updated/{email_id}"))
+            .is_some(),
+        "Response: {response:?}"
+    );
+    assert_eq!(
+        server
+            .keyword_registry(account_id.document_id())
+            .await
+            .unwrap(),
+        vec!["projects".to_string()]
+    );
+
+    // Cleanup, deleting the remaining messages empties the registry
+    admin_client.set_default_account_id(account_id.to_string());
+    destroy_all_mailboxes(admin_client).await;
+    assert_eq!(
+        server
+            .keyword_registry(account_id.document_id())
+            .await
+            .unwrap(),
+        Vec::<String>::new()
+    );
+    server.store.assert_is_empty().await;
+}
+
+async fn keyword_counts(account_id: &Id) -> Vec<(String, u64, u64)> {
+    let response = jmap_request(
+        account_id,
+        r#"[[
+            "Keyword/get",
+            {
+             "accountId": "$$"
+            },
+            "R1"
+           ]]"#,
+    )
+    .await;
+    response
+        .pointer("/methodResponses/0/1/list")
+        .and_then(|v| v.as_array())
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+        .iter()
+        .map(|keyword| {
+            (
+                keyword["id"].as_str().unwrap().to_string(),
+                keyword["totalEmails"].as_u64().unwrap(),
+                keyword["unreadEmails"].as_u64().unwrap(),
+            )
+        })
+        .collect()
+}
+
+async fn jmap_request(account_id: &Id, body: impl AsRef<str>) -> Value {
+    jmap_json_request(
+        body.as_ref().replace("$$", &account_id.to_string()),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await
+}
@@ -0,0 +1,328 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Instant};
+
+use jmap::{mdn::field_value, JMAP};
+use jmap_client::client::Client;
+use jmap_proto::types::id::Id;
+use serde_json::Value;
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{
+        delivery::SmtpConnection,
+        email_submission::{expect_message_delivery, expect_nothing, spawn_mock_smtp_server},
+        jmap_json_request,
+        mailbox::destroy_all_mailboxes,
+    },
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running MDN tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "jdoe@example.com", "12345", "John Doe").await;
+    let account_id = Id::from(server.get_account_id("jdoe@example.com").await.unwrap());
+
+    // Start mock SMTP server
+    let (mut smtp_rx, smtp_settings) = spawn_mock_smtp_server();
+    server.smtp.resolvers.dns.ipv4_add(
+        "localhost",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + std::time::Duration::from_secs(10),
+    );
+
+    // Create an identity
+    let identity_id = jmap_request(
+        &account_id,
+        r#"[[
+            "Identity/set",
+            {
+             "accountId": "$$",
+             "create": {
+              "i1": { "name": "John Doe", "email": "jdoe@example.com" }
+             }
+            },
+            "R1"
+           ]]"#,
+    )
+    .await
+    .pointer("/methodResponses/0/1/created/i1/id")
+    .and_then(|v| v.as_str())
+    .unwrap()
+    .to_string();
+
+    // Deliver a message requesting a read receipt, no MDN should be sent automatically
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.ingest(
+        "bill@remote.org",
+        &["jdoe@example.com"],
+        concat!(
+            "From: bill@remote.org\r\n",
+            "To: jdoe@example.com\r\n",
+            "Subject: TPS Report\r\n",
+            "Message-ID: <tps-report@remote.org>\r\n",
+            "Disposition-Notification-To: bill@remote.org\r\n",
+            "\r\n",
+            "Did you get the memo?\r\n"
+        ),
+    )
+    .await;
+    expect_nothing(&mut smtp_rx).await;
+    let email_id = query_email(&account_id, "TPS Report").await;
+
+    // Send a read receipt and mark the message as $mdnsent
+    let response = mdn_send(&account_id, &identity_id, &email_id).await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/sent/k1/finalRecipient")
+            .and_then(|v| v.as_str()),
+        Some("rfc822; jdoe@example.com"),
+        "Response: {response:?}"
+    );
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/sent/k1/originalMessageId")
+            .and_then(|v| v.as_str()),
+        Some("<tps-report@remote.org>"),
+        "Response: {response:?}"
+    );
+    assert_eq!(
+        response
+            .pointer("/methodResponses/1/0")
+            .and_then(|v| v.as_str()),
+        Some("Email/set"),
+        "Response: {response:?}"
+    );
+    let message = expect_message_delivery(&mut smtp_rx).await;
+    assert_eq!(message.mail_from, "<>");
+    assert_eq!(message.rcpt_to, vec!["<bill@remote.org>".to_string()]);
+    for needle in [
+        "report-type=\"disposition-notification\"",
+        "Final-Recipient: rfc822; jdoe@example.com",
+        "Original-Message-ID: <tps-report@remote.org>",
+        "Disposition: manual-action/MDN-sent-manually; displayed",
+        "Subject: Return Receipt (displayed) - TPS Report",
+    ] {
+        assert!(
+            message.message.contains(needle),
+            "{needle:?} not found in {}",
+            message.message
+        );
+    }
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Email/get",
+            {
+             "accountId": "$$",
+             "ids": ["%%"],
+             "properties": ["keywords"]
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &email_id),
+    )
+    .await;
+    assert_eq!(
+        response.pointer("/methodResponses/0/1/list/0/keywords/$mdnsent"),
+        Some(&Value::Bool(true)),
+        "Response: {response:?}"
+    );
+
+    // Sending a second MDN for the same message should fail
+    let response = mdn_send(&account_id, &identity_id, &email_id).await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/notSent/k1/type")
+            .and_then(|v| v.as_str()),
+        Some("mdnAlreadySent"),
+        "Response: {response:?}"
+    );
+
+    // Deliver the generated MDN and parse it
+    lmtp.ingest("", &["jdoe@example.com"], &message.message)
+        .await;
+    let mdn_email_id = query_email(&account_id, "Return Receipt").await;
+    let blob_id = jmap_request(
+        &account_id,
+        r#"[[
+            "Email/get",
+            {
+             "accountId": "$$",
+             "ids": ["%%"],
+             "properties": ["blobId"]
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &mdn_email_id),
+    )
+    .await
+    .pointer("/methodResponses/0/1/list/0/blobId")
+    .and_then(|v| v.as_str())
+    .unwrap()
+    .to_string();
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "MDN/parse",
+            {
+             "accountId": "$$",
+             "blobIds": ["%%", "not-a-blob"]
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &blob_id),
+    )
+    .await;
+    let mdn = response
+        .pointer(&format!("/methodResponses/0/1/parsed/{blob_id}"))
+        .unwrap_or_else(|| panic!("Response: {response:?}"));
+    assert_eq!(
+        mdn.pointer("/finalRecipient").and_then(|v| v.as_str()),
+        Some("rfc822; jdoe@example.com")
+    );
+    assert_eq!(
+        mdn.pointer("/originalMessageId").and_then(|v| v.as_str()),
+        Some("<tps-report@remote.org>")
+    );
+    assert_eq!(
+        mdn.pointer("/disposition"),
+        Some(&serde_json::json!({
+            "actionMode": "manual-action",
+            "sendingMode": "mdn-sent-manually",
+            "type": "displayed"
+        }))
+    );
+    assert_eq!(
+        mdn.pointer("/includeOriginalMessage"),
+        Some(&Value::Bool(false))
+    );
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/notFound/0")
+            .and_then(|v| v.as_str()),
+        Some("not-a-blob"),
+        "Response: {response:?}"
+    );
+
+    // Messages that do not request an MDN should be rejected
+    let response = mdn_send(&account_id, &identity_id, &mdn_email_id).await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/notSent/k1/type")
+            .and_then(|v| v.as_str()),
+        Some("noRecipients"),
+        "Response: {response:?}"
+    );
+    expect_nothing(&mut smtp_rx).await;
+    smtp_settings.lock().do_stop = true;
+
+    // Caller-provided field values must not be able to inject report fields
+    assert_eq!(
+        field_value("Mailer/1.0\r\nDisposition: automatic-action/MDN-sent-automatically; deleted"),
+        "Mailer/1.0 Disposition: automatic-action/MDN-sent-automatically; deleted"
+    );
+    assert_eq!(
+        field_value("rfc822;\n jdoe@example.com\r\n"),
+        "rfc822; jdoe@example.com"
+    );
+
+    // Empty store
+    jmap_request(
+        &account_id,
+        r#"[[
+            "Identity/set",
+            {
+             "accountId": "$$",
+             "destroy": ["%%"]
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &identity_id),
+    )
+    .await;
+    admin_client.set_default_account_id(account_id.to_string());
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
+
+async fn mdn_send(account_id: &Id, identity_id: &str, email_id: &str) -> Value {
+    jmap_request(
+        account_id,
+        r##"[[
+            "MDN/send",
+            {
+             "accountId": "$$",
+             "identityId": "@@",
+             "send": {
+              "k1": {
+               "forEmailId": "%%",
+               "disposition": {
+                "actionMode": "manual-action",
+                "sendingMode": "mdn-sent-manually",
+                "type": "displayed"
+               }
+              }
+             },
+             "onSuccessUpdateEmail": {
+              "#k1": { "keywords/$mdnsent": true }
+             }
+            },
+            "R1"
+           ]]"##
+            .replace("@@", identity_id)
+            .replace("%%", email_id),
+    )
+    .await
+}
+
+async fn query_email(account_id: &Id, subject: &str) -> String {
+    let response = jmap_request(
+        account_id,
+        r#"[[
+            "Email/query",
+            {
+             "accountId": "$$",
+             "filter": { "subject": "%%" }
+            },
+            "R1"
+           ]]"#
+        .replace("%%", subject),
+    )
+    .await;
+    response
+        .pointer("/methodResponses/0/1/ids/0")
+        .and_then(|v| v.as_str())
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+        .to_string()
+}
+
+async fn jmap_request(account_id: &Id, body: impl AsRef<str>) -> Value {
+    jmap_json_request(
+        body.as_ref().replace("$$", &account_id.to_string()),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await
+}
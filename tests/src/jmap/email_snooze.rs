@@ -0,0 +1,250 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use jmap::{mailbox::INBOX_ID, JMAP};
+use jmap_client::client::Client;
+use jmap_proto::types::{date::UTCDate, id::Id};
+use serde_json::Value;
+use store::write::now;
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{jmap_json_request, mailbox::destroy_all_mailboxes},
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running Email snooze tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "jdoe@example.com", "12345", "John Doe").await;
+    let account_id = Id::from(server.get_account_id("jdoe@example.com").await.unwrap());
+    let inbox_id = Id::from(INBOX_ID).to_string();
+
+    // Create a read message in the Inbox
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Email/set",
+            {
+             "accountId": "$$",
+             "create": {
+              "m1": {
+               "mailboxIds": { "%%": true },
+               "keywords": { "$seen": true },
+               "subject": "Remind me later",
+               "textBody": [{ "partId": "1", "type": "text/plain" }],
+               "bodyValues": { "1": { "value": "Snoozed message" } }
+              }
+             }
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &inbox_id),
+    )
+    .await;
+    let email_id = response
+        .pointer("/methodResponses/0/1/created/m1/id")
+        .and_then(|v| v.as_str())
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+        .to_string();
+
+    // Snoozing into the past is not allowed
+    let response = snooze(&account_id, &email_id, Some(now() - 60)).await;
+    assert_eq!(
+        response
+            .pointer(&format!("/methodResponses/0/1/notUpdated/{email_id}/type"))
+            .and_then(|v| v.as_str())
+            .unwrap_or_default(),
+        "invalidProperties",
+        "Response: {response:?}"
+    );
+
+    // Snooze the message, it should be moved out of the Inbox
+    let until = now() + 2;
+    let response = snooze(&account_id, &email_id, Some(until)).await;
+    assert!(
+        response
+            .pointer(&format!("/methodResponses/0/1/updated/{email_id}"))
+            .is_some(),
+        "Response: {response:?}"
+    );
+    let snoozed_id = jmap_request(
+        &account_id,
+        r#"[[
+            "Mailbox/query",
+            {
+             "accountId": "$$",
+             "filter": { "role": "snoozed" }
+            },
+            "R1"
+           ]]"#,
+    )
+    .await
+    .pointer("/methodResponses/0/1/ids/0")
+    .and_then(|v| v.as_str())
+    .unwrap()
+    .to_string();
+    let email = get_email(&account_id, &email_id).await;
+    assert_eq!(mailbox_ids(&email), vec![snoozed_id.as_str()]);
+    assert_eq!(
+        email.pointer("/snoozedUntil").and_then(|v| v.as_str()),
+        Some(UTCDate::from_timestamp(until as i64).to_string().as_str())
+    );
+
+    // Nothing should happen until the message is due
+    let state = email_state(&account_id).await;
+    server.wake_snoozed_emails().await.unwrap();
+    assert_eq!(
+        mailbox_ids(&get_email(&account_id, &email_id).await),
+        vec![snoozed_id.as_str()]
+    );
+
+    // Once due, the message is restored to the Inbox and marked as unread
+    tokio::time::sleep(Duration::from_secs(3)).await;
+    server.wake_snoozed_emails().await.unwrap();
+    let email = get_email(&account_id, &email_id).await;
+    assert_eq!(mailbox_ids(&email), vec![inbox_id.as_str()]);
+    assert_eq!(email.pointer("/keywords/$seen"), None, "Email: {email:?}");
+    assert_eq!(email.pointer("/snoozedUntil"), Some(&Value::Null));
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Email/changes",
+            {
+             "accountId": "$$",
+             "sinceState": "%%"
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &state),
+    )
+    .await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/updated/0")
+            .and_then(|v| v.as_str()),
+        Some(email_id.as_str()),
+        "Response: {response:?}"
+    );
+
+    // Unsnoozing restores the message without changing its keywords
+    snooze(&account_id, &email_id, Some(now() + 3600)).await;
+    let response = snooze(&account_id, &email_id, None).await;
+    assert!(
+        response
+            .pointer(&format!("/methodResponses/0/1/updated/{email_id}"))
+            .is_some(),
+        "Response: {response:?}"
+    );
+    let email = get_email(&account_id, &email_id).await;
+    assert_eq!(mailbox_ids(&email), vec![inbox_id.as_str()]);
+    assert_eq!(email.pointer("/snoozedUntil"), Some(&Value::Null));
+
+    // Empty store
+    admin_client.set_default_account_id(account_id.to_string());
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
+
+async fn snooze(account_id: &Id, email_id: &str, until: Option<u64>) -> Value {
+    jmap_request(
+        account_id,
+        r#"[[
+            "Email/set",
+            {
+             "accountId": "$$",
+             "update": {
+              "%%": { "snoozedUntil": ## }
+             }
+            },
+            "R1"
+           ]]"#
+        .replace("%%", email_id)
+        .replace(
+            "##",
+            &until.map_or_else(
+                || "null".to_string(),
+                |until| format!("{:?}", UTCDate::from_timestamp(until as i64).to_string()),
+            ),
+        ),
+    )
+    .await
+}
+
+async fn get_email(account_id: &Id, email_id: &str) -> Value {
+    let mut response = jmap_request(
+        account_id,
+        r#"[[
+            "Email/get",
+            {
+             "accountId": "$$",
+             "ids": ["%%"],
+             "properties": ["mailboxIds", "keywords", "snoozedUntil"]
+            },
+            "R1"
+           ]]"#
+        .replace("%%", email_id),
+    )
+    .await;
+    response
+        .pointer_mut("/methodResponses/0/1/list/0")
+        .unwrap_or_else(|| panic!("Email {email_id} not found"))
+        .take()
+}
+
+async fn email_state(account_id: &Id) -> String {
+    jmap_request(
+        account_id,
+        r#"[[
+            "Email/get",
+            {
+             "accountId": "$$",
+             "ids": []
+            },
+            "R1"
+           ]]"#,
+    )
+    .await
+    .pointer("/methodResponses/0/1/state")
+    .and_then(|v| v.as_str())
+    .unwrap()
+    .to_string()
+}
+
+fn mailbox_ids(email: &Value) -> Vec<&str> {
+    email
+        .pointer("/mailboxIds")
+        .and_then(|v| v.as_object())
+        .map(|ids| ids.keys().map(|id| id.as_str()).collect())
+        .unwrap_or_default()
+}
+
+async fn jmap_request(account_id: &Id, body: impl AsRef<str>) -> Value {
+    jmap_json_request(
+        body.as_ref().replace("$$", &account_id.to_string()),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await
+}
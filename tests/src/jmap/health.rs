@@ -0,0 +1,69 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use jmap::{api::health::HealthStatus, JMAP};
+use jmap_client::client::Client;
+
+pub async fn test(server: Arc<JMAP>, _client: &mut Client) {
+    println!("Running health check tests...");
+
+    // Liveness probe
+    let (code, response) = health_request("healthz").await;
+    assert_eq!(code, 200);
+    assert_eq!(response["status"], "ok");
+
+    // Readiness probe should report all components
+    let (code, response) = health_request("readyz").await;
+    assert_eq!(code, 200, "{response}");
+    assert_eq!(response["status"], "ok");
+    for component in ["store", "blob", "directory", "queue"] {
+        assert_eq!(
+            response["components"][component]["status"], "ok",
+            "{component}: {response}"
+        );
+    }
+
+    // Readiness report should match the HTTP response
+    let report = server.readiness().await;
+    assert_eq!(report.status, HealthStatus::Ok);
+    assert_eq!(report.components.len(), 4);
+}
+
+async fn health_request(path: &str) -> (u16, serde_json::Value) {
+    let response = reqwest::Client::builder()
+        .timeout(Duration::from_millis(5000))
+        .danger_accept_invalid_certs(true)
+        .build()
+        .unwrap_or_default()
+        .get(format!("https://127.0.0.1:8899/{path}"))
+        .send()
+        .await
+        .unwrap();
+
+    (
+        response.status().as_u16(),
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap(),
+    )
+}
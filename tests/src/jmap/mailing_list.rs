@@ -0,0 +1,401 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Instant};
+
+use jmap::{
+    mailbox::INBOX_ID,
+    mailing_list::{list_headers, ListHeaders},
+    JMAP,
+};
+use jmap_client::{client::Client, mailbox::Role};
+use jmap_proto::types::{collection::Collection, id::Id, property::Property};
+use serde_json::{json, Value};
+use utils::ipc::DeliveryResult;
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{
+        delivery::SmtpConnection,
+        email_submission::{expect_message_delivery, expect_nothing, spawn_mock_smtp_server},
+        jmap_json_request,
+        mailbox::destroy_all_mailboxes,
+    },
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running mailing list tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "jdoe@example.com", "12345", "John Doe").await;
+    let account_id = server.get_account_id("jdoe@example.com").await.unwrap();
+
+    // Start mock SMTP server
+    let (mut smtp_rx, smtp_settings) = spawn_mock_smtp_server();
+    server.smtp.resolvers.dns.ipv4_add(
+        "localhost",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + std::time::Duration::from_secs(10),
+    );
+
+    // Parse list headers
+    assert_eq!(
+        list_headers(
+            concat!(
+                "From: Rust Users <rust-users@lists.example.org>\r\n",
+                "List-Id: \"Rust Users\"\r\n <Rust-Users.lists.example.org>\r\n",
+                "List-Unsubscribe: <https://lists.example.org/unsub?id=1>,\r\n",
+                " <mailto:leave@lists.example.org?subject=unsubscribe>, <ftp://invalid>\r\n",
+                "List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n",
+                "\r\n",
+                "Hi\r\n"
+            )
+            .as_bytes()
+        ),
+        Some(ListHeaders {
+            id: "rust-users.lists.example.org".to_string(),
+            name: Some("Rust Users".to_string()),
+            sender: Some("rust-users@lists.example.org".to_string()),
+            unsubscribe: vec![
+                "https://lists.example.org/unsub?id=1".to_string(),
+                "mailto:leave@lists.example.org?subject=unsubscribe".to_string()
+            ],
+            one_click: true,
+        })
+    );
+    for message in [
+        "From: bill@remote.org\r\nSubject: Hi\r\n\r\nHi\r\n",
+        "List-Id: <>\r\n\r\nHi\r\n",
+        "List-Id: not a list id\r\n\r\nHi\r\n",
+    ] {
+        assert_eq!(list_headers(message.as_bytes()), None, "{message}");
+    }
+
+    // Deliver messages from two lists and one regular message
+    for (list_headers, subject) in [
+        (
+            concat!(
+                "List-Id: Rust Users <rust-users.lists.example.org>\r\n",
+                "List-Unsubscribe: <https://127.0.0.1:1/unsub>,\r\n",
+                " <mailto:leave@lists.example.org?subject=unsubscribe%20me>\r\n",
+                "List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n",
+            ),
+            "Release announcement",
+        ),
+        (
+            "List-Id: <rust-users.lists.example.org>\r\n",
+            "Re: Release announcement",
+        ),
+        (
+            "List-Id: Announcements <announce.example.org>\r\n",
+            "Newsletter",
+        ),
+        ("", "Personal message"),
+    ] {
+        deliver_authenticated(
+            &server,
+            &format!(
+                concat!(
+                    "From: list@lists.example.org\r\n",
+                    "To: jdoe@example.com\r\n",
+                    "{}",
+                    "Subject: {}\r\n",
+                    "\r\n",
+                    "Test message.\r\n"
+                ),
+                list_headers, subject
+            ),
+        )
+        .await;
+    }
+
+    // Unauthenticated messages cannot register or modify lists, and no
+    // more lists are registered once the account limit is reached
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.ingest(
+        "bounces@lists.example.org",
+        &["jdoe@example.com"],
+        concat!(
+            "From: list@lists.example.org\r\n",
+            "To: jdoe@example.com\r\n",
+            "List-Id: Forged <rust-users.lists.example.org>\r\n",
+            "List-Unsubscribe: <https://attacker.example.net/unsub>\r\n",
+            "List-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n",
+            "Subject: Forged list message\r\n",
+            "\r\n",
+            "Test message.\r\n"
+        ),
+    )
+    .await;
+    deliver_authenticated(
+        &server,
+        concat!(
+            "From: list@lists.example.org\r\n",
+            "To: jdoe@example.com\r\n",
+            "List-Id: <third.lists.example.org>\r\n",
+            "Subject: Third list\r\n",
+            "\r\n",
+            "Test message.\r\n"
+        ),
+    )
+    .await;
+    expect_nothing(&mut smtp_rx).await;
+
+    // Both lists are registered
+    let account_id_str = Id::from(account_id).to_string();
+    let response = jmap_request(json!([[
+        "MailingList/get",
+        {"accountId": account_id_str},
+        "R1"
+    ]]))
+    .await;
+    let list = response
+        .pointer("/methodResponses/0/1/list")
+        .and_then(|v| v.as_array())
+        .unwrap_or_else(|| panic!("Response: {response:?}"));
+    assert_eq!(list.len(), 2, "{response}");
+    let rust_users = list
+        .iter()
+        .find(|list| list["id"] == "rust-users.lists.example.org")
+        .unwrap_or_else(|| panic!("Response: {response:?}"));
+    assert_eq!(rust_users["name"], "Rust Users", "{response}");
+    assert_eq!(rust_users["sender"], "list@lists.example.org", "{response}");
+    assert_eq!(rust_users["totalEmails"], 2, "{response}");
+    assert_eq!(rust_users["oneClick"], true, "{response}");
+    assert_eq!(rust_users["mailboxId"], Value::Null, "{response}");
+    assert_eq!(
+        rust_users["unsubscribe"],
+        json!([
+            "https://127.0.0.1:1/unsub",
+            "mailto:leave@lists.example.org?subject=unsubscribe%20me"
+        ]),
+        "{response}"
+    );
+    let announce = list
+        .iter()
+        .find(|list| list["id"] == "announce.example.org")
+        .unwrap_or_else(|| panic!("Response: {response:?}"));
+    assert_eq!(announce["totalEmails"], 1, "{response}");
+    assert_eq!(announce["oneClick"], false, "{response}");
+    assert_eq!(announce["unsubscribe"], json!([]), "{response}");
+
+    // Fetch lists by id
+    let response = jmap_request(json!([[
+        "MailingList/get",
+        {
+            "accountId": account_id_str,
+            "ids": ["Rust-Users.lists.example.org", "unknown.example.org"]
+        },
+        "R1"
+    ]]))
+    .await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/list/0/id")
+            .and_then(|v| v.as_str()),
+        Some("rust-users.lists.example.org"),
+        "{response}"
+    );
+    assert_eq!(
+        response.pointer("/methodResponses/0/1/notFound"),
+        Some(&json!(["unknown.example.org"])),
+        "{response}"
+    );
+
+    // File messages from the list into a folder
+    admin_client.set_default_account_id(Id::from(account_id).to_string());
+    let mailbox_id = admin_client
+        .mailbox_create("Rust", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+    let response = jmap_request(json!([[
+        "MailingList/set",
+        {
+            "accountId": account_id_str,
+            "update": {
+                "rust-users.lists.example.org": {"mailboxId": mailbox_id},
+                "announce.example.org": {"mailboxId": Id::from(9999u64).to_string()},
+                "unknown.example.org": {"mailboxId": null}
+            }
+        },
+        "R1"
+    ]]))
+    .await;
+    assert_eq!(
+        response.pointer("/methodResponses/0/1/updated"),
+        Some(&json!(["rust-users.lists.example.org"])),
+        "{response}"
+    );
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/notUpdated/announce.example.org/type")
+            .and_then(|v| v.as_str()),
+        Some("invalidProperties"),
+        "{response}"
+    );
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/notUpdated/unknown.example.org/type")
+            .and_then(|v| v.as_str()),
+        Some("notFound"),
+        "{response}"
+    );
+    deliver_authenticated(
+        &server,
+        concat!(
+            "From: list@lists.example.org\r\n",
+            "To: jdoe@example.com\r\n",
+            "List-Id: <rust-users.lists.example.org>\r\n",
+            "Subject: Filed message\r\n",
+            "\r\n",
+            "Test message.\r\n"
+        ),
+    )
+    .await;
+    lmtp.quit().await;
+    for (mailbox_id, expected) in [
+        (
+            Id::from_bytes(mailbox_id.as_bytes()).unwrap().document_id(),
+            1,
+        ),
+        (INBOX_ID, 6),
+    ] {
+        assert_eq!(
+            server
+                .get_tag(
+                    account_id,
+                    Collection::Email,
+                    Property::MailboxIds,
+                    mailbox_id
+                )
+                .await
+                .unwrap()
+                .unwrap_or_default()
+                .len(),
+            expected,
+            "mailbox {mailbox_id}"
+        );
+    }
+
+    // Unsubscribe, the one-click endpoint is unreachable so the mailto address is used
+    let response = jmap_request(json!([[
+        "MailingList/unsubscribe",
+        {
+            "accountId": account_id_str,
+            "ids": [
+                "rust-users.lists.example.org",
+                "announce.example.org",
+                "unknown.example.org"
+            ]
+        },
+        "R1"
+    ]]))
+    .await;
+    assert_eq!(
+        response.pointer("/methodResponses/0/1/unsubscribed"),
+        Some(&json!(["rust-users.lists.example.org"])),
+        "{response}"
+    );
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/notUnsubscribed/announce.example.org/type")
+            .and_then(|v| v.as_str()),
+        Some("forbidden"),
+        "{response}"
+    );
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/notUnsubscribed/unknown.example.org/type")
+            .and_then(|v| v.as_str()),
+        Some("notFound"),
+        "{response}"
+    );
+    let message = expect_message_delivery(&mut smtp_rx).await;
+    assert_eq!(message.mail_from, "<jdoe@example.com>");
+    assert_eq!(
+        message.rcpt_to,
+        vec!["<leave@lists.example.org>".to_string()]
+    );
+    assert!(
+        message.message.contains("Subject: unsubscribe me"),
+        "{}",
+        message.message
+    );
+    expect_nothing(&mut smtp_rx).await;
+    smtp_settings.lock().do_stop = true;
+
+    let response = jmap_request(json!([[
+        "MailingList/get",
+        {"accountId": account_id_str, "ids": ["rust-users.lists.example.org"]},
+        "R1"
+    ]]))
+    .await;
+    let rust_users = response
+        .pointer("/methodResponses/0/1/list/0")
+        .unwrap_or_else(|| panic!("Response: {response:?}"));
+    assert_eq!(rust_users["totalEmails"], 3, "{response}");
+    assert!(rust_users["unsubscribedAt"].is_string(), "{response}");
+    assert_eq!(rust_users["mailboxId"], mailbox_id, "{response}");
+
+    // Empty store
+    for list_id in ["rust-users.lists.example.org", "announce.example.org"] {
+        server
+            .set_mailing_list(account_id, list_id, None)
+            .await
+            .unwrap();
+    }
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
+
+async fn jmap_request(body: Value) -> Value {
+    jmap_json_request(body.to_string(), "jdoe@example.com", "12345").await
+}
+
+// Delivers a message as if it had passed DKIM verification for the sender domain
+pub async fn deliver_authenticated(server: &JMAP, message: &str) {
+    let sender = message
+        .split("\r\n")
+        .find_map(|line| line.strip_prefix("From: "))
+        .unwrap();
+    let domain = sender.rsplit_once('@').unwrap().1;
+    assert!(matches!(
+        server
+            .deliver_to_account(
+                format!(
+                    concat!(
+                        "Authentication-Results: jmap.example.org;\r\n",
+                        "\tdkim=pass header.d={} header.s=default\r\n",
+                        "{}"
+                    ),
+                    domain, message
+                )
+                .as_bytes(),
+                sender,
+                "jdoe@example.com",
+                "jdoe@example.com",
+            )
+            .await,
+        DeliveryResult::Success
+    ));
+}
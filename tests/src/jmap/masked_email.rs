@@ -0,0 +1,273 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::JMAP;
+use jmap_client::client::Client;
+use jmap_proto::types::id::Id;
+use serde_json::Value;
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{delivery::SmtpConnection, jmap_json_request, mailbox::destroy_all_mailboxes},
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running Masked Email tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "jdoe@example.com", "12345", "John Doe").await;
+    let account_id = Id::from(server.get_account_id("jdoe@example.com").await.unwrap());
+
+    // Create a masked email address
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "MaskedEmail/set",
+            {
+             "accountId": "$$",
+             "create": {
+              "m1": {
+               "forDomain": "https://shop.example.net",
+               "description": "Online shop",
+               "emailPrefix": "shop"
+              },
+              "m2": {
+               "emailPrefix": "Not Valid!"
+              },
+              "m3": {
+               "state": "enabled",
+               "totalEmails": 100
+              }
+             }
+            },
+            "R1"
+           ]]"#,
+    )
+    .await;
+    let masked_id = response
+        .pointer("/methodResponses/0/1/created/m1/id")
+        .and_then(|v| v.as_str())
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+        .to_string();
+    let masked_email = response
+        .pointer("/methodResponses/0/1/created/m1/email")
+        .and_then(|v| v.as_str())
+        .unwrap()
+        .to_string();
+    assert!(
+        masked_email.starts_with("shop.") && masked_email.ends_with("@example.com"),
+        "Invalid address {masked_email}"
+    );
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/created/m1/state")
+            .and_then(|v| v.as_str()),
+        Some("pending"),
+        "Response: {response:?}"
+    );
+    for (create_id, property) in [("m2", "emailPrefix"), ("m3", "totalEmails")] {
+        assert_eq!(
+            response
+                .pointer(&format!("/methodResponses/0/1/notCreated/{create_id}/type"))
+                .and_then(|v| v.as_str()),
+            Some("invalidProperties"),
+            "Response: {response:?}"
+        );
+        assert_eq!(
+            response
+                .pointer(&format!(
+                    "/methodResponses/0/1/notCreated/{create_id}/properties/0"
+                ))
+                .and_then(|v| v.as_str()),
+            Some(property),
+            "Response: {response:?}"
+        );
+    }
+
+    // Messages sent to the masked address should be delivered to the owner
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.ingest(
+        "bill@remote.org",
+        &[masked_email.as_str()],
+        &concat!(
+            "From: bill@remote.org\r\n",
+            "To: @@\r\n",
+            "Subject: Your order\r\n",
+            "\r\n",
+            "Thank you for your order.\r\n"
+        )
+        .replace("@@", &masked_email),
+    )
+    .await;
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Email/query",
+            {
+             "accountId": "$$",
+             "filter": { "subject": "Your order" }
+            },
+            "R1"
+           ]]"#,
+    )
+    .await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/ids")
+            .and_then(|v| v.as_array())
+            .map(|ids| ids.len()),
+        Some(1),
+        "Response: {response:?}"
+    );
+
+    // The address should now be enabled and the message counted
+    let masked = get_masked_email(&account_id, &masked_id).await;
+    assert_eq!(masked["state"], "enabled", "{masked:?}");
+    assert_eq!(masked["totalEmails"], 1, "{masked:?}");
+    assert_eq!(
+        masked["forDomain"], "https://shop.example.net",
+        "{masked:?}"
+    );
+    assert_eq!(masked["createdBy"], "jdoe@example.com", "{masked:?}");
+    assert!(masked["lastMessageAt"].is_string(), "{masked:?}");
+    assert!(masked["createdAt"].is_string(), "{masked:?}");
+
+    // Disabled addresses should be rejected at RCPT time
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "MaskedEmail/set",
+            {
+             "accountId": "$$",
+             "update": {
+              "%%": { "state": "disabled", "description": "No longer used" }
+             }
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &masked_id),
+    )
+    .await;
+    assert!(
+        response
+            .pointer(&format!("/methodResponses/0/1/updated/{masked_id}"))
+            .is_some(),
+        "Response: {response:?}"
+    );
+    lmtp.mail_from("bill@remote.org", 2).await;
+    lmtp.rcpt_to(&masked_email, 5).await;
+    lmtp.rset().await;
+    let masked = get_masked_email(&account_id, &masked_id).await;
+    assert_eq!(masked["state"], "disabled", "{masked:?}");
+    assert_eq!(masked["description"], "No longer used", "{masked:?}");
+    assert_eq!(masked["totalEmails"], 1, "{masked:?}");
+
+    // Pending state cannot be restored
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "MaskedEmail/set",
+            {
+             "accountId": "$$",
+             "update": {
+              "%%": { "state": "pending" }
+             }
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &masked_id),
+    )
+    .await;
+    assert_eq!(
+        response
+            .pointer(&format!("/methodResponses/0/1/notUpdated/{masked_id}/type"))
+            .and_then(|v| v.as_str()),
+        Some("invalidProperties"),
+        "Response: {response:?}"
+    );
+
+    // Destroying the masked email releases the address
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "MaskedEmail/set",
+            {
+             "accountId": "$$",
+             "destroy": ["%%"]
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &masked_id),
+    )
+    .await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/destroyed/0")
+            .and_then(|v| v.as_str()),
+        Some(masked_id.as_str()),
+        "Response: {response:?}"
+    );
+    assert!(server
+        .masked_email_route(&masked_email)
+        .await
+        .unwrap()
+        .is_none());
+    lmtp.mail_from("bill@remote.org", 2).await;
+    lmtp.rcpt_to(&masked_email, 5).await;
+    lmtp.quit().await;
+
+    // Empty store
+    admin_client.set_default_account_id(account_id.to_string());
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
+
+async fn get_masked_email(account_id: &Id, masked_id: &str) -> Value {
+    let response = jmap_request(
+        account_id,
+        r#"[[
+            "MaskedEmail/get",
+            {
+             "accountId": "$$",
+             "ids": ["%%"]
+            },
+            "R1"
+           ]]"#
+        .replace("%%", masked_id),
+    )
+    .await;
+    response
+        .pointer("/methodResponses/0/1/list/0")
+        .cloned()
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+}
+
+async fn jmap_request(account_id: &Id, body: impl AsRef<str>) -> Value {
+    jmap_json_request(
+        body.as_ref().replace("$$", &account_id.to_string()),
+        "jdoe@example.com",
+        "12345",
+    )
+    .await
+}
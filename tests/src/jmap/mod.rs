@@ -39,27 +39,53 @@ use crate::{
     store::TempDir,
 };
 
+pub mod account_delete;
+pub mod account_rename;
+pub mod admin_console;
+pub mod attachment_link;
 pub mod auth_acl;
 pub mod auth_limits;
 pub mod auth_oauth;
 pub mod blob;
+pub mod collected_address;
+pub mod compression;
+pub mod cors;
 pub mod crypto;
 pub mod delivery;
+pub mod delivery_dedup;
+pub mod digest;
+pub mod domain_policy;
 pub mod email_changes;
+pub mod email_convert;
 pub mod email_copy;
 pub mod email_get;
+pub mod email_html;
+pub mod email_keywords;
+pub mod email_mdn;
 pub mod email_parse;
 pub mod email_query;
 pub mod email_query_changes;
 pub mod email_search_snippet;
 pub mod email_set;
+pub mod email_snooze;
 pub mod email_submission;
 pub mod event_source;
+pub mod health;
 pub mod mailbox;
+pub mod mailing_list;
+pub mod masked_email;
+pub mod principal;
 pub mod push_subscription;
 pub mod quota;
+pub mod sender_list;
+pub mod sent_copy;
+pub mod sessions;
+pub mod settings;
+pub mod share_invitation;
 pub mod sieve_script;
+pub mod spam_train;
 pub mod stress_test;
+pub mod subaddress;
 pub mod thread_get;
 pub mod thread_merge;
 pub mod vacation_response;
@@ -84,6 +110,9 @@ tls.implicit = false
 [server.socket]
 reuse-addr = true
 
+[server.session]
+max-memory = 10485760
+
 [server.tls]
 enable = true
 implicit = false
@@ -109,6 +138,10 @@ hash = 64
 path = "{TMP}"
 hash = 64
 
+[tracking]
+enable = true
+path = "{TMP}/tracking"
+
 [resolver]
 type = "system"
 
@@ -151,6 +184,7 @@ set.max-objects = 100000
 
 [jmap.protocol.request]
 max-concurrent = 8
+max-cost = 100000
 
 [jmap.protocol.upload]
 max-size = 5000000
@@ -165,6 +199,11 @@ size = 50000
 account = "1000/1m"
 authentication = "100/2s"
 anonymous = "100/1m"
+oauth = "100/1m"
+
+[jmap.http.cors]
+allowed-origins = ["https://webmail.example.org"]
+allow-credentials = true
 
 [jmap.event-source]
 throttle = "500ms"
@@ -176,10 +215,69 @@ throttle = "500ms"
 throttle = "500ms"
 attempts.interval = "500ms"
 
+[jmap.settings.password]
+query = "UPDATE accounts SET secret = ? WHERE name = ?"
+
+[jmap.sharing]
+require-acceptance = false
+invitation.email = true
+
+[jmap.account]
+rename.query = ["UPDATE accounts SET name = ? WHERE name = ?",
+                "UPDATE emails SET name = ? WHERE name = ?"]
+rename.address.query = "UPDATE emails SET address = ? WHERE name = ? AND address = ? AND type = 'primary'"
+rename.alias.query = "INSERT INTO emails (name, address, type) VALUES (?, ?, 'alias')"
+rename.alias.expire-query = "DELETE FROM emails WHERE name = ? AND address = ? AND type = 'alias'"
+rename.alias.grace-period = "0s"
+delete.query = ["DELETE FROM group_members WHERE name = ?",
+                "DELETE FROM emails WHERE name = ?",
+                "DELETE FROM accounts WHERE name = ?"]
+
+[jmap.submission.sent-copy]
+enable = true
+window = "5m"
+
+[jmap.submission.attachment-links]
+threshold = 1024
+expiry = "5s"
+url = "https://127.0.0.1:8899/attachment"
+
+[jmap.html.image-proxy]
+enable = true
+key = "image-proxy-test-key"
+
+[jmap.spam]
+junk-folder = true
+training.interval = "1h"
+
+[jmap.mailing-list]
+max-lists = 2
+
+[session.data]
+script = "spam-test"
+
+[sieve.trusted]
+no-capability-check = true
+
+[sieve.trusted.scripts]
+spam-test = '''
+if header :is "X-Spam-Test" "yes" {
+    eval "add_header('X-Spam-Status', 'Yes, score=7.5')";
+} elsif header :is "X-Spam-Test" "no" {
+    eval "add_header('X-Spam-Status', 'No, score=0.1')";
+}
+'''
+
+[sieve.untrusted.limits]
+override = [{principal = "sieve-limited", max-scripts = 2, max-total-size = 200}]
+
 [directory."sql"]
 type = "sql"
 address = "sqlite::memory:"
 
+[directory."sql".options]
+subaddressing = true
+
 [directory."sql".pool]
 max-connections = 1
 
@@ -239,6 +337,8 @@ pub async fn jmap_tests() {
     email_changes::test(params.server.clone(), &mut params.client).await;
     email_query_changes::test(params.server.clone(), &mut params.client).await;
     email_copy::test(params.server.clone(), &mut params.client).await;
+    email_snooze::test(params.server.clone(), &mut params.client).await;
+    email_keywords::test(params.server.clone(), &mut params.client).await;
     thread_get::test(params.server.clone(), &mut params.client).await;
     thread_merge::test(params.server.clone(), &mut params.client).await;
     mailbox::test(params.server.clone(), &mut params.client).await;
@@ -250,11 +350,35 @@ pub async fn jmap_tests() {
     push_subscription::test(params.server.clone(), &mut params.client).await;
     sieve_script::test(params.server.clone(), &mut params.client).await;
     vacation_response::test(params.server.clone(), &mut params.client).await;
+    email_mdn::test(params.server.clone(), &mut params.client).await;
+    masked_email::test(params.server.clone(), &mut params.client).await;
+    share_invitation::test(params.server.clone(), &mut params.client).await;
+    principal::test(params.server.clone(), &mut params.client).await;
+    collected_address::test(params.server.clone()).await;
+    mailing_list::test(params.server.clone(), &mut params.client).await;
+    digest::test(params.server.clone(), &mut params.client).await;
+    attachment_link::test(params.server.clone(), &mut params.client).await;
+    email_html::test(params.server.clone(), &mut params.client).await;
+    email_convert::test(params.server.clone(), &mut params.client).await;
+    delivery_dedup::test(params.server.clone(), &mut params.client).await;
+    sent_copy::test(params.server.clone(), &mut params.client).await;
     email_submission::test(params.server.clone(), &mut params.client).await;
     websocket::test(params.server.clone(), &mut params.client).await;
     quota::test(params.server.clone(), &mut params.client).await;
     crypto::test(params.server.clone(), &mut params.client).await;
     blob::test(params.server.clone(), &mut params.client).await;
+    health::test(params.server.clone(), &mut params.client).await;
+    admin_console::test(params.server.clone(), &mut params.client).await;
+    cors::test(params.server.clone(), &mut params.client).await;
+    compression::test(params.server.clone(), &mut params.client).await;
+    settings::test(params.server.clone(), &mut params.client).await;
+    sender_list::test(params.server.clone(), &mut params.client).await;
+    subaddress::test(params.server.clone(), &mut params.client).await;
+    domain_policy::test(params.server.clone(), &mut params.client).await;
+    sessions::test(params.server.clone(), &mut params.client).await;
+    account_rename::test(params.server.clone(), &mut params.client).await;
+    account_delete::test(params.server.clone(), &mut params.client).await;
+    spam_train::test(params.server.clone(), &mut params.client).await;
 
     if delete {
         params.temp_dir.delete();
@@ -0,0 +1,301 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::JMAP;
+use jmap_client::client::Client;
+use jmap_proto::types::id::Id;
+use serde_json::Value;
+
+use crate::{
+    directory::sql::{create_test_group_with_email, create_test_user_with_email},
+    jmap::{jmap_json_request, mailbox::destroy_all_mailboxes},
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running Principal tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "peter@example.com", "pet123", "Peter Parker").await;
+    create_test_group_with_email(directory, "sales@example.com", "Sales Team").await;
+    let account_id = Id::from(server.get_account_id("peter@example.com").await.unwrap());
+    let group_id = Id::from(server.get_account_id("sales@example.com").await.unwrap());
+
+    // Principals can be found by text and type
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Principal/query",
+            {
+             "accountId": "$$",
+             "filter": { "text": "parker" }
+            },
+            "R1"
+           ],
+           [
+            "Principal/query",
+            {
+             "accountId": "$$",
+             "filter": { "type": "group" }
+            },
+            "R2"
+           ]]"#,
+    )
+    .await;
+    let ids = query_ids(&response, 0);
+    assert!(ids.contains(&account_id.to_string()), "{response:?}");
+    assert!(!ids.contains(&group_id.to_string()), "{response:?}");
+    let ids = query_ids(&response, 1);
+    assert!(ids.contains(&group_id.to_string()), "{response:?}");
+    assert!(!ids.contains(&account_id.to_string()), "{response:?}");
+
+    // Obtain principal details from the directory
+    let principal = get_principal(&account_id, &account_id).await;
+    assert_eq!(principal["name"], "peter@example.com", "{principal:?}");
+    assert_eq!(principal["email"], "peter@example.com", "{principal:?}");
+    assert_eq!(principal["type"], "individual", "{principal:?}");
+    assert_eq!(principal["description"], "Peter Parker", "{principal:?}");
+    assert_eq!(principal["timezone"], Value::Null, "{principal:?}");
+    assert!(
+        principal
+            .pointer("/capabilities/urn:ietf:params:jmap:mail")
+            .is_some(),
+        "{principal:?}"
+    );
+    assert!(
+        principal
+            .pointer("/capabilities/urn:ietf:params:jmap:submission")
+            .is_some(),
+        "{principal:?}"
+    );
+    let principal = get_principal(&account_id, &group_id).await;
+    assert_eq!(principal["type"], "group", "{principal:?}");
+    assert_eq!(principal["description"], "Sales Team", "{principal:?}");
+    assert!(
+        principal
+            .pointer("/capabilities/urn:ietf:params:jmap:mail")
+            .is_some(),
+        "{principal:?}"
+    );
+    assert!(
+        principal
+            .pointer("/capabilities/urn:ietf:params:jmap:submission")
+            .is_none(),
+        "{principal:?}"
+    );
+
+    // Obtain the current principal state
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Principal/get",
+            {
+             "accountId": "$$",
+             "ids": []
+            },
+            "R1"
+           ]]"#,
+    )
+    .await;
+    let state = response
+        .pointer("/methodResponses/0/1/state")
+        .and_then(|v| v.as_str())
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+        .to_string();
+
+    // Principals can't be created or destroyed, and directory fields are read-only
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Principal/set",
+            {
+             "accountId": "$$",
+             "create": {
+              "p1": {
+               "name": "bob@example.com"
+              }
+             },
+             "update": {
+              "$$": {
+               "name": "peter.parker@example.com"
+              },
+              "%%": {
+               "description": "Sales department"
+              }
+             },
+             "destroy": ["%%"]
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &group_id.to_string()),
+    )
+    .await;
+    for path in [
+        "notCreated/p1".to_string(),
+        format!("notUpdated/{account_id}"),
+        format!("notUpdated/{group_id}"),
+        format!("notDestroyed/{group_id}"),
+    ] {
+        assert_eq!(
+            response
+                .pointer(&format!("/methodResponses/0/1/{path}/type"))
+                .and_then(|v| v.as_str()),
+            Some("forbidden"),
+            "{path}: {response:?}"
+        );
+    }
+
+    // Update the description and timezone of the user's principal
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Principal/set",
+            {
+             "accountId": "$$",
+             "update": {
+              "$$": {
+               "description": "Spider-Man",
+               "timezone": "America/New_York"
+              }
+             }
+            },
+            "R1"
+           ]]"#,
+    )
+    .await;
+    assert!(
+        response
+            .pointer(&format!("/methodResponses/0/1/updated/{account_id}"))
+            .is_some(),
+        "{response:?}"
+    );
+    let principal = get_principal(&account_id, &account_id).await;
+    assert_eq!(principal["description"], "Spider-Man", "{principal:?}");
+    assert_eq!(principal["timezone"], "America/New_York", "{principal:?}");
+
+    // The update should be reported as a change
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Principal/changes",
+            {
+             "accountId": "$$",
+             "sinceState": "%%"
+            },
+            "R1"
+           ],
+           [
+            "Principal/query",
+            {
+             "accountId": "$$",
+             "filter": { "timezone": "America/New_York" }
+            },
+            "R2"
+           ]]"#
+        .replace("%%", &state),
+    )
+    .await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/updated")
+            .and_then(|v| v.as_array())
+            .map(|ids| ids.iter().filter_map(|id| id.as_str()).collect::<Vec<_>>()),
+        Some(vec![account_id.to_string().as_str()]),
+        "{response:?}"
+    );
+    assert_eq!(
+        query_ids(&response, 1),
+        vec![account_id.to_string()],
+        "{response:?}"
+    );
+
+    // Reset the description
+    let response = jmap_request(
+        &account_id,
+        r#"[[
+            "Principal/set",
+            {
+             "accountId": "$$",
+             "update": {
+              "$$": {
+               "description": null
+              }
+             }
+            },
+            "R1"
+           ]]"#,
+    )
+    .await;
+    assert!(
+        response
+            .pointer(&format!("/methodResponses/0/1/updated/{account_id}"))
+            .is_some(),
+        "{response:?}"
+    );
+    let principal = get_principal(&account_id, &account_id).await;
+    assert_eq!(principal["description"], "Peter Parker", "{principal:?}");
+
+    // Empty store
+    admin_client.set_default_account_id(account_id.to_string());
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
+
+fn query_ids(response: &Value, index: usize) -> Vec<String> {
+    response
+        .pointer(&format!("/methodResponses/{index}/1/ids"))
+        .and_then(|v| v.as_array())
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+        .iter()
+        .filter_map(|id| id.as_str().map(|id| id.to_string()))
+        .collect()
+}
+
+async fn get_principal(account_id: &Id, principal_id: &Id) -> Value {
+    let response = jmap_request(
+        account_id,
+        r#"[[
+            "Principal/get",
+            {
+             "accountId": "$$",
+             "ids": ["%%"]
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &principal_id.to_string()),
+    )
+    .await;
+    response
+        .pointer("/methodResponses/0/1/list/0")
+        .cloned()
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+}
+
+async fn jmap_request(account_id: &Id, body: impl AsRef<str>) -> Value {
+    jmap_json_request(
+        body.as_ref().replace("$$", &account_id.to_string()),
+        "peter@example.com",
+        "pet123",
+    )
+    .await
+}
@@ -32,7 +32,9 @@ use jmap_client::{
 use jmap_proto::types::{collection::Collection, id::Id};
 
 use crate::{
-    directory::sql::{add_to_group, create_test_user_with_email, set_test_quota},
+    directory::sql::{
+        add_to_group, create_test_group, create_test_user_with_email, set_test_quota,
+    },
     jmap::{
         delivery::SmtpConnection, jmap_raw_request, mailbox::destroy_all_mailboxes,
         test_account_login,
@@ -48,6 +50,8 @@ pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
     let account_id = Id::from(server.get_account_id("robert@example.com").await.unwrap());
     set_test_quota(directory, "robert@example.com", 1024).await;
     add_to_group(directory, "robert@example.com", "jdoe@example.com").await;
+    create_test_group(directory, "sieve-limited", "Limited Sieve users").await;
+    add_to_group(directory, "robert@example.com", "sieve-limited").await;
 
     // Delete temporary blobs from previous tests
     server
@@ -333,6 +337,43 @@ pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
     );
     DISABLE_UPLOAD_QUOTA.store(true, std::sync::atomic::Ordering::Relaxed);
 
+    // Test Sieve script limits (overridden for the 'sieve-limited' group)
+    let mut script_ids = Vec::new();
+    for i in 0..2 {
+        script_ids.push(
+            client
+                .sieve_script_create(
+                    format!("script_{i}"),
+                    format!("require \"fileinto\"; fileinto \"{i}\";").into_bytes(),
+                    false,
+                )
+                .await
+                .unwrap()
+                .take_id(),
+        );
+    }
+    assert_over_quota(
+        client
+            .sieve_script_create("script_2", b"keep;".to_vec(), false)
+            .await,
+    );
+    client
+        .sieve_script_destroy(&script_ids.pop().unwrap())
+        .await
+        .unwrap();
+    let mut large_script = b"require \"fileinto\";\r\n".to_vec();
+    while large_script.len() < 250 {
+        large_script.extend_from_slice(b"fileinto \"a\";\r\n");
+    }
+    assert_over_quota(
+        client
+            .sieve_script_create("script_large", large_script, false)
+            .await,
+    );
+    for script_id in script_ids {
+        client.sieve_script_destroy(&script_id).await.unwrap();
+    }
+
     // Remove test data
     for account_id in [&account_id, &other_account_id] {
         admin_client.set_default_account_id(account_id.to_string());
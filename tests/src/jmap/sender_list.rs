@@ -0,0 +1,228 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::{mailbox::INBOX_ID, JMAP};
+use jmap_client::client::Client;
+use jmap_proto::types::{collection::Collection, id::Id, property::Property};
+use reqwest::Method;
+use serde_json::json;
+use utils::ipc::DeliveryResult;
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{delivery::SmtpConnection, mailbox::destroy_all_mailboxes, settings::settings_request},
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running sender allow/block list tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "alice@example.com", "ali123", "Alice Doe").await;
+    let account_id = server.get_account_id("alice@example.com").await.unwrap();
+    let login = "alice@example.com";
+
+    // Lists are empty by default
+    let (code, response) =
+        settings_request(Method::GET, "sender-lists", login, "ali123", None).await;
+    assert_eq!(code, 200, "{response}");
+    assert_eq!(response["allow"], json!([]), "{response}");
+    assert_eq!(response["block"], json!([]), "{response}");
+    assert_eq!(response["blockAction"], "trash", "{response}");
+
+    // Invalid entries are rejected
+    let (code, response) = settings_request(
+        Method::PUT,
+        "sender-lists",
+        login,
+        "ali123",
+        json!({"block": ["not a domain"]}).into(),
+    )
+    .await;
+    assert_eq!(code, 400, "{response}");
+
+    // Block a domain but allow one of its addresses
+    let (code, response) = settings_request(
+        Method::PUT,
+        "sender-lists",
+        login,
+        "ali123",
+        json!({
+            "allow": ["Friend@Spammy.org", "@trusted.org"],
+            "block": ["spammy.org"],
+            "blockAction": "trash"
+        })
+        .into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    let (_, response) = settings_request(Method::GET, "sender-lists", login, "ali123", None).await;
+    assert_eq!(
+        response["allow"],
+        json!(["friend@spammy.org", "trusted.org"]),
+        "{response}"
+    );
+
+    // Blocked senders are filed into Trash and allow entries are ignored for
+    // unauthenticated senders. Spam verdicts are only trusted when added by the
+    // spam filter, not when supplied by the sender.
+    let mut lmtp = SmtpConnection::connect().await;
+    for (sender, subject, spam_test, spam_status) in [
+        ("offers@spammy.org", "Blocked", "no", "No"),
+        ("offers@mail.spammy.org", "Blocked subdomain", "no", "No"),
+        ("friend@spammy.org", "Forged allowed", "no", "No"),
+        ("boss@trusted.org", "Forged allowed domain", "yes", "No"),
+        ("unknown@remote.org", "Spam", "yes", "No, score=0.0"),
+        ("other@remote.org", "Ham", "no", "Yes, score=9.0"),
+    ] {
+        lmtp.ingest(
+            sender,
+            &["alice@example.com"],
+            &format!(
+                concat!(
+                    "X-Spam-Test: {}\r\n",
+                    "X-Spam-Status: {}\r\n",
+                    "From: {}\r\n",
+                    "To: alice@example.com\r\n",
+                    "Subject: {}\r\n",
+                    "\r\n",
+                    "Test message.\r\n"
+                ),
+                spam_test, spam_status, sender, subject
+            ),
+        )
+        .await;
+    }
+
+    // Allowlisted senders that passed authentication bypass the block list and spam verdicts
+    for (sender, auth_results) in [
+        ("friend@spammy.org", "dmarc=pass header.from=spammy.org"),
+        (
+            "boss@mail.trusted.org",
+            "dkim=pass header.d=trusted.org header.s=default",
+        ),
+    ] {
+        assert!(matches!(
+            server
+                .deliver_to_account(
+                    format!(
+                        concat!(
+                            "X-Spam-Status: Yes, score=9.0\r\n",
+                            "Authentication-Results: jmap.example.org;\r\n\t{}\r\n",
+                            "From: {}\r\n",
+                            "To: alice@example.com\r\n",
+                            "Subject: Allowed\r\n",
+                            "\r\n",
+                            "Test message.\r\n"
+                        ),
+                        auth_results, sender
+                    )
+                    .as_bytes(),
+                    sender,
+                    "alice@example.com",
+                    "alice@example.com",
+                )
+                .await,
+            DeliveryResult::Success
+        ));
+    }
+    let trash_id = server
+        .mailbox_get_by_role(account_id, "trash")
+        .await
+        .unwrap()
+        .unwrap();
+    let junk_id = server
+        .mailbox_get_by_role(account_id, "junk")
+        .await
+        .unwrap()
+        .unwrap();
+    for (mailbox_id, expected) in [(trash_id, 3), (junk_id, 2), (INBOX_ID, 3)] {
+        assert_eq!(
+            server
+                .get_tag(
+                    account_id,
+                    Collection::Email,
+                    Property::MailboxIds,
+                    mailbox_id
+                )
+                .await
+                .unwrap()
+                .unwrap_or_default()
+                .len(),
+            expected,
+            "mailbox {mailbox_id}"
+        );
+    }
+
+    // Blocked senders can also be rejected
+    let (code, response) = settings_request(
+        Method::PUT,
+        "sender-lists",
+        login,
+        "ali123",
+        json!({
+            "block": ["offers@spammy.org"],
+            "blockAction": "reject"
+        })
+        .into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    lmtp.ingest_with_code(
+        "offers@spammy.org",
+        &["alice@example.com"],
+        concat!(
+            "From: offers@spammy.org\r\n",
+            "To: alice@example.com\r\n",
+            "Subject: Rejected\r\n",
+            "\r\n",
+            "Test message.\r\n"
+        ),
+        5,
+    )
+    .await;
+    lmtp.quit().await;
+    assert_eq!(
+        server
+            .get_document_ids(account_id, Collection::Email)
+            .await
+            .unwrap()
+            .unwrap_or_default()
+            .len(),
+        8
+    );
+
+    // Empty store
+    let (code, response) = settings_request(
+        Method::PUT,
+        "sender-lists",
+        login,
+        "ali123",
+        json!({}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    admin_client.set_default_account_id(Id::from(account_id).to_string());
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
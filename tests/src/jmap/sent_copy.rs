@@ -0,0 +1,132 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::JMAP;
+use jmap_client::client::Client;
+use jmap_proto::types::{collection::Collection, id::Id, property::Property};
+
+use crate::{directory::sql::create_test_user_with_email, jmap::mailbox::destroy_all_mailboxes};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running Sent copy tests...");
+    create_test_user_with_email(
+        server.directory.as_ref(),
+        "sent@example.com",
+        "sent123",
+        "Sent Doe",
+    )
+    .await;
+    let account_id = server.get_account_id("sent@example.com").await.unwrap();
+
+    // Messages submitted over SMTP are filed into the Sent mailbox
+    server
+        .file_sent_copy("sent@example.com", &message("sent-1@example.com"))
+        .await;
+    let sent_id = server
+        .mailbox_get_by_role(account_id, "sent")
+        .await
+        .unwrap()
+        .unwrap();
+    let inbox_id = server
+        .mailbox_get_by_role(account_id, "inbox")
+        .await
+        .unwrap()
+        .unwrap();
+    let sent_ids = mailbox_messages(&server, account_id, sent_id).await;
+    assert_eq!(sent_ids.len(), 1);
+    let document_id = sent_ids[0];
+
+    // Appending to another mailbox is not affected
+    assert_eq!(
+        server
+            .claim_sent_copy(account_id, inbox_id, &message("sent-1@example.com"))
+            .await,
+        None
+    );
+
+    // The client's own copy is matched against the filed one, only once
+    assert_eq!(
+        server
+            .claim_sent_copy(account_id, sent_id, &message("sent-1@example.com"))
+            .await,
+        Some(document_id)
+    );
+    assert_eq!(
+        server
+            .claim_sent_copy(account_id, sent_id, &message("sent-1@example.com"))
+            .await,
+        None
+    );
+
+    // Submissions appended before being filed are not filed again
+    assert_eq!(
+        server
+            .claim_sent_copy(account_id, sent_id, &message("sent-2@example.com"))
+            .await,
+        None
+    );
+    server
+        .file_sent_copy("sent@example.com", &message("sent-2@example.com"))
+        .await;
+    assert_eq!(
+        mailbox_messages(&server, account_id, sent_id).await.len(),
+        1
+    );
+
+    // Empty store
+    admin_client.set_default_account_id(Id::from(account_id).to_string());
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
+
+fn message(message_id: &str) -> Vec<u8> {
+    format!(
+        concat!(
+            "From: sent@example.com\r\n",
+            "To: jdoe@example.com\r\n",
+            "Message-ID: <{}>\r\n",
+            "Subject: Hello\r\n",
+            "\r\n",
+            "Test message.\r\n"
+        ),
+        message_id
+    )
+    .into_bytes()
+}
+
+async fn mailbox_messages(server: &JMAP, account_id: u32, mailbox_id: u32) -> Vec<u32> {
+    server
+        .get_tag(
+            account_id,
+            Collection::Email,
+            Property::MailboxIds,
+            mailbox_id,
+        )
+        .await
+        .unwrap()
+        .unwrap_or_default()
+        .into_iter()
+        .collect()
+}
@@ -0,0 +1,199 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, sync::Arc};
+
+use jmap::{
+    auth::sessions::{
+        token_id, SessionProtocol, METRIC_IMAP_BYTES_IN, METRIC_IMAP_BYTES_OUT,
+        METRIC_SESSION_EVICTED, METRIC_SESSION_MEMORY,
+    },
+    JMAP,
+};
+use jmap_client::client::Client;
+use reqwest::Method;
+use utils::metrics;
+
+use crate::{directory::sql::create_test_user_with_email, jmap::settings::settings_request};
+
+pub async fn test(server: Arc<JMAP>, _client: &mut Client) {
+    println!("Running session management tests...");
+
+    create_test_user_with_email(
+        server.directory.as_ref(),
+        "bill@example.com",
+        "bill_secret",
+        "Bill Foobar",
+    )
+    .await;
+    let account_id = server.get_account_id("bill@example.com").await.unwrap();
+    let login = "bill@example.com";
+    let secret = "bill_secret";
+
+    // Register an IMAP connection and an OAuth token
+    let live_session = server.register_live_session(
+        account_id,
+        SessionProtocol::Imap,
+        "10.0.0.1".parse::<IpAddr>().unwrap().into(),
+    );
+    server
+        .register_token(account_id, "bill_token", "test_client", None, false, 3600)
+        .await
+        .unwrap();
+    let live_id = format!("{:016x}", live_session.id);
+    let token_id = format!("{:016x}", token_id("bill_token"));
+
+    // List sessions
+    let (code, response) = settings_request(Method::GET, "sessions", login, secret, None).await;
+    assert_eq!(code, 200, "{response}");
+    let sessions = response.as_array().unwrap();
+    let imap = sessions
+        .iter()
+        .find(|session| session["id"] == live_id.as_str())
+        .unwrap_or_else(|| panic!("IMAP session not found: {response}"));
+    assert_eq!(imap["type"], "imap");
+    assert_eq!(imap["remoteIp"], "10.0.0.1");
+    let oauth = sessions
+        .iter()
+        .find(|session| session["id"] == token_id.as_str())
+        .unwrap_or_else(|| panic!("OAuth token not found: {response}"));
+    assert_eq!(oauth["type"], "oauth");
+    assert_eq!(oauth["clientId"], "test_client");
+    assert_eq!(oauth["tokenType"], "access");
+    assert!(
+        sessions.iter().any(|session| session["type"] == "http"),
+        "{response}"
+    );
+
+    // Revoke the IMAP connection
+    let revoke_rx = live_session.revoke_rx();
+    let (code, response) = settings_request(
+        Method::DELETE,
+        &format!("sessions/{live_id}"),
+        login,
+        secret,
+        None,
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    assert!(*revoke_rx.borrow());
+
+    // Revoke the OAuth token
+    assert!(!server
+        .is_token_revoked(account_id, "bill_token")
+        .await
+        .unwrap());
+    let (code, response) = settings_request(
+        Method::DELETE,
+        &format!("sessions/{token_id}"),
+        login,
+        secret,
+        None,
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    assert!(server
+        .is_token_revoked(account_id, "bill_token")
+        .await
+        .unwrap());
+    let (_, response) = settings_request(Method::GET, "sessions", login, secret, None).await;
+    assert!(
+        !response
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|session| session["id"] == live_id.as_str() || session["id"] == token_id.as_str()),
+        "{response}"
+    );
+
+    // Unknown sessions cannot be revoked
+    let (code, _) = settings_request(
+        Method::DELETE,
+        &format!("sessions/{live_id}"),
+        login,
+        secret,
+        None,
+    )
+    .await;
+    assert_eq!(code, 404);
+
+    // Revoke all sessions
+    let live_session = server.register_live_session(account_id, SessionProtocol::WebSocket, None);
+    let revoke_rx = live_session.revoke_rx();
+    let (code, _) = settings_request(Method::DELETE, "sessions", login, secret, None).await;
+    assert_eq!(code, 200);
+    assert!(*revoke_rx.borrow());
+    assert!(!server.live_sessions.contains_key(&live_session.id));
+
+    // Resource accounting
+    let session_1 = server.register_live_session(
+        account_id,
+        SessionProtocol::Imap,
+        "10.0.0.2".parse::<IpAddr>().unwrap().into(),
+    );
+    let session_2 = server.register_live_session(account_id, SessionProtocol::WebSocket, None);
+    session_1.record_read(100);
+    session_1.record_write(250);
+    session_1.set_memory(4 * 1024 * 1024);
+    session_2.record_read(10);
+    session_2.set_memory(1024);
+    let report = server.session_report();
+    assert_eq!(report.memory_used, 4 * 1024 * 1024 + 1024);
+    assert_eq!(report.memory_max, Some(10485760));
+    let imap = report
+        .protocols
+        .iter()
+        .find(|p| p.protocol == "imap")
+        .unwrap();
+    assert_eq!(imap.sessions, 1);
+    assert_eq!(imap.bytes_in, 100);
+    assert_eq!(imap.bytes_out, 250);
+    let usage = report
+        .sessions
+        .iter()
+        .find(|s| s.id == format!("{:016x}", session_1.id))
+        .unwrap();
+    assert_eq!(usage.remote_ip.as_deref(), Some("10.0.0.2"));
+    assert_eq!(usage.memory, 4 * 1024 * 1024);
+
+    assert!(metrics::get(METRIC_IMAP_BYTES_IN) >= 100);
+    assert!(metrics::get(METRIC_IMAP_BYTES_OUT) >= 250);
+    assert!(metrics::get(METRIC_SESSION_MEMORY) >= 4 * 1024 * 1024 + 1024);
+
+    // Exceeding the memory limit disconnects the oldest sessions until usage is back under it
+    let revoke_1 = session_1.revoke_rx();
+    let revoke_2 = session_2.revoke_rx();
+    let evicted = metrics::get(METRIC_SESSION_EVICTED);
+    session_2.set_memory(8 * 1024 * 1024);
+    assert!(*revoke_1.borrow());
+    assert!(!*revoke_2.borrow());
+    assert!(!server.live_sessions.contains_key(&session_1.id));
+    assert!(server.live_sessions.contains_key(&session_2.id));
+    assert_eq!(server.session_report().memory_used, 8 * 1024 * 1024);
+    assert!(metrics::get(METRIC_SESSION_EVICTED) > evicted);
+
+    // Memory is released when sessions are dropped
+    drop(session_1);
+    drop(session_2);
+    assert_eq!(server.session_report().memory_used, 0);
+}
@@ -0,0 +1,362 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose, Engine};
+use directory::totp::hotp;
+use jmap::JMAP;
+use jmap_client::client::Client;
+use reqwest::{header, Method};
+use serde_json::{json, Value};
+use store::write::now;
+use tokio::sync::oneshot;
+use utils::ipc::DeliveryEvent;
+
+use crate::directory::sql::create_test_user_with_email;
+
+pub async fn test(server: Arc<JMAP>, _client: &mut Client) {
+    println!("Running self-service settings tests...");
+
+    create_test_user_with_email(
+        server.directory.as_ref(),
+        "jane@example.com",
+        "jane_secret",
+        "Jane Smith",
+    )
+    .await;
+    let account_id = server.get_account_id("jane@example.com").await.unwrap();
+    let login = "jane@example.com";
+
+    // Unauthenticated requests are rejected
+    let (code, _) = settings_request(Method::GET, "", login, "wrong_secret", None).await;
+    assert_eq!(code, 401);
+
+    // Obtain settings
+    let (code, response) = settings_request(Method::GET, "", login, "jane_secret", None).await;
+    assert_eq!(code, 200, "{response}");
+    assert_eq!(response["name"], "jane@example.com");
+    assert_eq!(response["emails"], json!(["jane@example.com"]));
+    assert_eq!(response["twoFactor"], false);
+    assert_eq!(response["canChangePassword"], true);
+    assert_eq!(response["spamTraining"]["enabled"], true);
+
+    // Opt out of spam filter training
+    let (code, response) = settings_request(
+        Method::PUT,
+        "spam-training",
+        login,
+        "jane_secret",
+        json!({"enabled": false}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    assert!(server.spam_train_opted_out(account_id).await.unwrap());
+    assert_eq!(server.spam_train_junk_id(account_id).await.unwrap(), None);
+    let (_, response) = settings_request(Method::GET, "", login, "jane_secret", None).await;
+    assert_eq!(response["spamTraining"]["enabled"], false);
+    let (code, response) = settings_request(
+        Method::PUT,
+        "spam-training",
+        login,
+        "jane_secret",
+        json!({"enabled": true}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    assert!(!server.spam_train_opted_out(account_id).await.unwrap());
+
+    // Change password
+    let (code, response) = settings_request(
+        Method::POST,
+        "password",
+        login,
+        "jane_secret",
+        json!({"currentPassword": "wrong", "newPassword": "new_jane_secret"}).into(),
+    )
+    .await;
+    assert_eq!(code, 400, "{response}");
+    let (code, response) = settings_request(
+        Method::POST,
+        "password",
+        login,
+        "jane_secret",
+        json!({"currentPassword": "jane_secret", "newPassword": "short"}).into(),
+    )
+    .await;
+    assert_eq!(code, 400, "{response}");
+    let (code, response) = settings_request(
+        Method::POST,
+        "password",
+        login,
+        "jane_secret",
+        json!({"currentPassword": "jane_secret", "newPassword": "new_jane_secret"}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    let (code, _) = settings_request(Method::GET, "", login, "jane_secret", None).await;
+    assert_eq!(code, 401);
+    let secret = "new_jane_secret";
+    let (code, _) = settings_request(Method::GET, "", login, secret, None).await;
+    assert_eq!(code, 200);
+
+    // Create an app password
+    let (code, response) = settings_request(
+        Method::POST,
+        "app-passwords",
+        login,
+        secret,
+        json!({"name": "Phone"}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    let app_password = response["password"].as_str().unwrap().to_string();
+    let (code, _) = settings_request(
+        Method::POST,
+        "app-passwords",
+        login,
+        secret,
+        json!({"name": "Phone"}).into(),
+    )
+    .await;
+    assert_eq!(code, 400);
+    let (code, response) = settings_request(Method::GET, "", login, &app_password, None).await;
+    assert_eq!(code, 200);
+    assert_eq!(response["appPasswords"][0]["name"], "Phone");
+    assert!(response["appPasswords"][0].get("password").is_none());
+
+    // Enable two-factor authentication, which requires the current password
+    let (code, _) = settings_request(
+        Method::POST,
+        "totp",
+        login,
+        &app_password,
+        json!({"currentPassword": app_password}).into(),
+    )
+    .await;
+    assert_eq!(code, 400);
+    let (code, response) = settings_request(
+        Method::POST,
+        "totp",
+        login,
+        secret,
+        json!({"currentPassword": secret}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    assert!(response["uri"]
+        .as_str()
+        .unwrap()
+        .starts_with("otpauth://totp/"));
+    let totp_secret = server
+        .get_account_settings(account_id)
+        .await
+        .unwrap()
+        .totp_pending
+        .unwrap();
+    let (code, _) = settings_request(
+        Method::POST,
+        "totp/confirm",
+        login,
+        secret,
+        json!({"code": "000000"}).into(),
+    )
+    .await;
+    assert_eq!(code, 400);
+    let (code, response) = settings_request(
+        Method::POST,
+        "totp/confirm",
+        login,
+        secret,
+        json!({"code": totp_code(&totp_secret)}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+
+    // The password alone is no longer accepted
+    let (code, _) = settings_request(Method::GET, "", login, secret, None).await;
+    assert_eq!(code, 401);
+    let secret_totp = format!("{secret}${}", totp_code(&totp_secret));
+    let (code, response) = settings_request(Method::GET, "", login, &secret_totp, None).await;
+    assert_eq!(code, 200);
+    assert_eq!(response["twoFactor"], true);
+
+    // App passwords bypass the second factor
+    let (code, _) = settings_request(Method::GET, "", login, &app_password, None).await;
+    assert_eq!(code, 200);
+
+    // SMTP AUTH enforces the second factor as well
+    assert_eq!(smtp_authenticate(&server, login, secret).await, None);
+    for secret in [&secret_totp, &app_password] {
+        assert_eq!(
+            smtp_authenticate(&server, login, secret).await.as_deref(),
+            Some(login)
+        );
+    }
+    let (result_tx, result_rx) = oneshot::channel();
+    server
+        .smtp
+        .core()
+        .delivery_tx
+        .send(DeliveryEvent::RequiresSecondFactor {
+            account: login.to_string(),
+            result_tx,
+        })
+        .await
+        .unwrap();
+    assert!(result_rx.await.unwrap());
+
+    // Revoke the app password
+    let (code, _) = settings_request(
+        Method::DELETE,
+        "app-passwords/Phone",
+        login,
+        &secret_totp,
+        None,
+    )
+    .await;
+    assert_eq!(code, 200);
+    let (code, _) = settings_request(Method::GET, "", login, &app_password, None).await;
+    assert_eq!(code, 401);
+
+    // Disable two-factor authentication
+    let (code, response) = settings_request(
+        Method::POST,
+        "totp/disable",
+        login,
+        &secret_totp,
+        json!({"currentPassword": secret}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    let (code, _) = settings_request(Method::GET, "", login, secret, None).await;
+    assert_eq!(code, 200);
+
+    // Forwarding
+    let (code, _) = settings_request(
+        Method::PUT,
+        "forwarding",
+        login,
+        secret,
+        json!({"addresses": ["jane@example.com"]}).into(),
+    )
+    .await;
+    assert_eq!(code, 400);
+    let (code, _) = settings_request(
+        Method::PUT,
+        "forwarding",
+        login,
+        secret,
+        json!({"addresses": ["not-an-address"]}).into(),
+    )
+    .await;
+    assert_eq!(code, 400);
+    let (code, response) = settings_request(
+        Method::PUT,
+        "forwarding",
+        login,
+        secret,
+        json!({"addresses": ["Jane@Remote.org"], "keepCopy": true}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    let (_, response) = settings_request(Method::GET, "", login, secret, None).await;
+    assert_eq!(
+        response["forwarding"],
+        json!({"addresses": ["jane@remote.org"], "keepCopy": true})
+    );
+
+    // Sessions
+    let (code, response) = settings_request(Method::GET, "sessions", login, secret, None).await;
+    assert_eq!(code, 200);
+    assert!(!response.as_array().unwrap().is_empty(), "{response}");
+
+    // Remove forwarding
+    let (code, _) = settings_request(
+        Method::PUT,
+        "forwarding",
+        login,
+        secret,
+        json!({"addresses": []}).into(),
+    )
+    .await;
+    assert_eq!(code, 200);
+}
+
+async fn smtp_authenticate(server: &JMAP, username: &str, secret: &str) -> Option<String> {
+    let (result_tx, result_rx) = oneshot::channel();
+    server
+        .smtp
+        .core()
+        .delivery_tx
+        .send(DeliveryEvent::Authenticate {
+            username: username.to_string(),
+            secret: secret.to_string(),
+            result_tx,
+        })
+        .await
+        .unwrap();
+    result_rx.await.unwrap()
+}
+
+fn totp_code(secret: &[u8]) -> String {
+    format!("{:06}", hotp(secret, now() / 30, 6))
+}
+
+pub async fn settings_request(
+    method: Method,
+    path: &str,
+    login: &str,
+    secret: &str,
+    body: Option<Value>,
+) -> (u16, Value) {
+    let mut headers = header::HeaderMap::new();
+    headers.insert(
+        header::AUTHORIZATION,
+        header::HeaderValue::from_str(&format!(
+            "Basic {}",
+            general_purpose::STANDARD.encode(format!("{login}:{secret}"))
+        ))
+        .unwrap(),
+    );
+
+    let mut request = reqwest::Client::builder()
+        .timeout(Duration::from_millis(5000))
+        .danger_accept_invalid_certs(true)
+        .default_headers(headers)
+        .build()
+        .unwrap_or_default()
+        .request(
+            method,
+            format!("https://127.0.0.1:8899/settings/{path}").trim_end_matches('/'),
+        );
+    if let Some(body) = body {
+        request = request.body(body.to_string());
+    }
+    let response = request.send().await.unwrap();
+
+    (
+        response.status().as_u16(),
+        serde_json::from_slice(&response.bytes().await.unwrap()).unwrap_or_default(),
+    )
+}
@@ -0,0 +1,381 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::{mailbox::INBOX_ID, JMAP};
+use jmap_client::{client::Client, mailbox::Role, principal::ACL};
+use jmap_proto::types::id::Id;
+use reqwest::Method;
+use serde_json::{json, Value};
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{
+        jmap_json_request, mailbox::destroy_all_mailboxes, settings::settings_request,
+        test_account_login,
+    },
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running sharing invitation tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "robert@example.com", "rob123", "Robert Brown").await;
+    create_test_user_with_email(directory, "maria@example.com", "mar123", "Maria Garcia").await;
+    let robert_id = Id::from(server.get_account_id("robert@example.com").await.unwrap());
+    let maria_id = Id::from(server.get_account_id("maria@example.com").await.unwrap());
+    let inbox_id = Id::new(INBOX_ID as u64).to_string();
+    let robert_client = test_account_login("robert@example.com", "rob123").await;
+    let mut maria_client = test_account_login("maria@example.com", "mar123").await;
+
+    // Maria requires shared mailboxes to be accepted
+    let (code, response) = settings_request(
+        Method::PUT,
+        "sharing",
+        "maria@example.com",
+        "mar123",
+        json!({"requireAcceptance": true}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    let (_, response) =
+        settings_request(Method::GET, "", "maria@example.com", "mar123", None).await;
+    assert_eq!(response["sharing"]["requireAcceptance"], true, "{response}");
+
+    // Sharing Robert's Inbox creates an invitation but no shared account
+    robert_client
+        .mailbox_update_acl(&inbox_id, "maria@example.com", [ACL::Read, ACL::ReadItems])
+        .await
+        .unwrap();
+    maria_client.refresh_session().await.unwrap();
+    assert!(maria_client
+        .session()
+        .account(&robert_id.to_string())
+        .is_none());
+    let invitations = get_invitations(&maria_id).await;
+    assert_eq!(invitations.len(), 1, "{invitations:?}");
+    let invitation = &invitations[0];
+    let inbox_invitation_id = invitation["id"].as_str().unwrap().to_string();
+    assert_eq!(invitation["state"], "pending", "{invitation:?}");
+    assert_eq!(
+        invitation["createdBy"], "robert@example.com",
+        "{invitation:?}"
+    );
+    assert_eq!(
+        invitation["objectAccountId"],
+        robert_id.to_string(),
+        "{invitation:?}"
+    );
+    assert_eq!(invitation["objectId"], inbox_id, "{invitation:?}");
+    assert_eq!(invitation["name"], "Inbox", "{invitation:?}");
+    assert_eq!(
+        invitation["myRights"],
+        json!(["read", "readItems"]),
+        "{invitation:?}"
+    );
+
+    // Robert still lists Maria as a grantee
+    assert!(mailbox_acl(&robert_id, &inbox_id)
+        .await
+        .get("maria@example.com")
+        .is_some());
+
+    // Maria is also notified by e-mail
+    let response = jmap_request(
+        &maria_id,
+        r#"[[
+            "Email/query",
+            {
+             "accountId": "$$",
+             "filter": { "subject": "shared the folder" }
+            },
+            "R1"
+           ]]"#,
+    )
+    .await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/ids")
+            .and_then(|v| v.as_array())
+            .map(|ids| ids.len()),
+        Some(1),
+        "Response: {response:?}"
+    );
+
+    // Invitations cannot be created by clients nor set to arbitrary states
+    let response = jmap_request(
+        &maria_id,
+        r#"[[
+            "ShareInvitation/set",
+            {
+             "accountId": "$$",
+             "create": {
+              "i1": { "state": "accepted" }
+             },
+             "update": {
+              "%%": { "state": "pending" }
+             }
+            },
+            "R1"
+           ]]"#
+        .replace("%%", &inbox_invitation_id),
+    )
+    .await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/notCreated/i1/type")
+            .and_then(|v| v.as_str()),
+        Some("forbidden"),
+        "Response: {response:?}"
+    );
+    assert_eq!(
+        response
+            .pointer(&format!(
+                "/methodResponses/0/1/notUpdated/{inbox_invitation_id}/type"
+            ))
+            .and_then(|v| v.as_str()),
+        Some("invalidProperties"),
+        "Response: {response:?}"
+    );
+
+    // Accepting the invitation makes Robert's account available to Maria
+    answer_invitation(&maria_id, &inbox_invitation_id, "accepted", true).await;
+    maria_client.refresh_session().await.unwrap();
+    assert_eq!(
+        maria_client
+            .session()
+            .account(&robert_id.to_string())
+            .unwrap()
+            .name(),
+        "robert@example.com"
+    );
+    assert_eq!(
+        get_invitations(&maria_id).await[0]["state"],
+        "accepted",
+        "Invitation was not updated"
+    );
+
+    // Invitations can only be answered once
+    answer_invitation(&maria_id, &inbox_invitation_id, "declined", false).await;
+
+    // Changing the rights of an accepted share does not require a new invitation
+    robert_client
+        .mailbox_update_acl(
+            &inbox_id,
+            "maria@example.com",
+            [ACL::Read, ACL::ReadItems, ACL::AddItems],
+        )
+        .await
+        .unwrap();
+    assert_eq!(get_invitations(&maria_id).await.len(), 1);
+
+    // Declining an invitation removes the grant
+    let projects_id = robert_client
+        .mailbox_create("Projects", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+    robert_client
+        .mailbox_update_acl(&projects_id, "maria@example.com", [ACL::Read])
+        .await
+        .unwrap();
+    let invitations = get_invitations(&maria_id).await;
+    assert_eq!(invitations.len(), 2, "{invitations:?}");
+    let projects_invitation_id = invitations
+        .iter()
+        .find(|invitation| invitation["objectId"] == projects_id.as_str())
+        .and_then(|invitation| invitation["id"].as_str())
+        .unwrap()
+        .to_string();
+    answer_invitation(&maria_id, &projects_invitation_id, "declined", true).await;
+    assert!(mailbox_acl(&robert_id, &projects_id)
+        .await
+        .get("maria@example.com")
+        .is_none());
+
+    // Pending invitations are withdrawn when the share is revoked or the mailbox destroyed
+    let archive_id = robert_client
+        .mailbox_create("Archive", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+    robert_client
+        .mailbox_update_acl(&archive_id, "maria@example.com", [ACL::Read])
+        .await
+        .unwrap();
+    assert_eq!(get_invitations(&maria_id).await.len(), 3);
+    robert_client
+        .mailbox_update_acl(&archive_id, "maria@example.com", [])
+        .await
+        .unwrap();
+    assert_eq!(get_invitations(&maria_id).await.len(), 2);
+    robert_client
+        .mailbox_update_acl(&archive_id, "maria@example.com", [ACL::Read])
+        .await
+        .unwrap();
+    assert_eq!(get_invitations(&maria_id).await.len(), 3);
+    robert_client
+        .mailbox_destroy(&archive_id, true)
+        .await
+        .unwrap();
+    let invitations = get_invitations(&maria_id).await;
+    assert_eq!(invitations.len(), 2, "{invitations:?}");
+    assert!(invitations
+        .iter()
+        .all(|invitation| invitation["objectId"] != archive_id.as_str()));
+
+    // Accounts that do not require acceptance are granted access immediately
+    let (code, response) = settings_request(
+        Method::PUT,
+        "sharing",
+        "maria@example.com",
+        "mar123",
+        json!({"requireAcceptance": false}).into(),
+    )
+    .await;
+    assert_eq!(code, 200, "{response}");
+    robert_client
+        .mailbox_update_acl(&projects_id, "maria@example.com", [ACL::Read])
+        .await
+        .unwrap();
+    assert_eq!(get_invitations(&maria_id).await.len(), 2);
+
+    // Destroy invitations
+    let response = jmap_request(
+        &maria_id,
+        r#"[[
+            "ShareInvitation/set",
+            {
+             "accountId": "$$",
+             "destroy": ["%1", "%2"]
+            },
+            "R1"
+           ]]"#
+        .replace("%1", &inbox_invitation_id)
+        .replace("%2", &projects_invitation_id),
+    )
+    .await;
+    assert_eq!(
+        response
+            .pointer("/methodResponses/0/1/destroyed")
+            .and_then(|v| v.as_array())
+            .map(|ids| ids.len()),
+        Some(2),
+        "Response: {response:?}"
+    );
+
+    // Empty store
+    for account_id in [&robert_id, &maria_id] {
+        admin_client.set_default_account_id(account_id.to_string());
+        destroy_all_mailboxes(admin_client).await;
+    }
+    server.store.assert_is_empty().await;
+}
+
+async fn answer_invitation(account_id: &Id, invitation_id: &str, state: &str, success: bool) {
+    let response = jmap_request(
+        account_id,
+        r#"[[
+            "ShareInvitation/set",
+            {
+             "accountId": "$$",
+             "update": {
+              "%%": { "state": "@@" }
+             }
+            },
+            "R1"
+           ]]"#
+        .replace("%%", invitation_id)
+        .replace("@@", state),
+    )
+    .await;
+    if success {
+        assert!(
+            response
+                .pointer(&format!("/methodResponses/0/1/updated/{invitation_id}"))
+                .is_some(),
+            "Response: {response:?}"
+        );
+    } else {
+        assert_eq!(
+            response
+                .pointer(&format!(
+                    "/methodResponses/0/1/notUpdated/{invitation_id}/type"
+                ))
+                .and_then(|v| v.as_str()),
+            Some("forbidden"),
+            "Response: {response:?}"
+        );
+    }
+}
+
+async fn get_invitations(account_id: &Id) -> Vec<Value> {
+    let response = jmap_request(
+        account_id,
+        r#"[[
+            "ShareInvitation/get",
+            {
+             "accountId": "$$"
+            },
+            "R1"
+           ]]"#,
+    )
+    .await;
+    response
+        .pointer("/methodResponses/0/1/list")
+        .and_then(|v| v.as_array())
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+        .clone()
+}
+
+async fn mailbox_acl(account_id: &Id, mailbox_id: &str) -> Value {
+    let response = jmap_json_request(
+        r#"[[
+            "Mailbox/get",
+            {
+             "accountId": "$$",
+             "ids": ["%%"],
+             "properties": ["acl"]
+            },
+            "R1"
+           ]]"#
+        .replace("$$", &account_id.to_string())
+        .replace("%%", mailbox_id),
+        "robert@example.com",
+        "rob123",
+    )
+    .await;
+    response
+        .pointer("/methodResponses/0/1/list/0/acl")
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+        .clone()
+}
+
+async fn jmap_request(account_id: &Id, body: impl AsRef<str>) -> Value {
+    jmap_json_request(
+        body.as_ref().replace("$$", &account_id.to_string()),
+        "maria@example.com",
+        "mar123",
+    )
+    .await
+}
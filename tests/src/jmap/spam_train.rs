@@ -0,0 +1,141 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::{mailbox::INBOX_ID, JMAP};
+use jmap_client::{client::Client, email::query::Filter, mailbox::Role};
+use jmap_proto::types::id::Id;
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{delivery::SmtpConnection, mailbox::destroy_all_mailboxes},
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running spam training tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "trainer@example.com", "train123", "Trainer").await;
+    let account_id = server.get_account_id("trainer@example.com").await.unwrap();
+    admin_client.set_default_account_id(Id::from(account_id).to_string());
+    let junk_id = admin_client
+        .mailbox_create("Junk", None::<String>, Role::Junk)
+        .await
+        .unwrap()
+        .take_id();
+    let inbox_id = Id::from(INBOX_ID).to_string();
+
+    let mut lmtp = SmtpConnection::connect().await;
+    lmtp.ingest(
+        "sender@remote.org",
+        &["trainer@example.com"],
+        concat!(
+            "From: sender@remote.org\r\n",
+            "To: trainer@example.com\r\n",
+            "Subject: Cheap watches\r\n",
+            "\r\n",
+            "Buy cheap watches now.\r\n"
+        ),
+    )
+    .await;
+    lmtp.quit().await;
+    let email_id = admin_client
+        .email_query(None::<Filter>, None::<Vec<_>>)
+        .await
+        .unwrap()
+        .take_ids()
+        .pop()
+        .unwrap();
+    let document_id = Id::from_bytes(email_id.as_bytes()).unwrap().document_id();
+
+    // Moving a message to Junk queues it as spam
+    admin_client
+        .email_set_mailboxes(&email_id, [&junk_id])
+        .await
+        .unwrap();
+    assert_eq!(
+        pending(&server, account_id).await,
+        vec![(account_id, document_id, true)]
+    );
+
+    // Moving it back before it was trained replaces the pending verdict
+    admin_client
+        .email_set_mailboxes(&email_id, [&inbox_id])
+        .await
+        .unwrap();
+    assert_eq!(
+        pending(&server, account_id).await,
+        vec![(account_id, document_id, false)]
+    );
+
+    // Moves that do not involve Inbox and Junk are not queued
+    let other_id = admin_client
+        .mailbox_create("Other", None::<String>, Role::None)
+        .await
+        .unwrap()
+        .take_id();
+    admin_client
+        .email_set_mailboxes(&email_id, [&other_id])
+        .await
+        .unwrap();
+    assert_eq!(pending(&server, account_id).await.len(), 1);
+
+    // Processing a batch consumes pending requests, training fails without a
+    // spam database so no verdict is recorded for the message
+    let report = server.spam_train_report.summary();
+    assert!(!server.spam_train_batch(100).await.unwrap());
+    assert!(pending(&server, account_id).await.is_empty());
+    assert_eq!(
+        server
+            .spam_train_verdict(account_id, document_id)
+            .await
+            .unwrap(),
+        None
+    );
+    assert!(server.spam_train_report.summary().failed > report.failed);
+
+    // Requests for deleted messages are skipped
+    admin_client
+        .email_set_mailboxes(&email_id, [&junk_id])
+        .await
+        .unwrap();
+    admin_client.email_destroy(&email_id).await.unwrap();
+    let report = server.spam_train_report.summary();
+    assert!(!server.spam_train_batch(100).await.unwrap());
+    assert!(pending(&server, account_id).await.is_empty());
+    assert!(server.spam_train_report.summary().skipped > report.skipped);
+
+    // Empty store
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
+
+async fn pending(server: &JMAP, account_id: u32) -> Vec<(u32, u32, bool)> {
+    server
+        .spam_train_pending(usize::MAX)
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|(pending_account_id, _, _)| *pending_account_id == account_id)
+        .collect()
+}
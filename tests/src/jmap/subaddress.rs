@@ -0,0 +1,208 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::sync::Arc;
+
+use jmap::{mailbox::INBOX_ID, JMAP};
+use jmap_client::client::Client;
+use jmap_proto::types::id::Id;
+use serde_json::Value;
+
+use crate::{
+    directory::sql::create_test_user_with_email,
+    jmap::{delivery::SmtpConnection, jmap_json_request, mailbox::destroy_all_mailboxes},
+};
+
+pub async fn test(server: Arc<JMAP>, admin_client: &mut Client) {
+    println!("Running subaddress tests...");
+    let directory = server.directory.as_ref();
+    create_test_user_with_email(directory, "rosa@example.com", "ros123", "Rosa Doe").await;
+    let account_id = Id::from(server.get_account_id("rosa@example.com").await.unwrap());
+
+    // Messages to subaddresses are delivered to the account
+    let mut lmtp = SmtpConnection::connect().await;
+    for (rcpt, subject) in [
+        ("rosa+Newsletters@example.com", "Weekly news"),
+        ("rosa+newsletters@example.com", "Monthly news"),
+        ("rosa+shop@example.com", "Order confirmation"),
+        ("rosa@example.com", "Hello"),
+    ] {
+        lmtp.ingest(
+            "sender@remote.org",
+            &[rcpt],
+            &format!(
+                concat!(
+                    "From: sender@remote.org\r\n",
+                    "To: {}\r\n",
+                    "Subject: {}\r\n",
+                    "\r\n",
+                    "Test message.\r\n"
+                ),
+                rcpt, subject
+            ),
+        )
+        .await;
+    }
+
+    // Filter by subaddress, matching is case insensitive
+    let ids = query(&account_id, "NEWSLETTERS").await;
+    assert_eq!(ids.len(), 2, "{ids:?}");
+    assert_eq!(query(&account_id, "shop").await.len(), 1);
+    assert!(query(&account_id, "rosa").await.is_empty());
+
+    // The subaddress is returned as received
+    let emails = get(&account_id, &ids).await;
+    let mut subaddresses = emails
+        .iter()
+        .map(|email| {
+            email
+                .pointer("/subaddress")
+                .and_then(|v| v.as_str())
+                .unwrap()
+        })
+        .collect::<Vec<_>>();
+    subaddresses.sort_unstable();
+    assert_eq!(subaddresses, ["Newsletters", "newsletters"]);
+    let hello = query_subject(&account_id, "Hello").await;
+    assert_eq!(
+        get(&account_id, &hello).await[0].pointer("/subaddress"),
+        Some(&Value::Null)
+    );
+
+    // Sieve scripts can branch on the subaddress
+    admin_client.set_default_account_id(account_id.to_string());
+    let script_id = admin_client
+        .sieve_script_create(
+            "subaddress",
+            concat!(
+                "require [\"fileinto\", \"mailbox\", \"variables\"];\r\n",
+                "if string \"${env.subaddress}\" \"shop\" {\r\n",
+                "    fileinto :create \"Shopping\";\r\n",
+                "}\r\n"
+            )
+            .as_bytes()
+            .to_vec(),
+            true,
+        )
+        .await
+        .unwrap()
+        .take_id();
+    lmtp.ingest(
+        "sender@remote.org",
+        &["rosa+shop@example.com"],
+        concat!(
+            "From: sender@remote.org\r\n",
+            "To: rosa+shop@example.com\r\n",
+            "Subject: Shipping notice\r\n",
+            "\r\n",
+            "Test message.\r\n"
+        ),
+    )
+    .await;
+    let ids = query(&account_id, "shop").await;
+    assert_eq!(ids.len(), 2, "{ids:?}");
+    let shipping = query_subject(&account_id, "Shipping").await;
+    assert_eq!(shipping.len(), 1);
+    let email = &get(&account_id, &shipping).await[0];
+    assert_eq!(
+        email.pointer("/subaddress").and_then(|v| v.as_str()),
+        Some("shop")
+    );
+    let mailbox_ids = email
+        .pointer("/mailboxIds")
+        .and_then(|v| v.as_object())
+        .unwrap();
+    assert_eq!(mailbox_ids.len(), 1);
+    assert!(!mailbox_ids.contains_key(&Id::from(INBOX_ID).to_string()));
+
+    // Empty store
+    admin_client.sieve_script_deactivate().await.unwrap();
+    admin_client.sieve_script_destroy(&script_id).await.unwrap();
+    destroy_all_mailboxes(admin_client).await;
+    server.store.assert_is_empty().await;
+}
+
+async fn query(account_id: &Id, subaddress: &str) -> Vec<String> {
+    query_filter(account_id, format!("{{\"subaddress\": \"{subaddress}\"}}")).await
+}
+
+async fn query_subject(account_id: &Id, subject: &str) -> Vec<String> {
+    query_filter(account_id, format!("{{\"subject\": \"{subject}\"}}")).await
+}
+
+async fn query_filter(account_id: &Id, filter: String) -> Vec<String> {
+    let response = jmap_request(
+        account_id,
+        format!(
+            r#"[[
+            "Email/query",
+            {{
+             "accountId": "$$",
+             "filter": {filter}
+            }},
+            "R1"
+           ]]"#
+        ),
+    )
+    .await;
+    response
+        .pointer("/methodResponses/0/1/ids")
+        .and_then(|v| v.as_array())
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+        .iter()
+        .map(|id| id.as_str().unwrap().to_string())
+        .collect()
+}
+
+async fn get(account_id: &Id, ids: &[String]) -> Vec<Value> {
+    let response = jmap_request(
+        account_id,
+        format!(
+            r#"[[
+            "Email/get",
+            {{
+             "accountId": "$$",
+             "ids": {},
+             "properties": ["subject", "mailboxIds", "subaddress"]
+            }},
+            "R1"
+           ]]"#,
+            serde_json::to_string(ids).unwrap()
+        ),
+    )
+    .await;
+    response
+        .pointer("/methodResponses/0/1/list")
+        .and_then(|v| v.as_array())
+        .unwrap_or_else(|| panic!("Response: {response:?}"))
+        .clone()
+}
+
+async fn jmap_request(account_id: &Id, body: impl AsRef<str>) -> Value {
+    jmap_json_request(
+        body.as_ref().replace("$$", &account_id.to_string()),
+        "rosa@example.com",
+        "ros123",
+    )
+    .await
+}
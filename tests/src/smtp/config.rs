@@ -32,18 +32,23 @@ use std::{
 
 use tokio::net::TcpSocket;
 
-use utils::config::{Config, DynValue, KeyLookup, Listener, Rate, Server, ServerProtocol};
+use utils::config::{
+    Config, ConfigSource, DynValue, KeyLookup, Listener, Rate, Server, ServerProtocol,
+};
 
 use ahash::AHashMap;
-use directory::{config::ConfigDirectory, Lookup, LookupList};
-
-use smtp::config::{
-    condition::ConfigCondition, if_block::ConfigIf, throttle::ConfigThrottle, Condition,
-    ConditionMatch, Conditions, ConfigContext, EnvelopeKey, IfBlock, IfThen, IpAddrMask,
-    StringMatch, Throttle, THROTTLE_AUTH_AS, THROTTLE_REMOTE_IP, THROTTLE_SENDER_DOMAIN,
+use directory::{config::ConfigDirectory, DirectoryConfig, Lookup, LookupList};
+
+use smtp::{
+    config::{
+        condition::ConfigCondition, if_block::ConfigIf, throttle::ConfigThrottle, Condition,
+        ConditionMatch, Conditions, ConfigContext, EnvelopeKey, IfBlock, IfThen, IpAddrMask,
+        StringMatch, Throttle, THROTTLE_AUTH_AS, THROTTLE_REMOTE_IP, THROTTLE_SENDER_DOMAIN,
+    },
+    core::{reload::SmtpHandle, SMTP},
 };
 
-use super::add_test_certs;
+use super::{add_test_certs, make_temp_dir, TestConfig};
 
 struct TestEnvelope {
     pub local_ip: IpAddr,
@@ -444,6 +449,8 @@ fn parse_servers() {
             tls: None,
             tls_implicit: false,
             max_connections: 8192,
+            acme: vec![],
+            tenants: Default::default(),
         },
         Server {
             id: "smtps".to_string(),
@@ -472,6 +479,8 @@ fn parse_servers() {
             tls: None,
             tls_implicit: true,
             max_connections: 1024,
+            acme: vec![],
+            tenants: Default::default(),
         },
         Server {
             id: "submission".to_string(),
@@ -490,6 +499,8 @@ fn parse_servers() {
             tls: None,
             tls_implicit: true,
             max_connections: 8192,
+            acme: vec![],
+            tenants: Default::default(),
         },
     ];
 
@@ -544,6 +555,23 @@ fn parse_servers() {
             );
         }
     }
+    // Listeners binding to the same address are rejected
+    let config = Config::new(
+        r#"
+[server.listener."smtp"]
+bind = ["127.0.0.1:9925"]
+protocol = "smtp"
+
+[server.listener."lmtp"]
+bind = ["127.0.0.1:9925"]
+protocol = "lmtp"
+"#,
+    )
+    .unwrap();
+    assert!(config
+        .parse_servers()
+        .unwrap_err()
+        .contains("already in use by listener"));
 }
 
 #[tokio::test]
@@ -739,3 +767,80 @@ impl TestEnvelope {
         }
     }
 }
+
+const RELOAD_CONFIG: &str = r#"
+[server]
+hostname = "mx.example.org"
+
+[server.listener."smtp"]
+bind = ["127.0.0.1:9925"]
+protocol = "smtp"
+
+[session.rcpt]
+max-recipients = {MAX_RCPT}
+
+[queue]
+path = "{PATH}"
+
+[report]
+path = "{PATH}"
+"#;
+
+#[tokio::test]
+async fn reload_config() {
+    let temp_dir = make_temp_dir("smtp_reload_test", true);
+    let mut config_path = temp_dir.temp_dir.clone();
+    config_path.push("config.toml");
+    let write_config = |max_rcpt: &str| {
+        fs::write(
+            &config_path,
+            RELOAD_CONFIG
+                .replace("{PATH}", temp_dir.temp_dir.to_str().unwrap())
+                .replace("{MAX_RCPT}", max_rcpt),
+        )
+        .unwrap();
+    };
+
+    // Reloading is not available without a configuration source
+    let core = Arc::new(SMTP::test());
+    assert!(SmtpHandle::new(core.clone()).reload().await.is_err());
+
+    // Apply a new configuration
+    write_config("5");
+    let handle = SmtpHandle::with_reload(
+        core.clone(),
+        ConfigSource {
+            path: config_path.clone(),
+            overlays: vec![],
+        },
+        Config::default(),
+        DirectoryConfig::default(),
+    );
+    let result = handle.reload().await.unwrap();
+    assert_eq!(result.restart_required, vec!["server".to_string()]);
+    let new_core = handle.core();
+    assert!(!Arc::ptr_eq(&core, &new_core));
+    assert_eq!(new_core.session.config.rcpt.max_recipients.default, 5);
+    assert_eq!(core.session.config.rcpt.max_recipients.default, 3);
+
+    // Runtime state is shared with the previous core
+    assert!(Arc::ptr_eq(
+        &core.session.throttle,
+        &new_core.session.throttle
+    ));
+    assert!(Arc::ptr_eq(&core.queue.quota, &new_core.queue.quota));
+    assert!(Arc::ptr_eq(&core.usage.counters, &new_core.usage.counters));
+    assert!(Arc::ptr_eq(&core.sieve.shadow, &new_core.sieve.shadow));
+    assert!(core.queue.tx.same_channel(&new_core.queue.tx));
+
+    // Invalid configurations are rejected and the active core is kept
+    write_config("'invalid'");
+    assert!(handle.reload().await.is_err());
+    assert!(Arc::ptr_eq(&new_core, &handle.core()));
+
+    // Reloading an unchanged configuration does not require a restart
+    write_config("5");
+    let result = handle.reload().await.unwrap();
+    assert!(result.restart_required.is_empty());
+    assert_eq!(handle.core().session.config.rcpt.max_recipients.default, 5);
+}
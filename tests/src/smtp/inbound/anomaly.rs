@@ -0,0 +1,160 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use utils::config::Config;
+
+use crate::smtp::{inbound::TestQueueEvent, session::TestSession, TestConfig, TestSMTP};
+use smtp::{
+    anomaly::{secret_fingerprint, AnomalySignal},
+    config::{anomaly::ConfigAnomaly, AnomalyAction, IfBlock},
+    core::{Session, SMTP},
+};
+
+const CONFIG: &str = r#"
+[anomaly]
+enable = true
+min-signals = 2
+action = "throttle"
+throttle.messages = 2
+volume.min-messages = 3
+"#;
+
+#[tokio::test]
+async fn anomaly_detection() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_anomaly_test");
+    core.anomaly.config = Config::new(CONFIG).unwrap().parse_anomaly().unwrap();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+
+    // Logins that cannot be located do not raise any signal
+    for ip in ["10.0.0.1", "192.168.1.1", "172.16.0.1"] {
+        core.anomaly
+            .record_login("john", ip.parse().unwrap(), None)
+            .await;
+    }
+    assert!(core
+        .anomaly
+        .accounts
+        .get("john")
+        .map_or(true, |activity| activity.signals.is_empty()
+            && activity.locations.is_empty()));
+
+    // The first location is the baseline, a login from another country is flagged
+    core.anomaly
+        .record_location("john", Some("ES".to_string()), None)
+        .await;
+    core.anomaly
+        .record_location("john", Some("ES".to_string()), None)
+        .await;
+    assert!(core.anomaly.list().is_empty());
+    core.anomaly
+        .record_location("john", Some("AS64496".to_string()), None)
+        .await;
+    assert!(core.anomaly.list().is_empty());
+
+    // A volume spike is the second signal, the account gets throttled
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.data.authenticated_as = "john".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    for _ in 0..4 {
+        session
+            .send_message(
+                "john@foobar.org",
+                &["bill@remote.org"],
+                "test:no_dkim",
+                "250",
+            )
+            .await;
+        qr.read_event().await.unwrap_message();
+    }
+    let contained = session.core.anomaly.list();
+    assert_eq!(contained.len(), 1);
+    assert_eq!(contained[0].0, "john");
+    assert_eq!(contained[0].1.action, AnomalyAction::Throttle);
+    assert!(contained[0].1.signals.contains(&AnomalySignal::NewLocation));
+    assert!(contained[0].1.signals.contains(&AnomalySignal::VolumeSpike));
+    assert!(!session
+        .core
+        .anomaly
+        .is_contained("john", AnomalyAction::PasswordReset));
+    session
+        .send_message(
+            "john@foobar.org",
+            &["bill@remote.org"],
+            "test:no_dkim",
+            "451 4.7.1",
+        )
+        .await;
+    qr.assert_empty_queue();
+
+    // Clearing the containment lifts the throttle
+    assert!(session.core.anomaly.clear("john"));
+    assert!(!session.core.anomaly.clear("john"));
+    assert!(session.core.anomaly.list().is_empty());
+    session
+        .send_message(
+            "john@foobar.org",
+            &["bill@remote.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    qr.read_event().await.unwrap_message();
+}
+
+#[tokio::test]
+async fn anomaly_password_reset() {
+    let mut core = SMTP::test();
+    core.anomaly.config = Config::new(CONFIG).unwrap().parse_anomaly().unwrap();
+    core.anomaly.config.action = AnomalyAction::PasswordReset;
+    core.anomaly.config.min_signals = 1;
+    let secret = secret_fingerprint(&["old-secret".to_string()]);
+
+    // The account is locked after a login from a new location
+    core.anomaly
+        .record_location("jane", Some("ES".to_string()), Some(secret.clone()))
+        .await;
+    core.anomaly
+        .record_location("jane", Some("US".to_string()), Some(secret.clone()))
+        .await;
+    let contained = core.anomaly.list();
+    assert_eq!(contained.len(), 1);
+    assert_eq!(contained[0].1.action, AnomalyAction::PasswordReset);
+    assert!(core
+        .anomaly
+        .is_contained("jane", AnomalyAction::PasswordReset));
+
+    // Logins with the same or unknown secrets are refused
+    assert!(!core.anomaly.is_login_allowed("jane", Some(&secret)));
+    assert!(!core.anomaly.is_login_allowed("jane", None));
+    assert!(core.anomaly.is_login_allowed("john", Some(&secret)));
+
+    // Changing the password lifts the containment
+    let new_secret = secret_fingerprint(&["new-secret".to_string()]);
+    assert_ne!(secret, new_secret);
+    assert!(core.anomaly.is_login_allowed("jane", Some(&new_secret)));
+    assert!(core.anomaly.list().is_empty());
+    assert!(core.anomaly.is_login_allowed("jane", Some(&secret)));
+}
@@ -91,6 +91,11 @@ comment = '#'
 values = ["bit.ly", "redirect.io", "redirect.me", "redirect.org",
  "redirect.com", "redirect.net", "t.ly", "tinyurl.com"]
 
+[directory."spam".lookup."url-blocklist"]
+type = "glob"
+comment = '#'
+values = ["blocked-url.org", "*.phishing-kit.net"]
+
 [directory."spam".lookup."dmarc-allow"]
 type = "glob"
 comment = '#'
@@ -384,6 +389,12 @@ async fn antispam() {
                                 DkimResult::from_str(value).as_str().to_string().into(),
                             );
                         }
+                        "dkim.replay" => {
+                            variables.insert(
+                                param.to_string(),
+                                Variable::Integer(value.parse().unwrap()),
+                            );
+                        }
                         "dkim.domains" => {
                             variables.insert(
                                 param.to_string(),
@@ -489,6 +500,26 @@ async fn antispam() {
                 .unwrap()
             {
                 ScriptResult::Accept { modifications } => {
+                    // The verdict reported to the SMTP server has to match the X-Spam-Status header
+                    let mut verdict = None;
+                    let modifications = modifications
+                        .into_iter()
+                        .filter(|modification| {
+                            if let ScriptModification::SpamVerdict(is_spam) = modification {
+                                verdict = Some(*is_spam);
+                                false
+                            } else {
+                                true
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    if let Some(status) = expected_headers.get("X-Spam-Status") {
+                        assert_eq!(
+                            verdict,
+                            Some(status.starts_with("Yes")),
+                            "Unexpected spam verdict for status {status:?}"
+                        );
+                    }
                     if modifications.len() != expected_headers.len() {
                         panic!(
                             "Expected {:?} headers, got {:?}",
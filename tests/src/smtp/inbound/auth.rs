@@ -21,17 +21,23 @@
  * for more details.
 */
 
-use directory::config::ConfigDirectory;
-use smtp_proto::{AUTH_LOGIN, AUTH_PLAIN};
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+use base64::{engine::general_purpose, Engine};
+use directory::{config::ConfigDirectory, scram::ScramAlgorithm};
+use mail_parser::decoders::base64::base64_decode;
+use smtp_proto::{AUTH_LOGIN, AUTH_PLAIN, AUTH_SCRAM_SHA_256};
 use utils::config::{Config, DynValue};
 
 use crate::smtp::{
+    inbound::{TestMessage, TestQueueEvent},
     session::{TestSession, VerifyResponse},
-    ParseTestConfig, TestConfig,
+    ParseTestConfig, TestConfig, TestSMTP,
 };
 use smtp::{
-    config::{ConfigContext, EnvelopeKey},
+    config::{anomaly::ConfigAnomaly, AnomalyAction, ConfigContext, EnvelopeKey},
     core::{Session, State, SMTP},
+    inbound::auth::oauthbearer_authzid,
 };
 
 const DIRECTORY: &str = r#"
@@ -78,7 +84,7 @@ async fn auth() {
     config.mechanisms = format!(
         "[{{if = 'remote-ip', eq = '10.0.0.1', then = {}}},
     {{else = 0}}]",
-        AUTH_PLAIN | AUTH_LOGIN
+        AUTH_PLAIN | AUTH_LOGIN | AUTH_SCRAM_SHA_256
     )
     .as_str()
     .parse_if(&ctx);
@@ -96,7 +102,8 @@ async fn auth() {
         .ehlo("mx.foobar.org")
         .await
         .assert_not_contains(" PLAIN")
-        .assert_not_contains(" LOGIN");
+        .assert_not_contains(" LOGIN")
+        .assert_contains(" SCRAM-SHA-256");
 
     // EHLO should advertise AUTH for 10.0.0.1
     session.stream.tls = true;
@@ -152,6 +159,53 @@ async fn auth() {
     session.cmd("amFuZQ==", "334").await;
     session.cmd("cDRzc3cwcmQ=", "235 2.7.0").await;
 
+    // Successful SCRAM-SHA-256 authentication
+    session.data.authenticated_as.clear();
+    session.cmd("AUTH SCRAM-SHA-256", "334").await;
+    let client_first_bare = "n=jane,r=rOprNGfwEbeRWgbNEkqO";
+    let server_first = session
+        .cmd(
+            &general_purpose::STANDARD.encode(format!("n,,{client_first_bare}")),
+            "334",
+        )
+        .await;
+    let (client_final, server_final) = scram_client_final(
+        ScramAlgorithm::Sha256,
+        "p4ssw0rd",
+        client_first_bare,
+        &server_first,
+    );
+    session
+        .cmd(&general_purpose::STANDARD.encode(client_final), "334")
+        .await
+        .assert_contains(&general_purpose::STANDARD.encode(server_final));
+    session.cmd("", "235 2.7.0").await;
+    assert_eq!(session.data.authenticated_as, "jane");
+
+    // SCRAM-SHA-256 with an invalid password should fail
+    session.data.authenticated_as.clear();
+    session.data.auth_errors = 0;
+    let client_first_bare = "n=john,r=fyko+d2lbbFgONRv9qkxdawL";
+    let server_first = session
+        .cmd(
+            &format!(
+                "AUTH SCRAM-SHA-256 {}",
+                general_purpose::STANDARD.encode(format!("n,,{client_first_bare}"))
+            ),
+            "334",
+        )
+        .await;
+    let (client_final, _) = scram_client_final(
+        ScramAlgorithm::Sha256,
+        "wrong",
+        client_first_bare,
+        &server_first,
+    );
+    session
+        .cmd(&general_purpose::STANDARD.encode(client_final), "535 5.7.8")
+        .await;
+    assert!(session.data.authenticated_as.is_empty());
+
     // Login should not be advertised to 10.0.0.2
     session.data.remote_ip = "10.0.0.2".parse().unwrap();
     session.eval_session_params().await;
@@ -166,3 +220,203 @@ async fn auth() {
         .cmd("AUTH PLAIN AGpvaG4Ac2VjcmV0", "503 5.5.1")
         .await;
 }
+
+#[tokio::test]
+async fn auth_client_cert() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_auth_client_cert");
+    core.anomaly.config = Config::new(
+        r#"[anomaly]
+enable = true
+min-signals = 1
+action = "password-reset"
+"#,
+    )
+    .unwrap()
+    .parse_anomaly()
+    .unwrap();
+    let mut ctx = ConfigContext::new(&[]);
+    ctx.directory = Config::new(DIRECTORY).unwrap().parse_directory().unwrap();
+
+    let config = &mut core.session.config;
+    config.auth.directory = "'local'"
+        .parse_if::<Option<DynValue<EnvelopeKey>>>(&ctx)
+        .map_if_block(&ctx.directory.directories, "", "")
+        .unwrap();
+    config.auth.client_cert = "true".parse_if(&ctx);
+    config.auth.client_cert_require = r"[{if = 'remote-ip', eq = '10.0.0.1', then = true},
+    {else = false}]"
+        .parse_if(&ctx);
+    config.rcpt.relay = "true".parse_if(&ctx);
+    config.data.add_received = "true".parse_if(&ctx);
+    config.auth.client_cert_map = Config::new(
+        r#"[[server.tls.client-auth.map]]
+identity = "jane@example.org"
+account = "jane"
+"#,
+    )
+    .unwrap()
+    .parse_client_certificate_map()
+    .unwrap();
+
+    // Load client certificate
+    let mut cert_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    cert_path.push("resources");
+    cert_path.push("smtp");
+    cert_path.push("certs");
+    cert_path.push("client_cert.pem");
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path).unwrap()))
+        .unwrap()
+        .into_iter()
+        .map(rustls::Certificate)
+        .collect::<Vec<_>>();
+
+    // Sessions without a client certificate should be rejected when required
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.stream.tls = true;
+    session.ehlo("mx.foobar.org").await;
+    assert!(session.data.authenticated_as.is_empty());
+    session.mail_from("bill@foobar.org", "530 5.7.0").await;
+
+    // Client certificate should be mapped to an account
+    session.stream.peer_certs = Some(certs.clone());
+    session
+        .ehlo("mx.foobar.org")
+        .await
+        .assert_not_contains("AUTH ");
+    assert_eq!(session.data.authenticated_as, "jane");
+    assert_eq!(session.data.client_cert_identity, "jane@example.org");
+
+    // Mapping should be recorded in the Received header
+    session
+        .send_message(
+            "jane@example.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    qr.read_event()
+        .await
+        .unwrap_message()
+        .read_lines()
+        .assert_contains("(client certificate jane@example.org)");
+
+    // Certificates should not unlock accounts contained pending a password reset
+    for location in ["ES", "US"] {
+        session
+            .core
+            .anomaly
+            .record_location("jane", Some(location.to_string()), None)
+            .await;
+    }
+    assert!(session
+        .core
+        .anomaly
+        .is_contained("jane", AnomalyAction::PasswordReset));
+    let mut session = Session::test(session.core.clone());
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.stream.tls = true;
+    session.stream.peer_certs = Some(certs.clone());
+    session.ehlo("mx.foobar.org").await;
+    assert!(session.data.authenticated_as.is_empty());
+    assert!(session.data.client_cert_identity.is_empty());
+    session.mail_from("jane@example.org", "530 5.7.0").await;
+
+    // Certificates without an explicit mapping should not authenticate,
+    // even when the identity matches a directory account
+    let mut core = SMTP::test();
+    core.session.config.auth.client_cert = "true".parse_if(&ctx);
+    core.session.config.auth.client_cert_require = "true".parse_if(&ctx);
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.2".parse().unwrap();
+    session.eval_session_params().await;
+    session.stream.tls = true;
+    session.stream.peer_certs = Some(certs);
+    session.ehlo("mx.foobar.org").await;
+    assert!(session.data.authenticated_as.is_empty());
+    assert!(session.data.client_cert_identity.is_empty());
+    session.mail_from("bill@foobar.org", "530 5.7.0").await;
+}
+
+#[test]
+fn oauthbearer_authorization_identity() {
+    for (message, expected) in [
+        (
+            "n,a=user@example.com,\x01host=server.example.com\x01port=587\x01auth=Bearer vF9dft4qmTc2Nvb3RlckBhbHRhdmlzdGEuY29tCg==\x01\x01",
+            Some("user@example.com"),
+        ),
+        (
+            "n,a=first=2Clast=3D@example.com,\x01auth=Bearer token\x01\x01",
+            Some("first,last=@example.com"),
+        ),
+        ("n,,\x01auth=Bearer token\x01\x01", None),
+        ("n,a=,\x01auth=Bearer token\x01\x01", None),
+    ] {
+        assert_eq!(
+            oauthbearer_authzid(message).as_deref(),
+            expected,
+            "{message:?}"
+        );
+    }
+}
+
+pub fn scram_client_final(
+    algorithm: ScramAlgorithm,
+    password: &str,
+    client_first_bare: &str,
+    server_first: &[String],
+) -> (String, String) {
+    let server_first = String::from_utf8(
+        base64_decode(
+            server_first
+                .last()
+                .unwrap()
+                .split_once(' ')
+                .unwrap()
+                .1
+                .trim()
+                .as_bytes(),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let mut nonce = "";
+    let mut salt = Vec::new();
+    let mut iterations = 0;
+    for attribute in server_first.split(',') {
+        match attribute.split_once('=').unwrap() {
+            ("r", value) => nonce = value,
+            ("s", value) => salt = base64_decode(value.as_bytes()).unwrap(),
+            ("i", value) => iterations = value.parse().unwrap(),
+            _ => (),
+        }
+    }
+    assert!(nonce.starts_with(client_first_bare.split_once(",r=").unwrap().1));
+
+    let salted_password = algorithm.salted_password(password.as_bytes(), &salt, iterations);
+    let client_key = algorithm.hmac(&salted_password, b"Client Key");
+    let server_key = algorithm.hmac(&salted_password, b"Server Key");
+    let without_proof = format!("c=biws,r={nonce}");
+    let auth_message = format!("{client_first_bare},{server_first},{without_proof}");
+    let client_signature = algorithm.hmac(&algorithm.hash(&client_key), auth_message.as_bytes());
+    let proof = client_key
+        .iter()
+        .zip(client_signature.iter())
+        .map(|(a, b)| a ^ b)
+        .collect::<Vec<_>>();
+
+    (
+        format!(
+            "{without_proof},p={}",
+            general_purpose::STANDARD.encode(proof)
+        ),
+        format!(
+            "v={}",
+            general_purpose::STANDARD.encode(algorithm.hmac(&server_key, auth_message.as_bytes()))
+        ),
+    )
+}
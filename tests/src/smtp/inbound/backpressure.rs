@@ -0,0 +1,129 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::atomic::Ordering, time::Duration};
+
+use crate::smtp::{
+    inbound::TestQueueEvent,
+    session::{DummyIo, TestSession},
+    QueueReceiver, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::IfBlock,
+    core::{QueueCore, Session, SMTP},
+    queue::{
+        backpressure::{
+            BackpressureReason, METRIC_BACKPRESSURE_ACTIVE, METRIC_BACKPRESSURE_DEFERRED,
+        },
+        manager::Queue,
+        Message, OnHold, Timestamp,
+    },
+};
+use utils::metrics;
+
+#[tokio::test]
+async fn queue_backpressure() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_backpressure_test");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.queue.config.backpressure.max_messages = Some(2);
+    let load = core.queue.load.clone();
+
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+
+    // Messages are accepted while the queue is below the limits
+    let first = send(&mut session, &mut qr).await;
+    let second = send(&mut session, &mut qr).await;
+    assert_eq!(load.messages(), 2);
+    assert_eq!(load.size(), first.size + second.size);
+
+    // New transactions are deferred once the queue is full
+    session.mail_from("john@foobar.org", "452 4.3.1").await;
+    assert!(load.deferring.load(Ordering::Relaxed));
+    assert_eq!(metrics::get(METRIC_BACKPRESSURE_ACTIVE), 1);
+    assert!(metrics::get(METRIC_BACKPRESSURE_DEFERRED) > 0);
+
+    // Intake resumes after messages leave the queue
+    drop(first);
+    assert_eq!(load.messages(), 1);
+    session.mail_from("john@foobar.org", "250").await;
+    assert!(!load.deferring.load(Ordering::Relaxed));
+    session.cmd("RSET", "250").await;
+
+    // Size and lag thresholds
+    let mut queue = QueueCore::test();
+    queue.load = load.clone();
+    assert_eq!(queue.backpressure(), None);
+    queue.config.backpressure.max_size = Some(second.size);
+    assert_eq!(queue.backpressure(), Some(BackpressureReason::Size));
+    queue.config.backpressure.max_size = None;
+    queue.config.backpressure.max_lag = Some(Duration::from_secs(3600));
+    load.set_lag(Duration::from_secs(60));
+    assert_eq!(queue.backpressure(), None);
+    load.set_lag(Duration::from_secs(7200));
+    assert_eq!(queue.backpressure(), Some(BackpressureReason::Lag));
+    load.set_lag(Duration::ZERO);
+    assert_eq!(queue.backpressure(), None);
+
+    // Old messages that are not yet due do not count towards the lag,
+    // overdue ones waiting for a delivery slot do
+    let mut backlog = Queue::default();
+    let mut message = second;
+    let queue_id = message.id;
+    let now = Timestamp::now();
+    message.created = 0;
+    for domain in &mut message.domains {
+        domain.retry.due = now + Duration::from_secs(600);
+    }
+    backlog.on_hold(OnHold {
+        next_due: None,
+        limiters: vec![],
+        message,
+    });
+    assert_eq!(backlog.backlog_lag(), Duration::ZERO);
+    for domain in &mut backlog.messages.get_mut(&queue_id).unwrap().domains {
+        domain.retry.due = now - Duration::from_secs(300);
+    }
+    assert!(backlog.backlog_lag() >= Duration::from_secs(300));
+
+    // Dropping messages releases their load
+    drop(backlog);
+    assert_eq!(load.messages(), 0);
+    assert_eq!(load.size(), 0);
+    assert_eq!(queue.backpressure(), None);
+}
+
+async fn send(session: &mut Session<DummyIo>, qr: &mut QueueReceiver) -> Box<Message> {
+    session
+        .send_message(
+            "john@foobar.org",
+            &["bill@remote.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    qr.read_event().await.unwrap_message()
+}
@@ -0,0 +1,287 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{io::Cursor, path::PathBuf};
+
+use mail_auth::{dmarc::Policy, AuthenticatedMessage, DmarcResult};
+use smtp::{
+    config::VerifyStrategy,
+    core::{Session, SMTP},
+    inbound::bimi::{
+        strip_untrusted_headers, verify_vmc, BimiAuthority, BimiRecord, BimiResult,
+        BIMI_TEST_INDICATOR, BIMI_TEST_RECORDS, BIMI_TEST_VMC,
+    },
+};
+
+use crate::smtp::{session::TestSession, TestConfig};
+
+const MESSAGE: &str = concat!(
+    "From: Bill <bill@example.org>\r\n",
+    "To: jdoe@example.com\r\n",
+    "Subject: Brand new logo\r\n",
+    "\r\n",
+    "Hi!\r\n"
+);
+
+#[tokio::test]
+async fn bimi() {
+    let vmc = read_resource("vmc.pem");
+    let not_vmc = read_resource("not_vmc.pem");
+    let not_ca = read_resource("not_ca.pem");
+    let no_logotype = read_resource("no_logotype.pem");
+    let logo = read_resource("logo.svg");
+    let trust_anchors = rustls_pemfile::certs(&mut Cursor::new(read_resource("root.pem"))).unwrap();
+
+    // Parse BIMI records
+    assert_eq!(
+        BimiRecord::parse("v=BIMI1; l=https://example.org/logo.svg; a=https://example.org/vmc.pem"),
+        Some(BimiRecord {
+            location: "https://example.org/logo.svg".to_string().into(),
+            authority: "https://example.org/vmc.pem".to_string().into(),
+        })
+    );
+    assert_eq!(
+        BimiRecord::parse("v=BIMI1; l=; a=;"),
+        Some(BimiRecord {
+            location: None,
+            authority: None,
+        })
+    );
+    assert_eq!(BimiRecord::parse("l=https://example.org/logo.svg"), None);
+    assert_eq!(BimiRecord::parse("v=DMARC1; p=reject"), None);
+    assert_eq!(
+        BimiRecord::parse("v=BIMI1; l=https://example.org/logo.svg\r\nX-Injected: yes"),
+        None
+    );
+
+    // Validate evidence documents
+    let now = 1700000000;
+    assert_eq!(
+        verify_vmc(&vmc, &logo, "example.org", "default", &trust_anchors, now),
+        Ok(())
+    );
+    assert_eq!(
+        verify_vmc(
+            &vmc,
+            &logo,
+            "mail.example.org",
+            "default",
+            &trust_anchors,
+            now
+        ),
+        Ok(())
+    );
+    let other_logo = b"<svg></svg>".to_vec();
+    for (test_num, (pem, indicator, domain, trust_anchors, now)) in [
+        (&not_vmc, &logo, "example.org", &trust_anchors, now),
+        (&vmc, &logo, "example.com", &trust_anchors, now),
+        (&vmc, &logo, "badexample.org", &trust_anchors, now),
+        (&vmc, &logo, "example.org", &vec![], now),
+        (&vmc, &logo, "example.org", &trust_anchors, 0),
+        (&vmc, &other_logo, "example.org", &trust_anchors, now),
+        (&no_logotype, &logo, "example.org", &trust_anchors, now),
+        (&not_ca, &logo, "example.org", &trust_anchors, now),
+        (
+            &b"not a certificate".to_vec(),
+            &logo,
+            "example.org",
+            &trust_anchors,
+            now,
+        ),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        assert!(
+            verify_vmc(pem, indicator, domain, "default", trust_anchors, now).is_err(),
+            "test {test_num}: {domain}"
+        );
+    }
+
+    // Verify BIMI during message delivery
+    let mut core = SMTP::test();
+    core.mail_auth.bimi.trust_anchors = trust_anchors;
+    let session = Session::test(core);
+    let message = AuthenticatedMessage::parse(MESSAGE.as_bytes()).unwrap();
+    *BIMI_TEST_VMC.lock() = vmc;
+    *BIMI_TEST_INDICATOR.lock() = logo;
+    BIMI_TEST_RECORDS.lock().push((
+        "default._bimi.example.org.".to_string(),
+        "v=BIMI1; l=https://example.org/logo.svg; a=https://example.org/vmc.pem".to_string(),
+    ));
+
+    // BIMI is skipped unless DMARC passes with an enforced policy
+    for (dmarc_result, dmarc_policy) in [
+        (DmarcResult::Pass, Policy::None),
+        (DmarcResult::None, Policy::Reject),
+    ] {
+        assert!(matches!(
+            session
+                .verify_bimi(
+                    VerifyStrategy::Relaxed,
+                    &message,
+                    Some(&dmarc_result),
+                    Some(&dmarc_policy)
+                )
+                .await
+                .unwrap()
+                .result,
+            BimiResult::Skipped(_)
+        ));
+    }
+
+    // Validated indicators are added to the Authentication-Results header
+    let output = session
+        .verify_bimi(
+            VerifyStrategy::Relaxed,
+            &message,
+            Some(&DmarcResult::Pass),
+            Some(&Policy::Reject),
+        )
+        .await
+        .unwrap();
+    assert_eq!(output.result, BimiResult::Pass);
+    assert_eq!(output.authority, BimiAuthority::Pass);
+    let mut headers = vec![];
+    output.write_header("mx.example.com", &mut headers);
+    assert_eq!(
+        String::from_utf8(headers).unwrap(),
+        concat!(
+            "Authentication-Results: mx.example.com;\r\n",
+            "\tbimi=pass header.d=example.org header.selector=default ",
+            "policy.authority=pass policy.authority-uri=https://example.org/vmc.pem ",
+            "policy.indicator-uri=https://example.org/logo.svg\r\n",
+            "BIMI-Location: v=BIMI1;\r\n",
+            "\tl=https://example.org/logo.svg;\r\n",
+            "\ta=https://example.org/vmc.pem\r\n",
+        )
+    );
+
+    // Invalid evidence documents fail validation
+    BIMI_TEST_RECORDS.lock().push((
+        "brand._bimi.example.org.".to_string(),
+        "v=BIMI1; l=https://example.org/logo.svg; a=https://example.org/other.pem".to_string(),
+    ));
+    *BIMI_TEST_VMC.lock() = not_vmc;
+    let message = AuthenticatedMessage::parse(
+        format!("BIMI-Selector: v=BIMI1; s=brand\r\n{MESSAGE}").as_bytes(),
+    )
+    .unwrap();
+    let output = session
+        .verify_bimi(
+            VerifyStrategy::Relaxed,
+            &message,
+            Some(&DmarcResult::Pass),
+            Some(&Policy::Quarantine),
+        )
+        .await
+        .unwrap();
+    assert_eq!(output.selector, "brand");
+    assert!(matches!(output.result, BimiResult::Fail(_)));
+    assert_eq!(output.authority, BimiAuthority::Fail);
+    let mut headers = vec![];
+    output.write_header("mx.example.com", &mut headers);
+    let headers = String::from_utf8(headers).unwrap();
+    assert!(headers.contains("bimi=fail"), "{headers}");
+    assert!(!headers.contains("indicator-uri"), "{headers}");
+    assert!(!headers.contains("BIMI-Location"), "{headers}");
+
+    // Missing evidence documents are only accepted in relaxed mode
+    BIMI_TEST_RECORDS.lock().push((
+        "default._bimi.example.net.".to_string(),
+        "v=BIMI1; l=https://example.net/logo.svg".to_string(),
+    ));
+    let message =
+        AuthenticatedMessage::parse(MESSAGE.replace("example.org", "example.net").as_bytes())
+            .unwrap();
+    for (strategy, expected_result) in [
+        (VerifyStrategy::Relaxed, "pass"),
+        (VerifyStrategy::Strict, "fail"),
+    ] {
+        let output = session
+            .verify_bimi(
+                strategy,
+                &message,
+                Some(&DmarcResult::Pass),
+                Some(&Policy::Reject),
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.result.as_str(), expected_result);
+        assert_eq!(output.authority, BimiAuthority::None);
+    }
+
+    // Domains without a BIMI record
+    let message =
+        AuthenticatedMessage::parse(MESSAGE.replace("example.org", "example.com").as_bytes())
+            .unwrap();
+    assert_eq!(
+        session
+            .verify_bimi(
+                VerifyStrategy::Relaxed,
+                &message,
+                Some(&DmarcResult::Pass),
+                Some(&Policy::Reject),
+            )
+            .await
+            .unwrap()
+            .result,
+        BimiResult::None
+    );
+
+    // Inbound BIMI headers and results claiming to be from this server are removed
+    let message = concat!(
+        "Authentication-Results: mx.example.com;\r\n",
+        "\tbimi=pass header.d=example.org header.selector=default\r\n",
+        "Authentication-Results: mx.example.org; dkim=pass\r\n",
+        "BIMI-Location: v=BIMI1;\r\n",
+        "\tl=https://example.org/logo.svg\r\n",
+        "bimi-indicator: PHN2Zz4=\r\n",
+        "From: Bill <bill@example.org>\r\n",
+        "\r\n",
+        "BIMI-Location: body text\r\n"
+    );
+    assert_eq!(
+        String::from_utf8(strip_untrusted_headers(message.as_bytes(), "mx.example.com").unwrap())
+            .unwrap(),
+        concat!(
+            "Authentication-Results: mx.example.org; dkim=pass\r\n",
+            "From: Bill <bill@example.org>\r\n",
+            "\r\n",
+            "BIMI-Location: body text\r\n"
+        )
+    );
+    assert_eq!(
+        strip_untrusted_headers(MESSAGE.as_bytes(), "mx.example.com"),
+        None
+    );
+}
+
+fn read_resource(name: &str) -> Vec<u8> {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("resources");
+    path.push("smtp");
+    path.push("bimi");
+    path.push(name);
+    std::fs::read(path).unwrap()
+}
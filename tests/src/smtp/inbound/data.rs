@@ -95,6 +95,13 @@ async fn data() {
     config.data.add_return_path = config.data.add_auth_results.clone();
     config.data.add_received_spf = config.data.add_auth_results.clone();
     config.data.max_received_headers = IfBlock::new(3);
+    config.data.max_trace_headers = r"[{if = 'remote-ip', eq = '10.0.0.3', then = 2},
+    {else = 0}]"
+        .parse_if(&ConfigContext::new(&[]));
+    config.data.received_template = IfBlock::new(
+        "from {{helo}} {{tls}} {{authenticated-as}} by {{hostname}} with {{protocol}} id {{id}}; {{date}}"
+            .to_string(),
+    );
     config.data.max_messages = r"[{if = 'remote-ip', eq = '10.0.0.1', then = 1},
     {else = 100}]"
         .parse_if(&ConfigContext::new(&[]));
@@ -179,9 +186,29 @@ async fn data() {
         .assert_contains("Date: ")
         .assert_contains("Message-ID: ")
         .assert_contains("Return-Path: ")
-        .assert_contains("Received: ")
+        .assert_contains("Received: from mx.doe.org by ")
         .assert_contains("Authentication-Results: ")
-        .assert_contains("Received-SPF: ");
+        .assert_contains("Received-SPF: ")
+        .assert_not_contains("[10.0.0.3]");
+
+    // Trace headers are capped for 10.0.0.3
+    session
+        .send_message(
+            "john@doe.org",
+            &["mike@test.com"],
+            concat!(
+                "Received: from mx1.doe.org\r\n",
+                "Received: from mx2.doe.org\r\n",
+                "Return-Path: <john@doe.org>\r\n",
+                "From: john@doe.org\r\n",
+                "To: mike@test.com\r\n",
+                "Subject: Trace\r\n",
+                "\r\n",
+                "Hello"
+            ),
+            "554 5.4.6",
+        )
+        .await;
 
     // Only one message is allowed in the queue from john@doe.org
     let mut queued_messages = vec![];
@@ -0,0 +1,110 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use utils::config::Config;
+
+use crate::smtp::TestConfig;
+use smtp::{config::replay::ConfigDkimReplay, core::DkimReplayCore};
+
+const CONFIG: &str = r#"
+[auth.dkim.replay]
+enable = true
+window = "1h"
+max-networks = 2
+"#;
+
+#[test]
+fn dkim_replay() {
+    let mut replay = DkimReplayCore::test();
+    replay.config = Config::new(CONFIG).unwrap().parse_dkim_replay().unwrap();
+    let body_hash = b"body-hash";
+
+    // Signatures seen from the same network are not replays
+    for ip in ["10.0.0.1", "10.0.0.2", "10.0.1.1"] {
+        assert!(!replay.record("example.org", "mail", body_hash, ip.parse().unwrap()));
+    }
+    let entry = replay.get("EXAMPLE.org", "mail", body_hash).unwrap();
+    assert_eq!(entry.count, 3);
+    assert_eq!(entry.networks, vec!["10.0.0.0/16".to_string()]);
+
+    // Signatures seen from more unrelated networks than allowed are replays
+    assert!(!replay.record(
+        "example.org",
+        "mail",
+        body_hash,
+        "10.1.0.1".parse().unwrap()
+    ));
+    assert!(replay.record(
+        "example.org",
+        "mail",
+        body_hash,
+        "192.168.0.1".parse().unwrap()
+    ));
+    assert!(replay.record(
+        "example.org",
+        "mail",
+        body_hash,
+        "10.0.0.1".parse().unwrap()
+    ));
+
+    // Different selectors or body hashes are tracked separately
+    assert!(!replay.record(
+        "example.org",
+        "other",
+        body_hash,
+        "192.168.0.1".parse().unwrap()
+    ));
+    assert!(!replay.record(
+        "example.org",
+        "mail",
+        b"other-hash",
+        "192.168.0.1".parse().unwrap()
+    ));
+
+    // Signatures outside the window are forgotten
+    for mut entry in replay.entries.iter_mut() {
+        entry.first_seen = 0;
+    }
+    assert!(!replay.record(
+        "example.org",
+        "mail",
+        body_hash,
+        "172.16.0.1".parse().unwrap()
+    ));
+    assert_eq!(
+        replay.get("example.org", "mail", body_hash).unwrap().count,
+        1
+    );
+    replay.cleanup();
+    assert_eq!(replay.entries.len(), 1);
+
+    // Nothing is recorded when disabled
+    replay.config.enable = false;
+    assert!(!replay.record(
+        "example.com",
+        "mail",
+        body_hash,
+        "10.0.0.1".parse().unwrap()
+    ));
+    assert!(replay.get("example.com", "mail", body_hash).is_none());
+}
@@ -46,6 +46,7 @@ use smtp::{
         AggregateFrequency, ConfigContext, EnvelopeKey, IfBlock, MaybeDynValue, VerifyStrategy,
     },
     core::{Session, SMTP},
+    queue::MAIL_AUTH_FAILED,
 };
 
 const DIRECTORY: &str = r#"
@@ -310,12 +311,23 @@ async fn dmarc() {
             "250",
         )
         .await;
-    qr.read_event()
-        .await
-        .unwrap_message()
+    let message = qr.read_event().await.unwrap_message();
+    assert_eq!(message.flags & MAIL_AUTH_FAILED, 0);
+    message
         .read_lines()
         .assert_contains("dkim=pass")
         .assert_contains("spf=pass")
         .assert_contains("dmarc=pass")
         .assert_contains("Received-SPF: pass");
+
+    // Signatures that are not aligned with the return path do not vouch for it
+    session
+        .send_message("joe@test.net", &["jdoe@example.com"], "test:dkim", "250")
+        .await;
+    let message = qr.read_event().await.unwrap_message();
+    assert_ne!(message.flags & MAIL_AUTH_FAILED, 0);
+    message
+        .read_lines()
+        .assert_contains("dkim=pass")
+        .assert_contains("spf=fail");
 }
@@ -0,0 +1,124 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use crate::smtp::{
+    inbound::TestQueueEvent,
+    session::{TestSession, VerifyResponse},
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{ConfigContext, IfBlock},
+    core::{management, Session, SMTP},
+};
+use smtp_proto::{
+    MAIL_RET_HDRS, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_NEVER, RCPT_NOTIFY_SUCCESS,
+};
+
+#[tokio::test]
+async fn dsn_parameters() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_dsn_params_test");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.extensions.dsn = r"[{if = 'remote-ip', eq = '10.0.0.1', then = true},
+    {else = false}]"
+        .parse_if(&ConfigContext::new(&[]));
+
+    // DSN parameters are rejected when the extension is disabled
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.2".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await.assert_not_contains("DSN");
+    for mail_from in ["<john@test.org> ENVID=abc123", "<john@test.org> RET=FULL"] {
+        session.mail_from(mail_from, "501 5.5.4").await;
+    }
+    session.mail_from("john@test.org", "250").await;
+    for rcpt_to in [
+        "<bill@foobar.org> NOTIFY=SUCCESS",
+        "<bill@foobar.org> ORCPT=rfc822;bill@foobar.org",
+    ] {
+        session.rcpt_to(rcpt_to, "501 5.5.4").await;
+    }
+    session.rset().await;
+
+    // DSN parameters are stored in the queue
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await.assert_contains("DSN");
+    session
+        .send_message(
+            "<john@test.org> ENVID=abc123 RET=HDRS",
+            &[
+                "<bill@foobar.org> NOTIFY=SUCCESS,DELAY ORCPT=rfc822;bill@example.org",
+                "<jane@foobar.org> NOTIFY=NEVER",
+                "<mike@foobar.org>",
+            ],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    let message = qr.read_event().await.unwrap_message();
+    assert_eq!(message.env_id.as_deref(), Some("abc123"));
+    assert_ne!(message.flags & MAIL_RET_HDRS, 0);
+    let flags = RCPT_NOTIFY_SUCCESS | RCPT_NOTIFY_DELAY | RCPT_NOTIFY_FAILURE | RCPT_NOTIFY_NEVER;
+    assert_eq!(
+        message
+            .recipients
+            .iter()
+            .map(|rcpt| (
+                rcpt.address.as_str(),
+                rcpt.flags & flags,
+                rcpt.orcpt.as_deref()
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            (
+                "bill@foobar.org",
+                RCPT_NOTIFY_SUCCESS | RCPT_NOTIFY_DELAY,
+                Some("bill@example.org")
+            ),
+            ("jane@foobar.org", RCPT_NOTIFY_NEVER, None),
+            (
+                "mike@foobar.org",
+                RCPT_NOTIFY_DELAY | RCPT_NOTIFY_FAILURE,
+                None
+            ),
+        ]
+    );
+
+    // DSN requests are visible to administrators
+    let message = management::Message::from(message.as_ref());
+    assert_eq!(message.env_id.as_deref(), Some("abc123"));
+    assert_eq!(message.ret.as_deref(), Some("hdrs"));
+    assert_eq!(
+        message.domains[0]
+            .recipients
+            .iter()
+            .map(|rcpt| (rcpt.notify.join(","), rcpt.orcpt.as_deref()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("success,delay".to_string(), Some("bill@example.org")),
+            ("never".to_string(), None),
+            ("delay,failure".to_string(), None),
+        ]
+    );
+}
@@ -0,0 +1,318 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use utils::config::Config;
+
+use crate::smtp::{
+    inbound::{TestMessage, TestQueueEvent},
+    session::{TestSession, VerifyResponse},
+    TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{session::ConfigSession, ConfigContext, EnvelopeKey, IfBlock},
+    core::{Session, SMTP},
+    inbound::filter::{DeferredScan, FilterAction, FilterResponse},
+    queue::{
+        manager::Queue, ScanVerdict, Schedule, Status, Timestamp, RCPT_QUARANTINED,
+        RCPT_SCAN_PENDING,
+    },
+};
+
+const FILTER: &str = r#"
+[session.data.filter."test"]
+enable = true
+type = "command"
+command = "/bin/sh"
+arguments = ["-c", "cat > /dev/null; case \"$SMTP_SENDER\" in spammer@*) printf 'Action: reject\nReply: 550 5.7.1 Spam detected\n' ;; later@*) printf 'Action: tempfail\n' ;; held@*) printf 'Action: quarantine\nReason: Suspicious\n' ;; *) printf 'Add-Header: X-Filter: scanned\n' ;; esac"]
+timeout = "10s"
+"#;
+
+#[tokio::test]
+async fn content_filter() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::DEBUG)
+            .finish(),
+    )
+    .unwrap();*/
+
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_filter_test");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.data.filters = Config::new(FILTER)
+        .unwrap()
+        .parse_content_filters(&ConfigContext::new(&[]), &[EnvelopeKey::RemoteIp])
+        .unwrap();
+
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+
+    // Rejected and deferred messages
+    session
+        .send_message(
+            "spammer@foobar.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "550 5.7.1 Spam detected",
+        )
+        .await;
+    session
+        .send_message(
+            "later@foobar.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "451 4.7.1",
+        )
+        .await;
+    qr.assert_empty_queue();
+
+    // Accepted message with added headers
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], "test:no_dkim", "250")
+        .await;
+    qr.read_event()
+        .await
+        .unwrap_message()
+        .read_lines()
+        .assert_contains("X-Filter: scanned");
+
+    // Quarantined message is held in the queue
+    session
+        .send_message("held@doe.org", &["bill@foobar.org"], "test:no_dkim", "250")
+        .await;
+    let message = qr.read_event().await.unwrap_message();
+    for domain in &message.domains {
+        assert_eq!(domain.retry.due, domain.expires);
+    }
+    qr.assert_empty_queue();
+}
+
+const DEFERRED_FILTER: &str = r#"
+[session.data.filter."deferred"]
+enable = true
+type = "command"
+command = "/bin/sh"
+arguments = ["-c", "cat > /dev/null; case \"$SMTP_SENDER\" in spammer@*) printf 'Action: reject\nReply: 550 5.7.1 Spam detected\n' ;; later@*) printf 'Action: tempfail\n' ;; *) printf 'Add-Header: X-Filter: scanned\n' ;; esac"]
+timeout = "10s"
+mode = "deferred"
+
+[session.data.filter."deferred".deferred]
+on-reject = "bounce"
+on-tempfail = "quarantine"
+on-error = "deliver"
+max-concurrent = 2
+"#;
+
+#[tokio::test]
+async fn content_filter_deferred() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_filter_deferred_test");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.data.filters = Config::new(DEFERRED_FILTER)
+        .unwrap()
+        .parse_content_filters(&ConfigContext::new(&[]), &[EnvelopeKey::RemoteIp])
+        .unwrap();
+
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.doe.org").await;
+    let mut queue = Queue::default();
+
+    // Messages are deferred while too many scans are pending
+    let limiter = session.core.session.config.data.filters[0]
+        .deferred
+        .as_ref()
+        .unwrap()
+        .limiter
+        .clone();
+    let in_flight = [limiter.is_allowed().unwrap(), limiter.is_allowed().unwrap()];
+    session
+        .send_message(
+            "john@doe.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "451 4.3.2",
+        )
+        .await;
+    drop(in_flight);
+    qr.assert_empty_queue();
+
+    // Messages are accepted and held until scanned
+    for (sender, expected_verdict) in [
+        ("spammer@foobar.org", "bounce"),
+        ("later@foobar.org", "quarantine"),
+        ("john@doe.org", "deliver"),
+    ] {
+        session
+            .send_message(sender, &["bill@foobar.org"], "test:no_dkim", "250")
+            .await;
+        let message = qr.read_event().await.unwrap_message();
+        let queue_id = message.id;
+        assert!(message.recipients[0].has_flag(RCPT_SCAN_PENDING));
+        for domain in &message.domains {
+            assert_eq!(domain.retry.due, domain.expires);
+            assert_eq!(domain.notify.due, domain.expires);
+        }
+        let expires = message.domains[0].expires;
+        queue.schedule(Schedule {
+            due: expires,
+            inner: message,
+        });
+
+        // Wait for the scan to complete
+        let result = tokio::time::timeout(Duration::from_secs(5), qr.queue_rx.recv())
+            .await
+            .expect("No scan result received.")
+            .unwrap()
+            .unwrap_scanned();
+        assert_eq!(result.queue_id, queue_id);
+        match (&result.verdict, expected_verdict) {
+            (ScanVerdict::Bounce(response), "bounce") => {
+                assert_eq!(response.response.code, 550);
+                assert_eq!(response.response.esc, [5, 7, 1]);
+                assert_eq!(response.response.message, "Spam detected");
+            }
+            (ScanVerdict::Quarantine(_), "quarantine") | (ScanVerdict::Deliver, "deliver") => (),
+            (verdict, expected) => panic!("Expected {expected} verdict, got {verdict:?}"),
+        }
+        queue.scanned(result).await;
+
+        let message = queue.messages.get(&queue_id).unwrap();
+        assert!(!message.recipients[0].has_flag(RCPT_SCAN_PENDING));
+        match expected_verdict {
+            "bounce" => {
+                assert!(matches!(
+                    message.recipients[0].status,
+                    Status::PermanentFailure(_)
+                ));
+                assert!(matches!(message.domains[0].status, Status::Completed(_)));
+                assert!(queue.scheduled.peek().unwrap().due <= Timestamp::now());
+            }
+            "quarantine" => {
+                assert_eq!(message.domains[0].retry.due, expires);
+                assert!(message.recipients[0].has_flag(RCPT_QUARANTINED));
+
+                // Quarantined messages are delivered once released
+                queue.scheduled.clear();
+                assert!(queue.release(queue_id).await);
+                assert!(!queue.release(queue_id).await);
+                let message = queue.messages.get(&queue_id).unwrap();
+                assert!(!message.recipients[0].has_flag(RCPT_QUARANTINED));
+                assert!(message.domains[0].retry.due <= Timestamp::now());
+                assert!(queue.scheduled.peek().unwrap().due <= Timestamp::now());
+            }
+            _ => {
+                assert!(message.domains[0].retry.due < expires);
+                assert!(message.domains[0].notify.due < expires);
+                assert!(matches!(message.domains[0].status, Status::Scheduled));
+            }
+        }
+        queue.messages.remove(&queue_id);
+        queue.scheduled.clear();
+    }
+    qr.assert_empty_queue();
+
+    // Scans interrupted by a restart are resumed
+    session
+        .send_message("john@doe.org", &["bill@foobar.org"], "test:no_dkim", "250")
+        .await;
+    let message = qr.read_event().await.unwrap_message();
+    let queue_id = message.id;
+    let expires = message.domains[0].expires;
+    queue.schedule(Schedule {
+        due: expires,
+        inner: message,
+    });
+    let result = tokio::time::timeout(Duration::from_secs(5), qr.queue_rx.recv())
+        .await
+        .expect("No scan result received.")
+        .unwrap()
+        .unwrap_scanned();
+    assert_eq!(result.queue_id, queue_id);
+    DeferredScan::resume(&session.core, &queue).await;
+    let result = tokio::time::timeout(Duration::from_secs(5), qr.queue_rx.recv())
+        .await
+        .expect("No resumed scan result received.")
+        .unwrap()
+        .unwrap_scanned();
+    assert_eq!(result.queue_id, queue_id);
+    assert!(matches!(result.verdict, ScanVerdict::Deliver));
+    assert!(result.schedule.is_empty());
+    queue.scanned(result).await;
+    let message = queue.messages.get(&queue_id).unwrap();
+    assert!(!message.recipients[0].has_flag(RCPT_SCAN_PENDING));
+    assert!(message.domains[0].retry.due <= Timestamp::now());
+    queue.messages.remove(&queue_id);
+    queue.scheduled.clear();
+    qr.assert_empty_queue();
+}
+
+#[test]
+fn content_filter_responses() {
+    // Command output
+    assert_eq!(
+        FilterResponse::parse_command_output(
+            b"Action: reject\r\nReply: 554 5.7.1 Virus found\r\nReason: Eicar\r\n"
+        )
+        .unwrap(),
+        FilterResponse {
+            action: FilterAction::Reject,
+            reply: Some("554 5.7.1 Virus found".to_string()),
+            reason: Some("Eicar".to_string()),
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        FilterResponse::parse_command_output(
+            b"Add-Header: X-Score: 1.5\n\nSubject: hi\r\n\r\nbody"
+        )
+        .unwrap(),
+        FilterResponse {
+            add_headers: vec![("X-Score".to_string(), "1.5".to_string())],
+            replace: Some(b"Subject: hi\r\n\r\nbody".to_vec()),
+            ..Default::default()
+        }
+    );
+    assert!(FilterResponse::parse_command_output(b"Action: explode\n").is_err());
+
+    // gRPC response: action = QUARANTINE, reason = "spam", add_headers = [{X-A: b}]
+    let mut message = vec![0x08, 0x04, 0x1a, 0x04];
+    message.extend_from_slice(b"spam");
+    message.extend_from_slice(&[0x22, 0x08, 0x0a, 0x03]);
+    message.extend_from_slice(b"X-A");
+    message.extend_from_slice(&[0x12, 0x01, b'b']);
+    assert_eq!(
+        FilterResponse::decode(&message).unwrap(),
+        FilterResponse {
+            action: FilterAction::Quarantine,
+            reason: Some("spam".to_string()),
+            add_headers: vec![("X-A".to_string(), "b".to_string())],
+            ..Default::default()
+        }
+    );
+}
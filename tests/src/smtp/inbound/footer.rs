@@ -0,0 +1,268 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::{Duration, Instant};
+
+use directory::config::ConfigDirectory;
+use mail_auth::{
+    common::{parse::TxtRecordParser, verify::DomainKey},
+    AuthenticatedMessage, DkimResult,
+};
+use utils::config::{Config, DynValue};
+
+use crate::smtp::{
+    inbound::{sign::TextConfigContext, TestMessage, TestQueueEvent},
+    session::{TestSession, VerifyResponse},
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{
+        if_block::ConfigIf, session::ConfigSession, ConfigContext, EnvelopeKey, IfBlock,
+        MaybeDynValue,
+    },
+    core::{Session, SMTP},
+    inbound::footer::render_template,
+};
+
+const CONFIG: &str = r#"
+[session.data]
+footer = [ { if = "sender-domain", eq = "example.com", then = "disclaimer" },
+           { else = false } ]
+
+[footer."disclaimer"]
+text = "--\n{{name}} <{{email}}>\nThis message is confidential."
+html = "<p>{{name}} &lt;{{email}}&gt;</p>"
+
+[directory."local"]
+type = "memory"
+
+[[directory."local".users]]
+name = "john"
+description = "John & Jane Doe"
+secret = "secret"
+email = ["jdoe@example.com"]
+
+[directory."local".lookup]
+domains = ["example.com"]
+"#;
+
+#[tokio::test]
+async fn footer_injection() {
+    let mut core = SMTP::test();
+
+    // Create temp dir for queue
+    let mut qr = core.init_test_queue("smtp_footer_test");
+
+    // Add DKIM record for the signer
+    core.resolvers.dns.txt_add(
+        "ed._domainkey.example.com",
+        DomainKey::parse(
+            concat!(
+                "v=DKIM1; k=ed25519; ",
+                "p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo="
+            )
+            .as_bytes(),
+        )
+        .unwrap(),
+        Instant::now() + Duration::from_secs(5),
+    );
+
+    let config = Config::new(CONFIG).unwrap();
+    let directory = config.parse_directory().unwrap();
+    let footers = config.parse_footers().unwrap();
+    assert_eq!(footers.len(), 1);
+    let footer = config
+        .parse_if_block::<Option<String>>(
+            "session.data.footer",
+            &ConfigContext::new(&[]),
+            &[EnvelopeKey::SenderDomain],
+        )
+        .unwrap()
+        .unwrap()
+        .map_if_block(&footers, "session.data.footer", "footer")
+        .unwrap();
+
+    let config = &mut core.session.config;
+    config.rcpt.relay = IfBlock::new(true);
+    config.data.footer = footer;
+    config.auth.directory = IfBlock::new(Some(MaybeDynValue::Static(
+        directory.directories.get("local").unwrap().clone(),
+    )));
+    let ctx = ConfigContext::new(&[]).parse_signatures();
+    core.mail_auth.dkim.sign = "['ed']"
+        .parse_if::<Vec<DynValue<EnvelopeKey>>>(&ctx)
+        .map_if_block(&ctx.signers, "", "")
+        .unwrap();
+
+    // Authenticated senders from a configured domain get the footer,
+    // and the DKIM signature covers the modified body
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.example.com").await;
+    session.data.authenticated_as = "john".to_string();
+    session
+        .send_message(
+            "jdoe@example.com",
+            &["bill@foobar.org"],
+            concat!(
+                "From: jdoe@example.com\r\n",
+                "To: bill@foobar.org\r\n",
+                "Subject: Quarterly report\r\n",
+                "Content-Type: text/plain; charset=utf-8\r\n",
+                "\r\n",
+                "Please find the report attached.\r\n"
+            ),
+            "250",
+        )
+        .await;
+    let message = qr.read_event().await.unwrap_message().read_message();
+    vec![message.clone()]
+        .assert_contains("DKIM-Signature:")
+        .assert_contains(concat!(
+            "Please find the report attached.\r\n",
+            "\r\n",
+            "--\r\n",
+            "John & Jane Doe <jdoe@example.com>\r\n",
+            "This message is confidential.\r\n"
+        ));
+    let signatures = session
+        .core
+        .resolvers
+        .dns
+        .verify_dkim(&AuthenticatedMessage::parse(message.as_bytes()).unwrap())
+        .await;
+    assert!(
+        !signatures.is_empty()
+            && signatures
+                .iter()
+                .all(|output| output.result() == &DkimResult::Pass),
+        "{signatures:?}"
+    );
+
+    // HTML parts get the footer rendered from the HTML template
+    session
+        .send_message(
+            "jdoe@example.com",
+            &["bill@foobar.org"],
+            concat!(
+                "From: jdoe@example.com\r\n",
+                "To: bill@foobar.org\r\n",
+                "Subject: Newsletter\r\n",
+                "Content-Type: text/html; charset=utf-8\r\n",
+                "\r\n",
+                "<html><body><p>Hello</p></body></html>\r\n"
+            ),
+            "250",
+        )
+        .await;
+    qr.read_event()
+        .await
+        .unwrap_message()
+        .read_lines()
+        .assert_contains(concat!(
+            "<html><body><p>Hello</p>",
+            "<p>John &amp; Jane Doe &lt;jdoe@example.com&gt;</p>",
+            "</body></html>"
+        ));
+
+    // Senders from other domains are left untouched
+    session
+        .send_message(
+            "john@foobar.org",
+            &["bill@foobar.org"],
+            concat!(
+                "From: john@foobar.org\r\n",
+                "To: bill@foobar.org\r\n",
+                "Subject: Hi\r\n",
+                "\r\n",
+                "No footer here.\r\n"
+            ),
+            "250",
+        )
+        .await;
+    qr.read_event()
+        .await
+        .unwrap_message()
+        .read_lines()
+        .assert_not_contains("This message is confidential.");
+
+    // Signed messages are never modified
+    session
+        .send_message(
+            "jdoe@example.com",
+            &["bill@foobar.org"],
+            concat!(
+                "From: jdoe@example.com\r\n",
+                "To: bill@foobar.org\r\n",
+                "Subject: Signed\r\n",
+                "Content-Type: multipart/signed; boundary=\"b1\"\r\n",
+                "\r\n",
+                "--b1\r\n",
+                "Content-Type: text/plain\r\n",
+                "\r\n",
+                "Signed.\r\n",
+                "--b1--\r\n"
+            ),
+            "250",
+        )
+        .await;
+    qr.read_event()
+        .await
+        .unwrap_message()
+        .read_lines()
+        .assert_not_contains("This message is confidential.");
+
+    // Unauthenticated sessions do not get a footer
+    session.data.authenticated_as.clear();
+    session
+        .send_message(
+            "jdoe@example.com",
+            &["bill@foobar.org"],
+            concat!(
+                "From: jdoe@example.com\r\n",
+                "To: bill@foobar.org\r\n",
+                "Subject: Hi\r\n",
+                "\r\n",
+                "No footer here.\r\n"
+            ),
+            "250",
+        )
+        .await;
+    qr.read_event()
+        .await
+        .unwrap_message()
+        .read_lines()
+        .assert_not_contains("This message is confidential.");
+    qr.assert_empty_queue();
+
+    // Unknown placeholders are left as they are
+    assert_eq!(
+        render_template(
+            "{{name}} {{ email }} {{unknown}} {{",
+            &[("name", "<John>"), ("email", "john@example.com")],
+            true
+        ),
+        "&lt;John&gt; john@example.com {{unknown}} {{"
+    );
+}
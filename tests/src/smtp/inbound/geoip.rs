@@ -0,0 +1,118 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+use std::time::Duration;
+
+use directory::config::ConfigDirectory;
+use utils::config::{Config, KeyLookup};
+
+use crate::smtp::{session::TestSession, ParseTestConfig, TestConfig};
+use smtp::{
+    config::{geoip::ConfigGeoIp, ConfigContext, EnvelopeKey, IfBlock, MaybeDynValue},
+    core::{GeoIpCore, Session, SMTP},
+    geoip::GeoIpInfo,
+};
+
+const CONFIG: &str = r#"
+[directory."local"]
+type = "memory"
+
+[[directory."local".users]]
+name = "jane"
+description = "Jane Doe"
+secret = "p4ssw0rd"
+email = "jane@foobar.org"
+
+[directory."local".lookup]
+domains = ["foobar.org"]
+"#;
+
+#[tokio::test]
+async fn geoip_enrichment() {
+    // Missing or invalid databases are reported at startup
+    let tmp_file = std::env::temp_dir().join("smtp_geoip_test.mmdb");
+    std::fs::write(&tmp_file, b"not a maxmind database").unwrap();
+    for path in [
+        tmp_file.clone(),
+        std::env::temp_dir().join("smtp_geoip_missing.mmdb"),
+    ] {
+        let config = Config::new(&format!(
+            "[geoip.database]\ncountry = \"{}\"\n",
+            path.display()
+        ))
+        .unwrap()
+        .parse_geoip()
+        .unwrap();
+        assert!(GeoIpCore::open(config).await.is_err());
+    }
+    std::fs::remove_file(&tmp_file).unwrap();
+
+    // Without databases lookups return no information
+    let mut core = SMTP::test();
+    assert!(!core.geoip.is_enabled());
+    assert!(core.geoip.reload_frequency().is_none());
+    assert!(core.geoip.lookup("10.0.0.1".parse().unwrap()).is_empty());
+
+    // Only clients from the allowed country are allowed to relay
+    let directory = Config::new(CONFIG).unwrap().parse_directory().unwrap();
+    let rcpt = &mut core.session.config.rcpt;
+    rcpt.directory = IfBlock::new(Some(MaybeDynValue::Static(
+        directory.directories.get("local").unwrap().clone(),
+    )));
+    rcpt.errors_max = IfBlock::new(100);
+    rcpt.errors_wait = IfBlock::new(Duration::from_millis(0));
+    rcpt.relay = r"[{if = 'country', eq = 'DE', then = true},
+    {if = 'asn', eq = '3320', then = true},
+    {else = false}]"
+        .parse_if(&ConfigContext::new(&[]));
+
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.data.geo = GeoIpInfo {
+        country: Some("DE".to_string()),
+        asn: None,
+        asn_org: None,
+    };
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    assert_eq!(session.key(&EnvelopeKey::Country), "DE");
+    assert_eq!(session.key(&EnvelopeKey::Asn), "");
+    session.mail_from("john@example.org", "250").await;
+    session.rcpt_to("bill@remote.org", "250").await;
+
+    session.cmd("RSET", "250").await;
+    session.data.geo = GeoIpInfo {
+        country: Some("US".to_string()),
+        asn: Some(7018),
+        asn_org: Some("AT&T".to_string()),
+    };
+    assert_eq!(session.key(&EnvelopeKey::Country), "US");
+    assert_eq!(session.key(&EnvelopeKey::Asn), "7018");
+    session.mail_from("john@example.org", "250").await;
+    session.rcpt_to("bill@remote.org", "550 5.1.2").await;
+    session.rcpt_to("jane@foobar.org", "250").await;
+
+    session.cmd("RSET", "250").await;
+    session.data.geo.asn = Some(3320);
+    session.mail_from("john@example.org", "250").await;
+    session.rcpt_to("bill@remote.org", "250").await;
+}
@@ -21,16 +21,20 @@
  * for more details.
 */
 
-use std::time::{Duration, Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use tokio::sync::watch;
 
 use crate::smtp::{
+    inbound::TestQueueEvent,
     session::{TestSession, VerifyResponse},
-    ParseTestConfig, TestConfig,
+    ParseTestConfig, TestConfig, TestSMTP,
 };
 use smtp::{
-    config::ConfigContext,
+    config::{ConfigContext, IfBlock},
     core::{Session, SMTP},
 };
 
@@ -85,3 +89,97 @@ async fn limits() {
     session.handle_conn_().await;
     session.response().assert_code("221 2.0.0");
 }
+
+#[tokio::test]
+async fn shutdown_drain() {
+    let mut core = SMTP::test();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    let mut qr = core.init_test_queue("smtp_shutdown_test");
+    let core = Arc::new(core);
+    let (tx, rx) = watch::channel(false);
+
+    // Create an idle session and a session with a transaction in progress
+    let mut idle_session = Session::test_with_shutdown(core.clone(), rx.clone());
+    idle_session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    idle_session.eval_session_params().await;
+    idle_session.ehlo("mx.foobar.org").await;
+
+    let mut session = Session::test_with_shutdown(core, rx);
+    session.data.remote_ip = "10.0.0.2".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    session.mail_from("john@foobar.org", "250").await;
+    session.rcpt_to("bill@remote.org", "250").await;
+
+    // Idle sessions are closed immediately
+    tx.send(true).unwrap();
+    idle_session.handle_conn_().await;
+    idle_session.response().assert_code("421 4.3.0");
+
+    // Sessions in a transaction are allowed to complete it
+    session.write_rx(&format!(
+        "DATA\r\n{}\r\n.\r\n",
+        "From: john@foobar.org\r\nSubject: test\r\n\r\ntest"
+    ));
+    session.handle_conn_().await;
+    session
+        .response()
+        .assert_contains("250 2.0.0")
+        .assert_code("421 4.3.0");
+    qr.read_event().await.unwrap_message();
+}
+
+#[tokio::test]
+async fn early_talker_and_tarpit() {
+    let mut core = SMTP::test();
+    let config = &mut core.session.config;
+    config.connect.greeting_delay = r"[{if = 'remote-ip', eq = '10.0.0.1', then = '100ms'},
+    {else = false}]"
+        .parse_if(&ConfigContext::new(&[]));
+    config.connect.tarpit = r"[{if = 'remote-ip', eq = '10.0.0.2', then = '200ms'},
+    {else = false}]"
+        .parse_if(&ConfigContext::new(&[]));
+    config.rcpt.errors_tarpit = IfBlock::new(Some(Duration::from_millis(300)));
+    let core = Arc::new(core);
+
+    // Clients sending data before the greeting are disconnected
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.write_rx("EHLO mx.foobar.org\r\n");
+    assert!(!session.init_conn().await);
+    session.response().assert_code("554 5.5.0");
+
+    // Clients waiting for the greeting are accepted
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    assert!(session.init_conn().await);
+    session.response();
+
+    // Clients that do not wait for the EHLO response are disconnected
+    session
+        .ingest(b"EHLO mx.foobar.org\r\nMAIL FROM:<john@foobar.org>\r\n")
+        .await
+        .unwrap_err();
+    session.response().assert_code("554 5.5.0");
+
+    // Responses to tarpitted sessions are delayed
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip = "10.0.0.2".parse().unwrap();
+    session.eval_session_params().await;
+    let time = Instant::now();
+    session.ehlo("mx.foobar.org").await;
+    assert!(time.elapsed() >= Duration::from_millis(200));
+
+    // Failed recipients tarpit the session
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.3".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    session.mail_from("john@foobar.org", "250").await;
+    assert_eq!(session.params.tarpit, None);
+    session.rcpt_to("bill@remote.org", "550 5.1.2").await;
+    assert_eq!(session.params.tarpit, Some(Duration::from_millis(300)));
+    let time = Instant::now();
+    session.rset().await;
+    assert!(time.elapsed() >= Duration::from_millis(300));
+}
@@ -33,6 +33,7 @@ use smtp::{
         receiver::{FrameResult, Receiver},
         Action, Command, Macros, MilterClient, Modification, Options, Response, Version,
     },
+    queue::RCPT_QUARANTINED,
 };
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
@@ -77,6 +78,8 @@ async fn milter_session() {
     #port = 7357
     enable = true
     options.version = 6
+    options.reuse-connection = true
+    options.max-idle-connections = 1
     tls = false
     "#
     .parse_milters(&ConfigContext::new(&[]));
@@ -191,6 +194,34 @@ async fn milter_session() {
         .read_lines()
         .assert_contains("X-Spam: Yes")
         .assert_contains("123456");
+
+    // Test quarantine
+    session
+        .send_message(
+            "quarantine@doe.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "250 2.0.0",
+        )
+        .await;
+    let message = qr.read_event().await.unwrap_message();
+    assert!(message.recipients[0].has_flag(RCPT_QUARANTINED));
+    for domain in &message.domains {
+        assert_eq!(domain.retry.due, domain.expires);
+        assert_eq!(domain.notify.due, domain.expires);
+    }
+    message
+        .read_lines()
+        .assert_contains("X-Quarantine: Suspicious content");
+
+    // Connections are reused, up to the idle limit
+    assert_eq!(
+        session.core.session.config.data.milters[0]
+            .connections
+            .lock()
+            .len(),
+        1
+    );
 }
 
 #[test]
@@ -393,6 +424,9 @@ async fn milter_client_test() {
             protocol_version: Version::V6,
             flags_actions: None,
             flags_protocol: None,
+            reuse_connection: false,
+            max_idle_connections: 0,
+            connections: Default::default(),
         },
         tracing::span!(tracing::Level::TRACE, "hi"),
     )
@@ -529,8 +563,12 @@ async fn accept_milter(
                         | Command::Header { .. }
                         | Command::Helo { .. }
                         | Command::Rcpt { .. }
-                        | Command::QuitNewConnection
                         | Command::EndOfHeader => Response::Action(Action::Accept),
+                        Command::QuitNewConnection => {
+                            action = None;
+                            modidications = None;
+                            continue;
+                        }
                         Command::OptionNegotiation(_) => Response::OptionNegotiation(Options {
                             version: 6,
                             actions: 0,
@@ -555,6 +593,13 @@ async fn accept_milter(
                                     code: [b'3', b'2', b'1'],
                                     text: "test".to_string(),
                                 },
+                                "quarantine" => {
+                                    modidications = vec![Modification::Quarantine {
+                                        reason: "Suspicious content".to_string(),
+                                    }]
+                                    .into();
+                                    Action::Accept
+                                }
                                 test_num => {
                                     modidications = tests[test_num.parse::<usize>().unwrap()]
                                         .modifications
@@ -26,27 +26,42 @@ use std::time::Duration;
 use tokio::sync::mpsc::error::TryRecvError;
 
 use smtp::{
-    queue::{self, Message, OnHold, Schedule, WorkerResult},
+    queue::{self, Message, OnHold, ScanResult, Schedule, WorkerResult},
     reporting::{self, DmarcEvent, TlsEvent},
 };
 
 use super::{QueueReceiver, ReportReceiver};
 
+pub mod anomaly;
 pub mod antispam;
 pub mod auth;
+pub mod backpressure;
 pub mod basic;
+pub mod bimi;
 pub mod data;
+pub mod dkim_replay;
 pub mod dmarc;
+pub mod dsn;
 pub mod ehlo;
+pub mod filter;
+pub mod footer;
+pub mod geoip;
 pub mod limits;
 pub mod mail;
 pub mod milter;
+pub mod policy;
 pub mod rcpt;
+pub mod reputation;
 pub mod rewrite;
 pub mod scripts;
 pub mod sign;
+pub mod suppression;
 pub mod throttle;
+pub mod transform;
+pub mod unsubscribe;
+pub mod usage;
 pub mod vrfy;
+pub mod xclient;
 
 impl QueueReceiver {
     pub async fn read_event(&mut self) -> queue::Event {
@@ -105,6 +120,7 @@ pub trait TestQueueEvent {
     fn unwrap_done(self);
     fn unwrap_on_hold(self) -> OnHold<Box<Message>>;
     fn unwrap_retry(self) -> Schedule<Box<Message>>;
+    fn unwrap_scanned(self) -> ScanResult;
 }
 
 impl TestQueueEvent for queue::Event {
@@ -161,6 +177,16 @@ impl TestQueueEvent for queue::Event {
             e => panic!("Unexpected event: {e:?}"),
         }
     }
+
+    fn unwrap_scanned(self) -> ScanResult {
+        match self {
+            queue::Event::Scanned(result) => result,
+            queue::Event::Queue(message) => {
+                panic!("Unexpected message: {}", message.inner.read_message());
+            }
+            e => panic!("Unexpected event: {e:?}"),
+        }
+    }
 }
 
 pub trait TestReportingEvent {
@@ -0,0 +1,128 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    sync::watch,
+};
+use utils::config::{Config, DynValue};
+
+use crate::smtp::{
+    session::{TestSession, VerifyResponse},
+    ParseTestConfig, TestConfig,
+};
+use smtp::{
+    config::{policy::ConfigPolicy, ConfigContext, EnvelopeKey},
+    core::{Session, SMTP},
+};
+
+const POLICY: &str = r#"
+[policy."mock"]
+hostname = "127.0.0.1"
+port = 9333
+timeout = "5s"
+"#;
+
+#[tokio::test]
+async fn policy_delegation() {
+    // Start mock policy server
+    let (_tx, rx) = watch::channel(true);
+    spawn_mock_policy_server(rx).await;
+
+    let mut ctx = ConfigContext::new(&[]);
+    Config::new(POLICY)
+        .unwrap()
+        .parse_policy_servers(&mut ctx)
+        .unwrap();
+
+    let mut core = SMTP::test();
+    core.session.config.mail.policy = "['mock']"
+        .parse_if::<Vec<DynValue<EnvelopeKey>>>(&ctx)
+        .map_if_block(&ctx.policies, "", "")
+        .unwrap();
+    core.session.config.rcpt.policy = "['mock']"
+        .parse_if::<Vec<DynValue<EnvelopeKey>>>(&ctx)
+        .map_if_block(&ctx.policies, "", "")
+        .unwrap();
+    core.session.config.rcpt.relay = "true".parse_if(&ctx);
+
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+
+    // Sender rejected by policy server
+    session.mail_from("spammer@foobar.org", "554 5.7.1").await;
+
+    // Recipient deferred and rejected by policy server
+    session.mail_from("john@foobar.org", "250").await;
+    session.rcpt_to("greylist@foobar.org", "450 4.7.1").await;
+    session.rcpt_to("custom@foobar.org", "550 5.1.1").await;
+    session.rcpt_to("jane@foobar.org", "250").await;
+}
+
+async fn spawn_mock_policy_server(mut rx: watch::Receiver<bool>) {
+    let listener = TcpListener::bind("127.0.0.1:9333")
+        .await
+        .unwrap_or_else(|e| panic!("Failed to bind mock policy server to 127.0.0.1:9333: {e}"));
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                stream = listener.accept() => {
+                    if let Ok((mut stream, _)) = stream {
+                        tokio::spawn(async move {
+                            let mut request = Vec::new();
+                            let mut buf = [0u8; 1024];
+                            while !request.ends_with(b"\n\n") {
+                                match stream.read(&mut buf).await {
+                                    Ok(0) | Err(_) => return,
+                                    Ok(n) => request.extend_from_slice(&buf[..n]),
+                                }
+                            }
+                            let request = String::from_utf8(request).unwrap();
+                            assert!(request.starts_with("request=smtpd_access_policy\n"));
+
+                            let action = if request.contains("sender=spammer@foobar.org\n") {
+                                "REJECT"
+                            } else if request.contains("recipient=greylist@foobar.org\n") {
+                                "DEFER_IF_PERMIT Greylisted, try again later"
+                            } else if request.contains("recipient=custom@foobar.org\n") {
+                                "550 5.1.1 Unknown user"
+                            } else {
+                                "DUNNO"
+                            };
+                            let _ = stream
+                                .write_all(format!("action={action}\n\n").as_bytes())
+                                .await;
+                        });
+                    }
+                }
+                _ = rx.changed() => {
+                    break;
+                }
+            };
+        }
+    });
+}
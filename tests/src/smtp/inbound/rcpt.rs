@@ -93,6 +93,12 @@ async fn rcpt() {
     config.errors_wait = r"[{if = 'remote-ip', eq = '10.0.0.1', then = '5ms'},
     {else = '1s'}]"
         .parse_if(&ConfigContext::new(&[]));
+    config.retry_hint = r"[{if = 'sender-domain', eq = 'example.net', then = '2s'},
+    {else = false}]"
+        .parse_if(&ConfigContext::new(&[]));
+    config.max_message_size = r"[{if = 'rcpt-domain', eq = 'domain.com', then = 1000},
+    {else = false}]"
+        .parse_if(&ConfigContext::new(&[]));
     core.session.config.throttle.rcpt_to = r"[[throttle]]
     match = {if = 'remote-ip', eq = '10.0.0.1'}
     key = 'sender'
@@ -133,12 +139,19 @@ async fn rcpt() {
     session.state = State::default();
     session.rcpt_to("Jane@FooBar.org", "250").await;
     session.rcpt_to("Bill@FooBar.org", "250").await;
-    session.rcpt_to("Mike@FooBar.org", "451 4.4.5").await;
+    session
+        .ingest(b"RCPT TO:<Mike@FooBar.org>\r\n")
+        .await
+        .unwrap();
+    session
+        .response()
+        .assert_code("451 4.4.5")
+        .assert_contains("try again in 2 seconds");
 
     // Restore rate limit
     tokio::time::sleep(Duration::from_millis(1100)).await;
     session.rcpt_to("Mike@FooBar.org", "250").await;
-    session.rcpt_to("john@foobar.org", "451 4.5.3").await;
+    session.rcpt_to("john@foobar.org", "452 4.5.3").await;
 
     // Check recipients
     assert_eq!(session.data.rcpt_to.len(), 3);
@@ -170,4 +183,17 @@ async fn rcpt() {
     let rcpt = session.data.rcpt_to.last().unwrap();
     assert!((rcpt.flags & (RCPT_NOTIFY_DELAY | RCPT_NOTIFY_SUCCESS | RCPT_NOTIFY_FAILURE)) != 0);
     assert_eq!(rcpt.dsn_info.as_ref().unwrap(), "Jane.Doe@Foobar.org");
+
+    // Message size limits per recipient domain
+    session.rset().await;
+    session
+        .mail_from("<john@example.net> SIZE=2000", "250")
+        .await;
+    session.rcpt_to("external@domain.com", "552 5.3.4").await;
+    session.rcpt_to("jane@foobar.org", "250").await;
+    assert_eq!(session.params.max_message_size, 1024 * 1024);
+    session.rset().await;
+    session.mail_from("john@example.net", "250").await;
+    session.rcpt_to("external@domain.com", "250").await;
+    assert_eq!(session.params.max_message_size, 1000);
 }
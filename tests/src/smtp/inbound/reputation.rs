@@ -0,0 +1,161 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+use std::time::Duration;
+
+use directory::config::ConfigDirectory;
+use utils::config::{Config, KeyLookup};
+
+use crate::smtp::{
+    inbound::TestQueueEvent, session::TestSession, ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{reputation::ConfigReputation, ConfigContext, EnvelopeKey, IfBlock, MaybeDynValue},
+    core::{Session, SMTP},
+    reputation::ReputationEvent,
+};
+
+const CONFIG: &str = r#"
+[session.reputation]
+enable = true
+min-events = 3
+threshold = {good = 10, poor = 50}
+
+[directory."local"]
+type = "memory"
+
+[[directory."local".users]]
+name = "jane"
+description = "Jane Doe"
+secret = "p4ssw0rd"
+email = "jane@foobar.org"
+
+[directory."local".lookup]
+domains = ["foobar.org"]
+"#;
+
+#[tokio::test]
+async fn client_reputation() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_reputation_test");
+    let config = Config::new(CONFIG).unwrap();
+    let directory = config.parse_directory().unwrap();
+    core.reputation.config = config.parse_reputation(&ConfigContext::new(&[])).unwrap();
+    let rcpt = &mut core.session.config.rcpt;
+    rcpt.directory = IfBlock::new(Some(MaybeDynValue::Static(
+        directory.directories.get("local").unwrap().clone(),
+    )));
+    rcpt.errors_max = IfBlock::new(100);
+    rcpt.errors_wait = IfBlock::new(Duration::from_millis(0));
+    rcpt.relay = r"[{if = 'reputation', eq = 'poor', then = false},
+    {else = true}]"
+        .parse_if(&ConfigContext::new(&[]));
+
+    // Clients without enough history have an unknown reputation
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    assert_eq!(session.key(&EnvelopeKey::Reputation), "unknown");
+
+    // Delivering legitimate messages builds a good reputation
+    for _ in 0..3 {
+        session
+            .send_message(
+                "john@example.org",
+                &["jane@foobar.org"],
+                "test:no_dkim",
+                "250",
+            )
+            .await;
+        qr.read_event().await.unwrap_message();
+    }
+    assert_eq!(session.key(&EnvelopeKey::Reputation), "good");
+    let entry = session
+        .core
+        .reputation
+        .get(&"10.0.0.1".parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(entry.accepted, 3);
+    assert_eq!(entry.percent(), 0);
+    session.mail_from("john@example.org", "250").await;
+    session.rcpt_to("bill@remote.org", "250").await;
+
+    // Probing for mailboxes degrades the reputation of another client
+    session.cmd("RSET", "250").await;
+    session.data.remote_ip = "10.0.0.2".parse().unwrap();
+    session.load_reputation().await;
+    assert_eq!(session.key(&EnvelopeKey::Reputation), "unknown");
+    session.mail_from("spammer@example.org", "250").await;
+    for rcpt in ["tom", "sam", "bob", "ann"] {
+        session
+            .rcpt_to(&format!("{rcpt}@foobar.org"), "550 5.1.2")
+            .await;
+    }
+    let entry = session
+        .core
+        .reputation
+        .get(&"10.0.0.2".parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(entry.invalid_rcpts, 4);
+    assert_eq!(entry.percent(), 50);
+    assert_eq!(session.key(&EnvelopeKey::Reputation), "poor");
+
+    // Poor reputation clients are not allowed to relay
+    session.rcpt_to("bill@remote.org", "550 5.1.2").await;
+    session.rcpt_to("jane@foobar.org", "250").await;
+
+    // Expired entries are removed
+    for mut entry in session.core.reputation.entries.iter_mut() {
+        entry.last_seen = 0;
+    }
+    session.core.reputation.cleanup();
+    assert!(session.core.reputation.entries.is_empty());
+    session.load_reputation().await;
+    assert_eq!(session.key(&EnvelopeKey::Reputation), "unknown");
+
+    // The number of tracked clients is bounded, the least recently seen are evicted first
+    let mut core = SMTP::test();
+    core.reputation.config = config.parse_reputation(&ConfigContext::new(&[])).unwrap();
+    core.reputation.config.max_entries = 10;
+    for n in 0..25u8 {
+        let ip = format!("10.0.1.{n}").parse().unwrap();
+        core.reputation
+            .record(ip, ReputationEvent::Accepted)
+            .await
+            .unwrap();
+        if let Some(mut entry) = core.reputation.entries.get_mut(&ip) {
+            entry.last_seen = n as u64 + 1;
+        }
+        assert!(core.reputation.entries.len() <= 10);
+    }
+    assert!(core
+        .reputation
+        .entries
+        .contains_key(&"10.0.1.24".parse().unwrap()));
+    assert!(!core
+        .reputation
+        .entries
+        .contains_key(&"10.0.1.0".parse().unwrap()));
+}
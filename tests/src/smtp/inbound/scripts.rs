@@ -22,7 +22,7 @@
 */
 
 use core::panic;
-use std::{fmt::Write, fs, path::PathBuf, sync::Arc};
+use std::{fmt::Write, fs, path::PathBuf, sync::Arc, time::Duration};
 
 use crate::smtp::{
     inbound::{sign::TextConfigContext, TestMessage, TestQueueEvent},
@@ -141,6 +141,7 @@ async fn sieve_scripts() {
     config.mail.script = IfBlock::new(ctx.scripts.get("stage_mail").cloned());
     config.rcpt.script = IfBlock::new(ctx.scripts.get("stage_rcpt").cloned());
     config.data.script = IfBlock::new(ctx.scripts.get("stage_data").cloned());
+    config.data.shadow_script = IfBlock::new(ctx.scripts.get("stage_data").cloned());
     config.rcpt.relay = IfBlock::new(true);
     config.data.pipe_commands = pipes;
     let core = Arc::new(core);
@@ -371,4 +372,11 @@ async fn sieve_scripts() {
         .assert_contains("X-My-Header: true")
         .assert_contains("Authentication-Results");
     qr.assert_empty_queue();
+
+    // Shadow evaluation must not queue messages and should agree with the active script
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    qr.assert_empty_queue();
+    let summary = core.sieve.shadow.summary();
+    assert_eq!(summary.total, 7);
+    assert_eq!(summary.disagreed, 0);
 }
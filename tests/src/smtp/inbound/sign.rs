@@ -219,6 +219,77 @@ async fn sign_and_seal() {
         );
 }
 
+#[tokio::test]
+async fn seal_relayed() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_seal_relay_test");
+
+    // Add DKIM records used by the ARC chain
+    core.resolvers.dns.txt_add(
+        "ed._domainkey.scamorza.org",
+        DomainKey::parse(
+            concat!(
+                "v=DKIM1; k=ed25519; ",
+                "p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo="
+            )
+            .as_bytes(),
+        )
+        .unwrap(),
+        Instant::now() + Duration::from_secs(5),
+    );
+    core.resolvers.dns.txt_add(
+        "rsa._domainkey.manchego.org",
+        DomainKey::parse(
+            concat!(
+                "v=DKIM1; t=s; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQ",
+                "KBgQDwIRP/UC3SBsEmGqZ9ZJW3/DkMoGeLnQg1fWn7/zYt",
+                "IxN2SnFCjxOCKG9v3b4jYfcTNh5ijSsq631uBItLa7od+v",
+                "/RtdC2UzJ1lWT947qR+Rcac2gbto/NMqJ0fzfVjH4OuKhi",
+                "tdY9tf6mcwGjaNBcWToIMmPSPDdQPNUYckcQ2QIDAQAB",
+            )
+            .as_bytes(),
+        )
+        .unwrap(),
+        Instant::now() + Duration::from_secs(5),
+    );
+    core.session.config.rcpt.relay = IfBlock::new(true);
+
+    // Seal only messages relayed to foobar.net
+    let ctx = ConfigContext::new(&[]).parse_signatures();
+    core.mail_auth.dkim.verify = IfBlock::new(VerifyStrategy::Relaxed);
+    core.mail_auth.arc.verify = IfBlock::new(VerifyStrategy::Disable);
+    core.queue.config.arc_seal = "[{if = 'rcpt-domain', eq = 'foobar.net', then = 'ed'},
+    {else = false}]"
+        .parse_if::<Option<DynValue<EnvelopeKey>>>(&ctx)
+        .map_if_block(&ctx.sealers, "", "")
+        .unwrap();
+
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.2".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.example.com").await;
+
+    // Messages to other routes are not sealed
+    session
+        .send_message("bill@foobar.org", &["jane@foobar.com"], "test:arc", "250")
+        .await;
+    qr.read_event()
+        .await
+        .unwrap_message()
+        .read_lines()
+        .assert_not_contains("ARC-Seal: i=3;");
+
+    // Messages relayed to foobar.net are sealed
+    session
+        .send_message("bill@foobar.org", &["jane@foobar.net"], "test:arc", "250")
+        .await;
+    qr.read_event()
+        .await
+        .unwrap_message()
+        .read_lines()
+        .assert_contains("ARC-Seal: i=3; a=ed25519-sha256; s=ed; d=example.com; cv=pass;");
+}
+
 pub trait TextConfigContext<'x> {
     fn parse_signatures(self) -> ConfigContext<'x>;
 }
@@ -0,0 +1,279 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use directory::config::ConfigDirectory;
+use smtp_proto::Response;
+use utils::config::Config;
+
+use crate::smtp::{
+    inbound::TestQueueEvent,
+    session::{DummyIo, TestSession},
+    QueueReceiver, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{suppression::ConfigSuppression, ConfigContext, IfBlock},
+    core::{Session, SMTP},
+    queue::{ErrorDetails, HostResponse, Message, Status},
+};
+
+const CONFIG: &str = r#"
+[queue.suppression]
+enable = true
+threshold = 2
+expiry = "30d"
+"#;
+
+#[tokio::test]
+async fn suppression_list() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_suppression_test");
+    core.suppression.config = Config::new(CONFIG)
+        .unwrap()
+        .parse_suppression(&ConfigContext::new(&[]))
+        .unwrap();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.rcpt.errors_max = IfBlock::new(100);
+    core.session.config.rcpt.errors_wait = IfBlock::new(Duration::from_millis(0));
+
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.data.authenticated_as = "john".to_string();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+
+    // A single hard bounce does not suppress the address
+    let mut message = send(&mut session, &mut qr, "bill@remote.org").await;
+    set_status(&mut message, 550, [5, 1, 1]);
+    session.core.suppression.record_outcomes(&mut message).await;
+    session.core.suppression.record_outcomes(&mut message).await;
+    assert_eq!(
+        session
+            .core
+            .suppression
+            .entries
+            .get("bill@remote.org")
+            .unwrap()
+            .bounces,
+        1
+    );
+    assert!(session
+        .core
+        .suppression
+        .get("bill@remote.org")
+        .await
+        .is_none());
+
+    // Policy rejections are not counted as hard bounces
+    for _ in 0..2 {
+        let mut message = send(&mut session, &mut qr, "jane@remote.org").await;
+        set_status(&mut message, 550, [5, 7, 1]);
+        session.core.suppression.record_outcomes(&mut message).await;
+    }
+    assert!(session
+        .core
+        .suppression
+        .entries
+        .get("jane@remote.org")
+        .is_none());
+
+    // The second hard bounce adds the address to the suppression list
+    let mut message = send(&mut session, &mut qr, "bill@remote.org").await;
+    set_status(&mut message, 550, [5, 1, 1]);
+    session.core.suppression.record_outcomes(&mut message).await;
+    let list = session.core.suppression.list();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].0, "bill@remote.org");
+    assert_eq!(list[0].1.bounces, 2);
+    session.mail_from("john@foobar.org", "250").await;
+    session.rcpt_to("Bill@remote.org", "550 5.1.1").await;
+    session.rcpt_to("jane@remote.org", "250").await;
+    session.cmd("RSET", "250").await;
+
+    // Only authenticated submissions are checked against the list
+    session.data.authenticated_as.clear();
+    session.mail_from("john@foobar.org", "250").await;
+    session.rcpt_to("bill@remote.org", "250").await;
+    session.cmd("RSET", "250").await;
+    session.data.authenticated_as = "john".to_string();
+
+    // Successful deliveries reset the history of addresses that are not suppressed
+    session
+        .core
+        .suppression
+        .record_bounce("tom@remote.org", "550".to_string())
+        .await;
+    let mut message = send(&mut session, &mut qr, "tom@remote.org").await;
+    set_status(&mut message, 250, [2, 1, 5]);
+    session.core.suppression.record_outcomes(&mut message).await;
+    assert!(session
+        .core
+        .suppression
+        .entries
+        .get("tom@remote.org")
+        .is_none());
+
+    // Addresses can be managed manually
+    assert!(session.core.suppression.remove("bill@remote.org").await);
+    assert!(!session.core.suppression.remove("bill@remote.org").await);
+    session
+        .core
+        .suppression
+        .suppress("Sam@remote.org", "Complaint".to_string())
+        .await;
+    session.mail_from("john@foobar.org", "250").await;
+    session.rcpt_to("bill@remote.org", "250").await;
+    session.rcpt_to("sam@remote.org", "550 5.1.1").await;
+    session.cmd("RSET", "250").await;
+
+    // Expired entries are removed
+    for mut entry in session.core.suppression.entries.iter_mut() {
+        entry.last_bounce = 0;
+    }
+    assert!(session
+        .core
+        .suppression
+        .get("sam@remote.org")
+        .await
+        .is_none());
+    session.core.suppression.cleanup();
+    assert!(session.core.suppression.entries.is_empty());
+    session.mail_from("john@foobar.org", "250").await;
+    session.rcpt_to("sam@remote.org", "250").await;
+}
+
+const SQL_CONFIG: &str = r#"
+[directory."sql"]
+type = "sql"
+address = "sqlite::memory:"
+
+[directory."sql".pool]
+max-connections = 1
+
+[directory."sql".lookup]
+suppression-get = "SELECT bounces, reason, last_bounce FROM suppression WHERE address = ?"
+suppression-bounce = "INSERT INTO suppression (address, bounces, reason, last_bounce) VALUES (?, 1, ?, ?) ON CONFLICT(address) DO UPDATE SET bounces = bounces + 1, reason = excluded.reason, last_bounce = excluded.last_bounce RETURNING bounces"
+suppression-set = "INSERT INTO suppression (address, bounces, reason, last_bounce) VALUES (?, ?, ?, ?) ON CONFLICT(address) DO UPDATE SET bounces = excluded.bounces, reason = excluded.reason, last_bounce = excluded.last_bounce"
+suppression-delete = "DELETE FROM suppression WHERE address = ?"
+
+[queue.suppression]
+enable = true
+threshold = 2
+expiry = "30d"
+
+[queue.suppression.lookup]
+get = "sql/suppression-get"
+bounce = "sql/suppression-bounce"
+set = "sql/suppression-set"
+delete = "sql/suppression-delete"
+"#;
+
+#[tokio::test]
+async fn suppression_list_sql() {
+    let mut ctx = ConfigContext::new(&[]);
+    let config = Config::new(SQL_CONFIG).unwrap();
+    ctx.directory = config.parse_directory().unwrap();
+    ctx.directory
+        .directories
+        .get("sql")
+        .unwrap()
+        .query(
+            concat!(
+                "CREATE TABLE suppression (address TEXT PRIMARY KEY, ",
+                "bounces INTEGER, reason TEXT, last_bounce INTEGER)"
+            ),
+            &[],
+        )
+        .await
+        .unwrap();
+
+    // Both nodes share the list stored in the database
+    let mut node_a = SMTP::test();
+    node_a.suppression.config = config.parse_suppression(&ctx).unwrap();
+    let mut node_b = SMTP::test();
+    node_b.suppression.config = config.parse_suppression(&ctx).unwrap();
+    node_a
+        .suppression
+        .record_bounce("bill@remote.org", "550 5.1.1 Unknown user".to_string())
+        .await;
+    assert!(node_b.suppression.get("bill@remote.org").await.is_none());
+    node_b
+        .suppression
+        .record_bounce("bill@remote.org", "550 5.1.1 Unknown user".to_string())
+        .await;
+    let entry = node_a.suppression.get("bill@remote.org").await.unwrap();
+    assert_eq!(entry.bounces, 2);
+    assert_eq!(entry.reason, "550 5.1.1 Unknown user");
+    assert!(node_a.suppression.entries.is_empty());
+    assert!(node_b.suppression.entries.is_empty());
+
+    // Manual changes are visible to all nodes
+    assert!(node_b.suppression.remove("Bill@remote.org").await);
+    assert!(!node_a.suppression.remove("bill@remote.org").await);
+    assert!(node_a.suppression.get("bill@remote.org").await.is_none());
+    assert!(
+        node_a
+            .suppression
+            .suppress("sam@remote.org", "Complaint".to_string())
+            .await
+    );
+    assert_eq!(
+        node_b
+            .suppression
+            .get("sam@remote.org")
+            .await
+            .unwrap()
+            .reason,
+        "Complaint"
+    );
+}
+
+async fn send(session: &mut Session<DummyIo>, qr: &mut QueueReceiver, rcpt: &str) -> Box<Message> {
+    session
+        .send_message("john@foobar.org", &[rcpt], "test:no_dkim", "250")
+        .await;
+    qr.read_event().await.unwrap_message()
+}
+
+fn set_status(message: &mut Message, code: u16, esc: [u8; 3]) {
+    let response = Response {
+        code,
+        esc,
+        message: "Recipient rejected".to_string(),
+    };
+    message.recipients[0].status = if code < 300 {
+        Status::Completed(HostResponse {
+            hostname: "mx.remote.org".to_string(),
+            response,
+        })
+    } else {
+        Status::PermanentFailure(HostResponse {
+            hostname: ErrorDetails {
+                entity: "mx.remote.org".to_string(),
+                details: "RCPT TO:<rcpt@remote.org>".to_string(),
+            },
+            response,
+        })
+    };
+}
@@ -21,13 +21,14 @@
  * for more details.
 */
 
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
 use crate::smtp::{session::TestSession, ParseTestConfig, TestConfig};
 use smtp::{
     config::ConfigContext,
     core::{Session, SessionAddress, SMTP},
 };
+use utils::config::utils::ParseKey;
 
 #[tokio::test]
 async fn throttle_inbound() {
@@ -111,3 +112,61 @@ async fn throttle_inbound() {
     session.data.remote_ip = "10.0.0.2".parse().unwrap();
     assert!(session.is_allowed().await, "Rate limiter too strict.");
 }
+
+#[tokio::test]
+async fn connection_limits() {
+    let mut core = SMTP::test();
+    core.session.config.connections.exempt = vec!["192.168.0.0/16".parse_key("exempt").unwrap()];
+    core.session.connections.set_limits(3, 2);
+    let core = Arc::new(core);
+
+    // Enforce per-IP limit
+    let mut sessions = Vec::new();
+    for (remote_ip, is_allowed) in [
+        ("10.0.0.1", true),
+        ("10.0.0.1", true),
+        ("10.0.0.1", false),
+        ("10.0.0.2", true),
+    ] {
+        let mut session = Session::test(core.clone());
+        session.data.remote_ip = remote_ip.parse().unwrap();
+        assert_eq!(session.is_connection_allowed(), is_allowed, "{remote_ip}");
+        sessions.push(session);
+    }
+
+    // Enforce global limit, exempt allowlisted networks
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip = "10.0.0.3".parse().unwrap();
+    assert!(!session.is_connection_allowed());
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip = "192.168.1.1".parse().unwrap();
+    assert!(session.is_connection_allowed());
+
+    let status = core.session.connections.status(10);
+    assert_eq!(status.concurrent, 3);
+    assert_eq!(status.rejected, 2);
+    assert_eq!(
+        status.top_ips,
+        vec![
+            ("10.0.0.1".parse().unwrap(), 2),
+            ("10.0.0.2".parse().unwrap(), 1)
+        ]
+    );
+
+    // Closing sessions releases their slots
+    sessions.clear();
+    let status = core.session.connections.status(10);
+    assert_eq!(status.concurrent, 0);
+    assert!(status.top_ips.is_empty());
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip = "10.0.0.3".parse().unwrap();
+    assert!(session.is_connection_allowed());
+
+    // Limits can be changed at runtime
+    core.session.connections.set_limits(0, 0);
+    for _ in 0..5 {
+        let mut session = Session::test(core.clone());
+        session.data.remote_ip = "10.0.0.1".parse().unwrap();
+        assert!(session.is_connection_allowed());
+    }
+}
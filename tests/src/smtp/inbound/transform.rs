@@ -0,0 +1,178 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::config::ConfigDirectory;
+use utils::config::{Config, DynValue};
+
+use crate::smtp::{
+    inbound::{sign::TextConfigContext, TestMessage, TestQueueEvent},
+    session::{TestSession, VerifyResponse},
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{session::ConfigSession, ConfigContext, EnvelopeKey},
+    core::{Session, SMTP},
+};
+
+const CONFIG: &str = r#"
+[session.data.srs]
+enable = true
+domain = "srs.example.com"
+keys = ["srs-secret-key"]
+
+[directory."local"]
+type = "memory"
+
+[[directory."local".users]]
+name = "john"
+description = "John Doe"
+secret = "secret"
+email = ["jdoe@example.com"]
+
+[directory."local".lookup]
+domains = ["example.com"]
+"#;
+
+#[tokio::test]
+async fn transform_message() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_transform_test");
+    let config = Config::new(CONFIG).unwrap();
+    let mut ctx = ConfigContext::new(&[]).parse_signatures();
+    ctx.directory = config.parse_directory().unwrap();
+
+    // Rewritten addresses can be reversed until they expire
+    let srs = config.parse_srs(&ctx, &[]).unwrap();
+    let now = 1700000000;
+    let address = srs.forward("Bill@foobar.org", now).unwrap();
+    assert!(
+        address.starts_with("SRS0=") && address.ends_with("=foobar.org=Bill@srs.example.com"),
+        "{address}"
+    );
+    assert_eq!(
+        srs.reverse(&address, now + 86400 * 20).unwrap(),
+        "Bill@foobar.org"
+    );
+    assert_eq!(
+        srs.reverse(&address.to_lowercase(), now).unwrap(),
+        "bill@foobar.org"
+    );
+    assert_eq!(srs.reverse(&address, now + 86400 * 30), None);
+    assert_eq!(srs.reverse(&address.replace("Bill", "Jane"), now), None);
+    assert_eq!(
+        srs.reverse(&address.replace("srs.example.com", "example.com"), now),
+        None
+    );
+
+    let config = &mut core.session.config;
+    config.rcpt.directory = "'local'"
+        .parse_if::<Option<DynValue<EnvelopeKey>>>(&ctx)
+        .map_if_block(&ctx.directory.directories, "", "")
+        .unwrap();
+    config.rcpt.relay = r"[{if = 'authenticated-as', ne = '', then = true},
+    {else = false}]"
+        .parse_if(&ctx);
+    config.data.srs = srs;
+    config.data.scrub_headers = "['X-Mailer', 'X-Originating-IP']".parse_if(&ctx);
+    core.mail_auth.dkim.sign = "['rsa']"
+        .parse_if::<Vec<DynValue<EnvelopeKey>>>(&ctx)
+        .map_if_block(&ctx.signers, "", "")
+        .unwrap();
+
+    // Messages relayed on behalf of remote senders get a rewritten return path,
+    // and scrubbed headers are removed
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.example.com").await;
+    session.data.authenticated_as = "john".to_string();
+    let message = concat!(
+        "From: bill@foobar.org\r\n",
+        "To: jane@remote.org\r\n",
+        "X-Mailer: Test Mailer\r\n",
+        "X-Originating-IP:\r\n",
+        "\t10.0.0.1\r\n",
+        "Subject: Forwarded\r\n",
+        "\r\n",
+        "Hi!\r\n"
+    );
+    session
+        .send_message("bill@foobar.org", &["jane@remote.org"], message, "250")
+        .await;
+    let queued = qr.read_event().await.unwrap_message();
+    assert!(
+        queued.return_path.starts_with("SRS0=")
+            && queued
+                .return_path
+                .ends_with("=foobar.org=bill@srs.example.com"),
+        "{}",
+        queued.return_path
+    );
+    assert_eq!(queued.return_path_domain, "srs.example.com");
+    let srs_address = queued.return_path.clone();
+    queued
+        .read_lines()
+        .assert_not_contains("X-Mailer")
+        .assert_not_contains("X-Originating-IP")
+        .assert_not_contains("10.0.0.1")
+        .assert_contains("Subject: Forwarded");
+
+    // Senders of local domains keep their return path
+    session
+        .send_message("jdoe@example.com", &["jane@remote.org"], message, "250")
+        .await;
+    assert_eq!(
+        qr.read_event().await.unwrap_message().return_path,
+        "jdoe@example.com"
+    );
+
+    // Previews include the rewritten return path but not the signature value
+    session.mail_from("bill@foobar.org", "250").await;
+    let preview =
+        String::from_utf8(session.preview_message(message.as_bytes().to_vec()).await).unwrap();
+    assert!(preview.starts_with("Return-Path: <SRS0="), "{preview}");
+    assert!(preview.contains("DKIM-Signature:"), "{preview}");
+    assert!(preview.contains("b=\r\n"), "{preview}");
+    assert!(!preview.contains("X-Mailer"), "{preview}");
+    session.rset().await;
+
+    // Bounces addressed to rewritten return paths are relayed to the original sender
+    session.data.authenticated_as.clear();
+    session.mail_from("<>", "250").await;
+    session.rcpt_to(&srs_address, "250").await;
+    assert_eq!(
+        session.data.rcpt_to.last().unwrap().address,
+        "bill@foobar.org"
+    );
+    session
+        .rcpt_to(
+            "SRS0=AAAAAA=AA=foobar.org=bill@srs.example.com",
+            "550 5.1.2",
+        )
+        .await;
+    session.rset().await;
+
+    // Other senders cannot use rewritten addresses to relay messages
+    session.mail_from("john@foobar.org", "250").await;
+    session.rcpt_to(&srs_address, "550 5.1.2").await;
+}
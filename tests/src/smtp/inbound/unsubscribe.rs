@@ -0,0 +1,263 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use directory::config::ConfigDirectory;
+use utils::config::Config;
+
+use crate::smtp::{
+    inbound::{TestMessage, TestQueueEvent},
+    session::{TestSession, VerifyResponse},
+    TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{unsubscribe::ConfigUnsubscribe, IfBlock, MaybeDynValue},
+    core::{Session, SMTP},
+    unsubscribe::{find_list_id, is_bulk_message, UnsubscribeError, UnsubscribeToken},
+    webhook::now,
+};
+
+const CONFIG: &str = r#"
+[unsubscribe]
+url = "https://mail.example.org/unsubscribe/"
+signature.keys = ["new-secret", "old-secret"]
+expiry = "7d"
+"#;
+
+const DIRECTORY: &str = r#"
+[directory."local"]
+type = "memory"
+
+[[directory."local".users]]
+name = "john"
+description = "John Doe"
+secret = "secret"
+email = ["john@foobar.org"]
+
+[[directory."local".users]]
+name = "jane"
+description = "Jane Doe"
+secret = "secret"
+email = ["jane@foobar.org"]
+
+[directory."local".lookup]
+domains = ["foobar.org"]
+"#;
+
+#[tokio::test]
+async fn unsubscribe() {
+    // Parse configuration
+    let config = Config::new(CONFIG).unwrap().parse_unsubscribe().unwrap();
+    assert!(config.is_enabled());
+    assert_eq!(config.expiry.as_secs(), 7 * 86400);
+    for invalid_config in [
+        "[unsubscribe]\nurl = \"http://mail.example.org/unsubscribe\"\nsignature.keys = [\"a\"]",
+        "[unsubscribe]\nurl = \"https://mail.example.org/unsubscribe\"",
+    ] {
+        assert!(Config::new(invalid_config)
+            .unwrap()
+            .parse_unsubscribe()
+            .is_err());
+    }
+    assert!(!Config::new("")
+        .unwrap()
+        .parse_unsubscribe()
+        .unwrap()
+        .is_enabled());
+
+    // Sign and verify tokens
+    let token = UnsubscribeToken {
+        queue_id: 1234,
+        from: "news@example.org".to_string(),
+        to: "john@foobar.org".to_string(),
+        list_id: "news.example.org".to_string().into(),
+        message_id: "abc@example.org".to_string().into(),
+        expires: now() + 60,
+    };
+    let signed_token = config.sign(&token).unwrap();
+    assert_eq!(config.verify(&signed_token), Ok(token.clone()));
+
+    // Tokens signed with a rotated key are still accepted
+    let mut old_config = Config::new(CONFIG).unwrap().parse_unsubscribe().unwrap();
+    old_config.keys.reverse();
+    assert_eq!(
+        config.verify(&old_config.sign(&token).unwrap()),
+        Ok(token.clone())
+    );
+    old_config.keys = vec![b"unknown-secret".to_vec()];
+    assert_eq!(
+        config.verify(&old_config.sign(&token).unwrap()),
+        Err(UnsubscribeError::Invalid)
+    );
+
+    // Tampered, malformed and expired tokens are rejected
+    let (payload, signature) = signed_token.split_once('.').unwrap();
+    let tampered_token = config
+        .sign(&UnsubscribeToken {
+            to: "jane@foobar.org".to_string(),
+            ..token.clone()
+        })
+        .unwrap();
+    let (tampered_payload, _) = tampered_token.split_once('.').unwrap();
+    for invalid_token in [
+        format!("{tampered_payload}.{signature}"),
+        payload.to_string(),
+        format!("{payload}.!!"),
+        String::new(),
+    ] {
+        assert_eq!(
+            config.verify(&invalid_token),
+            Err(UnsubscribeError::Invalid),
+            "{invalid_token}"
+        );
+    }
+    assert_eq!(
+        config.verify(
+            &config
+                .sign(&UnsubscribeToken {
+                    expires: now() - 1,
+                    ..token.clone()
+                })
+                .unwrap()
+        ),
+        Err(UnsubscribeError::Expired)
+    );
+
+    // Detect bulk messages
+    for (headers, expected_bulk, expected_list_id) in [
+        (
+            vec![
+                ("Precedence", " bulk"),
+                ("List-Id", " Newsletter <news.example.org>"),
+            ],
+            true,
+            Some("news.example.org"),
+        ),
+        (vec![("Precedence", " List")], true, None),
+        (vec![("Precedence", " first-class")], false, None),
+        (
+            vec![
+                ("Precedence", " bulk"),
+                ("List-Unsubscribe", " <mailto:leave@example.org>"),
+            ],
+            false,
+            None,
+        ),
+        (
+            vec![("Subject", " hello"), ("List-Id", " news.example.org")],
+            false,
+            Some("news.example.org"),
+        ),
+    ] {
+        let headers = headers
+            .into_iter()
+            .map(|(name, value)| (name.as_bytes(), value.as_bytes()))
+            .collect::<Vec<_>>();
+        assert_eq!(is_bulk_message(&headers), expected_bulk, "{headers:?}");
+        assert_eq!(
+            find_list_id(&headers).as_deref(),
+            expected_list_id,
+            "{headers:?}"
+        );
+    }
+
+    // Add unsubscribe headers to bulk messages
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_unsubscribe_test");
+    let directory = Config::new(DIRECTORY).unwrap().parse_directory().unwrap();
+    core.session.config.rcpt.directory = IfBlock::new(Some(MaybeDynValue::Static(
+        directory.directories.get("local").unwrap().clone(),
+    )));
+    core.session.config.data.add_list_unsubscribe = IfBlock::new(true);
+    core.unsubscribe = config;
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.example.org").await;
+    let message = concat!(
+        "From: news@example.org\r\n",
+        "To: john@foobar.org\r\n",
+        "Subject: Weekly news\r\n",
+        "Message-ID: <abc@example.org>\r\n",
+        "List-Id: Newsletter <news.example.org>\r\n",
+        "Precedence: bulk\r\n",
+        "\r\n",
+        "Read all about it!\r\n"
+    );
+    session
+        .send_message("news@example.org", &["john@foobar.org"], message, "250")
+        .await;
+    let lines = qr
+        .read_event()
+        .await
+        .unwrap_message()
+        .read_lines()
+        .assert_contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click");
+    let header = lines
+        .iter()
+        .find_map(|line| line.strip_prefix("List-Unsubscribe: <"))
+        .unwrap()
+        .trim_end()
+        .strip_suffix('>')
+        .unwrap();
+    let token = session
+        .core
+        .process_unsubscribe(
+            header
+                .strip_prefix("https://mail.example.org/unsubscribe/")
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(token.from, "news@example.org");
+    assert_eq!(token.to, "john@foobar.org");
+    assert_eq!(token.list_id.as_deref(), Some("news.example.org"));
+    assert_eq!(token.message_id.as_deref(), Some("abc@example.org"));
+
+    // Messages that are not bulk or have multiple recipients are left unchanged
+    session
+        .send_message(
+            "news@example.org",
+            &["john@foobar.org"],
+            &message.replace("Precedence: bulk", "Precedence: normal"),
+            "250",
+        )
+        .await;
+    qr.read_event()
+        .await
+        .unwrap_message()
+        .read_lines()
+        .assert_not_contains("List-Unsubscribe");
+    session
+        .send_message(
+            "news@example.org",
+            &["john@foobar.org", "jane@foobar.org"],
+            message,
+            "250",
+        )
+        .await;
+    qr.read_event()
+        .await
+        .unwrap_message()
+        .read_lines()
+        .assert_not_contains("List-Unsubscribe");
+}
@@ -0,0 +1,253 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use utils::config::Config;
+
+use crate::smtp::{
+    inbound::TestQueueEvent,
+    session::{TestSession, VerifyResponse},
+    TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{usage::ConfigUsage, ConfigContext, IfBlock},
+    core::{Session, SMTP},
+    usage::UsageKey,
+};
+
+const CONFIG: &str = r#"
+[[usage.limit]]
+match = {if = "authenticated-as", eq = "john"}
+scope = "account"
+period = "daily"
+messages = 2
+
+[[usage.limit]]
+scope = "domain"
+period = "monthly"
+messages = 3
+"#;
+
+#[tokio::test]
+async fn usage_limits() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_usage_test");
+    core.usage.config = Config::new(CONFIG)
+        .unwrap()
+        .parse_usage(&ConfigContext::new(&[]))
+        .unwrap();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+
+    // Unauthenticated sessions are not accounted
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    session
+        .send_message(
+            "john@foobar.org",
+            &["bill@remote.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    qr.read_event().await.unwrap_message();
+    assert!(session.core.usage.counters.is_empty());
+
+    // John is allowed to send two messages per day
+    session.data.authenticated_as = "john".to_string();
+    for _ in 0..2 {
+        session
+            .send_message(
+                "john@foobar.org",
+                &["bill@remote.org"],
+                "test:no_dkim",
+                "250",
+            )
+            .await;
+        qr.read_event().await.unwrap_message();
+    }
+    session
+        .send_message(
+            "john@foobar.org",
+            &["bill@remote.org"],
+            "test:no_dkim",
+            "550 5.4.5",
+        )
+        .await;
+    qr.assert_empty_queue();
+
+    let counter = session
+        .core
+        .usage
+        .get(&UsageKey::Account("john".to_string()))
+        .unwrap();
+    assert_eq!(counter.daily.messages, 2);
+    assert_eq!(counter.monthly.messages, 2);
+    assert_eq!(counter.total.messages, 2);
+    assert!(counter.daily.size > 0);
+
+    // The domain limit applies to all accounts sending from foobar.org
+    session.data.authenticated_as = "jane".to_string();
+    session
+        .send_message(
+            "jane@foobar.org",
+            &["bill@remote.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    qr.read_event().await.unwrap_message();
+    session
+        .send_message(
+            "jane@foobar.org",
+            &["bill@remote.org"],
+            "test:no_dkim",
+            "550 5.4.5",
+        )
+        .await;
+    qr.assert_empty_queue();
+
+    // Other domains are not affected
+    session
+        .send_message(
+            "jane@domain.net",
+            &["bill@remote.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    qr.read_event().await.unwrap_message();
+    assert_eq!(
+        session
+            .core
+            .usage
+            .get(&UsageKey::Domain("foobar.org".to_string()))
+            .unwrap()
+            .monthly
+            .messages,
+        3
+    );
+    assert_eq!(
+        session
+            .core
+            .usage
+            .get(&UsageKey::Account("jane".to_string()))
+            .unwrap()
+            .total
+            .messages,
+        2
+    );
+}
+
+const CONFIG_ACTIONS: &str = r#"
+[[usage.limit]]
+match = {if = "authenticated-as", eq = "john"}
+scope = "account"
+period = "hourly"
+recipients = 3
+action = "defer"
+
+[[usage.limit]]
+match = {if = "authenticated-as", eq = "jane"}
+scope = "account"
+period = "hourly"
+messages = 1
+action = "alert"
+"#;
+
+#[tokio::test]
+async fn usage_limits_actions() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_usage_actions_test");
+    core.usage.config = Config::new(CONFIG_ACTIONS)
+        .unwrap()
+        .parse_usage(&ConfigContext::new(&[]))
+        .unwrap();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+
+    // John can send to three recipients per hour, further messages are deferred
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.foobar.org").await;
+    session.data.authenticated_as = "john".to_string();
+    session
+        .send_message(
+            "john@foobar.org",
+            &["bill@remote.org", "jane@remote.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    qr.read_event().await.unwrap_message();
+    session
+        .send_message(
+            "john@foobar.org",
+            &["mike@remote.org", "lisa@remote.org"],
+            "test:no_dkim",
+            "451 4.4.5",
+        )
+        .await;
+    qr.assert_empty_queue();
+    session
+        .send_message(
+            "john@foobar.org",
+            &["mike@remote.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    qr.read_event().await.unwrap_message();
+    let counter = session
+        .core
+        .usage
+        .get(&UsageKey::Account("john".to_string()))
+        .unwrap();
+    assert_eq!(counter.hourly.messages, 2);
+    assert_eq!(counter.hourly.recipients, 3);
+    assert_eq!(counter.total.recipients, 3);
+
+    // Jane's limit only raises an alert
+    session.data.authenticated_as = "jane".to_string();
+    for _ in 0..2 {
+        session
+            .send_message(
+                "jane@foobar.org",
+                &["bill@remote.org"],
+                "test:no_dkim",
+                "250",
+            )
+            .await;
+        qr.read_event().await.unwrap_message();
+    }
+    assert_eq!(
+        session
+            .core
+            .usage
+            .get(&UsageKey::Account("jane".to_string()))
+            .unwrap()
+            .hourly
+            .messages,
+        2
+    );
+}
@@ -0,0 +1,152 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::net::IpAddr;
+
+use crate::smtp::{
+    inbound::{TestMessage, TestQueueEvent},
+    session::{TestSession, VerifyResponse},
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{ConfigContext, IfBlock},
+    core::{Session, SMTP},
+    inbound::xclient::{ClientAttributes, XCommand},
+};
+
+#[tokio::test]
+async fn xclient() {
+    let mut core = SMTP::test();
+    let mut qr = core.init_test_queue("smtp_xclient_test");
+    let ctx = ConfigContext::new(&[]);
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.data.add_received = IfBlock::new(true);
+    let config = &mut core.session.config.extensions;
+    config.xclient = r"[{if = 'remote-ip', eq = '10.0.0.1', then = true},
+    {else = false}]"
+        .parse_if(&ctx);
+    config.xforward = config.xclient.clone();
+
+    // Attribute parsing
+    assert_eq!(
+        ClientAttributes::parse(
+            XCommand::XClient,
+            "XCLIENT NAME=[UNAVAILABLE] ADDR=IPV6:2001:db8::1 PORT=25 PROTO=ESMTP LOGIN=john+2Bdoe"
+        ),
+        Some(ClientAttributes {
+            name: Some(None),
+            addr: Some(Some("2001:db8::1".parse().unwrap())),
+            port: Some(Some(25)),
+            login: Some(Some("john+doe".to_string())),
+            ..Default::default()
+        })
+    );
+    for line in [
+        "XCLIENT",
+        "XCLIENT ADDR=bogus",
+        "XCLIENT PORT=70000",
+        "XCLIENT PROTO=HTTP",
+        "XCLIENT NAME=a+ZZ",
+        "XCLIENT UNKNOWN=1",
+        "XFORWARD LOGIN=john",
+    ] {
+        let command = if line.starts_with("XCLIENT") {
+            XCommand::XClient
+        } else {
+            XCommand::XForward
+        };
+        assert_eq!(ClientAttributes::parse(command, line), None, "{line}");
+    }
+
+    // Untrusted clients cannot use XCLIENT or XFORWARD
+    let mut session = Session::test(core);
+    session.data.remote_ip = "10.0.0.2".parse().unwrap();
+    session.eval_session_params().await;
+    session
+        .ehlo("mx.foobar.org")
+        .await
+        .assert_not_contains("XCLIENT")
+        .assert_not_contains("XFORWARD");
+    session.cmd("XCLIENT ADDR=192.168.1.1", "550 5.7.0").await;
+    session.cmd("XFORWARD ADDR=192.168.1.1", "550 5.7.0").await;
+    assert_eq!(
+        session.data.remote_ip,
+        "10.0.0.2".parse::<IpAddr>().unwrap()
+    );
+
+    // Trusted upstreams
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session
+        .ehlo("mx.foobar.org")
+        .await
+        .assert_contains("XCLIENT NAME ADDR PORT PROTO HELO LOGIN DESTADDR DESTPORT")
+        .assert_contains("XFORWARD NAME ADDR PORT PROTO HELO IDENT SOURCE");
+    session.cmd("XCLIENT ADDR=bogus", "501 5.5.4").await;
+    session.cmd("xforward helo=relay.remote.org", "250").await;
+    assert_eq!(session.data.helo_domain, "relay.remote.org");
+
+    // Not allowed during a transaction
+    session.mail_from("john@remote.org", "250").await;
+    session.cmd("XFORWARD ADDR=192.168.1.1", "503 5.5.1").await;
+    session.rset().await;
+
+    // XCLIENT restarts the session on behalf of the original client,
+    // commands split across reads are also handled
+    session
+        .ingest(b"XCLIENT NAME=mail.remote.org ")
+        .await
+        .unwrap();
+    session
+        .ingest(b"ADDR=192.168.1.1 PORT=4567 LOGIN=john\r\n")
+        .await
+        .unwrap();
+    session.response().assert_code("220");
+    assert_eq!(
+        session.data.remote_ip,
+        "192.168.1.1".parse::<IpAddr>().unwrap()
+    );
+    assert_eq!(session.data.remote_port, 4567);
+    assert_eq!(session.data.authenticated_as, "john");
+    assert!(session.data.helo_domain.is_empty());
+
+    // The original client is recorded in the Received header
+    session.ehlo("mail.remote.org").await;
+    session
+        .send_message(
+            "john@remote.org",
+            &["bill@foobar.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    qr.read_event()
+        .await
+        .unwrap_message()
+        .read_lines()
+        .assert_contains("Received: from mail.remote.org (mail.remote.org [192.168.1.1])");
+
+    // Permissions are kept for the remainder of the session
+    session.cmd("XCLIENT NAME=[TEMPUNAVAIL]", "220").await;
+    assert!(session.data.iprev.is_none());
+}
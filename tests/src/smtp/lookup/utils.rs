@@ -30,7 +30,7 @@ use mail_parser::DateTime;
 use smtp::{
     config::AggregateFrequency,
     outbound::{
-        lookup::ToNextHop,
+        lookup::{is_valid_mx, ToNextHop},
         mta_sts::{Mode, MxPattern, Policy},
     },
     queue::RecipientDomain,
@@ -143,6 +143,47 @@ fn to_remote_hosts() {
         preference: 0,
     }];
     assert!(mx.to_remote_hosts("domain", 10).is_none());
+
+    // Null MX published with a non-zero preference
+    let mx = vec![MX {
+        exchanges: vec!["".to_string()],
+        preference: 10,
+    }];
+    assert!(mx.to_remote_hosts("domain", 10).is_none());
+
+    // Null MX mixed with regular exchanges is skipped
+    let mx = vec![
+        MX {
+            exchanges: vec![".".to_string()],
+            preference: 0,
+        },
+        MX {
+            exchanges: vec!["mx1".to_string()],
+            preference: 10,
+        },
+    ];
+    let hosts = mx.to_remote_hosts("domain", 10).unwrap();
+    assert_eq!(hosts.len(), 1);
+    assert!(matches!(hosts[0], NextHop::MX("mx1")));
+}
+
+#[test]
+fn validate_mx() {
+    for (host, expected) in [
+        ("mx.foobar.org", true),
+        ("mx.foobar.org.", true),
+        ("localhost.foobar.org", true),
+        ("127.0.0.1", false),
+        ("[192.168.1.1]", false),
+        ("::1", false),
+        ("[IPv6:::1]", false),
+        ("localhost", false),
+        ("LOCALHOST.", false),
+        ("mx.localhost", false),
+        ("", false),
+    ] {
+        assert_eq!(is_valid_mx(host), expected, "{host}");
+    }
 }
 
 #[test]
@@ -23,8 +23,10 @@
 
 use std::time::Duration;
 
+use http_body_util::BodyExt;
 use hyper::header::AUTHORIZATION;
 use serde::{de::DeserializeOwned, Deserialize};
+use smtp::core::SMTP;
 
 pub mod queue;
 pub mod report;
@@ -59,6 +61,32 @@ pub async fn send_manage_request_raw(query: &str) -> Result<String, String> {
         .map_err(|err| err.to_string())
 }
 
+pub async fn send_scoped_manage_request<T: DeserializeOwned>(
+    core: &SMTP,
+    query: &str,
+    scope: &[&str],
+) -> Response<T> {
+    let uri = query.parse::<hyper::Uri>().unwrap();
+    let mut path = uri.path().split('/').skip(2);
+    let scope = scope.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let body = core
+        .handle_manage_request(
+            &uri,
+            &hyper::Method::GET,
+            path.next().unwrap_or_default(),
+            path.next().unwrap_or_default(),
+            Some(scope.as_slice()),
+        )
+        .await
+        .into_body()
+        .collect()
+        .await
+        .unwrap()
+        .to_bytes();
+    let result = String::from_utf8(body.to_vec()).unwrap();
+    serde_json::from_str::<Response<T>>(&result).unwrap_or_else(|err| panic!("{err}: {result}"))
+}
+
 impl<T> Response<T> {
     pub fn unwrap_data(self) -> T {
         match self {
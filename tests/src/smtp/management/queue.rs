@@ -34,8 +34,11 @@ use mail_parser::DateTime;
 use utils::config::{Config, ServerProtocol};
 
 use crate::smtp::{
-    inbound::TestQueueEvent, management::send_manage_request, outbound::start_test_server,
-    session::TestSession, TestConfig, TestSMTP,
+    inbound::TestQueueEvent,
+    management::{send_manage_request, send_scoped_manage_request},
+    outbound::start_test_server,
+    session::TestSession,
+    TestConfig, TestSMTP,
 };
 use smtp::{
     config::IfBlock,
@@ -188,6 +191,16 @@ async fn manage_queue() {
         .unwrap()
         .unwrap_data();
     assert_eq!(ids.len(), 6);
+
+    // Queue metrics are exported
+    let metrics = send_manage_request::<AHashMap<String, u64>>("/admin/metrics")
+        .await
+        .unwrap()
+        .unwrap_data();
+    assert!(
+        metrics.get("queue.messages").map_or(false, |v| *v > 0),
+        "{metrics:?}"
+    );
     let mut id_map = AHashMap::new();
     let mut id_map_rev = AHashMap::new();
     let mut test_search = String::new();
@@ -296,6 +309,66 @@ async fn manage_queue() {
         assert_eq!(ids, expected_ids, "failed for {query}");
     }
 
+    // Test domain scoped requests
+    for (scope, expected_ids) in [
+        (vec!["example1.org"], vec![]),
+        (vec!["example2.com", "example1.net"], vec![]),
+        (vec!["foobar.net"], vec!["a", "b", "c", "d", "e"]),
+        (
+            vec!["FOOBAR.net", "example1.org"],
+            vec!["a", "b", "c", "d", "e"],
+        ),
+        (vec!["unknown.org"], vec![]),
+    ] {
+        let expected_ids = HashSet::from_iter(expected_ids.into_iter().map(|s| s.to_string()));
+        let ids = send_scoped_manage_request::<Vec<QueueId>>(&core, "/admin/queue/list", &scope)
+            .await
+            .unwrap_data()
+            .into_iter()
+            .map(|id| id_map_rev.get(&id).unwrap().clone())
+            .collect::<HashSet<_>>();
+        assert_eq!(ids, expected_ids, "failed for {scope:?}");
+    }
+    let scoped_ids = format!(
+        "ids={},{}",
+        id_map.get("a").unwrap(),
+        id_map.get("b").unwrap()
+    );
+    let messages = send_scoped_manage_request::<Vec<Option<Message>>>(
+        &core,
+        &format!("/admin/queue/status?{scoped_ids}"),
+        &["foobar.net"],
+    )
+    .await
+    .unwrap_data();
+    assert_eq!(messages[0].as_ref().unwrap().env_id.as_deref(), Some("a"));
+    assert_eq!(messages[1].as_ref().unwrap().env_id.as_deref(), Some("b"));
+    let messages = send_scoped_manage_request::<Vec<Option<Message>>>(
+        &core,
+        &format!("/admin/queue/status?{scoped_ids}"),
+        &["example1.net"],
+    )
+    .await
+    .unwrap_data();
+    assert!(messages.iter().all(|message| message.is_none()));
+    assert_eq!(
+        send_scoped_manage_request::<Vec<bool>>(
+            &core,
+            &format!("/admin/queue/cancel?{scoped_ids}"),
+            &["unknown.org"],
+        )
+        .await
+        .unwrap_data(),
+        vec![false, false]
+    );
+    assert_eq!(
+        send_scoped_manage_request::<()>(&core, "/admin/report/list", &["example1.org"])
+            .await
+            .unwrap_error()
+            .0,
+        "forbidden"
+    );
+
     // Retry delivery
     assert_eq!(
         send_manage_request::<Vec<bool>>(&format!(
@@ -446,6 +519,59 @@ async fn manage_queue() {
         }
     }
 
+    // Modify envelope recipients and headers
+    let id = *id_map.get("c").unwrap();
+    assert_eq!(
+        send_manage_request::<bool>(&format!(
+            concat!(
+                "/admin/queue/modify?id={}&add-rcpt=rcpt10@example4.com",
+                "&remove-rcpt=rcpt5@example1.com&replace-header=Subject:%20Rerouted",
+                "&add-header=X-Routed-By:%20admin"
+            ),
+            id
+        ))
+        .await
+        .unwrap()
+        .unwrap_data(),
+        true
+    );
+    let message = get_messages(&[id]).await.pop().unwrap().unwrap();
+    assert!(message.size > 0);
+    let domains = message
+        .domains
+        .iter()
+        .map(|domain| domain.name.as_str())
+        .collect::<Vec<_>>();
+    assert!(!domains.contains(&"example1.com"), "{domains:?}");
+    assert!(domains.contains(&"example4.com"), "{domains:?}");
+    assert_eq!(
+        message
+            .domains
+            .iter()
+            .find(|domain| domain.name == "example4.com")
+            .unwrap()
+            .recipients
+            .iter()
+            .map(|rcpt| rcpt.address.as_str())
+            .collect::<Vec<_>>(),
+        vec!["rcpt9@example4.com", "rcpt10@example4.com"]
+    );
+
+    // Only pending recipients can be removed
+    assert_eq!(
+        send_manage_request::<bool>(&format!(
+            "/admin/queue/modify?id={id}&remove-rcpt=rcpt5@example1.com"
+        ))
+        .await
+        .unwrap()
+        .unwrap_data(),
+        false
+    );
+    send_manage_request::<bool>(&format!("/admin/queue/modify?id={id}"))
+        .await
+        .unwrap()
+        .unwrap_error();
+
     // Test authentication error
     assert_eq!(
         reqwest::Client::builder()
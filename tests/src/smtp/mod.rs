@@ -39,20 +39,25 @@ use tokio::sync::mpsc;
 use smtp::{
     config::{
         if_block::ConfigIf, queue::ConfigQueue, scripts::SieveContext, session::ConfigSession,
-        throttle::ConfigThrottle, AggregateReport, ArcAuthConfig, Auth, ConfigContext, Connect,
-        Data, DkimAuthConfig, DmarcAuthConfig, Dsn, Ehlo, EnvelopeKey, Extensions, IfBlock,
-        IpRevAuthConfig, Mail, MailAuthConfig, Milter, QueueConfig, QueueOutboundSourceIp,
+        throttle::ConfigThrottle, AggregateReport, AnomalyAction, AnomalyConfig, ArcAuthConfig,
+        Auth, BimiAuthConfig, ConfigContext, Connect, ConnectionsConfig, Data, DkimAuthConfig,
+        DkimReplayConfig, DmarcAuthConfig, Dsn, Ehlo, EnvelopeKey, Extensions, GeoIpConfig,
+        IfBlock, IpRevAuthConfig, Mail, MailAuthConfig, Milter, QueueBackpressure, QueueConfig,
+        QueueOutboundHappyEyeballs, QueueOutboundReuse, QueueOutboundSourceIp,
         QueueOutboundTimeout, QueueOutboundTls, QueueQuotas, QueueThrottle, Rcpt, Report,
-        ReportAnalysis, ReportConfig, SessionConfig, SessionThrottle, SpfAuthConfig, Throttle,
-        VerifyStrategy,
+        ReportAnalysis, ReportConfig, ReputationConfig, SessionConfig, SessionThrottle,
+        SpfAuthConfig, Srs, SuppressionConfig, Throttle, TrackingConfig, UnsubscribeConfig,
+        UsageConfig, VerifyStrategy, WebhookConfig,
     },
     core::{
-        throttle::ThrottleKeyHasherBuilder, QueueCore, ReportCore, Resolvers, SessionCore,
-        SieveConfig, SieveCore, TlsConnectors, SMTP,
+        throttle::ThrottleKeyHasherBuilder, AnomalyCore, DkimReplayCore, GeoIpCore, QueueCore,
+        ReportCore, ReputationCore, Resolvers, SessionCore, SieveConfig, SieveCore,
+        SuppressionCore, TlsConnectors, TrackingCore, UsageCore, WebhookCore, SMTP,
     },
-    outbound::dane::DnssecResolver,
+    outbound::{dane::DnssecResolver, pool::ConnectionPool},
+    queue::QueueLoad,
 };
-use utils::config::{utils::ParseValues, Config};
+use utils::config::{certificate::ClientCertificateMap, utils::ParseValues, Config};
 
 pub mod config;
 pub mod inbound;
@@ -90,6 +95,9 @@ impl ParseTestConfig for &str {
                     EnvelopeKey::RemoteIp,
                     EnvelopeKey::LocalIp,
                     EnvelopeKey::Priority,
+                    EnvelopeKey::Reputation,
+                    EnvelopeKey::Country,
+                    EnvelopeKey::Asn,
                 ],
             )
             .unwrap()
@@ -161,13 +169,15 @@ pub trait TestConfig {
 impl TestConfig for SMTP {
     fn test() -> Self {
         SMTP {
-            worker_pool: rayon::ThreadPoolBuilder::new()
-                .num_threads(num_cpus::get())
-                .build()
-                .unwrap(),
+            worker_pool: Arc::new(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(num_cpus::get())
+                    .build()
+                    .unwrap(),
+            ),
             session: SessionCore::test(),
             queue: QueueCore::test(),
-            resolvers: Resolvers {
+            resolvers: Arc::new(Resolvers {
                 dns: Resolver::new_system_conf().unwrap(),
                 dnssec: DnssecResolver::with_capacity(
                     ResolverConfig::cloudflare(),
@@ -177,25 +187,47 @@ impl TestConfig for SMTP {
                 cache: smtp::core::DnsCache {
                     tlsa: LruCache::with_capacity(100),
                     mta_sts: LruCache::with_capacity(100),
+                    bimi: LruCache::with_capacity(100),
                 },
-            },
+                overrides: vec![],
+            }),
             mail_auth: MailAuthConfig::test(),
             report: ReportCore::test(),
             sieve: SieveCore::test(),
+            webhook: Arc::new(WebhookCore::test()),
+            tracking: Arc::new(TrackingCore::test()),
+            usage: UsageCore::test(),
+            reputation: ReputationCore::test(),
+            suppression: SuppressionCore::test(),
+            anomaly: AnomalyCore::test(),
+            dkim_replay: DkimReplayCore::test(),
+            unsubscribe: UnsubscribeConfig::test(),
+            geoip: Arc::new(GeoIpCore::test()),
             delivery_tx: mpsc::channel(1).0,
         }
     }
 }
 
+impl TestConfig for UnsubscribeConfig {
+    fn test() -> Self {
+        UnsubscribeConfig {
+            url: String::new(),
+            keys: vec![],
+            expiry: Duration::from_secs(86400),
+        }
+    }
+}
+
 impl TestConfig for SessionCore {
     fn test() -> Self {
         SessionCore {
             config: SessionConfig::test(),
-            throttle: DashMap::with_capacity_and_hasher_and_shard_amount(
+            throttle: Arc::new(DashMap::with_capacity_and_hasher_and_shard_amount(
                 10,
                 ThrottleKeyHasherBuilder::default(),
                 16,
-            ),
+            )),
+            connections: Default::default(),
         }
     }
 }
@@ -211,8 +243,16 @@ impl TestConfig for SessionConfig {
                 mail_from: vec![],
                 rcpt_to: vec![],
             },
+            connections: ConnectionsConfig {
+                max_concurrent: 0,
+                max_concurrent_per_ip: 0,
+                exempt: vec![],
+            },
             connect: Connect {
                 script: IfBlock::new(None),
+                policy: IfBlock::default(),
+                greeting_delay: IfBlock::new(None),
+                tarpit: IfBlock::new(None),
             },
             ehlo: Ehlo {
                 script: IfBlock::new(None),
@@ -230,6 +270,9 @@ impl TestConfig for SessionConfig {
                 dsn: IfBlock::new(true),
                 expn: IfBlock::new(true),
                 vrfy: IfBlock::new(true),
+                xclient: IfBlock::new(false),
+                xforward: IfBlock::new(false),
+                limits: IfBlock::new(false),
             },
             auth: Auth {
                 directory: IfBlock::new(None),
@@ -238,33 +281,55 @@ impl TestConfig for SessionConfig {
                 errors_max: IfBlock::new(10),
                 errors_wait: IfBlock::new(Duration::from_secs(1)),
                 allow_plain_text: IfBlock::new(false),
+                client_cert: IfBlock::new(false),
+                client_cert_require: IfBlock::new(false),
+                client_cert_map: ClientCertificateMap::default(),
             },
             mail: Mail {
                 script: IfBlock::new(None),
+                policy: IfBlock::default(),
                 rewrite: IfBlock::new(None),
             },
             rcpt: Rcpt {
                 script: IfBlock::new(None),
+                policy: IfBlock::default(),
                 relay: IfBlock::new(false),
                 directory: IfBlock::new(None),
                 errors_max: IfBlock::new(3),
                 errors_wait: IfBlock::new(Duration::from_secs(1)),
+                errors_tarpit: IfBlock::new(None),
                 max_recipients: IfBlock::new(3),
+                max_message_size: IfBlock::new(None),
+                retry_hint: IfBlock::new(None),
                 rewrite: IfBlock::new(None),
             },
             data: Data {
                 script: IfBlock::new(None),
+                shadow_script: IfBlock::new(None),
                 max_messages: IfBlock::new(10),
                 max_message_size: IfBlock::new(1024 * 1024),
                 max_received_headers: IfBlock::new(10),
+                max_trace_headers: IfBlock::new(0),
                 add_received: IfBlock::new(true),
+                received_template: IfBlock::new(
+                    smtp::config::session::DEFAULT_RECEIVED_TEMPLATE.to_string(),
+                ),
                 add_received_spf: IfBlock::new(true),
                 add_return_path: IfBlock::new(true),
                 add_auth_results: IfBlock::new(true),
                 add_message_id: IfBlock::new(true),
                 add_date: IfBlock::new(true),
+                add_list_unsubscribe: IfBlock::new(false),
                 pipe_commands: vec![],
+                filters: vec![],
+                footer: IfBlock::new(None),
                 milters: vec![],
+                scrub_headers: IfBlock::new(vec![]),
+                srs: Srs {
+                    enable: IfBlock::new(false),
+                    domain: String::new(),
+                    keys: vec![],
+                },
             },
         }
     }
@@ -274,22 +339,25 @@ impl TestConfig for QueueCore {
     fn test() -> Self {
         Self {
             config: QueueConfig::test(),
-            throttle: DashMap::with_capacity_and_hasher_and_shard_amount(
+            throttle: Arc::new(DashMap::with_capacity_and_hasher_and_shard_amount(
                 10,
                 ThrottleKeyHasherBuilder::default(),
                 16,
-            ),
-            quota: DashMap::with_capacity_and_hasher_and_shard_amount(
+            )),
+            quota: Arc::new(DashMap::with_capacity_and_hasher_and_shard_amount(
                 10,
                 ThrottleKeyHasherBuilder::default(),
                 16,
-            ),
+            )),
             tx: mpsc::channel(1024).0,
-            id_seq: 0.into(),
+            id_seq: Arc::new(0.into()),
             connectors: TlsConnectors {
                 pki_verify: build_tls_connector(false),
                 dummy_verify: build_tls_connector(true),
             },
+            ipv6_fallback: Arc::new(DashMap::default()),
+            connections: Arc::new(ConnectionPool::default()),
+            load: Arc::new(QueueLoad::default()),
         }
     }
 }
@@ -300,27 +368,50 @@ impl TestConfig for QueueConfig {
             path: Default::default(),
             hash: IfBlock::new(10),
             retry: IfBlock::new(vec![Duration::from_secs(10)]),
+            retry_class: vec![],
+            retry_jitter: IfBlock::new(0.0),
+            retry_max: IfBlock::new(None),
             notify: IfBlock::new(vec![Duration::from_secs(20)]),
             expire: IfBlock::new(Duration::from_secs(10)),
+            dead_letter: None,
             hostname: IfBlock::new("mx.example.org".to_string()),
             next_hop: Default::default(),
+            transport: Default::default(),
             max_mx: IfBlock::new(5),
+            max_rcpt: IfBlock::new(0),
+            batch_mx: IfBlock::new(false),
+            reject_invalid_mx: IfBlock::new(true),
             max_multihomed: IfBlock::new(5),
             source_ip: QueueOutboundSourceIp {
                 ipv4: IfBlock::new(vec![]),
                 ipv6: IfBlock::new(vec![]),
             },
             ip_strategy: IfBlock::new(IpLookupStrategy::Ipv4thenIpv6),
+            happy_eyeballs: QueueOutboundHappyEyeballs {
+                delay: IfBlock::new(None),
+                fallback_ttl: Duration::from_secs(30 * 60),
+            },
+            connection_reuse: QueueOutboundReuse {
+                max_messages: IfBlock::new(1),
+                max_age: IfBlock::new(Duration::from_secs(5 * 60)),
+                idle_timeout: Duration::from_secs(30),
+            },
             tls: QueueOutboundTls {
                 dane: IfBlock::new(smtp::config::RequireOptional::Optional),
+                dane_failure: IfBlock::new(smtp::config::DaneFailurePolicy::Bounce),
                 mta_sts: IfBlock::new(smtp::config::RequireOptional::Optional),
                 start: IfBlock::new(smtp::config::RequireOptional::Optional),
                 invalid_certs: IfBlock::new(false),
             },
+            arc_seal: IfBlock::default(),
             dsn: Dsn {
                 name: IfBlock::new("Mail Delivery Subsystem".to_string()),
                 address: IfBlock::new("MAILER-DAEMON@example.org".to_string()),
                 sign: IfBlock::default(),
+                verbose: IfBlock::new(false),
+                rate: IfBlock::default(),
+                total_rate: None,
+                suppress_auth_failed: IfBlock::new(false),
             },
             timeout: QueueOutboundTimeout {
                 connect: IfBlock::new(Duration::from_secs(1)),
@@ -342,6 +433,7 @@ impl TestConfig for QueueConfig {
                 rcpt: vec![],
                 rcpt_domain: vec![],
             },
+            backpressure: QueueBackpressure::default(),
             management_lookup: Arc::new(MemoryDirectory::default()),
         }
     }
@@ -368,6 +460,12 @@ impl TestConfig for MailAuthConfig {
             iprev: IpRevAuthConfig {
                 verify: IfBlock::new(VerifyStrategy::Relaxed),
             },
+            bimi: BimiAuthConfig {
+                verify: IfBlock::new(VerifyStrategy::Disable),
+                trust_anchors: vec![],
+                timeout: Duration::from_secs(10),
+                max_size: 1024 * 1024,
+            },
         }
     }
 }
@@ -381,6 +479,136 @@ impl TestConfig for ReportCore {
     }
 }
 
+impl TestConfig for WebhookCore {
+    fn test() -> Self {
+        Self {
+            config: WebhookConfig {
+                path: Default::default(),
+                retention: Duration::from_secs(86400),
+                endpoints: vec![],
+            },
+            id_seq: 0.into(),
+            tx: AHashMap::new(),
+        }
+    }
+}
+
+impl TestConfig for TrackingCore {
+    fn test() -> Self {
+        Self {
+            config: TrackingConfig {
+                path: None,
+                retention: Duration::from_secs(30 * 86400),
+            },
+            tx: mpsc::channel(1024).0,
+            index: Default::default(),
+        }
+    }
+}
+
+impl TestConfig for UsageCore {
+    fn test() -> Self {
+        Self {
+            config: UsageConfig {
+                path: None,
+                flush_frequency: Duration::from_secs(60),
+                thresholds: vec![],
+                limits: vec![],
+            },
+            counters: Arc::new(DashMap::default()),
+        }
+    }
+}
+
+impl TestConfig for ReputationCore {
+    fn test() -> Self {
+        Self {
+            config: ReputationConfig {
+                enable: false,
+                path: None,
+                flush_frequency: Duration::from_secs(60),
+                expiry: Duration::from_secs(30 * 86400),
+                min_events: 5,
+                threshold_good: 10,
+                threshold_poor: 50,
+                max_entries: 100_000,
+                lookup: None,
+            },
+            entries: Arc::new(DashMap::default()),
+        }
+    }
+}
+
+impl TestConfig for SuppressionCore {
+    fn test() -> Self {
+        Self {
+            config: SuppressionConfig {
+                enable: false,
+                path: None,
+                flush_frequency: Duration::from_secs(60),
+                threshold: 3,
+                expiry: Duration::from_secs(30 * 86400),
+                lookup: None,
+            },
+            entries: Arc::new(DashMap::default()),
+        }
+    }
+}
+
+impl TestConfig for AnomalyCore {
+    fn test() -> Self {
+        Self {
+            config: AnomalyConfig {
+                enable: false,
+                path: None,
+                flush_frequency: Duration::from_secs(60),
+                expiry: Duration::from_secs(90 * 86400),
+                window: Duration::from_secs(86400),
+                min_signals: 2,
+                action: AnomalyAction::Throttle,
+                throttle_messages: 10,
+                max_locations: 20,
+                spike_factor: 10,
+                spike_min_messages: 50,
+                bounce_rate: 30,
+                bounce_min_messages: 20,
+            },
+            accounts: Arc::new(DashMap::default()),
+            senders: Arc::new(DashMap::default()),
+            webhook: Arc::new(WebhookCore::test()),
+            geoip: Arc::new(GeoIpCore::test()),
+            delivery_tx: mpsc::channel(1).0,
+        }
+    }
+}
+
+impl TestConfig for DkimReplayCore {
+    fn test() -> Self {
+        Self {
+            config: DkimReplayConfig {
+                enable: false,
+                window: Duration::from_secs(3600),
+                max_networks: 3,
+            },
+            entries: Arc::new(DashMap::default()),
+            geoip: Arc::new(GeoIpCore::test()),
+        }
+    }
+}
+
+impl TestConfig for GeoIpCore {
+    fn test() -> Self {
+        Self {
+            config: GeoIpConfig {
+                country: None,
+                asn: None,
+                reload_frequency: Duration::from_secs(3600),
+            },
+            databases: Default::default(),
+        }
+    }
+}
+
 impl TestConfig for ReportConfig {
     fn test() -> Self {
         Self {
@@ -434,6 +662,7 @@ impl TestConfig for SieveCore {
             runtime: Runtime::new_with_context(SieveContext::default()),
             scripts: AHashMap::new(),
             lookup: AHashMap::new(),
+            shadow: Default::default(),
             config: SieveConfig {
                 from_addr: "MAILER-DAEMON@example.org".to_string(),
                 from_name: "Mailer Daemon".to_string(),
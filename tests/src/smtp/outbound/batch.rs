@@ -0,0 +1,149 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use mail_auth::MX;
+use utils::config::ServerProtocol;
+
+use crate::smtp::{
+    inbound::{TestMessage, TestQueueEvent},
+    outbound::start_test_server,
+    session::{TestSession, VerifyResponse},
+    TestConfig, TestSMTP,
+};
+use smtp::{
+    config::IfBlock,
+    core::{Session, SMTP},
+    queue::{manager::Queue, DeliveryAttempt},
+};
+
+#[tokio::test]
+#[serial_test::serial]
+async fn batch_mx() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::TRACE)
+            .finish(),
+    )
+    .unwrap();*/
+
+    // Start test server
+    let mut core = SMTP::test();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    let mut remote_qr = core.init_test_queue("smtp_batch_remote");
+    let _rx = start_test_server(core.into(), &[ServerProtocol::Smtp]);
+
+    for (batch_mx, max_rcpt, expected) in [
+        (
+            false,
+            0,
+            vec![
+                vec!["a@foobar.org"],
+                vec!["b@foobar.net"],
+                vec!["c@other.org"],
+            ],
+        ),
+        (
+            true,
+            0,
+            vec![vec!["a@foobar.org", "b@foobar.net"], vec!["c@other.org"]],
+        ),
+        (
+            true,
+            1,
+            vec![
+                vec!["a@foobar.org"],
+                vec!["b@foobar.net"],
+                vec!["c@other.org"],
+            ],
+        ),
+    ] {
+        // Add mock DNS entries, foobar.org and foobar.net share the same MX hosts
+        let mut core = SMTP::test();
+        for (domain, exchanges) in [
+            ("foobar.org", vec!["mx1.foobar.org", "mx2.foobar.org"]),
+            ("foobar.net", vec!["MX2.foobar.org.", "mx1.foobar.org"]),
+            ("other.org", vec!["mx1.other.org", "mx2.foobar.org"]),
+        ] {
+            core.resolvers.dns.mx_add(
+                domain,
+                vec![MX {
+                    exchanges: exchanges.into_iter().map(|mx| mx.to_string()).collect(),
+                    preference: 10,
+                }],
+                Instant::now() + Duration::from_secs(10),
+            );
+        }
+        for mx in ["mx1.foobar.org", "mx2.foobar.org", "mx1.other.org"] {
+            core.resolvers.dns.ipv4_add(
+                mx,
+                vec!["127.0.0.1".parse().unwrap()],
+                Instant::now() + Duration::from_secs(10),
+            );
+        }
+
+        let mut local_qr = core.init_test_queue("smtp_batch_local");
+        core.session.config.rcpt.relay = IfBlock::new(true);
+        core.queue.config.batch_mx = IfBlock::new(batch_mx);
+        core.queue.config.max_rcpt = IfBlock::new(max_rcpt);
+        let core = Arc::new(core);
+        let mut queue = Queue::default();
+        let mut session = Session::test(core.clone());
+        session.data.remote_ip = "10.0.0.1".parse().unwrap();
+        session.eval_session_params().await;
+        session.ehlo("mx.test.org").await;
+        session
+            .send_message(
+                "john@test.org",
+                &["a@foobar.org", "b@foobar.net", "c@other.org"],
+                "test:no_dkim",
+                "250",
+            )
+            .await;
+        DeliveryAttempt::from(local_qr.read_event().await.unwrap_message())
+            .try_deliver(core.clone(), &mut queue)
+            .await;
+        local_qr.read_event().await.unwrap_done();
+
+        // Recipients of domains sharing MX hosts are delivered in a single
+        // transaction, unless the per-destination recipient cap is exceeded
+        for expected_rcpts in expected {
+            let message = remote_qr.read_event().await.unwrap_message();
+            assert_eq!(
+                message
+                    .recipients
+                    .iter()
+                    .map(|rcpt| rcpt.address.as_str())
+                    .collect::<Vec<_>>(),
+                expected_rcpts,
+                "batch_mx: {batch_mx}, max_rcpt: {max_rcpt}"
+            );
+            message.read_lines().assert_contains("We lost the game.");
+        }
+        remote_qr.assert_empty_queue();
+    }
+}
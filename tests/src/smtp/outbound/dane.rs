@@ -54,7 +54,7 @@ use crate::smtp::{
     TestConfig, TestSMTP,
 };
 use smtp::{
-    config::{AggregateFrequency, IfBlock, RequireOptional},
+    config::{AggregateFrequency, DaneFailurePolicy, IfBlock, RequireOptional},
     core::{Resolvers, Session, SMTP},
     outbound::dane::{DnssecResolver, Tlsa, TlsaEntry},
     queue::{manager::Queue, DeliveryAttempt, Error, ErrorDetails, Status},
@@ -222,6 +222,107 @@ async fn dane_verify() {
     assert!(report.failure.is_none());
 }
 
+#[tokio::test]
+#[serial_test::serial]
+async fn dane_failure_policy() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::TRACE)
+            .finish(),
+    )
+    .unwrap();*/
+
+    // Start test server
+    let mut core = SMTP::test();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    let mut remote_qr = core.init_test_queue("smtp_dane_policy_remote");
+    let _rx = start_test_server(core.into(), &[ServerProtocol::Smtp]);
+
+    for policy in [DaneFailurePolicy::Defer, DaneFailurePolicy::Deliver] {
+        // Add mock DNS entries and a TLSA record that does not match
+        let mut core = SMTP::test();
+        core.resolvers.dns.mx_add(
+            "foobar.org",
+            vec![MX {
+                exchanges: vec!["mx.foobar.org".to_string()],
+                preference: 10,
+            }],
+            Instant::now() + Duration::from_secs(10),
+        );
+        core.resolvers.dns.ipv4_add(
+            "mx.foobar.org",
+            vec!["127.0.0.1".parse().unwrap()],
+            Instant::now() + Duration::from_secs(10),
+        );
+        core.resolvers.dns.txt_add(
+            "_smtp._tls.foobar.org",
+            TlsRpt::parse(b"v=TLSRPTv1; rua=mailto:reports@foobar.org").unwrap(),
+            Instant::now() + Duration::from_secs(10),
+        );
+        core.resolvers.tlsa_add(
+            "_25._tcp.mx.foobar.org",
+            Arc::new(Tlsa {
+                entries: vec![TlsaEntry {
+                    is_end_entity: true,
+                    is_sha256: true,
+                    is_spki: true,
+                    data: vec![1, 2, 3],
+                }],
+                has_end_entities: true,
+                has_intermediates: false,
+            }),
+            Instant::now() + Duration::from_secs(10),
+        );
+
+        let mut local_qr = core.init_test_queue("smtp_dane_policy_local");
+        let mut rr = core.init_test_report();
+        core.session.config.rcpt.relay = IfBlock::new(true);
+        core.queue.config.tls.dane = IfBlock::new(RequireOptional::Require);
+        core.queue.config.tls.dane_failure = IfBlock::new(policy);
+        core.report.config.tls.send = IfBlock::new(AggregateFrequency::Weekly);
+
+        let core = Arc::new(core);
+        let mut queue = Queue::default();
+        let mut session = Session::test(core.clone());
+        session.data.remote_ip = "10.0.0.1".parse().unwrap();
+        session.eval_session_params().await;
+        session.ehlo("mx.test.org").await;
+        session
+            .send_message("john@test.org", &["bill@foobar.org"], "test:no_dkim", "250")
+            .await;
+        DeliveryAttempt::from(local_qr.read_event().await.unwrap_message())
+            .try_deliver(core.clone(), &mut queue)
+            .await;
+
+        match policy {
+            DaneFailurePolicy::Defer => {
+                let retry = local_qr.read_event().await.unwrap_retry();
+                assert!(
+                    matches!(
+                        &retry.inner.domains[0].status,
+                        Status::TemporaryFailure(Error::DaneError(_))
+                    ),
+                    "{:?}",
+                    retry.inner.domains[0].status
+                );
+                remote_qr.assert_empty_queue();
+            }
+            _ => {
+                local_qr.read_event().await.unwrap_done();
+                remote_qr.read_event().await.unwrap_message();
+            }
+        }
+
+        // The validation failure is reported in both cases
+        let report = rr.read_report().await.unwrap_tls();
+        assert_eq!(
+            report.failure.as_ref().unwrap().result_type,
+            ResultType::ValidationFailure
+        );
+        assert!(rr.report_rx.try_recv().is_err());
+    }
+}
+
 #[tokio::test]
 async fn dane_test() {
     let conf = ResolverConfig::cloudflare_tls();
@@ -233,11 +334,14 @@ async fn dane_test() {
         dns: Resolver::new_cloudflare().unwrap(),
         dnssec: DnssecResolver {
             resolver: AsyncResolver::tokio(conf, opts),
+            enabled: true,
         },
         cache: smtp::core::DnsCache {
             tlsa: LruCache::with_capacity(10),
             mta_sts: LruCache::with_capacity(10),
+            bimi: LruCache::with_capacity(10),
         },
+        overrides: vec![],
     };
 
     // Add dns entries
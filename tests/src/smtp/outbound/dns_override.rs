@@ -0,0 +1,148 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{net::IpAddr, sync::Arc};
+
+use mail_auth::{hickory_resolver::config::ResolverOpts, IpLookupStrategy};
+use utils::config::{Config, ServerProtocol};
+
+use crate::smtp::{
+    inbound::TestQueueEvent, outbound::start_test_server, session::TestSession, TestConfig,
+    TestSMTP,
+};
+use smtp::{
+    config::{resolver::ConfigResolver, IfBlock},
+    core::{Session, SMTP},
+    queue::{manager::Queue, DeliveryAttempt},
+};
+
+const CONFIG: &str = r#"
+[resolver.override."lab"]
+domains = ["lab.internal", "*.corp.internal"]
+mx = ["mx.lab.internal", "mx2.lab.internal."]
+hosts = ["127.0.0.1 mx.lab.internal", "::1 MX.lab.internal", "127.0.0.2 mx2.lab.internal # backup"]
+"#;
+
+#[tokio::test]
+#[serial_test::serial]
+async fn dns_override() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::TRACE)
+            .finish(),
+    )
+    .unwrap();*/
+
+    // Parse overrides
+    let overrides = Config::new(CONFIG)
+        .unwrap()
+        .parse_dns_overrides(&ResolverOpts::default())
+        .unwrap();
+    assert_eq!(overrides.len(), 1);
+    assert!(
+        Config::new("[resolver.override.\"bad\"]\nmx = [\"mx.example.org\"]\n")
+            .unwrap()
+            .parse_dns_overrides(&ResolverOpts::default())
+            .is_err()
+    );
+    assert!(
+        Config::new("[resolver.override.\"bad\"]\nhosts = [\"mx.example.org\"]\n")
+            .unwrap()
+            .parse_dns_overrides(&ResolverOpts::default())
+            .is_err()
+    );
+
+    let mut core = SMTP::test();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.queue.config.ip_strategy = IfBlock::new(IpLookupStrategy::Ipv4thenIpv6);
+    Arc::get_mut(&mut core.resolvers).unwrap().overrides = overrides;
+
+    // Domain matching
+    assert!(core.resolvers.dns_override("lab.internal").is_some());
+    assert!(core.resolvers.dns_override("LAB.internal.").is_some());
+    assert!(core.resolvers.dns_override("sub.lab.internal").is_none());
+    assert!(core.resolvers.dns_override("host.corp.internal").is_some());
+    assert!(core.resolvers.dns_override("corp.internal").is_none());
+
+    // Static MX and host entries
+    let mx = core.mx_lookup("lab.internal").await.unwrap();
+    assert_eq!(
+        mx.iter()
+            .map(|mx| mx.exchanges.first().unwrap().as_str())
+            .collect::<Vec<_>>(),
+        vec!["mx.lab.internal", "mx2.lab.internal"]
+    );
+    assert!(mx[0].preference < mx[1].preference);
+    let localhost_v4: IpAddr = "127.0.0.1".parse().unwrap();
+    let localhost_v6: IpAddr = "::1".parse().unwrap();
+    assert_eq!(
+        core.ip_lookup("mx.lab.internal.", IpLookupStrategy::Ipv4Only, 2)
+            .await
+            .unwrap(),
+        vec![localhost_v4]
+    );
+    assert_eq!(
+        core.ip_lookup("mx.lab.internal", IpLookupStrategy::Ipv6thenIpv4, 2)
+            .await
+            .unwrap(),
+        vec![localhost_v6, localhost_v4]
+    );
+    assert_eq!(
+        core.ip_lookup("mx.lab.internal", IpLookupStrategy::Ipv4thenIpv6, 1)
+            .await
+            .unwrap(),
+        vec![localhost_v4]
+    );
+    assert!(core
+        .ip_lookup("mx2.lab.internal", IpLookupStrategy::Ipv6Only, 2)
+        .await
+        .unwrap()
+        .is_empty());
+
+    // Deliver to a domain that is not in public DNS
+    let mut remote = SMTP::test();
+    remote.session.config.rcpt.relay = IfBlock::new(true);
+    let mut remote_qr = remote.init_test_queue("smtp_dns_override_remote");
+    let _rx = start_test_server(remote.into(), &[ServerProtocol::Smtp]);
+
+    let mut local_qr = core.init_test_queue("smtp_dns_override_local");
+    let core = Arc::new(core);
+    let mut queue = Queue::default();
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    session
+        .send_message(
+            "john@test.org",
+            &["bill@lab.internal"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    DeliveryAttempt::from(local_qr.read_event().await.unwrap_message())
+        .try_deliver(core.clone(), &mut queue)
+        .await;
+    local_qr.read_event().await.unwrap_done();
+    remote_qr.read_event().await.unwrap_message();
+}
@@ -108,3 +108,64 @@ async fn ip_lookup_strategy() {
         }
     }
 }
+
+#[tokio::test]
+#[serial_test::serial]
+async fn ip_lookup_happy_eyeballs() {
+    // Start test server
+    let mut core = SMTP::test();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    let mut remote_qr = core.init_test_queue("smtp_iplookup_remote");
+    let _rx = start_test_server(core.into(), &[ServerProtocol::Smtp]);
+
+    // Add mock DNS entries, the IPv6 address is unreachable
+    let mut core = SMTP::test();
+    core.queue.config.ip_strategy = IfBlock::new(IpLookupStrategy::Ipv6thenIpv4);
+    core.queue.config.happy_eyeballs.delay = IfBlock::new(Some(Duration::from_millis(250)));
+    core.resolvers.dns.mx_add(
+        "foobar.org",
+        vec![MX {
+            exchanges: vec!["mx.foobar.org".to_string()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.resolvers.dns.ipv4_add(
+        "mx.foobar.org",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.resolvers.dns.ipv6_add(
+        "mx.foobar.org",
+        vec!["::1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+    let mut local_qr = core.init_test_queue("smtp_iplookup_local");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+
+    let core = Arc::new(core);
+    let mut queue = Queue::default();
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    assert!(!core.queue.prefer_ipv4("mx.foobar.org"));
+
+    // The IPv4 fallback should be used and remembered for the host
+    for _ in 0..2 {
+        session
+            .send_message("john@test.org", &["bill@foobar.org"], "test:no_dkim", "250")
+            .await;
+        DeliveryAttempt::from(local_qr.read_event().await.unwrap_message())
+            .try_deliver(core.clone(), &mut queue)
+            .await;
+        local_qr.read_event().await.unwrap_done();
+        remote_qr.read_event().await.unwrap_message();
+        assert!(core.queue.prefer_ipv4("mx.foobar.org"));
+    }
+
+    // A successful IPv6 connection clears the fallback
+    core.queue
+        .record_connection("mx.foobar.org", "::1".parse().unwrap(), false);
+    assert!(!core.queue.prefer_ipv4("mx.foobar.org"));
+}
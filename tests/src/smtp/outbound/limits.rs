@@ -0,0 +1,168 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use mail_auth::MX;
+use utils::config::ServerProtocol;
+
+use crate::smtp::{
+    inbound::{TestMessage, TestQueueEvent},
+    outbound::start_test_server,
+    session::{TestSession, VerifyResponse},
+    TestConfig, TestSMTP,
+};
+use smtp::{
+    config::IfBlock,
+    core::{Session, SMTP},
+    outbound::session::ServerLimits,
+    queue::{manager::Queue, DeliveryAttempt, Status, Timestamp},
+};
+
+#[tokio::test]
+#[serial_test::serial]
+async fn limits() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::TRACE)
+            .finish(),
+    )
+    .unwrap();*/
+
+    // Parse advertised limits
+    assert_eq!(
+        ServerLimits::parse(
+            concat!(
+                "250-mx.foobar.org\r\n",
+                "250-PIPELINING\r\n",
+                "250-limits RCPTMAX=20 MAILMAX=5 RCPTDOMAINMAX=3\r\n",
+                "250 SMTPUTF8\r\n"
+            )
+            .as_bytes()
+        ),
+        ServerLimits {
+            rcpt_max: 20,
+            mail_max: 5
+        }
+    );
+    assert_eq!(
+        ServerLimits::parse(b"250-mx.foobar.org\r\n250 PIPELINING\r\n"),
+        ServerLimits::default()
+    );
+
+    // Start test server
+    let mut core = SMTP::test();
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.rcpt.max_recipients = IfBlock::new(2);
+    core.session.config.data.max_messages = IfBlock::new(2);
+    core.session.config.extensions.limits = IfBlock::new(true);
+    let mut remote_qr = core.init_test_queue("smtp_limits_remote");
+    let _rx = start_test_server(core.into(), &[ServerProtocol::Smtp]);
+
+    // Add mock DNS entries
+    let mut core = SMTP::test();
+    core.resolvers.dns.mx_add(
+        "foobar.org",
+        vec![MX {
+            exchanges: vec!["mx.foobar.org".to_string()],
+            preference: 10,
+        }],
+        Instant::now() + Duration::from_secs(10),
+    );
+    core.resolvers.dns.ipv4_add(
+        "mx.foobar.org",
+        vec!["127.0.0.1".parse().unwrap()],
+        Instant::now() + Duration::from_secs(10),
+    );
+
+    // The limits are advertised in the EHLO response
+    let mut local_qr = core.init_test_queue("smtp_limits_local");
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.rcpt.max_recipients = IfBlock::new(10);
+    core.session.config.extensions.limits = IfBlock::new(true);
+    let core = Arc::new(core);
+    let mut queue = Queue::default();
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session
+        .ehlo("mx.test.org")
+        .await
+        .assert_contains("250-LIMITS RCPTMAX=10 MAILMAX=10");
+
+    // Recipients are split into transactions of RCPTMAX recipients,
+    // up to MAILMAX transactions per connection
+    session
+        .send_message(
+            "john@test.org",
+            &[
+                "a@foobar.org",
+                "b@foobar.org",
+                "c@foobar.org",
+                "d@foobar.org",
+                "e@foobar.org",
+            ],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    DeliveryAttempt::from(local_qr.read_event().await.unwrap_message())
+        .try_deliver(core.clone(), &mut queue)
+        .await;
+    let mut retry = local_qr.read_event().await.unwrap_retry();
+    assert_eq!(
+        retry
+            .inner
+            .recipients
+            .iter()
+            .filter(|rcpt| matches!(rcpt.status, Status::Completed(_)))
+            .count(),
+        4
+    );
+    for _ in 0..2 {
+        assert_eq!(
+            remote_qr
+                .read_event()
+                .await
+                .unwrap_message()
+                .recipients
+                .len(),
+            2
+        );
+    }
+    remote_qr.assert_empty_queue();
+
+    // The remaining recipient is delivered on the next attempt
+    retry.inner.domains[0].retry.due = Timestamp::now();
+    DeliveryAttempt::from(retry.inner)
+        .try_deliver(core.clone(), &mut queue)
+        .await;
+    local_qr.read_event().await.unwrap_done();
+    let message = remote_qr.read_event().await.unwrap_message();
+    assert_eq!(message.recipients.len(), 1);
+    assert_eq!(message.recipients[0].address, "e@foobar.org");
+    message.read_lines().assert_contains("We lost the game.");
+}
@@ -30,14 +30,19 @@ use utils::config::{Config, ServerProtocol};
 
 use super::add_test_certs;
 
+pub mod batch;
 pub mod dane;
+pub mod dns_override;
 pub mod extensions;
 pub mod ip_lookup;
+pub mod limits;
 pub mod lmtp;
 pub mod mta_sts;
+pub mod reuse;
 pub mod smtp;
 pub mod throttle;
 pub mod tls;
+pub mod transport;
 
 const SERVER: &str = "
 [server]
@@ -36,7 +36,7 @@ use crate::smtp::{
 use smtp::{
     config::{ConfigContext, IfBlock},
     core::{Session, SMTP},
-    queue::{manager::Queue, DeliveryAttempt, Message, QueueEnvelope},
+    queue::{manager::Queue, DeliveryAttempt, Message, QueueEnvelope, Timestamp},
 };
 
 const THROTTLE: &str = "
@@ -154,7 +154,7 @@ async fn throttle_outbound() {
             .pop()
             .unwrap()
             .due
-            .duration_since(Instant::now())
+            .duration_since(Timestamp::now())
             .as_secs()
     ));
 
@@ -216,7 +216,7 @@ async fn throttle_outbound() {
             .await
             .unwrap_retry()
             .due
-            .duration_since(Instant::now())
+            .duration_since(Timestamp::now())
             .as_secs()
     ));
 
@@ -293,7 +293,7 @@ async fn throttle_outbound() {
             .await
             .unwrap_retry()
             .due
-            .duration_since(Instant::now())
+            .duration_since(Timestamp::now())
             .as_secs()
     ));
 }
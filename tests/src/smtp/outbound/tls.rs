@@ -38,7 +38,7 @@ use crate::smtp::{
 use smtp::{
     config::{IfBlock, RequireOptional},
     core::{Session, SMTP},
-    queue::{manager::Queue, DeliveryAttempt},
+    queue::{manager::Queue, DeliveryAttempt, Timestamp},
 };
 
 #[tokio::test]
@@ -93,7 +93,7 @@ async fn starttls_optional() {
         .await;
     let mut retry = local_qr.read_event().await.unwrap_retry();
     assert!(retry.inner.domains[0].disable_tls);
-    retry.inner.domains[0].retry.due = Instant::now();
+    retry.inner.domains[0].retry.due = Timestamp::now();
     DeliveryAttempt::from(retry.inner)
         .try_deliver(core.clone(), &mut queue)
         .await;
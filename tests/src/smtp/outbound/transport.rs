@@ -0,0 +1,218 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use crate::smtp::{
+    inbound::{TestMessage, TestQueueEvent},
+    session::{TestSession, VerifyResponse},
+    ParseTestConfig, TestConfig, TestSMTP,
+};
+use smtp::{
+    config::{transport::ConfigTransport, ConfigContext, IfBlock},
+    core::{Session, SMTP},
+    outbound::transport::expand_pipe_arguments,
+    queue::{manager::Queue, DeliveryAttempt, Event, WorkerResult},
+};
+use utils::config::Config;
+
+const TRANSPORT: &str = r#"
+[transport."pipe"]
+type = "pipe"
+command = "/bin/sh"
+arguments = ["-c", "case \"$1\" in fail@*) echo 'No such user' >&2; exit 67;; delay@*) exit 75;; esac; cat > /dev/null", "sh", "${recipient}"]
+per-recipient = true
+timeout = "5s"
+"#;
+
+#[tokio::test]
+#[serial_test::serial]
+async fn pipe_delivery() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::TRACE)
+            .finish(),
+    )
+    .unwrap();*/
+
+    let mut core = SMTP::test();
+    let mut local_qr = core.init_test_queue("pipe_delivery");
+
+    // Parse transport
+    let ctx = ConfigContext::new(&[]);
+    let transport = Config::new(TRANSPORT)
+        .unwrap()
+        .parse_transport("pipe")
+        .unwrap();
+    assert_eq!(transport.id, "pipe");
+
+    core.session.config.rcpt.relay = IfBlock::new(true);
+    core.session.config.rcpt.max_recipients = IfBlock::new(100);
+    core.session.config.extensions.dsn = IfBlock::new(true);
+    let config = &mut core.queue.config;
+    config.transport = IfBlock::new(Some(Arc::new(transport)));
+    config.retry = IfBlock::new(vec![Duration::from_millis(100)]);
+    config.notify = "[{if = 'rcpt-domain', eq = 'foobar.org', then = ['100ms', '200ms']},
+    {else = ['100ms']}]"
+        .parse_if(&ctx);
+    config.expire = "[{if = 'rcpt-domain', eq = 'foobar.org', then = '400ms'},
+    {else = '500ms'}]"
+        .parse_if(&ctx);
+
+    let core = Arc::new(core);
+    let mut queue = Queue::default();
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    session
+        .send_message(
+            "john@test.org",
+            &[
+                "<bill@foobar.org> NOTIFY=SUCCESS,DELAY,FAILURE",
+                "<jane@foobar.org> NOTIFY=SUCCESS,DELAY,FAILURE",
+                "<delay@foobar.org> NOTIFY=SUCCESS,DELAY,FAILURE",
+                "<fail@foobar.org> NOTIFY=SUCCESS,DELAY,FAILURE",
+            ],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    DeliveryAttempt::from(local_qr.read_event().await.unwrap_message())
+        .try_deliver(core.clone(), &mut queue)
+        .await;
+    let mut dsn = Vec::new();
+    loop {
+        match local_qr.try_read_event().await {
+            Some(Event::Queue(message)) => {
+                dsn.push(message.inner);
+            }
+            Some(Event::Done(wr)) => match wr {
+                WorkerResult::Done => {
+                    break;
+                }
+                WorkerResult::Retry(retry) => {
+                    queue.schedule(retry);
+                }
+                WorkerResult::OnHold(_) => unreachable!(),
+            },
+            None | Some(Event::Stop) => break,
+            Some(Event::Manage(_)) => unreachable!(),
+        }
+
+        if !queue.scheduled.is_empty() {
+            tokio::time::sleep(queue.wake_up_time()).await;
+            DeliveryAttempt::from(queue.next_due().unwrap())
+                .try_deliver(core.clone(), &mut queue)
+                .await;
+        }
+    }
+    assert!(queue.scheduled.is_empty());
+    assert_eq!(dsn.len(), 4);
+
+    let mut dsn = dsn.into_iter();
+
+    dsn.next()
+        .unwrap()
+        .read_lines()
+        .assert_contains("<bill@foobar.org> (delivered to 'pipe' with code 250 (2.0.0)")
+        .assert_contains("<jane@foobar.org> (delivered to 'pipe' with code 250 (2.0.0)")
+        .assert_contains("<fail@foobar.org> (host 'pipe' rejected command '/bin/sh <fail@foobar.org>' with code 550 (5.1.1) 'No such user')");
+
+    dsn.next()
+        .unwrap()
+        .read_lines()
+        .assert_contains("<delay@foobar.org> (host 'pipe' rejected command '/bin/sh <delay@foobar.org>' with code 451 (4.3.0)")
+        .assert_contains("Action: delayed");
+
+    dsn.next()
+        .unwrap()
+        .read_lines()
+        .assert_contains("<delay@foobar.org> (host 'pipe' rejected")
+        .assert_contains("Action: delayed");
+
+    dsn.next()
+        .unwrap()
+        .read_lines()
+        .assert_contains("<delay@foobar.org> (host 'pipe' rejected")
+        .assert_contains("Action: failed");
+}
+
+#[test]
+fn pipe_arguments() {
+    let arguments = ["-f", "${sender}", "--", "${recipient}"]
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        expand_pipe_arguments(
+            &arguments,
+            "john@test.org",
+            &["bill@foobar.org", "jane@foobar.org"]
+        )
+        .unwrap(),
+        vec![
+            "-f",
+            "john@test.org",
+            "--",
+            "bill@foobar.org",
+            "jane@foobar.org"
+        ]
+    );
+    let arguments = ["post".to_string(), "${user}".to_string()];
+    assert_eq!(
+        expand_pipe_arguments(&arguments, "", &["list@foobar.org"]).unwrap(),
+        vec!["post", "list"]
+    );
+
+    // Addresses must not be able to inject command line options
+    assert_eq!(
+        expand_pipe_arguments(&arguments, "", &["-oQ/tmp@foobar.org"]).unwrap_err(),
+        "-oQ/tmp"
+    );
+    assert_eq!(
+        expand_pipe_arguments(
+            &["${recipient}".to_string()],
+            "",
+            &["bill@foobar.org", "-X/tmp/log@foobar.org"]
+        )
+        .unwrap_err(),
+        "-X/tmp/log@foobar.org"
+    );
+    assert!(expand_pipe_arguments(&["${sender}".to_string()], "-bd@test.org", &[]).is_err());
+
+    // Placeholders in substituted values are not expanded
+    let arguments = ["--from=${sender}", "${user}", "${domain}"]
+        .into_iter()
+        .map(String::from)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        expand_pipe_arguments(
+            &arguments,
+            "${user}${recipient}@${domain}",
+            &["list@foobar.org"]
+        )
+        .unwrap(),
+        vec!["--from=${user}${recipient}@${domain}", "list", "foobar.org"]
+    );
+}
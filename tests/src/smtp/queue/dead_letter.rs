@@ -0,0 +1,138 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use crate::smtp::{
+    inbound::{TestMessage, TestQueueEvent},
+    session::{TestSession, VerifyResponse},
+    TestConfig, TestSMTP,
+};
+use smtp::{
+    config::IfBlock,
+    core::{Session, SMTP},
+    queue::{manager::Queue, DeliveryAttempt, Event, Status, WorkerResult},
+};
+
+#[tokio::test]
+async fn dead_letter() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::DEBUG)
+            .finish(),
+    )
+    .unwrap();*/
+
+    let mut core = SMTP::test();
+
+    // Create temp dir for queue
+    let mut qr = core.init_test_queue("smtp_dead_letter_test");
+    let dead_letter = core.queue.config.path.default.join("dead-letter");
+
+    let config = &mut core.session.config.rcpt;
+    config.relay = IfBlock::new(true);
+    let config = &mut core.queue.config;
+    config.retry = IfBlock::new(vec![Duration::from_millis(100)]);
+    config.expire = IfBlock::new(Duration::from_millis(300));
+    config.dead_letter = dead_letter.clone().into();
+
+    // Send a message that can't be delivered
+    let core = Arc::new(core);
+    let mut queue = Queue::default();
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    session
+        .send_message(
+            "john@test.org",
+            &["jane@_dns_error.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    let attempt = DeliveryAttempt::from(qr.read_event().await.unwrap_message());
+    let queue_id = attempt.message.id;
+    let path = attempt.message.path.clone();
+    let mut dsn = Vec::new();
+    attempt.try_deliver(core.clone(), &mut queue).await;
+    loop {
+        match qr.try_read_event().await {
+            Some(Event::Queue(message)) => {
+                dsn.push(message.inner);
+            }
+            Some(Event::Done(wr)) => match wr {
+                WorkerResult::Done => break,
+                WorkerResult::Retry(retry) => {
+                    queue.schedule(retry);
+                }
+                WorkerResult::OnHold(_) => unreachable!(),
+            },
+            None | Some(Event::Stop) => break,
+            Some(Event::Manage(_)) => unreachable!(),
+        }
+
+        if !queue.scheduled.is_empty() {
+            tokio::time::sleep(queue.wake_up_time()).await;
+            DeliveryAttempt::from(queue.next_due().unwrap())
+                .try_deliver(core.clone(), &mut queue)
+                .await;
+        }
+    }
+
+    // The expired message should have been moved to the dead-letter archive
+    dsn.pop()
+        .unwrap()
+        .read_lines()
+        .assert_contains("Final-Recipient: rfc822;jane@_dns_error.org")
+        .assert_contains("Action: failed");
+    assert!(!path.exists());
+    assert_eq!(core.queue.dead_letter_list().await, vec![queue_id]);
+    let message = core.queue.dead_letter_get(queue_id).await.unwrap();
+    assert!(message.path.starts_with(&dead_letter));
+    assert!(matches!(
+        message.domains.first().unwrap().status,
+        Status::PermanentFailure(_)
+    ));
+    assert!(matches!(
+        message.recipients.first().unwrap().status,
+        Status::PermanentFailure(_)
+    ));
+    let raw_message = core.queue.dead_letter_export(queue_id).await.unwrap();
+    assert_eq!(raw_message.len(), message.size);
+    assert!(core.queue.dead_letter_get(queue_id + 1).await.is_none());
+
+    // Re-inject the message
+    assert!(core.queue.dead_letter_reinject(queue_id).await);
+    let message = qr.read_event().await.unwrap_message();
+    assert_eq!(message.id, queue_id);
+    assert!(message.path.exists());
+    assert_eq!(message.domains.first().unwrap().status, Status::Scheduled);
+    assert_eq!(
+        message.recipients.first().unwrap().status,
+        Status::Scheduled
+    );
+    assert!(core.queue.dead_letter_list().await.is_empty());
+    assert!(!core.queue.dead_letter_reinject(queue_id).await);
+    qr.assert_empty_queue();
+}
@@ -24,23 +24,28 @@
 use std::{
     fs,
     path::PathBuf,
-    time::{Duration, Instant, SystemTime},
+    time::{Duration, SystemTime},
 };
 
 use smtp_proto::{Response, RCPT_NOTIFY_DELAY, RCPT_NOTIFY_FAILURE, RCPT_NOTIFY_SUCCESS};
 use tokio::{fs::File, io::AsyncReadExt};
-use utils::config::DynValue;
+use utils::{
+    config::{DynValue, Rate},
+    metrics,
+};
 
 use crate::smtp::{
-    inbound::{sign::TextConfigContext, TestQueueEvent},
+    inbound::{sign::TextConfigContext, TestMessage, TestQueueEvent},
+    session::VerifyResponse,
     ParseTestConfig, TestConfig, TestSMTP,
 };
 use smtp::{
-    config::{ConfigContext, EnvelopeKey},
+    config::{ConfigContext, EnvelopeKey, IfBlock},
     core::SMTP,
     queue::{
-        DeliveryAttempt, Domain, Error, ErrorDetails, HostResponse, Message, Recipient, Schedule,
-        Status,
+        dsn::{METRIC_DSN_SUPPRESSED_AUTH_FAILED, METRIC_DSN_SUPPRESSED_RATE_LIMITED},
+        DeliveryAttempt, Domain, Error, ErrorDetails, HistoryEntry, HostResponse, Message,
+        Recipient, Schedule, Status, Timestamp, MAIL_AUTH_FAILED,
     },
 };
 
@@ -86,19 +91,22 @@ async fn generate_dsn() {
             domain: "example.org".to_string(),
             retry: Schedule::now(),
             notify: Schedule::now(),
-            expires: Instant::now() + Duration::from_secs(10),
+            expires: Timestamp::now() + Duration::from_secs(10),
             status: Status::TemporaryFailure(Error::ConnectionError(ErrorDetails {
                 entity: "mx.domain.org".to_string(),
                 details: "Connection timeout".to_string(),
             })),
+            class_attempt: 0,
             disable_tls: false,
             changed: false,
         }],
         flags: 0,
         env_id: None,
         priority: 0,
+        history: vec![],
 
         queue_refs: vec![],
+        queue_load: None,
     });
     let mut attempt = DeliveryAttempt {
         span: tracing::span!(tracing::Level::INFO, "hi"),
@@ -162,13 +170,67 @@ async fn generate_dsn() {
     for rcpt in &mut attempt.message.recipients {
         rcpt.flags = flags;
     }
-    attempt.message.domains[0].notify.due = Instant::now();
+    attempt.message.domains[0].notify.due = Timestamp::now();
     core.queue.send_dsn(&mut attempt).await;
     compare_dsn(qr.read_event().await.unwrap_message(), "mixed.eml").await;
 
     // Load queue
     let queue = core.queue.read_queue().await;
     assert_eq!(queue.scheduled.len(), 4);
+
+    // Verbose delay DSN including the delivery history
+    core.queue.config.dsn.verbose = IfBlock::new(true);
+    attempt.message.history.push(HistoryEntry {
+        domain_idx: 0,
+        time: 1700000000,
+        mx: "mx.domain.org".to_string(),
+        remote_ip: "10.0.0.1".to_string(),
+        tls: "TLSv1.3 TLS13_AES_256_GCM_SHA384".to_string(),
+        status: Status::TemporaryFailure(
+            "Connection to 'mx.domain.org' failed: Connection timeout".to_string(),
+        ),
+        changed: true,
+    });
+    attempt.message.domains[0].notify.due = Timestamp::now();
+    core.queue.send_dsn(&mut attempt).await;
+    qr.read_event()
+        .await
+        .unwrap_message()
+        .read_lines()
+        .assert_contains("----- Delivery attempts -----")
+        .assert_contains(concat!(
+            "'example.org' via 'mx.domain.org' [10.0.0.1] using TLSv1.3 ",
+            "TLS13_AES_256_GCM_SHA384: temporary failure (Connection to ",
+            "'mx.domain.org' failed: Connection timeout)"
+        ));
+
+    // DSNs to the same address are rate limited
+    core.queue.config.dsn.rate = IfBlock::new(Some(Rate {
+        requests: 1,
+        period: Duration::from_secs(3600),
+    }));
+    attempt.message.domains[0].notify.due = Timestamp::now();
+    assert!(core.queue.send_dsn(&mut attempt).await.is_some());
+    qr.read_event().await.unwrap_message();
+    attempt.message.domains[0].notify.due = Timestamp::now();
+    let rate_limited = metrics::get(METRIC_DSN_SUPPRESSED_RATE_LIMITED);
+    assert!(core.queue.send_dsn(&mut attempt).await.is_none());
+    qr.assert_empty_queue();
+    assert!(metrics::get(METRIC_DSN_SUPPRESSED_RATE_LIMITED) > rate_limited);
+    core.queue.config.dsn.rate = IfBlock::default();
+
+    // No backscatter for messages that failed authentication
+    attempt.message.flags |= MAIL_AUTH_FAILED;
+    core.queue.config.dsn.suppress_auth_failed = IfBlock::new(true);
+    attempt.message.domains[0].notify.due = Timestamp::now();
+    let auth_failed = metrics::get(METRIC_DSN_SUPPRESSED_AUTH_FAILED);
+    assert!(core.queue.send_dsn(&mut attempt).await.is_none());
+    qr.assert_empty_queue();
+    assert!(metrics::get(METRIC_DSN_SUPPRESSED_AUTH_FAILED) > auth_failed);
+    core.queue.config.dsn.suppress_auth_failed = IfBlock::new(false);
+    attempt.message.domains[0].notify.due = Timestamp::now();
+    assert!(core.queue.send_dsn(&mut attempt).await.is_some());
+    qr.read_event().await.unwrap_message();
 }
 
 async fn compare_dsn(message: Box<Message>, test: &str) {
@@ -21,11 +21,13 @@
  * for more details.
 */
 
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 use mail_auth::hickory_resolver::proto::op::ResponseCode;
 
-use smtp::queue::{manager::Queue, Domain, Message, Schedule, Status};
+use smtp::queue::{
+    manager::Queue, retry::RetryPolicy, Domain, Message, Schedule, Status, Timestamp,
+};
 
 #[test]
 fn queue_due() {
@@ -108,21 +110,21 @@ fn delivery_events() {
 
     message.domain_mut("a").set_status(
         mail_auth::Error::DnsRecordNotFound(ResponseCode::BADCOOKIE),
-        &[],
+        &RetryPolicy::default(),
     );
     assert_eq!(message.next_event().unwrap(), message.domain("b").retry.due);
     assert_eq!(message.next_delivery_event(), message.domain("b").retry.due);
 
     message.domain_mut("b").set_status(
         mail_auth::Error::DnsRecordNotFound(ResponseCode::BADCOOKIE),
-        &[],
+        &RetryPolicy::default(),
     );
     assert_eq!(message.next_event().unwrap(), message.domain("c").retry.due);
     assert_eq!(message.next_delivery_event(), message.domain("c").retry.due);
 
     message.domain_mut("c").set_status(
         mail_auth::Error::DnsRecordNotFound(ResponseCode::BADCOOKIE),
-        &[],
+        &RetryPolicy::default(),
     );
     assert!(message.next_event().is_none());
 }
@@ -141,7 +143,9 @@ pub fn new_message(id: u64) -> Box<Message> {
         flags: 0,
         env_id: None,
         priority: 0,
+        history: vec![],
         queue_refs: vec![],
+        queue_load: None,
     })
 }
 
@@ -150,8 +154,9 @@ fn domain(domain: &str, retry: u64, notify: u64, expires: u64) -> Domain {
         domain: domain.to_string(),
         retry: Schedule::later(Duration::from_secs(retry)),
         notify: Schedule::later(Duration::from_secs(notify)),
-        expires: Instant::now() + Duration::from_secs(expires),
+        expires: Timestamp::now() + Duration::from_secs(expires),
         status: Status::Scheduled,
+        class_attempt: 0,
         disable_tls: false,
         changed: false,
     }
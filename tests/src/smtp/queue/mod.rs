@@ -21,7 +21,10 @@
  * for more details.
 */
 
+pub mod dead_letter;
 pub mod dsn;
 pub mod manager;
 pub mod retry;
 pub mod serialize;
+pub mod tracking;
+pub mod webhook;
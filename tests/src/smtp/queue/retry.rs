@@ -21,10 +21,7 @@
  * for more details.
 */
 
-use std::{
-    sync::Arc,
-    time::{Duration, Instant},
-};
+use std::{sync::Arc, time::Duration};
 
 use crate::smtp::{
     inbound::{TestMessage, TestQueueEvent},
@@ -32,10 +29,14 @@ use crate::smtp::{
     ParseTestConfig, TestConfig, TestSMTP,
 };
 use smtp::{
-    config::{ConfigContext, IfBlock},
+    config::{ConfigContext, IfBlock, RetryClass},
     core::{Session, SMTP},
-    queue::{manager::Queue, DeliveryAttempt, Event, WorkerResult},
+    queue::{
+        manager::Queue, retry::RetryPolicy, DeliveryAttempt, Domain, Error, ErrorDetails, Event,
+        HostResponse, Schedule, Status, Timestamp, WorkerResult,
+    },
 };
+use smtp_proto::Response;
 
 #[tokio::test]
 async fn queue_retry() {
@@ -180,7 +181,7 @@ async fn queue_retry() {
             "250",
         )
         .await;
-    let now = Instant::now();
+    let now = Timestamp::now();
     let schedule = qr.read_event().await.unwrap_schedule();
     assert!([59, 60].contains(&schedule.due.duration_since(now).as_secs()));
     assert!([59, 60].contains(
@@ -221,7 +222,7 @@ async fn queue_retry() {
             "250",
         )
         .await;
-    let now = Instant::now();
+    let now = Timestamp::now();
     let schedule = qr.read_event().await.unwrap_schedule();
     assert!([3599, 3600].contains(
         &schedule
@@ -235,3 +236,140 @@ async fn queue_retry() {
             .as_secs()
     ));
 }
+
+#[test]
+fn retry_policy() {
+    let schedule = [Duration::from_secs(60), Duration::from_secs(120)];
+    let connection = [Duration::from_secs(300)];
+    let greeting = [Duration::from_secs(600), Duration::from_secs(1200)];
+    let mut policy = RetryPolicy {
+        schedule: &schedule,
+        overrides: vec![
+            (RetryClass::Connection, &connection[..]),
+            (RetryClass::Greeting, &greeting[..]),
+        ],
+        jitter: 0.0,
+        max: None,
+    };
+
+    // Classify errors
+    let connection_error = Status::TemporaryFailure(Error::ConnectionError(ErrorDetails {
+        entity: "mx.foobar.org".to_string(),
+        details: "Connection refused".to_string(),
+    }));
+    let response_error = |command: &str, esc: [u8; 3]| {
+        Status::TemporaryFailure(Error::UnexpectedResponse(HostResponse {
+            hostname: ErrorDetails {
+                entity: "mx.foobar.org".to_string(),
+                details: command.to_string(),
+            },
+            response: Response {
+                code: 421,
+                esc,
+                message: "Try again later".to_string(),
+            },
+        }))
+    };
+    let greeting = |status: Status<(), Error>| match status {
+        Status::TemporaryFailure(err) => Status::TemporaryFailure(Error::Greeting(err.into())),
+        status => status,
+    };
+    let greeting_error = greeting(response_error("", [4, 3, 2]));
+    let greeting_timeout = greeting(connection_error.clone());
+    let rcpt_error = response_error("RCPT TO:<john@foobar.org>", [4, 2, 1]);
+    let pipelined_error = response_error("", [4, 2, 1]);
+    let rate_error = response_error("MAIL FROM:<bill@foobar.org>", [4, 7, 28]);
+    for (status, class) in [
+        (&connection_error, RetryClass::Connection),
+        (&greeting_error, RetryClass::Greeting),
+        (&greeting_timeout, RetryClass::Greeting),
+        (&rcpt_error, RetryClass::Response),
+        (&pipelined_error, RetryClass::Response),
+        (&rate_error, RetryClass::RateLimit),
+    ] {
+        match status {
+            Status::TemporaryFailure(err) => assert_eq!(err.retry_class(), Some(class)),
+            _ => unreachable!(),
+        }
+    }
+    assert_eq!(
+        Error::RateLimited.retry_class(),
+        Some(RetryClass::RateLimit)
+    );
+    assert_eq!(Error::NullMx.retry_class(), Some(RetryClass::Dns));
+
+    // Errors with an overridden class use their own progression
+    for (status, attempt, expected) in [
+        (&connection_error, 0, 300),
+        (&connection_error, 5, 300),
+        (&greeting_error, 0, 600),
+        (&greeting_error, 1, 1200),
+        (&greeting_error, 9, 1200),
+        (&rcpt_error, 0, 60),
+        (&rcpt_error, 1, 120),
+        (&rate_error, 3, 120),
+        (&Status::Scheduled, 0, 60),
+    ] {
+        assert_eq!(
+            policy.interval(status, attempt),
+            Duration::from_secs(expected),
+            "{status:?} {attempt}"
+        );
+    }
+
+    // Intervals are capped
+    policy.max = Some(Duration::from_secs(900));
+    assert_eq!(
+        policy.interval(&greeting_error, 1),
+        Duration::from_secs(900)
+    );
+    assert_eq!(policy.interval(&rcpt_error, 1), Duration::from_secs(120));
+
+    // Jitter spreads retries around the scheduled interval
+    policy.max = None;
+    policy.jitter = 0.5;
+    let intervals = (0..100)
+        .map(|_| policy.interval(&greeting_error, 1))
+        .collect::<Vec<_>>();
+    assert!(intervals
+        .iter()
+        .all(|interval| (600..=1800).contains(&interval.as_secs())));
+    assert!(intervals.iter().any(|interval| interval.as_secs() != 1200));
+
+    // Jitter never exceeds the cap
+    policy.max = Some(Duration::from_secs(1000));
+    assert!((0..100)
+        .map(|_| policy.interval(&greeting_error, 1))
+        .all(|interval| interval <= Duration::from_secs(1000)));
+
+    // Changing the error class restarts the schedule of the new class
+    policy.max = None;
+    policy.jitter = 0.0;
+    let mut domain = Domain {
+        domain: "foobar.org".to_string(),
+        retry: Schedule::now(),
+        notify: Schedule::now(),
+        expires: Timestamp::now() + Duration::from_secs(86400),
+        status: Status::Scheduled,
+        class_attempt: 0,
+        disable_tls: false,
+        changed: false,
+    };
+    for (status, expected, attempt, class_attempt) in [
+        (&rcpt_error, 60, 1, 1),
+        (&rcpt_error, 120, 2, 2),
+        (&rcpt_error, 120, 3, 3),
+        (&greeting_error, 600, 4, 1),
+        (&greeting_timeout, 1200, 5, 2),
+        (&rcpt_error, 60, 6, 1),
+    ] {
+        domain.set_status(status.clone(), &policy);
+        assert!(
+            (expected - 1..=expected)
+                .contains(&domain.retry.due.duration_since(Timestamp::now()).as_secs()),
+            "{status:?}"
+        );
+        assert_eq!(domain.retry.inner, attempt);
+        assert_eq!(domain.class_attempt, class_attempt);
+    }
+}
@@ -21,18 +21,15 @@
  * for more details.
 */
 
-use std::{
-    path::PathBuf,
-    time::{Duration, Instant},
-};
+use std::{path::PathBuf, time::Duration};
 
 use smtp_proto::{Response, MAIL_REQUIRETLS, MAIL_SMTPUTF8, RCPT_CONNEG, RCPT_NOTIFY_FAILURE};
 
 use smtp::{
     core::SMTP,
     queue::{
-        Domain, Error, ErrorDetails, HostResponse, Message, Recipient, Schedule, Status,
-        RCPT_STATUS_CHANGED,
+        Domain, Error, ErrorDetails, HistoryEntry, HostResponse, Message, Recipient, Schedule,
+        Status, Timestamp, RCPT_STATUS_CHANGED,
     },
 };
 
@@ -77,8 +74,9 @@ async fn queue_serialize() {
                 domain: "example.org".to_string(),
                 retry: Schedule::now(),
                 notify: Schedule::now(),
-                expires: Instant::now() + Duration::from_secs(10),
+                expires: Timestamp::now() + Duration::from_secs(10),
                 status: Status::Scheduled,
+                class_attempt: 0,
                 disable_tls: false,
                 changed: false,
             },
@@ -86,8 +84,9 @@ async fn queue_serialize() {
                 domain: "example.com".to_string(),
                 retry: Schedule::now(),
                 notify: Schedule::now(),
-                expires: Instant::now() + Duration::from_secs(10),
+                expires: Timestamp::now() + Duration::from_secs(10),
                 status: Status::Scheduled,
+                class_attempt: 0,
                 disable_tls: false,
                 changed: false,
             },
@@ -95,8 +94,10 @@ async fn queue_serialize() {
         flags: MAIL_REQUIRETLS | MAIL_SMTPUTF8,
         env_id: "hello".to_string().into(),
         priority: -1,
+        history: vec![],
 
         queue_refs: vec![],
+        queue_load: None,
     };
 
     // Queue message
@@ -155,16 +156,32 @@ async fn queue_serialize() {
     }));
     message.domains[0].changed = true;
 
-    message.domains[1].status = Status::TemporaryFailure(Error::ConnectionError(ErrorDetails {
-        entity: "mx.domain.org".to_string(),
-        details: "Connection timeout".to_string(),
-    }));
+    message.domains[1].status = Status::TemporaryFailure(Error::Greeting(
+        Error::ConnectionError(ErrorDetails {
+            entity: "mx.domain.org".to_string(),
+            details: "Connection timeout".to_string(),
+        })
+        .into(),
+    ));
+    message.domains[1].class_attempt = 3;
     message.domains[1].changed = true;
     message.domains[1].notify = Schedule::later(Duration::from_secs(30));
     message.domains[1].notify.inner = 321;
     message.domains[1].retry = Schedule::later(Duration::from_secs(62));
     message.domains[1].retry.inner = 678;
 
+    message.history.push(HistoryEntry {
+        domain_idx: 1,
+        time: 1700000000,
+        mx: "mx.domain.org".to_string(),
+        remote_ip: "10.0.0.1".to_string(),
+        tls: "TLSv1.3 TLS13_AES_256_GCM_SHA384".to_string(),
+        status: Status::TemporaryFailure(
+            "Connection to 'mx.domain.org' failed: Connection timeout".to_string(),
+        ),
+        changed: true,
+    });
+
     // Save changes
     message.save_changes().await;
     assert!(message.serialize_changes().is_empty());
@@ -186,15 +203,17 @@ fn assert_msg_eq(msg: &Message, other: &Message) {
     assert_eq!(msg.return_path_lcase, other.return_path_lcase);
     assert_eq!(msg.return_path_domain, other.return_path_domain);
     assert_eq!(msg.recipients, other.recipients);
+    assert_eq!(msg.history, other.history);
     assert_eq!(msg.domains.len(), other.domains.len());
     for (domain, other) in msg.domains.iter().zip(other.domains.iter()) {
         assert_eq!(domain.domain, other.domain);
         assert_eq!(domain.retry.inner, other.retry.inner);
+        assert_eq!(domain.class_attempt, other.class_attempt);
         assert_eq!(domain.notify.inner, other.notify.inner);
         assert_eq!(domain.status, other.status);
-        assert_instant_eq(domain.expires, other.expires);
-        assert_instant_eq(domain.retry.due, other.retry.due);
-        assert_instant_eq(domain.notify.due, other.notify.due);
+        assert_timestamp_eq(domain.expires, other.expires);
+        assert_timestamp_eq(domain.retry.due, other.retry.due);
+        assert_timestamp_eq(domain.notify.due, other.notify.due);
     }
     assert_eq!(msg.flags, other.flags);
     assert_eq!(msg.env_id, other.env_id);
@@ -202,12 +221,11 @@ fn assert_msg_eq(msg: &Message, other: &Message) {
     assert_eq!(msg.size, other.size);
 }
 
-fn assert_instant_eq(instant: Instant, other: Instant) {
-    let dur = if instant > other {
-        instant - other
-    } else {
-        other - instant
-    }
+fn assert_timestamp_eq(timestamp: Timestamp, other: Timestamp) {
+    let dur = std::cmp::max(
+        timestamp.duration_since(other),
+        other.duration_since(timestamp),
+    )
     .as_secs();
     assert!(dur <= 1, "dur {dur}");
 }
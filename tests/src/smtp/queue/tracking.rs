@@ -0,0 +1,236 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::{sync::Arc, time::Duration};
+
+use crate::smtp::{inbound::TestQueueEvent, session::TestSession, TestConfig, TestSMTP};
+use smtp::{
+    config::{IfBlock, TrackingConfig},
+    core::{Session, TrackingCore, SMTP},
+    queue::{manager::Queue, DeliveryAttempt, Event, WorkerResult},
+    tracking::{
+        manager::{SpawnTracking, TrackingLog},
+        TrackingEventType, TrackingFilter,
+    },
+};
+use tokio::sync::mpsc;
+
+#[tokio::test]
+async fn message_tracking() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::DEBUG)
+            .finish(),
+    )
+    .unwrap();*/
+
+    let mut core = SMTP::test();
+
+    // Create temp dir for queue
+    let mut qr = core.init_test_queue("smtp_tracking_test");
+
+    let config = &mut core.session.config.rcpt;
+    config.relay = IfBlock::new(true);
+    let config = &mut core.queue.config;
+    config.retry = IfBlock::new(vec![Duration::from_millis(100)]);
+    config.expire = IfBlock::new(Duration::from_millis(300));
+
+    // Enable tracking
+    let (tx, rx) = mpsc::channel(1024);
+    core.tracking = Arc::new(TrackingCore {
+        config: TrackingConfig {
+            path: core.queue.config.path.default.join("tracking").into(),
+            retention: Duration::from_secs(86400),
+        },
+        tx,
+        index: Default::default(),
+    });
+    rx.spawn(TrackingLog::new(&core.tracking).unwrap());
+
+    // Send a message that can't be delivered
+    let core = Arc::new(core);
+    let mut queue = Queue::default();
+    let mut session = Session::test(core.clone());
+    session.data.remote_ip = "10.0.0.1".parse().unwrap();
+    session.eval_session_params().await;
+    session.ehlo("mx.test.org").await;
+    session
+        .send_message(
+            "john@test.org",
+            &["jane@_dns_error.org"],
+            "test:no_dkim",
+            "250",
+        )
+        .await;
+    let attempt = DeliveryAttempt::from(qr.read_event().await.unwrap_message());
+    let queue_id = attempt.message.id;
+    let mut dsn_id = 0;
+    attempt.try_deliver(core.clone(), &mut queue).await;
+    loop {
+        match qr.try_read_event().await {
+            Some(Event::Queue(message)) => {
+                dsn_id = message.inner.id;
+            }
+            Some(Event::Done(wr)) => match wr {
+                WorkerResult::Done => break,
+                WorkerResult::Retry(retry) => {
+                    queue.schedule(retry);
+                }
+                WorkerResult::OnHold(_) => unreachable!(),
+            },
+            None | Some(Event::Stop) => break,
+            Some(Event::Manage(_)) => unreachable!(),
+        }
+
+        if !queue.scheduled.is_empty() {
+            tokio::time::sleep(queue.wake_up_time()).await;
+            DeliveryAttempt::from(queue.next_due().unwrap())
+                .try_deliver(core.clone(), &mut queue)
+                .await;
+        }
+    }
+    assert_ne!(dsn_id, 0);
+    core.tracking.flush().await;
+
+    // Trace the message by its Message-ID
+    let events = core
+        .tracking
+        .search(&TrackingFilter {
+            message_id: "<20030712040037.46341.5F8J@football.example.com>"
+                .to_string()
+                .into(),
+            ..Default::default()
+        })
+        .await;
+    let types = events.iter().map(|event| event.typ).collect::<Vec<_>>();
+    assert_eq!(
+        &types[..2],
+        &[TrackingEventType::Received, TrackingEventType::Queued]
+    );
+    assert!(types.contains(&TrackingEventType::Deferred), "{types:?}");
+    assert_eq!(types.last(), Some(&TrackingEventType::Dsn));
+    for event in &events {
+        assert_eq!(event.queue_id, queue_id);
+        assert_eq!(event.from, "john@test.org");
+        if event.typ != TrackingEventType::Dsn {
+            assert_eq!(event.to, vec!["jane@_dns_error.org".to_string()]);
+        } else {
+            assert_eq!(event.to, vec!["john@test.org".to_string()]);
+        }
+    }
+    assert_eq!(events[0].remote_ip.as_deref(), Some("10.0.0.1"));
+    assert_eq!(
+        events[1].message_id.as_deref(),
+        Some("20030712040037.46341.5F8J@football.example.com")
+    );
+    assert!(events
+        .last()
+        .unwrap()
+        .details
+        .as_ref()
+        .unwrap()
+        .contains(&dsn_id.to_string()));
+
+    // Search by queue id and recipient
+    assert_eq!(
+        core.tracking
+            .search(&TrackingFilter {
+                queue_id: queue_id.into(),
+                to: "jane@_dns_error.org".to_string().into(),
+                ..Default::default()
+            })
+            .await,
+        &events[..events.len() - 1]
+    );
+    assert!(core
+        .tracking
+        .search(&TrackingFilter {
+            queue_id: queue_id.into(),
+            to: "bill@_dns_error.org".to_string().into(),
+            ..Default::default()
+        })
+        .await
+        .is_empty());
+    assert!(core
+        .tracking
+        .search(&TrackingFilter {
+            message_id: "unknown@example.org".to_string().into(),
+            ..Default::default()
+        })
+        .await
+        .is_empty());
+
+    // Limit the number of results
+    let last = core
+        .tracking
+        .search(&TrackingFilter {
+            queue_id: queue_id.into(),
+            limit: 1,
+            ..Default::default()
+        })
+        .await;
+    assert_eq!(last.len(), 1);
+    assert_eq!(last[0].typ, TrackingEventType::Dsn);
+
+    // Search by sender and recipient address, the DSN was sent to the sender
+    assert_eq!(
+        core.tracking
+            .search(&TrackingFilter {
+                from: "john@test.org".to_string().into(),
+                ..Default::default()
+            })
+            .await,
+        events
+    );
+    assert_eq!(
+        core.tracking
+            .search(&TrackingFilter {
+                to: "john@test.org".to_string().into(),
+                ..Default::default()
+            })
+            .await,
+        &events[events.len() - 1..]
+    );
+
+    // Logs written before a restart are indexed when the tracking manager starts
+    let (tx, rx) = mpsc::channel(1024);
+    let tracking = TrackingCore {
+        config: TrackingConfig {
+            path: core.tracking.config.path.clone(),
+            retention: Duration::from_secs(86400),
+        },
+        tx,
+        index: Default::default(),
+    };
+    rx.spawn(TrackingLog::new(&tracking).unwrap());
+    tracking.flush().await;
+    assert_eq!(
+        tracking
+            .search(&TrackingFilter {
+                queue_id: queue_id.into(),
+                ..Default::default()
+            })
+            .await,
+        events
+    );
+}
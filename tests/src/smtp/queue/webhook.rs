@@ -0,0 +1,256 @@
+/*
+ * Copyright (c) 2023 Stalwart Labs Ltd.
+ *
+ * This file is part of Stalwart Mail Server.
+ *
+ * This program is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Affero General Public License as
+ * published by the Free Software Foundation, either version 3 of
+ * the License, or (at your option) any later version.
+ *
+ * This program is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ * GNU Affero General Public License for more details.
+ * in the LICENSE file at the top-level directory of this distribution.
+ * You should have received a copy of the GNU Affero General Public License
+ * along with this program.  If not, see <http://www.gnu.org/licenses/>.
+ *
+ * You can be released from the requirements of the AGPLv3 license by
+ * purchasing a commercial license. Please contact licensing@stalw.art
+ * for more details.
+*/
+
+use std::time::Duration;
+
+use ahash::AHashMap;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    sync::{mpsc, oneshot},
+};
+use utils::config::Config;
+
+use crate::smtp::make_temp_dir;
+use smtp::{
+    config::webhook::ConfigWebhook,
+    core::WebhookCore,
+    webhook::{
+        manager::{EndpointQueue, SpawnWebhook},
+        signature::sign_payload,
+        Event, WebhookEventType, DELIVERED_DIR,
+    },
+};
+
+const WEBHOOK: &str = r#"
+[webhook]
+path = "{PATH}"
+
+[webhook.endpoint."mock"]
+url = "http://127.0.0.1:9335/events"
+events = ["message.queued", "delivery.completed"]
+headers = ["X-Api-Key: abc"]
+signature.keys = ["old-secret", "new-secret"]
+retry = ["1s"]
+"#;
+
+struct MockRequest {
+    headers: String,
+    body: Vec<u8>,
+}
+
+#[tokio::test]
+async fn queue_webhooks() {
+    /*tracing::subscriber::set_global_default(
+        tracing_subscriber::FmtSubscriber::builder()
+            .with_max_level(tracing::Level::DEBUG)
+            .finish(),
+    )
+    .unwrap();*/
+
+    // Start mock webhook receiver, the first request fails
+    let mut requests = spawn_mock_webhook_receiver().await;
+
+    let temp_dir = make_temp_dir("smtp_webhook_test", true);
+    let config = Config::new(&WEBHOOK.replace("{PATH}", temp_dir.temp_dir.to_str().unwrap()))
+        .unwrap()
+        .parse_webhooks()
+        .unwrap();
+    let endpoint = config.endpoints[0].clone();
+    let (tx, rx) = mpsc::channel(1024);
+    let mut webhook_tx = AHashMap::new();
+    webhook_tx.insert("mock".to_string(), tx.clone());
+    let core = WebhookCore {
+        config,
+        id_seq: 0.into(),
+        tx: webhook_tx,
+    };
+    rx.spawn(EndpointQueue::new(endpoint, &core.config).await);
+
+    // Publish events, filtered events must be discarded
+    core.publish(
+        WebhookEventType::MessageQueued,
+        Some(1),
+        serde_json::json!({"to": ["john@example.org"]}),
+    );
+    core.publish(
+        WebhookEventType::DeliveryDeferred,
+        Some(1),
+        serde_json::json!({"to": "john@example.org"}),
+    );
+    core.publish(
+        WebhookEventType::DeliveryCompleted,
+        Some(1),
+        serde_json::json!({"to": "john@example.org"}),
+    );
+
+    // Events for the same message are delivered in order, retrying failures
+    let mut types = Vec::new();
+    for _ in 0..3 {
+        let request = tokio::time::timeout(Duration::from_secs(5), requests.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(
+            request.headers.contains("x-api-key: abc"),
+            "{}",
+            request.headers
+        );
+        request.verify_signature();
+        let event: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(event["messageId"], 1);
+        types.push(event["type"].as_str().unwrap().to_string());
+    }
+    assert_eq!(
+        types,
+        ["message.queued", "message.queued", "delivery.completed"]
+    );
+
+    // Delivered events are archived, events are processed in order so once
+    // an empty replay returns the last delivery has been archived
+    assert_eq!(replay(&tx, u64::MAX, u64::MAX).await, 0);
+    assert_eq!(
+        std::fs::read_dir(temp_dir.temp_dir.join("mock").join(DELIVERED_DIR))
+            .unwrap()
+            .count(),
+        2
+    );
+
+    // Replay archived events
+    assert_eq!(replay(&tx, 0, u64::MAX).await, 2);
+    for _ in 0..2 {
+        tokio::time::timeout(Duration::from_secs(5), requests.recv())
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    tx.send(Event::Stop).await.unwrap();
+}
+
+async fn replay(tx: &mpsc::Sender<Event>, from: u64, to: u64) -> usize {
+    let (result_tx, result_rx) = oneshot::channel();
+    tx.send(Event::Replay {
+        from,
+        to,
+        result_tx,
+    })
+    .await
+    .unwrap();
+    result_rx.await.unwrap()
+}
+
+impl MockRequest {
+    fn verify_signature(&self) {
+        let signature = self
+            .headers
+            .lines()
+            .find_map(|line| line.strip_prefix("x-webhook-signature: "))
+            .expect("Missing signature header");
+        let timestamp = signature
+            .split(',')
+            .next()
+            .and_then(|ts| ts.strip_prefix("t="))
+            .and_then(|ts| ts.parse::<u64>().ok())
+            .unwrap();
+        assert_eq!(signature.matches("v1=").count(), 2);
+        assert_eq!(
+            sign_payload(
+                &[b"old-secret".to_vec(), b"new-secret".to_vec()],
+                timestamp,
+                &self.body
+            )
+            .unwrap(),
+            signature
+        );
+    }
+}
+
+async fn spawn_mock_webhook_receiver() -> mpsc::Receiver<MockRequest> {
+    let (tx, rx) = mpsc::channel(10);
+    let listener = TcpListener::bind("127.0.0.1:9335")
+        .await
+        .unwrap_or_else(|e| panic!("Failed to bind mock webhook receiver: {e}"));
+
+    tokio::spawn(async move {
+        let mut is_first = true;
+
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let mut buf = Vec::new();
+            let mut chunk = [0u8; 1024];
+            let header_end = loop {
+                let n = stream.read(&mut chunk).await.unwrap();
+                if n == 0 {
+                    break None;
+                }
+                buf.extend_from_slice(&chunk[..n]);
+                if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                    break Some(pos + 4);
+                }
+            };
+            let header_end = if let Some(header_end) = header_end {
+                header_end
+            } else {
+                continue;
+            };
+
+            let headers = String::from_utf8_lossy(&buf[..header_end]).to_lowercase();
+            let content_length = headers
+                .lines()
+                .find_map(|line| line.strip_prefix("content-length: "))
+                .and_then(|len| len.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            while buf.len() < header_end + content_length {
+                let n = stream.read(&mut chunk).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&chunk[..n]);
+            }
+
+            let status = if is_first {
+                is_first = false;
+                "503 Service Unavailable"
+            } else {
+                "200 OK"
+            };
+            stream
+                .write_all(
+                    format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                        .as_bytes(),
+                )
+                .await
+                .unwrap();
+            let _ = stream.shutdown().await;
+
+            tx.send(MockRequest {
+                headers,
+                body: buf[header_end..].to_vec(),
+            })
+            .await
+            .unwrap();
+        }
+    });
+
+    rx
+}
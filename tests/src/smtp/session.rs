@@ -43,6 +43,7 @@ pub struct DummyIo {
     pub tx_buf: Vec<u8>,
     pub rx_buf: Vec<u8>,
     pub tls: bool,
+    pub peer_certs: Option<Vec<rustls::Certificate>>,
 }
 
 impl AsyncRead for DummyIo {
@@ -96,6 +97,10 @@ impl IsTls for DummyIo {
     fn tls_version_and_cipher(&self) -> (&'static str, &'static str) {
         ("", "")
     }
+
+    fn peer_certificates(&self) -> Option<&[rustls::Certificate]> {
+        self.peer_certs.as_deref()
+    }
 }
 
 impl Unpin for DummyIo {}
@@ -128,6 +133,7 @@ impl TestSession for Session<DummyIo> {
                 rx_buf: vec![],
                 tx_buf: vec![],
                 tls: false,
+                peer_certs: None,
             },
             data: SessionData::new(
                 "127.0.0.1".parse().unwrap(),
@@ -370,6 +376,8 @@ impl TestServerInstance for ServerInstance {
             data: "220 mx.example.org at your service.\r\n".to_string(),
             tls_acceptor: None,
             is_tls_implicit: false,
+            acme: vec![],
+            tenants: Default::default(),
             limiter: ConcurrencyLimiter::new(100),
             shutdown_rx,
         }